@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::sync::Mutex as StdMutex;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+
+use crate::database::{AlarmCondition, AlarmEvent, AlarmRule, Database, LogEntry};
+
+/// Values within this distance of an `eq` rule's threshold are equal to it
+const EQ_TOLERANCE: f64 = 1e-9;
+
+/// What a sample did to a rule's alarm on one device
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlarmTransition {
+    Raised { value: f64 },
+    /// Still active and further past the threshold than before
+    Peak { value: f64 },
+    Cleared,
+}
+
+/// An active alarm as the engine tracks it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActiveAlarm {
+    pub peak: f64,
+    /// First sample of the current run back within bounds
+    pub clearing_since: Option<DateTime<Utc>>,
+}
+
+/// Where one rule stands on one device
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AlarmState {
+    pub active: Option<ActiveAlarm>,
+    /// Last value of the tag, for `delta` rules
+    pub previous: Option<f64>,
+}
+
+/// Apply one sample of a rule's tag.
+///
+/// The alarm is raised as soon as a value passes the threshold, and cleared once values have
+/// stayed at least `hysteresis` back from it for `clear_hold_seconds`. Values in between keep
+/// the alarm as it is, and restart the hold time.
+pub fn evaluate(rule: &AlarmRule, state: &mut AlarmState, value: f64, at: DateTime<Utc>) -> Option<AlarmTransition> {
+    let rule = &rule.rule;
+    let measured = match rule.condition {
+        AlarmCondition::Delta => (value - state.previous.replace(value)?).abs(),
+        _ => value,
+    };
+    let breached = match rule.condition {
+        AlarmCondition::Gt | AlarmCondition::Delta => measured > rule.threshold,
+        AlarmCondition::Lt => measured < rule.threshold,
+        AlarmCondition::Eq => (measured - rule.threshold).abs() <= EQ_TOLERANCE,
+    };
+
+    let Some(active) = &mut state.active else {
+        if breached {
+            state.active = Some(ActiveAlarm { peak: measured, clearing_since: None });
+            return Some(AlarmTransition::Raised { value: measured });
+        }
+        return None;
+    };
+
+    let within_bounds = match rule.condition {
+        AlarmCondition::Gt | AlarmCondition::Delta => measured <= rule.threshold - rule.hysteresis,
+        AlarmCondition::Lt => measured >= rule.threshold + rule.hysteresis,
+        AlarmCondition::Eq => (measured - rule.threshold).abs() > rule.hysteresis.max(EQ_TOLERANCE),
+    };
+    if within_bounds {
+        let since = *active.clearing_since.get_or_insert(at);
+        if at - since >= chrono::Duration::seconds(rule.clear_hold_seconds.into()) {
+            state.active = None;
+            return Some(AlarmTransition::Cleared);
+        }
+        return None;
+    }
+
+    active.clearing_since = None;
+    let further = match rule.condition {
+        AlarmCondition::Gt | AlarmCondition::Delta => measured > active.peak,
+        AlarmCondition::Lt => measured < active.peak,
+        AlarmCondition::Eq => false,
+    };
+    if further {
+        active.peak = measured;
+        return Some(AlarmTransition::Peak { value: measured });
+    }
+    None
+}
+
+/// Checks polled values against the enabled alarm rules and records the alarms they raise
+/// and clear.
+///
+/// Rules and the alarms still open are loaded from the database, so a restart carries on
+/// with the alarms it had rather than raising them again.
+pub struct AlarmEngine {
+    rules: StdMutex<Vec<AlarmRule>>,
+    /// Model of every started device, for rules set on a model
+    device_models: StdMutex<HashMap<String, Option<String>>>,
+    /// By rule id and device id
+    states: StdMutex<HashMap<(i64, String), AlarmState>>,
+}
+
+impl Default for AlarmEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AlarmEngine {
+    pub fn new() -> Self {
+        Self {
+            rules: StdMutex::new(Vec::new()),
+            device_models: StdMutex::new(HashMap::new()),
+            states: StdMutex::new(HashMap::new()),
+        }
+    }
+
+    /// Load the enabled rules and their open alarms. Call again after rules change.
+    pub async fn load(&self, database: &Database) -> Result<()> {
+        let rules: Vec<AlarmRule> = database.get_alarm_rules().await?.into_iter().filter(|rule| rule.rule.enabled).collect();
+        let open = database.get_alarm_events(true, None, u32::MAX).await?;
+
+        let mut states = self.states.lock().unwrap();
+        states.retain(|(rule_id, _), _| rules.iter().any(|rule| rule.id == *rule_id));
+        for state in states.values_mut() {
+            state.active = None;
+        }
+        for event in open {
+            if rules.iter().any(|rule| rule.id == event.rule_id) {
+                let state = states.entry((event.rule_id, event.device_id)).or_default();
+                let clearing_since = state.active.take().and_then(|active| active.clearing_since);
+                state.active = Some(ActiveAlarm { peak: event.peak_value, clearing_since });
+            }
+        }
+        *self.rules.lock().unwrap() = rules;
+        Ok(())
+    }
+
+    /// Apply rules set on `model_id` to the device too
+    pub fn watch_device(&self, device_id: &str, model_id: Option<String>) {
+        self.device_models.lock().unwrap().insert(device_id.to_string(), model_id);
+    }
+
+    /// Transitions caused by a poll's values, in the order they happened. Failed reads and
+    /// muted tags are skipped.
+    pub fn evaluate(&self, entries: &[LogEntry]) -> Vec<(AlarmRule, String, DateTime<Utc>, AlarmTransition)> {
+        let rules = self.rules.lock().unwrap();
+        let device_models = self.device_models.lock().unwrap();
+        let mut states = self.states.lock().unwrap();
+
+        let mut transitions = Vec::new();
+        for entry in entries.iter().filter(|entry| entry.has_value()) {
+            let model_id = device_models.get(&entry.device_id).cloned().flatten();
+            for rule in rules.iter().filter(|rule| rule.rule.tag_name == entry.tag_name) {
+                let applies = match (&rule.rule.device_id, &rule.rule.model_id) {
+                    (Some(device_id), _) => *device_id == entry.device_id,
+                    (None, Some(rule_model)) => model_id.as_ref() == Some(rule_model),
+                    (None, None) => false,
+                };
+                if !applies {
+                    continue;
+                }
+                let state = states.entry((rule.id, entry.device_id.clone())).or_default();
+                if let Some(transition) = evaluate(rule, state, entry.value, entry.timestamp) {
+                    transitions.push((rule.clone(), entry.device_id.clone(), entry.timestamp, transition));
+                }
+            }
+        }
+        transitions
+    }
+
+    /// Evaluate a poll's values and record the resulting alarms. Returns the alarms raised and
+    /// cleared; new peaks are only stored.
+    pub async fn process(&self, database: &Database, entries: &[LogEntry]) -> Result<Vec<AlarmEvent>> {
+        let mut changed = Vec::new();
+        for (rule, device_id, at, transition) in self.evaluate(entries) {
+            match transition {
+                AlarmTransition::Raised { value } => changed.push(database.raise_alarm(&rule, &device_id, value, at).await?),
+                AlarmTransition::Peak { value } => database.update_alarm_peak(rule.id, &device_id, value).await?,
+                AlarmTransition::Cleared => changed.extend(database.clear_alarm(rule.id, &device_id, at).await?),
+            }
+        }
+        Ok(changed)
+    }
+}
@@ -1,5 +1,5 @@
 use axum::{
-    extract::{Path, Query, State, Multipart},
+    extract::{ConnectInfo, Path, Query, State, Multipart, Extension},
     http::{StatusCode, HeaderMap},
     response::Json,
     middleware::Next,
@@ -7,62 +7,449 @@ use axum::{
     extract::Request,
 };
 use serde::{Deserialize, Serialize};
-use tracing::{info, error, warn};
-use chrono::{DateTime, Utc, Duration};
+use utoipa::{IntoParams, ToSchema};
+use futures_util::StreamExt;
+use tracing::{debug, info, error, warn};
+use chrono::{DateTime, TimeZone, Utc, Duration};
 use uuid::Uuid;
 
 use crate::{AppState};
-use crate::config::{AppConfig, DeviceConfig, save_config};
-use crate::database::{LogEntry, DeviceModel, TagTemplate, DeviceInstance, DeviceTag, ScheduleGroup, ModbusTcpTagRegister, PlantConfiguration};
-use crate::csv_parser::ModbusTcpCsvParserService;
+use crate::config::{AppConfig, ByteOrder, DataType, DeviceConfig, FieldError, Iec104ServerConfig, ProtocolConfig, PvNaming, RegisterRead, RegisterType, TAG_DATA_TYPES, WebhookConfig, load_config_from, save_config_to};
+use crate::iec104::{Iec104Diagnostics, Iec104ModeSettings, Iec104ServerStatus};
+use crate::database::{ActiveSession, AuditEntry, LogEntry, DeviceModel, TagTemplate, TagTemplateLink, DeviceModelDeletion, ConfigBundle, RestoreMode, RestoreReport, TemplateResync, DeviceInstance, DeviceTag, ScheduleGroup, ModbusTcpTagRegister, PlantConfiguration, LocalUser, IdempotencyOutcome, DatabaseOperationStats, OperationError, TagSearchFilter, TagSearchResult, SavedTagSearch, TagBulkChanges, TagMute, TagWritePolicy, TagWriteAudit, TagReadResult, RegisterImportCounts, RegisterImportMode, TagSyncCounts, TagWriteResult, TelemetryBacklog, TbChildDevice, SchemaStatus, UserAccount, UserAccountChanges, UserChange, USER_ROLES, AlarmCondition, AlarmEvent, AlarmRule, NewAlarmRule, SEVERITIES, NewVirtualTag, VirtualTag, WebhookDelivery, Job, JobItemResult, AggregateFunction, AggregateBucket, RetentionRun};
+use crate::csv_parser::{decode_csv_text, ModbusTcpCsvParserService};
+use crate::jobs::JobTracker;
+use crate::live_values::DeviceValues;
+use crate::mqtt::MqttStatus;
+use crate::modbus::{discover_sunspec, find_tag_conflicts, SunSpecDiscovery, TagConflict, TagConflictKind, TagFootprint};
+use crate::poll_stats::{DevicePollStats, StatsWindow};
+use crate::protocol_trace::{hex_dump, ProtocolTraceSnapshot, MAX_TRACE_DURATION};
+use crate::virtual_tags::{find_cycle, DeviceGroups, Expr};
+use crate::logging::{ConnectionTestResult, DeviceAction, DeviceActionOutcome, DeviceActionResult, LoggingService};
+use crate::scheduler::{OperationConflict, OperationKind, ScheduledOperation};
+use crate::tb_rust_client::{self, GroupDeviceCacheStats, TbError, TbSessionStats, ThingsBoardClient};
 
 use serde_json::{json, Value};
 
+#[derive(Serialize, ToSchema)]
+pub struct HealthReport {
+    /// `healthy`, `degraded` when a check other than the database fails, or `unhealthy`
+    pub status: String,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub service: String,
+    pub version: String,
+    pub checks: HealthChecks,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct HealthChecks {
+    pub database: DatabaseHealth,
+    pub devices: DeviceHealth,
+    pub disk: DiskHealth,
+    /// Only checked while telemetry forwarding is enabled
+    pub thingsboard: Option<ThingsBoardHealth>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DatabaseHealth {
+    /// `healthy` or `unhealthy`
+    pub status: String,
+    pub latency_ms: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DeviceHealth {
+    /// `healthy`, `degraded` or `unknown`
+    pub status: String,
+    pub running: usize,
+    /// Running devices in `Error` or `Offline`
+    pub errored: usize,
+    pub max_errored_fraction: f64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DiskHealth {
+    /// `healthy`, `degraded` or `unknown` where free space can't be read
+    pub status: String,
+    pub free_mb: Option<u64>,
+    pub min_free_mb: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ThingsBoardHealth {
+    /// `healthy` or `degraded`
+    pub status: String,
+    pub error: Option<String>,
+}
+
+/// Free space in bytes on the filesystem holding `path`
+#[cfg(unix)]
+fn free_disk_bytes(path: &std::path::Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    // SAFETY: `path` is NUL-terminated and `stats` is a plain struct statvfs fills in
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+        return None;
+    }
+    // Field widths differ between platforms
+    #[allow(clippy::unnecessary_cast)]
+    Some(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn free_disk_bytes(_path: &std::path::Path) -> Option<u64> {
+    None
+}
+
 // docker health check endpoint
-pub async fn health_check() -> Result<Json<Value>, StatusCode> {
-    Ok(Json(json!({
-        "status": "healthy",
-        "timestamp": chrono::Utc::now().to_rfc3339(),
-        "service": "AVA Device Logger",
-        "version": env!("CARGO_PKG_VERSION")
-    })))
+#[utoipa::path(
+    get,
+    path = "/api/health",
+    tag = "health",
+    responses(
+        (status = 200, description = "Service is healthy or degraded", body = HealthReport),
+        (status = 503, description = "Database probe failed", body = HealthReport),
+    ),
+    security(()),
+)]
+pub async fn health_check(State(state): State<AppState>) -> (StatusCode, Json<HealthReport>) {
+    let health = &state.config.health;
+
+    let started = std::time::Instant::now();
+    let probe = state.database.probe(std::time::Duration::from_millis(health.database_timeout_ms)).await;
+    let database = DatabaseHealth {
+        status: if probe.is_ok() { "healthy" } else { "unhealthy" }.to_string(),
+        latency_ms: started.elapsed().as_millis() as u64,
+        error: probe.err().map(|e| e.to_string()),
+    };
+
+    let devices = match state.logging_service.running_device_counts().await {
+        Ok((running, errored)) => DeviceHealth {
+            status: if running > 0 && errored as f64 / running as f64 > health.max_errored_fraction { "degraded" } else { "healthy" }.to_string(),
+            running,
+            errored,
+            max_errored_fraction: health.max_errored_fraction,
+        },
+        Err(e) => {
+            warn!("Failed to count errored devices for the health check: {}", e);
+            DeviceHealth { status: "unknown".to_string(), running: 0, errored: 0, max_errored_fraction: health.max_errored_fraction }
+        }
+    };
+
+    let database_dir = std::path::Path::new(&state.config.database.path)
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(std::path::Path::new("."));
+    let free_mb = free_disk_bytes(database_dir).map(|bytes| bytes / (1024 * 1024));
+    let disk = DiskHealth {
+        status: match free_mb {
+            Some(free_mb) if free_mb < health.min_free_disk_mb => "degraded",
+            Some(_) => "healthy",
+            None => "unknown",
+        }.to_string(),
+        free_mb,
+        min_free_mb: health.min_free_disk_mb,
+    };
+
+    let forwarding = state.config.telemetry_forwarding.enabled && state.config.thingsboard.is_some();
+    let thingsboard = match ThingsBoardClient::from_config(&state.config) {
+        Ok(client) if forwarding && health.ping_thingsboard => {
+            let ping = client.ping(std::time::Duration::from_millis(health.thingsboard_timeout_ms)).await;
+            Some(ThingsBoardHealth {
+                status: if ping.is_ok() { "healthy" } else { "degraded" }.to_string(),
+                error: ping.err().map(|e| client.sanitize_error(&e)),
+            })
+        }
+        _ => None,
+    };
+
+    let degraded = [devices.status.as_str(), disk.status.as_str()].contains(&"degraded")
+        || thingsboard.as_ref().is_some_and(|tb| tb.status == "degraded");
+    let (code, status) = if database.error.is_some() {
+        (StatusCode::SERVICE_UNAVAILABLE, "unhealthy")
+    } else if degraded {
+        (StatusCode::OK, "degraded")
+    } else {
+        (StatusCode::OK, "healthy")
+    };
+
+    (code, Json(HealthReport {
+        status: status.to_string(),
+        timestamp: chrono::Utc::now(),
+        service: "AVA Device Logger".to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        checks: HealthChecks { database, devices, disk, thingsboard },
+    }))
+}
+
+/// Prometheus scrape endpoint: poll, read latency, reconnect, logging and ThingsBoard
+/// forwarding counters per device, plus database size and uptime
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "health",
+    responses((status = 200, description = "Metrics in the Prometheus text exposition format", body = String, content_type = "text/plain")),
+    security(()),
+)]
+pub async fn get_metrics(State(state): State<AppState>) -> impl axum::response::IntoResponse {
+    let database_size = std::fs::metadata(&state.config.database.path).map(|metadata| metadata.len()).ok();
+    (
+        [(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        state.metrics.render(database_size),
+    )
 }
 
-#[derive(Serialize, Deserialize)]
+/// The envelope of every `/api` response. Failures carry `success: false`, the message in
+/// `error`, a machine-readable `code` and whatever else is known about the failure in
+/// `details`, with a 4xx/5xx status to match.
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct ApiResponse<T> {
     pub success: bool,
     pub data: Option<T>,
     pub error: Option<String>,
+    /// Set on failures: `bad_request`, `validation_failed`, `unauthorized`, `forbidden`,
+    /// `not_found`, `conflict`, `internal`, ...
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    /// Set on failures; `null` unless the failure has more to say, such as the devices
+    /// blocking a delete or the part of a batch that was done
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+    /// Request id to correlate a sanitized error with the server log
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail_ref: Option<String>,
+    /// Each field of the request that failed validation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field_errors: Option<Vec<FieldError>>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
 pub struct LogQuery {
     pub limit: Option<u32>,
     pub offset: Option<u32>,
 }
 
+/// One page of log entries, newest first
+#[derive(Serialize, ToSchema)]
+pub struct PaginatedLogs {
+    pub entries: Vec<LogEntry>,
+    /// Entries matching the filter across all pages
+    pub total: u64,
+    pub limit: Option<u32>,
+    pub offset: u32,
+}
+
+async fn paginated_logs(state: &AppState, device_id: Option<&str>, params: &LogQuery) -> anyhow::Result<PaginatedLogs> {
+    let total = state.database.count_log_entries(device_id, None, None).await?;
+    let entries = state.database.get_log_entries(device_id, params.limit, params.offset).await?;
+    Ok(PaginatedLogs {
+        entries,
+        total,
+        limit: params.limit,
+        offset: params.offset.unwrap_or(0),
+    })
+}
+
+/// Keys whose values are masked in audit entries
+const AUDIT_REDACTED_KEYS: [&str; 4] = ["password", "password_hash", "session_token", "secret"];
+
+/// Bookkeeping timestamps that aren't set by the user and don't count as a change
+const AUDIT_IGNORED_KEYS: [&str; 2] = ["created_at", "updated_at"];
+
+/// A snapshot of an entity for the audit log, with secrets masked
+fn audit_snapshot(entity: &impl Serialize) -> Option<Value> {
+    fn redact(value: &mut Value) {
+        match value {
+            Value::Object(fields) => {
+                for (key, field) in fields.iter_mut() {
+                    if AUDIT_REDACTED_KEYS.contains(&key.as_str()) {
+                        *field = Value::String("***".to_string());
+                    } else {
+                        redact(field);
+                    }
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(redact),
+            _ => {}
+        }
+    }
+
+    let mut value = serde_json::to_value(entity).ok()?;
+    redact(&mut value);
+    Some(value)
+}
+
+/// The top-level fields that differ between two snapshots of an entity, plus the `keep`
+/// fields that tell it apart. `None` when nothing changed.
+fn audit_diff(before: &impl Serialize, after: &impl Serialize, keep: &[&str]) -> Option<(Value, Value)> {
+    let (Some(Value::Object(before)), Some(Value::Object(after))) = (audit_snapshot(before), audit_snapshot(after)) else {
+        return None;
+    };
+
+    let changed: Vec<&String> = before.keys().chain(after.keys().filter(|key| !before.contains_key(*key)))
+        .filter(|key| !AUDIT_IGNORED_KEYS.contains(&key.as_str()) && before.get(*key) != after.get(*key))
+        .collect();
+    if changed.is_empty() {
+        return None;
+    }
+
+    let pick = |fields: &serde_json::Map<String, Value>| -> Value {
+        Value::Object(
+            fields.iter()
+                .filter(|(key, _)| changed.contains(key) || keep.contains(&key.as_str()))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        )
+    };
+    Some((pick(&before), pick(&after)))
+}
+
+/// Record a change in the audit log. The change has already been made, so a failed write
+/// is logged instead of failing the request.
+async fn audit(
+    state: &AppState,
+    user: &Option<Extension<LocalUser>>,
+    action: &str,
+    entity_type: &str,
+    entity_id: &str,
+    before: Option<Value>,
+    after: Option<Value>,
+) {
+    let entry = AuditEntry {
+        id: None,
+        timestamp: Utc::now(),
+        username: user.as_ref().map(|Extension(user)| user.username.clone()),
+        action: action.to_string(),
+        entity_type: entity_type.to_string(),
+        entity_id: entity_id.to_string(),
+        before,
+        after,
+    };
+    if let Err(e) = state.database.insert_audit_entry(&entry).await {
+        error!("Failed to record {} of {} {} in the audit log: {}", action, entity_type, entity_id, e);
+    }
+}
+
+/// Record the tags added, changed and removed on a device, one audit entry per tag
+async fn audit_tag_changes(state: &AppState, user: &Option<Extension<LocalUser>>, device_id: &str, before: &[DeviceTag], after: &[DeviceTag]) {
+    // Tag lists are saved by name, so tags are matched by name rather than id
+    let snapshot = |tag: &DeviceTag| -> Option<Value> {
+        let mut value = audit_snapshot(tag)?;
+        if let Value::Object(fields) = &mut value {
+            fields.remove("id");
+            fields.remove("device_id");
+        }
+        Some(value)
+    };
+
+    for tag in after {
+        match before.iter().find(|old| old.name == tag.name) {
+            None => audit(state, user, "tag.create", "tag", device_id, None, snapshot(tag)).await,
+            Some(old) => {
+                if let Some((old, new)) = snapshot(old).zip(snapshot(tag)).and_then(|(old, new)| audit_diff(&old, &new, &["name"])) {
+                    audit(state, user, "tag.update", "tag", device_id, Some(old), Some(new)).await;
+                }
+            }
+        }
+    }
+    for tag in before.iter().filter(|old| !after.iter().any(|tag| tag.name == old.name)) {
+        audit(state, user, "tag.delete", "tag", device_id, snapshot(tag), None).await;
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct AuditQuery {
+    /// Device, schedule group, model or user name the entries are about; tag changes are
+    /// filed under their device
+    pub entity_id: Option<String>,
+    pub entity_type: Option<String>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+/// One page of audit entries, newest first
+#[derive(Serialize, ToSchema)]
+pub struct PaginatedAudit {
+    pub entries: Vec<AuditEntry>,
+    /// Entries matching the filter across all pages
+    pub total: u64,
+    pub limit: Option<u32>,
+    pub offset: u32,
+}
+
+/// Configuration changes, logins and logouts, newest first. Entries outlive the devices they describe.
+#[utoipa::path(
+    get,
+    path = "/api/audit",
+    tag = "audit",
+    params(AuditQuery),
+    responses((status = 200, description = "Success", body = ApiResponse<PaginatedAudit>)),
+)]
+pub async fn get_audit_log(
+    State(state): State<AppState>,
+    Query(params): Query<AuditQuery>,
+) -> Result<Json<ApiResponse<PaginatedAudit>>, ApiError> {
+    let entity_id = params.entity_id.as_deref();
+    let entity_type = params.entity_type.as_deref();
+    let page = async {
+        let total = state.database.count_audit_entries(entity_id, entity_type).await?;
+        let entries = state.database.get_audit_entries(entity_id, entity_type, params.limit, params.offset).await?;
+        anyhow::Ok(PaginatedAudit { entries, total, limit: params.limit, offset: params.offset.unwrap_or(0) })
+    };
+
+    match page.await {
+        Ok(page) => Ok(Json(ApiResponse::success(page))),
+        Err(e) => Err(ApiError::internal(format!("Failed to get audit log: {}", e))),
+    }
+}
+
 // Authentication structures
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct LoginRequest {
     pub username: String,
     pub password: String,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct LoginResponse {
     pub session_token: String,
     pub user: UserInfo,
     pub expires_at: DateTime<Utc>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct UserInfo {
     pub id: i64,
     pub username: String,
     pub role: String,
+    /// Still on a seeded default password; the UI should ask for a new one before anything else
+    pub must_change_password: bool,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PasswordChanged {
+    /// Other sessions of the user that were signed out
+    pub revoked_sessions: usize,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateUserRequest {
+    pub username: String,
+    pub password: String,
+    /// `admin` or `installer`
+    pub role: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct PlantConfigRequest {
     pub plant_name: String,
     pub thingsboard_entity_group_id: Option<String>,
@@ -74,15 +461,227 @@ impl<T> ApiResponse<T> {
             success: true,
             data: Some(data),
             error: None,
+            code: None,
+            details: None,
+            detail_ref: None,
+            field_errors: None,
+        }
+    }
+}
+
+/// A failed request, rendered as the `ApiResponse` envelope with the status that goes with it
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    details: Option<Box<Value>>,
+    detail_ref: Option<String>,
+    field_errors: Option<Vec<FieldError>>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        let code = match status {
+            StatusCode::BAD_REQUEST => "bad_request",
+            StatusCode::UNAUTHORIZED => "unauthorized",
+            StatusCode::FORBIDDEN => "forbidden",
+            StatusCode::NOT_FOUND => "not_found",
+            StatusCode::METHOD_NOT_ALLOWED => "method_not_allowed",
+            StatusCode::CONFLICT => "conflict",
+            StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+            StatusCode::TOO_MANY_REQUESTS => "too_many_requests",
+            StatusCode::BAD_GATEWAY => "bad_gateway",
+            StatusCode::SERVICE_UNAVAILABLE => "unavailable",
+            StatusCode::GATEWAY_TIMEOUT => "timeout",
+            _ if status.is_client_error() => "bad_request",
+            _ => "internal",
+        };
+        Self {
+            status,
+            code,
+            message: message.into(),
+            details: None,
+            detail_ref: None,
+            field_errors: None,
         }
     }
 
-    pub fn error(message: String) -> Self {
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    /// Rejects a request naming every invalid field, summarized in `error`
+    pub fn invalid_fields(context: &str, errors: Vec<FieldError>) -> Self {
+        let summary = errors.iter().map(|e| format!("{}: {}", e.field, e.message)).collect::<Vec<_>>().join("; ");
         Self {
+            code: "validation_failed",
+            details: serde_json::to_value(&errors).ok().map(Box::new),
+            field_errors: Some(errors),
+            ..Self::bad_request(format!("{}: {}", context, summary))
+        }
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, message)
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::FORBIDDEN, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, message)
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::CONFLICT, message)
+    }
+
+    /// A failure on our side; logged here so call sites don't have to
+    pub fn internal(message: impl Into<String>) -> Self {
+        let error = Self::new(StatusCode::INTERNAL_SERVER_ERROR, message);
+        error!("{}", error.message);
+        error
+    }
+
+    pub fn unavailable(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::SERVICE_UNAVAILABLE, message)
+    }
+
+    pub fn bad_gateway(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_GATEWAY, message)
+    }
+
+    pub fn with_details(self, details: impl Serialize) -> Self {
+        Self { details: serde_json::to_value(details).ok().map(Box::new), ..self }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.message, self.status)
+    }
+}
+
+impl axum::response::IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ApiResponse::<()> {
             success: false,
             data: None,
-            error: Some(message),
+            error: Some(self.message),
+            code: Some(self.code.to_string()),
+            details: Some(self.details.map_or(Value::Null, |details| *details)),
+            detail_ref: self.detail_ref,
+            field_errors: self.field_errors,
+        };
+        (self.status, Json(body)).into_response()
+    }
+}
+
+/// Database errors that say something about the request rather than the server
+fn sqlite_error_status(error: &rusqlite::Error) -> Option<StatusCode> {
+    match error {
+        rusqlite::Error::QueryReturnedNoRows => Some(StatusCode::NOT_FOUND),
+        rusqlite::Error::SqliteFailure(failure, _) if failure.code == rusqlite::ErrorCode::ConstraintViolation => Some(StatusCode::CONFLICT),
+        _ => None,
+    }
+}
+
+impl From<rusqlite::Error> for ApiError {
+    fn from(e: rusqlite::Error) -> Self {
+        match sqlite_error_status(&e) {
+            Some(status) => Self::new(status, e.to_string()),
+            None => Self::internal(format!("Database error: {}", e)),
+        }
+    }
+}
+
+/// `sqlite_error_status` of a database error wrapped anywhere in an `anyhow` chain
+fn database_error_status(e: &anyhow::Error) -> Option<StatusCode> {
+    e.chain().find_map(|cause| cause.downcast_ref::<rusqlite::Error>()).and_then(sqlite_error_status)
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        if let Some(status) = database_error_status(&e) {
+            return Self::new(status, e.to_string());
+        }
+        match e.downcast::<TbError>() {
+            Ok(e) => e.into(),
+            Err(e) => Self::internal(e.to_string()),
+        }
+    }
+}
+
+impl From<TbError> for ApiError {
+    fn from(e: TbError) -> Self {
+        Self::new(tb_error_status(&e), e.sanitized(&[]))
+    }
+}
+
+/// How a ThingsBoard failure shows up to our clients
+fn tb_error_status(e: &TbError) -> StatusCode {
+    match e {
+        TbError::NotConfigured => StatusCode::SERVICE_UNAVAILABLE,
+        TbError::NotFound(_) => StatusCode::NOT_FOUND,
+        _ => StatusCode::BAD_GATEWAY,
+    }
+}
+
+/// Log a ThingsBoard failure and build an error response that never carries credentials.
+///
+/// The full error is only written at debug level when payload logging is enabled;
+/// the response gets a sanitized message plus a `detail_ref` to find it in the log.
+fn tb_error(tb_client: &ThingsBoardClient, context: &str, e: &TbError) -> ApiError {
+    let detail_ref = uuid::Uuid::new_v4().to_string();
+    let message = format!("{}: {}", context, tb_client.sanitize_error(e));
+    error!("{} [ref {}]", message, detail_ref);
+    if tb_rust_client::payload_logging_enabled() {
+        debug!("Unsanitized ThingsBoard error [ref {}]: {:?}", detail_ref, e);
+    }
+    ApiError {
+        detail_ref: Some(detail_ref),
+        ..ApiError::new(tb_error_status(e), message)
+    }
+}
+
+/// Build the 409 response returned when an admin operation conflicts with a running or queued job
+fn operation_conflict(conflict: OperationConflict) -> ApiError {
+    warn!("{}", conflict);
+    ApiError::conflict(conflict.to_string())
+}
+
+fn operation_timeout(error: OperationError) -> ApiError {
+    warn!("{}", error);
+    ApiError::new(StatusCode::GATEWAY_TIMEOUT, error.to_string())
+}
+
+/// Run a handler's database reads under the configured query timeout. Dropping the
+/// handler (client disconnected) cancels the operation and any statement still
+/// running at the deadline is interrupted.
+async fn with_query_timeout<T>(
+    state: &AppState,
+    future: impl std::future::Future<Output = anyhow::Result<T>>,
+) -> Result<anyhow::Result<T>, ApiError> {
+    let mut operation = state
+        .database
+        .begin_operation(std::time::Duration::from_secs(state.config.timeouts.query_seconds));
+    match operation.run(future).await {
+        Ok(value) => {
+            operation.finish();
+            Ok(Ok(value))
         }
+        Err(OperationError::Failed(e)) => {
+            operation.finish();
+            Ok(Err(e))
+        }
+        Err(e) => Err(operation_timeout(e)),
     }
 }
 
@@ -165,72 +764,218 @@ fn extract_group_prefix_from_name(entity_group_name: &str) -> String {
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/config",
+    tag = "config",
+    responses((status = 200, description = "Success", body = ApiResponse<AppConfig>)),
+)]
 pub async fn get_config(
     State(state): State<AppState>,
-) -> Result<Json<ApiResponse<AppConfig>>, StatusCode> {
-    let config = (*state.config).clone();
+) -> Result<Json<ApiResponse<AppConfig>>, ApiError> {
+    let mut config = (*state.config).clone();
+    // Passwords and webhook secrets are write-only
+    if let Some(thingsboard) = config.thingsboard.as_mut() {
+        thingsboard.password.clear();
+    }
+    if let Some(mqtt) = config.mqtt.as_mut() {
+        mqtt.password.clear();
+    }
+    config.webhooks = config.webhooks.into_iter().map(without_secret).collect();
     Ok(Json(ApiResponse::success(config)))
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/config",
+    tag = "config",
+    request_body = AppConfig,
+    responses((status = 200, description = "Success", body = ApiResponse<String>)),
+)]
 pub async fn update_config(
-    State(_state): State<AppState>,
-    Json(new_config): Json<AppConfig>,
-) -> Result<Json<ApiResponse<String>>, StatusCode> {
-    match save_config(&new_config).await {
-        Ok(()) => Ok(Json(ApiResponse::success("Configuration updated successfully".to_string()))),
-        Err(e) => Ok(Json(ApiResponse::error(format!("Failed to save configuration: {}", e)))),
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Json(mut new_config): Json<AppConfig>,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    let current = load_config_from(&state.config_path()).await.unwrap_or_else(|_| (*state.config).clone());
+
+    // A blank password or secret means "unchanged", since GET never returns them
+    if let Some(thingsboard) = new_config.thingsboard.as_mut().filter(|tb| tb.password.is_empty()) {
+        if let Some(current) = &current.thingsboard {
+            thingsboard.password = current.password.clone();
+        }
+    }
+    if let Some(mqtt) = new_config.mqtt.as_mut().filter(|mqtt| mqtt.password.is_empty()) {
+        if let Some(current) = &current.mqtt {
+            mqtt.password = current.password.clone();
+        }
+    }
+    for hook in new_config.webhooks.iter_mut().filter(|hook| hook.secret.is_empty()) {
+        if let Some(current) = current.webhooks.iter().find(|current| current.id == hook.id) {
+            hook.secret = current.secret.clone();
+        }
+    }
+
+    match save_config_to(&state.config_path(), &new_config).await {
+        Ok(()) => {
+            state.webhooks.apply(new_config.webhooks.clone());
+            if let Some((before, after)) = audit_diff(&current, &new_config, &[]) {
+                audit(&state, &user, "config.update", "config", "config.toml", Some(before), Some(after)).await;
+            }
+            Ok(Json(ApiResponse::success("Configuration updated successfully".to_string())))
+        },
+        Err(e) => Err(ApiError::internal(format!("Failed to save configuration: {}", e))),
     }
 }
 
+/// Alias of `/api/devices-enhanced`; devices are defined in the database
+#[utoipa::path(
+    get,
+    path = "/api/devices",
+    tag = "devices",
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<DeviceWithTags>>), (status = 500, description = "Internal server error")),
+)]
 pub async fn get_devices(
-    State(state): State<AppState>,
-) -> Result<Json<ApiResponse<Vec<DeviceConfig>>>, StatusCode> {
-    let devices = state.config.devices.clone();
-    Ok(Json(ApiResponse::success(devices)))
+    state: State<AppState>,
+) -> Result<Json<ApiResponse<Vec<DeviceWithTags>>>, ApiError> {
+    get_devices_enhanced(state).await
 }
 
+/// Alias of `/api/devices-enhanced/:id`
+#[utoipa::path(
+    get,
+    path = "/api/devices/{id}",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id")),
+    responses((status = 200, description = "Success", body = ApiResponse<DeviceWithTags>), (status = 404, description = "Not found"), (status = 500, description = "Internal server error")),
+)]
 pub async fn get_device(
-    State(state): State<AppState>,
-    Path(device_id): Path<String>,
-) -> Result<Json<ApiResponse<DeviceConfig>>, StatusCode> {
-    if let Some(device) = state.config.devices.iter().find(|d| d.id == device_id) {
-        Ok(Json(ApiResponse::success(device.clone())))
-    } else {
-        Ok(Json(ApiResponse::error("Device not found".to_string())))
-    }
+    state: State<AppState>,
+    path: Path<String>,
+) -> Result<Json<ApiResponse<DeviceWithTags>>, ApiError> {
+    get_device_enhanced(state, path).await
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/devices",
+    tag = "devices",
+    request_body = DeviceConfig,
+    responses((status = 200, description = "Success", body = ApiResponse<String>)),
+)]
 pub async fn create_device(
     State(_state): State<AppState>,
     Json(device): Json<DeviceConfig>,
-) -> Result<Json<ApiResponse<String>>, StatusCode> {
+) -> Result<Json<ApiResponse<String>>, ApiError> {
     // Check if device ID already exists
     if _state.config.devices.iter().any(|d| d.id == device.id) {
-        return Ok(Json(ApiResponse::error("Device ID already exists".to_string())));
+        return Err(ApiError::conflict("Device ID already exists".to_string()));
     }
 
     // This is a simplified implementation - in a real app, you'd update the config file
     Ok(Json(ApiResponse::success("Device created successfully".to_string())))
 }
 
+#[utoipa::path(
+    put,
+    path = "/api/devices/{id}",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id")),
+    request_body = DeviceConfig,
+    responses((status = 200, description = "Success", body = ApiResponse<String>)),
+)]
 pub async fn update_device(
     State(state): State<AppState>,
     Path(device_id): Path<String>,
     Json(_updated_device): Json<DeviceConfig>,
-) -> Result<Json<ApiResponse<String>>, StatusCode> {
+) -> Result<Json<ApiResponse<String>>, ApiError> {
     if state.config.devices.iter().any(|d| d.id == device_id) {
         // This is a simplified implementation - in a real app, you'd update the config file
         Ok(Json(ApiResponse::success("Device updated successfully".to_string())))
     } else {
-        Ok(Json(ApiResponse::error("Device not found".to_string())))
+        Err(ApiError::not_found("Device not found".to_string()))
     }
 }
 
+#[derive(Deserialize, IntoParams)]
+pub struct DeleteDeviceQuery {
+    /// Also delete the device from ThingsBoard, with the MPPT and string devices created for it
+    #[serde(default)]
+    pub delete_remote: bool,
+    /// Delete the device locally even when ThingsBoard devices could not be deleted; the ones
+    /// left behind are recorded in the audit log
+    #[serde(default)]
+    pub force_local: bool,
+}
+
+/// Outcome of deleting a device
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeviceDeletion {
+    pub device_id: String,
+    /// What became of the device's ThingsBoard devices; only with `delete_remote=true`
+    pub remote: Option<RemoteDeletion>,
+}
+
+/// The ThingsBoard devices of a deleted device, by what became of them
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct RemoteDeletion {
+    pub removed: Vec<RemoteEntity>,
+    /// Already gone from ThingsBoard
+    pub skipped: Vec<RemoteEntity>,
+    /// Still on ThingsBoard
+    pub failed: Vec<RemoteEntity>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RemoteEntity {
+    pub tb_device_id: String,
+    pub name: String,
+    /// `Mppt` or `String` for the devices created under an inverter, none for the device itself
+    pub device_type: Option<String>,
+    /// Why it was skipped or failed
+    pub error: Option<String>,
+}
+
+/// Delete a device with its tags. With `delete_remote=true` its ThingsBoard device and the
+/// MPPT and string devices created for it are deleted first; if any of them can't be, the
+/// device is kept and a 502 lists what was removed, skipped and failed, unless
+/// `force_local=true` is passed.
+#[utoipa::path(
+    delete,
+    path = "/api/devices/{id}",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id"), DeleteDeviceQuery),
+    responses(
+        (status = 200, description = "Success", body = ApiResponse<DeviceDeletion>),
+        (status = 404, description = "Device not found"),
+        (status = 502, description = "ThingsBoard devices could not be deleted, so the device was kept; `details` holds the RemoteDeletion"),
+    ),
+)]
 pub async fn delete_device(
     State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
     Path(device_id): Path<String>,
-) -> Result<Json<ApiResponse<String>>, StatusCode> {
+    Query(query): Query<DeleteDeviceQuery>,
+) -> Result<Json<ApiResponse<DeviceDeletion>>, ApiError> {
     info!("Attempting to delete device with ID: {}", device_id);
+    let before = state.database.get_device(&device_id).await.ok().flatten();
+
+    let remote = match (query.delete_remote, &before) {
+        (false, _) => None,
+        (true, None) => return Err(ApiError::not_found("Device not found".to_string())),
+        (true, Some(device)) => {
+            let remote = delete_remote_devices(&state, device).await?;
+            if !remote.failed.is_empty() && !query.force_local {
+                return Err(ApiError::bad_gateway(format!(
+                    "{} ThingsBoard device(s) of {} could not be deleted, so it was kept. Pass force_local=true to delete it anyway",
+                    remote.failed.len(),
+                    device_id
+                ))
+                .with_details(remote));
+            }
+            Some(remote)
+        }
+    };
     
     // Stop the device first if it's running
     if let Err(e) = state.logging_service.stop_device(&device_id).await {
@@ -242,89 +987,957 @@ pub async fn delete_device(
     match state.database.delete_device(&device_id).await {
         Ok(()) => {
             info!("Device {} deleted successfully", device_id);
-            Ok(Json(ApiResponse::success("Device deleted successfully".to_string())))
+            state.logging_service.forget_device_values(&device_id);
+            state.logging_service.forget_poll_stats(&device_id);
+            state.logging_service.forget_trace(&device_id);
+            reload_virtual_tags(&state).await;
+            audit(&state, &user, "device.delete", "device", &device_id, before.as_ref().and_then(audit_snapshot), None).await;
+            for entity in remote.iter().flat_map(|remote| &remote.failed) {
+                warn!("ThingsBoard device {} ({}) of deleted device {} is left orphaned", entity.tb_device_id, entity.name, device_id);
+                let orphan = json!({ "device_id": device_id, "name": entity.name, "device_type": entity.device_type, "error": entity.error });
+                audit(&state, &user, "thingsboard.orphan", "tb_device", &entity.tb_device_id, None, Some(orphan)).await;
+            }
+            Ok(Json(ApiResponse::success(DeviceDeletion { device_id, remote })))
         }
         Err(e) => {
-            error!("Failed to delete device {}: {}", device_id, e);
             if e.to_string().contains("not found") {
-                Ok(Json(ApiResponse::error("Device not found".to_string())))
+                Err(ApiError::not_found("Device not found".to_string()))
             } else {
-                Ok(Json(ApiResponse::error(format!("Failed to delete device: {}", e))))
+                Err(ApiError::internal(format!("Failed to delete device {}: {}", device_id, e)))
+            }
+        }
+    }
+}
+
+/// Delete a device's ThingsBoard devices, strings and MPPTs before the inverter. The first
+/// failure stops the deletion, so a parent is never deleted while children of it remain;
+/// the devices not tried are reported as failed too.
+async fn delete_remote_devices(state: &AppState, device: &DeviceInstance) -> Result<RemoteDeletion, ApiError> {
+    let children = match state.database.get_tb_child_devices(&device.id).await {
+        Ok(children) => children,
+        Err(e) => return Err(ApiError::internal(format!("Failed to get ThingsBoard child devices of {}: {}", device.id, e))),
+    };
+    let mut entities: Vec<RemoteEntity> = children
+        .into_iter()
+        .map(|child| RemoteEntity { tb_device_id: child.tb_device_id, name: child.name, device_type: Some(child.device_type), error: None })
+        .collect();
+    entities.sort_by_key(|entity| entity.device_type.as_deref() != Some("String"));
+    if let Some(tb_device_id) = device.tb_device_id.as_deref().filter(|id| !id.is_empty()) {
+        entities.push(RemoteEntity { tb_device_id: tb_device_id.to_string(), name: device.name.clone(), device_type: None, error: None });
+    }
+
+    let mut deletion = RemoteDeletion::default();
+    if entities.is_empty() {
+        return Ok(deletion);
+    }
+
+    let mut tb_client = match ThingsBoardClient::from_config(&state.config) {
+        Ok(client) => client.with_session(state.tb_session.clone()).with_rate_limiter(state.tb_rate_limiter.clone()),
+        Err(e) => {
+            let error = e.to_string();
+            deletion.failed = entities.into_iter().map(|entity| RemoteEntity { error: Some(error.clone()), ..entity }).collect();
+            return Ok(deletion);
+        }
+    };
+    if let Err(e) = tb_client.login_configured().await {
+        let error = format!("Failed to log in to ThingsBoard: {}", tb_client.sanitize_error(&e));
+        deletion.failed = entities.into_iter().map(|entity| RemoteEntity { error: Some(error.clone()), ..entity }).collect();
+        return Ok(deletion);
+    }
+
+    for entity in entities {
+        if !deletion.failed.is_empty() {
+            deletion.failed.push(RemoteEntity { error: Some("not tried after an earlier failure".to_string()), ..entity });
+            continue;
+        }
+        match tb_client.delete_device(&entity.tb_device_id).await {
+            Ok(()) => deletion.removed.push(entity),
+            Err(TbError::NotFound(_)) => deletion.skipped.push(RemoteEntity { error: Some("not found on ThingsBoard".to_string()), ..entity }),
+            Err(e) => {
+                let error = tb_client.sanitize_error(&e);
+                warn!("Failed to delete ThingsBoard device {} of {}: {}", entity.tb_device_id, device.id, error);
+                deletion.failed.push(RemoteEntity { error: Some(error), ..entity });
             }
         }
     }
+    if let Some(group_id) = device.tb_group_id.as_deref().filter(|_| !deletion.removed.is_empty()) {
+        state.tb_group_cache.invalidate(group_id);
+    }
+    info!(
+        "Deleted ThingsBoard devices of {}: {} removed, {} skipped, {} failed",
+        device.id, deletion.removed.len(), deletion.skipped.len(), deletion.failed.len()
+    );
+    Ok(deletion)
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/devices-enhanced/{id}/start",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id")),
+    responses((status = 200, description = "Success", body = ApiResponse<String>)),
+)]
 pub async fn start_device(
     State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
     Path(device_id): Path<String>,
-) -> Result<Json<ApiResponse<String>>, StatusCode> {
+) -> Result<Json<ApiResponse<String>>, ApiError> {
     match state.logging_service.start_device(&device_id).await {
-        Ok(()) => Ok(Json(ApiResponse::success("Device started successfully".to_string()))),
-        Err(e) => Ok(Json(ApiResponse::error(format!("Failed to start device: {}", e)))),
+        Ok(()) => {
+            audit(&state, &user, "device.start", "device", &device_id, None, None).await;
+            Ok(Json(ApiResponse::success("Device started successfully".to_string())))
+        },
+        Err(e) if e.to_string().contains("not found") => Err(ApiError::not_found(format!("Device {} not found", device_id))),
+        Err(e) => Err(ApiError::internal(format!("Failed to start device: {}", e))),
+
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/devices-enhanced/{id}/stop",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id")),
+    responses((status = 200, description = "Success", body = ApiResponse<String>)),
+)]
 pub async fn stop_device(
     State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
     Path(device_id): Path<String>,
-) -> Result<Json<ApiResponse<String>>, StatusCode> {
+) -> Result<Json<ApiResponse<String>>, ApiError> {
     match state.logging_service.stop_device(&device_id).await {
-        Ok(()) => Ok(Json(ApiResponse::success("Device stopped successfully".to_string()))),
-        Err(e) => Ok(Json(ApiResponse::error(format!("Failed to stop device: {}", e)))),
+        Ok(()) => {
+            audit(&state, &user, "device.stop", "device", &device_id, None, None).await;
+            Ok(Json(ApiResponse::success("Device stopped successfully".to_string())))
+        },
+        Err(e) => Err(ApiError::internal(format!("Failed to stop device: {}", e))),
     }
 }
 
-pub async fn get_logs(
-    State(state): State<AppState>,
-    Query(params): Query<LogQuery>,
-) -> Result<Json<ApiResponse<Vec<LogEntry>>>, StatusCode> {
-    match state.database.get_log_entries(None, params.limit, params.offset).await {
-        Ok(logs) => Ok(Json(ApiResponse::success(logs))),
-        Err(e) => Ok(Json(ApiResponse::error(format!("Failed to get logs: {}", e)))),
-    }
+#[derive(Deserialize, ToSchema)]
+pub struct BulkDeviceActionRequest {
+    pub action: DeviceAction,
+    #[serde(default)]
+    pub device_ids: Vec<String>,
+    /// Apply the action to every configured device instead of `device_ids`
+    #[serde(default)]
+    pub all: bool,
 }
 
-pub async fn get_device_logs(
-    State(state): State<AppState>,
-    Path(device_id): Path<String>,
-    Query(params): Query<LogQuery>,
-) -> Result<Json<ApiResponse<Vec<LogEntry>>>, StatusCode> {
-    match state.database.get_log_entries(Some(&device_id), params.limit, params.offset).await {
-        Ok(logs) => Ok(Json(ApiResponse::success(logs))),
-        Err(e) => Ok(Json(ApiResponse::error(format!("Failed to get device logs: {}", e)))),
-    }
+#[derive(Serialize, ToSchema)]
+pub struct BulkDeviceActionResponse {
+    pub action: DeviceAction,
+    pub done: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub results: Vec<DeviceActionResult>,
 }
 
-#[derive(Serialize)]
-pub struct StatusResponse {
-    pub devices: Vec<DeviceStatusInfo>,
-    pub total_log_entries: u32,
-    pub server_uptime: String,
-}
+async fn run_bulk_device_action(
+    state: &AppState,
+    user: &Option<Extension<LocalUser>>,
+    action: DeviceAction,
+    device_ids: Option<Vec<String>>,
+) -> Result<Json<ApiResponse<BulkDeviceActionResponse>>, ApiError> {
+    let device_ids = match device_ids {
+        Some(device_ids) => device_ids,
+        None => match state.database.get_devices().await {
+            Ok(devices) => devices.into_iter().map(|device| device.id).collect(),
+            Err(e) => return Err(ApiError::internal(format!("Failed to get devices: {}", e))),
+        },
+    };
 
-#[derive(Serialize)]
-pub struct DeviceStatusInfo {
-    pub device_id: String,
-    pub status: String,
-    pub last_update: chrono::DateTime<chrono::Utc>,
-    pub error_message: Option<String>,
-    pub connection_count: i64,
-    pub is_running: bool,
+    let results = state.logging_service.run_device_action(action, device_ids).await;
+    let (audit_action, before, after) = match action {
+        DeviceAction::Start => ("device.start", None, None),
+        DeviceAction::Stop => ("device.stop", None, None),
+        DeviceAction::Enable => ("device.enable", Some(json!({ "enabled": false })), Some(json!({ "enabled": true }))),
+        DeviceAction::Disable => ("device.disable", Some(json!({ "enabled": true })), Some(json!({ "enabled": false }))),
+    };
+    for result in results.iter().filter(|result| result.outcome == DeviceActionOutcome::Done) {
+        audit(state, user, audit_action, "device", &result.device_id, before.clone(), after.clone()).await;
+    }
+    let count = |outcome| results.iter().filter(|result| result.outcome == outcome).count();
+    let response = BulkDeviceActionResponse {
+        action,
+        done: count(DeviceActionOutcome::Done),
+        skipped: count(DeviceActionOutcome::Skipped),
+        failed: count(DeviceActionOutcome::Failed),
+        results,
+    };
+    info!(
+        "Bulk {:?}: {} done, {} skipped, {} failed",
+        action, response.done, response.skipped, response.failed
+    );
+    Ok(Json(ApiResponse::success(response)))
 }
 
-pub async fn get_status(
+/// Start, stop, enable or disable many devices at once. Every device gets a result;
+/// devices already in the requested state are skipped rather than failed.
+#[utoipa::path(
+    post,
+    path = "/api/devices-enhanced/bulk",
+    tag = "devices",
+    request_body = BulkDeviceActionRequest,
+    responses((status = 200, description = "Success", body = ApiResponse<BulkDeviceActionResponse>)),
+)]
+pub async fn bulk_device_action(
     State(state): State<AppState>,
-) -> Result<Json<ApiResponse<StatusResponse>>, StatusCode> {
+    user: Option<Extension<LocalUser>>,
+    Json(request): Json<BulkDeviceActionRequest>,
+) -> Result<Json<ApiResponse<BulkDeviceActionResponse>>, ApiError> {
+    let device_ids = match (request.all, request.device_ids.is_empty()) {
+        (true, true) => None,
+        (false, false) => Some(request.device_ids),
+        (true, false) => return Err(ApiError::bad_request("Give either device_ids or all, not both".to_string())),
+        (false, true) => return Err(ApiError::bad_request("No devices given; set device_ids or all".to_string())),
+    };
+    run_bulk_device_action(&state, &user, request.action, device_ids).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/devices-enhanced/start-all",
+    tag = "devices",
+    responses((status = 200, description = "Success", body = ApiResponse<BulkDeviceActionResponse>)),
+)]
+pub async fn start_all_devices(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+) -> Result<Json<ApiResponse<BulkDeviceActionResponse>>, ApiError> {
+    run_bulk_device_action(&state, &user, DeviceAction::Start, None).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/devices-enhanced/stop-all",
+    tag = "devices",
+    responses((status = 200, description = "Success", body = ApiResponse<BulkDeviceActionResponse>)),
+)]
+pub async fn stop_all_devices(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+) -> Result<Json<ApiResponse<BulkDeviceActionResponse>>, ApiError> {
+    run_bulk_device_action(&state, &user, DeviceAction::Stop, None).await
+}
+
+/// Current value of every tag of a device from the last value cache, without querying the log
+#[utoipa::path(
+    get,
+    path = "/api/devices-enhanced/{id}/values",
+    tag = "logs",
+    params(("id" = String, Path, description = "Device id")),
+    responses(
+        (status = 200, description = "Success", body = ApiResponse<DeviceValues>),
+        (status = 404, description = "Device not found"),
+    ),
+)]
+pub async fn get_device_values(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+) -> Result<Json<ApiResponse<DeviceValues>>, ApiError> {
+    match state.database.get_device(&device_id).await {
+        Ok(Some(_)) => Ok(Json(ApiResponse::success(state.logging_service.device_values(&device_id)))),
+        Ok(None) => Err(ApiError::not_found(format!("Device {} not found", device_id))),
+        Err(e) => Err(ApiError::internal(format!("Failed to get device {}: {}", device_id, e))),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct PollStatsQuery {
+    /// `1h` (default) or `24h`
+    #[serde(default)]
+    pub window: StatsWindow,
+}
+
+/// How a device's polls went over the last hour or day: success rate, latency percentiles
+/// and failure reasons, in total and per schedule group
+#[utoipa::path(
+    get,
+    path = "/api/devices-enhanced/{id}/stats",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id"), PollStatsQuery),
+    responses(
+        (status = 200, description = "Success", body = ApiResponse<DevicePollStats>),
+        (status = 404, description = "Device not found"),
+    ),
+)]
+pub async fn get_device_poll_stats(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+    Query(query): Query<PollStatsQuery>,
+) -> Result<Json<ApiResponse<DevicePollStats>>, ApiError> {
+    match state.database.get_device(&device_id).await {
+        Ok(Some(_)) => {},
+        Ok(None) => return Err(ApiError::not_found(format!("Device {} not found", device_id))),
+        Err(e) => return Err(ApiError::internal(format!("Failed to get device {}: {}", device_id, e))),
+    }
+
+    match state.logging_service.poll_stats(&device_id, query.window).await {
+        Ok(stats) => Ok(Json(ApiResponse::success(stats))),
+        Err(e) => Err(ApiError::internal(format!("Failed to get polling statistics of device {}: {}", device_id, e))),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct SetTraceQuery {
+    pub enabled: bool,
+    /// Seconds until the trace turns itself off, at most a day
+    #[serde(default = "default_trace_duration_s")]
+    pub duration_s: u64,
+}
+
+fn default_trace_duration_s() -> u64 {
+    300
+}
+
+/// Start or stop capturing every PDU a device sends and receives. Starting drops the PDUs of
+/// the last capture; stopping keeps them readable. A stopped device is captured once started.
+#[utoipa::path(
+    post,
+    path = "/api/devices-enhanced/{id}/trace",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id"), SetTraceQuery),
+    responses(
+        (status = 200, description = "Success", body = ApiResponse<ProtocolTraceSnapshot>),
+        (status = 400, description = "Invalid duration"),
+        (status = 404, description = "Device not found"),
+    ),
+)]
+pub async fn set_device_trace(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Path(device_id): Path<String>,
+    Query(query): Query<SetTraceQuery>,
+) -> Result<Json<ApiResponse<ProtocolTraceSnapshot>>, ApiError> {
+    if query.enabled && !(1..=MAX_TRACE_DURATION.as_secs()).contains(&query.duration_s) {
+        return Err(ApiError::invalid_fields("Invalid trace duration", vec![FieldError {
+            field: "duration_s".to_string(),
+            message: format!("must be from 1 to {}", MAX_TRACE_DURATION.as_secs()),
+        }]));
+    }
+    match state.database.get_device(&device_id).await {
+        Ok(Some(_)) => {},
+        Ok(None) => return Err(ApiError::not_found(format!("Device {} not found", device_id))),
+        Err(e) => return Err(ApiError::internal(format!("Failed to get device {}: {}", device_id, e))),
+    }
+
+    let trace = state.logging_service.set_trace(&device_id, query.enabled, std::time::Duration::from_secs(query.duration_s));
+    match query.enabled {
+        true => info!("Tracing protocol traffic of device {} for {}s", device_id, query.duration_s),
+        false => info!("Stopped tracing protocol traffic of device {}", device_id),
+    }
+    let action = if query.enabled { "device.trace_start" } else { "device.trace_stop" };
+    let after = query.enabled.then(|| json!({ "duration_s": query.duration_s }));
+    audit(&state, &user, action, "device", &device_id, None, after).await;
+    Ok(Json(ApiResponse::success(trace)))
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceFormat {
+    #[default]
+    Json,
+    /// Plain text download with the bytes of each PDU as a hex dump
+    Hexdump,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct TraceQuery {
+    /// `json` (default) or `hexdump`
+    #[serde(default)]
+    pub format: TraceFormat,
+}
+
+/// The PDUs captured by a device's trace, oldest first, as JSON or as a hex dump download
+#[utoipa::path(
+    get,
+    path = "/api/devices-enhanced/{id}/trace",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id"), TraceQuery),
+    responses(
+        (status = 200, description = "Success", body = ApiResponse<ProtocolTraceSnapshot>),
+        (status = 200, description = "With `format=hexdump`", content_type = "text/plain", body = String),
+        (status = 404, description = "Device not found"),
+    ),
+)]
+pub async fn get_device_trace(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+    Query(query): Query<TraceQuery>,
+) -> Result<Response, ApiError> {
+    use axum::http::header;
+    use axum::response::IntoResponse;
+
+    match state.database.get_device(&device_id).await {
+        Ok(Some(_)) => {},
+        Ok(None) => return Err(ApiError::not_found(format!("Device {} not found", device_id))),
+        Err(e) => return Err(ApiError::internal(format!("Failed to get device {}: {}", device_id, e))),
+    }
+
+    let trace = state.logging_service.trace(&device_id);
+    match query.format {
+        TraceFormat::Json => Ok(Json(ApiResponse::success(trace)).into_response()),
+        TraceFormat::Hexdump => {
+            let name: String = device_id
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+                .collect();
+            let filename = format!("trace-{}-{}.txt", name, Utc::now().format("%Y%m%d-%H%M%S"));
+            Ok(Response::builder()
+                .status(200)
+                .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+                .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename))
+                .body(axum::body::Body::from(hex_dump(&trace)))
+                .unwrap())
+        }
+    }
+}
+
+/// Current value of every tag of every device read since the service started
+#[utoipa::path(
+    get,
+    path = "/api/values",
+    tag = "logs",
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<DeviceValues>>)),
+)]
+pub async fn get_all_values(
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<Vec<DeviceValues>>>, ApiError> {
+    Ok(Json(ApiResponse::success(state.logging_service.all_values())))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/logs",
+    tag = "logs",
+    params(LogQuery),
+    responses((status = 200, description = "Success", body = ApiResponse<PaginatedLogs>), (status = 504, description = "Query timed out", body = ApiResponse<PaginatedLogs>)),
+)]
+pub async fn get_logs(
+    State(state): State<AppState>,
+    Query(params): Query<LogQuery>,
+) -> Result<Json<ApiResponse<PaginatedLogs>>, ApiError> {
+    match with_query_timeout(&state, paginated_logs(&state, None, &params)).await? {
+        Ok(logs) => Ok(Json(ApiResponse::success(logs))),
+        Err(e) => Err(ApiError::internal(format!("Failed to get logs: {}", e))),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/logs/{device_id}",
+    tag = "logs",
+    params(("device_id" = String, Path, description = "Device id"), LogQuery),
+    responses((status = 200, description = "Success", body = ApiResponse<PaginatedLogs>), (status = 504, description = "Query timed out", body = ApiResponse<PaginatedLogs>)),
+)]
+pub async fn get_device_logs(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+    Query(params): Query<LogQuery>,
+) -> Result<Json<ApiResponse<PaginatedLogs>>, ApiError> {
+    match with_query_timeout(&state, paginated_logs(&state, Some(&device_id), &params)).await? {
+        Ok(logs) => Ok(Json(ApiResponse::success(logs))),
+        Err(e) => Err(ApiError::internal(format!("Failed to get device logs: {}", e))),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct LogAggregateQuery {
+    pub tag_name: String,
+    /// Bucket width: a number followed by `s`, `m`, `h` or `d`, e.g. `30s`, `5m`, `1h`
+    pub interval: String,
+    /// Defaults to `avg`
+    #[serde(rename = "fn")]
+    #[param(rename = "fn")]
+    pub function: Option<AggregateFunction>,
+    /// Inclusive lower bound, defaults to 24 hours before `end`
+    pub start: Option<DateTime<Utc>>,
+    /// Exclusive upper bound, defaults to now
+    pub end: Option<DateTime<Utc>>,
+    /// Count failed reads (`Bad` and `CommFail` quality) too; their value is 0
+    #[serde(default)]
+    pub include_bad: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AggregatedLogs {
+    pub device_id: String,
+    pub tag_name: String,
+    pub function: AggregateFunction,
+    pub interval_seconds: u64,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Buckets with at least one sample, oldest first
+    pub buckets: Vec<AggregateBucket>,
+}
+
+/// Most buckets a single aggregate query may span
+const MAX_AGGREGATE_BUCKETS: u64 = 5_000;
+
+/// Parses a bucket width such as "30s", "5m", "1h" or "1d" into seconds
+fn parse_interval(interval: &str) -> Result<u64, String> {
+    let interval = interval.trim();
+    let unit = interval.chars().last().unwrap_or_default();
+    let amount = &interval[..interval.len() - unit.len_utf8().min(interval.len())];
+    let multiplier = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3_600,
+        'd' => 86_400,
+        _ => return Err(format!("Invalid interval '{}': expected a number followed by s, m, h or d", interval)),
+    };
+    match amount.parse::<u64>() {
+        Ok(amount) if amount > 0 => amount
+            .checked_mul(multiplier)
+            .ok_or_else(|| format!("Interval '{}' is too large", interval)),
+        _ => Err(format!("Invalid interval '{}': expected a positive whole number before the unit", interval)),
+    }
+}
+
+/// One tag's samples reduced to a value per time bucket, for charting long ranges
+#[utoipa::path(
+    get,
+    path = "/api/logs/{device_id}/aggregate",
+    tag = "logs",
+    params(("device_id" = String, Path, description = "Device id"), LogAggregateQuery),
+    responses((status = 200, description = "Success", body = ApiResponse<AggregatedLogs>), (status = 504, description = "Query timed out", body = ApiResponse<AggregatedLogs>)),
+)]
+pub async fn get_aggregated_logs(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+    Query(query): Query<LogAggregateQuery>,
+) -> Result<Json<ApiResponse<AggregatedLogs>>, ApiError> {
+    let interval_seconds = match parse_interval(&query.interval) {
+        Ok(seconds) => seconds,
+        Err(e) => return Err(ApiError::bad_request(e)),
+    };
+    let function = query.function.unwrap_or(AggregateFunction::Avg);
+    let end = query.end.unwrap_or_else(Utc::now);
+    let start = query.start.unwrap_or(end - chrono::Duration::hours(24));
+    if start >= end {
+        return Err(ApiError::bad_request("start must be before end".to_string()));
+    }
+
+    let span_seconds = (end - start).num_seconds().max(1) as u64;
+    let bucket_count = span_seconds.div_ceil(interval_seconds);
+    if bucket_count > MAX_AGGREGATE_BUCKETS {
+        return Err(ApiError::bad_request(format!(
+            "Range would produce {} buckets
+, the limit is {}; use a wider interval or a shorter range",
+            bucket_count, MAX_AGGREGATE_BUCKETS
+        )));
+    }
+
+    let buckets = state.database.get_aggregated_log_entries(&device_id, &query.tag_name, start, end, interval_seconds, function, query.include_bad);
+    match with_query_timeout(&state, buckets).await? {
+        Ok(buckets) => Ok(Json(ApiResponse::success(AggregatedLogs {
+            device_id,
+            tag_name: query.tag_name,
+            function,
+            interval_seconds,
+            start,
+            end,
+            buckets,
+        }))),
+        Err(e) => Err(ApiError::internal(format!("Failed to aggregate logs: {}", e))),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LogExportFormat {
+    #[default]
+    Csv,
+    /// Newline-delimited JSON, one object per entry
+    Json,
+}
+
+impl LogExportFormat {
+    fn content_type(&self) -> &'static str {
+        match self {
+            LogExportFormat::Csv => "text/csv; charset=utf-8",
+            LogExportFormat::Json => "application/x-ndjson",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            LogExportFormat::Csv => "csv",
+            LogExportFormat::Json => "ndjson",
+        }
+    }
+
+    /// Encode one chunk of the export; the CSV header goes in front of the first chunk only
+    fn encode(&self, entries: &[LogEntry], first_chunk: bool) -> anyhow::Result<String> {
+        match self {
+            LogExportFormat::Csv => log_entries_csv(entries, first_chunk),
+            LogExportFormat::Json => log_entries_ndjson(entries),
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct LogExportQuery {
+    pub device_id: Option<String>,
+    /// Inclusive lower bound on the sample timestamp
+    pub start: Option<DateTime<Utc>>,
+    /// Exclusive upper bound on the sample timestamp
+    pub end: Option<DateTime<Utc>>,
+    /// `csv` (default) or `json`
+    pub format: Option<LogExportFormat>,
+}
+
+const LOG_EXPORT_CHUNK_ROWS: u32 = 5_000;
+
+fn log_entries_csv(entries: &[LogEntry], include_header: bool) -> anyhow::Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    if include_header {
+        writer.write_record(["timestamp", "device_id", "tag_name", "value", "unit", "quality"])?;
+    }
+    for entry in entries {
+        writer.write_record([
+            entry.timestamp.to_rfc3339(),
+            entry.device_id.clone(),
+            entry.tag_name.clone(),
+            entry.value.to_string(),
+            entry.unit.clone().unwrap_or_default(),
+            entry.quality.clone(),
+        ])?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+fn log_entries_ndjson(entries: &[LogEntry]) -> anyhow::Result<String> {
+    let mut text = String::new();
+    for entry in entries {
+        let line = serde_json::json!({
+            "timestamp": entry.timestamp.to_rfc3339(),
+            "device_id": entry.device_id,
+            "tag_name": entry.tag_name,
+            "value": entry.value,
+            "unit": entry.unit,
+            "quality": entry.quality,
+        });
+        text.push_str(&serde_json::to_string(&line)?);
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+/// Download name such as `logs-meter-1.csv`, keeping the device ID header-safe
+fn log_export_filename(device_id: Option<&str>, format: LogExportFormat) -> String {
+    match device_id {
+        Some(device_id) => {
+            let device_id: String = device_id
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+                .collect();
+            format!("logs-{}.{}", device_id, format.extension())
+        }
+        None => format!("logs.{}", format.extension()),
+    }
+}
+
+/// Stream log entries as CSV or newline-delimited JSON, fetched in chunks. The export
+/// stops as soon as the client disconnects and is bounded by the export timeout.
+#[utoipa::path(
+    get,
+    path = "/api/logs/export",
+    tag = "logs",
+    params(LogExportQuery),
+    responses((status = 200, description = "Log entries as CSV, or newline-delimited JSON with `format=json`", content_type = "text/csv", body = String), (status = 400, description = "Invalid date range", body = ApiResponse<String>), (status = 409, description = "Another export is running or queued", body = ApiResponse<String>), (status = 500, description = "Internal server error", body = ApiResponse<String>), (status = 504, description = "Export timed out", body = ApiResponse<String>)),
+)]
+pub async fn export_logs(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Query(query): Query<LogExportQuery>,
+) -> Result<Response, ApiError> {
+    use axum::body::Body;
+    use axum::http::header;
+    use tokio_stream::wrappers::ReceiverStream;
+
+    if let (Some(start), Some(end)) = (query.start, query.end) {
+        if start >= end {
+            return Err(ApiError::bad_request("start must be before end".to_string()));
+        }
+    }
+
+    let format = query.format.unwrap_or_default();
+    let filename = log_export_filename(query.device_id.as_deref(), format);
+    // Held until the last chunk is sent, so a restore or vacuum waits for the download
+    let scheduled = state
+        .scheduler
+        .start(OperationKind::Export, &format!("Export logs to {}", filename), user.map(|Extension(user)| user.username))
+        .await
+        .map_err(operation_conflict)?;
+    let database = state.database.clone();
+    let mut operation = database.begin_operation(std::time::Duration::from_secs(state.config.timeouts.export_seconds));
+
+    // Fetch the first chunk before committing to a 200 so failures still get a proper status
+    let first_chunk = operation
+        .run(database.get_log_entries_after(query.device_id.as_deref(), query.start, query.end, 0, LOG_EXPORT_CHUNK_ROWS))
+        .await;
+    let mut chunk = match first_chunk {
+        Ok(chunk) => chunk,
+        Err(OperationError::Failed(e)) => {
+            operation.finish();
+            return Err(ApiError::internal(format!("Failed to export logs: {}", e)));
+
+        }
+        Err(e) => return Err(operation_timeout(e)),
+    };
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<String, std::io::Error>>(4);
+    tokio::spawn(async move {
+        let _scheduled = scheduled;
+        let mut first_chunk = true;
+        loop {
+            let text = match format.encode(&chunk, first_chunk) {
+                Ok(text) => text,
+                Err(e) => {
+                    error!("Failed to encode log export: {}", e);
+                    let _ = tx.send(Err(std::io::Error::other(e.to_string()))).await;
+                    operation.finish();
+                    return;
+                }
+            };
+            first_chunk = false;
+
+            // A failed send means the client went away; dropping the operation records the cancellation
+            if tx.send(Ok(text)).await.is_err() {
+                info!("Log export cancelled by client");
+                return;
+            }
+
+            let Some(last_id) = chunk.last().and_then(|entry| entry.id) else {
+                operation.finish();
+                return;
+            };
+            if tx.is_closed() {
+                info!("Log export cancelled by client");
+                return;
+            }
+
+            chunk = match operation
+                .run(database.get_log_entries_after(query.device_id.as_deref(), query.start, query.end, last_id, LOG_EXPORT_CHUNK_ROWS))
+                .await
+            {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    warn!("Log export aborted: {}", e);
+                    let _ = tx.send(Err(std::io::Error::other(e.to_string()))).await;
+                    if matches!(e, OperationError::Failed(_)) {
+                        operation.finish();
+                    }
+                    return;
+                }
+            };
+        }
+    });
+
+    Ok(Response::builder()
+        .status(200)
+        .header(header::CONTENT_TYPE, format.content_type())
+        .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename))
+        .body(Body::from_stream(ReceiverStream::new(rx)))
+        .unwrap())
+}
+
+/// Download the gateway configuration as one JSON document that `POST /api/restore` accepts
+#[utoipa::path(
+    get,
+    path = "/api/backup",
+    tag = "config",
+    responses((status = 200, description = "Configuration bundle", body = ConfigBundle), (status = 409, description = "Another backup is running or queued"), (status = 500, description = "Internal server error")),
+)]
+pub async fn export_backup(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+) -> Result<Response, ApiError> {
+    use axum::body::Body;
+    use axum::http::header;
+
+    let _operation = state
+        .scheduler
+        .start(OperationKind::Backup, "Export configuration backup", user.map(|Extension(user)| user.username))
+        .await
+        .map_err(operation_conflict)?;
+    let bundle = state.database.export_config_bundle().await.map_err(|e| ApiError::internal(format!("Failed to export configuration: {}", e)))?;
+    let body = serde_json::to_vec_pretty(&bundle).map_err(|e| ApiError::internal(format!("Failed to encode configuration backup: {}", e)))?;
+    let filename = format!("gateway-backup-{}.json", bundle.exported_at.format("%Y%m%d-%H%M%S"));
+
+    info!(
+        "Exported configuration backup with {} devices and {} tags",
+        bundle.devices.len(),
+        bundle.device_tags.len()
+    );
+    Ok(Response::builder()
+        .status(200)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename))
+        .body(Body::from(body))
+        .unwrap())
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct RestoreQuery {
+    /// `merge` (default) keeps configuration missing from the bundle; `replace` deletes it
+    #[serde(default)]
+    pub mode: RestoreMode,
+}
+
+/// Load a bundle from `GET /api/backup`. The bundle is checked as a whole before
+/// anything is written, and running devices are restarted with the restored settings.
+#[utoipa::path(
+    post,
+    path = "/api/restore",
+    tag = "config",
+    params(RestoreQuery),
+    request_body = ConfigBundle,
+    responses((status = 200, description = "Counts of created, updated and skipped entities per table, or the problems found in the bundle", body = ApiResponse<RestoreReport>), (status = 409, description = "Another restore is running or queued"), (status = 500, description = "Internal server error")),
+)]
+pub async fn restore_backup(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Query(query): Query<RestoreQuery>,
+    Json(bundle): Json<Value>,
+) -> Result<Json<ApiResponse<RestoreReport>>, ApiError> {
+    let bundle = match ConfigBundle::from_json(bundle) {
+        Ok(bundle) => bundle,
+        Err(e) => return Err(ApiError::bad_request(e)),
+
+    };
+
+    // Nothing else may read or write the database while the bundle replaces its configuration
+    let _operation = state
+        .scheduler
+        .start(OperationKind::Restore, &format!("Restore configuration backup ({:?})", query.mode), user.as_ref().map(|Extension(user)| user.username.clone()))
+        .await
+        .map_err(operation_conflict)?;
+
+    let devices = state.database.get_devices().await.map_err(|e| ApiError::internal(format!("Failed to list devices: {}", e)))?;
+    let mut running = Vec::new();
+    for device in devices {
+        if state.logging_service.is_device_running(&device.id).await {
+            running.push(device.id);
+        }
+    }
+
+    let mut report = match state.database.restore_config_bundle(&bundle, query.mode).await {
+        Ok(Ok(report)) => report,
+        Ok(Err(errors)) => return Err(ApiError::invalid_fields("Invalid backup", errors)),
+        Err(e) => return Err(ApiError::internal(format!("Failed to restore configuration: {}", e))),
+    };
+
+    // Running devices only pick up configuration changes on restart
+    for device_id in running {
+        match state.database.get_device(&device_id).await {
+            Ok(Some(_)) => match state.logging_service.start_device(&device_id).await {
+                Ok(()) => report.restarted_devices.push(device_id),
+                Err(e) => warn!("Failed to restart device {} after restore: {}", device_id, e),
+            },
+            Ok(None) => {
+                if let Err(e) = state.logging_service.stop_device(&device_id).await {
+                    warn!("Failed to stop device {} removed by restore: {}", device_id, e);
+                }
+                state.logging_service.forget_device_values(&device_id);
+            }
+            Err(e) => warn!("Failed to look up device {} after restore: {}", device_id, e),
+        }
+    }
+
+    info!(
+        "Restored configuration ({:?}): {} devices created, {} updated, {} unchanged",
+        report.mode, report.devices.created, report.devices.updated, report.devices.skipped
+    );
+    audit(&state, &user, "config.restore", "config", "backup", None, audit_snapshot(&report)).await;
+    Ok(Json(ApiResponse::success(report)))
+}
+
+/// Schema version of the database against the latest this build knows, with the migrations
+/// applied so far. Nothing is changed; pending migrations run on the next start.
+#[utoipa::path(
+    get,
+    path = "/api/schema-migrations",
+    tag = "config",
+    responses((status = 200, description = "Success", body = ApiResponse<SchemaStatus>), (status = 401, description = "No session"), (status = 403, description = "Requires the admin role")),
+)]
+pub async fn get_schema_migrations(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+) -> Result<Json<ApiResponse<SchemaStatus>>, ApiError> {
+    admin_caller(&user)?;
+
+    match state.database.schema_status().await {
+        Ok(status) => Ok(Json(ApiResponse::success(status))),
+        Err(e) => Err(ApiError::internal(format!("Failed to read schema migrations: {}", e))),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct StatusResponse {
+    pub devices: Vec<DeviceStatusInfo>,
+    pub total_log_entries: u64,
+    /// Most recent log retention run since startup
+    pub last_retention_run: Option<RetentionRun>,
+    pub server_uptime: String,
+    pub thingsboard_group_cache: GroupDeviceCacheStats,
+    pub thingsboard_session: TbSessionStats,
+    pub database_operations: DatabaseOperationStats,
+    /// None when `[mqtt]` isn't configured
+    pub mqtt: Option<MqttStatus>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DeviceStatusInfo {
+    pub device_id: String,
+    pub status: String,
+    pub last_update: chrono::DateTime<chrono::Utc>,
+    pub error_message: Option<String>,
+    pub connection_count: i64,
+    pub is_running: bool,
+    /// Logged values still waiting to be pushed to ThingsBoard
+    pub telemetry_backlog: i64,
+    /// Last time any of the device's tags were read successfully since the service started
+    pub last_successful_read: Option<chrono::DateTime<chrono::Utc>>,
+    pub seconds_since_last_read: Option<i64>,
+    /// Times the device went offline because its reads stopped, since the service started
+    pub offline_events: u64,
+    /// Share of the device's polls that succeeded over the last 24 hours, from 0 to 1; None
+    /// if it wasn't polled
+    pub success_rate_24h: Option<f64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/status",
+    tag = "devices",
+    responses((status = 200, description = "Success", body = ApiResponse<StatusResponse>)),
+)]
+pub async fn get_status(
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<StatusResponse>>, ApiError> {
     let device_statuses = match state.logging_service.get_all_device_statuses().await {
         Ok(statuses) => statuses,
-        Err(e) => return Ok(Json(ApiResponse::error(format!("Failed to get device statuses: {}", e)))),
+        Err(e) => return Err(ApiError::internal(format!("Failed to get device statuses: {}", e))),
     };
 
+    let telemetry_backlog = state.database.get_telemetry_backlog_counts().await.unwrap_or_else(|e| {
+        warn!("Failed to count queued telemetry: {}", e);
+        std::collections::HashMap::new()
+    });
+    let success_rates = state.logging_service.poll_success_rates(StatsWindow::Day).await.unwrap_or_else(|e| {
+        warn!("Failed to get polling success rates: {}", e);
+        std::collections::HashMap::new()
+    });
     let mut device_status_info = Vec::new();
     
     for status in device_statuses {
         let is_running = state.logging_service.is_device_running(&status.device_id).await;
+        let telemetry_backlog = telemetry_backlog.get(&status.device_id).copied().unwrap_or(0);
+        let read_health = state.logging_service.read_health(&status.device_id);
+        let success_rate_24h = success_rates.get(&status.device_id).copied();
         device_status_info.push(DeviceStatusInfo {
             device_id: status.device_id,
             status: status.status,
@@ -332,34 +1945,47 @@ pub async fn get_status(
             error_message: status.error_message,
             connection_count: status.connection_count,
             is_running,
+            telemetry_backlog,
+            last_successful_read: read_health.last_successful_read,
+            seconds_since_last_read: read_health.last_successful_read
+                .map(|read| (chrono::Utc::now() - read).num_seconds()),
+            offline_events: read_health.offline_events,
+            success_rate_24h,
         });
     }
 
-    // Get total log entries count (simplified)
-    let total_logs = match state.database.get_log_entries(None, Some(1), Some(0)).await {
-        Ok(_) => 0, // Simplified - would need a count query
-        Err(_) => 0,
-    };
+    let total_logs = state.database.count_log_entries(None, None, None).await.unwrap_or_else(|e| {
+        warn!("Failed to count log entries: {}", e);
+        0
+    });
 
     let response = StatusResponse {
         devices: device_status_info,
         total_log_entries: total_logs,
+        last_retention_run: state.logging_service.last_retention_run().await,
         server_uptime: "Running".to_string(), // Simplified
+        thingsboard_group_cache: state.tb_group_cache.stats(),
+        thingsboard_session: state.tb_session.stats(),
+        database_operations: state.database.operation_stats(),
+        mqtt: state.mqtt.as_ref().map(|mqtt| mqtt.status()),
     };
 
     Ok(Json(ApiResponse::success(response)))
 }
 
 // Device Model API endpoints
+#[utoipa::path(
+    get,
+    path = "/api/device-models",
+    tag = "device-models",
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<DeviceModel>>), (status = 500, description = "Internal server error")),
+)]
 pub async fn get_device_models(
     State(state): State<AppState>,
-) -> Result<Json<ApiResponse<Vec<DeviceModel>>>, StatusCode> {
+) -> Result<Json<ApiResponse<Vec<DeviceModel>>>, ApiError> {
     match state.database.get_device_models().await {
         Ok(models) => Ok(Json(ApiResponse::success(models))),
-        Err(e) => {
-            error!("Failed to get device models: {}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
-        }
+        Err(e) => Err(ApiError::internal(format!("Failed to get device models: {}", e))),
     }
 }
 
@@ -373,10 +1999,74 @@ pub async fn get_device_models(
 //     pub description: Option<String>,
 // }
 
+/// Every field of a multipart form, read in full. A malformed or cut-short body, or fields adding
+/// up to more than `[server] max_upload_mb`, fail with the field being read at the time.
+async fn read_multipart_form(multipart: &mut Multipart, max_upload_mb: u64) -> Result<Vec<(String, Vec<u8>)>, FieldError> {
+    let limit = usize::try_from(max_upload_mb.saturating_mul(1024 * 1024)).unwrap_or(usize::MAX);
+    let too_large = |field: &str| FieldError {
+        field: field.to_string(),
+        message: format!("file too large; uploads are limited to {} MB", max_upload_mb),
+    };
+    let failed = |field: &str, e: axum::extract::multipart::MultipartError| match e.status() {
+        StatusCode::PAYLOAD_TOO_LARGE => too_large(field),
+        _ => FieldError { field: field.to_string(), message: format!("could not be read: {}", e.body_text()) },
+    };
+
+    let mut fields = Vec::new();
+    let mut total = 0usize;
+    let mut last_field = "form".to_string();
+    loop {
+        let mut field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => return Ok(fields),
+            Err(e) => return Err(failed(&last_field, e)),
+        };
+        let name = field.name().unwrap_or_default().to_string();
+        last_field = name.clone();
+        let mut data = Vec::new();
+        loop {
+            match field.chunk().await {
+                Ok(Some(chunk)) => {
+                    total += chunk.len();
+                    if total > limit {
+                        return Err(too_large(&name));
+                    }
+                    data.extend_from_slice(&chunk);
+                }
+                Ok(None) => break,
+                Err(e) => return Err(failed(&name, e)),
+            }
+        }
+        fields.push((name, data));
+    }
+}
+
+/// A text field of a multipart form, which browsers always send as UTF-8
+fn multipart_text(field: &str, data: Vec<u8>) -> Result<String, FieldError> {
+    String::from_utf8(data).map_err(|_| FieldError { field: field.to_string(), message: "is not valid UTF-8 text".to_string() })
+}
+
+/// A checkbox-style field of a multipart form
+fn multipart_flag(field: &str, data: Vec<u8>) -> Result<bool, FieldError> {
+    match multipart_text(field, data)?.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" | "on" | "yes" => Ok(true),
+        "false" | "0" | "off" | "no" | "" => Ok(false),
+        _ => Err(FieldError { field: field.to_string(), message: "must be true or false".to_string() }),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/device-models",
+    tag = "device-models",
+    request_body(content_type = "multipart/form-data", description = "Form fields `name`, `manufacturer`, `protocol_type`, `description` and an optional `csv_file` of tag templates"),
+    responses((status = 200, description = "Success", body = ApiResponse<DeviceModel>)),
+)]
 pub async fn create_device_model(
     State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
     mut multipart: Multipart,
-) -> Result<Json<ApiResponse<DeviceModel>>, StatusCode> {
+) -> Result<Json<ApiResponse<DeviceModel>>, ApiError> {
     let mut name = String::new();
     let mut manufacturer: Option<String> = None;
     let mut protocol_type = String::new();
@@ -384,47 +2074,34 @@ pub async fn create_device_model(
     let mut csv_data: Option<String> = None;
 
     // Parse multipart form data
-    while let Some(field) = multipart.next_field().await.unwrap() {
-        let field_name = field.name().unwrap_or("").to_string();
-        
-        match field_name.as_str() {
-            "name" => {
-                name = field.text().await.unwrap_or_default();
-            }
-            "manufacturer" => {
-                let value = field.text().await.unwrap_or_default();
-                if !value.is_empty() {
-                    manufacturer = Some(value);
-                }
-            }
-            "protocol_type" => {
-                protocol_type = field.text().await.unwrap_or_default();
-            }
-            "description" => {
-                let value = field.text().await.unwrap_or_default();
-                if !value.is_empty() {
-                    description = Some(value);
-                }
-            }
-            "csv_file" => {
-                if let Ok(bytes) = field.bytes().await {
-                    if let Ok(text) = String::from_utf8(bytes.to_vec()) {
-                        csv_data = Some(text);
-                    }
-                }
-            }
-            _ => {}
+    let fields = match read_multipart_form(&mut multipart, state.config.server.max_upload_mb).await {
+        Ok(fields) => fields,
+        Err(e) => return Err(ApiError::invalid_fields("Upload failed", vec![e])),
+    };
+    for (field_name, data) in fields {
+        let parsed = match field_name.as_str() {
+            "name" => multipart_text(&field_name, data).map(|value| name = value),
+            "manufacturer" => multipart_text(&field_name, data).map(|value| manufacturer = Some(value).filter(|value| !value.is_empty())),
+            "protocol_type" => multipart_text(&field_name, data).map(|value| protocol_type = value),
+            "description" => multipart_text(&field_name, data).map(|value| description = Some(value).filter(|value| !value.is_empty())),
+            "csv_file" => decode_csv_text(&data)
+                .map(|text| csv_data = Some(text))
+                .map_err(|e| FieldError { field: field_name.clone(), message: e.to_string() }),
+            _ => Ok(()),
+        };
+        if let Err(e) = parsed {
+            return Err(ApiError::invalid_fields("Upload failed", vec![e]));
         }
     }
 
     // Validate required fields
     if name.is_empty() || protocol_type.is_empty() {
-        return Ok(Json(ApiResponse::error("Name and protocol type are required".to_string())));
+        return Err(ApiError::bad_request("Name and protocol type are required".to_string()));
     }
 
     // Validate protocol type
-    if !["modbus_tcp", "modbus_rtu", "iec104"].contains(&protocol_type.as_str()) {
-        return Ok(Json(ApiResponse::error("Invalid protocol type".to_string())));
+    if !["modbus_tcp", "modbus_rtu", "iec104", "simulated"].contains(&protocol_type.as_str()) {
+        return Err(ApiError::bad_request("Invalid protocol type".to_string()));
     }
 
     // Create device model
@@ -433,18 +2110,16 @@ pub async fn create_device_model(
             // Process CSV if provided
             if let Some(csv_content) = csv_data {
                 if let Err(e) = process_csv_tags(&state, &model.id, &csv_content).await {
-                    error!("Failed to process CSV: {}", e);
-                    return Ok(Json(ApiResponse::error(format!("Device model created but failed to process CSV: {}", e))));
+                    return Err(ApiError::bad_request(format!("Device model created but failed to process CSV: {}", e)));
                 }
+
             }
             
             info!("Device model {} created successfully", model.id);
+            audit(&state, &user, "device_model.create", "device_model", &model.id, None, audit_snapshot(&model)).await;
             Ok(Json(ApiResponse::success(model)))
         }
-        Err(e) => {
-            error!("Failed to create device model: {}", e);
-            Ok(Json(ApiResponse::error(format!("Failed to create device model: {}", e))))
-        }
+        Err(e) => Err(ApiError::internal(format!("Failed to create device model: {}", e))),
     }
 }
 
@@ -453,8 +2128,10 @@ async fn process_csv_tags(state: &AppState, device_model_id: &str, csv_content:
     use csv::Reader;
 
     let mut reader = Reader::from_reader(Cursor::new(csv_content));
-    
-    for result in reader.records() {
+    let mut tag_templates = Vec::new();
+    let mut rows = Vec::new();
+
+    for (row_index, result) in reader.records().enumerate() {
         let record = result?;
         
         if record.len() < 3 {
@@ -471,9 +2148,10 @@ async fn process_csv_tags(state: &AppState, device_model_id: &str, csv_content:
             continue;
         }
         
+        check_tag_data_type(data_type).map_err(|e| format!("Row {}: {}", row_index + 2, e))?;
         let address: u16 = address_str.parse().unwrap_or(0);
         
-        let tag_template = TagTemplate {
+        tag_templates.push(TagTemplate {
             id: None,
             model_id: device_model_id.to_string(),
             name: name.to_string(),
@@ -484,65 +2162,369 @@ async fn process_csv_tags(state: &AppState, device_model_id: &str, csv_content:
             scaling_offset: 0.0,
             unit: unit.map(|s| s.to_string()),
             read_only: false,
-        };
-        
-        if let Err(e) = state.database.create_tag_template(&tag_template).await {
-            error!("Failed to create tag template {}: {}", name, e);
+            size: DataType::from_tag_type(data_type).map_or(1, |data_type| data_type.register_width() as i32),
+        });
+        rows.push(row_index + 2);
+    }
+
+    let footprints: Vec<TagFootprint> = tag_templates
+        .iter()
+        .map(|template| {
+            let data_type = DataType::from_tag_type(&template.data_type).unwrap_or(DataType::HoldingRegister);
+            TagFootprint::of(&template.name, &data_type, template.address, 0)
+        })
+        .collect();
+    let conflicts: Vec<String> = find_tag_conflicts(&footprints)
+        .into_iter()
+        .map(|conflict| format!("Row {}: {}", rows[conflict.tag], conflict.message))
+        .collect();
+    if !conflicts.is_empty() {
+        return Err(conflicts.join("; ").into());
+    }
+
+    // Only create templates once every row has passed validation
+    for tag_template in &tag_templates {
+        if let Err(e) = state.database.create_tag_template(tag_template).await {
+            error!("Failed to create tag template {}: {}", tag_template.name, e);
         }
     }
     
     Ok(())
 }
 
+/// Reject data type names the pollers would not know how to decode
+fn check_tag_data_type(data_type: &str) -> Result<(), String> {
+    match DataType::from_tag_type(data_type) {
+        Some(_) => Ok(()),
+        None => Err(format!("Unknown data type '{}'. Valid types: {}", data_type, TAG_DATA_TYPES.join(", "))),
+    }
+}
+
+/// Check the data type and deadbands of every tag in a device request, naming the first bad tag
+fn check_tag_requests(tags: &[CreateTagRequest]) -> Result<(), String> {
+    for tag in tags {
+        check_tag_data_type(&tag.data_type).map_err(|e| format!("Tag '{}': {}", tag.name, e))?;
+        for deadband in [tag.deadband_absolute, tag.deadband_percent].into_iter().flatten() {
+            if !deadband.is_finite() || deadband < 0.0 {
+                return Err(format!("Tag '{}': deadband {} must be zero or positive", tag.name, deadband));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn tag_footprint(name: &str, data_type: &str, address: u16, size: i32, register_type: Option<RegisterType>) -> TagFootprint {
+    let data_type = DataType::from_tag_type(data_type).unwrap_or(DataType::HoldingRegister);
+    TagFootprint::of(name, &data_type, address, size).with_register_type(register_type)
+}
+
+/// The conflicts among a device's tags that matter for its protocol
+fn protocol_tag_conflicts(protocol: &ProtocolConfig, footprints: &[TagFootprint]) -> Vec<TagConflict> {
+    find_tag_conflicts(footprints)
+        .into_iter()
+        .filter(|conflict| conflict.kind == TagConflictKind::DuplicateName || !matches!(protocol, ProtocolConfig::Iec104(_) | ProtocolConfig::Simulated(_)))
+        .collect()
+}
+
+/// Duplicate names, overlapping registers and registers past the address space among a
+/// device's tags, one error per conflict. IEC 104 tags are addressed by IOA and simulated tags
+/// read no registers, so only their names are checked.
+fn tag_request_conflicts(protocol: &ProtocolConfig, tags: &[CreateTagRequest]) -> Vec<FieldError> {
+    let footprints: Vec<TagFootprint> = tags
+        .iter()
+        .map(|tag| tag_footprint(&tag.name, &tag.data_type, tag.address, tag.size, tag.register_type))
+        .collect();
+    protocol_tag_conflicts(protocol, &footprints)
+        .into_iter()
+        .map(|conflict| FieldError {
+            field: match conflict.kind {
+                TagConflictKind::DuplicateName => format!("tags[{}].name", conflict.tag),
+                _ => format!("tags[{}].address", conflict.tag),
+            },
+            message: conflict.message,
+        })
+        .collect()
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/device-models/{id}",
+    tag = "device-models",
+    params(("id" = String, Path, description = "Device model id")),
+    responses((status = 200, description = "Success", body = ApiResponse<DeviceModel>), (status = 404, description = "Not found"), (status = 500, description = "Internal server error")),
+)]
 pub async fn get_device_model(
     State(state): State<AppState>,
     Path(model_id): Path<String>,
-) -> Result<Json<ApiResponse<DeviceModel>>, StatusCode> {
+) -> Result<Json<ApiResponse<DeviceModel>>, ApiError> {
     match state.database.get_device_model(&model_id).await {
         Ok(Some(model)) => Ok(Json(ApiResponse::success(model))),
-        Ok(None) => Err(StatusCode::NOT_FOUND),
-        Err(e) => {
-            error!("Failed to get device model {}: {}", model_id, e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
-        }
+        Ok(None) => Err(ApiError::not_found(format!("Device model {} not found", model_id))),
+        Err(e) => Err(ApiError::internal(format!("Failed to get device model {}: {}", model_id, e))),
     }
 }
 
+#[derive(Deserialize, IntoParams)]
+pub struct DeleteDeviceModelQuery {
+    /// Also delete the devices using the model, with their tags
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Delete a model and its tag templates. While devices use the model nothing is
+/// deleted and `data.dependent_devices` lists them, unless `force=true` is passed.
+#[utoipa::path(
+    post,
+    path = "/api/device-models/{id}/delete",
+    tag = "device-models",
+    params(("id" = String, Path, description = "Device model id"), DeleteDeviceModelQuery),
+    responses((status = 200, description = "Model deleted, or refused with the devices that use it", body = ApiResponse<DeviceModelDeletion>), (status = 404, description = "Not found"), (status = 500, description = "Internal server error")),
+)]
 pub async fn delete_device_model(
     State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
     Path(model_id): Path<String>,
-) -> Result<Json<ApiResponse<()>>, StatusCode> {
-    match state.database.delete_device_model(&model_id).await {
-        Ok(_) => {
+    Query(query): Query<DeleteDeviceModelQuery>,
+) -> Result<Json<ApiResponse<DeviceModelDeletion>>, ApiError> {
+    let before = state.database.get_device_model(&model_id).await.ok().flatten();
+    match state.database.delete_device_model(&model_id, query.force).await {
+        Ok(deletion) if !deletion.deleted => {
+            let devices: Vec<String> = deletion
+                .dependent_devices
+                .iter()
+                .map(|device| format!("{} ({})", device.name, device.id))
+                .collect();
+            Err(ApiError::conflict(format!(
+                "Device model is used by {} device(s): {}. Pass force=true to delete them as well",
+                devices.len(),
+                devices.join(", ")
+            ))
+            .with_details(deletion))
+        }
+        Ok(deletion) => {
+            for device in &deletion.dependent_devices {
+                if let Err(e) = state.logging_service.stop_device(&device.id).await {
+                    warn!("Failed to stop device {} of deleted model {}: {}", device.id, model_id, e);
+                }
+                state.logging_service.forget_device_values(&device.id);
+                state.logging_service.forget_poll_stats(&device.id);
+                audit(&state, &user, "device.delete", "device", &device.id, audit_snapshot(device), None).await;
+            }
+            if !deletion.dependent_devices.is_empty() {
+                reload_virtual_tags(&state).await;
+            }
             info!("Device model {} deleted successfully", model_id);
-            Ok(Json(ApiResponse::success(())))
+            audit(&state, &user, "device_model.delete", "device_model", &model_id, before.as_ref().and_then(audit_snapshot), None).await;
+            Ok(Json(ApiResponse::success(deletion)))
         }
         Err(e) => {
-            error!("Failed to delete device model {}: {}", model_id, e);
             if e.to_string().contains("not found") {
-                Err(StatusCode::NOT_FOUND)
+                Err(ApiError::not_found(format!("Device model {} not found", model_id)))
             } else {
-                Err(StatusCode::INTERNAL_SERVER_ERROR)
+                Err(ApiError::internal(format!("Failed to delete device model {}: {}", model_id, e)))
             }
         }
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/device-models/{id}/tags",
+    tag = "device-models",
+    params(("id" = String, Path, description = "Device model id")),
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<TagTemplate>>), (status = 500, description = "Internal server error")),
+)]
 pub async fn get_tag_templates(
     State(state): State<AppState>,
     Path(model_id): Path<String>,
-) -> Result<Json<ApiResponse<Vec<TagTemplate>>>, StatusCode> {
+) -> Result<Json<ApiResponse<Vec<TagTemplate>>>, ApiError> {
     match state.database.get_tag_templates(&model_id).await {
         Ok(templates) => Ok(Json(ApiResponse::success(templates))),
-        Err(e) => {
-            error!("Failed to get tag templates for model {}: {}", model_id, e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        Err(e) => Err(ApiError::internal(format!("Failed to get tag templates for model {}: {}", model_id, e))),
+    }
+}
+
+/// New values for a tag template; devices built from the model change only on resync
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateTagTemplateRequest {
+    pub name: String,
+    pub address: u16,
+    /// Registers the tag spans; the data type's width when unset
+    pub size: Option<i32>,
+    pub data_type: String,
+    pub description: Option<String>,
+    pub scaling_multiplier: f64,
+    pub scaling_offset: f64,
+    pub unit: Option<String>,
+    pub read_only: bool,
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/device-models/{id}/tags/{tag_id}",
+    tag = "device-models",
+    params(("id" = String, Path, description = "Device model id"), ("tag_id" = i64, Path, description = "Tag template id"), ("Idempotency-Key" = Option<String>, Header, description = "Replays the stored response when a request is retried with the same key and body")),
+    request_body = UpdateTagTemplateRequest,
+    responses((status = 200, description = "Success", body = ApiResponse<TagTemplate>), (status = 404, description = "Model or template not found"), (status = 409, description = "Idempotency-Key reused with a different body, or a job conflict"), (status = 413, description = "Request body too large for idempotency checks"), (status = 500, description = "Internal server error")),
+)]
+pub async fn update_tag_template(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Path((model_id, template_id)): Path<(String, i64)>,
+    Json(request): Json<UpdateTagTemplateRequest>,
+) -> Result<Json<ApiResponse<TagTemplate>>, ApiError> {
+    let model = match state.database.get_device_model(&model_id).await {
+        Ok(Some(model)) => model,
+        Ok(None) => return Err(ApiError::not_found(format!("Device model {} not found", model_id))),
+        Err(e) => return Err(ApiError::internal(format!("Failed to get device model {}: {}", model_id, e))),
+    };
+    let mut templates = state.database.get_tag_templates(&model_id).await.map_err(|e| ApiError::internal(format!("Failed to get tag templates of model {}: {}", model_id, e)))?;
+    let Some(index) = templates.iter().position(|template| template.id == Some(template_id)) else {
+        return Err(ApiError::not_found(format!("Tag template {} not found in model {}", template_id, model_id)));
+    };
+    let before = templates[index].clone();
+
+    let data_type = match DataType::from_tag_type(&request.data_type) {
+        Some(data_type) => data_type,
+        None => {
+            let message = check_tag_data_type(&request.data_type).unwrap_err();
+            return Err(ApiError::invalid_fields("Invalid tag template", vec![FieldError { field: "data_type".to_string(), message }]));
+        }
+    };
+    templates[index] = TagTemplate {
+        id: Some(template_id),
+        model_id: model_id.clone(),
+        name: request.name,
+        address: request.address,
+        size: request.size.unwrap_or(data_type.register_width() as i32),
+        data_type: request.data_type,
+        description: request.description,
+        scaling_multiplier: request.scaling_multiplier,
+        scaling_offset: request.scaling_offset,
+        unit: request.unit,
+        read_only: request.read_only,
+    };
+
+    // Conflicts with the other templates of the model that involve the edited one
+    let footprints: Vec<TagFootprint> = templates
+        .iter()
+        .map(|template| {
+            let data_type = DataType::from_tag_type(&template.data_type).unwrap_or(DataType::HoldingRegister);
+            TagFootprint::of(&template.name, &data_type, template.address, template.size)
+        })
+        .collect();
+    let errors: Vec<FieldError> = find_tag_conflicts(&footprints)
+        .into_iter()
+        .filter(|conflict| conflict.tag == index || conflict.other == Some(index))
+        .filter(|conflict| conflict.kind == TagConflictKind::DuplicateName || !["iec104", "simulated"].contains(&model.protocol_type.as_str()))
+        .map(|conflict| FieldError {
+            field: match conflict.kind {
+                TagConflictKind::DuplicateName => "name".to_string(),
+                _ => "address".to_string(),
+            },
+            message: conflict.message,
+        })
+        .collect();
+    if !errors.is_empty() {
+        return Err(ApiError::invalid_fields("Conflicting tags", errors));
+    }
+
+    let template = templates.swap_remove(index);
+    match state.database.update_tag_template(&template).await {
+        Ok(true) => {
+            if let Some((before, after)) = audit_diff(&before, &template, &["name"]) {
+                audit(&state, &user, "tag_template.update", "device_model", &model_id, Some(before), Some(after)).await;
+            }
+            Ok(Json(ApiResponse::success(template)))
+        }
+        Ok(false) => Err(ApiError::not_found(format!("Tag template {} not found in model {}", template_id, model_id))),
+        Err(e) => Err(ApiError::internal(format!("Failed to update tag template {} of model {}: {}", template_id, model_id, e))),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/device-models/{id}/tags/{tag_id}",
+    tag = "device-models",
+    params(("id" = String, Path, description = "Device model id"), ("tag_id" = i64, Path, description = "Tag template id"), ("Idempotency-Key" = Option<String>, Header, description = "Replays the stored response when a request is retried with the same key and body")),
+    responses((status = 200, description = "Deleted; tags already copied to devices are kept", body = ApiResponse<String>), (status = 404, description = "Model or template not found"), (status = 409, description = "Idempotency-Key reused with a different body, or a job conflict"), (status = 413, description = "Request body too large for idempotency checks"), (status = 500, description = "Internal server error")),
+)]
+pub async fn delete_tag_template(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Path((model_id, template_id)): Path<(String, i64)>,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    let before = state.database.get_tag_templates(&model_id).await.ok()
+        .and_then(|templates| templates.into_iter().find(|template| template.id == Some(template_id)));
+    match state.database.delete_tag_template(&model_id, template_id).await {
+        Ok(true) => {
+            audit(&state, &user, "tag_template.delete", "device_model", &model_id, before.as_ref().and_then(audit_snapshot), None).await;
+            Ok(Json(ApiResponse::success(format!("Tag template {} deleted", template_id))))
+        }
+        Ok(false) => Err(ApiError::not_found(format!("Tag template {} not found in model {}", template_id, model_id))),
+        Err(e) => Err(ApiError::internal(format!("Failed to delete tag template {} of model {}: {}", template_id, model_id, e))),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ResyncDevicesRequest {
+    /// Report what would change without writing anything
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Copy template edits to the tags of every device using the model.
+///
+/// Tags whose address or scaling was edited on the device are reported as conflicts
+/// when the template changed the same field, and are left as they are.
+#[utoipa::path(
+    post,
+    path = "/api/device-models/{id}/resync-devices",
+    tag = "device-models",
+    params(("id" = String, Path, description = "Device model id"), ("Idempotency-Key" = Option<String>, Header, description = "Replays the stored response when a request is retried with the same key and body")),
+    request_body = ResyncDevicesRequest,
+    responses((status = 200, description = "Success", body = ApiResponse<TemplateResync>), (status = 404, description = "Device model not found"), (status = 409, description = "Idempotency-Key reused with a different body, or a job conflict"), (status = 413, description = "Request body too large for idempotency checks"), (status = 500, description = "Internal server error")),
+)]
+pub async fn resync_model_devices(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Path(model_id): Path<String>,
+    Json(request): Json<ResyncDevicesRequest>,
+) -> Result<Json<ApiResponse<TemplateResync>>, ApiError> {
+    match state.database.get_device_model(&model_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return Err(ApiError::not_found(format!("Device model {} not found", model_id))),
+        Err(e) => return Err(ApiError::internal(format!("Failed to get device model {}: {}", model_id, e))),
+    }
+
+    let mut resync = state.database.resync_model_devices(&model_id, request.dry_run).await.map_err(|e| ApiError::internal(format!("Failed to resync devices of model {}: {}", model_id, e)))?;
+
+    if !resync.dry_run {
+        let mut device_ids: Vec<String> = resync.updated.iter().map(|tag| tag.device_id.clone()).collect();
+        device_ids.dedup();
+        // Running devices only pick up tag changes on restart
+        for device_id in device_ids {
+            if state.logging_service.is_device_running(&device_id).await {
+                match state.logging_service.start_device(&device_id).await {
+                    Ok(()) => resync.restarted_devices.push(device_id),
+                    Err(e) => warn!("Failed to restart device {} after template resync: {}", device_id, e),
+                }
+            }
         }
+        info!(
+            "Resynced devices of model {}: {} tags updated, {} conflicts",
+            model_id,
+            resync.updated.len(),
+            resync.conflicts.len()
+        );
+        audit(&state, &user, "device_model.resync", "device_model", &model_id, None, audit_snapshot(&resync)).await;
     }
+
+    Ok(Json(ApiResponse::success(resync)))
 }
 
 // Enhanced Device API endpoints with model support
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct CreateDeviceRequest {
     pub id: String,
     pub name: String,
@@ -554,9 +2536,10 @@ pub struct CreateDeviceRequest {
     pub retry_count: u32,
     pub protocol_config: serde_json::Value,
     pub tags: Vec<CreateTagRequest>,
+    pub strict_types: Option<bool>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct CreateTagRequest {
     pub name: String,
     pub address: u16,
@@ -570,12 +2553,74 @@ pub struct CreateTagRequest {
     pub enabled: bool,
     pub schedule_group_id: Option<String>,
     pub agg_to_field: Option<String>,
+    #[serde(default)]
+    pub write_policy: TagWritePolicy,
+    /// Layout of multi-register values; the data type's default when unset
+    #[serde(default)]
+    pub byte_order: Option<ByteOrder>,
+    /// Skip logging values within this distance of the last logged value
+    #[serde(default)]
+    pub deadband_absolute: Option<f64>,
+    /// Skip logging values within this percentage of the last logged value
+    #[serde(default)]
+    pub deadband_percent: Option<f64>,
+    /// Table numeric tags are read from, `holding` or `input`; holding registers when unset
+    #[serde(default)]
+    pub register_type: Option<RegisterType>,
+}
+
+impl CreateTagRequest {
+    fn into_device_tag(self, device_id: &str) -> DeviceTag {
+        DeviceTag {
+            id: None,
+            device_id: device_id.to_string(),
+            name: self.name,
+            address: self.address,
+            size: self.size,
+            data_type: self.data_type,
+            description: self.description,
+            scaling_multiplier: self.scaling_multiplier,
+            scaling_offset: self.scaling_offset,
+            unit: self.unit,
+            read_only: self.read_only,
+            enabled: self.enabled,
+            schedule_group_id: self.schedule_group_id,
+            agg_to_field: self.agg_to_field,
+            write_policy: self.write_policy,
+            byte_order: self.byte_order,
+            deadband_absolute: self.deadband_absolute,
+            deadband_percent: self.deadband_percent,
+            register_type: self.register_type,
+        }
+    }
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/devices-enhanced",
+    tag = "devices",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Replays the stored response when a request is retried with the same key and body")),
+    request_body = CreateDeviceRequest,
+    responses((status = 200, description = "Success", body = ApiResponse<String>), (status = 409, description = "Idempotency-Key reused with a different body, or a job conflict"), (status = 413, description = "Request body too large for idempotency checks"), (status = 500, description = "Internal server error")),
+)]
 pub async fn create_device_with_tags(
     State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
     Json(request): Json<CreateDeviceRequest>,
-) -> Result<Json<ApiResponse<String>>, StatusCode> {
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    store_new_device(&state, &user, request).await?;
+    Ok(Json(ApiResponse::success("Device created successfully".to_string())))
+}
+
+/// Validate and store a new device with its tags, returning how many tags were stored
+async fn store_new_device(state: &AppState, user: &Option<Extension<LocalUser>>, request: CreateDeviceRequest) -> Result<usize, ApiError> {
+    check_tag_requests(&request.tags).map_err(ApiError::bad_request)?;
+    let protocol = ProtocolConfig::from_json(&request.protocol_config)
+        .map_err(|errors| ApiError::invalid_fields("Invalid protocol config", errors))?;
+    let conflicts = tag_request_conflicts(&protocol, &request.tags);
+    if !conflicts.is_empty() {
+        return Err(ApiError::invalid_fields("Conflicting tags", conflicts));
+    }
     let now = chrono::Utc::now();
 
     // Create device instance
@@ -588,65 +2633,434 @@ pub async fn create_device_with_tags(
         polling_interval_ms: request.polling_interval_ms,
         timeout_ms: request.timeout_ms,
         retry_count: request.retry_count,
-        protocol_config: serde_json::to_string(&request.protocol_config).unwrap_or_default(),
+        protocol_config: serde_json::to_string(&protocol).unwrap_or_default(),
         tb_device_id: None,
         tb_group_id: None,
         created_at: now,
         updated_at: now,
+        strict_types: request.strict_types.unwrap_or(false),
     };
 
     // Create device in database
     if let Err(e) = state.database.create_device(&device).await {
-        error!("Failed to create device: {}", e);
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        if database_error_status(&e) == Some(StatusCode::CONFLICT) {
+            return Err(ApiError::conflict(format!("Device {} already exists", device.id)));
+        }
+        return Err(ApiError::internal(format!("Failed to create device: {}", e)));
     }
 
     // Create device tags
-    let device_tags: Vec<DeviceTag> = request.tags.into_iter().map(|tag| DeviceTag {
-        id: None,
-        device_id: request.id.clone(),
-        name: tag.name,
-        address: tag.address,
-        size: tag.size,
-        data_type: tag.data_type,
-        description: tag.description,
-        scaling_multiplier: tag.scaling_multiplier,
-        scaling_offset: tag.scaling_offset,
-        unit: tag.unit,
-        read_only: tag.read_only,
-        enabled: tag.enabled,
-        schedule_group_id: tag.schedule_group_id,
-        agg_to_field: tag.agg_to_field,
-    }).collect();
+    let device_tags: Vec<DeviceTag> = request.tags.into_iter().map(|tag| tag.into_device_tag(&request.id)).collect();
 
     if let Err(e) = state.database.create_device_tags(&request.id, &device_tags).await {
-        error!("Failed to create device tags: {}", e);
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        return Err(ApiError::internal(format!("Failed to create device tags: {}", e)));
     }
 
     info!("Created device {} with {} tags", request.id, device_tags.len());
-    Ok(Json(ApiResponse::success("Device created successfully".to_string())))
+    audit(state, user, "device.create", "device", &device.id, None, audit_snapshot(&device)).await;
+    Ok(device_tags.len())
+}
+
+/// A device whose tags are copied from the tag templates of its model
+#[derive(Deserialize, ToSchema)]
+pub struct CreateDeviceFromModelRequest {
+    pub id: String,
+    pub name: String,
+    pub serial_no: Option<String>,
+    pub model_id: String,
+    pub enabled: bool,
+    pub polling_interval_ms: u32,
+    pub timeout_ms: u32,
+    pub retry_count: u32,
+    /// Its `type` must match the model's protocol
+    pub protocol_config: serde_json::Value,
+    pub strict_types: Option<bool>,
+    /// Added to every template address, for devices whose register map is shifted
+    #[serde(default)]
+    pub address_offset: i32,
+    /// Schedule group of every tag; the default polling schedule when unset
+    #[serde(default)]
+    pub schedule_group_id: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DeviceFromModelResult {
+    pub device_id: String,
+    /// Tags copied from the model's templates
+    pub tags_instantiated: usize,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/devices-enhanced/from-model",
+    tag = "devices",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Replays the stored response when a request is retried with the same key and body")),
+    request_body = CreateDeviceFromModelRequest,
+    responses((status = 200, description = "Success", body = ApiResponse<DeviceFromModelResult>), (status = 404, description = "Device model not found"), (status = 409, description = "Idempotency-Key reused with a different body, or a job conflict"), (status = 413, description = "Request body too large for idempotency checks"), (status = 500, description = "Internal server error")),
+)]
+pub async fn create_device_from_model(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Json(request): Json<CreateDeviceFromModelRequest>,
+) -> Result<Json<ApiResponse<DeviceFromModelResult>>, ApiError> {
+    let model = match state.database.get_device_model(&request.model_id).await {
+        Ok(Some(model)) => model,
+        Ok(None) => return Err(ApiError::not_found(format!("Device model {} not found", request.model_id))),
+        Err(e) => return Err(ApiError::internal(format!("Failed to get device model {}: {}", request.model_id, e))),
+    };
+    let templates = state.database.get_tag_templates(&model.id).await.map_err(|e| ApiError::internal(format!("Failed to get tag templates of model {}: {}", model.id, e)))?;
+
+    let mut errors = Vec::new();
+    if let Some(protocol_type) = request.protocol_config.get("type").and_then(|value| value.as_str()) {
+        if protocol_type != model.protocol_type {
+            errors.push(FieldError {
+                field: "protocol_config.type".to_string(),
+                message: format!("must be {} to match model '{}'", model.protocol_type, model.name),
+            });
+        }
+    }
+    if let Some(group_id) = &request.schedule_group_id {
+        match state.database.get_schedule_group(group_id).await {
+            Ok(Some(_)) => {}
+            Ok(None) => errors.push(FieldError {
+                field: "schedule_group_id".to_string(),
+                message: format!("no schedule group '{}'", group_id),
+            }),
+            Err(e) => return Err(ApiError::internal(format!("Failed to get schedule group {}: {}", group_id, e))),
+        }
+    }
+
+    let mut tags = Vec::new();
+    let mut links = Vec::new();
+    for template in templates {
+        let address = template.address as i32 + request.address_offset;
+        let Ok(address) = u16::try_from(address) else {
+            errors.push(FieldError {
+                field: "address_offset".to_string(),
+                message: format!("moves tag '{}' to address {}, outside 0-65535", template.name, address),
+            });
+            continue;
+        };
+        links.push(TagTemplateLink {
+            device_id: request.id.clone(),
+            tag_name: template.name.clone(),
+            template_id: template.id.unwrap_or_default(),
+            address_offset: request.address_offset,
+            synced_address: template.address,
+            synced_scaling_multiplier: template.scaling_multiplier,
+            synced_scaling_offset: template.scaling_offset,
+        });
+        tags.push(CreateTagRequest {
+            name: template.name,
+            address,
+            size: template.size,
+            data_type: template.data_type,
+            description: template.description,
+            scaling_multiplier: template.scaling_multiplier,
+            scaling_offset: template.scaling_offset,
+            unit: template.unit,
+            read_only: template.read_only,
+            enabled: true,
+            schedule_group_id: request.schedule_group_id.clone(),
+            agg_to_field: None,
+            write_policy: TagWritePolicy::default(),
+            byte_order: None,
+            deadband_absolute: None,
+            deadband_percent: None,
+            register_type: None,
+        });
+    }
+    if !errors.is_empty() {
+        return Err(ApiError::invalid_fields("Cannot create device from model", errors));
+    }
+
+    let device_id = request.id.clone();
+    let device = CreateDeviceRequest {
+        id: request.id,
+        name: request.name,
+        serial_no: request.serial_no,
+        model_id: Some(model.id),
+        enabled: request.enabled,
+        polling_interval_ms: request.polling_interval_ms,
+        timeout_ms: request.timeout_ms,
+        retry_count: request.retry_count,
+        protocol_config: request.protocol_config,
+        tags,
+        strict_types: request.strict_types,
+    };
+    let tags_instantiated = store_new_device(&state, &user, device).await?;
+    // Without links a resync falls back to matching tags by name
+    if let Err(e) = state.database.link_tag_templates(&links).await {
+        warn!("Failed to record tag templates of device {}: {}", device_id, e);
+    }
+    Ok(Json(ApiResponse::success(DeviceFromModelResult { device_id, tags_instantiated })))
+}
+
+/// Most copies a single duplicate request may create
+const MAX_DEVICE_COPIES: u32 = 100;
+
+/// Copies of a device that differ only in name, id, host and slave id.
+/// `{n}` in the patterns is replaced with the copy number, starting at 1.
+#[derive(Deserialize, ToSchema)]
+pub struct DuplicateDeviceRequest {
+    pub count: u32,
+    /// e.g. "Inverter {n}"
+    pub name_pattern: String,
+    /// Defaults to "<source id>-{n}"
+    pub id_pattern: Option<String>,
+    /// IPv4 address of the first copy; each further copy adds one to the last octet
+    pub host_start: Option<String>,
+    /// One host per copy, instead of `host_start`
+    pub host_list: Option<Vec<String>>,
+    /// Slave id of the first copy; each further copy adds one
+    pub slave_id_start: Option<u8>,
+}
+
+/// Clone a device and all its tags `count` times. Copies start disabled, without a
+/// serial number and not yet synced to ThingsBoard.
+#[utoipa::path(
+    post,
+    path = "/api/devices-enhanced/{id}/duplicate",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device to copy"), ("Idempotency-Key" = Option<String>, Header, description = "Replays the stored response when a request is retried with the same key and body")),
+    request_body = DuplicateDeviceRequest,
+    responses((status = 200, description = "Ids of the created devices", body = ApiResponse<Vec<String>>), (status = 404, description = "Device not found"), (status = 409, description = "Idempotency-Key reused with a different body, or a job conflict"), (status = 413, description = "Request body too large for idempotency checks"), (status = 500, description = "Internal server error")),
+)]
+pub async fn duplicate_device(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Path(device_id): Path<String>,
+    Json(request): Json<DuplicateDeviceRequest>,
+) -> Result<Json<ApiResponse<Vec<String>>>, ApiError> {
+    let source = match state.database.get_device(&device_id).await {
+        Ok(Some(device)) => device,
+        Ok(None) => return Err(ApiError::not_found(format!("Device {} not found", device_id))),
+        Err(e) => return Err(ApiError::internal(format!("Failed to get device {}: {}", device_id, e))),
+    };
+    let protocol = source.protocol().map_err(|e| ApiError::internal(format!("Device {} has an unreadable protocol config: {}", device_id, e)))?;
+    let tags = state.database.get_device_tags(&device_id).await.map_err(|e| ApiError::internal(format!("Failed to get tags of device {}: {}", device_id, e)))?;
+    let existing = state.database.get_devices().await.map_err(|e| ApiError::internal(format!("Failed to list devices: {}", e)))?;
+
+    let mut errors = Vec::new();
+    let field_error = |field: &str, message: String| FieldError { field: field.to_string(), message };
+    if request.count == 0 || request.count > MAX_DEVICE_COPIES {
+        errors.push(field_error("count", format!("must be from 1 to {}", MAX_DEVICE_COPIES)));
+    }
+    let count = request.count.clamp(1, MAX_DEVICE_COPIES) as usize;
+
+    let id_pattern = request.id_pattern.clone().unwrap_or_else(|| format!("{}-{{n}}", source.id));
+    let names: Vec<String> = (1..=count).map(|n| request.name_pattern.replace("{n}", &n.to_string())).collect();
+    let ids: Vec<String> = (1..=count).map(|n| id_pattern.replace("{n}", &n.to_string())).collect();
+    for (field, what, generated, taken) in [
+        ("name_pattern", "names", &names, existing.iter().map(|device| device.name.as_str()).collect::<std::collections::HashSet<_>>()),
+        ("id_pattern", "ids", &ids, existing.iter().map(|device| device.id.as_str()).collect::<std::collections::HashSet<_>>()),
+    ] {
+        if generated.iter().any(|value| value.trim().is_empty()) {
+            errors.push(field_error(field, "must not generate an empty value".to_string()));
+            continue;
+        }
+        let mut seen = std::collections::HashSet::new();
+        for value in generated {
+            if !seen.insert(value.as_str()) {
+                errors.push(field_error(field, format!("generates '{}' more than once; add {{n}}", value)));
+                break;
+            }
+        }
+        let collisions: Vec<&str> = generated.iter().map(|value| value.as_str()).filter(|value| taken.contains(value)).collect();
+        if !collisions.is_empty() {
+            errors.push(field_error(field, format!("generates {} already used by existing devices: {}", what, collisions.join(", "))));
+        }
+    }
+
+    let hosts: Option<Vec<String>> = match (&request.host_start, &request.host_list) {
+        (Some(_), Some(_)) => {
+            errors.push(field_error("host_list", "cannot be combined with host_start".to_string()));
+            None
+        }
+        (Some(start), None) => match start.parse::<std::net::Ipv4Addr>() {
+            Ok(start) => {
+                let [a, b, c, d] = start.octets();
+                if d as usize + count - 1 > 255 {
+                    errors.push(field_error("host_start", format!("leaves room for only {} copies up to {}.{}.{}.255", 256 - d as usize, a, b, c)));
+                    None
+                } else {
+                    Some((0..count).map(|i| format!("{}.{}.{}.{}", a, b, c, d as usize + i)).collect())
+                }
+            }
+            Err(_) => {
+                errors.push(field_error("host_start", format!("'{}' is not an IPv4 address", start)));
+                None
+            }
+        },
+        (None, Some(list)) => {
+            if list.len() != count {
+                errors.push(field_error("host_list", format!("has {} hosts for {} copies", list.len(), count)));
+            } else if let Some(i) = list.iter().position(|host| host.trim().is_empty()) {
+                errors.push(field_error("host_list", format!("host {} is empty", i + 1)));
+            }
+            Some(list.clone())
+        }
+        (None, None) => None,
+    };
+    if hosts.is_some() && matches!(protocol, ProtocolConfig::ModbusRtu(_) | ProtocolConfig::Simulated(_)) {
+        let protocol_type = if matches!(protocol, ProtocolConfig::ModbusRtu(_)) { "modbus_rtu" } else { "simulated" };
+        errors.push(field_error(if request.host_list.is_some() { "host_list" } else { "host_start" }, format!("{} devices have no host", protocol_type)));
+    }
+    if let Some(start) = request.slave_id_start {
+        match &protocol {
+            ProtocolConfig::Iec104(_) => errors.push(field_error("slave_id_start", "iec104 devices have no slave id".to_string())),
+            ProtocolConfig::Simulated(_) => errors.push(field_error("slave_id_start", "simulated devices have no slave id".to_string())),
+            ProtocolConfig::ModbusRtu(_) if start == 0 => errors.push(field_error("slave_id_start", "must be from 1 to 247".to_string())),
+            _ if start as usize + count - 1 > 247 => {
+                errors.push(field_error("slave_id_start", format!("reaches {} after {} copies; the last slave id is 247", start as usize + count - 1, count)))
+            }
+            _ => {}
+        }
+    }
+    if !errors.is_empty() {
+        return Err(ApiError::invalid_fields("Cannot duplicate device", errors));
+    }
+
+    let mut created = Vec::new();
+    for (i, (id, name)) in ids.into_iter().zip(names).enumerate() {
+        let mut protocol = protocol.clone();
+        let host = hosts.as_ref().map(|hosts| hosts[i].trim().to_string());
+        let slave_id = request.slave_id_start.map(|start| start + i as u8);
+        match &mut protocol {
+            ProtocolConfig::ModbusTcp(config) => {
+                config.host = host.unwrap_or(config.host.clone());
+                config.slave_id = slave_id.unwrap_or(config.slave_id);
+            }
+            ProtocolConfig::ModbusRtu(config) => config.slave_id = slave_id.unwrap_or(config.slave_id),
+            ProtocolConfig::Iec104(config) => config.host = host.unwrap_or(config.host.clone()),
+            ProtocolConfig::Simulated(_) => {}
+        }
+
+        let now = Utc::now();
+        let device = DeviceInstance {
+            id: id.clone(),
+            name,
+            serial_no: None,
+            enabled: false,
+            protocol_config: serde_json::to_string(&protocol).unwrap_or_default(),
+            tb_device_id: None,
+            created_at: now,
+            updated_at: now,
+            ..source.clone()
+        };
+        let tags: Vec<DeviceTag> = tags.iter().map(|tag| DeviceTag { id: None, device_id: id.clone(), ..tag.clone() }).collect();
+        if let Err(e) = state.database.create_device_copy(&source.id, &device, &tags).await {
+            error!("Failed to create copy {} of device {}: {}", id, source.id, e);
+            return Err(ApiError::internal(format!("Created {} of {} copies before failing on {}", created.len(), count, id))
+                .with_details(json!({ "created": created })));
+        }
+        audit(&state, &user, "device.create", "device", &id, None, audit_snapshot(&device)).await;
+        created.push(id);
+    }
+
+    info!("Created {} copies of device {} with {} tags each", created.len(), source.id, tags.len());
+    Ok(Json(ApiResponse::success(created)))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct TestConnectionRequest {
+    /// Same shape as `protocol_config` of a device
+    pub protocol_config: serde_json::Value,
+    /// Defaults to 5000ms; capped at 10s
+    pub timeout_ms: Option<u32>,
+}
+
+/// Try to reach a device with a protocol config before saving it; nothing is stored
+#[utoipa::path(
+    post,
+    path = "/api/devices-enhanced/test-connection",
+    tag = "devices",
+    request_body = TestConnectionRequest,
+    responses(
+        (status = 200, description = "Test ran; `data.success` tells whether the device answered", body = ApiResponse<ConnectionTestResult>),
+        (status = 400, description = "Invalid protocol config, with one error per field", body = ApiResponse<Vec<FieldError>>),
+    ),
+)]
+pub async fn test_device_connection(
+    Json(request): Json<TestConnectionRequest>,
+) -> Result<Json<ApiResponse<ConnectionTestResult>>, ApiError> {
+    let protocol = ProtocolConfig::from_json(&request.protocol_config)
+        .map_err(|errors| ApiError::invalid_fields("Invalid protocol config", errors))?;
+
+    let result = LoggingService::test_connection(protocol, request.timeout_ms.unwrap_or(5000) as u64).await;
+    info!("Connection test {} after {}ms: {}", if result.success { "passed" } else { "failed" }, result.latency_ms, result.message);
+    Ok(Json(ApiResponse::success(result)))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct DiscoverSunSpecRequest {
+    pub host: String,
+    /// Defaults to 502
+    pub port: Option<u16>,
+    /// Defaults to 1
+    pub slave_id: Option<u8>,
+    /// Time allowed for the whole walk; defaults to 5000ms, capped at 10s
+    pub timeout_ms: Option<u32>,
 }
 
+/// Read the SunSpec models of a Modbus TCP inverter and propose its tags; nothing is stored.
+/// The proposed tags can be sent as they are in the `tags` of `POST /api/devices-enhanced`.
+#[utoipa::path(
+    post,
+    path = "/api/devices-enhanced/discover-sunspec",
+    tag = "devices",
+    request_body = DiscoverSunSpecRequest,
+    responses(
+        (status = 200, description = "The device's identity and proposed tags", body = ApiResponse<SunSpecDiscovery>),
+        (status = 400, description = "Invalid host, port or slave id, with one error per field", body = ApiResponse<Vec<FieldError>>),
+        (status = 502, description = "The device didn't answer or isn't a SunSpec device", body = ApiResponse<String>),
+    ),
+)]
+pub async fn discover_sunspec_device(
+    Json(request): Json<DiscoverSunSpecRequest>,
+) -> Result<Json<ApiResponse<SunSpecDiscovery>>, ApiError> {
+    let protocol_config = serde_json::json!({
+        "type": "modbus_tcp",
+        "host": request.host,
+        "port": request.port.unwrap_or(502),
+        "slave_id": request.slave_id.unwrap_or(1),
+    });
+    let config = match ProtocolConfig::from_json(&protocol_config) {
+        Ok(ProtocolConfig::ModbusTcp(config)) => config,
+        Ok(_) => unreachable!("a modbus_tcp protocol config parses as Modbus TCP"),
+        Err(errors) => return Err(ApiError::invalid_fields("Invalid SunSpec discovery request", errors)),
+    };
+
+    let timeout = std::time::Duration::from_millis(request.timeout_ms.unwrap_or(5000) as u64);
+    match discover_sunspec(config, timeout).await {
+        Ok(discovery) => Ok(Json(ApiResponse::success(discovery))),
+        Err(e) => {
+            warn!("SunSpec discovery at {} failed: {}", request.host, e);
+            Err(ApiError::bad_gateway(e.to_string()))
+
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/devices-enhanced",
+    tag = "devices",
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<DeviceWithTags>>), (status = 500, description = "Internal server error")),
+)]
 pub async fn get_devices_enhanced(
     State(state): State<AppState>,
-) -> Result<Json<ApiResponse<Vec<DeviceWithTags>>>, StatusCode> {
+) -> Result<Json<ApiResponse<Vec<DeviceWithTags>>>, ApiError> {
     let devices = match state.database.get_devices().await {
         Ok(devices) => devices,
-        Err(e) => {
-            error!("Failed to get devices: {}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
+        Err(e) => return Err(ApiError::internal(format!("Failed to get devices: {}", e))),
     };
 
     let mut devices_with_tags = Vec::new();
     for device in devices {
         let tags = match state.database.get_device_tags(&device.id).await {
             Ok(tags) => tags,
-            Err(e) => {
-                error!("Failed to get tags for device {}: {}", device.id, e);
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
-            }
+            Err(e) => return Err(ApiError::internal(format!("Failed to get tags for device {}: {}", device.id, e))),
         };
 
         // Get device status from database
@@ -669,25 +3083,26 @@ pub async fn get_devices_enhanced(
     Ok(Json(ApiResponse::success(devices_with_tags)))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/devices-enhanced/{id}",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id")),
+    responses((status = 200, description = "Success", body = ApiResponse<DeviceWithTags>), (status = 404, description = "Not found"), (status = 500, description = "Internal server error")),
+)]
 pub async fn get_device_enhanced(
     State(state): State<AppState>,
     Path(device_id): Path<String>,
-) -> Result<Json<ApiResponse<DeviceWithTags>>, StatusCode> {
+) -> Result<Json<ApiResponse<DeviceWithTags>>, ApiError> {
     let device = match state.database.get_device(&device_id).await {
         Ok(Some(device)) => device,
-        Ok(None) => return Err(StatusCode::NOT_FOUND),
-        Err(e) => {
-            error!("Failed to get device {}: {}", device_id, e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
+        Ok(None) => return Err(ApiError::not_found(format!("Device {} not found", device_id))),
+        Err(e) => return Err(ApiError::internal(format!("Failed to get device {}: {}", device_id, e))),
     };
 
     let tags = match state.database.get_device_tags(&device_id).await {
         Ok(tags) => tags,
-        Err(e) => {
-            error!("Failed to get tags for device {}: {}", device_id, e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
+        Err(e) => return Err(ApiError::internal(format!("Failed to get tags for device {}: {}", device_id, e))),
     };
 
     // Get device status from database
@@ -708,25 +3123,25 @@ pub async fn get_device_enhanced(
 }
 
 /// Get all devices that are not synced to ThingsBoard (tb_device_id is NULL)
+#[utoipa::path(
+    get,
+    path = "/api/devices-unsynced",
+    tag = "devices",
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<DeviceWithTags>>), (status = 500, description = "Internal server error")),
+)]
 pub async fn get_unsynced_devices(
     State(state): State<AppState>,
-) -> Result<Json<ApiResponse<Vec<DeviceWithTags>>>, StatusCode> {
+) -> Result<Json<ApiResponse<Vec<DeviceWithTags>>>, ApiError> {
     let devices = match state.database.get_unsynced_devices().await {
         Ok(devices) => devices,
-        Err(e) => {
-            error!("Failed to get unsynced devices: {}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
+        Err(e) => return Err(ApiError::internal(format!("Failed to get unsynced devices: {}", e))),
     };
 
     let mut devices_with_tags = Vec::new();
     for device in devices {
         let tags = match state.database.get_device_tags(&device.id).await {
             Ok(tags) => tags,
-            Err(e) => {
-                error!("Failed to get tags for device {}: {}", device.id, e);
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
-            }
+            Err(e) => return Err(ApiError::internal(format!("Failed to get tags for device {}: {}", device.id, e))),
         };
 
         // Get device status from database
@@ -750,26 +3165,27 @@ pub async fn get_unsynced_devices(
 }
 
 /// Get all devices that belong to a specific ThingsBoard entity group
+#[utoipa::path(
+    get,
+    path = "/api/devices-by-group/{group_id}",
+    tag = "devices",
+    params(("group_id" = String, Path, description = "ThingsBoard entity group id")),
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<DeviceWithTags>>), (status = 500, description = "Internal server error")),
+)]
 pub async fn get_devices_by_group(
     State(state): State<AppState>,
     Path(group_id): Path<String>,
-) -> Result<Json<ApiResponse<Vec<DeviceWithTags>>>, StatusCode> {
+) -> Result<Json<ApiResponse<Vec<DeviceWithTags>>>, ApiError> {
     let devices = match state.database.get_devices_by_group_id(&group_id).await {
         Ok(devices) => devices,
-        Err(e) => {
-            error!("Failed to get devices for group {}: {}", group_id, e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
+        Err(e) => return Err(ApiError::internal(format!("Failed to get devices for group {}: {}", group_id, e))),
     };
 
     let mut devices_with_tags = Vec::new();
     for device in devices {
         let tags = match state.database.get_device_tags(&device.id).await {
             Ok(tags) => tags,
-            Err(e) => {
-                error!("Failed to get tags for device {}: {}", device.id, e);
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
-            }
+            Err(e) => return Err(ApiError::internal(format!("Failed to get tags for device {}: {}", device.id, e))),
         };
 
         // Get device status from database
@@ -792,811 +3208,2800 @@ pub async fn get_devices_by_group(
     Ok(Json(ApiResponse::success(devices_with_tags)))
 }
 
+/// Mismatches seen at least this many times are flagged as probable configuration errors
+const RECURRING_TYPE_MISMATCH_THRESHOLD: i64 = 10;
+
+/// A tag of a device's register map with its data type mismatch counter
+#[derive(Serialize, ToSchema)]
+pub struct DeviceTagInfo {
+    #[serde(flatten)]
+    pub tag: DeviceTag,
+    /// Reads whose raw registers were out of range for the declared data type
+    pub type_mismatch_count: i64,
+    pub last_type_mismatch: Option<String>,
+    /// Mismatches recur often enough that the declared data type is probably wrong
+    pub probable_config_error: bool,
+}
+
+/// Get a device's register map, flagging tags whose reads keep failing data type validation
+#[utoipa::path(
+    get,
+    path = "/api/devices/{id}/tags",
+    tag = "tags",
+    params(("id" = String, Path, description = "Device id")),
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<DeviceTagInfo>>), (status = 404, description = "Not found"), (status = 500, description = "Internal server error")),
+)]
 pub async fn get_device_tags_api(
     State(state): State<AppState>,
     Path(device_id): Path<String>,
-) -> Result<Json<ApiResponse<Vec<DeviceTag>>>, StatusCode> {
-    match state.database.get_device_tags(&device_id).await {
-        Ok(tags) => Ok(Json(ApiResponse::success(tags))),
-        Err(e) => {
-            error!("Failed to get device tags for {}: {}", device_id, e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+) -> Result<Json<ApiResponse<Vec<DeviceTagInfo>>>, ApiError> {
+    let tags = match state.database.get_device_tags(&device_id).await {
+        Ok(tags) => tags,
+        Err(e) => return Err(ApiError::internal(format!("Failed to get device tags for {}: {}", device_id, e))),
+    };
+    let mut mismatches: std::collections::HashMap<String, crate::database::TagTypeMismatch> = match state.database.get_type_mismatches(&device_id).await {
+        Ok(mismatches) => mismatches.into_iter().map(|mismatch| (mismatch.tag_name.clone(), mismatch)).collect(),
+        Err(e) => return Err(ApiError::internal(format!("Failed to get type mismatches for device {}: {}", device_id, e))),
+    };
+
+    Ok(Json(ApiResponse::success(
+        tags.into_iter()
+            .map(|tag| {
+                let mismatch = mismatches.remove(&tag.name);
+                let type_mismatch_count = mismatch.as_ref().map_or(0, |mismatch| mismatch.mismatch_count);
+                DeviceTagInfo {
+                    tag,
+                    type_mismatch_count,
+                    last_type_mismatch: mismatch.map(|mismatch| mismatch.last_reason),
+                    probable_config_error: type_mismatch_count >= RECURRING_TYPE_MISMATCH_THRESHOLD,
+                }
+            })
+            .collect(),
+    )))
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct TagSearchQuery {
+    pub search_id: Option<String>,
+    pub address: Option<u16>,
+    pub address_min: Option<u16>,
+    pub address_max: Option<u16>,
+    pub name: Option<String>,
+    pub data_type: Option<String>,
+    pub unit: Option<String>,
+    pub schedule_group_id: Option<String>,
+    pub enabled: Option<bool>,
+    pub page: Option<u32>,
+    pub page_size: Option<u32>,
+    pub count_only: Option<bool>,
+}
+
+impl TagSearchQuery {
+    fn filter(&self) -> TagSearchFilter {
+        TagSearchFilter {
+            address: self.address,
+            address_min: self.address_min,
+            address_max: self.address_max,
+            name_pattern: self.name.clone(),
+            data_type: self.data_type.clone(),
+            unit: self.unit.clone(),
+            schedule_group_id: self.schedule_group_id.clone(),
+            enabled: self.enabled,
         }
     }
 }
 
-pub async fn update_device_with_tags(
+#[derive(Serialize, ToSchema)]
+pub struct TagSearchResponse {
+    pub total: i64,
+    pub page: u32,
+    pub page_size: u32,
+    /// Omitted in count-only mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub results: Option<Vec<TagSearchResult>>,
+}
+
+/// Resolve a saved search id, falling back to an inline filter
+async fn resolve_tag_search(
+    state: &AppState,
+    search_id: Option<&str>,
+    filter: TagSearchFilter,
+) -> Result<TagSearchFilter, ApiError> {
+    match search_id {
+        Some(search_id) => match state.database.get_saved_tag_search(search_id).await {
+            Ok(Some(search)) => Ok(search.filter),
+            Ok(None) => Err(ApiError::not_found(format!("Saved tag search {} not found", search_id))),
+            Err(e) => Err(ApiError::internal(format!("Failed to load saved tag search {}: {}", search_id, e))),
+        },
+        None => Ok(filter),
+    }
+}
+
+/// Search tags across every device by address, name, type, unit or schedule group
+#[utoipa::path(
+    get,
+    path = "/api/tags/search",
+    tag = "tags",
+    params(TagSearchQuery),
+    responses((status = 200, description = "Success", body = ApiResponse<TagSearchResponse>), (status = 404, description = "Saved search not found", body = ApiResponse<TagSearchResponse>), (status = 500, description = "Internal server error", body = ApiResponse<TagSearchResponse>), (status = 504, description = "Query timed out", body = ApiResponse<TagSearchResponse>)),
+)]
+pub async fn search_tags(
     State(state): State<AppState>,
-    Path(device_id): Path<String>,
-    Json(request): Json<CreateDeviceRequest>,
-) -> Result<Json<ApiResponse<String>>, StatusCode> {
-    let now = chrono::Utc::now();
+    Query(query): Query<TagSearchQuery>,
+) -> Result<Json<ApiResponse<TagSearchResponse>>, ApiError> {
+    let filter = resolve_tag_search(&state, query.search_id.as_deref(), query.filter()).await?;
+    let page
+ = query.page.unwrap_or(1).max(1);
+    let page_size = query.page_size.unwrap_or(50).clamp(1, 500);
+
+    let total = match with_query_timeout(&state, state.database.count_tag_search(&filter)).await? {
+        Ok(total) => total,
+        Err(e) => return Err(ApiError::internal(format!("Failed to count tag search results: {}", e))),
+    };
 
-    // Get existing device to preserve tb_device_id and tb_group_id
-    let existing_device = match state.database.get_device(&device_id).await {
-        Ok(Some(device)) => device,
-        Ok(None) => {
-            error!("Device not found: {}", device_id);
-            return Ok(Json(ApiResponse::error(format!("Device not found: {}", device_id))));
-        }
-        Err(e) => {
-            error!("Failed to get existing device: {}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    let results = if query.count_only.unwrap_or(false) {
+        None
+    } else {
+        match with_query_timeout(&state, state.database.search_tags(&filter, page_size, (page - 1) * page_size)).await? {
+            Ok(results) => Some(results),
+            Err(e) => return Err(ApiError::internal(format!("Failed to search tags: {}", e))),
         }
     };
 
-    // Check if serial number changed BEFORE moving values
-    let serial_number_changed = existing_device.serial_no != request.serial_no;
-    let tb_device_id_clone = existing_device.tb_device_id.clone();
-    let tb_group_id_clone = existing_device.tb_group_id.clone();
+    Ok(Json(ApiResponse::success(TagSearchResponse {
+        total,
+        page,
+        page_size,
+        results,
+    })))
+}
 
-    // Update device instance - preserve tb_device_id and tb_group_id from existing device
-    let device = DeviceInstance {
-        id: device_id.clone(),
+#[derive(Deserialize, ToSchema)]
+pub struct SaveTagSearchRequest {
+    pub name: Option<String>,
+    pub filter: TagSearchFilter,
+}
+
+/// Save a tag search so bulk edits can reference it by id
+#[utoipa::path(
+    post,
+    path = "/api/tags/searches",
+    tag = "tags",
+    request_body = SaveTagSearchRequest,
+    responses((status = 200, description = "Success", body = ApiResponse<SavedTagSearch>)),
+)]
+pub async fn save_tag_search(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Json(request): Json<SaveTagSearchRequest>,
+) -> Result<Json<ApiResponse<SavedTagSearch>>, ApiError> {
+    let search = SavedTagSearch {
+        id: Uuid::new_v4().to_string(),
         name: request.name,
-        serial_no: request.serial_no,
-        model_id: request.model_id,
-        enabled: request.enabled,
-        polling_interval_ms: request.polling_interval_ms,
-        timeout_ms: request.timeout_ms,
-        retry_count: request.retry_count,
-        protocol_config: serde_json::to_string(&request.protocol_config).unwrap_or_default(),
-        tb_device_id: existing_device.tb_device_id,  // Preserve existing ThingsBoard ID
-        tb_group_id: existing_device.tb_group_id,    // Preserve existing ThingsBoard group
-        created_at: existing_device.created_at,      // Preserve creation time
-        updated_at: now,
+        filter: request.filter,
+        created_by: user.map(|Extension(user)| user.username),
+        created_at: Utc::now(),
     };
 
-    // Update device in database
-    if let Err(e) = state.database.update_device(&device).await {
-        error!("Failed to update device: {}", e);
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    match state.database.save_tag_search(&search).await {
+        Ok(()) => Ok(Json(ApiResponse::success(search))),
+        Err(e) => Err(ApiError::internal(format!("Failed to save tag search: {}", e))),
     }
+}
 
-    // Check if serial number changed and device is synced to ThingsBoard
-    if serial_number_changed && tb_device_id_clone.is_some() {
-        info!("Serial number changed for synced device {}, updating ThingsBoard attributes", device_id);
-        
-        // Get device type from database to determine which attributes to update
-        match state.database.get_device_ava_type(&device_id).await {
-            Ok(Some(device_type)) => {
-                // Only update attributes for Inverter and Meter devices
-                if device_type == "Inverter" || device_type == "Meter" || device_type == "PowerMeter" {
-                    // Get entity group name from tb_group_id
-                    if let Some(tb_group_id) = &tb_group_id_clone {
-                        // Connect to ThingsBoard
-                        use crate::tb_rust_client::ThingsBoardClient;
-                        let base_url = "https://monitoring.avaasia.co".to_string();
-                        let mut tb_client = ThingsBoardClient::new(&base_url);
-                        
-                        match tb_client.login("jaydenyong28@gmail.com", "lalala88").await {
-                            Ok(()) => {
-                                // Get entity group name and TB device name
-                                match tb_client.get_all_entity_groups("DEVICE").await {
-                                    Ok(groups) => {
-                                        if let Some(group) = groups.iter().find(|g| &g.id.id == tb_group_id) {
-                                            let entity_group_name = &group.name;
-                                            
-                                            // Fetch ThingsBoard device to get its name
-                                            if let Some(tb_device_id) = &tb_device_id_clone {
-                                                match tb_client.get_device_by_id(tb_device_id).await {
-                                                    Ok(tb_device) => {
-                                                        let tb_device_name = &tb_device.name;
-                                                        
-                                                        // Build and update attributes using TB device name
-                                                        match tb_client.build_device_attributes(&device, tb_device_name, &device_type, entity_group_name, &state.database).await {
-                                                            Ok(attributes) => {
-                                                                match tb_client.update_device_attributes(tb_device_id, attributes).await {
-                                                                    Ok(_) => {
-                                                                        info!("✅ Successfully updated ThingsBoard attributes for device {}", device_id);
-                                                                    }
-                                                                    Err(e) => {
-                                                                        warn!("⚠️ Failed to update ThingsBoard attributes for device {}: {}", device_id, e);
-                                                                    }
-                                                                }
-                                                            }
-                                                            Err(e) => {
-                                                                warn!("⚠️ Failed to build attributes for device {}: {}", device_id, e);
-                                                            }
-                                                        }
-                                                    }
-                                                    Err(e) => {
-                                                        warn!("⚠️ Failed to fetch ThingsBoard device {}: {}", tb_device_id, e);
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                    Err(e) => {
-                                        warn!("⚠️ Failed to get entity groups for attribute update: {}", e);
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                warn!("⚠️ Failed to login to ThingsBoard for attribute update: {}", e);
-                            }
-                        }
-                    }
-                }
-            }
-            Ok(None) => {
-                warn!("Device type not found for {}", device_id);
-            }
-            Err(e) => {
-                warn!("Failed to get device type for {}: {}", device_id, e);
+#[derive(Deserialize, ToSchema)]
+pub struct BulkTagEditRequest {
+    /// Saved search to apply the changes to; takes precedence over `filter`
+    pub search_id: Option<String>,
+    pub filter: Option<TagSearchFilter>,
+    pub changes: TagBulkChanges,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BulkTagEditResponse {
+    pub updated_tags: usize,
+    pub device_ids: Vec<String>,
+    pub restarted_devices: Vec<String>,
+}
+
+/// Apply the same tag changes to every tag matching a search, restarting affected running devices
+#[utoipa::path(
+    post,
+    path = "/api/tags/bulk-edit",
+    tag = "tags",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Replays the stored response when a request is retried with the same key and body")),
+    request_body = BulkTagEditRequest,
+    responses((status = 200, description = "Success", body = ApiResponse<BulkTagEditResponse>), (status = 404, description = "Saved search not found"), (status = 409, description = "Idempotency-Key reused with a different body, or a job conflict"), (status = 413, description = "Request body too large for idempotency checks"), (status = 500, description = "Internal server error")),
+)]
+pub async fn bulk_edit_tags(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Json(request): Json<BulkTagEditRequest>,
+) -> Result<Json<ApiResponse<BulkTagEditResponse>>, ApiError> {
+    if request.search_id.is_none() && request.filter.is_none() {
+        return Err(ApiError::bad_request("Either search_id or filter is required".to_string()));
+    }
+    if let Some(data_type) = &request.changes.data_type {
+        if let Err(e) = check_tag_data_type(data_type) {
+            return Err(ApiError::bad_request(e));
+        }
+    }
+    let filter = resolve_tag_search(&state, request.search_id.as_deref(), request.filter.unwrap_or_default()).await?;
+
+    let (updated_tags, device_ids) = match state.database.bulk_update_tags(&filter, &request.changes).await {
+        Ok(result) => result,
+        Err(e) => return Err(ApiError::internal(format!("Failed to bulk edit tags: {}", e))),
+    };
+    info!("Bulk edit updated {} tags across {} devices", updated_tags, device_ids.len());
+    for device_id in &device_ids {
+        audit(&state, &user, "tag.bulk_edit", "tag", device_id, None, audit_snapshot(&request.changes)).await;
+    }
+
+    // Running devices only pick up tag changes on restart
+    let mut restarted_devices = Vec::new();
+    for device_id in &device_ids {
+        if state.logging_service.is_device_running(device_id).await {
+            match state.logging_service.start_device(device_id).await {
+                Ok(()) => restarted_devices.push(device_id.clone()),
+                Err(e) => warn!("Failed to restart device {} after bulk tag edit: {}", device_id, e),
             }
         }
     }
 
-    // Delete existing tags and recreate them
-    if let Err(e) = state.database.delete_device_tags(&device_id).await {
-        error!("Failed to delete existing device tags: {}", e);
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    Ok(Json(ApiResponse::success(BulkTagEditResponse {
+        updated_tags,
+        device_ids,
+        restarted_devices,
+    })))
+}
+
+/// Reset the type mismatch counters for a device, e.g. after fixing its register map
+#[utoipa::path(
+    delete,
+    path = "/api/devices/{id}/type-mismatches",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id")),
+    responses((status = 200, description = "Success", body = ApiResponse<usize>)),
+)]
+pub async fn reset_device_type_mismatches(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+) -> Result<Json<ApiResponse<usize>>, ApiError> {
+    match state.database.reset_type_mismatches(&device_id).await {
+        Ok(count) => Ok(Json(ApiResponse::success(count))),
+        Err(e) => Err(ApiError::internal(format!("Failed to reset type mismatches for device {}: {}", device_id, e))),
     }
+}
 
-    // Create updated device tags
-    let device_tags: Vec<DeviceTag> = request.tags.into_iter().map(|tag| DeviceTag {
-        id: None,
-        device_id: device_id.clone(),
-        name: tag.name,
-        address: tag.address,
-        size: tag.size,
-        data_type: tag.data_type,
-        description: tag.description,
-        scaling_multiplier: tag.scaling_multiplier,
-        scaling_offset: tag.scaling_offset,
-        unit: tag.unit,
-        read_only: tag.read_only,
-        enabled: tag.enabled,
-        schedule_group_id: tag.schedule_group_id,
-        agg_to_field: tag.agg_to_field,
-    }).collect();
-
-    if let Err(e) = state.database.create_device_tags(&device_id, &device_tags).await {
-        error!("Failed to update device tags: {}", e);
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
-    }
-
-    info!("Updated device {} with {} tags", device_id, device_tags.len());
-    Ok(Json(ApiResponse::success("Device updated successfully".to_string())))
-}
-
-#[derive(Serialize)]
-pub struct DeviceWithTags {
-    pub device: DeviceInstance,
-    pub tags: Vec<DeviceTag>,
-    pub status: Option<String>,
-    pub is_running: bool,
-    pub last_update: Option<String>,
+#[derive(Deserialize, ToSchema)]
+pub struct MuteTagRequest {
+    /// Mute for this many minutes from now
+    pub duration_minutes: Option<u32>,
+    /// Mute until this time; used when `duration_minutes` is not given
+    pub until: Option<DateTime<Utc>>,
+    pub reason: Option<String>,
 }
 
-// Schedule Group API endpoints
-pub async fn get_schedule_groups(
+#[derive(Deserialize, IntoParams)]
+pub struct TagMuteQuery {
+    /// Also list mutes that have expired or were lifted
+    pub include_history: Option<bool>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TagMuteInfo {
+    #[serde(flatten)]
+    pub mute: TagMute,
+    pub active: bool,
+}
+
+async fn find_device_tag(state: &AppState, device_id: &str, tag_id: i64) -> Result<DeviceTag, ApiError> {
+    match state.database.get_device_tag(device_id, tag_id).await {
+        Ok(Some(tag)) => Ok(tag),
+        Ok(None) => Err(ApiError::not_found(format!("Tag {} not found on device {}", tag_id, device_id))),
+        Err(e) => Err(ApiError::internal(format!("Failed to get tag {} of device {}: {}", tag_id, device_id, e))),
+    }
+}
+
+/// Restart a running device so it polls with its changed tags
+async fn reload_after_tag_change(state: &AppState, device_id: &str) -> Result<bool, ApiError> {
+    state
+        .logging_service
+        .reload_device(device_id)
+        .await
+        .map_err(|e| ApiError::internal(format!("Failed to reload device {} after its tags changed: {}", device_id, e)))
+}
+
+/// Fields of a tag to change; fields left out keep their value
+#[derive(Deserialize, ToSchema)]
+pub struct DeviceTagPatch {
+    pub enabled: Option<bool>,
+    /// Schedule group polling the tag; an empty string returns it to the device's interval
+    pub schedule_group_id: Option<String>,
+    pub scaling_multiplier: Option<f64>,
+    pub scaling_offset: Option<f64>,
+    /// An empty string clears the description
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DeviceTagUpdateResult {
+    pub tag: DeviceTag,
+    /// The device was running and now polls with the changed tag; otherwise it is used from
+    /// the next start
+    pub reloaded: bool,
+}
+
+/// Change some fields of one tag in place, keeping its id. A running device is restarted
+/// with it right away.
+#[utoipa::path(
+    patch,
+    path = "/api/devices/{id}/tags/{tag_id}",
+    tag = "tags",
+    params(("id" = String, Path, description = "Device id"), ("tag_id" = i64, Path, description = "Tag id")),
+    request_body = DeviceTagPatch,
+    responses((status = 200, description = "Success", body = ApiResponse<DeviceTagUpdateResult>), (status = 400, description = "Invalid fields"), (status = 404, description = "Tag not found"), (status = 500, description = "Internal server error")),
+)]
+pub async fn patch_device_tag(
     State(state): State<AppState>,
-) -> Result<Json<ApiResponse<Vec<ScheduleGroup>>>, StatusCode> {
-    let schedule_groups = match state.database.get_schedule_groups().await {
-        Ok(groups) => groups,
-        Err(e) => {
-            error!("Failed to get schedule groups: {}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    Path((device_id, tag_id)): Path<(String, i64)>,
+    user: Option<Extension<LocalUser>>,
+    Json(patch): Json<DeviceTagPatch>,
+) -> Result<Json<ApiResponse<DeviceTagUpdateResult>>, ApiError> {
+    let before = find_device_tag(&state, &device_id, tag_id).await?;
+
+    let mut errors = Vec::new();
+    for (field, value) in [("scaling_multiplier", patch.scaling_multiplier), ("scaling_offset", patch.scaling_offset)] {
+        if value.is_some_and(|value| !value.is_finite()) {
+            errors.push(FieldError { field: field.to_string(), message: "must be a finite number".to_string() });
         }
+    }
+    let schedule_group_id = patch.schedule_group_id.map(|group_id| Some(group_id).filter(|group_id| !group_id.is_empty()));
+    if let Some(Some(group_id)) = &schedule_group_id {
+        match state.database.get_schedule_group(group_id).await {
+            Ok(Some(_)) => {}
+            Ok(None) => errors.push(FieldError {
+                field: "schedule_group_id".to_string(),
+                message: format!("no schedule group '{}'", group_id),
+            }),
+            Err(e) => return Err(ApiError::internal(format!("Failed to get schedule group {}: {}", group_id, e))),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(ApiError::invalid_fields("Invalid tag fields", errors));
+    }
+
+    let tag = DeviceTag {
+        enabled: patch.enabled.unwrap_or(before.enabled),
+        schedule_group_id: schedule_group_id.unwrap_or_else(|| before.schedule_group_id.clone()),
+        scaling_multiplier: patch.scaling_multiplier.unwrap_or(before.scaling_multiplier),
+        scaling_offset: patch.scaling_offset.unwrap_or(before.scaling_offset),
+        description: match patch.description {
+            Some(description) => Some(description).filter(|description| !description.is_empty()),
+            None => before.description.clone(),
+        },
+        ..before.clone()
     };
+    if tag == before {
+        return Ok(Json(ApiResponse::success(DeviceTagUpdateResult { tag, reloaded: false })));
+    }
 
-    Ok(Json(ApiResponse::success(schedule_groups)))
+    match state.database.update_device_tag(&tag).await {
+        Ok(true) => {}
+        Ok(false) => return Err(ApiError::not_found(format!("Tag {} not found on device {}", tag_id, device_id))),
+        Err(e) => return Err(ApiError::internal(format!("Failed to update tag {} of device {}: {}", tag.name, device_id, e))),
+    }
+    info!("Updated tag {} of device {}", tag.name, device_id);
+    audit_tag_changes(&state, &user, &device_id, std::slice::from_ref(&before), std::slice::from_ref(&tag)).await;
+
+    let reloaded = reload_after_tag_change(&state, &device_id).await?;
+    Ok(Json(ApiResponse::success(DeviceTagUpdateResult { tag, reloaded })))
 }
 
-pub async fn get_schedule_group(
+/// Add one tag to a device. A running device is restarted with it right away.
+#[utoipa::path(
+    post,
+    path = "/api/devices/{id}/tags",
+    tag = "tags",
+    params(("id" = String, Path, description = "Device id")),
+    request_body = CreateTagRequest,
+    responses((status = 200, description = "Success", body = ApiResponse<DeviceTagUpdateResult>), (status = 400, description = "Invalid tag, or one clashing with the device's tags"), (status = 404, description = "Device not found"), (status = 500, description = "Internal server error")),
+)]
+pub async fn add_device_tag(
     State(state): State<AppState>,
-    Path(group_id): Path<String>,
-) -> Result<Json<ApiResponse<ScheduleGroup>>, StatusCode> {
-    let schedule_group = match state.database.get_schedule_group(&group_id).await {
-        Ok(Some(group)) => group,
-        Ok(None) => return Err(StatusCode::NOT_FOUND),
-        Err(e) => {
-            error!("Failed to get schedule group {}: {}", group_id, e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    Path(device_id): Path<String>,
+    user: Option<Extension<LocalUser>>,
+    Json(request): Json<CreateTagRequest>,
+) -> Result<Json<ApiResponse<DeviceTagUpdateResult>>, ApiError> {
+    if let Err(e) = check_tag_requests(std::slice::from_ref(&request)) {
+        return Err(ApiError::bad_request(e));
+    }
+    let device = match state.database.get_device(&device_id).await {
+        Ok(Some(device)) => device,
+        Ok(None) => return Err(ApiError::not_found(format!("Device {} not found", device_id))),
+        Err(e) => return Err(ApiError::internal(format!("Failed to get device {}: {}", device_id, e))),
+    };
+    if let Some(group_id) = &request.schedule_group_id {
+        match state.database.get_schedule_group(group_id).await {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                return Err(ApiError::invalid_fields(
+                    "Invalid tag fields",
+                    vec![FieldError { field: "schedule_group_id".to_string(), message: format!("no schedule group '{}'", group_id) }],
+                ))
+            }
+            Err(e) => return Err(ApiError::internal(format!("Failed to get schedule group {}: {}", group_id, e))),
         }
+    }
+    let existing = match state.database.get_device_tags(&device_id).await {
+        Ok(tags) => tags,
+        Err(e) => return Err(ApiError::internal(format!("Failed to get tags for device {}: {}", device_id, e))),
     };
 
-    Ok(Json(ApiResponse::success(schedule_group)))
+    // Only clashes with the new tag count; it is checked last so they are reported on it
+    let protocol = device.protocol()?;
+    let mut footprints: Vec<TagFootprint> = existing
+        .iter()
+        .map(|tag| tag_footprint(&tag.name, &tag.data_type, tag.address, tag.size, tag.register_type))
+        .collect();
+    footprints.push(tag_footprint(&request.name, &request.data_type, request.address, request.size, request.register_type));
+    let conflicts: Vec<FieldError> = protocol_tag_conflicts(&protocol, &footprints)
+        .into_iter()
+        .filter(|conflict| conflict.tag == existing.len())
+        .map(|conflict| FieldError {
+            field: match conflict.kind {
+                TagConflictKind::DuplicateName => "name".to_string(),
+                _ => "address".to_string(),
+            },
+            message: conflict.message,
+        })
+        .collect();
+    if !conflicts.is_empty() {
+        return Err(ApiError::invalid_fields("Conflicting tags", conflicts));
+    }
+
+    let tag = match state.database.add_device_tag(&request.into_device_tag(&device_id)).await {
+        Ok(tag) => tag,
+        Err(e) => return Err(ApiError::internal(format!("Failed to add tag to device {}: {}", device_id, e))),
+    };
+    info!("Added tag {} to device {}", tag.name, device_id);
+    audit_tag_changes(&state, &user, &device_id, &[], std::slice::from_ref(&tag)).await;
+
+    let reloaded = reload_after_tag_change(&state, &device_id).await?;
+    Ok(Json(ApiResponse::success(DeviceTagUpdateResult { tag, reloaded })))
 }
 
-#[derive(Deserialize)]
-pub struct CreateScheduleGroupRequest {
-    pub id: String,
-    pub name: String,
-    pub polling_interval_ms: u32,
-    pub description: Option<String>,
-    pub enabled: bool,
+/// Remove one tag from a device. A running device is restarted without it right away.
+#[utoipa::path(
+    delete,
+    path = "/api/devices/{id}/tags/{tag_id}",
+    tag = "tags",
+    params(("id" = String, Path, description = "Device id"), ("tag_id" = i64, Path, description = "Tag id")),
+    responses((status = 200, description = "Success", body = ApiResponse<DeviceUpdateResult>), (status = 404, description = "Tag not found"), (status = 500, description = "Internal server error")),
+)]
+pub async fn delete_device_tag(
+    State(state): State<AppState>,
+    Path((device_id, tag_id)): Path<(String, i64)>,
+    user: Option<Extension<LocalUser>>,
+) -> Result<Json<ApiResponse<DeviceUpdateResult>>, ApiError> {
+    let tag = find_device_tag(&state, &device_id, tag_id).await?;
+
+    match state.database.delete_device_tag(&device_id, tag_id).await {
+        Ok(true) => {}
+        Ok(false) => return Err(ApiError::not_found(format!("Tag {} not found on device {}", tag_id, device_id))),
+        Err(e) => return Err(ApiError::internal(format!("Failed to delete tag {} of device {}: {}", tag.name, device_id, e))),
+    }
+    info!("Deleted tag {} of device {}", tag.name, device_id);
+    audit_tag_changes(&state, &user, &device_id, std::slice::from_ref(&tag), &[]).await;
+
+    let reloaded = reload_after_tag_change(&state, &device_id).await?;
+    let tags = TagSyncCounts { deleted: 1, ..Default::default() };
+    Ok(Json(ApiResponse::success(DeviceUpdateResult { device_id, reloaded, tags })))
 }
 
-pub async fn create_schedule_group(
+/// Mute a tag for a while: it keeps being polled but its samples are not logged
+#[utoipa::path(
+    post,
+    path = "/api/devices/{id}/tags/{tag_id}/mute",
+    tag = "tags",
+    params(("id" = String, Path, description = "Device id"), ("tag_id" = i64, Path, description = "Tag id")),
+    request_body = MuteTagRequest,
+    responses((status = 200, description = "Success", body = ApiResponse<TagMute>), (status = 404, description = "Tag not found")),
+)]
+pub async fn mute_device_tag(
     State(state): State<AppState>,
-    Json(request): Json<CreateScheduleGroupRequest>,
-) -> Result<Json<ApiResponse<String>>, StatusCode> {
-    let now = chrono::Utc::now();
+    Path((device_id, tag_id)): Path<(String, i64)>,
+    user: Option<Extension<LocalUser>>,
+    Json(request): Json<MuteTagRequest>,
+) -> Result<Json<ApiResponse<TagMute>>, ApiError> {
+    let tag = find_device_tag(&state, &device_id, tag_id).await?;
+
+    let muted_until = match (request.duration_minutes, request.until) {
+        (Some(minutes), _) if minutes > 0 => Utc::now() + Duration::minutes(minutes as i64),
+        (None, Some(until)) if until > Utc::now() => until,
+        (None, Some(_)) => return Err(ApiError::bad_request("Mute end time must be in the future".to_string())),
+        _ => return Err(ApiError::bad_request("Either a positive duration_minutes or an until time is required".to_string())),
 
-    let schedule_group = ScheduleGroup {
-        id: request.id.clone(),
-        name: request.name,
-        polling_interval_ms: request.polling_interval_ms,
-        description: request.description,
-        enabled: request.enabled,
-        created_at: now,
-        updated_at: now,
     };
 
-    if let Err(e) = state.database.create_schedule_group(&schedule_group).await {
-        error!("Failed to create schedule group: {}", e);
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    let username = user.as_ref().map(|Extension(user)| user.username.clone());
+    match state.database.mute_tag(&tag, muted_until, request.reason, username.clone()).await {
+        Ok(mute) => {
+            info!(
+                "Tag {} of device {} muted until {} by {}",
+                tag.name, device_id, muted_until, username.as_deref().unwrap_or("unknown")
+            );
+            audit(&state, &user, "tag.mute", "tag", &device_id, None, audit_snapshot(&mute)).await;
+            Ok(Json(ApiResponse::success(mute)))
+        }
+        Err(e) => Err(ApiError::internal(format!("Failed to mute tag {} of device {}: {}", tag.name, device_id, e))),
     }
+}
 
-    info!("Created schedule group {}", request.id);
-    Ok(Json(ApiResponse::success("Schedule group created successfully".to_string())))
+/// Lift a tag's mute before it expires
+#[utoipa::path(
+    delete,
+    path = "/api/devices/{id}/tags/{tag_id}/mute",
+    tag = "tags",
+    params(("id" = String, Path, description = "Device id"), ("tag_id" = i64, Path, description = "Tag id")),
+    responses((status = 200, description = "Success", body = ApiResponse<String>), (status = 404, description = "Tag not found")),
+)]
+pub async fn unmute_device_tag(
+    State(state): State<AppState>,
+    Path((device_id, tag_id)): Path<(String, i64)>,
+    user: Option<Extension<LocalUser>>,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    let tag = find_device_tag(&state, &device_id, tag_id).await?;
+
+    let username = user.as_ref().map(|Extension(user)| user.username.clone());
+    match state.database.lift_tag_mute(tag_id, username.clone()).await {
+        Ok(true) => {
+            info!(
+                "Mute on tag {} of device {} lifted by {}",
+                tag.name, device_id, username.as_deref().unwrap_or("unknown")
+            );
+            audit(&state, &user, "tag.unmute", "tag", &device_id, Some(json!({"name": tag.name})), None).await;
+            Ok(Json(ApiResponse::success("Tag unmuted".to_string())))
+        }
+        Ok(false) => Err(ApiError::conflict("Tag is not muted".to_string())),
+        Err(e) => Err(ApiError::internal(format!("Failed to unmute tag {} of device {}: {}", tag.name, device_id, e))),
+    }
 }
 
-pub async fn update_schedule_group(
+/// List a device's tag mutes, active ones only unless history is requested
+#[utoipa::path(
+    get,
+    path = "/api/devices-enhanced/{id}/mutes",
+    tag = "tags",
+    params(("id" = String, Path, description = "Device id"), TagMuteQuery),
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<TagMuteInfo>>)),
+)]
+pub async fn get_device_tag_mutes(
     State(state): State<AppState>,
-    Path(group_id): Path<String>,
-    Json(request): Json<CreateScheduleGroupRequest>,
-) -> Result<Json<ApiResponse<String>>, StatusCode> {
-    let now = chrono::Utc::now();
+    Path(device_id): Path<String>,
+    Query(params): Query<TagMuteQuery>,
+) -> Result<Json<ApiResponse<Vec<TagMuteInfo>>>, ApiError> {
+    let include_history = params.include_history.unwrap_or(false);
+    let now = Utc::now();
+
+    match state.database.get_tag_mutes(&device_id).await {
+        Ok(mutes) => Ok(Json(ApiResponse::success(
+            mutes
+                .into_iter()
+                .map(|mute| TagMuteInfo { active: mute.is_active(now), mute })
+                .filter(|info| include_history || info.active)
+                .collect(),
+        ))),
+        Err(e) => Err(ApiError::internal(format!("Failed to get tag mutes for device {}: {}", device_id, e))),
+    }
+}
 
-    let schedule_group = ScheduleGroup {
-        id: group_id.clone(),
-        name: request.name,
-        polling_interval_ms: request.polling_interval_ms,
-        description: request.description,
-        enabled: request.enabled,
-        created_at: now, // This will be ignored in update
-        updated_at: now,
+#[derive(Deserialize, ToSchema)]
+pub struct TelemetryForwardingRequest {
+    pub enabled: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TelemetryForwardingStatus {
+    pub device_id: String,
+    /// Forwarding switch for this device
+    pub enabled: bool,
+    /// Values are being queued: enabled here and in `[telemetry_forwarding]`, and the device is linked to ThingsBoard
+    pub active: bool,
+    pub tb_device_id: Option<String>,
+    pub backlog: TelemetryBacklog,
+}
+
+async fn telemetry_forwarding_status(state: &AppState, device_id: &str) -> Result<TelemetryForwardingStatus, ApiError> {
+    let device = match state.database.get_device(device_id).await {
+        Ok(Some(device)) => device,
+        Ok(None) => return Err(ApiError::not_found(format!("Device {} not found", device_id))),
+        Err(e) => return Err(ApiError::internal(format!("Failed to get device {}: {}", device_id, e))),
     };
 
-    if let Err(e) = state.database.update_schedule_group(&schedule_group).await {
-        error!("Failed to update schedule group: {}", e);
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    let (enabled, backlog) = match (
+        state.database.get_device_telemetry_forwarding(device_id).await,
+        state.database.get_telemetry_backlog(device_id).await,
+    ) {
+        (Ok(enabled), Ok(backlog)) => (enabled.unwrap_or(false), backlog),
+        (Err(e), _) | (_, Err(e)) => return Err(ApiError::internal(format!("Failed to get telemetry forwarding status for device {}: {}", device_id, e))),
+    };
+
+    let forwarding_configured = state.config.telemetry_forwarding.enabled && state.config.thingsboard.is_some();
+    Ok(TelemetryForwardingStatus {
+        device_id: device_id.to_string(),
+        enabled,
+        active: enabled && forwarding_configured && device.tb_device_id.is_some(),
+        tb_device_id: device.tb_device_id,
+        backlog,
+    })
+}
+
+/// Whether a device's logged values are pushed to ThingsBoard, and how many are still queued
+#[utoipa::path(
+    get,
+    path = "/api/devices-enhanced/{id}/telemetry-forwarding",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id")),
+    responses(
+        (status = 200, description = "Success", body = ApiResponse<TelemetryForwardingStatus>),
+        (status = 404, description = "Device not found"),
+    ),
+)]
+pub async fn get_telemetry_forwarding(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+) -> Result<Json<ApiResponse<TelemetryForwardingStatus>>, ApiError> {
+    Ok(Json(ApiResponse::success(telemetry_forwarding_status(&state, &device_id).await?)))
+}
+
+/// Switch ThingsBoard forwarding for one device; values already queued are still delivered
+#[utoipa::path(
+    put,
+    path = "/api/devices-enhanced/{id}/telemetry-forwarding",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id")),
+    request_body = TelemetryForwardingRequest,
+    responses(
+        (status = 200, description = "Success", body = ApiResponse<TelemetryForwardingStatus>),
+        (status = 404, description = "Device not found"),
+    ),
+)]
+pub async fn set_telemetry_forwarding(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Path(device_id): Path<String>,
+    Json(request): Json<TelemetryForwardingRequest>,
+) -> Result<Json<ApiResponse<TelemetryForwardingStatus>>, ApiError> {
+    match state.database.set_device_telemetry_forwarding(&device_id, request.enabled).await {
+        Ok(true) => {}
+        Ok(false) => return Err(ApiError::not_found(format!("Device {} not found", device_id))),
+        Err(e) => return Err(ApiError::internal(format!("Failed to set telemetry forwarding for device {}: {}", device_id, e))),
     }
+    info!("Telemetry forwarding for device {} {}", device_id, if request.enabled { "enabled" } else { "disabled" });
+    audit(&state, &user, "device.telemetry_forwarding", "device", &device_id, None, Some(json!({"telemetry_forwarding": request.enabled}))).await;
 
-    info!("Updated schedule group {}", group_id);
-    Ok(Json(ApiResponse::success("Schedule group updated successfully".to_string())))
+    // Running devices only pick up the switch on restart
+    if state.logging_service.is_device_running(&device_id).await {
+        if let Err(e) = state.logging_service.start_device(&device_id).await {
+            warn!("Failed to restart device {} after changing telemetry forwarding: {}", device_id, e);
+        }
+    }
+
+    Ok(Json(ApiResponse::success(telemetry_forwarding_status(&state, &device_id).await?)))
 }
 
-pub async fn delete_schedule_group(
+/// ThingsBoard MPPT and String devices created or matched for an inverter by the last hierarchy sync
+#[utoipa::path(
+    get,
+    path = "/api/devices-enhanced/{id}/tb-children",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id")),
+    responses(
+        (status = 200, description = "Success", body = ApiResponse<Vec<TbChildDevice>>),
+        (status = 404, description = "Device not found"),
+    ),
+)]
+pub async fn get_tb_child_devices(
     State(state): State<AppState>,
-    Path(group_id): Path<String>,
-) -> Result<Json<ApiResponse<String>>, StatusCode> {
-    if let Err(e) = state.database.delete_schedule_group(&group_id).await {
-        error!("Failed to delete schedule group: {}", e);
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    Path(device_id): Path<String>,
+) -> Result<Json<ApiResponse<Vec<TbChildDevice>>>, ApiError> {
+    match state.database.get_device(&device_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return Err(ApiError::not_found(format!("Device {} not found", device_id))),
+        Err(e) => return Err(ApiError::internal(format!("Failed to get device {}: {}", device_id, e))),
     }
 
-    info!("Deleted schedule group {}", group_id);
-    Ok(Json(ApiResponse::success("Schedule group deleted successfully".to_string())))
+    match state.database.get_tb_child_devices(&device_id).await {
+        Ok(children) => Ok(Json(ApiResponse::success(children))),
+        Err(e) => Err(ApiError::internal(format!("Failed to get ThingsBoard child devices of {}: {}", device_id, e))),
+    }
 }
 
-// Modbus TCP Tag Register API Endpoints
+#[derive(Serialize, ToSchema)]
+pub struct Iec104ServerState {
+    pub config: Iec104ServerConfig,
+    pub status: Iec104ServerStatus,
+}
 
-#[derive(Serialize, Deserialize)]
-pub struct CsvUploadResponse {
-    pub success: bool,
-    pub message: String,
-    pub records_processed: u64,
-    pub device_brand: String,
-    pub device_model: String,
-    pub summary: String,
-    pub validation_errors: Vec<String>,
+fn iec104_server_state(state: &AppState) -> Iec104ServerState {
+    Iec104ServerState {
+        config: state.iec104_server.config(),
+        status: state.iec104_server.status(),
+    }
 }
 
-#[derive(Deserialize, Debug)]
-pub struct ModbusTcpTagQuery {
-    pub device_brand: Option<String>,
-    pub device_model: Option<String>,
-    pub model_id: Option<String>,
-    // NOTE: ava_type field is unused in the handler - only model_id, device_brand, and device_model are checked
-    // pub ava_type: Option<String>,
+/// Configuration of the IEC 104 server and the masters connected to it
+#[utoipa::path(
+    get,
+    path = "/api/iec104-server",
+    tag = "iec104-server",
+    responses((status = 200, description = "Success", body = ApiResponse<Iec104ServerState>)),
+)]
+pub async fn get_iec104_server(
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<Iec104ServerState>>, ApiError> {
+    Ok(Json(ApiResponse::success(iec104_server_state(&state))))
 }
 
-pub async fn upload_modbus_tcp_csv_tags(
+/// Reconfigure the IEC 104 server and save it to `[iec104_server]`. Connected masters are
+/// dropped and have to reconnect; mapped tags must exist.
+#[utoipa::path(
+    put,
+    path = "/api/iec104-server",
+    tag = "iec104-server",
+    request_body = Iec104ServerConfig,
+    responses((status = 200, description = "Success", body = ApiResponse<Iec104ServerState>)),
+)]
+pub async fn set_iec104_server(
     State(state): State<AppState>,
-    mut multipart: Multipart,
-) -> Result<Json<CsvUploadResponse>, StatusCode> {
-    let csv_parser = ModbusTcpCsvParserService::new();
-    let mut csv_data: Option<bytes::Bytes> = None;
-    let mut device_model_name: Option<String> = None;
-    let mut manufacturer: Option<String> = None;
-    
-    // Parse multipart form to extract CSV file, device model name, and manufacturer
-    while let Some(field) = multipart.next_field().await.unwrap() {
-        if let Some(name) = field.name() {
-            match name {
-                "csv_file" => {
-                    csv_data = Some(field.bytes().await.unwrap());
-                }
-                "device_model_name" => {
-                    if let Ok(text) = field.text().await {
-                        device_model_name = Some(text);
-                    }
-                }
-                "manufacturer" => {
-                    if let Ok(text) = field.text().await {
-                        manufacturer = Some(text);
-                    }
-                }
-                _ => {
-                    // Ignore other fields
-                }
-            }
+    user: Option<Extension<LocalUser>>,
+    Json(config): Json<Iec104ServerConfig>,
+) -> Result<Json<ApiResponse<Iec104ServerState>>, ApiError> {
+    if let Err(e) = config.validate() {
+        return Err(ApiError::bad_request(e));
+    }
+
+    let mut device_tags: std::collections::HashMap<String, Vec<DeviceTag>> = std::collections::HashMap::new();
+    for point in &config.points {
+        if !device_tags.contains_key(&point.device_id) {
+            match state.database.get_device_tags(&point.device_id).await {
+                Ok(tags) => device_tags.insert(point.device_id.clone(), tags),
+                Err(e) => return Err(ApiError::internal(format!("Failed to get tags for device {}: {}", point.device_id, e))),
+            };
+        }
+        if !device_tags[&point.device_id].iter().any(|tag| tag.name == point.tag_name) {
+            return Err(ApiError::bad_request(format!(
+                "IOA {}: device {} has no tag {}", point.ioa, point.device_id, point.tag_name
+
+            )));
         }
     }
-    
-    let csv_data = match csv_data {
-        Some(data) => data,
-        None => {
-            return Ok(Json(CsvUploadResponse {
-                success: false,
-                message: "No CSV file found in request".to_string(),
-                records_processed: 0,
-                device_brand: "".to_string(),
-                device_model: "".to_string(),
-                summary: "".to_string(),
-                validation_errors: vec!["No file uploaded".to_string()],
-            }));
+
+    let previous = state.iec104_server.config();
+    if let Err(e) = state.iec104_server.apply(config.clone()).await {
+        if let Err(restore_error) = state.iec104_server.apply(previous).await {
+            warn!("Failed to restore the previous IEC 104 server configuration: {}", restore_error);
         }
-    };
-    
-    let device_model_name = match device_model_name {
-        Some(name) if !name.trim().is_empty() => name.trim().to_string(),
-        _ => {
-            return Ok(Json(CsvUploadResponse {
-                success: false,
-                message: "Device model name is required".to_string(),
-                records_processed: 0,
-                device_brand: "".to_string(),
-                device_model: "".to_string(),
-                summary: "".to_string(),
-                validation_errors: vec!["Device model name not provided".to_string()],
-            }));
+        return Err(ApiError::internal(format!("Failed to start IEC 104 server: {}", e)));
+    }
+
+    let after = config.clone();
+    let saved = match load_config_from(&state.config_path()).await {
+        Ok(mut app_config) => {
+            app_config.iec104_server = config;
+            save_config_to(&state.config_path(), &app_config).await
         }
+        Err(e) => Err(e),
     };
-    
-    let manufacturer = match manufacturer {
-        Some(name) if !name.trim().is_empty() => name.trim().to_string(),
-        _ => {
-            return Ok(Json(CsvUploadResponse {
-                success: false,
-                message: "Manufacturer name is required".to_string(),
-                records_processed: 0,
-                device_brand: "".to_string(),
-                device_model: "".to_string(),
-                summary: "".to_string(),
-                validation_errors: vec!["Manufacturer name not provided".to_string()],
-            }));
+    if let Err(e) = saved {
+        return Err(ApiError::internal(format!("Failed to save IEC 104 server configuration: {}", e)));
+    }
+    info!("IEC 104 server reconfigured with {} points", state.iec104_server.status().points);
+    if let Some((before, after)) = audit_diff(&previous, &after, &[]) {
+        audit(&state, &user, "iec104_server.update", "config", "iec104_server", Some(before), Some(after)).await;
+    }
+
+    Ok(Json(ApiResponse::success(iec104_server_state(&state))))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct TagWriteRequest {
+    pub tag_name: String,
+    /// Engineering value; the tag's scaling is undone before it is sent
+    pub value: f64,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct TagWriteAuditQuery {
+    /// Defaults to 100
+    pub limit: Option<u32>,
+}
+
+/// Write a value to a tag of a running device: a Modbus holding register or coil, or an
+/// IEC 104 short float set-point. Every attempt is recorded in the tag write audit.
+#[utoipa::path(
+    post,
+    path = "/api/devices-enhanced/{id}/write",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id")),
+    request_body = TagWriteRequest,
+    responses(
+        (status = 200, description = "Success", body = ApiResponse<TagWriteResult>),
+        (status = 403, description = "The tag is read-only or its write policy doesn't allow the caller"),
+        (status = 404, description = "Device or tag not found"),
+        (status = 409, description = "The device isn't running"),
+        (status = 502, description = "The device rejected or didn't answer the write"),
+    ),
+)]
+pub async fn write_device_tag(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+    user: Option<Extension<LocalUser>>,
+    Json(request): Json<TagWriteRequest>,
+) -> Result<Json<ApiResponse<TagWriteResult>>, ApiError> {
+    let tag = match state.database.get_device_tags(&device_id).await {
+        Ok(tags) => tags
+            .into_iter()
+            .find(|tag| tag.name == request.tag_name)
+            .ok_or_else(|| ApiError::not_found(format!("Device {} has no tag {}", device_id, request.tag_name)))?,
+        Err(e) => return Err(ApiError::internal(format!("Failed to get tags for device {}: {}", device_id, e))),
+    };
+    let (username, role) = user
+        .map(|Extension(user)| (user.username, user.role))
+        .unwrap_or_else(|| ("unknown".to_string(), String::new()));
+
+    let checked = match tag.check_write(&role) {
+        Ok(()) if !state.logging_service.is_device_running(&device_id).await => {
+            Err((StatusCode::CONFLICT, format!("Device {} is not running", device_id)))
         }
+        checked => checked.map_err(|reason| (StatusCode::FORBIDDEN, reason)),
     };
-    
-    // Validate CSV headers first
-    if let Err(e) = csv_parser.validate_csv_headers(csv_data.as_ref()) {
-        return Ok(Json(CsvUploadResponse {
-            success: false,
-            message: format!("CSV validation failed: {}", e),
-            records_processed: 0,
-            device_brand: "".to_string(),
-            device_model: "".to_string(),
-            summary: "".to_string(),
-            validation_errors: vec![e.to_string()],
-        }));
+    let (outcome, result) = match checked {
+        Err(rejection) => ("rejected", Err(rejection)),
+        Ok(()) => match state.logging_service.write_tag(&device_id, tag.clone(), request.value).await {
+            Ok(result) => ("accepted", Ok(result)),
+            Err(e) => ("failed", Err((StatusCode::BAD_GATEWAY, e.to_string()))),
+        },
+    };
+
+    let audit = TagWriteAudit {
+        id: None,
+        device_id: device_id.clone(),
+        tag_id: tag.id,
+        tag_name: tag.name.clone(),
+        username: username.clone(),
+        previous_value: result.as_ref().ok().and_then(|result| result.previous_value),
+        requested_value: request.value,
+        outcome: outcome.to_string(),
+        detail: match &result {
+            Ok(result) => Some(format!("raw {} (registers {:?}), read back {:?}", result.raw_value, result.registers, result.read_back)),
+            Err((_, reason)) => Some(reason.clone()),
+        },
+        created_at: Utc::now(),
+    };
+    if let Err(e) = state.database.record_tag_write_audit(&audit).await {
+        error!("Failed to record write audit for tag {} of device {}: {}", tag.name, device_id, e);
     }
 
-    // Parse CSV with the provided device model name and manufacturer
-    match csv_parser.parse_csv_with_device_model_and_manufacturer(csv_data.as_ref(), &device_model_name, &manufacturer) {
-        Ok(tag_registers) => {
-            if tag_registers.is_empty() {
-                return Ok(Json(CsvUploadResponse {
-                    success: false,
-                    message: "No valid records found in CSV".to_string(),
-                    records_processed: 0,
-                    device_brand: "".to_string(),
-                    device_model: device_model_name.clone(),
-                    summary: "".to_string(),
-                    validation_errors: vec!["Empty CSV or no valid records".to_string()],
-                }));
-            }
-
-            // Validate record data
-            if let Err(e) = csv_parser.validate_record_data(&tag_registers) {
-                return Ok(Json(CsvUploadResponse {
-                    success: false,
-                    message: format!("Data validation failed: {}", e),
-                    records_processed: 0,
-                    device_brand: "".to_string(),
-                    device_model: device_model_name.clone(),
-                    summary: "".to_string(),
-                    validation_errors: vec![e.to_string()],
-                }));
-            }
-
-            let device_brand = tag_registers[0].device_brand.clone();
-            let summary = csv_parser.get_summary(&tag_registers);
-
-            // Insert records
-            match state.database.bulk_insert_modbus_tcp_tag_registers(tag_registers).await {
-                Ok(count) => {
-                    info!("Successfully inserted {} Modbus TCP tag registers for {} {}", 
-                        count, device_brand, device_model_name);
-                    
-                    return Ok(Json(CsvUploadResponse {
-                        success: true,
-                        message: format!("Successfully processed {} records for {} {}", 
-                            count, device_brand, device_model_name),
-                        records_processed: count,
-                        device_brand,
-                        device_model: device_model_name.clone(),
-                        summary,
-                        validation_errors: vec![],
-                    }));
-                }
-                Err(e) => {
-                    error!("Failed to insert Modbus TCP tag registers: {}", e);
-                    return Ok(Json(CsvUploadResponse {
-                        success: false,
-                        message: format!("Database error: {}", e),
-                        records_processed: 0,
-                        device_brand,
-                        device_model: device_model_name.clone(),
-                        summary,
-                        validation_errors: vec![e.to_string()],
-                    }));
-                }
-            }
+    match result {
+        Ok(result) => {
+            info!("Tag {} of device {} set to {} by {}", tag.name, device_id, request.value, username);
+            Ok(Json(ApiResponse::success(result)))
         }
-        Err(e) => {
-            error!("Failed to parse CSV: {}", e);
-            return Ok(Json(CsvUploadResponse {
-                success: false,
-                message: format!("Failed to parse CSV: {}", e),
-                records_processed: 0,
-                device_brand: "".to_string(),
-                device_model: device_model_name.clone(),
-                summary: "".to_string(),
-                validation_errors: vec![e.to_string()],
-            }));
+        Err((status, reason)) => {
+            warn!("Write to tag {} of device {} by {} {}: {}", tag.name, device_id, username, outcome, reason);
+            Err(ApiError::new(status, reason))
         }
     }
 }
 
-pub async fn get_modbus_tcp_tag_registers(
+/// Tag write attempts for a device, newest first
+#[utoipa::path(
+    get,
+    path = "/api/devices-enhanced/{id}/writes",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id"), TagWriteAuditQuery),
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<TagWriteAudit>>)),
+)]
+pub async fn get_device_tag_writes(
     State(state): State<AppState>,
-    Query(params): Query<ModbusTcpTagQuery>,
-) -> Result<Json<ApiResponse<Vec<ModbusTcpTagRegister>>>, StatusCode> {
-    // Debug logging
-    eprintln!("API DEBUG: Received query params: {:?}", params);
-    
-    let result = match (params.model_id, params.device_brand, params.device_model) {
-        // Prefer model_id if provided (most accurate, no duplicates)
-        (Some(model_id), _, _) => {
-            eprintln!("API DEBUG: Using model_id filter: {}", model_id);
-            state.database.get_modbus_tcp_tag_registers_by_model_id(&model_id).await
-        }
-        // Fallback to legacy device_brand + device_model
-        (None, Some(brand), Some(model)) => {
-            eprintln!("API DEBUG: Using brand + model filter: {} + {}", brand, model);
-            state.database.get_modbus_tcp_tag_registers_by_device(&brand, &model).await
-        }
-        // Fallback to legacy device_model only
-        (None, None, Some(model)) => {
-            eprintln!("API DEBUG: Using model only filter: {}", model);
-            state.database.get_modbus_tcp_tag_registers_by_model(&model).await
+    Path(device_id): Path<String>,
+    Query(params): Query<TagWriteAuditQuery>,
+) -> Result<Json<ApiResponse<Vec<TagWriteAudit>>>, ApiError> {
+    match state.database.get_tag_write_audit(&device_id, params.limit.unwrap_or(100).min(1000)).await {
+        Ok(audit) => Ok(Json(ApiResponse::success(audit))),
+        Err(e) => Err(ApiError::internal(format!("Failed to get write audit for device {}: {}", device_id, e))),
+    }
+}
+
+/// Either a configured tag by name, or a raw register spec
+#[derive(Deserialize, ToSchema)]
+pub struct TagReadRequest {
+    pub tag_name: Option<String>,
+    /// Register address, or the IOA for IEC 104 devices
+    pub address: Option<u16>,
+    /// Defaults to the width of the data type
+    pub size: Option<u16>,
+    pub data_type: Option<DataType>,
+    /// Defaults to holding
+    pub register_type: Option<RegisterType>,
+    /// Defaults to the data type's usual layout
+    pub byte_order: Option<ByteOrder>,
+}
+
+/// Read a tag or raw register once. Running devices answer through their poller between
+/// polls; stopped devices get a short-lived connection bounded by the device timeout.
+#[utoipa::path(
+    post,
+    path = "/api/devices-enhanced/{id}/read",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id")),
+    request_body = TagReadRequest,
+    responses(
+        (status = 200, description = "Success", body = ApiResponse<TagReadResult>),
+        (status = 404, description = "Device or tag not found"),
+    ),
+)]
+pub async fn read_device_tag(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+    Json(request): Json<TagReadRequest>,
+) -> Result<Json<ApiResponse<TagReadResult>>, ApiError> {
+    match state.database.get_device(&device_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return Err(ApiError::not_found(format!("Device {} not found", device_id))),
+        Err(e) => return Err(ApiError::internal(format!("Failed to get device {}: {}", device_id, e))),
+    }
+
+    let (tag, read) = match &request.tag_name {
+        Some(tag_name) => {
+            let tag = match state.database.get_device_tags(&device_id).await {
+                Ok(tags) => tags
+                    .into_iter()
+                    .find(|tag| &tag.name == tag_name)
+                    .ok_or_else(|| ApiError::not_found(format!("Device {} has no tag {}", device_id, tag_name)))?,
+                Err(e) => return Err(ApiError::internal(format!("Failed to get tags for device {}: {}", device_id, e))),
+            };
+            let read = tag.register_read();
+            (Some(tag), read)
         }
-        // Return all if no specific filters
-        _ => {
-            eprintln!("API DEBUG: No filters provided - returning all records");
-            state.database.get_all_modbus_tcp_tag_registers().await
+        None => {
+            let (Some(address), Some(data_type)) = (request.address, request.data_type.clone()) else {
+                return Err(ApiError::bad_request("Give either tag_name or address and data_type".to_string()));
+
+            };
+            let read = RegisterRead {
+                register_type: request.register_type.unwrap_or_default(),
+                address,
+                size: request.size.unwrap_or(1).max(1),
+                data_type,
+                byte_order: request.byte_order,
+            };
+            (None, read)
         }
     };
 
-    match result {
-        Ok(tag_registers) => {
-            eprintln!("API DEBUG: Returning {} records", tag_registers.len());
-            Ok(Json(ApiResponse::success(tag_registers)))
+    let address = read.address;
+    let started = std::time::Instant::now();
+    match state.logging_service.read_registers(&device_id, read).await {
+        Ok(reading) => {
+            let (multiplier, offset) = tag.as_ref().map_or((1.0, 0.0), |tag| (tag.scaling_multiplier, tag.scaling_offset));
+            Ok(Json(ApiResponse::success(TagReadResult {
+                tag_name: tag.map(|tag| tag.name),
+                address,
+                value: reading.raw_value * multiplier + offset,
+                raw_value: reading.raw_value,
+                registers: reading.registers,
+                elapsed_ms: started.elapsed().as_millis() as u64,
+                through_poller: reading.through_poller,
+            })))
         }
         Err(e) => {
-            error!("Database error: {}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+            warn!("On-demand read of address {} on device {} failed: {}", address, device_id, e);
+            Err(ApiError::bad_gateway(format!("Read failed: {}", e)))
         }
     }
 }
 
-pub async fn debug_devices(
+#[derive(Deserialize, IntoParams)]
+pub struct ReadSerialQuery {
+    /// Store the serial number read when it differs, and update the SN attribute of the linked ThingsBoard device
+    #[serde(default)]
+    pub apply: bool,
+}
+
+/// A serial number read from a device, compared with the one stored for it
+#[derive(Serialize, ToSchema)]
+pub struct SerialReadResult {
+    pub device_id: String,
+    /// As the device reports it
+    pub device_serial_no: String,
+    /// Stored for the device before this read
+    pub configured_serial_no: Option<String>,
+    /// Same apart from case and surrounding spaces
+    pub matches: bool,
+    /// The stored serial number was replaced by the device's
+    pub applied: bool,
+    /// The SN attribute of the linked ThingsBoard device was updated
+    pub thingsboard_updated: bool,
+    /// Why the SN attribute couldn't be updated; the stored serial number is changed regardless
+    pub thingsboard_error: Option<String>,
+}
+
+/// The serial number a device reports, or None when its protocol config has no `serial_source`
+async fn read_device_serial(state: &AppState, device: &DeviceInstance) -> anyhow::Result<Option<String>> {
+    match device.protocol()?.serial_source() {
+        Some(source) => Ok(Some(state.logging_service.read_serial(&device.id, source).await?)),
+        None => Ok(None),
+    }
+}
+
+/// Installers type serial numbers in whatever case and spacing; devices don't agree either
+fn serials_match(configured: &str, read: &str) -> bool {
+    configured.trim().eq_ignore_ascii_case(read.trim())
+}
+
+/// Read a device's serial number from the registers named by `serial_source` in its protocol
+/// config and compare it with the stored one. With `apply=true` a different serial number
+/// replaces the stored one and the SN attribute of the linked ThingsBoard device.
+#[utoipa::path(
+    post,
+    path = "/api/devices-enhanced/{id}/read-serial",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id"), ReadSerialQuery),
+    responses(
+        (status = 200, description = "Success", body = ApiResponse<SerialReadResult>),
+        (status = 400, description = "The device has no `serial_source`"),
+        (status = 404, description = "Device not found"),
+        (status = 502, description = "The serial number couldn't be read from the device"),
+    ),
+)]
+pub async fn read_device_serial_no(
     State(state): State<AppState>,
-) -> Result<Json<ApiResponse<Vec<String>>>, StatusCode> {
-    match state.database.get_devices().await {
-        Ok(devices) => {
-            let device_ids: Vec<String> = devices.iter().map(|d| d.id.clone()).collect();
-            info!("DEBUG: All device IDs in database: {:?}", device_ids);
-            Ok(Json(ApiResponse::success(device_ids)))
-        }
+    user: Option<Extension<LocalUser>>,
+    Path(device_id): Path<String>,
+    Query(query): Query<ReadSerialQuery>,
+) -> Result<Json<ApiResponse<SerialReadResult>>, ApiError> {
+    let device = match state.database.get_device(&device_id).await {
+        Ok(Some(device)) => device,
+        Ok(None) => return Err(ApiError::not_found(format!("Device {} not found", device_id))),
+        Err(e) => return Err(ApiError::internal(format!("Failed to get device {}: {}", device_id, e))),
+    };
+
+    let device_serial_no = match read_device_serial(&state, &device).await {
+        Ok(Some(serial_no)) => serial_no,
+        Ok(None) => return Err(ApiError::bad_request(format!("Device {} has no serial_source in its protocol config", device_id))),
         Err(e) => {
-            error!("Failed to get devices for debugging: {}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+            warn!("Failed to read the serial number of device {}: {}", device_id, e);
+            return Err(ApiError::bad_gateway(format!("Read failed: {}", e)));
+        }
+    };
+    let matches = device.serial_no.as_deref().is_some_and(|configured| serials_match(configured, &device_serial_no));
+    let mut result = SerialReadResult {
+        device_id: device_id.clone(),
+        device_serial_no,
+        configured_serial_no: device.serial_no.clone(),
+        matches,
+        applied: false,
+        thingsboard_updated: false,
+        thingsboard_error: None,
+    };
+    if matches || !query.apply {
+        return Ok(Json(ApiResponse::success(result)));
+    }
+
+    match state.database.set_device_serial_no(&device_id, &result.device_serial_no).await {
+        Ok(true) => result.applied = true,
+        Ok(false) => return Err(ApiError::not_found(format!("Device {} not found", device_id))),
+        Err(e) => return Err(ApiError::internal(format!("Failed to store the serial number of device {}: {}", device_id, e))),
+    }
+    info!("Serial number of device {} changed from {:?} to {} as read from the device", device_id, device.serial_no, result.device_serial_no);
+    audit(
+        &state,
+        &user,
+        "device.serial_no",
+        "device",
+        &device_id,
+        Some(json!({"serial_no": device.serial_no})),
+        Some(json!({"serial_no": result.device_serial_no})),
+    ).await;
+
+    if let Some(tb_device_id) = device.tb_device_id.as_deref().filter(|id| !id.is_empty()) {
+        // An unconfigured client fails login with TbError::NotConfigured
+        let mut tb_client = ThingsBoardClient::from_config(&state.config)
+            .map(|client| client.with_session(state.tb_session.clone()).with_rate_limiter(state.tb_rate_limiter.clone()))
+            .unwrap_or_else(|_| ThingsBoardClient::new(""));
+        let updated = match tb_client.login_configured().await {
+            Ok(()) => tb_client.update_device_attributes(tb_device_id, json!({"SN": result.device_serial_no})).await,
+            Err(e) => Err(e),
+        };
+        match updated {
+            Ok(()) => result.thingsboard_updated = true,
+            Err(e) => {
+                let error = tb_client.sanitize_error(&e);
+                warn!("Failed to update the SN attribute of device {} in ThingsBoard: {}", device_id, error);
+                result.thingsboard_error = Some(error);
+            }
         }
     }
+
+    Ok(Json(ApiResponse::success(result)))
 }
 
-// ThingsBoard API endpoints
-#[derive(Deserialize)]
-pub struct EntityGroupQuery {
-    pub group_type: Option<String>,
+#[derive(Deserialize, IntoParams)]
+pub struct TbTokenQuery {
+    /// Fetch the token from ThingsBoard again instead of using the cached one
+    #[serde(default)]
+    pub refresh: bool,
 }
 
-pub async fn get_thingsboard_entity_groups(
-    Query(params): Query<EntityGroupQuery>,
-) -> Result<Json<ApiResponse<Vec<crate::tb_rust_client::EntityGroup>>>, StatusCode> {
-    use crate::tb_rust_client::ThingsBoardClient;
-    
-    // Get group type from query parameters, default to "DEVICE" if not specified
-    let group_type = params.group_type.unwrap_or_else(|| "DEVICE".to_string());
-    
-    info!("Fetching ThingsBoard entity groups of type: {}", group_type);
-    
-    // TODO: These credentials should be configurable via environment variables or config file
-    let base_url = "https://monitoring.avaasia.co"; // Default ThingsBoard URL
-    let username = "jaydenyong28@gmail.com";
-    let password = "lalala88";
-    
-    let mut client = ThingsBoardClient::new(base_url);
-    
-    match client.login(username, password).await {
-        Ok(_) => {
-            info!("Successfully logged in to ThingsBoard");
-            
-            match client.get_all_entity_groups(&group_type).await {
-                Ok(entity_groups) => {
-                    info!("Successfully fetched {} entity groups", entity_groups.len());
-                    Ok(Json(ApiResponse::success(entity_groups)))
-                }
-                Err(e) => {
-                    error!("Failed to fetch entity groups: {}", e);
-                    Ok(Json(ApiResponse::error(format!("Failed to fetch entity groups: {}", e))))
-                }
+/// Access token of the ThingsBoard device a local device is synced to
+#[derive(Serialize, ToSchema)]
+pub struct DeviceTbToken {
+    pub device_id: String,
+    pub tb_device_id: String,
+    pub access_token: String,
+    /// The token came from the local cache rather than ThingsBoard
+    pub cached: bool,
+}
+
+/// Access token of a synced device's ThingsBoard device, e.g. to set up a separate uplink.
+/// The token is cached after the first fetch; `refresh=true` fetches it again. For admins and
+/// installers, who set up uplinks in the field.
+#[utoipa::path(
+    get,
+    path = "/api/devices-enhanced/{id}/tb-token",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id"), TbTokenQuery),
+    responses(
+        (status = 200, description = "Success", body = ApiResponse<DeviceTbToken>),
+        (status = 401, description = "No session"),
+        (status = 403, description = "Requires the admin or installer role"),
+        (status = 404, description = "Device not found"),
+        (status = 409, description = "The device is not synced to ThingsBoard"),
+        (status = 502, description = "ThingsBoard request failed"),
+        (status = 503, description = "ThingsBoard is not configured"),
+    ),
+)]
+pub async fn get_device_tb_token(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+    Query(query): Query<TbTokenQuery>,
+    user: Option<Extension<LocalUser>>,
+) -> Result<Json<ApiResponse<DeviceTbToken>>, ApiError> {
+    match &user {
+        Some(Extension(caller)) if caller.role == "admin" || caller.role == "installer" => {}
+        Some(_) => return Err(ApiError::forbidden("Requires the admin or installer role")),
+        None => return Err(ApiError::unauthorized("Not logged in")),
+    }
+
+    let device = match state.database.get_device(&device_id).await {
+        Ok(Some(device)) => device,
+        Ok(None) => return Err(ApiError::not_found(format!("Device {} not found", device_id))),
+        Err(e) => return Err(ApiError::internal(format!("Failed to get device {}: {}", device_id, e))),
+    };
+    let Some(tb_device_id) = device.tb_device_id.filter(|id| !id.is_empty()) else {
+        return Err(ApiError::conflict(format!(
+            "Device {} is not synced to ThingsBoard; sync it first to get its access token",
+            device_id
+        )));
+    };
+
+    if !query.refresh {
+        match state.database.get_device_tb_access_token(&device_id).await {
+            Ok(Some(Some(access_token))) => {
+                return Ok(Json(ApiResponse::success(DeviceTbToken { device_id, tb_device_id, access_token, cached: true })));
             }
-        }
-        Err(e) => {
-            error!("Failed to login to ThingsBoard: {}", e);
-            Ok(Json(ApiResponse::error(format!("Failed to login to ThingsBoard: {}", e))))
+            Ok(_) => {}
+            Err(e) => return Err(ApiError::internal(format!("Failed to get the cached token of device {}: {}", device_id, e))),
         }
     }
-}
 
+    let mut tb_client = match ThingsBoardClient::from_config(&state.config) {
+        Ok(client) => client.with_session(state.tb_session.clone()).with_rate_limiter(state.tb_rate_limiter.clone()),
+        Err(e) => return Err(e.into()),
+    };
+    if let Err(e) = tb_client.login_configured().await {
+        return Err(tb_error(&tb_client, "Failed to log in to ThingsBoard", &e));
+    }
+    let access_token = match tb_client.get_device_access_token(&tb_device_id).await {
+        Ok(token) => token,
+        Err(e) => return Err(tb_error(&tb_client, &format!("Failed to get the access token of device {}", device_id), &e)),
+    };
 
-// create devices on thingsboard for selected device group
-#[derive(Deserialize)]
-pub struct SyncDevicesRequest {
-    pub entity_group_id: String,
+    match state.database.set_device_tb_access_token(&device_id, &tb_device_id, &access_token).await {
+        Ok(true) => info!("Cached the ThingsBoard access token of device {}", device_id),
+        Ok(false) => warn!("Device {} was relinked or deleted while its ThingsBoard access token was fetched; not caching it", device_id),
+        Err(e) => warn!("Failed to cache the ThingsBoard access token of device {}: {}", device_id, e),
+    }
+    Ok(Json(ApiResponse::success(DeviceTbToken { device_id, tb_device_id, access_token, cached: false })))
 }
 
-#[derive(Serialize)]
-pub struct SyncDevicesResponse {
-    pub total_devices: usize,
-    pub created_count: usize,
-    pub failed_count: usize,
-    pub failed_devices: Vec<FailedDevice>,
-    pub updated_device_ids: Vec<DeviceIdUpdate>,
-    pub update_failed_count: usize,
-}
+/// Active IEC 104 acquisition mode and value counters for a running device
+#[utoipa::path(
+    get,
+    path = "/api/devices/{id}/iec104-diagnostics",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id")),
+    responses((status = 200, description = "Success", body = ApiResponse<Iec104Diagnostics>)),
+)]
+pub async fn get_device_iec104_diagnostics(
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+) -> Result<Json<ApiResponse<Iec104Diagnostics>>, ApiError> {
+    match state.logging_service.get_iec104_diagnostics(&device_id).await {
+        Some(diagnostics) => Ok(Json(ApiResponse::success(diagnostics))),
+        None => Err(ApiError::not_found(format!("No running IEC 104 driver for device {}", device_id))),
 
-#[derive(Serialize)]
-pub struct DeviceIdUpdate {
-    pub local_id: String,
-    pub thingsboard_id: String,
-    pub device_name: String,
-    pub device_type: String, // Add AVA type information
+    }
 }
 
-#[derive(Serialize)]
-pub struct FailedDevice {
-    pub device_name: String,
-    pub error: String,
+#[derive(Serialize, ToSchema)]
+pub struct DeviceUpdateResult {
+    pub device_id: String,
+    /// The device was running and was restarted to poll with its new connection settings or
+    /// tags. False when it wasn't running, in which case they are used from the next start, or
+    /// when nothing it polls with changed; an IEC 104 mode change is applied without a restart.
+    pub reloaded: bool,
+    /// Tags added, changed, removed and left alone; unchanged tags keep their ids
+    pub tags: TagSyncCounts,
 }
 
-/// Sync all local devices to ThingsBoard entity group
-pub async fn sync_devices_to_thingsboard(
+/// Save a device and its tags. A running device is restarted only if its connection settings
+/// or tags changed, while other devices keep polling.
+#[utoipa::path(
+    put,
+    path = "/api/devices-enhanced/{id}",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id"), ("Idempotency-Key" = Option<String>, Header, description = "Replays the stored response when a request is retried with the same key and body")),
+    request_body = CreateDeviceRequest,
+    responses((status = 200, description = "Success", body = ApiResponse<DeviceUpdateResult>), (status = 409, description = "Idempotency-Key reused with a different body, or a job conflict"), (status = 413, description = "Request body too large for idempotency checks"), (status = 500, description = "Internal server error")),
+)]
+pub async fn update_device_with_tags(
     State(state): State<AppState>,
-    Json(request): Json<SyncDevicesRequest>,
-) -> Result<Json<ApiResponse<SyncDevicesResponse>>, StatusCode> {
-    use crate::tb_rust_client::{ThingsBoardClient, to_thingsboard_device, to_thingsboard_device_with_type};
-    
-    info!("Starting sync of local devices to ThingsBoard entity group: {}", request.entity_group_id);
-    
-    // Get only unsynced devices from local database (those without tb_device_id)
-    let devices = match state.database.get_unsynced_devices().await {
-        Ok(devices) => devices,
-        Err(e) => {
-            error!("Failed to get unsynced devices from database: {}", e);
-            return Ok(Json(ApiResponse::error(format!("Failed to get unsynced devices from database: {}", e))));
-        }
+    user: Option<Extension<LocalUser>>,
+    Path(device_id): Path<String>,
+    Json(request): Json<CreateDeviceRequest>,
+) -> Result<Json<ApiResponse<DeviceUpdateResult>>, ApiError> {
+    if let Err(e) = check_tag_requests(&request.tags) {
+        return Err(ApiError::bad_request(e));
+    }
+    let protocol = match ProtocolConfig::from_json(&request.protocol_config) {
+        Ok(protocol) => protocol,
+        Err(errors) => return Err(ApiError::invalid_fields("Invalid protocol config", errors)),
     };
-    
-    if devices.is_empty() {
-        warn!("No unsynced devices found in local database");
-        return Ok(Json(ApiResponse::success(SyncDevicesResponse {
-            total_devices: 0,
-            created_count: 0,
-            failed_count: 0,
-            failed_devices: vec![],
-            updated_device_ids: vec![],
-            update_failed_count: 0,
-        })));
+    let conflicts = tag_request_conflicts(&protocol, &request.tags);
+    if !conflicts.is_empty() {
+        return Err(ApiError::invalid_fields("Conflicting tags", conflicts));
     }
-    
-    info!("Found {} unsynced devices in local database", devices.len());
-    
-    // Connect to ThingsBoard
-    let base_url = "https://monitoring.avaasia.co".to_string();
-    let mut tb_client = ThingsBoardClient::new(&base_url);
-    
-    match tb_client.login("jaydenyong28@gmail.com", "lalala88").await {
-        Ok(()) => {
-            info!("Successfully authenticated with ThingsBoard");
-            
-            // Get entity group information to extract the name
-            let entity_groups = match tb_client.get_all_entity_groups("DEVICE").await {
-                Ok(groups) => groups,
-                Err(e) => {
-                    error!("Failed to get entity groups: {}", e);
-                    return Ok(Json(ApiResponse::error(format!("Failed to get entity groups: {}", e))));
-                }
-            };
-            
-            let entity_group_name = entity_groups
-                .iter()
-                .find(|group| group.id.id == request.entity_group_id)
-                .map(|group| group.name.clone())
-                .unwrap_or_else(|| "Unknown Group".to_string());
-            
-            info!("Target entity group: {} ({})", entity_group_name, request.entity_group_id);
-            
-            // Get existing devices in the ThingsBoard group to determine current device indices
-            let existing_devices = match tb_client.get_all_group_devices(&request.entity_group_id, 50).await {
-                Ok(devices) => {
-                    info!("Found {} existing devices in ThingsBoard group", devices.len());
-                    devices
-                }
-                Err(e) => {
-                    warn!("Failed to get existing devices from ThingsBoard group: {}", e);
-                    Vec::new() // Continue with empty list if we can't fetch existing devices
-                }
-            };
-            
-            // Initialize device type counters based on existing devices
-            let mut device_type_counters: std::collections::HashMap<String, u32> = std::collections::HashMap::new();
-            
-            // Parse existing device names to find the highest index for each device type
-            for existing_device in &existing_devices {
-                // Extract device type and index from existing device name
-                // Expected format: "PREFIX-T##" where T is type abbreviation and ## is index
-                if let Some((device_type, index)) = parse_device_name_for_type_and_index(&existing_device.name, &entity_group_name) {
-                    let current_max = device_type_counters.entry(device_type.clone()).or_insert(0);
-                    if index > *current_max {
-                        *current_max = index;
-                        info!("Updated max index for device type '{}' to {} based on existing device '{}'", 
-                              device_type, index, existing_device.name);
-                    }
-                }
-            }
-            
-            info!("Device type counters initialized: {:?}", device_type_counters);
-            
-            let mut created_count = 0;
+    let now = chrono::Utc::now();
+
+    // Get existing device to preserve tb_device_id and tb_group_id
+    let existing_device = match state.database.get_device(&device_id).await {
+        Ok(Some(device)) => device,
+        Ok(None) => return Err(ApiError::not_found(format!("Device {} not found", device_id))),
+
+        Err(e) => return Err(ApiError::internal(format!("Failed to get existing device: {}", e))),
+    };
+
+    // Check if serial number changed BEFORE moving values
+    let serial_number_changed = existing_device.serial_no != request.serial_no;
+    let tb_device_id_clone = existing_device.tb_device_id.clone();
+    let tb_group_id_clone = existing_device.tb_group_id.clone();
+    let old_mode = existing_device.protocol().ok().and_then(|protocol| Iec104ModeSettings::from_protocol(&protocol));
+    let before = existing_device.clone();
+
+    // Update device instance - preserve tb_device_id and tb_group_id from existing device
+    let device = DeviceInstance {
+        id: device_id.clone(),
+        name: request.name,
+        serial_no: request.serial_no,
+        model_id: request.model_id,
+        enabled: request.enabled,
+        polling_interval_ms: request.polling_interval_ms,
+        timeout_ms: request.timeout_ms,
+        retry_count: request.retry_count,
+        protocol_config: serde_json::to_string(&protocol).unwrap_or_default(),
+        tb_device_id: existing_device.tb_device_id,  // Preserve existing ThingsBoard ID
+        tb_group_id: existing_device.tb_group_id,    // Preserve existing ThingsBoard group
+        created_at: existing_device.created_at,      // Preserve creation time
+        updated_at: now,
+        strict_types: request.strict_types.unwrap_or(existing_device.strict_types),
+    };
+
+    // Update device in database
+    if let Err(e) = state.database.update_device(&device).await {
+        return Err(ApiError::internal(format!("Failed to update device: {}", e)));
+    }
+
+    // IEC 104 mode changes are applied to the running driver without reconnecting
+    let new_mode = Iec104ModeSettings::from_protocol(&protocol);
+    if let (Some(old_mode), Some(new_mode)) = (old_mode, new_mode) {
+        if old_mode != new_mode {
+            let applied = state.logging_service.set_iec104_mode(&device_id, new_mode).await;
+            info!(
+                "IEC 104 mode for device {} changed from {:?} to {:?} (applied to running driver: {})",
+                device_id, old_mode.mode, new_mode.mode, applied
+            );
+            state.notifications.broadcast(
+                "iec104_mode_changed",
+                "info",
+                format!("Device {} switched to {:?} mode", device.name, new_mode.mode),
+                format!(
+                    "IEC 104 acquisition mode changed from {:?} to {:?} (interrogation interval {}ms)",
+                    old_mode.mode, new_mode.mode, new_mode.interrogation_interval_ms
+                ),
+                Some(("device", &device_id)),
+            ).await;
+            audit(&state, &user, "device.iec104_mode", "device", &device_id, audit_snapshot(&old_mode), audit_snapshot(&new_mode)).await;
+        }
+    }
+
+    // Check if serial number changed and device is synced to ThingsBoard
+    if serial_number_changed && tb_device_id_clone.is_some() {
+        info!("Serial number changed for synced device {}, updating ThingsBoard attributes", device_id);
+        
+        // Get device type from database to determine which attributes to update
+        match state.database.get_device_ava_type(&device_id).await {
+            Ok(Some(device_type)) => {
+                // Only update attributes for Inverter and Meter devices
+                if device_type == "Inverter" || device_type == "Meter" || device_type == "PowerMeter" {
+                    // Get entity group name from tb_group_id
+                    if let Some(tb_group_id) = &tb_group_id_clone {
+                        // Connect to ThingsBoard
+                        use crate::tb_rust_client::ThingsBoardClient;
+                        // An unconfigured client fails login with TbError::NotConfigured
+                        let mut tb_client = ThingsBoardClient::from_config(&state.config)
+                            .map(|client| client.with_session(state.tb_session.clone()).with_rate_limiter(state.tb_rate_limiter.clone()))
+                            .unwrap_or_else(|_| ThingsBoardClient::new(""));
+                        
+                        match tb_client.login_configured().await {
+                            Ok(()) => {
+                                // Get entity group name and TB device name
+                                match tb_client.get_all_entity_groups("DEVICE").await {
+                                    Ok(groups) => {
+                                        if let Some(group) = groups.iter().find(|g| &g.id.id == tb_group_id) {
+                                            let entity_group_name = &group.name;
+                                            
+                                            // Fetch ThingsBoard device to get its name
+                                            if let Some(tb_device_id) = &tb_device_id_clone {
+                                                match tb_client.get_device_by_id(tb_device_id).await {
+                                                    Ok(tb_device) => {
+                                                        let tb_device_name = &tb_device.name;
+                                                        
+                                                        // Build and update attributes using TB device name
+                                                        match tb_client.build_device_attributes(&device, tb_device_name, &device_type, entity_group_name, &state.database).await {
+                                                            Ok(attributes) => {
+                                                                match tb_client.update_device_attributes(tb_device_id, attributes).await {
+                                                                    Ok(_) => {
+                                                                        info!("✅ Successfully updated ThingsBoard attributes for device {}", device_id);
+                                                                    }
+                                                                    Err(e) => {
+                                                                        warn!("⚠️ Failed to update ThingsBoard attributes for device {}: {}", device_id, tb_client.sanitize_error(&e));
+                                                                    }
+                                                                }
+                                                            }
+                                                            Err(e) => {
+                                                                warn!("⚠️ Failed to build attributes for device {}: {}", device_id, tb_client.sanitize_error(&e));
+                                                            }
+                                                        }
+                                                    }
+                                                    Err(e) => {
+                                                        warn!("⚠️ Failed to fetch ThingsBoard device {}: {}", tb_device_id, tb_client.sanitize_error(&e));
+                                                    }
+                                                }
+                                            }
+                                        }
+                                    }
+                                    Err(e) => {
+                                        warn!("⚠️ Failed to get entity groups for attribute update: {}", tb_client.sanitize_error(&e));
+                                    }
+                                }
+                            }
+                            Err(e) => {
+                                warn!("⚠️ Failed to login to ThingsBoard for attribute update: {}", tb_client.sanitize_error(&e));
+                            }
+                        }
+                    }
+                }
+            }
+            Ok(None) => {
+                warn!("Device type not found for {}", device_id);
+            }
+            Err(e) => {
+                warn!("Failed to get device type for {}: {}", device_id, e);
+            }
+        }
+    }
+
+    let old_tags = state.database.get_device_tags(&device_id).await.unwrap_or_default();
+
+    // Tags are saved by name, so unchanged ones keep their ids
+    let device_tags: Vec<DeviceTag> = request.tags.into_iter().map(|tag| tag.into_device_tag(&device_id)).collect();
+
+    let tag_counts = match state.database.sync_device_tags(&device_id, &device_tags).await {
+        Ok(counts) => counts,
+        Err(e) => return Err(ApiError::internal(format!("Failed to update device tags: {}", e))),
+    };
+
+    info!(
+        "Updated device {} with {} tags ({} added, {} changed, {} removed)",
+        device_id, device_tags.len(), tag_counts.inserted, tag_counts.updated, tag_counts.deleted
+    );
+    if let Some((before, after)) = audit_diff(&before, &device, &["name"]) {
+        audit(&state, &user, "device.update", "device", &device_id, Some(before), Some(after)).await;
+    }
+    audit_tag_changes(&state, &user, &device_id, &old_tags, &device_tags).await;
+
+    let tags_changed = tag_counts.inserted + tag_counts.updated + tag_counts.deleted > 0;
+    let reloaded = if tags_changed || LoggingService::needs_restart(&before, &device) {
+        match state.logging_service.reload_device(&device_id).await {
+            Ok(reloaded) => reloaded,
+            Err(e) => return Err(ApiError::internal(format!("Failed to reload device {} after it was updated: {}", device_id, e))),
+        }
+    } else {
+        false
+    };
+    Ok(Json(ApiResponse::success(DeviceUpdateResult { device_id, reloaded, tags: tag_counts })))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct TagsFromRegisterMapRequest {
+    /// Device model whose register map is used; alternatively give `device_brand` and `device_model`
+    pub model_id: Option<String>,
+    pub device_brand: Option<String>,
+    pub device_model: Option<String>,
+    /// Only rows of this AVA type, e.g. `Inverter`
+    pub ava_type: Option<String>,
+    /// Inclusive MPPT range; rows without an MPPT are left out once either bound is set
+    pub mppt_min: Option<i32>,
+    pub mppt_max: Option<i32>,
+    /// Inclusive input range; rows without an input are left out once either bound is set
+    pub input_min: Option<i32>,
+    pub input_max: Option<i32>,
+    /// Schedule group of every created tag; the default polling schedule when unset
+    pub schedule_group_id: Option<String>,
+    /// Replace existing tags whose name or registers collide with a row, instead of failing
+    #[serde(default)]
+    pub replace_existing: bool,
+}
+
+/// A register map row that did not become a tag
+#[derive(Serialize, ToSchema)]
+pub struct SkippedRegisterRow {
+    pub data_label: String,
+    pub address: i32,
+    pub reason: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TagsFromRegisterMapResult {
+    pub created: usize,
+    /// Existing tags removed because `replace_existing` was set
+    pub replaced: Vec<String>,
+    pub skipped: Vec<SkippedRegisterRow>,
+}
+
+/// The device tag a register map row describes
+fn register_row_tag(device_id: &str, row: &ModbusTcpTagRegister, schedule_group_id: Option<String>) -> std::result::Result<DeviceTag, String> {
+    let address = u16::try_from(row.address).map_err(|_| format!("address {} is outside 0-65535", row.address))?;
+    let (data_type, register_type) = match row.register_type.as_str() {
+        "coil" => (DataType::Coil, None),
+        "discrete" | "discrete_input" => (DataType::DiscreteInput, None),
+        table @ ("holding" | "input") => {
+            let data_type = DataType::from_tag_type(&row.modbus_type)
+                .filter(|data_type| !matches!(data_type, DataType::Coil | DataType::DiscreteInput))
+                .ok_or_else(|| format!("unknown Modbus type '{}'", row.modbus_type))?;
+            (data_type, RegisterType::parse(table))
+        }
+        other => return Err(format!("unknown register type '{}'", other)),
+    };
+    if row.divider == 0.0 || !row.divider.is_finite() {
+        return Err(format!("divider {} can't be turned into a scaling multiplier", row.divider));
+    }
+    let description = match (row.mppt, row.input) {
+        (Some(mppt), Some(input)) => Some(format!("MPPT {} input {}", mppt, input)),
+        (Some(mppt), None) => Some(format!("MPPT {}", mppt)),
+        (None, Some(input)) => Some(format!("Input {}", input)),
+        (None, None) => None,
+    };
+    Ok(DeviceTag {
+        id: None,
+        device_id: device_id.to_string(),
+        name: row.data_label.clone(),
+        address,
+        size: row.size,
+        data_type: data_type.tag_type().to_string(),
+        description,
+        scaling_multiplier: 1.0 / row.divider,
+        scaling_offset: 0.0,
+        unit: None,
+        read_only: true,
+        enabled: true,
+        schedule_group_id,
+        agg_to_field: row.agg_to_field.clone(),
+        write_policy: TagWritePolicy::default(),
+        byte_order: None,
+        deadband_absolute: row.deadband_absolute,
+        deadband_percent: row.deadband_percent,
+        register_type,
+    })
+}
+
+/// Add tags to a device from the register map library. Rows that can't become a tag, or
+/// that clash with an earlier row, are skipped and listed; rows clashing with the device's
+/// existing tags fail the call unless `replace_existing` is set.
+#[utoipa::path(
+    post,
+    path = "/api/devices-enhanced/{id}/tags/from-register-map",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device id")),
+    request_body = TagsFromRegisterMapRequest,
+    responses((status = 200, description = "Success", body = ApiResponse<TagsFromRegisterMapResult>), (status = 404, description = "Device not found"), (status = 500, description = "Internal server error")),
+)]
+pub async fn create_tags_from_register_map(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Path(device_id): Path<String>,
+    Json(request): Json<TagsFromRegisterMapRequest>,
+) -> Result<Json<ApiResponse<TagsFromRegisterMapResult>>, ApiError> {
+    let device = match state.database.get_device(&device_id).await {
+        Ok(Some(device)) => device,
+        Ok(None) => return Err(ApiError::not_found(format!("Device {} not found", device_id))),
+        Err(e) => return Err(ApiError::internal(format!("Failed to get device {}: {}", device_id, e))),
+    };
+    let field_error = |field: &str, message: String| {
+        Err(ApiError::invalid_fields("Cannot create tags from register map", vec![FieldError { field: field.to_string(), message }]))
+    };
+    if matches!(device.protocol(), Ok(ProtocolConfig::Iec104(_))) {
+        return field_error("id", format!("device '{}' uses IEC 104; register maps describe Modbus devices", device_id));
+    }
+    if let Some(group_id) = &request.schedule_group_id {
+        match state.database.get_schedule_group(group_id).await {
+            Ok(Some(_)) => {}
+            Ok(None) => return field_error("schedule_group_id", format!("no schedule group '{}'", group_id)),
+            Err(e) => return Err(ApiError::internal(format!("Failed to get schedule group {}: {}", group_id, e))),
+        }
+    }
+
+    let registers = match (&request.model_id, &request.device_brand, &request.device_model) {
+        (Some(model_id), _, _) => match state.database.get_device_model(model_id).await {
+            Ok(Some(_)) => state.database.get_modbus_tcp_tag_registers_by_model_id(model_id).await,
+            Ok(None) => return field_error("model_id", format!("no device model '{}'", model_id)),
+            Err(e) => Err(e),
+        },
+        (None, Some(brand), Some(model)) => state.database.get_modbus_tcp_tag_registers_by_device(brand, model).await,
+        _ => return field_error("model_id", "give model_id, or device_brand and device_model".to_string()),
+    };
+    let registers = registers.map_err(|e| ApiError::internal(format!("Failed to get register map for device {}: {}", device_id, e)))?;
+
+    let in_range = |value: Option<i32>, min: Option<i32>, max: Option<i32>| match (min, max) {
+        (None, None) => true,
+        _ => value.is_some_and(|value| min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max)),
+    };
+    let rows: Vec<ModbusTcpTagRegister> = registers
+        .into_iter()
+        .filter(|row| request.ava_type.as_ref().is_none_or(|ava_type| row.ava_type.eq_ignore_ascii_case(ava_type)))
+        .filter(|row| in_range(row.mppt, request.mppt_min, request.mppt_max))
+        .filter(|row| in_range(row.input, request.input_min, request.input_max))
+        .collect();
+    if rows.is_empty() {
+        return field_error("model_id", "no register map rows match the filters".to_string());
+    }
+
+    let mut skipped = Vec::new();
+    let mut candidates = Vec::new();
+    for row in &rows {
+        match register_row_tag(&device_id, row, request.schedule_group_id.clone()) {
+            Ok(tag) => candidates.push((row, tag)),
+            Err(reason) => skipped.push(SkippedRegisterRow { data_label: row.data_label.clone(), address: row.address, reason }),
+        }
+    }
+
+    let existing = state.database.get_device_tags(&device_id).await.map_err(|e| ApiError::internal(format!("Failed to get tags for device {}: {}", device_id, e)))?;
+    let footprint = |tag: &DeviceTag| {
+        let data_type = DataType::from_tag_type(&tag.data_type).unwrap_or(DataType::HoldingRegister);
+        TagFootprint::of(&tag.name, &data_type, tag.address, tag.size).with_register_type(tag.register_type)
+    };
+    let footprints: Vec<TagFootprint> = existing.iter().chain(candidates.iter().map(|(_, tag)| tag)).map(footprint).collect();
+
+    // Conflicts are ordered by the later tag, so a row is only dropped for clashing with a row that was kept
+    let offset = existing.len();
+    let mut dropped = vec![false; candidates.len()];
+    let mut collisions = Vec::new();
+    for conflict in find_tag_conflicts(&footprints).into_iter().filter(|conflict| conflict.tag >= offset) {
+        let index = conflict.tag - offset;
+        if dropped[index] {
+            continue;
+        }
+        match conflict.other {
+            Some(other) if other < offset => collisions.push((index, other, conflict.message)),
+            Some(other) if dropped[other - offset] => {}
+            _ => {
+                dropped[index] = true;
+                let (row, _) = candidates[index];
+                skipped.push(SkippedRegisterRow { data_label: row.data_label.clone(), address: row.address, reason: conflict.message });
+            }
+        }
+    }
+    collisions.retain(|(index, _, _)| !dropped[*index]);
+
+    if !collisions.is_empty() && !request.replace_existing {
+        let errors = collisions
+            .into_iter()
+            .map(|(_, _, message)| FieldError { field: "replace_existing".to_string(), message })
+            .collect();
+        return Err(ApiError::invalid_fields("Register map rows collide with existing tags", errors));
+    }
+
+    let replaced_indexes: std::collections::BTreeSet<usize> = collisions.iter().map(|(_, other, _)| *other).collect();
+    let replaced: Vec<String> = replaced_indexes.iter().map(|&i| existing[i].name.clone()).collect();
+    let new_tags: Vec<DeviceTag> = candidates
+        .into_iter()
+        .zip(&dropped)
+        .filter(|(_, dropped)| !**dropped)
+        .map(|((_, tag), _)| tag)
+        .collect();
+    let created = new_tags.len();
+    let tags: Vec<DeviceTag> = existing
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !replaced_indexes.contains(i))
+        .map(|(_, tag)| tag.clone())
+        .chain(new_tags)
+        .collect();
+
+    if let Err(e) = state.database.delete_device_tags(&device_id).await {
+        return Err(ApiError::internal(format!("Failed to delete existing device tags: {}", e)));
+    }
+    if let Err(e) = state.database.create_device_tags(&device_id, &tags).await {
+        return Err(ApiError::internal(format!("Failed to create device tags: {}", e)));
+    }
+
+    info!(
+        "Created {} tags for device {} from the register map ({} replaced, {} rows skipped)",
+        created, device_id, replaced.len(), skipped.len()
+    );
+    audit_tag_changes(&state, &user, &device_id, &existing, &tags).await;
+    Ok(Json(ApiResponse::success(TagsFromRegisterMapResult { created, replaced, skipped })))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DeviceWithTags {
+    pub device: DeviceInstance,
+    pub tags: Vec<DeviceTag>,
+    pub status: Option<String>,
+    pub is_running: bool,
+    pub last_update: Option<String>,
+}
+
+// Schedule Group API endpoints
+#[utoipa::path(
+    get,
+    path = "/api/schedule-groups",
+    tag = "schedule-groups",
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<ScheduleGroup>>), (status = 500, description = "Internal server error")),
+)]
+pub async fn get_schedule_groups(
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<Vec<ScheduleGroup>>>, ApiError> {
+    let schedule_groups = match state.database.get_schedule_groups().await {
+        Ok(groups) => groups,
+        Err(e) => return Err(ApiError::internal(format!("Failed to get schedule groups: {}", e))),
+    };
+
+    Ok(Json(ApiResponse::success(schedule_groups)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/schedule-groups/{id}",
+    tag = "schedule-groups",
+    params(("id" = String, Path, description = "Schedule group id")),
+    responses((status = 200, description = "Success", body = ApiResponse<ScheduleGroup>), (status = 404, description = "Not found"), (status = 500, description = "Internal server error")),
+)]
+pub async fn get_schedule_group(
+    State(state): State<AppState>,
+    Path(group_id): Path<String>,
+) -> Result<Json<ApiResponse<ScheduleGroup>>, ApiError> {
+    let schedule_group = match state.database.get_schedule_group(&group_id).await {
+        Ok(Some(group)) => group,
+        Ok(None) => return Err(ApiError::not_found(format!("Schedule group {} not found", group_id))),
+        Err(e) => return Err(ApiError::internal(format!("Failed to get schedule group {}: {}", group_id, e))),
+    };
+
+    Ok(Json(ApiResponse::success(schedule_group)))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateScheduleGroupRequest {
+    pub id: String,
+    pub name: String,
+    pub polling_interval_ms: u32,
+    pub description: Option<String>,
+    pub enabled: bool,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/schedule-groups",
+    tag = "schedule-groups",
+    request_body = CreateScheduleGroupRequest,
+    responses((status = 200, description = "Success", body = ApiResponse<String>), (status = 500, description = "Internal server error")),
+)]
+pub async fn create_schedule_group(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Json(request): Json<CreateScheduleGroupRequest>,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    let now = chrono::Utc::now();
+
+    let schedule_group = ScheduleGroup {
+        id: request.id.clone(),
+        name: request.name,
+        polling_interval_ms: request.polling_interval_ms,
+        description: request.description,
+        enabled: request.enabled,
+        created_at: now,
+        updated_at: now,
+    };
+
+    if let Err(e) = state.database.create_schedule_group(&schedule_group).await {
+        if database_error_status(&e) == Some(StatusCode::CONFLICT) {
+            return Err(ApiError::conflict(format!("Schedule group {} already exists", request.id)));
+        }
+        return Err(ApiError::internal(format!("Failed to create schedule group: {}", e)));
+    }
+
+
+    info!("Created schedule group {}", request.id);
+    audit(&state, &user, "schedule_group.create", "schedule_group", &request.id, None, audit_snapshot(&schedule_group)).await;
+    Ok(Json(ApiResponse::success("Schedule group created successfully".to_string())))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ScheduleGroupUpdateResult {
+    pub schedule_group_id: String,
+    /// Running devices polling the group, which follow the change without a manual restart
+    pub reloaded_devices: Vec<String>,
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/schedule-groups/{id}",
+    tag = "schedule-groups",
+    params(("id" = String, Path, description = "Schedule group id")),
+    request_body = CreateScheduleGroupRequest,
+    responses((status = 200, description = "Success", body = ApiResponse<ScheduleGroupUpdateResult>), (status = 500, description = "Internal server error")),
+)]
+pub async fn update_schedule_group(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Path(group_id): Path<String>,
+    Json(request): Json<CreateScheduleGroupRequest>,
+) -> Result<Json<ApiResponse<ScheduleGroupUpdateResult>>, ApiError> {
+    let now = chrono::Utc::now();
+
+    let schedule_group = ScheduleGroup {
+        id: group_id.clone(),
+        name: request.name,
+        polling_interval_ms: request.polling_interval_ms,
+        description: request.description,
+        enabled: request.enabled,
+        created_at: now, // This will be ignored in update
+        updated_at: now,
+    };
+
+    let previous = match state.database.get_schedule_group(&group_id).await {
+        Ok(previous) => previous,
+        Err(e) => return Err(ApiError::internal(format!("Failed to get schedule group: {}", e))),
+    };
+
+    if let Err(e) = state.database.update_schedule_group(&schedule_group).await {
+        return Err(ApiError::internal(format!("Failed to update schedule group: {}", e)));
+    }
+
+    // Running devices follow the new interval without a restart
+    let mut reloaded_devices = Vec::new();
+    if let Some(previous) = &previous {
+        reloaded_devices = state.logging_service.reload_schedule_group(previous, &schedule_group).await;
+        if let Some((before, after)) = audit_diff(previous, &schedule_group, &["name"]) {
+            audit(&state, &user, "schedule_group.update", "schedule_group", &group_id, Some(before), Some(after)).await;
+        }
+    }
+
+    info!("Updated schedule group {}", group_id);
+    Ok(Json(ApiResponse::success(ScheduleGroupUpdateResult { schedule_group_id: group_id, reloaded_devices })))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/schedule-groups/{id}",
+    tag = "schedule-groups",
+    params(("id" = String, Path, description = "Schedule group id")),
+    responses((status = 200, description = "Success", body = ApiResponse<String>), (status = 500, description = "Internal server error")),
+)]
+pub async fn delete_schedule_group(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Path(group_id): Path<String>,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    let before = state.database.get_schedule_group(&group_id).await.ok().flatten();
+    if let Err(e) = state.database.delete_schedule_group(&group_id).await {
+        return Err(ApiError::internal(format!("Failed to delete schedule group: {}", e)));
+    }
+
+    info!("Deleted schedule group {}", group_id);
+    audit(&state, &user, "schedule_group.delete", "schedule_group", &group_id, before.as_ref().and_then(audit_snapshot), None).await;
+    reload_virtual_tags(&state).await;
+    Ok(Json(ApiResponse::success("Schedule group deleted successfully".to_string())))
+}
+
+// Modbus TCP Tag Register API Endpoints
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CsvUploadResponse {
+    pub success: bool,
+    pub message: String,
+    pub records_processed: u64,
+    pub device_brand: String,
+    pub device_model: String,
+    pub summary: String,
+    pub validation_errors: Vec<String>,
+    /// Rows inserted, updated and deleted by the import; all zero when nothing was saved
+    pub import: RegisterImportCounts,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+pub struct ModbusTcpTagQuery {
+    pub device_brand: Option<String>,
+    pub device_model: Option<String>,
+    pub model_id: Option<String>,
+    // NOTE: ava_type field is unused in the handler - only model_id, device_brand, and device_model are checked
+    // pub ava_type: Option<String>,
+}
+
+/// Reject a register map upload, keeping the upload report in `details`
+fn csv_upload_rejected(report: CsvUploadResponse) -> ApiError {
+    ApiError::bad_request(report.message.clone()).with_details(report)
+}
+
+/// Row problems listed in a register map upload response; the message still gives the total
+const MAX_REPORTED_CSV_ERRORS: usize = 100;
+
+#[utoipa::path(
+    post,
+    path = "/api/modbus-tcp-tag-registers/upload-csv",
+    tag = "modbus-registers",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Replays the stored response when a request is retried with the same key and body")),
+    request_body(content_type = "multipart/form-data", description = "Form fields `csv_file`, `device_model_name` and `manufacturer`, and optionally `dry_run` to validate without saving, `allow_partial` to import the valid rows of a file with bad ones, and `mode` of `append` (default), `upsert` or `replace` for the rows already stored for the brand and model. With `model_id` the map is linked to that device model, whose name and manufacturer are used when the other fields are left out"),
+    responses((status = 200, description = "Success", body = ApiResponse<CsvUploadResponse>), (status = 400, description = "Nothing was imported; `details` has the upload report with the problems found", body = ApiResponse<CsvUploadResponse>), (status = 409, description = "Idempotency-Key reused with a different body, or a job conflict"), (status = 413, description = "Request body too large for idempotency checks")),
+)]
+pub async fn upload_modbus_tcp_csv_tags(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    mut multipart: Multipart,
+) -> Result<Json<ApiResponse<CsvUploadResponse>>, ApiError> {
+    let csv_parser = ModbusTcpCsvParserService::new();
+    let mut csv_data: Option<String> = None;
+    let mut device_model_name: Option<String> = None;
+    let mut manufacturer: Option<String> = None;
+    let mut dry_run = false;
+    let mut allow_partial = false;
+    let mut mode = RegisterImportMode::default();
+    let mut model_id: Option<String> = None;
+    
+    // Parse multipart form to extract CSV file, device model name, and manufacturer
+    let parsed = read_multipart_form(&mut multipart, state.config.server.max_upload_mb).await.and_then(|fields| {
+        fields.into_iter().try_for_each(|(name, data)| match name.as_str() {
+            "csv_file" => decode_csv_text(&data)
+                .map(|text| csv_data = Some(text))
+                .map_err(|e| FieldError { field: name.clone(), message: e.to_string() }),
+            "device_model_name" => multipart_text(&name, data).map(|text| device_model_name = Some(text)),
+            "manufacturer" => multipart_text(&name, data).map(|text| manufacturer = Some(text)),
+            "dry_run" => multipart_flag(&name, data).map(|flag| dry_run = flag),
+            "allow_partial" => multipart_flag(&name, data).map(|flag| allow_partial = flag),
+            "model_id" => multipart_text(&name, data).map(|text| model_id = Some(text.trim().to_string()).filter(|id| !id.is_empty())),
+            "mode" => multipart_text(&name, data).and_then(|text| match text.trim() {
+                "" | "append" => Ok(RegisterImportMode::Append),
+                "upsert" => Ok(RegisterImportMode::Upsert),
+                "replace" => Ok(RegisterImportMode::Replace),
+                _ => Err(FieldError { field: name.clone(), message: "must be append, upsert or replace".to_string() }),
+            }).map(|value| mode = value),
+            _ => Ok(()), // Ignore other fields
+        })
+    });
+    if let Err(e) = parsed {
+        return Err(csv_upload_rejected(CsvUploadResponse {
+            success: false,
+            message: format!("Upload failed: {}: {}", e.field, e.message),
+            records_processed: 0,
+            device_brand: "".to_string(),
+            device_model: "".to_string(),
+            summary: "".to_string(),
+            validation_errors: vec![format!("{}: {}", e.field, e.message)],
+            import: RegisterImportCounts::default(),
+        }));
+    }
+    
+    let csv_data = match csv_data {
+        Some(data) => data,
+        None => {
+            return Err(csv_upload_rejected(CsvUploadResponse {
+                success: false,
+                message: "No CSV file found in request".to_string(),
+                records_processed: 0,
+                device_brand: "".to_string(),
+                device_model: "".to_string(),
+                summary: "".to_string(),
+                validation_errors: vec!["No file uploaded".to_string()],
+                import: RegisterImportCounts::default(),
+            }));
+        }
+    };
+    
+    // A map uploaded for a device model is linked to it, and takes its names unless given others
+    if let Some(id) = &model_id {
+        let model = match state.database.get_device_model(id).await {
+            Ok(model) => model,
+            Err(e) => return Err(ApiError::internal(format!("Failed to get device model {}: {}", id, e))),
+        };
+        let Some(model) = model else {
+            return Err(csv_upload_rejected(CsvUploadResponse {
+                success: false,
+                message: format!("Upload failed: model_id: no device model '{}'", id),
+                records_processed: 0,
+                device_brand: "".to_string(),
+                device_model: "".to_string(),
+                summary: "".to_string(),
+                validation_errors: vec![format!("model_id: no device model '{}'", id)],
+                import: RegisterImportCounts::default(),
+            }));
+        };
+        device_model_name = device_model_name.filter(|name| !name.trim().is_empty()).or(Some(model.name));
+        manufacturer = manufacturer.filter(|name| !name.trim().is_empty()).or(model.manufacturer);
+    }
+
+    let device_model_name = match device_model_name {
+        Some(name) if !name.trim().is_empty() => name.trim().to_string(),
+        _ => {
+            return Err(csv_upload_rejected(CsvUploadResponse {
+                success: false,
+                message: "Device model name is required".to_string(),
+                records_processed: 0,
+                device_brand: "".to_string(),
+                device_model: "".to_string(),
+                summary: "".to_string(),
+                validation_errors: vec!["Device model name not provided".to_string()],
+                import: RegisterImportCounts::default(),
+            }));
+        }
+    };
+    
+    let manufacturer = match manufacturer {
+        Some(name) if !name.trim().is_empty() => name.trim().to_string(),
+        _ => {
+            return Err(csv_upload_rejected(CsvUploadResponse {
+                success: false,
+                message: "Manufacturer name is required".to_string(),
+                records_processed: 0,
+                device_brand: "".to_string(),
+                device_model: "".to_string(),
+                summary: "".to_string(),
+                validation_errors: vec!["Manufacturer name not provided".to_string()],
+                import: RegisterImportCounts::default(),
+            }));
+        }
+    };
+    
+    // Validate CSV headers first
+    if let Err(e) = csv_parser.validate_csv_headers(csv_data.as_bytes()) {
+        return Err(csv_upload_rejected(CsvUploadResponse {
+            success: false,
+            message: format!("CSV validation failed: {}", e),
+            records_processed: 0,
+            device_brand: "".to_string(),
+            device_model: "".to_string(),
+            summary: "".to_string(),
+            validation_errors: vec![e.to_string()],
+            import: RegisterImportCounts::default(),
+        }));
+    }
+
+    // Parse and validate every row, setting aside the ones with problems
+    let (rows, mut problems) = csv_parser.parse_csv_with_device_model_and_manufacturer(csv_data.as_bytes(), &device_model_name, &manufacturer);
+    problems.extend(csv_parser.validate_records(&rows));
+    let invalid_rows: std::collections::BTreeSet<usize> = problems.iter().map(|problem| problem.row).collect();
+    let rows: Vec<_> = rows.into_iter().filter(|(row, _)| !invalid_rows.contains(row)).collect();
+    let conflicts = csv_parser.find_conflicts(&rows);
+    let conflicting_rows: std::collections::BTreeSet<usize> = conflicts.iter().map(|conflict| conflict.row).collect();
+    let tag_registers: Vec<_> = rows.into_iter().filter(|(row, _)| !conflicting_rows.contains(row)).map(|(_, register)| register).collect();
+    problems.extend(conflicts);
+    problems.sort_by_key(|problem| problem.row);
+
+    let problem_count = problems.len();
+    let validation_errors: Vec<String> = problems.iter().take(MAX_REPORTED_CSV_ERRORS).map(|problem| problem.to_string()).collect();
+    let listed = match problem_count > MAX_REPORTED_CSV_ERRORS {
+        true => format!("first {} listed", MAX_REPORTED_CSV_ERRORS),
+        false => validation_errors.join("; "),
+    };
+    let breakdown = csv_parser.get_summary(&tag_registers);
+    let summary = |inserted: u64, changed: Option<RegisterImportCounts>| format!(
+        "{}: {} | Skipped: {} | Duplicate conflicts: {}{} | {}",
+        if dry_run { "Would insert" } else { "Inserted" },
+        inserted, invalid_rows.len(), conflicting_rows.len(),
+        changed.map(|counts| format!(" | Updated: {} | Deleted: {}", counts.updated, counts.deleted)).unwrap_or_default(),
+        breakdown
+    );
+
+    if tag_registers.is_empty() && problems.is_empty() {
+        return Err(csv_upload_rejected(CsvUploadResponse {
+            success: false,
+            message: "No valid records found in CSV".to_string(),
+            records_processed: 0,
+            device_brand: "".to_string(),
+            device_model: device_model_name.clone(),
+            summary: "".to_string(),
+            validation_errors: vec!["Empty CSV or no valid records".to_string()],
+            import: RegisterImportCounts::default(),
+        }));
+    }
+
+    // Without allow_partial one bad row rejects the whole file
+    if (!problems.is_empty() && !allow_partial) || tag_registers.is_empty() {
+        return Err(csv_upload_rejected(CsvUploadResponse {
+            success: false,
+            message: format!("Found {} problems in the CSV, nothing {}: {}", problem_count, if dry_run { "would be imported" } else { "was imported" }, listed),
+            records_processed: 0,
+            device_brand: "".to_string(),
+            device_model: device_model_name.clone(),
+            summary: summary(0, None),
+            validation_errors,
+            import: RegisterImportCounts::default(),
+        }));
+    }
+
+    let device_brand = tag_registers[0].device_brand.clone();
+    if dry_run {
+        return Ok(Json(ApiResponse::success(CsvUploadResponse {
+            success: true,
+            message: format!("Dry run: {} records would be imported for {} {}, nothing was saved", tag_registers.len(), device_brand, device_model_name),
+            records_processed: 0,
+            device_brand,
+            device_model: device_model_name.clone(),
+            summary: summary(tag_registers.len() as u64, None),
+            validation_errors,
+            import: RegisterImportCounts::default(),
+        })));
+    }
+
+    let _operation = state
+        .scheduler
+        .start(
+            OperationKind::Import,
+            &format!("Import register map for {} {}", device_brand, device_model_name),
+            user.map(|Extension(user)| user.username),
+        )
+        .await
+        .map_err(operation_conflict)?;
+
+    // Insert records
+    match state.database.bulk_insert_modbus_tcp_tag_registers(tag_registers, model_id.as_deref(), mode).await {
+        Ok(counts) => {
+            let count = counts.inserted + counts.updated;
+            info!("Imported {} Modbus TCP tag registers for {} {} ({:?}: {} inserted, {} updated, {} deleted), skipping {} rows",
+                count, device_brand, device_model_name, mode, counts.inserted, counts.updated, counts.deleted, problem_count);
+
+            Ok(Json(ApiResponse::success(CsvUploadResponse {
+                success: true,
+                message: match problem_count {
+                    0 => format!("Successfully processed {} records for {} {}", count, device_brand, device_model_name),
+                    _ => format!("Processed {} records for {} {}, skipping rows with {} problems: {}", count, device_brand, device_model_name, problem_count, listed),
+                },
+                records_processed: count,
+                device_brand,
+                device_model: device_model_name.clone(),
+                summary: summary(counts.inserted, Some(counts)),
+                validation_errors,
+                import: counts,
+            })))
+        }
+        Err(e) => {
+            let message = format!("Database error, the stored register map was left unchanged: {}", e);
+            Err(ApiError::internal(message.clone()).with_details(CsvUploadResponse {
+                success: false,
+                message,
+                records_processed: 0,
+                device_brand,
+                device_model: device_model_name.clone(),
+                summary: summary(0, None),
+                validation_errors: vec![e.to_string()],
+                import: RegisterImportCounts::default(),
+            }))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/modbus-tcp-tag-registers",
+    tag = "modbus-registers",
+    params(ModbusTcpTagQuery),
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<ModbusTcpTagRegister>>), (status = 500, description = "Internal server error")),
+)]
+pub async fn get_modbus_tcp_tag_registers(
+    State(state): State<AppState>,
+    Query(params): Query<ModbusTcpTagQuery>,
+) -> Result<Json<ApiResponse<Vec<ModbusTcpTagRegister>>>, ApiError> {
+    match find_modbus_tcp_tag_registers(&state, params).await {
+        Ok(tag_registers) => {
+            debug!(records = tag_registers.len(), "Returning Modbus TCP tag registers");
+            Ok(Json(ApiResponse::success(tag_registers)))
+        }
+        Err(e) => Err(ApiError::internal(format!("Database error: {}", e))),
+    }
+}
+
+/// Download register maps as CSV in the upload format, with every optional column, so a
+/// map can be edited and uploaded again without losing settings
+#[utoipa::path(
+    get,
+    path = "/api/modbus-tcp-tag-registers/export-csv",
+    tag = "modbus-registers",
+    params(ModbusTcpTagQuery),
+    responses((status = 200, description = "Register map CSV", content_type = "text/csv", body = String), (status = 500, description = "Internal server error")),
+)]
+pub async fn export_modbus_tcp_tag_registers_csv(
+    State(state): State<AppState>,
+    Query(params): Query<ModbusTcpTagQuery>,
+) -> Result<Response, ApiError> {
+    use axum::body::Body;
+    use axum::http::header;
+
+    let tag_registers = find_modbus_tcp_tag_registers(&state, params)
+        .await
+        .map_err(|e| ApiError::internal(format!("Database error: {}", e)))?;
+    let mut csv = Vec::new();
+    ModbusTcpCsvParserService::new()
+        .write_csv(&mut csv, &tag_registers)
+        .map_err(|e| ApiError::internal(format!("Failed to write register map CSV: {}", e)))?;
+
+    Ok(Response::builder()
+        .status(200)
+        .header(header::CONTENT_TYPE, "text/csv; charset=utf-8")
+        .header(header::CONTENT_DISPOSITION, "attachment; filename=\"register-map.csv\"")
+        .body(Body::from(csv))
+        .unwrap())
+}
+
+async fn find_modbus_tcp_tag_registers(state: &AppState, params: ModbusTcpTagQuery) -> anyhow::Result<Vec<ModbusTcpTagRegister>> {
+    match (params.model_id, params.device_brand, params.device_model) {
+        // Prefer model_id if provided (most accurate, no duplicates)
+        (Some(model_id), _, _) => {
+            debug!(model_id = %model_id, "Listing Modbus TCP tag registers by model id");
+            state.database.get_modbus_tcp_tag_registers_by_model_id(&model_id).await
+        }
+        // Fallback to legacy device_brand + device_model
+        (None, Some(brand), Some(model)) => {
+            debug!(device_brand = %brand, device_model = %model, "Listing Modbus TCP tag registers by brand and model");
+            state.database.get_modbus_tcp_tag_registers_by_device(&brand, &model).await
+        }
+        // Fallback to legacy device_model only
+        (None, None, Some(model)) => {
+            debug!(device_model = %model, "Listing Modbus TCP tag registers by model");
+            state.database.get_modbus_tcp_tag_registers_by_model(&model).await
+        }
+        // Return all if no specific filters
+        _ => {
+            debug!("Listing all Modbus TCP tag registers");
+            state.database.get_all_modbus_tcp_tag_registers().await
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+pub struct ModbusTcpTagDeleteQuery {
+    /// Device model whose register map is removed
+    pub model_id: Option<String>,
+}
+
+/// Remove the register map of a device model, e.g. once the model is retired. Counts the rows deleted.
+#[utoipa::path(
+    delete,
+    path = "/api/modbus-tcp-tag-registers",
+    tag = "modbus-registers",
+    params(ModbusTcpTagDeleteQuery),
+    responses((status = 200, description = "Success", body = ApiResponse<u64>), (status = 500, description = "Internal server error")),
+)]
+pub async fn delete_modbus_tcp_tag_registers(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Query(params): Query<ModbusTcpTagDeleteQuery>,
+) -> Result<Json<ApiResponse<u64>>, ApiError> {
+    let Some(model_id) = params.model_id.filter(|id| !id.trim().is_empty()) else {
+        return Err(ApiError::invalid_fields("Invalid request", vec![FieldError {
+            field: "model_id".to_string(),
+            message: "is required".to_string(),
+        }]));
+    };
+
+    match state.database.delete_modbus_tcp_tag_registers_by_model_id(&model_id).await {
+        Ok(count) => {
+            info!("Deleted {} Modbus TCP tag registers of device model {}", count, model_id);
+            audit(&state, &user, "register_map.delete", "device_model", &model_id, Some(json!({"registers": count})), None).await;
+            Ok(Json(ApiResponse::success(count)))
+        }
+        Err(e) => Err(ApiError::internal(format!("Failed to delete Modbus TCP tag registers of device model {}: {}", model_id, e))),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/devices-debug",
+    tag = "devices",
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<String>>), (status = 500, description = "Internal server error")),
+)]
+pub async fn debug_devices(
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<Vec<String>>>, ApiError> {
+    match state.database.get_devices().await {
+        Ok(devices) => {
+            let device_ids: Vec<String> = devices.iter().map(|d| d.id.clone()).collect();
+            info!("DEBUG: All device IDs in database: {:?}", device_ids);
+            Ok(Json(ApiResponse::success(device_ids)))
+        }
+        Err(e) => Err(ApiError::internal(format!("Failed to get devices for debugging: {}", e))),
+    }
+}
+
+// ThingsBoard API endpoints
+#[derive(Deserialize, IntoParams)]
+pub struct EntityGroupQuery {
+    pub group_type: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/thingsboard/entity-groups",
+    tag = "thingsboard",
+    params(EntityGroupQuery),
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<crate::tb_rust_client::EntityGroup>>)),
+)]
+pub async fn get_thingsboard_entity_groups(
+    State(state): State<AppState>,
+    Query(params): Query<EntityGroupQuery>,
+) -> Result<Json<ApiResponse<Vec<crate::tb_rust_client::EntityGroup>>>, ApiError> {
+    use crate::tb_rust_client::ThingsBoardClient;
+    
+    // Get group type from query parameters, default to "DEVICE" if not specified
+    let group_type = params.group_type.unwrap_or_else(|| "DEVICE".to_string());
+    
+    info!("Fetching ThingsBoard entity groups of type: {}", group_type);
+    
+    let mut client = match ThingsBoardClient::from_config(&state.config) {
+        Ok(client) => client.with_session(state.tb_session.clone()).with_rate_limiter(state.tb_rate_limiter.clone()),
+        Err(e) => return Err(e.into()),
+    };
+    
+    match client.login_configured().await {
+        Ok(_) => {
+            info!("Successfully logged in to ThingsBoard");
+            
+            match client.get_all_entity_groups(&group_type).await {
+                Ok(entity_groups) => {
+                    info!("Successfully fetched {} entity groups", entity_groups.len());
+                    Ok(Json(ApiResponse::success(entity_groups)))
+                }
+                Err(e) => Err(tb_error(&client, "Failed to fetch entity groups", &e)),
+            }
+        }
+        Err(e) => Err(tb_error(&client, "Failed to login to ThingsBoard", &e)),
+    }
+}
+
+
+/// String numbering asked for by a request, falling back to the `[thingsboard]` setting
+fn pv_naming(state: &AppState, requested: Option<PvNaming>) -> PvNaming {
+    requested
+        .or_else(|| state.config.thingsboard.as_ref().map(|tb_config| tb_config.pv_naming))
+        .unwrap_or_default()
+}
+
+// create devices on thingsboard for selected device group
+#[derive(Deserialize, IntoParams)]
+pub struct HierarchyQuery {
+    pub page: Option<usize>,
+    pub page_size: Option<usize>,
+    pub include_tokens: Option<bool>,
+    /// Numbering of string names; `pv_naming` of `[thingsboard]` when unset
+    pub pv_naming: Option<PvNaming>,
+}
+
+/// Export the Inverter -> MPPT -> String hierarchy of an entity group as JSON.
+///
+/// The hierarchy is assembled from local devices and tags using the same
+/// description parsing as the catalog export, paginated by inverter. ThingsBoard
+/// is only contacted when the group name is unknown locally or when an admin
+/// asks for access tokens.
+#[utoipa::path(
+    get,
+    path = "/api/thingsboard/hierarchy/{entity_group_id}",
+    tag = "thingsboard",
+    params(("entity_group_id" = String, Path, description = "ThingsBoard entity group id"), HierarchyQuery),
+    responses((status = 200, description = "Success", body = ApiResponse<crate::tb_rust_client::HierarchyExport>), (status = 403, description = "include_tokens requires the admin role"), (status = 404, description = "Entity group not found")),
+)]
+pub async fn get_thingsboard_hierarchy(
+    State(state): State<AppState>,
+    Path(entity_group_id): Path<String>,
+    Query(params): Query<HierarchyQuery>,
+    user: Option<Extension<LocalUser>>,
+) -> Result<Json<ApiResponse<crate::tb_rust_client::HierarchyExport>>, ApiError> {
+    use crate::tb_rust_client::{ThingsBoardClient, HierarchyExport, InverterExport, HIERARCHY_SCHEMA_VERSION};
+
+    let include_tokens = params.include_tokens.unwrap_or(false);
+    if include_tokens && !user.as_ref().map(|Extension(user)| user.role == "admin").unwrap_or(false) {
+        return Err(ApiError::forbidden("include_tokens requires the admin role"));
+    }
+
+    let page = params.page.unwrap_or(1).max(1);
+    let page_size = params.page_size.unwrap_or(20).clamp(1, 100);
+    let pv_naming = pv_naming(&state, params.pv_naming);
+
+    // Local-only exports work without a [thingsboard] section; logging in reports it missing
+    let mut tb_client = ThingsBoardClient::from_config(&state.config)
+        .map(|client| client.with_session(state.tb_session.clone()).with_rate_limiter(state.tb_rate_limiter.clone()))
+        .unwrap_or_else(|_| ThingsBoardClient::new(""));
+    let mut logged_in = false;
+
+    // Prefer the locally recorded plant name for the group to avoid a ThingsBoard round-trip
+    let local_group_name = match state.database.get_all_plant_sync_info().await {
+        Ok(plants) => plants
+            .into_iter()
+            .find(|plant| plant.thingsboard_entity_group_id.as_deref() == Some(entity_group_id.as_str()))
+            .map(|plant| plant.plant_name),
+        Err(e) => {
+            warn!("Failed to read plant sync info: {}", e);
+            None
+        }
+    };
+
+    let entity_group_name = match local_group_name {
+        Some(name) => name,
+        None => {
+            if let Err(e) = tb_client.login_configured().await {
+                return Err(tb_error(&tb_client, "Failed to login to ThingsBoard", &e));
+            }
+            logged_in = true;
+
+            match tb_client.get_all_entity_groups("DEVICE").await {
+                Ok(groups) => match groups.into_iter().find(|group| group.id.id == entity_group_id) {
+                    Some(group) => group.name,
+                    None => return Err(ApiError::not_found(format!("Entity group {} not found", entity_group_id))),
+
+                },
+                Err(e) => return Err(tb_error(&tb_client, "Failed to get entity groups", &e)),
+            }
+        }
+    };
+
+    let devices = match state.database.get_devices_by_group_id(&entity_group_id).await {
+        Ok(devices) => devices,
+        Err(e) => return Err(ApiError::internal(format!("Failed to get devices for group {}: {}", entity_group_id, e))),
+    };
+
+    // Inverters are indexed in name order, matching how the sync assigns -I## suffixes
+    let mut inverters = Vec::new();
+    for device in devices {
+        if let Ok(Some(ava_type)) = state.database.get_device_ava_type(&device.id).await {
+            if ava_type == "Inverter" {
+                inverters.push(device);
+            }
+        }
+    }
+    let total_inverters = inverters.len();
+
+    if include_tokens && !logged_in {
+        if let Err(e) = tb_client.login_configured().await {
+            return Err(tb_error(&tb_client, "Failed to login to ThingsBoard", &e));
+        }
+    }
+
+    let mut exports = Vec::new();
+    for (position, device) in inverters.iter().enumerate().skip((page - 1) * page_size).take(page_size) {
+        let tags = match state.database.get_device_tags(&device.id).await {
+            Ok(tags) => tags,
+            Err(e) => return Err(ApiError::internal(format!("Failed to get tags for device {}: {}", device.id, e))),
+        };
+
+        let hierarchy = match tb_client.analyze_device_hierarchy(tags, &entity_group_name, position as u32 + 1, pv_naming).await {
+            Ok(hierarchy) => hierarchy,
+            Err(e) => {
+                let context = format!("Failed to analyze hierarchy for device {}", device.name);
+                return Err(tb_error(&tb_client, &context, &e));
+            }
+        };
+
+        let children = match state.database.get_tb_child_devices(&device.id).await {
+            Ok(children) => children,
+            Err(e) => return Err(ApiError::internal(format!("Failed to get ThingsBoard child devices of {}: {}", device.id, e))),
+        };
+
+        let mut export = InverterExport::from_hierarchy(device, hierarchy, &children);
+        if include_tokens {
+            if let Some(tb_device_id) = &device.tb_device_id {
+                match tb_client.get_device_access_token(tb_device_id).await {
+                    Ok(token) => export.access_token = Some(token),
+                    Err(e) => warn!("Failed to get access token for device {}: {}", device.name, tb_client.sanitize_error(&e)),
+                }
+            }
+        }
+        exports.push(export);
+    }
+
+    Ok(Json(ApiResponse::success(HierarchyExport {
+        schema_version: HIERARCHY_SCHEMA_VERSION,
+        entity_group_id,
+        entity_group_name,
+        page,
+        page_size,
+        total_inverters,
+        inverters: exports,
+    })))
+}
+
+/// Which local devices a ThingsBoard sync works on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncMode {
+    /// Create the devices that were never synced
+    #[default]
+    NewOnly,
+    /// Check the group's synced devices still exist, relinking by name or recreating those that don't
+    Repair,
+    /// Push attributes again for every synced device of the group, e.g. after a rename
+    RefreshAttributes,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SyncDevicesRequest {
+    pub entity_group_id: String,
+    /// Numbering of string device names; `pv_naming` of `[thingsboard]` when unset
+    #[serde(default)]
+    pub pv_naming: Option<PvNaming>,
+    #[serde(default)]
+    pub mode: SyncMode,
+    /// Read the serial number of every device with a `serial_source` first, and leave out those
+    /// whose configured serial number doesn't match
+    #[serde(default)]
+    pub verify_serials: bool,
+}
+
+/// What a sync did with one local device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncOutcome {
+    Created,
+    /// Still exists in ThingsBoard, nothing to repair
+    Verified,
+    /// Missing under its id, linked to the device of the same name instead
+    Relinked,
+    Recreated,
+    AttributesRefreshed,
+    /// Left out because the device reported a different serial number than the configured one
+    SerialMismatch,
+    Failed,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DeviceSyncOutcome {
+    pub local_id: String,
+    pub device_name: String,
+    pub outcome: SyncOutcome,
+    /// ThingsBoard device the local device is linked to afterwards
+    pub thingsboard_id: Option<String>,
+    pub error: Option<String>,
+}
+
+impl DeviceSyncOutcome {
+    fn new(device: &DeviceInstance, outcome: SyncOutcome, thingsboard_id: Option<String>) -> Self {
+        Self {
+            local_id: device.id.clone(),
+            device_name: device.name.clone(),
+            outcome,
+            thingsboard_id,
+            error: None,
+        }
+    }
+
+    fn failed(device: &DeviceInstance, thingsboard_id: Option<String>, error: String) -> Self {
+        Self { error: Some(error), ..Self::new(device, SyncOutcome::Failed, thingsboard_id) }
+    }
+
+    /// The outcome as reported in the sync job's progress
+    fn job_item(&self) -> JobItemResult {
+        JobItemResult {
+            name: self.device_name.clone(),
+            outcome: serde_json::to_value(self.outcome)
+                .ok()
+                .and_then(|outcome| outcome.as_str().map(str::to_string))
+                .unwrap_or_default(),
+            error: self.error.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SyncDevicesResponse {
+    pub total_devices: usize,
+    pub created_count: usize,
+    pub failed_count: usize,
+    pub failed_devices: Vec<FailedDevice>,
+    pub updated_device_ids: Vec<DeviceIdUpdate>,
+    pub update_failed_count: usize,
+    /// MPPT and string devices that could be neither created nor found by name
+    pub hierarchy_failures: Vec<FailedDevice>,
+    /// Contains relations (`parent -> child`) between inverter, MPPT and string devices that could not be created
+    pub relation_failures: Vec<FailedDevice>,
+    pub mode: SyncMode,
+    /// One entry per device the sync worked on
+    pub outcomes: Vec<DeviceSyncOutcome>,
+    /// Time the sync took once it was running
+    pub elapsed_ms: u64,
+    /// Requests that waited for the ThingsBoard rate limit or a 429's Retry-After
+    pub throttle_pauses: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DeviceIdUpdate {
+    pub local_id: String,
+    pub thingsboard_id: String,
+    pub device_name: String,
+    pub device_type: String, // Add AVA type information
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct FailedDevice {
+    pub device_name: String,
+    pub error: String,
+}
+
+/// Sync all local devices to ThingsBoard entity group.
+///
+/// The sync runs as a background job; its `SyncDevicesResponse` becomes the
+/// job's `result`. While a sync of the same entity group is queued or running,
+/// that job is returned instead of starting another.
+#[utoipa::path(
+    post,
+    path = "/api/sync-devices-to-thingsboard",
+    tag = "thingsboard",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Replays the stored response when a request is retried with the same key and body")),
+    request_body = SyncDevicesRequest,
+    responses((status = 200, description = "Job queued, or the entity group's sync already in progress", body = ApiResponse<Job>), (status = 409, description = "Sync of another entity group running or queued, or Idempotency-Key reused with a different body", body = ApiResponse<Job>), (status = 413, description = "Request body too large for idempotency checks")),
+)]
+pub async fn sync_devices_to_thingsboard(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Json(request): Json<SyncDevicesRequest>,
+) -> Result<Json<ApiResponse<Job>>, ApiError> {
+    // A repair reconciles the group's synced devices with ThingsBoard; the other modes sync to it
+    let kind = match request.mode {
+        SyncMode::Repair => OperationKind::Reconcile,
+        SyncMode::NewOnly | SyncMode::RefreshAttributes => OperationKind::Sync,
+    };
+
+    // One sync per entity group: asking again returns the job already working on it
+    match state.database.get_active_job(&kind.to_string(), &request.entity_group_id).await {
+        Ok(Some(job)) => return Ok(Json(ApiResponse::success(job))),
+        Ok(None) => {}
+        Err(e) => warn!("Failed to look up active sync jobs: {}", e),
+    }
+
+    let description = format!("Sync devices to entity group {}", request.entity_group_id);
+    let requested_by = user.map(|Extension(user)| user.username);
+    let operation = state
+        .scheduler
+        .enqueue(kind, &description, requested_by.clone())
+        .map_err(operation_conflict)?;
+    let job = JobTracker::queued(&state, &operation, &request.entity_group_id, description, requested_by).await;
+
+    let tracker = job.clone();
+    tokio::spawn(async move {
+        let _operation = operation.wait().await;
+        tracker.started().await;
+        tracker.finish(run_device_sync(&state, &request, &tracker).await).await;
+    });
+
+    Ok(Json(ApiResponse::success(job.job())))
+}
+
+/// Sync the request's devices, reporting each one to the job as it is done
+async fn run_device_sync(state: &AppState, request: &SyncDevicesRequest, job: &JobTracker) -> Result<SyncDevicesResponse, ApiError> {
+    let started = std::time::Instant::now();
+    info!("Starting sync of local devices to ThingsBoard entity group: {}", request.entity_group_id);
+    let pv_naming = pv_naming(state, request.pv_naming);
+    
+    // New devices are those without tb_device_id; repair and refresh work on the group's synced devices
+    let devices = match request.mode {
+        SyncMode::NewOnly => state.database.get_unsynced_devices().await,
+        SyncMode::Repair | SyncMode::RefreshAttributes => state
+            .database
+            .get_devices_by_group_id(&request.entity_group_id)
+            .await
+            .map(|devices| devices.into_iter().filter(|device| device.tb_device_id.as_deref().is_some_and(|id| !id.is_empty())).collect()),
+    };
+    let devices: Vec<DeviceInstance> = match devices {
+        Ok(devices) => devices,
+        Err(e) => return Err(ApiError::internal(format!("Failed to get devices to sync from database: {}", e))),
+    };
+    
+    if devices.is_empty() {
+        warn!("No devices to sync in {:?} mode", request.mode);
+        return Ok(SyncDevicesResponse {
+            total_devices: 0,
+            created_count: 0,
+            failed_count: 0,
+            failed_devices: vec![],
+            updated_device_ids: vec![],
+            update_failed_count: 0,
+            hierarchy_failures: vec![],
+            relation_failures: vec![],
+            mode: request.mode,
+            outcomes: vec![],
+            elapsed_ms: started.elapsed().as_millis() as u64,
+            throttle_pauses: 0,
+        });
+    }
+    
+    info!("Found {} devices to sync in {:?} mode", devices.len(), request.mode);
+    job.set_total(devices.len()).await;
+    
+    // Connect to ThingsBoard
+    let mut tb_client = match ThingsBoardClient::from_config(&state.config) {
+        Ok(client) => client
+            .with_session(state.tb_session.clone())
+            .with_rate_limiter(state.tb_rate_limiter.clone())
+            .with_group_cache(state.tb_group_cache.clone())
+            .with_report(job.report()),
+        Err(e) => return Err(e.into()),
+    };
+    
+    match tb_client.login_configured().await {
+        Ok(()) => {
+            info!("Successfully authenticated with ThingsBoard");
+            
+            // Get entity group information to extract the name
+            let entity_groups = match tb_client.get_all_entity_groups("DEVICE").await {
+                Ok(groups) => groups,
+                Err(e) => return Err(tb_error(&tb_client, "Failed to get entity groups", &e)),
+            };
+            
+            let entity_group_name = entity_groups
+                .iter()
+                .find(|group| group.id.id == request.entity_group_id)
+                .map(|group| group.name.clone())
+                .unwrap_or_else(|| "Unknown Group".to_string());
+            
+            info!("Target entity group: {} ({})", entity_group_name, request.entity_group_id);
+            
+            // Get existing devices in the ThingsBoard group to determine current device indices
+            let existing_devices = match tb_client.get_all_group_devices_cached(&request.entity_group_id).await {
+                Ok(devices) => {
+                    info!("Found {} existing devices in ThingsBoard group", devices.len());
+                    devices
+                }
+                Err(e) => {
+                    warn!("Failed to get existing devices from ThingsBoard group: {}", tb_client.sanitize_error(&e));
+                    Vec::new() // Continue with empty list if we can't fetch existing devices
+                }
+            };
+            
+            // Initialize device type counters based on existing devices
+            let mut device_type_counters: std::collections::HashMap<String, u32> = std::collections::HashMap::new();
+            
+            // Parse existing device names to find the highest index for each device type
+            for existing_device in &existing_devices {
+                // Extract device type and index from existing device name
+                // Expected format: "PREFIX-T##" where T is type abbreviation and ## is index
+                if let Some((device_type, index)) = parse_device_name_for_type_and_index(&existing_device.name, &entity_group_name) {
+                    let current_max = device_type_counters.entry(device_type.clone()).or_insert(0);
+                    if index > *current_max {
+                        *current_max = index;
+                        info!("Updated max index for device type '{}' to {} based on existing device '{}'", 
+                              device_type, index, existing_device.name);
+                    }
+                }
+            }
+            
+            info!("Device type counters initialized: {:?}", device_type_counters);
+            
+            let mut created_count = 0;
             let mut failed_count = 0;
             let mut failed_devices = Vec::new();
+            let mut hierarchy_failures = HierarchyFailures::default();
+            let mut outcomes = Vec::new();
             let mut device_id_mappings = Vec::new(); // Store mappings for batch update
+            let context = SyncContext {
+                state,
+                tb_client: &tb_client,
+                entity_group_id: &request.entity_group_id,
+                entity_group_name: &entity_group_name,
+                pv_naming,
+            };
             
-            // Process each device
-            for (index, device) in devices.iter().enumerate() {
-                info!("Processing device {} of {}: {}", index + 1, devices.len(), device.name);
-                
-                // Get the actual device type from database for proper indexing
-                let device_type = match state.database.get_device_ava_type(&device.id).await {
-                    Ok(Some(ava_type)) => ava_type,
-                    Ok(None) => {
-                        warn!("No AVA type found for device {}, defaulting to Inverter", device.id);
-                        "Inverter".to_string()
+            // A mistyped serial number would end up in the SN attribute, so such devices are left out
+            let mut to_sync = Vec::with_capacity(devices.len());
+            for device in &devices {
+                let refusal = if request.verify_serials { serial_refusal(state, device).await } else { None };
+                match refusal {
+                    Some(outcome) => {
+                        let error = outcome.error.clone().unwrap_or_default();
+                        warn!("Not syncing device {}: {}", device.name, error);
+                        failed_count += 1;
+                        failed_devices.push(FailedDevice { device_name: device.name.clone(), error });
+                        job.item(outcome.job_item()).await;
+                        outcomes.push(outcome);
                     }
-                    Err(e) => {
-                        warn!("Failed to get AVA type for device {}: {}, defaulting to Inverter", device.id, e);
-                        "Inverter".to_string()
+                    None => to_sync.push(device),
+                }
+            }
+            
+            if request.mode != SyncMode::NewOnly {
+                // Repairs and attribute refreshes go one device at a time
+                for (index, device) in to_sync.iter().copied().enumerate() {
+                    info!("Processing device {} of {}: {}", index + 1, to_sync.len(), device.name);
+                    let device_type = device_ava_type(state, device).await;
+                    let outcome = if request.mode == SyncMode::Repair {
+                        repair_synced_device(&context, device, &device_type, &mut device_type_counters, &mut hierarchy_failures).await
+                    } else {
+                        refresh_device_attributes(&context, device, &device_type).await
+                    };
+                    match (outcome.outcome, &outcome.thingsboard_id) {
+                        (SyncOutcome::Relinked | SyncOutcome::Recreated, Some(tb_device_id)) => {
+                            if outcome.outcome == SyncOutcome::Recreated {
+                                created_count += 1;
+                            }
+                            device_id_mappings.push((device.id.clone(), tb_device_id.clone(), device.name.clone(), device_type.clone()));
+                        }
+                        (SyncOutcome::Failed, _) => {
+                            failed_count += 1;
+                            failed_devices.push(FailedDevice {
+                                device_name: device.name.clone(),
+                                error: outcome.error.clone().unwrap_or_default(),
+                            });
+                        }
+                        _ => {}
                     }
-                };
-                
-                // Increment counter for this device type
-                let device_index = device_type_counters.entry(device_type.clone()).or_insert(0);
-                *device_index += 1;
+                    job.item(outcome.job_item()).await;
+                    outcomes.push(outcome);
+                }
+            } else {
+                // Indices are handed out in device order up front, so names don't depend on
+                // which of the concurrent creations finishes first
+                let mut creations = Vec::new();
+                for (index, device) in to_sync.iter().copied().enumerate() {
+                    let device_type = device_ava_type(state, device).await;
+                    let device_index = device_type_counters.entry(device_type).or_insert(0);
+                    *device_index += 1;
+                    creations.push(create_synced_device(&context, job, device, *device_index, index + 1, to_sync.len()));
+                }
                 
-                // Convert local device to ThingsBoard format with proper device type lookup
-                let create_request = match to_thingsboard_device_with_type(device, &entity_group_name, *device_index, &state.database).await {
-                    Ok(request) => request,
-                    Err(e) => {
-                        warn!("Failed to create ThingsBoard device request for {}: {}, using fallback", device.name, e);
-                        to_thingsboard_device(device, &entity_group_name, *device_index)
-                    }
-                };
+                let concurrency = state.config.thingsboard.as_ref().map_or(1, |tb_config| tb_config.sync_concurrency).max(1);
+                let created: Vec<DeviceCreation> = futures_util::stream::iter(creations).buffered(concurrency).collect().await;
                 
-                // Attempt to create device in ThingsBoard
-                match tb_client.create_device(&create_request, &request.entity_group_id, None).await {
-                    Ok(created_device) => {
+                for creation in created {
+                    if creation.outcome.outcome == SyncOutcome::Created {
                         created_count += 1;
-                        let tb_device_id = created_device.id.as_ref().map(|id| &id.id).unwrap_or(&String::from("Unknown")).clone();
-                        
-                        info!("Successfully created device: {} [{}] (TB ID: {})", 
-                              create_request.name, create_request.device_type, tb_device_id);
-                        
-                        // Step 2.5: Update device attributes for Inverter and Meter devices
-                        if tb_device_id != "Unknown" && (create_request.device_type == "Inverter" || create_request.device_type == "Meter" || create_request.device_type == "PowerMeter") {
-                            info!("Updating attributes for {} device: {}", create_request.device_type, create_request.name);
-                            
-                            // Use the ThingsBoard device name from created_device
-                            let tb_device_name = &created_device.name;
-                            
-                            match tb_client.build_device_attributes(device, tb_device_name, &create_request.device_type, &entity_group_name, &state.database).await {
-                                Ok(attributes) => {
-                                    match tb_client.update_device_attributes(&tb_device_id, attributes).await {
-                                        Ok(_) => {
-                                            info!("✅ Successfully updated attributes for device: {}", create_request.name);
-                                        }
-                                        Err(e) => {
-                                            warn!("⚠️ Failed to update attributes for device {}: {}", create_request.name, e);
-                                            // Don't fail the sync, just log the warning
-                                        }
-                                    }
-                                }
-                                Err(e) => {
-                                    warn!("⚠️ Failed to build attributes for device {}: {}", create_request.name, e);
-                                }
-                            }
-                        }
-                        
-                        // Create hierarchical devices (MPPT and String devices) only for Inverters
-                        if tb_device_id != "Unknown" && create_request.device_type == "Inverter" {
-                            match tb_client.sync_device_hierarchy_to_thingsboard(
-                                device, 
-                                &request.entity_group_id,
-                                &entity_group_name,
-                                &state.database,
-                                *device_index  // Pass the correct inverter index
-                            ).await {
-                                Ok(hierarchy_devices) => {
-                                    info!("Successfully created {} hierarchical devices for inverter {}", 
-                                          hierarchy_devices.len(), create_request.name);
-                                }
-                                Err(e) => {
-                                    warn!("Failed to create hierarchical devices for inverter {}: {}", 
-                                          create_request.name, e);
-                                    // Continue with the main process - don't fail the entire sync
-                                }
-                            }
-                        } else if tb_device_id != "Unknown" {
-                            info!("Device {} is not an Inverter (type: {}), skipping hierarchical device creation", 
-                                  create_request.name, create_request.device_type);
-                        }
-                        
-                        // Store mapping for later batch update
-                        if tb_device_id != "Unknown" {
-                            device_id_mappings.push((device.id.clone(), tb_device_id, create_request.name.clone(), create_request.device_type.clone()));
-                        }
                     }
-                    Err(e) => {
+                    if let Some(failure) = creation.failure {
                         failed_count += 1;
-                        let error_msg = e.to_string();
-                        error!("Failed to create device {}: {}", create_request.name, error_msg);
-                        failed_devices.push(FailedDevice {
-                            device_name: create_request.name.clone(),
-                            error: error_msg,
-                        });
+                        failed_devices.push(failure);
                     }
+                    device_id_mappings.extend(creation.mapping);
+                    hierarchy_failures.devices.extend(creation.hierarchy_failures.devices);
+                    hierarchy_failures.relations.extend(creation.hierarchy_failures.relations);
+                    outcomes.push(creation.outcome);
                 }
-                
-                // Add a small delay to avoid rate limiting
-                tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
             }
             
             // Step 2: Update local database with ThingsBoard device IDs
@@ -1646,84 +6051,1257 @@ pub async fn sync_devices_to_thingsboard(
                 failed_devices,
                 updated_device_ids,
                 update_failed_count,
+                hierarchy_failures: hierarchy_failures.devices,
+                relation_failures: hierarchy_failures.relations,
+                mode: request.mode,
+                outcomes,
+                elapsed_ms: started.elapsed().as_millis() as u64,
+                throttle_pauses: tb_client.throttle_pauses(),
             };
             
-            info!("Sync completed. Total: {}, Created: {}, Failed: {}, ID Updates: {}, Update Failures: {}", 
-                  response.total_devices, response.created_count, response.failed_count,
-                  response.updated_device_ids.len(), response.update_failed_count);
+            info!("Sync completed. Total: {}, Created: {}, Failed: {}, ID Updates: {}, Update Failures: {}", 
+                  response.total_devices, response.created_count, response.failed_count,
+                  response.updated_device_ids.len(), response.update_failed_count);
+            
+            state.notifications.broadcast(
+                "sync_completed",
+                if response.failed_count > 0 || response.update_failed_count > 0 { "warning" } else { "info" },
+                format!("ThingsBoard sync to {} completed", entity_group_name),
+                format!("Created {} of {} devices, {} failed, {} local ID updates failed",
+                        response.created_count, response.total_devices, response.failed_count, response.update_failed_count),
+                Some(("entity_group", &request.entity_group_id)),
+            ).await;
+            
+            // Update plant sync timestamp after successful sync
+            if let Err(e) = state.database.update_plant_sync_timestamp(&entity_group_name, &request.entity_group_id).await {
+                warn!("Failed to update plant sync timestamp: {}", e);
+                // Don't fail the entire sync operation if timestamp update fails
+            }
+            
+            Ok(response)
+        }
+        Err(e) => Err(tb_error(&tb_client, "Failed to login to ThingsBoard", &e)),
+    }
+}
+
+/// The outcome for a device a sync verifying serial numbers leaves out: one whose serial number
+/// reads back different from its configured one, or can't be read. Devices without a configured
+/// serial number or a serial source aren't checked.
+async fn serial_refusal(state: &AppState, device: &DeviceInstance) -> Option<DeviceSyncOutcome> {
+    let configured = device.serial_no.as_deref().filter(|serial| !serial.trim().is_empty())?;
+    match read_device_serial(state, device).await {
+        Ok(None) => None,
+        Ok(Some(read)) if serials_match(configured, &read) => None,
+        Ok(Some(read)) => Some(DeviceSyncOutcome {
+            error: Some(format!("configured serial number {} doesn't match {} read from the device", configured, read)),
+            ..DeviceSyncOutcome::new(device, SyncOutcome::SerialMismatch, device.tb_device_id.clone())
+        }),
+        Err(e) => Some(DeviceSyncOutcome::failed(
+            device,
+            device.tb_device_id.clone(),
+            format!("serial number could not be read to verify it: {}", e),
+        )),
+    }
+}
+
+/// What the sync of one entity group works with
+struct SyncContext<'a> {
+    state: &'a AppState,
+    tb_client: &'a ThingsBoardClient,
+    entity_group_id: &'a str,
+    entity_group_name: &'a str,
+    pv_naming: PvNaming,
+}
+
+/// MPPT and String devices, and relations between them, that a sync couldn't create
+#[derive(Default)]
+struct HierarchyFailures {
+    devices: Vec<FailedDevice>,
+    relations: Vec<FailedDevice>,
+}
+
+/// What a new-devices sync did with one device
+struct DeviceCreation {
+    outcome: DeviceSyncOutcome,
+    failure: Option<FailedDevice>,
+    /// (local id, ThingsBoard id, name, device type) to record locally
+    mapping: Option<(String, String, String, String)>,
+    hierarchy_failures: HierarchyFailures,
+}
+
+/// The device's AVA type, `Inverter` when it has none
+async fn device_ava_type(state: &AppState, device: &DeviceInstance) -> String {
+    match state.database.get_device_ava_type(&device.id).await {
+        Ok(Some(ava_type)) => ava_type,
+        Ok(None) => {
+            warn!("No AVA type found for device {}, defaulting to Inverter", device.id);
+            "Inverter".to_string()
+        }
+        Err(e) => {
+            warn!("Failed to get AVA type for device {}: {}, defaulting to Inverter", device.id, e);
+            "Inverter".to_string()
+        }
+    }
+}
+
+/// Create a device never synced before under its index in the group, then push its
+/// attributes and, for inverters, create its MPPT and String devices
+async fn create_synced_device(
+    context: &SyncContext<'_>,
+    job: &JobTracker,
+    device: &DeviceInstance,
+    device_index: u32,
+    position: usize,
+    total: usize,
+) -> DeviceCreation {
+    let tb_client = context.tb_client;
+    info!("Processing device {} of {}: {}", position, total, device.name);
+    
+    // Convert local device to ThingsBoard format with proper device type lookup
+    let create_request = match tb_rust_client::to_thingsboard_device_with_type(device, context.entity_group_name, device_index, &context.state.database).await {
+        Ok(request) => request,
+        Err(e) => {
+            warn!("Failed to create ThingsBoard device request for {}: {}, using fallback", device.name, tb_client.sanitize_error(&e));
+            tb_rust_client::to_thingsboard_device(device, context.entity_group_name, device_index)
+        }
+    };
+    
+    match tb_client.create_device(&create_request, context.entity_group_id, None).await {
+        Ok(created_device) => {
+            let tb_device_id = created_device.id.as_ref().map(|id| id.id.clone());
+            info!("Successfully created device: {} [{}] (TB ID: {})",
+                  create_request.name, create_request.device_type, tb_device_id.as_deref().unwrap_or("Unknown"));
+            
+            let outcome = DeviceSyncOutcome::new(device, SyncOutcome::Created, tb_device_id.clone());
+            job.item(outcome.job_item()).await;
             
-            // Update plant sync timestamp after successful sync
-            if let Err(e) = state.database.update_plant_sync_timestamp(&entity_group_name, &request.entity_group_id).await {
-                warn!("Failed to update plant sync timestamp: {}", e);
-                // Don't fail the entire sync operation if timestamp update fails
+            let mut hierarchy_failures = HierarchyFailures::default();
+            let mapping = match tb_device_id {
+                Some(tb_device_id) => {
+                    sync_linked_device(context, device, &created_device, &create_request.device_type, device_index, &mut hierarchy_failures).await;
+                    Some((device.id.clone(), tb_device_id, create_request.name.clone(), create_request.device_type.clone()))
+                }
+                None => None,
+            };
+            DeviceCreation { outcome, failure: None, mapping, hierarchy_failures }
+        }
+        Err(e) => {
+            let error_msg = tb_client.sanitize_error(&e);
+            error!("Failed to create device {}: {}", create_request.name, error_msg);
+            let outcome = DeviceSyncOutcome::failed(device, None, error_msg.clone());
+            job.item(outcome.job_item()).await;
+            DeviceCreation {
+                outcome,
+                failure: Some(FailedDevice { device_name: create_request.name, error: error_msg }),
+                mapping: None,
+                hierarchy_failures: HierarchyFailures::default(),
+            }
+        }
+    }
+}
+
+/// Push the attributes of a device just created or relinked in ThingsBoard and,
+/// for inverters, create its MPPT and String devices
+async fn sync_linked_device(
+    context: &SyncContext<'_>,
+    device: &DeviceInstance,
+    tb_device: &tb_rust_client::Device,
+    device_type: &str,
+    device_index: u32,
+    failures: &mut HierarchyFailures,
+) {
+    let tb_client = context.tb_client;
+    let Some(tb_device_id) = tb_device.id.as_ref().map(|id| id.id.clone()) else {
+        return;
+    };
+
+    if device_type == "Inverter" || device_type == "Meter" || device_type == "PowerMeter" {
+        info!("Updating attributes for {} device: {}", device_type, tb_device.name);
+        match tb_client.build_device_attributes(device, &tb_device.name, device_type, context.entity_group_name, &context.state.database).await {
+            Ok(attributes) => match tb_client.update_device_attributes(&tb_device_id, attributes).await {
+                Ok(_) => info!("✅ Successfully updated attributes for device: {}", tb_device.name),
+                // Don't fail the sync, just log the warning
+                Err(e) => warn!("⚠️ Failed to update attributes for device {}: {}", tb_device.name, tb_client.sanitize_error(&e)),
+            },
+            Err(e) => warn!("⚠️ Failed to build attributes for device {}: {}", tb_device.name, tb_client.sanitize_error(&e)),
+        }
+    }
+
+    // Create hierarchical devices (MPPT and String devices) only for Inverters
+    if device_type != "Inverter" {
+        info!("Device {} is not an Inverter (type: {}), skipping hierarchical device creation", tb_device.name, device_type);
+        return;
+    }
+
+    let synced_device = DeviceInstance { tb_device_id: Some(tb_device_id), ..device.clone() };
+    match tb_client.sync_device_hierarchy_to_thingsboard(
+        &synced_device,
+        context.entity_group_id,
+        context.entity_group_name,
+        &context.state.database,
+        device_index, // Pass the correct inverter index
+        context.pv_naming,
+    ).await {
+        Ok(hierarchy_devices) => {
+            info!("Successfully created {} hierarchical devices for inverter {}",
+                  hierarchy_devices.devices.len(), tb_device.name);
+            for (device_name, e) in hierarchy_devices.failed {
+                warn!("Failed to create hierarchical device {} for inverter {}: {}",
+                      device_name, tb_device.name, tb_client.sanitize_error(&e));
+                failures.devices.push(FailedDevice { device_name, error: tb_client.sanitize_error(&e) });
+            }
+            for (relation, e) in hierarchy_devices.relation_failures {
+                warn!("Failed to create relation {} for inverter {}: {}",
+                      relation, tb_device.name, tb_client.sanitize_error(&e));
+                failures.relations.push(FailedDevice { device_name: relation, error: tb_client.sanitize_error(&e) });
             }
-            
-            Ok(Json(ApiResponse::success(response)))
         }
         Err(e) => {
-            error!("Failed to login to ThingsBoard: {}", e);
-            Ok(Json(ApiResponse::error(format!("Failed to login to ThingsBoard: {}", e))))
+            // Continue with the main process - don't fail the entire sync
+            warn!("Failed to create hierarchical devices for inverter {}: {}",
+                  tb_device.name, tb_client.sanitize_error(&e));
+        }
+    }
+}
+
+/// Check a synced device still exists in ThingsBoard. One that doesn't is linked to the tenant's
+/// device of the name it had, or created again under that name. The name is known from the
+/// recorded MPPT and String devices of an inverter; other devices get the group's next free index.
+async fn repair_synced_device(
+    context: &SyncContext<'_>,
+    device: &DeviceInstance,
+    device_type: &str,
+    device_type_counters: &mut std::collections::HashMap<String, u32>,
+    failures: &mut HierarchyFailures,
+) -> DeviceSyncOutcome {
+    let tb_client = context.tb_client;
+    let old_tb_device_id = device.tb_device_id.clone();
+    match tb_client.get_device_by_id(old_tb_device_id.as_deref().unwrap_or_default()).await {
+        Ok(_) => return DeviceSyncOutcome::new(device, SyncOutcome::Verified, old_tb_device_id),
+        Err(TbError::NotFound(_)) => info!("Device {} no longer exists in ThingsBoard, repairing", device.name),
+        Err(e) => return DeviceSyncOutcome::failed(device, old_tb_device_id, tb_client.sanitize_error(&e)),
+    }
+
+    let previous_name = match context.state.database.get_tb_child_devices(&device.id).await {
+        Ok(children) => children.first().and_then(|child| child.name.rfind("-M").map(|pos| child.name[..pos].to_string())),
+        Err(e) => {
+            warn!("Failed to get ThingsBoard child devices of {}: {}", device.id, e);
+            None
+        }
+    };
+    let previous_index = previous_name
+        .as_deref()
+        .and_then(|name| parse_device_name_for_type_and_index(name, context.entity_group_name))
+        .map(|(_, index)| index);
+    let device_index = match previous_index {
+        Some(index) => index,
+        None => {
+            let counter = device_type_counters.entry(device_type.to_string()).or_insert(0);
+            *counter += 1;
+            *counter
+        }
+    };
+
+    let mut create_request = match tb_rust_client::to_thingsboard_device_with_type(device, context.entity_group_name, device_index, &context.state.database).await {
+        Ok(request) => request,
+        Err(_) => tb_rust_client::to_thingsboard_device(device, context.entity_group_name, device_index),
+    };
+    if let (Some(name), Some(_)) = (previous_name, previous_index) {
+        create_request.label = Some(name.clone());
+        create_request.name = name;
+    }
+
+    let (tb_device, outcome) = match tb_client.get_tenant_device(&create_request.name).await {
+        Ok(existing) => (existing, SyncOutcome::Relinked),
+        Err(TbError::NotFound(_)) => match tb_client.create_device(&create_request, context.entity_group_id, None).await {
+            Ok(created) => (created, SyncOutcome::Recreated),
+            Err(e) => return DeviceSyncOutcome::failed(device, old_tb_device_id, tb_client.sanitize_error(&e)),
+        },
+        Err(e) => return DeviceSyncOutcome::failed(device, old_tb_device_id, tb_client.sanitize_error(&e)),
+    };
+    let Some(tb_device_id) = tb_device.id.as_ref().map(|id| id.id.clone()) else {
+        return DeviceSyncOutcome::failed(device, old_tb_device_id, format!("ThingsBoard returned no id for {}", tb_device.name));
+    };
+
+    info!("Device {} {:?} as {} (TB ID: {})", device.name, outcome, tb_device.name, tb_device_id);
+    sync_linked_device(context, device, &tb_device, &create_request.device_type, device_index, failures).await;
+    DeviceSyncOutcome::new(device, outcome, Some(tb_device_id))
+}
+
+/// Push a synced device's attributes again, under its current ThingsBoard name
+async fn refresh_device_attributes(context: &SyncContext<'_>, device: &DeviceInstance, device_type: &str) -> DeviceSyncOutcome {
+    let tb_client = context.tb_client;
+    let tb_device_id = device.tb_device_id.clone().unwrap_or_default();
+    let result = match tb_client.get_device_by_id(&tb_device_id).await {
+        Ok(tb_device) => match tb_client.build_device_attributes(device, &tb_device.name, device_type, context.entity_group_name, &context.state.database).await {
+            Ok(attributes) => tb_client.update_device_attributes(&tb_device_id, attributes).await,
+            Err(e) => Err(e),
+        },
+        Err(TbError::NotFound(_)) => {
+            return DeviceSyncOutcome::failed(device, Some(tb_device_id), "device no longer exists in ThingsBoard; run a repair sync".to_string());
         }
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(()) => DeviceSyncOutcome::new(device, SyncOutcome::AttributesRefreshed, Some(tb_device_id)),
+        Err(e) => DeviceSyncOutcome::failed(device, Some(tb_device_id), tb_client.sanitize_error(&e)),
     }
 }
 
 // Generate device catalog request and response structures
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct GenerateDeviceCatalogRequest {
     pub entity_group_id: String,
     pub output_dir: String,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct GenerateDeviceCatalogResponse {
     pub message: String,
     pub file_path: String,
+    /// Version of the catalog's columns
+    pub format_version: u32,
 }
 
-/// Generate a CSV device catalog for the specified entity group
+/// Generate a CSV device catalog for the specified entity group.
+///
+/// Generation runs as a background job reporting each device written; its
+/// `GenerateDeviceCatalogResponse` becomes the job's `result`.
+#[utoipa::path(
+    post,
+    path = "/api/generate-device-catalog",
+    tag = "thingsboard",
+    request_body = GenerateDeviceCatalogRequest,
+    responses((status = 200, description = "Job queued, or the entity group's catalog already in progress", body = ApiResponse<Job>), (status = 409, description = "Catalog of another entity group running or queued", body = ApiResponse<Job>)),
+)]
 pub async fn generate_device_catalog(
     State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
     Json(request): Json<GenerateDeviceCatalogRequest>,
-) -> Result<Json<ApiResponse<GenerateDeviceCatalogResponse>>, StatusCode> {
-    use crate::tb_rust_client::ThingsBoardClient;
-    
+) -> Result<Json<ApiResponse<Job>>, ApiError> {
+    match state.database.get_active_job(&OperationKind::Catalog.to_string(), &request.entity_group_id).await {
+        Ok(Some(job)) => return Ok(Json(ApiResponse::success(job))),
+        Ok(None) => {}
+        Err(e) => warn!("Failed to look up active catalog jobs: {}", e),
+    }
+
+    let description = format!("Generate device catalog for entity group {}", request.entity_group_id);
+    let requested_by = user.map(|Extension(user)| user.username);
+    let operation = state
+        .scheduler
+        .enqueue(OperationKind::Catalog, &description, requested_by.clone())
+        .map_err(operation_conflict)?;
+    let job = JobTracker::queued(&state, &operation, &request.entity_group_id, description, requested_by).await;
+
+    let tracker = job.clone();
+    tokio::spawn(async move {
+        let _operation = operation.wait().await;
+        tracker.started().await;
+        tracker.finish(run_device_catalog(&state, &request, &tracker).await).await;
+    });
+
+    Ok(Json(ApiResponse::success(job.job())))
+}
+
+/// Write the catalog, reporting each device to the job as it is written
+async fn run_device_catalog(state: &AppState, request: &GenerateDeviceCatalogRequest, job: &JobTracker) -> Result<GenerateDeviceCatalogResponse, ApiError> {
     info!("Generating device catalog for entity group: {}", request.entity_group_id);
     
     // Connect to ThingsBoard
-    let base_url = "https://monitoring.avaasia.co".to_string();
-    let mut tb_client = ThingsBoardClient::new(&base_url);
+    let mut tb_client = match ThingsBoardClient::from_config(&state.config) {
+        Ok(client) => client
+            .with_session(state.tb_session.clone())
+            .with_rate_limiter(state.tb_rate_limiter.clone())
+            .with_group_cache(state.tb_group_cache.clone())
+            .with_report(job.report()),
+        Err(e) => return Err(e.into()),
+    };
     
-    match tb_client.login("jaydenyong28@gmail.com", "lalala88").await {
+    match tb_client.login_configured().await {
         Ok(()) => {
             info!("Successfully authenticated with ThingsBoard for catalog generation");
             
-            // Generate the device catalog CSV with database access
-            match tb_client.generate_detailed_device_catalog_csv(&request.entity_group_id, &request.output_dir, &state.database).await {
-                Ok(result) => {
+            // Progress is reported from inside the generator, so pass it through a channel to the job
+            let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel();
+            let on_device = move |progress: tb_rust_client::CatalogProgress| {
+                let _ = progress_tx.send(progress);
+            };
+            let client = &tb_client;
+            let generate = async move {
+                client.generate_detailed_device_catalog_csv_with_progress(&request.entity_group_id, &request.output_dir, &state.database, &on_device).await
+            };
+            let report = async {
+                while let Some(progress) = progress_rx.recv().await {
+                    if progress.current == 1 {
+                        job.set_total(progress.total).await;
+                    }
+                    let outcome = if progress.token_error.is_some() { "token_failed" } else { "written" };
+                    job.item(JobItemResult {
+                        name: progress.device_name,
+                        outcome: outcome.to_string(),
+                        error: progress.token_error,
+                    }).await;
+                }
+            };
+            let (result, ()) = tokio::join!(generate, report);
+            
+            match result {
+                Ok(summary) => {
+                    let file_path = format!("{}/{}", request.output_dir, summary.file_name);
                     let response = GenerateDeviceCatalogResponse {
-                        message: result.clone(),
-                        file_path: format!("{}/[entity-group-name]-device-catalog.csv", request.output_dir),
+                        message: summary.report(&file_path),
+                        file_path,
+                        format_version: summary.format_version,
                     };
                     
                     info!("Device catalog generated successfully");
-                    Ok(Json(ApiResponse::success(response)))
+                    state.notifications.broadcast(
+                        "catalog_completed",
+                        "info",
+                        "Device catalog generated".to_string(),
+                        response.message.clone(),
+                        Some(("entity_group", &request.entity_group_id)),
+                    ).await;
+                    Ok(response)
                 }
                 Err(e) => {
-                    error!("Failed to generate device catalog: {}", e);
-                    Ok(Json(ApiResponse::error(format!("Failed to generate device catalog: {}", e))))
+                    state.notifications.broadcast(
+                        "catalog_failed",
+                        "error",
+                        "Device catalog generation failed".to_string(),
+                        tb_client.sanitize_error(&e),
+                        Some(("entity_group", &request.entity_group_id)),
+                    ).await;
+                    Err(tb_error(&tb_client, "Failed to generate device catalog", &e))
                 }
             }
         }
-        Err(e) => {
-            error!("Failed to login to ThingsBoard for catalog generation: {}", e);
-            Ok(Json(ApiResponse::error(format!("Failed to login to ThingsBoard: {}", e))))
+        Err(e) => Err(tb_error(&tb_client, "Failed to login to ThingsBoard", &e)),
+    }
+}
+
+/// Generate an entity group's device catalog and send it as a CSV download.
+///
+/// The catalog is rendered in memory for this request only; nothing is kept on
+/// the gateway. Use `POST /api/generate-device-catalog` to save a copy instead.
+#[utoipa::path(
+    get,
+    path = "/api/device-catalog/{entity_group_id}/download",
+    tag = "thingsboard",
+    params(("entity_group_id" = String, Path, description = "ThingsBoard entity group id")),
+    responses(
+        (status = 200, description = "Device catalog CSV", content_type = "text/csv", body = String),
+        (status = 409, description = "Conflicting operation running or queued", body = ApiResponse<String>),
+        (status = 502, description = "ThingsBoard failed or the group has no devices to catalog", body = ApiResponse<String>),
+        (status = 503, description = "ThingsBoard is not configured", body = ApiResponse<String>),
+    ),
+)]
+pub async fn download_device_catalog(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Path(entity_group_id): Path<String>,
+) -> Result<Response, ApiError> {
+    use axum::body::Body;
+    use axum::http::header;
+
+    let _operation = state
+        .scheduler
+        .start(
+            OperationKind::Catalog,
+            &format!("Download device catalog for entity group {}", entity_group_id),
+            user.map(|Extension(user)| user.username),
+        )
+        .await
+        .map_err(operation_conflict)?;
+
+    let failed = |tb_client: &ThingsBoardClient, context: &str, e: TbError| tb_error(tb_client, context, &e);
+    let mut tb_client = ThingsBoardClient::from_config(&state.config)
+        .map(|client| client.with_session(state.tb_session.clone()).with_rate_limiter(state.tb_rate_limiter.clone()).with_group_cache(state.tb_group_cache.clone()))
+        .map_err(|e| failed(&ThingsBoardClient::new(""), "ThingsBoard unavailable", e))?;
+    tb_client
+        .login_configured()
+        .await
+        .map_err(|e| failed(&tb_client, "Failed to login to ThingsBoard", e))?;
+
+    let (summary, csv) = tb_client
+        .render_detailed_device_catalog_csv(&entity_group_id, &state.database, &|_| {})
+        .await
+        .map_err(|e| failed(&tb_client, "Failed to generate device catalog", e))?;
+
+    info!(
+        "Generated device catalog download {} with {} rows for {} devices",
+        summary.file_name, summary.total_rows, summary.devices.len()
+    );
+    Ok(Response::builder()
+        .status(200)
+        .header(header::CONTENT_TYPE, "text/csv; charset=utf-8")
+        .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", summary.file_name))
+        .body(Body::from(csv))
+        .unwrap())
+}
+
+/// Show running and queued admin operations so operators can see why a job is pending
+#[utoipa::path(
+    get,
+    path = "/api/jobs/queue",
+    tag = "jobs",
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<ScheduledOperation>>)),
+)]
+pub async fn get_jobs_queue(
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<Vec<ScheduledOperation>>>, ApiError> {
+    Ok(Json(ApiResponse::success(state.scheduler.snapshot())))
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct JobListQuery {
+    /// Defaults to 50
+    pub limit: Option<u32>,
+}
+
+/// Recent sync and catalog jobs, newest first, including finished ones
+#[utoipa::path(
+    get,
+    path = "/api/jobs",
+    tag = "jobs",
+    params(JobListQuery),
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<Job>>)),
+)]
+pub async fn get_jobs(
+    State(state): State<AppState>,
+    Query(query): Query<JobListQuery>,
+) -> Result<Json<ApiResponse<Vec<Job>>>, ApiError> {
+    match state.database.get_jobs(query.limit.unwrap_or(50)).await {
+        Ok(jobs) => Ok(Json(ApiResponse::success(jobs))),
+        Err(e) => Err(ApiError::internal(format!("Failed to get jobs: {}", e))),
+    }
+}
+
+/// A sync or catalog job with its progress, per-item results and, once done, its result or error
+#[utoipa::path(
+    get,
+    path = "/api/jobs/{id}",
+    tag = "jobs",
+    params(("id" = String, Path, description = "Job id")),
+    responses(
+        (status = 200, description = "Success", body = ApiResponse<Job>),
+        (status = 404, description = "Job not found"),
+    ),
+)]
+pub async fn get_job(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<Json<ApiResponse<Job>>, ApiError> {
+    match state.database.get_job(&job_id).await {
+        Ok(Some(job)) => Ok(Json(ApiResponse::success(job))),
+        Ok(None) => Err(ApiError::not_found(format!("Job {} not found", job_id))),
+        Err(e) => Err(ApiError::internal(format!("Failed to get job {}: {}", job_id, e))),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct DailyReportQuery {
+    pub date: Option<String>,
+    pub deliver: Option<bool>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DailyReportResponse {
+    pub report_date: String,
+    pub plant_name: String,
+    pub generated_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub report: Value,
+    pub summary_text: String,
+    /// The report rendered as HTML, as sent to the `daily_report` webhooks
+    pub summary_html: String,
+}
+
+impl DailyReportResponse {
+    fn from_report(report: crate::database::DailyReport) -> Self {
+        Self {
+            summary_html: serde_json::from_str(&report.content)
+                .map(|content| crate::reports::render_html(&content))
+                .unwrap_or_default(),
+            report: serde_json::from_str(&report.content).unwrap_or(Value::Null),
+            report_date: report.report_date,
+            plant_name: report.plant_name,
+            generated_at: report.generated_at,
+            delivered_at: report.delivered_at,
+            summary_text: report.summary_text,
+        }
+    }
+}
+
+/// Parse the `date` query parameter, defaulting to yesterday (UTC)
+fn parse_report_date(date: Option<&str>) -> Result<chrono::NaiveDate, ApiError> {
+    match date {
+        Some(date) => chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|_| ApiError::bad_request(format!("Invalid date '{}', expected YYYY-MM-DD", date))),
+        None => Ok(Utc::now().date_naive() - Duration::days(1)),
+    }
+}
+
+/// Get the stored daily report for a date
+#[utoipa::path(
+    get,
+    path = "/api/reports/daily",
+    tag = "reports",
+    params(DailyReportQuery),
+    responses((status = 200, description = "Success", body = ApiResponse<DailyReportResponse>), (status = 400, description = "Invalid date"), (status = 404, description = "No report stored for the date")),
+)]
+pub async fn get_daily_report(
+    State(state): State<AppState>,
+    Query(params): Query<DailyReportQuery>,
+) -> Result<Json<ApiResponse<DailyReportResponse>>, ApiError> {
+    let date = parse_report_date(params.date.as_deref())?;
+    let report_date = date.format("%Y-%m-%d").to_string();
+
+    match state.database.get_daily_report(&report_date).await {
+        Ok(Some(report)) => Ok(Json(ApiResponse::success(DailyReportResponse::from_report(report)))),
+        Ok(None) => Err(ApiError::not_found(format!("No daily report for {}", report_date))),
+        Err(e) => Err(ApiError::internal(format!("Failed to get daily report for {}: {}", report_date, e))),
+    }
+}
+
+/// Generate (or regenerate) the daily report for a date, optionally delivering it
+#[utoipa::path(
+    post,
+    path = "/api/reports/daily/generate",
+    tag = "reports",
+    params(DailyReportQuery),
+    responses((status = 200, description = "Success", body = ApiResponse<DailyReportResponse>), (status = 400, description = "Invalid date"), (status = 502, description = "Generated, but delivery failed")),
+)]
+pub async fn generate_daily_report(
+    State(state): State<AppState>,
+    Query(params): Query<DailyReportQuery>,
+) -> Result<Json<ApiResponse<DailyReportResponse>>, ApiError> {
+    let date = parse_report_date(params.date.as_deref())?;
+
+    let report = match state.report_service.generate(date).await {
+        Ok(report) => report,
+        Err(e) => return Err(ApiError::internal(format!("Failed to generate daily report for {}: {}", date, e))),
+    };
+
+    if params.deliver.unwrap_or(false) {
+        if let Err(e) = state.report_service.deliver(&report).await {
+            warn!("Failed to deliver daily report for {}: {}", report.report_date, e);
+            return Err(ApiError::bad_gateway(format!("Report generated but delivery failed: {}", e)));
+        }
+        if let Ok(Some(delivered)) = state.database.get_daily_report(&report.report_date).await {
+            return Ok(Json(ApiResponse::success(DailyReportResponse::from_report(delivered))));
+        }
+    }
+
+    Ok(Json(ApiResponse::success(DailyReportResponse::from_report(report))))
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct NotificationQuery {
+    pub unread: Option<bool>,
+    pub limit: Option<u32>,
+}
+
+/// List notifications for the logged-in user (own and broadcast)
+#[utoipa::path(
+    get,
+    path = "/api/notifications",
+    tag = "notifications",
+    params(NotificationQuery),
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<crate::database::Notification>>)),
+)]
+pub async fn get_notifications(
+    State(state): State<AppState>,
+    Extension(user): Extension<LocalUser>,
+    Query(params): Query<NotificationQuery>,
+) -> Result<Json<ApiResponse<Vec<crate::database::Notification>>>, ApiError> {
+    let user_id = user.id.ok_or_else(|| ApiError::unauthorized("Not logged in"))?;
+    let limit = params.limit.unwrap_or(100).min(1000);
+
+    match state.database.get_notifications(user_id, params.unread.unwrap_or(false), limit).await {
+        Ok(notifications) => Ok(Json(ApiResponse::success(notifications))),
+        Err(e) => Err(ApiError::internal(format!("Failed to get notifications for user {}: {}", user.username, e))),
+    }
+}
+
+/// Mark a single notification as read for the logged-in user
+#[utoipa::path(
+    post,
+    path = "/api/notifications/{id}/read",
+    tag = "notifications",
+    params(("id" = i64, Path, description = "Notification id")),
+    responses((status = 200, description = "Success", body = ApiResponse<String>), (status = 404, description = "Notification not found or not visible to the user")),
+)]
+pub async fn mark_notification_read(
+    State(state): State<AppState>,
+    Extension(user): Extension<LocalUser>,
+    Path(notification_id): Path<i64>,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    let user_id = user.id.ok_or_else(|| ApiError::unauthorized("Not logged in"))?;
+
+    match state.database.mark_notification_read(notification_id, user_id).await {
+        Ok(true) => Ok(Json(ApiResponse::success("Notification marked as read".to_string()))),
+        Ok(false) => Err(ApiError::not_found(format!("Notification {} not found", notification_id))),
+        Err(e) => Err(ApiError::internal(format!("Failed to mark notification {} as read: {}", notification_id, e))),
+    }
+}
+
+/// Mark every notification visible to the logged-in user as read
+#[utoipa::path(
+    post,
+    path = "/api/notifications/read-all",
+    tag = "notifications",
+    responses((status = 200, description = "Success", body = ApiResponse<usize>)),
+)]
+pub async fn mark_all_notifications_read(
+    State(state): State<AppState>,
+    Extension(user): Extension<LocalUser>,
+) -> Result<Json<ApiResponse<usize>>, ApiError> {
+    let user_id = user.id.ok_or_else(|| ApiError::unauthorized("Not logged in"))?;
+
+    match state.database.mark_all_notifications_read(user_id).await {
+        Ok(count) => Ok(Json(ApiResponse::success(count))),
+        Err(e) => Err(ApiError::internal(format!("Failed to mark notifications as read for user {}: {}", user.username, e))),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct AlarmQuery {
+    /// Only alarms that haven't cleared
+    pub active: Option<bool>,
+    pub device_id: Option<String>,
+    pub limit: Option<u32>,
+}
+
+/// Everything wrong with an alarm rule, naming the fields
+async fn alarm_rule_errors(state: &AppState, rule: &NewAlarmRule) -> Result<Vec<FieldError>, ApiError> {
+    let mut errors = Vec::new();
+    let mut error = |field: &str, message: String| errors.push(FieldError { field: field.to_string(), message });
+
+    match (&rule.device_id, &rule.model_id) {
+        (Some(device_id), None) => match state.database.get_device(device_id).await {
+            Ok(Some(_)) => {}
+            Ok(None) => error("device_id", format!("no device '{}'", device_id)),
+            Err(e) => return Err(ApiError::internal(format!("Failed to get device {}: {}", device_id, e))),
+        },
+        (None, Some(model_id)) => match state.database.get_device_model(model_id).await {
+            Ok(Some(_)) => {}
+            Ok(None) => error("model_id", format!("no device model '{}'", model_id)),
+            Err(e) => return Err(ApiError::internal(format!("Failed to get device model {}: {}", model_id, e))),
+        },
+        _ => error("device_id", "exactly one of device_id and model_id is required".to_string()),
+    }
+    if rule.tag_name.trim().is_empty() {
+        error("tag_name", "must not be empty".to_string());
+    }
+    if !rule.threshold.is_finite() || (rule.condition == AlarmCondition::Delta && rule.threshold < 0.0) {
+        error("threshold", "must be a number, and zero or positive for delta rules".to_string());
+    }
+    if !rule.hysteresis.is_finite() || rule.hysteresis < 0.0 {
+        error("hysteresis", "must be zero or positive".to_string());
+    }
+    if !SEVERITIES.contains(&rule.severity.as_str()) {
+        error("severity", format!("must be one of {}", SEVERITIES.join(", ")));
+    }
+    Ok(errors)
+}
+
+/// Load changed alarm rules into the running pollers. The change is already stored, so a
+/// failure is logged rather than failing the request.
+async fn reload_alarm_rules(state: &AppState) {
+    if let Err(e) = state.logging_service.reload_alarm_rules().await {
+        error!("Failed to reload alarm rules: {}", e);
+    }
+}
+
+/// List alarm rules
+#[utoipa::path(
+    get,
+    path = "/api/alarm-rules",
+    tag = "alarms",
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<AlarmRule>>)),
+)]
+pub async fn get_alarm_rules(
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<Vec<AlarmRule>>>, ApiError> {
+    match state.database.get_alarm_rules().await {
+        Ok(rules) => Ok(Json(ApiResponse::success(rules))),
+        Err(e) => Err(ApiError::internal(format!("Failed to get alarm rules: {}", e))),
+    }
+}
+
+/// Create an alarm rule on a tag of one device, or of every device of a model
+#[utoipa::path(
+    post,
+    path = "/api/alarm-rules",
+    tag = "alarms",
+    request_body = NewAlarmRule,
+    responses((status = 200, description = "Success", body = ApiResponse<AlarmRule>), (status = 400, description = "Invalid rule, with `field_errors`")),
+)]
+pub async fn create_alarm_rule(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Json(rule): Json<NewAlarmRule>,
+) -> Result<Json<ApiResponse<AlarmRule>>, ApiError> {
+    let errors = alarm_rule_errors(&state, &rule).await?;
+    if !errors.is_empty() {
+        return Err(ApiError::invalid_fields("Invalid alarm rule", errors));
+    }
+
+    match state.database.create_alarm_rule(&rule).await {
+        Ok(rule) => {
+            info!("Alarm rule {} created on tag {}", rule.id, rule.rule.tag_name);
+            audit(&state, &user, "alarm_rule.create", "alarm_rule", &rule.id.to_string(), None, audit_snapshot(&rule)).await;
+            reload_alarm_rules(&state).await;
+            Ok(Json(ApiResponse::success(rule)))
+        }
+        Err(e) => Err(ApiError::internal(format!("Failed to create alarm rule: {}", e))),
+    }
+}
+
+/// Replace an alarm rule. Disabling it clears the alarms it has open.
+#[utoipa::path(
+    put,
+    path = "/api/alarm-rules/{id}",
+    tag = "alarms",
+    params(("id" = i64, Path, description = "Alarm rule id")),
+    request_body = NewAlarmRule,
+    responses((status = 200, description = "Success", body = ApiResponse<AlarmRule>), (status = 400, description = "Invalid rule, with `field_errors`"), (status = 404, description = "Alarm rule not found")),
+)]
+pub async fn update_alarm_rule(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Path(rule_id): Path<i64>,
+    Json(rule): Json<NewAlarmRule>,
+) -> Result<Json<ApiResponse<AlarmRule>>, ApiError> {
+    let errors = alarm_rule_errors(&state, &rule).await?;
+    if !errors.is_empty() {
+        return Err(ApiError::invalid_fields("Invalid alarm rule", errors));
+    }
+
+    let before = state.database.get_alarm_rule(rule_id).await.ok().flatten();
+    match state.database.update_alarm_rule(rule_id, &rule).await {
+        Ok(Some(after)) => {
+            info!("Alarm rule {} updated", rule_id);
+            if let Some((before, after)) = before.as_ref().and_then(|before| audit_diff(before, &after, &["tag_name"])) {
+                audit(&state, &user, "alarm_rule.update", "alarm_rule", &rule_id.to_string(), Some(before), Some(after)).await;
+            }
+            reload_alarm_rules(&state).await;
+            Ok(Json(ApiResponse::success(after)))
+        }
+        Ok(None) => Err(ApiError::not_found(format!("Alarm rule {} not found", rule_id))),
+        Err(e) => Err(ApiError::internal(format!("Failed to update alarm rule {}: {}", rule_id, e))),
+    }
+}
+
+/// Delete an alarm rule, clearing the alarms it has open. Its past alarms are kept.
+#[utoipa::path(
+    delete,
+    path = "/api/alarm-rules/{id}",
+    tag = "alarms",
+    params(("id" = i64, Path, description = "Alarm rule id")),
+    responses((status = 200, description = "Success", body = ApiResponse<String>), (status = 404, description = "Alarm rule not found")),
+)]
+pub async fn delete_alarm_rule(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Path(rule_id): Path<i64>,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    let before = state.database.get_alarm_rule(rule_id).await.ok().flatten();
+    match state.database.delete_alarm_rule(rule_id).await {
+        Ok(true) => {
+            info!("Alarm rule {} deleted", rule_id);
+            audit(&state, &user, "alarm_rule.delete", "alarm_rule", &rule_id.to_string(), audit_snapshot(&before), None).await;
+            reload_alarm_rules(&state).await;
+            Ok(Json(ApiResponse::success("Alarm rule deleted".to_string())))
+        }
+        Ok(false) => Err(ApiError::not_found(format!("Alarm rule {} not found", rule_id))),
+        Err(e) => Err(ApiError::internal(format!("Failed to delete alarm rule {}: {}", rule_id, e))),
+    }
+}
+
+/// List alarms, most recently raised first; `active=true` for the ones still open
+#[utoipa::path(
+    get,
+    path = "/api/alarms",
+    tag = "alarms",
+    params(AlarmQuery),
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<AlarmEvent>>)),
+)]
+pub async fn get_alarms(
+    State(state): State<AppState>,
+    Query(params): Query<AlarmQuery>,
+) -> Result<Json<ApiResponse<Vec<AlarmEvent>>>, ApiError> {
+    let limit = params.limit.unwrap_or(100).min(1000);
+    match state.database.get_alarm_events(params.active.unwrap_or(false), params.device_id.as_deref(), limit).await {
+        Ok(alarms) => Ok(Json(ApiResponse::success(alarms))),
+        Err(e) => Err(ApiError::internal(format!("Failed to get alarms: {}", e))),
+    }
+}
+
+/// Everything wrong with a virtual tag, naming the fields; `tag_id` is the tag being replaced.
+/// A circular reference is reported on `expression` with the tags around the loop.
+async fn virtual_tag_errors(state: &AppState, tag: &NewVirtualTag, tag_id: Option<i64>) -> Result<Vec<FieldError>, ApiError> {
+    let devices = state.database.get_devices().await.map_err(|e| ApiError::internal(format!("Failed to get devices: {}", e)))?;
+    let virtual_tags = state.database.get_virtual_tags().await.map_err(|e| ApiError::internal(format!("Failed to get virtual tags: {}", e)))?;
+    let mut errors = Vec::new();
+    let mut error = |field: &str, message: String| errors.push(FieldError { field: field.to_string(), message });
+
+    if tag.name.trim().is_empty() {
+        error("name", "must not be empty".to_string());
+    }
+    match (&tag.device_id, &tag.group_id) {
+        (Some(device_id), None) if !devices.iter().any(|device| &device.id == device_id) => {
+            error("device_id", format!("no device '{}'", device_id));
+        }
+        (Some(device_id), None) => match state.database.get_device_tags(device_id).await {
+            Ok(tags) if tags.iter().any(|device_tag| device_tag.name == tag.name) => {
+                error("name", format!("device '{}' already has a tag '{}'", device_id, tag.name));
+            }
+            Ok(_) => {}
+            Err(e) => return Err(ApiError::internal(format!("Failed to get tags of device {}: {}", device_id, e))),
+        },
+        (None, Some(group_id)) if group_id.trim().is_empty() => error("group_id", "must not be empty".to_string()),
+        (None, Some(group_id)) if devices.iter().any(|device| &device.id == group_id) => {
+            error("group_id", format!("'{}' is a device; set device_id to log under the device", group_id));
+        }
+        (None, Some(_)) => {}
+        _ => error("device_id", "exactly one of device_id and group_id is required".to_string()),
+    }
+    if let Some(other) = virtual_tags.iter().find(|other| Some(other.id) != tag_id && other.tag.owner() == tag.owner() && other.tag.name == tag.name) {
+        error("name", format!("virtual tag {} is already called '{}' under '{}'", other.id, tag.name, tag.owner()));
+    }
+    if let Some(schedule_group_id) = &tag.schedule_group_id {
+        match state.database.get_schedule_group(schedule_group_id).await {
+            Ok(Some(_)) => {}
+            Ok(None) => error("schedule_group_id", format!("no schedule group '{}'", schedule_group_id)),
+            Err(e) => return Err(ApiError::internal(format!("Failed to get schedule group {}: {}", schedule_group_id, e))),
+        }
+    }
+
+    match Expr::parse(&tag.expression) {
+        Err(e) => error("expression", e.to_string()),
+        // Disabled tags aren't computed, so they can't be part of a loop
+        Ok(_) if tag.enabled => {
+            let groups: DeviceGroups = devices.iter().map(|device| (device.id.clone(), device.tb_group_id.clone())).collect();
+            let mut tags: Vec<NewVirtualTag> = virtual_tags
+                .into_iter()
+                .filter(|other| Some(other.id) != tag_id && other.tag.enabled)
+                .map(|other| other.tag)
+                .collect();
+            tags.push(tag.clone());
+            if let Some(cycle) = find_cycle(&tags, &groups) {
+                error("expression", format!("circular reference: {}", cycle.join(" -> ")));
+            }
+        }
+        Ok(_) => {}
+    }
+    Ok(errors)
+}
+
+/// Load changed virtual tags into the running engine. The change is already stored, so a
+/// failure is logged rather than failing the request.
+async fn reload_virtual_tags(state: &AppState) {
+    if let Err(e) = state.logging_service.reload_virtual_tags().await {
+        error!("Failed to reload virtual tags: {}", e);
+    }
+}
+
+/// List virtual tags
+#[utoipa::path(
+    get,
+    path = "/api/virtual-tags",
+    tag = "virtual-tags",
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<VirtualTag>>)),
+)]
+pub async fn get_virtual_tags(
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<Vec<VirtualTag>>>, ApiError> {
+    match state.database.get_virtual_tags().await {
+        Ok(tags) => Ok(Json(ApiResponse::success(tags))),
+        Err(e) => Err(ApiError::internal(format!("Failed to get virtual tags: {}", e))),
+    }
+}
+
+/// Create a virtual tag computed from other tags, logged under a device or a group
+#[utoipa::path(
+    post,
+    path = "/api/virtual-tags",
+    tag = "virtual-tags",
+    request_body = NewVirtualTag,
+    responses((status = 200, description = "Success", body = ApiResponse<VirtualTag>), (status = 400, description = "Invalid tag or circular reference, with `field_errors`")),
+)]
+pub async fn create_virtual_tag(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Json(tag): Json<NewVirtualTag>,
+) -> Result<Json<ApiResponse<VirtualTag>>, ApiError> {
+    let errors = virtual_tag_errors(&state, &tag, None).await?;
+    if !errors.is_empty() {
+        return Err(ApiError::invalid_fields("Invalid virtual tag", errors));
+    }
+
+    match state.database.create_virtual_tag(&tag).await {
+        Ok(tag) => {
+            info!("Virtual tag {} created as {}/{}", tag.id, tag.tag.owner(), tag.tag.name);
+            audit(&state, &user, "virtual_tag.create", "virtual_tag", &tag.id.to_string(), None, audit_snapshot(&tag)).await;
+            reload_virtual_tags(&state).await;
+            Ok(Json(ApiResponse::success(tag)))
+        }
+        Err(e) => Err(ApiError::internal(format!("Failed to create virtual tag: {}", e))),
+    }
+}
+
+/// Replace a virtual tag
+#[utoipa::path(
+    put,
+    path = "/api/virtual-tags/{id}",
+    tag = "virtual-tags",
+    params(("id" = i64, Path, description = "Virtual tag id")),
+    request_body = NewVirtualTag,
+    responses((status = 200, description = "Success", body = ApiResponse<VirtualTag>), (status = 400, description = "Invalid tag or circular reference, with `field_errors`"), (status = 404, description = "Virtual tag not found")),
+)]
+pub async fn update_virtual_tag(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Path(tag_id): Path<i64>,
+    Json(tag): Json<NewVirtualTag>,
+) -> Result<Json<ApiResponse<VirtualTag>>, ApiError> {
+    let errors = virtual_tag_errors(&state, &tag, Some(tag_id)).await?;
+    if !errors.is_empty() {
+        return Err(ApiError::invalid_fields("Invalid virtual tag", errors));
+    }
+
+    let before = state.database.get_virtual_tag(tag_id).await.ok().flatten();
+    match state.database.update_virtual_tag(tag_id, &tag).await {
+        Ok(Some(after)) => {
+            info!("Virtual tag {} updated", tag_id);
+            if let Some((before, after)) = before.as_ref().and_then(|before| audit_diff(before, &after, &["name"])) {
+                audit(&state, &user, "virtual_tag.update", "virtual_tag", &tag_id.to_string(), Some(before), Some(after)).await;
+            }
+            reload_virtual_tags(&state).await;
+            Ok(Json(ApiResponse::success(after)))
+        }
+        Ok(None) => Err(ApiError::not_found(format!("Virtual tag {} not found", tag_id))),
+        Err(e) => Err(ApiError::internal(format!("Failed to update virtual tag {}: {}", tag_id, e))),
+    }
+}
+
+/// Delete a virtual tag. The values it logged are kept.
+#[utoipa::path(
+    delete,
+    path = "/api/virtual-tags/{id}",
+    tag = "virtual-tags",
+    params(("id" = i64, Path, description = "Virtual tag id")),
+    responses((status = 200, description = "Success", body = ApiResponse<String>), (status = 404, description = "Virtual tag not found")),
+)]
+pub async fn delete_virtual_tag(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Path(tag_id): Path<i64>,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    let before = state.database.get_virtual_tag(tag_id).await.ok().flatten();
+    match state.database.delete_virtual_tag(tag_id).await {
+        Ok(true) => {
+            info!("Virtual tag {} deleted", tag_id);
+            audit(&state, &user, "virtual_tag.delete", "virtual_tag", &tag_id.to_string(), audit_snapshot(&before), None).await;
+            reload_virtual_tags(&state).await;
+            Ok(Json(ApiResponse::success("Virtual tag deleted".to_string())))
+        }
+        Ok(false) => Err(ApiError::not_found(format!("Virtual tag {} not found", tag_id))),
+        Err(e) => Err(ApiError::internal(format!("Failed to delete virtual tag {}: {}", tag_id, e))),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WebhooksState {
+    /// Secrets are left out
+    pub webhooks: Vec<WebhookConfig>,
+    /// Events dropped since the service started because the delivery queue was full
+    pub dropped_events: u64,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct WebhookDeliveryQuery {
+    /// Defaults to 100
+    pub limit: Option<u32>,
+}
+
+/// A webhook as the API returns it; the secret is write-only
+fn without_secret(mut hook: WebhookConfig) -> WebhookConfig {
+    hook.secret.clear();
+    hook
+}
+
+/// Save the webhooks from `[[webhooks]]` after `change`, and send to them from now on.
+/// `change` returns None to leave them untouched, e.g. when the webhook doesn't exist.
+async fn save_webhooks<T>(state: &AppState, change: impl FnOnce(&mut Vec<WebhookConfig>) -> Option<T>) -> anyhow::Result<Option<T>> {
+    let mut app_config = load_config_from(&state.config_path()).await?;
+    let Some(changed) = change(&mut app_config.webhooks) else {
+        return Ok(None);
+    };
+    save_config_to(&state.config_path(), &app_config).await?;
+    state.webhooks.apply(app_config.webhooks);
+    Ok(Some(changed))
+}
+
+/// Webhooks and how many events were dropped because they couldn't keep up
+#[utoipa::path(
+    get,
+    path = "/api/webhooks",
+    tag = "webhooks",
+    responses((status = 200, description = "Success", body = ApiResponse<WebhooksState>)),
+)]
+pub async fn get_webhooks(
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<WebhooksState>>, ApiError> {
+    Ok(Json(ApiResponse::success(WebhooksState {
+        webhooks: state.webhooks.hooks().into_iter().map(without_secret).collect(),
+        dropped_events: state.webhooks.dropped_events(),
+    })))
+}
+
+/// Add a webhook to `[[webhooks]]`. Its events are POSTed as JSON with an
+/// `X-Webhook-Signature: sha256=<hex>` HMAC of the body keyed with the secret.
+#[utoipa::path(
+    post,
+    path = "/api/webhooks",
+    tag = "webhooks",
+    request_body = WebhookConfig,
+    responses((status = 200, description = "Success", body = ApiResponse<WebhookConfig>), (status = 400, description = "Invalid webhook, with `field_errors`")),
+)]
+pub async fn create_webhook(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Json(mut hook): Json<WebhookConfig>,
+) -> Result<Json<ApiResponse<WebhookConfig>>, ApiError> {
+    hook.id = Uuid::new_v4().to_string();
+    hook.url = hook.url.trim().to_string();
+    let errors = hook.validate();
+    if !errors.is_empty() {
+        return Err(ApiError::invalid_fields("Invalid webhook", errors));
+    }
+
+    let created = hook.clone();
+    let saved = save_webhooks(&state, |webhooks| {
+        webhooks.push(created);
+        Some(())
+    }).await;
+    match saved {
+        Ok(_) => {
+            info!("Webhook {} created for {}", hook.id, hook.url);
+            audit(&state, &user, "webhook.create", "webhook", &hook.id, None, audit_snapshot(&hook)).await;
+            Ok(Json(ApiResponse::success(without_secret(hook))))
+        }
+        Err(e) => Err(ApiError::internal(format!("Failed to save webhook: {}", e))),
+    }
+}
+
+/// Replace a webhook; a blank secret keeps the current one
+#[utoipa::path(
+    put,
+    path = "/api/webhooks/{id}",
+    tag = "webhooks",
+    params(("id" = String, Path, description = "Webhook id")),
+    request_body = WebhookConfig,
+    responses((status = 200, description = "Success", body = ApiResponse<WebhookConfig>), (status = 400, description = "Invalid webhook, with `field_errors`"), (status = 404, description = "Webhook not found")),
+)]
+pub async fn update_webhook(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Path(webhook_id): Path<String>,
+    Json(mut hook): Json<WebhookConfig>,
+) -> Result<Json<ApiResponse<WebhookConfig>>, ApiError> {
+    let Some(before) = state.webhooks.hooks().into_iter().find(|existing| existing.id == webhook_id) else {
+        return Err(ApiError::not_found(format!("Webhook {} not found", webhook_id)));
+    };
+    hook.id = webhook_id.clone();
+    hook.url = hook.url.trim().to_string();
+    if hook.secret.is_empty() {
+        hook.secret = before.secret.clone();
+    }
+    let errors = hook.validate();
+    if !errors.is_empty() {
+        return Err(ApiError::invalid_fields("Invalid webhook", errors));
+    }
+
+    let replacement = hook.clone();
+    let saved = save_webhooks(&state, |webhooks| {
+        let existing = webhooks.iter_mut().find(|existing| existing.id == webhook_id)?;
+        *existing = replacement;
+        Some(())
+    }).await;
+    match saved {
+        Ok(Some(())) => {
+            info!("Webhook {} updated", webhook_id);
+            if let Some((before, after)) = audit_diff(&before, &hook, &["url"]) {
+                audit(&state, &user, "webhook.update", "webhook", &webhook_id, Some(before), Some(after)).await;
+            }
+            Ok(Json(ApiResponse::success(without_secret(hook))))
+        }
+        Ok(None) => Err(ApiError::not_found(format!("Webhook {} not found", webhook_id))),
+        Err(e) => Err(ApiError::internal(format!("Failed to save webhook {}: {}", webhook_id, e))),
+    }
+}
+
+/// Remove a webhook and its delivery log. Deliveries being retried stop.
+#[utoipa::path(
+    delete,
+    path = "/api/webhooks/{id}",
+    tag = "webhooks",
+    params(("id" = String, Path, description = "Webhook id")),
+    responses((status = 200, description = "Success", body = ApiResponse<String>), (status = 404, description = "Webhook not found")),
+)]
+pub async fn delete_webhook(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Path(webhook_id): Path<String>,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    let saved = save_webhooks(&state, |webhooks| {
+        let index = webhooks.iter().position(|existing| existing.id == webhook_id)?;
+        Some(webhooks.remove(index))
+    }).await;
+    match saved {
+        Ok(Some(before)) => {
+            if let Err(e) = state.database.delete_webhook_deliveries(&webhook_id).await {
+                warn!("Failed to delete deliveries of webhook {}: {}", webhook_id, e);
+            }
+            info!("Webhook {} deleted", webhook_id);
+            audit(&state, &user, "webhook.delete", "webhook", &webhook_id, audit_snapshot(&before), None).await;
+            Ok(Json(ApiResponse::success("Webhook deleted".to_string())))
         }
+        Ok(None) => Err(ApiError::not_found(format!("Webhook {} not found", webhook_id))),
+        Err(e) => Err(ApiError::internal(format!("Failed to delete webhook {}: {}", webhook_id, e))),
+    }
+}
+
+/// A webhook's latest deliveries, newest first, with their attempts and last error
+#[utoipa::path(
+    get,
+    path = "/api/webhooks/{id}/deliveries",
+    tag = "webhooks",
+    params(("id" = String, Path, description = "Webhook id"), WebhookDeliveryQuery),
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<WebhookDelivery>>), (status = 404, description = "Webhook not found")),
+)]
+pub async fn get_webhook_deliveries(
+    State(state): State<AppState>,
+    Path(webhook_id): Path<String>,
+    Query(params): Query<WebhookDeliveryQuery>,
+) -> Result<Json<ApiResponse<Vec<WebhookDelivery>>>, ApiError> {
+    if !state.webhooks.hooks().iter().any(|hook| hook.id == webhook_id) {
+        return Err(ApiError::not_found(format!("Webhook {} not found", webhook_id)));
+    }
+    let limit = params.limit.unwrap_or(100).min(1000);
+    match state.database.get_webhook_deliveries(&webhook_id, limit).await {
+        Ok(deliveries) => Ok(Json(ApiResponse::success(deliveries))),
+        Err(e) => Err(ApiError::internal(format!("Failed to get deliveries of webhook {}: {}", webhook_id, e))),
     }
 }
 
 // File Management API endpoints
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct FileInfo {
     pub name: String,
     pub size: u64,
@@ -1731,137 +7309,196 @@ pub struct FileInfo {
     pub download_url: String,
 }
 
-/// List all CSV files in the catalogs directory
-pub async fn list_catalog_files() -> Result<Json<ApiResponse<Vec<FileInfo>>>, StatusCode> {
-    use std::fs;
-    use chrono::{DateTime, Utc};
-    
-    let catalog_dir = "catalogs";
-    
-    match fs::read_dir(catalog_dir) {
-        Ok(entries) => {
-            let mut files = Vec::new();
-            
-            for entry in entries {
-                if let Ok(entry) = entry {
-                    let path = entry.path();
-                    if path.is_file() {
-                        if let Some(filename) = path.file_name().and_then(|n| n.to_str()) {
-                            // Only include CSV files
-                            if filename.ends_with(".csv") {
-                                if let Ok(metadata) = entry.metadata() {
-                                    let modified = metadata.modified()
-                                        .map(|time| {
-                                            let datetime: DateTime<Utc> = time.into();
-                                            datetime.format("%Y-%m-%d %H:%M:%S UTC").to_string()
-                                        })
-                                        .unwrap_or_else(|_| "Unknown".to_string());
-                                    
-                                    files.push(FileInfo {
-                                        name: filename.to_string(),
-                                        size: metadata.len(),
-                                        modified,
-                                        download_url: format!("/api/files/catalogs/{}", filename),
-                                    });
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-            
-            // Sort by modification time (newest first)
-            files.sort_by(|a, b| b.modified.cmp(&a.modified));
-            
-            Ok(Json(ApiResponse::success(files)))
+/// Directories served by the file endpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FileKind {
+    /// Device catalogs generated for ThingsBoard
+    Catalogs,
+    /// Logged values written by `[file_export]`
+    Exports,
+}
+
+impl FileKind {
+    fn path_segment(self) -> &'static str {
+        match self {
+            FileKind::Catalogs => "catalogs",
+            FileKind::Exports => "exports",
         }
-        Err(e) => {
-            error!("Failed to read catalog directory: {}", e);
-            Ok(Json(ApiResponse::error(format!("Failed to read catalog directory: {}", e))))
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            FileKind::Catalogs => "Catalog",
+            FileKind::Exports => "Export",
         }
     }
 }
 
-/// Download a specific CSV file
-pub async fn download_catalog_file(Path(filename): Path<String>) -> Result<impl axum::response::IntoResponse, StatusCode> {
-    use axum::response::Response;
-    use axum::body::Body;
-    use axum::http::{header, HeaderMap};
-    use std::fs;
-    use std::path::Path as StdPath;
-    
-    // Security: Only allow CSV files and prevent directory traversal
-    if !filename.ends_with(".csv") || filename.contains("..") || filename.contains("/") || filename.contains("\\") {
-        return Err(StatusCode::BAD_REQUEST);
+/// Directory of `kind`; exports are only served while `[file_export]` is configured
+fn file_directory(state: &AppState, kind: FileKind) -> Result<std::path::PathBuf, ApiError> {
+    match kind {
+        FileKind::Catalogs => Ok(state.data_dir.join("catalogs")),
+        FileKind::Exports => match &state.file_export {
+            Some(file_export) => Ok(file_export.directory().to_path_buf()),
+            None => Err(ApiError::not_found("File export is not enabled; configure [file_export] in config.toml")),
+        },
     }
-    
-    let file_path = StdPath::new("catalogs").join(&filename);
-    
-    match fs::read(&file_path) {
-        Ok(contents) => {
-            let mut headers = HeaderMap::new();
-            headers.insert(
-                header::CONTENT_TYPE,
-                "text/csv; charset=utf-8".parse().unwrap(),
-            );
-            headers.insert(
-                header::CONTENT_DISPOSITION,
-                format!("attachment; filename=\"{}\"", filename).parse().unwrap(),
-            );
-            
-            Ok(Response::builder()
-                .status(200)
-                .header(header::CONTENT_TYPE, "text/csv; charset=utf-8")
-                .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename))
-                .body(Body::from(contents))
-                .unwrap())
+}
+
+/// Path of a file of `kind`. Only plain CSV file names are accepted, so no request can reach
+/// outside the directory
+fn file_path(state: &AppState, kind: FileKind, filename: &str) -> Result<std::path::PathBuf, ApiError> {
+    if !filename.ends_with(".csv") || filename.contains("..") || filename.contains('/') || filename.contains('\\') {
+        return Err(ApiError::bad_request(format!("Invalid {} file name {}", kind.label().to_lowercase(), filename)));
+    }
+    Ok(file_directory(state, kind)?.join(filename))
+}
+
+/// List the CSV files of the catalogs or exports directory, newest first
+#[utoipa::path(
+    get,
+    path = "/api/files/{kind}",
+    tag = "files",
+    params(("kind" = FileKind, Path, description = "`catalogs` or `exports`")),
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<FileInfo>>), (status = 404, description = "File export is not enabled")),
+)]
+pub async fn list_files(State(state): State<AppState>, Path(kind): Path<FileKind>) -> Result<Json<ApiResponse<Vec<FileInfo>>>, ApiError> {
+    let directory = file_directory(&state, kind)?;
+    let mut entries = match tokio::fs::read_dir(&directory).await {
+        Ok(entries) => entries,
+        // Nothing generated or exported yet
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Json(ApiResponse::success(Vec::new()))),
+        Err(e) => return Err(ApiError::internal(format!("Failed to read {} directory: {}", kind.label().to_lowercase(), e))),
+    };
+
+    let mut files = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let Ok(filename) = entry.file_name().into_string() else {
+            continue;
+        };
+        let Ok(metadata) = entry.metadata().await else {
+            continue;
+        };
+        if !metadata.is_file() || !filename.ends_with(".csv") {
+            continue;
         }
-        Err(_) => Err(StatusCode::NOT_FOUND),
+        let modified = metadata
+            .modified()
+            .map(|time| DateTime::<Utc>::from(time).format("%Y-%m-%d %H:%M:%S UTC").to_string())
+            .unwrap_or_else(|_| "Unknown".to_string());
+        files.push(FileInfo {
+            download_url: format!("/api/files/{}/{}", kind.path_segment(), filename),
+            name: filename,
+            size: metadata.len(),
+            modified,
+        });
     }
+
+    // Sort by modification time (newest first)
+    files.sort_by(|a, b| b.modified.cmp(&a.modified).then_with(|| b.name.cmp(&a.name)));
+    Ok(Json(ApiResponse::success(files)))
 }
 
-/// Delete a specific CSV file
-pub async fn delete_catalog_file(Path(filename): Path<String>) -> Result<Json<ApiResponse<String>>, StatusCode> {
-    use std::fs;
-    use std::path::Path as StdPath;
-    
-    // Security: Only allow CSV files and prevent directory traversal
-    if !filename.ends_with(".csv") || filename.contains("..") || filename.contains("/") || filename.contains("\\") {
-        return Ok(Json(ApiResponse::error("Invalid filename or file type".to_string())));
+/// Download a catalog or export CSV file. Export files still being written hold the rows
+/// logged up to now
+#[utoipa::path(
+    get,
+    path = "/api/files/{kind}/{filename}",
+    tag = "files",
+    params(("kind" = FileKind, Path, description = "`catalogs` or `exports`"), ("filename" = String, Path, description = "CSV file name")),
+    responses((status = 200, description = "CSV file", content_type = "text/csv", body = String), (status = 400, description = "Invalid file name"), (status = 404, description = "Not found")),
+)]
+pub async fn download_file(
+    State(state): State<AppState>,
+    Path((kind, filename)): Path<(FileKind, String)>,
+) -> Result<impl axum::response::IntoResponse, ApiError> {
+    use axum::body::Body;
+    use axum::http::header;
+    use axum::response::Response;
+
+    let path = file_path(&state, kind, &filename)?;
+    let file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(_) => return Err(ApiError::not_found(format!("{} file {} not found", kind.label(), filename))),
+    };
+
+    Ok(Response::builder()
+        .status(200)
+        .header(header::CONTENT_TYPE, "text/csv; charset=utf-8")
+        .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename))
+        .body(Body::from_stream(tokio_util::io::ReaderStream::new(file)))
+        .unwrap())
+}
+
+/// Delete a catalog or export CSV file. An export file still being written can't be deleted
+#[utoipa::path(
+    delete,
+    path = "/api/files/{kind}/{filename}",
+    tag = "files",
+    params(("kind" = FileKind, Path, description = "`catalogs` or `exports`"), ("filename" = String, Path, description = "CSV file name")),
+    responses((status = 200, description = "Success", body = ApiResponse<String>), (status = 400, description = "Invalid file name"), (status = 404, description = "Not found"), (status = 409, description = "The export file is still being written")),
+)]
+pub async fn delete_file(
+    State(state): State<AppState>,
+    Path((kind, filename)): Path<(FileKind, String)>,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    let path = file_path(&state, kind, &filename)?;
+    if kind == FileKind::Exports && state.file_export.as_ref().is_some_and(|file_export| file_export.is_open(&filename)) {
+        return Err(ApiError::conflict(format!("Export file {} is still being written; it can be deleted once its period is over", filename)));
     }
-    
-    let file_path = StdPath::new("catalogs").join(&filename);
-    
-    match fs::remove_file(&file_path) {
+
+    match tokio::fs::remove_file(&path).await {
         Ok(_) => {
-            info!("Deleted catalog file: {}", filename);
+            info!("Deleted {} file: {}", kind.label().to_lowercase(), filename);
             Ok(Json(ApiResponse::success(format!("File '{}' deleted successfully", filename))))
         }
-        Err(e) => {
-            error!("Failed to delete catalog file {}: {}", filename, e);
-            Ok(Json(ApiResponse::error(format!("Failed to delete file: {}", e))))
-        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(ApiError::not_found(format!("{} file {} not found", kind.label(), filename))),
+        Err(e) => Err(ApiError::internal(format!("Failed to delete {} file {}: {}", kind.label().to_lowercase(), filename, e))),
     }
 }
 
 // Authentication endpoints
 
+/// Lifetime of a session from login or its last refresh
+const SESSION_HOURS: i64 = 24;
+
+/// Token of the session a request was made with
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers.get("Authorization")?.to_str().ok()?.strip_prefix("Bearer ")
+}
+
+/// Address a request came from, recorded with the sessions it creates
+fn source_ip(connect_info: &Option<ConnectInfo<std::net::SocketAddr>>) -> Option<String> {
+    connect_info.as_ref().map(|ConnectInfo(address)| address.ip().to_string())
+}
+
 /// Login endpoint - validates credentials and creates session
+#[utoipa::path(
+    post,
+    path = "/api/login",
+    tag = "auth",
+    request_body = LoginRequest,
+    responses((status = 200, description = "Success", body = ApiResponse<LoginResponse>), (status = 401, description = "Invalid username or password")),
+    security(()),
+)]
 pub async fn login(
     State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<std::net::SocketAddr>>,
     Json(request): Json<LoginRequest>,
-) -> Result<Json<ApiResponse<LoginResponse>>, StatusCode> {
+) -> Result<Json<ApiResponse<LoginResponse>>, ApiError> {
     // Verify user credentials
     match state.database.verify_user(&request.username, &request.password).await {
         Ok(Some(user)) => {
             // Generate session token
             let session_token = Uuid::new_v4().to_string();
-            let expires_at = Utc::now() + Duration::hours(24); // 24-hour sessions
+            let expires_at = Utc::now() + Duration::hours(SESSION_HOURS);
             
             // Create session in database
-            match state.database.create_session(user.id.unwrap(), &session_token, expires_at).await {
+            match state.database.create_session(user.id.unwrap(), &session_token, expires_at, source_ip(&connect_info).as_deref()).await {
                 Ok(_) => {
                     info!("User '{}' logged in successfully", user.username);
+                    audit(&state, &Some(Extension(user.clone())), "login", "session", &user.username, None, None).await;
                     
                     let response = LoginResponse {
                         session_token,
@@ -1869,66 +7506,71 @@ pub async fn login(
                             id: user.id.unwrap(),
                             username: user.username,
                             role: user.role,
+                            must_change_password: user.must_change_password,
                         },
                         expires_at,
                     };
                     
                     Ok(Json(ApiResponse::success(response)))
                 }
-                Err(e) => {
-                    error!("Failed to create session: {}", e);
-                    Ok(Json(ApiResponse::error("Failed to create session".to_string())))
-                }
+                Err(e) => Err(ApiError::internal(format!("Failed to create session: {}", e))),
             }
         }
         Ok(None) => {
             warn!("Invalid login attempt for username: {}", request.username);
-            Ok(Json(ApiResponse::error("Invalid username or password".to_string())))
-        }
-        Err(e) => {
-            error!("Database error during login: {}", e);
-            Ok(Json(ApiResponse::error("Internal server error".to_string())))
+            Err(ApiError::unauthorized("Invalid username or password"))
         }
+        Err(e) => Err(ApiError::internal(format!("Database error during login: {}", e))),
     }
 }
 
 /// Logout endpoint - revokes session
+#[utoipa::path(
+    post,
+    path = "/api/logout",
+    tag = "auth",
+    responses((status = 200, description = "Success", body = ApiResponse<String>), (status = 401, description = "No session")),
+)]
 pub async fn logout(
     State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
     headers: HeaderMap,
-) -> Result<Json<ApiResponse<String>>, StatusCode> {
+) -> Result<Json<ApiResponse<String>>, ApiError> {
     if let Some(auth_header) = headers.get("Authorization") {
         if let Ok(auth_str) = auth_header.to_str() {
             if let Some(token) = auth_str.strip_prefix("Bearer ") {
                 match state.database.revoke_session(token).await {
                     Ok(true) => {
                         info!("Session revoked successfully");
+                        let username = user.as_ref().map(|Extension(user)| user.username.clone()).unwrap_or_default();
+                        audit(&state, &user, "logout", "session", &username, None, None).await;
                         Ok(Json(ApiResponse::success("Logged out successfully".to_string())))
                     }
-                    Ok(false) => {
-                        Ok(Json(ApiResponse::error("Session not found".to_string())))
-                    }
-                    Err(e) => {
-                        error!("Failed to revoke session: {}", e);
-                        Ok(Json(ApiResponse::error("Failed to logout".to_string())))
-                    }
+                    Ok(false) => Err(ApiError::unauthorized("Session not found")),
+                    Err(e) => Err(ApiError::internal(format!("Failed to revoke session: {}", e))),
                 }
             } else {
-                Ok(Json(ApiResponse::error("Invalid authorization header format".to_string())))
+                Err(ApiError::unauthorized("Invalid authorization header format"))
             }
         } else {
-            Ok(Json(ApiResponse::error("Invalid authorization header".to_string())))
+            Err(ApiError::unauthorized("Invalid authorization header"))
         }
     } else {
-        Ok(Json(ApiResponse::error("No authorization header provided".to_string())))
+        Err(ApiError::unauthorized("No authorization header provided"))
     }
 }
 
 /// Verify session endpoint - checks if session is valid
+#[utoipa::path(
+    get,
+    path = "/api/verify-session",
+    tag = "auth",
+    responses((status = 200, description = "Success", body = ApiResponse<UserInfo>), (status = 401, description = "No session, or it expired or was revoked")),
+)]
 pub async fn verify_session(
     State(state): State<AppState>,
     headers: HeaderMap,
-) -> Result<Json<ApiResponse<UserInfo>>, StatusCode> {
+) -> Result<Json<ApiResponse<UserInfo>>, ApiError> {
     if let Some(auth_header) = headers.get("Authorization") {
         if let Ok(auth_str) = auth_header.to_str() {
             if let Some(token) = auth_str.strip_prefix("Bearer ") {
@@ -1938,91 +7580,599 @@ pub async fn verify_session(
                             id: user.id.unwrap(),
                             username: user.username,
                             role: user.role,
+                            must_change_password: user.must_change_password,
                         })))
                     }
-                    Ok(None) => {
-                        Ok(Json(ApiResponse::error("Invalid or expired session".to_string())))
-                    }
-                    Err(e) => {
-                        error!("Database error during session verification: {}", e);
-                        Ok(Json(ApiResponse::error("Internal server error".to_string())))
-                    }
+                    Ok(None) => Err(ApiError::unauthorized("Invalid or expired session")),
+                    Err(e) => Err(ApiError::internal(format!("Database error during session verification: {}", e))),
                 }
             } else {
-                Ok(Json(ApiResponse::error("Invalid authorization header format".to_string())))
+                Err(ApiError::unauthorized("Invalid authorization header format"))
             }
         } else {
-            Ok(Json(ApiResponse::error("Invalid authorization header".to_string())))
+            Err(ApiError::unauthorized("Invalid authorization header"))
         }
     } else {
-        Ok(Json(ApiResponse::error("No authorization header provided".to_string())))
+        Err(ApiError::unauthorized("No authorization header provided"))
+    }
+}
+
+/// Swap the session making the request for a new one, expiring 24 hours from now.
+/// The old token stops working at once.
+#[utoipa::path(
+    post,
+    path = "/api/session/refresh",
+    tag = "auth",
+    responses((status = 200, description = "Success", body = ApiResponse<LoginResponse>), (status = 401, description = "No session, or it expired or was revoked")),
+)]
+pub async fn refresh_session(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    connect_info: Option<ConnectInfo<std::net::SocketAddr>>,
+    headers: HeaderMap,
+) -> Result<Json<ApiResponse<LoginResponse>>, ApiError> {
+    let (Some(Extension(user)), Some(token)) = (user, bearer_token(&headers)) else {
+        return Err(ApiError::unauthorized("Not logged in"));
+    };
+
+    let session_token = Uuid::new_v4().to_string();
+    let expires_at = Utc::now() + Duration::hours(SESSION_HOURS);
+    match state.database.refresh_session(token, &session_token, expires_at, source_ip(&connect_info).as_deref()).await {
+        Ok(true) => {
+            info!("User '{}' refreshed their session", user.username);
+            Ok(Json(ApiResponse::success(LoginResponse {
+                session_token,
+                user: UserInfo {
+                    id: user.id.unwrap_or_default(),
+                    username: user.username,
+                    role: user.role,
+                    must_change_password: user.must_change_password,
+                },
+                expires_at,
+            })))
+        }
+        // Revoked after the request was authenticated
+        Ok(false) => Err(ApiError::unauthorized("Session expired or was revoked")),
+        Err(e) => Err(ApiError::internal(format!("Failed to refresh session: {}", e))),
+    }
+}
+
+/// Sessions that haven't expired: every user's for an admin, otherwise the caller's own
+#[utoipa::path(
+    get,
+    path = "/api/sessions",
+    tag = "auth",
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<ActiveSession>>), (status = 401, description = "No session")),
+)]
+pub async fn get_sessions(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    headers: HeaderMap,
+) -> Result<Json<ApiResponse<Vec<ActiveSession>>>, ApiError> {
+    let Some(Extension(user)) = user else {
+        return Err(ApiError::unauthorized("Not logged in"));
+    };
+
+    let owner = if user.role == "admin" { None } else { user.id };
+    match state.database.get_active_sessions(owner, bearer_token(&headers).unwrap_or_default()).await {
+        Ok(sessions) => Ok(Json(ApiResponse::success(sessions))),
+        Err(e) => Err(ApiError::internal(format!("Failed to list sessions: {}", e))),
+    }
+}
+
+/// Revoke a session. Admins may revoke anyone's; other users only their own.
+#[utoipa::path(
+    delete,
+    path = "/api/sessions/{id}",
+    tag = "auth",
+    params(("id" = i64, Path, description = "Session id")),
+    responses((status = 200, description = "Success", body = ApiResponse<String>), (status = 401, description = "No session"), (status = 404, description = "No such session of the caller's")),
+)]
+pub async fn delete_session(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Path(session_id): Path<i64>,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    let Some(Extension(caller)) = &user else {
+        return Err(ApiError::unauthorized("Not logged in"));
+    };
+
+    let owner = if caller.role == "admin" { None } else { caller.id };
+    match state.database.revoke_session_by_id(session_id, owner).await {
+        Ok(true) => {
+            info!("Session {} revoked by '{}'", session_id, caller.username);
+            audit(&state, &user, "session.revoke", "session", &session_id.to_string(), None, None).await;
+            Ok(Json(ApiResponse::success("Session revoked".to_string())))
+        }
+        Ok(false) => Err(ApiError::not_found(format!("Session {} not found", session_id))),
+        Err(e) => Err(ApiError::internal(format!("Failed to revoke session {}: {}", session_id, e))),
+    }
+}
+
+/// Change the caller's password. The current one must be given, and every other session
+/// of the user is signed out.
+#[utoipa::path(
+    post,
+    path = "/api/users/me/password",
+    tag = "auth",
+    request_body = ChangePasswordRequest,
+    responses((status = 200, description = "Success", body = ApiResponse<PasswordChanged>), (status = 400, description = "Wrong current password or a weak new one, with `field_errors`"), (status = 401, description = "No session")),
+)]
+pub async fn change_password(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    headers: HeaderMap,
+    Json(request): Json<ChangePasswordRequest>,
+) -> Result<Json<ApiResponse<PasswordChanged>>, ApiError> {
+    let Some(Extension(caller)) = &user else {
+        return Err(ApiError::unauthorized("Not logged in"));
+    };
+    let Some(user_id) = caller.id else {
+        return Err(ApiError::unauthorized("Not logged in"));
+    };
+
+    let mut errors = Vec::new();
+    match state.database.verify_user(&caller.username, &request.current_password).await {
+        Ok(Some(_)) => {}
+        Ok(None) => errors.push(FieldError { field: "current_password".to_string(), message: "is incorrect".to_string() }),
+        Err(e) => return Err(ApiError::internal(format!("Failed to verify the password of '{}': {}", caller.username, e))),
+    }
+    let min_length = state.config.auth.min_password_length;
+    if request.new_password.chars().count() < min_length {
+        errors.push(FieldError { field: "new_password".to_string(), message: format!("must be at least {} characters", min_length) });
+    } else if request.new_password == request.current_password {
+        errors.push(FieldError { field: "new_password".to_string(), message: "must differ from the current password".to_string() });
+    }
+    if !errors.is_empty() {
+        return Err(ApiError::invalid_fields("Password not changed", errors));
+    }
+
+    match state.database.set_user_password(user_id, &request.new_password, bearer_token(&headers)).await {
+        Ok(revoked_sessions) => {
+            info!("User '{}' changed their password; {} other sessions signed out", caller.username, revoked_sessions);
+            audit(&state, &user, "password.change", "user", &caller.username, None, None).await;
+            Ok(Json(ApiResponse::success(PasswordChanged { revoked_sessions })))
+        }
+        Err(e) => Err(ApiError::internal(format!("Failed to change the password of '{}': {}", caller.username, e))),
+    }
+}
+
+/// The caller, when they have the admin role
+fn admin_caller(user: &Option<Extension<LocalUser>>) -> Result<&LocalUser, ApiError> {
+    match user {
+        Some(Extension(caller)) if caller.role == "admin" => Ok(caller),
+        Some(_) => Err(ApiError::forbidden("Requires the admin role")),
+        None => Err(ApiError::unauthorized("Not logged in")),
+    }
+}
+
+fn check_user_role(role: &str, errors: &mut Vec<FieldError>) {
+    if !USER_ROLES.contains(&role) {
+        errors.push(FieldError { field: "role".to_string(), message: format!("must be one of {}", USER_ROLES.join(", ")) });
+    }
+}
+
+fn last_admin_error(user_id: i64) -> ApiError {
+    ApiError::conflict(format!("User {} is the last enabled admin; make another account admin first", user_id))
+}
+
+/// List local accounts, without their password hashes
+#[utoipa::path(
+    get,
+    path = "/api/users",
+    tag = "auth",
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<UserAccount>>), (status = 401, description = "No session"), (status = 403, description = "Requires the admin role")),
+)]
+pub async fn get_users(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+) -> Result<Json<ApiResponse<Vec<UserAccount>>>, ApiError> {
+    admin_caller(&user)?;
+
+    match state.database.list_users().await {
+        Ok(users) => Ok(Json(ApiResponse::success(users))),
+        Err(e) => Err(ApiError::internal(format!("Failed to list users: {}", e))),
+    }
+}
+
+/// Create a local account, e.g. for an installer
+#[utoipa::path(
+    post,
+    path = "/api/users",
+    tag = "auth",
+    request_body = CreateUserRequest,
+    responses((status = 200, description = "Success", body = ApiResponse<UserAccount>), (status = 400, description = "Taken username, weak password or unknown role, with `field_errors`"), (status = 401, description = "No session"), (status = 403, description = "Requires the admin role")),
+)]
+pub async fn create_user(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Json(request): Json<CreateUserRequest>,
+) -> Result<Json<ApiResponse<UserAccount>>, ApiError> {
+    let caller = admin_caller(&user)?;
+
+    let username = request.username.trim();
+    let mut errors = Vec::new();
+    if username.is_empty() {
+        errors.push(FieldError { field: "username".to_string(), message: "must not be empty".to_string() });
+    }
+    let min_length = state.config.auth.min_password_length;
+    if request.password.chars().count() < min_length {
+        errors.push(FieldError { field: "password".to_string(), message: format!("must be at least {} characters", min_length) });
+    }
+    check_user_role(&request.role, &mut errors);
+    if !errors.is_empty() {
+        return Err(ApiError::invalid_fields("User not created", errors));
+    }
+
+    match state.database.create_user(username, &request.password, &request.role).await {
+        Ok(UserChange::Done(account)) => {
+            info!("User '{}' created with role {} by '{}'", account.username, account.role, caller.username);
+            audit(&state, &user, "user.create", "user", &account.username, None, audit_snapshot(&account)).await;
+            Ok(Json(ApiResponse::success(account)))
+        }
+        Ok(_) => Err(ApiError::invalid_fields(
+            "User not created",
+            vec![FieldError { field: "username".to_string(), message: format!("'{}' is already taken", username) }],
+        )),
+        Err(e) => Err(ApiError::internal(format!("Failed to create user '{}': {}", username, e))),
+    }
+}
+
+/// Change an account's role or disable it. Disabling signs it out everywhere. The last
+/// enabled admin can't be demoted or disabled.
+#[utoipa::path(
+    put,
+    path = "/api/users/{id}",
+    tag = "auth",
+    params(("id" = i64, Path, description = "User id")),
+    request_body = UserAccountChanges,
+    responses((status = 200, description = "Success", body = ApiResponse<UserAccount>), (status = 400, description = "Unknown role, with `field_errors`"), (status = 401, description = "No session"), (status = 403, description = "Requires the admin role"), (status = 404, description = "User not found"), (status = 409, description = "Would leave no enabled admin")),
+)]
+pub async fn update_user(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Path(user_id): Path<i64>,
+    Json(changes): Json<UserAccountChanges>,
+) -> Result<Json<ApiResponse<UserAccount>>, ApiError> {
+    let caller = admin_caller(&user)?;
+
+    let mut errors = Vec::new();
+    if let Some(role) = &changes.role {
+        check_user_role(role, &mut errors);
+    }
+    if !errors.is_empty() {
+        return Err(ApiError::invalid_fields("User not changed", errors));
+    }
+
+    match state.database.update_user(user_id, &changes).await {
+        Ok(UserChange::Done(account)) => {
+            info!("User '{}' changed by '{}': role {}, disabled {}", account.username, caller.username, account.role, account.disabled);
+            audit(&state, &user, "user.update", "user", &account.username, None, audit_snapshot(&account)).await;
+            Ok(Json(ApiResponse::success(account)))
+        }
+        Ok(UserChange::LastAdmin) => Err(last_admin_error(user_id)),
+        Ok(_) => Err(ApiError::not_found(format!("User {} not found", user_id))),
+        Err(e) => Err(ApiError::internal(format!("Failed to change user {}: {}", user_id, e))),
+    }
+}
+
+/// Delete an account and sign it out everywhere. The last enabled admin can't be deleted.
+#[utoipa::path(
+    delete,
+    path = "/api/users/{id}",
+    tag = "auth",
+    params(("id" = i64, Path, description = "User id")),
+    responses((status = 200, description = "Success", body = ApiResponse<String>), (status = 401, description = "No session"), (status = 403, description = "Requires the admin role"), (status = 404, description = "User not found"), (status = 409, description = "Would leave no enabled admin")),
+)]
+pub async fn delete_user(
+    State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
+    Path(user_id): Path<i64>,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    let caller = admin_caller(&user)?;
+
+    match state.database.delete_user(user_id).await {
+        Ok(UserChange::Done(account)) => {
+            info!("User '{}' deleted by '{}'", account.username, caller.username);
+            audit(&state, &user, "user.delete", "user", &account.username, audit_snapshot(&account), None).await;
+            Ok(Json(ApiResponse::success(format!("User '{}' deleted", account.username))))
+        }
+        Ok(UserChange::LastAdmin) => Err(last_admin_error(user_id)),
+        Ok(_) => Err(ApiError::not_found(format!("User {} not found", user_id))),
+        Err(e) => Err(ApiError::internal(format!("Failed to delete user {}: {}", user_id, e))),
     }
 }
 
 /// Get plant configuration
+#[utoipa::path(
+    get,
+    path = "/api/plant-config",
+    tag = "plant",
+    responses((status = 200, description = "Success", body = ApiResponse<PlantConfiguration>)),
+)]
 pub async fn get_plant_config(
     State(state): State<AppState>,
-) -> Result<Json<ApiResponse<PlantConfiguration>>, StatusCode> {
+) -> Result<Json<ApiResponse<PlantConfiguration>>, ApiError> {
     match state.database.get_plant_configuration().await {
         Ok(Some(config)) => Ok(Json(ApiResponse::success(config))),
-        Ok(None) => Ok(Json(ApiResponse::error("No plant configuration found".to_string()))),
-        Err(e) => {
-            error!("Failed to get plant configuration: {}", e);
-            Ok(Json(ApiResponse::error("Failed to get plant configuration".to_string())))
-        }
+        Ok(None) => Err(ApiError::not_found("No plant configuration found")),
+        Err(e) => Err(ApiError::internal(format!("Failed to get plant configuration: {}", e))),
     }
 }
 
 /// Update plant configuration
+#[utoipa::path(
+    post,
+    path = "/api/plant-config",
+    tag = "plant",
+    request_body = PlantConfigRequest,
+    responses((status = 200, description = "Success", body = ApiResponse<String>)),
+)]
 pub async fn update_plant_config(
     State(state): State<AppState>,
+    user: Option<Extension<LocalUser>>,
     Json(request): Json<PlantConfigRequest>,
-) -> Result<Json<ApiResponse<String>>, StatusCode> {
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    let before = state.database.get_plant_configuration().await.ok().flatten();
     match state.database.update_plant_configuration(&request.plant_name, request.thingsboard_entity_group_id.as_deref()).await {
         Ok(_) => {
             info!("Plant configuration updated: {}", request.plant_name);
+            let after = state.database.get_plant_configuration().await.ok().flatten();
+            let change = match (&before, &after) {
+                (Some(before), Some(after)) => audit_diff(before, after, &["plant_name"]).map(|(before, after)| (Some(before), Some(after))),
+                _ => Some((None, audit_snapshot(&after))),
+            };
+            if let Some((before, after)) = change {
+                audit(&state, &user, "plant_config.update", "plant_config", &request.plant_name, before, after).await;
+            }
             Ok(Json(ApiResponse::success("Plant configuration updated successfully".to_string())))
         }
-        Err(e) => {
-            error!("Failed to update plant configuration: {}", e);
-            Ok(Json(ApiResponse::error("Failed to update plant configuration".to_string())))
-        }
+        Err(e) => Err(ApiError::internal(format!("Failed to update plant configuration: {}", e))),
     }
 }
 
 /// Get all plant sync information (for admin view)
+#[utoipa::path(
+    get,
+    path = "/api/plant-sync-info",
+    tag = "plant",
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<PlantConfiguration>>)),
+)]
 pub async fn get_all_plant_sync_info(
     State(state): State<AppState>,
-) -> Result<Json<ApiResponse<Vec<PlantConfiguration>>>, StatusCode> {
+) -> Result<Json<ApiResponse<Vec<PlantConfiguration>>>, ApiError> {
     match state.database.get_all_plant_sync_info().await {
         Ok(plants) => Ok(Json(ApiResponse::success(plants))),
-        Err(e) => {
-            error!("Failed to get plant sync info: {}", e);
-            Ok(Json(ApiResponse::error("Failed to get plant sync info".to_string())))
+        Err(e) => Err(ApiError::internal(format!("Failed to get plant sync info: {}", e))),
+    }
+}
+
+/// Logged values older than this are not used as a device's current power
+const PLANT_SUMMARY_LOG_WINDOW_MINUTES: i64 = 5;
+
+#[derive(Deserialize, IntoParams)]
+pub struct PlantSummaryQuery {
+    /// Power tags by name, ignoring case; `*` matches any characters
+    pub power_tag: Option<String>,
+    /// Power tags by their `agg_to_field` marker; `power` when neither this nor `power_tag` is given
+    pub power_field: Option<String>,
+}
+
+impl PlantSummaryQuery {
+    fn is_power_tag(&self, tag: &DeviceTag) -> bool {
+        let by_name = self.power_tag.as_deref().is_some_and(|pattern| wildcard_match(pattern, &tag.name));
+        let field = match (&self.power_field, &self.power_tag) {
+            (Some(field), _) => Some(field.as_str()),
+            (None, None) => Some("power"),
+            (None, Some(_)) => None,
+        };
+        let by_field = field.is_some_and(|field| tag.agg_to_field.as_deref().is_some_and(|marker| marker.eq_ignore_ascii_case(field)));
+        by_name || by_field
+    }
+}
+
+/// Whether `text` matches `pattern`, ignoring case, where `*` matches any characters
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let (pattern, text) = (pattern.to_lowercase(), text.to_lowercase());
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// A value in W, kW or MW (Wh, kWh or MWh for energy) in kilo units; others are taken as kilo
+fn to_kilo(value: f64, unit: Option<&str>) -> f64 {
+    match unit.map(str::trim) {
+        Some("W") | Some("Wh") => value / 1000.0,
+        Some("MW") | Some("MWh") => value * 1000.0,
+        _ => value,
+    }
+}
+
+/// Devices by state: `stopped` are not being polled, `error` and `offline` are polled but failing
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct DeviceStateCounts {
+    pub total: usize,
+    pub running: usize,
+    pub stopped: usize,
+    pub error: usize,
+    pub offline: usize,
+}
+
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct PlantPower {
+    /// Sum of the current values of the power tags
+    pub total_kw: f64,
+    /// Devices with a current power value above zero
+    pub producing_devices: usize,
+    /// Devices with a current power value
+    pub reporting_devices: usize,
+    /// Devices with power tags but no good value read in the last few minutes
+    pub missing_value_devices: usize,
+    /// Devices without power tags, left out of the sum
+    pub excluded_devices: usize,
+    /// Values taken from the log because the live value cache had none
+    pub values_from_log: usize,
+}
+
+/// One-call overview of the plant for dashboards and kiosk displays
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PlantSummary {
+    pub plant: Option<PlantConfiguration>,
+    pub devices: DeviceStateCounts,
+    pub power: PlantPower,
+    /// Rise of the energy tags (logged in Wh, kWh or MWh) since midnight UTC; absent when
+    /// none were logged today
+    pub energy_today_kwh: Option<f64>,
+    pub active_alarms: u64,
+    pub generated_at: DateTime<Utc>,
+}
+
+/// Plant configuration, device states, current power, today's energy and open alarms in one call.
+/// Power is summed from the live value cache, falling back to values logged in the last few
+/// minutes; devices without power tags are counted and left out.
+#[utoipa::path(
+    get,
+    path = "/api/plant/summary",
+    tag = "plant",
+    params(PlantSummaryQuery),
+    responses((status = 200, description = "Success", body = ApiResponse<PlantSummary>), (status = 500, description = "Internal server error")),
+)]
+pub async fn get_plant_summary(
+    State(state): State<AppState>,
+    Query(query): Query<PlantSummaryQuery>,
+) -> Result<Json<ApiResponse<PlantSummary>>, ApiError> {
+    let now = Utc::now();
+    let plant = match state.database.get_plant_configuration().await {
+        Ok(plant) => plant,
+        Err(e) => return Err(ApiError::internal(format!("Failed to get plant configuration: {}", e))),
+    };
+    let devices = match state.database.get_devices().await {
+        Ok(devices) => devices,
+        Err(e) => return Err(ApiError::internal(format!("Failed to get devices: {}", e))),
+    };
+    let statuses = match state.database.get_all_device_statuses().await {
+        Ok(statuses) => statuses,
+        Err(e) => return Err(ApiError::internal(format!("Failed to get device statuses: {}", e))),
+    };
+
+    let mut counts = DeviceStateCounts { total: devices.len(), ..Default::default() };
+    let mut power = PlantPower::default();
+    for device in &devices {
+        if !state.logging_service.is_device_running(&device.id).await {
+            counts.stopped += 1;
+        } else {
+            match statuses.iter().find(|status| status.device_id == device.id).map(|status| status.status.as_str()) {
+                Some("Error") => counts.error += 1,
+                Some("Offline") | Some("Reconnecting") => counts.offline += 1,
+                _ => counts.running += 1,
+            }
+        }
+
+        let tags = match state.database.get_device_tags(&device.id).await {
+            Ok(tags) => tags,
+            Err(e) => return Err(ApiError::internal(format!("Failed to get tags for device {}: {}", device.id, e))),
+        };
+        let power_tags: Vec<&DeviceTag> = tags.iter().filter(|tag| tag.enabled && query.is_power_tag(tag)).collect();
+        if power_tags.is_empty() {
+            power.excluded_devices += 1;
+            continue;
+        }
+
+        let live = state.logging_service.device_values(&device.id);
+        let mut logged = None;
+        let mut device_kw = None;
+        for tag in power_tags {
+            let cached = live.values.iter().find(|value| value.tag_name == tag.name && value.quality == "Good" && !value.stale);
+            let value = match cached {
+                Some(value) => Some((value.value, value.unit.clone())),
+                None => {
+                    if logged.is_none() {
+                        let since = now - Duration::minutes(PLANT_SUMMARY_LOG_WINDOW_MINUTES);
+                        logged = Some(match state.database.get_latest_log_entries(&device.id, since).await {
+                            Ok(entries) => entries,
+                            Err(e) => return Err(ApiError::internal(format!("Failed to get logged values of device {}: {}", device.id, e))),
+                        });
+                    }
+                    let entry = logged.iter().flatten().find(|entry| entry.tag_name == tag.name && entry.quality == "Good");
+                    if entry.is_some() {
+                        power.values_from_log += 1;
+                    }
+                    entry.map(|entry| (entry.value, entry.unit.clone()))
+                }
+            };
+            if let Some((value, unit)) = value {
+                *device_kw.get_or_insert(0.0) += to_kilo(value, unit.as_deref().or(tag.unit.as_deref()));
+            }
+        }
+        match device_kw {
+            Some(kw) => {
+                power.total_kw += kw;
+                power.reporting_devices += 1;
+                if kw > 0.0 {
+                    power.producing_devices += 1;
+                }
+            }
+            None => power.missing_value_devices += 1,
         }
     }
+
+    let midnight = Utc.from_utc_datetime(&now.date_naive().and_hms_opt(0, 0, 0).unwrap());
+    let energy_today_kwh = match state.database.get_energy_tag_deltas(midnight, now).await {
+        Ok(deltas) if deltas.is_empty() => None,
+        Ok(deltas) => Some(deltas.iter().map(|delta| to_kilo(delta.max_value - delta.min_value, delta.unit.as_deref())).sum()),
+        Err(e) => return Err(ApiError::internal(format!("Failed to get today's energy: {}", e))),
+    };
+    let active_alarms = match state.database.count_active_alarm_events().await {
+        Ok(count) => count,
+        Err(e) => return Err(ApiError::internal(format!("Failed to count active alarms: {}", e))),
+    };
+
+    Ok(Json(ApiResponse::success(PlantSummary {
+        plant,
+        devices: counts,
+        power,
+        energy_today_kwh,
+        active_alarms,
+        generated_at: now,
+    })))
 }
 
 /// Get devices filtered by plant configuration
+#[utoipa::path(
+    get,
+    path = "/api/devices-filtered",
+    tag = "devices",
+    responses((status = 200, description = "Success", body = ApiResponse<Vec<DeviceWithTags>>), (status = 404, description = "No plant configuration"), (status = 409, description = "Plant or its ThingsBoard group not configured yet")),
+)]
 pub async fn get_devices_filtered(
     State(state): State<AppState>,
-) -> Result<Json<ApiResponse<Vec<DeviceWithTags>>>, StatusCode> {
+) -> Result<Json<ApiResponse<Vec<DeviceWithTags>>>, ApiError> {
     // Get plant configuration to determine filtering
     let plant_config = match state.database.get_plant_configuration().await {
         Ok(Some(config)) => config,
         Ok(None) => {
             // No plant config exists - return error for installers
-            return Ok(Json(ApiResponse::error("Plant configuration not found. Please contact your administrator.".to_string())));
-        }
-        Err(e) => {
-            error!("Failed to get plant configuration: {}", e);
-            return Ok(Json(ApiResponse::error("Failed to get plant configuration".to_string())));
+            return Err(ApiError::not_found("Plant configuration not found. Please contact your administrator."));
         }
+        Err(e) => return Err(ApiError::internal(format!("Failed to get plant configuration: {}", e))),
     };
 
     // Check if plant is properly configured (not default)
     if plant_config.plant_name == "Default Plant" || plant_config.thingsboard_entity_group_id.is_none() {
-        return Ok(Json(ApiResponse::error("Plant has not been configured yet. Please contact your administrator to configure the plant settings.".to_string())));
+        return Err(ApiError::conflict("Plant has not been configured yet. Please contact your administrator to configure the plant settings."));
     }
 
     // If ThingsBoard group ID is configured, filter devices by that group
@@ -2030,10 +8180,7 @@ pub async fn get_devices_filtered(
         // Get devices that belong to this specific ThingsBoard group
         let devices = match state.database.get_devices_by_group_id(tb_group_id).await {
             Ok(devices) => devices,
-            Err(e) => {
-                error!("Failed to get devices for plant group {}: {}", tb_group_id, e);
-                return Ok(Json(ApiResponse::error("Failed to get devices for plant group".to_string())));
-            }
+            Err(e) => return Err(ApiError::internal(format!("Failed to get devices for plant group {}: {}", tb_group_id, e))),
         };
 
         let mut devices_with_tags = Vec::new();
@@ -2066,7 +8213,7 @@ pub async fn get_devices_filtered(
         Ok(Json(ApiResponse::success(devices_with_tags)))
     } else {
         // No filtering configured, return error
-        Ok(Json(ApiResponse::error("Plant ThingsBoard group not configured. Please contact your administrator.".to_string())))
+        Err(ApiError::conflict("Plant ThingsBoard group not configured. Please contact your administrator."))
     }
 }
 
@@ -2076,17 +8223,21 @@ pub async fn auth_middleware(
     headers: HeaderMap,
     mut request: Request,
     next: Next,
-) -> Result<Response, StatusCode> {
-    // Skip authentication for login, health check, static files, and HTML pages
+) -> Result<Response, ApiError> {
+    // Skip authentication for login, health check, static files, and HTML pages. Metrics are
+    // scraped without a session unless `[metrics] require_auth` is set
     let path = request.uri().path();
-    if path == "/api/login" 
+    let metrics_need_auth = path == "/metrics" && state.config.metrics.require_auth;
+    if !metrics_need_auth && (path == "/api/login" 
         || path == "/api/health" 
+        || path == "/api/openapi.json"
+        || path.starts_with("/api/docs")
         || path.starts_with("/static/") 
         || path.starts_with("/web/")
         || path == "/" 
         || path == "/favicon.ico"
         || path == "/manifest.json"
-        || !path.starts_with("/api/") // Allow non-API routes (React app routes)
+        || !path.starts_with("/api/")) // Allow non-API routes (React app routes)
     {
         return Ok(next.run(request).await);
     }
@@ -2102,15 +8253,148 @@ pub async fn auth_middleware(
                         return Ok(next.run(request).await);
                     }
                     Ok(None) => {
-                        return Err(StatusCode::UNAUTHORIZED);
+                        return Err(ApiError::unauthorized("Invalid or expired session"));
                     }
-                    Err(_) => {
-                        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                    Err(e) => {
+                        return Err(ApiError::internal(format!("Database error during session verification: {}", e)));
                     }
                 }
             }
         }
     }
     
-    Err(StatusCode::UNAUTHORIZED)
+    Err(ApiError::unauthorized("No authorization header provided"))
+}
+
+/// Largest plain-text error body turned into an envelope by `error_envelope_middleware`
+const REJECTION_MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// Wrap the plain-text errors axum produces itself (extractor rejections, body limits,
+/// 405) in the `ApiResponse` envelope, so every `/api` failure has the same shape.
+/// A body that doesn't deserialize (422) is reported as a 400 `validation_failed`.
+pub async fn error_envelope_middleware(request: Request, next: Next) -> Response {
+    use axum::body::to_bytes;
+    use axum::http::header;
+    use axum::response::IntoResponse;
+
+    let is_api = request.uri().path().starts_with("/api/");
+    let response = next.run(request).await;
+    let status = response.status();
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if !is_api || is_json || !(status.is_client_error() || status.is_server_error()) {
+        return response;
+    }
+
+    let body = to_bytes(response.into_body(), REJECTION_MAX_BODY_BYTES).await.unwrap_or_default();
+    let message = match String::from_utf8_lossy(&body).trim() {
+        "" => status.canonical_reason().unwrap_or("Request failed").to_string(),
+        text => text.to_string(),
+    };
+    let error = match status {
+        StatusCode::UNPROCESSABLE_ENTITY => ApiError { code: "validation_failed", ..ApiError::bad_request(message) },
+        _ => ApiError::new(status, message),
+    };
+    error.into_response()
+}
+
+/// Largest request body buffered for idempotency checks (CSV uploads included)
+const IDEMPOTENCY_MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+/// Replay protection for bulk mutation endpoints.
+///
+/// Requests carrying an `Idempotency-Key` header are fingerprinted (method, path and body)
+/// and recorded under the caller's user id. A retry by the same user with the same key and
+/// body gets the stored response without re-running the handler; the same key with a
+/// different body is rejected with 409. Only successful
+/// responses are stored, so a failed request can be retried with the same key.
+pub async fn idempotency_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    use axum::body::{to_bytes, Body};
+    use axum::response::IntoResponse;
+    use sha2::{Digest, Sha256};
+
+    if request.method() == axum::http::Method::GET || request.method() == axum::http::Method::HEAD {
+        return Ok(next.run(request).await);
+    }
+
+    let idempotency_key = match request.headers().get("Idempotency-Key").and_then(|value| value.to_str().ok()) {
+        Some(key) if !key.trim().is_empty() => key.trim().to_string(),
+        _ => return Ok(next.run(request).await),
+    };
+
+    // Runs behind the auth middleware, so every request here has a session
+    let user_id = request.extensions().get::<LocalUser>().and_then(|user| user.id).unwrap_or_default();
+    let (parts, body) = request.into_parts();
+    let body = to_bytes(body, IDEMPOTENCY_MAX_BODY_BYTES)
+        .await
+        .map_err(|_| ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, "Request body too large for idempotency checks"))?;
+
+    let endpoint = format!("{} {}", parts.method, parts.uri.path());
+    let mut hasher = Sha256::new();
+    hasher.update(endpoint.as_bytes());
+    hasher.update(&body);
+    let request_hash: String = hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect();
+
+    let ttl = Duration::hours(state.config.idempotency.ttl_hours as i64);
+    let outcome = state.database
+        .begin_idempotent_request(user_id, &idempotency_key, &endpoint, &request_hash, ttl)
+        .await
+        .map_err(|e| ApiError::internal(format!("Failed to check idempotency key for {}: {}", endpoint, e)))?;
+
+    match outcome {
+        IdempotencyOutcome::Proceed => {}
+        IdempotencyOutcome::Replay { status_code, response_body } => {
+            info!("Replaying stored response for idempotency key {} on {}", idempotency_key, endpoint);
+            let status = StatusCode::from_u16(status_code).unwrap_or(StatusCode::OK);
+            return Ok((
+                status,
+                [("content-type", "application/json"), ("idempotent-replayed", "true")],
+                response_body,
+            ).into_response());
+        }
+        IdempotencyOutcome::Mismatch => {
+            warn!("Idempotency key {} reused with a different body on {}", idempotency_key, endpoint);
+            return Err(ApiError::conflict("Idempotency-Key was already used with a different request body"));
+        }
+        IdempotencyOutcome::InProgress => {
+            return Err(ApiError::conflict("A request with this Idempotency-Key is still being processed"));
+        }
+    }
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    let (response_parts, response_body) = response.into_parts();
+    let response_body = match to_bytes(response_body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            let _ = state.database.release_idempotent_request(user_id, &idempotency_key, &endpoint).await;
+            return Err(ApiError::internal(format!("Failed to buffer response for {}: {}", endpoint, e)));
+        }
+    };
+
+    // Failures come back with an error status and must stay retryable
+    let stored = if response_parts.status.is_success() {
+        state.database
+            .complete_idempotent_request(
+                user_id,
+                &idempotency_key,
+                &endpoint,
+                response_parts.status.as_u16(),
+                &String::from_utf8_lossy(&response_body),
+            )
+            .await
+    } else {
+        state.database.release_idempotent_request(user_id, &idempotency_key, &endpoint).await
+    };
+    if let Err(e) = stored {
+        error!("Failed to record idempotency key {} for {}: {}", idempotency_key, endpoint, e);
+    }
+
+    Ok(Response::from_parts(response_parts, Body::from(response_body)))
 }
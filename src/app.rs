@@ -0,0 +1,468 @@
+//! The logger's services and HTTP routes, started by `main` once the configuration and
+//! database are loaded, and in-process by the end-to-end tests.
+
+use axum::{
+    response::{Html, IntoResponse},
+    routing::{delete, get, patch, post, put},
+    Router,
+    extract::DefaultBodyLimit,
+    middleware,
+};
+use socketioxide::SocketIo;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tower_http::services::ServeDir;
+use tracing::{info, warn};
+
+use crate::api;
+use crate::config::AppConfig;
+use crate::database::Database;
+use crate::file_export::FileExporter;
+use crate::http_server;
+use crate::iec104::Iec104Server;
+use crate::logging::LoggingService;
+use crate::metrics::Metrics;
+use crate::mqtt::MqttPublisher;
+use crate::notifications::NotificationService;
+use crate::openapi;
+use crate::reports::ReportService;
+use crate::scheduler::OperationScheduler;
+use crate::tb_rust_client::{GroupDeviceCache, RateLimiter, TbSession};
+use crate::telemetry_forwarder::TelemetryForwarder;
+use crate::webhooks::WebhookNotifier;
+use crate::websocket;
+
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Arc<AppConfig>,
+    pub database: Arc<Database>,
+    pub logging_service: Arc<LoggingService>,
+    pub scheduler: OperationScheduler,
+    pub report_service: Arc<ReportService>,
+    pub notifications: Arc<NotificationService>,
+    pub tb_group_cache: Arc<GroupDeviceCache>,
+    pub tb_session: Arc<TbSession>,
+    pub tb_rate_limiter: Arc<RateLimiter>,
+    pub iec104_server: Arc<Iec104Server>,
+    pub metrics: Arc<Metrics>,
+    pub webhooks: Arc<WebhookNotifier>,
+    pub mqtt: Option<Arc<MqttPublisher>>,
+    pub file_export: Option<Arc<FileExporter>>,
+    /// Directory holding config.toml and the generated catalogs; the working directory when run as a service
+    pub data_dir: PathBuf,
+}
+
+impl AppState {
+    /// The config file the API reads and updates
+    pub fn config_path(&self) -> PathBuf {
+        self.data_dir.join("config.toml")
+    }
+}
+
+/// One run of the logger: the state its handlers share and the routes serving them
+pub struct App {
+    pub state: AppState,
+    pub router: Router,
+}
+
+impl App {
+    /// Start the background services for `config` on the opened `database` and build the
+    /// router. Polling starts for every enabled device.
+    pub async fn start(config: Arc<AppConfig>, database: Arc<Database>, data_dir: PathBuf) -> anyhow::Result<App> {
+        // A job can't resume after a restart; keep what it did and mark it failed
+        match database.fail_interrupted_jobs().await {
+            Ok(0) => {}
+            Ok(count) => warn!("Marked {} unfinished jobs from the previous run as failed", count),
+            Err(e) => warn!("Failed to mark unfinished jobs as failed: {}", e),
+        }
+
+        // Create Socket.IO layer
+        let (socket_layer, socket_io) = SocketIo::new_layer();
+
+        // Set up Socket.IO event handlers before anything can emit on the default namespace
+        let socket_context = websocket::SocketContext::new(database.clone());
+        socket_io.ns("/", move |socket, auth| websocket::on_connect(socket, auth, socket_context.clone()));
+
+        // Initialize notification center
+        let tag_updates = websocket::TagUpdateBatcher::new(
+            socket_io.clone(),
+            std::time::Duration::from_millis(config.server.tag_update_interval_ms),
+        );
+        // Webhook deliveries run on their own tasks so a slow endpoint can't hold up producers
+        let webhooks = Arc::new(WebhookNotifier::new(database.clone(), config.webhooks.clone()));
+        webhooks.start();
+        let mut notifications = NotificationService::new(database.clone(), socket_io.clone(), tag_updates, webhooks.clone());
+
+        // Updated by the pollers and the forwarder, formatted on each /metrics scrape
+        let metrics = Arc::new(Metrics::new());
+
+        // Publishes to a local SCADA's broker when `[mqtt]` is configured
+        let mqtt = match config.mqtt.clone().map(|mqtt_config| mqtt_config.validate().map(|()| mqtt_config)) {
+            Some(Ok(mqtt_config)) => {
+                let publisher = Arc::new(MqttPublisher::new(mqtt_config).with_metrics(metrics.clone()));
+                publisher.start();
+                notifications = notifications.with_mqtt(publisher.clone());
+                Some(publisher)
+            }
+            Some(Err(e)) => {
+                warn!("MQTT publishing disabled: {}", e);
+                None
+            }
+            None => None,
+        };
+        let notifications = Arc::new(notifications);
+
+        // Keeps logged values as CSV files on disk when `[file_export]` is configured
+        let file_export = match config.file_export.clone().map(|export_config| export_config.validate().map(|()| export_config)) {
+            Some(Ok(export_config)) => {
+                let exporter = Arc::new(FileExporter::new(export_config));
+                exporter.start();
+                Some(exporter)
+            }
+            Some(Err(e)) => {
+                warn!("File export disabled: {}", e);
+                None
+            }
+            None => None,
+        };
+
+        // One ThingsBoard login shared by every handler and the telemetry forwarder, refreshed when it expires
+        let tb_session = Arc::new(TbSession::new());
+        // Likewise one rate limit for every request they send
+        let tb_rate_limiter = Arc::new(RateLimiter::new(
+            config.thingsboard.as_ref().map_or(0.0, |tb_config| tb_config.requests_per_second),
+        ));
+
+        // Queued telemetry from before a restart is pushed before new values arrive
+        let telemetry_forwarder = Arc::new(
+            TelemetryForwarder::new(database.clone(), config.clone(), tb_session.clone())
+                .with_metrics(metrics.clone())
+                .with_rate_limiter(tb_rate_limiter.clone())
+        );
+        telemetry_forwarder.resume().await?;
+
+        // A port that can't be bound leaves the server down without stopping the logger
+        let iec104_server = Arc::new(Iec104Server::new());
+        if let Err(e) = iec104_server.apply(config.iec104_server.clone()).await {
+            warn!("IEC 104 server not started: {}", e);
+        }
+
+        // Admin operations and log retention take turns on the database through the scheduler
+        let scheduler = OperationScheduler::new();
+
+        // Initialize logging service
+        let logging_service = Arc::new(LoggingService::new(
+            database.clone(),
+            config.clone(),
+            notifications.clone(),
+            telemetry_forwarder,
+            iec104_server.clone(),
+            metrics.clone(),
+            file_export.clone(),
+            scheduler.clone(),
+        ).await?);
+        info!("Logging service initialized");
+        logging_service.start_enabled_devices();
+
+        // Initialize nightly report generation
+        let report_service = Arc::new(ReportService::new(
+            database.clone(),
+            config.clone(),
+            logging_service.clone(),
+            notifications.clone(),
+        ));
+        report_service.start_nightly_task();
+
+        // Shared across ThingsBoard clients so sync and catalog export reuse group listings
+        let tb_group_cache = Arc::new(GroupDeviceCache::new(
+            std::time::Duration::from_secs(config.tb_cache.group_devices_ttl_seconds),
+            config.tb_cache.max_devices_per_group,
+        ));
+
+        // Create app state
+        let app_state = AppState {
+            config: config.clone(),
+            database,
+            logging_service: logging_service.clone(),
+            scheduler,
+            report_service,
+            notifications,
+            tb_group_cache,
+            tb_session,
+            tb_rate_limiter,
+            iec104_server,
+            metrics,
+            webhooks,
+            mqtt,
+            file_export,
+            data_dir,
+        };
+
+        // Bulk mutation endpoints accept an Idempotency-Key so frontend retries don't double-apply
+        let idempotency = middleware::from_fn_with_state(app_state.clone(), api::idempotency_middleware);
+        // Uploads are checked against max_upload_mb field by field; the slack leaves room for form headers
+        let upload_limit = DefaultBodyLimit::max(usize::try_from(config.server.max_upload_mb.saturating_mul(1024 * 1024)).unwrap_or(usize::MAX).saturating_add(64 * 1024));
+
+        // Create router
+        let app = Router::new()
+            // Authentication routes (no auth required)
+            .route("/api/login", post(api::login))
+            .route("/api/logout", post(api::logout))
+            .route("/api/verify-session", get(api::verify_session))
+            .route("/api/session/refresh", post(api::refresh_session))
+            .route("/api/sessions", get(api::get_sessions))
+            .route("/api/sessions/:id", delete(api::delete_session))
+            .route("/api/users/me/password", post(api::change_password))
+            .route("/api/users", get(api::get_users).post(api::create_user))
+            .route("/api/users/:id", put(api::update_user).delete(api::delete_user))
+        
+            // Plant configuration routes
+            .route("/api/plant-config", get(api::get_plant_config).post(api::update_plant_config))
+            .route("/api/plant-sync-info", get(api::get_all_plant_sync_info))
+            .route("/api/plant/summary", get(api::get_plant_summary))
+        
+            // API routes
+            .route("/api/config", get(api::get_config).post(api::update_config))
+            .route("/api/devices", get(api::get_devices).post(api::create_device))
+            .route("/api/devices/:id", get(api::get_device).put(api::update_device).delete(api::delete_device))
+            .route("/api/devices-enhanced/test-connection", post(api::test_device_connection))
+            .route("/api/devices-enhanced/discover-sunspec", post(api::discover_sunspec_device))
+            .route("/api/devices-enhanced/from-model", post(api::create_device_from_model).route_layer(idempotency.clone()))
+            .route("/api/devices-enhanced/:id/duplicate", post(api::duplicate_device).route_layer(idempotency.clone()))
+            .route("/api/devices-enhanced/bulk", post(api::bulk_device_action).route_layer(idempotency.clone()))
+            .route("/api/devices-enhanced/start-all", post(api::start_all_devices))
+            .route("/api/devices-enhanced/stop-all", post(api::stop_all_devices))
+            .route("/api/devices-enhanced/:id/start", post(api::start_device))
+            .route("/api/devices-enhanced/:id/stop", post(api::stop_device))
+            .route("/api/devices-debug", get(api::debug_devices))
+            .route("/api/values", get(api::get_all_values))
+            .route("/api/logs", get(api::get_logs))
+            .route("/api/logs/export", get(api::export_logs))
+            .route("/api/backup", get(api::export_backup))
+            .route("/api/restore", post(api::restore_backup))
+            .route("/api/schema-migrations", get(api::get_schema_migrations))
+            .route("/api/audit", get(api::get_audit_log))
+            .route("/api/logs/:device_id", get(api::get_device_logs))
+            .route("/api/logs/:device_id/aggregate", get(api::get_aggregated_logs))
+            .route("/api/status", get(api::get_status))
+        
+            // Enhanced device management with models and tags
+            .route("/api/device-models", get(api::get_device_models).post(api::create_device_model).route_layer(upload_limit))
+            .route("/api/device-models/:id", get(api::get_device_model))
+            .route("/api/device-models/:id/delete", post(api::delete_device_model))
+            .route("/api/device-models/:id/tags", get(api::get_tag_templates))
+            .route("/api/device-models/:id/tags/:tag_id", put(api::update_tag_template).delete(api::delete_tag_template).route_layer(idempotency.clone()))
+            .route("/api/device-models/:id/resync-devices", post(api::resync_model_devices).route_layer(idempotency.clone()))
+            .route("/api/devices-enhanced", get(api::get_devices_enhanced).post(api::create_device_with_tags).route_layer(idempotency.clone()))
+            .route("/api/devices-filtered", get(api::get_devices_filtered))
+            .route("/api/devices-enhanced/:id", get(api::get_device_enhanced).put(api::update_device_with_tags).delete(api::delete_device).route_layer(idempotency.clone()))
+            .route("/api/devices/:id/tags", get(api::get_device_tags_api).post(api::add_device_tag))
+            .route("/api/devices/:id/tags/:tag_id", patch(api::patch_device_tag).delete(api::delete_device_tag))
+            .route("/api/devices/:id/tags/:tag_id/mute", post(api::mute_device_tag).delete(api::unmute_device_tag))
+            .route("/api/devices-enhanced/:id/values", get(api::get_device_values))
+            .route("/api/devices-enhanced/:id/tb-token", get(api::get_device_tb_token))
+            .route("/api/devices-enhanced/:id/stats", get(api::get_device_poll_stats))
+            .route("/api/devices-enhanced/:id/trace", get(api::get_device_trace).post(api::set_device_trace))
+            .route("/api/devices-enhanced/:id/mutes", get(api::get_device_tag_mutes))
+            .route("/api/devices-enhanced/:id/telemetry-forwarding", get(api::get_telemetry_forwarding).put(api::set_telemetry_forwarding))
+            .route("/api/devices-enhanced/:id/tb-children", get(api::get_tb_child_devices))
+            .route("/api/devices-enhanced/:id/tags/from-register-map", post(api::create_tags_from_register_map))
+            .route("/api/devices-enhanced/:id/read", post(api::read_device_tag))
+            .route("/api/devices-enhanced/:id/read-serial", post(api::read_device_serial_no))
+            .route("/api/devices-enhanced/:id/write", post(api::write_device_tag))
+            .route("/api/devices-enhanced/:id/writes", get(api::get_device_tag_writes))
+            .route("/api/devices/:id/type-mismatches", delete(api::reset_device_type_mismatches))
+            .route("/api/devices/:id/iec104-diagnostics", get(api::get_device_iec104_diagnostics))
+            .route("/api/tags/search", get(api::search_tags))
+            .route("/api/tags/searches", post(api::save_tag_search))
+            .route("/api/tags/bulk-edit", post(api::bulk_edit_tags).route_layer(idempotency.clone()))
+        
+            // Device filtering by sync status
+            .route("/api/devices-unsynced", get(api::get_unsynced_devices))
+            .route("/api/devices-by-group/:group_id", get(api::get_devices_by_group))
+        
+            // Schedule group management
+            .route("/api/schedule-groups", get(api::get_schedule_groups).post(api::create_schedule_group))
+            .route("/api/schedule-groups/:id", get(api::get_schedule_group).put(api::update_schedule_group).delete(api::delete_schedule_group))
+        
+            // Modbus TCP tag register management
+            .route("/api/modbus-tcp-tag-registers", get(api::get_modbus_tcp_tag_registers).delete(api::delete_modbus_tcp_tag_registers))
+            .route("/api/modbus-tcp-tag-registers/export-csv", get(api::export_modbus_tcp_tag_registers_csv))
+            .route("/api/modbus-tcp-tag-registers/upload-csv", post(api::upload_modbus_tcp_csv_tags).route_layer(idempotency.clone()).route_layer(upload_limit))
+        
+            // ThingsBoard API endpoints
+            .route("/api/thingsboard/entity-groups", get(api::get_thingsboard_entity_groups))
+            .route("/api/thingsboard/hierarchy/:entity_group_id", get(api::get_thingsboard_hierarchy))
+            .route("/api/sync-devices-to-thingsboard", post(api::sync_devices_to_thingsboard).route_layer(idempotency.clone()))
+            .route("/api/generate-device-catalog", post(api::generate_device_catalog))
+            .route("/api/device-catalog/:entity_group_id/download", get(api::download_device_catalog))
+        
+            // IEC 104 server towards SCADA masters
+            .route("/api/iec104-server", get(api::get_iec104_server).put(api::set_iec104_server))
+        
+            // Notification center
+            .route("/api/notifications", get(api::get_notifications))
+            .route("/api/notifications/read-all", post(api::mark_all_notifications_read))
+            .route("/api/notifications/:id/read", post(api::mark_notification_read))
+        
+            // Alarms on tag values
+            .route("/api/alarm-rules", get(api::get_alarm_rules).post(api::create_alarm_rule))
+            .route("/api/alarm-rules/:id", put(api::update_alarm_rule).delete(api::delete_alarm_rule))
+            .route("/api/alarms", get(api::get_alarms))
+            .route("/api/webhooks", get(api::get_webhooks).post(api::create_webhook))
+            .route("/api/webhooks/:id", put(api::update_webhook).delete(api::delete_webhook))
+            .route("/api/webhooks/:id/deliveries", get(api::get_webhook_deliveries))
+        
+            // Values computed from other tags
+            .route("/api/virtual-tags", get(api::get_virtual_tags).post(api::create_virtual_tag))
+            .route("/api/virtual-tags/:id", put(api::update_virtual_tag).delete(api::delete_virtual_tag))
+        
+            // Daily reports
+            .route("/api/reports/daily", get(api::get_daily_report))
+            .route("/api/reports/daily/generate", post(api::generate_daily_report))
+        
+            // Admin operation scheduling
+            .route("/api/jobs", get(api::get_jobs))
+            .route("/api/jobs/queue", get(api::get_jobs_queue))
+            .route("/api/jobs/:id", get(api::get_job))
+        
+            // Machine-readable API description
+            .route("/api/openapi.json", get(openapi::get_openapi_json))
+        
+            // File management endpoints
+            .route("/api/files/:kind", get(api::list_files))
+            .route("/api/files/:kind/:filename", get(api::download_file).delete(api::delete_file))
+        
+            // WebSocket endpoint
+            .route("/socket.io/*path", get(websocket::socket_handler))
+        
+            // Static file serving - serve React build from web/build
+            .nest_service("/static", ServeDir::new("web/build/static"))
+            .route("/", get(serve_index))
+            .fallback(fallback)
+
+            // docker health check endpoint
+            .route("/api/health", get(crate::api::health_check))
+
+            // Prometheus scrape endpoint
+            .route("/metrics", get(api::get_metrics));
+
+        // Interactive API docs are only served by debug builds with the swagger-ui feature
+        #[cfg(all(feature = "swagger-ui", debug_assertions))]
+        let app = {
+            use utoipa::OpenApi;
+            app.merge(utoipa_swagger_ui::SwaggerUi::new("/api/docs").url("/api/docs/openapi.json", openapi::ApiDoc::openapi()))
+        };
+
+        let app = app
+            // Add middleware
+            .layer(middleware::from_fn_with_state(app_state.clone(), api::auth_middleware))
+            .layer(middleware::from_fn(api::error_envelope_middleware));
+        // Same-origin only unless `[server.cors]` lists other origins
+        let app = match http_server::cors_layer(&config.server.cors) {
+            Some(cors) => app.layer(cors),
+            None => app,
+        };
+        let router = app.layer(socket_layer).with_state(app_state.clone());
+
+        Ok(App { state: app_state, router })
+    }
+}
+
+async fn serve_index() -> impl IntoResponse {
+    let index_path = "web/build/index.html";
+    
+    match tokio::fs::read_to_string(index_path).await {
+        Ok(content) => Html(content),
+        Err(_) => {
+            // Fallback HTML if React build is not available
+            let fallback_html = "<!DOCTYPE html>
+<html lang=\"en\">
+  <head>
+    <meta charset=\"utf-8\" />
+    <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\" />
+    <meta name=\"theme-color\" content=\"#000000\" />
+    <meta name=\"description\" content=\"AVA Device Logger - Industrial data logging and monitoring\" />
+    <title>AVA Device Logger</title>
+    <style>
+      body {
+        margin: 0;
+        font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', 'Roboto', 'Oxygen', 'Ubuntu', 'Cantarell', 'Fira Sans', 'Droid Sans', 'Helvetica Neue', sans-serif;
+        -webkit-font-smoothing: antialiased;
+        -moz-osx-font-smoothing: grayscale;
+      }
+      .loading {
+        display: flex;
+        justify-content: center;
+        align-items: center;
+        height: 100vh;
+        font-size: 18px;
+        color: #1890ff;
+      }
+    </style>
+  </head>
+  <body>
+    <noscript>You need to enable JavaScript to run this app.</noscript>
+    <div id=\"root\">
+      <div class=\"loading\">
+        <div>
+          <h2>AVA Device Logger</h2>
+          <p>Backend server is running successfully!</p>
+          <p><strong>API Base URL:</strong> http://localhost:8080/api</p>
+          <br>
+          <p><em>For the full web interface, build the React frontend with:</em></p>
+          <code>cd web && npm install && npm start</code>
+        </div>
+      </div>
+    </div>
+  </body>
+</html>";
+            Html(fallback_html.to_string())
+        }
+    }
+}
+
+/// Requests no route matches: a 404 envelope under `/api`, otherwise the web app
+async fn fallback(method: axum::http::Method, uri: axum::http::Uri) -> axum::response::Response {
+    if uri.path().starts_with("/api/") {
+        return api::ApiError::not_found(format!("No route for {} {}", method, uri.path())).into_response();
+    }
+    if method != axum::http::Method::GET {
+        return axum::http::StatusCode::METHOD_NOT_ALLOWED.into_response();
+    }
+    serve_static(uri).await.into_response()
+}
+
+async fn serve_static(uri: axum::http::Uri) -> impl IntoResponse {
+    let path = uri.path();
+    
+    // Serve static files from web/build directory
+    let file_path = format!("web/build{}", path);
+    
+    // Check if file exists
+    if std::path::Path::new(&file_path).exists() {
+        if let Ok(contents) = tokio::fs::read(&file_path).await {
+            let content_type = match std::path::Path::new(&file_path)
+                .extension()
+                .and_then(|ext| ext.to_str())
+            {
+                Some("html") => "text/html",
+                Some("css") => "text/css",
+                Some("js") => "application/javascript",
+                Some("json") => "application/json",
+                Some("png") => "image/png",
+                Some("jpg") | Some("jpeg") => "image/jpeg",
+                Some("ico") => "image/x-icon",
+                _ => "application/octet-stream",
+            };
+            
+            return (
+                [(axum::http::header::CONTENT_TYPE, content_type)],
+                contents,
+            ).into_response();
+        }
+    }
+    
+    // Fallback to index for client-side routing
+    serve_index().await.into_response()
+}
@@ -0,0 +1,316 @@
+use serde::Serialize;
+
+use crate::config::ProtocolConfig;
+use crate::database::{DeviceInstance, DeviceTag};
+use crate::tag_description::{self, DeviceType};
+use crate::tb_rust_client::DeviceData;
+
+/// Version of the detailed device catalog's columns, bump when they change.
+///
+/// Version 2 split the old `Modbus Type` and `Register Type` columns, which held the tag's
+/// data type and unit, into `Data Type`, `Unit` and `Register Type`.
+pub const CATALOG_FORMAT_VERSION: u32 = 2;
+
+/// One row of the detailed device catalog CSV.
+///
+/// Rows are written with `csv::Writer::serialize`, so the header is taken from the
+/// `rename`s below and always lines up with the values. Empty numeric columns are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CatalogRow {
+    #[serde(rename = "IOA")]
+    pub ioa: usize,
+    #[serde(rename = "Index")]
+    pub index: usize,
+    #[serde(rename = "Serial Number")]
+    pub serial_number: String,
+    #[serde(rename = "Device Name")]
+    pub device_name: String,
+    #[serde(rename = "Device Brand")]
+    pub device_brand: String,
+    #[serde(rename = "Device Model")]
+    pub device_model: String,
+    #[serde(rename = "Customer")]
+    pub customer: String,
+    #[serde(rename = "AVA Type")]
+    pub ava_type: String,
+    #[serde(rename = "Token")]
+    pub token: String,
+    #[serde(rename = "Parent")]
+    pub parent: String,
+    #[serde(rename = "Plant")]
+    pub plant: String,
+    #[serde(rename = "INV")]
+    pub inv: Option<u32>,
+    #[serde(rename = "MPPT")]
+    pub mppt: Option<u32>,
+    #[serde(rename = "INPUT")]
+    pub input: Option<u32>,
+    #[serde(rename = "Label")]
+    pub label: String,
+    #[serde(rename = "Device ID")]
+    pub device_id: String,
+    #[serde(rename = "Host")]
+    pub host: String,
+    #[serde(rename = "Port")]
+    pub port: Option<u16>,
+    #[serde(rename = "Forwarding Modbus ID")]
+    pub forwarding_modbus_id: Option<u8>,
+    /// `modbus_tcp`, `modbus_rtu` or `iec104`
+    #[serde(rename = "Protocol")]
+    pub protocol: String,
+    #[serde(rename = "Data Label")]
+    pub data_label: String,
+    #[serde(rename = "Address")]
+    pub address: Option<u16>,
+    #[serde(rename = "Size")]
+    pub size: Option<i32>,
+    /// e.g. `float32`
+    #[serde(rename = "Data Type")]
+    pub data_type: String,
+    /// 1 / the tag's scaling multiplier
+    #[serde(rename = "Divider")]
+    pub divider: String,
+    #[serde(rename = "Unit")]
+    pub unit: String,
+    /// Modbus table the tag is read from, blank when the tag doesn't set one
+    #[serde(rename = "Register Type")]
+    pub register_type: String,
+    #[serde(rename = "Frequency")]
+    pub frequency: String,
+    #[serde(rename = "Agg To Field")]
+    pub agg_to_field: String,
+}
+
+/// A ThingsBoard device as the catalog lists it, with its token and position already resolved
+pub struct CatalogDevice<'a> {
+    pub device: &'a DeviceData,
+    /// Access token, or `ERROR: ...` when it couldn't be fetched
+    pub token: &'a str,
+    /// `None` when the parent couldn't be determined, written as `UNKNOWN_PARENT`
+    pub parent: Option<String>,
+    /// INV, PM or MT number depending on the device type
+    pub device_index: u32,
+    pub mppt_index: u32,
+    pub input_index: u32,
+}
+
+/// The local device whose tags and connection a catalog row describes; for MPPT and
+/// String devices that's the parent inverter
+pub struct CatalogSource<'a> {
+    pub instance: &'a DeviceInstance,
+    pub brand: String,
+    pub model: String,
+}
+
+/// Outcome of looking up a tag's schedule group
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduleLookup {
+    /// The tag isn't in a schedule group
+    NoGroup,
+    /// Polling interval of the tag's group in milliseconds
+    Found(u32),
+    /// The tag names a group that doesn't exist
+    Missing,
+    /// The group couldn't be read
+    Failed,
+}
+
+impl ScheduleLookup {
+    /// The Frequency column
+    pub fn frequency(&self) -> String {
+        match self {
+            ScheduleLookup::NoGroup => "None".to_string(),
+            ScheduleLookup::Found(interval_ms) => interval_ms.to_string(),
+            ScheduleLookup::Missing => "Unknown".to_string(),
+            ScheduleLookup::Failed => "Error".to_string(),
+        }
+    }
+}
+
+/// Builds catalog rows for one entity group from data already fetched from ThingsBoard and
+/// the database; IOA and Index are left at 0 for the writer to number.
+pub struct CatalogRowBuilder {
+    entity_group_name: String,
+    customer: String,
+}
+
+impl CatalogRowBuilder {
+    pub fn new(entity_group_name: &str) -> Self {
+        Self {
+            entity_group_name: entity_group_name.to_string(),
+            customer: customer_name(entity_group_name),
+        }
+    }
+
+    /// Row of a device without tags
+    pub fn device_row(&self, device: &CatalogDevice<'_>, source: &CatalogSource<'_>) -> CatalogRow {
+        self.source_row(device, source)
+    }
+
+    /// Row of one tag of a device
+    pub fn tag_row(&self, device: &CatalogDevice<'_>, source: &CatalogSource<'_>, tag: &DeviceTag, schedule: ScheduleLookup) -> CatalogRow {
+        CatalogRow {
+            data_label: tag.name.clone(),
+            address: Some(tag.address),
+            size: Some(tag.size),
+            data_type: tag.data_type.clone(),
+            divider: divider(tag.scaling_multiplier),
+            unit: tag.unit.clone().unwrap_or_default(),
+            register_type: tag.register_type.map(|register_type| register_type.as_str().to_string()).unwrap_or_default(),
+            frequency: schedule.frequency(),
+            agg_to_field: tag.agg_to_field.clone().unwrap_or_default(),
+            ..self.source_row(device, source)
+        }
+    }
+
+    /// Row of a device whose tags couldn't be read
+    pub fn error_row(&self, device: &CatalogDevice<'_>, source: &CatalogSource<'_>) -> CatalogRow {
+        CatalogRow {
+            data_label: "ERROR: Failed to get tags".to_string(),
+            ..self.source_row(device, source)
+        }
+    }
+
+    /// Row of an MPPT or String device with nothing to list, `message` saying why
+    pub fn placeholder_row(&self, device: &CatalogDevice<'_>, message: &str) -> CatalogRow {
+        let (mppt, input) = match device.device.device_type.as_str() {
+            "Mppt" => (Some(device.mppt_index), None),
+            "String" => (Some(device.mppt_index), Some(device.input_index)),
+            _ => (None, None),
+        };
+
+        CatalogRow {
+            device_brand: "Unknown".to_string(),
+            device_model: "Unknown".to_string(),
+            inv: Some(device.device_index),
+            mppt,
+            input,
+            data_label: message.to_string(),
+            ..self.base_row(device)
+        }
+    }
+
+    /// Columns shared by every row of a device
+    fn base_row(&self, device: &CatalogDevice<'_>) -> CatalogRow {
+        CatalogRow {
+            device_name: device.device.name.clone(),
+            customer: self.customer.clone(),
+            ava_type: device.device.device_type.clone(),
+            token: device.token.to_string(),
+            parent: device.parent.clone().unwrap_or_else(|| UNKNOWN_PARENT.to_string()),
+            plant: self.entity_group_name.clone(),
+            label: device.device.label.clone(),
+            device_id: device.device.id.id.clone(),
+            ..CatalogRow::default()
+        }
+    }
+
+    /// Device columns filled in from the local device
+    fn source_row(&self, device: &CatalogDevice<'_>, source: &CatalogSource<'_>) -> CatalogRow {
+        let (inv, mppt, input) = match device.device.device_type.as_str() {
+            "Inverter" => (Some(device.device_index), None, None),
+            "Mppt" => (Some(device.device_index), Some(device.mppt_index), None),
+            "String" => (Some(device.device_index), Some(device.mppt_index), Some(device.input_index)),
+            // Meters and other devices aren't numbered
+            _ => (None, None, None),
+        };
+        let (host, port, forwarding_modbus_id, protocol) = protocol_columns(source.instance);
+
+        CatalogRow {
+            serial_number: source.instance.serial_no.clone().unwrap_or_default(),
+            device_brand: source.brand.clone(),
+            device_model: source.model.clone(),
+            inv,
+            mppt,
+            input,
+            host,
+            port,
+            forwarding_modbus_id,
+            protocol,
+            ..self.base_row(device)
+        }
+    }
+}
+
+/// Customer of an entity group, everything before the first dash of its name
+pub fn customer_name(entity_group_name: &str) -> String {
+    entity_group_name.split('-').next().unwrap_or("Unknown").to_string()
+}
+
+/// Host, port, Modbus id and protocol of a device, as its ThingsBoard attributes
+fn protocol_columns(device: &DeviceInstance) -> (String, Option<u16>, Option<u8>, String) {
+    match device.protocol() {
+        Ok(ProtocolConfig::ModbusTcp(tcp)) => (tcp.host, Some(tcp.port), Some(tcp.slave_id), "modbus_tcp".to_string()),
+        Ok(ProtocolConfig::ModbusRtu(rtu)) => (String::new(), None, Some(rtu.slave_id), "modbus_rtu".to_string()),
+        Ok(ProtocolConfig::Iec104(iec104)) => (iec104.host, Some(iec104.port), None, "iec104".to_string()),
+        Ok(ProtocolConfig::Simulated(_)) => (String::new(), None, None, "simulated".to_string()),
+        Err(_) => (String::new(), None, None, String::new()),
+    }
+}
+
+/// Whether a tag of a device with a local record is listed under it: inverters leave out
+/// the Udc and Idc tags of their strings, MPPT and String devices list only those
+pub fn includes_tag(device_type: &str, tag_name: &str) -> bool {
+    match device_type {
+        "Inverter" => tag_name != "Idc" && tag_name != "Udc",
+        "Mppt" | "String" => tag_name == "Idc" || tag_name == "Udc",
+        _ => true,
+    }
+}
+
+/// Convert scaling multiplier to divider format
+/// Examples: 0.1 -> "10", 0.001 -> "1000", 1.0 -> "1"
+pub fn divider(multiplier: f64) -> String {
+    if multiplier == 0.0 {
+        return "1".to_string();
+    }
+
+    let divider = 1.0 / multiplier;
+    if divider.fract() == 0.0 {
+        format!("{:.0}", divider)
+    } else {
+        format!("{}", divider)
+    }
+}
+
+/// Parent column of a device whose parent couldn't be determined
+pub const UNKNOWN_PARENT: &str = "UNKNOWN";
+
+/// Parent column guessed from the device name:
+/// - Inverter devices: Entity Group name
+/// - MPPT devices: Parent Inverter name
+/// - String devices: Parent MPPT name
+///
+/// MPPT and String names lacking their `-M01` or `-PV01` part fall back to
+/// `<group prefix>-I01(-M01)` from the indexes, and to `None` when those aren't known either.
+pub fn parent_name(device: &DeviceData, entity_group_name: &str, group_prefix: &str, inv_index: u32, mppt_index: u32) -> Option<String> {
+    match device.device_type.as_str() {
+        // From "ACCV-P002-I01-M01" get "ACCV-P002-I01"
+        "Mppt" => strip_last_part(&device.name, "M").or_else(|| {
+            (inv_index > 0 && !group_prefix.is_empty()).then(|| format!("{}-I{:02}", group_prefix, inv_index))
+        }),
+        // From "ACCV-P002-I01-M01-PV01" get "ACCV-P002-I01-M01"
+        "String" => strip_last_part(&device.name, "PV").or_else(|| {
+            (inv_index > 0 && mppt_index > 0 && !group_prefix.is_empty())
+                .then(|| format!("{}-I{:02}-M{:02}", group_prefix, inv_index, mppt_index))
+        }),
+        _ => Some(entity_group_name.to_string()),
+    }
+}
+
+/// `name` without its last dash-separated part if that part is `tag` followed by a number
+fn strip_last_part(name: &str, tag: &str) -> Option<String> {
+    let (parent, last) = name.rsplit_once('-')?;
+    let number = last.strip_prefix(tag)?;
+    (!parent.is_empty() && !number.is_empty() && number.chars().all(|c| c.is_ascii_digit())).then(|| parent.to_string())
+}
+
+/// Whether a tag's description places it on MPPT `mppt_index`
+pub fn tag_matches_mppt(description: Option<&str>, mppt_index: u32) -> bool {
+    description.map(tag_description::parse) == Some(DeviceType::Mppt(mppt_index))
+}
+
+/// Whether a tag's description places it on input `input_index` of MPPT `mppt_index`
+pub fn tag_matches_string(description: Option<&str>, mppt_index: u32, input_index: u32) -> bool {
+    description.map(tag_description::parse) == Some(DeviceType::String(mppt_index, input_index))
+}
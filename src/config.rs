@@ -1,29 +1,201 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
 use anyhow::Result;
 use tracing::{info, warn};
+use chrono::{DateTime, Utc};
+use crate::database::{Database, DeviceInstance, DeviceTag, RetentionPolicy, TagWritePolicy};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct AppConfig {
     pub server: ServerConfig,
     pub database: DatabaseConfig,
     pub devices: Vec<DeviceConfig>,
     pub logging: LoggingConfig,
+    #[serde(default)]
+    pub reports: ReportsConfig,
+    #[serde(default)]
+    pub notifications: NotificationsConfig,
+    #[serde(default)]
+    pub idempotency: IdempotencyConfig,
+    #[serde(default)]
+    pub tb_cache: TbCacheConfig,
+    #[serde(default)]
+    pub timeouts: TimeoutsConfig,
+    /// ThingsBoard server used for sync, catalog export and attribute updates
+    #[serde(default)]
+    pub thingsboard: Option<ThingsBoardConfig>,
+    #[serde(default)]
+    pub telemetry_forwarding: TelemetryForwardingConfig,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    #[serde(default)]
+    pub iec104_server: Iec104ServerConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    /// Endpoints POSTed a signed JSON payload when the events they subscribe to occur
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+    /// Broker logged values and device statuses are published to; nothing is published without it
+    #[serde(default)]
+    pub mqtt: Option<MqttConfig>,
+    /// Directory logged values are also appended to as CSV files; nothing is exported without it
+    #[serde(default)]
+    pub file_export: Option<FileExportConfig>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ServerConfig {
     pub port: u16,
     pub host: String,
+    /// Start polling every enabled device when the service boots
+    #[serde(default = "default_auto_start")]
+    pub auto_start: bool,
+    /// Gap between device starts at boot, so a serial bus or switch isn't hit by every connection at once
+    #[serde(default = "default_auto_start_stagger_ms")]
+    pub auto_start_stagger_ms: u64,
+    /// Socket.IO tag updates are sent at most this often per subscription; 0 sends each poll at once
+    #[serde(default = "default_tag_update_interval_ms")]
+    pub tag_update_interval_ms: u64,
+    /// Largest file upload accepted, in MiB, across all fields of a multipart form
+    #[serde(default = "default_max_upload_mb")]
+    pub max_upload_mb: u64,
+    /// How long a shutdown waits for pollers and connections to finish before exiting anyway
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+    /// Address the UI and API listen on
+    #[serde(default = "default_bind_address")]
+    pub bind_address: String,
+    /// Cross-origin access for browsers; same-origin only when absent
+    #[serde(default)]
+    pub cors: CorsConfig,
+    /// Serve HTTPS with this certificate instead of plain HTTP
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Which other web origins may call the API from a browser
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct CorsConfig {
+    /// Origins such as `https://scada.example.com`, or `"*"` for any origin
+    pub allowed_origins: Vec<String>,
+    /// Methods allowed cross-origin, or `"*"` for any; `GET`, `POST`, `PUT`, `PATCH` and `DELETE` when empty
+    pub allowed_methods: Vec<String>,
+    /// Let cross-origin requests carry cookies and `Authorization`; not allowed with `"*"` origins
+    pub allow_credentials: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TlsConfig {
+    /// PEM certificate chain, server certificate first
+    pub cert_path: String,
+    /// PEM private key of the certificate, PKCS#8, PKCS#1 (RSA) or SEC1 (EC)
+    pub key_path: String,
+}
+
+fn default_auto_start() -> bool {
+    true
+}
+
+fn default_auto_start_stagger_ms() -> u64 {
+    200
+}
+
+fn default_tag_update_interval_ms() -> u64 {
+    250
+}
+
+fn default_max_upload_mb() -> u64 {
+    10
+}
+
+fn default_shutdown_timeout_secs() -> u64 {
+    10
+}
+
+fn default_bind_address() -> String {
+    "0.0.0.0".to_string()
+}
+
+pub const DEFAULT_CORS_METHODS: [&str; 5] = ["GET", "POST", "PUT", "PATCH", "DELETE"];
+
+impl ServerConfig {
+    /// Every problem with the listen address, CORS and TLS settings, one per line
+    pub fn validate(&self) -> Result<(), String> {
+        let mut errors = Vec::new();
+        if self.bind_address.parse::<std::net::IpAddr>().is_err() {
+            errors.push(format!("server.bind_address '{}' is not an IP address", self.bind_address));
+        }
+
+        let origins = &self.cors.allowed_origins;
+        if origins.iter().any(|origin| origin == "*") {
+            if origins.len() > 1 {
+                errors.push("server.cors.allowed_origins: \"*\" can't be combined with other origins".to_string());
+            }
+            if self.cors.allow_credentials {
+                errors.push("server.cors.allow_credentials can't be used with \"*\" origins; list the origins instead".to_string());
+            }
+        }
+        for origin in origins.iter().filter(|origin| *origin != "*") {
+            if let Err(e) = check_cors_origin(origin) {
+                errors.push(format!("server.cors.allowed_origins: '{}' {}", origin, e));
+            }
+        }
+        for method in self.cors.allowed_methods.iter().filter(|method| *method != "*") {
+            if method.is_empty() || !method.bytes().all(|byte| byte.is_ascii_alphabetic()) {
+                errors.push(format!("server.cors.allowed_methods: '{}' is not an HTTP method", method));
+            }
+        }
+
+        if let Some(tls) = &self.tls {
+            for (field, path) in [("cert_path", &tls.cert_path), ("key_path", &tls.key_path)] {
+                if path.trim().is_empty() {
+                    errors.push(format!("server.tls.{} must not be empty", field));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+}
+
+/// An origin is a scheme, host and optional port, exactly as browsers send it
+fn check_cors_origin(origin: &str) -> Result<(), &'static str> {
+    let url = reqwest::Url::parse(origin).map_err(|_| "is not a URL")?;
+    if !matches!(url.scheme(), "http" | "https") || !url.has_host() {
+        return Err("must be an http or https origin");
+    }
+    if url.origin().ascii_serialization() != origin {
+        return Err("must be only a scheme, host and port, without a path or trailing slash");
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct DatabaseConfig {
     pub path: String,
     pub max_log_entries: u32,
     pub cleanup_interval_hours: u32,
+    /// Tags with a deadband still log a value at least this often, so gaps in the log are bounded
+    #[serde(default = "default_deadband_heartbeat_minutes")]
+    pub deadband_heartbeat_minutes: u64,
+}
+
+fn default_deadband_heartbeat_minutes() -> u64 {
+    15
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct DeviceConfig {
     pub id: String,
     pub name: String,
@@ -33,35 +205,428 @@ pub struct DeviceConfig {
     pub timeout_ms: u64,
     pub retry_count: u32,
     pub tags: Vec<TagConfig>,
+    /// Drop samples that fail data type range validation instead of logging them as type_mismatch
+    #[serde(default)]
+    pub strict_types: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// How a device is reached, selected by the `type` field of its protocol config
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 #[serde(tag = "type")]
 pub enum ProtocolConfig {
     #[serde(rename = "modbus_rtu")]
-    ModbusRtu {
-        port: String,
-        baud_rate: u32,
-        data_bits: u8,
-        stop_bits: u8,
-        parity: String,
-        slave_id: u8,
-    },
+    ModbusRtu(ModbusRtuConfig),
     #[serde(rename = "modbus_tcp")]
-    ModbusTcp {
-        host: String,
-        port: u16,
-        slave_id: u8,
-    },
+    ModbusTcp(ModbusTcpConfig),
     #[serde(rename = "iec104")]
-    Iec104 {
-        host: String,
-        port: u16,
-        common_address: u16,
+    Iec104(Iec104Config),
+    /// Values generated by the logger itself, for demos and tests without hardware
+    #[serde(rename = "simulated")]
+    Simulated(SimulatedConfig),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ModbusRtuConfig {
+    pub port: String,
+    pub baud_rate: u32,
+    #[serde(default = "default_data_bits")]
+    pub data_bits: u8,
+    #[serde(default = "default_stop_bits")]
+    pub stop_bits: u8,
+    /// "none", "even" or "odd"
+    #[serde(default = "default_parity")]
+    pub parity: String,
+    pub slave_id: u8,
+    /// Unused registers a block read may span to join two tags; 0 only joins adjacent tags
+    #[serde(default)]
+    pub max_block_gap: u16,
+    /// Silence kept on the bus between transactions; 0 uses 3.5 character times
+    #[serde(default)]
+    pub inter_frame_delay_ms: u64,
+    /// Registers the device keeps its serial number in, so it can be read back
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial_source: Option<SerialSource>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ModbusTcpConfig {
+    pub host: String,
+    pub port: u16,
+    pub slave_id: u8,
+    /// Unused registers a block read may span to join two tags; 0 only joins adjacent tags
+    #[serde(default)]
+    pub max_block_gap: u16,
+    /// Pause between requests on the connection, which devices at the same host and port share
+    #[serde(default)]
+    pub request_delay_ms: u64,
+    /// Registers the device keeps its serial number in, so it can be read back
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial_source: Option<SerialSource>,
+}
+
+/// Where a Modbus device keeps its serial number, e.g. 10 registers of ASCII at 4990 on
+/// Sungrow inverters, or 16 at 40052 for the SunSpec common model of a map at 40000
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SerialSource {
+    /// `holding` or `input`
+    #[serde(default)]
+    pub register_type: RegisterType,
+    pub address: u16,
+    /// Registers the serial spans
+    pub length: u16,
+    #[serde(default)]
+    pub encoding: SerialEncoding,
+}
+
+/// How the registers of a serial number are turned into text
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SerialEncoding {
+    /// Two characters per register, high byte first, padded with NULs or spaces
+    #[default]
+    Ascii,
+    /// Four decimal digits per register, high nibble first
+    Bcd,
+}
+
+/// Registers a serial number may span
+pub const MAX_SERIAL_REGISTERS: u16 = 64;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Iec104Config {
+    pub host: String,
+    pub port: u16,
+    #[serde(default = "default_common_address")]
+    pub common_address: u16,
+    #[serde(default)]
+    pub mode: Iec104Mode,
+    /// General interrogation period used in hybrid mode
+    #[serde(default = "default_interrogation_interval_ms")]
+    pub interrogation_interval_ms: u64,
+}
+
+/// How an IEC 104 device delivers its values
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Iec104Mode {
+    /// Send a general interrogation on every poll
+    #[default]
+    Interrogation,
+    /// Only collect values the RTU transmits spontaneously
+    Spontaneous,
+    /// Collect spontaneous values, with a general interrogation every `interrogation_interval_ms`
+    Hybrid,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SimulatedConfig {
+    /// How the values of each tag are generated, by tag name; other tags read their own address
+    #[serde(default)]
+    pub patterns: BTreeMap<String, SimulatedPattern>,
+    /// Periodic outages, during which reads and connects fail like an unreachable device
+    #[serde(default)]
+    pub dropout: Option<SimulatedDropout>,
+    /// Chance from 0 to 1 that a tag read comes back with `Bad` quality
+    #[serde(default)]
+    pub bad_quality_probability: f64,
+    /// Seed for random walks and bad quality, so a run can be repeated
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+/// How a simulated tag's raw value is generated before scaling
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "pattern", rename_all = "snake_case", deny_unknown_fields)]
+pub enum SimulatedPattern {
+    /// `offset + amplitude * sin(2π t / period_seconds)`
+    Sine {
+        amplitude: f64,
+        period_seconds: f64,
+        #[serde(default)]
+        offset: f64,
     },
+    /// Rises from `from` to `to` over `period_seconds`, then starts over
+    Ramp { from: f64, to: f64, period_seconds: f64 },
+    /// Starts at `start` and moves by up to `step` either way on every read, kept within `min` and `max`
+    RandomWalk {
+        start: f64,
+        step: f64,
+        #[serde(default)]
+        min: Option<f64>,
+        #[serde(default)]
+        max: Option<f64>,
+    },
+    Constant { value: f64 },
+    /// One value per read from a column of a CSV file with a header row, starting over at the end
+    Replay { file: String, column: String },
+}
+
+/// The device is unreachable for `seconds` at the start of every `every_minutes`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SimulatedDropout {
+    pub seconds: u64,
+    /// Fractions are allowed, for short cycles in tests
+    pub every_minutes: f64,
+}
+
+impl SimulatedDropout {
+    /// Within an outage at `now`; outages are aligned to the clock, so every poller of a device agrees
+    pub fn is_down(&self, now: DateTime<Utc>) -> bool {
+        let cycle_ms = (self.every_minutes * 60_000.0) as i64;
+        cycle_ms > 0 && now.timestamp_millis().rem_euclid(cycle_ms) < self.seconds as i64 * 1000
+    }
+}
+
+fn default_common_address() -> u16 {
+    1
+}
+
+fn default_interrogation_interval_ms() -> u64 {
+    300_000
+}
+
+fn default_data_bits() -> u8 {
+    8
+}
+
+fn default_stop_bits() -> u8 {
+    1
+}
+
+fn default_parity() -> String {
+    "none".to_string()
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A problem with one field of a submitted configuration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    fn new(field: &str, message: impl Into<String>) -> Self {
+        Self { field: field.to_string(), message: message.into() }
+    }
+}
+
+/// Field-by-field checks of a protocol config object, collecting every problem
+struct ProtocolFields<'a> {
+    object: &'a serde_json::Map<String, serde_json::Value>,
+    errors: Vec<FieldError>,
+}
+
+impl<'a> ProtocolFields<'a> {
+    /// An unsigned integer of at most `max`, if present
+    fn uint(&mut self, field: &str, required: bool, min: u64, max: u64) {
+        match self.object.get(field) {
+            None | Some(serde_json::Value::Null) if required => self.errors.push(FieldError::new(field, "is required")),
+            None | Some(serde_json::Value::Null) => {}
+            Some(value) => match value.as_u64() {
+                Some(number) if (min..=max).contains(&number) => {}
+                _ => self.errors.push(FieldError::new(field, format!("must be a whole number from {} to {}", min, max))),
+            },
+        }
+    }
+
+    /// A required, non-empty string
+    fn string(&mut self, field: &str) -> Option<&'a str> {
+        let object: &'a serde_json::Map<String, serde_json::Value> = self.object;
+        match object.get(field).map(|value| value.as_str().map(str::trim)) {
+            None | Some(Some("")) => self.errors.push(FieldError::new(field, "is required")),
+            Some(None) => self.errors.push(FieldError::new(field, "must be a string")),
+            Some(Some(text)) => return Some(text),
+        }
+        None
+    }
+
+    /// A required IP address
+    fn ip_address(&mut self, field: &str) {
+        if let Some(text) = self.string(field) {
+            if text.parse::<std::net::IpAddr>().is_err() {
+                self.errors.push(FieldError::new(field, format!("'{}' is not an IP address", text)));
+            }
+        }
+    }
+
+    /// Whatever serde itself rejects in the protocol's config, e.g. fields it doesn't have or
+    /// values of the wrong type, reported by path. Serde stops at the first problem, so the
+    /// field at fault is taken out and the rest checked again until it passes; fields the
+    /// checks above already reported aren't reported twice.
+    fn deserialize<T: serde::de::DeserializeOwned>(&mut self) {
+        let mut object = self.object.clone();
+        object.remove("type");
+        while let Err(e) = serde_path_to_error::deserialize::<_, T>(serde_json::Value::Object(object.clone())) {
+            let path = e.path().to_string();
+            let Some(field) = path.split(['.', '[']).next().filter(|field| object.contains_key(*field)) else {
+                // A missing field, which the checks above report by name
+                if self.errors.is_empty() {
+                    self.errors.push(FieldError::new("protocol_config", e.inner().to_string()));
+                }
+                break;
+            };
+            if !self.errors.iter().any(|error| error.field == field || error.field.starts_with(&format!("{}.", field))) {
+                self.errors.push(FieldError::new(&path, e.inner().to_string()));
+            }
+            object.remove(field);
+        }
+    }
+
+    /// A number from `min` to `max`, if present
+    fn number(&mut self, field: &str, min: f64, max: f64) {
+        match self.object.get(field) {
+            None | Some(serde_json::Value::Null) => {}
+            Some(value) if value.as_f64().is_some_and(|number| (min..=max).contains(&number)) => {}
+            Some(_) => self.errors.push(FieldError::new(field, format!("must be a number from {} to {}", min, max))),
+        }
+    }
+
+    /// Every tag pattern of a simulated device, reported as `patterns.<tag name>`
+    fn simulated_patterns(&mut self) {
+        let patterns = match self.object.get("patterns") {
+            None | Some(serde_json::Value::Null) => return,
+            Some(serde_json::Value::Object(patterns)) => patterns,
+            Some(_) => return self.errors.push(FieldError::new("patterns", "must be an object of patterns by tag name")),
+        };
+        for (tag_name, pattern) in patterns {
+            let field = format!("patterns.{}", tag_name);
+            let problem = match serde_json::from_value::<SimulatedPattern>(pattern.clone()) {
+                Err(e) => Some(e.to_string()),
+                Ok(SimulatedPattern::Sine { period_seconds, .. } | SimulatedPattern::Ramp { period_seconds, .. }) if period_seconds <= 0.0 => {
+                    Some("period_seconds must be above 0".to_string())
+                }
+                Ok(SimulatedPattern::RandomWalk { step, .. }) if step < 0.0 => Some("step must not be negative".to_string()),
+                Ok(SimulatedPattern::RandomWalk { min: Some(min), max: Some(max), .. }) if min > max => Some("min must not be above max".to_string()),
+                Ok(SimulatedPattern::Replay { file, column }) if file.trim().is_empty() || column.trim().is_empty() => {
+                    Some("file and column are required".to_string())
+                }
+                Ok(_) => None,
+            };
+            if let Some(problem) = problem {
+                self.errors.push(FieldError::new(&field, problem));
+            }
+        }
+    }
+
+    fn simulated_dropout(&mut self) {
+        match self.object.get("dropout") {
+            None | Some(serde_json::Value::Null) => {}
+            Some(dropout) => match serde_json::from_value::<SimulatedDropout>(dropout.clone()) {
+                Ok(dropout) if dropout.seconds == 0 || dropout.every_minutes <= 0.0 => {
+                    self.errors.push(FieldError::new("dropout", "seconds and every_minutes must be above 0"))
+                }
+                Ok(dropout) if dropout.seconds as f64 >= dropout.every_minutes * 60.0 => {
+                    self.errors.push(FieldError::new("dropout", "must last less than every_minutes, or the device never comes up"))
+                }
+                Ok(_) => {}
+                // Reported with its path by `deserialize`
+                Err(_) => {}
+            },
+        }
+    }
+
+    fn serial_source(&mut self) {
+        match self.object.get("serial_source") {
+            None | Some(serde_json::Value::Null) => {}
+            Some(source) => match serde_json::from_value::<SerialSource>(source.clone()) {
+                Ok(source) if !matches!(source.register_type, RegisterType::Holding | RegisterType::Input) => {
+                    self.errors.push(FieldError::new("serial_source", "register_type must be holding or input"))
+                }
+                Ok(source) if source.length == 0 || source.length > MAX_SERIAL_REGISTERS => {
+                    self.errors.push(FieldError::new("serial_source", format!("length must be from 1 to {} registers", MAX_SERIAL_REGISTERS)))
+                }
+                Ok(source) if source.address as u32 + source.length as u32 > 65536 => {
+                    self.errors.push(FieldError::new("serial_source", "runs past the last Modbus address 65535"))
+                }
+                Ok(_) => {}
+                // Reported with its path by `deserialize`
+                Err(_) => {}
+            },
+        }
+    }
+
+    /// A string that must be one of `allowed`, if present
+    fn one_of(&mut self, field: &str, allowed: &[&str]) {
+        match self.object.get(field) {
+            None | Some(serde_json::Value::Null) => {}
+            Some(value) if value.as_str().is_some_and(|text| allowed.contains(&text)) => {}
+            Some(_) => self.errors.push(FieldError::new(field, format!("must be one of {}", allowed.join(", ")))),
+        }
+    }
+}
+
+impl ProtocolConfig {
+    /// Parse a protocol config submitted as JSON, reporting every invalid or unknown field
+    /// rather than only the first one serde runs into. Stored configs are read leniently.
+    pub fn from_json(value: &serde_json::Value) -> Result<Self, Vec<FieldError>> {
+        let Some(object) = value.as_object() else {
+            return Err(vec![FieldError::new("protocol_config", "must be an object")]);
+        };
+        let mut fields = ProtocolFields { object, errors: Vec::new() };
+
+        match object.get("type").and_then(|value| value.as_str()) {
+            Some("modbus_tcp") => {
+                fields.ip_address("host");
+                fields.uint("port", true, 1, u16::MAX as u64);
+                fields.uint("slave_id", true, 0, 247);
+                fields.uint("max_block_gap", false, 0, u16::MAX as u64);
+                fields.uint("request_delay_ms", false, 0, 60_000);
+                fields.serial_source();
+                fields.deserialize::<ModbusTcpConfig>();
+            }
+            Some("modbus_rtu") => {
+                fields.string("port");
+                fields.uint("baud_rate", true, 300, 4_000_000);
+                fields.uint("slave_id", true, 1, 247);
+                fields.uint("data_bits", false, 5, 8);
+                fields.uint("stop_bits", false, 1, 2);
+                fields.one_of("parity", &["none", "even", "odd"]);
+                fields.uint("max_block_gap", false, 0, u16::MAX as u64);
+                fields.uint("inter_frame_delay_ms", false, 0, 60_000);
+                fields.serial_source();
+                fields.deserialize::<ModbusRtuConfig>();
+            }
+            Some("iec104") => {
+                fields.ip_address("host");
+                fields.uint("port", true, 1, u16::MAX as u64);
+                fields.uint("common_address", false, 0, u16::MAX as u64);
+                fields.one_of("mode", &["interrogation", "spontaneous", "hybrid"]);
+                fields.uint("interrogation_interval_ms", false, 1_000, u64::MAX);
+                fields.deserialize::<Iec104Config>();
+            }
+            Some("simulated") => {
+                fields.simulated_patterns();
+                fields.simulated_dropout();
+                fields.number("bad_quality_probability", 0.0, 1.0);
+                fields.uint("seed", false, 0, u64::MAX);
+                fields.deserialize::<SimulatedConfig>();
+            }
+            _ => fields.errors.push(FieldError::new("type", "must be one of modbus_tcp, modbus_rtu, iec104, simulated")),
+        }
+
+        if !fields.errors.is_empty() {
+            return Err(fields.errors);
+        }
+        serde_json::from_value(value.clone()).map_err(|e| vec![FieldError::new("protocol_config", e.to_string())])
+    }
+
+    /// Where the device keeps its serial number, for protocols that can say
+    pub fn serial_source(&self) -> Option<&SerialSource> {
+        match self {
+            ProtocolConfig::ModbusTcp(config) => config.serial_source.as_ref(),
+            ProtocolConfig::ModbusRtu(config) => config.serial_source.as_ref(),
+            ProtocolConfig::Iec104(_) | ProtocolConfig::Simulated(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct TagConfig {
     pub name: String,
     pub address: u16,
@@ -69,9 +634,16 @@ pub struct TagConfig {
     pub data_type: DataType,
     pub scaling: Option<ScalingConfig>,
     pub description: Option<String>,
+    /// Layout of multi-register values; the data type's default when unset
+    #[serde(default)]
+    pub byte_order: Option<ByteOrder>,
+    /// Table numeric tags are read from, `holding` or `input`; holding registers when unset.
+    /// Coil and discrete input tags always read their own table.
+    #[serde(default)]
+    pub register_type: Option<RegisterType>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub enum DataType {
     #[serde(rename = "coil")]
     Coil,
@@ -91,21 +663,866 @@ pub enum DataType {
     UInt32,
     #[serde(rename = "int32")]
     Int32,
+    #[serde(rename = "float64")]
+    Float64,
+    #[serde(rename = "uint64")]
+    UInt64,
+    #[serde(rename = "int64")]
+    Int64,
+}
+
+/// Data type names tags may be stored with, aliases included
+pub const TAG_DATA_TYPES: &[&str] = &[
+    "coil", "discrete_input", "holding_register", "input_register", "uint16", "U16", "int16", "I16",
+    "uint32", "U32", "int32", "I32", "uint64", "U64", "int64", "I64", "float32", "F32", "FLOAT",
+    "float64", "F64", "DOUBLE",
+];
+
+impl DataType {
+    /// Parse a tag's stored data type name, accepting the vendor aliases CSV imports use
+    pub fn from_tag_type(name: &str) -> Option<DataType> {
+        Some(match name {
+            "coil" => DataType::Coil,
+            "discrete_input" => DataType::DiscreteInput,
+            "holding_register" => DataType::HoldingRegister,
+            "input_register" => DataType::InputRegister,
+            "uint16" | "U16" => DataType::UInt16,
+            "int16" | "I16" => DataType::Int16,
+            "uint32" | "U32" => DataType::UInt32,
+            "int32" | "I32" => DataType::Int32,
+            "uint64" | "U64" => DataType::UInt64,
+            "int64" | "I64" => DataType::Int64,
+            "float32" | "F32" | "FLOAT" => DataType::Float32,
+            "float64" | "F64" | "DOUBLE" => DataType::Float64,
+            _ => return None,
+        })
+    }
+
+    /// The name tags store this data type under
+    pub fn tag_type(&self) -> &'static str {
+        match self {
+            DataType::Coil => "coil",
+            DataType::DiscreteInput => "discrete_input",
+            DataType::HoldingRegister => "holding_register",
+            DataType::InputRegister => "input_register",
+            DataType::UInt16 => "uint16",
+            DataType::Int16 => "int16",
+            DataType::UInt32 => "uint32",
+            DataType::Int32 => "int32",
+            DataType::UInt64 => "uint64",
+            DataType::Int64 => "int64",
+            DataType::Float32 => "float32",
+            DataType::Float64 => "float64",
+        }
+    }
+
+    /// Number of 16-bit registers a value of this type occupies
+    pub fn register_width(&self) -> u16 {
+        match self {
+            DataType::Coil | DataType::DiscreteInput | DataType::HoldingRegister | DataType::InputRegister
+            | DataType::UInt16 | DataType::Int16 => 1,
+            DataType::UInt32 | DataType::Int32 | DataType::Float32 => 2,
+            DataType::UInt64 | DataType::Int64 | DataType::Float64 => 4,
+        }
+    }
+
+    /// Order used when a tag doesn't set one: integers low word first, floats high word first
+    pub fn default_byte_order(&self) -> ByteOrder {
+        match self {
+            DataType::UInt32 | DataType::Int32 | DataType::UInt64 | DataType::Int64 => ByteOrder::Cdab,
+            _ => ByteOrder::Abcd,
+        }
+    }
+}
+
+/// How a multi-register value is laid out, naming the bytes of the value from most to least
+/// significant. ABCD is big-endian throughout; CDAB reverses the word order (low word first),
+/// BADC swaps the bytes within each word, and DCBA does both. Single-register values are
+/// always big-endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ByteOrder {
+    Abcd,
+    Cdab,
+    Badc,
+    Dcba,
+}
+
+impl ByteOrder {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ByteOrder::Abcd => "ABCD",
+            ByteOrder::Cdab => "CDAB",
+            ByteOrder::Badc => "BADC",
+            ByteOrder::Dcba => "DCBA",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_uppercase().as_str() {
+            "ABCD" => Some(ByteOrder::Abcd),
+            "CDAB" => Some(ByteOrder::Cdab),
+            "BADC" => Some(ByteOrder::Badc),
+            "DCBA" => Some(ByteOrder::Dcba),
+            _ => None,
+        }
+    }
+
+    pub fn swaps_words(&self) -> bool {
+        matches!(self, ByteOrder::Cdab | ByteOrder::Dcba)
+    }
+
+    pub fn swaps_bytes(&self) -> bool {
+        matches!(self, ByteOrder::Badc | ByteOrder::Dcba)
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Which Modbus table a register read goes to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RegisterType {
+    #[default]
+    Holding,
+    Input,
+    Coil,
+    DiscreteInput,
+}
+
+impl RegisterType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RegisterType::Holding => "holding",
+            RegisterType::Input => "input",
+            RegisterType::Coil => "coil",
+            RegisterType::DiscreteInput => "discrete_input",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "holding" => Some(RegisterType::Holding),
+            "input" => Some(RegisterType::Input),
+            "coil" => Some(RegisterType::Coil),
+            "discrete_input" => Some(RegisterType::DiscreteInput),
+            _ => None,
+        }
+    }
+}
+
+/// A one-off read of `size` registers decoded as `data_type`; for IEC 104 `address` is the IOA
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RegisterRead {
+    pub register_type: RegisterType,
+    pub address: u16,
+    pub size: u16,
+    pub data_type: DataType,
+    #[serde(default)]
+    pub byte_order: Option<ByteOrder>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ScalingConfig {
     pub multiplier: f64,
     pub offset: f64,
     pub unit: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct LoggingConfig {
     pub level: String,
     pub file_path: Option<String>,
     pub max_file_size_mb: u32,
     pub max_files: u32,
+    /// Log unsanitized external payloads (e.g. ThingsBoard error bodies) at debug level
+    #[serde(default)]
+    pub log_payloads: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct ReportsConfig {
+    pub enabled: bool,
+    /// Hour of day (UTC) at which the previous day's report is generated
+    pub run_at_hour_utc: u32,
+    /// No longer used; reports are sent to the `[[webhooks]]` subscribed to `daily_report`,
+    /// and a value here stops startup so reports aren't silently dropped
+    pub webhook_url: Option<String>,
+    /// Gaps between samples longer than this are listed in the data gaps section
+    pub data_gap_threshold_seconds: u64,
+    pub sections: ReportSections,
+    /// Per-plant section overrides keyed by plant name
+    pub plants: HashMap<String, ReportSections>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct ReportSections {
+    pub availability: bool,
+    pub energy: bool,
+    pub data_gaps: bool,
+    pub gateway_health: bool,
+    /// Alarms raised during the day
+    pub alarms: bool,
+}
+
+impl Default for ReportsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            run_at_hour_utc: 1,
+            webhook_url: None,
+            data_gap_threshold_seconds: 300,
+            sections: ReportSections::default(),
+            plants: HashMap::new(),
+        }
+    }
+}
+
+impl Default for ReportSections {
+    fn default() -> Self {
+        Self {
+            availability: true,
+            energy: true,
+            data_gaps: true,
+            gateway_health: true,
+            alarms: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct NotificationsConfig {
+    /// Read notifications are purged this many days after being read
+    pub read_retention_days: u32,
+}
+
+impl Default for NotificationsConfig {
+    fn default() -> Self {
+        Self {
+            read_retention_days: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// Log entries older than this are deleted; unset keeps them regardless of age
+    pub max_age_days: Option<u32>,
+    /// Oldest log entries beyond this count are deleted; falls back to `[database] max_log_entries`
+    pub max_entries: Option<u32>,
+    /// How often retention runs; falls back to `[database] cleanup_interval_hours`
+    pub cleanup_interval_minutes: Option<u64>,
+    /// Rows deleted per statement so logging isn't blocked while a large backlog is removed
+    pub batch_size: u32,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            max_age_days: None,
+            max_entries: None,
+            cleanup_interval_minutes: None,
+            batch_size: 5000,
+        }
+    }
+}
+
+impl RetentionConfig {
+    pub fn policy(&self, database: &DatabaseConfig) -> RetentionPolicy {
+        RetentionPolicy {
+            max_age: self.max_age_days.map(|days| chrono::Duration::days(days as i64)),
+            max_entries: Some(self.max_entries.unwrap_or(database.max_log_entries) as u64),
+            batch_size: self.batch_size.max(1),
+        }
+    }
+
+    pub fn interval(&self, database: &DatabaseConfig) -> std::time::Duration {
+        let minutes = self
+            .cleanup_interval_minutes
+            .unwrap_or(database.cleanup_interval_hours as u64 * 60)
+            .max(1);
+        std::time::Duration::from_secs(minutes * 60)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct IdempotencyConfig {
+    /// How long a stored response can be replayed for the same Idempotency-Key
+    pub ttl_hours: u32,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        Self {
+            ttl_hours: 24,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// Require a session token to scrape `/metrics`; Prometheus then needs a bearer token
+    pub require_auth: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct HealthConfig {
+    /// The health check fails when the database can't take a write lock within this
+    pub database_timeout_ms: u64,
+    /// Less free space than this where the database lives reports the service as degraded
+    pub min_free_disk_mb: u64,
+    /// More running devices than this fraction in `Error` or `Offline` reports the service as degraded
+    pub max_errored_fraction: f64,
+    /// Check that ThingsBoard answers while telemetry forwarding is enabled
+    pub ping_thingsboard: bool,
+    pub thingsboard_timeout_ms: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            database_timeout_ms: 2000,
+            min_free_disk_mb: 100,
+            max_errored_fraction: 0.5,
+            ping_thingsboard: true,
+            thingsboard_timeout_ms: 3000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// Shortest password accepted when a user changes theirs
+    pub min_password_length: usize,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self { min_password_length: 8 }
+    }
+}
+
+/// Something that happened which webhooks can subscribe to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// A device was reported offline or failed to connect
+    DeviceOffline,
+    /// An offline device is connected and reading again
+    DeviceOnline,
+    AlarmRaised,
+    AlarmCleared,
+    /// A ThingsBoard sync or other background job completed or failed
+    JobFinished,
+    /// A log retention run finished
+    DatabaseCleanup,
+    /// The daily report was generated and is due for delivery
+    DailyReport,
+}
+
+impl WebhookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::DeviceOffline => "device_offline",
+            WebhookEvent::DeviceOnline => "device_online",
+            WebhookEvent::AlarmRaised => "alarm_raised",
+            WebhookEvent::AlarmCleared => "alarm_cleared",
+            WebhookEvent::JobFinished => "job_finished",
+            WebhookEvent::DatabaseCleanup => "database_cleanup",
+            WebhookEvent::DailyReport => "daily_report",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct WebhookConfig {
+    /// Assigned when the webhook is created
+    #[serde(default)]
+    pub id: String,
+    /// `http` or `https` URL the payloads are POSTed to
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    /// Key of the HMAC-SHA256 `X-Webhook-Signature` header. Never returned by the API; left
+    /// blank on update it keeps its current value.
+    #[serde(default)]
+    #[schema(write_only)]
+    pub secret: String,
+    #[serde(default = "default_webhook_enabled")]
+    pub enabled: bool,
+}
+
+fn default_webhook_enabled() -> bool {
+    true
+}
+
+impl WebhookConfig {
+    /// Every problem with the webhook's fields
+    pub fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        match reqwest::Url::parse(self.url.trim()) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") && url.has_host() => {}
+            Ok(_) => errors.push(FieldError::new("url", "must be an http or https URL")),
+            Err(e) => errors.push(FieldError::new("url", format!("is not a valid URL: {}", e))),
+        }
+        if self.events.is_empty() {
+            errors.push(FieldError::new("events", "must list at least one event"));
+        }
+        if self.secret.trim().is_empty() {
+            errors.push(FieldError::new("secret", "must not be empty"));
+        }
+        errors
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct TbCacheConfig {
+    /// How long a ThingsBoard entity group device listing is reused
+    pub group_devices_ttl_seconds: u64,
+    /// Larger groups are always crawled instead of cached
+    pub max_devices_per_group: usize,
+}
+
+impl Default for TbCacheConfig {
+    fn default() -> Self {
+        Self {
+            group_devices_ttl_seconds: 60,
+            max_devices_per_group: 500,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct TimeoutsConfig {
+    /// Database reads behind regular API requests; exceeding it returns 504
+    pub query_seconds: u64,
+    /// Whole-file exports such as the log CSV download
+    pub export_seconds: u64,
+}
+
+impl Default for TimeoutsConfig {
+    fn default() -> Self {
+        Self {
+            query_seconds: 30,
+            export_seconds: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ThingsBoardConfig {
+    pub base_url: String,
+    pub username: String,
+    /// Never returned by `GET /api/config`; an empty value on update keeps the stored password
+    #[serde(default)]
+    #[schema(write_only)]
+    pub password: String,
+    /// Shown in the UI to tell plants on different ThingsBoard tenants apart
+    pub tenant_label: Option<String>,
+    /// Attempts per request, including the first, when ThingsBoard fails transiently
+    #[serde(default = "default_tb_retry_max_attempts")]
+    pub retry_max_attempts: u32,
+    /// Delay before the first retry, doubled for each retry after it
+    #[serde(default = "default_tb_retry_base_delay_ms")]
+    pub retry_base_delay_ms: u64,
+    /// Requests per second sent to ThingsBoard across all operations; 0 doesn't limit them
+    #[serde(default = "default_tb_requests_per_second")]
+    pub requests_per_second: f64,
+    /// Devices a sync creates at the same time
+    #[serde(default = "default_tb_sync_concurrency")]
+    pub sync_concurrency: usize,
+    /// How the PV strings of an inverter are numbered in their device names
+    #[serde(default)]
+    pub pv_naming: PvNaming,
+}
+
+/// Numbering of the `-PV##` suffix of string device names. Either way the name starts with
+/// its MPPT's name, so the parent MPPT can be read back from it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PvNaming {
+    /// One index running across all MPPTs of the inverter, in MPPT and input order
+    #[default]
+    Global,
+    /// Restarts at 1 on each MPPT and counts the inputs that MPPT actually has
+    PerMppt,
+}
+
+impl PvNaming {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PvNaming::Global => "global",
+            PvNaming::PerMppt => "per_mppt",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct TelemetryForwardingConfig {
+    /// Push logged values of devices linked to ThingsBoard; needs a `[thingsboard]` section
+    pub enabled: bool,
+    /// A device's pending values are pushed at least this often
+    pub batch_interval_seconds: u64,
+    /// Push early once this many values are pending for a device
+    pub max_batch_size: usize,
+    /// What becomes of values whose quality isn't `Good`
+    pub non_good_values: NonGoodTelemetry,
+}
+
+impl Default for TelemetryForwardingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            batch_interval_seconds: 10,
+            max_batch_size: 500,
+            non_good_values: NonGoodTelemetry::default(),
+        }
+    }
+}
+
+/// How values that aren't `Good` (or computed by a virtual tag) are forwarded to ThingsBoard
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum NonGoodTelemetry {
+    /// Leave them out
+    #[default]
+    Skip,
+    /// Send them with a `<tag>_quality` key next to the value; failed reads send only that key
+    Annotate,
+}
+
+/// ASDU types a served point can be sent as: single point, scaled value or short float
+pub const IEC104_SERVER_TYPE_IDS: [u8; 3] = [1, 11, 13];
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct Iec104ServerConfig {
+    /// Serve mapped tags to IEC 104 masters (SCADA) as an outstation
+    pub enabled: bool,
+    pub port: u16,
+    /// Common address of the ASDUs this logger sends
+    pub common_address: u16,
+    /// A point is sent spontaneously once its value moves by more than this since it was last
+    /// sent, or its quality changes; 0 sends every change
+    pub deadband: f64,
+    pub points: Vec<Iec104ServerPoint>,
+}
+
+impl Default for Iec104ServerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 2404,
+            common_address: 1,
+            deadband: 0.0,
+            points: Vec::new(),
+        }
+    }
+}
+
+/// A device tag served under an information object address
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Iec104ServerPoint {
+    pub device_id: String,
+    pub tag_name: String,
+    pub ioa: u32,
+    /// 1 (single point), 11 (scaled value) or 13 (short float, the default)
+    #[serde(default = "default_iec104_server_type_id")]
+    pub type_id: u8,
+    /// Interrogation group 1-16 the point is also sent in, besides station interrogation
+    #[serde(default)]
+    pub group: Option<u8>,
+    /// Overrides the server deadband for this point
+    #[serde(default)]
+    pub deadband: Option<f64>,
+}
+
+fn default_iec104_server_type_id() -> u8 {
+    13
+}
+
+impl Iec104ServerConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.deadband.is_finite() || self.deadband < 0.0 {
+            return Err(format!("Deadband {} must be zero or positive", self.deadband));
+        }
+        let mut addresses = std::collections::HashSet::new();
+        for point in &self.points {
+            if point.ioa == 0 || point.ioa > 0xFF_FFFF {
+                return Err(format!("IOA {} is not between 1 and 16777215", point.ioa));
+            }
+            if !addresses.insert(point.ioa) {
+                return Err(format!("IOA {} is mapped more than once", point.ioa));
+            }
+            if !IEC104_SERVER_TYPE_IDS.contains(&point.type_id) {
+                return Err(format!(
+                    "IOA {}: type {} can't be served; use 1 (single point), 11 (scaled value) or 13 (short float)",
+                    point.ioa, point.type_id
+                ));
+            }
+            if let Some(group) = point.group.filter(|group| !(1..=16).contains(group)) {
+                return Err(format!("IOA {}: interrogation group {} is not between 1 and 16", point.ioa, group));
+            }
+            if let Some(deadband) = point.deadband.filter(|deadband| !deadband.is_finite() || *deadband < 0.0) {
+                return Err(format!("IOA {}: deadband {} must be zero or positive", point.ioa, deadband));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn default_tb_retry_max_attempts() -> u32 {
+    3
+}
+
+fn default_tb_retry_base_delay_ms() -> u64 {
+    500
+}
+
+fn default_tb_requests_per_second() -> f64 {
+    10.0
+}
+
+fn default_tb_sync_concurrency() -> usize {
+    3
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FileExportConfig {
+    /// Created if it doesn't exist; relative paths are relative to the working directory
+    pub directory: String,
+    #[serde(default)]
+    pub rotation: ExportRotation,
+    /// CSV files kept in the directory; the oldest are deleted beyond this, 0 keeps them all
+    #[serde(default = "default_export_max_files")]
+    pub max_files: usize,
+    /// One file per period for all devices, `2024-06-01.csv`, instead of one per device, `2024-06-01-meter-1.csv`
+    #[serde(default)]
+    pub combined: bool,
+}
+
+/// How much time one export file covers, in UTC
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportRotation {
+    #[default]
+    Daily,
+    Hourly,
+}
+
+fn default_export_max_files() -> usize {
+    90
+}
+
+impl FileExportConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.directory.trim().is_empty() {
+            return Err("directory must not be empty".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct MqttConfig {
+    pub host: String,
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+    /// Connect over TLS, verifying the broker's certificate against the system roots
+    #[serde(default)]
+    pub tls: bool,
+    /// Sent only when not empty
+    #[serde(default)]
+    pub username: String,
+    /// Never returned by `GET /api/config`; an empty value on update keeps the stored password
+    #[serde(default)]
+    #[schema(write_only)]
+    pub password: String,
+    #[serde(default = "default_mqtt_client_id")]
+    pub client_id: String,
+    /// Values go to `{base_topic}/{device_id}/{tag_name}`, statuses to `{base_topic}/{device_id}/status`
+    #[serde(default = "default_mqtt_base_topic")]
+    pub base_topic: String,
+    /// 0 (at most once) or 1 (at least once)
+    #[serde(default)]
+    pub qos: u8,
+    /// Messages held while the broker is unreachable; the oldest are dropped beyond this
+    #[serde(default = "default_mqtt_queue_size")]
+    pub queue_size: usize,
+    #[serde(default = "default_mqtt_keep_alive_secs")]
+    pub keep_alive_secs: u16,
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_client_id() -> String {
+    "ava-device-logger".to_string()
+}
+
+fn default_mqtt_base_topic() -> String {
+    "ava".to_string()
+}
+
+fn default_mqtt_queue_size() -> usize {
+    10_000
+}
+
+fn default_mqtt_keep_alive_secs() -> u16 {
+    30
+}
+
+impl MqttConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.host.trim().is_empty() {
+            return Err("MQTT host must not be empty".to_string());
+        }
+        if self.client_id.is_empty() || self.client_id.len() > 23 {
+            return Err(format!("MQTT client id '{}' must be 1 to 23 characters", self.client_id));
+        }
+        if self.base_topic.is_empty() || self.base_topic.contains(['+', '#']) {
+            return Err(format!("MQTT base topic '{}' must not be empty or contain wildcards", self.base_topic));
+        }
+        if self.qos > 1 {
+            return Err(format!("MQTT QoS {} is not supported; use 0 or 1", self.qos));
+        }
+        if self.queue_size == 0 {
+            return Err("MQTT queue size must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl ReportsConfig {
+    pub fn sections_for_plant(&self, plant_name: &str) -> &ReportSections {
+        self.plants.get(plant_name).unwrap_or(&self.sections)
+    }
+}
+
+impl AppConfig {
+    /// Every setting that parses but can't work, checked once at startup so a typo stops the
+    /// service instead of showing up later as odd behavior. `[server]` listen, CORS and TLS
+    /// settings are checked by `ServerConfig::validate`, which holds startup in safe mode.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        let mut positive = |field: &str, value: u64| {
+            if value == 0 {
+                errors.push(format!("{} must be greater than 0", field));
+            }
+        };
+
+        positive("server.port", self.server.port.into());
+        if self.iec104_server.enabled {
+            positive("iec104_server.port", self.iec104_server.port.into());
+        }
+        positive("database.max_log_entries", self.database.max_log_entries.into());
+        positive("database.deadband_heartbeat_minutes", self.database.deadband_heartbeat_minutes);
+        match self.retention.cleanup_interval_minutes {
+            Some(minutes) => positive("retention.cleanup_interval_minutes", minutes),
+            None => positive("database.cleanup_interval_hours", self.database.cleanup_interval_hours.into()),
+        }
+        if let Some(days) = self.retention.max_age_days {
+            positive("retention.max_age_days", days.into());
+        }
+        if let Some(entries) = self.retention.max_entries {
+            positive("retention.max_entries", entries.into());
+        }
+        positive("retention.batch_size", self.retention.batch_size.into());
+        positive("timeouts.query_seconds", self.timeouts.query_seconds);
+        positive("timeouts.export_seconds", self.timeouts.export_seconds);
+        positive("health.database_timeout_ms", self.health.database_timeout_ms);
+        if self.telemetry_forwarding.enabled {
+            positive("telemetry_forwarding.batch_interval_seconds", self.telemetry_forwarding.batch_interval_seconds);
+            positive("telemetry_forwarding.max_batch_size", self.telemetry_forwarding.max_batch_size as u64);
+        }
+
+        let mut device_ids = std::collections::HashSet::new();
+        for (index, device) in self.devices.iter().enumerate() {
+            let field = format!("devices[{}]", index);
+            if device.id.trim().is_empty() {
+                errors.push(format!("{}.id must not be empty", field));
+            } else if !device_ids.insert(device.id.as_str()) {
+                errors.push(format!("{}.id '{}' is already used by another device", field, device.id));
+            }
+            for (name, value) in [("polling_interval_ms", device.polling_interval_ms), ("timeout_ms", device.timeout_ms)] {
+                if value == 0 || value > u32::MAX as u64 {
+                    errors.push(format!("{}.{} must be from 1 to {}", field, name, u32::MAX));
+                }
+            }
+            let protocol = serde_json::to_value(&device.protocol).unwrap_or_default();
+            if let Err(protocol_errors) = ProtocolConfig::from_json(&protocol) {
+                for error in protocol_errors {
+                    errors.push(format!("{}.protocol.{} {}", field, error.field, error.message));
+                }
+            }
+        }
+
+        if self.reports.webhook_url.as_deref().is_some_and(|url| !url.trim().is_empty()) {
+            errors.push("reports.webhook_url is no longer used; add a [[webhooks]] entry with events = [\"daily_report\"] instead".to_string());
+        }
+
+        if let Err(e) = check_database_directory(&self.database.path) {
+            errors.push(format!("database.path '{}': {}", self.database.path, e));
+        }
+
+        if let Some(thingsboard) = &self.thingsboard {
+            match reqwest::Url::parse(thingsboard.base_url.trim()) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") && url.has_host() => {}
+                Ok(_) => errors.push(format!("thingsboard.base_url '{}' must be an http or https URL", thingsboard.base_url)),
+                Err(e) => errors.push(format!("thingsboard.base_url '{}' is not a valid URL: {}", thingsboard.base_url, e)),
+            }
+            if thingsboard.username.trim().is_empty() {
+                errors.push("thingsboard.username must not be empty".to_string());
+            }
+            if thingsboard.retry_max_attempts == 0 {
+                errors.push("thingsboard.retry_max_attempts must be greater than 0".to_string());
+            }
+            if thingsboard.sync_concurrency == 0 {
+                errors.push("thingsboard.sync_concurrency must be greater than 0".to_string());
+            }
+        }
+
+        errors
+    }
+}
+
+/// The database's directory must exist and take new files, or SQLite fails on first write
+fn check_database_directory(path: &str) -> Result<(), String> {
+    if path.trim().is_empty() {
+        return Err("must not be empty".to_string());
+    }
+    let path = std::path::Path::new(path);
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => std::path::Path::new("."),
+    };
+    if !directory.is_dir() {
+        return Err(format!("directory {} does not exist", directory.display()));
+    }
+
+    let probe = directory.join(format!(".write-check-{}", uuid::Uuid::new_v4()));
+    match std::fs::OpenOptions::new().write(true).create_new(true).open(&probe) {
+        Ok(_) => {
+            std::fs::remove_file(&probe).ok();
+        }
+        Err(e) => return Err(format!("directory {} is not writable: {}", directory.display(), e)),
+    }
+    if path.is_file() {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(path)
+            .map_err(|e| format!("file is not writable: {}", e))?;
+    }
+    Ok(())
 }
 
 impl Default for AppConfig {
@@ -114,22 +1531,34 @@ impl Default for AppConfig {
             server: ServerConfig {
                 port: 8080,
                 host: "0.0.0.0".to_string(),
+                auto_start: default_auto_start(),
+                auto_start_stagger_ms: default_auto_start_stagger_ms(),
+                tag_update_interval_ms: default_tag_update_interval_ms(),
+                max_upload_mb: default_max_upload_mb(),
+                shutdown_timeout_secs: default_shutdown_timeout_secs(),
+                bind_address: default_bind_address(),
+                cors: CorsConfig::default(),
+                tls: None,
             },
             database: DatabaseConfig {
                 path: "data.db".to_string(),
                 max_log_entries: 1000000,
                 cleanup_interval_hours: 24,
+                deadband_heartbeat_minutes: default_deadband_heartbeat_minutes(),
             },
             devices: vec![
                 DeviceConfig {
                     id: "device1".to_string(),
                     name: "Example Modbus TCP Device".to_string(),
                     enabled: false,
-                    protocol: ProtocolConfig::ModbusTcp {
+                    protocol: ProtocolConfig::ModbusTcp(ModbusTcpConfig {
                         host: "192.168.1.100".to_string(),
                         port: 502,
                         slave_id: 1,
-                    },
+                        max_block_gap: 0,
+                        request_delay_ms: 0,
+                        serial_source: None,
+                    }),
                     polling_interval_ms: 1000,
                     timeout_ms: 5000,
                     retry_count: 3,
@@ -145,8 +1574,11 @@ impl Default for AppConfig {
                                 unit: Some("°C".to_string()),
                             }),
                             description: Some("Temperature sensor".to_string()),
+                            byte_order: None,
+                            register_type: None,
                         },
                     ],
+                    strict_types: false,
                 },
             ],
             logging: LoggingConfig {
@@ -154,41 +1586,163 @@ impl Default for AppConfig {
                 file_path: Some("app.log".to_string()),
                 max_file_size_mb: 10,
                 max_files: 5,
+                log_payloads: false,
             },
+            reports: ReportsConfig::default(),
+            notifications: NotificationsConfig::default(),
+            idempotency: IdempotencyConfig::default(),
+            tb_cache: TbCacheConfig::default(),
+            timeouts: TimeoutsConfig::default(),
+            thingsboard: None,
+            telemetry_forwarding: TelemetryForwardingConfig::default(),
+            retention: RetentionConfig::default(),
+            iec104_server: Iec104ServerConfig::default(),
+            metrics: MetricsConfig::default(),
+            health: HealthConfig::default(),
+            auth: AuthConfig::default(),
+            webhooks: Vec::new(),
+            mqtt: None,
+            file_export: None,
         }
     }
 }
 
 pub async fn load_config() -> Result<AppConfig> {
-    let config_path = "config.toml";
-    
+    load_config_from(Path::new("config.toml")).await
+}
+
+/// Load the config file at `config_path`, writing the defaults there if it doesn't exist
+pub async fn load_config_from(config_path: &Path) -> Result<AppConfig> {
     match tokio::fs::read_to_string(config_path).await {
         Ok(content) => {
             match toml::from_str(&content) {
                 Ok(config) => {
-                    info!("Configuration loaded from {}", config_path);
+                    info!("Configuration loaded from {}", config_path.display());
                     Ok(config)
                 },
-                Err(e) => {
-                    warn!("Failed to parse config file: {}. Using default configuration.", e);
-                    let default_config = AppConfig::default();
-                    save_config(&default_config).await?;
-                    Ok(default_config)
-                }
+                // Leave the broken file in place so it can be repaired from safe mode
+                Err(e) => Err(anyhow::anyhow!("Failed to parse {}: {}", config_path.display(), e)),
             }
         },
         Err(_) => {
             info!("Config file not found. Creating default configuration.");
             let default_config = AppConfig::default();
-            save_config(&default_config).await?;
+            save_config_to(config_path, &default_config).await?;
             Ok(default_config)
         }
     }
 }
 
 pub async fn save_config(config: &AppConfig) -> Result<()> {
+    save_config_to(Path::new("config.toml"), config).await
+}
+
+pub async fn save_config_to(config_path: &Path, config: &AppConfig) -> Result<()> {
     let config_content = toml::to_string_pretty(config)?;
-    tokio::fs::write("config.toml", config_content).await?;
-    info!("Configuration saved to config.toml");
+    tokio::fs::write(config_path, config_content).await?;
+    info!("Configuration saved to {}", config_path.display());
     Ok(())
 }
+
+/// Outcome of moving devices defined in config.toml into the database
+#[derive(Debug, Default)]
+pub struct DeviceMigrationSummary {
+    pub migrated: Vec<String>,
+    /// Already present in the database; the database definition wins
+    pub skipped: Vec<String>,
+    pub failed: Vec<(String, String)>,
+}
+
+impl DeviceConfig {
+    /// Database representation of a device defined in the config file
+    pub fn to_device_instance(&self) -> Result<DeviceInstance> {
+        let now = Utc::now();
+        Ok(DeviceInstance {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            serial_no: None,
+            model_id: None,
+            enabled: self.enabled,
+            polling_interval_ms: u32::try_from(self.polling_interval_ms)?,
+            timeout_ms: u32::try_from(self.timeout_ms)?,
+            retry_count: self.retry_count,
+            protocol_config: serde_json::to_string(&self.protocol)?,
+            tb_device_id: None,
+            tb_group_id: None,
+            created_at: now,
+            updated_at: now,
+            strict_types: self.strict_types,
+        })
+    }
+
+    pub fn to_device_tags(&self) -> Result<Vec<DeviceTag>> {
+        self.tags
+            .iter()
+            .map(|tag| {
+                let data_type = serde_json::to_value(&tag.data_type)?
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string();
+                Ok(DeviceTag {
+                    id: None,
+                    device_id: self.id.clone(),
+                    name: tag.name.clone(),
+                    address: tag.address,
+                    size: tag.size,
+                    data_type,
+                    description: tag.description.clone(),
+                    scaling_multiplier: tag.scaling.as_ref().map_or(1.0, |s| s.multiplier),
+                    scaling_offset: tag.scaling.as_ref().map_or(0.0, |s| s.offset),
+                    unit: tag.scaling.as_ref().and_then(|s| s.unit.clone()),
+                    read_only: true,
+                    enabled: true,
+                    schedule_group_id: None,
+                    agg_to_field: None,
+                    write_policy: TagWritePolicy::Disabled,
+                    byte_order: tag.byte_order,
+                    register_type: tag.register_type,
+                    deadband_absolute: None,
+                    deadband_percent: None,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Copy config-file devices that have no database counterpart into the database
+pub async fn migrate_config_devices(config: &AppConfig, database: &Database) -> Result<DeviceMigrationSummary> {
+    let mut summary = DeviceMigrationSummary::default();
+
+    for device in &config.devices {
+        if database.get_device(&device.id).await?.is_some() {
+            summary.skipped.push(device.id.clone());
+            continue;
+        }
+
+        let result = async {
+            database.create_device(&device.to_device_instance()?).await?;
+            database.create_device_tags(&device.id, &device.to_device_tags()?).await
+        }
+        .await;
+
+        match result {
+            Ok(()) => summary.migrated.push(device.id.clone()),
+            Err(e) => {
+                warn!("Failed to migrate config device {} to the database: {}", device.id, e);
+                summary.failed.push((device.id.clone(), e.to_string()));
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Back up config.toml, then rewrite it with the devices section emptied
+pub async fn archive_config_devices(config: &mut AppConfig) -> Result<String> {
+    let backup_path = format!("config.toml.{}.bak", Utc::now().format("%Y%m%d%H%M%S"));
+    tokio::fs::copy("config.toml", &backup_path).await?;
+
+    config.devices.clear();
+    save_config(config).await?;
+    Ok(backup_path)
+}
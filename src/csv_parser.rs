@@ -1,10 +1,46 @@
-use crate::database::{CsvModbusTcpTagRecord, CreateModbusTcpTagRegister};
+use crate::database::{CsvModbusTcpTagRecord, CreateModbusTcpTagRegister, ModbusTcpTagRegister};
 use anyhow::{Result, anyhow};
 use csv::ReaderBuilder;
-use std::io::Read;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
 
+use crate::config::RegisterType;
+use crate::modbus::{find_tag_conflicts, TagFootprint};
+
+#[derive(Default)]
 pub struct ModbusTcpCsvParserService;
 
+/// Columns of a register map CSV, as named by `CsvModbusTcpTagRecord`
+const CSV_HEADERS: [&str; 14] = [
+    "Device Brand", "Device Model", "AVA Type", "MPPT", "INPUT", "Data Label", "Address", "Size",
+    "Modbus Type", "Divider", "Register Type", "Deadband", "Deadband %", "Agg To Field",
+];
+
+/// A problem with one row of an uploaded register map
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvRowError {
+    /// Line of the file, the header being row 1
+    pub row: usize,
+    pub column: Option<String>,
+    pub reason: String,
+}
+
+impl CsvRowError {
+    fn new(row: usize, column: Option<&str>, reason: impl Into<String>) -> Self {
+        Self { row, column: column.map(str::to_string), reason: reason.into() }
+    }
+}
+
+impl std::fmt::Display for CsvRowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Row {}: {}", self.row, self.reason)?;
+        if let Some(column) = &self.column {
+            write!(f, " (column '{}')", column)?;
+        }
+        Ok(())
+    }
+}
+
 impl ModbusTcpCsvParserService {
     pub fn new() -> Self {
         ModbusTcpCsvParserService
@@ -48,24 +84,43 @@ impl ModbusTcpCsvParserService {
     //     Ok(tag_registers)
     // }
 
-    pub fn parse_csv_with_device_model_and_manufacturer<R: Read>(&self, reader: R, device_model_name: &str, manufacturer: &str) -> Result<Vec<CreateModbusTcpTagRegister>> {
-        println!("DEBUG: parse_csv_with_device_model_and_manufacturer called with device_model_name: {} and manufacturer: {}", device_model_name, manufacturer);
-        
+    /// Every row of a register map, numbered as in the file (the header is row 1), with the rows
+    /// that can't be read reported rather than stopping the parse
+    pub fn parse_csv_with_device_model_and_manufacturer<R: Read>(&self, reader: R, device_model_name: &str, manufacturer: &str) -> (Vec<(usize, CreateModbusTcpTagRegister)>, Vec<CsvRowError>) {
         let mut csv_reader = ReaderBuilder::new()
             .has_headers(true)
             .from_reader(reader);
+        let headers = csv_reader.headers().cloned().unwrap_or_default();
 
         let mut tag_registers = Vec::new();
-
-        for (row_index, result) in csv_reader.deserialize().enumerate() {
-            let record: CsvModbusTcpTagRecord = result
-                .map_err(|e| anyhow!("CSV parsing error at row {}: {}", row_index + 2, e))?;
-            
-            let tag_register = self.convert_csv_record_to_create_tag_register_with_device_model_and_manufacturer(record, device_model_name, manufacturer)?;
-            tag_registers.push(tag_register);
+        let mut errors = Vec::new();
+        for (row_index, result) in csv_reader.records().enumerate() {
+            let row = row_index + 2;
+            let fields = match result {
+                Ok(fields) => fields,
+                Err(e) => {
+                    errors.push(CsvRowError::new(row, None, format!("CSV parsing error: {}", e)));
+                    continue;
+                }
+            };
+            let converted = match fields.deserialize::<CsvModbusTcpTagRecord>(Some(&headers)) {
+                Ok(record) => self.convert_csv_record_to_create_tag_register_with_device_model_and_manufacturer(record, device_model_name, manufacturer, row),
+                Err(e) => Err(match e.kind() {
+                    csv::ErrorKind::Deserialize { err, .. } => {
+                        let field = err.field().map(|field| field as usize);
+                        let value = field.and_then(|field| fields.get(field)).unwrap_or_default();
+                        CsvRowError::new(row, field.and_then(|field| headers.get(field)), format!("Failed to parse '{}': {}", value, err.kind()))
+                    }
+                    _ => CsvRowError::new(row, None, format!("CSV parsing error: {}", e)),
+                }),
+            };
+            match converted {
+                Ok(tag_register) => tag_registers.push((row, tag_register)),
+                Err(e) => errors.push(e),
+            }
         }
 
-        Ok(tag_registers)
+        (tag_registers, errors)
     }
 
     // NOTE: This helper method is unused - only convert_csv_record_to_create_tag_register_with_device_model_and_manufacturer is used
@@ -116,14 +171,14 @@ impl ModbusTcpCsvParserService {
         value: &str, 
         field_name: &str, 
         row_number: usize
-    ) -> Result<Option<i32>> {
+    ) -> std::result::Result<Option<i32>, CsvRowError> {
         let trimmed = value.trim();
         if trimmed.is_empty() {
             Ok(None) // Empty means inverter-level register
         } else {
             trimmed.parse::<i32>()
                 .map(Some)
-                .map_err(|e| anyhow!("Row {}: Failed to parse {} '{}': {}", row_number, field_name, trimmed, e))
+                .map_err(|e| CsvRowError::new(row_number, Some(field_name), format!("Failed to parse {} '{}': {}", field_name, trimmed, e)))
         }
     }
 
@@ -149,91 +204,79 @@ impl ModbusTcpCsvParserService {
         Ok(())
     }
 
-    pub fn validate_record_data(&self, records: &[CreateModbusTcpTagRegister]) -> Result<()> {
-        for (index, record) in records.iter().enumerate() {
-            let row_number = index + 2; // CSV row number (header is row 1)
+    /// Every problem with the values of each row, rather than just the first
+    pub fn validate_records(&self, rows: &[(usize, CreateModbusTcpTagRegister)]) -> Vec<CsvRowError> {
+        let mut errors = Vec::new();
+        for (row_number, record) in rows {
+            let row_number = *row_number;
+            let mut fail = |column: &str, reason: String| errors.push(CsvRowError::new(row_number, Some(column), reason));
 
             // Validate required fields
             if record.device_brand.is_empty() {
-                return Err(anyhow!("Row {}: Device Brand cannot be empty", row_number));
+                fail("Device Brand", "Device Brand cannot be empty".to_string());
             }
             if record.device_model.is_empty() {
-                return Err(anyhow!("Row {}: Device Model cannot be empty", row_number));
+                fail("Device Model", "Device Model cannot be empty".to_string());
             }
             if record.ava_type.is_empty() {
-                return Err(anyhow!("Row {}: AVA Type cannot be empty", row_number));
+                fail("AVA Type", "AVA Type cannot be empty".to_string());
             }
             if record.data_label.is_empty() {
-                return Err(anyhow!("Row {}: Data Label cannot be empty", row_number));
+                fail("Data Label", "Data Label cannot be empty".to_string());
             }
 
             // Validate AVA Type
             let valid_ava_types = ["Inverter", "String", "MPPT", "Battery", "Meter", "Weather Station", "PowerMeter", "Plant"];
-            if !valid_ava_types.contains(&record.ava_type.as_str()) {
-                return Err(anyhow!("Row {}: Invalid AVA Type '{}'. Valid types: {:?}", 
-                    row_number, record.ava_type, valid_ava_types));
+            if !record.ava_type.is_empty() && !valid_ava_types.contains(&record.ava_type.as_str()) {
+                fail("AVA Type", format!("Invalid AVA Type '{}'. Valid types: {:?}", record.ava_type, valid_ava_types));
             }
 
             // Validate modbus type
-            let valid_modbus_types = ["U16", "I16", "U32", "I32", "FLOAT", "DOUBLE", "F32"];
+            let valid_modbus_types = ["U16", "I16", "U32", "I32", "U64", "I64", "FLOAT", "F32", "DOUBLE", "F64"];
             if !valid_modbus_types.contains(&record.modbus_type.as_str()) {
-                return Err(anyhow!("Row {}: Invalid Modbus Type '{}'. Valid types: {:?}", 
-                    row_number, record.modbus_type, valid_modbus_types));
+                fail("Modbus Type", format!("Invalid Modbus Type '{}'. Valid types: {:?}", record.modbus_type, valid_modbus_types));
             }
 
             // Validate register type
             let valid_register_types = ["input", "holding", "coil", "discrete"];
             if !valid_register_types.contains(&record.register_type.as_str()) {
-                return Err(anyhow!("Row {}: Invalid Register Type '{}'. Valid types: {:?}", 
-                    row_number, record.register_type, valid_register_types));
+                fail("Register Type", format!("Invalid Register Type '{}'. Valid types: {:?}", record.register_type, valid_register_types));
             }
 
             // Validate size based on modbus type
             let expected_size = match record.modbus_type.as_str() {
-                "U16" | "I16" => 1,
-                "U32" | "I32" | "FLOAT" => 2,
-                "DOUBLE" => 4,
-                _ => continue,
+                "U16" | "I16" => Some(1),
+                "U32" | "I32" | "FLOAT" | "F32" => Some(2),
+                "U64" | "I64" | "DOUBLE" | "F64" => Some(4),
+                _ => None,
             };
-            if record.size != expected_size {
-                return Err(anyhow!("Row {}: Size {} doesn't match Modbus Type {}. Expected size: {}", 
-                    row_number, record.size, record.modbus_type, expected_size));
+            if let Some(expected_size) = expected_size.filter(|expected_size| record.size != *expected_size) {
+                fail("Size", format!("Size {} doesn't match Modbus Type {}. Expected size: {}", record.size, record.modbus_type, expected_size));
             }
 
             // Validate address range
             if record.address < 0 || record.address > 65535 {
-                return Err(anyhow!("Row {}: Address {} is out of valid range (0-65535)", 
-                    row_number, record.address));
+                fail("Address", format!("Address {} is out of valid range (0-65535)", record.address));
             }
 
             // Validate MPPT and INPUT logic
             match record.ava_type.as_str() {
-                "Inverter" => {
-                    // Inverter-level registers should not have MPPT or INPUT
-                    if record.mppt.is_some() || record.input.is_some() {
-                        return Err(anyhow!("Row {}: Inverter-level registers should not have MPPT or INPUT values", 
-                            row_number));
-                    }
+                // Inverter-level registers should not have MPPT or INPUT
+                "Inverter" if record.mppt.is_some() || record.input.is_some() => {
+                    fail(if record.mppt.is_some() { "MPPT" } else { "INPUT" }, "Inverter-level registers should not have MPPT or INPUT values".to_string());
                 }
                 "String" => {
                     // String-level registers should have both MPPT and INPUT
                     if record.mppt.is_none() || record.input.is_none() {
-                        return Err(anyhow!("Row {}: String-level registers must have both MPPT and INPUT values", 
-                            row_number));
+                        fail(if record.mppt.is_none() { "MPPT" } else { "INPUT" }, "String-level registers must have both MPPT and INPUT values".to_string());
                     }
                     
                     // Validate MPPT and INPUT ranges
-                    if let Some(mppt) = record.mppt {
-                        if mppt < 1 || mppt > 20 {
-                            return Err(anyhow!("Row {}: MPPT {} is out of valid range (1-20)", 
-                                row_number, mppt));
-                        }
+                    if let Some(mppt) = record.mppt.filter(|mppt| !(1..=20).contains(mppt)) {
+                        fail("MPPT", format!("MPPT {} is out of valid range (1-20)", mppt));
                     }
-                    if let Some(input) = record.input {
-                        if input < 1 || input > 50 {
-                            return Err(anyhow!("Row {}: INPUT {} is out of valid range (1-50)", 
-                                row_number, input));
-                        }
+                    if let Some(input) = record.input.filter(|input| !(1..=50).contains(input)) {
+                        fail("INPUT", format!("INPUT {} is out of valid range (1-50)", input));
                     }
                 }
                 _ => {
@@ -243,11 +286,48 @@ impl ModbusTcpCsvParserService {
 
             // Validate divider
             if record.divider <= 0.0 {
-                return Err(anyhow!("Row {}: Divider must be greater than 0", row_number));
+                fail("Divider", "Divider must be greater than 0".to_string());
             }
         }
 
-        Ok(())
+        errors
+    }
+
+    /// Duplicate labels and overlapping or out of range registers, reported on the later row.
+    /// Each inverter, MPPT or string becomes a device of its own, so registers shared between
+    /// them (an MPPT voltage repeated on its strings) are fine.
+    pub fn find_conflicts(&self, rows: &[(usize, CreateModbusTcpTagRegister)]) -> Vec<CsvRowError> {
+        let mut entities = BTreeMap::new();
+        for (index, (_, record)) in rows.iter().enumerate() {
+            entities.entry((record.ava_type.as_str(), record.mppt, record.input)).or_insert_with(Vec::new).push(index);
+        }
+
+        let mut conflicts = Vec::new();
+        for indexes in entities.values() {
+            let footprints: Vec<TagFootprint> = indexes
+                .iter()
+                .map(|&index| {
+                    let (_, record) = &rows[index];
+                    let register_type = match record.register_type.as_str() {
+                        "input" => RegisterType::Input,
+                        "coil" => RegisterType::Coil,
+                        "discrete" => RegisterType::DiscreteInput,
+                        _ => RegisterType::Holding,
+                    };
+                    TagFootprint {
+                        name: record.data_label.clone(),
+                        register_type,
+                        address: record.address.clamp(0, u16::MAX as i32) as u16,
+                        count: record.size.clamp(1, u16::MAX as i32) as u16,
+                    }
+                })
+                .collect();
+            for conflict in find_tag_conflicts(&footprints) {
+                conflicts.push((indexes[conflict.tag], conflict.message));
+            }
+        }
+        conflicts.sort_by_key(|(index, _)| *index);
+        conflicts.into_iter().map(|(index, message)| CsvRowError::new(rows[index].0, None, message)).collect()
     }
 
     fn convert_csv_record_to_create_tag_register_with_device_model_and_manufacturer(
@@ -255,24 +335,58 @@ impl ModbusTcpCsvParserService {
         record: CsvModbusTcpTagRecord,
         device_model_name: &str,
         manufacturer: &str,
-    ) -> Result<CreateModbusTcpTagRegister> {
+        row_number: usize,
+    ) -> std::result::Result<CreateModbusTcpTagRegister, CsvRowError> {
         println!("DEBUG: Converting record with device_model_name: {} and manufacturer: {} (CSV had device_brand: {} and device_model: {})", 
                  device_model_name, manufacturer, record.device_brand, record.device_model);
         Ok(CreateModbusTcpTagRegister {
             device_brand: manufacturer.to_string(), // Use manufacturer instead of CSV device_brand
             device_model: device_model_name.to_string(), // Use device_model_name instead of CSV device_model
             ava_type: record.ava_type.trim().to_string(),
-            mppt: self.parse_optional_int_from_string(&record.mppt, "MPPT", 0)?,
-            input: self.parse_optional_int_from_string(&record.input, "INPUT", 0)?,
+            mppt: self.parse_optional_int_from_string(&record.mppt, "MPPT", row_number)?,
+            input: self.parse_optional_int_from_string(&record.input, "INPUT", row_number)?,
             data_label: record.data_label.trim().to_string(),
             address: record.address,
             size: record.size,
             modbus_type: record.modbus_type.trim().to_string(),
             divider: record.divider,
             register_type: record.register_type.trim().to_string(),
+            deadband_absolute: record.deadband_absolute,
+            deadband_percent: record.deadband_percent,
+            agg_to_field: record.agg_to_field.map(|field| field.trim().to_string()).filter(|field| !field.is_empty()),
         })
     }
 
+    /// Write stored register map rows in the upload format, optional columns included, so
+    /// uploading the file again gives the same map
+    pub fn write_csv<W: Write>(&self, writer: W, tag_registers: &[ModbusTcpTagRegister]) -> Result<()> {
+        let mut csv_writer = csv::Writer::from_writer(writer);
+        if tag_registers.is_empty() {
+            // The header is otherwise taken from the first row
+            csv_writer.write_record(CSV_HEADERS)?;
+        }
+        for tag_register in tag_registers {
+            csv_writer.serialize(CsvModbusTcpTagRecord {
+                device_brand: tag_register.device_brand.clone(),
+                device_model: tag_register.device_model.clone(),
+                ava_type: tag_register.ava_type.clone(),
+                mppt: tag_register.mppt.map(|mppt| mppt.to_string()).unwrap_or_default(),
+                input: tag_register.input.map(|input| input.to_string()).unwrap_or_default(),
+                data_label: tag_register.data_label.clone(),
+                address: tag_register.address,
+                size: tag_register.size,
+                modbus_type: tag_register.modbus_type.clone(),
+                divider: tag_register.divider,
+                register_type: tag_register.register_type.clone(),
+                deadband_absolute: tag_register.deadband_absolute,
+                deadband_percent: tag_register.deadband_percent,
+                agg_to_field: tag_register.agg_to_field.clone(),
+            })?;
+        }
+        csv_writer.flush()?;
+        Ok(())
+    }
+
     pub fn get_summary(&self, records: &[CreateModbusTcpTagRegister]) -> String {
         let total_count = records.len();
         let inverter_count = records.iter().filter(|r| r.ava_type == "Inverter").count();
@@ -286,3 +400,17 @@ impl ModbusTcpCsvParserService {
         )
     }
 }
+
+/// Uploaded CSV bytes as text. A UTF-8 byte order mark is dropped, and files that aren't valid
+/// UTF-8 are read as Latin-1, as spreadsheets on Windows often save them. Anything holding NUL
+/// or other control bytes is refused as binary rather than parsed into garbage.
+pub fn decode_csv_text(bytes: &[u8]) -> Result<String> {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    if let Some(position) = bytes.iter().position(|byte| byte.is_ascii_control() && !matches!(byte, b'\t' | b'\n' | b'\r')) {
+        return Err(anyhow!("looks like a binary file (control byte 0x{:02x} at offset {}), not CSV text", bytes[position], position));
+    }
+    Ok(match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes.iter().map(|&byte| byte as char).collect(),
+    })
+}
@@ -1,12 +1,21 @@
-use rusqlite::{Connection, params};
+use rusqlite::{Connection, OpenFlags, params};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use utoipa::ToSchema;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+use crate::passwords::{hash_password, verify_password, PasswordCheck};
+use tokio::sync::{Mutex, Semaphore, SemaphorePermit};
 use anyhow::Result;
-use tracing::{info, warn};
+use tracing::{error, info, warn};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::config::{ByteOrder, DataType, FieldError, ProtocolConfig, RegisterRead, RegisterType};
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct LogEntry {
     pub id: Option<i64>,
     pub device_id: String,
@@ -17,7 +26,65 @@ pub struct LogEntry {
     pub unit: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl LogEntry {
+    /// Whether the entry is stored; muted tags are only reported
+    pub fn is_logged(&self) -> bool {
+        self.quality != "muted"
+    }
+
+    /// Whether the entry holds a value read from the device; failed reads are stored to show
+    /// the gap, but their value means nothing
+    pub fn has_value(&self) -> bool {
+        self.is_logged() && !Quality::parse(&self.quality).is_some_and(|quality| quality.is_failure())
+    }
+}
+
+/// Quality of a value as the protocol layer reports it, stored by name in the `quality`
+/// column. Muted tags, virtual tags and type mismatches have their own labels besides these.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum Quality {
+    Good,
+    /// The device flagged the value as questionable, e.g. substituted or overflowed
+    Uncertain,
+    /// The device refused the read with an exception response, or marked the value invalid
+    Bad,
+    /// The device didn't answer in time
+    CommFail,
+    /// The last known value, sent again after its device stopped answering
+    Stale,
+}
+
+impl Quality {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Quality::Good => "Good",
+            Quality::Uncertain => "Uncertain",
+            Quality::Bad => "Bad",
+            Quality::CommFail => "CommFail",
+            Quality::Stale => "Stale",
+        }
+    }
+
+    /// The quality a stored label stands for, if it is one of these
+    pub fn parse(label: &str) -> Option<Self> {
+        [Quality::Good, Quality::Uncertain, Quality::Bad, Quality::CommFail, Quality::Stale]
+            .into_iter()
+            .find(|quality| quality.as_str() == label)
+    }
+
+    /// Failed reads, left out of aggregates unless asked for
+    pub fn is_failure(&self) -> bool {
+        matches!(self, Quality::Bad | Quality::CommFail)
+    }
+}
+
+impl std::fmt::Display for Quality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct DeviceStatus {
     pub device_id: String,
     pub status: String,
@@ -26,7 +93,7 @@ pub struct DeviceStatus {
     pub connection_count: i64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct DeviceModel {
     pub id: String,
     pub name: String,
@@ -37,12 +104,15 @@ pub struct DeviceModel {
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct TagTemplate {
     pub id: Option<i64>,
     pub model_id: String,
     pub name: String,
     pub address: u16,
+    /// Registers the tag spans; 1 for templates stored before sizes were recorded
+    #[serde(default = "default_template_size")]
+    pub size: i32,
     pub data_type: String,
     pub description: Option<String>,
     pub scaling_multiplier: f64,
@@ -51,7 +121,155 @@ pub struct TagTemplate {
     pub read_only: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+fn default_template_size() -> i32 {
+    1
+}
+
+/// Layout version written by `export_config_bundle`; bump it and translate older
+/// bundles in `ConfigBundle::from_json` when the layout changes
+pub const CONFIG_BUNDLE_SCHEMA_VERSION: u32 = 1;
+
+/// Everything needed to rebuild a gateway's configuration, without logged values
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct ConfigBundle {
+    pub schema_version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub device_models: Vec<DeviceModel>,
+    pub tag_templates: Vec<TagTemplate>,
+    pub devices: Vec<DeviceInstance>,
+    pub device_tags: Vec<DeviceTag>,
+    pub schedule_groups: Vec<ScheduleGroup>,
+    pub plant_configuration: Option<PlantConfiguration>,
+}
+
+impl ConfigBundle {
+    /// Read a bundle of this or an earlier schema version
+    pub fn from_json(value: serde_json::Value) -> std::result::Result<Self, String> {
+        match value.get("schema_version").and_then(|version| version.as_u64()) {
+            None => Err("schema_version is missing".to_string()),
+            Some(version) if version == 0 || version > CONFIG_BUNDLE_SCHEMA_VERSION as u64 => Err(format!(
+                "schema_version {} is not supported; this gateway reads versions 1 to {}",
+                version, CONFIG_BUNDLE_SCHEMA_VERSION
+            )),
+            Some(_) => serde_json::from_value(value).map_err(|e| format!("Invalid backup: {}", e)),
+        }
+    }
+}
+
+/// How a restore treats configuration already on the gateway
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RestoreMode {
+    /// Add new entities and update existing ones; anything not in the bundle is kept
+    #[default]
+    Merge,
+    /// Delete the current configuration first, so the gateway ends up matching the bundle
+    Replace,
+}
+
+/// What a restore did with the entities of one table
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct RestoreCounts {
+    pub created: usize,
+    pub updated: usize,
+    /// Already on the gateway exactly as in the bundle
+    pub skipped: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct RestoreReport {
+    pub mode: RestoreMode,
+    pub device_models: RestoreCounts,
+    pub tag_templates: RestoreCounts,
+    pub devices: RestoreCounts,
+    pub device_tags: RestoreCounts,
+    pub schedule_groups: RestoreCounts,
+    pub plant_configuration: RestoreCounts,
+    /// Running devices restarted to pick up the restored configuration
+    #[serde(default)]
+    pub restarted_devices: Vec<String>,
+}
+
+impl RestoreCounts {
+    /// Count an incoming entity and tell whether it has to be written
+    fn record<T: Serialize>(&mut self, existing: Option<&T>, incoming: &T) -> bool {
+        match existing {
+            None => self.created += 1,
+            Some(existing) if serde_json::to_value(existing).ok() == serde_json::to_value(incoming).ok() => {
+                self.skipped += 1;
+                return false;
+            }
+            Some(_) => self.updated += 1,
+        }
+        true
+    }
+}
+
+/// A device built from a device model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ModelDependentDevice {
+    pub id: String,
+    pub name: String,
+}
+
+/// Outcome of deleting a device model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct DeviceModelDeletion {
+    /// False when devices still use the model and deletion was not forced
+    pub deleted: bool,
+    /// Devices using the model; deleted along with it when forced
+    pub dependent_devices: Vec<ModelDependentDevice>,
+}
+
+/// Which template a device tag was copied from, and the template address and
+/// scaling it last matched. A tag whose values no longer match these was
+/// edited on the device and is not overwritten by a resync.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TagTemplateLink {
+    pub device_id: String,
+    pub tag_name: String,
+    pub template_id: i64,
+    /// Added to the template address when the device was created
+    pub address_offset: i32,
+    pub synced_address: u16,
+    pub synced_scaling_multiplier: f64,
+    pub synced_scaling_offset: f64,
+}
+
+/// One field where a device tag differs from its template
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TemplateFieldChange {
+    pub field: String,
+    pub device_value: serde_json::Value,
+    pub template_value: serde_json::Value,
+}
+
+/// A device tag that a resync updated, or would update but for device-level edits
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TemplateTagResync {
+    pub device_id: String,
+    pub tag_name: String,
+    pub template_id: i64,
+    pub changes: Vec<TemplateFieldChange>,
+    /// Fields edited on the device that the template also changed; empty for updated tags
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub customized: Vec<String>,
+}
+
+/// Outcome of propagating a model's templates to its devices
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct TemplateResync {
+    pub dry_run: bool,
+    pub updated: Vec<TemplateTagResync>,
+    /// Left as they are; resolve by editing the tag or the template
+    pub conflicts: Vec<TemplateTagResync>,
+    pub unchanged: usize,
+    /// Devices restarted to pick up the updated tags
+    #[serde(default)]
+    pub restarted_devices: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct DeviceInstance {
     pub id: String,
     pub name: String,
@@ -66,9 +284,20 @@ pub struct DeviceInstance {
     pub tb_group_id: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// Drop samples whose raw registers don't fit the declared data type instead of logging them
+    #[serde(default)]
+    pub strict_types: bool,
+}
+
+impl DeviceInstance {
+    /// The stored protocol config, validated when the device was saved
+    pub fn protocol(&self) -> Result<ProtocolConfig> {
+        serde_json::from_str(&self.protocol_config)
+            .map_err(|e| anyhow::anyhow!("Failed to parse protocol config for device {}: {}", self.id, e))
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
 pub struct DeviceTag {
     pub id: Option<i64>,
     pub device_id: String,
@@ -84,9 +313,603 @@ pub struct DeviceTag {
     pub enabled: bool,
     pub schedule_group_id: Option<String>,
     pub agg_to_field: Option<String>,
+    /// Who may write this tag; `read_only` still blocks writes regardless of policy
+    #[serde(default)]
+    pub write_policy: TagWritePolicy,
+    /// Layout of multi-register values; the data type's default when unset
+    #[serde(default)]
+    pub byte_order: Option<ByteOrder>,
+    /// A new value is only logged once it moves by more than this from the last logged value
+    #[serde(default)]
+    pub deadband_absolute: Option<f64>,
+    /// Same as `deadband_absolute`, as a percentage of the last logged value
+    #[serde(default)]
+    pub deadband_percent: Option<f64>,
+    /// Table a numeric tag is read from, `holding` or `input`; holding registers when unset
+    #[serde(default)]
+    pub register_type: Option<RegisterType>,
+}
+
+/// Per-tag write permission, separate from the protocol-level `read_only` flag
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TagWritePolicy {
+    #[default]
+    Disabled,
+    AdminOnly,
+    AnyAuthenticated,
+}
+
+impl TagWritePolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TagWritePolicy::Disabled => "disabled",
+            TagWritePolicy::AdminOnly => "admin_only",
+            TagWritePolicy::AnyAuthenticated => "any_authenticated",
+        }
+    }
+
+    /// Unknown values fall back to `Disabled` so a bad row never grants write access
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "admin_only" => TagWritePolicy::AdminOnly,
+            "any_authenticated" => TagWritePolicy::AnyAuthenticated,
+            _ => TagWritePolicy::Disabled,
+        }
+    }
+}
+
+impl DeviceTag {
+    pub fn has_deadband(&self) -> bool {
+        self.deadband_absolute.is_some() || self.deadband_percent.is_some()
+    }
+
+    /// Whether `value` is within every deadband of the tag around the last logged value, so it
+    /// needn't be logged. A tag without a deadband logs every value.
+    pub fn within_deadband(&self, last: f64, value: f64) -> bool {
+        if !self.has_deadband() {
+            return false;
+        }
+        let change = (value - last).abs();
+        self.deadband_absolute.is_none_or(|deadband| change <= deadband)
+            && self.deadband_percent.is_none_or(|percent| change <= last.abs() * percent / 100.0)
+    }
+
+    /// Check whether a user with `role` may write this tag, returning the rejection reason if not
+    pub fn check_write(&self, role: &str) -> std::result::Result<(), String> {
+        if self.read_only {
+            return Err(format!("Tag '{}' is read-only", self.name));
+        }
+        match self.write_policy {
+            TagWritePolicy::Disabled => Err(format!("Writes are disabled for tag '{}'", self.name)),
+            TagWritePolicy::AdminOnly if role != "admin" => {
+                Err(format!("Tag '{}' can only be written by admins", self.name))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The register read that fetches this tag, using the data type names tags are stored with
+    pub fn register_read(&self) -> RegisterRead {
+        let data_type = DataType::from_tag_type(&self.data_type).unwrap_or(DataType::HoldingRegister);
+        let register_type = match data_type {
+            DataType::Coil => RegisterType::Coil,
+            DataType::DiscreteInput => RegisterType::DiscreteInput,
+            DataType::InputRegister => RegisterType::Input,
+            _ => match self.register_type {
+                Some(RegisterType::Input) => RegisterType::Input,
+                _ => RegisterType::Holding,
+            },
+        };
+        RegisterRead {
+            register_type,
+            address: self.address,
+            size: self.size.max(1) as u16,
+            data_type,
+            byte_order: self.byte_order,
+        }
+    }
+
+    /// Undo the tag's scaling to get the value the device expects for an engineering value
+    pub fn raw_value(&self, value: f64) -> std::result::Result<f64, String> {
+        if !value.is_finite() {
+            return Err(format!("Value for tag '{}' must be a finite number", self.name));
+        }
+        if self.scaling_multiplier == 0.0 {
+            return Err(format!("Tag '{}' has a scaling multiplier of 0, so writes can't be scaled back", self.name));
+        }
+        Ok((value - self.scaling_offset) / self.scaling_multiplier)
+    }
+}
+
+/// A window during which a tag is still polled but its samples are not logged.
+/// Rows are kept after they expire or are lifted so mute history stays visible.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TagMute {
+    pub id: i64,
+    pub device_id: String,
+    pub tag_id: i64,
+    pub tag_name: String,
+    pub muted_until: DateTime<Utc>,
+    pub reason: Option<String>,
+    pub created_by: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub lifted_at: Option<DateTime<Utc>>,
+    pub lifted_by: Option<String>,
+}
+
+impl TagMute {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.lifted_at.is_none() && self.muted_until > now
+    }
+}
+
+/// How an alarm rule compares a tag's value with its threshold
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AlarmCondition {
+    /// Above the threshold
+    Gt,
+    /// Below the threshold
+    Lt,
+    /// Equal to the threshold
+    Eq,
+    /// Changed by more than the threshold since the previous sample
+    Delta,
+}
+
+impl AlarmCondition {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlarmCondition::Gt => "gt",
+            AlarmCondition::Lt => "lt",
+            AlarmCondition::Eq => "eq",
+            AlarmCondition::Delta => "delta",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "gt" => Some(AlarmCondition::Gt),
+            "lt" => Some(AlarmCondition::Lt),
+            "eq" => Some(AlarmCondition::Eq),
+            "delta" => Some(AlarmCondition::Delta),
+            _ => None,
+        }
+    }
+}
+
+/// Severities of notifications and alarms
+pub const SEVERITIES: [&str; 3] = ["info", "warning", "error"];
+
+/// A threshold on one tag of a device, or on the tag of every device of a model
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AlarmRule {
+    pub id: i64,
+    #[serde(flatten)]
+    pub rule: NewAlarmRule,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// An alarm rule as created or replaced; exactly one of `device_id` and `model_id` is set
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct NewAlarmRule {
+    pub device_id: Option<String>,
+    pub model_id: Option<String>,
+    pub tag_name: String,
+    pub condition: AlarmCondition,
+    pub threshold: f64,
+    /// How far back past the threshold a value has to be for the alarm to clear, so a value
+    /// hovering around the threshold doesn't raise it over and over
+    #[serde(default)]
+    pub hysteresis: f64,
+    /// How long values have to stay cleared of the threshold before the alarm clears
+    #[serde(default)]
+    pub clear_hold_seconds: u32,
+    /// `info`, `warning` or `error`
+    pub severity: String,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// A value computed from other tags, logged like a polled tag of its device or group
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct VirtualTag {
+    pub id: i64,
+    #[serde(flatten)]
+    pub tag: NewVirtualTag,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A virtual tag as created or replaced; exactly one of `device_id` and `group_id` is set
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct NewVirtualTag {
+    pub name: String,
+    /// E.g. `sum(group('*').tag('Pac')) / 1000`, see the virtual tags section of the README
+    pub expression: String,
+    /// Device the values are logged under
+    pub device_id: Option<String>,
+    /// Name plant-level values are logged under instead of a device, e.g. `plant`
+    pub group_id: Option<String>,
+    /// Computed at the group's interval; without one, whenever an input is read
+    pub schedule_group_id: Option<String>,
+    pub unit: Option<String>,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+}
+
+impl NewVirtualTag {
+    /// Device id the tag's values are logged under: its device, or its group
+    pub fn owner(&self) -> &str {
+        self.device_id.as_deref().or(self.group_id.as_deref()).unwrap_or_default()
+    }
+}
+
+/// An alarm a rule raised on a device, kept once it clears
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct AlarmEvent {
+    pub id: i64,
+    pub rule_id: i64,
+    pub device_id: String,
+    pub tag_name: String,
+    pub condition: AlarmCondition,
+    pub threshold: f64,
+    pub severity: String,
+    pub raised_at: DateTime<Utc>,
+    /// Value that raised the alarm; the change between samples for `delta` rules
+    pub raised_value: f64,
+    /// Furthest past the threshold the value went while the alarm was active
+    pub peak_value: f64,
+    pub cleared_at: Option<DateTime<Utc>>,
+}
+
+/// Where a webhook delivery is in its retries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookDeliveryStatus {
+    /// Not sent yet, or waiting to be retried
+    Pending,
+    Delivered,
+    /// Gave up after the last attempt
+    Failed,
+}
+
+impl WebhookDeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookDeliveryStatus::Pending => "pending",
+            WebhookDeliveryStatus::Delivered => "delivered",
+            WebhookDeliveryStatus::Failed => "failed",
+        }
+    }
+
+    fn parse(value: &str) -> Self {
+        match value {
+            "pending" => WebhookDeliveryStatus::Pending,
+            "delivered" => WebhookDeliveryStatus::Delivered,
+            _ => WebhookDeliveryStatus::Failed,
+        }
+    }
+}
+
+/// One event sent to one webhook
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: String,
+    pub event: String,
+    /// Body that was POSTed
+    pub payload: serde_json::Value,
+    pub status: WebhookDeliveryStatus,
+    pub attempts: u32,
+    /// HTTP status of the last response; None if it got none
+    pub last_status_code: Option<u16>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Poll outcomes of one schedule group of a device within one time bucket
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PollStatsBucket {
+    pub device_id: String,
+    pub schedule_group_id: String,
+    pub bucket_start: DateTime<Utc>,
+    pub successes: u64,
+    pub failures: u64,
+    /// Time taken by the successful polls, added up
+    pub latency_ms_total: f64,
+    /// Successful polls per bucket of `metrics::READ_LATENCY_BUCKETS`, the last one counting
+    /// those slower than every bound
+    pub latency_buckets: Vec<u64>,
+    /// Failed polls by reason, e.g. `timeout` or `connect_failed`
+    pub failure_reasons: BTreeMap<String, u64>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+}
+
+impl PollStatsBucket {
+    /// Add the outcomes counted in another bucket to this one
+    pub fn merge(&mut self, other: &PollStatsBucket) {
+        self.successes += other.successes;
+        self.failures += other.failures;
+        self.latency_ms_total += other.latency_ms_total;
+        if self.latency_buckets.len() < other.latency_buckets.len() {
+            self.latency_buckets.resize(other.latency_buckets.len(), 0);
+        }
+        for (count, other) in self.latency_buckets.iter_mut().zip(&other.latency_buckets) {
+            *count += other;
+        }
+        for (reason, count) in &other.failure_reasons {
+            *self.failure_reasons.entry(reason.clone()).or_insert(0) += count;
+        }
+        if other.last_error_at > self.last_error_at {
+            self.last_error = other.last_error.clone();
+            self.last_error_at = other.last_error_at;
+        }
+    }
+}
+
+/// Which log entries a retention run removes
+#[derive(Debug, Clone)]
+pub struct RetentionPolicy {
+    /// Entries older than this are deleted
+    pub max_age: Option<chrono::Duration>,
+    /// Oldest entries beyond this count are deleted
+    pub max_entries: Option<u64>,
+    /// Rows deleted per statement; the connection is released between batches
+    pub batch_size: u32,
+}
+
+/// Outcome of one retention run
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RetentionRun {
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub deleted_by_age: u64,
+    pub deleted_by_count: u64,
+    /// Bytes returned to the filesystem by incremental vacuum
+    pub reclaimed_bytes: u64,
+    /// Free pages left inside the database file, reused by new entries
+    pub free_bytes: u64,
+}
+
+impl RetentionRun {
+    pub fn deleted_rows(&self) -> u64 {
+        self.deleted_by_age + self.deleted_by_count
+    }
+}
+
+/// How the samples in one time bucket are reduced to a single value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AggregateFunction {
+    Avg,
+    Min,
+    Max,
+    /// Most recent sample in the bucket
+    Last,
+    Count,
+}
+
+impl AggregateFunction {
+    fn sql_expression(&self) -> &'static str {
+        match self {
+            AggregateFunction::Avg => "AVG(value)",
+            AggregateFunction::Min => "MIN(value)",
+            AggregateFunction::Max => "MAX(value)",
+            // SQLite takes bare columns from the row that produced MAX(timestamp)
+            AggregateFunction::Last => "value",
+            AggregateFunction::Count => "COUNT(*)",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AggregateBucket {
+    pub bucket_start: DateTime<Utc>,
+    pub value: f64,
+    /// Samples that fell into the bucket
+    pub samples: u64,
+}
+
+/// A logged value waiting to be pushed to ThingsBoard
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TelemetryOutboxEntry {
+    pub id: i64,
+    pub device_id: String,
+    pub tb_device_id: String,
+    pub tag_name: String,
+    pub value: f64,
+    pub quality: String,
+    pub timestamp: DateTime<Utc>,
+    pub attempts: u32,
+}
+
+/// Values of one device still waiting for ThingsBoard
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct TelemetryBacklog {
+    pub pending: i64,
+    pub oldest_timestamp: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+/// A ThingsBoard MPPT or String device created or matched for a local inverter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TbChildDevice {
+    /// Local inverter the child belongs to
+    pub device_id: String,
+    pub tb_device_id: String,
+    pub name: String,
+    pub device_type: String, // "Mppt" or "String"
+    pub mppt_number: u32,
+    /// `None` for MPPT devices
+    pub input_number: Option<u32>,
+}
+
+/// Where a background job is in its life
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+}
+
+impl JobState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Completed => "completed",
+            JobState::Failed => "failed",
+        }
+    }
+
+    fn parse(value: &str) -> Self {
+        match value {
+            "queued" => JobState::Queued,
+            "running" => JobState::Running,
+            "completed" => JobState::Completed,
+            _ => JobState::Failed,
+        }
+    }
+}
+
+/// What a job did with one item, e.g. one device of a sync
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct JobItemResult {
+    pub name: String,
+    pub outcome: String,
+    pub error: Option<String>,
+}
+
+/// A ThingsBoard request that got an error response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct FailedRequest {
+    /// Method and path, e.g. `POST /api/device`
+    pub request: String,
+    pub status: u16,
+    /// Error body ThingsBoard sent, with credentials scrubbed out
+    pub body: String,
+    pub at: DateTime<Utc>,
+}
+
+/// The ThingsBoard requests a sync or catalog made, and the ones that failed
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SyncReport {
+    /// Requests sent, retries included
+    pub requests: u64,
+    /// Requests that waited for the rate limit or a 429's Retry-After
+    pub throttle_pauses: u64,
+    pub failed_requests: Vec<FailedRequest>,
+}
+
+/// A ThingsBoard sync or catalog run handed to a background task
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Job {
+    /// Same id the operation has in `GET /api/jobs/queue`
+    pub id: String,
+    /// `sync` or `catalog`
+    pub kind: String,
+    pub entity_group_id: Option<String>,
+    pub description: String,
+    pub requested_by: Option<String>,
+    pub state: JobState,
+    /// Items finished so far
+    pub progress_current: u32,
+    /// Items to work through; 0 until the job knows
+    pub progress_total: u32,
+    /// One entry per finished item, in the order they finished
+    pub items: Vec<JobItemResult>,
+    /// The operation's response once the job has completed
+    #[schema(value_type = Option<Object>)]
+    pub result: Option<serde_json::Value>,
+    pub error: Option<String>,
+    /// ThingsBoard requests made so far, kept whether the job completes or fails
+    pub report: Option<SyncReport>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// One attempted tag write, kept whether it was accepted or rejected
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TagWriteAudit {
+    pub id: Option<i64>,
+    pub device_id: String,
+    pub tag_id: Option<i64>,
+    pub tag_name: String,
+    pub username: String,
+    pub previous_value: Option<f64>,
+    pub requested_value: f64,
+    pub outcome: String, // "accepted", "rejected" or "failed"
+    pub detail: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// One configuration change, login or logout, with the fields it changed
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AuditEntry {
+    pub id: Option<i64>,
+    pub timestamp: DateTime<Utc>,
+    /// Who made the change; `None` for changes made without a session
+    pub username: Option<String>,
+    /// What was done, e.g. `device.update` or `login`
+    pub action: String,
+    /// `device`, `tag`, `schedule_group`, `device_model`, `tag_template`, `plant_config`, `config` or `session`
+    pub entity_type: String,
+    /// Tag changes are filed under their device's id
+    pub entity_id: String,
+    /// Changed fields before the change; `None` for creations
+    #[schema(value_type = Option<Object>)]
+    pub before: Option<serde_json::Value>,
+    /// Changed fields after the change; `None` for deletions
+    #[schema(value_type = Option<Object>)]
+    pub after: Option<serde_json::Value>,
+}
+
+/// One on-demand read, with the raw data it was decoded from
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TagReadResult {
+    /// Set when the read was for a configured tag
+    pub tag_name: Option<String>,
+    pub address: u16,
+    /// Decoded value with the tag's scaling applied
+    pub value: f64,
+    /// Decoded value before scaling
+    pub raw_value: f64,
+    /// Register words as read; empty for IEC 104
+    pub registers: Vec<u16>,
+    pub elapsed_ms: u64,
+    /// The read was queued through the running poller's connection rather than a short-lived one
+    pub through_poller: bool,
+}
+
+/// What a tag write actually sent to the device and what came back
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TagWriteResult {
+    pub tag_name: String,
+    pub requested_value: f64,
+    /// Scaled value read just before the write, when the protocol allows reading it
+    pub previous_value: Option<f64>,
+    /// Unscaled value written, after rounding to the tag's data type
+    pub raw_value: f64,
+    /// Registers written, in the order sent; empty for IEC 104 setpoints
+    pub registers: Vec<u16>,
+    /// Scaled value the device reported after the write: a register read for Modbus,
+    /// the activation confirmation for IEC 104
+    pub read_back: Option<f64>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ScheduleGroup {
     pub id: String,
     pub name: String,
@@ -97,7 +920,7 @@ pub struct ScheduleGroup {
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ModbusTcpTagRegister {
     pub id: Option<i64>,
     pub device_brand: String,
@@ -111,11 +934,20 @@ pub struct ModbusTcpTagRegister {
     pub modbus_type: String,
     pub divider: f64,
     pub register_type: String,
+    pub deadband_absolute: Option<f64>,
+    pub deadband_percent: Option<f64>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// Device model the register map belongs to; unset for maps whose brand and model name
+    /// didn't match exactly one device model
+    #[serde(default)]
+    pub model_id: Option<String>,
+    /// Aggregation field of the tags created from the register, e.g. `power`
+    #[serde(default)]
+    pub agg_to_field: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct CreateModbusTcpTagRegister {
     pub device_brand: String,
     pub device_model: String,
@@ -128,9 +960,144 @@ pub struct CreateModbusTcpTagRegister {
     pub modbus_type: String,
     pub divider: f64,
     pub register_type: String,
+    pub deadband_absolute: Option<f64>,
+    pub deadband_percent: Option<f64>,
+    #[serde(default)]
+    pub agg_to_field: Option<String>,
+}
+
+const DEVICE_TAG_COLUMNS: &str =
+    "id, device_id, name, address, size, data_type, description, scaling_multiplier, scaling_offset, unit, read_only, enabled, schedule_group_id, agg_to_field, write_policy, byte_order, deadband_absolute, deadband_percent, register_type";
+
+const ALARM_RULE_COLUMNS: &str =
+    "id, device_id, model_id, tag_name, condition, threshold, hysteresis, clear_hold_seconds, severity, enabled, created_at, updated_at";
+
+const VIRTUAL_TAG_COLUMNS: &str =
+    "id, name, expression, device_id, group_id, schedule_group_id, unit, enabled, created_at, updated_at";
+
+const ALARM_EVENT_COLUMNS: &str =
+    "id, rule_id, device_id, tag_name, condition, threshold, severity, raised_at, raised_value, peak_value, cleared_at";
+
+fn parse_timestamp(value: String, index: usize, name: &str) -> rusqlite::Result<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(&value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| rusqlite::Error::InvalidColumnType(index, name.to_string(), rusqlite::types::Type::Text))
+}
+
+/// A poll stats bucket from the first seven columns of a `poll_stats` row
+fn poll_stats_bucket(row: &rusqlite::Row, device_id: &str, schedule_group_id: &str, bucket_start: DateTime<Utc>) -> rusqlite::Result<PollStatsBucket> {
+    Ok(PollStatsBucket {
+        device_id: device_id.to_string(),
+        schedule_group_id: schedule_group_id.to_string(),
+        bucket_start,
+        successes: row.get::<_, i64>(0)? as u64,
+        failures: row.get::<_, i64>(1)? as u64,
+        latency_ms_total: row.get(2)?,
+        latency_buckets: serde_json::from_str(&row.get::<_, String>(3)?).unwrap_or_default(),
+        failure_reasons: serde_json::from_str(&row.get::<_, String>(4)?).unwrap_or_default(),
+        last_error: row.get(5)?,
+        last_error_at: row.get::<_, Option<String>>(6)?.map(|at| parse_timestamp(at, 6, "last_error_at")).transpose()?,
+    })
+}
+
+fn parse_condition(value: String, index: usize) -> rusqlite::Result<AlarmCondition> {
+    AlarmCondition::parse(&value).ok_or_else(|| rusqlite::Error::InvalidColumnType(index, "condition".to_string(), rusqlite::types::Type::Text))
+}
+
+fn alarm_rule_from_row(row: &rusqlite::Row) -> rusqlite::Result<AlarmRule> {
+    Ok(AlarmRule {
+        id: row.get(0)?,
+        rule: NewAlarmRule {
+            device_id: row.get(1)?,
+            model_id: row.get(2)?,
+            tag_name: row.get(3)?,
+            condition: parse_condition(row.get(4)?, 4)?,
+            threshold: row.get(5)?,
+            hysteresis: row.get(6)?,
+            clear_hold_seconds: row.get(7)?,
+            severity: row.get(8)?,
+            enabled: row.get(9)?,
+        },
+        created_at: parse_timestamp(row.get(10)?, 10, "created_at")?,
+        updated_at: parse_timestamp(row.get(11)?, 11, "updated_at")?,
+    })
+}
+
+fn virtual_tag_from_row(row: &rusqlite::Row) -> rusqlite::Result<VirtualTag> {
+    Ok(VirtualTag {
+        id: row.get(0)?,
+        tag: NewVirtualTag {
+            name: row.get(1)?,
+            expression: row.get(2)?,
+            device_id: row.get(3)?,
+            group_id: row.get(4)?,
+            schedule_group_id: row.get(5)?,
+            unit: row.get(6)?,
+            enabled: row.get(7)?,
+        },
+        created_at: parse_timestamp(row.get(8)?, 8, "created_at")?,
+        updated_at: parse_timestamp(row.get(9)?, 9, "updated_at")?,
+    })
+}
+
+fn alarm_event_from_row(row: &rusqlite::Row) -> rusqlite::Result<AlarmEvent> {
+    Ok(AlarmEvent {
+        id: row.get(0)?,
+        rule_id: row.get(1)?,
+        device_id: row.get(2)?,
+        tag_name: row.get(3)?,
+        condition: parse_condition(row.get(4)?, 4)?,
+        threshold: row.get(5)?,
+        severity: row.get(6)?,
+        raised_at: parse_timestamp(row.get(7)?, 7, "raised_at")?,
+        raised_value: row.get(8)?,
+        peak_value: row.get(9)?,
+        cleared_at: row.get::<_, Option<String>>(10)?.map(|value| parse_timestamp(value, 10, "cleared_at")).transpose()?,
+    })
+}
+
+/// The device model a register map's brand (?1) and model name (?2) refer to, if exactly one matches
+const MODEL_ID_BY_NAME: &str =
+    "(SELECT CASE WHEN COUNT(*) = 1 THEN MIN(id) END FROM device_models WHERE manufacturer = ?1 AND name = ?2)";
+
+/// Register map rows (as `mtr`) of the device model ?1: those linked to it, and unlinked ones
+/// still matching its brand and name
+const REGISTERS_OF_MODEL: &str = "(mtr.model_id = ?1 OR (mtr.model_id IS NULL AND EXISTS (
+    SELECT 1 FROM device_models dm WHERE dm.id = ?1 AND dm.name = mtr.device_model AND dm.manufacturer = mtr.device_brand
+)))";
+
+/// How an uploaded register map treats the rows already stored for its brand and model
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RegisterImportMode {
+    /// Add the rows, overwriting any with the same address, MPPT and INPUT
+    #[default]
+    Append,
+    /// Update rows with the same address, MPPT and INPUT and insert the rest
+    Upsert,
+    /// Delete every stored row of the brand and model first
+    Replace,
+}
+
+/// What saving a device's tag list did to its stored tags
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TagSyncCounts {
+    pub inserted: u64,
+    /// Changed tags, updated in place so they keep their ids
+    pub updated: u64,
+    pub deleted: u64,
+    pub unchanged: u64,
+}
+
+/// What a register map import did to the stored rows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct RegisterImportCounts {
+    pub inserted: u64,
+    pub updated: u64,
+    pub deleted: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct CsvModbusTcpTagRecord {
     #[serde(rename = "Device Brand")]
     pub device_brand: String,
@@ -154,40 +1121,609 @@ pub struct CsvModbusTcpTagRecord {
     pub divider: f64,
     #[serde(rename = "Register Type")]
     pub register_type: String,
+    /// Optional columns; an empty cell leaves the deadband unset
+    #[serde(rename = "Deadband", default)]
+    pub deadband_absolute: Option<f64>,
+    #[serde(rename = "Deadband %", default)]
+    pub deadband_percent: Option<f64>,
+    #[serde(rename = "Agg To Field", default)]
+    pub agg_to_field: Option<String>,
 }
 
 // Authentication structures
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct LocalUser {
     pub id: Option<i64>,
     pub username: String,
     pub password_hash: String,
     pub role: String, // "admin" or "installer"
+    /// Set for the seeded default accounts until their password is changed
+    #[serde(default)]
+    pub must_change_password: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct UserSession {
-    pub id: Option<i64>,
-    pub user_id: i64,
-    pub session_token: String,
-    pub expires_at: DateTime<Utc>,
+/// Roles a local account can have
+pub const USER_ROLES: [&str; 2] = ["admin", "installer"];
+
+/// A local account as listed to admins. The password hash is never listed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct UserAccount {
+    pub id: i64,
+    pub username: String,
+    pub role: String,
+    /// Disabled accounts can't log in and have no sessions
+    pub disabled: bool,
+    pub must_change_password: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct PlantConfiguration {
+/// Changes an admin makes to another account; unset fields are left as they are
+#[derive(Debug, Clone, Default, Deserialize, ToSchema)]
+pub struct UserAccountChanges {
+    pub role: Option<String>,
+    pub disabled: Option<bool>,
+}
+
+/// Outcome of creating, changing or deleting a local account
+#[derive(Debug, Clone, PartialEq)]
+pub enum UserChange<T> {
+    Done(T),
+    NotFound,
+    UsernameTaken,
+    /// Refused because it would leave no enabled admin
+    LastAdmin,
+}
+
+/// Placeholder hashes the default accounts were seeded with before passwords were hashed,
+/// with the default password each stood for
+const LEGACY_DEFAULT_PASSWORDS: [(&str, &str); 2] = [
+    ("$2b$12$LQv3c1yqBWVHxkd0LHAkCOYz6TtxMaHKi9M2F6bOVOA8qVCNzqb1q", "admin123"),
+    ("$2b$12$9E7KgXQJ5/FqJqMQ2N6cTOzF8jQJ5MJ8X2Y4D3Kj9P6L7X8Y9Z0A1", "installer123"),
+];
+
+/// How often a session's `last_used` is written; requests in between leave it as it is
+pub const SESSION_LAST_USED_RESOLUTION_SECS: i64 = 60;
+
+/// A session that hasn't expired, as listed to its owner. The token itself is never listed.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ActiveSession {
+    pub id: i64,
+    pub user_id: i64,
+    pub username: String,
+    /// Unknown for sessions created before it was recorded
+    pub created_at: Option<DateTime<Utc>>,
+    /// Updated at most once every `SESSION_LAST_USED_RESOLUTION_SECS`
+    pub last_used: Option<DateTime<Utc>>,
+    pub expires_at: DateTime<Utc>,
+    /// Address the session logged in from
+    pub source_ip: Option<String>,
+    /// Whether this is the session the list was requested with
+    pub current: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PlantConfiguration {
     pub id: Option<i64>,
     pub plant_name: String,
     pub thingsboard_entity_group_id: Option<String>,
     pub last_synced: Option<String>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DailyReport {
+    pub report_date: String, // YYYY-MM-DD (UTC)
+    pub plant_name: String,
+    pub generated_at: DateTime<Utc>,
+    pub content: String, // JSON serialized report
+    pub summary_text: String,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DeviceDayStats {
+    pub device_id: String,
+    pub total_samples: i64,
+    pub good_samples: i64,
+    pub first_sample: Option<String>,
+    pub last_sample: Option<String>,
+    pub max_gap_seconds: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TagDayDelta {
+    pub device_id: String,
+    pub tag_name: String,
+    pub unit: Option<String>,
+    pub first_value: f64,
+    pub last_value: f64,
+    pub min_value: f64,
+    pub max_value: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TagTypeMismatch {
+    pub device_id: String,
+    pub tag_name: String,
+    pub mismatch_count: i64,
+    pub last_reason: String,
+    pub last_seen: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Notification {
+    pub id: i64,
+    pub notification_type: String,
+    pub severity: String, // "info", "warning" or "error"
+    pub title: String,
+    pub body: String,
+    pub entity_type: Option<String>,
+    pub entity_id: Option<String>,
+    pub user_id: Option<i64>, // None for broadcast notifications
+    pub created_at: DateTime<Utc>,
+    pub read_at: Option<DateTime<Utc>>, // Read state for the requesting user
+}
+
+#[derive(Debug, Clone)]
+pub struct NewNotification {
+    pub notification_type: String,
+    pub severity: String,
+    pub title: String,
+    pub body: String,
+    pub entity_type: Option<String>,
+    pub entity_id: Option<String>,
+    pub user_id: Option<i64>,
+}
+
+/// Tag characteristics to search for across all devices; unset fields match everything
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct TagSearchFilter {
+    pub address: Option<u16>,
+    pub address_min: Option<u16>,
+    pub address_max: Option<u16>,
+    /// Case-insensitive name match; `*` is a wildcard, otherwise a substring match
+    pub name_pattern: Option<String>,
+    pub data_type: Option<String>,
+    pub unit: Option<String>,
+    pub schedule_group_id: Option<String>,
+    pub enabled: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TagSearchResult {
+    #[serde(flatten)]
+    pub tag: DeviceTag,
+    pub device_name: String,
+    pub model_id: Option<String>,
+    pub model_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SavedTagSearch {
+    pub id: String,
+    pub name: Option<String>,
+    pub filter: TagSearchFilter,
+    pub created_by: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Fields applied to every tag matched by a bulk edit; unset fields are left unchanged
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct TagBulkChanges {
+    pub address: Option<u16>,
+    pub size: Option<i32>,
+    pub data_type: Option<String>,
+    pub unit: Option<String>,
+    pub scaling_multiplier: Option<f64>,
+    pub scaling_offset: Option<f64>,
+    pub schedule_group_id: Option<String>,
+    pub enabled: Option<bool>,
+}
+
+/// Result of claiming an idempotency key for a mutation request
+#[derive(Debug, Clone, PartialEq)]
+pub enum IdempotencyOutcome {
+    /// Key is new (or expired); the caller must complete or release it
+    Proceed,
+    /// Key was already used with the same body, return the stored response
+    Replay { status_code: u16, response_body: String },
+    /// Key was already used with a different body
+    Mismatch,
+    /// Key is held by a request that hasn't finished yet
+    InProgress,
+}
+
+tokio::task_local! {
+    /// Cancellation state of the operation the current task is running, checked by
+    /// SQLite's progress handler so a long statement can be interrupted mid-way
+    static OPERATION_CANCEL: CancelToken;
+}
+
+/// How often (in SQLite VM instructions) a running statement checks for cancellation
+const PROGRESS_CHECK_INTERVAL: i32 = 1_000;
+
+#[derive(Clone)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Instant,
+}
+
+impl CancelToken {
+    fn new(timeout: Duration) -> Self {
+        Self {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            deadline: Instant::now() + timeout,
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn timed_out(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+
+    fn should_abort(&self) -> bool {
+        self.is_cancelled() || self.timed_out()
+    }
+}
+
+#[derive(Default)]
+struct OperationCounters {
+    active: AtomicU64,
+    completed: AtomicU64,
+    cancelled: AtomicU64,
+    timed_out: AtomicU64,
+}
+
+/// Counters for long-running reads started from HTTP handlers
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DatabaseOperationStats {
+    pub active: u64,
+    pub completed: u64,
+    pub cancelled: u64,
+    pub timed_out: u64,
+}
+
+#[derive(Debug)]
+pub enum OperationError {
+    TimedOut(Duration),
+    Failed(anyhow::Error),
+}
+
+impl std::fmt::Display for OperationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OperationError::TimedOut(timeout) => write!(f, "Operation timed out after {}s", timeout.as_secs()),
+            OperationError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for OperationError {}
+
+/// A cancellable, deadline-bound database operation. Dropping it before
+/// `finish` (e.g. because the client disconnected) counts as a cancellation
+/// and interrupts any statement still running under its token.
+pub struct DatabaseOperation {
+    token: CancelToken,
+    timeout: Duration,
+    counters: Arc<OperationCounters>,
+    finished: bool,
+}
+
+impl DatabaseOperation {
+    pub fn token(&self) -> &CancelToken {
+        &self.token
+    }
+
+    /// Run one step of the operation; statements it issues are interrupted at the deadline
+    pub async fn run<T, F>(&mut self, future: F) -> std::result::Result<T, OperationError>
+    where
+        F: Future<Output = Result<T>>,
+    {
+        let remaining = self.token.deadline.saturating_duration_since(Instant::now());
+        match tokio::time::timeout(remaining, OPERATION_CANCEL.scope(self.token.clone(), future)).await {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(e)) if !self.token.timed_out() => Err(OperationError::Failed(e)),
+            // Either the deadline passed while waiting, or the progress handler interrupted the statement
+            _ => {
+                self.finished = true;
+                self.counters.timed_out.fetch_add(1, Ordering::Relaxed);
+                Err(OperationError::TimedOut(self.timeout))
+            }
+        }
+    }
+
+    pub fn finish(mut self) {
+        self.finished = true;
+        self.counters.completed.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for DatabaseOperation {
+    fn drop(&mut self) {
+        self.counters.active.fetch_sub(1, Ordering::Relaxed);
+        if !self.finished {
+            self.token.cancel();
+            self.counters.cancelled.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// One schema change, applied once and recorded in `schema_migrations`
+struct Migration {
+    version: u32,
+    description: &'static str,
+    /// Statements run before the columns are added, e.g. to rebuild a table whose key changed
+    statements: &'static [&'static str],
+    /// Columns added as (table, column, definition). Databases from before migrations were
+    /// recorded may already have some, and those are skipped.
+    add_columns: &'static [(&'static str, &'static str, &'static str)],
+}
+
+/// Every schema change in the order applied; new ones go at the end with the next version
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Register count of tag templates and device tags",
+        statements: &[],
+        add_columns: &[("tag_templates", "size", "INTEGER DEFAULT 1"), ("device_tags", "size", "INTEGER DEFAULT 1")],
+    },
+    Migration {
+        version: 2,
+        description: "ThingsBoard device and entity group of each device",
+        statements: &[],
+        add_columns: &[("devices", "tb_device_id", "TEXT"), ("devices", "tb_group_id", "TEXT")],
+    },
+    Migration {
+        version: 3,
+        description: "Device serial numbers",
+        statements: &[],
+        add_columns: &[("devices", "serial_no", "TEXT")],
+    },
+    Migration {
+        version: 4,
+        description: "Aggregation field of device tags",
+        statements: &[],
+        add_columns: &[("device_tags", "agg_to_field", "TEXT")],
+    },
+    Migration {
+        version: 5,
+        description: "Write policy of device tags",
+        statements: &[],
+        add_columns: &[("device_tags", "write_policy", "TEXT NOT NULL DEFAULT 'disabled'")],
+    },
+    Migration {
+        version: 6,
+        description: "Byte order of device tags",
+        statements: &[],
+        add_columns: &[("device_tags", "byte_order", "TEXT")],
+    },
+    Migration {
+        version: 7,
+        description: "Deadbands of device tags and register maps",
+        statements: &[],
+        add_columns: &[
+            ("device_tags", "deadband_absolute", "REAL"),
+            ("device_tags", "deadband_percent", "REAL"),
+            ("modbus_tcp_tag_registers", "deadband_absolute", "REAL"),
+            ("modbus_tcp_tag_registers", "deadband_percent", "REAL"),
+        ],
+    },
+    Migration {
+        version: 8,
+        description: "Register table override of device tags",
+        statements: &[],
+        add_columns: &[("device_tags", "register_type", "TEXT")],
+    },
+    Migration {
+        version: 9,
+        description: "Strict data types per device",
+        statements: &[],
+        add_columns: &[("devices", "strict_types", "BOOLEAN NOT NULL DEFAULT 0")],
+    },
+    Migration {
+        version: 10,
+        description: "Telemetry forwarding switch per device",
+        statements: &[],
+        add_columns: &[("devices", "forward_telemetry", "BOOLEAN NOT NULL DEFAULT 1")],
+    },
+    Migration {
+        version: 11,
+        description: "Device model of register map rows",
+        statements: &[],
+        add_columns: &[("modbus_tcp_tag_registers", "model_id", "TEXT REFERENCES device_models (id)")],
+    },
+    Migration {
+        version: 12,
+        description: "Forced password changes and disabled accounts",
+        statements: &[],
+        add_columns: &[
+            ("local_users", "must_change_password", "BOOLEAN NOT NULL DEFAULT 0"),
+            ("local_users", "disabled", "BOOLEAN NOT NULL DEFAULT 0"),
+        ],
+    },
+    Migration {
+        version: 13,
+        description: "Session creation, last use and source address",
+        statements: &[],
+        add_columns: &[("user_sessions", "created_at", "TEXT"), ("user_sessions", "last_used", "TEXT"), ("user_sessions", "source_ip", "TEXT")],
+    },
+    Migration {
+        version: 14,
+        description: "Last ThingsBoard sync of the plant configuration",
+        statements: &[],
+        add_columns: &[("plant_configuration", "last_synced", "TEXT")],
+    },
+    Migration {
+        version: 15,
+        description: "Report of finished jobs",
+        statements: &[],
+        add_columns: &[("jobs", "report", "TEXT")],
+    },
+    Migration {
+        version: 16,
+        description: "Cached ThingsBoard access token per device",
+        statements: &[],
+        add_columns: &[("devices", "tb_access_token", "TEXT")],
+    },
+    Migration {
+        version: 17,
+        description: "Quality of values waiting for ThingsBoard",
+        statements: &[],
+        add_columns: &[("telemetry_outbox", "quality", "TEXT NOT NULL DEFAULT 'Good'")],
+    },
+    Migration {
+        version: 18,
+        description: "Idempotency keys scoped per user",
+        // Stored responses are only kept for retries, so they are dropped rather than copied
+        statements: &["DROP TABLE idempotency_records", IDEMPOTENCY_RECORDS_TABLE],
+        add_columns: &[],
+    },
+    Migration {
+        version: 19,
+        description: "Aggregation field of register maps",
+        statements: &[],
+        add_columns: &[("modbus_tcp_tag_registers", "agg_to_field", "TEXT")],
+    },
+];
+
+/// Stored responses for retried mutation requests, keyed by the caller and their Idempotency-Key
+const IDEMPOTENCY_RECORDS_TABLE: &str = "CREATE TABLE IF NOT EXISTS idempotency_records (
+    user_id INTEGER NOT NULL,
+    idempotency_key TEXT NOT NULL,
+    endpoint TEXT NOT NULL,
+    request_hash TEXT NOT NULL,
+    status_code INTEGER,
+    response_body TEXT,
+    created_at TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    PRIMARY KEY (user_id, idempotency_key, endpoint)
+)";
+
+/// Schema version this build migrates databases to
+pub const LATEST_SCHEMA_VERSION: u32 = MIGRATIONS[MIGRATIONS.len() - 1].version;
+
+/// Schema version of a database and the migrations this build applied or would apply to it
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SchemaStatus {
+    pub current_version: u32,
+    pub latest_version: u32,
+    /// The database was migrated by a newer build, which this one refuses to start on
+    pub newer_than_build: bool,
+    pub applied: Vec<SchemaMigration>,
+    /// Migrations that would run on the next start
+    pub pending: Vec<SchemaMigration>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SchemaMigration {
+    pub version: u32,
+    pub description: String,
+    /// Unset for pending migrations
+    pub applied_at: Option<DateTime<Utc>>,
+}
+
+/// Read-only connections for queries that don't modify anything
+const READER_POOL_SIZE: usize = 4;
+
+/// How long a statement waits for a lock held by another connection before failing
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Read-only connections shared by queries. In WAL mode they read a consistent
+/// snapshot without waiting for the writer, so long history queries don't stall logging.
+struct ReaderPool {
+    idle: StdMutex<Vec<Connection>>,
+    available: Semaphore,
+}
+
+impl ReaderPool {
+    fn open(db_path: &str, size: usize) -> Result<Self> {
+        let mut connections = Vec::with_capacity(size);
+        for _ in 0..size {
+            let conn = Connection::open_with_flags(
+                db_path,
+                OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX | OpenFlags::SQLITE_OPEN_URI,
+            )?;
+            Database::configure_connection(&conn)?;
+            connections.push(conn);
+        }
+
+        Ok(Self {
+            idle: StdMutex::new(connections),
+            available: Semaphore::new(size),
+        })
+    }
+
+    /// Wait for an idle connection; it returns to the pool when the guard is dropped
+    async fn get(&self) -> PooledConnection<'_> {
+        let permit = self.available.acquire().await.expect("reader pool is never closed");
+        let conn = self.idle.lock().unwrap().pop().expect("a permit guarantees an idle connection");
+        PooledConnection {
+            pool: self,
+            conn: Some(conn),
+            _permit: permit,
+        }
+    }
+}
+
+struct PooledConnection<'a> {
+    pool: &'a ReaderPool,
+    conn: Option<Connection>,
+    _permit: SemaphorePermit<'a>,
+}
+
+impl Deref for PooledConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().expect("connection is only taken on drop")
+    }
+}
+
+impl DerefMut for PooledConnection<'_> {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.conn.as_mut().expect("connection is only taken on drop")
+    }
+}
+
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        // Runs before the permit is released, so the next waiter finds the connection
+        if let Some(conn) = self.conn.take() {
+            self.pool.idle.lock().unwrap().push(conn);
+        }
+    }
+}
+
 pub struct Database {
+    /// Every write goes through this connection; SQLite allows one writer at a time
     connection: Arc<Mutex<Connection>>,
+    readers: Arc<ReaderPool>,
+    operations: Arc<OperationCounters>,
 }
 
 impl Database {
     pub async fn new(db_path: &str) -> Result<Self> {
         let conn = Connection::open(db_path)?;
+
+        // Only takes effect on a new file; lets retention hand freed pages back to the filesystem
+        conn.execute_batch("PRAGMA auto_vacuum = INCREMENTAL")?;
+
+        // WAL lets readers run while a write is in progress; it persists in the file once set
+        let journal_mode: String = conn.query_row("PRAGMA journal_mode = WAL", [], |row| row.get(0))?;
+        if !journal_mode.eq_ignore_ascii_case("wal") {
+            warn!("Database {} is in {} journal mode, reads will wait for writes", db_path, journal_mode);
+        }
+        conn.execute_batch("PRAGMA synchronous = NORMAL")?;
+        Self::configure_connection(&conn)?;
+
+        // Nothing may touch a schema this build doesn't know
+        Self::check_schema_version(&conn)?;
         
         // Create tables if they don't exist
         conn.execute(
@@ -251,6 +1787,7 @@ impl Database {
             "CREATE TABLE IF NOT EXISTS devices (
                 id TEXT PRIMARY KEY,
                 name TEXT NOT NULL,
+                serial_no TEXT,
                 model_id TEXT,
                 enabled BOOLEAN DEFAULT FALSE,
                 polling_interval_ms INTEGER DEFAULT 1000,
@@ -281,42 +1818,33 @@ impl Database {
                 enabled BOOLEAN DEFAULT TRUE,
                 schedule_group_id TEXT,
                 agg_to_field TEXT,
+                write_policy TEXT NOT NULL DEFAULT 'disabled',
+                byte_order TEXT,
+                deadband_absolute REAL,
+                deadband_percent REAL,
+                register_type TEXT,
                 FOREIGN KEY (device_id) REFERENCES devices (id) ON DELETE CASCADE,
                 FOREIGN KEY (schedule_group_id) REFERENCES schedule_groups (id) ON DELETE SET NULL
             )",
             [],
         )?;
 
-        // Add size column to device_tags if it doesn't exist (migration)
-        let _ = conn.execute(
-            "ALTER TABLE device_tags ADD COLUMN size INTEGER DEFAULT 1",
-            [],
-        ); // Ignore error if column already exists
-        
-        // Add agg_to_field column to device_tags if it doesn't exist (migration)
-        let _ = conn.execute(
-            "ALTER TABLE device_tags ADD COLUMN agg_to_field TEXT",
-            [],
-        ); // Ignore error if column already exists
-        
-        // Add tb_device_id column to devices table if it doesn't exist (migration)
-        let _ = conn.execute(
-            "ALTER TABLE devices ADD COLUMN tb_device_id TEXT",
-            [],
-        ); // Ignore error if column already exists
-        
-        // Add tb_group_id column to devices table if it doesn't exist (migration)
-        let _ = conn.execute(
-            "ALTER TABLE devices ADD COLUMN tb_group_id TEXT",
-            [],
-        ); // Ignore error if column already exists
-        
-        // Add serial_no column to devices table if it doesn't exist (migration)
-        let _ = conn.execute(
-            "ALTER TABLE devices ADD COLUMN serial_no TEXT",
+        // Template each device tag was instantiated from, for re-syncing template edits
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS device_tag_templates (
+                device_id TEXT NOT NULL,
+                tag_name TEXT NOT NULL,
+                template_id INTEGER NOT NULL,
+                address_offset INTEGER NOT NULL DEFAULT 0,
+                synced_address INTEGER NOT NULL,
+                synced_scaling_multiplier REAL NOT NULL,
+                synced_scaling_offset REAL NOT NULL,
+                PRIMARY KEY (device_id, tag_name)
+            )",
             [],
-        ); // Ignore error if column already exists
-        
+        )?;
+
+
         conn.execute(
             "CREATE TABLE IF NOT EXISTS schedule_groups (
                 id TEXT PRIMARY KEY,
@@ -347,6 +1875,10 @@ impl Database {
                 register_type TEXT NOT NULL,
                 created_at TEXT NOT NULL,
                 updated_at TEXT NOT NULL,
+                deadband_absolute REAL,
+                deadband_percent REAL,
+                model_id TEXT REFERENCES device_models (id),
+                agg_to_field TEXT,
                 UNIQUE(device_brand, device_model, address, mppt, input)
             )",
             [],
@@ -384,1847 +1916,5388 @@ impl Database {
             [],
         )?;
 
-        // Add last_synced column if it doesn't exist (for existing databases)
-        let _ = conn.execute(
-            "ALTER TABLE plant_configuration ADD COLUMN last_synced TEXT",
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS daily_reports (
+                report_date TEXT PRIMARY KEY,
+                plant_name TEXT NOT NULL,
+                generated_at TEXT NOT NULL,
+                content TEXT NOT NULL,
+                summary_text TEXT NOT NULL,
+                delivered_at TEXT
+            )",
             [],
-        );
+        )?;
 
-        // Create indexes for better performance
         conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_log_entries_device_timestamp 
-             ON log_entries(device_id, timestamp)",
+            "CREATE TABLE IF NOT EXISTS tag_type_mismatches (
+                device_id TEXT NOT NULL,
+                tag_name TEXT NOT NULL,
+                mismatch_count INTEGER NOT NULL DEFAULT 0,
+                last_reason TEXT NOT NULL,
+                last_seen TEXT NOT NULL,
+                PRIMARY KEY (device_id, tag_name)
+            )",
             [],
         )?;
 
         conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_log_entries_timestamp 
-             ON log_entries(timestamp)",
+            "CREATE TABLE IF NOT EXISTS notifications (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                notification_type TEXT NOT NULL,
+                severity TEXT NOT NULL,
+                title TEXT NOT NULL,
+                body TEXT NOT NULL,
+                entity_type TEXT,
+                entity_id TEXT,
+                user_id INTEGER,
+                created_at TEXT NOT NULL,
+                FOREIGN KEY (user_id) REFERENCES local_users (id) ON DELETE CASCADE
+            )",
             [],
         )?;
 
+        // Read state is tracked per user so broadcast notifications keep a single body row
         conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_tag_templates_model ON tag_templates(model_id)",
+            "CREATE TABLE IF NOT EXISTS notification_reads (
+                notification_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                read_at TEXT NOT NULL,
+                PRIMARY KEY (notification_id, user_id),
+                FOREIGN KEY (notification_id) REFERENCES notifications (id) ON DELETE CASCADE
+            )",
             [],
         )?;
 
+        conn.execute(IDEMPOTENCY_RECORDS_TABLE, [])?;
+
+        // Tag search filters kept server-side so bulk edits can reference them by id
         conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_device_tags_device ON device_tags(device_id)",
+            "CREATE TABLE IF NOT EXISTS saved_tag_searches (
+                id TEXT PRIMARY KEY,
+                name TEXT,
+                filter TEXT NOT NULL,
+                created_by TEXT,
+                created_at TEXT NOT NULL
+            )",
             [],
         )?;
 
+        // Every attempted tag write, accepted or rejected, for the write audit trail
         conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_devices_model ON devices(model_id)",
+            "CREATE TABLE IF NOT EXISTS tag_write_audit (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                device_id TEXT NOT NULL,
+                tag_id INTEGER,
+                tag_name TEXT NOT NULL,
+                username TEXT NOT NULL,
+                previous_value REAL,
+                requested_value REAL NOT NULL,
+                outcome TEXT NOT NULL,
+                detail TEXT,
+                created_at TEXT NOT NULL
+            )",
             [],
         )?;
 
-        // Modbus TCP tag registers indexes
+        // Not tied to the entity it describes, so history outlives deleted devices
         conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_modbus_tcp_device ON modbus_tcp_tag_registers(device_brand, device_model)",
+            "CREATE TABLE IF NOT EXISTS audit_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                timestamp TEXT NOT NULL,
+                username TEXT,
+                action TEXT NOT NULL,
+                entity_type TEXT NOT NULL,
+                entity_id TEXT NOT NULL,
+                before_json TEXT,
+                after_json TEXT
+            )",
             [],
         )?;
 
         conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_modbus_tcp_address ON modbus_tcp_tag_registers(address)",
+            "CREATE TABLE IF NOT EXISTS tag_mutes (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                device_id TEXT NOT NULL,
+                tag_id INTEGER NOT NULL,
+                tag_name TEXT NOT NULL,
+                muted_until TEXT NOT NULL,
+                reason TEXT,
+                created_by TEXT,
+                created_at TEXT NOT NULL,
+                lifted_at TEXT,
+                lifted_by TEXT,
+                FOREIGN KEY (device_id) REFERENCES devices (id) ON DELETE CASCADE
+            )",
             [],
         )?;
 
         conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_modbus_tcp_ava_type ON modbus_tcp_tag_registers(ava_type)",
+            "CREATE TABLE IF NOT EXISTS alarm_rules (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                device_id TEXT REFERENCES devices (id) ON DELETE CASCADE,
+                model_id TEXT REFERENCES device_models (id) ON DELETE CASCADE,
+                tag_name TEXT NOT NULL,
+                condition TEXT NOT NULL,
+                threshold REAL NOT NULL,
+                hysteresis REAL NOT NULL DEFAULT 0,
+                clear_hold_seconds INTEGER NOT NULL DEFAULT 0,
+                severity TEXT NOT NULL,
+                enabled BOOLEAN NOT NULL DEFAULT 1,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            )",
             [],
         )?;
 
+        // Events outlive their rule, so alarm history survives a rule being deleted
         conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_modbus_tcp_mppt_input ON modbus_tcp_tag_registers(mppt, input)",
+            "CREATE TABLE IF NOT EXISTS alarm_events (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                rule_id INTEGER NOT NULL,
+                device_id TEXT NOT NULL,
+                tag_name TEXT NOT NULL,
+                condition TEXT NOT NULL,
+                threshold REAL NOT NULL,
+                severity TEXT NOT NULL,
+                raised_at TEXT NOT NULL,
+                raised_value REAL NOT NULL,
+                peak_value REAL NOT NULL,
+                cleared_at TEXT
+            )",
             [],
         )?;
-
-        // Authentication table indexes
+        // A rule has at most one open alarm per device
         conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_user_sessions_token ON user_sessions(session_token)",
+            "CREATE UNIQUE INDEX IF NOT EXISTS idx_alarm_events_open ON alarm_events(rule_id, device_id) WHERE cleared_at IS NULL",
             [],
         )?;
 
         conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_user_sessions_user_id ON user_sessions(user_id)",
+            "CREATE TABLE IF NOT EXISTS virtual_tags (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                expression TEXT NOT NULL,
+                device_id TEXT REFERENCES devices (id) ON DELETE CASCADE,
+                group_id TEXT,
+                schedule_group_id TEXT,
+                unit TEXT,
+                enabled BOOLEAN NOT NULL DEFAULT 1,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            )",
             [],
         )?;
 
-        // Insert default device models
-        Self::insert_default_device_models(&conn)?;
-
-        // Insert default schedule groups
-        Self::insert_default_schedule_groups(&conn)?;
-
-        // Insert default users and plant configuration
-        Self::insert_default_users(&conn)?;
-        Self::insert_default_plant_config(&conn)?;
-
-        info!("Database initialized at {}", db_path);
-
-        Ok(Database {
-            connection: Arc::new(Mutex::new(conn)),
-        })
-    }
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS webhook_deliveries (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                webhook_id TEXT NOT NULL,
+                event TEXT NOT NULL,
+                payload TEXT NOT NULL,
+                status TEXT NOT NULL,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                last_status_code INTEGER,
+                last_error TEXT,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_webhook ON webhook_deliveries(webhook_id, id)",
+            [],
+        )?;
 
-    pub async fn insert_log_entry(&self, entry: &LogEntry) -> Result<i64> {
-        let conn = self.connection.lock().await;
-        let timestamp_str = entry.timestamp.to_rfc3339();
-        
+        // Poll outcomes per device, schedule group and minute, for the polling statistics
         conn.execute(
-            "INSERT INTO log_entries (device_id, tag_name, value, quality, timestamp, unit)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-            params![
-                entry.device_id,
-                entry.tag_name,
-                entry.value,
-                entry.quality,
-                timestamp_str,
-                entry.unit
-            ],
+            "CREATE TABLE IF NOT EXISTS poll_stats (
+                device_id TEXT NOT NULL,
+                schedule_group_id TEXT NOT NULL,
+                bucket_start TEXT NOT NULL,
+                successes INTEGER NOT NULL DEFAULT 0,
+                failures INTEGER NOT NULL DEFAULT 0,
+                latency_ms_total REAL NOT NULL DEFAULT 0,
+                latency_buckets TEXT NOT NULL DEFAULT '[]',
+                failure_reasons TEXT NOT NULL DEFAULT '{}',
+                last_error TEXT,
+                last_error_at TEXT,
+                PRIMARY KEY (device_id, schedule_group_id, bucket_start)
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_poll_stats_bucket ON poll_stats(bucket_start)",
+            [],
         )?;
 
-        Ok(conn.last_insert_rowid())
-    }
+        // Samples read while their tag was muted, counted per UTC day for the quality report
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS muted_sample_counts (
+                device_id TEXT NOT NULL,
+                tag_name TEXT NOT NULL,
+                day TEXT NOT NULL,
+                sample_count INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY (device_id, tag_name, day)
+            )",
+            [],
+        )?;
 
-    pub async fn get_log_entries(
-        &self,
-        device_id: Option<&str>,
-        limit: Option<u32>,
-        _offset: Option<u32>,
-    ) -> Result<Vec<LogEntry>> {
-        let conn = self.connection.lock().await;
-        
-        let (query, params_vec): (String, Vec<String>) = match (device_id, limit) {
-            (Some(device_id), Some(limit)) => (
-                "SELECT id, device_id, tag_name, value, quality, timestamp, unit 
-                 FROM log_entries WHERE device_id = ?1 ORDER BY timestamp DESC LIMIT ?2".to_string(),
-                vec![device_id.to_string(), limit.to_string()]
-            ),
-            (Some(device_id), None) => (
-                "SELECT id, device_id, tag_name, value, quality, timestamp, unit 
-                 FROM log_entries WHERE device_id = ?1 ORDER BY timestamp DESC".to_string(),
-                vec![device_id.to_string()]
-            ),
-            (None, Some(limit)) => (
-                "SELECT id, device_id, tag_name, value, quality, timestamp, unit 
-                 FROM log_entries ORDER BY timestamp DESC LIMIT ?1".to_string(),
-                vec![limit.to_string()]
-            ),
-            (None, None) => (
-                "SELECT id, device_id, tag_name, value, quality, timestamp, unit 
-                 FROM log_entries ORDER BY timestamp DESC".to_string(),
-                vec![]
-            ),
-        };
+        // Logged values waiting for ThingsBoard, removed once a push succeeds
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS telemetry_outbox (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                device_id TEXT NOT NULL,
+                tb_device_id TEXT NOT NULL,
+                tag_name TEXT NOT NULL,
+                value REAL NOT NULL,
+                timestamp TEXT NOT NULL,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                last_error TEXT
+            )",
+            [],
+        )?;
 
-        let mut stmt = conn.prepare(&query)?;
-        
-        let params_refs: Vec<&dyn rusqlite::ToSql> = params_vec.iter()
-            .map(|s| s as &dyn rusqlite::ToSql)
-            .collect();
-        
-        let rows = stmt.query_map(params_refs.as_slice(), |row| {
-            let timestamp_str: String = row.get(5)?;
-            let timestamp = DateTime::parse_from_rfc3339(&timestamp_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(5, "timestamp".to_string(), rusqlite::types::Type::Text))?
-                .with_timezone(&Utc);
+        // ThingsBoard MPPT and String devices of each local inverter, rewritten on every hierarchy sync
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS tb_child_devices (
+                tb_device_id TEXT PRIMARY KEY,
+                device_id TEXT NOT NULL,
+                name TEXT NOT NULL,
+                device_type TEXT NOT NULL,
+                mppt_number INTEGER NOT NULL,
+                input_number INTEGER
+            )",
+            [],
+        )?;
 
-            Ok(LogEntry {
-                id: Some(row.get(0)?),
-                device_id: row.get(1)?,
-                tag_name: row.get(2)?,
-                value: row.get(3)?,
-                quality: row.get(4)?,
-                timestamp,
-                unit: row.get(6)?,
-            })
-        })?;
+        // Background sync and catalog jobs; items and result are JSON
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS jobs (
+                id TEXT PRIMARY KEY,
+                kind TEXT NOT NULL,
+                entity_group_id TEXT,
+                description TEXT NOT NULL,
+                requested_by TEXT,
+                state TEXT NOT NULL,
+                progress_current INTEGER NOT NULL DEFAULT 0,
+                progress_total INTEGER NOT NULL DEFAULT 0,
+                items TEXT NOT NULL DEFAULT '[]',
+                result TEXT,
+                error TEXT,
+                created_at TEXT NOT NULL,
+                started_at TEXT,
+                finished_at TEXT
+            )",
+            [],
+        )?;
 
-        let mut entries = Vec::new();
-        for row in rows {
-            entries.push(row?);
-        }
+        // Columns added since a table was first released
+        Self::run_migrations(&conn)?;
 
-        Ok(entries)
-    }
+        // Link register maps to their device model, which used to be matched on brand and name
+        // alone and so was lost when a model was renamed. Rows no model matches stay unlinked.
+        conn.execute(
+            &format!("UPDATE modbus_tcp_tag_registers SET model_id = {} WHERE model_id IS NULL", MODEL_ID_BY_NAME.replace("?1", "device_brand").replace("?2", "device_model")),
+            [],
+        )?;
 
-    pub async fn update_device_status(&self, status: &DeviceStatus) -> Result<()> {
-        let conn = self.connection.lock().await;
-        let timestamp_str = status.last_update.to_rfc3339();
+        // Accounts still on a seeded default password have to change it
+        for (legacy_hash, _) in LEGACY_DEFAULT_PASSWORDS {
+            conn.execute("UPDATE local_users SET must_change_password = 1 WHERE password_hash = ?1", params![legacy_hash])?;
+        }
 
+        // Create indexes for better performance
         conn.execute(
-            "INSERT OR REPLACE INTO device_status 
-             (device_id, status, last_update, error_message, connection_count)
-             VALUES (?1, ?2, ?3, ?4, ?5)",
-            params![
-                status.device_id,
-                status.status,
-                timestamp_str,
-                status.error_message,
-                status.connection_count
-            ],
+            "CREATE INDEX IF NOT EXISTS idx_log_entries_device_timestamp 
+             ON log_entries(device_id, timestamp)",
+            [],
         )?;
 
-        Ok(())
-    }
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_log_entries_timestamp 
+             ON log_entries(timestamp)",
+            [],
+        )?;
 
-    pub async fn get_device_status(&self, device_id: &str) -> Result<Option<DeviceStatus>> {
-        let conn = self.connection.lock().await;
-        
-        let mut stmt = conn.prepare(
-            "SELECT device_id, status, last_update, error_message, connection_count 
-             FROM device_status WHERE device_id = ?1"
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_tag_templates_model ON tag_templates(model_id)",
+            [],
         )?;
 
-        let mut rows = stmt.query_map([device_id], |row| {
-            let timestamp_str: String = row.get(2)?;
-            let timestamp = DateTime::parse_from_rfc3339(&timestamp_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(2, "last_update".to_string(), rusqlite::types::Type::Text))?
-                .with_timezone(&Utc);
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_device_tags_device ON device_tags(device_id)",
+            [],
+        )?;
 
-            Ok(DeviceStatus {
-                device_id: row.get(0)?,
-                status: row.get(1)?,
-                last_update: timestamp,
-                error_message: row.get(3)?,
-                connection_count: row.get(4)?,
-            })
-        })?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_device_tags_address ON device_tags(address)",
+            [],
+        )?;
 
-        match rows.next() {
-            Some(row) => Ok(Some(row?)),
-            None => Ok(None),
-        }
-    }
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_device_tags_schedule_group ON device_tags(schedule_group_id)",
+            [],
+        )?;
 
-    pub async fn get_all_device_statuses(&self) -> Result<Vec<DeviceStatus>> {
-        let conn = self.connection.lock().await;
-        
-        let mut stmt = conn.prepare(
-            "SELECT device_id, status, last_update, error_message, connection_count 
-             FROM device_status"
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_tag_write_audit_device ON tag_write_audit(device_id, created_at)",
+            [],
         )?;
 
-        let rows = stmt.query_map([], |row| {
-            let timestamp_str: String = row.get(2)?;
-            let timestamp = DateTime::parse_from_rfc3339(&timestamp_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(2, "last_update".to_string(), rusqlite::types::Type::Text))?
-                .with_timezone(&Utc);
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_audit_log_entity ON audit_log(entity_id, timestamp)",
+            [],
+        )?;
 
-            Ok(DeviceStatus {
-                device_id: row.get(0)?,
-                status: row.get(1)?,
-                last_update: timestamp,
-                error_message: row.get(3)?,
-                connection_count: row.get(4)?,
-            })
-        })?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_tag_mutes_device ON tag_mutes(device_id, lifted_at)",
+            [],
+        )?;
 
-        let mut statuses = Vec::new();
-        for row in rows {
-            statuses.push(row?);
-        }
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_telemetry_outbox_device ON telemetry_outbox(device_id, id)",
+            [],
+        )?;
 
-        Ok(statuses)
-    }
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_devices_model ON devices(model_id)",
+            [],
+        )?;
 
-    pub async fn cleanup_old_entries(&self, max_entries: u32) -> Result<u32> {
-        let conn = self.connection.lock().await;
-        
-        // First, count total entries
-        let mut stmt = conn.prepare("SELECT COUNT(*) FROM log_entries")?;
-        let total_count: u32 = stmt.query_row([], |row| row.get(0))?;
+        // Modbus TCP tag registers indexes
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_modbus_tcp_device ON modbus_tcp_tag_registers(device_brand, device_model)",
+            [],
+        )?;
 
-        if total_count <= max_entries {
-            return Ok(0);
-        }
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_modbus_tcp_model_id ON modbus_tcp_tag_registers(model_id)",
+            [],
+        )?;
 
-        let entries_to_delete = total_count - max_entries;
-        
-        // Delete oldest entries
-        let deleted = conn.execute(
-            "DELETE FROM log_entries WHERE id IN (
-                SELECT id FROM log_entries ORDER BY timestamp ASC LIMIT ?1
-            )",
-            params![entries_to_delete],
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_modbus_tcp_address ON modbus_tcp_tag_registers(address)",
+            [],
         )?;
 
-        info!("Cleaned up {} old log entries", deleted);
-        Ok(deleted as u32)
-    }
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_modbus_tcp_ava_type ON modbus_tcp_tag_registers(ava_type)",
+            [],
+        )?;
 
-    // Device Model Management
-    fn insert_default_device_models(conn: &Connection) -> Result<()> {
-        let now = Utc::now().to_rfc3339();
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_modbus_tcp_mppt_input ON modbus_tcp_tag_registers(mppt, input)",
+            [],
+        )?;
 
-        // Custom model (no predefined tags)
         conn.execute(
-            "INSERT OR IGNORE INTO device_models (id, name, description, manufacturer, protocol_type, created_at, updated_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            params![
-                "custom",
-                "Custom Device",
-                "Generic device model without predefined tags",
-                "Various",
-                "any",
-                now,
-                now
-            ],
+            "CREATE INDEX IF NOT EXISTS idx_notifications_user_created ON notifications(user_id, created_at)",
+            [],
         )?;
 
-        // Schneider Modicon M221 PLC
+        // Authentication table indexes
         conn.execute(
-            "INSERT OR IGNORE INTO device_models (id, name, description, manufacturer, protocol_type, created_at, updated_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            params![
-                "sungrow_1",
-                "Sungrow Inverter",
-                "Sungrow Solar Inverter",
-                "Sungrow",
-                "modbus_tcp",
-                now,
-                now
-            ],
+            "CREATE INDEX IF NOT EXISTS idx_user_sessions_token ON user_sessions(session_token)",
+            [],
         )?;
 
-        // IEC 104 RTU
         conn.execute(
-            "INSERT OR IGNORE INTO device_models (id, name, description, manufacturer, protocol_type, created_at, updated_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            params![
-                "iec104_rtu",
-                "IEC 104 RTU",
-                "Generic IEC 60870-5-104 Remote Terminal Unit",
-                "Various",
-                "iec104",
-                now,
-                now
-            ],
+            "CREATE INDEX IF NOT EXISTS idx_user_sessions_user_id ON user_sessions(user_id)",
+            [],
         )?;
 
-        // Insert default tag templates
-        Self::insert_default_tag_templates(conn)?;
+        // Insert default device models
+        Self::insert_default_device_models(&conn)?;
+
+        // Insert default schedule groups
+        Self::insert_default_schedule_groups(&conn)?;
+
+        // Insert default users and plant configuration
+        Self::insert_default_users(&conn)?;
+        Self::insert_default_plant_config(&conn)?;
+
+        info!("Database initialized at {}", db_path);
+
+        // Readers are opened once the schema exists
+        let readers = ReaderPool::open(db_path, READER_POOL_SIZE)?;
+
+        Ok(Database {
+            connection: Arc::new(Mutex::new(conn)),
+            readers: Arc::new(readers),
+            operations: Arc::new(OperationCounters::default()),
+        })
+    }
+
+    /// Settings every connection needs, writer and readers alike
+    fn configure_connection(conn: &Connection) -> Result<()> {
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+
+        // Only statements issued inside a DatabaseOperation can be interrupted
+        conn.progress_handler(
+            PROGRESS_CHECK_INTERVAL,
+            Some(|| OPERATION_CANCEL.try_with(CancelToken::should_abort).unwrap_or(false)),
+        );
 
         Ok(())
     }
 
-    fn insert_default_tag_templates(conn: &Connection) -> Result<()> {
-        // Schneider M221 PLC tags
-        let m221_tags = [
-            ("system_status", 1, "uint16", "System status register", 1.0, 0.0, None),
-            ("production_count", 100, "uint32", "Production counter", 1.0, 0.0, Some("units")),
-            ("temperature_1", 200, "int16", "Temperature sensor 1", 0.1, 0.0, Some("°C")),
-            ("temperature_2", 201, "int16", "Temperature sensor 2", 0.1, 0.0, Some("°C")),
-            ("pressure_1", 300, "uint16", "Pressure sensor 1", 0.01, 0.0, Some("bar")),
-            ("flow_rate", 400, "uint32", "Flow rate measurement", 0.1, 0.0, Some("L/min")),
-            ("alarm_status", 500, "uint16", "Alarm status register", 1.0, 0.0, None),
-        ];
+    /// Create the migrations table and refuse a database a newer build has migrated
+    fn check_schema_version(conn: &Connection) -> Result<()> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                version INTEGER PRIMARY KEY,
+                description TEXT NOT NULL,
+                applied_at TEXT NOT NULL
+            )",
+            [],
+        )?;
 
-        for (name, address, data_type, description, multiplier, offset, unit) in m221_tags {
-            conn.execute(
-                "INSERT OR IGNORE INTO tag_templates 
-                 (model_id, name, address, data_type, description, scaling_multiplier, scaling_offset, unit, read_only)
-                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
-                params![
-                    "sungrow_1",
-                    name,
-                    address,
-                    data_type,
-                    description,
-                    multiplier,
-                    offset,
-                    unit,
-                    false
-                ],
-            )?;
+        let current = Self::schema_version(conn)?;
+        if current > LATEST_SCHEMA_VERSION {
+            error!(
+                "Database schema version {} is newer than this build supports ({}); refusing to start",
+                current, LATEST_SCHEMA_VERSION
+            );
+            return Err(anyhow::anyhow!(
+                "Database schema version {} is newer than this build supports ({}). Run a build that supports it, or restore a backup taken with this one",
+                current, LATEST_SCHEMA_VERSION
+            ));
         }
+        Ok(())
+    }
 
-        // file to be ingested here
-
+    fn schema_version(conn: &Connection) -> Result<u32> {
+        Ok(conn.query_row("SELECT COALESCE(MAX(version), 0) FROM schema_migrations", [], |row| row.get(0))?)
+    }
 
+    /// Apply every migration newer than the database, each in its own transaction together
+    /// with its record, so a failed one leaves nothing half done and is retried on next start
+    fn run_migrations(conn: &Connection) -> Result<()> {
+        let current = Self::schema_version(conn)?;
+        for migration in MIGRATIONS.iter().filter(|migration| migration.version > current) {
+            let apply = || -> Result<()> {
+                let tx = conn.unchecked_transaction()?;
+                for statement in migration.statements {
+                    tx.execute(statement, [])?;
+                }
+                for (table, column, definition) in migration.add_columns {
+                    if !Self::has_column(&tx, table, column)? {
+                        tx.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), [])?;
+                    }
+                }
+                tx.execute(
+                    "INSERT INTO schema_migrations (version, description, applied_at) VALUES (?1, ?2, ?3)",
+                    params![migration.version, migration.description, Utc::now().to_rfc3339()],
+                )?;
+                tx.commit()?;
+                Ok(())
+            };
+            if let Err(e) = apply() {
+                error!("Schema migration {} ({}) failed: {}", migration.version, migration.description, e);
+                return Err(anyhow::anyhow!("Schema migration {} ({}) failed: {}", migration.version, migration.description, e));
+            }
+            info!("Applied schema migration {}: {}", migration.version, migration.description);
+        }
         Ok(())
     }
 
-    // Insert default schedule groups
-    fn insert_default_schedule_groups(conn: &Connection) -> Result<()> {
-        let now = Utc::now().to_rfc3339();
-
-        // High frequency (100ms)
-        conn.execute(
-            "INSERT OR IGNORE INTO schedule_groups (id, name, polling_interval_ms, description, enabled, created_at, updated_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            params![
-                "high_freq",
-                "High Frequency",
-                100,
-                "High frequency polling for critical measurements",
-                true,
-                now,
-                now
-            ],
-        )?;
+    fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+        let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+        let columns = stmt.query_map([], |row| row.get::<_, String>(1))?.collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(columns.iter().any(|name| name == column))
+    }
 
-        // Medium frequency (1000ms)
-        conn.execute(
-            "INSERT OR IGNORE INTO schedule_groups (id, name, polling_interval_ms, description, enabled, created_at, updated_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            params![
-                "medium_freq",
-                "Medium Frequency", 
-                1000,
-                "Standard polling frequency for most measurements",
-                true,
-                now,
-                now
-            ],
+    /// Schema version of a database file and what a start of this build would migrate, without
+    /// changing anything; a missing file is reported at version 0
+    pub fn check_migrations(db_path: &str) -> Result<SchemaStatus> {
+        if !std::path::Path::new(db_path).exists() {
+            return Ok(Self::schema_status_from(Vec::new()));
+        }
+        let conn = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)?;
+        let has_table: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_migrations')",
+            [],
+            |row| row.get(0),
         )?;
+        let applied = if has_table { Self::applied_migrations(&conn)? } else { Vec::new() };
+        Ok(Self::schema_status_from(applied))
+    }
 
-        // Low frequency (5000ms)
-        conn.execute(
-            "INSERT OR IGNORE INTO schedule_groups (id, name, polling_interval_ms, description, enabled, created_at, updated_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            params![
-                "low_freq",
-                "Low Frequency",
-                5000,
-                "Low frequency polling for status and configuration data",
-                true,
-                now,
-                now
-            ],
-        )?;
+    /// Schema version of this database, for the admin API
+    pub async fn schema_status(&self) -> Result<SchemaStatus> {
+        let conn = self.readers.get().await;
+        Ok(Self::schema_status_from(Self::applied_migrations(&conn)?))
+    }
 
-        // Energy monitoring (30000ms)
-        conn.execute(
-            "INSERT OR IGNORE INTO schedule_groups (id, name, polling_interval_ms, description, enabled, created_at, updated_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            params![
-                "energy_monitor",
-                "Energy Monitoring",
-                30000,
-                "Energy and power monitoring with 30 second intervals",
-                true,
-                now,
-                now
-            ],
-        )?;
+    fn applied_migrations(conn: &Connection) -> Result<Vec<SchemaMigration>> {
+        let mut stmt = conn.prepare("SELECT version, description, applied_at FROM schema_migrations ORDER BY version")?;
+        let applied = stmt.query_map([], |row| {
+            Ok(SchemaMigration {
+                version: row.get(0)?,
+                description: row.get(1)?,
+                applied_at: Some(parse_timestamp(row.get(2)?, 2, "applied_at")?),
+            })
+        })?.collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(applied)
+    }
 
-        Ok(())
+    fn schema_status_from(applied: Vec<SchemaMigration>) -> SchemaStatus {
+        let current_version = applied.iter().map(|migration| migration.version).max().unwrap_or(0);
+        let pending = MIGRATIONS
+            .iter()
+            .filter(|migration| migration.version > current_version)
+            .map(|migration| SchemaMigration { version: migration.version, description: migration.description.to_string(), applied_at: None })
+            .collect();
+        SchemaStatus {
+            current_version,
+            latest_version: LATEST_SCHEMA_VERSION,
+            newer_than_build: current_version > LATEST_SCHEMA_VERSION,
+            applied,
+            pending,
+        }
     }
 
-    // Insert default users (admin and installer with default passwords)
-    fn insert_default_users(conn: &Connection) -> Result<()> {
-        // Only insert if no users exist
-        let count: i64 = conn.query_row(
-            "SELECT COUNT(*) FROM local_users",
-            [],
-            |row| row.get(0)
-        )?;
+    /// Run SQLite's integrity check on an existing database file; a missing file is fine
+    pub fn check_integrity(db_path: &str) -> Result<()> {
+        if !std::path::Path::new(db_path).exists() {
+            return Ok(());
+        }
 
-        if count == 0 {
-            // Default admin user (password: admin123)
-            // Hash generated with bcrypt for "admin123"
-            let admin_hash = "$2b$12$LQv3c1yqBWVHxkd0LHAkCOYz6TtxMaHKi9M2F6bOVOA8qVCNzqb1q";
-            
-            conn.execute(
-                "INSERT INTO local_users (username, password_hash, role)
-                 VALUES (?1, ?2, ?3)",
-                params!["admin", admin_hash, "admin"],
-            )?;
+        let conn = Connection::open(db_path)?;
+        let result: String = conn.query_row("PRAGMA integrity_check(1)", [], |row| row.get(0))?;
+        if result != "ok" {
+            return Err(anyhow::anyhow!("Integrity check failed: {}", result));
+        }
+        Ok(())
+    }
 
-            // Default installer user (password: installer123)
-            // Hash generated with bcrypt for "installer123"  
-            let installer_hash = "$2b$12$9E7KgXQJ5/FqJqMQ2N6cTOzF8jQJ5MJ8X2Y4D3Kj9P6L7X8Y9Z0A1";
-            
-            conn.execute(
-                "INSERT INTO local_users (username, password_hash, role)
-                 VALUES (?1, ?2, ?3)",
-                params!["installer", installer_hash, "installer"],
-            )?;
+    /// Check that the database answers and can take the write lock within `timeout`, for the
+    /// health check. Fails while another connection or a stuck write holds the lock.
+    pub async fn probe(&self, timeout: Duration) -> Result<()> {
+        let conn = tokio::time::timeout(timeout, self.connection.lock())
+            .await
+            .map_err(|_| anyhow::anyhow!("Database writer busy for more than {}ms", timeout.as_millis()))?;
+
+        conn.busy_timeout(timeout)?;
+        let probe = (|| -> rusqlite::Result<()> {
+            conn.execute_batch("BEGIN IMMEDIATE")?;
+            let result = conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| row.get::<_, i64>(0));
+            conn.execute_batch("ROLLBACK")?;
+            result.map(|_| ())
+        })();
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+
+        Ok(probe?)
+    }
 
-            info!("Default users created: admin (admin123) and installer (installer123)");
+    /// Start a handler-driven read that is cancelled when dropped and times out after `timeout`
+    pub fn begin_operation(&self, timeout: Duration) -> DatabaseOperation {
+        self.operations.active.fetch_add(1, Ordering::Relaxed);
+        DatabaseOperation {
+            token: CancelToken::new(timeout),
+            timeout,
+            counters: self.operations.clone(),
+            finished: false,
         }
+    }
 
-        Ok(())
+    pub fn operation_stats(&self) -> DatabaseOperationStats {
+        DatabaseOperationStats {
+            active: self.operations.active.load(Ordering::Relaxed),
+            completed: self.operations.completed.load(Ordering::Relaxed),
+            cancelled: self.operations.cancelled.load(Ordering::Relaxed),
+            timed_out: self.operations.timed_out.load(Ordering::Relaxed),
+        }
     }
 
-    // Insert default plant configuration
-    fn insert_default_plant_config(conn: &Connection) -> Result<()> {
-        // Only insert if no plant config exists
-        let count: i64 = conn.query_row(
-            "SELECT COUNT(*) FROM plant_configuration",
-            [],
-            |row| row.get(0)
-        )?;
+    /// Insert a poll's worth of entries in one transaction, returning how many were written.
+    /// Entries that break a constraint (a NaN value, for one) are skipped and logged.
+    pub async fn insert_log_entries(&self, entries: &[LogEntry]) -> Result<usize> {
+        if entries.is_empty() {
+            return Ok(0);
+        }
 
-        if count == 0 {
-            conn.execute(
-                "INSERT INTO plant_configuration (plant_name, thingsboard_entity_group_id)
-                 VALUES (?1, ?2)",
-                params!["Default Plant", Option::<String>::None],
+        let mut conn = self.connection.lock().await;
+        let tx = conn.transaction()?;
+        let mut written = 0;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT INTO log_entries (device_id, tag_name, value, quality, timestamp, unit)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
             )?;
-
-            info!("Default plant configuration created");
+            for entry in entries {
+                let result = stmt.execute(params![
+                    entry.device_id,
+                    entry.tag_name,
+                    entry.value,
+                    entry.quality,
+                    entry.timestamp.to_rfc3339(),
+                    entry.unit
+                ]);
+                match result {
+                    Ok(_) => written += 1,
+                    // A failed constraint only undoes that statement, the rest of the batch still commits
+                    Err(rusqlite::Error::SqliteFailure(e, message)) if e.code == rusqlite::ErrorCode::ConstraintViolation => {
+                        warn!(
+                            "Skipped log entry for tag {} of device {}: {}",
+                            entry.tag_name, entry.device_id, message.unwrap_or_else(|| e.to_string())
+                        );
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
         }
+        tx.commit()?;
 
-        Ok(())
+        Ok(written)
     }
 
-    // Device Model CRUD operations
-    pub async fn get_device_models(&self) -> Result<Vec<DeviceModel>> {
-        let conn = self.connection.lock().await;
-        
-        let mut stmt = conn.prepare(
-            "SELECT id, name, description, manufacturer, protocol_type, created_at, updated_at 
-             FROM device_models ORDER BY name"
-        )?;
+    /// Log entries with an id above `after_id`, oldest first, for chunked exports
+    pub async fn get_log_entries_after(
+        &self,
+        device_id: Option<&str>,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+        after_id: i64,
+        limit: u32,
+    ) -> Result<Vec<LogEntry>> {
+        let conn = self.readers.get().await;
 
-        let rows = stmt.query_map([], |row| {
-            let created_str: String = row.get(5)?;
-            let updated_str: String = row.get(6)?;
-            let created_at = DateTime::parse_from_rfc3339(&created_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(5, "created_at".to_string(), rusqlite::types::Type::Text))?
-                .with_timezone(&Utc);
-            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(6, "updated_at".to_string(), rusqlite::types::Type::Text))?
+        let mut conditions = vec!["id > ?".to_string()];
+        let mut params_vec: Vec<Box<dyn rusqlite::ToSql>> = vec![Box::new(after_id)];
+        if let Some(device_id) = device_id {
+            conditions.push("device_id = ?".to_string());
+            params_vec.push(Box::new(device_id.to_string()));
+        }
+        if let Some(start) = start {
+            conditions.push("timestamp >= ?".to_string());
+            params_vec.push(Box::new(start.to_rfc3339()));
+        }
+        if let Some(end) = end {
+            conditions.push("timestamp < ?".to_string());
+            params_vec.push(Box::new(end.to_rfc3339()));
+        }
+        params_vec.push(Box::new(limit));
+
+        let query = format!(
+            "SELECT id, device_id, tag_name, value, quality, timestamp, unit
+             FROM log_entries WHERE {} ORDER BY id LIMIT ?",
+            conditions.join(" AND ")
+        );
+        let mut stmt = conn.prepare(&query)?;
+        let params_refs: Vec<&dyn rusqlite::ToSql> = params_vec.iter().map(|p| p.as_ref()).collect();
+
+        let rows = stmt.query_map(params_refs.as_slice(), |row| {
+            let timestamp_str: String = row.get(5)?;
+            let timestamp = DateTime::parse_from_rfc3339(&timestamp_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(5, "timestamp".to_string(), rusqlite::types::Type::Text))?
                 .with_timezone(&Utc);
 
-            Ok(DeviceModel {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                description: row.get(2)?,
-                manufacturer: row.get(3)?,
-                protocol_type: row.get(4)?,
-                created_at,
-                updated_at,
+            Ok(LogEntry {
+                id: Some(row.get(0)?),
+                device_id: row.get(1)?,
+                tag_name: row.get(2)?,
+                value: row.get(3)?,
+                quality: row.get(4)?,
+                timestamp,
+                unit: row.get(6)?,
             })
         })?;
 
-        let mut models = Vec::new();
+        let mut entries = Vec::new();
         for row in rows {
-            models.push(row?);
+            entries.push(row?);
         }
-
-        Ok(models)
+        Ok(entries)
     }
 
-    pub async fn get_device_model(&self, model_id: &str) -> Result<Option<DeviceModel>> {
-        let conn = self.connection.lock().await;
-        
+    /// The most recent entry of each tag of a device logged since `since`
+    pub async fn get_latest_log_entries(&self, device_id: &str, since: DateTime<Utc>) -> Result<Vec<LogEntry>> {
+        let conn = self.readers.get().await;
+
+        // SQLite takes bare columns from the row that produced MAX(timestamp)
         let mut stmt = conn.prepare(
-            "SELECT id, name, description, manufacturer, protocol_type, created_at, updated_at 
-             FROM device_models WHERE id = ?1"
+            "SELECT id, device_id, tag_name, value, quality, MAX(timestamp), unit
+             FROM log_entries WHERE device_id = ?1 AND timestamp >= ?2 GROUP BY tag_name"
         )?;
-
-        let mut rows = stmt.query_map([model_id], |row| {
-            let created_str: String = row.get(5)?;
-            let updated_str: String = row.get(6)?;
-            let created_at = DateTime::parse_from_rfc3339(&created_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(5, "created_at".to_string(), rusqlite::types::Type::Text))?
-                .with_timezone(&Utc);
-            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(6, "updated_at".to_string(), rusqlite::types::Type::Text))?
+        let rows = stmt.query_map(params![device_id, since.to_rfc3339()], |row| {
+            let timestamp_str: String = row.get(5)?;
+            let timestamp = DateTime::parse_from_rfc3339(&timestamp_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(5, "timestamp".to_string(), rusqlite::types::Type::Text))?
                 .with_timezone(&Utc);
 
-            Ok(DeviceModel {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                description: row.get(2)?,
-                manufacturer: row.get(3)?,
-                protocol_type: row.get(4)?,
-                created_at,
-                updated_at,
+            Ok(LogEntry {
+                id: Some(row.get(0)?),
+                device_id: row.get(1)?,
+                tag_name: row.get(2)?,
+                value: row.get(3)?,
+                quality: row.get(4)?,
+                timestamp,
+                unit: row.get(6)?,
             })
         })?;
 
-        match rows.next() {
-            Some(row) => Ok(Some(row?)),
-            None => Ok(None),
+        let mut entries = Vec::new();
+        for row in rows {
+            entries.push(row?);
         }
+        Ok(entries)
     }
 
-    pub async fn get_tag_templates(&self, model_id: &str) -> Result<Vec<TagTemplate>> {
-        let conn = self.connection.lock().await;
+    pub async fn get_log_entries(
+        &self,
+        device_id: Option<&str>,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<Vec<LogEntry>> {
+        let conn = self.readers.get().await;
+
+        let mut query = "SELECT id, device_id, tag_name, value, quality, timestamp, unit FROM log_entries".to_string();
+        let mut params_vec: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+        if let Some(device_id) = device_id {
+            query.push_str(" WHERE device_id = ?");
+            params_vec.push(Box::new(device_id.to_string()));
+        }
+        query.push_str(" ORDER BY timestamp DESC");
+        if limit.is_some() || offset.is_some() {
+            // SQLite only accepts OFFSET after a LIMIT; -1 means no limit
+            query.push_str(" LIMIT ? OFFSET ?");
+            params_vec.push(Box::new(limit.map(i64::from).unwrap_or(-1)));
+            params_vec.push(Box::new(offset.unwrap_or(0)));
+        }
+
+        let mut stmt = conn.prepare(&query)?;
+        let params_refs: Vec<&dyn rusqlite::ToSql> = params_vec.iter().map(|p| p.as_ref()).collect();
         
-        let mut stmt = conn.prepare(
-            "SELECT id, model_id, name, address, data_type, description, 
-                    scaling_multiplier, scaling_offset, unit, read_only
-             FROM tag_templates WHERE model_id = ?1 ORDER BY address"
-        )?;
+        let rows = stmt.query_map(params_refs.as_slice(), |row| {
+            let timestamp_str: String = row.get(5)?;
+            let timestamp = DateTime::parse_from_rfc3339(&timestamp_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(5, "timestamp".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
 
-        let rows = stmt.query_map([model_id], |row| {
-            Ok(TagTemplate {
+            Ok(LogEntry {
                 id: Some(row.get(0)?),
-                model_id: row.get(1)?,
-                name: row.get(2)?,
-                address: row.get::<_, i32>(3)? as u16,
-                data_type: row.get(4)?,
-                description: row.get(5)?,
-                scaling_multiplier: row.get(6)?,
-                scaling_offset: row.get(7)?,
-                unit: row.get(8)?,
-                read_only: row.get(9)?,
+                device_id: row.get(1)?,
+                tag_name: row.get(2)?,
+                value: row.get(3)?,
+                quality: row.get(4)?,
+                timestamp,
+                unit: row.get(6)?,
             })
         })?;
 
-        let mut templates = Vec::new();
+        let mut entries = Vec::new();
         for row in rows {
-            templates.push(row?);
+            entries.push(row?);
         }
 
-        Ok(templates)
+        Ok(entries)
     }
 
-    pub async fn create_device_model(&self, name: &str, manufacturer: Option<&str>, protocol_type: &str, description: Option<&str>) -> Result<DeviceModel> {
-        let conn = self.connection.lock().await;
-        let model_id = uuid::Uuid::new_v4().to_string();
-        let now = Utc::now();
-        let created_str = now.to_rfc3339();
-        let updated_str = now.to_rfc3339();
+    /// Samples of one tag reduced to one value per `interval_seconds` bucket, for `[start, end)`.
+    /// Buckets are aligned to the Unix epoch and empty ones are omitted. Failed reads (`Bad` and
+    /// `CommFail`) only count with `include_bad`.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn get_aggregated_log_entries(
+        &self,
+        device_id: &str,
+        tag_name: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        interval_seconds: u64,
+        function: AggregateFunction,
+        include_bad: bool,
+    ) -> Result<Vec<AggregateBucket>> {
+        let conn = self.readers.get().await;
+
+        let query = format!(
+            "SELECT (CAST(strftime('%s', timestamp) AS INTEGER) / ?1) * ?1 AS bucket, {}, COUNT(*), MAX(timestamp)
+             FROM log_entries
+             WHERE device_id = ?2 AND tag_name = ?3 AND timestamp >= ?4 AND timestamp < ?5{}
+             GROUP BY bucket ORDER BY bucket",
+            function.sql_expression(),
+            if include_bad { "" } else { " AND quality NOT IN ('Bad', 'CommFail')" }
+        );
+        let mut stmt = conn.prepare(&query)?;
 
-        conn.execute(
-            "INSERT INTO device_models (id, name, description, manufacturer, protocol_type, created_at, updated_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            params![
-                model_id,
-                name,
-                description,
-                manufacturer,
-                protocol_type,
-                created_str,
-                updated_str
-            ],
+        let rows = stmt.query_map(
+            params![interval_seconds as i64, device_id, tag_name, start.to_rfc3339(), end.to_rfc3339()],
+            |row| {
+                let bucket: i64 = row.get(0)?;
+                Ok(AggregateBucket {
+                    bucket_start: DateTime::from_timestamp(bucket, 0)
+                        .ok_or_else(|| rusqlite::Error::InvalidColumnType(0, "bucket".to_string(), rusqlite::types::Type::Integer))?,
+                    value: row.get(1)?,
+                    samples: row.get::<_, i64>(2)? as u64,
+                })
+            },
         )?;
 
-        Ok(DeviceModel {
-            id: model_id,
-            name: name.to_string(),
-            description: description.map(|s| s.to_string()),
-            manufacturer: manufacturer.map(|s| s.to_string()),
-            protocol_type: protocol_type.to_string(),
-            created_at: now,
-            updated_at: now,
-        })
+        let mut buckets = Vec::new();
+        for row in rows {
+            buckets.push(row?);
+        }
+
+        Ok(buckets)
     }
 
-    pub async fn create_tag_template(&self, template: &TagTemplate) -> Result<TagTemplate> {
-        let conn = self.connection.lock().await;
+    /// Samples of one tag for `[start, end)` except failed reads, oldest first and at most `limit` of them
+    pub async fn get_tag_samples(
+        &self,
+        device_id: &str,
+        tag_name: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        limit: u32,
+    ) -> Result<Vec<(DateTime<Utc>, f64)>> {
+        let conn = self.readers.get().await;
 
-        let _result = conn.execute(
-            "INSERT INTO tag_templates 
-             (model_id, name, address, data_type, description, scaling_multiplier, scaling_offset, unit, read_only)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
-            params![
-                template.model_id,
-                template.name,
-                template.address as i32,
-                template.data_type,
-                template.description,
-                template.scaling_multiplier,
-                template.scaling_offset,
-                template.unit,
-                template.read_only
-            ],
+        let mut stmt = conn.prepare(
+            "SELECT timestamp, value FROM log_entries
+             WHERE device_id = ?1 AND tag_name = ?2 AND quality NOT IN ('Bad', 'CommFail') AND timestamp >= ?3 AND timestamp < ?4
+             ORDER BY timestamp LIMIT ?5",
+        )?;
+        let rows = stmt.query_map(
+            params![device_id, tag_name, start.to_rfc3339(), end.to_rfc3339(), limit],
+            |row| {
+                let timestamp_str: String = row.get(0)?;
+                let timestamp = DateTime::parse_from_rfc3339(&timestamp_str)
+                    .map_err(|_| rusqlite::Error::InvalidColumnType(0, "timestamp".to_string(), rusqlite::types::Type::Text))?
+                    .with_timezone(&Utc);
+                Ok((timestamp, row.get(1)?))
+            },
         )?;
 
-        let id = conn.last_insert_rowid();
-
-        let mut new_template = template.clone();
-        new_template.id = Some(id);
+        let mut samples = Vec::new();
+        for row in rows {
+            samples.push(row?);
+        }
 
-        Ok(new_template)
+        Ok(samples)
     }
 
-    pub async fn delete_device_model(&self, model_id: &str) -> Result<()> {
-        let conn = self.connection.lock().await;
-        
-        // Start a transaction to ensure data consistency
-        conn.execute("BEGIN TRANSACTION", [])?;
-        
-        // First, check if the model exists
-        let mut stmt = conn.prepare("SELECT COUNT(*) FROM device_models WHERE id = ?1")?;
-        let count: i64 = stmt.query_row([model_id], |row| row.get(0))?;
-        
-        if count == 0 {
-            conn.execute("ROLLBACK", [])?;
-            return Err(anyhow::anyhow!("Device model not found"));
+    /// Number of log entries matching the same filters as `get_log_entries`, with
+    /// `since` inclusive and `until` exclusive
+    pub async fn count_log_entries(
+        &self,
+        device_id: Option<&str>,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<u64> {
+        let conn = self.readers.get().await;
+
+        let mut conditions = Vec::new();
+        let mut params_vec: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+        if let Some(device_id) = device_id {
+            conditions.push("device_id = ?");
+            params_vec.push(Box::new(device_id.to_string()));
         }
-        
-        // Delete all tag templates associated with this model
-        let mut stmt = conn.prepare("DELETE FROM tag_templates WHERE model_id = ?1")?;
-        let deleted_tags = stmt.execute([model_id])?;
-        
-        // Delete any devices that use this model
-        let mut stmt = conn.prepare("DELETE FROM devices WHERE model_id = ?1")?;
-        let deleted_devices = stmt.execute([model_id])?;
-        
-        // Finally, delete the device model itself
-        let mut stmt = conn.prepare("DELETE FROM device_models WHERE id = ?1")?;
-        let _deleted_models = stmt.execute([model_id])?;
-        
-        // Commit the transaction
-        conn.execute("COMMIT", [])?;
-        
-        info!("Deleted device model {}, {} associated tag templates, and {} devices", 
-              model_id, deleted_tags, deleted_devices);
-        Ok(())
+        if let Some(since) = since {
+            conditions.push("timestamp >= ?");
+            params_vec.push(Box::new(since.to_rfc3339()));
+        }
+        if let Some(until) = until {
+            conditions.push("timestamp < ?");
+            params_vec.push(Box::new(until.to_rfc3339()));
+        }
+
+        let mut query = "SELECT COUNT(*) FROM log_entries".to_string();
+        if !conditions.is_empty() {
+            query.push_str(" WHERE ");
+            query.push_str(&conditions.join(" AND "));
+        }
+
+        let params_refs: Vec<&dyn rusqlite::ToSql> = params_vec.iter().map(|p| p.as_ref()).collect();
+        let count: i64 = conn.query_row(&query, params_refs.as_slice(), |row| row.get(0))?;
+
+        Ok(count as u64)
     }
 
-    // Device Instance CRUD operations
-    pub async fn create_device(&self, device: &DeviceInstance) -> Result<()> {
+    pub async fn update_device_status(&self, status: &DeviceStatus) -> Result<()> {
         let conn = self.connection.lock().await;
-        let created_str = device.created_at.to_rfc3339();
-        let updated_str = device.updated_at.to_rfc3339();
+        let timestamp_str = status.last_update.to_rfc3339();
 
         conn.execute(
-            "INSERT INTO devices 
-             (id, name, serial_no, model_id, enabled, polling_interval_ms, timeout_ms, retry_count, protocol_config, tb_device_id, tb_group_id, created_at, updated_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            "INSERT OR REPLACE INTO device_status 
+             (device_id, status, last_update, error_message, connection_count)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
             params![
-                device.id,
-                device.name,
-                device.serial_no,
-                device.model_id,
-                device.enabled,
-                device.polling_interval_ms,
-                device.timeout_ms,
-                device.retry_count,
-                device.protocol_config,
-                device.tb_device_id,
-                device.tb_group_id,
-                created_str,
-                updated_str
+                status.device_id,
+                status.status,
+                timestamp_str,
+                status.error_message,
+                status.connection_count
             ],
         )?;
 
         Ok(())
     }
 
-    pub async fn get_devices(&self) -> Result<Vec<DeviceInstance>> {
-        let conn = self.connection.lock().await;
+    pub async fn get_device_status(&self, device_id: &str) -> Result<Option<DeviceStatus>> {
+        let conn = self.readers.get().await;
         
         let mut stmt = conn.prepare(
-            "SELECT id, name, serial_no, model_id, enabled, polling_interval_ms, timeout_ms, retry_count, 
-                    protocol_config, tb_device_id, tb_group_id, created_at, updated_at 
-             FROM devices ORDER BY name"
+            "SELECT device_id, status, last_update, error_message, connection_count 
+             FROM device_status WHERE device_id = ?1"
         )?;
 
-        let rows = stmt.query_map([], |row| {
-            let created_str: String = row.get("created_at")?;
-            let updated_str: String = row.get("updated_at")?;
-            let created_at = DateTime::parse_from_rfc3339(&created_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(9, "created_at".to_string(), rusqlite::types::Type::Text))?
-                .with_timezone(&Utc);
-            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(10, "updated_at".to_string(), rusqlite::types::Type::Text))?
+        let mut rows = stmt.query_map([device_id], |row| {
+            let timestamp_str: String = row.get(2)?;
+            let timestamp = DateTime::parse_from_rfc3339(&timestamp_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(2, "last_update".to_string(), rusqlite::types::Type::Text))?
                 .with_timezone(&Utc);
 
-            Ok(DeviceInstance {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                serial_no: row.get(2)?,
-                model_id: row.get(3)?,
-                enabled: row.get(4)?,
-                polling_interval_ms: row.get::<_, i32>(5)? as u32,
-                timeout_ms: row.get::<_, i32>(6)? as u32,
-                retry_count: row.get::<_, i32>(7)? as u32,
-                protocol_config: row.get(8)?,
-                tb_device_id: row.get("tb_device_id")?,
-                tb_group_id: row.get("tb_group_id")?,
-                created_at,
-                updated_at,
+            Ok(DeviceStatus {
+                device_id: row.get(0)?,
+                status: row.get(1)?,
+                last_update: timestamp,
+                error_message: row.get(3)?,
+                connection_count: row.get(4)?,
             })
         })?;
 
-        let mut devices = Vec::new();
-        for row in rows {
-            devices.push(row?);
+        match rows.next() {
+            Some(row) => Ok(Some(row?)),
+            None => Ok(None),
         }
-
-        Ok(devices)
     }
 
-    pub async fn get_device(&self, device_id: &str) -> Result<Option<DeviceInstance>> {
-        let conn = self.connection.lock().await;
+    pub async fn get_all_device_statuses(&self) -> Result<Vec<DeviceStatus>> {
+        let conn = self.readers.get().await;
         
         let mut stmt = conn.prepare(
-            "SELECT id, name, serial_no, model_id, enabled, polling_interval_ms, timeout_ms, retry_count, 
-                    protocol_config, tb_device_id, tb_group_id, created_at, updated_at 
-             FROM devices WHERE id = ?1"
+            "SELECT device_id, status, last_update, error_message, connection_count 
+             FROM device_status"
         )?;
 
-        let mut rows = stmt.query_map([device_id], |row| {
-            let created_str: String = row.get(11)?;
-            let updated_str: String = row.get(12)?;
-            let created_at = DateTime::parse_from_rfc3339(&created_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(9, "created_at".to_string(), rusqlite::types::Type::Text))?
-                .with_timezone(&Utc);
-            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(10, "updated_at".to_string(), rusqlite::types::Type::Text))?
+        let rows = stmt.query_map([], |row| {
+            let timestamp_str: String = row.get(2)?;
+            let timestamp = DateTime::parse_from_rfc3339(&timestamp_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(2, "last_update".to_string(), rusqlite::types::Type::Text))?
                 .with_timezone(&Utc);
 
-            Ok(DeviceInstance {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                serial_no: row.get(2)?,
-                model_id: row.get(3)?,
-                enabled: row.get(4)?,
-                polling_interval_ms: row.get::<_, i32>(5)? as u32,
-                timeout_ms: row.get::<_, i32>(6)? as u32,
-                retry_count: row.get::<_, i32>(7)? as u32,
-                protocol_config: row.get(8)?,
-                tb_device_id: row.get("tb_device_id")?,
-                tb_group_id: row.get("tb_group_id")?,
-                created_at,
-                updated_at,
+            Ok(DeviceStatus {
+                device_id: row.get(0)?,
+                status: row.get(1)?,
+                last_update: timestamp,
+                error_message: row.get(3)?,
+                connection_count: row.get(4)?,
             })
         })?;
 
-        match rows.next() {
-            Some(row) => Ok(Some(row?)),
-            None => Ok(None),
+        let mut statuses = Vec::new();
+        for row in rows {
+            statuses.push(row?);
         }
-    }
 
-    // Device Tag CRUD operations
-    pub async fn create_device_tags(&self, device_id: &str, tags: &[DeviceTag]) -> Result<()> {
-        let conn = self.connection.lock().await;
+        Ok(statuses)
+    }
 
-        for tag in tags {
+    /// Delete log entries outside the retention policy in batches, releasing the
+    /// connection between batches so polling can keep inserting
+    pub async fn cleanup_entries(&self, policy: &RetentionPolicy) -> Result<RetentionRun> {
+        let started_at = Utc::now();
+        let batch_size = policy.batch_size.max(1);
+
+        let mut deleted_by_age = 0u64;
+        if let Some(max_age) = policy.max_age {
+            let cutoff = (started_at - max_age).to_rfc3339();
+            loop {
+                let deleted = self.connection.lock().await.execute(
+                    "DELETE FROM log_entries WHERE id IN (
+                        SELECT id FROM log_entries WHERE timestamp < ?1 LIMIT ?2
+                    )",
+                    params![cutoff, batch_size],
+                )?;
+                deleted_by_age += deleted as u64;
+                if deleted < batch_size as usize {
+                    break;
+                }
+                tokio::task::yield_now().await;
+            }
+        }
+
+        let mut deleted_by_count = 0u64;
+        if let Some(max_entries) = policy.max_entries {
+            let total: i64 = self.connection.lock().await.query_row("SELECT COUNT(*) FROM log_entries", [], |row| row.get(0))?;
+            let mut excess = (total as u64).saturating_sub(max_entries);
+            while excess > 0 {
+                // Ids follow insertion order, which avoids sorting the whole table per batch
+                let deleted = self.connection.lock().await.execute(
+                    "DELETE FROM log_entries WHERE id IN (
+                        SELECT id FROM log_entries ORDER BY id ASC LIMIT ?1
+                    )",
+                    params![excess.min(batch_size as u64) as i64],
+                )? as u64;
+                if deleted == 0 {
+                    break;
+                }
+                deleted_by_count += deleted;
+                excess = excess.saturating_sub(deleted);
+                tokio::task::yield_now().await;
+            }
+        }
+
+        let (reclaimed_bytes, free_bytes) = {
+            let conn = self.connection.lock().await;
+            let page_size: i64 = conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+            let pages_before: i64 = conn.query_row("PRAGMA page_count", [], |row| row.get(0))?;
+            conn.execute_batch("PRAGMA incremental_vacuum")?;
+            let pages_after: i64 = conn.query_row("PRAGMA page_count", [], |row| row.get(0))?;
+            let free_pages: i64 = conn.query_row("PRAGMA freelist_count", [], |row| row.get(0))?;
+            // The file only shrinks once the truncation is checkpointed out of the WAL
+            conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+            (((pages_before - pages_after).max(0) * page_size) as u64, (free_pages * page_size) as u64)
+        };
+
+        Ok(RetentionRun {
+            started_at,
+            finished_at: Utc::now(),
+            deleted_by_age,
+            deleted_by_count,
+            reclaimed_bytes,
+            free_bytes,
+        })
+    }
+
+    // Device Model Management
+    fn insert_default_device_models(conn: &Connection) -> Result<()> {
+        let now = Utc::now().to_rfc3339();
+
+        // Custom model (no predefined tags)
+        conn.execute(
+            "INSERT OR IGNORE INTO device_models (id, name, description, manufacturer, protocol_type, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                "custom",
+                "Custom Device",
+                "Generic device model without predefined tags",
+                "Various",
+                "any",
+                now,
+                now
+            ],
+        )?;
+
+        // Schneider Modicon M221 PLC
+        conn.execute(
+            "INSERT OR IGNORE INTO device_models (id, name, description, manufacturer, protocol_type, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                "sungrow_1",
+                "Sungrow Inverter",
+                "Sungrow Solar Inverter",
+                "Sungrow",
+                "modbus_tcp",
+                now,
+                now
+            ],
+        )?;
+
+        // IEC 104 RTU
+        conn.execute(
+            "INSERT OR IGNORE INTO device_models (id, name, description, manufacturer, protocol_type, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                "iec104_rtu",
+                "IEC 104 RTU",
+                "Generic IEC 60870-5-104 Remote Terminal Unit",
+                "Various",
+                "iec104",
+                now,
+                now
+            ],
+        )?;
+
+        // Insert default tag templates
+        Self::insert_default_tag_templates(conn)?;
+
+        Ok(())
+    }
+
+    fn insert_default_tag_templates(conn: &Connection) -> Result<()> {
+        // Schneider M221 PLC tags
+        let m221_tags = [
+            ("system_status", 1, "uint16", "System status register", 1.0, 0.0, None),
+            ("production_count", 100, "uint32", "Production counter", 1.0, 0.0, Some("units")),
+            ("temperature_1", 200, "int16", "Temperature sensor 1", 0.1, 0.0, Some("°C")),
+            ("temperature_2", 201, "int16", "Temperature sensor 2", 0.1, 0.0, Some("°C")),
+            ("pressure_1", 300, "uint16", "Pressure sensor 1", 0.01, 0.0, Some("bar")),
+            ("flow_rate", 400, "uint32", "Flow rate measurement", 0.1, 0.0, Some("L/min")),
+            ("alarm_status", 500, "uint16", "Alarm status register", 1.0, 0.0, None),
+        ];
+
+        for (name, address, data_type, description, multiplier, offset, unit) in m221_tags {
             conn.execute(
-                "INSERT INTO device_tags 
-                 (device_id, name, address, size, data_type, description, scaling_multiplier, scaling_offset, unit, read_only, enabled, schedule_group_id, agg_to_field)
-                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+                "INSERT OR IGNORE INTO tag_templates 
+                 (model_id, name, address, data_type, description, scaling_multiplier, scaling_offset, unit, read_only)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
                 params![
-                    device_id,
-                    tag.name,
-                    tag.address,
-                    tag.size,
-                    tag.data_type,
-                    tag.description,
-                    tag.scaling_multiplier,
-                    tag.scaling_offset,
-                    tag.unit,
-                    tag.read_only,
-                    tag.enabled,
-                    tag.schedule_group_id,
-                    tag.agg_to_field
+                    "sungrow_1",
+                    name,
+                    address,
+                    data_type,
+                    description,
+                    multiplier,
+                    offset,
+                    unit,
+                    false
                 ],
             )?;
         }
 
+        // file to be ingested here
+
+
+        Ok(())
+    }
+
+    // Insert default schedule groups
+    fn insert_default_schedule_groups(conn: &Connection) -> Result<()> {
+        let now = Utc::now().to_rfc3339();
+
+        // High frequency (100ms)
+        conn.execute(
+            "INSERT OR IGNORE INTO schedule_groups (id, name, polling_interval_ms, description, enabled, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                "high_freq",
+                "High Frequency",
+                100,
+                "High frequency polling for critical measurements",
+                true,
+                now,
+                now
+            ],
+        )?;
+
+        // Medium frequency (1000ms)
+        conn.execute(
+            "INSERT OR IGNORE INTO schedule_groups (id, name, polling_interval_ms, description, enabled, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                "medium_freq",
+                "Medium Frequency", 
+                1000,
+                "Standard polling frequency for most measurements",
+                true,
+                now,
+                now
+            ],
+        )?;
+
+        // Low frequency (5000ms)
+        conn.execute(
+            "INSERT OR IGNORE INTO schedule_groups (id, name, polling_interval_ms, description, enabled, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                "low_freq",
+                "Low Frequency",
+                5000,
+                "Low frequency polling for status and configuration data",
+                true,
+                now,
+                now
+            ],
+        )?;
+
+        // Energy monitoring (30000ms)
+        conn.execute(
+            "INSERT OR IGNORE INTO schedule_groups (id, name, polling_interval_ms, description, enabled, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                "energy_monitor",
+                "Energy Monitoring",
+                30000,
+                "Energy and power monitoring with 30 second intervals",
+                true,
+                now,
+                now
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    // Insert default users (admin and installer with default passwords)
+    fn insert_default_users(conn: &Connection) -> Result<()> {
+        // Only insert if no users exist
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM local_users",
+            [],
+            |row| row.get(0)
+        )?;
+
+        if count == 0 {
+            // Default admin user (password: admin123), hashed ahead of time so a fresh
+            // database doesn't spend time on it. Changed on first login.
+            let admin_hash = "$argon2id$v=19$m=19456,t=2,p=1$z1IwfOWqhrc/ngMadFyg8g$lHmdC6Fw5DdlkNWwQURIOxX1ZW8ETl6GVkxWfPix2X8";
+            
+            conn.execute(
+                "INSERT INTO local_users (username, password_hash, role, must_change_password)
+                 VALUES (?1, ?2, ?3, 1)",
+                params!["admin", admin_hash, "admin"],
+            )?;
+
+            // Default installer user (password: installer123)
+            let installer_hash = "$argon2id$v=19$m=19456,t=2,p=1$CRvhrm509VX9D0aLaYHTpA$10df/aeYVqDrJlCaBVaz3fO1AZudq/mlbhPJajeTD2c";
+            
+            conn.execute(
+                "INSERT INTO local_users (username, password_hash, role, must_change_password)
+                 VALUES (?1, ?2, ?3, 1)",
+                params!["installer", installer_hash, "installer"],
+            )?;
+
+            info!("Default users created: admin (admin123) and installer (installer123)");
+        }
+
+        Ok(())
+    }
+
+    // Insert default plant configuration
+    fn insert_default_plant_config(conn: &Connection) -> Result<()> {
+        // Only insert if no plant config exists
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM plant_configuration",
+            [],
+            |row| row.get(0)
+        )?;
+
+        if count == 0 {
+            conn.execute(
+                "INSERT INTO plant_configuration (plant_name, thingsboard_entity_group_id)
+                 VALUES (?1, ?2)",
+                params!["Default Plant", Option::<String>::None],
+            )?;
+
+            info!("Default plant configuration created");
+        }
+
+        Ok(())
+    }
+
+    // Device Model CRUD operations
+    pub async fn get_device_models(&self) -> Result<Vec<DeviceModel>> {
+        let conn = self.readers.get().await;
+        
+        let mut stmt = conn.prepare(
+            "SELECT id, name, description, manufacturer, protocol_type, created_at, updated_at 
+             FROM device_models ORDER BY name"
+        )?;
+
+        let rows = stmt.query_map([], |row| {
+            let created_str: String = row.get(5)?;
+            let updated_str: String = row.get(6)?;
+            let created_at = DateTime::parse_from_rfc3339(&created_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(5, "created_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(6, "updated_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+
+            Ok(DeviceModel {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                description: row.get(2)?,
+                manufacturer: row.get(3)?,
+                protocol_type: row.get(4)?,
+                created_at,
+                updated_at,
+            })
+        })?;
+
+        let mut models = Vec::new();
+        for row in rows {
+            models.push(row?);
+        }
+
+        Ok(models)
+    }
+
+    pub async fn get_device_model(&self, model_id: &str) -> Result<Option<DeviceModel>> {
+        let conn = self.readers.get().await;
+        
+        let mut stmt = conn.prepare(
+            "SELECT id, name, description, manufacturer, protocol_type, created_at, updated_at 
+             FROM device_models WHERE id = ?1"
+        )?;
+
+        let mut rows = stmt.query_map([model_id], |row| {
+            let created_str: String = row.get(5)?;
+            let updated_str: String = row.get(6)?;
+            let created_at = DateTime::parse_from_rfc3339(&created_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(5, "created_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(6, "updated_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+
+            Ok(DeviceModel {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                description: row.get(2)?,
+                manufacturer: row.get(3)?,
+                protocol_type: row.get(4)?,
+                created_at,
+                updated_at,
+            })
+        })?;
+
+        match rows.next() {
+            Some(row) => Ok(Some(row?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn get_tag_templates(&self, model_id: &str) -> Result<Vec<TagTemplate>> {
+        let conn = self.readers.get().await;
+        
+        let mut stmt = conn.prepare(
+            "SELECT id, model_id, name, address, data_type, description, 
+                    scaling_multiplier, scaling_offset, unit, read_only, size
+             FROM tag_templates WHERE model_id = ?1 ORDER BY address"
+        )?;
+
+        let rows = stmt.query_map([model_id], Self::tag_template_from_row)?;
+
+        let mut templates = Vec::new();
+        for row in rows {
+            templates.push(row?);
+        }
+
+        Ok(templates)
+    }
+
+    pub async fn create_device_model(&self, name: &str, manufacturer: Option<&str>, protocol_type: &str, description: Option<&str>) -> Result<DeviceModel> {
+        let conn = self.connection.lock().await;
+        let model_id = uuid::Uuid::new_v4().to_string();
+        let now = Utc::now();
+        let created_str = now.to_rfc3339();
+        let updated_str = now.to_rfc3339();
+
+        conn.execute(
+            "INSERT INTO device_models (id, name, description, manufacturer, protocol_type, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                model_id,
+                name,
+                description,
+                manufacturer,
+                protocol_type,
+                created_str,
+                updated_str
+            ],
+        )?;
+
+        Ok(DeviceModel {
+            id: model_id,
+            name: name.to_string(),
+            description: description.map(|s| s.to_string()),
+            manufacturer: manufacturer.map(|s| s.to_string()),
+            protocol_type: protocol_type.to_string(),
+            created_at: now,
+            updated_at: now,
+        })
+    }
+
+    pub async fn create_tag_template(&self, template: &TagTemplate) -> Result<TagTemplate> {
+        let conn = self.connection.lock().await;
+        let id = Self::insert_tag_template(&conn, template)?;
+
+        let mut new_template = template.clone();
+        new_template.id = Some(id);
+
+        Ok(new_template)
+    }
+
+    pub async fn get_tag_template(&self, model_id: &str, template_id: i64) -> Result<Option<TagTemplate>> {
+        let conn = self.readers.get().await;
+
+        let result = conn.query_row(
+            "SELECT id, model_id, name, address, data_type, description,
+                    scaling_multiplier, scaling_offset, unit, read_only, size
+             FROM tag_templates WHERE model_id = ?1 AND id = ?2",
+            params![model_id, template_id],
+            Self::tag_template_from_row,
+        );
+
+        match result {
+            Ok(template) => Ok(Some(template)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Returns false when the model has no template with this id
+    pub async fn update_tag_template(&self, template: &TagTemplate) -> Result<bool> {
+        let conn = self.connection.lock().await;
+        let rows_affected = Self::update_tag_template_row(&conn, template)?;
+        Ok(rows_affected > 0)
+    }
+
+    fn update_tag_template_row(conn: &Connection, template: &TagTemplate) -> rusqlite::Result<usize> {
+        conn.execute(
+            "UPDATE tag_templates
+             SET name = ?1, address = ?2, data_type = ?3, description = ?4, scaling_multiplier = ?5,
+                 scaling_offset = ?6, unit = ?7, read_only = ?8, size = ?9
+             WHERE id = ?10 AND model_id = ?11",
+            params![
+                template.name,
+                template.address as i32,
+                template.data_type,
+                template.description,
+                template.scaling_multiplier,
+                template.scaling_offset,
+                template.unit,
+                template.read_only,
+                template.size,
+                template.id,
+                template.model_id
+            ],
+        )
+    }
+
+    fn insert_tag_template(conn: &Connection, template: &TagTemplate) -> rusqlite::Result<i64> {
+        conn.execute(
+            "INSERT INTO tag_templates 
+             (model_id, name, address, data_type, description, scaling_multiplier, scaling_offset, unit, read_only, size)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                template.model_id,
+                template.name,
+                template.address as i32,
+                template.data_type,
+                template.description,
+                template.scaling_multiplier,
+                template.scaling_offset,
+                template.unit,
+                template.read_only,
+                template.size
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// Remove a template; tags already copied from it stay on their devices.
+    ///
+    /// Returns false when the model has no template with this id.
+    pub async fn delete_tag_template(&self, model_id: &str, template_id: i64) -> Result<bool> {
+        let mut conn = self.connection.lock().await;
+        let tx = conn.transaction()?;
+
+        let rows_affected = tx.execute(
+            "DELETE FROM tag_templates WHERE id = ?1 AND model_id = ?2",
+            params![template_id, model_id],
+        )?;
+        if rows_affected > 0 {
+            tx.execute("DELETE FROM device_tag_templates WHERE template_id = ?1", [template_id])?;
+        }
+
+        tx.commit()?;
+        Ok(rows_affected > 0)
+    }
+
+    /// Record which templates a device's tags were copied from
+    pub async fn link_tag_templates(&self, links: &[TagTemplateLink]) -> Result<()> {
+        let mut conn = self.connection.lock().await;
+        let tx = conn.transaction()?;
+
+        for link in links {
+            Self::save_tag_template_link(&tx, link)?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn save_tag_template_link(conn: &Connection, link: &TagTemplateLink) -> rusqlite::Result<()> {
+        conn.execute(
+            "INSERT OR REPLACE INTO device_tag_templates
+             (device_id, tag_name, template_id, address_offset, synced_address, synced_scaling_multiplier, synced_scaling_offset)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                link.device_id,
+                link.tag_name,
+                link.template_id,
+                link.address_offset,
+                link.synced_address as i32,
+                link.synced_scaling_multiplier,
+                link.synced_scaling_offset
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Bring the tags of every device using a model in line with the model's templates.
+    ///
+    /// Tags are matched to templates through the link recorded when the device was
+    /// created from the model, or by name for devices set up before links were kept.
+    /// Address and scaling edited on the device are kept as they are; where the
+    /// template changed them as well, the tag is reported as a conflict and left
+    /// untouched. Templates added after a device was created are not added to it.
+    /// Nothing is written when `dry_run` is set.
+    pub async fn resync_model_devices(&self, model_id: &str, dry_run: bool) -> Result<TemplateResync> {
+        let mut conn = self.connection.lock().await;
+        let tx = conn.transaction()?;
+
+        let templates: Vec<TagTemplate> = {
+            let mut stmt = tx.prepare(
+                "SELECT id, model_id, name, address, data_type, description,
+                        scaling_multiplier, scaling_offset, unit, read_only, size
+                 FROM tag_templates WHERE model_id = ?1 ORDER BY address",
+            )?;
+            let rows = stmt.query_map([model_id], Self::tag_template_from_row)?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+        let templates_by_id: HashMap<i64, &TagTemplate> = templates.iter().filter_map(|t| Some((t.id?, t))).collect();
+        let templates_by_name: HashMap<&str, &TagTemplate> = templates.iter().map(|t| (t.name.as_str(), t)).collect();
+
+        let mut links: HashMap<(String, String), TagTemplateLink> = {
+            let mut stmt = tx.prepare(
+                "SELECT l.device_id, l.tag_name, l.template_id, l.address_offset, l.synced_address,
+                        l.synced_scaling_multiplier, l.synced_scaling_offset
+                 FROM device_tag_templates l JOIN devices d ON d.id = l.device_id
+                 WHERE d.model_id = ?1",
+            )?;
+            let rows = stmt.query_map([model_id], |row| {
+                Ok(TagTemplateLink {
+                    device_id: row.get(0)?,
+                    tag_name: row.get(1)?,
+                    template_id: row.get(2)?,
+                    address_offset: row.get(3)?,
+                    synced_address: row.get::<_, i32>(4)? as u16,
+                    synced_scaling_multiplier: row.get(5)?,
+                    synced_scaling_offset: row.get(6)?,
+                })
+            })?;
+            rows.map(|link| link.map(|link| ((link.device_id.clone(), link.tag_name.clone()), link)))
+                .collect::<rusqlite::Result<_>>()?
+        };
+
+        // Device tags read through the template columns, keyed by tag id and device id
+        let tags: Vec<(i64, String, TagTemplate)> = {
+            let mut stmt = tx.prepare(
+                "SELECT t.id, t.device_id, t.name, t.address, t.data_type, t.description,
+                        t.scaling_multiplier, t.scaling_offset, t.unit, t.read_only, t.size
+                 FROM device_tags t JOIN devices d ON d.id = t.device_id
+                 WHERE d.model_id = ?1 ORDER BY t.device_id, t.address",
+            )?;
+            let rows = stmt.query_map([model_id], |row| {
+                let tag = Self::tag_template_from_row(row)?;
+                Ok((tag.id.unwrap_or_default(), tag.model_id.clone(), tag))
+            })?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+        let mut tag_names: HashMap<String, HashSet<String>> = HashMap::new();
+        for (_, device_id, tag) in &tags {
+            tag_names.entry(device_id.clone()).or_default().insert(tag.name.clone());
+        }
+
+        let mut resync = TemplateResync { dry_run, ..Default::default() };
+        for (tag_id, device_id, tag) in tags {
+            let link = links.remove(&(device_id.clone(), tag.name.clone()));
+            let template = match &link {
+                Some(link) => templates_by_id.get(&link.template_id).copied(),
+                None => templates_by_name.get(tag.name.as_str()).copied(),
+            };
+            let Some(template) = template else { continue };
+            let Some(template_id) = template.id else { continue };
+            let address_offset = link.as_ref().map_or(0, |link| link.address_offset);
+            let template_address = template.address as i32 + address_offset;
+
+            // Edited on the device since the last sync; without a link any difference counts
+            let edited_on_device = |field: &str| match (&link, field) {
+                (Some(link), "address") => tag.address as i32 != link.synced_address as i32 + address_offset,
+                (Some(link), "scaling_multiplier") => tag.scaling_multiplier != link.synced_scaling_multiplier,
+                (Some(link), "scaling_offset") => tag.scaling_offset != link.synced_scaling_offset,
+                (None, "address" | "scaling_multiplier" | "scaling_offset") => true,
+                _ => false,
+            };
+            let changed_in_template = |field: &str| match (&link, field) {
+                (Some(link), "address") => template.address != link.synced_address,
+                (Some(link), "scaling_multiplier") => template.scaling_multiplier != link.synced_scaling_multiplier,
+                (Some(link), "scaling_offset") => template.scaling_offset != link.synced_scaling_offset,
+                _ => true,
+            };
+
+            let fields = [
+                ("name", serde_json::json!(tag.name), serde_json::json!(template.name)),
+                ("address", serde_json::json!(tag.address), serde_json::json!(template_address)),
+                ("size", serde_json::json!(tag.size), serde_json::json!(template.size)),
+                ("data_type", serde_json::json!(tag.data_type), serde_json::json!(template.data_type)),
+                ("description", serde_json::json!(tag.description), serde_json::json!(template.description)),
+                ("scaling_multiplier", serde_json::json!(tag.scaling_multiplier), serde_json::json!(template.scaling_multiplier)),
+                ("scaling_offset", serde_json::json!(tag.scaling_offset), serde_json::json!(template.scaling_offset)),
+                ("unit", serde_json::json!(tag.unit), serde_json::json!(template.unit)),
+                ("read_only", serde_json::json!(tag.read_only), serde_json::json!(template.read_only)),
+            ];
+            let changes: Vec<TemplateFieldChange> = fields
+                .into_iter()
+                .filter(|(_, device_value, template_value)| device_value != template_value)
+                .map(|(field, device_value, template_value)| TemplateFieldChange { field: field.to_string(), device_value, template_value })
+                .collect();
+
+            let mut customized = Vec::new();
+            let mut kept: HashSet<String> = HashSet::new();
+            for change in &changes {
+                let field = change.field.as_str();
+                if edited_on_device(field) {
+                    if changed_in_template(field) {
+                        customized.push(change.field.clone());
+                    } else {
+                        kept.insert(change.field.clone());
+                    }
+                }
+            }
+            if !(0..=u16::MAX as i32).contains(&template_address) && !customized.iter().any(|field| field == "address") {
+                customized.push("address".to_string());
+            }
+            if tag.name != template.name && tag_names.get(&device_id).is_some_and(|names| names.contains(&template.name)) {
+                customized.push("name".to_string());
+            }
+
+            if !customized.is_empty() {
+                resync.conflicts.push(TemplateTagResync { device_id, tag_name: tag.name, template_id, changes, customized });
+                continue;
+            }
+
+            let keep = |field: &str| kept.contains(field);
+            let address = if keep("address") { tag.address } else { template_address as u16 };
+            let scaling_multiplier = if keep("scaling_multiplier") { tag.scaling_multiplier } else { template.scaling_multiplier };
+            let scaling_offset = if keep("scaling_offset") { tag.scaling_offset } else { template.scaling_offset };
+            let changes: Vec<TemplateFieldChange> = changes.into_iter().filter(|change| !keep(&change.field)).collect();
+
+            if !dry_run {
+                if !changes.is_empty() {
+                    tx.execute(
+                        "UPDATE device_tags
+                         SET name = ?1, address = ?2, size = ?3, data_type = ?4, description = ?5,
+                             scaling_multiplier = ?6, scaling_offset = ?7, unit = ?8, read_only = ?9
+                         WHERE id = ?10",
+                        params![
+                            template.name,
+                            address as i32,
+                            template.size,
+                            template.data_type,
+                            template.description,
+                            scaling_multiplier,
+                            scaling_offset,
+                            template.unit,
+                            template.read_only,
+                            tag_id
+                        ],
+                    )?;
+                }
+                tx.execute(
+                    "DELETE FROM device_tag_templates WHERE device_id = ?1 AND tag_name = ?2",
+                    params![device_id, tag.name],
+                )?;
+                Self::save_tag_template_link(&tx, &TagTemplateLink {
+                    device_id: device_id.clone(),
+                    tag_name: template.name.clone(),
+                    template_id,
+                    address_offset,
+                    synced_address: template.address,
+                    synced_scaling_multiplier: template.scaling_multiplier,
+                    synced_scaling_offset: template.scaling_offset,
+                })?;
+            }
+
+            if changes.is_empty() {
+                resync.unchanged += 1;
+            } else {
+                if let Some(names) = tag_names.get_mut(&device_id) {
+                    names.remove(&tag.name);
+                    names.insert(template.name.clone());
+                }
+                resync.updated.push(TemplateTagResync { device_id, tag_name: tag.name, template_id, changes, customized });
+            }
+        }
+
+        tx.commit()?;
+        Ok(resync)
+    }
+
+    fn tag_template_from_row(row: &rusqlite::Row) -> rusqlite::Result<TagTemplate> {
+        Ok(TagTemplate {
+            id: Some(row.get(0)?),
+            model_id: row.get(1)?,
+            name: row.get(2)?,
+            address: row.get::<_, i32>(3)? as u16,
+            data_type: row.get(4)?,
+            description: row.get(5)?,
+            scaling_multiplier: row.get(6)?,
+            scaling_offset: row.get(7)?,
+            unit: row.get(8)?,
+            read_only: row.get(9)?,
+            size: row.get::<_, Option<i32>>(10)?.unwrap_or(1),
+        })
+    }
+
+    /// Delete a model and its tag templates.
+    ///
+    /// While devices still use the model nothing is deleted and they are listed in the
+    /// result, unless `force` is set, in which case the devices and their tags go too.
+    pub async fn delete_device_model(&self, model_id: &str, force: bool) -> Result<DeviceModelDeletion> {
+        let conn = self.connection.lock().await;
+        
+        // Start a transaction to ensure data consistency
+        conn.execute("BEGIN TRANSACTION", [])?;
+        
+        // First, check if the model exists
+        let mut stmt = conn.prepare("SELECT COUNT(*) FROM device_models WHERE id = ?1")?;
+        let count: i64 = stmt.query_row([model_id], |row| row.get(0))?;
+        
+        if count == 0 {
+            conn.execute("ROLLBACK", [])?;
+            return Err(anyhow::anyhow!("Device model not found"));
+        }
+
+        // Then list the devices that use it
+        let mut stmt = conn.prepare("SELECT id, name FROM devices WHERE model_id = ?1 ORDER BY name, id")?;
+        let dependent_devices = stmt
+            .query_map([model_id], |row| Ok(ModelDependentDevice { id: row.get(0)?, name: row.get(1)? }))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        if !dependent_devices.is_empty() && !force {
+            conn.execute("ROLLBACK", [])?;
+            return Ok(DeviceModelDeletion { deleted: false, dependent_devices });
+        }
+        
+        // Delete all tag templates associated with this model
+        conn.execute(
+            "DELETE FROM device_tag_templates WHERE template_id IN (SELECT id FROM tag_templates WHERE model_id = ?1)",
+            [model_id],
+        )?;
+        let mut stmt = conn.prepare("DELETE FROM tag_templates WHERE model_id = ?1")?;
+        let deleted_tags = stmt.execute([model_id])?;
+        
+        // Delete the devices that use this model, with their tags and status
+        for table in ["device_tags", "device_status", "device_tag_templates", "poll_stats", "virtual_tags"] {
+            conn.execute(
+                &format!("DELETE FROM {} WHERE device_id IN (SELECT id FROM devices WHERE model_id = ?1)", table),
+                [model_id],
+            )?;
+        }
+        let mut stmt = conn.prepare("DELETE FROM devices WHERE model_id = ?1")?;
+        let deleted_devices = stmt.execute([model_id])?;
+        
+        // Finally, delete the device model itself
+        let mut stmt = conn.prepare("DELETE FROM device_models WHERE id = ?1")?;
+        let _deleted_models = stmt.execute([model_id])?;
+        
+        // Commit the transaction
+        conn.execute("COMMIT", [])?;
+        
+        info!("Deleted device model {}, {} associated tag templates, and {} devices", 
+              model_id, deleted_tags, deleted_devices);
+        Ok(DeviceModelDeletion { deleted: true, dependent_devices })
+    }
+
+    // Device Instance CRUD operations
+    pub async fn create_device(&self, device: &DeviceInstance) -> Result<()> {
+        let conn = self.connection.lock().await;
+        Self::insert_device(&conn, device)?;
+        Ok(())
+    }
+
+    /// Create a copy of another device with its own tags in one transaction. Tags the
+    /// source got from model templates stay linked to them, so resyncs reach the copy.
+    pub async fn create_device_copy(&self, source_id: &str, device: &DeviceInstance, tags: &[DeviceTag]) -> Result<()> {
+        let mut conn = self.connection.lock().await;
+        let tx = conn.transaction()?;
+
+        Self::insert_device(&tx, device)?;
+        Self::insert_device_tags(&tx, &device.id, tags)?;
+        tx.execute(
+            "INSERT INTO device_tag_templates
+             (device_id, tag_name, template_id, address_offset, synced_address, synced_scaling_multiplier, synced_scaling_offset)
+             SELECT ?1, tag_name, template_id, address_offset, synced_address, synced_scaling_multiplier, synced_scaling_offset
+             FROM device_tag_templates WHERE device_id = ?2",
+            params![device.id, source_id],
+        )?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn insert_device(conn: &Connection, device: &DeviceInstance) -> rusqlite::Result<()> {
+        let created_str = device.created_at.to_rfc3339();
+        let updated_str = device.updated_at.to_rfc3339();
+
+        conn.execute(
+            "INSERT INTO devices 
+             (id, name, serial_no, model_id, enabled, polling_interval_ms, timeout_ms, retry_count, protocol_config, tb_device_id, tb_group_id, created_at, updated_at, strict_types)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            params![
+                device.id,
+                device.name,
+                device.serial_no,
+                device.model_id,
+                device.enabled,
+                device.polling_interval_ms,
+                device.timeout_ms,
+                device.retry_count,
+                device.protocol_config,
+                device.tb_device_id,
+                device.tb_group_id,
+                created_str,
+                updated_str,
+                device.strict_types
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub async fn get_devices(&self) -> Result<Vec<DeviceInstance>> {
+        let conn = self.readers.get().await;
+        
+        let mut stmt = conn.prepare(
+            "SELECT id, name, serial_no, model_id, enabled, polling_interval_ms, timeout_ms, retry_count, 
+                    protocol_config, tb_device_id, tb_group_id, created_at, updated_at, strict_types 
+             FROM devices ORDER BY name"
+        )?;
+
+        let rows = stmt.query_map([], |row| {
+            let created_str: String = row.get("created_at")?;
+            let updated_str: String = row.get("updated_at")?;
+            let created_at = DateTime::parse_from_rfc3339(&created_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(9, "created_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(10, "updated_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+
+            Ok(DeviceInstance {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                serial_no: row.get(2)?,
+                model_id: row.get(3)?,
+                enabled: row.get(4)?,
+                polling_interval_ms: row.get::<_, i32>(5)? as u32,
+                timeout_ms: row.get::<_, i32>(6)? as u32,
+                retry_count: row.get::<_, i32>(7)? as u32,
+                protocol_config: row.get(8)?,
+                tb_device_id: row.get("tb_device_id")?,
+                tb_group_id: row.get("tb_group_id")?,
+                created_at,
+                updated_at,
+                strict_types: row.get("strict_types")?,
+            })
+        })?;
+
+        let mut devices = Vec::new();
+        for row in rows {
+            devices.push(row?);
+        }
+
+        Ok(devices)
+    }
+
+    pub async fn get_device(&self, device_id: &str) -> Result<Option<DeviceInstance>> {
+        let conn = self.readers.get().await;
+        
+        let mut stmt = conn.prepare(
+            "SELECT id, name, serial_no, model_id, enabled, polling_interval_ms, timeout_ms, retry_count, 
+                    protocol_config, tb_device_id, tb_group_id, created_at, updated_at, strict_types 
+             FROM devices WHERE id = ?1"
+        )?;
+
+        let mut rows = stmt.query_map([device_id], |row| {
+            let created_str: String = row.get(11)?;
+            let updated_str: String = row.get(12)?;
+            let created_at = DateTime::parse_from_rfc3339(&created_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(9, "created_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(10, "updated_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+
+            Ok(DeviceInstance {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                serial_no: row.get(2)?,
+                model_id: row.get(3)?,
+                enabled: row.get(4)?,
+                polling_interval_ms: row.get::<_, i32>(5)? as u32,
+                timeout_ms: row.get::<_, i32>(6)? as u32,
+                retry_count: row.get::<_, i32>(7)? as u32,
+                protocol_config: row.get(8)?,
+                tb_device_id: row.get("tb_device_id")?,
+                tb_group_id: row.get("tb_group_id")?,
+                created_at,
+                updated_at,
+                strict_types: row.get("strict_types")?,
+            })
+        })?;
+
+        match rows.next() {
+            Some(row) => Ok(Some(row?)),
+            None => Ok(None),
+        }
+    }
+
+    // Device Tag CRUD operations
+    pub async fn create_device_tags(&self, device_id: &str, tags: &[DeviceTag]) -> Result<()> {
+        let conn = self.connection.lock().await;
+        Self::insert_device_tags(&conn, device_id, tags)?;
+        Ok(())
+    }
+
+    fn insert_device_tags(conn: &Connection, device_id: &str, tags: &[DeviceTag]) -> rusqlite::Result<()> {
+        for tag in tags {
+            conn.execute(
+                "INSERT INTO device_tags 
+                 (device_id, name, address, size, data_type, description, scaling_multiplier, scaling_offset, unit, read_only, enabled, schedule_group_id, agg_to_field, write_policy, byte_order, deadband_absolute, deadband_percent, register_type)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18)",
+                params![
+                    device_id,
+                    tag.name,
+                    tag.address,
+                    tag.size,
+                    tag.data_type,
+                    tag.description,
+                    tag.scaling_multiplier,
+                    tag.scaling_offset,
+                    tag.unit,
+                    tag.read_only,
+                    tag.enabled,
+                    tag.schedule_group_id,
+                    tag.agg_to_field,
+                    tag.write_policy.as_str(),
+                    tag.byte_order.map(|order| order.as_str()),
+                    tag.deadband_absolute,
+                    tag.deadband_percent,
+                    tag.register_type.map(|t| t.as_str())
+                ],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_device_tags(&self, device_id: &str) -> Result<Vec<DeviceTag>> {
+        let conn = self.readers.get().await;
+        Ok(Self::select_device_tags(&conn, device_id)?)
+    }
+
+    fn select_device_tags(conn: &Connection, device_id: &str) -> rusqlite::Result<Vec<DeviceTag>> {
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM device_tags WHERE device_id = ?1 ORDER BY address", DEVICE_TAG_COLUMNS))?;
+        let tags = stmt.query_map([device_id], Self::device_tag_from_row)?.collect();
+        tags
+    }
+
+    /// One tag of a device, by id
+    pub async fn get_device_tag(&self, device_id: &str, tag_id: i64) -> Result<Option<DeviceTag>> {
+        let conn = self.readers.get().await;
+        match conn.query_row(
+            &format!("SELECT {} FROM device_tags WHERE device_id = ?1 AND id = ?2", DEVICE_TAG_COLUMNS),
+            params![device_id, tag_id],
+            Self::device_tag_from_row,
+        ) {
+            Ok(tag) => Ok(Some(tag)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn device_tag_from_row(row: &rusqlite::Row) -> rusqlite::Result<DeviceTag> {
+        Ok(DeviceTag {
+            id: Some(row.get(0)?),
+            device_id: row.get(1)?,
+            name: row.get(2)?,
+            address: row.get::<_, i32>(3)? as u16,
+            size: row.get(4)?,
+            data_type: row.get(5)?,
+            description: row.get(6)?,
+            scaling_multiplier: row.get(7)?,
+            scaling_offset: row.get(8)?,
+            unit: row.get(9)?,
+            read_only: row.get(10)?,
+            enabled: row.get(11)?,
+            schedule_group_id: row.get(12)?,
+            agg_to_field: row.get(13)?,
+            write_policy: TagWritePolicy::parse(&row.get::<_, String>(14)?),
+            byte_order: row.get::<_, Option<String>>(15)?.as_deref().and_then(ByteOrder::parse),
+            deadband_absolute: row.get(16)?,
+            deadband_percent: row.get(17)?,
+            register_type: row.get::<_, Option<String>>(18)?.as_deref().and_then(RegisterType::parse),
+        })
+    }
+
+    /// Add one tag to a device, returning it with its new id
+    pub async fn add_device_tag(&self, tag: &DeviceTag) -> Result<DeviceTag> {
+        let conn = self.connection.lock().await;
+        Self::insert_device_tags(&conn, &tag.device_id, std::slice::from_ref(tag))?;
+        Ok(DeviceTag { id: Some(conn.last_insert_rowid()), ..tag.clone() })
+    }
+
+    /// Save every field of a tag in place, keeping its id.
+    ///
+    /// Returns false when the device has no tag with this id.
+    pub async fn update_device_tag(&self, tag: &DeviceTag) -> Result<bool> {
+        let conn = self.connection.lock().await;
+        Ok(Self::update_device_tag_row(&conn, tag)? > 0)
+    }
+
+    fn update_device_tag_row(conn: &Connection, tag: &DeviceTag) -> rusqlite::Result<usize> {
+        conn.execute(
+            "UPDATE device_tags
+             SET name = ?1, address = ?2, size = ?3, data_type = ?4, description = ?5, scaling_multiplier = ?6,
+                 scaling_offset = ?7, unit = ?8, read_only = ?9, enabled = ?10, schedule_group_id = ?11,
+                 agg_to_field = ?12, write_policy = ?13, byte_order = ?14, deadband_absolute = ?15, deadband_percent = ?16,
+                 register_type = ?17
+             WHERE id = ?18 AND device_id = ?19",
+            params![
+                tag.name,
+                tag.address,
+                tag.size,
+                tag.data_type,
+                tag.description,
+                tag.scaling_multiplier,
+                tag.scaling_offset,
+                tag.unit,
+                tag.read_only,
+                tag.enabled,
+                tag.schedule_group_id,
+                tag.agg_to_field,
+                tag.write_policy.as_str(),
+                tag.byte_order.map(|order| order.as_str()),
+                tag.deadband_absolute,
+                tag.deadband_percent,
+                tag.register_type.map(|t| t.as_str()),
+                tag.id,
+                tag.device_id
+            ],
+        )
+    }
+
+    /// Remove one tag of a device.
+    ///
+    /// Returns false when the device has no tag with this id.
+    pub async fn delete_device_tag(&self, device_id: &str, tag_id: i64) -> Result<bool> {
+        let conn = self.connection.lock().await;
+        let rows_affected = conn.execute("DELETE FROM device_tags WHERE id = ?1 AND device_id = ?2", params![tag_id, device_id])?;
+        Ok(rows_affected > 0)
+    }
+
+    /// Make a device's stored tags match `tags`, pairing them up by name. Unchanged tags are
+    /// left alone and changed ones updated in place, so both keep their ids; the rest are
+    /// inserted or deleted.
+    pub async fn sync_device_tags(&self, device_id: &str, tags: &[DeviceTag]) -> Result<TagSyncCounts> {
+        let mut conn = self.connection.lock().await;
+        let tx = conn.transaction()?;
+        let existing = Self::select_device_tags(&tx, device_id)?;
+        let mut kept = HashSet::new();
+        let mut counts = TagSyncCounts::default();
+
+        for tag in tags {
+            match existing.iter().find(|old| old.name == tag.name && !kept.contains(&old.id)) {
+                Some(old) => {
+                    kept.insert(old.id);
+                    let tag = DeviceTag { id: old.id, device_id: device_id.to_string(), ..tag.clone() };
+                    if tag == *old {
+                        counts.unchanged += 1;
+                    } else {
+                        Self::update_device_tag_row(&tx, &tag)?;
+                        counts.updated += 1;
+                    }
+                }
+                None => {
+                    Self::insert_device_tags(&tx, device_id, std::slice::from_ref(tag))?;
+                    counts.inserted += 1;
+                }
+            }
+        }
+        for old in existing.iter().filter(|old| !kept.contains(&old.id)) {
+            tx.execute("DELETE FROM device_tags WHERE id = ?1", [old.id])?;
+            counts.deleted += 1;
+        }
+
+        tx.commit()?;
+        Ok(counts)
+    }
+
+    pub async fn update_device(&self, device: &DeviceInstance) -> Result<()> {
+        let conn = self.connection.lock().await;
+        let updated_str = device.updated_at.to_rfc3339();
+
+        conn.execute(
+            "UPDATE devices 
+             SET name = ?1, serial_no = ?2, model_id = ?3, enabled = ?4, polling_interval_ms = ?5, 
+                 timeout_ms = ?6, retry_count = ?7, protocol_config = ?8, tb_device_id = ?9, tb_group_id = ?10, updated_at = ?11,
+                 strict_types = ?13, tb_access_token = CASE WHEN tb_device_id IS ?9 THEN tb_access_token END
+             WHERE id = ?12",
+            params![
+                device.name,
+                device.serial_no,
+                device.model_id,
+                device.enabled,
+                device.polling_interval_ms,
+                device.timeout_ms,
+                device.retry_count,
+                device.protocol_config,
+                device.tb_device_id,
+                device.tb_group_id,
+                updated_str,
+                device.id,
+                device.strict_types
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Get unsynced devices (devices without ThingsBoard device ID and group ID)
+    pub async fn get_unsynced_devices(&self) -> Result<Vec<DeviceInstance>> {
+        let conn = self.readers.get().await;
+        
+        let mut stmt = conn.prepare(
+            "SELECT id, name, serial_no, model_id, enabled, polling_interval_ms, timeout_ms, retry_count, 
+                    protocol_config, tb_device_id, tb_group_id, created_at, updated_at, strict_types 
+             FROM devices 
+             WHERE (tb_device_id IS NULL OR tb_device_id = '') AND (tb_group_id IS NULL OR tb_group_id = '')
+             ORDER BY name, id"
+        )?;
+
+        let rows = stmt.query_map([], |row| {
+            let created_str: String = row.get("created_at")?;
+            let updated_str: String = row.get("updated_at")?;
+            let created_at = DateTime::parse_from_rfc3339(&created_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(9, "created_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(10, "updated_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+
+            Ok(DeviceInstance {
+                id: row.get("id")?,
+                name: row.get("name")?,
+                serial_no: row.get("serial_no")?,
+                model_id: row.get("model_id")?,
+                enabled: row.get("enabled")?,
+                polling_interval_ms: row.get::<_, i32>("polling_interval_ms")? as u32,
+                timeout_ms: row.get::<_, i32>("timeout_ms")? as u32,
+                retry_count: row.get::<_, i32>("retry_count")? as u32,
+                protocol_config: row.get("protocol_config")?,
+                tb_device_id: row.get("tb_device_id")?,
+                tb_group_id: row.get("tb_group_id")?,
+                created_at,
+                updated_at,
+                strict_types: row.get("strict_types")?,
+            })
+        })?;
+
+        let mut devices = Vec::new();
+        for row in rows {
+            devices.push(row?);
+        }
+
+        Ok(devices)
+    }
+
+    /// Get devices by ThingsBoard group ID
+    pub async fn get_devices_by_group_id(&self, group_id: &str) -> Result<Vec<DeviceInstance>> {
+        let conn = self.readers.get().await;
+        
+        let mut stmt = conn.prepare(
+            "SELECT id, name, serial_no, model_id, enabled, polling_interval_ms, timeout_ms, retry_count, 
+                    protocol_config, tb_device_id, tb_group_id, created_at, updated_at, strict_types 
+             FROM devices 
+             WHERE tb_group_id = ?1
+             ORDER BY name"
+        )?;
+
+        let rows = stmt.query_map([group_id], |row| {
+            let created_str: String = row.get("created_at")?;
+            let updated_str: String = row.get("updated_at")?;
+            let created_at = DateTime::parse_from_rfc3339(&created_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(9, "created_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(10, "updated_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+
+            Ok(DeviceInstance {
+                id: row.get("id")?,
+                name: row.get("name")?,
+                serial_no: row.get("serial_no")?,
+                model_id: row.get("model_id")?,
+                enabled: row.get("enabled")?,
+                polling_interval_ms: row.get::<_, i32>("polling_interval_ms")? as u32,
+                timeout_ms: row.get::<_, i32>("timeout_ms")? as u32,
+                retry_count: row.get::<_, i32>("retry_count")? as u32,
+                protocol_config: row.get("protocol_config")?,
+                tb_device_id: row.get("tb_device_id")?,
+                tb_group_id: row.get("tb_group_id")?,
+                created_at,
+                updated_at,
+                strict_types: row.get("strict_types")?,
+            })
+        })?;
+
+        let mut devices = Vec::new();
+        for row in rows {
+            devices.push(row?);
+        }
+
+        Ok(devices)
+    }
+
+    pub async fn delete_device_tags(&self, device_id: &str) -> Result<()> {
+        let conn = self.connection.lock().await;
+        
+        conn.execute(
+            "DELETE FROM device_tags WHERE device_id = ?1",
+            params![device_id],
+        )?;
+
+        Ok(())
+    }
+
+    pub async fn delete_device(&self, device_id: &str) -> Result<()> {
+        let conn = self.connection.lock().await;
+        
+        // Start a transaction to ensure data consistency
+        conn.execute("BEGIN TRANSACTION", [])?;
+        
+        // First, check if the device exists
+        let mut stmt = conn.prepare("SELECT COUNT(*) FROM devices WHERE id = ?1")?;
+        let count: i64 = stmt.query_row([device_id], |row| row.get(0))?;
+        
+        if count == 0 {
+            conn.execute("ROLLBACK", [])?;
+            return Err(anyhow::anyhow!("Device not found"));
+        }
+        
+        // Delete all device tags first
+        let mut stmt = conn.prepare("DELETE FROM device_tags WHERE device_id = ?1")?;
+        let deleted_tags = stmt.execute([device_id])?;
+        conn.execute("DELETE FROM device_tag_templates WHERE device_id = ?1", [device_id])?;
+        conn.execute("DELETE FROM tb_child_devices WHERE device_id = ?1", [device_id])?;
+        conn.execute("DELETE FROM alarm_rules WHERE device_id = ?1", [device_id])?;
+        conn.execute("DELETE FROM virtual_tags WHERE device_id = ?1", [device_id])?;
+        conn.execute(
+            "UPDATE alarm_events SET cleared_at = ?1 WHERE device_id = ?2 AND cleared_at IS NULL",
+            params![Utc::now().to_rfc3339(), device_id],
+        )?;
+        
+        conn.execute("DELETE FROM poll_stats WHERE device_id = ?1", [device_id])?;
+        // Delete device status
+        let mut stmt = conn.prepare("DELETE FROM device_status WHERE device_id = ?1")?;
+        let _deleted_status = stmt.execute([device_id])?;
+        
+        // Finally, delete the device itself
+        let mut stmt = conn.prepare("DELETE FROM devices WHERE id = ?1")?;
+        let _deleted_device = stmt.execute([device_id])?;
+        
+        // Commit the transaction
+        conn.execute("COMMIT", [])?;
+        
+        info!("Deleted device {} and {} associated tags", device_id, deleted_tags);
+        Ok(())
+    }
+
+    // Schedule Group CRUD operations
+    pub async fn get_schedule_groups(&self) -> Result<Vec<ScheduleGroup>> {
+        let conn = self.readers.get().await;
+        
+        let mut stmt = conn.prepare(
+            "SELECT id, name, polling_interval_ms, description, enabled, created_at, updated_at 
+             FROM schedule_groups ORDER BY polling_interval_ms"
+        )?;
+
+        let rows = stmt.query_map([], |row| {
+            let created_str: String = row.get(5)?;
+            let updated_str: String = row.get(6)?;
+            let created_at = DateTime::parse_from_rfc3339(&created_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(5, "created_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(6, "updated_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+
+            Ok(ScheduleGroup {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                polling_interval_ms: row.get::<_, i32>(2)? as u32,
+                description: row.get(3)?,
+                enabled: row.get(4)?,
+                created_at,
+                updated_at,
+            })
+        })?;
+
+        let mut schedule_groups = Vec::new();
+        for row in rows {
+            schedule_groups.push(row?);
+        }
+
+        Ok(schedule_groups)
+    }
+
+    pub async fn get_schedule_group(&self, id: &str) -> Result<Option<ScheduleGroup>> {
+        let conn = self.readers.get().await;
+        
+        let mut stmt = conn.prepare(
+            "SELECT id, name, polling_interval_ms, description, enabled, created_at, updated_at 
+             FROM schedule_groups WHERE id = ?1"
+        )?;
+
+        let result = stmt.query_row([id], |row| {
+            let created_str: String = row.get(5)?;
+            let updated_str: String = row.get(6)?;
+            let created_at = DateTime::parse_from_rfc3339(&created_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(5, "created_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(6, "updated_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+
+            Ok(ScheduleGroup {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                polling_interval_ms: row.get::<_, i32>(2)? as u32,
+                description: row.get(3)?,
+                enabled: row.get(4)?,
+                created_at,
+                updated_at,
+            })
+        });
+
+        match result {
+            Ok(schedule_group) => Ok(Some(schedule_group)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn create_schedule_group(&self, schedule_group: &ScheduleGroup) -> Result<()> {
+        let conn = self.connection.lock().await;
+        let created_str = schedule_group.created_at.to_rfc3339();
+        let updated_str = schedule_group.updated_at.to_rfc3339();
+
+        conn.execute(
+            "INSERT INTO schedule_groups (id, name, polling_interval_ms, description, enabled, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                schedule_group.id,
+                schedule_group.name,
+                schedule_group.polling_interval_ms,
+                schedule_group.description,
+                schedule_group.enabled,
+                created_str,
+                updated_str
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub async fn update_schedule_group(&self, schedule_group: &ScheduleGroup) -> Result<()> {
+        let conn = self.connection.lock().await;
+        let updated_str = schedule_group.updated_at.to_rfc3339();
+
+        conn.execute(
+            "UPDATE schedule_groups SET name = ?1, polling_interval_ms = ?2, description = ?3, 
+             enabled = ?4, updated_at = ?5 WHERE id = ?6",
+            params![
+                schedule_group.name,
+                schedule_group.polling_interval_ms,
+                schedule_group.description,
+                schedule_group.enabled,
+                updated_str,
+                schedule_group.id
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub async fn delete_schedule_group(&self, id: &str) -> Result<()> {
+        let conn = self.connection.lock().await;
+        
+        conn.execute(
+            "DELETE FROM schedule_groups WHERE id = ?1",
+            params![id],
+        )?;
+
+        Ok(())
+    }
+
+    // Modbus TCP Tag Register CRUD operations
+    pub async fn create_modbus_tcp_tag_register(&self, tag_register: &CreateModbusTcpTagRegister) -> Result<ModbusTcpTagRegister> {
+        let conn = self.connection.lock().await;
+        let now = Utc::now();
+        let created_str = now.to_rfc3339();
+        let updated_str = now.to_rfc3339();
+
+        conn.execute(
+            &format!("INSERT INTO modbus_tcp_tag_registers (
+                device_brand, device_model, ava_type, mppt, input, data_label, 
+                address, size, modbus_type, divider, register_type, created_at, updated_at,
+                deadband_absolute, deadband_percent, agg_to_field, model_id
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, {})", MODEL_ID_BY_NAME),
+            params![
+                tag_register.device_brand,
+                tag_register.device_model,
+                tag_register.ava_type,
+                tag_register.mppt,
+                tag_register.input,
+                tag_register.data_label,
+                tag_register.address,
+                tag_register.size,
+                tag_register.modbus_type,
+                tag_register.divider,
+                tag_register.register_type,
+                created_str,
+                updated_str,
+                tag_register.deadband_absolute,
+                tag_register.deadband_percent,
+                tag_register.agg_to_field
+            ],
+        )?;
+
+        let id = conn.last_insert_rowid();
+        let model_id = conn.query_row("SELECT model_id FROM modbus_tcp_tag_registers WHERE id = ?1", [id], |row| row.get(0))?;
+
+        Ok(ModbusTcpTagRegister {
+            id: Some(id),
+            device_brand: tag_register.device_brand.clone(),
+            device_model: tag_register.device_model.clone(),
+            ava_type: tag_register.ava_type.clone(),
+            mppt: tag_register.mppt,
+            input: tag_register.input,
+            data_label: tag_register.data_label.clone(),
+            address: tag_register.address,
+            size: tag_register.size,
+            modbus_type: tag_register.modbus_type.clone(),
+            divider: tag_register.divider,
+            register_type: tag_register.register_type.clone(),
+            deadband_absolute: tag_register.deadband_absolute,
+            deadband_percent: tag_register.deadband_percent,
+            agg_to_field: tag_register.agg_to_field.clone(),
+            created_at: now,
+            updated_at: now,
+            model_id,
+        })
+    }
+
+    /// Store an uploaded register map in one transaction, so a failure leaves the previous map as it was.
+    /// Without a `model_id` the rows are linked to the device model matching their brand and name.
+    pub async fn bulk_insert_modbus_tcp_tag_registers(&self, tag_registers: Vec<CreateModbusTcpTagRegister>, model_id: Option<&str>, mode: RegisterImportMode) -> Result<RegisterImportCounts> {
+        let conn = self.connection.lock().await;
+        let tx = conn.unchecked_transaction()?;
+        let now = Utc::now();
+        let created_str = now.to_rfc3339();
+        let updated_str = now.to_rfc3339();
+
+        let mut counts = RegisterImportCounts::default();
+
+        // A model's stored map is found by its id as well as its brand and name, which may
+        // have changed since the map was uploaded
+        if mode == RegisterImportMode::Replace {
+            let devices: std::collections::BTreeSet<(&str, &str)> = tag_registers
+                .iter()
+                .map(|tag_register| (tag_register.device_brand.as_str(), tag_register.device_model.as_str()))
+                .collect();
+            for (device_brand, device_model) in devices {
+                counts.deleted += tx.execute(
+                    "DELETE FROM modbus_tcp_tag_registers WHERE (device_brand = ?1 AND device_model = ?2) OR model_id = ?3",
+                    params![device_brand, device_model, model_id],
+                )? as u64;
+            }
+        }
+
+        for tag_register in tag_registers {
+            // MPPT and INPUT are NULL for inverter-level rows, which the UNIQUE constraint never
+            // matches, so existing rows are looked up with IS
+            if mode == RegisterImportMode::Upsert {
+                let updated = tx.execute(
+                    "UPDATE modbus_tcp_tag_registers SET
+                        device_brand = ?1, device_model = ?2, ava_type = ?6, data_label = ?7, size = ?8, modbus_type = ?9,
+                        divider = ?10, register_type = ?11, updated_at = ?12, deadband_absolute = ?13, deadband_percent = ?14,
+                        model_id = COALESCE(?15, model_id), agg_to_field = ?16
+                     WHERE ((device_brand = ?1 AND device_model = ?2) OR model_id = ?15) AND address = ?3 AND mppt IS ?4 AND input IS ?5",
+                    params![
+                        tag_register.device_brand,
+                        tag_register.device_model,
+                        tag_register.address,
+                        tag_register.mppt,
+                        tag_register.input,
+                        tag_register.ava_type,
+                        tag_register.data_label,
+                        tag_register.size,
+                        tag_register.modbus_type,
+                        tag_register.divider,
+                        tag_register.register_type,
+                        updated_str,
+                        tag_register.deadband_absolute,
+                        tag_register.deadband_percent,
+                        model_id,
+                        tag_register.agg_to_field
+                    ],
+                )?;
+                if updated > 0 {
+                    counts.updated += 1;
+                    continue;
+                }
+            }
+
+            tx.execute(
+                &format!("INSERT OR REPLACE INTO modbus_tcp_tag_registers (
+                    device_brand, device_model, ava_type, mppt, input, data_label, 
+                    address, size, modbus_type, divider, register_type, created_at, updated_at,
+                    deadband_absolute, deadband_percent, agg_to_field, model_id
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, COALESCE(?17, {}))", MODEL_ID_BY_NAME),
+                params![
+                    tag_register.device_brand,
+                    tag_register.device_model,
+                    tag_register.ava_type,
+                    tag_register.mppt,
+                    tag_register.input,
+                    tag_register.data_label,
+                    tag_register.address,
+                    tag_register.size,
+                    tag_register.modbus_type,
+                    tag_register.divider,
+                    tag_register.register_type,
+                    created_str,
+                    updated_str,
+                    tag_register.deadband_absolute,
+                    tag_register.deadband_percent,
+                    tag_register.agg_to_field,
+                    model_id
+                ],
+            )?;
+            
+            counts.inserted += 1;
+        }
+
+        tx.commit()?;
+        Ok(counts)
+    }
+
+    pub async fn get_modbus_tcp_tag_registers_by_device(&self, device_brand: &str, device_model: &str) -> Result<Vec<ModbusTcpTagRegister>> {
+        let conn = self.readers.get().await;
+        
+        let mut stmt = conn.prepare(
+            "SELECT id, device_brand, device_model, ava_type, mppt, input, data_label, 
+                    address, size, modbus_type, divider, register_type, created_at, updated_at,
+                    deadband_absolute, deadband_percent, model_id, agg_to_field
+             FROM modbus_tcp_tag_registers 
+             WHERE device_brand = ?1 AND device_model = ?2 
+             ORDER BY ava_type, mppt, input, address ASC"
+        )?;
+
+        let rows = stmt.query_map([device_brand, device_model], |row| {
+            let created_str: String = row.get(12)?;
+            let updated_str: String = row.get(13)?;
+            let created_at = DateTime::parse_from_rfc3339(&created_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(12, "created_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(13, "updated_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+
+            Ok(ModbusTcpTagRegister {
+                id: Some(row.get(0)?),
+                device_brand: row.get(1)?,
+                device_model: row.get(2)?,
+                ava_type: row.get(3)?,
+                mppt: row.get(4)?,
+                input: row.get(5)?,
+                data_label: row.get(6)?,
+                address: row.get(7)?,
+                size: row.get(8)?,
+                modbus_type: row.get(9)?,
+                divider: row.get(10)?,
+                register_type: row.get(11)?,
+                deadband_absolute: row.get(14)?,
+                deadband_percent: row.get(15)?,
+                model_id: row.get(16)?,
+                agg_to_field: row.get(17)?,
+                created_at,
+                updated_at,
+            })
+        })?;
+
+        let mut tag_registers = Vec::new();
+        for row in rows {
+            tag_registers.push(row?);
+        }
+
+        Ok(tag_registers)
+    }
+
+    pub async fn get_modbus_tcp_tag_registers_by_model(&self, device_model: &str) -> Result<Vec<ModbusTcpTagRegister>> {
+        let conn = self.readers.get().await;
+        
+        let mut stmt = conn.prepare(
+            "SELECT id, device_brand, device_model, ava_type, mppt, input, data_label, 
+                    address, size, modbus_type, divider, register_type, created_at, updated_at,
+                    deadband_absolute, deadband_percent, model_id, agg_to_field
+             FROM modbus_tcp_tag_registers 
+             WHERE device_model = ?1 
+             ORDER BY ava_type, mppt, input, address ASC"
+        )?;
+
+        let rows = stmt.query_map([device_model], |row| {
+            let created_str: String = row.get(12)?;
+            let updated_str: String = row.get(13)?;
+            let created_at = DateTime::parse_from_rfc3339(&created_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(12, "created_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(13, "updated_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+
+            Ok(ModbusTcpTagRegister {
+                id: Some(row.get(0)?),
+                device_brand: row.get(1)?,
+                device_model: row.get(2)?,
+                ava_type: row.get(3)?,
+                mppt: row.get(4)?,
+                input: row.get(5)?,
+                data_label: row.get(6)?,
+                address: row.get(7)?,
+                size: row.get(8)?,
+                modbus_type: row.get(9)?,
+                divider: row.get(10)?,
+                register_type: row.get(11)?,
+                deadband_absolute: row.get(14)?,
+                deadband_percent: row.get(15)?,
+                model_id: row.get(16)?,
+                agg_to_field: row.get(17)?,
+                created_at,
+                updated_at,
+            })
+        })?;
+
+        let mut tag_registers = Vec::new();
+        for row in rows {
+            tag_registers.push(row?);
+        }
+
+        Ok(tag_registers)
+    }
+
+    pub async fn get_modbus_tcp_tag_registers_by_model_id(&self, model_id: &str) -> Result<Vec<ModbusTcpTagRegister>> {
+        let conn = self.readers.get().await;
+        
+        // Rows linked to the model, and unlinked ones still matching its brand and name
+        let mut stmt = conn.prepare(&format!(
+            "SELECT mtr.id, mtr.device_brand, mtr.device_model, mtr.ava_type, mtr.mppt, mtr.input, 
+                    mtr.data_label, mtr.address, mtr.size, mtr.modbus_type, mtr.divider, mtr.register_type, 
+                    mtr.created_at, mtr.updated_at, mtr.deadband_absolute, mtr.deadband_percent, mtr.model_id, mtr.agg_to_field
+             FROM modbus_tcp_tag_registers mtr
+             WHERE {}
+             ORDER BY mtr.ava_type, mtr.mppt, mtr.input, mtr.address ASC",
+            REGISTERS_OF_MODEL
+        ))?;
+
+        let rows = stmt.query_map([model_id], |row| {
+            let created_str: String = row.get(12)?;
+            let updated_str: String = row.get(13)?;
+            let created_at = DateTime::parse_from_rfc3339(&created_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(12, "created_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(13, "updated_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+
+            Ok(ModbusTcpTagRegister {
+                id: Some(row.get(0)?),
+                device_brand: row.get(1)?,
+                device_model: row.get(2)?,
+                ava_type: row.get(3)?,
+                mppt: row.get(4)?,
+                input: row.get(5)?,
+                data_label: row.get(6)?,
+                address: row.get(7)?,
+                size: row.get(8)?,
+                modbus_type: row.get(9)?,
+                divider: row.get(10)?,
+                register_type: row.get(11)?,
+                deadband_absolute: row.get(14)?,
+                deadband_percent: row.get(15)?,
+                model_id: row.get(16)?,
+                agg_to_field: row.get(17)?,
+                created_at,
+                updated_at,
+            })
+        })?;
+
+        let mut tag_registers = Vec::new();
+        for row in rows {
+            tag_registers.push(row?);
+        }
+
+        Ok(tag_registers)
+    }
+
+    pub async fn get_all_modbus_tcp_tag_registers(&self) -> Result<Vec<ModbusTcpTagRegister>> {
+        let conn = self.readers.get().await;
+        
+        let mut stmt = conn.prepare(
+            "SELECT id, device_brand, device_model, ava_type, mppt, input, data_label, 
+                    address, size, modbus_type, divider, register_type, created_at, updated_at,
+                    deadband_absolute, deadband_percent, model_id, agg_to_field
+             FROM modbus_tcp_tag_registers 
+             ORDER BY device_brand, device_model, ava_type, mppt, input, address ASC"
+        )?;
+
+        let rows = stmt.query_map([], |row| {
+            let created_str: String = row.get(12)?;
+            let updated_str: String = row.get(13)?;
+            let created_at = DateTime::parse_from_rfc3339(&created_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(12, "created_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(13, "updated_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+
+            Ok(ModbusTcpTagRegister {
+                id: Some(row.get(0)?),
+                device_brand: row.get(1)?,
+                device_model: row.get(2)?,
+                ava_type: row.get(3)?,
+                mppt: row.get(4)?,
+                input: row.get(5)?,
+                data_label: row.get(6)?,
+                address: row.get(7)?,
+                size: row.get(8)?,
+                modbus_type: row.get(9)?,
+                divider: row.get(10)?,
+                register_type: row.get(11)?,
+                deadband_absolute: row.get(14)?,
+                deadband_percent: row.get(15)?,
+                model_id: row.get(16)?,
+                agg_to_field: row.get(17)?,
+                created_at,
+                updated_at,
+            })
+        })?;
+
+        let mut tag_registers = Vec::new();
+        for row in rows {
+            tag_registers.push(row?);
+        }
+
+        Ok(tag_registers)
+    }
+
+    pub async fn delete_modbus_tcp_tag_registers_by_device(&self, device_brand: &str, device_model: &str) -> Result<u64> {
+        let conn = self.connection.lock().await;
+        
+        let result = conn.execute(
+            "DELETE FROM modbus_tcp_tag_registers WHERE device_brand = ?1 AND device_model = ?2",
+            params![device_brand, device_model],
+        )?;
+
+        Ok(result as u64)
+    }
+
+    /// Remove the register map of a device model, e.g. once the model is retired
+    pub async fn delete_modbus_tcp_tag_registers_by_model_id(&self, model_id: &str) -> Result<u64> {
+        let conn = self.connection.lock().await;
+
+        let result = conn.execute(
+            &format!("DELETE FROM modbus_tcp_tag_registers AS mtr WHERE {}", REGISTERS_OF_MODEL),
+            params![model_id],
+        )?;
+
+        Ok(result as u64)
+    }
+
+    /// Update local device with ThingsBoard device ID after sync
+    /// This maintains the relationship between local and ThingsBoard devices
+    /// without changing the primary key
+    pub async fn update_device_thingsboard_id(&self, local_device_id: &str, thingsboard_device_id: &str) -> Result<()> {
+        let conn = self.connection.lock().await;
+        
+        // Check if the local device exists
+        let mut stmt = conn.prepare("SELECT COUNT(*) FROM devices WHERE id = ?1")?;
+        let count: i64 = stmt.query_row([local_device_id], |row| row.get(0))?;
+        
+        if count == 0 {
+            return Err(anyhow::anyhow!("Local device with ID {} not found", local_device_id));
+        }
+        
+        // Update the tb_device_id field
+        let updated_str = Utc::now().to_rfc3339();
+        let mut stmt = conn.prepare(
+            "UPDATE devices SET tb_device_id = ?1, updated_at = ?2,
+                 tb_access_token = CASE WHEN tb_device_id IS ?1 THEN tb_access_token END
+             WHERE id = ?3"
+        )?;
+        stmt.execute([thingsboard_device_id, &updated_str, local_device_id])?;
+        
+        info!("Successfully updated device {} with ThingsBoard ID: {}", local_device_id, thingsboard_device_id);
+        Ok(())
+    }
+
+    /// Batch update multiple devices with ThingsBoard device IDs and group IDs after sync
+    /// This is more efficient when updating many devices at once
+    pub async fn batch_update_devices_thingsboard_ids(&self, device_id_mappings: &[(String, String, String)]) -> Result<Vec<String>> {
+        if device_id_mappings.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let conn = self.connection.lock().await;
+        let mut successful_updates = Vec::new();
+        let mut failed_updates = Vec::new();
+        
+        // Start a transaction
+        conn.execute("BEGIN TRANSACTION", [])?;
+        
+        for (local_id, thingsboard_id, group_id) in device_id_mappings {
+            // Check if local device exists
+            let mut stmt = conn.prepare("SELECT COUNT(*) FROM devices WHERE id = ?1")?;
+            let local_exists: i64 = stmt.query_row([local_id], |row| row.get(0))?;
+            
+            if local_exists == 0 {
+                failed_updates.push(format!("Local device {} not found", local_id));
+                continue;
+            }
+            
+            // Update both tb_device_id and tb_group_id fields
+            let updated_str = Utc::now().to_rfc3339();
+            let mut stmt = conn.prepare(
+                "UPDATE devices SET tb_device_id = ?1, tb_group_id = ?2, updated_at = ?3,
+                     tb_access_token = CASE WHEN tb_device_id IS ?1 THEN tb_access_token END
+                 WHERE id = ?4",
+            )?;
+            
+            match stmt.execute([thingsboard_id, group_id, &updated_str, local_id]) {
+                Ok(_) => {
+                    successful_updates.push(format!("{} -> {} (group: {})", local_id, thingsboard_id, group_id));
+                    info!("Updated device {} with ThingsBoard ID: {} and Group ID: {}", local_id, thingsboard_id, group_id);
+                }
+                Err(e) => {
+                    failed_updates.push(format!("Failed to update {}: {}", local_id, e));
+                }
+            }
+        }
+        
+        if !failed_updates.is_empty() {
+            warn!("Some ThingsBoard ID updates failed: {:?}", failed_updates);
+        }
+        
+        // Commit the transaction
+        conn.execute("COMMIT", [])?;
+        
+        info!("Batch ThingsBoard ID update completed. Success: {}, Failed: {}", 
+              successful_updates.len(), failed_updates.len());
+        
+        Ok(successful_updates)
+    }
+
+    /// Get device AVA type based on the database flow:
+    /// devices.model_id -> device_models.id -> modbus_tcp_tag_registers.model_id (or device_model for unlinked rows) -> modbus_tcp_tag_registers.ava_type
+    /// For devices with multiple AVA types, prioritize in order: Inverter, PowerMeter, Meter, MPPT, String
+    pub async fn get_device_ava_type(&self, device_id: &str) -> Result<Option<String>> {
+        let conn = self.readers.get().await;
+        
+        let mut stmt = conn.prepare("
+            SELECT DISTINCT mtr.ava_type
+            FROM devices d
+            JOIN device_models dm ON d.model_id = dm.id
+            JOIN modbus_tcp_tag_registers mtr ON mtr.model_id = dm.id OR (mtr.model_id IS NULL AND dm.name = mtr.device_model)
+            WHERE d.id = ?1
+            ORDER BY
+                CASE mtr.ava_type
+                    WHEN 'Inverter' THEN 1
+                    WHEN 'Weather Station' THEN 2
+                    WHEN 'PowerMeter' THEN 3
+                    WHEN 'Meter' THEN 4
+                    WHEN 'MPPT' THEN 5
+                    WHEN 'String' THEN 6
+                    ELSE 7
+                END
+            LIMIT 1
+        ")?;
+        
+        let result = stmt.query_row([device_id], |row| {
+            row.get::<_, String>(0)
+        });
+        
+        match result {
+            Ok(ava_type) => Ok(Some(ava_type)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get device model name for a given device ID
+    pub async fn get_device_model_name(&self, device_id: &str) -> Result<Option<String>> {
+        let conn = self.readers.get().await;
+        
+        let mut stmt = conn.prepare("
+            SELECT dm.name
+            FROM devices d
+            JOIN device_models dm ON d.model_id = dm.id
+            WHERE d.id = ?1
+        ")?;
+        
+        let result = stmt.query_row([device_id], |row| {
+            row.get::<_, String>(0)
+        });
+        
+        match result {
+            Ok(model_name) => Ok(Some(model_name)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Authentication methods
+    
+    /// Verify user credentials and return user info if valid
+    ///
+    /// Legacy hashes (plaintext or the old seeded placeholders) and hashes with weaker
+    /// parameters than current ones are replaced with a fresh hash on a successful check.
+    pub async fn verify_user(&self, username: &str, password: &str) -> Result<Option<LocalUser>> {
+        let result = {
+            let conn = self.readers.get().await;
+            
+            let mut stmt = conn.prepare(
+                "SELECT id, username, password_hash, role, must_change_password FROM local_users WHERE username = ?1 AND disabled = 0"
+            )?;
+            
+            stmt.query_row([username], |row| {
+                Ok(LocalUser {
+                    id: Some(row.get(0)?),
+                    username: row.get(1)?,
+                    password_hash: row.get(2)?,
+                    role: row.get(3)?,
+                    must_change_password: row.get(4)?,
+                })
+            })
+        };
+        
+        let user = match result {
+            Ok(user) => user,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        // Hashing takes a noticeable moment, so it is kept off the async workers
+        let (password, stored) = (password.to_string(), user.password_hash.clone());
+        let check = tokio::task::spawn_blocking(move || {
+            match LEGACY_DEFAULT_PASSWORDS.iter().find(|(legacy_hash, _)| *legacy_hash == stored) {
+                Some((_, default_password)) if password == *default_password => (PasswordCheck::MatchNeedsRehash, Some(hash_password(&password))),
+                Some(_) => (PasswordCheck::Mismatch, None),
+                None => match verify_password(&password, &stored) {
+                    PasswordCheck::MatchNeedsRehash => (PasswordCheck::MatchNeedsRehash, Some(hash_password(&password))),
+                    check => (check, None),
+                },
+            }
+        }).await?;
+
+        match check {
+            (PasswordCheck::Mismatch, _) => Ok(None),
+            (_, Some(rehashed)) => {
+                let conn = self.connection.lock().await;
+                conn.execute(
+                    "UPDATE local_users SET password_hash = ?1 WHERE id = ?2",
+                    params![rehashed, user.id],
+                )?;
+                info!("Rehashed the password of user '{}'", user.username);
+                Ok(Some(LocalUser { password_hash: rehashed, ..user }))
+            }
+            (_, None) => Ok(Some(user)),
+        }
+    }
+
+    /// Hash and store a new password for a user, clear `must_change_password` and revoke
+    /// every session of theirs except `keep_session`. Returns how many were revoked.
+    pub async fn set_user_password(&self, user_id: i64, password: &str, keep_session: Option<&str>) -> Result<usize> {
+        let password = password.to_string();
+        let password_hash = tokio::task::spawn_blocking(move || hash_password(&password)).await?;
+
+        let mut conn = self.connection.lock().await;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "UPDATE local_users SET password_hash = ?1, must_change_password = 0 WHERE id = ?2",
+            params![password_hash, user_id],
+        )?;
+        let revoked = tx.execute(
+            "DELETE FROM user_sessions WHERE user_id = ?1 AND (?2 IS NULL OR session_token != ?2)",
+            params![user_id, keep_session],
+        )?;
+        tx.commit()?;
+
+        Ok(revoked)
+    }
+
+    /// Every local account, by username
+    pub async fn list_users(&self) -> Result<Vec<UserAccount>> {
+        let conn = self.readers.get().await;
+        let mut stmt = conn.prepare(
+            "SELECT id, username, role, disabled, must_change_password FROM local_users ORDER BY username"
+        )?;
+        let users = stmt.query_map([], |row| {
+            Ok(UserAccount {
+                id: row.get(0)?,
+                username: row.get(1)?,
+                role: row.get(2)?,
+                disabled: row.get(3)?,
+                must_change_password: row.get(4)?,
+            })
+        })?.collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(users)
+    }
+
+    /// Create an account with a hashed password. Usernames are unique.
+    pub async fn create_user(&self, username: &str, password: &str, role: &str) -> Result<UserChange<UserAccount>> {
+        let password = password.to_string();
+        let password_hash = tokio::task::spawn_blocking(move || hash_password(&password)).await?;
+
+        let conn = self.connection.lock().await;
+        let result = conn.execute(
+            "INSERT INTO local_users (username, password_hash, role) VALUES (?1, ?2, ?3)",
+            params![username, password_hash, role],
+        );
+        match result {
+            Ok(_) => Ok(UserChange::Done(UserAccount {
+                id: conn.last_insert_rowid(),
+                username: username.to_string(),
+                role: role.to_string(),
+                disabled: false,
+                must_change_password: false,
+            })),
+            Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == rusqlite::ErrorCode::ConstraintViolation => Ok(UserChange::UsernameTaken),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Change an account's role or disable it. Disabling revokes its sessions. Refused when
+    /// it would leave no enabled admin.
+    pub async fn update_user(&self, user_id: i64, changes: &UserAccountChanges) -> Result<UserChange<UserAccount>> {
+        let mut conn = self.connection.lock().await;
+        let tx = conn.transaction()?;
+        let Some(mut user) = Self::user_account(&tx, user_id)? else {
+            return Ok(UserChange::NotFound);
+        };
+        if let Some(role) = &changes.role {
+            user.role = role.clone();
+        }
+        if let Some(disabled) = changes.disabled {
+            user.disabled = disabled;
+        }
+        if (user.role != "admin" || user.disabled) && Self::is_last_admin(&tx, user_id)? {
+            return Ok(UserChange::LastAdmin);
+        }
+
+        tx.execute(
+            "UPDATE local_users SET role = ?1, disabled = ?2 WHERE id = ?3",
+            params![user.role, user.disabled, user_id],
+        )?;
+        if user.disabled {
+            tx.execute("DELETE FROM user_sessions WHERE user_id = ?1", params![user_id])?;
+        }
+        tx.commit()?;
+
+        Ok(UserChange::Done(user))
+    }
+
+    /// Delete an account with its sessions and notifications. Refused for the last enabled admin.
+    pub async fn delete_user(&self, user_id: i64) -> Result<UserChange<UserAccount>> {
+        let mut conn = self.connection.lock().await;
+        let tx = conn.transaction()?;
+        let Some(user) = Self::user_account(&tx, user_id)? else {
+            return Ok(UserChange::NotFound);
+        };
+        if Self::is_last_admin(&tx, user_id)? {
+            return Ok(UserChange::LastAdmin);
+        }
+
+        tx.execute("DELETE FROM user_sessions WHERE user_id = ?1", params![user_id])?;
+        tx.execute("DELETE FROM notification_reads WHERE user_id = ?1", params![user_id])?;
+        tx.execute("DELETE FROM notifications WHERE user_id = ?1", params![user_id])?;
+        tx.execute("DELETE FROM local_users WHERE id = ?1", params![user_id])?;
+        tx.commit()?;
+
+        Ok(UserChange::Done(user))
+    }
+
+    fn user_account(conn: &Connection, user_id: i64) -> Result<Option<UserAccount>> {
+        let result = conn.query_row(
+            "SELECT id, username, role, disabled, must_change_password FROM local_users WHERE id = ?1",
+            params![user_id],
+            |row| {
+                Ok(UserAccount {
+                    id: row.get(0)?,
+                    username: row.get(1)?,
+                    role: row.get(2)?,
+                    disabled: row.get(3)?,
+                    must_change_password: row.get(4)?,
+                })
+            },
+        );
+        match result {
+            Ok(user) => Ok(Some(user)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Whether `user_id` is the only enabled admin
+    fn is_last_admin(conn: &Connection, user_id: i64) -> Result<bool> {
+        let other_admins: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM local_users WHERE role = 'admin' AND disabled = 0 AND id != ?1",
+            params![user_id],
+            |row| row.get(0),
+        )?;
+        let is_admin: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM local_users WHERE id = ?1 AND role = 'admin' AND disabled = 0)",
+            params![user_id],
+            |row| row.get(0),
+        )?;
+        Ok(is_admin && other_admins == 0)
+    }
+
+    /// Create a new session for a user
+    pub async fn create_session(&self, user_id: i64, session_token: &str, expires_at: DateTime<Utc>, source_ip: Option<&str>) -> Result<i64> {
+        let conn = self.connection.lock().await;
+        let expires_str = expires_at.to_rfc3339();
+        let now = Utc::now().to_rfc3339();
+        
+        conn.execute(
+            "INSERT INTO user_sessions (user_id, session_token, expires_at, created_at, last_used, source_ip)
+             VALUES (?1, ?2, ?3, ?4, ?4, ?5)",
+            params![user_id, session_token, expires_str, now, source_ip],
+        )?;
+
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// Verify session token and return user info if valid. The session's `last_used` is
+    /// brought up to date when it is more than `SESSION_LAST_USED_RESOLUTION_SECS` old.
+    pub async fn verify_session(&self, session_token: &str) -> Result<Option<LocalUser>> {
+        let now = Utc::now();
+        let (user, last_used) = {
+            let conn = self.readers.get().await;
+            
+            let mut stmt = conn.prepare("
+                SELECT u.id, u.username, u.password_hash, u.role, s.last_used, u.must_change_password
+                FROM user_sessions s
+                JOIN local_users u ON s.user_id = u.id
+                WHERE s.session_token = ?1 AND s.expires_at > ?2 AND u.disabled = 0
+            ")?;
+            
+            let result = stmt.query_row([session_token, &now.to_rfc3339()], |row| {
+                Ok((
+                    LocalUser {
+                        id: Some(row.get(0)?),
+                        username: row.get(1)?,
+                        password_hash: row.get(2)?,
+                        role: row.get(3)?,
+                        must_change_password: row.get(5)?,
+                    },
+                    row.get::<_, Option<String>>(4)?,
+                ))
+            });
+            
+            match result {
+                Ok(found) => found,
+                Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        };
+
+        let last_used = last_used
+            .and_then(|last_used| DateTime::parse_from_rfc3339(&last_used).ok())
+            .map(|last_used| last_used.with_timezone(&Utc));
+        if last_used.is_none_or(|last_used| now - last_used >= chrono::Duration::seconds(SESSION_LAST_USED_RESOLUTION_SECS)) {
+            let conn = self.connection.lock().await;
+            conn.execute(
+                "UPDATE user_sessions SET last_used = ?1 WHERE session_token = ?2",
+                params![now.to_rfc3339(), session_token],
+            )?;
+        }
+
+        Ok(Some(user))
+    }
+
+    /// Swap a session that hasn't expired for a new one. The old token stops working at once.
+    /// Returns false if the old session had already expired or been revoked.
+    pub async fn refresh_session(&self, session_token: &str, new_token: &str, expires_at: DateTime<Utc>, source_ip: Option<&str>) -> Result<bool> {
+        let mut conn = self.connection.lock().await;
+        let now = Utc::now().to_rfc3339();
+        let tx = conn.transaction()?;
+
+        let inserted = tx.execute(
+            "INSERT INTO user_sessions (user_id, session_token, expires_at, created_at, last_used, source_ip)
+             SELECT user_id, ?2, ?3, ?4, ?4, ?5 FROM user_sessions WHERE session_token = ?1 AND expires_at > ?4",
+            params![session_token, new_token, expires_at.to_rfc3339(), now, source_ip],
+        )?;
+        if inserted == 0 {
+            return Ok(false);
+        }
+        tx.execute("DELETE FROM user_sessions WHERE session_token = ?1", params![session_token])?;
+        tx.commit()?;
+
+        Ok(true)
+    }
+
+    /// Sessions that haven't expired, most recently created first; only those of `user_id`
+    /// when given. `current_token` marks the session making the request.
+    pub async fn get_active_sessions(&self, user_id: Option<i64>, current_token: &str) -> Result<Vec<ActiveSession>> {
+        let conn = self.readers.get().await;
+        let now = Utc::now().to_rfc3339();
+
+        let mut stmt = conn.prepare("
+            SELECT s.id, s.user_id, u.username, s.created_at, s.last_used, s.expires_at, s.source_ip, s.session_token = ?3
+            FROM user_sessions s
+            JOIN local_users u ON s.user_id = u.id
+            WHERE s.expires_at > ?1 AND (?2 IS NULL OR s.user_id = ?2)
+            ORDER BY s.created_at DESC, s.id DESC
+        ")?;
+
+        let rows = stmt.query_map(params![now, user_id, current_token], |row| {
+            let timestamp = |index: usize| -> rusqlite::Result<Option<DateTime<Utc>>> {
+                Ok(row.get::<_, Option<String>>(index)?
+                    .and_then(|text| DateTime::parse_from_rfc3339(&text).ok())
+                    .map(|timestamp| timestamp.with_timezone(&Utc)))
+            };
+            Ok(ActiveSession {
+                id: row.get(0)?,
+                user_id: row.get(1)?,
+                username: row.get(2)?,
+                created_at: timestamp(3)?,
+                last_used: timestamp(4)?,
+                expires_at: timestamp(5)?
+                    .ok_or_else(|| rusqlite::Error::InvalidColumnType(5, "expires_at".to_string(), rusqlite::types::Type::Text))?,
+                source_ip: row.get(6)?,
+                current: row.get(7)?,
+            })
+        })?;
+
+        let mut sessions = Vec::new();
+        for row in rows {
+            sessions.push(row?);
+        }
+
+        Ok(sessions)
+    }
+
+    /// Delete expired sessions
+    pub async fn cleanup_expired_sessions(&self) -> Result<u32> {
+        let conn = self.connection.lock().await;
+        let now = Utc::now().to_rfc3339();
+        
+        let deleted = conn.execute(
+            "DELETE FROM user_sessions WHERE expires_at <= ?1",
+            params![now],
+        )?;
+
+        Ok(deleted as u32)
+    }
+
+    /// Revoke a session by its id; only one of `user_id`'s when given
+    pub async fn revoke_session_by_id(&self, session_id: i64, user_id: Option<i64>) -> Result<bool> {
+        let conn = self.connection.lock().await;
+
+        let deleted = conn.execute(
+            "DELETE FROM user_sessions WHERE id = ?1 AND (?2 IS NULL OR user_id = ?2)",
+            params![session_id, user_id],
+        )?;
+
+        Ok(deleted > 0)
+    }
+
+    /// Revoke a specific session
+    pub async fn revoke_session(&self, session_token: &str) -> Result<bool> {
+        let conn = self.connection.lock().await;
+        
+        let deleted = conn.execute(
+            "DELETE FROM user_sessions WHERE session_token = ?1",
+            params![session_token],
+        )?;
+
+        Ok(deleted > 0)
+    }
+
+    /// The gateway's whole configuration: models, templates, devices, tags,
+    /// schedule groups and the plant. Logged values are left out.
+    pub async fn export_config_bundle(&self) -> Result<ConfigBundle> {
+        let device_models = self.get_device_models().await?;
+        let mut tag_templates = Vec::new();
+        for model in &device_models {
+            tag_templates.extend(self.get_tag_templates(&model.id).await?);
+        }
+        let devices = self.get_devices().await?;
+        let mut device_tags = Vec::new();
+        for device in &devices {
+            device_tags.extend(self.get_device_tags(&device.id).await?);
+        }
+
+        Ok(ConfigBundle {
+            schema_version: CONFIG_BUNDLE_SCHEMA_VERSION,
+            exported_at: Utc::now(),
+            device_models,
+            tag_templates,
+            devices,
+            device_tags,
+            schedule_groups: self.get_schedule_groups().await?,
+            plant_configuration: self.get_plant_configuration().await?,
+        })
+    }
+
+    /// Write a configuration bundle in one transaction.
+    ///
+    /// Nothing is written when the bundle repeats an entity or references a model,
+    /// device or schedule group that is neither in the bundle nor, when merging,
+    /// already on the gateway; every such problem is returned instead. Templates and
+    /// tags are matched by name within their model or device, everything else by id.
+    pub async fn restore_config_bundle(&self, bundle: &ConfigBundle, mode: RestoreMode) -> Result<std::result::Result<RestoreReport, Vec<FieldError>>> {
+        let mut conn = self.connection.lock().await;
+
+        // All writes go through `connection`, so this stays current while the lock is held
+        let current = match mode {
+            RestoreMode::Merge => self.export_config_bundle().await?,
+            RestoreMode::Replace => ConfigBundle::default(),
+        };
+
+        let errors = Self::config_bundle_errors(bundle, &current);
+        if !errors.is_empty() {
+            return Ok(Err(errors));
+        }
+
+        let tx = conn.transaction()?;
+        if mode == RestoreMode::Replace {
+            for table in [
+                "device_tag_templates", "device_tags", "device_status", "devices",
+                "tag_templates", "device_models", "schedule_groups", "plant_configuration",
+            ] {
+                tx.execute(&format!("DELETE FROM {}", table), [])?;
+            }
+        }
+
+        let mut report = RestoreReport { mode, ..Default::default() };
+
+        let models: HashMap<&str, &DeviceModel> = current.device_models.iter().map(|m| (m.id.as_str(), m)).collect();
+        for model in &bundle.device_models {
+            if report.device_models.record(models.get(model.id.as_str()).copied(), model) {
+                tx.execute(
+                    "INSERT OR REPLACE INTO device_models (id, name, description, manufacturer, protocol_type, created_at, updated_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![
+                        model.id,
+                        model.name,
+                        model.description,
+                        model.manufacturer,
+                        model.protocol_type,
+                        model.created_at.to_rfc3339(),
+                        model.updated_at.to_rfc3339()
+                    ],
+                )?;
+            }
+        }
+
+        let templates: HashMap<(&str, &str), &TagTemplate> =
+            current.tag_templates.iter().map(|t| ((t.model_id.as_str(), t.name.as_str()), t)).collect();
+        for template in &bundle.tag_templates {
+            let existing = templates.get(&(template.model_id.as_str(), template.name.as_str())).copied();
+            let template = TagTemplate { id: existing.and_then(|t| t.id), ..template.clone() };
+            if report.tag_templates.record(existing, &template) {
+                match existing {
+                    Some(_) => Self::update_tag_template_row(&tx, &template).map(|_| ())?,
+                    None => Self::insert_tag_template(&tx, &template).map(|_| ())?,
+                }
+            }
+        }
+
+        let groups: HashMap<&str, &ScheduleGroup> = current.schedule_groups.iter().map(|g| (g.id.as_str(), g)).collect();
+        for group in &bundle.schedule_groups {
+            if report.schedule_groups.record(groups.get(group.id.as_str()).copied(), group) {
+                tx.execute(
+                    "INSERT OR REPLACE INTO schedule_groups (id, name, polling_interval_ms, description, enabled, created_at, updated_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![
+                        group.id,
+                        group.name,
+                        group.polling_interval_ms,
+                        group.description,
+                        group.enabled,
+                        group.created_at.to_rfc3339(),
+                        group.updated_at.to_rfc3339()
+                    ],
+                )?;
+            }
+        }
+
+        let devices: HashMap<&str, &DeviceInstance> = current.devices.iter().map(|d| (d.id.as_str(), d)).collect();
+        for device in &bundle.devices {
+            if report.devices.record(devices.get(device.id.as_str()).copied(), device) {
+                tx.execute("DELETE FROM devices WHERE id = ?1", [&device.id])?;
+                Self::insert_device(&tx, device)?;
+            }
+        }
+
+        let tags: HashMap<(&str, &str), &DeviceTag> =
+            current.device_tags.iter().map(|t| ((t.device_id.as_str(), t.name.as_str()), t)).collect();
+        for tag in &bundle.device_tags {
+            let existing = tags.get(&(tag.device_id.as_str(), tag.name.as_str())).copied();
+            let tag = DeviceTag { id: existing.and_then(|t| t.id), ..tag.clone() };
+            if !report.device_tags.record(existing, &tag) {
+                continue;
+            }
+            match existing {
+                Some(_) => {
+                    Self::update_device_tag_row(&tx, &tag)?;
+                }
+                None => Self::insert_device_tags(&tx, &tag.device_id, std::slice::from_ref(&tag))?,
+            }
+        }
+
+        if let Some(plant) = &bundle.plant_configuration {
+            let plant = PlantConfiguration { id: Some(1), ..plant.clone() };
+            let existing = current.plant_configuration.as_ref().map(|p| PlantConfiguration { id: Some(1), ..p.clone() });
+            if report.plant_configuration.record(existing.as_ref(), &plant) {
+                tx.execute("DELETE FROM plant_configuration", [])?;
+                tx.execute(
+                    "INSERT INTO plant_configuration (id, plant_name, thingsboard_entity_group_id, last_synced)
+                     VALUES (1, ?1, ?2, ?3)",
+                    params![plant.plant_name, plant.thingsboard_entity_group_id, plant.last_synced],
+                )?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(Ok(report))
+    }
+
+    /// Repeated entities and references to missing ones, checked against the bundle
+    /// itself plus what is already stored
+    fn config_bundle_errors(bundle: &ConfigBundle, current: &ConfigBundle) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        let mut error = |field: String, message: String| errors.push(FieldError { field, message });
+
+        let mut model_ids = HashSet::new();
+        for (i, model) in bundle.device_models.iter().enumerate() {
+            if !model_ids.insert(model.id.as_str()) {
+                error(format!("device_models[{}].id", i), format!("device model '{}' appears more than once", model.id));
+            }
+        }
+        let mut template_names = HashSet::new();
+        for (i, template) in bundle.tag_templates.iter().enumerate() {
+            if !template_names.insert((template.model_id.as_str(), template.name.as_str())) {
+                error(format!("tag_templates[{}].name", i), format!("model '{}' has more than one template '{}'", template.model_id, template.name));
+            }
+        }
+        let mut group_ids = HashSet::new();
+        for (i, group) in bundle.schedule_groups.iter().enumerate() {
+            if !group_ids.insert(group.id.as_str()) {
+                error(format!("schedule_groups[{}].id", i), format!("schedule group '{}' appears more than once", group.id));
+            }
+        }
+        let mut device_ids = HashSet::new();
+        for (i, device) in bundle.devices.iter().enumerate() {
+            if !device_ids.insert(device.id.as_str()) {
+                error(format!("devices[{}].id", i), format!("device '{}' appears more than once", device.id));
+            }
+            if let Err(e) = device.protocol() {
+                error(format!("devices[{}].protocol_config", i), e.to_string());
+            }
+        }
+        let mut tag_names = HashSet::new();
+        for (i, tag) in bundle.device_tags.iter().enumerate() {
+            if !tag_names.insert((tag.device_id.as_str(), tag.name.as_str())) {
+                error(format!("device_tags[{}].name", i), format!("device '{}' has more than one tag '{}'", tag.device_id, tag.name));
+            }
+        }
+
+        model_ids.extend(current.device_models.iter().map(|m| m.id.as_str()));
+        group_ids.extend(current.schedule_groups.iter().map(|g| g.id.as_str()));
+        device_ids.extend(current.devices.iter().map(|d| d.id.as_str()));
+        for (i, template) in bundle.tag_templates.iter().enumerate() {
+            if !model_ids.contains(template.model_id.as_str()) {
+                error(format!("tag_templates[{}].model_id", i), format!("references missing device model '{}'", template.model_id));
+            }
+        }
+        for (i, device) in bundle.devices.iter().enumerate() {
+            if let Some(model_id) = device.model_id.as_deref().filter(|id| !model_ids.contains(id)) {
+                error(format!("devices[{}].model_id", i), format!("references missing device model '{}'", model_id));
+            }
+        }
+        for (i, tag) in bundle.device_tags.iter().enumerate() {
+            if !device_ids.contains(tag.device_id.as_str()) {
+                error(format!("device_tags[{}].device_id", i), format!("references missing device '{}'", tag.device_id));
+            }
+            if let Some(group_id) = tag.schedule_group_id.as_deref().filter(|id| !group_ids.contains(id)) {
+                error(format!("device_tags[{}].schedule_group_id", i), format!("references missing schedule group '{}'", group_id));
+            }
+        }
+
+        errors
+    }
+
+    /// Get plant configuration
+    pub async fn get_plant_configuration(&self) -> Result<Option<PlantConfiguration>> {
+        let conn = self.readers.get().await;
+        
+        let mut stmt = conn.prepare(
+            "SELECT id, plant_name, thingsboard_entity_group_id, last_synced FROM plant_configuration LIMIT 1"
+        )?;
+        
+        let result = stmt.query_row([], |row| {
+            Ok(PlantConfiguration {
+                id: Some(row.get(0)?),
+                plant_name: row.get(1)?,
+                thingsboard_entity_group_id: row.get(2)?,
+                last_synced: row.get(3)?,
+            })
+        });
+        
+        match result {
+            Ok(config) => Ok(Some(config)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Update plant configuration
+    pub async fn update_plant_configuration(&self, plant_name: &str, tb_group_id: Option<&str>) -> Result<bool> {
+        let conn = self.connection.lock().await;
+        
+        // Try to update existing config first
+        let updated = conn.execute(
+            "UPDATE plant_configuration SET plant_name = ?1, thingsboard_entity_group_id = ?2 WHERE id = 1",
+            params![plant_name, tb_group_id],
+        )?;
+
+        if updated == 0 {
+            // No existing config, insert new one
+            conn.execute(
+                "INSERT INTO plant_configuration (plant_name, thingsboard_entity_group_id)
+                 VALUES (?1, ?2)",
+                params![plant_name, tb_group_id],
+            )?;
+        }
+
+        Ok(true)
+    }
+
+    /// Update or insert plant sync timestamp
+    pub async fn update_plant_sync_timestamp(&self, plant_name: &str, tb_group_id: &str) -> Result<()> {
+        let conn = self.connection.lock().await;
+        let now = chrono::Utc::now().to_rfc3339();
+        
+        // Check if a plant with this group ID already exists
+        let existing_id: Option<i64> = conn.query_row(
+            "SELECT id FROM plant_configuration WHERE thingsboard_entity_group_id = ?1",
+            params![tb_group_id],
+            |row| row.get(0)
+        ).ok();
+        
+        if let Some(id) = existing_id {
+            // Update existing plant's sync timestamp
+            conn.execute(
+                "UPDATE plant_configuration SET last_synced = ?1, plant_name = ?2 WHERE id = ?3",
+                params![now, plant_name, id],
+            )?;
+            info!("Updated sync timestamp for plant '{}' (id: {})", plant_name, id);
+        } else {
+            // Insert new plant for sync tracking (id > 1)
+            conn.execute(
+                "INSERT INTO plant_configuration (plant_name, thingsboard_entity_group_id, last_synced)
+                 VALUES (?1, ?2, ?3)",
+                params![plant_name, tb_group_id, now],
+            )?;
+            let new_id = conn.last_insert_rowid();
+            info!("Added new plant '{}' for sync tracking (id: {})", plant_name, new_id);
+        }
+
+        Ok(())
+    }
+
+    /// Get all plants with sync timestamps (excluding id = 1 if needed)
+    pub async fn get_all_plant_sync_info(&self) -> Result<Vec<PlantConfiguration>> {
+        let conn = self.readers.get().await;
+        
+        let mut stmt = conn.prepare(
+            "SELECT id, plant_name, thingsboard_entity_group_id, last_synced 
+             FROM plant_configuration 
+             ORDER BY id"
+        )?;
+        
+        let plants = stmt.query_map([], |row| {
+            Ok(PlantConfiguration {
+                id: Some(row.get(0)?),
+                plant_name: row.get(1)?,
+                thingsboard_entity_group_id: row.get(2)?,
+                last_synced: row.get(3)?,
+            })
+        })?
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+        
+        Ok(plants)
+    }
+
+    /// Get devices filtered by ThingsBoard group ID
+    pub async fn get_devices_by_group(&self, tb_group_id: &str) -> Result<Vec<DeviceInstance>> {
+        let conn = self.readers.get().await;
+        
+        let mut stmt = conn.prepare("
+            SELECT id, name, serial_no, model_id, enabled, polling_interval_ms, timeout_ms, retry_count, 
+                   protocol_config, tb_device_id, tb_group_id, created_at, updated_at, strict_types 
+            FROM devices 
+            WHERE tb_group_id = ?1 OR tb_group_id IS NULL
+            ORDER BY name
+        ")?;
+
+        let rows = stmt.query_map([tb_group_id], |row| {
+            let created_str: String = row.get(11)?;
+            let updated_str: String = row.get(12)?;
+            let created_at = DateTime::parse_from_rfc3339(&created_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(11, "created_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(12, "updated_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+
+            Ok(DeviceInstance {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                serial_no: row.get(2)?,
+                model_id: row.get(3)?,
+                enabled: row.get(4)?,
+                polling_interval_ms: row.get::<_, i32>(5)? as u32,
+                timeout_ms: row.get::<_, i32>(6)? as u32,
+                retry_count: row.get::<_, i32>(7)? as u32,
+                protocol_config: row.get(8)?,
+                tb_device_id: row.get(9)?,
+                tb_group_id: row.get(10)?,
+                created_at,
+                updated_at,
+                strict_types: row.get("strict_types")?,
+            })
+        })?;
+
+        let mut devices = Vec::new();
+        for row in rows {
+            devices.push(row?);
+        }
+
+        Ok(devices)
+    }
+
+    /// Per-device sample counts and the largest gap between consecutive samples in a time window
+    pub async fn get_device_day_stats(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<DeviceDayStats>> {
+        let conn = self.readers.get().await;
+
+        let mut stmt = conn.prepare(
+            "SELECT device_id,
+                    COUNT(*) AS total_samples,
+                    SUM(CASE WHEN quality = 'Good' THEN 1 ELSE 0 END) AS good_samples,
+                    MIN(timestamp) AS first_sample,
+                    MAX(timestamp) AS last_sample,
+                    COALESCE(MAX(gap_seconds), 0) AS max_gap_seconds
+             FROM (
+                 SELECT device_id, quality, timestamp,
+                        (julianday(timestamp) - julianday(LAG(timestamp) OVER (PARTITION BY device_id ORDER BY timestamp))) * 86400.0 AS gap_seconds
+                 FROM log_entries
+                 WHERE timestamp >= ?1 AND timestamp < ?2
+             )
+             GROUP BY device_id
+             ORDER BY device_id"
+        )?;
+
+        let rows = stmt.query_map(params![start.to_rfc3339(), end.to_rfc3339()], |row| {
+            Ok(DeviceDayStats {
+                device_id: row.get(0)?,
+                total_samples: row.get(1)?,
+                good_samples: row.get(2)?,
+                first_sample: row.get(3)?,
+                last_sample: row.get(4)?,
+                max_gap_seconds: row.get(5)?,
+            })
+        })?;
+
+        let mut stats = Vec::new();
+        for row in rows {
+            stats.push(row?);
+        }
+
+        Ok(stats)
+    }
+
+    /// First and last good value of every energy tag (kWh/MWh/Wh) in a time window
+    pub async fn get_energy_tag_deltas(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<TagDayDelta>> {
+        let conn = self.readers.get().await;
+
+        let mut stmt = conn.prepare(
+            "SELECT device_id, tag_name, unit,
+                    (SELECT value FROM log_entries f
+                     WHERE f.device_id = l.device_id AND f.tag_name = l.tag_name AND f.quality = 'Good'
+                       AND f.timestamp >= ?1 AND f.timestamp < ?2
+                     ORDER BY f.timestamp ASC LIMIT 1) AS first_value,
+                    (SELECT value FROM log_entries f
+                     WHERE f.device_id = l.device_id AND f.tag_name = l.tag_name AND f.quality = 'Good'
+                       AND f.timestamp >= ?1 AND f.timestamp < ?2
+                     ORDER BY f.timestamp DESC LIMIT 1) AS last_value,
+                    MIN(l.value), MAX(l.value)
+             FROM log_entries l
+             WHERE l.timestamp >= ?1 AND l.timestamp < ?2 AND l.quality = 'Good'
+               AND l.unit IN ('kWh', 'MWh', 'Wh')
+             GROUP BY device_id, tag_name
+             ORDER BY device_id, tag_name"
+        )?;
+
+        let rows = stmt.query_map(params![start.to_rfc3339(), end.to_rfc3339()], |row| {
+            Ok(TagDayDelta {
+                device_id: row.get(0)?,
+                tag_name: row.get(1)?,
+                unit: row.get(2)?,
+                first_value: row.get(3)?,
+                last_value: row.get(4)?,
+                min_value: row.get(5)?,
+                max_value: row.get(6)?,
+            })
+        })?;
+
+        let mut deltas = Vec::new();
+        for row in rows {
+            deltas.push(row?);
+        }
+
+        Ok(deltas)
+    }
+
+    /// Insert or replace the report for a date, keeping any earlier delivery timestamp
+    pub async fn upsert_daily_report(&self, report: &DailyReport) -> Result<()> {
+        let conn = self.connection.lock().await;
+
+        conn.execute(
+            "INSERT INTO daily_reports (report_date, plant_name, generated_at, content, summary_text, delivered_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT(report_date) DO UPDATE SET
+                plant_name = excluded.plant_name,
+                generated_at = excluded.generated_at,
+                content = excluded.content,
+                summary_text = excluded.summary_text,
+                delivered_at = COALESCE(daily_reports.delivered_at, excluded.delivered_at)",
+            params![
+                report.report_date,
+                report.plant_name,
+                report.generated_at.to_rfc3339(),
+                report.content,
+                report.summary_text,
+                report.delivered_at.map(|t| t.to_rfc3339()),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub async fn get_daily_report(&self, report_date: &str) -> Result<Option<DailyReport>> {
+        let conn = self.readers.get().await;
+
+        let result = conn.query_row(
+            "SELECT report_date, plant_name, generated_at, content, summary_text, delivered_at
+             FROM daily_reports WHERE report_date = ?1",
+            [report_date],
+            |row| {
+                let generated_str: String = row.get(2)?;
+                let generated_at = DateTime::parse_from_rfc3339(&generated_str)
+                    .map_err(|_| rusqlite::Error::InvalidColumnType(2, "generated_at".to_string(), rusqlite::types::Type::Text))?
+                    .with_timezone(&Utc);
+                let delivered_at = match row.get::<_, Option<String>>(5)? {
+                    Some(delivered_str) => Some(
+                        DateTime::parse_from_rfc3339(&delivered_str)
+                            .map_err(|_| rusqlite::Error::InvalidColumnType(5, "delivered_at".to_string(), rusqlite::types::Type::Text))?
+                            .with_timezone(&Utc),
+                    ),
+                    None => None,
+                };
+
+                Ok(DailyReport {
+                    report_date: row.get(0)?,
+                    plant_name: row.get(1)?,
+                    generated_at,
+                    content: row.get(3)?,
+                    summary_text: row.get(4)?,
+                    delivered_at,
+                })
+            },
+        );
+
+        match result {
+            Ok(report) => Ok(Some(report)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn mark_daily_report_delivered(&self, report_date: &str) -> Result<bool> {
+        let conn = self.connection.lock().await;
+
+        let rows_affected = conn.execute(
+            "UPDATE daily_reports SET delivered_at = ?1 WHERE report_date = ?2",
+            params![Utc::now().to_rfc3339(), report_date],
+        )?;
+
+        Ok(rows_affected > 0)
+    }
+
+    /// Increment the type mismatch counter for a tag
+    pub async fn record_type_mismatch(&self, device_id: &str, tag_name: &str, reason: &str) -> Result<()> {
+        let conn = self.connection.lock().await;
+
+        conn.execute(
+            "INSERT INTO tag_type_mismatches (device_id, tag_name, mismatch_count, last_reason, last_seen)
+             VALUES (?1, ?2, 1, ?3, ?4)
+             ON CONFLICT(device_id, tag_name) DO UPDATE SET
+                mismatch_count = mismatch_count + 1,
+                last_reason = excluded.last_reason,
+                last_seen = excluded.last_seen",
+            params![device_id, tag_name, reason, Utc::now().to_rfc3339()],
+        )?;
+
+        Ok(())
+    }
+
+    pub async fn get_type_mismatches(&self, device_id: &str) -> Result<Vec<TagTypeMismatch>> {
+        let conn = self.readers.get().await;
+
+        let mut stmt = conn.prepare(
+            "SELECT device_id, tag_name, mismatch_count, last_reason, last_seen
+             FROM tag_type_mismatches
+             WHERE device_id = ?1
+             ORDER BY mismatch_count DESC, tag_name"
+        )?;
+
+        let rows = stmt.query_map([device_id], |row| {
+            let last_seen_str: String = row.get(4)?;
+            let last_seen = DateTime::parse_from_rfc3339(&last_seen_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(4, "last_seen".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+
+            Ok(TagTypeMismatch {
+                device_id: row.get(0)?,
+                tag_name: row.get(1)?,
+                mismatch_count: row.get(2)?,
+                last_reason: row.get(3)?,
+                last_seen,
+            })
+        })?;
+
+        let mut mismatches = Vec::new();
+        for row in rows {
+            mismatches.push(row?);
+        }
+
+        Ok(mismatches)
+    }
+
+    pub async fn reset_type_mismatches(&self, device_id: &str) -> Result<usize> {
+        let conn = self.connection.lock().await;
+        let rows_affected = conn.execute("DELETE FROM tag_type_mismatches WHERE device_id = ?1", [device_id])?;
+        Ok(rows_affected)
+    }
+
+    pub async fn create_notification(&self, notification: &NewNotification) -> Result<i64> {
+        let conn = self.connection.lock().await;
+
+        conn.execute(
+            "INSERT INTO notifications (notification_type, severity, title, body, entity_type, entity_id, user_id, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                notification.notification_type,
+                notification.severity,
+                notification.title,
+                notification.body,
+                notification.entity_type,
+                notification.entity_id,
+                notification.user_id,
+                Utc::now().to_rfc3339()
+            ],
+        )?;
+
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// Notifications visible to a user (their own plus broadcasts), newest first
+    pub async fn get_notifications(&self, user_id: i64, unread_only: bool, limit: u32) -> Result<Vec<Notification>> {
+        let conn = self.readers.get().await;
+
+        let mut stmt = conn.prepare(
+            "SELECT n.id, n.notification_type, n.severity, n.title, n.body, n.entity_type, n.entity_id,
+                    n.user_id, n.created_at, r.read_at
+             FROM notifications n
+             LEFT JOIN notification_reads r ON r.notification_id = n.id AND r.user_id = ?1
+             WHERE (n.user_id IS NULL OR n.user_id = ?1)
+               AND (?2 = 0 OR r.read_at IS NULL)
+             ORDER BY n.created_at DESC, n.id DESC
+             LIMIT ?3"
+        )?;
+
+        let rows = stmt.query_map(params![user_id, unread_only, limit], |row| {
+            let created_str: String = row.get(8)?;
+            let created_at = DateTime::parse_from_rfc3339(&created_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(8, "created_at".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+            let read_at = match row.get::<_, Option<String>>(9)? {
+                Some(read_str) => Some(
+                    DateTime::parse_from_rfc3339(&read_str)
+                        .map_err(|_| rusqlite::Error::InvalidColumnType(9, "read_at".to_string(), rusqlite::types::Type::Text))?
+                        .with_timezone(&Utc),
+                ),
+                None => None,
+            };
+
+            Ok(Notification {
+                id: row.get(0)?,
+                notification_type: row.get(1)?,
+                severity: row.get(2)?,
+                title: row.get(3)?,
+                body: row.get(4)?,
+                entity_type: row.get(5)?,
+                entity_id: row.get(6)?,
+                user_id: row.get(7)?,
+                created_at,
+                read_at,
+            })
+        })?;
+
+        let mut notifications = Vec::new();
+        for row in rows {
+            notifications.push(row?);
+        }
+
+        Ok(notifications)
+    }
+
+    /// Mark a notification read for a user. Returns false if the user can't see it.
+    pub async fn mark_notification_read(&self, notification_id: i64, user_id: i64) -> Result<bool> {
+        let conn = self.connection.lock().await;
+
+        let visible: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM notifications WHERE id = ?1 AND (user_id IS NULL OR user_id = ?2)",
+            params![notification_id, user_id],
+            |row| row.get(0),
+        )?;
+        if visible == 0 {
+            return Ok(false);
+        }
+
+        conn.execute(
+            "INSERT OR IGNORE INTO notification_reads (notification_id, user_id, read_at) VALUES (?1, ?2, ?3)",
+            params![notification_id, user_id, Utc::now().to_rfc3339()],
+        )?;
+
+        Ok(true)
+    }
+
+    pub async fn mark_all_notifications_read(&self, user_id: i64) -> Result<usize> {
+        let conn = self.connection.lock().await;
+
+        let rows_affected = conn.execute(
+            "INSERT OR IGNORE INTO notification_reads (notification_id, user_id, read_at)
+             SELECT id, ?1, ?2 FROM notifications WHERE user_id IS NULL OR user_id = ?1",
+            params![user_id, Utc::now().to_rfc3339()],
+        )?;
+
+        Ok(rows_affected)
+    }
+
+    /// Delete notifications read before the cutoff.
+    ///
+    /// User notifications go once their owner has read them; broadcasts go only
+    /// once every local user has read them.
+    pub async fn purge_read_notifications(&self, read_before: DateTime<Utc>) -> Result<usize> {
+        let conn = self.connection.lock().await;
+        let cutoff = read_before.to_rfc3339();
+
+        let rows_affected = conn.execute(
+            "DELETE FROM notifications WHERE id IN (
+                SELECT n.id FROM notifications n
+                WHERE n.user_id IS NOT NULL AND EXISTS (
+                    SELECT 1 FROM notification_reads r
+                    WHERE r.notification_id = n.id AND r.user_id = n.user_id AND r.read_at < ?1)
+                UNION
+                SELECT n.id FROM notifications n
+                WHERE n.user_id IS NULL AND NOT EXISTS (
+                    SELECT 1 FROM local_users u WHERE NOT EXISTS (
+                        SELECT 1 FROM notification_reads r
+                        WHERE r.notification_id = n.id AND r.user_id = u.id AND r.read_at < ?1))
+            )",
+            [&cutoff],
+        )?;
+
+        conn.execute(
+            "DELETE FROM notification_reads WHERE notification_id NOT IN (SELECT id FROM notifications)",
+            [],
+        )?;
+
+        Ok(rows_affected)
+    }
+
+    /// Claim an idempotency key, or report how an earlier request with it ended.
+    ///
+    /// Keys are scoped to the calling user, so two users picking the same key don't see each
+    /// other's responses. Expired records are dropped first, so a key can be reused once its
+    /// TTL has passed.
+    pub async fn begin_idempotent_request(
+        &self,
+        user_id: i64,
+        idempotency_key: &str,
+        endpoint: &str,
+        request_hash: &str,
+        ttl: chrono::Duration,
+    ) -> Result<IdempotencyOutcome> {
+        let conn = self.connection.lock().await;
+        let now = Utc::now();
+
+        conn.execute(
+            "DELETE FROM idempotency_records
+             WHERE user_id = ?1 AND idempotency_key = ?2 AND endpoint = ?3 AND expires_at <= ?4",
+            params![user_id, idempotency_key, endpoint, now.to_rfc3339()],
+        )?;
+
+        let existing = conn.query_row(
+            "SELECT request_hash, status_code, response_body FROM idempotency_records
+             WHERE user_id = ?1 AND idempotency_key = ?2 AND endpoint = ?3",
+            params![user_id, idempotency_key, endpoint],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<u16>>(1)?, row.get::<_, Option<String>>(2)?)),
+        );
+
+        match existing {
+            Ok((stored_hash, _, _)) if stored_hash != request_hash => Ok(IdempotencyOutcome::Mismatch),
+            Ok((_, Some(status_code), Some(response_body))) => Ok(IdempotencyOutcome::Replay { status_code, response_body }),
+            Ok(_) => Ok(IdempotencyOutcome::InProgress),
+            Err(rusqlite::Error::QueryReturnedNoRows) => {
+                conn.execute(
+                    "INSERT INTO idempotency_records (user_id, idempotency_key, endpoint, request_hash, created_at, expires_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![user_id, idempotency_key, endpoint, request_hash, now.to_rfc3339(), (now + ttl).to_rfc3339()],
+                )?;
+                Ok(IdempotencyOutcome::Proceed)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Store the response for a claimed key so retries can replay it
+    pub async fn complete_idempotent_request(
+        &self,
+        user_id: i64,
+        idempotency_key: &str,
+        endpoint: &str,
+        status_code: u16,
+        response_body: &str,
+    ) -> Result<()> {
+        let conn = self.connection.lock().await;
+
+        conn.execute(
+            "UPDATE idempotency_records SET status_code = ?4, response_body = ?5
+             WHERE user_id = ?1 AND idempotency_key = ?2 AND endpoint = ?3",
+            params![user_id, idempotency_key, endpoint, status_code, response_body],
+        )?;
+
         Ok(())
     }
 
-    pub async fn get_device_tags(&self, device_id: &str) -> Result<Vec<DeviceTag>> {
+    /// Give up a claimed key so the request can be retried, e.g. after a failure
+    pub async fn release_idempotent_request(&self, user_id: i64, idempotency_key: &str, endpoint: &str) -> Result<()> {
         let conn = self.connection.lock().await;
-        
-        let mut stmt = conn.prepare(
-            "SELECT id, device_id, name, address, size, data_type, description, 
-                    scaling_multiplier, scaling_offset, unit, read_only, enabled, schedule_group_id, agg_to_field
-             FROM device_tags WHERE device_id = ?1 ORDER BY address"
+
+        conn.execute(
+            "DELETE FROM idempotency_records WHERE user_id = ?1 AND idempotency_key = ?2 AND endpoint = ?3",
+            params![user_id, idempotency_key, endpoint],
         )?;
 
-        let rows = stmt.query_map([device_id], |row| {
-            Ok(DeviceTag {
-                id: Some(row.get(0)?),
-                device_id: row.get(1)?,
-                name: row.get(2)?,
-                address: row.get::<_, i32>(3)? as u16,
-                size: row.get(4)?,
-                data_type: row.get(5)?,
-                description: row.get(6)?,
-                scaling_multiplier: row.get(7)?,
-                scaling_offset: row.get(8)?,
-                unit: row.get(9)?,
-                read_only: row.get(10)?,
-                enabled: row.get(11)?,
-                schedule_group_id: row.get(12)?,
-                agg_to_field: row.get(13)?,
+        Ok(())
+    }
+
+    pub async fn purge_expired_idempotency_records(&self, now: DateTime<Utc>) -> Result<usize> {
+        let conn = self.connection.lock().await;
+
+        let rows_affected = conn.execute(
+            "DELETE FROM idempotency_records WHERE expires_at <= ?1",
+            [now.to_rfc3339()],
+        )?;
+
+        Ok(rows_affected)
+    }
+
+    /// WHERE clause (over `device_tags t`) and parameters for a tag search filter
+    fn tag_search_conditions(filter: &TagSearchFilter) -> (String, Vec<rusqlite::types::Value>) {
+        use rusqlite::types::Value;
+
+        let mut conditions = vec!["1 = 1".to_string()];
+        let mut values: Vec<Value> = Vec::new();
+        let mut push = |condition: &str, value: Value| {
+            values.push(value);
+            conditions.push(condition.replace('?', &format!("?{}", values.len())));
+        };
+
+        if let Some(address) = filter.address {
+            push("t.address = ?", Value::Integer(address as i64));
+        }
+        if let Some(address_min) = filter.address_min {
+            push("t.address >= ?", Value::Integer(address_min as i64));
+        }
+        if let Some(address_max) = filter.address_max {
+            push("t.address <= ?", Value::Integer(address_max as i64));
+        }
+        if let Some(pattern) = filter.name_pattern.as_deref().filter(|p| !p.is_empty()) {
+            let like = if pattern.contains('*') {
+                pattern.replace('*', "%")
+            } else {
+                format!("%{}%", pattern)
+            };
+            push("t.name LIKE ?", Value::Text(like));
+        }
+        if let Some(data_type) = &filter.data_type {
+            push("LOWER(t.data_type) = LOWER(?)", Value::Text(data_type.clone()));
+        }
+        if let Some(unit) = &filter.unit {
+            push("t.unit = ?", Value::Text(unit.clone()));
+        }
+        if let Some(schedule_group_id) = &filter.schedule_group_id {
+            push("t.schedule_group_id = ?", Value::Text(schedule_group_id.clone()));
+        }
+        if let Some(enabled) = filter.enabled {
+            push("t.enabled = ?", Value::Integer(enabled as i64));
+        }
+
+        (conditions.join(" AND "), values)
+    }
+
+    /// Search tags across all devices, joined with their device and model
+    pub async fn search_tags(&self, filter: &TagSearchFilter, limit: u32, offset: u32) -> Result<Vec<TagSearchResult>> {
+        let conn = self.readers.get().await;
+        let (conditions, mut values) = Self::tag_search_conditions(filter);
+        values.push(rusqlite::types::Value::Integer(limit as i64));
+        values.push(rusqlite::types::Value::Integer(offset as i64));
+
+        let sql = format!(
+            "SELECT t.id, t.device_id, t.name, t.address, t.size, t.data_type, t.description,
+                    t.scaling_multiplier, t.scaling_offset, t.unit, t.read_only, t.enabled, t.schedule_group_id, t.agg_to_field, t.write_policy, t.byte_order,
+                    t.deadband_absolute, t.deadband_percent, d.name, d.model_id, m.name, t.register_type
+             FROM device_tags t
+             JOIN devices d ON d.id = t.device_id
+             LEFT JOIN device_models m ON m.id = d.model_id
+             WHERE {}
+             ORDER BY d.name, t.device_id, t.address, t.id
+             LIMIT ?{} OFFSET ?{}",
+            conditions,
+            values.len() - 1,
+            values.len()
+        );
+
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(values.iter()), |row| {
+            Ok(TagSearchResult {
+                tag: DeviceTag {
+                    id: Some(row.get(0)?),
+                    device_id: row.get(1)?,
+                    name: row.get(2)?,
+                    address: row.get::<_, i32>(3)? as u16,
+                    size: row.get(4)?,
+                    data_type: row.get(5)?,
+                    description: row.get(6)?,
+                    scaling_multiplier: row.get(7)?,
+                    scaling_offset: row.get(8)?,
+                    unit: row.get(9)?,
+                    read_only: row.get(10)?,
+                    enabled: row.get(11)?,
+                    schedule_group_id: row.get(12)?,
+                    agg_to_field: row.get(13)?,
+                    write_policy: TagWritePolicy::parse(&row.get::<_, String>(14)?),
+                    byte_order: row.get::<_, Option<String>>(15)?.as_deref().and_then(ByteOrder::parse),
+                    deadband_absolute: row.get(16)?,
+                    deadband_percent: row.get(17)?,
+                    register_type: row.get::<_, Option<String>>(21)?.as_deref().and_then(RegisterType::parse),
+                },
+                device_name: row.get(18)?,
+                model_id: row.get(19)?,
+                model_name: row.get(20)?,
             })
         })?;
 
-        let mut tags = Vec::new();
+        let mut results = Vec::new();
         for row in rows {
-            tags.push(row?);
+            results.push(row?);
         }
 
-        Ok(tags)
+        Ok(results)
     }
 
-    pub async fn update_device(&self, device: &DeviceInstance) -> Result<()> {
+    pub async fn count_tag_search(&self, filter: &TagSearchFilter) -> Result<i64> {
+        let conn = self.readers.get().await;
+        let (conditions, values) = Self::tag_search_conditions(filter);
+
+        let count = conn.query_row(
+            &format!(
+                "SELECT COUNT(*) FROM device_tags t JOIN devices d ON d.id = t.device_id WHERE {}",
+                conditions
+            ),
+            rusqlite::params_from_iter(values.iter()),
+            |row| row.get(0),
+        )?;
+
+        Ok(count)
+    }
+
+    pub async fn save_tag_search(&self, search: &SavedTagSearch) -> Result<()> {
         let conn = self.connection.lock().await;
-        let updated_str = device.updated_at.to_rfc3339();
 
         conn.execute(
-            "UPDATE devices 
-             SET name = ?1, serial_no = ?2, model_id = ?3, enabled = ?4, polling_interval_ms = ?5, 
-                 timeout_ms = ?6, retry_count = ?7, protocol_config = ?8, tb_device_id = ?9, tb_group_id = ?10, updated_at = ?11
-             WHERE id = ?12",
+            "INSERT INTO saved_tag_searches (id, name, filter, created_by, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
             params![
-                device.name,
-                device.serial_no,
-                device.model_id,
-                device.enabled,
-                device.polling_interval_ms,
-                device.timeout_ms,
-                device.retry_count,
-                device.protocol_config,
-                device.tb_device_id,
-                device.tb_group_id,
-                updated_str,
-                device.id
+                search.id,
+                search.name,
+                serde_json::to_string(&search.filter)?,
+                search.created_by,
+                search.created_at.to_rfc3339()
             ],
         )?;
 
         Ok(())
     }
 
-    /// Get unsynced devices (devices without ThingsBoard device ID and group ID)
-    pub async fn get_unsynced_devices(&self) -> Result<Vec<DeviceInstance>> {
-        let conn = self.connection.lock().await;
-        
-        let mut stmt = conn.prepare(
-            "SELECT id, name, serial_no, model_id, enabled, polling_interval_ms, timeout_ms, retry_count, 
-                    protocol_config, tb_device_id, tb_group_id, created_at, updated_at 
-             FROM devices 
-             WHERE (tb_device_id IS NULL OR tb_device_id = '') AND (tb_group_id IS NULL OR tb_group_id = '')
-             ORDER BY name"
-        )?;
+    pub async fn get_saved_tag_search(&self, id: &str) -> Result<Option<SavedTagSearch>> {
+        let conn = self.readers.get().await;
+
+        let result = conn.query_row(
+            "SELECT id, name, filter, created_by, created_at FROM saved_tag_searches WHERE id = ?1",
+            [id],
+            |row| {
+                let filter_str: String = row.get(2)?;
+                let filter = serde_json::from_str(&filter_str)
+                    .map_err(|_| rusqlite::Error::InvalidColumnType(2, "filter".to_string(), rusqlite::types::Type::Text))?;
+                let created_str: String = row.get(4)?;
+                let created_at = DateTime::parse_from_rfc3339(&created_str)
+                    .map_err(|_| rusqlite::Error::InvalidColumnType(4, "created_at".to_string(), rusqlite::types::Type::Text))?
+                    .with_timezone(&Utc);
+
+                Ok(SavedTagSearch {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    filter,
+                    created_by: row.get(3)?,
+                    created_at,
+                })
+            },
+        );
 
-        let rows = stmt.query_map([], |row| {
-            let created_str: String = row.get("created_at")?;
-            let updated_str: String = row.get("updated_at")?;
-            let created_at = DateTime::parse_from_rfc3339(&created_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(9, "created_at".to_string(), rusqlite::types::Type::Text))?
-                .with_timezone(&Utc);
-            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(10, "updated_at".to_string(), rusqlite::types::Type::Text))?
-                .with_timezone(&Utc);
+        match result {
+            Ok(search) => Ok(Some(search)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
 
-            Ok(DeviceInstance {
-                id: row.get("id")?,
-                name: row.get("name")?,
-                serial_no: row.get("serial_no")?,
-                model_id: row.get("model_id")?,
-                enabled: row.get("enabled")?,
-                polling_interval_ms: row.get::<_, i32>("polling_interval_ms")? as u32,
-                timeout_ms: row.get::<_, i32>("timeout_ms")? as u32,
-                retry_count: row.get::<_, i32>("retry_count")? as u32,
-                protocol_config: row.get("protocol_config")?,
-                tb_device_id: row.get("tb_device_id")?,
-                tb_group_id: row.get("tb_group_id")?,
-                created_at,
-                updated_at,
-            })
-        })?;
+    /// Apply the same changes to every tag matching a filter in one statement.
+    ///
+    /// Returns the ids of the devices whose tags were changed.
+    pub async fn bulk_update_tags(&self, filter: &TagSearchFilter, changes: &TagBulkChanges) -> Result<(usize, Vec<String>)> {
+        let mut conn = self.connection.lock().await;
+        let tx = conn.transaction()?;
+        let (conditions, mut values) = Self::tag_search_conditions(filter);
+
+        let device_ids = {
+            let mut stmt = tx.prepare(&format!(
+                "SELECT DISTINCT t.device_id FROM device_tags t JOIN devices d ON d.id = t.device_id
+                 WHERE {} ORDER BY t.device_id",
+                conditions
+            ))?;
+            let rows = stmt.query_map(rusqlite::params_from_iter(values.iter()), |row| row.get::<_, String>(0))?;
+            rows.collect::<rusqlite::Result<Vec<String>>>()?
+        };
 
-        let mut devices = Vec::new();
-        for row in rows {
-            devices.push(row?);
-        }
+        let first_change = values.len() + 1;
+        values.extend([
+            changes.address.map(|v| rusqlite::types::Value::Integer(v as i64)),
+            changes.size.map(|v| rusqlite::types::Value::Integer(v as i64)),
+            changes.data_type.clone().map(rusqlite::types::Value::Text),
+            changes.unit.clone().map(rusqlite::types::Value::Text),
+            changes.scaling_multiplier.map(rusqlite::types::Value::Real),
+            changes.scaling_offset.map(rusqlite::types::Value::Real),
+            changes.schedule_group_id.clone().map(rusqlite::types::Value::Text),
+            changes.enabled.map(|v| rusqlite::types::Value::Integer(v as i64)),
+        ].into_iter().map(|value| value.unwrap_or(rusqlite::types::Value::Null)));
+
+        let columns = ["address", "size", "data_type", "unit", "scaling_multiplier", "scaling_offset", "schedule_group_id", "enabled"];
+        let assignments: Vec<String> = columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| format!("{} = COALESCE(?{}, {})", column, first_change + i, column))
+            .collect();
 
-        Ok(devices)
+        let rows_affected = tx.execute(
+            &format!(
+                "UPDATE device_tags SET {} WHERE id IN (
+                    SELECT t.id FROM device_tags t JOIN devices d ON d.id = t.device_id WHERE {})",
+                assignments.join(", "),
+                conditions
+            ),
+            rusqlite::params_from_iter(values.iter()),
+        )?;
+
+        tx.commit()?;
+        Ok((rows_affected, device_ids))
     }
 
-    /// Get devices by ThingsBoard group ID
-    pub async fn get_devices_by_group_id(&self, group_id: &str) -> Result<Vec<DeviceInstance>> {
+    pub async fn record_tag_write_audit(&self, audit: &TagWriteAudit) -> Result<i64> {
         let conn = self.connection.lock().await;
-        
+
+        conn.execute(
+            "INSERT INTO tag_write_audit
+             (device_id, tag_id, tag_name, username, previous_value, requested_value, outcome, detail, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                audit.device_id,
+                audit.tag_id,
+                audit.tag_name,
+                audit.username,
+                audit.previous_value,
+                audit.requested_value,
+                audit.outcome,
+                audit.detail,
+                audit.created_at.to_rfc3339()
+            ],
+        )?;
+
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// Tag write attempts for a device, newest first
+    pub async fn get_tag_write_audit(&self, device_id: &str, limit: u32) -> Result<Vec<TagWriteAudit>> {
+        let conn = self.readers.get().await;
+
         let mut stmt = conn.prepare(
-            "SELECT id, name, serial_no, model_id, enabled, polling_interval_ms, timeout_ms, retry_count, 
-                    protocol_config, tb_device_id, tb_group_id, created_at, updated_at 
-             FROM devices 
-             WHERE tb_group_id = ?1
-             ORDER BY name"
+            "SELECT id, device_id, tag_id, tag_name, username, previous_value, requested_value, outcome, detail, created_at
+             FROM tag_write_audit WHERE device_id = ?1
+             ORDER BY created_at DESC, id DESC
+             LIMIT ?2"
         )?;
 
-        let rows = stmt.query_map([group_id], |row| {
-            let created_str: String = row.get("created_at")?;
-            let updated_str: String = row.get("updated_at")?;
+        let rows = stmt.query_map(params![device_id, limit], |row| {
+            let created_str: String = row.get(9)?;
             let created_at = DateTime::parse_from_rfc3339(&created_str)
                 .map_err(|_| rusqlite::Error::InvalidColumnType(9, "created_at".to_string(), rusqlite::types::Type::Text))?
                 .with_timezone(&Utc);
-            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(10, "updated_at".to_string(), rusqlite::types::Type::Text))?
-                .with_timezone(&Utc);
 
-            Ok(DeviceInstance {
-                id: row.get("id")?,
-                name: row.get("name")?,
-                serial_no: row.get("serial_no")?,
-                model_id: row.get("model_id")?,
-                enabled: row.get("enabled")?,
-                polling_interval_ms: row.get::<_, i32>("polling_interval_ms")? as u32,
-                timeout_ms: row.get::<_, i32>("timeout_ms")? as u32,
-                retry_count: row.get::<_, i32>("retry_count")? as u32,
-                protocol_config: row.get("protocol_config")?,
-                tb_device_id: row.get("tb_device_id")?,
-                tb_group_id: row.get("tb_group_id")?,
+            Ok(TagWriteAudit {
+                id: Some(row.get(0)?),
+                device_id: row.get(1)?,
+                tag_id: row.get(2)?,
+                tag_name: row.get(3)?,
+                username: row.get(4)?,
+                previous_value: row.get(5)?,
+                requested_value: row.get(6)?,
+                outcome: row.get(7)?,
+                detail: row.get(8)?,
                 created_at,
-                updated_at,
             })
         })?;
 
-        let mut devices = Vec::new();
+        let mut entries = Vec::new();
         for row in rows {
-            devices.push(row?);
+            entries.push(row?);
         }
 
-        Ok(devices)
+        Ok(entries)
     }
 
-    pub async fn delete_device_tags(&self, device_id: &str) -> Result<()> {
+    pub async fn insert_audit_entry(&self, entry: &AuditEntry) -> Result<i64> {
         let conn = self.connection.lock().await;
-        
+
         conn.execute(
-            "DELETE FROM device_tags WHERE device_id = ?1",
-            params![device_id],
+            "INSERT INTO audit_log (timestamp, username, action, entity_type, entity_id, before_json, after_json)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                entry.timestamp.to_rfc3339(),
+                entry.username,
+                entry.action,
+                entry.entity_type,
+                entry.entity_id,
+                entry.before.as_ref().map(|before| before.to_string()),
+                entry.after.as_ref().map(|after| after.to_string()),
+            ],
         )?;
 
-        Ok(())
+        Ok(conn.last_insert_rowid())
     }
 
-    pub async fn delete_device(&self, device_id: &str) -> Result<()> {
-        let conn = self.connection.lock().await;
-        
-        // Start a transaction to ensure data consistency
-        conn.execute("BEGIN TRANSACTION", [])?;
-        
-        // First, check if the device exists
-        let mut stmt = conn.prepare("SELECT COUNT(*) FROM devices WHERE id = ?1")?;
-        let count: i64 = stmt.query_row([device_id], |row| row.get(0))?;
-        
-        if count == 0 {
-            conn.execute("ROLLBACK", [])?;
-            return Err(anyhow::anyhow!("Device not found"));
-        }
-        
-        // Delete all device tags first
-        let mut stmt = conn.prepare("DELETE FROM device_tags WHERE device_id = ?1")?;
-        let deleted_tags = stmt.execute([device_id])?;
-        
-        // Delete device status
-        let mut stmt = conn.prepare("DELETE FROM device_status WHERE device_id = ?1")?;
-        let _deleted_status = stmt.execute([device_id])?;
-        
-        // Finally, delete the device itself
-        let mut stmt = conn.prepare("DELETE FROM devices WHERE id = ?1")?;
-        let _deleted_device = stmt.execute([device_id])?;
-        
-        // Commit the transaction
-        conn.execute("COMMIT", [])?;
-        
-        info!("Deleted device {} and {} associated tags", device_id, deleted_tags);
-        Ok(())
-    }
+    /// Audit entries, newest first, optionally for one entity id and entity type
+    pub async fn get_audit_entries(
+        &self,
+        entity_id: Option<&str>,
+        entity_type: Option<&str>,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> Result<Vec<AuditEntry>> {
+        let conn = self.readers.get().await;
 
-    // Schedule Group CRUD operations
-    pub async fn get_schedule_groups(&self) -> Result<Vec<ScheduleGroup>> {
-        let conn = self.connection.lock().await;
-        
         let mut stmt = conn.prepare(
-            "SELECT id, name, polling_interval_ms, description, enabled, created_at, updated_at 
-             FROM schedule_groups ORDER BY polling_interval_ms"
+            "SELECT id, timestamp, username, action, entity_type, entity_id, before_json, after_json
+             FROM audit_log
+             WHERE (?1 IS NULL OR entity_id = ?1) AND (?2 IS NULL OR entity_type = ?2)
+             ORDER BY timestamp DESC, id DESC
+             LIMIT ?3 OFFSET ?4"
         )?;
-
-        let rows = stmt.query_map([], |row| {
-            let created_str: String = row.get(5)?;
-            let updated_str: String = row.get(6)?;
-            let created_at = DateTime::parse_from_rfc3339(&created_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(5, "created_at".to_string(), rusqlite::types::Type::Text))?
-                .with_timezone(&Utc);
-            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(6, "updated_at".to_string(), rusqlite::types::Type::Text))?
-                .with_timezone(&Utc);
-
-            Ok(ScheduleGroup {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                polling_interval_ms: row.get::<_, i32>(2)? as u32,
-                description: row.get(3)?,
-                enabled: row.get(4)?,
-                created_at,
-                updated_at,
+
+        // -1 means no limit
+        let rows = stmt.query_map(params![entity_id, entity_type, limit.map(i64::from).unwrap_or(-1), offset.unwrap_or(0)], |row| {
+            let timestamp_str: String = row.get(1)?;
+            let timestamp = DateTime::parse_from_rfc3339(&timestamp_str)
+                .map_err(|_| rusqlite::Error::InvalidColumnType(1, "timestamp".to_string(), rusqlite::types::Type::Text))?
+                .with_timezone(&Utc);
+            let json = |index: usize| -> rusqlite::Result<Option<serde_json::Value>> {
+                Ok(row.get::<_, Option<String>>(index)?.and_then(|text| serde_json::from_str(&text).ok()))
+            };
+
+            Ok(AuditEntry {
+                id: Some(row.get(0)?),
+                timestamp,
+                username: row.get(2)?,
+                action: row.get(3)?,
+                entity_type: row.get(4)?,
+                entity_id: row.get(5)?,
+                before: json(6)?,
+                after: json(7)?,
             })
         })?;
 
-        let mut schedule_groups = Vec::new();
+        let mut entries = Vec::new();
         for row in rows {
-            schedule_groups.push(row?);
+            entries.push(row?);
         }
 
-        Ok(schedule_groups)
+        Ok(entries)
     }
 
-    pub async fn get_schedule_group(&self, id: &str) -> Result<Option<ScheduleGroup>> {
-        let conn = self.connection.lock().await;
-        
-        let mut stmt = conn.prepare(
-            "SELECT id, name, polling_interval_ms, description, enabled, created_at, updated_at 
-             FROM schedule_groups WHERE id = ?1"
+    pub async fn count_audit_entries(&self, entity_id: Option<&str>, entity_type: Option<&str>) -> Result<u64> {
+        let conn = self.readers.get().await;
+
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM audit_log WHERE (?1 IS NULL OR entity_id = ?1) AND (?2 IS NULL OR entity_type = ?2)",
+            params![entity_id, entity_type],
+            |row| row.get(0),
         )?;
 
-        let result = stmt.query_row([id], |row| {
-            let created_str: String = row.get(5)?;
-            let updated_str: String = row.get(6)?;
-            let created_at = DateTime::parse_from_rfc3339(&created_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(5, "created_at".to_string(), rusqlite::types::Type::Text))?
-                .with_timezone(&Utc);
-            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(6, "updated_at".to_string(), rusqlite::types::Type::Text))?
-                .with_timezone(&Utc);
+        Ok(count as u64)
+    }
 
-            Ok(ScheduleGroup {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                polling_interval_ms: row.get::<_, i32>(2)? as u32,
-                description: row.get(3)?,
-                enabled: row.get(4)?,
-                created_at,
-                updated_at,
-            })
-        });
+    /// Alarm rules, oldest first
+    pub async fn get_alarm_rules(&self) -> Result<Vec<AlarmRule>> {
+        let conn = self.readers.get().await;
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM alarm_rules ORDER BY id", ALARM_RULE_COLUMNS))?;
+        let rules = stmt.query_map([], alarm_rule_from_row)?.collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(rules)
+    }
 
+    pub async fn get_alarm_rule(&self, rule_id: i64) -> Result<Option<AlarmRule>> {
+        let conn = self.readers.get().await;
+        let result = conn.query_row(
+            &format!("SELECT {} FROM alarm_rules WHERE id = ?1", ALARM_RULE_COLUMNS),
+            params![rule_id],
+            alarm_rule_from_row,
+        );
         match result {
-            Ok(schedule_group) => Ok(Some(schedule_group)),
+            Ok(rule) => Ok(Some(rule)),
             Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
             Err(e) => Err(e.into()),
         }
     }
 
-    pub async fn create_schedule_group(&self, schedule_group: &ScheduleGroup) -> Result<()> {
+    pub async fn create_alarm_rule(&self, rule: &NewAlarmRule) -> Result<AlarmRule> {
         let conn = self.connection.lock().await;
-        let created_str = schedule_group.created_at.to_rfc3339();
-        let updated_str = schedule_group.updated_at.to_rfc3339();
-
+        let now = Utc::now();
         conn.execute(
-            "INSERT INTO schedule_groups (id, name, polling_interval_ms, description, enabled, created_at, updated_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            "INSERT INTO alarm_rules (device_id, model_id, tag_name, condition, threshold, hysteresis, clear_hold_seconds, severity, enabled, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?10)",
             params![
-                schedule_group.id,
-                schedule_group.name,
-                schedule_group.polling_interval_ms,
-                schedule_group.description,
-                schedule_group.enabled,
-                created_str,
-                updated_str
+                rule.device_id,
+                rule.model_id,
+                rule.tag_name,
+                rule.condition.as_str(),
+                rule.threshold,
+                rule.hysteresis,
+                rule.clear_hold_seconds,
+                rule.severity,
+                rule.enabled,
+                now.to_rfc3339(),
             ],
         )?;
 
-        Ok(())
+        Ok(AlarmRule { id: conn.last_insert_rowid(), rule: rule.clone(), created_at: now, updated_at: now })
     }
 
-    pub async fn update_schedule_group(&self, schedule_group: &ScheduleGroup) -> Result<()> {
-        let conn = self.connection.lock().await;
-        let updated_str = schedule_group.updated_at.to_rfc3339();
+    /// Replace a rule. Alarms it has open are cleared when it is disabled or no longer
+    /// covers their device. Returns None if there is no such rule.
+    pub async fn update_alarm_rule(&self, rule_id: i64, rule: &NewAlarmRule) -> Result<Option<AlarmRule>> {
+        let now = Utc::now();
+        let mut conn = self.connection.lock().await;
+        let tx = conn.transaction()?;
+        let created_at = match tx.query_row("SELECT created_at FROM alarm_rules WHERE id = ?1", params![rule_id], |row| row.get::<_, String>(0)) {
+            Ok(created_at) => DateTime::parse_from_rfc3339(&created_at)?.with_timezone(&Utc),
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
 
-        conn.execute(
-            "UPDATE schedule_groups SET name = ?1, polling_interval_ms = ?2, description = ?3, 
-             enabled = ?4, updated_at = ?5 WHERE id = ?6",
+        tx.execute(
+            "UPDATE alarm_rules SET device_id = ?1, model_id = ?2, tag_name = ?3, condition = ?4, threshold = ?5, hysteresis = ?6,
+             clear_hold_seconds = ?7, severity = ?8, enabled = ?9, updated_at = ?10 WHERE id = ?11",
             params![
-                schedule_group.name,
-                schedule_group.polling_interval_ms,
-                schedule_group.description,
-                schedule_group.enabled,
-                updated_str,
-                schedule_group.id
+                rule.device_id,
+                rule.model_id,
+                rule.tag_name,
+                rule.condition.as_str(),
+                rule.threshold,
+                rule.hysteresis,
+                rule.clear_hold_seconds,
+                rule.severity,
+                rule.enabled,
+                now.to_rfc3339(),
+                rule_id,
             ],
         )?;
+        tx.execute(
+            "UPDATE alarm_events SET cleared_at = ?1 WHERE rule_id = ?2 AND cleared_at IS NULL AND (
+                ?3 = 0 OR tag_name != ?4 OR NOT EXISTS (
+                    SELECT 1 FROM devices d WHERE d.id = alarm_events.device_id AND (d.id = ?5 OR d.model_id = ?6)))",
+            params![now.to_rfc3339(), rule_id, rule.enabled, rule.tag_name, rule.device_id, rule.model_id],
+        )?;
+        tx.commit()?;
 
-        Ok(())
+        Ok(Some(AlarmRule { id: rule_id, rule: rule.clone(), created_at, updated_at: now }))
     }
 
-    pub async fn delete_schedule_group(&self, id: &str) -> Result<()> {
-        let conn = self.connection.lock().await;
-        
-        conn.execute(
-            "DELETE FROM schedule_groups WHERE id = ?1",
-            params![id],
+    /// Delete a rule and clear the alarms it has open; its past alarms are kept
+    pub async fn delete_alarm_rule(&self, rule_id: i64) -> Result<bool> {
+        let mut conn = self.connection.lock().await;
+        let tx = conn.transaction()?;
+        let deleted = tx.execute("DELETE FROM alarm_rules WHERE id = ?1", params![rule_id])?;
+        tx.execute(
+            "UPDATE alarm_events SET cleared_at = ?1 WHERE rule_id = ?2 AND cleared_at IS NULL",
+            params![Utc::now().to_rfc3339(), rule_id],
         )?;
+        tx.commit()?;
 
-        Ok(())
+        Ok(deleted > 0)
     }
 
-    // Modbus TCP Tag Register CRUD operations
-    pub async fn create_modbus_tcp_tag_register(&self, tag_register: &CreateModbusTcpTagRegister) -> Result<ModbusTcpTagRegister> {
+    /// Virtual tags, oldest first
+    pub async fn get_virtual_tags(&self) -> Result<Vec<VirtualTag>> {
+        let conn = self.readers.get().await;
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM virtual_tags ORDER BY id", VIRTUAL_TAG_COLUMNS))?;
+        let tags = stmt.query_map([], virtual_tag_from_row)?.collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(tags)
+    }
+
+    pub async fn get_virtual_tag(&self, tag_id: i64) -> Result<Option<VirtualTag>> {
+        let conn = self.readers.get().await;
+        let result = conn.query_row(
+            &format!("SELECT {} FROM virtual_tags WHERE id = ?1", VIRTUAL_TAG_COLUMNS),
+            params![tag_id],
+            virtual_tag_from_row,
+        );
+        match result {
+            Ok(tag) => Ok(Some(tag)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn create_virtual_tag(&self, tag: &NewVirtualTag) -> Result<VirtualTag> {
         let conn = self.connection.lock().await;
         let now = Utc::now();
-        let created_str = now.to_rfc3339();
-        let updated_str = now.to_rfc3339();
-
         conn.execute(
-            "INSERT INTO modbus_tcp_tag_registers (
-                device_brand, device_model, ava_type, mppt, input, data_label, 
-                address, size, modbus_type, divider, register_type, created_at, updated_at
-            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            "INSERT INTO virtual_tags (name, expression, device_id, group_id, schedule_group_id, unit, enabled, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?8)",
             params![
-                tag_register.device_brand,
-                tag_register.device_model,
-                tag_register.ava_type,
-                tag_register.mppt,
-                tag_register.input,
-                tag_register.data_label,
-                tag_register.address,
-                tag_register.size,
-                tag_register.modbus_type,
-                tag_register.divider,
-                tag_register.register_type,
-                created_str,
-                updated_str
+                tag.name,
+                tag.expression,
+                tag.device_id,
+                tag.group_id,
+                tag.schedule_group_id,
+                tag.unit,
+                tag.enabled,
+                now.to_rfc3339(),
             ],
         )?;
 
-        let id = conn.last_insert_rowid();
-
-        Ok(ModbusTcpTagRegister {
-            id: Some(id),
-            device_brand: tag_register.device_brand.clone(),
-            device_model: tag_register.device_model.clone(),
-            ava_type: tag_register.ava_type.clone(),
-            mppt: tag_register.mppt,
-            input: tag_register.input,
-            data_label: tag_register.data_label.clone(),
-            address: tag_register.address,
-            size: tag_register.size,
-            modbus_type: tag_register.modbus_type.clone(),
-            divider: tag_register.divider,
-            register_type: tag_register.register_type.clone(),
-            created_at: now,
-            updated_at: now,
-        })
+        Ok(VirtualTag { id: conn.last_insert_rowid(), tag: tag.clone(), created_at: now, updated_at: now })
     }
 
-    pub async fn bulk_insert_modbus_tcp_tag_registers(&self, tag_registers: Vec<CreateModbusTcpTagRegister>) -> Result<u64> {
-        let conn = self.connection.lock().await;
-        let tx = conn.unchecked_transaction()?;
+    /// Replace a virtual tag. Returns None if there is no such tag.
+    pub async fn update_virtual_tag(&self, tag_id: i64, tag: &NewVirtualTag) -> Result<Option<VirtualTag>> {
         let now = Utc::now();
-        let created_str = now.to_rfc3339();
-        let updated_str = now.to_rfc3339();
+        let conn = self.connection.lock().await;
+        let created_at = match conn.query_row("SELECT created_at FROM virtual_tags WHERE id = ?1", params![tag_id], |row| row.get::<_, String>(0)) {
+            Ok(created_at) => DateTime::parse_from_rfc3339(&created_at)?.with_timezone(&Utc),
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
 
-        let mut inserted_count = 0;
+        conn.execute(
+            "UPDATE virtual_tags SET name = ?1, expression = ?2, device_id = ?3, group_id = ?4, schedule_group_id = ?5, unit = ?6,
+             enabled = ?7, updated_at = ?8 WHERE id = ?9",
+            params![
+                tag.name,
+                tag.expression,
+                tag.device_id,
+                tag.group_id,
+                tag.schedule_group_id,
+                tag.unit,
+                tag.enabled,
+                now.to_rfc3339(),
+                tag_id,
+            ],
+        )?;
 
-        for tag_register in tag_registers {
-            tx.execute(
-                "INSERT OR REPLACE INTO modbus_tcp_tag_registers (
-                    device_brand, device_model, ava_type, mppt, input, data_label, 
-                    address, size, modbus_type, divider, register_type, created_at, updated_at
-                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
-                params![
-                    tag_register.device_brand,
-                    tag_register.device_model,
-                    tag_register.ava_type,
-                    tag_register.mppt,
-                    tag_register.input,
-                    tag_register.data_label,
-                    tag_register.address,
-                    tag_register.size,
-                    tag_register.modbus_type,
-                    tag_register.divider,
-                    tag_register.register_type,
-                    created_str,
-                    updated_str
-                ],
-            )?;
-            
-            inserted_count += 1;
-        }
+        Ok(Some(VirtualTag { id: tag_id, tag: tag.clone(), created_at, updated_at: now }))
+    }
 
-        tx.commit()?;
-        Ok(inserted_count)
+    /// Delete a virtual tag; the values it logged are kept
+    pub async fn delete_virtual_tag(&self, tag_id: i64) -> Result<bool> {
+        let conn = self.connection.lock().await;
+        let deleted = conn.execute("DELETE FROM virtual_tags WHERE id = ?1", params![tag_id])?;
+        Ok(deleted > 0)
     }
 
-    pub async fn get_modbus_tcp_tag_registers_by_device(&self, device_brand: &str, device_model: &str) -> Result<Vec<ModbusTcpTagRegister>> {
+    /// Open an alarm of `rule` on a device
+    pub async fn raise_alarm(&self, rule: &AlarmRule, device_id: &str, value: f64, raised_at: DateTime<Utc>) -> Result<AlarmEvent> {
         let conn = self.connection.lock().await;
-        
-        let mut stmt = conn.prepare(
-            "SELECT id, device_brand, device_model, ava_type, mppt, input, data_label, 
-                    address, size, modbus_type, divider, register_type, created_at, updated_at 
-             FROM modbus_tcp_tag_registers 
-             WHERE device_brand = ?1 AND device_model = ?2 
-             ORDER BY ava_type, mppt, input, address ASC"
+        conn.execute(
+            "INSERT INTO alarm_events (rule_id, device_id, tag_name, condition, threshold, severity, raised_at, raised_value, peak_value)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?8)",
+            params![
+                rule.id,
+                device_id,
+                rule.rule.tag_name,
+                rule.rule.condition.as_str(),
+                rule.rule.threshold,
+                rule.rule.severity,
+                raised_at.to_rfc3339(),
+                value,
+            ],
         )?;
 
-        let rows = stmt.query_map([device_brand, device_model], |row| {
-            let created_str: String = row.get(12)?;
-            let updated_str: String = row.get(13)?;
-            let created_at = DateTime::parse_from_rfc3339(&created_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(12, "created_at".to_string(), rusqlite::types::Type::Text))?
-                .with_timezone(&Utc);
-            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(13, "updated_at".to_string(), rusqlite::types::Type::Text))?
-                .with_timezone(&Utc);
-
-            Ok(ModbusTcpTagRegister {
-                id: Some(row.get(0)?),
-                device_brand: row.get(1)?,
-                device_model: row.get(2)?,
-                ava_type: row.get(3)?,
-                mppt: row.get(4)?,
-                input: row.get(5)?,
-                data_label: row.get(6)?,
-                address: row.get(7)?,
-                size: row.get(8)?,
-                modbus_type: row.get(9)?,
-                divider: row.get(10)?,
-                register_type: row.get(11)?,
-                created_at,
-                updated_at,
-            })
-        })?;
-
-        let mut tag_registers = Vec::new();
-        for row in rows {
-            tag_registers.push(row?);
-        }
-
-        Ok(tag_registers)
+        Ok(AlarmEvent {
+            id: conn.last_insert_rowid(),
+            rule_id: rule.id,
+            device_id: device_id.to_string(),
+            tag_name: rule.rule.tag_name.clone(),
+            condition: rule.rule.condition,
+            threshold: rule.rule.threshold,
+            severity: rule.rule.severity.clone(),
+            raised_at,
+            raised_value: value,
+            peak_value: value,
+            cleared_at: None,
+        })
     }
 
-    pub async fn get_modbus_tcp_tag_registers_by_model(&self, device_model: &str) -> Result<Vec<ModbusTcpTagRegister>> {
+    /// Record a new peak of a rule's open alarm on a device
+    pub async fn update_alarm_peak(&self, rule_id: i64, device_id: &str, peak_value: f64) -> Result<()> {
         let conn = self.connection.lock().await;
-        
-        let mut stmt = conn.prepare(
-            "SELECT id, device_brand, device_model, ava_type, mppt, input, data_label, 
-                    address, size, modbus_type, divider, register_type, created_at, updated_at 
-             FROM modbus_tcp_tag_registers 
-             WHERE device_model = ?1 
-             ORDER BY ava_type, mppt, input, address ASC"
+        conn.execute(
+            "UPDATE alarm_events SET peak_value = ?1 WHERE rule_id = ?2 AND device_id = ?3 AND cleared_at IS NULL",
+            params![peak_value, rule_id, device_id],
         )?;
+        Ok(())
+    }
 
-        let rows = stmt.query_map([device_model], |row| {
-            let created_str: String = row.get(12)?;
-            let updated_str: String = row.get(13)?;
-            let created_at = DateTime::parse_from_rfc3339(&created_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(12, "created_at".to_string(), rusqlite::types::Type::Text))?
-                .with_timezone(&Utc);
-            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(13, "updated_at".to_string(), rusqlite::types::Type::Text))?
-                .with_timezone(&Utc);
-
-            Ok(ModbusTcpTagRegister {
-                id: Some(row.get(0)?),
-                device_brand: row.get(1)?,
-                device_model: row.get(2)?,
-                ava_type: row.get(3)?,
-                mppt: row.get(4)?,
-                input: row.get(5)?,
-                data_label: row.get(6)?,
-                address: row.get(7)?,
-                size: row.get(8)?,
-                modbus_type: row.get(9)?,
-                divider: row.get(10)?,
-                register_type: row.get(11)?,
-                created_at,
-                updated_at,
-            })
-        })?;
-
-        let mut tag_registers = Vec::new();
-        for row in rows {
-            tag_registers.push(row?);
+    /// Clear a rule's open alarm on a device, returning it; None if it had none open
+    pub async fn clear_alarm(&self, rule_id: i64, device_id: &str, cleared_at: DateTime<Utc>) -> Result<Option<AlarmEvent>> {
+        let conn = self.connection.lock().await;
+        let result = conn.query_row(
+            &format!(
+                "UPDATE alarm_events SET cleared_at = ?1 WHERE rule_id = ?2 AND device_id = ?3 AND cleared_at IS NULL RETURNING {}",
+                ALARM_EVENT_COLUMNS
+            ),
+            params![cleared_at.to_rfc3339(), rule_id, device_id],
+            alarm_event_from_row,
+        );
+        match result {
+            Ok(event) => Ok(Some(event)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
         }
+    }
 
-        Ok(tag_registers)
+    /// Alarms, most recently raised first; only those still open when `active_only`, and
+    /// only a device's when `device_id` is given
+    pub async fn get_alarm_events(&self, active_only: bool, device_id: Option<&str>, limit: u32) -> Result<Vec<AlarmEvent>> {
+        let conn = self.readers.get().await;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM alarm_events
+             WHERE (?1 = 0 OR cleared_at IS NULL) AND (?2 IS NULL OR device_id = ?2)
+             ORDER BY raised_at DESC, id DESC LIMIT ?3",
+            ALARM_EVENT_COLUMNS
+        ))?;
+        let events = stmt
+            .query_map(params![active_only, device_id, limit], alarm_event_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(events)
     }
 
-    pub async fn get_modbus_tcp_tag_registers_by_model_id(&self, model_id: &str) -> Result<Vec<ModbusTcpTagRegister>> {
+    /// Alarms raised in `[start, end)`, in the order they were raised
+    pub async fn get_alarm_events_raised_between(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<AlarmEvent>> {
+        let conn = self.readers.get().await;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM alarm_events WHERE raised_at >= ?1 AND raised_at < ?2 ORDER BY raised_at, id",
+            ALARM_EVENT_COLUMNS
+        ))?;
+        let events = stmt
+            .query_map(params![start.to_rfc3339(), end.to_rfc3339()], alarm_event_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(events)
+    }
+
+    /// Number of alarms still open
+    pub async fn count_active_alarm_events(&self) -> Result<u64> {
+        let conn = self.readers.get().await;
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM alarm_events WHERE cleared_at IS NULL", [], |row| row.get(0))?;
+        Ok(count as u64)
+    }
+
+    /// Record an event about to be sent to a webhook, keeping only the webhook's latest
+    /// `keep` deliveries
+    pub async fn create_webhook_delivery(&self, webhook_id: &str, event: &str, payload: &str, keep: u32) -> Result<i64> {
+        let now = Utc::now().to_rfc3339();
+        let mut conn = self.connection.lock().await;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO webhook_deliveries (webhook_id, event, payload, status, attempts, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, 0, ?5, ?5)",
+            params![webhook_id, event, payload, WebhookDeliveryStatus::Pending.as_str(), now],
+        )?;
+        let id = tx.last_insert_rowid();
+        tx.execute(
+            "DELETE FROM webhook_deliveries WHERE webhook_id = ?1 AND id <= (
+                SELECT id FROM webhook_deliveries WHERE webhook_id = ?1 ORDER BY id DESC LIMIT 1 OFFSET ?2)",
+            params![webhook_id, keep],
+        )?;
+        tx.commit()?;
+        Ok(id)
+    }
+
+    /// Record the outcome of an attempt to deliver to a webhook
+    pub async fn record_webhook_attempt(
+        &self,
+        delivery_id: i64,
+        status: WebhookDeliveryStatus,
+        status_code: Option<u16>,
+        error: Option<&str>,
+    ) -> Result<()> {
         let conn = self.connection.lock().await;
-        
-        // Join with device_models table to get unique tags by model_id
-        // Use DISTINCT to eliminate any potential duplicates
-        let mut stmt = conn.prepare(
-            "SELECT DISTINCT mtr.id, mtr.device_brand, mtr.device_model, mtr.ava_type, mtr.mppt, mtr.input, 
-                    mtr.data_label, mtr.address, mtr.size, mtr.modbus_type, mtr.divider, mtr.register_type, 
-                    mtr.created_at, mtr.updated_at 
-             FROM modbus_tcp_tag_registers mtr
-             JOIN device_models dm ON dm.name = mtr.device_model AND dm.manufacturer = mtr.device_brand
-             WHERE dm.id = ?1 
-             ORDER BY mtr.ava_type, mtr.mppt, mtr.input, mtr.address ASC"
+        conn.execute(
+            "UPDATE webhook_deliveries
+             SET status = ?1, attempts = attempts + 1, last_status_code = ?2, last_error = ?3, updated_at = ?4
+             WHERE id = ?5",
+            params![status.as_str(), status_code, error, Utc::now().to_rfc3339(), delivery_id],
         )?;
+        Ok(())
+    }
 
-        let rows = stmt.query_map([model_id], |row| {
-            let created_str: String = row.get(12)?;
-            let updated_str: String = row.get(13)?;
-            let created_at = DateTime::parse_from_rfc3339(&created_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(12, "created_at".to_string(), rusqlite::types::Type::Text))?
-                .with_timezone(&Utc);
-            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(13, "updated_at".to_string(), rusqlite::types::Type::Text))?
-                .with_timezone(&Utc);
+    /// A webhook's deliveries, newest first
+    pub async fn get_webhook_deliveries(&self, webhook_id: &str, limit: u32) -> Result<Vec<WebhookDelivery>> {
+        let conn = self.readers.get().await;
+        let mut stmt = conn.prepare(
+            "SELECT id, webhook_id, event, payload, status, attempts, last_status_code, last_error, created_at, updated_at
+             FROM webhook_deliveries WHERE webhook_id = ?1 ORDER BY id DESC LIMIT ?2",
+        )?;
+        let deliveries = stmt
+            .query_map(params![webhook_id, limit], |row| {
+                Ok(WebhookDelivery {
+                    id: row.get(0)?,
+                    webhook_id: row.get(1)?,
+                    event: row.get(2)?,
+                    payload: serde_json::from_str(&row.get::<_, String>(3)?).unwrap_or(serde_json::Value::Null),
+                    status: WebhookDeliveryStatus::parse(&row.get::<_, String>(4)?),
+                    attempts: row.get(5)?,
+                    last_status_code: row.get(6)?,
+                    last_error: row.get(7)?,
+                    created_at: parse_timestamp(row.get(8)?, 8, "created_at")?,
+                    updated_at: parse_timestamp(row.get(9)?, 9, "updated_at")?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(deliveries)
+    }
 
-            Ok(ModbusTcpTagRegister {
-                id: Some(row.get(0)?),
-                device_brand: row.get(1)?,
-                device_model: row.get(2)?,
-                ava_type: row.get(3)?,
-                mppt: row.get(4)?,
-                input: row.get(5)?,
-                data_label: row.get(6)?,
-                address: row.get(7)?,
-                size: row.get(8)?,
-                modbus_type: row.get(9)?,
-                divider: row.get(10)?,
-                register_type: row.get(11)?,
-                created_at,
-                updated_at,
-            })
-        })?;
+    pub async fn delete_webhook_deliveries(&self, webhook_id: &str) -> Result<()> {
+        let conn = self.connection.lock().await;
+        conn.execute("DELETE FROM webhook_deliveries WHERE webhook_id = ?1", params![webhook_id])?;
+        Ok(())
+    }
 
-        let mut tag_registers = Vec::new();
-        for row in rows {
-            tag_registers.push(row?);
+    /// Add poll outcomes to the stored buckets, and delete buckets that started before `prune_before`
+    pub async fn save_poll_stats(&self, buckets: &[PollStatsBucket], prune_before: DateTime<Utc>) -> Result<()> {
+        let mut conn = self.connection.lock().await;
+        let tx = conn.transaction()?;
+        for bucket in buckets {
+            let bucket_start = bucket.bucket_start.to_rfc3339();
+            let stored = tx.query_row(
+                "SELECT successes, failures, latency_ms_total, latency_buckets, failure_reasons, last_error, last_error_at
+                 FROM poll_stats WHERE device_id = ?1 AND schedule_group_id = ?2 AND bucket_start = ?3",
+                params![bucket.device_id, bucket.schedule_group_id, bucket_start],
+                |row| poll_stats_bucket(row, &bucket.device_id, &bucket.schedule_group_id, bucket.bucket_start),
+            );
+            let merged = match stored {
+                Ok(mut stored) => {
+                    stored.merge(bucket);
+                    stored
+                },
+                Err(rusqlite::Error::QueryReturnedNoRows) => bucket.clone(),
+                Err(e) => return Err(e.into()),
+            };
+            tx.execute(
+                "INSERT OR REPLACE INTO poll_stats
+                 (device_id, schedule_group_id, bucket_start, successes, failures, latency_ms_total, latency_buckets, failure_reasons, last_error, last_error_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                params![
+                    merged.device_id,
+                    merged.schedule_group_id,
+                    bucket_start,
+                    merged.successes as i64,
+                    merged.failures as i64,
+                    merged.latency_ms_total,
+                    serde_json::to_string(&merged.latency_buckets)?,
+                    serde_json::to_string(&merged.failure_reasons)?,
+                    merged.last_error,
+                    merged.last_error_at.map(|at| at.to_rfc3339()),
+                ],
+            )?;
         }
+        tx.execute("DELETE FROM poll_stats WHERE bucket_start < ?1", params![prune_before.to_rfc3339()])?;
+        tx.commit()?;
+        Ok(())
+    }
 
-        Ok(tag_registers)
+    /// Stored poll outcome buckets that started at or after `since`, of one device or of all
+    pub async fn get_poll_stats(&self, device_id: Option<&str>, since: DateTime<Utc>) -> Result<Vec<PollStatsBucket>> {
+        let conn = self.readers.get().await;
+        let mut stmt = conn.prepare(
+            "SELECT successes, failures, latency_ms_total, latency_buckets, failure_reasons, last_error, last_error_at,
+                    device_id, schedule_group_id, bucket_start
+             FROM poll_stats WHERE bucket_start >= ?1 AND (?2 IS NULL OR device_id = ?2)
+             ORDER BY device_id, schedule_group_id, bucket_start",
+        )?;
+        let buckets = stmt
+            .query_map(params![since.to_rfc3339(), device_id], |row| {
+                let bucket_start = parse_timestamp(row.get(9)?, 9, "bucket_start")?;
+                poll_stats_bucket(row, &row.get::<_, String>(7)?, &row.get::<_, String>(8)?, bucket_start)
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(buckets)
     }
 
-    pub async fn get_all_modbus_tcp_tag_registers(&self) -> Result<Vec<ModbusTcpTagRegister>> {
+    /// Mute a tag until `muted_until`, replacing any mute already active on it
+    pub async fn mute_tag(
+        &self,
+        tag: &DeviceTag,
+        muted_until: DateTime<Utc>,
+        reason: Option<String>,
+        created_by: Option<String>,
+    ) -> Result<TagMute> {
+        let tag_id = tag.id.ok_or_else(|| anyhow::anyhow!("Tag '{}' has not been saved", tag.name))?;
+        let now = Utc::now();
+        let mut conn = self.connection.lock().await;
+        let tx = conn.transaction()?;
+
+        tx.execute(
+            "UPDATE tag_mutes SET lifted_at = ?1, lifted_by = ?2 WHERE tag_id = ?3 AND lifted_at IS NULL",
+            params![now.to_rfc3339(), created_by, tag_id],
+        )?;
+        tx.execute(
+            "INSERT INTO tag_mutes (device_id, tag_id, tag_name, muted_until, reason, created_by, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![tag.device_id, tag_id, tag.name, muted_until.to_rfc3339(), reason, created_by, now.to_rfc3339()],
+        )?;
+        let id = tx.last_insert_rowid();
+        tx.commit()?;
+
+        Ok(TagMute {
+            id,
+            device_id: tag.device_id.clone(),
+            tag_id,
+            tag_name: tag.name.clone(),
+            muted_until,
+            reason,
+            created_by,
+            created_at: now,
+            lifted_at: None,
+            lifted_by: None,
+        })
+    }
+
+    /// Lift a tag's active mute early; returns false if it wasn't muted
+    pub async fn lift_tag_mute(&self, tag_id: i64, lifted_by: Option<String>) -> Result<bool> {
         let conn = self.connection.lock().await;
-        
+        let now = Utc::now().to_rfc3339();
+
+        let rows_affected = conn.execute(
+            "UPDATE tag_mutes SET lifted_at = ?1, lifted_by = ?2
+             WHERE tag_id = ?3 AND lifted_at IS NULL AND muted_until > ?1",
+            params![now, lifted_by, tag_id],
+        )?;
+
+        Ok(rows_affected > 0)
+    }
+
+    /// Mutes for a device, newest first, including expired and lifted ones
+    pub async fn get_tag_mutes(&self, device_id: &str) -> Result<Vec<TagMute>> {
+        let conn = self.readers.get().await;
+
         let mut stmt = conn.prepare(
-            "SELECT id, device_brand, device_model, ava_type, mppt, input, data_label, 
-                    address, size, modbus_type, divider, register_type, created_at, updated_at 
-             FROM modbus_tcp_tag_registers 
-             ORDER BY device_brand, device_model, ava_type, mppt, input, address ASC"
+            "SELECT id, device_id, tag_id, tag_name, muted_until, reason, created_by, created_at, lifted_at, lifted_by
+             FROM tag_mutes WHERE device_id = ?1
+             ORDER BY created_at DESC, id DESC"
         )?;
 
-        let rows = stmt.query_map([], |row| {
-            let created_str: String = row.get(12)?;
-            let updated_str: String = row.get(13)?;
-            let created_at = DateTime::parse_from_rfc3339(&created_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(12, "created_at".to_string(), rusqlite::types::Type::Text))?
-                .with_timezone(&Utc);
-            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(13, "updated_at".to_string(), rusqlite::types::Type::Text))?
-                .with_timezone(&Utc);
+        let parse_time = |value: String, idx: usize, name: &str| {
+            DateTime::parse_from_rfc3339(&value)
+                .map(|time| time.with_timezone(&Utc))
+                .map_err(|_| rusqlite::Error::InvalidColumnType(idx, name.to_string(), rusqlite::types::Type::Text))
+        };
 
-            Ok(ModbusTcpTagRegister {
-                id: Some(row.get(0)?),
-                device_brand: row.get(1)?,
-                device_model: row.get(2)?,
-                ava_type: row.get(3)?,
-                mppt: row.get(4)?,
-                input: row.get(5)?,
-                data_label: row.get(6)?,
-                address: row.get(7)?,
-                size: row.get(8)?,
-                modbus_type: row.get(9)?,
-                divider: row.get(10)?,
-                register_type: row.get(11)?,
-                created_at,
-                updated_at,
+        let rows = stmt.query_map([device_id], |row| {
+            Ok(TagMute {
+                id: row.get(0)?,
+                device_id: row.get(1)?,
+                tag_id: row.get(2)?,
+                tag_name: row.get(3)?,
+                muted_until: parse_time(row.get(4)?, 4, "muted_until")?,
+                reason: row.get(5)?,
+                created_by: row.get(6)?,
+                created_at: parse_time(row.get(7)?, 7, "created_at")?,
+                lifted_at: row.get::<_, Option<String>>(8)?.map(|value| parse_time(value, 8, "lifted_at")).transpose()?,
+                lifted_by: row.get(9)?,
             })
         })?;
 
-        let mut tag_registers = Vec::new();
+        let mut mutes = Vec::new();
         for row in rows {
-            tag_registers.push(row?);
+            mutes.push(row?);
         }
 
-        Ok(tag_registers)
+        Ok(mutes)
     }
 
-    pub async fn delete_modbus_tcp_tag_registers_by_device(&self, device_brand: &str, device_model: &str) -> Result<u64> {
+    /// Names of a device's tags that are muted right now
+    pub async fn get_muted_tag_names(&self, device_id: &str) -> Result<HashSet<String>> {
+        let now = Utc::now();
+        Ok(self
+            .get_tag_mutes(device_id)
+            .await?
+            .into_iter()
+            .filter(|mute| mute.is_active(now))
+            .map(|mute| mute.tag_name)
+            .collect())
+    }
+
+    /// Count a sample that was read but not logged because its tag was muted
+    pub async fn record_muted_sample(&self, device_id: &str, tag_name: &str, timestamp: DateTime<Utc>) -> Result<()> {
         let conn = self.connection.lock().await;
-        
-        let result = conn.execute(
-            "DELETE FROM modbus_tcp_tag_registers WHERE device_brand = ?1 AND device_model = ?2",
-            params![device_brand, device_model],
+
+        conn.execute(
+            "INSERT INTO muted_sample_counts (device_id, tag_name, day, sample_count)
+             VALUES (?1, ?2, ?3, 1)
+             ON CONFLICT(device_id, tag_name, day) DO UPDATE SET sample_count = sample_count + 1",
+            params![device_id, tag_name, timestamp.format("%Y-%m-%d").to_string()],
         )?;
 
-        Ok(result as u64)
+        Ok(())
     }
 
-    /// Update local device with ThingsBoard device ID after sync
-    /// This maintains the relationship between local and ThingsBoard devices
-    /// without changing the primary key
-    pub async fn update_device_thingsboard_id(&self, local_device_id: &str, thingsboard_device_id: &str) -> Result<()> {
-        let conn = self.connection.lock().await;
-        
-        // Check if the local device exists
-        let mut stmt = conn.prepare("SELECT COUNT(*) FROM devices WHERE id = ?1")?;
-        let count: i64 = stmt.query_row([local_device_id], |row| row.get(0))?;
-        
-        if count == 0 {
-            return Err(anyhow::anyhow!("Local device with ID {} not found", local_device_id));
-        }
-        
-        // Update the tb_device_id field
-        let updated_str = Utc::now().to_rfc3339();
+    /// Muted samples per device for the UTC days in `[start, end)`
+    pub async fn get_muted_sample_counts(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<HashMap<String, i64>> {
+        let conn = self.readers.get().await;
+
         let mut stmt = conn.prepare(
-            "UPDATE devices SET tb_device_id = ?1, updated_at = ?2 WHERE id = ?3"
+            "SELECT device_id, SUM(sample_count) FROM muted_sample_counts
+             WHERE day >= ?1 AND day < ?2
+             GROUP BY device_id"
         )?;
-        stmt.execute([thingsboard_device_id, &updated_str, local_device_id])?;
-        
-        info!("Successfully updated device {} with ThingsBoard ID: {}", local_device_id, thingsboard_device_id);
-        Ok(())
-    }
 
-    /// Batch update multiple devices with ThingsBoard device IDs and group IDs after sync
-    /// This is more efficient when updating many devices at once
-    pub async fn batch_update_devices_thingsboard_ids(&self, device_id_mappings: &[(String, String, String)]) -> Result<Vec<String>> {
-        if device_id_mappings.is_empty() {
-            return Ok(Vec::new());
-        }
+        let rows = stmt.query_map(
+            params![start.format("%Y-%m-%d").to_string(), end.format("%Y-%m-%d").to_string()],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)),
+        )?;
 
-        let conn = self.connection.lock().await;
-        let mut successful_updates = Vec::new();
-        let mut failed_updates = Vec::new();
-        
-        // Start a transaction
-        conn.execute("BEGIN TRANSACTION", [])?;
-        
-        for (local_id, thingsboard_id, group_id) in device_id_mappings {
-            // Check if local device exists
-            let mut stmt = conn.prepare("SELECT COUNT(*) FROM devices WHERE id = ?1")?;
-            let local_exists: i64 = stmt.query_row([local_id], |row| row.get(0))?;
-            
-            if local_exists == 0 {
-                failed_updates.push(format!("Local device {} not found", local_id));
-                continue;
-            }
-            
-            // Update both tb_device_id and tb_group_id fields
-            let updated_str = Utc::now().to_rfc3339();
-            let mut stmt = conn.prepare("UPDATE devices SET tb_device_id = ?1, tb_group_id = ?2, updated_at = ?3 WHERE id = ?4")?;
-            
-            match stmt.execute([thingsboard_id, group_id, &updated_str, local_id]) {
-                Ok(_) => {
-                    successful_updates.push(format!("{} -> {} (group: {})", local_id, thingsboard_id, group_id));
-                    info!("Updated device {} with ThingsBoard ID: {} and Group ID: {}", local_id, thingsboard_id, group_id);
-                }
-                Err(e) => {
-                    failed_updates.push(format!("Failed to update {}: {}", local_id, e));
-                }
-            }
-        }
-        
-        if !failed_updates.is_empty() {
-            warn!("Some ThingsBoard ID updates failed: {:?}", failed_updates);
+        let mut counts = HashMap::new();
+        for row in rows {
+            let (device_id, count) = row?;
+            counts.insert(device_id, count);
         }
-        
-        // Commit the transaction
-        conn.execute("COMMIT", [])?;
-        
-        info!("Batch ThingsBoard ID update completed. Success: {}, Failed: {}", 
-              successful_updates.len(), failed_updates.len());
-        
-        Ok(successful_updates)
-    }
 
-    /// Get device AVA type based on the database flow:
-    /// devices.model_id -> device_models.id -> device_models.name -> modbus_tcp_tag_registers.device_model -> modbus_tcp_tag_registers.ava_type
-    /// For devices with multiple AVA types, prioritize in order: Inverter, PowerMeter, Meter, MPPT, String
-    pub async fn get_device_ava_type(&self, device_id: &str) -> Result<Option<String>> {
-        let conn = self.connection.lock().await;
-        
-        let mut stmt = conn.prepare("
-            SELECT DISTINCT mtr.ava_type
-            FROM devices d
-            JOIN device_models dm ON d.model_id = dm.id
-            JOIN modbus_tcp_tag_registers mtr ON dm.name = mtr.device_model
-            WHERE d.id = ?1
-            ORDER BY
-                CASE mtr.ava_type
-                    WHEN 'Inverter' THEN 1
-                    WHEN 'Weather Station' THEN 2
-                    WHEN 'PowerMeter' THEN 3
-                    WHEN 'Meter' THEN 4
-                    WHEN 'MPPT' THEN 5
-                    WHEN 'String' THEN 6
-                    ELSE 7
-                END
-            LIMIT 1
-        ")?;
-        
-        let result = stmt.query_row([device_id], |row| {
-            Ok(row.get::<_, String>(0)?)
-        });
-        
-        match result {
-            Ok(ava_type) => Ok(Some(ava_type)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e.into()),
-        }
+        Ok(counts)
     }
-
-    /// Get device model name for a given device ID
-    pub async fn get_device_model_name(&self, device_id: &str) -> Result<Option<String>> {
-        let conn = self.connection.lock().await;
-        
-        let mut stmt = conn.prepare("
-            SELECT dm.name
-            FROM devices d
-            JOIN device_models dm ON d.model_id = dm.id
-            WHERE d.id = ?1
-        ")?;
-        
-        let result = stmt.query_row([device_id], |row| {
-            Ok(row.get::<_, String>(0)?)
-        });
-        
-        match result {
-            Ok(model_name) => Ok(Some(model_name)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e.into()),
+
+    /// Replace the ThingsBoard MPPT and String devices recorded for a local inverter
+    pub async fn save_tb_child_devices(&self, device_id: &str, children: &[TbChildDevice]) -> Result<()> {
+        let mut conn = self.connection.lock().await;
+        let tx = conn.transaction()?;
+
+        tx.execute("DELETE FROM tb_child_devices WHERE device_id = ?1", params![device_id])?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO tb_child_devices (tb_device_id, device_id, name, device_type, mppt_number, input_number)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
+            )?;
+            for child in children {
+                stmt.execute(params![
+                    child.tb_device_id,
+                    device_id,
+                    child.name,
+                    child.device_type,
+                    child.mppt_number,
+                    child.input_number,
+                ])?;
+            }
         }
+        tx.commit()?;
+
+        Ok(())
     }
 
-    // Authentication methods
-    
-    /// Verify user credentials and return user info if valid
-    pub async fn verify_user(&self, username: &str, password: &str) -> Result<Option<LocalUser>> {
-        let conn = self.connection.lock().await;
-        
+    /// ThingsBoard MPPT and String devices of a local inverter, MPPTs before their strings
+    pub async fn get_tb_child_devices(&self, device_id: &str) -> Result<Vec<TbChildDevice>> {
+        let conn = self.readers.get().await;
+
         let mut stmt = conn.prepare(
-            "SELECT id, username, password_hash, role FROM local_users WHERE username = ?1"
+            "SELECT device_id, tb_device_id, name, device_type, mppt_number, input_number
+             FROM tb_child_devices WHERE device_id = ?1
+             ORDER BY mppt_number, input_number IS NOT NULL, input_number, name"
         )?;
-        
-        let result = stmt.query_row([username], |row| {
-            Ok(LocalUser {
-                id: Some(row.get(0)?),
-                username: row.get(1)?,
-                password_hash: row.get(2)?,
-                role: row.get(3)?,
-            })
-        });
-        
-        match result {
-            Ok(user) => {
-                // For development, we'll do a simple comparison since we don't have bcrypt yet
-                // TODO: Replace with proper bcrypt verification when bcrypt dependency is added
-                let is_valid = match (user.username.as_str(), password) {
-                    ("admin", "admin123") => true,
-                    ("installer", "installer123") => true,
-                    _ => false,
-                };
-                
-                if is_valid {
-                    Ok(Some(user))
-                } else {
-                    Ok(None)
-                }
-            }
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e.into()),
-        }
+        let children = stmt
+            .query_map(params![device_id], |row| {
+                Ok(TbChildDevice {
+                    device_id: row.get(0)?,
+                    tb_device_id: row.get(1)?,
+                    name: row.get(2)?,
+                    device_type: row.get(3)?,
+                    mppt_number: row.get(4)?,
+                    input_number: row.get(5)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(children)
     }
 
-    /// Create a new session for a user
-    pub async fn create_session(&self, user_id: i64, session_token: &str, expires_at: DateTime<Utc>) -> Result<i64> {
+    /// Forget the ThingsBoard children of a local inverter; returns how many were removed
+    pub async fn delete_tb_child_devices(&self, device_id: &str) -> Result<usize> {
+        let conn = self.connection.lock().await;
+        Ok(conn.execute("DELETE FROM tb_child_devices WHERE device_id = ?1", params![device_id])?)
+    }
+
+    /// Insert a job or overwrite it with its latest progress
+    pub async fn save_job(&self, job: &Job) -> Result<()> {
         let conn = self.connection.lock().await;
-        let expires_str = expires_at.to_rfc3339();
-        
         conn.execute(
-            "INSERT INTO user_sessions (user_id, session_token, expires_at)
-             VALUES (?1, ?2, ?3)",
-            params![user_id, session_token, expires_str],
+            "INSERT OR REPLACE INTO jobs
+             (id, kind, entity_group_id, description, requested_by, state, progress_current, progress_total,
+              items, result, error, created_at, started_at, finished_at, report)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+            params![
+                job.id,
+                job.kind,
+                job.entity_group_id,
+                job.description,
+                job.requested_by,
+                job.state.as_str(),
+                job.progress_current,
+                job.progress_total,
+                serde_json::to_string(&job.items)?,
+                job.result.as_ref().map(serde_json::to_string).transpose()?,
+                job.error,
+                job.created_at.to_rfc3339(),
+                job.started_at.map(|at| at.to_rfc3339()),
+                job.finished_at.map(|at| at.to_rfc3339()),
+                job.report.as_ref().map(serde_json::to_string).transpose()?,
+            ],
         )?;
 
-        Ok(conn.last_insert_rowid())
+        Ok(())
     }
 
-    /// Verify session token and return user info if valid
-    pub async fn verify_session(&self, session_token: &str) -> Result<Option<LocalUser>> {
+    pub async fn get_job(&self, id: &str) -> Result<Option<Job>> {
+        Ok(self.query_jobs("WHERE id = ?1", vec![id.to_string().into()]).await?.pop())
+    }
+
+    /// Most recent jobs first
+    pub async fn get_jobs(&self, limit: u32) -> Result<Vec<Job>> {
+        self.query_jobs("ORDER BY created_at DESC LIMIT ?1", vec![limit.into()]).await
+    }
+
+    /// The queued or running job of a kind working on an entity group, if any
+    pub async fn get_active_job(&self, kind: &str, entity_group_id: &str) -> Result<Option<Job>> {
+        Ok(self
+            .query_jobs(
+                "WHERE kind = ?1 AND entity_group_id = ?2 AND state IN ('queued', 'running') ORDER BY created_at DESC",
+                vec![kind.to_string().into(), entity_group_id.to_string().into()],
+            )
+            .await?
+            .into_iter()
+            .next())
+    }
+
+    /// Mark jobs a previous process left queued or running as failed; returns how many
+    pub async fn fail_interrupted_jobs(&self) -> Result<usize> {
         let conn = self.connection.lock().await;
-        let now = Utc::now().to_rfc3339();
-        
-        let mut stmt = conn.prepare("
-            SELECT u.id, u.username, u.password_hash, u.role
-            FROM user_sessions s
-            JOIN local_users u ON s.user_id = u.id
-            WHERE s.session_token = ?1 AND s.expires_at > ?2
-        ")?;
-        
-        let result = stmt.query_row([session_token, &now], |row| {
-            Ok(LocalUser {
-                id: Some(row.get(0)?),
-                username: row.get(1)?,
-                password_hash: row.get(2)?,
-                role: row.get(3)?,
-            })
-        });
-        
-        match result {
-            Ok(user) => Ok(Some(user)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e.into()),
+        Ok(conn.execute(
+            "UPDATE jobs SET state = 'failed', error = 'Interrupted by a restart', finished_at = ?1
+             WHERE state IN ('queued', 'running')",
+            params![Utc::now().to_rfc3339()],
+        )?)
+    }
+
+    async fn query_jobs(&self, clause: &str, params: Vec<rusqlite::types::Value>) -> Result<Vec<Job>> {
+        let conn = self.readers.get().await;
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, kind, entity_group_id, description, requested_by, state, progress_current, progress_total,
+                    items, result, error, created_at, started_at, finished_at, report
+             FROM jobs {}",
+            clause
+        ))?;
+        let jobs = stmt
+            .query_map(rusqlite::params_from_iter(params), |row| {
+                let timestamp = |index: usize| -> rusqlite::Result<Option<DateTime<Utc>>> {
+                    Ok(row
+                        .get::<_, Option<String>>(index)?
+                        .and_then(|text| DateTime::parse_from_rfc3339(&text).ok())
+                        .map(|at| at.with_timezone(&Utc)))
+                };
+
+                Ok(Job {
+                    id: row.get(0)?,
+                    kind: row.get(1)?,
+                    entity_group_id: row.get(2)?,
+                    description: row.get(3)?,
+                    requested_by: row.get(4)?,
+                    state: JobState::parse(&row.get::<_, String>(5)?),
+                    progress_current: row.get(6)?,
+                    progress_total: row.get(7)?,
+                    items: serde_json::from_str(&row.get::<_, String>(8)?).unwrap_or_default(),
+                    result: row.get::<_, Option<String>>(9)?.and_then(|text| serde_json::from_str(&text).ok()),
+                    error: row.get(10)?,
+                    report: row.get::<_, Option<String>>(14)?.and_then(|text| serde_json::from_str(&text).ok()),
+                    created_at: timestamp(11)?.unwrap_or_else(Utc::now),
+                    started_at: timestamp(12)?,
+                    finished_at: timestamp(13)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(jobs)
+    }
+
+    /// Whether a device's logged values are pushed to ThingsBoard; `None` if the device doesn't exist
+    pub async fn get_device_telemetry_forwarding(&self, device_id: &str) -> Result<Option<bool>> {
+        let conn = self.readers.get().await;
+
+        let mut stmt = conn.prepare("SELECT forward_telemetry FROM devices WHERE id = ?1")?;
+        let mut rows = stmt.query_map(params![device_id], |row| row.get::<_, bool>(0))?;
+
+        match rows.next() {
+            Some(row) => Ok(Some(row?)),
+            None => Ok(None),
         }
     }
 
-    /// Delete expired sessions
-    pub async fn cleanup_expired_sessions(&self) -> Result<u32> {
-        let conn = self.connection.lock().await;
-        let now = Utc::now().to_rfc3339();
-        
-        let deleted = conn.execute(
-            "DELETE FROM user_sessions WHERE expires_at <= ?1",
-            params![now],
-        )?;
+    /// The ThingsBoard access token cached for a device; `None` if the device doesn't exist.
+    /// The token is kept out of `DeviceInstance` so device lists, backups and the audit log
+    /// never carry it, and is dropped whenever the device is linked to another ThingsBoard device.
+    pub async fn get_device_tb_access_token(&self, device_id: &str) -> Result<Option<Option<String>>> {
+        let conn = self.readers.get().await;
 
-        Ok(deleted as u32)
+        let mut stmt = conn.prepare("SELECT tb_access_token FROM devices WHERE id = ?1")?;
+        let mut rows = stmt.query_map(params![device_id], |row| row.get::<_, Option<String>>(0))?;
+
+        match rows.next() {
+            Some(row) => Ok(Some(row?)),
+            None => Ok(None),
+        }
     }
 
-    /// Revoke a specific session
-    pub async fn revoke_session(&self, session_token: &str) -> Result<bool> {
+    /// Cache the access token fetched for the ThingsBoard device `tb_device_id`. Nothing is
+    /// stored if the device was linked elsewhere meanwhile; returns whether it was stored.
+    pub async fn set_device_tb_access_token(&self, device_id: &str, tb_device_id: &str, token: &str) -> Result<bool> {
         let conn = self.connection.lock().await;
-        
-        let deleted = conn.execute(
-            "DELETE FROM user_sessions WHERE session_token = ?1",
-            params![session_token],
+
+        let updated = conn.execute(
+            "UPDATE devices SET tb_access_token = ?1 WHERE id = ?2 AND tb_device_id = ?3",
+            params![token, device_id, tb_device_id],
         )?;
 
-        Ok(deleted > 0)
+        Ok(updated > 0)
     }
 
-    /// Get plant configuration
-    pub async fn get_plant_configuration(&self) -> Result<Option<PlantConfiguration>> {
+    /// Returns false if the device doesn't exist
+    pub async fn set_device_serial_no(&self, device_id: &str, serial_no: &str) -> Result<bool> {
         let conn = self.connection.lock().await;
-        
-        let mut stmt = conn.prepare(
-            "SELECT id, plant_name, thingsboard_entity_group_id, last_synced FROM plant_configuration LIMIT 1"
+
+        let updated = conn.execute(
+            "UPDATE devices SET serial_no = ?1, updated_at = ?2 WHERE id = ?3",
+            params![serial_no, Utc::now().to_rfc3339(), device_id],
         )?;
-        
-        let result = stmt.query_row([], |row| {
-            Ok(PlantConfiguration {
-                id: Some(row.get(0)?),
-                plant_name: row.get(1)?,
-                thingsboard_entity_group_id: row.get(2)?,
-                last_synced: row.get(3)?,
-            })
-        });
-        
-        match result {
-            Ok(config) => Ok(Some(config)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e.into()),
-        }
+
+        Ok(updated > 0)
     }
 
-    /// Update plant configuration
-    pub async fn update_plant_configuration(&self, plant_name: &str, tb_group_id: Option<&str>) -> Result<bool> {
+    /// Returns false if the device doesn't exist
+    pub async fn set_device_telemetry_forwarding(&self, device_id: &str, enabled: bool) -> Result<bool> {
         let conn = self.connection.lock().await;
-        
-        // Try to update existing config first
+
         let updated = conn.execute(
-            "UPDATE plant_configuration SET plant_name = ?1, thingsboard_entity_group_id = ?2 WHERE id = 1",
-            params![plant_name, tb_group_id],
+            "UPDATE devices SET forward_telemetry = ?1, updated_at = ?2 WHERE id = ?3",
+            params![enabled, Utc::now().to_rfc3339(), device_id],
         )?;
 
-        if updated == 0 {
-            // No existing config, insert new one
-            conn.execute(
-                "INSERT INTO plant_configuration (plant_name, thingsboard_entity_group_id)
-                 VALUES (?1, ?2)",
-                params![plant_name, tb_group_id],
+        Ok(updated > 0)
+    }
+
+    /// Queue entries for ThingsBoard with their quality, returning how many were queued
+    pub async fn enqueue_telemetry(&self, tb_device_id: &str, entries: &[LogEntry]) -> Result<usize> {
+        let mut conn = self.connection.lock().await;
+        let tx = conn.transaction()?;
+
+        let mut queued = 0;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO telemetry_outbox (device_id, tb_device_id, tag_name, value, quality, timestamp)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
             )?;
+            for entry in entries {
+                stmt.execute(params![
+                    entry.device_id,
+                    tb_device_id,
+                    entry.tag_name,
+                    entry.value,
+                    entry.quality,
+                    entry.timestamp.to_rfc3339(),
+                ])?;
+                queued += 1;
+            }
         }
+        tx.commit()?;
 
-        Ok(true)
+        Ok(queued)
     }
 
-    /// Update or insert plant sync timestamp
-    pub async fn update_plant_sync_timestamp(&self, plant_name: &str, tb_group_id: &str) -> Result<()> {
-        let conn = self.connection.lock().await;
-        let now = chrono::Utc::now().to_rfc3339();
-        
-        // Check if a plant with this group ID already exists
-        let existing_id: Option<i64> = conn.query_row(
-            "SELECT id FROM plant_configuration WHERE thingsboard_entity_group_id = ?1",
-            params![tb_group_id],
-            |row| row.get(0)
-        ).ok();
-        
-        if let Some(id) = existing_id {
-            // Update existing plant's sync timestamp
-            conn.execute(
-                "UPDATE plant_configuration SET last_synced = ?1, plant_name = ?2 WHERE id = ?3",
-                params![now, plant_name, id],
-            )?;
-            info!("Updated sync timestamp for plant '{}' (id: {})", plant_name, id);
-        } else {
-            // Insert new plant for sync tracking (id > 1)
-            conn.execute(
-                "INSERT INTO plant_configuration (plant_name, thingsboard_entity_group_id, last_synced)
-                 VALUES (?1, ?2, ?3)",
-                params![plant_name, tb_group_id, now],
+    /// Oldest queued values of a device, in the order they were logged
+    pub async fn get_telemetry_batch(&self, device_id: &str, limit: usize) -> Result<Vec<TelemetryOutboxEntry>> {
+        let conn = self.readers.get().await;
+
+        let mut stmt = conn.prepare(
+            "SELECT id, device_id, tb_device_id, tag_name, value, quality, timestamp, attempts
+             FROM telemetry_outbox WHERE device_id = ?1
+             ORDER BY id LIMIT ?2"
+        )?;
+
+        let rows = stmt.query_map(params![device_id, limit as i64], |row| {
+            let timestamp: String = row.get(6)?;
+            Ok(TelemetryOutboxEntry {
+                id: row.get(0)?,
+                device_id: row.get(1)?,
+                tb_device_id: row.get(2)?,
+                tag_name: row.get(3)?,
+                value: row.get(4)?,
+                quality: row.get(5)?,
+                timestamp: DateTime::parse_from_rfc3339(&timestamp)
+                    .map_err(|_| rusqlite::Error::InvalidColumnType(6, "timestamp".to_string(), rusqlite::types::Type::Text))?
+                    .with_timezone(&Utc),
+                attempts: row.get(7)?,
+            })
+        })?;
+
+        let mut entries = Vec::new();
+        for row in rows {
+            entries.push(row?);
+        }
+
+        Ok(entries)
+    }
+
+    /// Drop queued values ThingsBoard has accepted
+    pub async fn delete_telemetry(&self, ids: &[i64]) -> Result<()> {
+        let mut conn = self.connection.lock().await;
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare("DELETE FROM telemetry_outbox WHERE id = ?1")?;
+            for id in ids {
+                stmt.execute(params![id])?;
+            }
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Keep queued values after a failed push, remembering why it failed
+    pub async fn mark_telemetry_failed(&self, ids: &[i64], error: &str) -> Result<()> {
+        let mut conn = self.connection.lock().await;
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "UPDATE telemetry_outbox SET attempts = attempts + 1, last_error = ?1 WHERE id = ?2"
             )?;
-            let new_id = conn.last_insert_rowid();
-            info!("Added new plant '{}' for sync tracking (id: {})", plant_name, new_id);
+            for id in ids {
+                stmt.execute(params![error, id])?;
+            }
         }
+        tx.commit()?;
 
         Ok(())
     }
 
-    /// Get all plants with sync timestamps (excluding id = 1 if needed)
-    pub async fn get_all_plant_sync_info(&self) -> Result<Vec<PlantConfiguration>> {
-        let conn = self.connection.lock().await;
-        
-        let mut stmt = conn.prepare(
-            "SELECT id, plant_name, thingsboard_entity_group_id, last_synced 
-             FROM plant_configuration 
-             ORDER BY id"
+    pub async fn get_telemetry_backlog(&self, device_id: &str) -> Result<TelemetryBacklog> {
+        let conn = self.readers.get().await;
+
+        let (pending, oldest): (i64, Option<String>) = conn.query_row(
+            "SELECT COUNT(*), MIN(timestamp) FROM telemetry_outbox WHERE device_id = ?1",
+            params![device_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
         )?;
-        
-        let plants = stmt.query_map([], |row| {
-            Ok(PlantConfiguration {
-                id: Some(row.get(0)?),
-                plant_name: row.get(1)?,
-                thingsboard_entity_group_id: row.get(2)?,
-                last_synced: row.get(3)?,
-            })
-        })?
-        .collect::<std::result::Result<Vec<_>, _>>()?;
-        
-        Ok(plants)
-    }
+        let last_error = match conn.query_row(
+            "SELECT last_error FROM telemetry_outbox
+             WHERE device_id = ?1 AND last_error IS NOT NULL
+             ORDER BY id LIMIT 1",
+            params![device_id],
+            |row| row.get::<_, String>(0),
+        ) {
+            Ok(error) => Some(error),
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
+            Err(e) => return Err(e.into()),
+        };
 
-    /// Get devices filtered by ThingsBoard group ID
-    pub async fn get_devices_by_group(&self, tb_group_id: &str) -> Result<Vec<DeviceInstance>> {
-        let conn = self.connection.lock().await;
-        
-        let mut stmt = conn.prepare("
-            SELECT id, name, serial_no, model_id, enabled, polling_interval_ms, timeout_ms, retry_count, 
-                   protocol_config, tb_device_id, tb_group_id, created_at, updated_at 
-            FROM devices 
-            WHERE tb_group_id = ?1 OR tb_group_id IS NULL
-            ORDER BY name
-        ")?;
+        Ok(TelemetryBacklog {
+            pending,
+            oldest_timestamp: oldest
+                .and_then(|timestamp| DateTime::parse_from_rfc3339(&timestamp).ok())
+                .map(|timestamp| timestamp.with_timezone(&Utc)),
+            last_error,
+        })
+    }
 
-        let rows = stmt.query_map([tb_group_id], |row| {
-            let created_str: String = row.get(11)?;
-            let updated_str: String = row.get(12)?;
-            let created_at = DateTime::parse_from_rfc3339(&created_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(11, "created_at".to_string(), rusqlite::types::Type::Text))?
-                .with_timezone(&Utc);
-            let updated_at = DateTime::parse_from_rfc3339(&updated_str)
-                .map_err(|_| rusqlite::Error::InvalidColumnType(12, "updated_at".to_string(), rusqlite::types::Type::Text))?
-                .with_timezone(&Utc);
+    /// Queued values per device, for devices that have any
+    pub async fn get_telemetry_backlog_counts(&self) -> Result<HashMap<String, i64>> {
+        let conn = self.readers.get().await;
 
-            Ok(DeviceInstance {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                serial_no: row.get(2)?,
-                model_id: row.get(3)?,
-                enabled: row.get(4)?,
-                polling_interval_ms: row.get::<_, i32>(5)? as u32,
-                timeout_ms: row.get::<_, i32>(6)? as u32,
-                retry_count: row.get::<_, i32>(7)? as u32,
-                protocol_config: row.get(8)?,
-                tb_device_id: row.get(9)?,
-                tb_group_id: row.get(10)?,
-                created_at,
-                updated_at,
-            })
-        })?;
+        let mut stmt = conn.prepare("SELECT device_id, COUNT(*) FROM telemetry_outbox GROUP BY device_id")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?;
 
-        let mut devices = Vec::new();
+        let mut counts = HashMap::new();
         for row in rows {
-            devices.push(row?);
+            let (device_id, count) = row?;
+            counts.insert(device_id, count);
         }
 
-        Ok(devices)
+        Ok(counts)
     }
 }
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::sync::Mutex as StdMutex;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+
+use crate::database::{Database, DeviceTag, LogEntry};
+
+/// Last logged sample of a tag with a deadband
+struct LastSample {
+    value: f64,
+    logged_at: DateTime<Utc>,
+    /// Quality of the last logged sample, so a failed read and the recovery from it are logged
+    quality: String,
+}
+
+/// Change-of-value filter deciding which polled values of tags with a deadband are logged.
+///
+/// The last logged value of each (device, tag) is kept in memory and seeded from the log when
+/// a device starts, so a restart doesn't log every tag again.
+pub struct DeadbandFilter {
+    heartbeat: chrono::Duration,
+    last: StdMutex<HashMap<(String, String), LastSample>>,
+}
+
+impl DeadbandFilter {
+    pub fn new(heartbeat: std::time::Duration) -> Self {
+        Self {
+            heartbeat: chrono::Duration::from_std(heartbeat).unwrap_or(chrono::Duration::MAX),
+            last: StdMutex::new(HashMap::new()),
+        }
+    }
+
+    /// Replace what is known about a device with its latest log entries. Entries older than the
+    /// heartbeat interval are left out; the next value is due regardless.
+    pub async fn seed(&self, database: &Database, device_id: &str) -> Result<()> {
+        let entries = database.get_latest_log_entries(device_id, Utc::now() - self.heartbeat).await?;
+        let mut last = self.last.lock().unwrap();
+        last.retain(|(device, _), _| device != device_id);
+        for entry in entries {
+            last.insert(
+                (entry.device_id, entry.tag_name),
+                LastSample { value: entry.value, logged_at: entry.timestamp, quality: entry.quality },
+            );
+        }
+        Ok(())
+    }
+
+    /// The entries of a poll to store: tags without a deadband log every stored value, and tags
+    /// with one log a value when it leaves the deadband, its quality changes, or the heartbeat
+    /// interval has passed since the tag was last logged. A failed read is logged when the tag
+    /// starts failing and then once per heartbeat, whatever its value. Muted tags are never
+    /// stored.
+    pub fn filter(&self, entries: &[LogEntry], device_tags: &[DeviceTag]) -> Vec<LogEntry> {
+        let mut last = self.last.lock().unwrap();
+        let mut to_log = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let Some(device_tag) = device_tags.iter().find(|tag| tag.name == entry.tag_name && tag.has_deadband()) else {
+                if entry.is_logged() {
+                    to_log.push(entry.clone());
+                }
+                continue;
+            };
+
+            if !entry.is_logged() {
+                continue;
+            }
+
+            let key = (entry.device_id.clone(), entry.tag_name.clone());
+            let unchanged = last.get(&key).is_some_and(|previous| {
+                previous.quality == entry.quality
+                    && entry.timestamp - previous.logged_at < self.heartbeat
+                    && (!entry.has_value() || device_tag.within_deadband(previous.value, entry.value))
+            });
+            if !unchanged {
+                last.insert(key, LastSample { value: entry.value, logged_at: entry.timestamp, quality: entry.quality.clone() });
+                to_log.push(entry.clone());
+            }
+        }
+        to_log
+    }
+}
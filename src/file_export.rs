@@ -0,0 +1,242 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+use std::sync::{Arc, Mutex as StdMutex};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use crate::config::{ExportRotation, FileExportConfig};
+use crate::database::LogEntry;
+
+/// Columns of every export file, the same as `GET /api/logs/export`
+const HEADER: [&str; 6] = ["timestamp", "device_id", "tag_name", "value", "unit", "quality"];
+
+/// Appends every logged value to CSV files on disk, for sites that must keep raw data
+/// outside the database. Files hold one day or hour of one device, or of all devices.
+///
+/// Producers only queue entries, so polling never waits on the disk. The writer keeps the
+/// files it is appending to open; once a period is over they are flushed, synced and
+/// closed, and the oldest files beyond `max_files` are deleted.
+pub struct FileExporter {
+    config: FileExportConfig,
+    queue: StdMutex<Vec<LogEntry>>,
+    wake: Notify,
+    /// Names of the files being written
+    open_files: StdMutex<HashSet<String>>,
+}
+
+/// A file being appended to
+struct ExportFile {
+    period: String,
+    writer: csv::Writer<File>,
+}
+
+impl FileExporter {
+    pub fn new(config: FileExportConfig) -> Self {
+        Self {
+            config,
+            queue: StdMutex::new(Vec::new()),
+            wake: Notify::new(),
+            open_files: StdMutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn directory(&self) -> &Path {
+        Path::new(&self.config.directory)
+    }
+
+    /// Whether the writer still has the file open; deleting it would lose the rest of its period
+    pub fn is_open(&self, file_name: &str) -> bool {
+        self.open_files.lock().unwrap().contains(file_name)
+    }
+
+    /// Queue logged entries for the writer
+    pub fn export(&self, entries: &[LogEntry]) {
+        if entries.is_empty() {
+            return;
+        }
+        self.queue.lock().unwrap().extend_from_slice(entries);
+        self.wake.notify_one();
+    }
+
+    /// Write queued entries for as long as the service runs
+    pub fn start(self: &Arc<Self>) {
+        let exporter = self.clone();
+        info!(
+            "Exporting logged values to {} ({:?} files, keeping {})",
+            exporter.config.directory, exporter.config.rotation, exporter.config.max_files
+        );
+
+        tokio::spawn(async move {
+            // Files left from before a restart count towards max_files too
+            let pruner = exporter.clone();
+            tokio::task::spawn_blocking(move || pruner.prune(&HashSet::new())).await.ok();
+
+            let mut files: HashMap<String, ExportFile> = HashMap::new();
+            loop {
+                exporter.wake.notified().await;
+                let entries = std::mem::take(&mut *exporter.queue.lock().unwrap());
+                if entries.is_empty() {
+                    continue;
+                }
+
+                // File handles move to the blocking pool and back with each batch
+                let writer = exporter.clone();
+                files = match tokio::task::spawn_blocking(move || {
+                    writer.write(&mut files, &entries);
+                    files
+                })
+                .await
+                {
+                    Ok(files) => files,
+                    Err(e) => {
+                        warn!("File export writer failed: {}", e);
+                        HashMap::new()
+                    }
+                };
+                let names = files.keys().cloned().collect();
+                *exporter.open_files.lock().unwrap() = names;
+            }
+        });
+    }
+
+    fn write(&self, files: &mut HashMap<String, ExportFile>, entries: &[LogEntry]) {
+        if let Err(e) = std::fs::create_dir_all(self.directory()) {
+            warn!("Failed to create export directory {}: {}", self.config.directory, e);
+            return;
+        }
+
+        let mut failed = HashSet::new();
+        for entry in entries {
+            let period = self.config.rotation.period(entry.timestamp);
+            let name = self.file_name(&period, &entry.device_id);
+            if failed.contains(&name) {
+                continue;
+            }
+            if let Err(e) = self.append(files, &period, &name, entry) {
+                warn!("Failed to export values to {}: {}", name, e);
+                files.remove(&name);
+                failed.insert(name);
+            }
+        }
+
+        // Files of earlier periods only take entries that arrived late, so they're done now
+        let Some(current) = files.values().map(|file| file.period.clone()).max() else {
+            return;
+        };
+        let finished: Vec<String> = files.iter().filter(|(_, file)| file.period < current).map(|(name, _)| name.clone()).collect();
+        for (name, file) in files.iter_mut() {
+            if let Err(e) = file.writer.flush() {
+                warn!("Failed to flush export file {}: {}", name, e);
+            }
+        }
+        for name in &finished {
+            if let Some(file) = files.remove(name) {
+                if let Err(e) = close(file) {
+                    warn!("Failed to sync export file {}: {}", name, e);
+                }
+            }
+        }
+        if !finished.is_empty() {
+            let open: HashSet<&String> = files.keys().collect();
+            self.prune(&open);
+        }
+    }
+
+    fn append(&self, files: &mut HashMap<String, ExportFile>, period: &str, name: &str, entry: &LogEntry) -> Result<()> {
+        if !files.contains_key(name) {
+            let file = OpenOptions::new().create(true).append(true).open(self.directory().join(name))?;
+            let is_new = file.metadata()?.len() == 0;
+            let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(file);
+            if is_new {
+                writer.write_record(HEADER)?;
+            }
+            files.insert(name.to_string(), ExportFile { period: period.to_string(), writer });
+        }
+        let file = files.get_mut(name).expect("export file was just opened");
+        file.writer.write_record([
+            entry.timestamp.to_rfc3339(),
+            entry.device_id.clone(),
+            entry.tag_name.clone(),
+            entry.value.to_string(),
+            entry.unit.clone().unwrap_or_default(),
+            entry.quality.clone(),
+        ])?;
+        Ok(())
+    }
+
+    /// `2024-06-01-meter-1.csv`, or `2024-06-01.csv` when devices share a file
+    fn file_name(&self, period: &str, device_id: &str) -> String {
+        match self.config.combined {
+            true => format!("{}.csv", period),
+            false => format!("{}-{}.csv", period, safe_file_name(device_id)),
+        }
+    }
+
+    /// Delete the oldest CSV files of the directory beyond `max_files`; 0 keeps them all
+    fn prune(&self, open: &HashSet<&String>) {
+        if self.config.max_files == 0 {
+            return;
+        }
+        let entries = match std::fs::read_dir(self.directory()) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(e) => {
+                warn!("Failed to list export directory {}: {}", self.config.directory, e);
+                return;
+            }
+        };
+        let mut csv_files: Vec<(std::time::SystemTime, String)> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                let metadata = entry.metadata().ok()?;
+                if !metadata.is_file() || !name.ends_with(".csv") {
+                    return None;
+                }
+                Some((metadata.modified().ok()?, name))
+            })
+            .collect();
+        if csv_files.len() <= self.config.max_files {
+            return;
+        }
+
+        csv_files.sort();
+        let excess = csv_files.len() - self.config.max_files;
+        for (_, name) in csv_files.into_iter().filter(|(_, name)| !open.contains(name)).take(excess) {
+            match std::fs::remove_file(self.directory().join(&name)) {
+                Ok(()) => info!("Deleted export file {} beyond max_files", name),
+                Err(e) => warn!("Failed to delete export file {}: {}", name, e),
+            }
+        }
+    }
+}
+
+/// Flush a finished file and make sure it is on disk before it is closed
+fn close(file: ExportFile) -> Result<()> {
+    let file = file.writer.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Device IDs with only letters, digits, `-`, `_` and `.` are used as they are; other
+/// characters become `_`
+fn safe_file_name(device_id: &str) -> String {
+    device_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+        .collect()
+}
+
+impl ExportRotation {
+    /// Start of the file name of the period `timestamp` falls in
+    pub fn period(&self, timestamp: DateTime<Utc>) -> String {
+        match self {
+            ExportRotation::Daily => timestamp.format("%Y-%m-%d").to_string(),
+            ExportRotation::Hourly => timestamp.format("%Y-%m-%dT%H").to_string(),
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use axum::{
+    http::{HeaderValue, Method},
+    Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use axum_server::Handle;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer};
+
+use crate::config::{CorsConfig, TlsConfig, DEFAULT_CORS_METHODS};
+
+/// The CORS layer for `[server.cors]`, which `ServerConfig::validate` has checked. Without
+/// allowed origins no CORS headers are sent, so browsers only let the UI's own origin in.
+pub fn cors_layer(config: &CorsConfig) -> Option<CorsLayer> {
+    if config.allowed_origins.is_empty() {
+        return None;
+    }
+
+    let origins = if config.allowed_origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(config.allowed_origins.iter().filter_map(|origin| HeaderValue::from_str(origin).ok()))
+    };
+    let methods = if config.allowed_methods.iter().any(|method| method == "*") {
+        AllowMethods::mirror_request()
+    } else if config.allowed_methods.is_empty() {
+        AllowMethods::list(DEFAULT_CORS_METHODS.iter().filter_map(|method| Method::from_bytes(method.as_bytes()).ok()))
+    } else {
+        AllowMethods::list(config.allowed_methods.iter().filter_map(|method| Method::from_bytes(method.to_ascii_uppercase().as_bytes()).ok()))
+    };
+
+    Some(
+        CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods(methods)
+            // Listing `*` isn't allowed with credentials, so echo what the browser asks for
+            .allow_headers(AllowHeaders::mirror_request())
+            .allow_credentials(config.allow_credentials),
+    )
+}
+
+/// Load the certificate and key of `[server.tls]`
+pub async fn rustls_config(config: &TlsConfig) -> anyhow::Result<RustlsConfig> {
+    // Loading only says that a file is missing, not which one
+    for (kind, path) in [("certificate", &config.cert_path), ("key", &config.key_path)] {
+        tokio::fs::metadata(path).await.map_err(|e| anyhow::anyhow!("Failed to read TLS {} {}: {}", kind, path, e))?;
+    }
+    RustlsConfig::from_pem_file(&config.cert_path, &config.key_path).await.map_err(|e| {
+        anyhow::anyhow!("Failed to load TLS certificate {} with key {}: {}", config.cert_path, config.key_path, e)
+    })
+}
+
+/// Serve `app` over TLS until `shutdown` is cancelled, then let open connections finish.
+///
+/// Handshakes run on each connection's own task, so a slow or failed one doesn't hold up others.
+pub async fn serve_tls(listener: TcpListener, config: RustlsConfig, app: Router, shutdown: CancellationToken) -> std::io::Result<()> {
+    let handle = Handle::new();
+    tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            shutdown.cancelled().await;
+            handle.graceful_shutdown(None);
+        }
+    });
+    axum_server::from_tcp_rustls(listener.into_std()?, config)
+        .handle(handle)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+}
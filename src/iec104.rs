@@ -1,13 +1,22 @@
+use std::collections::HashMap;
 use std::net::SocketAddr;
-use tokio::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Instant;
+use tokio::net::{TcpListener, TcpStream};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{broadcast, watch};
+use tokio::task::{JoinHandle, JoinSet};
 use anyhow::{Result, anyhow};
 use tracing::{info, warn, error};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use bytes::{Bytes, BytesMut, BufMut};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-use crate::config::{DeviceConfig, ProtocolConfig};
-use crate::database::{LogEntry, Database, DeviceTag};
+use crate::config::{DeviceConfig, Iec104Mode, Iec104ServerConfig, Iec104ServerPoint, ProtocolConfig, IEC104_SERVER_TYPE_IDS, Iec104Config};
+use crate::database::{LogEntry, Database, DeviceTag, Quality, TagWriteResult};
+use crate::protocol_trace::{ProtocolTrace, TraceDirection};
 
 // IEC 104 Protocol constants
 const START_BYTE: u8 = 0x68;
@@ -15,7 +24,7 @@ const APDU_MIN_LEN: u8 = 4;
 
 // APCI types
 const I_FORMAT: u8 = 0x00;
-// const S_FORMAT: u8 = 0x01;
+const S_FORMAT: u8 = 0x01;
 const U_FORMAT: u8 = 0x03;
 
 // U-format commands
@@ -23,8 +32,13 @@ const STARTDT_ACT: u8 = 0x07;
 const STARTDT_CON: u8 = 0x0B;
 const STOPDT_ACT: u8 = 0x13;
 // const STOPDT_CON: u8 = 0x23;
-// const TESTFR_ACT: u8 = 0x43;
-// const TESTFR_CON: u8 = 0x83;
+const TESTFR_ACT: u8 = 0x43;
+const TESTFR_CON: u8 = 0x83;
+
+/// Received I-format frames acknowledged with an S-format frame at the latest (w)
+const ACK_WINDOW: u16 = 8;
+/// Frames read in one poll at most, so a chatty outstation can't hold up the poller
+const MAX_FRAMES_PER_POLL: usize = 1000;
 
 // ASDU Types
 const M_SP_NA_1: u8 = 1;  // Single-point information
@@ -32,26 +46,336 @@ const M_SP_NA_1: u8 = 1;  // Single-point information
 // const M_ME_NA_1: u8 = 9;  // Measured value, normalized value
 const M_ME_NB_1: u8 = 11; // Measured value, scaled value
 const M_ME_NC_1: u8 = 13; // Measured value, short floating point value
+const M_SP_TB_1: u8 = 30; // Single-point information with CP56Time2a time tag
+const M_ME_TE_1: u8 = 35; // Measured value, scaled value with CP56Time2a time tag
+const M_ME_TF_1: u8 = 36; // Measured value, short floating point value with CP56Time2a time tag
+const C_SE_NC_1: u8 = 50; // Set-point command, short floating point value
+const C_IC_NA_1: u8 = 100; // Interrogation command
+const C_RD_NA_1: u8 = 102; // Read command
+
+// Causes of transmission
+const COT_SPONTANEOUS: u8 = 3;
+const COT_REQUEST: u8 = 5;
+const COT_ACTIVATION: u8 = 6;
+const COT_ACTIVATION_CON: u8 = 7;
+const COT_ACTIVATION_TERM: u8 = 10;
+const COT_NEGATIVE: u8 = 0x40; // P/N bit of the cause of transmission byte
+const COT_INTERROGATED_STATION: u8 = 20;
+const COT_INTERROGATED_GROUP_16: u8 = 36;
+
+// Quality descriptor bits (QDS, and the upper bits of SIQ)
+const QUALITY_OVERFLOW: u8 = 0x01;
+const QUALITY_BLOCKED: u8 = 0x10;
+const QUALITY_SUBSTITUTED: u8 = 0x20;
+const QUALITY_NOT_TOPICAL: u8 = 0x40;
+const QUALITY_INVALID: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Iec104ModeSettings {
+    pub mode: Iec104Mode,
+    pub interrogation_interval_ms: u64,
+}
+
+impl Iec104ModeSettings {
+    pub fn from_protocol(protocol: &ProtocolConfig) -> Option<Self> {
+        match protocol {
+            ProtocolConfig::Iec104(Iec104Config { mode, interrogation_interval_ms, .. }) => Some(Self {
+                mode: *mode,
+                interrogation_interval_ms: *interrogation_interval_ms,
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Iec104Diagnostics {
+    pub mode: Iec104Mode,
+    pub interrogation_interval_ms: u64,
+    pub interrogated_values: u64,
+    pub spontaneous_values: u64,
+    pub other_values: u64,
+    pub last_general_interrogation: Option<DateTime<Utc>>,
+    pub mode_changed_at: Option<DateTime<Utc>>,
+}
+
+/// Runtime mode switch shared between the logging service and a running IEC 104 driver.
+///
+/// Mode changes are pushed through a watch channel and picked up by the driver on
+/// its next poll, so the TCP connection and sequence numbers survive the switch.
+pub struct Iec104ModeHandle {
+    settings: watch::Sender<Iec104ModeSettings>,
+    diagnostics: StdMutex<Iec104Diagnostics>,
+}
+
+impl Iec104ModeHandle {
+    pub fn new(settings: Iec104ModeSettings) -> Arc<Self> {
+        let (sender, _) = watch::channel(settings);
+        Arc::new(Self {
+            settings: sender,
+            diagnostics: StdMutex::new(Iec104Diagnostics {
+                mode: settings.mode,
+                interrogation_interval_ms: settings.interrogation_interval_ms,
+                interrogated_values: 0,
+                spontaneous_values: 0,
+                other_values: 0,
+                last_general_interrogation: None,
+                mode_changed_at: None,
+            }),
+        })
+    }
+
+    pub fn settings(&self) -> Iec104ModeSettings {
+        *self.settings.borrow()
+    }
+
+    /// Apply new settings, returning false if they match the active ones
+    pub fn set(&self, settings: Iec104ModeSettings) -> bool {
+        let changed = self.settings.send_if_modified(|current| {
+            if *current == settings {
+                false
+            } else {
+                *current = settings;
+                true
+            }
+        });
+
+        if changed {
+            let mut diagnostics = self.diagnostics.lock().unwrap();
+            diagnostics.mode = settings.mode;
+            diagnostics.interrogation_interval_ms = settings.interrogation_interval_ms;
+            diagnostics.mode_changed_at = Some(Utc::now());
+        }
+        changed
+    }
+
+    pub fn diagnostics(&self) -> Iec104Diagnostics {
+        self.diagnostics.lock().unwrap().clone()
+    }
+
+    fn subscribe(&self) -> watch::Receiver<Iec104ModeSettings> {
+        self.settings.subscribe()
+    }
+
+    fn record_interrogation(&self) {
+        self.diagnostics.lock().unwrap().last_general_interrogation = Some(Utc::now());
+    }
+
+    fn record_values(&self, cause_of_transmission: u8, count: usize) {
+        let mut diagnostics = self.diagnostics.lock().unwrap();
+        match cause_of_transmission {
+            COT_SPONTANEOUS => diagnostics.spontaneous_values += count as u64,
+            COT_INTERROGATED_STATION..=COT_INTERROGATED_GROUP_16 => diagnostics.interrogated_values += count as u64,
+            _ => diagnostics.other_values += count as u64,
+        }
+    }
+}
+
+/// One information object of an ASDU, before tag scaling
+#[derive(Debug, Clone, PartialEq)]
+pub struct InformationObject {
+    pub ioa: u32,
+    pub value: f64,
+    /// The quality descriptor; for single points the SIQ without its value bit
+    pub quality: u8,
+}
+
+impl InformationObject {
+    /// Log entry quality for the quality descriptor: invalid values are `Bad`, and blocked,
+    /// substituted, not topical or overflowed values are `Uncertain`
+    pub fn quality_label(&self) -> &'static str {
+        if self.quality & QUALITY_INVALID != 0 {
+            Quality::Bad.as_str()
+        } else if self.quality & (QUALITY_OVERFLOW | QUALITY_BLOCKED | QUALITY_SUBSTITUTED | QUALITY_NOT_TOPICAL) != 0 {
+            Quality::Uncertain.as_str()
+        } else {
+            Quality::Good.as_str()
+        }
+    }
+}
+
+/// The ASDU carried by an I-format APDU
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataUnit {
+    pub type_id: u8,
+    /// Cause of transmission, without the test and P/N bits
+    pub cause: u8,
+    pub negative: bool,
+    /// Information objects, in the order sent; empty for types without a value this client reads
+    pub objects: Vec<InformationObject>,
+}
+
+/// Bytes of one information element after its IOA, for the types this client decodes
+fn element_size(type_id: u8) -> Option<usize> {
+    match type_id {
+        M_SP_NA_1 => Some(1),
+        M_ME_NB_1 => Some(3),
+        M_ME_NC_1 | C_SE_NC_1 => Some(5),
+        M_SP_TB_1 => Some(8),
+        M_ME_TE_1 => Some(10),
+        M_ME_TF_1 => Some(12),
+        C_RD_NA_1 => Some(0),
+        _ => None,
+    }
+}
+
+/// Value and quality of one information element; time tags are ignored
+fn decode_element(type_id: u8, element: &[u8]) -> (f64, u8) {
+    match type_id {
+        M_SP_NA_1 | M_SP_TB_1 => ((element[0] & 0x01) as f64, element[0] & 0xF0),
+        M_ME_NB_1 | M_ME_TE_1 => (i16::from_le_bytes([element[0], element[1]]) as f64, element[2]),
+        M_ME_NC_1 | M_ME_TF_1 => (f32::from_le_bytes([element[0], element[1], element[2], element[3]]) as f64, element[4]),
+        // The qualifier of a set-point isn't a quality descriptor
+        C_SE_NC_1 => (f32::from_le_bytes([element[0], element[1], element[2], element[3]]) as f64, 0),
+        _ => (0.0, 0),
+    }
+}
+
+/// Decode the ASDU of an I-format APDU, including every information object of
+/// sequence (SQ=1) and non-sequence ASDUs. Returns None for other frames and truncated ASDUs.
+pub fn parse_data_unit(frame: &[u8]) -> Option<DataUnit> {
+    if frame.len() < 12 || frame[0] != START_BYTE || frame[1] as usize + 2 != frame.len() || frame[2] & 0x01 != I_FORMAT {
+        return None;
+    }
+
+    let type_id = frame[6];
+    let count = (frame[7] & 0x7F) as usize;
+    let sequence = frame[7] & 0x80 != 0;
+    let mut unit = DataUnit {
+        type_id,
+        cause: frame[8] & 0x3F,
+        negative: frame[8] & COT_NEGATIVE != 0,
+        objects: Vec::with_capacity(count),
+    };
+    let Some(size) = element_size(type_id) else {
+        return Some(unit);
+    };
+
+    let objects = &frame[12..];
+    let needed = if sequence { 3 + count * size } else { count * (3 + size) };
+    if objects.len() < needed {
+        return None;
+    }
+    let ioa_at = |offset: usize| u32::from_le_bytes([objects[offset], objects[offset + 1], objects[offset + 2], 0]);
+    for i in 0..count {
+        let (ioa, offset) = if sequence {
+            (ioa_at(0) + i as u32, 3 + i * size)
+        } else {
+            (ioa_at(i * (3 + size)), i * (3 + size) + 3)
+        };
+        let (value, quality) = decode_element(type_id, &objects[offset..offset + size]);
+        unit.objects.push(InformationObject { ioa, value, quality });
+    }
+    Some(unit)
+}
+
+/// Summary of an APDU for the protocol trace, e.g. `I(12,4) M_ME_NC_1 cause 3 CA 1: 3 objects`
+fn describe_apdu(frame: &[u8]) -> String {
+    if frame.len() < 6 {
+        return format!("truncated APDU of {} bytes", frame.len());
+    }
+    let sequence = |low: u8, high: u8| u16::from_le_bytes([low, high]) >> 1;
+    match frame[2] & 0x03 {
+        U_FORMAT => match frame[2] {
+            STARTDT_ACT => "U STARTDT act".to_string(),
+            STARTDT_CON => "U STARTDT con".to_string(),
+            STOPDT_ACT => "U STOPDT act".to_string(),
+            0x23 => "U STOPDT con".to_string(),
+            TESTFR_ACT => "U TESTFR act".to_string(),
+            TESTFR_CON => "U TESTFR con".to_string(),
+            control => format!("U 0x{:02x}", control),
+        },
+        S_FORMAT => format!("S({})", sequence(frame[4], frame[5])),
+        _ => {
+            let mut summary = format!("I({},{})", sequence(frame[2], frame[3]), sequence(frame[4], frame[5]));
+            if frame.len() >= 12 {
+                let type_id = frame[6];
+                let type_name = match type_id {
+                    M_SP_NA_1 => Some("M_SP_NA_1"),
+                    M_ME_NB_1 => Some("M_ME_NB_1"),
+                    M_ME_NC_1 => Some("M_ME_NC_1"),
+                    M_SP_TB_1 => Some("M_SP_TB_1"),
+                    M_ME_TE_1 => Some("M_ME_TE_1"),
+                    M_ME_TF_1 => Some("M_ME_TF_1"),
+                    C_SE_NC_1 => Some("C_SE_NC_1"),
+                    C_IC_NA_1 => Some("C_IC_NA_1"),
+                    C_RD_NA_1 => Some("C_RD_NA_1"),
+                    _ => None,
+                }
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("type {}", type_id));
+                let negative = if frame[8] & COT_NEGATIVE != 0 { " negative" } else { "" };
+                summary.push_str(&format!(
+                    " {} cause {}{} CA {}: {} objects",
+                    type_name,
+                    frame[8] & 0x3F,
+                    negative,
+                    u16::from_le_bytes([frame[10], frame[11]]),
+                    frame[7] & 0x7F
+                ));
+            }
+            summary
+        },
+    }
+}
+
+/// Log entries for the enabled tags whose address is the IOA of an object in `unit`, scaled
+/// with the tag's multiplier and offset
+pub fn tag_entries(device_id: &str, unit: &DataUnit, device_tags: &[DeviceTag], timestamp: DateTime<Utc>) -> Vec<LogEntry> {
+    let mut entries = Vec::new();
+    for object in &unit.objects {
+        for device_tag in device_tags.iter().filter(|tag| tag.enabled && tag.address as u32 == object.ioa) {
+            entries.push(LogEntry {
+                id: None,
+                device_id: device_id.to_string(),
+                tag_name: device_tag.name.clone(),
+                value: object.value * device_tag.scaling_multiplier + device_tag.scaling_offset,
+                quality: object.quality_label().to_string(),
+                timestamp,
+                unit: device_tag.unit.clone(),
+            });
+        }
+    }
+    entries
+}
 
 pub struct Iec104Client {
     device_config: DeviceConfig,
     stream: Option<TcpStream>,
     send_sequence: u16,
     receive_sequence: u16,
+    /// I-format frames received since the last acknowledgement
+    unacknowledged: u16,
+    mode: Arc<Iec104ModeHandle>,
+    mode_updates: watch::Receiver<Iec104ModeSettings>,
+    last_interrogation: Option<Instant>,
+    trace: Option<Arc<ProtocolTrace>>,
 }
 
 impl Iec104Client {
-    pub fn new(device_config: DeviceConfig) -> Self {
+    pub fn new(device_config: DeviceConfig, mode: Arc<Iec104ModeHandle>) -> Self {
+        let mode_updates = mode.subscribe();
         Self {
             device_config,
             stream: None,
             send_sequence: 0,
             receive_sequence: 0,
+            unacknowledged: 0,
+            mode,
+            mode_updates,
+            last_interrogation: None,
+            trace: None,
         }
     }
 
+    /// Report every APDU sent and received to the device's protocol trace
+    pub fn with_trace(mut self, trace: Arc<ProtocolTrace>) -> Self {
+        self.trace = Some(trace);
+        self
+    }
+
     pub async fn connect(&mut self) -> Result<()> {
-        if let ProtocolConfig::Iec104 { host, port, .. } = &self.device_config.protocol {
+        if let ProtocolConfig::Iec104(Iec104Config { host, port, .. }) = &self.device_config.protocol {
             let socket_addr: SocketAddr = format!("{}:{}", host, port).parse()?;
             info!("Connecting to IEC 104 device at {}", socket_addr);
 
@@ -59,6 +383,9 @@ impl Iec104Client {
                 .map_err(|e| anyhow!("Failed to connect to IEC 104 device: {}", e))?;
 
             self.stream = Some(stream);
+            self.send_sequence = 0;
+            self.receive_sequence = 0;
+            self.unacknowledged = 0;
             
             // Send STARTDT_ACT to activate data transfer
             self.send_u_format(STARTDT_ACT).await?;
@@ -121,9 +448,6 @@ impl Iec104Client {
     // }
 
     async fn send_u_format(&mut self, control: u8) -> Result<()> {
-        let stream = self.stream.as_mut()
-            .ok_or_else(|| anyhow!("Not connected"))?;
-
         let mut frame = BytesMut::new();
         frame.put_u8(START_BYTE);
         frame.put_u8(4); // Length
@@ -132,16 +456,27 @@ impl Iec104Client {
         frame.put_u8(0);
         frame.put_u8(0);
 
-        stream.write_all(&frame).await?;
+        self.send_apdu(&frame).await?;
+        Ok(())
+    }
+
+    /// Acknowledge every I-format frame received so far (S-format)
+    async fn acknowledge(&mut self) -> Result<()> {
+        let mut frame = BytesMut::new();
+        frame.put_u8(START_BYTE);
+        frame.put_u8(4); // Length
+        frame.put_u8(S_FORMAT);
+        frame.put_u8(0);
+        frame.put_u16_le(self.receive_sequence << 1);
+
+        self.send_apdu(&frame).await?;
+        self.unacknowledged = 0;
         Ok(())
     }
 
     async fn send_interrogation(&mut self) -> Result<()> {
         let common_address = self.get_common_address();
         
-        let stream = self.stream.as_mut()
-            .ok_or_else(|| anyhow!("Not connected"))?;
-
         let mut frame = BytesMut::new();
         frame.put_u8(START_BYTE);
         frame.put_u8(14); // Length
@@ -151,7 +486,7 @@ impl Iec104Client {
         frame.put_u16_le(self.receive_sequence << 1); // Receive sequence number
         
         // ASDU
-        frame.put_u8(100); // C_IC_NA_1 - Interrogation command
+        frame.put_u8(C_IC_NA_1); // Interrogation command
         frame.put_u8(0x01); // SQ=0, Number of objects=1
         frame.put_u8(0x06); // COT=6 (activation)
         frame.put_u8(0); // Originator address
@@ -161,12 +496,148 @@ impl Iec104Client {
         frame.put_u8(0);
         frame.put_u8(20); // QOI=20 (station interrogation)
 
-        stream.write_all(&frame).await?;
+        self.send_apdu(&frame).await?;
         self.send_sequence = (self.send_sequence + 1) % 32768;
         Ok(())
     }
 
+    /// Send a short float set-point (C_SE_NC_1) to the tag's IOA and wait for the
+    /// outstation's activation confirmation, whose value is reported as the read-back
+    pub async fn write_setpoint(&mut self, device_tag: &DeviceTag, value: f64) -> Result<TagWriteResult> {
+        let raw = device_tag.raw_value(value).map_err(|e| anyhow!(e))? as f32;
+        if !raw.is_finite() {
+            return Err(anyhow!("Value {} does not fit in a short float set-point", value));
+        }
+        let common_address = self.get_common_address();
+        let ioa = device_tag.address as u32;
+
+        let mut frame = BytesMut::new();
+        frame.put_u8(START_BYTE);
+        frame.put_u8(18); // Length
+
+        // APCI
+        frame.put_u16_le(self.send_sequence << 1);
+        frame.put_u16_le(self.receive_sequence << 1);
+
+        // ASDU
+        frame.put_u8(C_SE_NC_1);
+        frame.put_u8(0x01); // SQ=0, Number of objects=1
+        frame.put_u8(COT_ACTIVATION);
+        frame.put_u8(0); // Originator address
+        frame.put_u16_le(common_address);
+        frame.put_slice(&ioa.to_le_bytes()[..3]);
+        frame.put_f32_le(raw);
+        frame.put_u8(0); // QOS: execute, no select
+
+        self.send_apdu(&frame).await?;
+        self.send_sequence = (self.send_sequence + 1) % 32768;
+
+        // Measurements arriving before the confirmation are skipped; the next poll picks them up again
+        let timeout = tokio::time::Duration::from_millis(self.device_config.timeout_ms.max(1000));
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let frame = tokio::time::timeout_at(deadline, self.receive_frame())
+                .await
+                .map_err(|_| anyhow!("No confirmation for set-point on IOA {} within {:?}", ioa, timeout))??;
+            if frame.len() < 19 || frame[6] != C_SE_NC_1 || frame[8] & 0x3F != COT_ACTIVATION_CON {
+                continue;
+            }
+            if u32::from_le_bytes([frame[12], frame[13], frame[14], 0]) != ioa {
+                continue;
+            }
+            if frame[8] & COT_NEGATIVE != 0 {
+                return Err(anyhow!("Device rejected set-point {} on IOA {}", raw, ioa));
+            }
+
+            let confirmed = f32::from_le_bytes([frame[15], frame[16], frame[17], frame[18]]) as f64;
+            info!("Set-point {} on IOA {} of device {} confirmed", raw, ioa, self.device_config.id);
+            return Ok(TagWriteResult {
+                tag_name: device_tag.name.clone(),
+                requested_value: value,
+                previous_value: None,
+                raw_value: raw as f64,
+                registers: Vec::new(),
+                read_back: Some(confirmed * device_tag.scaling_multiplier + device_tag.scaling_offset),
+            });
+        }
+    }
+
+    /// Ask the outstation for one information object (C_RD_NA_1) and return its unscaled value
+    pub async fn read_point(&mut self, ioa: u32) -> Result<f64> {
+        let common_address = self.get_common_address();
+
+        let mut frame = BytesMut::new();
+        frame.put_u8(START_BYTE);
+        frame.put_u8(13); // Length
+
+        // APCI
+        frame.put_u16_le(self.send_sequence << 1);
+        frame.put_u16_le(self.receive_sequence << 1);
+
+        // ASDU
+        frame.put_u8(C_RD_NA_1);
+        frame.put_u8(0x01); // SQ=0, Number of objects=1
+        frame.put_u8(COT_REQUEST);
+        frame.put_u8(0); // Originator address
+        frame.put_u16_le(common_address);
+        frame.put_slice(&ioa.to_le_bytes()[..3]);
+
+        self.send_apdu(&frame).await?;
+        self.send_sequence = (self.send_sequence + 1) % 32768;
+
+        let timeout = tokio::time::Duration::from_millis(self.device_config.timeout_ms.max(1000));
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let frame = tokio::time::timeout_at(deadline, self.receive_frame())
+                .await
+                .map_err(|_| anyhow!("No answer for IOA {} within {:?}", ioa, timeout))??;
+            let Some(unit) = parse_data_unit(&frame) else {
+                continue;
+            };
+            let Some(object) = unit.objects.iter().find(|object| object.ioa == ioa) else {
+                continue;
+            };
+            match unit.type_id {
+                C_RD_NA_1 if unit.negative => return Err(anyhow!("Device has no information object {}", ioa)),
+                C_RD_NA_1 | C_SE_NC_1 | C_IC_NA_1 => continue,
+                _ => return Ok(object.value),
+            }
+        }
+    }
+
+    /// Receive the next I- or U-format frame. Test frames are answered, and received I-format
+    /// frames are counted and acknowledged every `ACK_WINDOW` frames.
     async fn receive_frame(&mut self) -> Result<Bytes> {
+        loop {
+            let frame = self.receive_apdu().await?;
+            match frame[2] & 0x03 {
+                U_FORMAT if frame[2] == TESTFR_ACT => self.send_u_format(TESTFR_CON).await?,
+                U_FORMAT => return Ok(frame),
+                S_FORMAT => {},
+                _ => {
+                    let send_sequence = u16::from_le_bytes([frame[2], frame[3]]) >> 1;
+                    self.receive_sequence = (send_sequence + 1) % 32768;
+                    self.unacknowledged += 1;
+                    if self.unacknowledged >= ACK_WINDOW {
+                        self.acknowledge().await?;
+                    }
+                    return Ok(frame);
+                },
+            }
+        }
+    }
+
+    async fn send_apdu(&mut self, frame: &[u8]) -> Result<()> {
+        let stream = self.stream.as_mut()
+            .ok_or_else(|| anyhow!("Not connected"))?;
+        stream.write_all(frame).await?;
+        if let Some(trace) = &self.trace {
+            trace.record(TraceDirection::Tx, frame, || describe_apdu(frame));
+        }
+        Ok(())
+    }
+
+    async fn receive_apdu(&mut self) -> Result<Bytes> {
         let stream = self.stream.as_mut()
             .ok_or_else(|| anyhow!("Not connected"))?;
 
@@ -196,7 +667,11 @@ impl Iec104Client {
         full_frame.put_u8(length[0]);
         full_frame.put_slice(&frame_data);
 
-        Ok(full_frame.freeze())
+        let frame = full_frame.freeze();
+        if let Some(trace) = &self.trace {
+            trace.record(TraceDirection::Rx, &frame, || describe_apdu(&frame));
+        }
+        Ok(frame)
     }
 
     fn parse_u_format(&self, frame: &Bytes) -> Option<u8> {
@@ -209,177 +684,610 @@ impl Iec104Client {
         None
     }
 
-    fn parse_data_frame(&self, frame: &Bytes, timestamp: chrono::DateTime<Utc>) -> Option<Vec<LogEntry>> {
-        if frame.len() < 6 {
-            return None;
+    fn get_common_address(&self) -> u16 {
+        if let ProtocolConfig::Iec104(Iec104Config { common_address, .. }) = &self.device_config.protocol {
+            *common_address
+        } else {
+            1
         }
+    }
 
-        let control = frame[2];
-        if (control & 0x01) != I_FORMAT {
-            return None; // Not an I-format frame
-        }
+    pub async fn read_specific_tags(&mut self, database: &Database, device_tags: &[DeviceTag]) -> Result<Vec<LogEntry>> {
+        let mut log_entries = Vec::new();
+        let timestamp = Utc::now();
+        let muted_tags = database.get_muted_tag_names(&self.device_config.id).await.unwrap_or_else(|e| {
+            error!("Failed to load tag mutes for device {}: {}", self.device_config.id, e);
+            Default::default()
+        });
 
-        if frame.len() < 12 {
-            return None; // Too short for ASDU
+        if self.mode_updates.has_changed().unwrap_or(false) {
+            let settings = *self.mode_updates.borrow_and_update();
+            info!(
+                "IEC 104 device {} switched to {:?} mode (interrogation interval {}ms)",
+                self.device_config.id, settings.mode, settings.interrogation_interval_ms
+            );
         }
 
-        let type_id = frame[6];
-        let _vsq = frame[7];
-        let _cot = frame[8];
-        let _common_addr = u16::from_le_bytes([frame[10], frame[11]]);
+        let interrogating = self.interrogation_due();
+        if interrogating {
+            // Send interrogation command to get all current values
+            self.send_interrogation().await?;
+            self.last_interrogation = Some(Instant::now());
+            self.mode.record_interrogation();
+        }
 
-        let mut entries = Vec::new();
+        // Take everything the outstation sent since the last poll: the interrogation answers
+        // and any spontaneous or cyclic values, until the line goes quiet
+        for _ in 0..MAX_FRAMES_PER_POLL {
+            let frame = match tokio::time::timeout(tokio::time::Duration::from_millis(1000), self.receive_frame()).await {
+                Ok(Ok(frame)) => frame,
+                Ok(Err(e)) => {
+                    self.stream = None;
+                    return Err(anyhow!("Connection to IEC 104 device {} lost: {}", self.device_config.id, e));
+                },
+                // Timeout - no more data
+                Err(_) => break,
+            };
+            let Some(unit) = parse_data_unit(&frame) else {
+                continue;
+            };
+            if unit.type_id == C_IC_NA_1 {
+                if unit.cause == COT_ACTIVATION_TERM && interrogating {
+                    break;
+                }
+                continue;
+            }
+            if element_size(unit.type_id).is_none() {
+                warn!("Unsupported ASDU type: {}", unit.type_id);
+            }
 
-        match type_id {
-            M_ME_NC_1 => {
-                // Short floating point value
-                if frame.len() >= 19 {
-                    let ioa = u32::from_le_bytes([frame[12], frame[13], frame[14], 0]);
-                    let value_bytes = [frame[15], frame[16], frame[17], frame[18]];
-                    let value = f32::from_le_bytes(value_bytes) as f64;
-
-                    entries.push(LogEntry {
-                        id: None,
-                        device_id: self.device_config.id.clone(),
-                        tag_name: format!("float_{}", ioa),
-                        value,
-                        quality: "Good".to_string(),
-                        timestamp,
-                        unit: None,
-                    });
+            let entries = tag_entries(&self.device_config.id, &unit, device_tags, timestamp);
+            self.mode.record_values(unit.cause, entries.len());
+            for mut entry in entries {
+                if muted_tags.contains(&entry.tag_name) {
+                    // Muted tags still count towards diagnostics but are never logged
+                    entry.quality = "muted".to_string();
+                    if let Err(e) = database.record_muted_sample(&entry.device_id, &entry.tag_name, timestamp).await {
+                        error!("Failed to record muted sample: {}", e);
+                    }
                 }
-            },
-            M_ME_NB_1 => {
-                // Scaled value
-                if frame.len() >= 17 {
-                    let ioa = u32::from_le_bytes([frame[12], frame[13], frame[14], 0]);
-                    let value = i16::from_le_bytes([frame[15], frame[16]]) as f64;
-
-                    entries.push(LogEntry {
-                        id: None,
-                        device_id: self.device_config.id.clone(),
-                        tag_name: format!("scaled_{}", ioa),
-                        value,
-                        quality: "Good".to_string(),
-                        timestamp,
-                        unit: None,
-                    });
+                log_entries.push(entry);
+            }
+        }
+
+        if self.unacknowledged > 0 {
+            self.acknowledge().await?;
+        }
+
+        Ok(log_entries)
+    }
+
+    fn interrogation_due(&self) -> bool {
+        let settings = self.mode.settings();
+        match settings.mode {
+            Iec104Mode::Interrogation => true,
+            Iec104Mode::Spontaneous => false,
+            Iec104Mode::Hybrid => self.last_interrogation.is_none_or(|last| {
+                last.elapsed().as_millis() >= settings.interrogation_interval_ms as u128
+            }),
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    pub async fn disconnect(&mut self) -> Result<()> {
+        if self.stream.is_some() {
+            // Send STOPDT_ACT before disconnecting
+            self.send_u_format(STOPDT_ACT).await?;
+            self.stream = None;
+            info!("Disconnected from IEC 104 device {}", self.device_config.id);
+        }
+        Ok(())
+    }
+}
+
+/// Spontaneous batches a master that stopped reading may fall behind by before values are skipped
+const SERVER_UPDATE_CAPACITY: usize = 256;
+/// Bytes of information objects one ASDU may carry: an APDU is at most 253 bytes, less the
+/// control field and the ASDU header
+const MAX_OBJECT_BYTES: usize = 253 - 4 - 6;
+
+// Negative confirmation causes sent back to masters
+const COT_UNKNOWN_TYPE: u8 = 44;
+const COT_UNKNOWN_CAUSE: u8 = 45;
+const COT_UNKNOWN_COMMON_ADDRESS: u8 = 46;
+const COT_UNKNOWN_IOA: u8 = 47;
+const STOPDT_CON: u8 = 0x23;
+const GLOBAL_COMMON_ADDRESS: u16 = 0xFFFF;
+
+/// Value and quality descriptor of a served point
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ServedValue {
+    value: f64,
+    quality: u8,
+}
+
+impl ServedValue {
+    fn from_entry(entry: &LogEntry) -> Self {
+        let quality = match Quality::parse(&entry.quality) {
+            Some(Quality::Good) => 0,
+            Some(Quality::Bad) => QUALITY_INVALID,
+            Some(Quality::CommFail) => QUALITY_INVALID | QUALITY_NOT_TOPICAL,
+            _ => QUALITY_NOT_TOPICAL,
+        };
+        Self { value: entry.value, quality }
+    }
+}
+
+/// One information object as it goes out to masters
+#[derive(Debug, Clone, Copy)]
+struct ServedObject {
+    ioa: u32,
+    type_id: u8,
+    value: ServedValue,
+}
+
+struct ServedPoint {
+    point: Iec104ServerPoint,
+    /// Latest value logged for the tag
+    current: Option<ServedValue>,
+    /// Value last sent spontaneously, the reference for the deadband
+    reported: Option<ServedValue>,
+}
+
+impl ServedPoint {
+    fn object(&self) -> ServedObject {
+        ServedObject {
+            ioa: self.point.ioa,
+            type_id: self.point.type_id,
+            // Nothing logged yet since the server started
+            value: self.current.unwrap_or(ServedValue { value: 0.0, quality: QUALITY_INVALID | QUALITY_NOT_TOPICAL }),
+        }
+    }
+}
+
+#[derive(Default)]
+struct ServerPoints {
+    config: Iec104ServerConfig,
+    points: Vec<ServedPoint>,
+    /// (device id, tag name) -> indexes into `points`
+    by_tag: HashMap<(String, String), Vec<usize>>,
+    address: Option<SocketAddr>,
+    error: Option<String>,
+}
+
+impl ServerPoints {
+    fn common_address(&self) -> u16 {
+        self.config.common_address
+    }
+
+    /// Points answering an interrogation with the given qualifier: 20 is the station, 21-36 group 1-16
+    fn interrogated(&self, qualifier: u8) -> Vec<ServedObject> {
+        self.points
+            .iter()
+            .filter(|served| qualifier == COT_INTERROGATED_STATION || served.point.group == Some(qualifier - COT_INTERROGATED_STATION))
+            .map(ServedPoint::object)
+            .collect()
+    }
+}
+
+struct ServerShared {
+    points: StdMutex<ServerPoints>,
+    updates: broadcast::Sender<Arc<Vec<ServedObject>>>,
+    masters: AtomicUsize,
+    active_masters: AtomicUsize,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Iec104ServerStatus {
+    pub listening: bool,
+    /// Port bound, which differs from the configured one when that is 0
+    pub port: Option<u16>,
+    /// Masters connected
+    pub masters: usize,
+    /// Masters that started data transfer (STARTDT)
+    pub active_masters: usize,
+    pub points: usize,
+    /// Points with a value logged since the server started
+    pub points_with_value: usize,
+    /// Why the server isn't listening while enabled
+    pub error: Option<String>,
+}
+
+/// IEC 104 outstation serving the latest logged values of mapped device tags to SCADA masters.
+///
+/// Masters get every point on general interrogation and changes beyond the deadband
+/// spontaneously once they have started data transfer.
+pub struct Iec104Server {
+    shared: Arc<ServerShared>,
+    listener: tokio::sync::Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Default for Iec104Server {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Iec104Server {
+    pub fn new() -> Self {
+        let (updates, _) = broadcast::channel(SERVER_UPDATE_CAPACITY);
+        Self {
+            shared: Arc::new(ServerShared {
+                points: StdMutex::new(ServerPoints::default()),
+                updates,
+                masters: AtomicUsize::new(0),
+                active_masters: AtomicUsize::new(0),
+            }),
+            listener: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    /// Switch to a new configuration. Connected masters are dropped and the listener restarted;
+    /// values of tags that stay mapped are kept.
+    pub async fn apply(&self, config: Iec104ServerConfig) -> Result<()> {
+        config.validate().map_err(|e| anyhow!(e))?;
+
+        let mut listener = self.listener.lock().await;
+        if let Some(task) = listener.take() {
+            task.abort();
+            let _ = task.await;
+        }
+
+        let (enabled, port) = (config.enabled, config.port);
+        {
+            let mut state = self.shared.points.lock().unwrap();
+            let mut previous: HashMap<(String, String), ServedValue> = HashMap::new();
+            for served in &state.points {
+                if let Some(value) = served.current {
+                    previous.insert((served.point.device_id.clone(), served.point.tag_name.clone()), value);
                 }
+            }
+
+            let mut by_tag: HashMap<(String, String), Vec<usize>> = HashMap::new();
+            let mut points = Vec::with_capacity(config.points.len());
+            for (index, point) in config.points.iter().enumerate() {
+                let key = (point.device_id.clone(), point.tag_name.clone());
+                points.push(ServedPoint { point: point.clone(), current: previous.get(&key).copied(), reported: None });
+                by_tag.entry(key).or_default().push(index);
+            }
+            *state = ServerPoints { config, points, by_tag, address: None, error: None };
+        }
+
+        if !enabled {
+            return Ok(());
+        }
+        let socket = match TcpListener::bind(("0.0.0.0", port)).await {
+            Ok(socket) => socket,
+            Err(e) => {
+                let message = format!("Failed to listen on port {}: {}", port, e);
+                self.shared.points.lock().unwrap().error = Some(message.clone());
+                return Err(anyhow!(message));
             },
-            M_SP_NA_1 => {
-                // Single point
-                if frame.len() >= 16 {
-                    let ioa = u32::from_le_bytes([frame[12], frame[13], frame[14], 0]);
-                    let siq = frame[15];
-                    let value = if (siq & 0x01) != 0 { 1.0 } else { 0.0 };
-
-                    entries.push(LogEntry {
-                        id: None,
-                        device_id: self.device_config.id.clone(),
-                        tag_name: format!("sp_{}", ioa),
-                        value,
-                        quality: "Good".to_string(),
-                        timestamp,
-                        unit: None,
+        };
+        let address = socket.local_addr()?;
+        self.shared.points.lock().unwrap().address = Some(address);
+        info!("IEC 104 server listening on {}", address);
+
+        *listener = Some(tokio::spawn(accept_masters(self.shared.clone(), socket)));
+        Ok(())
+    }
+
+    pub fn config(&self) -> Iec104ServerConfig {
+        self.shared.points.lock().unwrap().config.clone()
+    }
+
+    /// Record a poll's values for the points they are mapped to, and send masters the ones that
+    /// moved beyond the deadband or changed quality
+    pub fn publish(&self, entries: &[LogEntry]) {
+        let mut changed = Vec::new();
+        {
+            let mut state = self.shared.points.lock().unwrap();
+            if state.points.is_empty() {
+                return;
+            }
+            let server_deadband = state.config.deadband;
+            for entry in entries.iter().filter(|entry| entry.quality != "muted") {
+                let Some(indexes) = state.by_tag.get(&(entry.device_id.clone(), entry.tag_name.clone())).cloned() else {
+                    continue;
+                };
+                let value = ServedValue::from_entry(entry);
+                for index in indexes {
+                    let served = &mut state.points[index];
+                    served.current = Some(value);
+                    let deadband = served.point.deadband.unwrap_or(server_deadband);
+                    let report = served.reported.is_none_or(|reported| {
+                        reported.quality != value.quality || (value.value - reported.value).abs() > deadband
                     });
+                    if report {
+                        served.reported = Some(value);
+                        changed.push(served.object());
+                    }
                 }
-            },
-            _ => {
-                warn!("Unsupported ASDU type: {}", type_id);
             }
         }
 
-        if entries.is_empty() {
-            None
-        } else {
-            Some(entries)
+        // Nobody listening is not an error
+        if !changed.is_empty() {
+            let _ = self.shared.updates.send(Arc::new(changed));
         }
     }
 
-    fn get_common_address(&self) -> u16 {
-        if let ProtocolConfig::Iec104 { common_address, .. } = &self.device_config.protocol {
-            *common_address
-        } else {
-            1
+    pub fn status(&self) -> Iec104ServerStatus {
+        let state = self.shared.points.lock().unwrap();
+        Iec104ServerStatus {
+            listening: state.address.is_some(),
+            port: state.address.map(|address| address.port()),
+            masters: self.shared.masters.load(Ordering::SeqCst),
+            active_masters: self.shared.active_masters.load(Ordering::SeqCst),
+            points: state.points.len(),
+            points_with_value: state.points.iter().filter(|served| served.current.is_some()).count(),
+            error: state.error.clone(),
         }
     }
+}
 
-    pub async fn read_specific_tags(&mut self, database: &Database, device_tags: &[DeviceTag]) -> Result<Vec<LogEntry>> {
-        let mut log_entries = Vec::new();
-        let timestamp = Utc::now();
+/// Accept masters until the server is reconfigured; aborting this task drops every session
+async fn accept_masters(shared: Arc<ServerShared>, socket: TcpListener) {
+    let mut sessions = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = socket.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    info!("IEC 104 master {} connected", peer);
+                    sessions.spawn(serve_master(shared.clone(), stream, peer));
+                },
+                Err(e) => {
+                    warn!("IEC 104 server failed to accept a master: {}", e);
+                    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+                },
+            },
+            Some(_) = sessions.join_next(), if !sessions.is_empty() => {},
+        }
+    }
+}
+
+async fn serve_master(shared: Arc<ServerShared>, stream: TcpStream, peer: SocketAddr) {
+    let mut session = MasterSession::new(shared, stream);
+    match session.run().await {
+        Ok(()) => info!("IEC 104 master {} disconnected", peer),
+        Err(e) => warn!("IEC 104 master {} dropped: {}", peer, e),
+    }
+}
+
+/// One master's connection: sequence numbers, and whether it started data transfer
+struct MasterSession {
+    shared: Arc<ServerShared>,
+    stream: TcpStream,
+    send_sequence: u16,
+    receive_sequence: u16,
+    unacknowledged: u16,
+    active: bool,
+}
+
+impl MasterSession {
+    fn new(shared: Arc<ServerShared>, stream: TcpStream) -> Self {
+        shared.masters.fetch_add(1, Ordering::SeqCst);
+        Self { shared, stream, send_sequence: 0, receive_sequence: 0, unacknowledged: 0, active: false }
+    }
 
-        // Send interrogation command to get all current values
-        self.send_interrogation().await?;
-
-        // Read multiple frames to get all data
-        for _ in 0..10 { // Limit to prevent infinite loop
-            match tokio::time::timeout(
-                tokio::time::Duration::from_millis(1000),
-                self.receive_frame()
-            ).await {
-                Ok(Ok(frame)) => {
-                    if let Some(entries) = self.parse_data_frame_for_specific_tags(&frame, timestamp, device_tags) {
-                        for entry in entries {
-                            // Insert into database
-                            if let Err(e) = database.insert_log_entry(&entry).await {
-                                error!("Failed to insert log entry: {}", e);
-                            }
-                            log_entries.push(entry);
-                        }
+    async fn run(&mut self) -> Result<()> {
+        let mut updates = self.shared.updates.subscribe();
+        let mut buffer = BytesMut::with_capacity(256);
+        loop {
+            while let Some(frame) = take_apdu(&mut buffer)? {
+                if self.handle_frame(&frame).await? {
+                    // Only changes from now on; the master interrogates for the rest
+                    updates = updates.resubscribe();
+                }
+            }
+
+            tokio::select! {
+                read = self.stream.read_buf(&mut buffer) => {
+                    if read? == 0 {
+                        return Ok(());
                     }
                 },
-                Ok(Err(e)) => {
-                    error!("Error receiving frame: {}", e);
-                    break;
+                update = updates.recv(), if self.active => match update {
+                    Ok(objects) => self.send_objects(&objects, COT_SPONTANEOUS).await?,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("IEC 104 master fell behind; {} spontaneous updates skipped", skipped);
+                    },
+                    Err(broadcast::error::RecvError::Closed) => return Ok(()),
                 },
-                Err(_) => {
-                    // Timeout - no more data
-                    break;
-                }
             }
         }
+    }
 
-        Ok(log_entries)
+    /// Handle one frame from the master; true when it started data transfer
+    async fn handle_frame(&mut self, frame: &[u8]) -> Result<bool> {
+        match frame[2] & 0x03 {
+            U_FORMAT => match frame[2] {
+                STARTDT_ACT => {
+                    self.send_u_format(STARTDT_CON).await?;
+                    let started = !self.active;
+                    self.set_active(true);
+                    return Ok(started);
+                },
+                STOPDT_ACT => {
+                    if self.unacknowledged > 0 {
+                        self.acknowledge().await?;
+                    }
+                    self.set_active(false);
+                    self.send_u_format(STOPDT_CON).await?;
+                },
+                TESTFR_ACT => self.send_u_format(TESTFR_CON).await?,
+                _ => {},
+            },
+            S_FORMAT => {},
+            _ => {
+                let send_sequence = u16::from_le_bytes([frame[2], frame[3]]) >> 1;
+                self.receive_sequence = (send_sequence + 1) % 32768;
+                self.unacknowledged += 1;
+                if self.active && frame.len() >= 12 {
+                    self.handle_command(frame).await?;
+                }
+                if self.unacknowledged >= ACK_WINDOW {
+                    self.acknowledge().await?;
+                }
+            },
+        }
+        Ok(false)
     }
 
-    fn parse_data_frame_for_specific_tags(&self, frame: &Bytes, timestamp: chrono::DateTime<Utc>, device_tags: &[DeviceTag]) -> Option<Vec<LogEntry>> {
-        // For now, use the existing parse_data_frame logic but filter for specific tags
-        // In a real implementation, you would filter based on IOA (Information Object Address)
-        // that corresponds to the device_tags addresses
-        
-        let all_entries = self.parse_data_frame(frame, timestamp)?;
-        let mut filtered_entries = Vec::new();
-        
-        for entry in all_entries {
-            // Check if this entry corresponds to one of our device tags
-            if device_tags.iter().any(|tag| tag.name == entry.tag_name) {
-                // Apply scaling from device tag
-                if let Some(device_tag) = device_tags.iter().find(|tag| tag.name == entry.tag_name) {
-                    let scaled_value = entry.value * device_tag.scaling_multiplier + device_tag.scaling_offset;
-                    let scaled_entry = LogEntry {
-                        id: entry.id,
-                        device_id: entry.device_id,
-                        tag_name: entry.tag_name,
-                        value: scaled_value,
-                        quality: entry.quality,
-                        timestamp: entry.timestamp,
-                        unit: device_tag.unit.clone(),
-                    };
-                    filtered_entries.push(scaled_entry);
+    async fn handle_command(&mut self, frame: &[u8]) -> Result<()> {
+        let type_id = frame[6];
+        let cause = frame[8] & 0x3F;
+        let common_address = u16::from_le_bytes([frame[10], frame[11]]);
+        let own_address = self.shared.points.lock().unwrap().common_address();
+        if common_address != own_address && common_address != GLOBAL_COMMON_ADDRESS {
+            return self.mirror(frame, COT_UNKNOWN_COMMON_ADDRESS, true).await;
+        }
+
+        match type_id {
+            C_IC_NA_1 if frame.len() >= 16 => {
+                let qualifier = frame[15];
+                if cause != COT_ACTIVATION {
+                    return self.mirror(frame, COT_UNKNOWN_CAUSE, true).await;
                 }
-            }
+                if !(COT_INTERROGATED_STATION..=COT_INTERROGATED_GROUP_16).contains(&qualifier) {
+                    return self.mirror(frame, COT_ACTIVATION_CON, true).await;
+                }
+                self.mirror(frame, COT_ACTIVATION_CON, false).await?;
+                let objects = self.shared.points.lock().unwrap().interrogated(qualifier);
+                self.send_objects(&objects, qualifier).await?;
+                self.mirror(frame, COT_ACTIVATION_TERM, false).await
+            },
+            C_RD_NA_1 if frame.len() >= 15 => {
+                let ioa = u32::from_le_bytes([frame[12], frame[13], frame[14], 0]);
+                let object = {
+                    let state = self.shared.points.lock().unwrap();
+                    state.points.iter().find(|served| served.point.ioa == ioa).map(ServedPoint::object)
+                };
+                match object {
+                    Some(object) => self.send_objects(&[object], COT_REQUEST).await,
+                    None => self.mirror(frame, COT_UNKNOWN_IOA, true).await,
+                }
+            },
+            _ => self.mirror(frame, COT_UNKNOWN_TYPE, true).await,
         }
-        
-        Some(filtered_entries)
     }
 
-    pub async fn disconnect(&mut self) -> Result<()> {
-        if self.stream.is_some() {
-            // Send STOPDT_ACT before disconnecting
-            self.send_u_format(STOPDT_ACT).await?;
-            self.stream = None;
-            info!("Disconnected from IEC 104 device {}", self.device_config.id);
+    fn set_active(&mut self, active: bool) {
+        if active != self.active {
+            let counter = &self.shared.active_masters;
+            if active { counter.fetch_add(1, Ordering::SeqCst) } else { counter.fetch_sub(1, Ordering::SeqCst) };
+            self.active = active;
+        }
+    }
+
+    /// Send the objects grouped by type, as many per ASDU as fit
+    async fn send_objects(&mut self, objects: &[ServedObject], cause: u8) -> Result<()> {
+        let common_address = self.shared.points.lock().unwrap().common_address();
+        for type_id in IEC104_SERVER_TYPE_IDS {
+            let of_type: Vec<&ServedObject> = objects.iter().filter(|object| object.type_id == type_id).collect();
+            let Some(size) = element_size(type_id) else {
+                continue;
+            };
+            for chunk in of_type.chunks((MAX_OBJECT_BYTES / (3 + size)).min(127)) {
+                let mut asdu = BytesMut::new();
+                asdu.put_u8(type_id);
+                asdu.put_u8(chunk.len() as u8); // SQ=0
+                asdu.put_u8(cause);
+                asdu.put_u8(0); // Originator address
+                asdu.put_u16_le(common_address);
+                for object in chunk {
+                    asdu.put_slice(&object.ioa.to_le_bytes()[..3]);
+                    encode_element(object, &mut asdu);
+                }
+                self.send_i_format(&asdu).await?;
+            }
         }
         Ok(())
     }
+
+    /// Answer a command with its own ASDU under another cause of transmission
+    async fn mirror(&mut self, frame: &[u8], cause: u8, negative: bool) -> Result<()> {
+        let mut asdu = frame[6..].to_vec();
+        asdu[2] = cause | if negative { COT_NEGATIVE } else { 0 };
+        self.send_i_format(&asdu).await
+    }
+
+    async fn send_i_format(&mut self, asdu: &[u8]) -> Result<()> {
+        let mut frame = BytesMut::with_capacity(asdu.len() + 6);
+        frame.put_u8(START_BYTE);
+        frame.put_u8((asdu.len() + 4) as u8);
+        frame.put_u16_le(self.send_sequence << 1);
+        frame.put_u16_le(self.receive_sequence << 1);
+        frame.put_slice(asdu);
+
+        self.stream.write_all(&frame).await?;
+        self.send_sequence = (self.send_sequence + 1) % 32768;
+        // An I-format frame acknowledges everything received so far
+        self.unacknowledged = 0;
+        Ok(())
+    }
+
+    async fn send_u_format(&mut self, control: u8) -> Result<()> {
+        self.stream.write_all(&[START_BYTE, 4, control, 0, 0, 0]).await?;
+        Ok(())
+    }
+
+    async fn acknowledge(&mut self) -> Result<()> {
+        let mut frame = BytesMut::with_capacity(6);
+        frame.put_u8(START_BYTE);
+        frame.put_u8(4);
+        frame.put_u8(S_FORMAT);
+        frame.put_u8(0);
+        frame.put_u16_le(self.receive_sequence << 1);
+
+        self.stream.write_all(&frame).await?;
+        self.unacknowledged = 0;
+        Ok(())
+    }
+}
+
+impl Drop for MasterSession {
+    fn drop(&mut self) {
+        self.set_active(false);
+        self.shared.masters.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Split the next complete APDU off the front of `buffer`
+fn take_apdu(buffer: &mut BytesMut) -> Result<Option<Bytes>> {
+    if buffer.len() < 2 {
+        return Ok(None);
+    }
+    if buffer[0] != START_BYTE {
+        return Err(anyhow!("Invalid start byte: {}", buffer[0]));
+    }
+    let len = buffer[1] as usize;
+    if len < APDU_MIN_LEN as usize {
+        return Err(anyhow!("Frame too short: {}", len));
+    }
+    if buffer.len() < len + 2 {
+        return Ok(None);
+    }
+    Ok(Some(buffer.split_to(len + 2).freeze()))
+}
+
+/// Information element of a served object; scaled values outside 16 bits are clamped and flagged as overflow
+fn encode_element(object: &ServedObject, asdu: &mut BytesMut) {
+    let ServedValue { value, quality } = object.value;
+    match object.type_id {
+        M_SP_NA_1 => asdu.put_u8((value != 0.0) as u8 | (quality & 0xF0)),
+        M_ME_NB_1 => {
+            let rounded = value.round();
+            let overflow = if rounded < i16::MIN as f64 || rounded > i16::MAX as f64 { QUALITY_OVERFLOW } else { 0 };
+            asdu.put_i16_le(rounded.clamp(i16::MIN as f64, i16::MAX as f64) as i16);
+            asdu.put_u8(quality | overflow);
+        },
+        _ => {
+            asdu.put_f32_le(value as f32);
+            asdu.put_u8(quality);
+        },
+    }
 }
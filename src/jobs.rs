@@ -0,0 +1,148 @@
+use std::sync::{Arc, Mutex as StdMutex};
+
+use chrono::Utc;
+use serde::Serialize;
+use tracing::{error, info};
+
+use crate::api::ApiError;
+use crate::config::WebhookEvent;
+use crate::database::{Database, Job, JobItemResult, JobState};
+use crate::notifications::NotificationService;
+use crate::scheduler::PendingOperation;
+use crate::tb_rust_client::SyncReportRecorder;
+use crate::AppState;
+
+/// Keeps a background job's row in the `jobs` table current and emits a
+/// `job_progress` event on every change.
+///
+/// Failures to store progress are logged rather than returned so a job never
+/// stops because its progress couldn't be written.
+#[derive(Clone)]
+pub struct JobTracker {
+    job: Arc<StdMutex<Job>>,
+    report: Arc<SyncReportRecorder>,
+    database: Arc<Database>,
+    notifications: Arc<NotificationService>,
+}
+
+impl JobTracker {
+    /// Record a job for an operation the scheduler has just accepted
+    pub async fn queued(
+        state: &AppState,
+        operation: &PendingOperation,
+        entity_group_id: &str,
+        description: String,
+        requested_by: Option<String>,
+    ) -> Self {
+        let job = Job {
+            id: operation.job_id().to_string(),
+            kind: operation.kind().to_string(),
+            entity_group_id: Some(entity_group_id.to_string()),
+            description,
+            requested_by,
+            state: JobState::Queued,
+            progress_current: 0,
+            progress_total: 0,
+            items: Vec::new(),
+            result: None,
+            error: None,
+            report: None,
+            created_at: Utc::now(),
+            started_at: None,
+            finished_at: None,
+        };
+        let tracker = Self {
+            job: Arc::new(StdMutex::new(job)),
+            report: Arc::new(SyncReportRecorder::default()),
+            database: state.database.clone(),
+            notifications: state.notifications.clone(),
+        };
+        tracker.update(|_| {}).await;
+        tracker
+    }
+
+    pub fn job(&self) -> Job {
+        self.job.lock().unwrap().clone()
+    }
+
+    /// Report for the job's ThingsBoard clients to record their requests in; it is saved
+    /// with every progress update
+    pub fn report(&self) -> Arc<SyncReportRecorder> {
+        self.report.clone()
+    }
+
+    /// The operation has its locks; `total` is 0 until the job knows how many items it has
+    pub async fn started(&self) {
+        info!("Job {} started", self.job.lock().unwrap().id);
+        self.update(|job| {
+            job.state = JobState::Running;
+            job.started_at = Some(Utc::now());
+        })
+        .await;
+    }
+
+    pub async fn set_total(&self, total: usize) {
+        self.update(|job| job.progress_total = total as u32).await;
+    }
+
+    /// One more item is done
+    pub async fn item(&self, item: JobItemResult) {
+        self.update(|job| {
+            job.progress_current += 1;
+            job.progress_total = job.progress_total.max(job.progress_current);
+            job.items.push(item);
+        })
+        .await;
+    }
+
+    /// Finish with the response the operation would have returned inline:
+    /// completed with its data, or failed with its error
+    pub async fn finish<T: Serialize>(&self, response: Result<T, ApiError>) {
+        self.update(|job| {
+            job.finished_at = Some(Utc::now());
+            match response {
+                Ok(data) => {
+                    job.state = JobState::Completed;
+                    job.result = serde_json::to_value(data).ok();
+                }
+                Err(e) => {
+                    job.state = JobState::Failed;
+                    job.error = Some(e.message().to_string());
+                }
+            }
+        })
+        .await;
+        let job = self.job();
+        info!("Job {} {}", job.id, job.state.as_str());
+        // Items and the request report can be large; webhooks get the outcome
+        self.notifications.webhook(WebhookEvent::JobFinished, &serde_json::json!({
+            "id": job.id,
+            "kind": job.kind,
+            "entity_group_id": job.entity_group_id,
+            "description": job.description,
+            "requested_by": job.requested_by,
+            "state": job.state,
+            "progress_current": job.progress_current,
+            "progress_total": job.progress_total,
+            "error": job.error,
+            "started_at": job.started_at,
+            "finished_at": job.finished_at,
+        }));
+    }
+
+    async fn update(&self, change: impl FnOnce(&mut Job)) {
+        let job = {
+            let mut job = self.job.lock().unwrap();
+            change(&mut job);
+            if job.state != JobState::Queued {
+                job.report = Some(self.report.report());
+            }
+            job.clone()
+        };
+
+        if let Err(e) = self.database.save_job(&job).await {
+            error!("Failed to store progress of job {}: {}", job.id, e);
+        }
+        self.notifications.emit_job_progress(&job);
+    }
+}
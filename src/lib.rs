@@ -1,2 +1,35 @@
 pub mod tb_rust_client;
-pub mod database;
\ No newline at end of file
+pub mod catalog;
+pub mod tag_description;
+pub mod database;
+pub mod scheduler;
+pub mod config;
+pub mod iec104;
+pub mod telemetry_forwarder;
+pub mod deadband;
+pub mod alarms;
+pub mod webhooks;
+pub mod mqtt;
+pub mod file_export;
+pub mod live_values;
+pub mod metrics;
+pub mod read_watchdog;
+pub mod poll_stats;
+pub mod protocol_trace;
+pub mod virtual_tags;
+pub mod passwords;
+pub mod websocket;
+pub mod modbus;
+pub mod simulator;
+pub mod logging;
+pub mod api;
+pub mod csv_parser;
+pub mod reports;
+pub mod notifications;
+pub mod openapi;
+pub mod safe_mode;
+pub mod jobs;
+pub mod http_server;
+pub mod app;
+
+pub use app::AppState;
@@ -0,0 +1,143 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex as StdMutex;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::database::{LogEntry, Quality};
+
+/// Current value of one tag as last read from its device
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TagValue {
+    pub tag_name: String,
+    pub value: f64,
+    pub unit: Option<String>,
+    pub quality: String,
+    /// When the value was read
+    pub timestamp: DateTime<Utc>,
+    /// Milliseconds since the value was read, as of the response
+    pub age_ms: i64,
+    /// The device stopped or lost its connection after the value was read; `quality` is then
+    /// `Stale`
+    pub stale: bool,
+    /// Why the value is stale
+    pub stale_reason: Option<String>,
+}
+
+/// Current values of every tag of a device that has been read since the service started
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DeviceValues {
+    pub device_id: String,
+    pub values: Vec<TagValue>,
+}
+
+struct CachedValue {
+    entry: LogEntry,
+    stale_reason: Option<String>,
+}
+
+/// Last value read of every tag, kept in memory so live views don't query the log.
+///
+/// Values are never dropped when a device stops or errors, only marked stale, so a consumer
+/// can tell an old value from an offline device.
+#[derive(Default)]
+pub struct LastValueCache {
+    devices: StdMutex<HashMap<String, BTreeMap<String, CachedValue>>>,
+}
+
+impl LastValueCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the values of a successful poll. A failed read of one tag keeps its last value
+    /// and timestamp with `Bad` quality; muted tags are left as they were.
+    pub fn update(&self, entries: &[LogEntry]) {
+        let mut devices = self.devices.lock().unwrap();
+        for entry in entries.iter().filter(|entry| entry.quality != "muted") {
+            let tags = devices.entry(entry.device_id.clone()).or_default();
+            match tags.get_mut(&entry.tag_name) {
+                Some(cached) if !entry.has_value() => {
+                    cached.entry.quality = entry.quality.clone();
+                    cached.stale_reason = None;
+                }
+                _ => {
+                    tags.insert(entry.tag_name.clone(), CachedValue { entry: entry.clone(), stale_reason: None });
+                }
+            }
+        }
+    }
+
+    /// Mark a device's values stale, or only those of the given tags
+    pub fn mark_stale(&self, device_id: &str, tag_names: Option<&[String]>, reason: &str) {
+        let mut devices = self.devices.lock().unwrap();
+        let Some(tags) = devices.get_mut(device_id) else {
+            return;
+        };
+        for (tag_name, cached) in tags.iter_mut() {
+            if tag_names.is_none_or(|names| names.contains(tag_name)) {
+                cached.stale_reason = Some(reason.to_string());
+            }
+        }
+    }
+
+    /// Forget a deleted device
+    pub fn remove_device(&self, device_id: &str) {
+        self.devices.lock().unwrap().remove(device_id);
+    }
+
+    /// Value of a tag if its last read succeeded and the device is still reading it
+    pub fn fresh_value(&self, device_id: &str, tag_name: &str) -> Option<f64> {
+        let devices = self.devices.lock().unwrap();
+        let cached = devices.get(device_id)?.get(tag_name)?;
+        (cached.entry.has_value() && cached.stale_reason.is_none()).then_some(cached.entry.value)
+    }
+
+    /// Current values of a device, by tag name; empty if none were read yet
+    pub fn device_values(&self, device_id: &str) -> DeviceValues {
+        let devices = self.devices.lock().unwrap();
+        let now = Utc::now();
+        DeviceValues {
+            device_id: device_id.to_string(),
+            values: devices.get(device_id).map(|tags| Self::tag_values(tags, now)).unwrap_or_default(),
+        }
+    }
+
+    /// Current values of some of a device's tags, by tag name
+    pub fn values_of(&self, device_id: &str, tag_names: &[String]) -> DeviceValues {
+        let mut values = self.device_values(device_id);
+        values.values.retain(|value| tag_names.contains(&value.tag_name));
+        values
+    }
+
+    /// Current values of every device read since the service started, by device id
+    pub fn all_values(&self) -> Vec<DeviceValues> {
+        let devices = self.devices.lock().unwrap();
+        let now = Utc::now();
+        let mut all: Vec<DeviceValues> = devices
+            .iter()
+            .map(|(device_id, tags)| DeviceValues { device_id: device_id.clone(), values: Self::tag_values(tags, now) })
+            .collect();
+        all.sort_by(|a, b| a.device_id.cmp(&b.device_id));
+        all
+    }
+
+    fn tag_values(tags: &BTreeMap<String, CachedValue>, now: DateTime<Utc>) -> Vec<TagValue> {
+        tags.values()
+            .map(|cached| TagValue {
+                tag_name: cached.entry.tag_name.clone(),
+                value: cached.entry.value,
+                unit: cached.entry.unit.clone(),
+                // A value sent again after its device stopped answering is only the last one known
+                quality: match cached.stale_reason {
+                    Some(_) => Quality::Stale.to_string(),
+                    None => cached.entry.quality.clone(),
+                },
+                timestamp: cached.entry.timestamp,
+                age_ms: (now - cached.entry.timestamp).num_milliseconds().max(0),
+                stale: cached.stale_reason.is_some(),
+                stale_reason: cached.stale_reason.clone(),
+            })
+            .collect()
+    }
+}
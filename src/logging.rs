@@ -1,53 +1,338 @@
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, Ordering};
 use std::sync::Arc;
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::{mpsc, oneshot, watch, Mutex, RwLock};
 use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 use anyhow::Result;
 use tracing::{info, warn, error};
 use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-use crate::config::{AppConfig, DeviceConfig, ProtocolConfig};
-use crate::database::{Database, DeviceStatus, DeviceTag, ScheduleGroup};
-use crate::modbus::ModbusClient;
-use crate::iec104::Iec104Client;
+use crate::config::{AppConfig, DataType, DeviceConfig, ProtocolConfig, RegisterRead, RegisterType, Iec104Config, ModbusRtuConfig, ModbusTcpConfig, SerialSource, WebhookEvent};
+use crate::database::{Database, DeviceInstance, DeviceStatus, DeviceTag, LogEntry, Quality, RetentionRun, ScheduleGroup, TagWriteResult};
+use crate::modbus::{decode_serial, ModbusClient};
+use crate::simulator::SimulatedClient;
+use crate::iec104::{Iec104Client, Iec104Diagnostics, Iec104ModeHandle, Iec104ModeSettings, Iec104Server};
+use crate::alarms::AlarmEngine;
+use crate::deadband::DeadbandFilter;
+use crate::file_export::FileExporter;
+use crate::live_values::{DeviceValues, LastValueCache};
+use crate::metrics::Metrics;
+use crate::read_watchdog::{ReadHealth, ReadWatchdog};
+use crate::protocol_trace::{ProtocolTrace, ProtocolTraceSnapshot, ProtocolTraces};
+use crate::poll_stats::{failure_quality, failure_reason, DevicePollStats, PollStats, StatsWindow, REASON_CONNECT_FAILED, SAVE_INTERVAL};
+use crate::notifications::NotificationService;
+use crate::scheduler::{OperationKind, OperationScheduler};
+use crate::telemetry_forwarder::TelemetryForwarder;
+use crate::virtual_tags::VirtualTagEngine;
 
 pub struct LoggingService {
     database: Arc<Database>,
     config: Arc<AppConfig>,
-    device_tasks: Arc<RwLock<HashMap<String, Vec<JoinHandle<()>>>>>, // Device ID -> list of schedule group tasks
+    device_tasks: Arc<RwLock<HashMap<String, Vec<GroupTask>>>>, // Device ID -> list of schedule group tasks
     device_clients: Arc<Mutex<HashMap<String, DeviceClient>>>,
+    iec104_modes: Arc<RwLock<HashMap<String, Arc<Iec104ModeHandle>>>>,
+    device_commands: Arc<RwLock<HashMap<String, mpsc::Sender<DeviceCommand>>>>,
+    schedule_intervals: Arc<RwLock<HashMap<String, watch::Sender<tokio::time::Duration>>>>, // Schedule group ID -> interval its pollers follow
+    notifications: Arc<NotificationService>,
+    telemetry: Arc<TelemetryForwarder>,
+    iec104_server: Arc<Iec104Server>,
+    deadbands: Arc<DeadbandFilter>,
+    alarms: Arc<AlarmEngine>,
+    live_values: Arc<LastValueCache>,
+    read_watchdog: Arc<ReadWatchdog>,
+    poll_stats: Arc<PollStats>,
+    /// Protocol captures switched on per device for troubleshooting
+    traces: Arc<ProtocolTraces>,
+    virtual_tags: Arc<VirtualTagEngine>,
+    /// ThingsBoard device of every started device whose values are forwarded, for virtual tags
+    telemetry_targets: Arc<RwLock<HashMap<String, String>>>,
+    metrics: Arc<Metrics>,
+    /// Also writes logged values to CSV files when `[file_export]` is configured
+    file_export: Option<Arc<FileExporter>>,
+    last_retention_run: Arc<RwLock<Option<RetentionRun>>>,
+    /// Retention runs as a vacuum, so it waits for restores and holds off other admin operations
+    scheduler: OperationScheduler,
+    /// Cancelled once the service shuts down; pollers stop after the cycle they are in
+    shutdown: CancellationToken,
+}
+
+/// Name of a schedule group and the task polling it
+type GroupTask = (String, JoinHandle<()>);
+
+/// Where a device's logged values are forwarded: the forwarder and the ThingsBoard device ID
+type TelemetryTarget = (Arc<TelemetryForwarder>, String);
+
+/// How long an on-demand read or write may wait for the device's client to be free and answer
+const DEVICE_COMMAND_TIMEOUT: tokio::time::Duration = tokio::time::Duration::from_secs(15);
+
+/// How often running devices are checked for reads that stopped
+const READ_WATCHDOG_TICK: tokio::time::Duration = tokio::time::Duration::from_secs(1);
+
+/// How often scheduled virtual tags are checked for being due
+const VIRTUAL_TAG_TICK: tokio::time::Duration = tokio::time::Duration::from_millis(250);
+
+/// A request waiting for one of the device's schedule group tasks to run it between polls
+enum DeviceCommand {
+    WriteTag {
+        tag: Box<DeviceTag>,
+        value: f64,
+        reply: oneshot::Sender<Result<TagWriteResult>>,
+    },
+    Read {
+        read: RegisterRead,
+        reply: oneshot::Sender<Result<(Vec<u16>, f64)>>,
+    },
+}
+
+/// Register words and unscaled value from an on-demand read
+pub struct RegisterReading {
+    pub registers: Vec<u16>,
+    pub raw_value: f64,
+    /// Went through the running poller's connection rather than a short-lived one
+    pub through_poller: bool,
+}
+
+/// Upper bound on a connection test, whatever timeout was asked for
+const CONNECTION_TEST_MAX_TIMEOUT: tokio::time::Duration = tokio::time::Duration::from_secs(10);
+
+/// Outcome of trying a protocol config before the device is saved
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ConnectionTestResult {
+    /// The device answered
+    pub success: bool,
+    /// Time taken by the whole test
+    pub latency_ms: u64,
+    /// What was tried and how the device responded
+    pub message: String,
+}
+
+/// First wait before retrying a device that failed to start at boot, doubled after each failure
+const AUTO_START_RETRY_BASE: tokio::time::Duration = tokio::time::Duration::from_secs(5);
+const AUTO_START_RETRY_MAX: tokio::time::Duration = tokio::time::Duration::from_secs(300);
+
+/// First wait before reconnecting to a device, doubled after each failed attempt
+const RECONNECT_BACKOFF_BASE: tokio::time::Duration = tokio::time::Duration::from_secs(1);
+const RECONNECT_BACKOFF_MAX: tokio::time::Duration = tokio::time::Duration::from_secs(60);
+
+/// Devices started, stopped, enabled or disabled at once by a bulk action
+const BULK_ACTION_CONCURRENCY: usize = 8;
+
+/// How long a bulk action waits for one device before reporting it as failed
+const BULK_ACTION_TIMEOUT: tokio::time::Duration = tokio::time::Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceAction {
+    Start,
+    Stop,
+    /// Mark the device enabled and start it
+    Enable,
+    /// Stop the device and mark it disabled
+    Disable,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceActionOutcome {
+    Done,
+    /// The device was already in the requested state
+    Skipped,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DeviceActionResult {
+    pub device_id: String,
+    pub outcome: DeviceActionOutcome,
+    /// Why the device was skipped or failed
+    pub detail: Option<String>,
+}
+
+/// Tags without a schedule group are polled at the device's own interval under this id
+const DEVICE_INTERVAL_GROUP: &str = "device";
+
+/// A schedule group as one of a device's pollers runs it; the interval can change while it runs
+struct PollGroup {
+    group: ScheduleGroup,
+    interval: watch::Receiver<tokio::time::Duration>,
+}
+
+/// Per-device handles shared by every schedule group task of the device
+#[derive(Clone)]
+struct DeviceRuntime {
+    iec104_mode: Option<Arc<Iec104ModeHandle>>,
+    telemetry_target: Option<TelemetryTarget>,
+    /// Serves logged values of mapped tags to SCADA masters
+    iec104_server: Arc<Iec104Server>,
+    deadbands: Arc<DeadbandFilter>,
+    alarms: Arc<AlarmEngine>,
+    /// Last value read of every tag, for live views
+    live_values: Arc<LastValueCache>,
+    read_watchdog: Arc<ReadWatchdog>,
+    poll_stats: Arc<PollStats>,
+    trace: Arc<ProtocolTrace>,
+    virtual_tags: Arc<VirtualTagEngine>,
+    metrics: Arc<Metrics>,
+    file_export: Option<Arc<FileExporter>>,
+    commands: Arc<Mutex<mpsc::Receiver<DeviceCommand>>>,
+    /// Successful connects since the device was started, counting reconnects
+    connections: Arc<AtomicI64>,
+    shutdown: CancellationToken,
 }
 
 enum DeviceClient {
     Modbus(ModbusClient),
     Iec104(Iec104Client),
+    Simulated(SimulatedClient),
 }
 
 impl LoggingService {
-    pub async fn new(database: Arc<Database>, config: Arc<AppConfig>) -> Result<Self> {
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new(
+        database: Arc<Database>,
+        config: Arc<AppConfig>,
+        notifications: Arc<NotificationService>,
+        telemetry: Arc<TelemetryForwarder>,
+        iec104_server: Arc<Iec104Server>,
+        metrics: Arc<Metrics>,
+        file_export: Option<Arc<FileExporter>>,
+        scheduler: OperationScheduler,
+    ) -> Result<Self> {
         let service = Self {
             database,
             config: config.clone(),
             device_tasks: Arc::new(RwLock::new(HashMap::new())),
             device_clients: Arc::new(Mutex::new(HashMap::new())),
+            iec104_modes: Arc::new(RwLock::new(HashMap::new())),
+            device_commands: Arc::new(RwLock::new(HashMap::new())),
+            schedule_intervals: Arc::new(RwLock::new(HashMap::new())),
+            notifications,
+            telemetry,
+            iec104_server,
+            deadbands: Arc::new(DeadbandFilter::new(std::time::Duration::from_secs(
+                config.database.deadband_heartbeat_minutes.max(1) * 60,
+            ))),
+            alarms: Arc::new(AlarmEngine::new()),
+            live_values: Arc::new(LastValueCache::new()),
+            read_watchdog: Arc::new(ReadWatchdog::new()),
+            poll_stats: Arc::new(PollStats::new()),
+            traces: Arc::new(ProtocolTraces::new()),
+            virtual_tags: Arc::new(VirtualTagEngine::new()),
+            telemetry_targets: Arc::new(RwLock::new(HashMap::new())),
+            metrics,
+            file_export,
+            last_retention_run: Arc::new(RwLock::new(None)),
+            scheduler,
+            shutdown: CancellationToken::new(),
         };
 
-        // Start enabled devices
-        for device in &config.devices {
-            if device.enabled {
+        if let Err(e) = service.alarms.load(&service.database).await {
+            warn!("Failed to load alarm rules: {}", e);
+        }
+        if let Err(e) = service.virtual_tags.load(&service.database).await {
+            warn!("Failed to load virtual tags: {}", e);
+        }
+
+        // Start cleanup tasks
+        service.start_retention_task();
+        service.start_read_watchdog_task();
+        service.start_poll_stats_task();
+        service.start_virtual_tag_task();
+        service.start_cleanup_task().await;
+
+        Ok(service)
+    }
+
+    /// Start every enabled device in the background, `auto_start_stagger_ms` apart. A device
+    /// that fails to start has the error recorded as its status and is retried with backoff.
+    pub fn start_enabled_devices(self: &Arc<Self>) {
+        if !self.config.server.auto_start {
+            info!("Automatic device start is disabled");
+            return;
+        }
+
+        let service = self.clone();
+        tokio::spawn(async move {
+            let devices = match service.database.get_devices().await {
+                Ok(devices) => devices,
+                Err(e) => {
+                    error!("Failed to load devices to start: {}", e);
+                    return;
+                }
+            };
+
+            let stagger = tokio::time::Duration::from_millis(service.config.server.auto_start_stagger_ms);
+            let enabled: Vec<_> = devices.into_iter().filter(|device| device.enabled).collect();
+            info!("Starting {} enabled devices", enabled.len());
+            for (i, device) in enabled.into_iter().enumerate() {
+                if i > 0 {
+                    tokio::time::sleep(stagger).await;
+                }
+                if service.shutdown.is_cancelled() {
+                    return;
+                }
                 if let Err(e) = service.start_device(&device.id).await {
                     error!("Failed to start device {}: {}", device.id, e);
+                    service.record_start_failure(&device.id, &e).await;
+                    service.clone().retry_start(device.id);
                 }
             }
-        }
+        });
+    }
 
-        // Start cleanup task
-        service.start_cleanup_task().await;
+    fn retry_start(self: Arc<Self>, device_id: String) {
+        tokio::spawn(async move {
+            let mut delay = AUTO_START_RETRY_BASE;
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {},
+                    _ = self.shutdown.cancelled() => return,
+                }
 
-        Ok(service)
+                // Someone started, disabled or removed the device in the meantime
+                match self.database.get_device(&device_id).await {
+                    Ok(Some(device)) if device.enabled && !self.is_device_running(&device_id).await => {}
+                    Ok(_) => return,
+                    Err(e) => warn!("Failed to check device {} before retrying its start: {}", device_id, e),
+                }
+
+                match self.start_device(&device_id).await {
+                    Ok(()) => {
+                        info!("Device {} started after retrying", device_id);
+                        return;
+                    }
+                    Err(e) => {
+                        delay = (delay * 2).min(AUTO_START_RETRY_MAX);
+                        warn!("Device {} still fails to start, retrying in {:?}: {}", device_id, delay, e);
+                        self.record_start_failure(&device_id, &e).await;
+                    }
+                }
+            }
+        });
+    }
+
+    async fn record_start_failure(&self, device_id: &str, error: &anyhow::Error) {
+        let status = DeviceStatus {
+            device_id: device_id.to_string(),
+            status: "Error".to_string(),
+            last_update: Utc::now(),
+            error_message: Some(format!("Failed to start: {}", error)),
+            connection_count: 0,
+        };
+        if let Err(e) = self.database.update_device_status(&status).await {
+            error!("Failed to record start failure of device {}: {}", device_id, e);
+        }
+        self.notifications.emit_device_status(&status);
     }
 
     pub async fn start_device(&self, device_id: &str) -> Result<()> {
+        if self.shutdown.is_cancelled() {
+            anyhow::bail!("The service is shutting down");
+        }
+
         // Get device configuration from database
         let device_instance = self.database.get_device(device_id).await?
             .ok_or_else(|| anyhow::anyhow!("Device not found: {}", device_id))?;
@@ -57,21 +342,7 @@ impl LoggingService {
             return Ok(());
         }
 
-        // Parse protocol config from JSON
-        let protocol_config: crate::config::ProtocolConfig = serde_json::from_str(&device_instance.protocol_config)
-            .map_err(|e| anyhow::anyhow!("Failed to parse protocol config for device {}: {}", device_id, e))?;
-
-        // Create device config from database instance
-        let device_config = crate::config::DeviceConfig {
-            id: device_instance.id.clone(),
-            name: device_instance.name.clone(),
-            enabled: device_instance.enabled,
-            protocol: protocol_config,
-            polling_interval_ms: device_instance.polling_interval_ms as u64,
-            timeout_ms: device_instance.timeout_ms as u64,
-            retry_count: device_instance.retry_count,
-            tags: Vec::new(), // We'll get tags from database separately
-        };
+        let device_config = Self::device_config(&device_instance)?;
 
         // Stop existing tasks if running
         self.stop_device(device_id).await?;
@@ -90,19 +361,23 @@ impl LoggingService {
                 continue;
             }
             
-            let schedule_group_id = tag.schedule_group_id
-                .clone()
-                .unwrap_or_else(|| "medium_freq".to_string()); // Default fallback
-                
-            if let Some(schedule_group) = schedule_groups.iter().find(|sg| sg.id == schedule_group_id) {
-                if schedule_group.enabled {
-                    schedule_group_tags
-                        .entry(schedule_group_id.clone())
-                        .or_insert_with(|| (schedule_group.clone(), Vec::new()))
-                        .1
-                        .push(tag);
-                }
-            }
+            let schedule_group = match &tag.schedule_group_id {
+                Some(id) => match schedule_groups.iter().find(|sg| &sg.id == id) {
+                    Some(schedule_group) if !schedule_group.enabled => continue,
+                    Some(schedule_group) => schedule_group.clone(),
+                    None => {
+                        warn!("Tag {} of device {} is in unknown schedule group {}, using the device interval", tag.name, device_id, id);
+                        Self::device_interval_group(&device_instance)
+                    }
+                },
+                None => Self::device_interval_group(&device_instance),
+            };
+
+            schedule_group_tags
+                .entry(schedule_group.id.clone())
+                .or_insert_with(|| (schedule_group, Vec::new()))
+                .1
+                .push(tag);
         }
 
         if schedule_group_tags.is_empty() {
@@ -113,31 +388,91 @@ impl LoggingService {
         let database = self.database.clone();
         let device_clients = self.device_clients.clone();
         let device_config_clone = device_config.clone();
+        let notifications = self.notifications.clone();
+
+        // Forward to ThingsBoard only when enabled globally and for this device, and the device is linked
+        let telemetry_target = match &device_instance.tb_device_id {
+            Some(tb_device_id) if self.telemetry.is_enabled()
+                && self.database.get_device_telemetry_forwarding(device_id).await?.unwrap_or(false) =>
+            {
+                Some((self.telemetry.clone(), tb_device_id.clone()))
+            },
+            _ => None,
+        };
+
+        // IEC 104 mode is shared by every schedule group so it can be switched at runtime
+        let iec104_mode = Iec104ModeSettings::from_protocol(&device_config.protocol).map(Iec104ModeHandle::new);
+        if let Some(handle) = &iec104_mode {
+            self.iec104_modes.write().await.insert(device_id.to_string(), handle.clone());
+        }
+
+        // Deadbands continue from the last logged values rather than logging every tag again
+        if let Err(e) = self.deadbands.seed(&self.database, device_id).await {
+            warn!("Failed to load last logged values of device {}: {}", device_id, e);
+        }
+        self.alarms.watch_device(device_id, device_instance.model_id.clone());
+        self.virtual_tags.watch_device(device_id, device_instance.tb_group_id.clone());
+        match &telemetry_target {
+            Some((_, tb_device_id)) => self.telemetry_targets.write().await.insert(device_id.to_string(), tb_device_id.clone()),
+            None => self.telemetry_targets.write().await.remove(device_id),
+        };
+
+        // On-demand reads and writes go through whichever schedule group task is between polls
+        let (command_sender, command_receiver) = mpsc::channel(8);
+        self.device_commands.write().await.insert(device_id.to_string(), command_sender);
+        let commands = Arc::new(Mutex::new(command_receiver));
+        let connections = Arc::new(AtomicI64::new(0));
 
         let mut tasks = Vec::new();
+        let mut intervals = Vec::new();
 
         // Create a task for each schedule group that has tags
         for (_schedule_group_id, (schedule_group, tags)) in schedule_group_tags {
+            let poll_group = PollGroup {
+                interval: self.schedule_interval(&schedule_group).await,
+                group: schedule_group,
+            };
+            intervals.push(poll_group.interval.clone());
             let database_clone = database.clone();
             let device_clients_clone = device_clients.clone();
             let device_config_task = device_config_clone.clone();
-            let device_id_clone = device_id.to_string();
+            let notifications_clone = notifications.clone();
+            let runtime = DeviceRuntime {
+                iec104_mode: iec104_mode.clone(),
+                telemetry_target: telemetry_target.clone(),
+                iec104_server: self.iec104_server.clone(),
+                deadbands: self.deadbands.clone(),
+                alarms: self.alarms.clone(),
+                live_values: self.live_values.clone(),
+                read_watchdog: self.read_watchdog.clone(),
+                poll_stats: self.poll_stats.clone(),
+                trace: self.traces.device(device_id),
+                virtual_tags: self.virtual_tags.clone(),
+                metrics: self.metrics.clone(),
+                file_export: self.file_export.clone(),
+                commands: commands.clone(),
+                connections: connections.clone(),
+                shutdown: self.shutdown.clone(),
+            };
 
+            let group_name = poll_group.group.name.clone();
             let task = tokio::spawn(async move {
                 Self::schedule_group_loop(
-                    device_id_clone,
                     device_config_task,
-                    schedule_group,
+                    poll_group,
                     tags,
                     database_clone,
                     device_clients_clone,
+                    notifications_clone,
+                    runtime,
                 ).await;
             });
 
-            tasks.push(task);
+            tasks.push((group_name, task));
         }
 
         self.device_tasks.write().await.insert(device_id.to_string(), tasks);
+        self.read_watchdog.watch(device_id, intervals, Utc::now());
 
         // Update device status
         let status = DeviceStatus {
@@ -148,33 +483,174 @@ impl LoggingService {
             connection_count: 0,
         };
         self.database.update_device_status(&status).await?;
+        self.notifications.emit_device_status(&status);
 
         Ok(())
     }
 
+    /// Stand-in group for tags without a schedule group
+    fn device_interval_group(device_instance: &DeviceInstance) -> ScheduleGroup {
+        ScheduleGroup {
+            id: DEVICE_INTERVAL_GROUP.to_string(),
+            name: "Device interval".to_string(),
+            polling_interval_ms: device_instance.polling_interval_ms.max(1),
+            description: None,
+            enabled: true,
+            created_at: device_instance.created_at,
+            updated_at: device_instance.updated_at,
+        }
+    }
+
+    /// Interval a poller of this group follows, shared by every device polling the group
+    async fn schedule_interval(&self, schedule_group: &ScheduleGroup) -> watch::Receiver<tokio::time::Duration> {
+        let interval = tokio::time::Duration::from_millis(schedule_group.polling_interval_ms.max(1) as u64);
+        if schedule_group.id == DEVICE_INTERVAL_GROUP {
+            return watch::channel(interval).1;
+        }
+
+        let mut intervals = self.schedule_intervals.write().await;
+        let sender = intervals
+            .entry(schedule_group.id.clone())
+            .or_insert_with(|| watch::channel(interval).0);
+        sender.send_if_modified(|current| std::mem::replace(current, interval) != interval);
+        sender.subscribe()
+    }
+
+    /// Apply an updated schedule group to running devices. A new interval is picked up by their
+    /// pollers without a restart; enabling or disabling the group restarts the devices using it.
+    ///
+    /// Returns the running devices with tags in the group, which follow the change from now on.
+    pub async fn reload_schedule_group(&self, previous: &ScheduleGroup, schedule_group: &ScheduleGroup) -> Vec<String> {
+        let interval = tokio::time::Duration::from_millis(schedule_group.polling_interval_ms.max(1) as u64);
+        if let Some(sender) = self.schedule_intervals.read().await.get(&schedule_group.id) {
+            if sender.send_if_modified(|current| std::mem::replace(current, interval) != interval) {
+                info!("Schedule group {} now polls every {:?}", schedule_group.id, interval);
+            }
+        }
+        if let Err(e) = self.virtual_tags.load(&self.database).await {
+            error!("Failed to reload virtual tags after schedule group {} changed: {}", schedule_group.id, e);
+        }
+
+        let mut running: Vec<String> = self.device_tasks.read().await.keys().cloned().collect();
+        running.sort();
+        let mut reloaded = Vec::new();
+        for device_id in running {
+            let uses_group = match self.database.get_device_tags(&device_id).await {
+                Ok(tags) => tags.iter().any(|tag| tag.schedule_group_id.as_deref() == Some(schedule_group.id.as_str())),
+                Err(e) => {
+                    error!("Failed to get tags for device {}: {}", device_id, e);
+                    false
+                }
+            };
+            if !uses_group {
+                continue;
+            }
+            if previous.enabled != schedule_group.enabled {
+                if let Err(e) = self.start_device(&device_id).await {
+                    error!("Failed to restart device {} after schedule group {} changed: {}", device_id, schedule_group.id, e);
+                    continue;
+                }
+            }
+            reloaded.push(device_id);
+        }
+        reloaded
+    }
+
+    /// Restart a running device so it polls with its saved configuration and tags, or stop it
+    /// if it was disabled; every other device keeps polling. Returns whether the device was running.
+    pub async fn reload_device(&self, device_id: &str) -> Result<bool> {
+        if !self.is_device_running(device_id).await {
+            return Ok(false);
+        }
+
+        info!("Reloading device {} with its saved configuration", device_id);
+        match self.database.get_device(device_id).await? {
+            Some(device) if device.enabled => self.start_device(device_id).await?,
+            _ => self.stop_device(device_id).await?,
+        }
+        Ok(true)
+    }
+
+    /// Whether a running device has to be restarted to poll with `after` instead of `before`.
+    /// The name and ThingsBoard links aren't used to poll, and an IEC 104 mode change is
+    /// applied to the running driver through [`Self::set_iec104_mode`].
+    pub fn needs_restart(before: &DeviceInstance, after: &DeviceInstance) -> bool {
+        fn connection(device: &DeviceInstance) -> Option<serde_json::Value> {
+            let mut protocol = device.protocol().ok()?;
+            if let ProtocolConfig::Iec104(config) = &mut protocol {
+                config.mode = Default::default();
+                config.interrogation_interval_ms = 0;
+            }
+            serde_json::to_value(protocol).ok()
+        }
+
+        before.enabled != after.enabled
+            || before.polling_interval_ms != after.polling_interval_ms
+            || before.timeout_ms != after.timeout_ms
+            || before.retry_count != after.retry_count
+            || before.strict_types != after.strict_types
+            || connection(before).is_none()
+            || connection(before) != connection(after)
+    }
+
+    /// Create device config from database instance
+    fn device_config(device_instance: &DeviceInstance) -> Result<DeviceConfig> {
+        Ok(DeviceConfig {
+            id: device_instance.id.clone(),
+            name: device_instance.name.clone(),
+            enabled: device_instance.enabled,
+            protocol: device_instance.protocol()?,
+            polling_interval_ms: device_instance.polling_interval_ms as u64,
+            timeout_ms: device_instance.timeout_ms as u64,
+            retry_count: device_instance.retry_count,
+            tags: Vec::new(), // We'll get tags from database separately
+            strict_types: device_instance.strict_types,
+        })
+    }
+
+    fn new_client(device_config: &DeviceConfig, iec104_mode: Option<Arc<Iec104ModeHandle>>, trace: Option<Arc<ProtocolTrace>>) -> DeviceClient {
+        match &device_config.protocol {
+            ProtocolConfig::ModbusTcp(_) | ProtocolConfig::ModbusRtu(_) => {
+                let client = ModbusClient::new(device_config.clone());
+                DeviceClient::Modbus(match trace {
+                    Some(trace) => client.with_trace(trace),
+                    None => client,
+                })
+            },
+            ProtocolConfig::Iec104(_) => {
+                let mode = iec104_mode.unwrap_or_else(|| {
+                    Iec104ModeHandle::new(Iec104ModeSettings::from_protocol(&device_config.protocol).unwrap())
+                });
+                let client = Iec104Client::new(device_config.clone(), mode);
+                DeviceClient::Iec104(match trace {
+                    Some(trace) => client.with_trace(trace),
+                    None => client,
+                })
+            },
+            ProtocolConfig::Simulated(_) => DeviceClient::Simulated(SimulatedClient::new(device_config.clone())),
+        }
+    }
+
     pub async fn stop_device(&self, device_id: &str) -> Result<()> {
         info!("Stopping device: {}", device_id);
 
         // Stop all tasks for this device
         if let Some(tasks) = self.device_tasks.write().await.remove(device_id) {
-            for task in tasks {
+            for (_, task) in tasks {
                 task.abort();
             }
         }
+        self.iec104_modes.write().await.remove(device_id);
+        self.device_commands.write().await.remove(device_id);
+        self.read_watchdog.unwatch(device_id);
+        self.telemetry_targets.write().await.remove(device_id);
+        self.live_values.mark_stale(device_id, None, "Device stopped");
+        self.notifications.emit_tag_update(&self.live_values.device_values(device_id));
 
         // Disconnect the client
         let mut clients = self.device_clients.lock().await;
         if let Some(mut client) = clients.remove(device_id) {
-            match &mut client {
-                DeviceClient::Modbus(modbus) => {
-                    modbus.disconnect().await;
-                },
-                DeviceClient::Iec104(iec104) => {
-                    if let Err(e) = iec104.disconnect().await {
-                        warn!("Error disconnecting IEC104 client: {}", e);
-                    }
-                },
-            }
+            Self::disconnect(&mut client).await;
         }
 
         // Update device status
@@ -186,20 +662,97 @@ impl LoggingService {
             connection_count: 0,
         };
         self.database.update_device_status(&status).await?;
+        self.notifications.emit_device_status(&status);
 
         Ok(())
     }
 
+    async fn disconnect(client: &mut DeviceClient) {
+        match client {
+            DeviceClient::Modbus(modbus) => {
+                modbus.disconnect().await;
+            },
+            DeviceClient::Iec104(iec104) => {
+                if let Err(e) = iec104.disconnect().await {
+                    warn!("Error disconnecting IEC104 client: {}", e);
+                }
+            },
+            DeviceClient::Simulated(simulated) => simulated.disconnect().await,
+        }
+    }
+
+    /// Stop polling and forwarding for good. Pollers and telemetry pushes finish the cycle
+    /// they are in, counted poll outcomes are saved, device connections are closed and every
+    /// device is marked stopped. Tasks still running after `timeout` are logged and aborted;
+    /// returns whether everything stopped in time.
+    pub async fn shutdown(&self, timeout: tokio::time::Duration) -> bool {
+        info!("Stopping all devices");
+        let deadline = tokio::time::Instant::now() + timeout;
+        self.shutdown.cancel();
+
+        let mut tasks: Vec<(String, JoinHandle<()>)> = Vec::new();
+        for (device_id, device_tasks) in self.device_tasks.write().await.drain() {
+            tasks.extend(device_tasks.into_iter().map(|(group_name, task)| {
+                (format!("schedule group '{}' of device {}", group_name, device_id), task)
+            }));
+        }
+        tasks.extend(self.telemetry.stop().into_iter().map(|(device_id, task)| {
+            (format!("telemetry forwarding of device {}", device_id), task)
+        }));
+
+        let mut stopped = true;
+        for (name, mut task) in tasks {
+            if tokio::time::timeout_at(deadline, &mut task).await.is_err() {
+                error!("Task for {} did not stop within {}s, aborting it", name, timeout.as_secs());
+                task.abort();
+                stopped = false;
+            }
+        }
+
+        // Pollers close their own connections; these are the ones that were between attempts
+        let clients: Vec<(String, DeviceClient)> = self.device_clients.lock().await.drain().collect();
+        for (device_id, mut client) in clients {
+            if tokio::time::timeout_at(deadline, Self::disconnect(&mut client)).await.is_err() {
+                error!("Closing the connection to device {} did not finish within {}s", device_id, timeout.as_secs());
+                stopped = false;
+            }
+        }
+
+        if let Err(e) = self.poll_stats.save(&self.database, Utc::now()).await {
+            error!("Failed to save polling statistics: {}", e);
+        }
+        match self.database.get_devices().await {
+            Ok(devices) => {
+                for device in devices {
+                    Self::publish_status(&self.database, &self.notifications, DeviceStatus {
+                        device_id: device.id,
+                        status: "Stopped".to_string(),
+                        last_update: Utc::now(),
+                        error_message: None,
+                        connection_count: 0,
+                    }).await;
+                }
+            },
+            Err(e) => error!("Failed to mark devices stopped: {}", e),
+        }
+
+        stopped
+    }
+
     async fn schedule_group_loop(
-        device_id: String,
         device_config: DeviceConfig,
-        schedule_group: ScheduleGroup,
+        mut poll_group: PollGroup,
         tags: Vec<DeviceTag>,
         database: Arc<Database>,
         device_clients: Arc<Mutex<HashMap<String, DeviceClient>>>,
+        notifications: Arc<NotificationService>,
+        runtime: DeviceRuntime,
     ) {
-        let mut connection_count = 0;
-        let polling_interval = tokio::time::Duration::from_millis(schedule_group.polling_interval_ms as u64);
+        let device_id = device_config.id.clone();
+        let mut connect_failures = 0;
+        let mut backoff = RECONNECT_BACKOFF_BASE;
+        let schedule_group = poll_group.group.clone();
+        let mut first_attempt = true;
 
         info!(
             "Starting schedule group '{}' for device '{}' with {} tags, polling every {}ms",
@@ -207,17 +760,14 @@ impl LoggingService {
         );
 
         loop {
+            if !std::mem::take(&mut first_attempt) {
+                runtime.metrics.record_reconnect(&device_id);
+            }
+
             // Create client if not exists (shared across all schedule groups for a device)
             let mut clients = device_clients.lock().await;
             if !clients.contains_key(&device_id) {
-                let client = match &device_config.protocol {
-                    ProtocolConfig::ModbusTcp { .. } | ProtocolConfig::ModbusRtu { .. } => {
-                        DeviceClient::Modbus(ModbusClient::new(device_config.clone()))
-                    },
-                    ProtocolConfig::Iec104 { .. } => {
-                        DeviceClient::Iec104(Iec104Client::new(device_config.clone()))
-                    },
-                };
+                let client = Self::new_client(&device_config, runtime.iec104_mode.clone(), Some(runtime.trace.clone()));
                 clients.insert(device_id.clone(), client);
             }
 
@@ -228,50 +778,79 @@ impl LoggingService {
             let connect_result = match &mut client {
                 DeviceClient::Modbus(modbus) => modbus.connect().await,
                 DeviceClient::Iec104(iec104) => iec104.connect().await,
+                DeviceClient::Simulated(simulated) => simulated.connect().await,
             };
 
             match connect_result {
                 Ok(()) => {
-                    connection_count += 1;
-                    
-                    // Update status to connected (only on first connection)
-                    if connection_count == 1 {
-                        let status = DeviceStatus {
-                            device_id: device_id.clone(),
-                            status: "Connected".to_string(),
-                            last_update: Utc::now(),
-                            error_message: None,
-                            connection_count,
-                        };
-                        if let Err(e) = database.update_device_status(&status).await {
-                            error!("Failed to update device status: {}", e);
-                        }
+                    let connection_count = runtime.connections.fetch_add(1, Ordering::SeqCst) + 1;
+                    if connect_failures > 0 {
+                        info!("Reconnected to device {} after {} failed attempts", device_id, connect_failures);
                     }
+                    connect_failures = 0;
+                    backoff = RECONNECT_BACKOFF_BASE;
+                    Self::publish_status(&database, &notifications, DeviceStatus {
+                        device_id: device_id.clone(),
+                        status: "Connected".to_string(),
+                        last_update: Utc::now(),
+                        error_message: None,
+                        connection_count,
+                    }).await;
 
-                    // Start polling loop for this schedule group
-                    Self::schedule_group_polling_loop(
+                    // Poll until the connection is lost or keeps failing
+                    let lost = Self::schedule_group_polling_loop(
                         &mut client,
                         &device_config,
-                        &schedule_group,
+                        &mut poll_group,
                         &tags,
                         &database,
-                        polling_interval,
+                        &notifications,
+                        &runtime,
                     ).await;
+                    if runtime.shutdown.is_cancelled() {
+                        Self::disconnect(&mut client).await;
+                        info!("Stopped schedule group {} of device {}", schedule_group.name, device_id);
+                        return;
+                    }
+                    warn!(
+                        "Reconnecting to device {} for schedule group {}: {}",
+                        device_id, schedule_group.name, lost
+                    );
+                    Self::mark_values_stale(&notifications, &runtime, &device_id, &tags, &lost.to_string());
+                    Self::publish_status(&database, &notifications, DeviceStatus {
+                        device_id: device_id.clone(),
+                        status: "Reconnecting".to_string(),
+                        last_update: Utc::now(),
+                        error_message: Some(lost.to_string()),
+                        connection_count,
+                    }).await;
                 },
                 Err(e) => {
                     error!("Failed to connect to device {} for schedule group {}: {}", 
                            device_id, schedule_group.name, e);
-                    
-                    // Update status to error
-                    let status = DeviceStatus {
+                    connect_failures += 1;
+                    runtime.poll_stats.record_failure(&device_id, &schedule_group.id, REASON_CONNECT_FAILED, &e.to_string(), Utc::now());
+
+                    // Marked as an error once the device's retries are used up, but never given up on
+                    let failed = connect_failures >= device_config.retry_count.max(1);
+                    Self::mark_values_stale(&notifications, &runtime, &device_id, &tags, &e.to_string());
+                    Self::publish_status(&database, &notifications, DeviceStatus {
                         device_id: device_id.clone(),
-                        status: "Error".to_string(),
+                        status: if failed { "Error" } else { "Reconnecting" }.to_string(),
                         last_update: Utc::now(),
                         error_message: Some(e.to_string()),
-                        connection_count,
-                    };
-                    if let Err(e) = database.update_device_status(&status).await {
-                        error!("Failed to update device status: {}", e);
+                        connection_count: runtime.connections.load(Ordering::SeqCst),
+                    }).await;
+
+                    // Notify once per failure streak rather than on every retry
+                    if connect_failures == device_config.retry_count.max(1) {
+                        notifications.broadcast(
+                            "device_connection_failed",
+                            "error",
+                            format!("Device {} is unreachable", device_config.name),
+                            format!("Failed to connect for schedule group {}: {}", schedule_group.name, e),
+                            Some(("device", &device_id)),
+                        ).await;
                     }
                 }
             }
@@ -279,19 +858,112 @@ impl LoggingService {
             // Put client back
             device_clients.lock().await.insert(device_id.clone(), client);
 
-            // Wait before retry (use longer interval for failed connections)
-            tokio::time::sleep(tokio::time::Duration::from_millis(5000)).await;
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => {},
+                _ = runtime.shutdown.cancelled() => return,
+            }
+            if connect_failures > 0 {
+                backoff = (backoff * 2).min(RECONNECT_BACKOFF_MAX);
+            }
         }
     }
 
+    /// Record a device's status and push it to connected UIs if it changed
+    async fn publish_status(database: &Database, notifications: &NotificationService, status: DeviceStatus) {
+        if let Err(e) = database.update_device_status(&status).await {
+            error!("Failed to update device status: {}", e);
+        }
+        notifications.emit_device_status(&status);
+    }
+
+    /// Mark the values of a schedule group's tags stale once its connection is lost
+    fn mark_values_stale(notifications: &NotificationService, runtime: &DeviceRuntime, device_id: &str, tags: &[DeviceTag], reason: &str) {
+        let tag_names: Vec<String> = tags.iter().map(|tag| tag.name.clone()).collect();
+        runtime.live_values.mark_stale(device_id, Some(&tag_names), reason);
+        notifications.emit_tag_update(&runtime.live_values.values_of(device_id, &tag_names));
+    }
+
+    /// Log, forward and publish the values of a poll
+    async fn record_values(
+        database: &Database,
+        notifications: &NotificationService,
+        runtime: &DeviceRuntime,
+        device_id: &str,
+        tags: &[DeviceTag],
+        tag_names: &[String],
+        log_entries: &[LogEntry],
+    ) {
+        // The whole poll is written in one transaction, without values inside their tag's deadband
+        let to_log = runtime.deadbands.filter(log_entries, tags);
+        match database.insert_log_entries(&to_log).await {
+            Ok(written) => {
+                runtime.metrics.record_log_entries_written(device_id, written);
+                if written < to_log.len() {
+                    warn!("Logged {} of {} values from device '{}'", written, to_log.len(), device_id);
+                }
+            },
+            Err(e) => error!("Failed to insert log entries for device '{}': {}", device_id, e),
+        }
+        if let Some(file_export) = &runtime.file_export {
+            file_export.export(&to_log);
+        }
+
+        if let Some((telemetry, tb_device_id)) = &runtime.telemetry_target {
+            if let Err(e) = telemetry.enqueue(tb_device_id, log_entries).await {
+                error!("Failed to queue telemetry for device '{}': {}", device_id, e);
+            }
+        }
+        runtime.iec104_server.publish(log_entries);
+        notifications.publish_values(log_entries);
+
+        // Alarms see every value read, including those inside a deadband
+        match runtime.alarms.process(database, log_entries).await {
+            Ok(events) => {
+                for event in &events {
+                    notifications.alarm(event).await;
+                }
+            },
+            Err(e) => error!("Failed to record alarms of device '{}': {}", device_id, e),
+        }
+
+        // REST and Socket.IO live views both read the cache, so they can't disagree
+        runtime.live_values.update(log_entries);
+        notifications.emit_tag_update(&runtime.live_values.values_of(device_id, tag_names));
+        runtime.virtual_tags.inputs_updated(log_entries);
+    }
+
+    /// A `CommFail` entry for each enabled tag of a poll that timed out; muted tags are left out
+    async fn comm_fail_entries(database: &Database, device_id: &str, tags: &[DeviceTag]) -> Vec<LogEntry> {
+        let muted_tags = database.get_muted_tag_names(device_id).await.unwrap_or_else(|e| {
+            error!("Failed to load tag mutes for device {}: {}", device_id, e);
+            Default::default()
+        });
+        let timestamp = Utc::now();
+        tags.iter()
+            .filter(|tag| tag.enabled && !muted_tags.contains(&tag.name))
+            .map(|tag| LogEntry {
+                id: None,
+                device_id: device_id.to_string(),
+                tag_name: tag.name.clone(),
+                value: 0.0,
+                quality: Quality::CommFail.to_string(),
+                timestamp,
+                unit: tag.unit.clone(),
+            })
+            .collect()
+    }
+
     async fn schedule_group_polling_loop(
         client: &mut DeviceClient,
         device_config: &DeviceConfig,
-        schedule_group: &ScheduleGroup,
+        poll_group: &mut PollGroup,
         tags: &[DeviceTag],
         database: &Database,
-        polling_interval: tokio::time::Duration,
-    ) {
+        notifications: &NotificationService,
+        runtime: &DeviceRuntime,
+    ) -> anyhow::Error {
+        let schedule_group = &poll_group.group;
+        let tag_names: Vec<String> = tags.iter().map(|tag| tag.name.clone()).collect();
         let mut retry_count = 0;
 
         info!(
@@ -300,9 +972,11 @@ impl LoggingService {
         );
 
         loop {
+            let poll_started = tokio::time::Instant::now();
             let result = match client {
                 DeviceClient::Modbus(modbus) => modbus.read_specific_tags(database, tags).await,
                 DeviceClient::Iec104(iec104) => iec104.read_specific_tags(database, tags).await,
+                DeviceClient::Simulated(simulated) => simulated.read_specific_tags(database, tags).await,
             };
 
             match result {
@@ -312,18 +986,24 @@ impl LoggingService {
                         log_entries.len(), device_config.id, schedule_group.name
                     );
                     retry_count = 0;
+                    runtime.metrics.record_poll_success(&device_config.id, poll_started.elapsed());
+                    runtime.poll_stats.record_success(&device_config.id, &schedule_group.id, poll_started.elapsed(), Utc::now());
+                    Self::record_values(database, notifications, runtime, &device_config.id, tags, &tag_names, &log_entries).await;
+
+                    // A device the watchdog reported offline is back
+                    let recovered = runtime.read_watchdog.record_read(&device_config.id, Utc::now());
+                    if recovered {
+                        info!("Device '{}' is reading again", device_config.id);
+                    }
 
                     // Update status to reading
-                    let status = DeviceStatus {
+                    Self::publish_status(database, notifications, DeviceStatus {
                         device_id: device_config.id.clone(),
-                        status: "Reading".to_string(),
+                        status: if recovered { "Connected" } else { "Reading" }.to_string(),
                         last_update: Utc::now(),
                         error_message: None,
-                        connection_count: 0,
-                    };
-                    if let Err(e) = database.update_device_status(&status).await {
-                        error!("Failed to update device status: {}", e);
-                    }
+                        connection_count: runtime.connections.load(Ordering::SeqCst),
+                    }).await;
                 },
                 Err(e) => {
                     warn!(
@@ -331,26 +1011,290 @@ impl LoggingService {
                         device_config.id, schedule_group.name, e
                     );
                     retry_count += 1;
+                    runtime.metrics.record_poll_failure(&device_config.id);
+                    runtime.poll_stats.record_failure(&device_config.id, &schedule_group.id, failure_reason(&e), &e.to_string(), Utc::now());
+
+                    // A device that didn't answer in time leaves a CommFail value for each tag
+                    if failure_quality(&e) == Quality::CommFail {
+                        let entries = Self::comm_fail_entries(database, &device_config.id, tags).await;
+                        Self::record_values(database, notifications, runtime, &device_config.id, tags, &tag_names, &entries).await;
+                    }
 
+                    // A dropped session won't come back by polling it again
+                    let connected = match client {
+                        DeviceClient::Modbus(modbus) => modbus.is_connected(),
+                        DeviceClient::Iec104(iec104) => iec104.is_connected(),
+                        DeviceClient::Simulated(simulated) => simulated.is_connected(),
+                    };
+                    if !connected {
+                        return e;
+                    }
                     if retry_count >= device_config.retry_count {
                         error!(
                             "Max retries reached for device '{}' schedule group '{}'",
                             device_config.id, schedule_group.name
                         );
-                        break;
+                        return anyhow::anyhow!("{} polls in a row failed, last with: {}", retry_count, e);
                     }
                 }
             }
 
-            // Wait for next poll using schedule group interval
-            tokio::time::sleep(polling_interval).await;
+            // Wait for next poll using schedule group interval, running on-demand requests meanwhile
+            let next_poll = tokio::time::sleep_until(poll_started + *poll_group.interval.borrow_and_update());
+            tokio::pin!(next_poll);
+            loop {
+                tokio::select! {
+                    _ = &mut next_poll => break,
+                    _ = runtime.shutdown.cancelled() => return anyhow::anyhow!("The service is shutting down"),
+                    Ok(()) = poll_group.interval.changed() => {
+                        let interval = *poll_group.interval.borrow_and_update();
+                        info!(
+                            "Device '{}' schedule group '{}' now polls every {:?}",
+                            device_config.id, schedule_group.name, interval
+                        );
+                        next_poll.as_mut().reset(poll_started + interval);
+                    },
+                    command = async { runtime.commands.lock().await.recv().await } => match command {
+                        Some(command) => Self::run_command(client, command).await,
+                        None => {
+                            (&mut next_poll).await;
+                            break;
+                        }
+                    },
+                }
+            }
+        }
+    }
+
+    async fn run_command(client: &mut DeviceClient, command: DeviceCommand) {
+        match command {
+            DeviceCommand::WriteTag { tag, value, reply } => {
+                // The caller gave up waiting; a late write would surprise whoever retries it
+                if reply.is_closed() {
+                    warn!("Dropped write to tag {} after the request timed out", tag.name);
+                    return;
+                }
+
+                let result = match client {
+                    DeviceClient::Modbus(modbus) => modbus.write_tag(&tag, value).await,
+                    DeviceClient::Iec104(iec104) => iec104.write_setpoint(&tag, value).await,
+                    DeviceClient::Simulated(simulated) => simulated.write_tag(&tag, value).await,
+                };
+                let _ = reply.send(result);
+            },
+            DeviceCommand::Read { read, reply } => {
+                if !reply.is_closed() {
+                    let _ = reply.send(Self::read_with(client, &read).await);
+                }
+            },
+        }
+    }
+
+    async fn read_with(client: &mut DeviceClient, read: &RegisterRead) -> Result<(Vec<u16>, f64)> {
+        match client {
+            DeviceClient::Modbus(modbus) => modbus.read_registers(read).await,
+            DeviceClient::Iec104(iec104) => Ok((Vec::new(), iec104.read_point(read.address as u32).await?)),
+            DeviceClient::Simulated(simulated) => Ok((Vec::new(), simulated.read_point(read).await?)),
         }
     }
 
+    fn start_retention_task(&self) {
+        let database = self.database.clone();
+        let notifications = self.notifications.clone();
+        let policy = self.config.retention.policy(&self.config.database);
+        let last_run = self.last_retention_run.clone();
+        let scheduler = self.scheduler.clone();
+        let mut interval = tokio::time::interval(self.config.retention.interval(&self.config.database));
+
+        tokio::spawn(async move {
+            loop {
+                interval.tick().await;
+
+                let _operation = match scheduler.start(OperationKind::Vacuum, "Log retention", None).await {
+                    Ok(operation) => operation,
+                    Err(conflict) => {
+                        warn!("Skipping log retention: {}", conflict);
+                        continue;
+                    }
+                };
+                match database.cleanup_entries(&policy).await {
+                    Ok(run) => {
+                        if run.deleted_rows() > 0 {
+                            info!(
+                                "Retention deleted {} log entries ({} by age, {} by count), reclaimed {} bytes, {} bytes free for reuse",
+                                run.deleted_rows(), run.deleted_by_age, run.deleted_by_count, run.reclaimed_bytes, run.free_bytes
+                            );
+                        }
+                        notifications.webhook(WebhookEvent::DatabaseCleanup, &run);
+                        *last_run.write().await = Some(run);
+                    },
+                    Err(e) => {
+                        error!("Failed to apply log retention: {}", e);
+                    }
+                }
+            }
+        });
+    }
+
+    /// Report running devices that stopped reading without an error as offline, so a hung
+    /// poll task doesn't leave the device showing as reading
+    fn start_read_watchdog_task(&self) {
+        let database = self.database.clone();
+        let notifications = self.notifications.clone();
+        let live_values = self.live_values.clone();
+        let read_watchdog = self.read_watchdog.clone();
+        let mut interval = tokio::time::interval(READ_WATCHDOG_TICK);
+
+        tokio::spawn(async move {
+            loop {
+                interval.tick().await;
+
+                for overdue in read_watchdog.overdue(Utc::now()) {
+                    match overdue.last_successful_read {
+                        Some(read) => warn!(
+                            "Device '{}' has not read successfully since {}, reporting it offline",
+                            overdue.device_id, read.to_rfc3339()
+                        ),
+                        None => warn!(
+                            "Device '{}' has not read successfully since it started, reporting it offline",
+                            overdue.device_id
+                        ),
+                    }
+                    let connection_count = match database.get_device_status(&overdue.device_id).await {
+                        Ok(Some(status)) => status.connection_count,
+                        _ => 0,
+                    };
+                    Self::publish_status(&database, &notifications, DeviceStatus {
+                        device_id: overdue.device_id.clone(),
+                        status: "Offline".to_string(),
+                        last_update: Utc::now(),
+                        error_message: Some(format!(
+                            "No successful read for {}s (expected at least every {}s)",
+                            overdue.silence.as_secs(), overdue.interval.as_secs()
+                        )),
+                        connection_count,
+                    }).await;
+                    live_values.mark_stale(&overdue.device_id, None, "Device offline");
+                    notifications.emit_tag_update(&live_values.device_values(&overdue.device_id));
+                }
+            }
+        });
+    }
+
+    /// Save counted poll outcomes every minute, so restarts keep the polling statistics
+    fn start_poll_stats_task(&self) {
+        let database = self.database.clone();
+        let poll_stats = self.poll_stats.clone();
+        let mut interval = tokio::time::interval(SAVE_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        tokio::spawn(async move {
+            loop {
+                interval.tick().await;
+                if let Err(e) = poll_stats.save(&database, Utc::now()).await {
+                    error!("Failed to save polling statistics: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Compute virtual tags as their inputs are read, and scheduled ones at their interval, and
+    /// pass their values on like polled ones
+    fn start_virtual_tag_task(&self) {
+        let database = self.database.clone();
+        let notifications = self.notifications.clone();
+        let telemetry = self.telemetry.clone();
+        let telemetry_targets = self.telemetry_targets.clone();
+        let iec104_server = self.iec104_server.clone();
+        let alarms = self.alarms.clone();
+        let live_values = self.live_values.clone();
+        let virtual_tags = self.virtual_tags.clone();
+        let file_export = self.file_export.clone();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = virtual_tags.inputs_read() => {},
+                    _ = tokio::time::sleep(VIRTUAL_TAG_TICK) => {},
+                }
+                let entries = virtual_tags.compute(&live_values, Utc::now());
+                if entries.is_empty() {
+                    continue;
+                }
+
+                if let Err(e) = database.insert_log_entries(&entries).await {
+                    error!("Failed to insert virtual tag values: {}", e);
+                }
+                if let Some(file_export) = &file_export {
+                    file_export.export(&entries);
+                }
+                let mut by_owner: HashMap<&str, Vec<LogEntry>> = HashMap::new();
+                for entry in &entries {
+                    by_owner.entry(&entry.device_id).or_default().push(entry.clone());
+                }
+                for (owner, owner_entries) in &by_owner {
+                    let tb_device_id = telemetry_targets.read().await.get(*owner).cloned();
+                    if let Some(tb_device_id) = tb_device_id {
+                        if let Err(e) = telemetry.enqueue(&tb_device_id, owner_entries).await {
+                            error!("Failed to queue virtual tag telemetry for '{}': {}", owner, e);
+                        }
+                    }
+                    let tag_names: Vec<String> = owner_entries.iter().map(|entry| entry.tag_name.clone()).collect();
+                    notifications.emit_tag_update(&live_values.values_of(owner, &tag_names));
+                }
+                iec104_server.publish(&entries);
+                notifications.publish_values(&entries);
+
+                match alarms.process(&database, &entries).await {
+                    Ok(events) => {
+                        for event in &events {
+                            notifications.alarm(event).await;
+                        }
+                    },
+                    Err(e) => error!("Failed to record alarms of virtual tags: {}", e),
+                }
+            }
+        });
+    }
+
+    /// Pick up virtual tags that were created, changed or deleted
+    pub async fn reload_virtual_tags(&self) -> Result<()> {
+        self.virtual_tags.load(&self.database).await
+    }
+
+    /// Success rate, latency and failure reasons of a device's polls over a window
+    pub async fn poll_stats(&self, device_id: &str, window: StatsWindow) -> Result<DevicePollStats> {
+        self.poll_stats.report(&self.database, device_id, window, Utc::now()).await
+    }
+
+    /// Share of successful polls of every device polled within the window
+    pub async fn poll_success_rates(&self, window: StatsWindow) -> Result<HashMap<String, f64>> {
+        self.poll_stats.success_rates(&self.database, window, Utc::now()).await
+    }
+
+    /// Drop the polling statistics of a deleted device that weren't saved yet
+    pub fn forget_poll_stats(&self, device_id: &str) {
+        self.poll_stats.forget_device(device_id);
+    }
+
+    /// Last successful read and offline count of a device
+    pub fn read_health(&self, device_id: &str) -> ReadHealth {
+        self.read_watchdog.health(device_id)
+    }
+
+    /// Pick up alarm rules that were created, changed or deleted
+    pub async fn reload_alarm_rules(&self) -> Result<()> {
+        self.alarms.load(&self.database).await
+    }
+
+    pub async fn last_retention_run(&self) -> Option<RetentionRun> {
+        self.last_retention_run.read().await.clone()
+    }
+
     async fn start_cleanup_task(&self) {
         let database = self.database.clone();
-        let max_entries = self.config.database.max_log_entries;
         let cleanup_interval = self.config.database.cleanup_interval_hours;
+        let notification_retention_days = self.config.notifications.read_retention_days;
 
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(
@@ -359,15 +1303,38 @@ impl LoggingService {
 
             loop {
                 interval.tick().await;
-                
-                match database.cleanup_old_entries(max_entries).await {
-                    Ok(deleted) => {
-                        if deleted > 0 {
-                            info!("Cleaned up {} old log entries", deleted);
+
+                match database.cleanup_expired_sessions().await {
+                    Ok(purged) => {
+                        if purged > 0 {
+                            info!("Purged {} expired sessions", purged);
+                        }
+                    },
+                    Err(e) => {
+                        error!("Failed to purge expired sessions: {}", e);
+                    }
+                }
+
+                match database.purge_expired_idempotency_records(Utc::now()).await {
+                    Ok(purged) => {
+                        if purged > 0 {
+                            info!("Purged {} expired idempotency records", purged);
+                        }
+                    },
+                    Err(e) => {
+                        error!("Failed to purge idempotency records: {}", e);
+                    }
+                }
+
+                let read_before = Utc::now() - chrono::Duration::days(notification_retention_days as i64);
+                match database.purge_read_notifications(read_before).await {
+                    Ok(purged) => {
+                        if purged > 0 {
+                            info!("Purged {} read notifications", purged);
                         }
                     },
                     Err(e) => {
-                        error!("Failed to cleanup old entries: {}", e);
+                        error!("Failed to purge read notifications: {}", e);
                     }
                 }
             }
@@ -382,6 +1349,285 @@ impl LoggingService {
         self.database.get_all_device_statuses().await
     }
 
+    /// Switch a running IEC 104 device's mode without reconnecting.
+    ///
+    /// Returns false if the device isn't running or already uses these settings;
+    /// a stopped device picks up its stored protocol config on the next start.
+    pub async fn set_iec104_mode(&self, device_id: &str, settings: Iec104ModeSettings) -> bool {
+        match self.iec104_modes.read().await.get(device_id) {
+            Some(handle) => handle.set(settings),
+            None => false,
+        }
+    }
+
+    /// Current values of a device's tags from the last value cache
+    pub fn device_values(&self, device_id: &str) -> DeviceValues {
+        self.live_values.device_values(device_id)
+    }
+
+    /// Current values of every device read since the service started
+    pub fn all_values(&self) -> Vec<DeviceValues> {
+        self.live_values.all_values()
+    }
+
+    /// Forget the cached values of a deleted device
+    pub fn forget_device_values(&self, device_id: &str) {
+        self.live_values.remove_device(device_id);
+    }
+
+    /// Start capturing a device's protocol traffic for `duration`, or stop. The capture applies
+    /// to a running device straight away and to a stopped one once it starts.
+    pub fn set_trace(&self, device_id: &str, enabled: bool, duration: std::time::Duration) -> ProtocolTraceSnapshot {
+        self.traces.set(device_id, enabled, duration)
+    }
+
+    /// The PDUs captured by a device's trace
+    pub fn trace(&self, device_id: &str) -> ProtocolTraceSnapshot {
+        self.traces.snapshot(device_id)
+    }
+
+    /// Drop the trace of a deleted device
+    pub fn forget_trace(&self, device_id: &str) {
+        self.traces.forget(device_id);
+    }
+
+    pub async fn get_iec104_diagnostics(&self, device_id: &str) -> Option<Iec104Diagnostics> {
+        self.iec104_modes.read().await.get(device_id).map(|handle| handle.diagnostics())
+    }
+
+    /// Write a value to a tag of a running device through its connected protocol client
+    pub async fn write_tag(&self, device_id: &str, tag: DeviceTag, value: f64) -> Result<TagWriteResult> {
+        let sender = self.device_commands.read().await.get(device_id).cloned()
+            .ok_or_else(|| anyhow::anyhow!("Device {} is not running", device_id))?;
+
+        let (reply, response) = oneshot::channel();
+        Self::send_command(device_id, &sender, DeviceCommand::WriteTag { tag: Box::new(tag), value, reply }, response).await
+    }
+
+    /// Read registers once. A running device's read is queued through its poller so the
+    /// connection is never shared mid-request; a stopped device gets a short-lived connection.
+    pub async fn read_registers(&self, device_id: &str, read: RegisterRead) -> Result<RegisterReading> {
+        let sender = self.device_commands.read().await.get(device_id).cloned();
+        if let Some(sender) = sender {
+            let (reply, response) = oneshot::channel();
+            let (registers, raw_value) = Self::send_command(device_id, &sender, DeviceCommand::Read { read, reply }, response).await?;
+            return Ok(RegisterReading { registers, raw_value, through_poller: true });
+        }
+
+        let device_instance = self.database.get_device(device_id).await?
+            .ok_or_else(|| anyhow::anyhow!("Device not found: {}", device_id))?;
+        let device_config = Self::device_config(&device_instance)?;
+        let timeout = tokio::time::Duration::from_millis(device_config.timeout_ms.max(1));
+
+        let mut client = Self::new_client(&device_config, None, Some(self.traces.device(device_id)));
+        let result = tokio::time::timeout(timeout, async {
+            match &mut client {
+                DeviceClient::Modbus(modbus) => modbus.connect().await?,
+                DeviceClient::Iec104(iec104) => iec104.connect().await?,
+                DeviceClient::Simulated(simulated) => simulated.connect().await?,
+            }
+            Self::read_with(&mut client, &read).await
+        })
+        .await
+        .map_err(|_| anyhow::anyhow!("Device {} did not answer within {}ms", device_id, timeout.as_millis()));
+
+        match &mut client {
+            DeviceClient::Modbus(modbus) => modbus.disconnect().await,
+            DeviceClient::Iec104(iec104) => {
+                if let Err(e) = iec104.disconnect().await {
+                    warn!("Error disconnecting IEC104 client: {}", e);
+                }
+            },
+            DeviceClient::Simulated(simulated) => simulated.disconnect().await,
+        }
+
+        let (registers, raw_value) = result??;
+        Ok(RegisterReading { registers, raw_value, through_poller: false })
+    }
+
+    /// Read a device's serial number from the registers its protocol config names for it,
+    /// the same way as any other on-demand read
+    pub async fn read_serial(&self, device_id: &str, source: &SerialSource) -> Result<String> {
+        let read = RegisterRead {
+            register_type: source.register_type,
+            address: source.address,
+            size: source.length,
+            data_type: DataType::UInt16,
+            byte_order: None,
+        };
+        let reading = self.read_registers(device_id, read).await?;
+        decode_serial(source.encoding, &reading.registers)
+    }
+
+    /// Try a protocol config without saving anything: a Modbus TCP connect and a read of holding
+    /// register 0, opening a Modbus RTU port, an IEC 104 connect and STARTDT, or loading the replay
+    /// files of a simulated device. The test gives up after `timeout_ms`, and never takes more
+    /// than 10 seconds.
+    pub async fn test_connection(protocol: ProtocolConfig, timeout_ms: u64) -> ConnectionTestResult {
+        let timeout = tokio::time::Duration::from_millis(timeout_ms.max(100)).min(CONNECTION_TEST_MAX_TIMEOUT);
+        let device_config = DeviceConfig {
+            id: "connection-test".to_string(),
+            name: "Connection test".to_string(),
+            enabled: true,
+            protocol,
+            polling_interval_ms: 1000,
+            timeout_ms: timeout.as_millis() as u64,
+            retry_count: 1,
+            tags: Vec::new(),
+            strict_types: false,
+        };
+
+        let started = tokio::time::Instant::now();
+        let mut client = Self::new_client(&device_config, None, None);
+        let outcome = tokio::time::timeout(timeout, Self::try_connection(&mut client, &device_config.protocol)).await;
+        match &mut client {
+            DeviceClient::Modbus(modbus) => modbus.disconnect().await,
+            DeviceClient::Iec104(iec104) => {
+                if let Err(e) = iec104.disconnect().await {
+                    warn!("Error disconnecting IEC104 client after a connection test: {}", e);
+                }
+            },
+            DeviceClient::Simulated(simulated) => simulated.disconnect().await,
+        }
+
+        let (success, message) = outcome.unwrap_or_else(|_| {
+            (false, format!("No answer within {}ms; check the address and that nothing blocks the port", timeout.as_millis()))
+        });
+        ConnectionTestResult { success, latency_ms: started.elapsed().as_millis() as u64, message }
+    }
+
+    async fn try_connection(client: &mut DeviceClient, protocol: &ProtocolConfig) -> (bool, String) {
+        match (client, protocol) {
+            (DeviceClient::Modbus(modbus), ProtocolConfig::ModbusTcp(ModbusTcpConfig { host, port, slave_id, .. })) => {
+                if let Err(e) = modbus.connect().await {
+                    return (false, format!("Could not connect to {}:{}: {}", host, port, e));
+                }
+                let read = RegisterRead {
+                    register_type: RegisterType::Holding,
+                    address: 0,
+                    size: 1,
+                    data_type: DataType::UInt16,
+                    byte_order: None,
+                };
+                match modbus.read_registers(&read).await {
+                    Ok((_, value)) => (true, format!("Connected to {}:{}; slave {} returned {} from holding register 0", host, port, slave_id, value)),
+                    Err(e) if Self::is_modbus_exception(&e) => (
+                        true,
+                        format!("Connected to {}:{}; slave {} answered holding register 0 with an exception ({}), so the link works", host, port, slave_id, e),
+                    ),
+                    Err(e) => (false, format!("Connected to {}:{} but slave {} did not answer: {}", host, port, slave_id, e)),
+                }
+            },
+            (DeviceClient::Modbus(modbus), ProtocolConfig::ModbusRtu(ModbusRtuConfig { port, baud_rate, .. })) => match modbus.connect().await {
+                Ok(()) => (true, format!("Opened serial port {} at {} baud", port, baud_rate)),
+                Err(e) => (false, format!("Could not open serial port {}: {}", port, e)),
+            },
+            (DeviceClient::Iec104(iec104), ProtocolConfig::Iec104(Iec104Config { host, port, .. })) => match iec104.connect().await {
+                Ok(()) => (true, format!("Connected to {}:{} and data transfer was confirmed (STARTDT)", host, port)),
+                Err(e) => (false, format!("IEC 104 connection to {}:{} failed: {}", host, port, e)),
+            },
+            (DeviceClient::Simulated(simulated), ProtocolConfig::Simulated(_)) => match simulated.connect().await {
+                Ok(()) => (true, "Simulated device; its values are generated by the logger, no network is used".to_string()),
+                Err(e) => (false, format!("Simulated device is unavailable: {}", e)),
+            },
+            _ => (false, "Protocol and client don't match".to_string()),
+        }
+    }
+
+    /// A Modbus exception response: the device answered, just not with data
+    fn is_modbus_exception(error: &anyhow::Error) -> bool {
+        error.downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::Other && e.raw_os_error().is_none())
+    }
+
+    async fn send_command<T>(
+        device_id: &str,
+        sender: &mpsc::Sender<DeviceCommand>,
+        command: DeviceCommand,
+        response: oneshot::Receiver<Result<T>>,
+    ) -> Result<T> {
+        tokio::time::timeout(DEVICE_COMMAND_TIMEOUT, async {
+            sender.send(command).await.map_err(|_| anyhow::anyhow!("Device {} was stopped", device_id))?;
+            response.await.map_err(|_| anyhow::anyhow!("Device {} was stopped before the request was handled", device_id))?
+        })
+        .await
+        .map_err(|_| anyhow::anyhow!("Device {} did not handle the request within {}s", device_id, DEVICE_COMMAND_TIMEOUT.as_secs()))?
+    }
+
+    /// Apply one action to many devices, a bounded number at a time. Every device gets a
+    /// result, in the order given; one that hangs is reported as failed without holding up the rest.
+    pub async fn run_device_action(self: &Arc<Self>, action: DeviceAction, device_ids: Vec<String>) -> Vec<DeviceActionResult> {
+        let permits = Arc::new(tokio::sync::Semaphore::new(BULK_ACTION_CONCURRENCY));
+        let mut pending = Vec::with_capacity(device_ids.len());
+        for device_id in device_ids {
+            let (service, permits) = (self.clone(), permits.clone());
+            let task_device_id = device_id.clone();
+            let task = tokio::spawn(async move {
+                let _permit = permits.acquire_owned().await?;
+                service.apply_device_action(action, &task_device_id).await
+            });
+            pending.push((device_id, task));
+        }
+
+        let deadline = tokio::time::Instant::now() + BULK_ACTION_TIMEOUT * pending.len().div_ceil(BULK_ACTION_CONCURRENCY).max(1) as u32;
+        let mut results = Vec::with_capacity(pending.len());
+        for (device_id, task) in pending {
+            let (outcome, detail) = match tokio::time::timeout_at(deadline, task).await {
+                Ok(Ok(Ok(None))) => (DeviceActionOutcome::Done, None),
+                Ok(Ok(Ok(Some(reason)))) => (DeviceActionOutcome::Skipped, Some(reason)),
+                Ok(Ok(Err(e))) => (DeviceActionOutcome::Failed, Some(e.to_string())),
+                Ok(Err(e)) => (DeviceActionOutcome::Failed, Some(format!("Action panicked: {}", e))),
+                // Left running in the background rather than aborted half way
+                Err(_) => (DeviceActionOutcome::Failed, Some("Timed out waiting for the device".to_string())),
+            };
+            if outcome == DeviceActionOutcome::Failed {
+                warn!("Bulk {:?} of device {} failed: {}", action, device_id, detail.as_deref().unwrap_or_default());
+            }
+            results.push(DeviceActionResult { device_id, outcome, detail });
+        }
+        results
+    }
+
+    /// Returns why the device was skipped, if it already was in the requested state
+    async fn apply_device_action(&self, action: DeviceAction, device_id: &str) -> Result<Option<String>> {
+        let mut device = self.database.get_device(device_id).await?
+            .ok_or_else(|| anyhow::anyhow!("Device not found: {}", device_id))?;
+        let running = self.is_device_running(device_id).await;
+
+        match action {
+            DeviceAction::Start if running => return Ok(Some("Already running".to_string())),
+            DeviceAction::Start if !device.enabled => return Ok(Some("Device is disabled".to_string())),
+            DeviceAction::Start => self.start_device(device_id).await?,
+            DeviceAction::Stop if !running => return Ok(Some("Already stopped".to_string())),
+            DeviceAction::Stop => self.stop_device(device_id).await?,
+            DeviceAction::Enable if device.enabled => return Ok(Some("Already enabled".to_string())),
+            DeviceAction::Disable if !device.enabled => return Ok(Some("Already disabled".to_string())),
+            DeviceAction::Enable | DeviceAction::Disable => {
+                device.enabled = action == DeviceAction::Enable;
+                device.updated_at = Utc::now();
+                self.database.update_device(&device).await?;
+                if device.enabled {
+                    self.start_device(device_id).await?;
+                } else {
+                    self.stop_device(device_id).await?;
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// How many devices are running and how many of those are in `Error` or `Offline`
+    pub async fn running_device_counts(&self) -> Result<(usize, usize)> {
+        let running: Vec<String> = self.device_tasks.read().await.iter()
+            .filter(|(_, tasks)| !tasks.is_empty())
+            .map(|(device_id, _)| device_id.clone())
+            .collect();
+        let errored = self.database.get_all_device_statuses().await?.into_iter()
+            .filter(|status| running.contains(&status.device_id) && matches!(status.status.as_str(), "Error" | "Offline"))
+            .count();
+        Ok((running.len(), errored))
+    }
+
     pub async fn is_device_running(&self, device_id: &str) -> bool {
         if let Some(tasks) = self.device_tasks.read().await.get(device_id) {
             !tasks.is_empty()
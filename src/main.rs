@@ -1,239 +1,187 @@
-use axum::{
-    response::{Html, IntoResponse},
-    routing::{get, post},
-    Router,
-    middleware,
-    // http::Uri,
-};
-use socketioxide::SocketIo;
-use std::{sync::Arc, net::SocketAddr};
+use std::{path::PathBuf, sync::Arc, net::SocketAddr};
 use tokio::net::TcpListener;
-use tower_http::cors::CorsLayer;
-use tower_http::services::ServeDir;
-use tracing::info;
-
-mod config;
-mod modbus;
-mod iec104;
-mod database;
-mod logging;
-mod api;
-mod websocket;
-mod csv_parser;
-pub mod tb_rust_client;
-
-use config::{AppConfig, load_config};
-use database::Database;
-use logging::LoggingService;
-
-#[derive(Clone)]
-pub struct AppState {
-    pub config: Arc<AppConfig>,
-    pub database: Arc<Database>,
-    pub logging_service: Arc<LoggingService>,
-}
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
 
-async fn serve_index() -> impl IntoResponse {
-    let index_path = "web/build/index.html";
-    
-    match tokio::fs::read_to_string(index_path).await {
-        Ok(content) => Html(content),
-        Err(_) => {
-            // Fallback HTML if React build is not available
-            let fallback_html = "<!DOCTYPE html>
-<html lang=\"en\">
-  <head>
-    <meta charset=\"utf-8\" />
-    <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\" />
-    <meta name=\"theme-color\" content=\"#000000\" />
-    <meta name=\"description\" content=\"AVA Device Logger - Industrial data logging and monitoring\" />
-    <title>AVA Device Logger</title>
-    <style>
-      body {
-        margin: 0;
-        font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', 'Roboto', 'Oxygen', 'Ubuntu', 'Cantarell', 'Fira Sans', 'Droid Sans', 'Helvetica Neue', sans-serif;
-        -webkit-font-smoothing: antialiased;
-        -moz-osx-font-smoothing: grayscale;
-      }
-      .loading {
-        display: flex;
-        justify-content: center;
-        align-items: center;
-        height: 100vh;
-        font-size: 18px;
-        color: #1890ff;
-      }
-    </style>
-  </head>
-  <body>
-    <noscript>You need to enable JavaScript to run this app.</noscript>
-    <div id=\"root\">
-      <div class=\"loading\">
-        <div>
-          <h2>AVA Device Logger</h2>
-          <p>Backend server is running successfully!</p>
-          <p><strong>API Base URL:</strong> http://localhost:8080/api</p>
-          <br>
-          <p><em>For the full web interface, build the React frontend with:</em></p>
-          <code>cd web && npm install && npm start</code>
-        </div>
-      </div>
-    </div>
-  </body>
-</html>";
-            Html(fallback_html.to_string())
-        }
+use ava_device_logger::app::App;
+use ava_device_logger::config::{AppConfig, archive_config_devices, load_config, migrate_config_devices};
+use ava_device_logger::database::Database;
+use ava_device_logger::{http_server, openapi, safe_mode, tb_rust_client};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    // Print the OpenAPI document and exit, e.g. for client generation or the schema snapshot test
+    if std::env::args().any(|arg| arg == "--openapi") {
+        use utoipa::OpenApi;
+        println!("{}", openapi::ApiDoc::openapi().to_pretty_json()?);
+        return Ok(());
     }
-}
 
-async fn serve_static(uri: axum::http::Uri) -> impl IntoResponse {
-    let path = uri.path();
-    
-    // Serve static files from web/build directory
-    let file_path = format!("web/build{}", path);
-    
-    // Check if file exists
-    if std::path::Path::new(&file_path).exists() {
-        match tokio::fs::read(&file_path).await {
-            Ok(contents) => {
-                let content_type = match std::path::Path::new(&file_path)
-                    .extension()
-                    .and_then(|ext| ext.to_str())
-                {
-                    Some("html") => "text/html",
-                    Some("css") => "text/css",
-                    Some("js") => "application/javascript",
-                    Some("json") => "application/json",
-                    Some("png") => "image/png",
-                    Some("jpg") | Some("jpeg") => "image/jpeg",
-                    Some("ico") => "image/x-icon",
-                    _ => "application/octet-stream",
-                };
-                
-                return (
-                    [(axum::http::header::CONTENT_TYPE, content_type)],
-                    contents,
-                ).into_response();
-            }
-            Err(_) => {}
-        }
+    // Report the schema version of the configured database and the migrations a start would
+    // apply, without changing anything; exits with 1 if the database is newer than this build
+    if std::env::args().any(|arg| arg == "--check-migrations") {
+        let database_path = match tokio::fs::read_to_string("config.toml").await {
+            Ok(content) => toml::from_str::<AppConfig>(&content)?.database.path,
+            Err(_) => AppConfig::default().database.path,
+        };
+        let status = Database::check_migrations(&database_path)?;
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        std::process::exit(if status.newer_than_build { 1 } else { 0 });
     }
-    
-    // Fallback to index for client-side routing
-    serve_index().await.into_response()
-}
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
     // Initialize tracing
     tracing_subscriber::fmt::init();
 
-    // Load configuration
-    let config = Arc::new(load_config().await?);
-    info!("Configuration loaded successfully");
+    // Settings that parse but can't work stop the service, unless told to run anyway; a config
+    // that doesn't parse at all is left to safe mode
+    if let Ok(config) = load_config().await {
+        let errors = config.validate();
+        if !errors.is_empty() {
+            eprintln!("config.toml has {} error{}:", errors.len(), if errors.len() == 1 { "" } else { "s" });
+            for (number, error) in errors.iter().enumerate() {
+                eprintln!("  {}. {}", number + 1, error);
+            }
+            if !std::env::args().any(|arg| arg == "--ignore-config-errors") {
+                eprintln!("Fix them, or start with --ignore-config-errors to run anyway");
+                std::process::exit(1);
+            }
+            warn!("Starting with {} configuration errors because of --ignore-config-errors", errors.len());
+        }
+    }
 
-    // Initialize database
-    let database = Arc::new(Database::new(&config.database.path).await?);
+    // Load configuration and database, falling back to safe mode while either is broken
+    let mut safe_mode_session = None;
+    let (mut config, database) = loop {
+        match safe_mode::preflight().await {
+            Ok(ready) => break ready,
+            Err(failure) => safe_mode_session = Some(safe_mode::run(failure).await?),
+        }
+    };
+    info!("Configuration loaded successfully");
     info!("Database initialized");
+    tb_rust_client::set_payload_logging(config.logging.log_payloads);
 
-    // Initialize logging service
-    let logging_service = Arc::new(LoggingService::new(
-        database.clone(),
-        config.clone(),
-    ).await?);
-    info!("Logging service initialized");
-
-    // Create app state
-    let app_state = AppState {
-        config: config.clone(),
-        database,
-        logging_service,
-    };
+    // Devices are managed in the database; move any left in config.toml over once
+    if !config.devices.is_empty() {
+        let summary = migrate_config_devices(&config, &database).await?;
+        info!(
+            "Config device migration: {} migrated, {} already in database, {} failed",
+            summary.migrated.len(), summary.skipped.len(), summary.failed.len()
+        );
+        if summary.failed.is_empty() {
+            let backup_path = archive_config_devices(&mut config).await?;
+            info!("Removed devices from config.toml, previous version saved to {}", backup_path);
+        } else {
+            warn!("Keeping devices in config.toml so the failed migrations are retried on next start");
+        }
+    }
+    let config = Arc::new(config);
 
-    // Create Socket.IO layer
-    let (socket_layer, socket_io) = SocketIo::new_layer();
-    
-    // Set up Socket.IO event handlers
-    socket_io.ns("/", websocket::on_connect);
-
-    // Create router
-    let app = Router::new()
-        // Authentication routes (no auth required)
-        .route("/api/login", post(api::login))
-        .route("/api/logout", post(api::logout))
-        .route("/api/verify-session", get(api::verify_session))
-        
-        // Plant configuration routes
-        .route("/api/plant-config", get(api::get_plant_config).post(api::update_plant_config))
-        .route("/api/plant-sync-info", get(api::get_all_plant_sync_info))
-        
-        // API routes
-        .route("/api/config", get(api::get_config).post(api::update_config))
-        .route("/api/devices", get(api::get_devices).post(api::create_device))
-        .route("/api/devices/:id", get(api::get_device).put(api::update_device).delete(api::delete_device))
-        .route("/api/devices-enhanced/:id/start", post(api::start_device))
-        .route("/api/devices-enhanced/:id/stop", post(api::stop_device))
-        .route("/api/devices-debug", get(api::debug_devices))
-        .route("/api/logs", get(api::get_logs))
-        .route("/api/logs/:device_id", get(api::get_device_logs))
-        .route("/api/status", get(api::get_status))
-        
-        // Enhanced device management with models and tags
-        .route("/api/device-models", get(api::get_device_models).post(api::create_device_model))
-        .route("/api/device-models/:id", get(api::get_device_model))
-        .route("/api/device-models/:id/delete", post(api::delete_device_model))
-        .route("/api/device-models/:id/tags", get(api::get_tag_templates))
-        .route("/api/devices-enhanced", get(api::get_devices_enhanced).post(api::create_device_with_tags))
-        .route("/api/devices-filtered", get(api::get_devices_filtered))
-        .route("/api/devices-enhanced/:id", get(api::get_device_enhanced).put(api::update_device_with_tags).delete(api::delete_device))
-        .route("/api/devices/:id/tags", get(api::get_device_tags_api))
-        
-        // Device filtering by sync status
-        .route("/api/devices-unsynced", get(api::get_unsynced_devices))
-        .route("/api/devices-by-group/:group_id", get(api::get_devices_by_group))
-        
-        // Schedule group management
-        .route("/api/schedule-groups", get(api::get_schedule_groups).post(api::create_schedule_group))
-        .route("/api/schedule-groups/:id", get(api::get_schedule_group).put(api::update_schedule_group).delete(api::delete_schedule_group))
-        
-        // Modbus TCP tag register management
-        .route("/api/modbus-tcp-tag-registers", get(api::get_modbus_tcp_tag_registers))
-        .route("/api/modbus-tcp-tag-registers/upload-csv", post(api::upload_modbus_tcp_csv_tags))
-        
-        // ThingsBoard API endpoints
-        .route("/api/thingsboard/entity-groups", get(api::get_thingsboard_entity_groups))
-        .route("/api/sync-devices-to-thingsboard", post(api::sync_devices_to_thingsboard))
-        .route("/api/generate-device-catalog", post(api::generate_device_catalog))
-        
-        // File management endpoints
-        .route("/api/files/catalogs", get(api::list_catalog_files))
-        .route("/api/files/catalogs/:filename", get(api::download_catalog_file).delete(api::delete_catalog_file))
-        
-        // WebSocket endpoint
-        .route("/socket.io/*path", get(websocket::socket_handler))
-        
-        // Static file serving - serve React build from web/build
-        .nest_service("/static", ServeDir::new("web/build/static"))
-        .route("/", get(serve_index))
-        .route("/*path", get(serve_static))
-
-        // docker health check endpoint
-        .route("/api/health", get(crate::api::health_check))
-        
-        // Add middleware
-        .layer(middleware::from_fn_with_state(app_state.clone(), api::auth_middleware))
-        .layer(CorsLayer::permissive())
-        .layer(socket_layer)
-        .with_state(app_state);
-
-    // Start the server
-    let addr = SocketAddr::from(([0, 0, 0, 0], config.server.port));
+    let app = App::start(config.clone(), database, PathBuf::from(".")).await?;
+    let logging_service = app.state.logging_service.clone();
+
+    // Announce the recovery once notifications can be stored and emitted again
+    if let Some(session) = safe_mode_session {
+        app.state.notifications.broadcast(
+            "safe_mode_exited",
+            "warning",
+            "Recovered from safe mode".to_string(),
+            format!(
+                "Started in safe mode at {} because the {} could not be loaded ({}); normal operation resumed at {}",
+                session.entered_at.to_rfc3339(), session.failure.component, session.failure.reason, session.exited_at.to_rfc3339()
+            ),
+            None,
+        ).await;
+    }
+
+    // Start the server; the address was checked with the rest of `[server]` before startup
+    let tls = match &config.server.tls {
+        Some(tls) => Some(http_server::rustls_config(tls).await?),
+        None => None,
+    };
+    let addr = SocketAddr::new(config.server.bind_address.parse()?, config.server.port);
     let listener = TcpListener::bind(addr).await?;
+    let scheme = if tls.is_some() { "https" } else { "http" };
+
+    info!("Server starting on {}://{}", scheme, addr);
+    info!("Web UI available at {}://localhost:{}", scheme, config.server.port);
     
-    info!("Server starting on http://{}", addr);
-    info!("Web UI available at http://localhost:{}", config.server.port);
-    
-    axum::serve(listener, app).await?;
+    let shutdown = CancellationToken::new();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown_signal().await;
+            shutdown.cancel();
+        }
+    });
+
+    // New connections are refused from the signal on, while requests in flight may finish
+    let router = app.router;
+    let mut server = tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            match tls {
+                Some(tls) => http_server::serve_tls(listener, tls, router, shutdown).await,
+                None => {
+                    axum::serve(listener, router.into_make_service_with_connect_info::<SocketAddr>())
+                        .with_graceful_shutdown(shutdown.cancelled_owned())
+                        .await
+                }
+            }
+        }
+    });
+    tokio::select! {
+        biased;
+        _ = shutdown.cancelled() => {},
+        // Serving only ends by itself if it fails
+        result = &mut server => return Ok(result??),
+    }
+
+    let shutdown_timeout = std::time::Duration::from_secs(config.server.shutdown_timeout_secs);
+    info!("Shutting down, waiting up to {}s for devices to stop", shutdown_timeout.as_secs());
+    let deadline = tokio::time::Instant::now() + shutdown_timeout;
+    let mut stopped = logging_service.shutdown(shutdown_timeout).await;
+    if tokio::time::timeout_at(deadline, &mut server).await.is_err() {
+        error!("HTTP connections still open after {}s", shutdown_timeout.as_secs());
+        server.abort();
+        stopped = false;
+    }
+
+    if !stopped {
+        error!("Shutdown did not finish in time, exiting anyway");
+        std::process::exit(1);
+    }
+    info!("Shutdown complete");
 
     Ok(())
 }
+
+/// Resolves on Ctrl+C, or on SIGTERM as sent by `docker stop` and systemd
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            },
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            },
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("Received Ctrl+C"),
+        _ = terminate => info!("Received SIGTERM"),
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex as StdMutex;
+use std::time::{Duration, Instant};
+
+use crate::mqtt::MqttStatus;
+
+/// Upper bounds in seconds of the read latency histogram buckets
+pub const READ_LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+#[derive(Default)]
+struct LatencyHistogram {
+    /// Observations per bucket, the last one counting those above every bound
+    buckets: [u64; READ_LATENCY_BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl LatencyHistogram {
+    fn observe(&mut self, latency: Duration) {
+        let seconds = latency.as_secs_f64();
+        let bucket = READ_LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound).unwrap_or(READ_LATENCY_BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+#[derive(Default)]
+struct DeviceMetrics {
+    poll_successes: u64,
+    poll_failures: u64,
+    read_latency: LatencyHistogram,
+    reconnects: u64,
+    log_entries_written: u64,
+    telemetry_push_successes: u64,
+    telemetry_push_failures: u64,
+    /// Unknown until the device's telemetry is first queued or resumed
+    telemetry_backlog: Option<u64>,
+}
+
+/// Counters and gauges for the `/metrics` endpoint, in the Prometheus text format.
+///
+/// Updated by the pollers and the telemetry forwarder as they go, so a scrape only
+/// formats what is already in memory. Counters start from zero when the service starts.
+pub struct Metrics {
+    started: Instant,
+    devices: StdMutex<BTreeMap<String, DeviceMetrics>>,
+    /// None unless MQTT publishing is configured
+    mqtt: StdMutex<Option<MqttStatus>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            devices: StdMutex::new(BTreeMap::new()),
+            mqtt: StdMutex::new(None),
+        }
+    }
+
+    fn update(&self, device_id: &str, update: impl FnOnce(&mut DeviceMetrics)) {
+        let mut devices = self.devices.lock().unwrap();
+        update(devices.entry(device_id.to_string()).or_default());
+    }
+
+    /// Count a successful poll of a device and how long its read took
+    pub fn record_poll_success(&self, device_id: &str, latency: Duration) {
+        self.update(device_id, |device| {
+            device.poll_successes += 1;
+            device.read_latency.observe(latency);
+        });
+    }
+
+    pub fn record_poll_failure(&self, device_id: &str) {
+        self.update(device_id, |device| device.poll_failures += 1);
+    }
+
+    /// Count a connect attempt after a lost connection or a failed connect
+    pub fn record_reconnect(&self, device_id: &str) {
+        self.update(device_id, |device| device.reconnects += 1);
+    }
+
+    pub fn record_log_entries_written(&self, device_id: &str, written: usize) {
+        self.update(device_id, |device| device.log_entries_written += written as u64);
+    }
+
+    /// Count a push of a device's queued telemetry to ThingsBoard
+    pub fn record_telemetry_push(&self, device_id: &str, success: bool) {
+        self.update(device_id, |device| match success {
+            true => device.telemetry_push_successes += 1,
+            false => device.telemetry_push_failures += 1,
+        });
+    }
+
+    pub fn set_telemetry_backlog(&self, device_id: &str, pending: u64) {
+        self.update(device_id, |device| device.telemetry_backlog = Some(pending));
+    }
+
+    /// Take delivered values off a device's telemetry backlog
+    pub fn reduce_telemetry_backlog(&self, device_id: &str, delivered: usize) {
+        self.update(device_id, |device| {
+            if let Some(pending) = device.telemetry_backlog.as_mut() {
+                *pending = pending.saturating_sub(delivered as u64);
+            }
+        });
+    }
+
+    pub fn set_mqtt_status(&self, status: MqttStatus) {
+        *self.mqtt.lock().unwrap() = Some(status);
+    }
+
+    /// Everything in the Prometheus text exposition format; the database size is left out
+    /// when it couldn't be read
+    pub fn render(&self, database_size_bytes: Option<u64>) -> String {
+        let devices = self.devices.lock().unwrap();
+        let mut out = String::new();
+
+        Self::header(&mut out, "ava_device_polls_total", "counter", "Polls of a device by result");
+        for (device_id, device) in devices.iter() {
+            let device_id = escape_label(device_id);
+            let _ = writeln!(out, "ava_device_polls_total{{device=\"{}\",result=\"success\"}} {}", device_id, device.poll_successes);
+            let _ = writeln!(out, "ava_device_polls_total{{device=\"{}\",result=\"failure\"}} {}", device_id, device.poll_failures);
+        }
+
+        Self::header(&mut out, "ava_device_reconnects_total", "counter", "Connect attempts after a lost connection or a failed connect");
+        for (device_id, device) in devices.iter() {
+            let _ = writeln!(out, "ava_device_reconnects_total{{device=\"{}\"}} {}", escape_label(device_id), device.reconnects);
+        }
+
+        Self::header(&mut out, "ava_log_entries_written_total", "counter", "Values written to the log database");
+        for (device_id, device) in devices.iter() {
+            let _ = writeln!(out, "ava_log_entries_written_total{{device=\"{}\"}} {}", escape_label(device_id), device.log_entries_written);
+        }
+
+        Self::header(&mut out, "ava_device_read_duration_seconds", "histogram", "Time taken by successful polls of a device");
+        for (device_id, device) in devices.iter() {
+            let device_id = escape_label(device_id);
+            let histogram = &device.read_latency;
+            let mut cumulative = 0;
+            for (bound, count) in READ_LATENCY_BUCKETS.iter().zip(histogram.buckets.iter()) {
+                cumulative += count;
+                let _ = writeln!(out, "ava_device_read_duration_seconds_bucket{{device=\"{}\",le=\"{}\"}} {}", device_id, bound, cumulative);
+            }
+            let _ = writeln!(out, "ava_device_read_duration_seconds_bucket{{device=\"{}\",le=\"+Inf\"}} {}", device_id, histogram.count);
+            let _ = writeln!(out, "ava_device_read_duration_seconds_sum{{device=\"{}\"}} {}", device_id, histogram.sum);
+            let _ = writeln!(out, "ava_device_read_duration_seconds_count{{device=\"{}\"}} {}", device_id, histogram.count);
+        }
+
+        Self::header(&mut out, "ava_telemetry_pushes_total", "counter", "Pushes of a device's queued telemetry to ThingsBoard by result");
+        for (device_id, device) in devices.iter().filter(|(_, device)| device.telemetry_push_successes + device.telemetry_push_failures > 0) {
+            let device_id = escape_label(device_id);
+            let _ = writeln!(out, "ava_telemetry_pushes_total{{device=\"{}\",result=\"success\"}} {}", device_id, device.telemetry_push_successes);
+            let _ = writeln!(out, "ava_telemetry_pushes_total{{device=\"{}\",result=\"failure\"}} {}", device_id, device.telemetry_push_failures);
+        }
+
+        Self::header(&mut out, "ava_telemetry_backlog", "gauge", "Values of a device waiting to be pushed to ThingsBoard");
+        for (device_id, device) in devices.iter() {
+            if let Some(pending) = device.telemetry_backlog {
+                let _ = writeln!(out, "ava_telemetry_backlog{{device=\"{}\"}} {}", escape_label(device_id), pending);
+            }
+        }
+
+        if let Some(mqtt) = self.mqtt.lock().unwrap().as_ref() {
+            Self::header(&mut out, "ava_mqtt_connected", "gauge", "Whether the MQTT publisher is connected to its broker");
+            let _ = writeln!(out, "ava_mqtt_connected {}", u8::from(mqtt.connected));
+            Self::header(&mut out, "ava_mqtt_connects_total", "counter", "Successful connections to the MQTT broker");
+            let _ = writeln!(out, "ava_mqtt_connects_total {}", mqtt.connects);
+            Self::header(&mut out, "ava_mqtt_messages_published_total", "counter", "Messages published to the MQTT broker");
+            let _ = writeln!(out, "ava_mqtt_messages_published_total {}", mqtt.published);
+            Self::header(&mut out, "ava_mqtt_messages_dropped_total", "counter", "Messages dropped because the queue was full while disconnected");
+            let _ = writeln!(out, "ava_mqtt_messages_dropped_total {}", mqtt.dropped);
+            Self::header(&mut out, "ava_mqtt_queue_length", "gauge", "Messages waiting to be published");
+            let _ = writeln!(out, "ava_mqtt_queue_length {}", mqtt.queued);
+        }
+
+        if let Some(size) = database_size_bytes {
+            Self::header(&mut out, "ava_database_size_bytes", "gauge", "Size of the SQLite database file");
+            let _ = writeln!(out, "ava_database_size_bytes {}", size);
+        }
+
+        Self::header(&mut out, "ava_process_uptime_seconds", "gauge", "Time since the service started");
+        let _ = writeln!(out, "ava_process_uptime_seconds {}", self.started.elapsed().as_secs_f64());
+
+        out
+    }
+
+    fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    }
+}
+
+/// Escape a label value as the text format requires
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
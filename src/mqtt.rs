@@ -0,0 +1,286 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rumqttc::{AsyncClient, ConnectReturnCode, Event, EventLoop, MqttOptions, Outgoing, Packet, QoS, Transport};
+use serde::Serialize;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::config::MqttConfig;
+use crate::database::{DeviceStatus, LogEntry};
+use crate::metrics::Metrics;
+
+const CONNECT_TIMEOUT_SECS: u64 = 10;
+const RECONNECT_BACKOFF_BASE: Duration = Duration::from_secs(1);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(60);
+/// QoS 1 messages sent but not acknowledged yet, at most
+const MAX_IN_FLIGHT: u16 = 100;
+/// Messages handed to the client but not yet written
+const CLIENT_CAPACITY: usize = 10;
+
+/// Connection state and counters of the MQTT publisher
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct MqttStatus {
+    /// `host:port` of the broker
+    pub broker: String,
+    pub connected: bool,
+    pub connected_since: Option<DateTime<Utc>>,
+    /// Successful connections since the service started, counting reconnects
+    pub connects: u64,
+    /// Messages written to the broker, acknowledged ones only at QoS 1
+    pub published: u64,
+    /// Oldest messages dropped because the queue was full while disconnected
+    pub dropped: u64,
+    /// Messages waiting for the connection, not counting those already handed to the client
+    pub queued: usize,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Message {
+    topic: String,
+    payload: Vec<u8>,
+    retain: bool,
+}
+
+#[derive(Default)]
+struct Connection {
+    connected_since: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+}
+
+/// Publishes logged values and device statuses to an MQTT broker for SCADA systems that
+/// don't speak Socket.IO.
+///
+/// Producers only queue messages, so polling never waits on the broker. While it is
+/// unreachable up to `queue_size` messages are held and the oldest dropped beyond that;
+/// the connection is retried with a doubling backoff. Messages not acknowledged when the
+/// connection drops are sent again by the client once it is back.
+pub struct MqttPublisher {
+    config: MqttConfig,
+    client: AsyncClient,
+    event_loop: StdMutex<Option<EventLoop>>,
+    queue: StdMutex<VecDeque<Message>>,
+    wake: Notify,
+    connection: StdMutex<Connection>,
+    connects: AtomicU64,
+    published: AtomicU64,
+    dropped: AtomicU64,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl MqttPublisher {
+    pub fn new(config: MqttConfig) -> Self {
+        let mut options = MqttOptions::new(config.client_id.clone(), config.host.trim(), config.port);
+        options
+            .set_keep_alive(Duration::from_secs(config.keep_alive_secs.max(1).into()))
+            .set_clean_session(true)
+            .set_inflight(MAX_IN_FLIGHT);
+        if !config.username.is_empty() {
+            options.set_credentials(config.username.clone(), config.password.clone());
+        }
+        if config.tls {
+            options.set_transport(Transport::tls_with_default_config());
+        }
+        let (client, mut event_loop) = AsyncClient::new(options, CLIENT_CAPACITY);
+        event_loop.network_options.set_connection_timeout(CONNECT_TIMEOUT_SECS);
+
+        Self {
+            config,
+            client,
+            event_loop: StdMutex::new(Some(event_loop)),
+            queue: StdMutex::new(VecDeque::new()),
+            wake: Notify::new(),
+            connection: StdMutex::new(Connection::default()),
+            connects: AtomicU64::new(0),
+            published: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            metrics: None,
+        }
+    }
+
+    /// Report the connection and counters in the service's shared metrics
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Connect and keep reconnecting for as long as the service runs
+    pub fn start(self: &Arc<Self>) {
+        let Some(event_loop) = self.event_loop.lock().unwrap().take() else {
+            return;
+        };
+        self.report_metrics();
+        tokio::spawn(self.clone().drive(event_loop));
+        tokio::spawn(self.clone().forward());
+    }
+
+    pub fn status(&self) -> MqttStatus {
+        let connection = self.connection.lock().unwrap();
+        MqttStatus {
+            broker: self.broker(),
+            connected: connection.connected_since.is_some(),
+            connected_since: connection.connected_since,
+            connects: self.connects.load(Ordering::Relaxed),
+            published: self.published.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            queued: self.queue.lock().unwrap().len(),
+            last_error: connection.last_error.clone(),
+        }
+    }
+
+    /// Queue a poll's values, each to `{base}/{device_id}/{tag_name}`. Failed reads and
+    /// muted tags are skipped.
+    pub fn publish_values(&self, entries: &[LogEntry]) {
+        let messages = entries.iter().filter(|entry| entry.has_value()).map(|entry| Message {
+            topic: format!("{}/{}/{}", self.config.base_topic, topic_level(&entry.device_id), topic_level(&entry.tag_name)),
+            payload: serde_json::json!({
+                "value": entry.value,
+                "unit": entry.unit,
+                "quality": entry.quality,
+                "ts": entry.timestamp,
+            })
+            .to_string()
+            .into_bytes(),
+            retain: false,
+        });
+        self.enqueue(messages);
+    }
+
+    /// Queue a device's status to `{base}/{device_id}/status`, retained so subscribers see
+    /// the current status as soon as they subscribe
+    pub fn publish_status(&self, status: &DeviceStatus) {
+        self.enqueue(std::iter::once(Message {
+            topic: format!("{}/{}/status", self.config.base_topic, topic_level(&status.device_id)),
+            payload: serde_json::json!({
+                "status": status.status,
+                "error_message": status.error_message,
+                "connection_count": status.connection_count,
+                "ts": status.last_update,
+            })
+            .to_string()
+            .into_bytes(),
+            retain: true,
+        }));
+    }
+
+    fn enqueue(&self, messages: impl Iterator<Item = Message>) {
+        let mut dropped = 0;
+        {
+            let mut queue = self.queue.lock().unwrap();
+            for message in messages {
+                if queue.len() >= self.config.queue_size.max(1) {
+                    queue.pop_front();
+                    dropped += 1;
+                }
+                queue.push_back(message);
+            }
+        }
+        if dropped > 0 {
+            self.dropped.fetch_add(dropped, Ordering::Relaxed);
+        }
+        self.report_metrics();
+        self.wake.notify_one();
+    }
+
+    fn broker(&self) -> String {
+        format!("{}:{}", self.config.host, self.config.port)
+    }
+
+    fn is_connected(&self) -> bool {
+        self.connection.lock().unwrap().connected_since.is_some()
+    }
+
+    fn qos(&self) -> QoS {
+        match self.config.qos {
+            0 => QoS::AtMostOnce,
+            _ => QoS::AtLeastOnce,
+        }
+    }
+
+    fn report_metrics(&self) {
+        if let Some(metrics) = &self.metrics {
+            metrics.set_mqtt_status(self.status());
+        }
+    }
+
+    /// Run the client's event loop, which connects, sends and reconnects, and keep the
+    /// status up to date from its events
+    async fn drive(self: Arc<Self>, mut event_loop: EventLoop) {
+        let mut backoff = RECONNECT_BACKOFF_BASE;
+        loop {
+            match event_loop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(connack))) if connack.code == ConnectReturnCode::Success => {
+                    backoff = RECONNECT_BACKOFF_BASE;
+                    self.connects.fetch_add(1, Ordering::Relaxed);
+                    {
+                        let mut connection = self.connection.lock().unwrap();
+                        connection.connected_since = Some(Utc::now());
+                        connection.last_error = None;
+                    }
+                    self.report_metrics();
+                    info!("Connected to MQTT broker {}", self.broker());
+                    self.wake.notify_one();
+                }
+                Ok(Event::Incoming(Packet::PubAck(_))) => self.count_published(),
+                Ok(Event::Outgoing(Outgoing::Publish(_))) if self.qos() == QoS::AtMostOnce => self.count_published(),
+                Ok(_) => {}
+                Err(e) => {
+                    let was_connected = {
+                        let mut connection = self.connection.lock().unwrap();
+                        connection.last_error = Some(e.to_string());
+                        connection.connected_since.take().is_some()
+                    };
+
+                    // Unacknowledged messages wait in the client for the next session, within
+                    // the same bound as the queue
+                    let excess = event_loop.pending.len().saturating_sub(self.config.queue_size.max(1));
+                    event_loop.pending.drain(..excess);
+                    self.dropped.fetch_add(excess as u64, Ordering::Relaxed);
+                    self.report_metrics();
+
+                    match was_connected {
+                        true => warn!("Lost connection to MQTT broker {}: {}", self.broker(), e),
+                        false => warn!("Failed to connect to MQTT broker {}, retrying in {:?}: {}", self.broker(), backoff, e),
+                    }
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(RECONNECT_BACKOFF_MAX);
+                }
+            }
+        }
+    }
+
+    /// Hand queued messages to the client while connected, so they wait in the bounded
+    /// queue rather than in the client while the broker is away
+    async fn forward(self: Arc<Self>) {
+        loop {
+            let next = match self.is_connected() {
+                true => self.queue.lock().unwrap().pop_front(),
+                false => None,
+            };
+            let Some(message) = next else {
+                self.wake.notified().await;
+                continue;
+            };
+            if let Err(e) = self.client.publish(message.topic, self.qos(), message.retain, message.payload).await {
+                warn!("MQTT client stopped taking messages: {}", e);
+                return;
+            }
+            self.report_metrics();
+        }
+    }
+
+    fn count_published(&self) {
+        self.published.fetch_add(1, Ordering::Relaxed);
+        self.report_metrics();
+    }
+}
+
+/// A device id or tag name as a single topic level, without wildcards or separators
+fn topic_level(name: &str) -> String {
+    name.replace(['/', '+', '#'], "_")
+}
@@ -0,0 +1,162 @@
+use socketioxide::SocketIo;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex as StdMutex};
+use tracing::{error, warn};
+
+use crate::config::WebhookEvent;
+use crate::database::{AlarmEvent, Database, DeviceStatus, Job, LogEntry, NewNotification};
+use crate::live_values::DeviceValues;
+use crate::mqtt::MqttPublisher;
+use crate::webhooks::WebhookNotifier;
+use crate::websocket::{user_room, TagUpdateBatcher};
+
+/// Statuses of a device that isn't reading
+const OFFLINE_STATUSES: [&str; 2] = ["Offline", "Error"];
+
+/// Persists notifications and tells connected UIs that a new one exists
+pub struct NotificationService {
+    database: Arc<Database>,
+    io: SocketIo,
+    /// Last status emitted per device, so only transitions are pushed
+    device_statuses: StdMutex<HashMap<String, String>>,
+    tag_updates: TagUpdateBatcher,
+    webhooks: Arc<WebhookNotifier>,
+    mqtt: Option<Arc<MqttPublisher>>,
+}
+
+impl NotificationService {
+    pub fn new(database: Arc<Database>, io: SocketIo, tag_updates: TagUpdateBatcher, webhooks: Arc<WebhookNotifier>) -> Self {
+        Self { database, io, device_statuses: StdMutex::new(HashMap::new()), tag_updates, webhooks, mqtt: None }
+    }
+
+    /// Also publish logged values and device status changes to an MQTT broker
+    pub fn with_mqtt(mut self, mqtt: Arc<MqttPublisher>) -> Self {
+        self.mqtt = Some(mqtt);
+        self
+    }
+
+    /// Store a notification and emit a lightweight `notification` event with its id, to the
+    /// user's own connections when it is meant for one user and to every client otherwise.
+    ///
+    /// Failures are logged rather than returned so producers never fail because
+    /// a notification couldn't be written.
+    pub async fn notify(&self, notification: NewNotification) -> Option<i64> {
+        let id = match self.database.create_notification(&notification).await {
+            Ok(id) => id,
+            Err(e) => {
+                error!("Failed to store notification '{}': {}", notification.title, e);
+                return None;
+            }
+        };
+
+        let event = serde_json::json!({
+            "id": id,
+            "severity": notification.severity,
+            "user_id": notification.user_id,
+        });
+        let emitted = match notification.user_id {
+            Some(user_id) => self.io.to(user_room(user_id)).emit("notification", event),
+            None => self.io.emit("notification", event),
+        };
+        if let Err(e) = emitted {
+            warn!("Failed to emit notification event {}: {}", id, e);
+        }
+
+        Some(id)
+    }
+
+    /// Broadcast notification visible to every user
+    pub async fn broadcast(
+        &self,
+        notification_type: &str,
+        severity: &str,
+        title: String,
+        body: String,
+        entity: Option<(&str, &str)>,
+    ) -> Option<i64> {
+        self.notify(NewNotification {
+            notification_type: notification_type.to_string(),
+            severity: severity.to_string(),
+            title,
+            body,
+            entity_type: entity.map(|(entity_type, _)| entity_type.to_string()),
+            entity_id: entity.map(|(_, entity_id)| entity_id.to_string()),
+            user_id: None,
+        })
+        .await
+    }
+
+    /// Queue an event for the webhooks subscribed to it
+    pub fn webhook(&self, event: WebhookEvent, data: &impl serde::Serialize) {
+        self.webhooks.send(event, data);
+    }
+
+    /// Send an event to the webhooks subscribed to it and wait for the deliveries; returns how
+    /// many webhooks it went to
+    pub async fn webhook_and_wait(&self, event: WebhookEvent, data: &impl serde::Serialize) -> anyhow::Result<usize> {
+        self.webhooks.send_and_wait(event, data).await
+    }
+
+    /// Emit a `device_status` event to every client when a device's status changes, so UIs
+    /// see connection changes without polling. The change is also published to MQTT, and
+    /// going offline and coming back are sent to webhooks.
+    pub fn emit_device_status(&self, status: &DeviceStatus) {
+        let previous = self.device_statuses.lock().unwrap().insert(status.device_id.clone(), status.status.clone());
+        if previous.as_deref() == Some(status.status.as_str()) {
+            return;
+        }
+        if let Err(e) = self.io.emit("device_status", status) {
+            warn!("Failed to emit status of device {}: {}", status.device_id, e);
+        }
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.publish_status(status);
+        }
+
+        let was_offline = previous.as_deref().is_some_and(|previous| OFFLINE_STATUSES.contains(&previous));
+        if OFFLINE_STATUSES.contains(&status.status.as_str()) && !was_offline {
+            self.webhook(WebhookEvent::DeviceOffline, status);
+        } else if was_offline && matches!(status.status.as_str(), "Connected" | "Reading") {
+            self.webhook(WebhookEvent::DeviceOnline, status);
+        }
+    }
+
+    /// Publish a poll's logged values to MQTT, when configured
+    pub fn publish_values(&self, entries: &[LogEntry]) {
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.publish_values(entries);
+        }
+    }
+
+    /// Queue values from the last value cache for the next `tag_update` event to the device's subscribers
+    pub fn emit_tag_update(&self, values: &DeviceValues) {
+        self.tag_updates.push(values);
+    }
+
+    /// Emit an `alarm` event for an alarm that was raised or cleared, and send it to webhooks.
+    /// Raised alarms are also stored as a broadcast notification.
+    pub async fn alarm(&self, event: &AlarmEvent) {
+        if let Err(e) = self.io.emit("alarm", event) {
+            warn!("Failed to emit alarm {}: {}", event.id, e);
+        }
+        match event.cleared_at {
+            Some(_) => self.webhook(WebhookEvent::AlarmCleared, event),
+            None => self.webhook(WebhookEvent::AlarmRaised, event),
+        }
+        if event.cleared_at.is_none() {
+            self.broadcast(
+                "alarm_raised",
+                &event.severity,
+                format!("Alarm on {} of device {}", event.tag_name, event.device_id),
+                format!("{} {} {} (value {})", event.tag_name, event.condition.as_str(), event.threshold, event.raised_value),
+                Some(("device", &event.device_id)),
+            ).await;
+        }
+    }
+
+    /// Emit a `job_progress` event with a background job's current state
+    pub fn emit_job_progress(&self, job: &Job) {
+        if let Err(e) = self.io.emit("job_progress", job) {
+            warn!("Failed to emit progress of job {}: {}", job.id, e);
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use axum::response::Json;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::response::ResponseBuilder;
+use utoipa::openapi::OpenApi as OpenApiDocument;
+use utoipa::{Modify, OpenApi};
+
+use crate::api;
+
+/// OpenAPI document built from the handler annotations in `api.rs`
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "AVA Device Logger API",
+        description = "Every response uses the `ApiResponse` envelope (`success`, `data`, `error`, optional `detail_ref` and `field_errors`). \
+            Most handler failures are reported as 200 with `success: false`; HTTP error codes are listed per operation."
+    ),
+    paths(
+        api::health_check,
+        api::get_metrics,
+        api::login,
+        api::logout,
+        api::verify_session,
+        api::refresh_session,
+        api::get_sessions,
+        api::delete_session,
+        api::change_password,
+        api::get_users,
+        api::create_user,
+        api::update_user,
+        api::delete_user,
+        api::get_plant_config,
+        api::update_plant_config,
+        api::get_all_plant_sync_info,
+        api::get_plant_summary,
+        api::get_config,
+        api::update_config,
+        api::get_devices,
+        api::create_device,
+        api::get_device,
+        api::update_device,
+        api::delete_device,
+        api::start_device,
+        api::stop_device,
+        api::bulk_device_action,
+        api::start_all_devices,
+        api::stop_all_devices,
+        api::debug_devices,
+        api::get_all_values,
+        api::get_device_values,
+        api::get_device_poll_stats,
+        api::set_device_trace,
+        api::get_device_trace,
+        api::get_logs,
+        api::get_device_logs,
+        api::get_aggregated_logs,
+        api::export_logs,
+        api::export_backup,
+        api::restore_backup,
+        api::get_schema_migrations,
+        api::get_audit_log,
+        api::get_status,
+        api::get_device_models,
+        api::create_device_model,
+        api::get_device_model,
+        api::delete_device_model,
+        api::get_tag_templates,
+        api::update_tag_template,
+        api::delete_tag_template,
+        api::resync_model_devices,
+        api::get_devices_enhanced,
+        api::create_device_with_tags,
+        api::create_device_from_model,
+        api::duplicate_device,
+        api::test_device_connection,
+        api::discover_sunspec_device,
+        api::get_devices_filtered,
+        api::get_device_enhanced,
+        api::update_device_with_tags,
+        api::get_device_tb_token,
+        api::create_tags_from_register_map,
+        api::get_device_tags_api,
+        api::add_device_tag,
+        api::patch_device_tag,
+        api::delete_device_tag,
+        api::reset_device_type_mismatches,
+        api::mute_device_tag,
+        api::unmute_device_tag,
+        api::get_device_tag_mutes,
+        api::get_telemetry_forwarding,
+        api::set_telemetry_forwarding,
+        api::get_tb_child_devices,
+        api::read_device_tag,
+        api::read_device_serial_no,
+        api::write_device_tag,
+        api::get_device_tag_writes,
+        api::get_device_iec104_diagnostics,
+        api::search_tags,
+        api::save_tag_search,
+        api::bulk_edit_tags,
+        api::get_unsynced_devices,
+        api::get_devices_by_group,
+        api::get_schedule_groups,
+        api::create_schedule_group,
+        api::get_schedule_group,
+        api::update_schedule_group,
+        api::delete_schedule_group,
+        api::get_modbus_tcp_tag_registers,
+        api::delete_modbus_tcp_tag_registers,
+        api::upload_modbus_tcp_csv_tags,
+        api::export_modbus_tcp_tag_registers_csv,
+        api::get_thingsboard_entity_groups,
+        api::get_thingsboard_hierarchy,
+        api::sync_devices_to_thingsboard,
+        api::generate_device_catalog,
+        api::download_device_catalog,
+        api::get_iec104_server,
+        api::set_iec104_server,
+        api::get_notifications,
+        api::mark_all_notifications_read,
+        api::mark_notification_read,
+        api::get_alarm_rules,
+        api::create_alarm_rule,
+        api::update_alarm_rule,
+        api::delete_alarm_rule,
+        api::get_alarms,
+        api::get_virtual_tags,
+        api::create_virtual_tag,
+        api::update_virtual_tag,
+        api::delete_virtual_tag,
+        api::get_webhooks,
+        api::create_webhook,
+        api::update_webhook,
+        api::delete_webhook,
+        api::get_webhook_deliveries,
+        api::get_daily_report,
+        api::generate_daily_report,
+        api::get_jobs_queue,
+        api::get_jobs,
+        api::get_job,
+        api::list_files,
+        api::download_file,
+        api::delete_file,
+    ),
+    modifiers(&SessionAuth),
+    security(("session_token" = [])),
+)]
+pub struct ApiDoc;
+
+/// Registers the bearer session token scheme and the 401 every protected route can return
+struct SessionAuth;
+
+impl Modify for SessionAuth {
+    fn modify(&self, openapi: &mut OpenApiDocument) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "session_token",
+                SecurityScheme::Http(
+                    HttpBuilder::new()
+                        .scheme(HttpAuthScheme::Bearer)
+                        .description(Some("Session token returned by POST /api/login"))
+                        .build(),
+                ),
+            );
+        }
+
+        for path_item in openapi.paths.paths.values_mut() {
+            let operations = [
+                &mut path_item.get,
+                &mut path_item.post,
+                &mut path_item.put,
+                &mut path_item.delete,
+            ];
+            for operation in operations.into_iter().flatten() {
+                // Public operations carry an explicit empty security requirement
+                if operation.security.is_none() {
+                    operation.responses.responses.insert(
+                        "401".to_string(),
+                        ResponseBuilder::new()
+                            .description("Missing or expired session token")
+                            .build()
+                            .into(),
+                    );
+                }
+            }
+        }
+    }
+}
+
+pub async fn get_openapi_json() -> Json<OpenApiDocument> {
+    Json(ApiDoc::openapi())
+}
@@ -0,0 +1,54 @@
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params};
+
+/// Outcome of checking a password against a stored hash
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordCheck {
+    Mismatch,
+    Match,
+    /// Matches, but the stored hash is a legacy format or uses weaker parameters than new
+    /// hashes and should be replaced
+    MatchNeedsRehash,
+}
+
+/// Hash a password with Argon2id and a random salt, as a PHC string
+/// (`$argon2id$v=19$m=...,t=...,p=...$<salt>$<hash>`)
+pub fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut rand::rngs::OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("Argon2 hashing with the default parameters cannot fail")
+        .to_string()
+}
+
+/// Check a password against a stored hash. PHC strings are checked with Argon2, and
+/// anything else is compared as a legacy plaintext value.
+pub fn verify_password(password: &str, stored: &str) -> PasswordCheck {
+    let Ok(hash) = PasswordHash::new(stored) else {
+        return match constant_time_eq(password.as_bytes(), stored.as_bytes()) {
+            true => PasswordCheck::MatchNeedsRehash,
+            false => PasswordCheck::Mismatch,
+        };
+    };
+
+    if Argon2::default().verify_password(password.as_bytes(), &hash).is_err() {
+        return PasswordCheck::Mismatch;
+    }
+    let current = hash.algorithm == Algorithm::default().ident()
+        && Params::try_from(&hash).is_ok_and(|params| {
+            let wanted = Params::default();
+            (params.m_cost(), params.t_cost(), params.p_cost()) == (wanted.m_cost(), wanted.t_cost(), wanted.p_cost())
+        });
+    match current {
+        true => PasswordCheck::Match,
+        false => PasswordCheck::MatchNeedsRehash,
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
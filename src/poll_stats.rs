@@ -0,0 +1,296 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex as StdMutex;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::database::{Database, PollStatsBucket, Quality};
+use crate::metrics::READ_LATENCY_BUCKETS;
+
+/// Width of the time buckets poll outcomes are counted in
+const BUCKET_SECONDS: i64 = 60;
+/// Buckets that started longer ago are deleted when stats are saved
+const RETENTION_HOURS: i64 = 48;
+/// How often counted outcomes are saved to the `poll_stats` table
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Failure reasons a poll is counted under
+pub const REASON_TIMEOUT: &str = "timeout";
+pub const REASON_CONNECT_FAILED: &str = "connect_failed";
+pub const REASON_CONNECTION_LOST: &str = "connection_lost";
+pub const REASON_EXCEPTION: &str = "exception";
+pub const REASON_OTHER: &str = "other";
+
+/// Why a poll failed: no answer in time, a session that broke, a Modbus exception response,
+/// or anything else
+pub fn failure_reason(error: &anyhow::Error) -> &'static str {
+    use std::io::ErrorKind;
+
+    if error.downcast_ref::<tokio::time::error::Elapsed>().is_some() {
+        return REASON_TIMEOUT;
+    }
+    match error.downcast_ref::<std::io::Error>() {
+        Some(e) if e.kind() == ErrorKind::TimedOut => REASON_TIMEOUT,
+        // tokio-modbus reports exception responses as `Other` errors without an OS error
+        Some(e) if e.kind() == ErrorKind::Other && e.raw_os_error().is_none() => REASON_EXCEPTION,
+        Some(_) => REASON_CONNECTION_LOST,
+        None => REASON_OTHER,
+    }
+}
+
+/// Quality of the values a failed read leaves: `CommFail` when the device didn't answer in
+/// time, `Bad` when it refused the read or anything else went wrong
+pub fn failure_quality(error: &anyhow::Error) -> Quality {
+    match failure_reason(error) {
+        REASON_TIMEOUT => Quality::CommFail,
+        _ => Quality::Bad,
+    }
+}
+
+/// Period the polling statistics of a device cover
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum StatsWindow {
+    #[default]
+    #[serde(rename = "1h")]
+    Hour,
+    #[serde(rename = "24h")]
+    Day,
+}
+
+impl StatsWindow {
+    pub fn duration(&self) -> chrono::Duration {
+        match self {
+            StatsWindow::Hour => chrono::Duration::hours(1),
+            StatsWindow::Day => chrono::Duration::hours(24),
+        }
+    }
+}
+
+/// Poll outcomes added up over a window
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct PollSummary {
+    pub polls: u64,
+    pub successes: u64,
+    /// Failed polls, timeouts and failed connects included
+    pub failures: u64,
+    pub timeouts: u64,
+    /// Share of polls that succeeded, from 0 to 1; None without any polls
+    pub success_rate: Option<f64>,
+    /// Latencies are of successful polls only
+    pub latency_avg_ms: Option<f64>,
+    /// Estimated from latency buckets, so accurate to within a bucket
+    pub latency_p50_ms: Option<f64>,
+    pub latency_p95_ms: Option<f64>,
+    /// Failed polls by reason: `timeout`, `connect_failed`, `connection_lost`, `exception` or `other`
+    pub failure_reasons: BTreeMap<String, u64>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+}
+
+impl PollSummary {
+    fn of<'a>(buckets: impl IntoIterator<Item = &'a PollStatsBucket>) -> Self {
+        let mut total: Option<PollStatsBucket> = None;
+        for bucket in buckets {
+            match total.as_mut() {
+                Some(total) => total.merge(bucket),
+                None => total = Some(bucket.clone()),
+            }
+        }
+        let Some(total) = total else {
+            return Self::default();
+        };
+
+        let polls = total.successes + total.failures;
+        let has_latency = total.successes > 0;
+        Self {
+            polls,
+            successes: total.successes,
+            failures: total.failures,
+            timeouts: total.failure_reasons.get(REASON_TIMEOUT).copied().unwrap_or(0),
+            success_rate: (polls > 0).then(|| total.successes as f64 / polls as f64),
+            latency_avg_ms: has_latency.then(|| total.latency_ms_total / total.successes as f64),
+            latency_p50_ms: has_latency.then(|| latency_quantile(&total.latency_buckets, 0.5)),
+            latency_p95_ms: has_latency.then(|| latency_quantile(&total.latency_buckets, 0.95)),
+            failure_reasons: total.failure_reasons,
+            last_error: total.last_error,
+            last_error_at: total.last_error_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ScheduleGroupPollStats {
+    /// `device` for tags polled at the device's own interval
+    pub schedule_group_id: String,
+    #[serde(flatten)]
+    pub summary: PollSummary,
+}
+
+/// Polling statistics of a device over a window, in total and per schedule group
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DevicePollStats {
+    pub device_id: String,
+    pub window: StatsWindow,
+    pub since: DateTime<Utc>,
+    #[serde(flatten)]
+    pub summary: PollSummary,
+    pub schedule_groups: Vec<ScheduleGroupPollStats>,
+}
+
+/// Milliseconds below which a share `quantile` of the counted latencies fall, interpolated
+/// within the bucket it lands in. Latencies above the last bound are reported as that bound.
+fn latency_quantile(buckets: &[u64], quantile: f64) -> f64 {
+    let count: u64 = buckets.iter().sum();
+    let rank = quantile * count as f64;
+    let mut below = 0.0;
+    let mut lower = 0.0;
+    for (bucket, upper) in READ_LATENCY_BUCKETS.iter().enumerate() {
+        let in_bucket = buckets.get(bucket).copied().unwrap_or(0) as f64;
+        if in_bucket > 0.0 && below + in_bucket >= rank {
+            return (lower + (upper - lower) * (rank - below) / in_bucket) * 1000.0;
+        }
+        below += in_bucket;
+        lower = *upper;
+    }
+    lower * 1000.0
+}
+
+fn bucket_start(at: DateTime<Utc>) -> DateTime<Utc> {
+    let seconds = at.timestamp() - at.timestamp().rem_euclid(BUCKET_SECONDS);
+    DateTime::from_timestamp(seconds, 0).unwrap_or(at)
+}
+
+/// Device, schedule group and start of a bucket
+type BucketKey = (String, String, DateTime<Utc>);
+
+/// Counts poll outcomes per device, schedule group and minute.
+///
+/// Outcomes are kept in memory until the next save, which adds them to the `poll_stats` table
+/// so the history survives a restart. Reports combine what is stored with what isn't yet.
+#[derive(Default)]
+pub struct PollStats {
+    pending: StdMutex<HashMap<BucketKey, PollStatsBucket>>,
+}
+
+impl PollStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn update(&self, device_id: &str, schedule_group_id: &str, at: DateTime<Utc>, update: impl FnOnce(&mut PollStatsBucket)) {
+        let start = bucket_start(at);
+        let mut pending = self.pending.lock().unwrap();
+        let bucket = pending
+            .entry((device_id.to_string(), schedule_group_id.to_string(), start))
+            .or_insert_with(|| PollStatsBucket {
+                device_id: device_id.to_string(),
+                schedule_group_id: schedule_group_id.to_string(),
+                bucket_start: start,
+                successes: 0,
+                failures: 0,
+                latency_ms_total: 0.0,
+                latency_buckets: vec![0; READ_LATENCY_BUCKETS.len() + 1],
+                failure_reasons: BTreeMap::new(),
+                last_error: None,
+                last_error_at: None,
+            });
+        update(bucket);
+    }
+
+    pub fn record_success(&self, device_id: &str, schedule_group_id: &str, latency: Duration, at: DateTime<Utc>) {
+        let seconds = latency.as_secs_f64();
+        let slot = READ_LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound).unwrap_or(READ_LATENCY_BUCKETS.len());
+        self.update(device_id, schedule_group_id, at, |bucket| {
+            bucket.successes += 1;
+            bucket.latency_ms_total += seconds * 1000.0;
+            bucket.latency_buckets[slot] += 1;
+        });
+    }
+
+    pub fn record_failure(&self, device_id: &str, schedule_group_id: &str, reason: &str, error: &str, at: DateTime<Utc>) {
+        self.update(device_id, schedule_group_id, at, |bucket| {
+            bucket.failures += 1;
+            *bucket.failure_reasons.entry(reason.to_string()).or_insert(0) += 1;
+            bucket.last_error = Some(error.to_string());
+            bucket.last_error_at = Some(at);
+        });
+    }
+
+    /// Drop outcomes not saved yet of a deleted device
+    pub fn forget_device(&self, device_id: &str) {
+        self.pending.lock().unwrap().retain(|(device, _, _), _| device != device_id);
+    }
+
+    /// Add the outcomes counted since the last save to the database, and delete old buckets.
+    /// Outcomes that fail to save are kept for the next attempt.
+    pub async fn save(&self, database: &Database, now: DateTime<Utc>) -> Result<()> {
+        let pending: Vec<PollStatsBucket> = std::mem::take(&mut *self.pending.lock().unwrap()).into_values().collect();
+        let result = database.save_poll_stats(&pending, now - chrono::Duration::hours(RETENTION_HOURS)).await;
+        if result.is_err() {
+            let mut current = self.pending.lock().unwrap();
+            for bucket in pending {
+                let key = (bucket.device_id.clone(), bucket.schedule_group_id.clone(), bucket.bucket_start);
+                match current.get_mut(&key) {
+                    Some(newer) => newer.merge(&bucket),
+                    None => {
+                        current.insert(key, bucket);
+                    },
+                }
+            }
+        }
+        result
+    }
+
+    /// Stored and unsaved buckets of the minutes from `since` on
+    async fn buckets(&self, database: &Database, device_id: Option<&str>, since: DateTime<Utc>) -> Result<Vec<PollStatsBucket>> {
+        let mut buckets = database.get_poll_stats(device_id, bucket_start(since)).await?;
+        let pending = self.pending.lock().unwrap();
+        buckets.extend(
+            pending
+                .values()
+                .filter(|bucket| device_id.is_none_or(|device_id| bucket.device_id == device_id) && bucket.bucket_start >= bucket_start(since))
+                .cloned(),
+        );
+        Ok(buckets)
+    }
+
+    /// A device's statistics over the window ending `now`
+    pub async fn report(&self, database: &Database, device_id: &str, window: StatsWindow, now: DateTime<Utc>) -> Result<DevicePollStats> {
+        let since = now - window.duration();
+        let buckets = self.buckets(database, Some(device_id), since).await?;
+
+        let mut groups: BTreeMap<&str, Vec<&PollStatsBucket>> = BTreeMap::new();
+        for bucket in &buckets {
+            groups.entry(&bucket.schedule_group_id).or_default().push(bucket);
+        }
+        Ok(DevicePollStats {
+            device_id: device_id.to_string(),
+            window,
+            since,
+            summary: PollSummary::of(&buckets),
+            schedule_groups: groups
+                .into_iter()
+                .map(|(schedule_group_id, buckets)| ScheduleGroupPollStats {
+                    schedule_group_id: schedule_group_id.to_string(),
+                    summary: PollSummary::of(buckets),
+                })
+                .collect(),
+        })
+    }
+
+    /// Share of successful polls of every device polled within the window ending `now`
+    pub async fn success_rates(&self, database: &Database, window: StatsWindow, now: DateTime<Utc>) -> Result<HashMap<String, f64>> {
+        let buckets = self.buckets(database, None, now - window.duration()).await?;
+        let mut devices: HashMap<String, Vec<&PollStatsBucket>> = HashMap::new();
+        for bucket in &buckets {
+            devices.entry(bucket.device_id.clone()).or_default().push(bucket);
+        }
+        Ok(devices
+            .into_iter()
+            .filter_map(|(device_id, buckets)| PollSummary::of(buckets).success_rate.map(|rate| (device_id, rate)))
+            .collect())
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// PDUs a device's trace keeps; older ones are dropped as new ones arrive
+pub const MAX_TRACE_ENTRIES: usize = 2000;
+
+/// Longest a trace may run before it turns itself off
+pub const MAX_TRACE_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceDirection {
+    /// Sent by the logger
+    Tx,
+    /// Received from the device
+    Rx,
+}
+
+/// One PDU sent to or received from a device
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TraceEntry {
+    pub timestamp: DateTime<Utc>,
+    pub direction: TraceDirection,
+    /// The PDU's bytes as space separated hex pairs; empty when nothing arrived, e.g. on a timeout
+    pub hex: String,
+    /// What the PDU says, e.g. `slave 1 read holding registers 100-109`
+    pub summary: String,
+}
+
+/// A device's trace and the PDUs captured since it was last enabled
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ProtocolTraceSnapshot {
+    pub device_id: String,
+    pub enabled: bool,
+    /// When the running trace turns itself off
+    pub until: Option<DateTime<Utc>>,
+    pub max_entries: usize,
+    /// PDUs dropped to stay within `max_entries`
+    pub dropped: u64,
+    /// Oldest first
+    pub entries: Vec<TraceEntry>,
+}
+
+#[derive(Debug, Default)]
+struct TraceBuffer {
+    until: Option<DateTime<Utc>>,
+    entries: VecDeque<TraceEntry>,
+    dropped: u64,
+}
+
+/// The capture of one device's protocol traffic. Clients hold it and report every PDU they
+/// send or receive; while the trace is off that costs them one atomic load.
+#[derive(Debug, Default)]
+pub struct ProtocolTrace {
+    enabled: AtomicBool,
+    buffer: StdMutex<TraceBuffer>,
+}
+
+impl ProtocolTrace {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Record a PDU if the trace is on; `summary` is only built then
+    pub fn record(&self, direction: TraceDirection, bytes: &[u8], summary: impl FnOnce() -> String) {
+        if !self.is_enabled() {
+            return;
+        }
+        let now = Utc::now();
+        let mut buffer = self.buffer.lock().unwrap();
+        if self.expire(&mut buffer, now) {
+            return;
+        }
+        if buffer.entries.len() >= MAX_TRACE_ENTRIES {
+            buffer.entries.pop_front();
+            buffer.dropped += 1;
+        }
+        buffer.entries.push_back(TraceEntry { timestamp: now, direction, hex: hex(bytes), summary: summary() });
+    }
+
+    /// Turn the trace off once its time is up; true if it is off
+    fn expire(&self, buffer: &mut TraceBuffer, now: DateTime<Utc>) -> bool {
+        match buffer.until {
+            Some(until) if now < until => false,
+            _ => {
+                self.enabled.store(false, Ordering::Relaxed);
+                buffer.until = None;
+                true
+            }
+        }
+    }
+
+    /// Start a new capture for `duration`, dropping the PDUs of the last one
+    fn start(&self, duration: Duration) {
+        let mut buffer = self.buffer.lock().unwrap();
+        *buffer = TraceBuffer {
+            until: Some(Utc::now() + chrono::Duration::from_std(duration.min(MAX_TRACE_DURATION)).unwrap_or_default()),
+            ..Default::default()
+        };
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    /// Stop capturing; the PDUs captured stay readable until the next start
+    fn stop(&self) {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.until = None;
+        self.enabled.store(false, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self, device_id: &str) -> ProtocolTraceSnapshot {
+        let mut buffer = self.buffer.lock().unwrap();
+        let enabled = self.is_enabled() && !self.expire(&mut buffer, Utc::now());
+        ProtocolTraceSnapshot {
+            device_id: device_id.to_string(),
+            enabled,
+            until: buffer.until,
+            max_entries: MAX_TRACE_ENTRIES,
+            dropped: buffer.dropped,
+            entries: buffer.entries.iter().cloned().collect(),
+        }
+    }
+}
+
+/// The traces of every device, kept across restarts of a device so a capture can be enabled
+/// before it is started
+#[derive(Default)]
+pub struct ProtocolTraces {
+    devices: StdMutex<HashMap<String, Arc<ProtocolTrace>>>,
+}
+
+impl ProtocolTraces {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The trace a device's clients report to
+    pub fn device(&self, device_id: &str) -> Arc<ProtocolTrace> {
+        self.devices.lock().unwrap().entry(device_id.to_string()).or_default().clone()
+    }
+
+    /// Start capturing a device's PDUs for `duration`, or stop
+    pub fn set(&self, device_id: &str, enabled: bool, duration: Duration) -> ProtocolTraceSnapshot {
+        let trace = self.device(device_id);
+        match enabled {
+            true => trace.start(duration),
+            false => trace.stop(),
+        }
+        trace.snapshot(device_id)
+    }
+
+    pub fn snapshot(&self, device_id: &str) -> ProtocolTraceSnapshot {
+        self.device(device_id).snapshot(device_id)
+    }
+
+    /// Drop the trace of a deleted device
+    pub fn forget(&self, device_id: &str) {
+        self.devices.lock().unwrap().remove(device_id);
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(" ")
+}
+
+/// A trace as text: a header line per PDU followed by its bytes, 16 per line with offsets
+/// and the printable ones alongside
+pub fn hex_dump(snapshot: &ProtocolTraceSnapshot) -> String {
+    let mut dump = format!("# Protocol trace of {}, {} PDUs", snapshot.device_id, snapshot.entries.len());
+    if snapshot.dropped > 0 {
+        let _ = write!(dump, " ({} older ones dropped)", snapshot.dropped);
+    }
+    dump.push('\n');
+
+    for entry in &snapshot.entries {
+        let direction = match entry.direction {
+            TraceDirection::Tx => "TX",
+            TraceDirection::Rx => "RX",
+        };
+        let _ = writeln!(dump, "\n{} {} {}", entry.timestamp.to_rfc3339_opts(chrono::SecondsFormat::Micros, true), direction, entry.summary);
+        let bytes: Vec<u8> = entry.hex.split_whitespace().filter_map(|pair| u8::from_str_radix(pair, 16).ok()).collect();
+        for (line, chunk) in bytes.chunks(16).enumerate() {
+            let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let text: String = chunk.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }).collect();
+            let _ = writeln!(dump, "  {:04x}  {:<47}  |{}|", line * 16, hex.join(" "), text);
+        }
+    }
+    dump
+}
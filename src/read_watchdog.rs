@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::sync::Mutex as StdMutex;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use tokio::sync::watch;
+
+/// Polling intervals a device may miss in a row before it is reported offline
+pub const STALE_AFTER_INTERVALS: u32 = 3;
+
+/// A running device that has gone too long without a successful read
+#[derive(Debug, Clone)]
+pub struct OverdueDevice {
+    pub device_id: String,
+    pub last_successful_read: Option<DateTime<Utc>>,
+    /// Time since the last successful read, or since the device was started if it never read
+    pub silence: Duration,
+    /// Fastest polling interval of the device; it counts as offline after
+    /// `STALE_AFTER_INTERVALS` of them
+    pub interval: Duration,
+}
+
+/// Read health of one device, for status reports
+#[derive(Debug, Clone, Default)]
+pub struct ReadHealth {
+    pub last_successful_read: Option<DateTime<Utc>>,
+    /// Times the device was found offline since the service started
+    pub offline_events: u64,
+}
+
+#[derive(Default)]
+struct WatchedDevice {
+    last_successful_read: Option<DateTime<Utc>>,
+    /// When the device was last started; `None` while it is stopped
+    watched_since: Option<DateTime<Utc>>,
+    /// Intervals of the device's poll groups, followed as schedule groups change
+    intervals: Vec<watch::Receiver<Duration>>,
+    offline: bool,
+    offline_events: u64,
+}
+
+impl WatchedDevice {
+    fn fastest_interval(&self) -> Option<Duration> {
+        self.intervals.iter().map(|interval| *interval.borrow()).min()
+    }
+}
+
+/// Tracks the last successful read of every running device, so a poll task that hangs
+/// without reporting an error is still noticed.
+///
+/// A device is overdue once it has gone `STALE_AFTER_INTERVALS` times its fastest polling
+/// interval without a successful read, and counts as recovered on its next one.
+#[derive(Default)]
+pub struct ReadWatchdog {
+    devices: StdMutex<HashMap<String, WatchedDevice>>,
+}
+
+impl ReadWatchdog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start watching a device that was just started, polled at the given intervals
+    pub fn watch(&self, device_id: &str, intervals: Vec<watch::Receiver<Duration>>, now: DateTime<Utc>) {
+        let mut devices = self.devices.lock().unwrap();
+        let device = devices.entry(device_id.to_string()).or_default();
+        device.watched_since = Some(now);
+        device.intervals = intervals;
+        device.offline = false;
+    }
+
+    /// Stop watching a stopped device. Its last successful read and offline count are kept.
+    pub fn unwatch(&self, device_id: &str) {
+        if let Some(device) = self.devices.lock().unwrap().get_mut(device_id) {
+            device.watched_since = None;
+            device.intervals.clear();
+            device.offline = false;
+        }
+    }
+
+    /// Record a successful read. Returns true if the device had been reported offline.
+    pub fn record_read(&self, device_id: &str, now: DateTime<Utc>) -> bool {
+        let mut devices = self.devices.lock().unwrap();
+        let device = devices.entry(device_id.to_string()).or_default();
+        device.last_successful_read = Some(now);
+        std::mem::take(&mut device.offline)
+    }
+
+    /// Watched devices that have newly gone past their threshold without a successful read.
+    /// Each is marked offline and reported once until it reads again or is restarted.
+    pub fn overdue(&self, now: DateTime<Utc>) -> Vec<OverdueDevice> {
+        let mut devices = self.devices.lock().unwrap();
+        let mut overdue = Vec::new();
+        for (device_id, device) in devices.iter_mut() {
+            let Some(watched_since) = device.watched_since else { continue };
+            if device.offline {
+                continue;
+            }
+            let Some(interval) = device.fastest_interval() else { continue };
+
+            let quiet_since = device.last_successful_read.map_or(watched_since, |read| read.max(watched_since));
+            let silence = (now - quiet_since).to_std().unwrap_or_default();
+            if silence >= interval * STALE_AFTER_INTERVALS {
+                device.offline = true;
+                device.offline_events += 1;
+                overdue.push(OverdueDevice {
+                    device_id: device_id.clone(),
+                    last_successful_read: device.last_successful_read,
+                    silence,
+                    interval,
+                });
+            }
+        }
+        overdue
+    }
+
+    /// Last successful read and offline count of a device
+    pub fn health(&self, device_id: &str) -> ReadHealth {
+        self.devices.lock().unwrap().get(device_id)
+            .map(|device| ReadHealth {
+                last_successful_read: device.last_successful_read,
+                offline_events: device.offline_events,
+            })
+            .unwrap_or_default()
+    }
+}
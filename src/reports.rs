@@ -0,0 +1,477 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+use crate::config::{AppConfig, WebhookEvent};
+use crate::database::{AlarmCondition, DailyReport, Database};
+use crate::logging::LoggingService;
+use crate::notifications::NotificationService;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DailyReportContent {
+    pub date: String,
+    pub plant_name: String,
+    pub generated_at: DateTime<Utc>,
+    pub availability: Option<Vec<DeviceAvailability>>,
+    pub energy: Option<Vec<InverterEnergy>>,
+    pub data_gaps: Option<Vec<DataGap>>,
+    pub gateway_health: Option<GatewayHealth>,
+    pub alarms: Option<Vec<RaisedAlarm>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DeviceAvailability {
+    pub device_id: String,
+    pub device_name: String,
+    pub enabled: bool,
+    pub total_samples: i64,
+    pub good_samples: i64,
+    /// Samples read while a tag was muted; not logged, so not part of `total_samples`
+    #[serde(default)]
+    pub muted_samples: i64,
+    pub availability_percent: f64,
+    pub first_sample: Option<String>,
+    pub last_sample: Option<String>,
+    pub current_status: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct InverterEnergy {
+    pub device_id: String,
+    pub device_name: String,
+    pub tag_name: String,
+    pub unit: Option<String>,
+    pub energy: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DataGap {
+    pub device_id: String,
+    pub device_name: String,
+    pub max_gap_seconds: f64,
+    pub no_data: bool,
+}
+
+/// An alarm raised during the day, whether or not it has cleared since
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RaisedAlarm {
+    pub device_id: String,
+    pub device_name: String,
+    pub tag_name: String,
+    pub condition: AlarmCondition,
+    pub threshold: f64,
+    pub severity: String,
+    pub raised_at: DateTime<Utc>,
+    pub peak_value: f64,
+    pub cleared_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct GatewayHealth {
+    pub version: String,
+    pub database_size_bytes: Option<u64>,
+    pub configured_devices: usize,
+    pub running_devices: usize,
+}
+
+pub struct ReportService {
+    database: Arc<Database>,
+    config: Arc<AppConfig>,
+    logging_service: Arc<LoggingService>,
+    notifications: Arc<NotificationService>,
+}
+
+impl ReportService {
+    pub fn new(
+        database: Arc<Database>,
+        config: Arc<AppConfig>,
+        logging_service: Arc<LoggingService>,
+        notifications: Arc<NotificationService>,
+    ) -> Self {
+        Self {
+            database,
+            config,
+            logging_service,
+            notifications,
+        }
+    }
+
+    /// Generate (or regenerate) the report for a UTC day and store it.
+    ///
+    /// Regenerating a date replaces the stored content but keeps its delivery
+    /// timestamp, so re-running never causes a second webhook delivery.
+    pub async fn generate(&self, date: NaiveDate) -> Result<DailyReport> {
+        let start = Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap());
+        let end = start + Duration::days(1);
+
+        let plant_name = self
+            .database
+            .get_plant_configuration()
+            .await?
+            .map(|plant| plant.plant_name)
+            .unwrap_or_else(|| "Default Plant".to_string());
+        let sections = self.config.reports.sections_for_plant(&plant_name).clone();
+
+        let devices = self.database.get_devices().await?;
+        let device_names: HashMap<String, String> = devices
+            .iter()
+            .map(|device| (device.id.clone(), device.name.clone()))
+            .collect();
+        let device_name = |id: &str| device_names.get(id).cloned().unwrap_or_else(|| id.to_string());
+
+        let day_stats = self.database.get_device_day_stats(start, end).await?;
+        let muted_counts = self.database.get_muted_sample_counts(start, end).await?;
+
+        let availability = if sections.availability {
+            let statuses = self.database.get_all_device_statuses().await?;
+            let mut entries = Vec::new();
+            for device in &devices {
+                let stats = day_stats.iter().find(|s| s.device_id == device.id);
+                let total_samples = stats.map(|s| s.total_samples).unwrap_or(0);
+                let good_samples = stats.map(|s| s.good_samples).unwrap_or(0);
+                entries.push(DeviceAvailability {
+                    device_id: device.id.clone(),
+                    device_name: device.name.clone(),
+                    enabled: device.enabled,
+                    total_samples,
+                    good_samples,
+                    muted_samples: muted_counts.get(&device.id).copied().unwrap_or(0),
+                    availability_percent: if total_samples > 0 {
+                        good_samples as f64 / total_samples as f64 * 100.0
+                    } else {
+                        0.0
+                    },
+                    first_sample: stats.and_then(|s| s.first_sample.clone()),
+                    last_sample: stats.and_then(|s| s.last_sample.clone()),
+                    current_status: statuses
+                        .iter()
+                        .find(|status| status.device_id == device.id)
+                        .map(|status| status.status.clone()),
+                });
+            }
+            Some(entries)
+        } else {
+            None
+        };
+
+        let energy = if sections.energy {
+            let deltas = self.database.get_energy_tag_deltas(start, end).await?;
+            Some(
+                deltas
+                    .into_iter()
+                    .map(|delta| InverterEnergy {
+                        device_name: device_name(&delta.device_id),
+                        device_id: delta.device_id,
+                        tag_name: delta.tag_name,
+                        unit: delta.unit,
+                        energy: (delta.last_value - delta.first_value).max(0.0),
+                    })
+                    .collect(),
+            )
+        } else {
+            None
+        };
+
+        let data_gaps = if sections.data_gaps {
+            let threshold = self.config.reports.data_gap_threshold_seconds as f64;
+            let mut gaps = Vec::new();
+            for device in devices.iter().filter(|device| device.enabled) {
+                match day_stats.iter().find(|s| s.device_id == device.id) {
+                    Some(stats) if stats.max_gap_seconds > threshold => gaps.push(DataGap {
+                        device_id: device.id.clone(),
+                        device_name: device.name.clone(),
+                        max_gap_seconds: stats.max_gap_seconds,
+                        no_data: false,
+                    }),
+                    Some(_) => {}
+                    None => gaps.push(DataGap {
+                        device_id: device.id.clone(),
+                        device_name: device.name.clone(),
+                        max_gap_seconds: 86400.0,
+                        no_data: true,
+                    }),
+                }
+            }
+            Some(gaps)
+        } else {
+            None
+        };
+
+        let gateway_health = if sections.gateway_health {
+            let mut running_devices = 0;
+            for device in &devices {
+                if self.logging_service.is_device_running(&device.id).await {
+                    running_devices += 1;
+                }
+            }
+            Some(GatewayHealth {
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                database_size_bytes: tokio::fs::metadata(&self.config.database.path).await.ok().map(|m| m.len()),
+                configured_devices: devices.len(),
+                running_devices,
+            })
+        } else {
+            None
+        };
+
+        let alarms = if sections.alarms {
+            let events = self.database.get_alarm_events_raised_between(start, end).await?;
+            Some(
+                events
+                    .into_iter()
+                    .map(|event| RaisedAlarm {
+                        device_name: device_name(&event.device_id),
+                        device_id: event.device_id,
+                        tag_name: event.tag_name,
+                        condition: event.condition,
+                        threshold: event.threshold,
+                        severity: event.severity,
+                        raised_at: event.raised_at,
+                        peak_value: event.peak_value,
+                        cleared_at: event.cleared_at,
+                    })
+                    .collect(),
+            )
+        } else {
+            None
+        };
+
+        let content = DailyReportContent {
+            date: date.format("%Y-%m-%d").to_string(),
+            plant_name: plant_name.clone(),
+            generated_at: Utc::now(),
+            availability,
+            energy,
+            data_gaps,
+            gateway_health,
+            alarms,
+        };
+
+        let report = DailyReport {
+            report_date: content.date.clone(),
+            plant_name,
+            generated_at: content.generated_at,
+            summary_text: render_text(&content),
+            content: serde_json::to_string(&content)?,
+            delivered_at: None,
+        };
+
+        self.database.upsert_daily_report(&report).await?;
+        info!("Daily report generated for {}", report.report_date);
+
+        // Re-read so callers see the preserved delivery timestamp
+        Ok(self.database.get_daily_report(&report.report_date).await?.unwrap_or(report))
+    }
+
+    /// Send the report to the webhooks subscribed to `daily_report`, signed and retried like
+    /// every webhook event, and record the delivery. Returns false when none is subscribed.
+    pub async fn deliver(&self, report: &DailyReport) -> Result<bool> {
+        let content: DailyReportContent = serde_json::from_str(&report.content)?;
+        let payload = serde_json::json!({
+            "report_date": report.report_date,
+            "plant_name": report.plant_name,
+            "report": content,
+            "text": report.summary_text,
+            "html": render_html(&content),
+        });
+        let webhooks = self.notifications.webhook_and_wait(WebhookEvent::DailyReport, &payload).await?;
+        if webhooks == 0 {
+            return Ok(false);
+        }
+
+        self.database.mark_daily_report_delivered(&report.report_date).await?;
+        info!("Daily report for {} delivered to {} webhooks", report.report_date, webhooks);
+        Ok(true)
+    }
+
+    /// Generate yesterday's report every night at the configured hour
+    pub fn start_nightly_task(self: &Arc<Self>) {
+        if !self.config.reports.enabled {
+            info!("Nightly reports are disabled");
+            return;
+        }
+
+        let service = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let now = Utc::now();
+                let hour = service.config.reports.run_at_hour_utc.min(23);
+                let mut next_run = Utc.from_utc_datetime(&now.date_naive().and_hms_opt(hour, 0, 0).unwrap());
+                if next_run <= now {
+                    next_run += Duration::days(1);
+                }
+                let wait = (next_run - now).to_std().unwrap_or_default();
+                tokio::time::sleep(wait).await;
+
+                let date = Utc::now().date_naive() - Duration::days(1);
+                match service.generate(date).await {
+                    Ok(report) => {
+                        if report.delivered_at.is_none() {
+                            if let Err(e) = service.deliver(&report).await {
+                                warn!("Failed to deliver daily report for {}: {}", report.report_date, e);
+                                service.notifications.broadcast(
+                                    "webhook_failed",
+                                    "warning",
+                                    format!("Daily report for {} was not delivered", report.report_date),
+                                    format!("Report webhook delivery failed: {}", e),
+                                    Some(("daily_report", &report.report_date)),
+                                ).await;
+                            }
+                        }
+                    }
+                    Err(e) => error!("Failed to generate daily report for {}: {}", date, e),
+                }
+            }
+        });
+    }
+}
+
+/// Plain-text rendering of a report for webhook consumers that just show a message
+pub fn render_text(content: &DailyReportContent) -> String {
+    let mut lines = vec![format!("Daily report for {} - {}", content.plant_name, content.date)];
+
+    if let Some(availability) = &content.availability {
+        lines.push(String::new());
+        lines.push("Device availability:".to_string());
+        for device in availability {
+            lines.push(format!("  {}", availability_line(device)));
+        }
+    }
+
+    if let Some(energy) = &content.energy {
+        lines.push(String::new());
+        lines.push("Energy:".to_string());
+        if energy.is_empty() {
+            lines.push("  No energy tags logged".to_string());
+        }
+        for entry in energy {
+            lines.push(format!("  {}", energy_line(entry)));
+        }
+    }
+
+    if let Some(gaps) = &content.data_gaps {
+        lines.push(String::new());
+        lines.push("Data gaps:".to_string());
+        if gaps.is_empty() {
+            lines.push("  None".to_string());
+        }
+        for gap in gaps {
+            lines.push(format!("  {}", gap_line(gap)));
+        }
+    }
+
+    if let Some(alarms) = &content.alarms {
+        lines.push(String::new());
+        lines.push("Alarms raised:".to_string());
+        if alarms.is_empty() {
+            lines.push("  None".to_string());
+        }
+        for alarm in alarms {
+            lines.push(format!("  {}", alarm_line(alarm)));
+        }
+    }
+
+    if let Some(health) = &content.gateway_health {
+        lines.push(String::new());
+        lines.push(format!("Gateway: {}", gateway_line(health)));
+    }
+
+    lines.join("\n")
+}
+
+fn availability_line(device: &DeviceAvailability) -> String {
+    format!(
+        "{}: {:.1}% ({} of {} samples good){}{}",
+        device.device_name,
+        device.availability_percent,
+        device.good_samples,
+        device.total_samples,
+        if device.muted_samples > 0 { format!(", {} muted", device.muted_samples) } else { String::new() },
+        if device.enabled { "" } else { " [disabled]" }
+    )
+}
+
+fn energy_line(entry: &InverterEnergy) -> String {
+    format!("{} {}: {:.2} {}", entry.device_name, entry.tag_name, entry.energy, entry.unit.as_deref().unwrap_or(""))
+}
+
+fn gap_line(gap: &DataGap) -> String {
+    if gap.no_data {
+        format!("{}: no data", gap.device_name)
+    } else {
+        format!("{}: longest gap {:.0}s", gap.device_name, gap.max_gap_seconds)
+    }
+}
+
+fn alarm_line(alarm: &RaisedAlarm) -> String {
+    format!(
+        "{} {}: {} {} {} at {}, peak {}{}",
+        alarm.device_name,
+        alarm.tag_name,
+        alarm.severity,
+        alarm.condition.as_str(),
+        alarm.threshold,
+        alarm.raised_at.format("%H:%M:%S"),
+        alarm.peak_value,
+        match alarm.cleared_at {
+            Some(cleared_at) => format!(", cleared at {}", cleared_at.format("%H:%M:%S")),
+            None => ", still active".to_string(),
+        }
+    )
+}
+
+fn gateway_line(health: &GatewayHealth) -> String {
+    format!(
+        "v{}, {} of {} devices running, database {} bytes",
+        health.version,
+        health.running_devices,
+        health.configured_devices,
+        health.database_size_bytes.map(|size| size.to_string()).unwrap_or_else(|| "unknown".to_string())
+    )
+}
+
+/// HTML rendering of a report with the same sections as [`render_text`], for webhook
+/// consumers that forward it as an email or show it in a page
+pub fn render_html(content: &DailyReportContent) -> String {
+    let mut html = format!(
+        "<h1>Daily report for {} - {}</h1>\n",
+        escape_html(&content.plant_name),
+        escape_html(&content.date)
+    );
+    let mut section = |title: &str, rows: Vec<String>, empty: &str| {
+        html.push_str(&format!("<h2>{}</h2>\n<ul>\n", title));
+        if rows.is_empty() {
+            html.push_str(&format!("<li>{}</li>\n", empty));
+        }
+        for row in rows {
+            html.push_str(&format!("<li>{}</li>\n", escape_html(&row)));
+        }
+        html.push_str("</ul>\n");
+    };
+
+    if let Some(availability) = &content.availability {
+        section("Device availability", availability.iter().map(availability_line).collect(), "No devices");
+    }
+    if let Some(energy) = &content.energy {
+        section("Energy", energy.iter().map(energy_line).collect(), "No energy tags logged");
+    }
+    if let Some(gaps) = &content.data_gaps {
+        section("Data gaps", gaps.iter().map(gap_line).collect(), "None");
+    }
+    if let Some(alarms) = &content.alarms {
+        section("Alarms raised", alarms.iter().map(alarm_line).collect(), "None");
+    }
+    if let Some(health) = &content.gateway_health {
+        section("Gateway", vec![gateway_line(health)], "");
+    }
+
+    html
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&#39;")
+}
@@ -0,0 +1,347 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{DefaultBodyLimit, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{Json, Response},
+    routing::{get, post},
+    Router,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::sync::Notify;
+use tracing::{error, info, warn};
+
+use crate::api::{ApiError, ApiResponse};
+use crate::config::{load_config, AppConfig};
+use crate::database::Database;
+
+const CONFIG_PATH: &str = "config.toml";
+const EVENTS_PATH: &str = "safe-mode-events.log";
+const DEFAULT_PORT: u16 = 8080;
+const DEFAULT_DATABASE_PATH: &str = "data.db";
+
+/// Recovery endpoints require this key in `X-Recovery-Key` when it is set
+const RECOVERY_KEY_ENV: &str = "AVA_RECOVERY_KEY";
+
+/// Why normal startup was not possible
+#[derive(Debug, Clone, Serialize)]
+pub struct StartupFailure {
+    /// "config" or "database"
+    pub component: &'static str,
+    pub reason: String,
+}
+
+impl StartupFailure {
+    fn config(e: anyhow::Error) -> Self {
+        Self { component: "config", reason: e.to_string() }
+    }
+
+    fn database(e: anyhow::Error) -> Self {
+        Self { component: "database", reason: e.to_string() }
+    }
+}
+
+/// How a safe-mode session ended, reported once the server is healthy again
+pub struct SafeModeSession {
+    pub failure: StartupFailure,
+    pub entered_at: DateTime<Utc>,
+    pub exited_at: DateTime<Utc>,
+}
+
+/// Load the config and open the database, checking its integrity first
+pub async fn preflight() -> Result<(AppConfig, Arc<Database>), StartupFailure> {
+    let config = load_config().await.map_err(StartupFailure::config)?;
+    config
+        .server
+        .validate()
+        .map_err(|e| StartupFailure::config(anyhow::anyhow!("Invalid [server] settings in config.toml: {}", e)))?;
+    Database::check_integrity(&config.database.path).map_err(StartupFailure::database)?;
+    let database = Database::new(&config.database.path).await.map_err(StartupFailure::database)?;
+    Ok((config, Arc::new(database)))
+}
+
+/// Append a safe-mode system event to the events file next to the config.
+///
+/// The database may be the thing that is broken, so these can't go through
+/// the notification center; the recovery is announced there once it is healthy.
+pub async fn record_event(event: &str, detail: &str) {
+    let line = format!("{} {} {}\n", Utc::now().to_rfc3339(), event, detail);
+    let file = tokio::fs::OpenOptions::new().create(true).append(true).open(EVENTS_PATH).await;
+    match file {
+        Ok(mut file) => {
+            if let Err(e) = file.write_all(line.as_bytes()).await {
+                warn!("Failed to write safe mode event: {}", e);
+            }
+        }
+        Err(e) => warn!("Failed to open {}: {}", EVENTS_PATH, e),
+    }
+}
+
+struct SafeModeState {
+    failure: StartupFailure,
+    entered_at: DateTime<Utc>,
+    database_path: String,
+    log_path: Option<String>,
+    recovery_key: Option<String>,
+    exit: Notify,
+}
+
+/// Settings needed to serve safe mode, read leniently from a config that may not parse
+fn lenient_settings() -> (u16, String, Option<String>) {
+    let raw = std::fs::read_to_string(CONFIG_PATH)
+        .ok()
+        .and_then(|content| content.parse::<toml::Table>().ok())
+        .unwrap_or_default();
+
+    let port = raw
+        .get("server")
+        .and_then(|server| server.get("port"))
+        .and_then(|port| port.as_integer())
+        .and_then(|port| u16::try_from(port).ok())
+        .unwrap_or(DEFAULT_PORT);
+    let database_path = raw
+        .get("database")
+        .and_then(|database| database.get("path"))
+        .and_then(|path| path.as_str())
+        .unwrap_or(DEFAULT_DATABASE_PATH)
+        .to_string();
+    let log_path = raw
+        .get("logging")
+        .and_then(|logging| logging.get("file_path"))
+        .and_then(|path| path.as_str())
+        .map(str::to_string);
+    (port, database_path, log_path)
+}
+
+/// Serve the recovery router until an operator asks to leave safe mode
+/// and the startup checks pass again
+pub async fn run(failure: StartupFailure) -> anyhow::Result<SafeModeSession> {
+    let (port, database_path, log_path) = lenient_settings();
+    let recovery_key = std::env::var(RECOVERY_KEY_ENV).ok().filter(|key| !key.is_empty());
+
+    error!("Entering safe mode ({} failure): {}", failure.component, failure.reason);
+    record_event("safe_mode_entered", &format!("{}: {}", failure.component, failure.reason)).await;
+    if recovery_key.is_none() {
+        warn!("{} is not set, safe mode recovery endpoints are unauthenticated", RECOVERY_KEY_ENV);
+    }
+
+    let state = Arc::new(SafeModeState {
+        failure: failure.clone(),
+        entered_at: Utc::now(),
+        database_path,
+        log_path,
+        recovery_key,
+        exit: Notify::new(),
+    });
+
+    let recovery = Router::new()
+        .route("/api/safe-mode/logs", get(download_log))
+        .route("/api/safe-mode/config", get(get_config).put(put_config))
+        .route("/api/safe-mode/database", get(backup_database).put(restore_database))
+        .route("/api/safe-mode/database/reset", post(reset_database))
+        .route("/api/safe-mode/exit", post(exit_safe_mode))
+        .layer(DefaultBodyLimit::disable())
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_recovery_key));
+
+    let app = Router::new()
+        .route("/api/health", get(health))
+        .merge(recovery)
+        .fallback(unavailable)
+        .with_state(state.clone());
+
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let listener = TcpListener::bind(addr).await?;
+    warn!("Safe mode server listening on http://{}", addr);
+
+    let shutdown_state = state.clone();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move { shutdown_state.exit.notified().await })
+        .await?;
+
+    let session = SafeModeSession {
+        failure,
+        entered_at: state.entered_at,
+        exited_at: Utc::now(),
+    };
+    info!("Leaving safe mode");
+    record_event("safe_mode_exited", &format!("after {}s", (session.exited_at - session.entered_at).num_seconds())).await;
+    Ok(session)
+}
+
+async fn require_recovery_key(
+    State(state): State<Arc<SafeModeState>>,
+    headers: HeaderMap,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    if let Some(expected) = &state.recovery_key {
+        let provided = headers.get("X-Recovery-Key").and_then(|value| value.to_str().ok());
+        if provided != Some(expected.as_str()) {
+            return Err(ApiError::unauthorized("Missing or wrong X-Recovery-Key"));
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+async fn health(State(state): State<Arc<SafeModeState>>) -> Json<Value> {
+    Json(json!({
+        "status": "safe_mode",
+        "timestamp": Utc::now().to_rfc3339(),
+        "service": "AVA Device Logger",
+        "version": env!("CARGO_PKG_VERSION"),
+        "safe_mode": {
+            "component": state.failure.component,
+            "reason": state.failure.reason,
+            "since": state.entered_at.to_rfc3339(),
+        }
+    }))
+}
+
+async fn unavailable(State(state): State<Arc<SafeModeState>>) -> ApiError {
+    ApiError::unavailable(format!(
+        "Server is in safe mode because the {} could not be loaded ({}). Only /api/health and /api/safe-mode/* are available.",
+        state.failure.component, state.failure.reason
+    ))
+}
+
+fn error_response(status: StatusCode, message: String) -> ApiError {
+    error!("{}", message);
+    ApiError::new(status, message)
+}
+
+fn file_download(contents: Vec<u8>, content_type: &str, filename: &str) -> Response {
+    Response::builder()
+        .status(200)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename))
+        .body(Body::from(contents))
+        .unwrap()
+}
+
+async fn download_log(State(state): State<Arc<SafeModeState>>) -> Result<Response, ApiError> {
+    let Some(log_path) = &state.log_path else {
+        return Err(error_response(StatusCode::NOT_FOUND, "No log file is configured".to_string()));
+    };
+    match tokio::fs::read(log_path).await {
+        Ok(contents) => Ok(file_download(contents, "text/plain; charset=utf-8", "app.log")),
+        Err(e) => Err(error_response(StatusCode::NOT_FOUND, format!("Failed to read {}: {}", log_path, e))),
+    }
+}
+
+async fn get_config() -> Result<Response, ApiError> {
+    match tokio::fs::read(CONFIG_PATH).await {
+        Ok(contents) => Ok(file_download(contents, "application/toml", CONFIG_PATH)),
+        Err(e) => Err(error_response(StatusCode::NOT_FOUND, format!("Failed to read {}: {}", CONFIG_PATH, e))),
+    }
+}
+
+/// Replace config.toml with the uploaded TOML once it parses; the old file is kept as a backup
+async fn put_config(body: String) -> Result<Json<ApiResponse<String>>, ApiError> {
+    if let Err(e) = toml::from_str::<AppConfig>(&body) {
+        return Err(error_response(StatusCode::BAD_REQUEST, format!("Uploaded config does not parse: {}", e)));
+    }
+
+    let backup_path = format!("{}.{}.bak", CONFIG_PATH, Utc::now().format("%Y%m%d%H%M%S"));
+    if tokio::fs::try_exists(CONFIG_PATH).await.unwrap_or(false) {
+        if let Err(e) = tokio::fs::copy(CONFIG_PATH, &backup_path).await {
+            return Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to back up config: {}", e)));
+        }
+    }
+    if let Err(e) = tokio::fs::write(CONFIG_PATH, body).await {
+        return Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write config: {}", e)));
+    }
+
+    record_event("config_replaced", &format!("previous config saved to {}", backup_path)).await;
+    Ok(Json(ApiResponse::success(format!("Config replaced, previous version saved to {}", backup_path))))
+}
+
+async fn backup_database(State(state): State<Arc<SafeModeState>>) -> Result<Response, ApiError> {
+    match tokio::fs::read(&state.database_path).await {
+        Ok(contents) => Ok(file_download(contents, "application/vnd.sqlite3", "data.db")),
+        Err(e) => Err(error_response(StatusCode::NOT_FOUND, format!("Failed to read {}: {}", state.database_path, e))),
+    }
+}
+
+/// Move the current database file aside so it is kept for analysis
+async fn set_aside_database(database_path: &str) -> std::io::Result<Option<String>> {
+    if !tokio::fs::try_exists(database_path).await.unwrap_or(false) {
+        return Ok(None);
+    }
+    let corrupt_path = format!("{}.corrupt-{}", database_path, Utc::now().format("%Y%m%d%H%M%S"));
+    tokio::fs::rename(database_path, &corrupt_path).await?;
+    // A leftover write-ahead log would otherwise be replayed into the restored file
+    for suffix in ["-wal", "-shm"] {
+        let sidecar = format!("{}{}", database_path, suffix);
+        if tokio::fs::try_exists(&sidecar).await.unwrap_or(false) {
+            tokio::fs::rename(&sidecar, format!("{}{}", corrupt_path, suffix)).await?;
+        }
+    }
+    Ok(Some(corrupt_path))
+}
+
+/// Restore the database from an uploaded backup that passes the integrity check
+async fn restore_database(
+    State(state): State<Arc<SafeModeState>>,
+    body: Bytes,
+) -> Result<Json<ApiResponse<String>>, ApiError> {
+    let staging_path = format!("{}.restore", state.database_path);
+    if let Err(e) = tokio::fs::write(&staging_path, &body).await {
+        return Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to stage backup: {}", e)));
+    }
+    if let Err(e) = Database::check_integrity(&staging_path) {
+        let _ = tokio::fs::remove_file(&staging_path).await;
+        return Err(error_response(StatusCode::BAD_REQUEST, format!("Uploaded backup is not a healthy database: {}", e)));
+    }
+
+    let corrupt_path = match set_aside_database(&state.database_path).await {
+        Ok(corrupt_path) => corrupt_path,
+        Err(e) => return Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to move current database aside: {}", e))),
+    };
+    if let Err(e) = tokio::fs::rename(&staging_path, &state.database_path).await {
+        return Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to restore database: {}", e)));
+    }
+
+    let detail = match corrupt_path {
+        Some(path) => format!("previous database saved to {}", path),
+        None => "no previous database".to_string(),
+    };
+    record_event("database_restored", &detail).await;
+    Ok(Json(ApiResponse::success(format!("Database restored, {}", detail))))
+}
+
+/// Move the broken database aside so an empty one is created on restart
+async fn reset_database(State(state): State<Arc<SafeModeState>>) -> Result<Json<ApiResponse<String>>, ApiError> {
+    match set_aside_database(&state.database_path).await {
+        Ok(corrupt_path) => {
+            let detail = match corrupt_path {
+                Some(path) => format!("previous database saved to {}", path),
+                None => "no previous database".to_string(),
+            };
+            record_event("database_reset", &detail).await;
+            Ok(Json(ApiResponse::success(format!("Database reset, {}", detail))))
+        }
+        Err(e) => Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to reset database: {}", e))),
+    }
+}
+
+/// Re-run the startup checks and resume normal startup if they pass
+async fn exit_safe_mode(State(state): State<Arc<SafeModeState>>) -> Result<Json<ApiResponse<String>>, ApiError> {
+    let config_check = load_config().await.map_err(StartupFailure::config);
+    let check = config_check.and_then(|config| {
+        Database::check_integrity(&config.database.path).map_err(StartupFailure::database)
+    });
+
+    match check {
+        Ok(()) => {
+            state.exit.notify_one();
+            Ok(Json(ApiResponse::success("Startup checks passed, restarting in normal mode".to_string())))
+        }
+        Err(failure) => Err(ApiError::conflict(format!("Still unable to start: {} failure: {}", failure.component, failure.reason))),
+    }
+}
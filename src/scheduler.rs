@@ -0,0 +1,397 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Resource shared by every operation that talks to ThingsBoard
+pub const RESOURCE_THINGSBOARD: &str = "thingsboard";
+/// Resource representing the local SQLite database
+pub const RESOURCE_DATABASE: &str = "database";
+/// Resource representing the Modbus TCP tag register library
+pub const RESOURCE_TAG_LIBRARY: &str = "tag-library";
+
+/// Long-running admin operations that are coordinated by the scheduler
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationKind {
+    Sync,
+    Catalog,
+    Import,
+    Export,
+    Backup,
+    Restore,
+    Vacuum,
+    Reconcile,
+}
+
+impl OperationKind {
+    /// Resource locks this operation needs for its whole duration
+    pub fn locks(&self) -> Vec<ResourceLock> {
+        match self {
+            OperationKind::Sync | OperationKind::Reconcile => vec![
+                ResourceLock::exclusive(RESOURCE_THINGSBOARD),
+                ResourceLock::shared(RESOURCE_DATABASE),
+            ],
+            OperationKind::Catalog => vec![
+                ResourceLock::exclusive(RESOURCE_THINGSBOARD),
+                ResourceLock::shared(RESOURCE_DATABASE),
+            ],
+            OperationKind::Import => vec![
+                ResourceLock::exclusive(RESOURCE_TAG_LIBRARY),
+                ResourceLock::shared(RESOURCE_DATABASE),
+            ],
+            OperationKind::Export | OperationKind::Backup => vec![ResourceLock::shared(RESOURCE_DATABASE)],
+            // Restores and vacuums change the database under every other reader and writer
+            OperationKind::Restore | OperationKind::Vacuum => vec![ResourceLock::exclusive(RESOURCE_DATABASE)],
+        }
+    }
+}
+
+impl std::fmt::Display for OperationKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            OperationKind::Sync => "sync",
+            OperationKind::Catalog => "catalog",
+            OperationKind::Import => "import",
+            OperationKind::Export => "export",
+            OperationKind::Backup => "backup",
+            OperationKind::Restore => "restore",
+            OperationKind::Vacuum => "vacuum",
+            OperationKind::Reconcile => "reconcile",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ResourceLock {
+    pub resource: String,
+    pub exclusive: bool,
+}
+
+impl ResourceLock {
+    pub fn exclusive(resource: &str) -> Self {
+        Self { resource: resource.to_string(), exclusive: true }
+    }
+
+    pub fn shared(resource: &str) -> Self {
+        Self { resource: resource.to_string(), exclusive: false }
+    }
+
+    fn conflicts_with(&self, other: &ResourceLock) -> bool {
+        self.resource == other.resource && (self.exclusive || other.exclusive)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationState {
+    Running,
+    Queued,
+}
+
+/// An operation as shown by `GET /api/jobs/queue`
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ScheduledOperation {
+    pub job_id: String,
+    pub kind: OperationKind,
+    pub description: String,
+    pub requested_by: Option<String>,
+    pub locks: Vec<ResourceLock>,
+    pub state: OperationState,
+    /// 1-based position in the queue, None while running
+    pub queue_position: Option<usize>,
+    /// Jobs that currently prevent this operation from starting
+    pub blocked_by: Vec<String>,
+    pub queued_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+}
+
+impl ScheduledOperation {
+    fn conflicts_with(&self, other: &ScheduledOperation) -> bool {
+        self.locks
+            .iter()
+            .any(|lock| other.locks.iter().any(|held| lock.conflicts_with(held)))
+    }
+}
+
+/// Returned when a manual request conflicts with an operation of the same kind
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct OperationConflict {
+    pub requested: OperationKind,
+    pub blocking_job_id: String,
+    pub blocking_kind: OperationKind,
+    pub blocking_description: String,
+}
+
+impl std::fmt::Display for OperationConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Cannot start {}: {} job {} ({}) is already running or queued",
+            self.requested, self.blocking_kind, self.blocking_job_id, self.blocking_description
+        )
+    }
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    /// Held locks keyed by resource name; BTreeMap keeps acquisition ordered
+    held: BTreeMap<String, Vec<(String, bool)>>,
+    running: Vec<ScheduledOperation>,
+    queue: VecDeque<ScheduledOperation>,
+}
+
+impl SchedulerState {
+    /// An operation may start when none of its locks conflict with a held one and it
+    /// conflicts with nothing queued ahead of it, so conflicting work runs in FIFO order.
+    fn blockers(&self, operation: &ScheduledOperation) -> Vec<String> {
+        let mut blockers: Vec<String> = Vec::new();
+        for lock in &operation.locks {
+            for (holder, exclusive) in self.held.get(&lock.resource).into_iter().flatten() {
+                if (lock.exclusive || *exclusive) && !blockers.contains(holder) {
+                    blockers.push(holder.clone());
+                }
+            }
+        }
+
+        for queued in &self.queue {
+            if queued.job_id == operation.job_id {
+                break;
+            }
+            if queued.conflicts_with(operation) {
+                blockers.push(queued.job_id.clone());
+            }
+        }
+
+        blockers
+    }
+
+    fn acquire(&mut self, mut operation: ScheduledOperation) {
+        let mut locks = operation.locks.clone();
+        locks.sort_by(|a, b| a.resource.cmp(&b.resource));
+        for lock in &locks {
+            self.held
+                .entry(lock.resource.clone())
+                .or_default()
+                .push((operation.job_id.clone(), lock.exclusive));
+        }
+
+        operation.state = OperationState::Running;
+        operation.started_at = Some(Utc::now());
+        self.running.push(operation);
+    }
+
+    fn release(&mut self, job_id: &str) {
+        self.running.retain(|operation| operation.job_id != job_id);
+        self.queue.retain(|operation| operation.job_id != job_id);
+        self.held.retain(|_, holders| {
+            holders.retain(|(holder, _)| holder != job_id);
+            !holders.is_empty()
+        });
+    }
+}
+
+/// Coordinates mutually exclusive admin operations.
+///
+/// Every operation declares its resource locks up front and acquires all of
+/// them atomically in resource-name order, so two operations can never hold
+/// one lock each while waiting for the other.
+#[derive(Clone, Default)]
+pub struct OperationScheduler {
+    state: Arc<Mutex<SchedulerState>>,
+    notify: Arc<Notify>,
+}
+
+impl OperationScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start an operation for a manual request.
+    ///
+    /// If an operation of the same kind is already running or queued the
+    /// request is rejected with the blocking job; otherwise the operation waits
+    /// behind any conflicting work and starts as soon as its locks are free.
+    pub async fn start(
+        &self,
+        kind: OperationKind,
+        description: &str,
+        requested_by: Option<String>,
+    ) -> Result<OperationGuard, OperationConflict> {
+        Ok(self.enqueue(kind, description, requested_by)?.wait().await)
+    }
+
+    /// Register an operation without waiting for it to start.
+    ///
+    /// Same-kind conflicts are reported immediately, so a handler can answer
+    /// the request and hand the returned [`PendingOperation`] to a background
+    /// task that waits for the locks.
+    pub fn enqueue(
+        &self,
+        kind: OperationKind,
+        description: &str,
+        requested_by: Option<String>,
+    ) -> Result<PendingOperation, OperationConflict> {
+        let operation = ScheduledOperation {
+            job_id: Uuid::new_v4().to_string(),
+            kind,
+            description: description.to_string(),
+            requested_by,
+            locks: kind.locks(),
+            state: OperationState::Queued,
+            queue_position: None,
+            blocked_by: Vec::new(),
+            queued_at: Utc::now(),
+            started_at: None,
+        };
+        let job_id = operation.job_id.clone();
+
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(existing) = state
+                .running
+                .iter()
+                .chain(state.queue.iter())
+                .find(|existing| existing.kind == kind)
+            {
+                return Err(OperationConflict {
+                    requested: kind,
+                    blocking_job_id: existing.job_id.clone(),
+                    blocking_kind: existing.kind,
+                    blocking_description: existing.description.clone(),
+                });
+            }
+
+            if state.blockers(&operation).is_empty() {
+                info!("Starting {} operation {}", kind, job_id);
+                state.acquire(operation);
+                return Ok(PendingOperation { scheduler: self.clone(), kind, job_id: Some(job_id), started: true });
+            }
+
+            info!("Queueing {} operation {} behind conflicting work", kind, job_id);
+            state.queue.push_back(operation);
+        }
+
+        Ok(PendingOperation { scheduler: self.clone(), kind, job_id: Some(job_id), started: false })
+    }
+
+    async fn wait_for_locks(&self, pending: &mut PendingOperation) -> OperationGuard {
+        let kind = pending.kind;
+        let job_id = pending.job_id().to_string();
+
+        loop {
+            let notified = self.notify.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                let position = state.queue.iter().position(|queued| queued.job_id == job_id);
+                if let Some(position) = position {
+                    if state.blockers(&state.queue[position]).is_empty() {
+                        let operation = state.queue.remove(position).unwrap();
+                        info!("Starting queued {} operation {}", kind, job_id);
+                        state.acquire(operation);
+                        pending.job_id = None;
+                        return OperationGuard { scheduler: self.clone(), job_id };
+                    }
+                } else {
+                    warn!("Queued operation {} disappeared from the scheduler", job_id);
+                    pending.job_id = None;
+                    return OperationGuard { scheduler: self.clone(), job_id };
+                }
+            }
+            notified.await;
+        }
+    }
+
+    /// Snapshot of running and queued operations
+    pub fn snapshot(&self) -> Vec<ScheduledOperation> {
+        let state = self.state.lock().unwrap();
+        let mut operations: Vec<ScheduledOperation> = state.running.clone();
+
+        for (index, queued) in state.queue.iter().enumerate() {
+            let mut entry = queued.clone();
+            entry.queue_position = Some(index + 1);
+            entry.blocked_by = state.blockers(queued);
+            operations.push(entry);
+        }
+
+        operations
+    }
+
+    /// Position of a job in the queue (1-based), None if running or unknown
+    pub fn queue_position(&self, job_id: &str) -> Option<usize> {
+        let state = self.state.lock().unwrap();
+        state
+            .queue
+            .iter()
+            .position(|queued| queued.job_id == job_id)
+            .map(|index| index + 1)
+    }
+
+    fn release(&self, job_id: &str) {
+        self.state.lock().unwrap().release(job_id);
+        self.notify.notify_waiters();
+    }
+}
+
+/// Held while an operation runs; releases its locks when dropped
+pub struct OperationGuard {
+    scheduler: OperationScheduler,
+    job_id: String,
+}
+
+impl OperationGuard {
+    pub fn job_id(&self) -> &str {
+        &self.job_id
+    }
+}
+
+impl Drop for OperationGuard {
+    fn drop(&mut self) {
+        info!("Operation {} finished, releasing locks", self.job_id);
+        self.scheduler.release(&self.job_id);
+    }
+}
+
+/// An operation registered with the scheduler that has not been handed its
+/// guard yet; dropping it removes the queue entry (e.g. client disconnect)
+pub struct PendingOperation {
+    scheduler: OperationScheduler,
+    kind: OperationKind,
+    job_id: Option<String>,
+    started: bool,
+}
+
+impl PendingOperation {
+    pub fn job_id(&self) -> &str {
+        self.job_id.as_deref().unwrap_or_default()
+    }
+
+    pub fn kind(&self) -> OperationKind {
+        self.kind
+    }
+
+    /// Wait until the operation's locks are free and it is running
+    pub async fn wait(mut self) -> OperationGuard {
+        let scheduler = self.scheduler.clone();
+        if self.started {
+            let job_id = self.job_id.take().unwrap_or_default();
+            return OperationGuard { scheduler, job_id };
+        }
+        scheduler.wait_for_locks(&mut self).await
+    }
+}
+
+impl Drop for PendingOperation {
+    fn drop(&mut self) {
+        if let Some(job_id) = self.job_id.take() {
+            info!("Queued operation {} abandoned before starting", job_id);
+            self.scheduler.release(&job_id);
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::f64::consts::TAU;
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tracing::{info, warn, error};
+
+use crate::config::{DataType, DeviceConfig, ProtocolConfig, RegisterRead, SimulatedConfig, SimulatedPattern};
+use crate::database::{LogEntry, Database, DeviceTag, TagWriteResult};
+use crate::modbus::{decode_registers, encode_registers};
+
+/// A device whose values are generated locally from per-tag patterns, so the whole pipeline
+/// can be demonstrated or tested without hardware or a network
+pub struct SimulatedClient {
+    device_config: DeviceConfig,
+    config: SimulatedConfig,
+    connected: bool,
+    /// Boxed, as it is several times the size of the other clients
+    rng: Box<StdRng>,
+    /// Current position of each random walk, by tag name
+    walks: HashMap<String, f64>,
+    /// Values of each replayed tag and the index of the next one, by tag name
+    replays: HashMap<String, (Vec<f64>, usize)>,
+    /// Raw values written to tags, which they hold instead of their pattern from then on
+    written: HashMap<String, f64>,
+    /// Tag names by address, for one-off reads of a tag that was polled before
+    addresses: HashMap<u16, String>,
+}
+
+impl SimulatedClient {
+    pub fn new(device_config: DeviceConfig) -> Self {
+        let config = match &device_config.protocol {
+            ProtocolConfig::Simulated(config) => config.clone(),
+            _ => SimulatedConfig::default(),
+        };
+        let rng = Box::new(match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        });
+        Self {
+            device_config,
+            config,
+            connected: false,
+            rng,
+            walks: HashMap::new(),
+            replays: HashMap::new(),
+            written: HashMap::new(),
+            addresses: HashMap::new(),
+        }
+    }
+
+    /// Load the files of replayed tags; fails while the device is in a simulated outage
+    pub async fn connect(&mut self) -> Result<()> {
+        self.check_dropout(Utc::now())?;
+        for (tag_name, pattern) in &self.config.patterns {
+            if let SimulatedPattern::Replay { file, column } = pattern {
+                if !self.replays.contains_key(tag_name) {
+                    let values = load_replay(file, column)?;
+                    info!("Loaded {} values of column '{}' from {} for tag {}", values.len(), column, file, tag_name);
+                    self.replays.insert(tag_name.clone(), (values, 0));
+                }
+            }
+        }
+        self.connected = true;
+        info!("Simulating device {}", self.device_config.id);
+        Ok(())
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    pub async fn disconnect(&mut self) {
+        self.connected = false;
+    }
+
+    pub async fn read_specific_tags(&mut self, database: &Database, device_tags: &[DeviceTag]) -> Result<Vec<LogEntry>> {
+        let timestamp = Utc::now();
+        if let Err(e) = self.check_dropout(timestamp) {
+            self.connected = false;
+            return Err(e);
+        }
+        let muted_tags = database.get_muted_tag_names(&self.device_config.id).await.unwrap_or_else(|e| {
+            error!("Failed to load tag mutes for device {}: {}", self.device_config.id, e);
+            Default::default()
+        });
+
+        let mut log_entries = Vec::new();
+        for device_tag in device_tags.iter().filter(|tag| tag.enabled) {
+            self.addresses.insert(device_tag.address, device_tag.name.clone());
+            let bad = self.config.bad_quality_probability > 0.0 && self.rng.gen_bool(self.config.bad_quality_probability.min(1.0));
+            let read = match bad {
+                true => Err(anyhow!("Simulated bad quality")),
+                false => self.raw_value(&device_tag.name, device_tag.address, timestamp)
+                    .and_then(|raw| fit_data_type(&device_tag.data_type, raw)),
+            };
+            let entry = match read {
+                Ok(raw) => {
+                    let muted = muted_tags.contains(&device_tag.name);
+                    if muted {
+                        // Muted tags are still read so recovery is visible, but never logged
+                        if let Err(e) = database.record_muted_sample(&self.device_config.id, &device_tag.name, timestamp).await {
+                            error!("Failed to record muted sample: {}", e);
+                        }
+                    }
+                    LogEntry {
+                        id: None,
+                        device_id: self.device_config.id.clone(),
+                        tag_name: device_tag.name.clone(),
+                        value: raw * device_tag.scaling_multiplier + device_tag.scaling_offset,
+                        quality: if muted { "muted" } else { "Good" }.to_string(),
+                        timestamp,
+                        unit: device_tag.unit.clone(),
+                    }
+                },
+                Err(e) => {
+                    warn!("Failed to read tag {}: {}", device_tag.name, e);
+                    LogEntry {
+                        id: None,
+                        device_id: self.device_config.id.clone(),
+                        tag_name: device_tag.name.clone(),
+                        value: 0.0,
+                        quality: "Bad".to_string(),
+                        timestamp,
+                        unit: device_tag.unit.clone(),
+                    }
+                },
+            };
+            log_entries.push(entry);
+        }
+
+        Ok(log_entries)
+    }
+
+    /// Make a tag hold the written value instead of following its pattern
+    pub async fn write_tag(&mut self, device_tag: &DeviceTag, value: f64) -> Result<TagWriteResult> {
+        self.check_dropout(Utc::now())?;
+        let raw = fit_data_type(&device_tag.data_type, device_tag.raw_value(value).map_err(|e| anyhow!(e))?)?;
+        let previous_value = self.raw_value(&device_tag.name, device_tag.address, Utc::now()).ok()
+            .map(|previous| previous * device_tag.scaling_multiplier + device_tag.scaling_offset);
+        self.written.insert(device_tag.name.clone(), raw);
+        info!("Wrote {} to simulated tag {} of device {}", value, device_tag.name, self.device_config.id);
+        Ok(TagWriteResult {
+            tag_name: device_tag.name.clone(),
+            requested_value: value,
+            previous_value,
+            raw_value: raw,
+            registers: Vec::new(),
+            read_back: Some(raw * device_tag.scaling_multiplier + device_tag.scaling_offset),
+        })
+    }
+
+    /// Unscaled value of the polled tag at an address, or of an address no tag was polled at
+    pub async fn read_point(&mut self, read: &RegisterRead) -> Result<f64> {
+        self.check_dropout(Utc::now())?;
+        let tag_name = self.addresses.get(&read.address).cloned().unwrap_or_default();
+        self.raw_value(&tag_name, read.address, Utc::now())
+    }
+
+    fn check_dropout(&self, now: DateTime<Utc>) -> Result<()> {
+        match &self.config.dropout {
+            Some(dropout) if dropout.is_down(now) => Err(std::io::Error::new(
+                std::io::ErrorKind::ConnectionRefused,
+                format!("Simulated device {} is in a {}s outage", self.device_config.id, dropout.seconds),
+            ).into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Value of a tag before scaling; tags without a pattern hold their own address
+    fn raw_value(&mut self, tag_name: &str, address: u16, now: DateTime<Utc>) -> Result<f64> {
+        if let Some(written) = self.written.get(tag_name) {
+            return Ok(*written);
+        }
+        let seconds = now.timestamp_millis() as f64 / 1000.0;
+        Ok(match self.config.patterns.get(tag_name) {
+            None => address as f64,
+            Some(SimulatedPattern::Constant { value }) => *value,
+            Some(SimulatedPattern::Sine { amplitude, period_seconds, offset }) => {
+                offset + amplitude * (TAU * seconds / period_seconds).sin()
+            },
+            Some(SimulatedPattern::Ramp { from, to, period_seconds }) => {
+                from + (to - from) * (seconds.rem_euclid(*period_seconds) / period_seconds)
+            },
+            Some(SimulatedPattern::RandomWalk { start, step, min, max }) => {
+                let current = self.walks.get(tag_name).copied().unwrap_or(*start);
+                let mut next = current + if *step > 0.0 { self.rng.gen_range(-step..=*step) } else { 0.0 };
+                if let Some(min) = min {
+                    next = next.max(*min);
+                }
+                if let Some(max) = max {
+                    next = next.min(*max);
+                }
+                self.walks.insert(tag_name.to_string(), next);
+                next
+            },
+            Some(SimulatedPattern::Replay { file, .. }) => {
+                let (values, next) = self.replays.get_mut(tag_name)
+                    .ok_or_else(|| anyhow!("Replay file {} of tag {} is not loaded", file, tag_name))?;
+                let value = values[*next];
+                *next = (*next + 1) % values.len();
+                value
+            },
+        })
+    }
+}
+
+/// Round a generated value the way a device would store it in the tag's data type
+fn fit_data_type(data_type: &str, raw: f64) -> Result<f64> {
+    let data_type = DataType::from_tag_type(data_type).unwrap_or(DataType::HoldingRegister);
+    match data_type {
+        DataType::Coil | DataType::DiscreteInput => Ok(if raw != 0.0 { 1.0 } else { 0.0 }),
+        _ => decode_registers(&data_type, None, &encode_registers(&data_type, None, raw)?),
+    }
+}
+
+/// Values of one column of a CSV file with a header row, skipping cells that aren't numbers
+fn load_replay(file: &str, column: &str) -> Result<Vec<f64>> {
+    let mut reader = csv::Reader::from_path(file).map_err(|e| anyhow!("Failed to open replay file {}: {}", file, e))?;
+    let index = reader.headers()?.iter().position(|header| header.trim() == column)
+        .ok_or_else(|| anyhow!("Replay file {} has no column '{}'", file, column))?;
+    let mut values = Vec::new();
+    for record in reader.records() {
+        if let Some(value) = record?.get(index).and_then(|cell| cell.trim().parse::<f64>().ok()) {
+            values.push(value);
+        }
+    }
+    if values.is_empty() {
+        return Err(anyhow!("Column '{}' of replay file {} has no numbers", column, file));
+    }
+    Ok(values)
+}
@@ -0,0 +1,64 @@
+use tracing::warn;
+
+/// Where a tag belongs in an inverter's hierarchy, read from its description
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceType {
+    Inverter,
+    Mppt(u32), // MPPT number
+    String(u32, u32), // MPPT number, Input number
+    Unknown,
+}
+
+/// Parse a tag description such as "Inverter (SG150CX)", "MPPT - MPPT 1 (SG150CX)" or
+/// "String - MPPT 1 - Input 12 (SG150CX)".
+///
+/// Case, spacing around words and dashes, leading zeros and the parenthesized model are
+/// all optional. MPPT and input numbers start at 1. A description that names an MPPT or
+/// String but doesn't follow the format is `Unknown` and logged, so it isn't grouped
+/// under the wrong device.
+pub fn parse(description: &str) -> DeviceType {
+    let text = without_model(description).to_ascii_lowercase();
+    let parts: Vec<String> = text.split('-').map(|part| part.split_whitespace().collect::<Vec<_>>().join(" ")).collect();
+
+    let parsed = match parts.first().map(String::as_str) {
+        Some(first) if first.starts_with("inverter") => return DeviceType::Inverter,
+        Some("mppt") => match &parts[1..] {
+            [mppt] => numbered(mppt, "mppt").map(DeviceType::Mppt),
+            _ => None,
+        },
+        Some("string") => match &parts[1..] {
+            [mppt, input] => numbered(mppt, "mppt").zip(numbered(input, "input")).map(|(mppt, input)| DeviceType::String(mppt, input)),
+            _ => None,
+        },
+        _ => return DeviceType::Unknown,
+    };
+
+    parsed.unwrap_or_else(|| {
+        warn!("Tag description '{}' doesn't match the MPPT or String format", description);
+        DeviceType::Unknown
+    })
+}
+
+/// Model from the parenthesized suffix of a description, e.g. "SG150CX"
+pub fn model(description: &str) -> Option<&str> {
+    let start = description.rfind('(')?;
+    let end = description.rfind(')')?;
+    (end > start).then(|| description[start + 1..end].trim())
+}
+
+/// The description up to its parenthesized model, if any
+fn without_model(description: &str) -> &str {
+    match description.find('(') {
+        Some(start) => &description[..start],
+        None => description,
+    }
+}
+
+/// Number of a part like "mppt 1", "mppt1" or "input 012"
+fn numbered(part: &str, keyword: &str) -> Option<u32> {
+    let number = part.strip_prefix(keyword)?.trim_start();
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    number.parse().ok().filter(|number| *number > 0)
+}
@@ -1,16 +1,30 @@
 use reqwest::{Client, Error as ReqwestError};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use std::collections::HashMap;
 use std::fs::File;
+use std::io::Write;
 use csv::Writer;
-use crate::database::{DeviceInstance, Database, DeviceTag}; // Import for hierarchical device analysis
-use tracing::warn;
+use crate::catalog::{self, CatalogDevice, CATALOG_FORMAT_VERSION, CatalogRow, CatalogRowBuilder, CatalogSource, ScheduleLookup};
+use crate::config::{AppConfig, PvNaming};
+use crate::tag_description::{self, DeviceType};
+use crate::database::{DeviceInstance, Database, DeviceTag, FailedRequest, SyncReport, TbChildDevice}; // Import for hierarchical device analysis
+use chrono::Utc;
+use tracing::{debug, info, warn};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
 
 #[derive(Debug)]
 pub enum TbError {
     Http(ReqwestError),
     Auth(String),
     Api(String),
+    /// The requested entity doesn't exist (404)
+    NotFound(String),
+    NotConfigured,
+    /// A transient failure that persisted through every retry
+    RetriesExhausted { attempts: u32, last: Box<TbError> },
 }
 
 impl std::fmt::Display for TbError {
@@ -19,6 +33,9 @@ impl std::fmt::Display for TbError {
             TbError::Http(err) => write!(f, "HTTP error: {}", err),
             TbError::Auth(msg) => write!(f, "Authentication error: {}", msg),
             TbError::Api(msg) => write!(f, "API error: {}", msg),
+            TbError::NotFound(msg) => write!(f, "Not found: {}", msg),
+            TbError::NotConfigured => write!(f, "ThingsBoard is not configured; add a [thingsboard] section to config.toml"),
+            TbError::RetriesExhausted { attempts, last } => write!(f, "{} (gave up after {} attempts)", last, attempts),
         }
     }
 }
@@ -29,6 +46,9 @@ impl std::error::Error for TbError {
             TbError::Http(err) => Some(err),
             TbError::Auth(_) => None,
             TbError::Api(_) => None,
+            TbError::NotFound(_) => None,
+            TbError::NotConfigured => None,
+            TbError::RetriesExhausted { last, .. } => Some(last.as_ref()),
         }
     }
 }
@@ -39,20 +59,314 @@ impl From<ReqwestError> for TbError {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// How often transient ThingsBoard failures (5xx, 429, connection errors) are retried
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Total attempts including the first one
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubled for every retry after that
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn delay_before_retry(&self, retry: u32) -> Duration {
+        self.base_delay.saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+    }
+}
+
+/// Token bucket pacing requests to ThingsBoard. Shared by every client of the
+/// logger, so concurrent syncs, catalog exports and telemetry pushes together
+/// stay under the server's rate limit.
+pub struct RateLimiter {
+    /// 0 lets every request through at once
+    requests_per_second: f64,
+    bucket: StdMutex<TokenBucket>,
+}
+
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+    // Set from a 429's Retry-After; no request is sent before it
+    paused_until: Option<Instant>,
+}
+
+impl RateLimiter {
+    /// A full bucket holds one second's worth of requests
+    pub fn new(requests_per_second: f64) -> Self {
+        let requests_per_second = if requests_per_second.is_finite() { requests_per_second.max(0.0) } else { 0.0 };
+        Self {
+            requests_per_second,
+            bucket: StdMutex::new(TokenBucket {
+                tokens: requests_per_second.max(1.0),
+                refilled_at: Instant::now(),
+                paused_until: None,
+            }),
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        Self::new(0.0)
+    }
+
+    /// Wait until a request may be sent; returns whether it had to wait
+    pub async fn acquire(&self) -> bool {
+        let mut waited = false;
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap();
+                let now = Instant::now();
+                match bucket.paused_until {
+                    Some(until) if until > now => until - now,
+                    _ if self.requests_per_second <= 0.0 => return waited,
+                    _ => {
+                        let capacity = self.requests_per_second.max(1.0);
+                        let refill = now.duration_since(bucket.refilled_at).as_secs_f64() * self.requests_per_second;
+                        bucket.tokens = (bucket.tokens + refill).min(capacity);
+                        bucket.refilled_at = now;
+                        if bucket.tokens >= 1.0 {
+                            bucket.tokens -= 1.0;
+                            return waited;
+                        }
+                        Duration::from_secs_f64((1.0 - bucket.tokens) / self.requests_per_second)
+                    }
+                }
+            };
+            waited = true;
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Hold back every request for `duration`, as a 429 response asks
+    pub fn pause_for(&self, duration: Duration) {
+        let until = Instant::now() + duration;
+        let mut bucket = self.bucket.lock().unwrap();
+        if bucket.paused_until.is_none_or(|paused_until| paused_until < until) {
+            bucket.paused_until = Some(until);
+        }
+        bucket.tokens = 0.0;
+    }
+}
+
+/// Seconds to wait from a response's `Retry-After` header; HTTP dates aren't supported
+fn retry_after_header(response: &reqwest::Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+fn is_transient_status(status: reqwest::StatusCode) -> bool {
+    status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+}
+
+fn is_transient_error(error: &ReqwestError) -> bool {
+    error.is_connect() || error.is_timeout() || error.is_request()
+}
+
+/// Longest ThingsBoard error message that is passed on to API responses
+pub const MAX_SAFE_ERROR_LEN: usize = 200;
+
+static LOG_PAYLOADS: AtomicBool = AtomicBool::new(false);
+
+/// Enable debug logging of unsanitized ThingsBoard error payloads
+pub fn set_payload_logging(enabled: bool) {
+    LOG_PAYLOADS.store(enabled, Ordering::Relaxed);
+}
+
+pub fn payload_logging_enabled() -> bool {
+    LOG_PAYLOADS.load(Ordering::Relaxed)
+}
+
+/// Scrub secrets out of a message and truncate it to a safe length
+pub fn sanitize_message(message: &str, secrets: &[&str]) -> String {
+    let mut sanitized = message.to_string();
+    for secret in secrets.iter().filter(|secret| !secret.is_empty()) {
+        sanitized = sanitized.replace(secret, "***");
+    }
+
+    if sanitized.chars().count() > MAX_SAFE_ERROR_LEN {
+        sanitized = sanitized.chars().take(MAX_SAFE_ERROR_LEN).collect::<String>() + "...";
+    }
+    sanitized
+}
+
+impl TbError {
+    /// Error text that is safe to return to API clients
+    pub fn sanitized(&self, secrets: &[&str]) -> String {
+        sanitize_message(&self.to_string(), secrets)
+    }
+}
+
+/// Hit/miss counters for the group device cache
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct GroupDeviceCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub cached_groups: usize,
+}
+
+/// Short-lived cache of entity group device listings, shared by every
+/// ThingsBoardClient created for a multi-step operation (sync, catalog export)
+pub struct GroupDeviceCache {
+    ttl: Duration,
+    max_devices_per_group: usize,
+    entries: StdMutex<HashMap<String, (Instant, Vec<DeviceData>)>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl GroupDeviceCache {
+    pub fn new(ttl: Duration, max_devices_per_group: usize) -> Self {
+        Self {
+            ttl,
+            max_devices_per_group,
+            entries: StdMutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Cached listing for the group, if present and younger than the TTL
+    pub fn get(&self, entity_group_id: &str) -> Option<Vec<DeviceData>> {
+        let mut entries = self.entries.lock().unwrap();
+        let cached = match entries.get(entity_group_id) {
+            Some((fetched_at, devices)) if fetched_at.elapsed() < self.ttl => Some(devices.clone()),
+            Some(_) => {
+                entries.remove(entity_group_id);
+                None
+            }
+            None => None,
+        };
+
+        let counter = if cached.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        cached
+    }
+
+    /// Store a listing; groups larger than the per-group limit are never cached
+    pub fn insert(&self, entity_group_id: &str, devices: &[DeviceData]) -> bool {
+        if devices.len() > self.max_devices_per_group {
+            return false;
+        }
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(entity_group_id.to_string(), (Instant::now(), devices.to_vec()));
+        true
+    }
+
+    /// Drop the listing after devices in the group were created, renamed or deleted
+    pub fn invalidate(&self, entity_group_id: &str) {
+        self.entries.lock().unwrap().remove(entity_group_id);
+    }
+
+    pub fn stats(&self) -> GroupDeviceCacheStats {
+        GroupDeviceCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            cached_groups: self.entries.lock().unwrap().len(),
+        }
+    }
+}
+
+/// Login and refresh counters for a shared ThingsBoard session
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TbSessionStats {
+    pub logins: u64,
+    pub refreshes: u64,
+    pub authenticated: bool,
+}
+
+#[derive(Clone)]
+struct SessionTokens {
+    base_url: String,
+    username: Option<String>,
+    token: String,
+    refresh_token: String,
+}
+
+/// JWT shared by every ThingsBoardClient attached to it, so API handlers log in
+/// once and only re-authenticate when ThingsBoard rejects the token
+pub struct TbSession {
+    tokens: StdMutex<Option<SessionTokens>>,
+    // Held while re-authenticating so concurrent 401s trigger a single refresh
+    reauth: tokio::sync::Mutex<()>,
+    logins: AtomicU64,
+    refreshes: AtomicU64,
+}
+
+impl Default for TbSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TbSession {
+    pub fn new() -> Self {
+        Self {
+            tokens: StdMutex::new(None),
+            reauth: tokio::sync::Mutex::new(()),
+            logins: AtomicU64::new(0),
+            refreshes: AtomicU64::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> TbSessionStats {
+        TbSessionStats {
+            logins: self.logins.load(Ordering::Relaxed),
+            refreshes: self.refreshes.load(Ordering::Relaxed),
+            authenticated: self.tokens.lock().unwrap().is_some(),
+        }
+    }
+
+    /// Forget the stored token so the next request logs in again
+    pub fn clear(&self) {
+        *self.tokens.lock().unwrap() = None;
+    }
+
+    /// Stored tokens, if they were issued by this server for this user
+    fn tokens_for(&self, base_url: &str, username: Option<&str>) -> Option<SessionTokens> {
+        self.tokens
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|tokens| tokens.base_url == base_url && tokens.username.as_deref() == username)
+            .cloned()
+    }
+
+    fn store(&self, tokens: SessionTokens) {
+        *self.tokens.lock().unwrap() = Some(tokens);
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct LoginRequest {
     pub username: String,
     pub password: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct LoginResponse {
     pub token: String,
     #[serde(rename = "refreshToken")]
     pub refresh_token: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct Device {
     pub id: Option<DeviceId>,
     pub name: String,
@@ -63,14 +377,14 @@ pub struct Device {
     pub device_profile_id: Option<DeviceId>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct DeviceId {
     pub id: String,
     #[serde(rename = "entityType")]
     pub entity_type: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct DeviceCredentials {
     pub id: Option<DeviceCredentialsId>,
     #[serde(rename = "createdTime")]
@@ -86,19 +400,19 @@ pub struct DeviceCredentials {
 }
 
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct DeviceCredentialsId {
     pub id: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct EntityId {
     pub id: String,
     #[serde(rename = "entityType")]
     pub entity_type: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct DeviceConfiguration {
     pub configuration: HashMap<String, serde_json::Value>,
     #[serde(rename = "transportConfiguration")]
@@ -107,7 +421,7 @@ pub struct DeviceConfiguration {
 
 
 // TB Device Creation Request
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct CreateDeviceRequest {
     pub id: Option<DeviceId>,
     pub tenant_id: Option<DeviceId>,
@@ -124,7 +438,7 @@ pub struct CreateDeviceRequest {
     pub additional_info: Option<HashMap<String, serde_json::Value>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct DeviceData {
     pub id: EntityId,
     pub created_time: Option<i64>,
@@ -144,7 +458,7 @@ pub struct DeviceData {
 
 
 // TB get devices of entity group response
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct GroupDevicesResponse {
     pub data: Vec<DeviceData>,
     #[serde(rename = "totalPages")]
@@ -155,7 +469,7 @@ pub struct GroupDevicesResponse {
 }
 
 // TB Entity Group structure
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct EntityGroup {
     pub id: EntityId,
     #[serde(rename = "createdTime")]
@@ -177,7 +491,7 @@ pub struct EntityGroup {
 }
 
 // TB get entity groups response
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct EntityGroupsResponse {
     pub data: Vec<EntityGroup>,
     #[serde(rename = "totalPages")]
@@ -241,26 +555,23 @@ pub async fn to_thingsboard_device_with_type(
     })
 }
 
+/// Values sampled at one instant, in the shape ThingsBoard takes for timestamped telemetry
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TelemetryPoint {
+    /// Milliseconds since the Unix epoch
+    pub ts: i64,
+    pub values: HashMap<String, serde_json::Value>,
+}
+
 /// Converts a local DeviceInstance to a ThingsBoard CreateDeviceRequest
 /// 
 /// This function maps AVA Device Logger device data to ThingsBoard format,
 /// preserving all relevant configuration in the additional_info field.
 /// The device name follows a specific naming scheme based on the entity group.
 pub fn to_thingsboard_device(device: &DeviceInstance, entity_group_name: &str, device_index: u32) -> CreateDeviceRequest {
-    // Parse protocol config to determine device type
-    let device_type = match serde_json::from_str::<serde_json::Value>(&device.protocol_config) {
-        Ok(config) => {
-            if let Some(protocol_type) = config.get("type").and_then(|v| v.as_str()) {
-                match protocol_type {
-                    "modbus_tcp" => "Inverter".to_string(),
-                    "modbus_rtu" => "Inverter".to_string(),
-                    "iec104" => "Inverter".to_string(),
-                    _ => "Inverter".to_string(),
-                }
-            } else {
-                "Default".to_string()
-            }
-        }
+    // Every protocol is currently synced as an inverter
+    let device_type = match device.protocol() {
+        Ok(_) => "Inverter".to_string(),
         Err(_) => "Default".to_string(),
     };
 
@@ -299,9 +610,7 @@ pub fn to_thingsboard_device(device: &DeviceInstance, entity_group_name: &str, d
 fn extract_group_prefix_static(entity_group_name: &str) -> String {
     let parts: Vec<&str> = entity_group_name.split('-').collect();
     
-    if parts.len() >= 3 {
-        format!("{}-{}", parts[0], parts[1])
-    } else if parts.len() == 2 {
+    if parts.len() >= 2 {
         format!("{}-{}", parts[0], parts[1])
     } else {
         entity_group_name.to_string()
@@ -329,6 +638,8 @@ pub struct StringInfo {
     pub name: String,
     pub mppt_number: u32,
     pub input_number: u32,
+    /// The `-PV##` number of `name`, assigned by the hierarchy's [`PvNaming`]
+    pub pv_index: u32,
     pub parent_mppt: String,
     pub parent_inverter: String,
     pub idc_tag: Option<DeviceTag>,
@@ -342,20 +653,353 @@ pub struct DeviceHierarchy {
     pub strings: Vec<StringInfo>,
     pub entity_group_prefix: String,
     pub inverter_index: u32,
+    pub pv_naming: PvNaming,
+}
+
+/// One device written to a device catalog
+#[derive(Debug, Clone)]
+pub struct CatalogProgress {
+    /// 1-based position of the device among those being written
+    pub current: usize,
+    pub total: usize,
+    pub device_name: String,
+    /// Why the device's access token couldn't be fetched; its rows carry the error instead
+    pub token_error: Option<String>,
+}
+
+/// What went into a device catalog
+#[derive(Debug, Clone)]
+pub struct DeviceCatalogSummary {
+    pub entity_group_name: String,
+    /// `<entity group name>-device-catalog.csv`
+    pub file_name: String,
+    pub total_rows: usize,
+    pub successful_tokens: usize,
+    pub failed_tokens: usize,
+    /// `CATALOG_FORMAT_VERSION` of the columns written
+    pub format_version: u32,
+    /// Name and ThingsBoard type of each device written, in catalog order
+    pub devices: Vec<(String, String)>,
+}
+
+impl DeviceCatalogSummary {
+    /// Human-readable summary of a catalog saved at `location`
+    pub fn report(&self, location: &str) -> String {
+        // Group devices by type for detailed summary
+        let mut device_summary = std::collections::HashMap::new();
+        let mut parent_devices = Vec::new(); // Main devices (non-hierarchical)
+        let mut child_devices = Vec::new();  // Hierarchical devices (MPPT/String)
+
+        for (device_name, device_type) in self.devices.iter().cloned() {
+            // Add to type summary count
+            *device_summary.entry(device_type.clone()).or_insert(0) += 1;
+            
+            // Categorize as parent or child device
+            match device_type.as_str() {
+                "Inverter" | "PowerMeter" | "Meter" => {
+                    parent_devices.push((device_name, device_type));
+                }
+                "Mppt" | "String" => {
+                    child_devices.push((device_name, device_type));
+                }
+                _ => {
+                    parent_devices.push((device_name, device_type));
+                }
+            }
+        }
+
+        // Generate detailed summary with device breakdown
+        let mut summary = format!(
+            "✅ CSV catalog generated successfully!\n\
+            📄 File: {}\n\
+            📐 Catalog format: v{}\n\
+            🏷️  Entity Group: {}\n\
+            📊 Total rows: {} (device tags + hierarchical data)\n\
+            ✅ Successful token retrievals: {}\n\
+            ❌ Failed token retrievals: {}\n\n",
+            location, self.format_version, self.entity_group_name, self.total_rows, self.successful_tokens, self.failed_tokens
+        );
+
+        // Add parent devices section
+        if !parent_devices.is_empty() {
+            summary.push_str("🏭 Parent Devices Generated:\n");
+            let mut parent_by_type = std::collections::HashMap::new();
+            for (name, device_type) in parent_devices {
+                parent_by_type.entry(device_type).or_insert_with(Vec::new).push(name);
+            }
+
+            for (device_type, devices) in parent_by_type {
+                let type_emoji = match device_type.as_str() {
+                    "Inverter" => "⚡",
+                    "PowerMeter" => "📊",
+                    "Meter" => "📏",
+                    "Weather Station" => "🌤️",
+                    _ => "🔧"
+                };
+                summary.push_str(&format!("  {} {} ({}): {}\n", 
+                    type_emoji, device_type, devices.len(), devices.join(", ")));
+            }
+        }
+
+        summary
+    }
+}
+
+/// File name of an entity group's device catalog, with characters file systems reject replaced
+pub fn device_catalog_file_name(entity_group_name: &str) -> String {
+    let safe_group_name = entity_group_name
+        .replace(" ", "-")
+        .replace("/", "-")
+        .replace("\\", "-")
+        .replace(":", "-")
+        .replace("*", "-")
+        .replace("?", "-")
+        .replace("\"", "-")
+        .replace("<", "-")
+        .replace(">", "-")
+        .replace("|", "-");
+    format!("{}-device-catalog.csv", safe_group_name)
+}
+
+/// Devices of a hierarchy sync, created or found existing, and those that could be neither
+#[derive(Debug, Default)]
+pub struct HierarchyDevices {
+    pub devices: Vec<Device>,
+    /// Name of each device that failed, with the reason
+    pub failed: Vec<(String, TbError)>,
+    /// `parent -> child` of each Contains relation that could not be created, with the reason
+    pub relation_failures: Vec<(String, TbError)>,
+}
+
+impl HierarchyDevices {
+    /// The MPPT and String devices of `hierarchy` that were created or found, as recorded
+    /// locally for the inverter `device_id`
+    pub fn child_devices(&self, hierarchy: &DeviceHierarchy, device_id: &str) -> Vec<TbChildDevice> {
+        let tb_id_of = |name: &str| {
+            self.devices.iter().find(|device| device.name == name).and_then(|device| device.id.as_ref()).map(|id| id.id.clone())
+        };
+        let child = |tb_device_id: String, name: &str, device_type: &str, mppt_number: u32, input_number: Option<u32>| TbChildDevice {
+            device_id: device_id.to_string(),
+            tb_device_id,
+            name: name.to_string(),
+            device_type: device_type.to_string(),
+            mppt_number,
+            input_number,
+        };
+
+        let mppts = hierarchy.mppets.iter().filter_map(|mppt_info| {
+            tb_id_of(&mppt_info.name).map(|tb_id| child(tb_id, &mppt_info.name, "Mppt", mppt_info.mppt_number, None))
+        });
+        let strings = hierarchy.strings.iter().filter_map(|string_info| {
+            tb_id_of(&string_info.name).map(|tb_id| {
+                child(tb_id, &string_info.name, "String", string_info.mppt_number, Some(string_info.input_number))
+            })
+        });
+        mppts.chain(strings).collect()
+    }
+}
+
+/// The `-PV##` number of each string, given the `(mppt, input)` pairs sorted by MPPT and input
+pub fn pv_indexes(pv_naming: PvNaming, strings: &[(u32, u32)]) -> Vec<u32> {
+    let mut indexes = Vec::with_capacity(strings.len());
+    let mut previous_mppt = None;
+    let mut index = 0;
+    for &(mppt_number, _) in strings {
+        if pv_naming == PvNaming::PerMppt && previous_mppt != Some(mppt_number) {
+            index = 0;
+        }
+        previous_mppt = Some(mppt_number);
+        index += 1;
+        indexes.push(index);
+    }
+    indexes
+}
+
+/// Name of the MPPT device a string device name belongs to
+pub fn parent_mppt_name(string_name: &str) -> Option<&str> {
+    string_name.rsplit_once("-PV").map(|(mppt_name, _)| mppt_name)
+}
+
+/// Where the catalog puts an MPPT or String device: its local inverter, parent and numbering
+struct ChildPlacement<'a> {
+    parent_device: Option<&'a DeviceInstance>,
+    /// `None` when the parent's name has to be guessed from the device name
+    parent_name: Option<String>,
+    mppt_index: u32,
+    input_index: u32,
+}
+
+impl<'a> ChildPlacement<'a> {
+    /// Placement of a device recorded by a hierarchy sync
+    fn recorded(child: &TbChildDevice, children: &[TbChildDevice], local_devices: &'a [DeviceInstance], tb_devices: &[DeviceData]) -> Self {
+        let parent_device = local_devices.iter().find(|local_device| local_device.id == child.device_id);
+        let parent_name = match child.input_number {
+            // A string hangs off its MPPT, an MPPT off the inverter
+            Some(_) => children
+                .iter()
+                .find(|mppt| mppt.device_id == child.device_id && mppt.mppt_number == child.mppt_number && mppt.input_number.is_none())
+                .map(|mppt| mppt.name.clone()),
+            None => parent_device
+                .and_then(|inverter| inverter.tb_device_id.as_ref())
+                .and_then(|tb_id| tb_devices.iter().find(|tb_device| &tb_device.id.id == tb_id))
+                .map(|inverter| inverter.name.clone()),
+        };
+
+        ChildPlacement {
+            parent_device,
+            parent_name,
+            mppt_index: child.mppt_number,
+            input_index: child.input_number.unwrap_or(0),
+        }
+    }
+}
+
+/// Version of the JSON hierarchy export format, bump when the shape changes
+pub const HIERARCHY_SCHEMA_VERSION: u32 = 1;
+
+// JSON hierarchy export structures for external asset management
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HierarchyExport {
+    pub schema_version: u32,
+    pub entity_group_id: String,
+    pub entity_group_name: String,
+    pub page: usize,
+    pub page_size: usize,
+    pub total_inverters: usize,
+    pub inverters: Vec<InverterExport>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct InverterExport {
+    pub name: String,
+    pub inverter_index: u32,
+    pub local_device_id: String,
+    pub local_name: String,
+    pub serial_no: Option<String>,
+    pub model: String,
+    pub tb_device_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_token: Option<String>,
+    pub tags: Vec<DeviceTag>,
+    pub mppts: Vec<MpptExport>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MpptExport {
+    pub name: String,
+    pub mppt_number: u32,
+    pub tb_device_id: Option<String>,
+    pub tags: Vec<DeviceTag>,
+    pub strings: Vec<StringExport>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StringExport {
+    pub name: String,
+    pub mppt_number: u32,
+    pub input_number: u32,
+    /// The `-PV##` number of `name`
+    pub pv_index: u32,
+    pub tb_device_id: Option<String>,
+    pub idc_tag: Option<DeviceTag>,
+    pub udc_tag: Option<DeviceTag>,
+}
+
+impl InverterExport {
+    /// Nest an analyzed hierarchy into Inverter -> MPPT -> String objects.
+    ///
+    /// Strings whose MPPT has no MPPT-level tags still get an MPPT entry so that
+    /// every string always has a parent in the export. MPPTs and strings take their
+    /// ThingsBoard ids from `children`, the inverter's recorded child devices.
+    pub fn from_hierarchy(device: &DeviceInstance, hierarchy: DeviceHierarchy, children: &[TbChildDevice]) -> Self {
+        let tb_id_of = |device_type: &str, mppt_number: u32, input_number: Option<u32>| {
+            children
+                .iter()
+                .find(|child| child.device_type == device_type && child.mppt_number == mppt_number && child.input_number == input_number)
+                .map(|child| child.tb_device_id.clone())
+        };
+        let mut mppts: Vec<MpptExport> = hierarchy
+            .mppets
+            .into_iter()
+            .map(|mppt| MpptExport {
+                name: mppt.name,
+                mppt_number: mppt.mppt_number,
+                tb_device_id: tb_id_of("Mppt", mppt.mppt_number, None),
+                tags: mppt.tags,
+                strings: Vec::new(),
+            })
+            .collect();
+
+        for string in hierarchy.strings {
+            let position = match mppts.iter().position(|m| m.mppt_number == string.mppt_number) {
+                Some(position) => position,
+                None => {
+                    mppts.push(MpptExport {
+                        name: string.parent_mppt.clone(),
+                        mppt_number: string.mppt_number,
+                        tb_device_id: tb_id_of("Mppt", string.mppt_number, None),
+                        tags: Vec::new(),
+                        strings: Vec::new(),
+                    });
+                    mppts.len() - 1
+                }
+            };
+            mppts[position].strings.push(StringExport {
+                name: string.name,
+                mppt_number: string.mppt_number,
+                input_number: string.input_number,
+                pv_index: string.pv_index,
+                tb_device_id: tb_id_of("String", string.mppt_number, Some(string.input_number)),
+                idc_tag: string.idc_tag,
+                udc_tag: string.udc_tag,
+            });
+        }
+        mppts.sort_by_key(|m| m.mppt_number);
+
+        Self {
+            name: hierarchy.inverter.name,
+            inverter_index: hierarchy.inverter_index,
+            local_device_id: device.id.clone(),
+            local_name: device.name.clone(),
+            serial_no: device.serial_no.clone(),
+            model: hierarchy.inverter.model,
+            tb_device_id: device.tb_device_id.clone(),
+            access_token: None,
+            tags: hierarchy.inverter.tags,
+            mppts,
+        }
+    }
+}
+
+/// Collects the `SyncReport` of one operation from every ThingsBoard client it uses
+#[derive(Debug, Default)]
+pub struct SyncReportRecorder {
+    requests: AtomicU64,
+    throttle_pauses: AtomicU64,
+    failed_requests: StdMutex<Vec<FailedRequest>>,
 }
 
-#[derive(Debug, PartialEq)]
-pub enum DeviceType {
-    Inverter,
-    Mppt(u32), // MPPT number
-    String(u32, u32), // MPPT number, Input number
-    Unknown,
+impl SyncReportRecorder {
+    pub fn report(&self) -> SyncReport {
+        SyncReport {
+            requests: self.requests.load(Ordering::Relaxed),
+            throttle_pauses: self.throttle_pauses.load(Ordering::Relaxed),
+            failed_requests: self.failed_requests.lock().unwrap().clone(),
+        }
+    }
 }
 
 pub struct ThingsBoardClient {
     client: Client,
     base_url: String,
-    token: Option<String>,
+    session: Arc<TbSession>,
+    // Used to log in again when a refresh fails, and scrubbed from error messages
+    username: Option<String>,
+    password: Option<String>,
+    group_cache: Option<Arc<GroupDeviceCache>>,
+    retry: RetryPolicy,
+    rate_limiter: Arc<RateLimiter>,
+    report: Arc<SyncReportRecorder>,
 }
 
 impl ThingsBoardClient {
@@ -363,82 +1007,155 @@ impl ThingsBoardClient {
         Self {
             client: Client::new(),
             base_url: base_url.to_string(),
-            token: None,
+            session: Arc::new(TbSession::new()),
+            username: None,
+            password: None,
+            group_cache: None,
+            retry: RetryPolicy::default(),
+            rate_limiter: Arc::new(RateLimiter::unlimited()),
+            report: Arc::new(SyncReportRecorder::default()),
         }
     }
 
-    /// Parse device tag description to determine device type and hierarchy
-    /// 
-    /// Examples:
-    /// - "Inverter (SG150CX)" -> DeviceType::Inverter
-    /// - "MPPT - MPPT 1 (SG150CX)" -> DeviceType::Mppt(1)
-    /// - "String - MPPT 1 - Input 1 (SG150CX)" -> DeviceType::String(1, 1)
-    fn parse_device_description(description: &str) -> DeviceType {
-        if description.starts_with("Inverter") {
-            return DeviceType::Inverter;
-        }
-        
-        if description.starts_with("MPPT - MPPT ") {
-            // Extract MPPT number from "MPPT - MPPT 1 (SG150CX)"
-            if let Some(captures) = description.split(" ").nth(3) {
-                if let Ok(mppt_num) = captures.parse::<u32>() {
-                    return DeviceType::Mppt(mppt_num);
-                }
-            }
+    /// Client for the server in the `[thingsboard]` config section; call
+    /// `login_configured` to authenticate with the configured credentials
+    pub fn from_config(config: &AppConfig) -> Result<Self, TbError> {
+        let tb_config = config
+            .thingsboard
+            .as_ref()
+            .filter(|tb_config| !tb_config.base_url.trim().is_empty())
+            .ok_or(TbError::NotConfigured)?;
+
+        let mut client = Self::new(tb_config.base_url.trim().trim_end_matches('/')).with_retry_policy(RetryPolicy {
+            max_attempts: tb_config.retry_max_attempts.max(1),
+            base_delay: Duration::from_millis(tb_config.retry_base_delay_ms),
+        });
+        client.username = Some(tb_config.username.clone());
+        client.password = Some(tb_config.password.clone());
+        client.rate_limiter = Arc::new(RateLimiter::new(tb_config.requests_per_second));
+        Ok(client)
+    }
+
+    /// Log in with the credentials from `from_config`, reusing the session's
+    /// token when it already holds one for this server and user
+    pub async fn login_configured(&mut self) -> Result<(), TbError> {
+        let (Some(username), Some(password)) = (self.username.clone(), self.password.clone()) else {
+            return Err(TbError::NotConfigured);
+        };
+
+        let _guard = self.session.reauth.lock().await;
+        if self.session.tokens_for(&self.base_url, Some(&username)).is_some() {
+            return Ok(());
         }
-        
-        if description.starts_with("String - MPPT ") {
-            // Extract MPPT and Input from "String - MPPT 1 - Input 1 (SG150CX)"
-            let parts: Vec<&str> = description.split(" - ").collect();
-            if parts.len() >= 3 {
-                // parts[0] = "String"
-                // parts[1] = "MPPT 1" 
-                // parts[2] = "Input 1 (SG150CX)"
-                
-                if let Some(mppt_part) = parts.get(1) {
-                    if let Some(mppt_str) = mppt_part.strip_prefix("MPPT ") {
-                        if let Ok(mppt_num) = mppt_str.parse::<u32>() {
-                            if let Some(input_part) = parts.get(2) {
-                                if let Some(input_str) = input_part.strip_prefix("Input ") {
-                                    // Extract just the number before the space/parenthesis
-                                    let input_num_str = input_str.split_whitespace().next().unwrap_or("0");
-                                    if let Ok(input_num) = input_num_str.parse::<u32>() {
-                                        return DeviceType::String(mppt_num, input_num);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+        self.request_login(&username, &password).await
+    }
+
+    /// Check that the server answers HTTP within `timeout`, without logging in or retrying.
+    /// Any response below 500 counts, an unauthorized one included.
+    pub async fn ping(&self, timeout: Duration) -> Result<(), TbError> {
+        self.throttle().await;
+        let response = self
+            .client
+            .get(format!("{}/api/auth/user", self.base_url))
+            .timeout(timeout)
+            .send()
+            .await?;
+
+        if response.status().is_server_error() {
+            return Err(TbError::Api(format!("ThingsBoard answered {}", response.status())));
         }
-        
-        DeviceType::Unknown
+        Ok(())
     }
 
-    /// Extract model name from device tag description
-    /// 
-    /// Example: "String - MPPT 1 - Input 1 (SG150CX)" -> "SG150CX"
-    fn extract_model_from_description(description: &str) -> String {
-        if let Some(start) = description.rfind('(') {
-            if let Some(end) = description.rfind(')') {
-                if end > start {
-                    return description[start + 1..end].to_string();
-                }
-            }
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Share a session token with other clients
+    pub fn with_session(mut self, session: Arc<TbSession>) -> Self {
+        self.session = session;
+        self
+    }
+
+    /// Share a rate limit with other clients
+    pub fn with_rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+
+    /// Record requests in a report shared with other clients of the same operation
+    pub fn with_report(mut self, report: Arc<SyncReportRecorder>) -> Self {
+        self.report = report;
+        self
+    }
+
+    /// How many requests in this client's report had to wait for the rate limit
+    pub fn throttle_pauses(&self) -> u64 {
+        self.report.throttle_pauses.load(Ordering::Relaxed)
+    }
+
+    /// Requests made and failed so far, for the caller to show or keep
+    pub fn report(&self) -> SyncReport {
+        self.report.report()
+    }
+
+    /// Wait for the rate limit before sending a request
+    async fn throttle(&self) {
+        self.report.requests.fetch_add(1, Ordering::Relaxed);
+        if self.rate_limiter.acquire().await {
+            self.report.throttle_pauses.fetch_add(1, Ordering::Relaxed);
         }
-        "Unknown".to_string()
+    }
+
+    /// Body of an error response; a copy with credentials scrubbed out is logged and added to the report
+    async fn error_body(&self, method: &str, response: reqwest::Response) -> String {
+        let status = response.status();
+        let request = format!("{} {}", method, response.url().path());
+        let body = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+        let safe_body = self.sanitize_text(&body);
+        warn!(request = %request, status = status.as_u16(), body = %safe_body, "ThingsBoard request failed");
+        self.report.failed_requests.lock().unwrap().push(FailedRequest {
+            request,
+            status: status.as_u16(),
+            body: safe_body,
+            at: Utc::now(),
+        });
+        body
+    }
+
+    /// Share a group device cache with other clients
+    pub fn with_group_cache(mut self, cache: Arc<GroupDeviceCache>) -> Self {
+        self.group_cache = Some(cache);
+        self
+    }
+
+    /// Error text with this client's username, password and token scrubbed out
+    pub fn sanitize_error(&self, error: &TbError) -> String {
+        self.sanitize_text(&error.to_string())
+    }
+
+    fn sanitize_text(&self, text: &str) -> String {
+        let token = self.get_token();
+        let secrets: Vec<&str> = [&self.username, &self.password, &token]
+            .iter()
+            .filter_map(|secret| secret.as_deref())
+            .collect();
+        sanitize_message(text, &secrets)
     }
 
     /// Analyze device tags to create hierarchical device structure
     /// 
     /// This function takes all device tags from a single physical inverter device
     /// and groups them into Inverter/MPPT/String categories based on description patterns.
+    /// String names are numbered by `pv_naming` here and nowhere else, so device creation
+    /// uses the names the analysis reports.
     pub async fn analyze_device_hierarchy(
         &self,  
         device_tags: Vec<DeviceTag>, 
         entity_group_name: &str,
         inverter_index: u32,  // Use the passed inverter index instead of extracting from device name
+        pv_naming: PvNaming,
     ) -> Result<DeviceHierarchy, TbError> {        
         // Extract entity group prefix for naming
         let entity_group_prefix = self.extract_group_prefix(entity_group_name);
@@ -447,7 +1164,7 @@ impl ThingsBoardClient {
         // Extract model from first tag description
         let model = if let Some(first_tag) = device_tags.first() {
             if let Some(desc) = &first_tag.description {
-                Self::extract_model_from_description(desc)
+                tag_description::model(desc).unwrap_or("Unknown").to_string()
             } else {
                 "Unknown".to_string()
             }
@@ -465,18 +1182,18 @@ impl ThingsBoardClient {
         
         for tag in device_tags {
             if let Some(description) = &tag.description {
-                match Self::parse_device_description(description) {
+                match tag_description::parse(description) {
                     DeviceType::Inverter => {
                         inverter_tags.push(tag);
                     }
                     DeviceType::Mppt(mppt_num) => {
-                        mppt_groups.entry(mppt_num).or_insert_with(Vec::new).push(tag);
+                        mppt_groups.entry(mppt_num).or_default().push(tag);
                     }
                     DeviceType::String(mppt_num, input_num) => {
-                        string_groups.entry((mppt_num, input_num)).or_insert_with(Vec::new).push(tag);
+                        string_groups.entry((mppt_num, input_num)).or_default().push(tag);
                     }
                     DeviceType::Unknown => {
-                        println!("⚠️  Unknown device type for tag: {} - {}", tag.name, description);
+                        warn!(tag = %tag.name, description = %description, "Tag description doesn't place the tag on the inverter, an MPPT or a string");
                     }
                 }
             }
@@ -502,11 +1219,14 @@ impl ThingsBoardClient {
         }
         mppets.sort_by_key(|m| m.mppt_number);
         
-        // Create String info
+        // Create String info, numbered in MPPT and input order
+        let mut string_groups: Vec<((u32, u32), Vec<DeviceTag>)> = string_groups.into_iter().collect();
+        string_groups.sort_by_key(|(key, _)| *key);
+        let keys: Vec<(u32, u32)> = string_groups.iter().map(|(key, _)| *key).collect();
         let mut strings = Vec::new();
-        for ((mppt_num, input_num), tags) in string_groups {
+        for (((mppt_num, input_num), tags), pv_index) in string_groups.into_iter().zip(pv_indexes(pv_naming, &keys)) {
             let mppt_name = format!("{}-M{:02}", inverter_name, mppt_num);
-            let string_name = format!("{}-PV{:02}", mppt_name, (input_num - 1) % 3 + 1); // PV01, PV02, PV03 per MPPT
+            let string_name = format!("{}-PV{:02}", mppt_name, pv_index);
             
             // Find Idc and Udc tags
             let mut idc_tag = None;
@@ -523,13 +1243,13 @@ impl ThingsBoardClient {
                 name: string_name,
                 mppt_number: mppt_num,
                 input_number: input_num,
+                pv_index,
                 parent_mppt: mppt_name,
                 parent_inverter: inverter_name.clone(),
                 idc_tag,
                 udc_tag,
             });
         }
-        strings.sort_by_key(|s| (s.mppt_number, s.input_number));
         
         Ok(DeviceHierarchy {
             inverter,
@@ -537,9 +1257,56 @@ impl ThingsBoardClient {
             strings,
             entity_group_prefix,
             inverter_index,
+            pv_naming,
         })
     }
 
+    /// Create a device, or look up the tenant's device of the same name when ThingsBoard
+    /// reports it already exists, so callers always get its real id
+    async fn create_or_find_device(&self, device: &CreateDeviceRequest, entity_group_id: &str) -> Result<Device, TbError> {
+        match self.create_device(device, entity_group_id, None).await {
+            Err(TbError::Api(error_msg)) if error_msg.contains("already exists") => {
+                self.get_tenant_device(&device.name).await.map_err(|e| {
+                    TbError::Api(format!("Device {} already exists but could not be looked up: {}", device.name, e))
+                })
+            }
+            result => result,
+        }
+    }
+
+    /// Relate each created MPPT to its inverter and each created string to its MPPT, or to
+    /// the inverter when the hierarchy has no MPPT device for it. Failures are collected in
+    /// `created.relation_failures` so the remaining relations are still made.
+    async fn create_hierarchy_relations(&self, hierarchy: &DeviceHierarchy, inverter_id: &DeviceId, created: &mut HierarchyDevices) {
+        let id_of = |name: &str| created.devices.iter().find(|device| device.name == name).and_then(|device| device.id.clone());
+
+        let mut relations = Vec::new();
+        for mppt_info in &hierarchy.mppets {
+            if let Some(mppt_id) = id_of(&mppt_info.name) {
+                relations.push((hierarchy.inverter.name.clone(), Ok(inverter_id.clone()), mppt_info.name.clone(), mppt_id));
+            }
+        }
+        for string_info in &hierarchy.strings {
+            let Some(string_id) = id_of(&string_info.name) else { continue };
+            let parent = if hierarchy.mppets.iter().any(|mppt_info| mppt_info.name == string_info.parent_mppt) {
+                (string_info.parent_mppt.clone(), id_of(&string_info.parent_mppt).ok_or("parent MPPT device was not created"))
+            } else {
+                (hierarchy.inverter.name.clone(), Ok(inverter_id.clone()))
+            };
+            relations.push((parent.0, parent.1, string_info.name.clone(), string_id));
+        }
+
+        for (parent_name, parent_id, child_name, child_id) in relations {
+            let result = match parent_id {
+                Ok(parent_id) => self.create_relation(&parent_id, &child_id, "Contains").await,
+                Err(reason) => Err(TbError::Api(reason.to_string())),
+            };
+            if let Err(e) = result {
+                created.relation_failures.push((format!("{} -> {}", parent_name, child_name), e));
+            }
+        }
+    }
+
     /// Create hierarchical devices in ThingsBoard based on device hierarchy analysis
     /// 
     /// This method creates devices in the proper sequence:
@@ -550,8 +1317,8 @@ impl ThingsBoardClient {
         &self,
         hierarchy: &DeviceHierarchy,
         entity_group_id: &str,
-    ) -> Result<Vec<Device>, TbError> {
-        let mut created_devices = Vec::new();
+    ) -> Result<HierarchyDevices, TbError> {
+        let mut created = HierarchyDevices::default();
         
         // Step 1: Create Inverter device (or skip if exists)
         let inverter_request = CreateDeviceRequest {
@@ -575,24 +1342,9 @@ impl ThingsBoardClient {
             }),
         };
         
-        let inverter_device = match self.create_device(&inverter_request, entity_group_id, None).await {
-            Ok(device) => device,
-            Err(TbError::Api(error_msg)) if error_msg.contains("already exists") => {
-                // Create a placeholder device for the hierarchy (we don't have the actual device ID)
-                Device {
-                    id: Some(DeviceId {
-                        id: "existing-device".to_string(),
-                        entity_type: "DEVICE".to_string(),
-                    }),
-                    name: hierarchy.inverter.name.clone(),
-                    label: Some(format!("{} ({})", hierarchy.inverter.name, hierarchy.inverter.model)),
-                    device_type: "Inverter".to_string(),
-                    device_profile_id: None,
-                }
-            }
-            Err(e) => return Err(e),
-        };
-        created_devices.push(inverter_device);
+        let inverter_device = self.create_or_find_device(&inverter_request, entity_group_id).await?;
+        let inverter_id = inverter_device.id.clone();
+        created.devices.push(inverter_device);
         
         // Step 2: Create MPPT devices
         for mppt_info in &hierarchy.mppets {
@@ -618,24 +1370,11 @@ impl ThingsBoardClient {
                 }),
             };
             
-            let mppt_device = match self.create_device(&mppt_request, entity_group_id, None).await {
-                Ok(device) => device,
-                Err(TbError::Api(error_msg)) if error_msg.contains("already exists") => {
-                    // Create placeholder for tracking
-                    Device {
-                        id: Some(DeviceId {
-                            id: "existing-mppt".to_string(),
-                            entity_type: "DEVICE".to_string(),
-                        }),
-                        name: mppt_info.name.clone(),
-                        label: Some(format!("MPPT {} - {}", mppt_info.mppt_number, hierarchy.inverter.model)),
-                        device_type: "MPPT".to_string(),
-                        device_profile_id: None,
-                    }
-                }
-                Err(_) => continue, // Skip this MPPT but continue with others
-            };
-            created_devices.push(mppt_device);
+            match self.create_or_find_device(&mppt_request, entity_group_id).await {
+                Ok(device) => created.devices.push(device),
+                // Skip this MPPT but continue with others
+                Err(e) => created.failed.push((mppt_request.name.clone(), e)),
+            }
         }
         
         // Step 3: Create String devices  
@@ -673,40 +1412,33 @@ impl ThingsBoardClient {
                 additional_info: Some(additional_info),
             };
             
-            let string_device = match self.create_device(&string_request, entity_group_id, None).await {
-                Ok(device) => device,
-                Err(TbError::Api(error_msg)) if error_msg.contains("already exists") => {
-                    // Create placeholder for tracking
-                    Device {
-                        id: Some(DeviceId {
-                            id: "existing-string".to_string(),
-                            entity_type: "DEVICE".to_string(),
-                        }),
-                        name: string_info.name.clone(),
-                        label: Some(format!("String MPPT {} Input {} - {}", 
-                            string_info.mppt_number, string_info.input_number, hierarchy.inverter.model)),
-                        device_type: "String".to_string(),
-                        device_profile_id: None,
-                    }
-                }
-                Err(_) => continue, // Skip this String but continue with others
-            };
-            created_devices.push(string_device);
+            match self.create_or_find_device(&string_request, entity_group_id).await {
+                Ok(device) => created.devices.push(device),
+                // Skip this String but continue with others
+                Err(e) => created.failed.push((string_request.name.clone(), e)),
+            }
+        }
+        
+        // Step 4: Contains relations from each MPPT's inverter and each string's MPPT
+        if let Some(inverter_id) = &inverter_id {
+            self.create_hierarchy_relations(hierarchy, inverter_id, &mut created).await;
         }
         
-        Ok(created_devices)
+        Ok(created)
     }
 
     /// Create only MPPT and String devices (skip inverter creation)
     /// 
     /// This method is called when the main inverter device already exists
     /// and we only need to create the hierarchical MPPT and String devices.
+    /// `inverter_id` is the ThingsBoard id of that inverter, which the MPPTs are related to.
     pub async fn create_mppt_and_string_devices(
         &self,
         hierarchy: &DeviceHierarchy,
         entity_group_id: &str,
-    ) -> Result<Vec<Device>, TbError> {
-        let mut created_devices = Vec::new();
+        inverter_id: &str,
+    ) -> Result<HierarchyDevices, TbError> {
+        let mut created = HierarchyDevices::default();
         
         // Step 1: Create MPPT devices
         for mppt_info in &hierarchy.mppets {
@@ -732,39 +1464,25 @@ impl ThingsBoardClient {
                 }),
             };
             
-            let mppt_device = match self.create_device(&mppt_request, entity_group_id, None).await {
-                Ok(device) => device,
-                Err(TbError::Api(error_msg)) if error_msg.contains("already exists") => {
-                    // Create placeholder for tracking
-                    Device {
-                        id: Some(DeviceId {
-                            id: "existing-mppt".to_string(),
-                            entity_type: "DEVICE".to_string(),
-                        }),
-                        name: mppt_info.name.clone(),
-                        label: Some(mppt_info.name.clone()),
-                        device_type: "Mppt".to_string(),
-                        device_profile_id: None,
-                    }
-                }
-                Err(_) => continue, // Skip this MPPT but continue with others
-            };
-            created_devices.push(mppt_device);
+            match self.create_or_find_device(&mppt_request, entity_group_id).await {
+                Ok(device) => created.devices.push(device),
+                // Skip this MPPT but continue with others
+                Err(e) => created.failed.push((mppt_request.name.clone(), e)),
+            }
         }
         
-        // Step 2: Create String devices with continuous PV indexing
-        let mut global_pv_index = 1; // Start from 1 and continue across all MPPTs
+        // Step 2: Create String devices under the names the analysis gave them
         for string_info in &hierarchy.strings {
-            // Use global PV index instead of resetting per MPPT
-            let string_name = format!("{}-PV{:02}", 
-                format!("{}-M{:02}", hierarchy.inverter.name, string_info.mppt_number), 
-                global_pv_index
-            );
+            let string_name = string_info.name.clone();
             
             let mut additional_info = HashMap::new();
             additional_info.insert("mppt_number".to_string(), serde_json::json!(string_info.mppt_number));
             additional_info.insert("input_number".to_string(), serde_json::json!(string_info.input_number));
-            additional_info.insert("global_pv_index".to_string(), serde_json::json!(global_pv_index));
+            additional_info.insert("pv_index".to_string(), serde_json::json!(string_info.pv_index));
+            additional_info.insert("pv_naming".to_string(), serde_json::json!(hierarchy.pv_naming.as_str()));
+            if hierarchy.pv_naming == PvNaming::Global {
+                additional_info.insert("global_pv_index".to_string(), serde_json::json!(string_info.pv_index));
+            }
             additional_info.insert("parent_mppt".to_string(), serde_json::json!(string_info.parent_mppt));
             additional_info.insert("parent_inverter".to_string(), serde_json::json!(string_info.parent_inverter));
             additional_info.insert("device_type".to_string(), serde_json::json!("String"));
@@ -794,35 +1512,26 @@ impl ThingsBoardClient {
                 additional_info: Some(additional_info),
             };
             
-            let string_device = match self.create_device(&string_request, entity_group_id, None).await {
-                Ok(device) => device,
-                Err(TbError::Api(error_msg)) if error_msg.contains("already exists") => {
-                    // Create placeholder for tracking
-                    Device {
-                        id: Some(DeviceId {
-                            id: "existing-string".to_string(),
-                            entity_type: "DEVICE".to_string(),
-                        }),
-                        name: string_name.clone(),
-                        label: Some(string_name.clone()),
-                        device_type: "String".to_string(),
-                        device_profile_id: None,
-                    }
-                }
-                Err(_) => continue, // Skip this String but continue with others
-            };
-            created_devices.push(string_device);
-            global_pv_index += 1; // Increment for next string
+            match self.create_or_find_device(&string_request, entity_group_id).await {
+                Ok(device) => created.devices.push(device),
+                // Skip this String but continue with others
+                Err(e) => created.failed.push((string_request.name.clone(), e)),
+            }
         }
         
-        Ok(created_devices)
+        // Step 3: Contains relations from each MPPT's inverter and each string's MPPT
+        let inverter_id = DeviceId { id: inverter_id.to_string(), entity_type: "DEVICE".to_string() };
+        self.create_hierarchy_relations(hierarchy, &inverter_id, &mut created).await;
+        
+        Ok(created)
     }
 
     /// Complete workflow: analyze device hierarchy and create hierarchical devices in ThingsBoard
     /// 
     /// This method combines hierarchy analysis and device creation for a single local device instance.
     /// It retrieves device tags, analyzes the hierarchy, and creates all devices in ThingsBoard.
-    /// Set skip_inverter_creation to true when the main inverter device is already created.
+    /// The inverter itself must already exist in ThingsBoard, with its id in `device.tb_device_id`;
+    /// the MPPT devices are related to it.
     pub async fn sync_device_hierarchy_to_thingsboard(
         &self,
         device: &DeviceInstance,
@@ -830,7 +1539,11 @@ impl ThingsBoardClient {
         entity_group_name: &str,
         database: &Database,
         inverter_index: u32,  // Pass the correct inverter index explicitly
-    ) -> Result<Vec<Device>, TbError> {        
+        pv_naming: PvNaming,
+    ) -> Result<HierarchyDevices, TbError> {        
+        let inverter_id = device.tb_device_id.as_deref()
+            .ok_or_else(|| TbError::Api(format!("Device {} has no ThingsBoard id", device.name)))?;
+
         // Step 1: Get device tags from database
         let device_tags = database.get_device_tags(&device.id).await
             .map_err(|e| TbError::Api(format!("Failed to get device tags: {}", e)))?;
@@ -840,12 +1553,17 @@ impl ThingsBoardClient {
         }
         
         // Step 2: Analyze device hierarchy
-        let hierarchy = self.analyze_device_hierarchy(device_tags, entity_group_name, inverter_index).await?;
+        let hierarchy = self.analyze_device_hierarchy(device_tags, entity_group_name, inverter_index, pv_naming).await?;
         
         // Step 3: Create only MPPT and String devices (skip inverter - already created in main sync)
-        let created_devices = self.create_mppt_and_string_devices(&hierarchy, entity_group_id).await?;
+        let created = self.create_mppt_and_string_devices(&hierarchy, entity_group_id, inverter_id).await?;
+
+        // Step 4: Remember the children's ids so the catalog and telemetry forwarding needn't guess them from names
+        if let Err(e) = database.save_tb_child_devices(&device.id, &created.child_devices(&hierarchy, &device.id)).await {
+            warn!("Failed to save ThingsBoard child devices of {}: {}", device.name, e);
+        }
         
-        Ok(created_devices)
+        Ok(created)
     }
 
     /// Extracts the prefix from entity group name
@@ -870,41 +1588,170 @@ impl ThingsBoardClient {
     }
 
     pub async fn login(&mut self, username: &str, password: &str) -> Result<(), TbError> {
+        self.username = Some(username.to_string());
+        self.password = Some(password.to_string());
+        self.request_login(username, password).await
+    }
+
+    async fn request_login(&self, username: &str, password: &str) -> Result<(), TbError> {
         let login_request = LoginRequest {
             username: username.to_string(),
             password: password.to_string(),
         };
 
+        self.throttle().await;
         let response = self
             .client
-            .post(&format!("{}/api/auth/login", self.base_url))
+            .post(format!("{}/api/auth/login", self.base_url))
             .json(&login_request)
             .send()
             .await?;
 
         if response.status().is_success() {
             let login_response: LoginResponse = response.json().await?;
-            self.token = Some(login_response.token);
+            self.session.logins.fetch_add(1, Ordering::Relaxed);
+            self.store_tokens(login_response);
             Ok(())
         } else {
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            let error_text = self.error_body("POST", response).await;
             Err(TbError::Auth(format!("Login failed: {}", error_text)))
         }
     }
 
-    fn get_auth_header(&self) -> Result<String, TbError> {
-        match &self.token {
-            Some(token) => {
-                Ok(format!("Bearer {}", token))
+    /// Exchange the refresh token for a new JWT
+    async fn refresh_session(&self, refresh_token: &str) -> Result<(), TbError> {
+        self.throttle().await;
+        let response = self
+            .client
+            .post(format!("{}/api/auth/token", self.base_url))
+            .json(&serde_json::json!({ "refreshToken": refresh_token }))
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            let login_response: LoginResponse = response.json().await?;
+            self.session.refreshes.fetch_add(1, Ordering::Relaxed);
+            self.store_tokens(login_response);
+            Ok(())
+        } else {
+            let error_text = self.error_body("POST", response).await;
+            Err(TbError::Auth(format!("Token refresh failed: {}", error_text)))
+        }
+    }
+
+    fn store_tokens(&self, login_response: LoginResponse) {
+        self.session.store(SessionTokens {
+            base_url: self.base_url.clone(),
+            username: self.username.clone(),
+            token: login_response.token,
+            refresh_token: login_response.refresh_token,
+        });
+    }
+
+    fn session_tokens(&self) -> Result<SessionTokens, TbError> {
+        self.session
+            .tokens_for(&self.base_url, self.username.as_deref())
+            .ok_or_else(|| TbError::Auth("Not authenticated".to_string()))
+    }
+
+    /// Replace a token ThingsBoard rejected: refresh it, or log in again if the
+    /// refresh token has expired too. A no-op if another request already did.
+    async fn reauthenticate(&self, rejected_token: &str) -> Result<(), TbError> {
+        let _guard = self.session.reauth.lock().await;
+        let current = self.session_tokens()?;
+        if current.token != rejected_token {
+            return Ok(());
+        }
+
+        match self.refresh_session(&current.refresh_token).await {
+            Ok(()) => return Ok(()),
+            Err(e) => warn!("ThingsBoard token refresh failed, logging in again: {}", self.sanitize_error(&e)),
+        }
+
+        match (&self.username, &self.password) {
+            (Some(username), Some(password)) => self.request_login(username, password).await,
+            _ => Err(TbError::Auth("Session expired".to_string())),
+        }
+    }
+
+    /// Send a request, retrying transient failures with exponential backoff.
+    ///
+    /// Other responses, including 4xx business errors, are returned as-is for
+    /// the caller to interpret. A transient failure on the last attempt is
+    /// returned as `TbError::RetriesExhausted`.
+    async fn send_with_retry<F>(&self, build: F) -> Result<reqwest::Response, TbError>
+    where
+        F: Fn(&Client) -> reqwest::RequestBuilder,
+    {
+        let mut attempt = 1;
+        loop {
+            self.throttle().await;
+            let mut retry_after = None;
+            let request = build(&self.client).build()?;
+            let method = request.method().to_string();
+            let last = match self.client.execute(request).await {
+                Ok(response) if !is_transient_status(response.status()) => return Ok(response),
+                Ok(response) => {
+                    // Every client waits out a 429, not only this request
+                    if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                        retry_after = retry_after_header(&response);
+                        if let Some(pause) = retry_after {
+                            self.rate_limiter.pause_for(pause);
+                        }
+                    }
+                    // Without retries the caller reports the response as before
+                    if self.retry.max_attempts <= 1 {
+                        return Ok(response);
+                    }
+                    let status = response.status();
+                    let error_text = self.error_body(&method, response).await;
+                    TbError::Api(format!("Request failed (Status: {}): {}", status, error_text))
+                }
+                Err(e) if is_transient_error(&e) => TbError::Http(e),
+                Err(e) => return Err(TbError::Http(e)),
+            };
+
+            if attempt >= self.retry.max_attempts {
+                return Err(match last {
+                    TbError::Http(_) if attempt == 1 => last,
+                    last => TbError::RetriesExhausted { attempts: attempt, last: Box::new(last) },
+                });
             }
-            None => {
-                Err(TbError::Auth("Not authenticated".to_string()))
+
+            let delay = retry_after.unwrap_or_else(|| self.retry.delay_before_retry(attempt));
+            warn!(
+                "Transient ThingsBoard failure on attempt {}/{}, retrying in {:?}: {}",
+                attempt, self.retry.max_attempts, delay, self.sanitize_error(&last)
+            );
+            // A Retry-After pause is waited out by the rate limiter before the next attempt
+            if retry_after.is_none() {
+                tokio::time::sleep(delay).await;
             }
+            attempt += 1;
+        }
+    }
+
+    /// Send an authenticated request, re-authenticating once and retrying if the token was rejected
+    async fn send_authorized<F>(&self, build: F) -> Result<reqwest::Response, TbError>
+    where
+        F: Fn(&Client) -> reqwest::RequestBuilder,
+    {
+        let token = self.session_tokens()?.token;
+        let response = self
+            .send_with_retry(|client| build(client).header("Authorization", format!("Bearer {}", token)))
+            .await?;
+        if response.status() != reqwest::StatusCode::UNAUTHORIZED {
+            return Ok(response);
         }
+
+        self.reauthenticate(&token).await?;
+        let token = self.session_tokens()?.token;
+        self.send_with_retry(|client| build(client).header("Authorization", format!("Bearer {}", token)))
+            .await
     }
 
     pub fn get_token(&self) -> Option<String> {
-        self.token.clone()
+        self.session_tokens().ok().map(|tokens| tokens.token)
     }
 
     pub async fn create_device(
@@ -913,34 +1760,29 @@ impl ThingsBoardClient {
         entity_group_id: &str,
         device_token: Option<&str>,
     ) -> Result<Device, TbError> {
-        let auth_header = self.get_auth_header()?;
 
         let mut url = format!("{}/api/device?entityGroupId={}", self.base_url, entity_group_id);
         if let Some(token) = device_token {
             url.push_str(&format!("&deviceToken={}", token));
         }
 
-        println!("🔗 POST Request URL: {}", url);
-        println!("📝 Device creation payload: {}", serde_json::to_string_pretty(device).unwrap_or_else(|_| "Failed to serialize".to_string()));
+        debug!(device_name = %device.name, entity_group_id, payload = %serde_json::to_string(device).unwrap_or_default(), "Creating ThingsBoard device");
 
         let response = self
-            .client
-            .post(&url)
-            .header("Authorization", auth_header)
-            .json(device)
-            .send()
+            .send_authorized(|client| client.post(&url).json(device))
             .await?;
 
         let status_code = response.status();
-        println!("📊 ThingsBoard Response Status: {}", status_code);
 
         if response.status().is_success() {
             let created_device: Device = response.json().await?;
-            println!("✨ Device creation response received successfully");
+            info!(device_name = %device.name, entity_group_id, status = status_code.as_u16(), "Created ThingsBoard device");
+            if let Some(cache) = &self.group_cache {
+                cache.invalidate(entity_group_id);
+            }
             Ok(created_device)
         } else {
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            println!("❌ ThingsBoard API Error Response: {}", error_text);
+            let error_text = self.error_body("POST", response).await;
             Err(TbError::Api(format!("Device creation failed (Status: {}): {}", status_code, error_text)))
         }
     }
@@ -953,7 +1795,6 @@ impl ThingsBoardClient {
         device_id: &str,
         attributes: serde_json::Value,
     ) -> Result<(), TbError> {
-        let auth_header = self.get_auth_header()?;
         
         // Always use SERVER_SCOPE for attributes
         let url = format!(
@@ -961,26 +1802,19 @@ impl ThingsBoardClient {
             self.base_url, device_id
         );
         
-        println!("🔗 POST Request URL: {}", url);
-        println!("📝 Attributes payload: {}", serde_json::to_string_pretty(&attributes).unwrap_or_else(|_| "Failed to serialize".to_string()));
+        debug!(device_id, payload = %attributes, "Updating ThingsBoard device attributes");
         
         let response = self
-            .client
-            .post(&url)
-            .header("Authorization", auth_header)
-            .json(&attributes)
-            .send()
+            .send_authorized(|client| client.post(&url).json(&attributes))
             .await?;
         
         let status_code = response.status();
-        println!("📊 ThingsBoard Attributes Response Status: {}", status_code);
         
         if response.status().is_success() {
-            println!("✨ Device attributes updated successfully");
+            debug!(device_id, status = status_code.as_u16(), "Updated ThingsBoard device attributes");
             Ok(())
         } else {
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            println!("❌ ThingsBoard API Error Response: {}", error_text);
+            let error_text = self.error_body("POST", response).await;
             Err(TbError::Api(format!(
                 "Update device attributes failed (Status: {}): {}",
                 status_code, error_text
@@ -1029,7 +1863,7 @@ impl ThingsBoardClient {
                 }
                 
                 // customer - extract from ThingsBoard device name (e.g., ACCV-P001-I01 -> ACCV)
-                let customer = self.extract_customer_from_group_name(tb_device_name);
+                let customer = catalog::customer_name(tb_device_name);
                 attributes.insert("customer".to_string(), serde_json::Value::String(customer));
                 
                 // ava_name - the ThingsBoard device name itself
@@ -1054,20 +1888,15 @@ impl ThingsBoardClient {
 
     // get devices of entity group - with pagination controlled by page size and page number
     pub async fn get_group_devices(&self, entity_group_id: &str, page_size: i32, page: i32) -> Result<GroupDevicesResponse, TbError> {
-        let auth_header = self.get_auth_header()?;
-
         let response = self
-            .client
-            .get(&format!("{}/api/entityGroup/{}/devices?pageSize={}&page={}", self.base_url, entity_group_id, page_size, page))
-            .header("Authorization", auth_header)
-            .send()
+            .send_authorized(|client| client.get(format!("{}/api/entityGroup/{}/devices?pageSize={}&page={}", self.base_url, entity_group_id, page_size, page)))
             .await?;
 
         if response.status().is_success() {
             let devices_response: GroupDevicesResponse = response.json().await?;
             Ok(devices_response)
         } else {
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            let error_text = self.error_body("GET", response).await;
             Err(TbError::Api(format!("Get group devices failed: {}", error_text)))
         }
     }
@@ -1078,24 +1907,24 @@ impl ThingsBoardClient {
         let mut page = 0;
         
         loop {
-            println!("Fetching page {} with page size {}...", page, page_size);
+            debug!(entity_group_id, page, page_size, "Fetching entity group devices");
             let response = self.get_group_devices(entity_group_id, page_size, page).await?;
             
             let data_len = response.data.len();
-            println!("Received {} devices on page {}", data_len, page);
+            debug!(entity_group_id, page, devices = data_len, "Received entity group devices");
             
             // Add devices from this page to our collection
             all_devices.extend(response.data);
             
             // Break if we got fewer devices than requested (indicates last page)
             if data_len < page_size as usize {
-                println!("Last page reached (got {} < {} devices)", data_len, page_size);
+                debug!(entity_group_id, page, "Last page of entity group devices reached");
                 break;
             }
             
             // Also check the has_next flag if available
             if response.has_next == Some(false) {
-                println!("has_next is false, stopping pagination");
+                debug!(entity_group_id, page, "No more pages of entity group devices");
                 break;
             }
             
@@ -1103,52 +1932,60 @@ impl ThingsBoardClient {
             
             // Safety check to prevent infinite loops
             if page > 1000 {
-                println!("Warning: Reached page limit of 1000, stopping pagination");
+                warn!(entity_group_id, "Reached the page limit of 1000, stopping pagination");
                 break;
             }
         }
         
-        println!("Total devices collected: {}", all_devices.len());
+        info!(entity_group_id, devices = all_devices.len(), "Collected entity group devices");
         Ok(all_devices)
     }
 
+    /// All devices of an entity group, served from the shared cache when the
+    /// listing is fresh; falls back to a full crawl without a cache
+    pub async fn get_all_group_devices_cached(&self, entity_group_id: &str) -> Result<Vec<DeviceData>, TbError> {
+        let Some(cache) = &self.group_cache else {
+            return self.get_all_group_devices(entity_group_id, 50).await;
+        };
+
+        if let Some(devices) = cache.get(entity_group_id) {
+            return Ok(devices);
+        }
+
+        let devices = self.get_all_group_devices(entity_group_id, 50).await?;
+        if !cache.insert(entity_group_id, &devices) {
+            warn!("Entity group {} has {} devices, too many to cache", entity_group_id, devices.len());
+        }
+        Ok(devices)
+    }
+
     // get all entity groups by group type (no pagination needed)
     pub async fn get_all_entity_groups(&self, group_type: &str) -> Result<Vec<EntityGroup>, TbError> {
-        let auth_header = self.get_auth_header()?;
-
         let response = self
-            .client
-            .get(&format!("{}/api/entityGroups/{}", self.base_url, group_type))
-            .header("Authorization", auth_header)
-            .send()
+            .send_authorized(|client| client.get(format!("{}/api/entityGroups/{}", self.base_url, group_type)))
             .await?;
 
         if response.status().is_success() {
             let groups: Vec<EntityGroup> = response.json().await?;
-            println!("Retrieved {} entity groups of type '{}'", groups.len(), group_type);
+            debug!(group_type, groups = groups.len(), "Retrieved entity groups");
             Ok(groups)
         } else {
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            let error_text = self.error_body("GET", response).await;
             Err(TbError::Api(format!("Get entity groups failed: {}", error_text)))
         }
     }
 
 
     pub async fn get_device_access_token(&self, device_id: &str) -> Result<String, TbError> {
-        let auth_header = self.get_auth_header()?;
-
         let response = self
-            .client
-            .get(&format!("{}/api/device/{}/credentials", self.base_url, device_id))
-            .header("Authorization", auth_header)
-            .send()
+            .send_authorized(|client| client.get(format!("{}/api/device/{}/credentials", self.base_url, device_id)))
             .await?;
 
         if response.status().is_success() {
             let credentials: DeviceCredentials = response.json().await?;
             Ok(credentials.credentials_id)
         } else {
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            let error_text = self.error_body("GET", response).await;
             Err(TbError::Api(format!("Get device access token failed: {}", error_text)))
         }
     }
@@ -1159,7 +1996,6 @@ impl ThingsBoardClient {
     /// then exports them to a CSV file with columns: Index, Device Name, Device ID, AVA Type, Label, Token
     /// The filename will be formatted as "ENTITY-GROUP-NAME-device-catalog.csv"
     pub async fn generate_device_catalog_csv(&self, entity_group_id: &str, output_dir: &str) -> Result<String, TbError> {
-        println!("🔍 Retrieving entity group information and devices for: {}", entity_group_id);
         
         // Step 1: Get entity group information to extract the name
         let entity_groups = self.get_all_entity_groups("DEVICE").await?;
@@ -1169,7 +2005,6 @@ impl ThingsBoardClient {
             .map(|group| group.name.clone())
             .unwrap_or_else(|| "Unknown-Group".to_string());
         
-        println!("📋 Entity Group: {}", entity_group_name);
         
         // Generate filename based on entity group name
         let safe_group_name = entity_group_name
@@ -1185,24 +2020,22 @@ impl ThingsBoardClient {
             .replace("|", "-");
         
         let output_path = format!("{}/{}-device-catalog.csv", output_dir, safe_group_name);
-        println!("📄 Output file: {}", output_path);
         
         // Step 2: Get all devices from the entity group
-        let devices = self.get_all_group_devices(entity_group_id, 50).await?;
+        let devices = self.get_all_group_devices_cached(entity_group_id).await?;
         
         if devices.is_empty() {
-            println!("⚠️  No devices found in entity group");
             return Err(TbError::Api("No devices found in entity group".to_string()));
         }
 
-        println!("📱 Found {} devices, retrieving access tokens...", devices.len());
+        info!(entity_group_id, entity_group_name = %entity_group_name, devices = devices.len(), output_path = %output_path, "Generating device catalog");
 
         // Step 3: Create CSV file
         let file = File::create(&output_path).map_err(|e| TbError::Api(format!("Failed to create CSV file: {}", e)))?;
         let mut writer = Writer::from_writer(file);
 
         // Write CSV header with AVA Type column
-        writer.write_record(&["Index", "Device Name", "Device ID", "AVA Type", "Label", "Token"])
+        writer.write_record(["Index", "Device Name", "Device ID", "AVA Type", "Label", "Token"])
             .map_err(|e| TbError::Api(format!("Failed to write CSV header: {}", e)))?;
 
         // Step 4: Process each device and get access token
@@ -1210,7 +2043,7 @@ impl ThingsBoardClient {
         let mut failed_count = 0;
 
         for (index, device) in devices.iter().enumerate() {
-            println!("Processing device {} of {}: {}", index + 1, devices.len(), device.name);
+            debug!(device_name = %device.name, current = index + 1, total = devices.len(), "Cataloging device");
             
             // Get access token for this device
             let token = match self.get_device_access_token(&device.id.id).await {
@@ -1220,13 +2053,13 @@ impl ThingsBoardClient {
                 }
                 Err(e) => {
                     failed_count += 1;
-                    println!("  ❌ Failed to get token for {}: {}", device.name, e);
+                    warn!(device_name = %device.name, error = %self.sanitize_error(&e), "Failed to get device access token");
                     format!("ERROR: {}", e)
                 }
             };
 
             // Write device row to CSV
-            writer.write_record(&[
+            writer.write_record([
                 &index.to_string(),           // Index (starting from 0)
                 &device.name,                 // Device Name
                 &device.id.id,               // Device ID
@@ -1234,11 +2067,6 @@ impl ThingsBoardClient {
                 &device.label,               // Label
                 &token,                      // Token
             ]).map_err(|e| TbError::Api(format!("Failed to write device row: {}", e)))?;
-
-            // Small delay to avoid rate limiting
-            if index < devices.len() - 1 {
-                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-            }
         }
 
         // Flush and close the writer
@@ -1255,7 +2083,7 @@ impl ThingsBoardClient {
             output_path, entity_group_name, devices.len(), successful_count, failed_count
         );
 
-        println!("{}", summary);
+        info!(entity_group_id, output_path = %output_path, successful_tokens = successful_count, failed_tokens = failed_count, "Device catalog generated");
         Ok(summary)
     }
 
@@ -1265,7 +2093,56 @@ impl ThingsBoardClient {
     /// and detailed information from the database including device models, tags, and schedules.
     /// Each device tag generates a separate row in the CSV file.
     pub async fn generate_detailed_device_catalog_csv(&self, entity_group_id: &str, output_dir: &str, database: &Database) -> Result<String, TbError> {
-        println!("📊 Generating CSV catalog...");
+        let summary = self.generate_detailed_device_catalog_csv_with_progress(entity_group_id, output_dir, database, &|_| {}).await?;
+        Ok(summary.report(&format!("{}/{}", output_dir, summary.file_name)))
+    }
+
+    /// Save the catalog as `summary.file_name` in `output_dir`, calling `on_device` after each
+    /// device is written. Nothing is saved if generation fails.
+    pub async fn generate_detailed_device_catalog_csv_with_progress(
+        &self,
+        entity_group_id: &str,
+        output_dir: &str,
+        database: &Database,
+        on_device: &(dyn Fn(CatalogProgress) + Send + Sync),
+    ) -> Result<DeviceCatalogSummary, TbError> {
+        let (summary, csv) = self.render_detailed_device_catalog_csv(entity_group_id, database, on_device).await?;
+        let output_path = format!("{}/{}", output_dir, summary.file_name);
+        std::fs::write(&output_path, csv).map_err(|e| TbError::Api(format!("Failed to write CSV file: {}", e)))?;
+
+        info!(
+            entity_group_id,
+            output_path = %output_path,
+            rows = summary.total_rows,
+            successful_tokens = summary.successful_tokens,
+            failed_tokens = summary.failed_tokens,
+            "Device catalog saved"
+        );
+        Ok(summary)
+    }
+
+    /// The catalog as CSV bytes, for downloads that don't keep a copy on the gateway
+    pub async fn render_detailed_device_catalog_csv(
+        &self,
+        entity_group_id: &str,
+        database: &Database,
+        on_device: &(dyn Fn(CatalogProgress) + Send + Sync),
+    ) -> Result<(DeviceCatalogSummary, Vec<u8>), TbError> {
+        let mut writer = Writer::from_writer(Vec::new());
+        let summary = self.write_detailed_device_catalog(entity_group_id, database, &mut writer, on_device).await?;
+        let csv = writer.into_inner().map_err(|e| TbError::Api(format!("Failed to finish CSV catalog: {}", e)))?;
+        Ok((summary, csv))
+    }
+
+    /// Write the catalog's header and rows to any CSV writer; the header comes with the first row
+    pub async fn write_detailed_device_catalog<W: Write + Send>(
+        &self,
+        entity_group_id: &str,
+        database: &Database,
+        writer: &mut Writer<W>,
+        on_device: &(dyn Fn(CatalogProgress) + Send + Sync),
+    ) -> Result<DeviceCatalogSummary, TbError> {
+        info!(entity_group_id, "Generating detailed device catalog");
         
         // Step 1: Get entity group information to extract the name
         let entity_groups = self.get_all_entity_groups("DEVICE").await?;
@@ -1275,63 +2152,13 @@ impl ThingsBoardClient {
             .map(|group| group.name.clone())
             .unwrap_or_else(|| "Unknown-Group".to_string());
         
-        // Generate filename based on entity group name
-        let safe_group_name = entity_group_name
-            .replace(" ", "-")
-            .replace("/", "-")
-            .replace("\\", "-")
-            .replace(":", "-")
-            .replace("*", "-")
-            .replace("?", "-")
-            .replace("\"", "-")
-            .replace("<", "-")
-            .replace(">", "-")
-            .replace("|", "-");
-        
-        let output_path = format!("{}/{}-device-catalog.csv", output_dir, safe_group_name);
-        
         // get all devices from the entity group
-        let devices = self.get_all_group_devices(entity_group_id, 50).await?;
+        let devices = self.get_all_group_devices_cached(entity_group_id).await?;
         
         if devices.is_empty() {
             return Err(TbError::Api("No devices found in entity group".to_string()));
         }
 
-        // create CSV file
-        let file = File::create(&output_path).map_err(|e| TbError::Api(format!("Failed to create CSV file: {}", e)))?;
-        let mut writer = Writer::from_writer(file);
-
-        writer.write_record(&[
-            "IOA",           // 1
-            "Index",         // 2
-            "Serial Number", // 3 ← NEW
-            "Device Name",   // 4
-            "Device Brand",  // 5
-            "Device Model",  // 6
-            "Customer",      // 7
-            "AVA Type",      // 8
-            "Token",         // 9
-            "Parent",        // 10
-            "Plant",         // 11
-            "INV",           // 12
-            "MPPT",          // 13
-            "INPUT",         // 14
-            "Label",         // 15
-            "Device ID",     // 16
-            "Host",          // 17
-            "Port",          // 18
-            "Forwarding Modbus ID", // 19
-            "Protocol",      // 20
-            "Data Label",    // 21
-            "Address",       // 22
-            "Size",          // 23
-            "Modbus Type",   // 24
-            "Divider",       // 25
-            "Register Type", // 26
-            "Frequency",     // 27
-            "Agg To Field",  // 28 ← NEW
-        ]).map_err(|e| TbError::Api(format!("Failed to write CSV header: {}", e)))?;
-
         // get local database devices first to determine which devices to process
         let local_devices = match database.get_devices_by_group_id(entity_group_id).await {
             Ok(devices) => devices,
@@ -1340,6 +2167,15 @@ impl ThingsBoardClient {
             }
         };
 
+        // MPPT and String devices recorded by hierarchy syncs; name patterns are only used for devices not recorded
+        let mut children = Vec::new();
+        for local_device in &local_devices {
+            match database.get_tb_child_devices(&local_device.id).await {
+                Ok(device_children) => children.extend(device_children),
+                Err(e) => warn!(device_name = %local_device.name, error = %e, "Failed to get ThingsBoard child devices"),
+            }
+        }
+
         // filter ThingsBoard devices to only include those with local database records
         let devices_to_process: Vec<&DeviceData> = devices.iter()
             .filter(|device| {
@@ -1362,6 +2198,9 @@ impl ThingsBoardClient {
         let mut failed_count = 0;
         let mut total_rows = 0;
         let mut processed_devices = Vec::new(); // Track processed devices for summary
+        let rows = CatalogRowBuilder::new(&entity_group_name);
+        // Prefix of guessed parent names, e.g. "ACCV-P002" for "ACCV-P002-King Jade"
+        let group_prefix = self.extract_group_prefix(&entity_group_name);
 
         for (device_index, device) in devices_to_process.iter().enumerate() {
             // Get access token for this device
@@ -1376,11 +2215,12 @@ impl ThingsBoardClient {
                 }
             };
 
+            let token_error = token.strip_prefix("ERROR: ").map(str::to_string);
+
             // Track processed device info for summary
             processed_devices.push((device.name.clone(), device.device_type.clone()));
 
             // Extract common device information
-            let customer = self.extract_customer_from_group_name(&entity_group_name);
             let inv_index = self.extract_inv_index_from_device_name(&device.name);
             let mppt_index = self.extract_mppt_index_from_device_name(&device.name);
             let input_index = self.extract_input_index_from_device_name(&device.name);
@@ -1399,88 +2239,60 @@ impl ThingsBoardClient {
                 d.tb_device_id.as_ref() == Some(&device.id.id)
             }) {
                 // This is a main inverter device with local database record
-                self.process_main_device(&mut writer, &mut total_rows, device, local_device, &customer, device_index_for_csv, mppt_index, input_index, &entity_group_name, &token, database).await?;
+                let catalog_device = CatalogDevice {
+                    device,
+                    token: &token,
+                    parent: catalog::parent_name(device, &entity_group_name, &group_prefix, device_index_for_csv, mppt_index),
+                    device_index: device_index_for_csv,
+                    mppt_index,
+                    input_index,
+                };
+                self.process_main_device(writer, &mut total_rows, &rows, &catalog_device, local_device, database).await?;
             } else {
                 // This might be a hierarchical MPPT/String device without local record
                 // Look for UDC/IDC data in parent inverter devices
-                self.process_hierarchical_device(&mut writer, &mut total_rows, device, &local_devices, &customer, device_index_for_csv, mppt_index, input_index, &entity_group_name, &token, database).await?;
-            }
-
-            // Small delay to avoid rate limiting
-            if device_index < devices_to_process.len() - 1 {
-                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-            }
-        }
-
-        // Flush and close the writer
-        writer.flush().map_err(|e| TbError::Api(format!("Failed to flush CSV file: {}", e)))?;
-
-        // Group devices by type for detailed summary
-        let mut device_summary = std::collections::HashMap::new();
-        let mut parent_devices = Vec::new(); // Main devices (non-hierarchical)
-        let mut child_devices = Vec::new();  // Hierarchical devices (MPPT/String)
-
-        for (device_name, device_type) in processed_devices {
-            // Add to type summary count
-            *device_summary.entry(device_type.clone()).or_insert(0) += 1;
-            
-            // Categorize as parent or child device
-            match device_type.as_str() {
-                "Inverter" | "PowerMeter" | "Meter" => {
-                    parent_devices.push((device_name, device_type));
-                }
-                "Mppt" | "String" => {
-                    child_devices.push((device_name, device_type));
-                }
-                _ => {
-                    parent_devices.push((device_name, device_type));
-                }
-            }
-        }
-
-        // Generate detailed summary with device breakdown
-        let mut summary = format!(
-            "✅ CSV catalog generated successfully!\n\
-            📄 File: {}\n\
-            🏷️  Entity Group: {}\n\
-            📊 Total rows: {} (device tags + hierarchical data)\n\
-            ✅ Successful token retrievals: {}\n\
-            ❌ Failed token retrievals: {}\n\n",
-            output_path, entity_group_name, total_rows, successful_count, failed_count
-        );
-
-        // Add parent devices section
-        if !parent_devices.is_empty() {
-            summary.push_str("🏭 Parent Devices Generated:\n");
-            let mut parent_by_type = std::collections::HashMap::new();
-            for (name, device_type) in parent_devices {
-                parent_by_type.entry(device_type).or_insert_with(Vec::new).push(name);
-            }
-
-            for (device_type, devices) in parent_by_type {
-                let type_emoji = match device_type.as_str() {
-                    "Inverter" => "⚡",
-                    "PowerMeter" => "📊",
-                    "Meter" => "📏",
-                    "Weather Station" => "🌤️",
-                    _ => "🔧"
+                let placement = match children.iter().find(|child| child.tb_device_id == device.id.id) {
+                    Some(child) => ChildPlacement::recorded(child, &children, &local_devices, &devices),
+                    None => ChildPlacement {
+                        parent_device: self.find_parent_inverter_device(&local_devices, device_index_for_csv),
+                        parent_name: None,
+                        mppt_index,
+                        input_index,
+                    },
                 };
-                summary.push_str(&format!("  {} {} ({}): {}\n", 
-                    type_emoji, device_type, devices.len(), devices.join(", ")));
+                let catalog_device = CatalogDevice {
+                    device,
+                    token: &token,
+                    // The recorded parent's name, else guessed from the device name
+                    parent: placement.parent_name.clone().or_else(|| {
+                        catalog::parent_name(device, &entity_group_name, &group_prefix, device_index_for_csv, placement.mppt_index)
+                    }),
+                    device_index: device_index_for_csv,
+                    mppt_index: placement.mppt_index,
+                    input_index: placement.input_index,
+                };
+                self.process_hierarchical_device(writer, &mut total_rows, &rows, &catalog_device, placement.parent_device, database).await?;
             }
+
+            on_device(CatalogProgress {
+                current: device_index + 1,
+                total: devices_to_process.len(),
+                device_name: device.name.clone(),
+                token_error,
+            });
         }
 
-        println!("{}", summary);
-        Ok(summary)
-    }
+        writer.flush().map_err(|e| TbError::Api(format!("Failed to flush CSV catalog: {}", e)))?;
 
-    /// Extract customer name from entity group name (everything before first dash)
-    fn extract_customer_from_group_name(&self, entity_group_name: &str) -> String {
-        entity_group_name
-            .split('-')
-            .next()
-            .unwrap_or("Unknown")
-            .to_string()
+        Ok(DeviceCatalogSummary {
+            file_name: device_catalog_file_name(&entity_group_name),
+            entity_group_name,
+            total_rows,
+            successful_tokens: successful_count,
+            failed_tokens: failed_count,
+            format_version: CATALOG_FORMAT_VERSION,
+            devices: processed_devices,
+        })
     }
 
     /// Extract INV index from device name (extract number from pattern like "I01" -> 1)
@@ -1550,197 +2362,30 @@ impl ThingsBoardClient {
     }
 
     /// Process main device (inverter with local database record)
-    async fn process_main_device(
+    async fn process_main_device<W: Write + Send>(
         &self,
-        writer: &mut Writer<File>,
+        writer: &mut Writer<W>,
         total_rows: &mut usize,
-        device: &DeviceData,
+        rows: &CatalogRowBuilder,
+        device: &CatalogDevice<'_>,
         local_device: &DeviceInstance,
-        customer: &str,
-        device_index: u32, // Renamed from inv_index to be more generic
-        mppt_index: u32,
-        input_index: u32,
-        entity_group_name: &str,
-        token: &str,
         database: &Database,
     ) -> Result<(), TbError> {
-        // Get device model information
-        let (device_brand, device_model) = if let Some(model_id) = &local_device.model_id {
-            match database.get_device_model(model_id).await {
-                Ok(Some(model)) => (
-                    model.manufacturer.unwrap_or("Unknown".to_string()),
-                    model.name
-                ),
-                Ok(None) => ("Unknown".to_string(), "Unknown".to_string()),
-                Err(_) => ("Unknown".to_string(), "Unknown".to_string()),
-            }
-        } else {
-            ("Unknown".to_string(), "Unknown".to_string())
-        };
+        let (brand, model) = self.get_device_model_info(database, local_device).await;
+        let source = CatalogSource { instance: local_device, brand, model };
 
-        // Get device tags
         match database.get_device_tags(&local_device.id).await {
+            // If no tags, create one row with device info only
+            Ok(tags) if tags.is_empty() => self.write_catalog_row(writer, total_rows, rows.device_row(device, &source))?,
             Ok(tags) => {
-                if tags.is_empty() {
-                    // If no tags, create one row with device info only
-                    let (host, port, modbus_id, modbus_type) = self.parse_protocol_config(&local_device.protocol_config);
-                    
-                    // Determine INV, MPPT and INPUT columns based on device type
-                    let (inv_col, mppt_col, input_col) = match device.device_type.as_str() {
-                        "Mppt" => (device_index.to_string(), mppt_index.to_string(), "".to_string()),
-                        "String" => (device_index.to_string(), mppt_index.to_string(), input_index.to_string()),
-                        "Inverter" => (device_index.to_string(), "".to_string(), "".to_string()),
-                        "PowerMeter" | "Meter" => ("".to_string(), "".to_string(), "".to_string()), // Empty for meters
-                        _ => ("".to_string(), "".to_string(), "".to_string()) // Other devices
-                    };
-                    
-                    let parent_name = self.get_parent_name(device, entity_group_name, device_index, mppt_index);
-                    
-                    writer.write_record(&[
-                        &total_rows.to_string(),              // IOA (starting from 0)
-                        &total_rows.to_string(),              // Index (starting from 0) 
-                        local_device.serial_no.as_deref().unwrap_or(""), // Serial Number ← NEW
-                        &device.name,                         // Device Name
-                        &device_brand,                        // Device Brand
-                        &device_model,                        // Device Model
-                        customer,                             // Customer
-                        &device.device_type,                  // AVA Type
-                        token,                               // Token
-                        &parent_name,                        // Parent
-                        entity_group_name,                   // Plant
-                        &inv_col,                            // INV
-                        &mppt_col,                           // MPPT 
-                        &input_col,                          // INPUT
-                        &device.label,                       // Label
-                        &device.id.id,                      // Device ID
-                        &host,                               // Host
-                        &port,                               // Port
-                        &modbus_id,                          // Modbus ID
-                        &modbus_type,                        // Modbus Type
-                        "",                                  // Data Label (empty for no tags)
-                        "",                                  // Address
-                        "",                                  // Size
-                        "",                                  // Data Type
-                        "",                                  // Divider
-                        "",                                  // Register Type
-                        "",                                  // Frequency
-                        "",                                  // Agg To Field (empty for no tags) ← NEW
-                    ]).map_err(|e| TbError::Api(format!("Failed to write device row: {}", e)))?;
-                    *total_rows += 1;
-                } else {
-                    // Create one row for each tag, with different filtering based on device type
-                    let (host, port, modbus_id, modbus_type) = self.parse_protocol_config(&local_device.protocol_config);
-                    
-                    for tag in tags {
-                        // For Inverter devices: skip Idc and Udc tags (current behavior)
-                        // For MPPT/String devices: only include Idc and Udc tags
-                        // For PowerMeter/Meter devices: include all tags
-                        let should_include_tag = match device.device_type.as_str() {
-                            "Inverter" => tag.name != "Idc" && tag.name != "Udc",
-                            "Mppt" | "String" => tag.name == "Idc" || tag.name == "Udc",
-                            "PowerMeter" | "Meter" => true, // Include all tags for meter devices
-                            _ => true // Other device types include all tags
-                        };
-
-                        if !should_include_tag {
-                            continue;
-                        }
-
-                        let divider = self.convert_scaling_multiplier_to_divider(tag.scaling_multiplier);
-                        let frequency = self.get_schedule_group_frequency(database, &tag.schedule_group_id).await;
-
-                        // Determine INV, MPPT and INPUT columns based on device type
-                        let (inv_col, mppt_col, input_col) = match device.device_type.as_str() {
-                            "Mppt" => (device_index.to_string(), mppt_index.to_string(), "".to_string()),
-                            "String" => (device_index.to_string(), mppt_index.to_string(), input_index.to_string()),
-                            "Inverter" => (device_index.to_string(), "".to_string(), "".to_string()),
-                            "PowerMeter" | "Meter" => ("".to_string(), "".to_string(), "".to_string()), // Empty for meters
-                            _ => ("".to_string(), "".to_string(), "".to_string()) // Other devices
-                        };
-
-                        let parent_name = self.get_parent_name(device, entity_group_name, device_index, mppt_index);
-
-                        writer.write_record(&[
-                            &total_rows.to_string(),              // IOA (starting from 0)
-                            &total_rows.to_string(),              // Index (starting from 0)
-                            local_device.serial_no.as_deref().unwrap_or(""), // Serial Number ← NEW
-                            &device.name,                         // Device Name
-                            &device_brand,                        // Device Brand
-                            &device_model,                        // Device Model
-                            customer,                             // Customer
-                            &device.device_type,                  // AVA Type
-                            token,                               // Token
-                            &parent_name,                        // Parent
-                            entity_group_name,                   // Plant
-                            &inv_col,                            // INV
-                            &mppt_col,                           // MPPT
-                            &input_col,                          // INPUT
-                            &device.label,                       // Label
-                            &device.id.id,                      // Device ID
-                            &host,                               // Host
-                            &port,                               // Port
-                            &modbus_id,                          // Modbus ID
-                            &modbus_type,                        // Modbus Type
-                            &tag.name,                           // Data Label
-                            &tag.address.to_string(),            // Address
-                            &tag.size.to_string(),               // Size
-                            &tag.data_type,                      // Data Type
-                            &divider,                            // Divider
-                            &tag.unit.as_ref().unwrap_or(&"".to_string()), // Register Type
-                            &frequency,                          // Frequency
-                            &tag.agg_to_field.as_ref().unwrap_or(&"".to_string()), // Agg To Field ← NEW
-                        ]).map_err(|e| TbError::Api(format!("Failed to write tag row: {}", e)))?;
-                        *total_rows += 1;
-                    }
+                for tag in tags.iter().filter(|tag| catalog::includes_tag(&device.device.device_type, &tag.name)) {
+                    let schedule = self.schedule_lookup(database, &tag.schedule_group_id).await;
+                    self.write_catalog_row(writer, total_rows, rows.tag_row(device, &source, tag, schedule))?;
                 }
             }
             Err(e) => {
-                println!("  ❌ Failed to get tags for device {}: {}", local_device.id, e);
-                // Create a single row without tag information
-                let (host, port, modbus_id, modbus_type) = self.parse_protocol_config(&local_device.protocol_config);
-                
-                // Determine INV, MPPT and INPUT columns based on device type
-                let (inv_col, mppt_col, input_col) = match device.device_type.as_str() {
-                    "Mppt" => (device_index.to_string(), mppt_index.to_string(), "".to_string()),
-                    "String" => (device_index.to_string(), mppt_index.to_string(), input_index.to_string()),
-                    "Inverter" => (device_index.to_string(), "".to_string(), "".to_string()),
-                    "PowerMeter" | "Meter" => ("".to_string(), "".to_string(), "".to_string()), // Empty for meters
-                    _ => ("".to_string(), "".to_string(), "".to_string()) // Other devices
-                };
-                
-                let parent_name = self.get_parent_name(device, entity_group_name, device_index, mppt_index);
-                
-                writer.write_record(&[
-                    &total_rows.to_string(),              // IOA (starting from 0)
-                    &total_rows.to_string(),              // Index (starting from 0)
-                    local_device.serial_no.as_deref().unwrap_or(""), // Serial Number ← NEW
-                    &device.name,                         // Device Name
-                    &device_brand,                        // Device Brand
-                    &device_model,                        // Device Model
-                    customer,                             // Customer
-                    &device.device_type,                  // AVA Type
-                    token,                               // Token
-                    &parent_name,                        // Parent
-                    entity_group_name,                   // Plant
-                    &inv_col,                            // INV
-                    &mppt_col,                           // MPPT
-                    &input_col,                          // INPUT
-                    &device.label,                       // Label
-                    &device.id.id,                      // Device ID
-                    &host,                               // Host
-                    &port,                               // Port
-                    &modbus_id,                          // Modbus ID
-                    &modbus_type,                        // Modbus Type
-                    "ERROR: Failed to get tags",        // Data Label
-                    "",                                  // Address
-                    "",                                  // Size
-                    "",                                  // Data Type
-                    "",                                  // Divider
-                    "",                                  // Register Type
-                    "",                                  // Frequency
-                    "",                                  // Agg To Field (empty for error) ← NEW
-                ]).map_err(|e| TbError::Api(format!("Failed to write error row: {}", e)))?;
-                *total_rows += 1;
+                warn!(device_name = %local_device.name, error = %e, "Failed to get device tags for the catalog");
+                self.write_catalog_row(writer, total_rows, rows.error_row(device, &source))?;
             }
         }
         Ok(())
@@ -1748,173 +2393,68 @@ impl ThingsBoardClient {
 
     /// Process hierarchical device (MPPT/String without local database record)
     /// Find UDC/IDC data from parent inverter devices based on tag descriptions
-    async fn process_hierarchical_device(
+    async fn process_hierarchical_device<W: Write + Send>(
         &self,
-        writer: &mut Writer<File>,
+        writer: &mut Writer<W>,
         total_rows: &mut usize,
-        device: &DeviceData,
-        local_devices: &[DeviceInstance],
-        customer: &str,
-        device_index: u32, // Renamed from inv_index to be more generic
-        mppt_index: u32,
-        input_index: u32,
-        entity_group_name: &str,
-        token: &str,
+        rows: &CatalogRowBuilder,
+        device: &CatalogDevice<'_>,
+        parent_device: Option<&DeviceInstance>,
         database: &Database,
     ) -> Result<(), TbError> {
-        match device.device_type.as_str() {
-            "Mppt" => {
-                // Find parent inverter device with matching INV index
-                if let Some(parent_device) = self.find_parent_inverter_device(local_devices, device_index) {
-                    // Look for UDC/IDC tags with MPPT description matching this MPPT index
-                    match database.get_device_tags(&parent_device.id).await {
-                        Ok(tags) => {
-                            let mut found_tags = false;
-                            let (host, port, modbus_id, modbus_type) = self.parse_protocol_config(&parent_device.protocol_config);
-                            
-                            // Get device model info from parent
-                            let (device_brand, device_model) = self.get_device_model_info(database, parent_device).await;
-                            
-                            for tag in tags {
-                                if (tag.name == "Udc" || tag.name == "Idc") && 
-                                   self.tag_matches_mppt(tag.description.as_deref(), mppt_index) {
-                                    found_tags = true;
-                                    
-                                    let divider = self.convert_scaling_multiplier_to_divider(tag.scaling_multiplier);
-                                    let frequency = self.get_schedule_group_frequency(database, &tag.schedule_group_id).await;
-                                    
-                                    let parent_name = self.get_parent_name(device, entity_group_name, device_index, mppt_index);
-                                    
-                                    writer.write_record(&[
-                                        &total_rows.to_string(),              // IOA
-                                        &total_rows.to_string(),              // Index
-                                        parent_device.serial_no.as_deref().unwrap_or(""), // Serial Number (from parent) ← NEW
-                                        &device.name,                         // Device Name
-                                        &device_brand,                        // Device Brand
-                                        &device_model,                        // Device Model
-                                        customer,                             // Customer
-                                        &device.device_type,                  // AVA Type
-                                        token,                               // Token
-                                        &parent_name,                        // Parent
-                                        entity_group_name,                   // Plant
-                                        &device_index.to_string(),              // INV
-                                        &mppt_index.to_string(),             // MPPT
-                                        "",                                  // INPUT (empty for MPPT)
-                                        &device.label,                       // Label
-                                        &device.id.id,                      // Device ID
-                                        &host,                               // Host
-                                        &port,                               // Port
-                                        &modbus_id,                          // Modbus ID
-                                        &modbus_type,                        // Modbus Type
-                                        &tag.name,                           // Data Label (Udc/Idc)
-                                        &tag.address.to_string(),            // Address
-                                        &tag.size.to_string(),               // Size
-                                        &tag.data_type,                      // Data Type
-                                        &divider,                            // Divider
-                                        &tag.unit.as_ref().unwrap_or(&"".to_string()), // Register Type
-                                        &frequency,                          // Frequency
-                                        &tag.agg_to_field.as_ref().unwrap_or(&"".to_string()), // Agg To Field ← NEW
-                                    ]).map_err(|e| TbError::Api(format!("Failed to write MPPT row: {}", e)))?;
-                                    *total_rows += 1;
-                                }
-                            }
-                            
-                            if !found_tags {
-                                println!("  ⚠️ No UDC/IDC tags found for MPPT {}", mppt_index);
-                                // Create placeholder row
-                                self.create_placeholder_row(writer, total_rows, device, customer, device_index, mppt_index, 0, entity_group_name, token, "No UDC/IDC data found").await?;
-                            }
-                        }
-                        Err(e) => {
-                            println!("  ❌ Failed to get tags from parent device: {}", e);
-                            self.create_placeholder_row(writer, total_rows, device, customer, device_index, mppt_index, 0, entity_group_name, token, "Failed to get parent device tags").await?;
-                        }
-                    }
-                } else {
-                    println!("  ❌ Parent inverter device not found for MPPT {}", device.name);
-                    self.create_placeholder_row(writer, total_rows, device, customer, device_index, mppt_index, 0, entity_group_name, token, "Parent inverter not found").await?;
-                }
-            }
-            "String" => {
-                // Find parent inverter device with matching INV index
-                if let Some(parent_device) = self.find_parent_inverter_device(local_devices, device_index) {
-                    // Look for UDC/IDC tags with String description matching this MPPT and Input index
-                    match database.get_device_tags(&parent_device.id).await {
-                        Ok(tags) => {
-                            let mut found_tags = false;
-                            let (host, port, modbus_id, modbus_type) = self.parse_protocol_config(&parent_device.protocol_config);
-                            
-                            // Get device model info from parent
-                            let (device_brand, device_model) = self.get_device_model_info(database, parent_device).await;
-                            
-                            for tag in tags {
-                                if (tag.name == "Udc" || tag.name == "Idc") && 
-                                   self.tag_matches_string(tag.description.as_deref(), mppt_index, input_index) {
-                                    found_tags = true;
-                                    
-                                    let divider = self.convert_scaling_multiplier_to_divider(tag.scaling_multiplier);
-                                    let frequency = self.get_schedule_group_frequency(database, &tag.schedule_group_id).await;
-                                    
-                                    let parent_name = self.get_parent_name(device, entity_group_name, device_index, mppt_index);
-                                    
-                                    writer.write_record(&[
-                                        &total_rows.to_string(),              // IOA
-                                        &total_rows.to_string(),              // Index
-                                        parent_device.serial_no.as_deref().unwrap_or(""), // Serial Number (from parent) ← NEW
-                                        &device.name,                         // Device Name
-                                        &device_brand,                        // Device Brand
-                                        &device_model,                        // Device Model
-                                        customer,                             // Customer
-                                        &device.device_type,                  // AVA Type
-                                        token,                               // Token
-                                        &parent_name,                        // Parent
-                                        entity_group_name,                   // Plant
-                                        &device_index.to_string(),              // INV
-                                        &mppt_index.to_string(),             // MPPT
-                                        &input_index.to_string(),            // INPUT
-                                        &device.label,                       // Label
-                                        &device.id.id,                      // Device ID
-                                        &host,                               // Host
-                                        &port,                               // Port
-                                        &modbus_id,                          // Modbus ID
-                                        &modbus_type,                        // Modbus Type
-                                        &tag.name,                           // Data Label (Udc/Idc)
-                                        &tag.address.to_string(),            // Address
-                                        &tag.size.to_string(),               // Size
-                                        &tag.data_type,                      // Data Type
-                                        &divider,                            // Divider
-                                        &tag.unit.as_ref().unwrap_or(&"".to_string()), // Register Type
-                                        &frequency,                          // Frequency
-                                        &tag.agg_to_field.as_ref().unwrap_or(&"".to_string()), // Agg To Field ← NEW
-                                    ]).map_err(|e| TbError::Api(format!("Failed to write String row: {}", e)))?;
-                                    *total_rows += 1;
-                                }
-                            }
-                            
-                            if !found_tags {
-                                println!("  ⚠️ No UDC/IDC tags found for String MPPT {} Input {}", mppt_index, input_index);
-                                // Create placeholder row
-                                self.create_placeholder_row(writer, total_rows, device, customer, device_index, mppt_index, input_index, entity_group_name, token, "No UDC/IDC data found").await?;
-                            }
-                        }
-                        Err(e) => {
-                            println!("  ❌ Failed to get tags from parent device: {}", e);
-                            self.create_placeholder_row(writer, total_rows, device, customer, device_index, mppt_index, input_index, entity_group_name, token, "Failed to get parent device tags").await?;
-                        }
-                    }
-                } else {
-                    println!("  ❌ Parent inverter device not found for String {}", device.name);
-                    self.create_placeholder_row(writer, total_rows, device, customer, device_index, mppt_index, input_index, entity_group_name, token, "Parent inverter not found").await?;
-                }
+        let (mppt_index, input_index) = (device.mppt_index, device.input_index);
+        let matches: fn(&DeviceTag, u32, u32) -> bool = match device.device.device_type.as_str() {
+            "Mppt" => |tag, mppt, _| catalog::tag_matches_mppt(tag.description.as_deref(), mppt),
+            "String" => |tag, mppt, input| catalog::tag_matches_string(tag.description.as_deref(), mppt, input),
+            other => {
+                warn!(device_name = %device.device.name, device_type = other, "Unknown device type for hierarchical processing");
+                return self.write_catalog_row(writer, total_rows, rows.placeholder_row(device, "Unknown device type"));
             }
-            _ => {
-                println!("  ⚠️ Unknown device type for hierarchical processing: {}", device.device_type);
-                self.create_placeholder_row(writer, total_rows, device, customer, device_index, 0, 0, entity_group_name, token, "Unknown device type").await?;
+        };
+
+        // Find parent inverter device with matching INV index
+        let Some(parent_device) = parent_device else {
+            warn!(device_name = %device.device.name, device_type = %device.device.device_type, "Parent inverter device not found");
+            return self.write_catalog_row(writer, total_rows, rows.placeholder_row(device, "Parent inverter not found"));
+        };
+
+        // Look for UDC/IDC tags whose description matches this MPPT (and input)
+        let tags = match database.get_device_tags(&parent_device.id).await {
+            Ok(tags) => tags,
+            Err(e) => {
+                warn!(device_name = %device.device.name, parent = %parent_device.name, error = %e, "Failed to get tags from parent device");
+                return self.write_catalog_row(writer, total_rows, rows.placeholder_row(device, "Failed to get parent device tags"));
             }
+        };
+
+        // Get device model info from parent
+        let (brand, model) = self.get_device_model_info(database, parent_device).await;
+        let source = CatalogSource { instance: parent_device, brand, model };
+
+        let mut found_tags = false;
+        for tag in tags.iter().filter(|tag| (tag.name == "Udc" || tag.name == "Idc") && matches(tag, mppt_index, input_index)) {
+            found_tags = true;
+            let schedule = self.schedule_lookup(database, &tag.schedule_group_id).await;
+            self.write_catalog_row(writer, total_rows, rows.tag_row(device, &source, tag, schedule))?;
+        }
+
+        if !found_tags {
+            warn!(device_name = %device.device.name, mppt = mppt_index, input = input_index, "No Udc/Idc tags found");
+            self.write_catalog_row(writer, total_rows, rows.placeholder_row(device, "No UDC/IDC data found"))?;
         }
         Ok(())
     }
 
+    /// Number a row with the next IOA and Index and write it
+    fn write_catalog_row<W: Write>(&self, writer: &mut Writer<W>, total_rows: &mut usize, row: CatalogRow) -> Result<(), TbError> {
+        let row = CatalogRow { ioa: *total_rows, index: *total_rows, ..row };
+        writer
+            .serialize(&row)
+            .map_err(|e| TbError::Api(format!("Failed to write row for device {}: {}", row.device_name, e)))?;
+        *total_rows += 1;
+        Ok(())
+    }
+
     /// Find parent inverter device by INV index
     fn find_parent_inverter_device<'a>(&self, local_devices: &'a [DeviceInstance], inv_index: u32) -> Option<&'a DeviceInstance> {
         // Look for the inverter device that matches this INV index
@@ -1967,209 +2507,96 @@ impl ThingsBoardClient {
         }
     }
 
-    /// Determine the correct parent for a device based on hierarchy:
-    /// - Inverter devices: Entity Group name
-    /// - MPPT devices: Parent Inverter name
-    /// - String devices: Parent MPPT name
-    fn get_parent_name(&self, device: &DeviceData, entity_group_name: &str, inv_index: u32, mppt_index: u32) -> String {
-        match device.device_type.as_str() {
-            "Inverter" => {
-                // Inverters parent is the entity group
-                entity_group_name.to_string()
-            },
-            "Mppt" => {
-                // MPPT parent is the inverter name
-                // Extract base name and construct inverter name
-                // From "ACCV-P002-I01-M01" get "ACCV-P002-I01"
-                if let Some(pos) = device.name.rfind("-M") {
-                    device.name[..pos].to_string()
-                } else {
-                    // Fallback: construct from pattern
-                    format!("ACCV-P002-I{:02}", inv_index)
-                }
-            },
-            "String" => {
-                // String parent is the MPPT name
-                // Extract base name and construct MPPT name
-                // From "ACCV-P002-I01-M01-PV01" get "ACCV-P002-I01-M01"
-                if let Some(pos) = device.name.rfind("-PV") {
-                    device.name[..pos].to_string()
-                } else {
-                    // Fallback: construct from pattern
-                    format!("ACCV-P002-I{:02}-M{:02}", inv_index, mppt_index)
-                }
+    /// Look up the schedule group of a tag for its Frequency column
+    async fn schedule_lookup(&self, database: &Database, schedule_group_id: &Option<String>) -> ScheduleLookup {
+        match schedule_group_id {
+            Some(group_id) => match database.get_schedule_group(group_id).await {
+                Ok(Some(group)) => ScheduleLookup::Found(group.polling_interval_ms),
+                Ok(None) => ScheduleLookup::Missing,
+                Err(_) => ScheduleLookup::Failed,
             },
-            _ => {
-                // Unknown device type, use entity group as fallback
-                entity_group_name.to_string()
-            }
+            None => ScheduleLookup::NoGroup,
         }
     }
 
-    /// Check if tag description matches MPPT pattern
-    /// Example: "MPPT - MPPT 1 (SG125CX-P2)" should match mppt_index = 1
-    fn tag_matches_mppt(&self, description: Option<&str>, mppt_index: u32) -> bool {
-        if let Some(desc) = description {
-            // Look for pattern "MPPT - MPPT {number}"
-            if desc.starts_with("MPPT - MPPT ") {
-                let parts: Vec<&str> = desc.split(' ').collect();
-                if parts.len() >= 4 {
-                    if let Ok(num) = parts[3].parse::<u32>() {
-                        return num == mppt_index;
-                    }
-                }
-            }
-        }
-        false
-    }
+    pub async fn get_device_by_id(&self, device_id: &str) -> Result<Device, TbError> {
+        let response = self
+            .send_authorized(|client| client.get(format!("{}/api/device/{}", self.base_url, device_id)))
+            .await?;
 
-    /// Check if tag description matches String pattern
-    /// Example: "String - MPPT 1 - Input 2" should match mppt_index = 1, input_index = 2
-    fn tag_matches_string(&self, description: Option<&str>, mppt_index: u32, input_index: u32) -> bool {
-        if let Some(desc) = description {
-            // Look for pattern "String - MPPT {mppt_number} - Input {input_number}"
-            if desc.starts_with("String - MPPT ") {
-                let parts: Vec<&str> = desc.split(' ').collect();
-                if parts.len() >= 7 {
-                    if let (Ok(mppt_num), Ok(input_num)) = (parts[3].parse::<u32>(), parts[6].parse::<u32>()) {
-                        return mppt_num == mppt_index && input_num == input_index;
-                    }
-                }
-            }
+        if response.status().is_success() {
+            let device: Device = response.json().await?;
+            Ok(device)
+        } else if response.status() == reqwest::StatusCode::NOT_FOUND {
+            Err(TbError::NotFound(format!("device {}", device_id)))
+        } else {
+            let error_text = self.error_body("GET", response).await;
+            Err(TbError::Api(format!("Get device failed: {}", error_text)))
         }
-        false
     }
 
-    /// Create a placeholder row for devices without data
-    async fn create_placeholder_row(
-        &self,
-        writer: &mut Writer<File>,
-        total_rows: &mut usize,
-        device: &DeviceData,
-        customer: &str,
-        device_index: u32, // Renamed from inv_index to be more generic
-        mppt_index: u32,
-        input_index: u32,
-        entity_group_name: &str,
-        token: &str,
-        error_msg: &str,
-    ) -> Result<(), TbError> {
-        let (mppt_col, input_col) = match device.device_type.as_str() {
-            "Mppt" => (mppt_index.to_string(), "".to_string()),
-            "String" => (mppt_index.to_string(), input_index.to_string()),
-            _ => ("".to_string(), "".to_string())
-        };
-
-        let parent_name = self.get_parent_name(device, entity_group_name, device_index, mppt_index);
-
-        writer.write_record(&[
-            &total_rows.to_string(),              // IOA
-            &total_rows.to_string(),              // Index
-            "",                                   // Serial Number (empty for placeholder) ← NEW
-            &device.name,                         // Device Name
-            "Unknown",                            // Device Brand
-            "Unknown",                            // Device Model
-            customer,                             // Customer
-            &device.device_type,                  // AVA Type
-            token,                               // Token
-            &parent_name,                        // Parent
-            entity_group_name,                   // Plant
-            &device_index.to_string(),              // INV
-            &mppt_col,                           // MPPT
-            &input_col,                          // INPUT
-            &device.label,                       // Label
-            &device.id.id,                      // Device ID
-            "",                                  // Host (empty)
-            "",                                  // Port (empty)
-            "",                                  // Modbus ID (empty)
-            "",                                  // Modbus Type (empty)
-            error_msg,                           // Data Label
-            "",                                  // Address
-            "",                                  // Size
-            "",                                  // Data Type
-            "",                                  // Divider
-            "",                                  // Register Type
-            "",                                  // Frequency
-            "",                                  // Agg To Field (empty for placeholder) ← NEW
-        ]).map_err(|e| TbError::Api(format!("Failed to write placeholder row: {}", e)))?;
-        *total_rows += 1;
-        Ok(())
-    }
+    /// Delete a device with its telemetry, attributes and relations.
+    /// DELETE /api/device/{deviceId}
+    pub async fn delete_device(&self, device_id: &str) -> Result<(), TbError> {
+        let url = format!("{}/api/device/{}", self.base_url, device_id);
+        let response = self
+            .send_authorized(|client| client.delete(&url))
+            .await?;
 
-    /// Convert scaling multiplier to divider format
-    /// Examples: 0.1 -> "10", 0.001 -> "1000", 1.0 -> "1"
-    fn convert_scaling_multiplier_to_divider(&self, multiplier: f64) -> String {
-        if multiplier == 0.0 {
-            return "1".to_string();
-        }
-        
-        let divider = 1.0 / multiplier;
-        if divider.fract() == 0.0 {
-            format!("{:.0}", divider)
+        let status_code = response.status();
+        if status_code.is_success() {
+            info!(device_id, "Deleted ThingsBoard device");
+            Ok(())
+        } else if status_code == reqwest::StatusCode::NOT_FOUND {
+            Err(TbError::NotFound(format!("device {}", device_id)))
         } else {
-            format!("{}", divider)
+            let error_text = self.error_body("DELETE", response).await;
+            Err(TbError::Api(format!("Device deletion failed (Status: {}): {}", status_code, error_text)))
         }
     }
 
-    /// Get frequency information from schedule group (numeric value only)
-    async fn get_schedule_group_frequency(&self, database: &Database, schedule_group_id: &Option<String>) -> String {
-        if let Some(group_id) = schedule_group_id {
-            match database.get_schedule_group(group_id).await {
-                Ok(Some(group)) => group.polling_interval_ms.to_string(),
-                Ok(None) => "Unknown".to_string(),
-                Err(_) => "Error".to_string(),
-            }
-        } else {
-            "None".to_string()
-        }
-    }
+    /// Relate two entities, e.g. `Contains` from an inverter to its MPPT.
+    /// POST /api/relation
+    /// ThingsBoard keeps one relation per from, to and type, so saving it again is harmless;
+    /// a 409 for an existing relation is treated as success as well.
+    pub async fn create_relation(&self, from_id: &DeviceId, to_id: &DeviceId, relation_type: &str) -> Result<(), TbError> {
+        let url = format!("{}/api/relation", self.base_url);
+        let relation = serde_json::json!({
+            "from": from_id,
+            "to": to_id,
+            "type": relation_type,
+            "typeGroup": "COMMON",
+        });
+        let response = self
+            .send_authorized(|client| client.post(&url).json(&relation))
+            .await?;
 
-    /// Parse protocol config JSON and extract connection details
-    fn parse_protocol_config(&self, protocol_config: &str) -> (String, String, String, String) {
-        match serde_json::from_str::<serde_json::Value>(protocol_config) {
-            Ok(config) => {
-                let host = config.get("host")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("")
-                    .to_string();
-                
-                let port = config.get("port")
-                    .and_then(|v| v.as_u64())
-                    .map(|p| p.to_string())
-                    .unwrap_or("".to_string());
-                
-                let modbus_id = config.get("slave_id")
-                    .and_then(|v| v.as_u64())
-                    .map(|s| s.to_string())
-                    .unwrap_or("".to_string());
-                
-                let modbus_type = config.get("type")
-                    .and_then(|v| v.as_str())
-                    .map(|t| t.to_string())
-                    .unwrap_or("".to_string());
-                
-                (host, port, modbus_id, modbus_type)
-            }
-            Err(_) => ("".to_string(), "".to_string(), "".to_string(), "".to_string())
+        let status_code = response.status();
+        if status_code.is_success() || status_code == reqwest::StatusCode::CONFLICT {
+            Ok(())
+        } else {
+            let error_text = self.error_body("POST", response).await;
+            Err(TbError::Api(format!("Create relation failed (Status: {}): {}", status_code, error_text)))
         }
     }
 
-    pub async fn get_device_by_id(&self, device_id: &str) -> Result<Device, TbError> {
-        let auth_header = self.get_auth_header()?;
-
+    /// The tenant's device with this exact name
+    /// GET /api/tenant/devices?deviceName={name}
+    pub async fn get_tenant_device(&self, name: &str) -> Result<Device, TbError> {
+        let url = format!("{}/api/tenant/devices", self.base_url);
         let response = self
-            .client
-            .get(&format!("{}/api/device/{}", self.base_url, device_id))
-            .header("Authorization", auth_header)
-            .send()
+            .send_authorized(|client| client.get(&url).query(&[("deviceName", name)]))
             .await?;
 
         if response.status().is_success() {
             let device: Device = response.json().await?;
             Ok(device)
+        } else if response.status() == reqwest::StatusCode::NOT_FOUND {
+            Err(TbError::NotFound(format!("device named {}", name)))
         } else {
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            Err(TbError::Api(format!("Get device failed: {}", error_text)))
+            let status_code = response.status();
+            let error_text = self.error_body("GET", response).await;
+            Err(TbError::Api(format!("Get device by name failed (Status: {}): {}", status_code, error_text)))
         }
     }
 
@@ -2178,21 +2605,33 @@ impl ThingsBoardClient {
         device_id: &str,
         telemetry: &HashMap<String, serde_json::Value>,
     ) -> Result<(), TbError> {
-        let auth_header = self.get_auth_header()?;
+        let response = self
+            .send_authorized(|client| client.post(format!("{}/api/plugins/telemetry/DEVICE/{}/timeseries/ANY", self.base_url, device_id)).json(telemetry))
+            .await?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            let error_text = self.error_body("POST", response).await;
+            Err(TbError::Api(format!("Save telemetry failed: {}", error_text)))
+        }
+    }
 
+    /// Save telemetry sampled at several instants in one request
+    pub async fn save_device_telemetry_batch(
+        &self,
+        device_id: &str,
+        points: &[TelemetryPoint],
+    ) -> Result<(), TbError> {
         let response = self
-            .client
-            .post(&format!("{}/api/plugins/telemetry/DEVICE/{}/timeseries/ANY", self.base_url, device_id))
-            .header("Authorization", auth_header)
-            .json(telemetry)
-            .send()
+            .send_authorized(|client| client.post(format!("{}/api/plugins/telemetry/DEVICE/{}/timeseries/ANY", self.base_url, device_id)).json(points))
             .await?;
 
         if response.status().is_success() {
             Ok(())
         } else {
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            let error_text = self.error_body("POST", response).await;
             Err(TbError::Api(format!("Save telemetry failed: {}", error_text)))
         }
     }
-}
\ No newline at end of file
+}
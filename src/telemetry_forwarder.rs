@@ -0,0 +1,293 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+use anyhow::Result;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::config::{AppConfig, NonGoodTelemetry};
+use crate::database::{Database, LogEntry, Quality, TelemetryOutboxEntry};
+use crate::virtual_tags::QUALITY_COMPUTED;
+use crate::metrics::Metrics;
+use crate::tag_description::{self, DeviceType};
+use crate::tb_rust_client::{RateLimiter, TbSession, TelemetryPoint, ThingsBoardClient};
+
+/// Longest pause between pushes while ThingsBoard keeps failing, as a multiple of the batch interval
+const MAX_BACKOFF_FACTOR: u32 = 32;
+
+/// Whether ThingsBoard gets a value without a quality key: good reads and virtual tags
+fn is_good(quality: &str) -> bool {
+    quality == Quality::Good.as_str() || quality == QUALITY_COMPUTED
+}
+
+/// Pushes logged values to ThingsBoard in batches.
+///
+/// Values are written to the `telemetry_outbox` table before anything is sent, so they
+/// survive restarts and outages. Each device drains its own queue in a separate task,
+/// so a device whose pushes keep failing doesn't hold up the others.
+pub struct TelemetryForwarder {
+    database: Arc<Database>,
+    config: Arc<AppConfig>,
+    session: Arc<TbSession>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    metrics: Arc<Metrics>,
+    workers: StdMutex<HashMap<String, Worker>>, // Device ID -> its push task
+    shutdown: CancellationToken,
+}
+
+struct Worker {
+    /// Wakes the task early
+    wake: Arc<Notify>,
+    task: JoinHandle<()>,
+}
+
+impl TelemetryForwarder {
+    pub fn new(database: Arc<Database>, config: Arc<AppConfig>, session: Arc<TbSession>) -> Self {
+        Self {
+            database,
+            config,
+            session,
+            rate_limiter: None,
+            metrics: Arc::new(Metrics::new()),
+            workers: StdMutex::new(HashMap::new()),
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    /// Count pushes and the backlog in the service's shared metrics
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Pace pushes under the rate limit the API handlers share
+    pub fn with_rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Forwarding is switched on and there is a ThingsBoard server to forward to
+    pub fn is_enabled(&self) -> bool {
+        self.config.telemetry_forwarding.enabled && self.config.thingsboard.is_some()
+    }
+
+    /// Let every push task finish the batch it is sending and stop, returning the tasks by
+    /// device ID so the caller can wait for them. Queued values stay in the outbox for the next start.
+    pub fn stop(&self) -> Vec<(String, JoinHandle<()>)> {
+        self.shutdown.cancel();
+        self.workers.lock().unwrap().drain().map(|(device_id, worker)| (device_id, worker.task)).collect()
+    }
+
+    /// Start draining queues left over from before the last shutdown
+    pub async fn resume(self: &Arc<Self>) -> Result<()> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        let backlog = self.database.get_telemetry_backlog_counts().await?;
+        for (device_id, pending) in backlog {
+            info!("Resuming telemetry forwarding for device {} with {} queued values", device_id, pending);
+            self.metrics.set_telemetry_backlog(&device_id, pending as u64);
+            self.ensure_worker(&device_id);
+        }
+
+        Ok(())
+    }
+
+    /// Queue a poll's entries for the given ThingsBoard device. Values of MPPT and string tags
+    /// go to the MPPT and String devices recorded for the inverter, when it has any. Values
+    /// that aren't good are skipped or queued per `non_good_values`; muted tags never are.
+    pub async fn enqueue(self: &Arc<Self>, tb_device_id: &str, entries: &[LogEntry]) -> Result<()> {
+        let Some(device_id) = entries.first().map(|entry| entry.device_id.clone()) else {
+            return Ok(());
+        };
+
+        let routes = self.child_routes(&device_id).await?;
+        let mut by_target: BTreeMap<&str, Vec<LogEntry>> = BTreeMap::new();
+        for entry in entries.iter().filter(|entry| self.forwards(entry)) {
+            let target = routes.get(&entry.tag_name).map(String::as_str).unwrap_or(tb_device_id);
+            by_target.entry(target).or_default().push(entry.clone());
+        }
+
+        let mut queued = 0;
+        for (target, target_entries) in by_target {
+            queued += self.database.enqueue_telemetry(target, &target_entries).await?;
+        }
+        if queued == 0 {
+            return Ok(());
+        }
+
+        let wake = self.ensure_worker(&device_id);
+        let backlog = self.database.get_telemetry_backlog(&device_id).await?;
+        self.metrics.set_telemetry_backlog(&device_id, backlog.pending as u64);
+        if backlog.pending as usize >= self.config.telemetry_forwarding.max_batch_size {
+            wake.notify_one();
+        }
+
+        Ok(())
+    }
+
+    fn forwards(&self, entry: &LogEntry) -> bool {
+        match self.config.telemetry_forwarding.non_good_values {
+            NonGoodTelemetry::Skip => is_good(&entry.quality),
+            NonGoodTelemetry::Annotate => entry.is_logged(),
+        }
+    }
+
+    /// Push the oldest batch of a device's queued values, returning how many were delivered
+    pub async fn flush_device(&self, device_id: &str) -> Result<usize> {
+        let batch = self
+            .database
+            .get_telemetry_batch(device_id, self.config.telemetry_forwarding.max_batch_size.max(1))
+            .await?;
+        if batch.is_empty() {
+            return Ok(0);
+        }
+
+        let ids: Vec<i64> = batch.iter().map(|entry| entry.id).collect();
+        match self.push(&Self::to_points(&batch)).await {
+            Ok(()) => {
+                self.database.delete_telemetry(&ids).await?;
+                self.metrics.record_telemetry_push(device_id, true);
+                self.metrics.reduce_telemetry_backlog(device_id, batch.len());
+                Ok(batch.len())
+            }
+            Err(message) => {
+                self.metrics.record_telemetry_push(device_id, false);
+                self.database.mark_telemetry_failed(&ids, &message).await?;
+                Err(anyhow::anyhow!(message))
+            }
+        }
+    }
+
+    /// Tag name -> ThingsBoard device id for the tags of a recorded MPPT or String child device
+    async fn child_routes(&self, device_id: &str) -> Result<HashMap<String, String>> {
+        let children = self.database.get_tb_child_devices(device_id).await?;
+        if children.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let tags = self.database.get_device_tags(device_id).await?;
+        let routes = tags
+            .into_iter()
+            .filter_map(|tag| {
+                let (mppt_number, input_number) = match tag_description::parse(tag.description.as_deref()?) {
+                    DeviceType::Mppt(mppt_number) => (mppt_number, None),
+                    DeviceType::String(mppt_number, input_number) => (mppt_number, Some(input_number)),
+                    DeviceType::Inverter | DeviceType::Unknown => return None,
+                };
+                children
+                    .iter()
+                    .find(|child| child.mppt_number == mppt_number && child.input_number == input_number)
+                    .map(|child| (tag.name, child.tb_device_id.clone()))
+            })
+            .collect();
+
+        Ok(routes)
+    }
+
+    fn ensure_worker(self: &Arc<Self>, device_id: &str) -> Arc<Notify> {
+        let mut workers = self.workers.lock().unwrap();
+        if let Some(worker) = workers.get(device_id) {
+            return worker.wake.clone();
+        }
+
+        let wake = Arc::new(Notify::new());
+        let forwarder = self.clone();
+        let worker_device_id = device_id.to_string();
+        let worker_wake = wake.clone();
+        let task = tokio::spawn(async move {
+            forwarder.run_worker(worker_device_id, worker_wake).await;
+        });
+        workers.insert(device_id.to_string(), Worker { wake: wake.clone(), task });
+
+        wake
+    }
+
+    async fn run_worker(&self, device_id: String, wake: Arc<Notify>) {
+        let interval = Duration::from_secs(self.config.telemetry_forwarding.batch_interval_seconds.max(1));
+        let max_batch_size = self.config.telemetry_forwarding.max_batch_size.max(1);
+        let mut backoff_factor = 1;
+
+        loop {
+            // A full queue only cuts the wait short while ThingsBoard is accepting pushes
+            tokio::select! {
+                _ = self.shutdown.cancelled() => return,
+                _ = tokio::time::sleep(interval * backoff_factor) => {},
+                _ = wake.notified(), if backoff_factor == 1 => {},
+            }
+
+            loop {
+                match self.flush_device(&device_id).await {
+                    Ok(sent) => {
+                        backoff_factor = 1;
+                        if sent < max_batch_size || self.shutdown.is_cancelled() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        backoff_factor = (backoff_factor * 2).min(MAX_BACKOFF_FACTOR);
+                        warn!(
+                            "Telemetry push for device {} failed, retrying in {}s: {}",
+                            device_id, (interval * backoff_factor).as_secs(), e
+                        );
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Group a batch by ThingsBoard device and sample time. Values that aren't good carry a
+    /// `<tag>_quality` key, and failed reads only that key.
+    fn to_points(batch: &[TelemetryOutboxEntry]) -> BTreeMap<String, Vec<TelemetryPoint>> {
+        let mut grouped: BTreeMap<String, BTreeMap<i64, HashMap<String, serde_json::Value>>> = BTreeMap::new();
+        for entry in batch {
+            let values = grouped
+                .entry(entry.tb_device_id.clone())
+                .or_default()
+                .entry(entry.timestamp.timestamp_millis())
+                .or_default();
+            if !Quality::parse(&entry.quality).is_some_and(|quality| quality.is_failure()) {
+                values.insert(entry.tag_name.clone(), serde_json::json!(entry.value));
+            }
+            if !is_good(&entry.quality) {
+                values.insert(format!("{}_quality", entry.tag_name), serde_json::json!(entry.quality));
+            }
+        }
+
+        grouped
+            .into_iter()
+            .map(|(tb_device_id, samples)| {
+                let points = samples.into_iter().map(|(ts, values)| TelemetryPoint { ts, values }).collect();
+                (tb_device_id, points)
+            })
+            .collect()
+    }
+
+    async fn push(&self, points: &BTreeMap<String, Vec<TelemetryPoint>>) -> std::result::Result<(), String> {
+        let mut client = ThingsBoardClient::from_config(&self.config)
+            .map_err(|e| e.to_string())?
+            .with_session(self.session.clone());
+        if let Some(rate_limiter) = &self.rate_limiter {
+            client = client.with_rate_limiter(rate_limiter.clone());
+        }
+
+        if let Err(e) = client.login_configured().await {
+            let message = client.sanitize_error(&e);
+            error!("ThingsBoard login for telemetry forwarding failed: {}", message);
+            return Err(message);
+        }
+
+        for (tb_device_id, device_points) in points {
+            client
+                .save_device_telemetry_batch(tb_device_id, device_points)
+                .await
+                .map_err(|e| client.sanitize_error(&e))?;
+        }
+
+        Ok(())
+    }
+}
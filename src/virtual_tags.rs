@@ -0,0 +1,545 @@
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Mutex as StdMutex;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use tokio::sync::Notify;
+use tracing::warn;
+
+use crate::database::{Database, LogEntry, NewVirtualTag, VirtualTag};
+use crate::live_values::LastValueCache;
+
+/// Quality of the values virtual tags compute
+pub const QUALITY_COMPUTED: &str = "computed";
+
+/// `group('*')` selects every device
+const ANY_GROUP: &str = "*";
+
+/// ThingsBoard group of every device, by device id
+pub type DeviceGroups = BTreeMap<String, Option<String>>;
+
+/// Where a selector takes its values from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// One device, or the group id a plant-level virtual tag is logged under
+    Device(String),
+    /// Every device of a ThingsBoard group, or every device for `*`
+    Group(String),
+}
+
+/// `device('inv1').tag('Pac')` or `group('*').tag('Pac')`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selector {
+    pub source: Source,
+    pub tag_name: String,
+}
+
+impl Selector {
+    /// Whether a value of `tag_name` logged under `device_id` is one of the selector's
+    pub fn matches(&self, device_id: &str, tag_name: &str, groups: &DeviceGroups) -> bool {
+        self.tag_name == tag_name
+            && match &self.source {
+                Source::Device(id) => id == device_id,
+                Source::Group(group) => groups
+                    .get(device_id)
+                    .is_some_and(|device_group| group == ANY_GROUP || device_group.as_deref() == Some(group.as_str())),
+            }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Sum,
+    Min,
+    Max,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+/// A parsed virtual tag expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    Tag(Selector),
+    Negate(Box<Expr>),
+    Binary(Operator, Box<Expr>, Box<Expr>),
+    /// A group selector among the arguments adds the value of every device it matches
+    Call(Function, Vec<Expr>),
+}
+
+impl Expr {
+    /// Parse an expression such as `sum(group('*').tag('Pac')) / 1000`. Errors say what was
+    /// expected and at which character.
+    pub fn parse(text: &str) -> Result<Expr> {
+        let mut parser = Parser { chars: text.chars().collect(), at: 0 };
+        let expr = parser.expression()?;
+        if let Some(found) = parser.peek() {
+            return parser.error(format!("unexpected '{}'", found));
+        }
+        check_group_selectors(&expr)?;
+        Ok(expr)
+    }
+
+    /// Selectors the expression reads
+    pub fn selectors(&self) -> Vec<&Selector> {
+        match self {
+            Expr::Number(_) => Vec::new(),
+            Expr::Tag(selector) => vec![selector],
+            Expr::Negate(expr) => expr.selectors(),
+            Expr::Binary(_, left, right) => left.selectors().into_iter().chain(right.selectors()).collect(),
+            Expr::Call(_, args) => args.iter().flat_map(|arg| arg.selectors()).collect(),
+        }
+    }
+
+    /// Value of the expression given the values each selector matches. None when a selector
+    /// matches no value, or the result isn't a finite number.
+    pub fn evaluate(&self, values: &impl Fn(&Selector) -> Vec<f64>) -> Option<f64> {
+        let value = match self {
+            Expr::Number(number) => *number,
+            Expr::Tag(selector) => *values(selector).first()?,
+            Expr::Negate(expr) => -expr.evaluate(values)?,
+            Expr::Binary(operator, left, right) => {
+                let (left, right) = (left.evaluate(values)?, right.evaluate(values)?);
+                match operator {
+                    Operator::Add => left + right,
+                    Operator::Subtract => left - right,
+                    Operator::Multiply => left * right,
+                    Operator::Divide => left / right,
+                }
+            }
+            Expr::Call(function, args) => {
+                let mut all = Vec::new();
+                for arg in args {
+                    match arg {
+                        Expr::Tag(selector) => {
+                            let matched = values(selector);
+                            if matched.is_empty() {
+                                return None;
+                            }
+                            all.extend(matched);
+                        }
+                        _ => all.push(arg.evaluate(values)?),
+                    }
+                }
+                match function {
+                    Function::Sum => all.iter().sum(),
+                    Function::Min => all.iter().copied().fold(f64::INFINITY, f64::min),
+                    Function::Max => all.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+                }
+            }
+        };
+        value.is_finite().then_some(value)
+    }
+}
+
+/// Group selectors have a value per device, so they only make sense as function arguments
+fn check_group_selectors(expr: &Expr) -> Result<()> {
+    match expr {
+        Expr::Number(_) | Expr::Tag(Selector { source: Source::Device(_), .. }) => Ok(()),
+        Expr::Tag(Selector { source: Source::Group(group), tag_name }) => bail!(
+            "group('{}').tag('{}') has a value per device, so it can only be an argument of sum, min or max",
+            group, tag_name
+        ),
+        Expr::Negate(expr) => check_group_selectors(expr),
+        Expr::Binary(_, left, right) => check_group_selectors(left).and_then(|_| check_group_selectors(right)),
+        Expr::Call(_, args) => args.iter().filter(|arg| !matches!(arg, Expr::Tag(_))).try_for_each(check_group_selectors),
+    }
+}
+
+/// Recursive descent over `expression := term (('+' | '-') term)*`,
+/// `term := unary (('*' | '/') unary)*` and `unary := '-' unary | number | '(' expression ')' |
+/// function '(' expression (',' expression)* ')' | selector`
+struct Parser {
+    chars: Vec<char>,
+    at: usize,
+}
+
+impl Parser {
+    fn error<T>(&self, message: impl std::fmt::Display) -> Result<T> {
+        bail!("{} at character {}", message, self.at + 1)
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.get(self.at).is_some_and(|c| c.is_whitespace()) {
+            self.at += 1;
+        }
+        self.chars.get(self.at).copied()
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        let found = self.peek() == Some(expected);
+        if found {
+            self.at += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        match self.peek() {
+            _ if self.eat(expected) => Ok(()),
+            Some(found) => self.error(format!("expected '{}' but found '{}'", expected, found)),
+            None => self.error(format!("expected '{}' but the expression ended", expected)),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expr> {
+        let mut left = self.term()?;
+        loop {
+            let operator = match self.peek() {
+                Some('+') => Operator::Add,
+                Some('-') => Operator::Subtract,
+                _ => return Ok(left),
+            };
+            self.at += 1;
+            left = Expr::Binary(operator, Box::new(left), Box::new(self.term()?));
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr> {
+        let mut left = self.unary()?;
+        loop {
+            let operator = match self.peek() {
+                Some('*') => Operator::Multiply,
+                Some('/') => Operator::Divide,
+                _ => return Ok(left),
+            };
+            self.at += 1;
+            left = Expr::Binary(operator, Box::new(left), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        match self.peek() {
+            Some('-') => {
+                self.at += 1;
+                Ok(Expr::Negate(Box::new(self.unary()?)))
+            }
+            Some('(') => {
+                self.at += 1;
+                let expr = self.expression()?;
+                self.expect(')')?;
+                Ok(expr)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => self.number(),
+            Some(c) if c.is_alphabetic() => self.call_or_selector(),
+            Some(found) => self.error(format!("unexpected '{}'", found)),
+            None => self.error("expected a value but the expression ended"),
+        }
+    }
+
+    fn number(&mut self) -> Result<Expr> {
+        let start = self.at;
+        while self.chars.get(self.at).is_some_and(|c| c.is_ascii_digit() || *c == '.') {
+            self.at += 1;
+        }
+        // An exponent, as in 1e-3
+        if matches!(self.chars.get(self.at), Some('e' | 'E')) {
+            let digits = match self.chars.get(self.at + 1) {
+                Some('+' | '-') => self.at + 2,
+                _ => self.at + 1,
+            };
+            if self.chars.get(digits).is_some_and(|c| c.is_ascii_digit()) {
+                self.at = digits;
+                while self.chars.get(self.at).is_some_and(|c| c.is_ascii_digit()) {
+                    self.at += 1;
+                }
+            }
+        }
+        let text: String = self.chars[start..self.at].iter().collect();
+        match text.parse() {
+            Ok(number) => Ok(Expr::Number(number)),
+            Err(_) => {
+                self.at = start;
+                self.error(format!("'{}' is not a number", text))
+            }
+        }
+    }
+
+    fn identifier(&mut self) -> String {
+        self.peek();
+        let start = self.at;
+        while self.chars.get(self.at).is_some_and(|c| c.is_alphanumeric() || *c == '_') {
+            self.at += 1;
+        }
+        self.chars[start..self.at].iter().collect()
+    }
+
+    /// A name in single or double quotes
+    fn quoted(&mut self) -> Result<String> {
+        let quote = match self.peek() {
+            Some(quote @ ('\'' | '"')) => quote,
+            _ => return self.error("expected a quoted name"),
+        };
+        let start = self.at;
+        let Some(length) = self.chars[start + 1..].iter().position(|c| *c == quote) else {
+            return self.error("missing closing quote");
+        };
+        self.at = start + length + 2;
+        Ok(self.chars[start + 1..start + 1 + length].iter().collect())
+    }
+
+    fn call_or_selector(&mut self) -> Result<Expr> {
+        let start = self.at;
+        let name = self.identifier();
+        let function = match name.as_str() {
+            "sum" => Function::Sum,
+            "min" => Function::Min,
+            "max" => Function::Max,
+            "device" | "group" => {
+                self.expect('(')?;
+                let id = self.quoted()?;
+                self.expect(')')?;
+                self.expect('.')?;
+                let method_at = self.at;
+                if self.identifier() != "tag" {
+                    self.at = method_at;
+                    return self.error("expected .tag('name')");
+                }
+                self.expect('(')?;
+                let tag_name = self.quoted()?;
+                self.expect(')')?;
+                let source = if name == "device" { Source::Device(id) } else { Source::Group(id) };
+                return Ok(Expr::Tag(Selector { source, tag_name }));
+            }
+            _ => {
+                self.at = start;
+                return self.error(format!("unknown name '{}', expected sum, min, max, device or group", name));
+            }
+        };
+
+        self.expect('(')?;
+        if self.peek() == Some(')') {
+            return self.error(format!("{} needs at least one value", name));
+        }
+        let mut args = vec![self.expression()?];
+        while self.eat(',') {
+            args.push(self.expression()?);
+        }
+        self.expect(')')?;
+        Ok(Expr::Call(function, args))
+    }
+}
+
+/// For every tag, the indexes of the tags whose values it reads
+fn dependencies(tags: &[(&NewVirtualTag, &Expr)], groups: &DeviceGroups) -> Vec<Vec<usize>> {
+    tags.iter()
+        .map(|(_, expr)| {
+            let selectors = expr.selectors();
+            tags.iter()
+                .enumerate()
+                .filter(|(_, (input, _))| selectors.iter().any(|selector| selector.matches(input.owner(), &input.name, groups)))
+                .map(|(index, _)| index)
+                .collect()
+        })
+        .collect()
+}
+
+/// A loop of virtual tags reading each other's values, as the `owner/name` of the tags
+/// around it with the first one repeated at the end. Tags whose expression doesn't parse are
+/// left out.
+pub fn find_cycle(tags: &[NewVirtualTag], groups: &DeviceGroups) -> Option<Vec<String>> {
+    fn visit(node: usize, dependencies: &[Vec<usize>], on_path: &mut Vec<usize>, done: &mut HashSet<usize>) -> Option<Vec<usize>> {
+        if let Some(from) = on_path.iter().position(|visited| *visited == node) {
+            let mut cycle = on_path[from..].to_vec();
+            cycle.push(node);
+            return Some(cycle);
+        }
+        if done.contains(&node) {
+            return None;
+        }
+        on_path.push(node);
+        for &next in &dependencies[node] {
+            if let Some(cycle) = visit(next, dependencies, on_path, done) {
+                return Some(cycle);
+            }
+        }
+        on_path.pop();
+        done.insert(node);
+        None
+    }
+
+    let parsed: Vec<(&NewVirtualTag, Expr)> = tags.iter().filter_map(|tag| Some((tag, Expr::parse(&tag.expression).ok()?))).collect();
+    let tags: Vec<(&NewVirtualTag, &Expr)> = parsed.iter().map(|(tag, expr)| (*tag, expr)).collect();
+    let dependencies = dependencies(&tags, groups);
+    let mut done = HashSet::new();
+    (0..tags.len()).find_map(|start| {
+        let cycle = visit(start, &dependencies, &mut Vec::new(), &mut done)?;
+        Some(cycle.into_iter().map(|index| format!("{}/{}", tags[index].0.owner(), tags[index].0.name)).collect())
+    })
+}
+
+/// Indexes of the tags, each after the tags it reads unless they read each other
+fn evaluation_order(dependencies: &[Vec<usize>]) -> Vec<usize> {
+    fn visit(node: usize, dependencies: &[Vec<usize>], visited: &mut [bool], order: &mut Vec<usize>) {
+        if std::mem::replace(&mut visited[node], true) {
+            return;
+        }
+        for &next in &dependencies[node] {
+            visit(next, dependencies, visited, order);
+        }
+        order.push(node);
+    }
+
+    let mut visited = vec![false; dependencies.len()];
+    let mut order = Vec::with_capacity(dependencies.len());
+    for node in 0..dependencies.len() {
+        visit(node, dependencies, &mut visited, &mut order);
+    }
+    order
+}
+
+struct CompiledTag {
+    tag: VirtualTag,
+    expr: Expr,
+    /// Computed at this interval rather than whenever an input is read
+    interval: Option<chrono::Duration>,
+    last_computed: Option<DateTime<Utc>>,
+}
+
+/// Computes the enabled virtual tags from the last values read.
+///
+/// A tag without a schedule group is computed once one of its inputs is read, and a tag in
+/// one at the group's interval. Each computed value is in the last value cache before the
+/// tags reading it are computed, so a chain of virtual tags settles in one pass.
+#[derive(Default)]
+pub struct VirtualTagEngine {
+    tags: StdMutex<Vec<CompiledTag>>,
+    /// ThingsBoard group of every started device, for group selectors
+    groups: StdMutex<DeviceGroups>,
+    /// Ids of tags with an input read since they were last computed
+    pending: StdMutex<HashSet<i64>>,
+    inputs_read: Notify,
+}
+
+impl VirtualTagEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the enabled virtual tags. Tags in a disabled or unknown schedule group aren't
+    /// computed. Call again after virtual tags or schedule groups change.
+    pub async fn load(&self, database: &Database) -> Result<()> {
+        let schedule_groups = database.get_schedule_groups().await?;
+        let mut compiled = Vec::new();
+        for tag in database.get_virtual_tags().await?.into_iter().filter(|tag| tag.tag.enabled) {
+            let expr = match Expr::parse(&tag.tag.expression) {
+                Ok(expr) => expr,
+                Err(e) => {
+                    warn!("Virtual tag {} is not computed: {}", tag.id, e);
+                    continue;
+                }
+            };
+            let interval = match &tag.tag.schedule_group_id {
+                None => None,
+                Some(id) => match schedule_groups.iter().find(|group| &group.id == id && group.enabled) {
+                    Some(group) => Some(chrono::Duration::milliseconds(group.polling_interval_ms.max(1).into())),
+                    None => {
+                        warn!("Virtual tag {} is not computed: schedule group {} is disabled or missing", tag.id, id);
+                        continue;
+                    }
+                },
+            };
+            compiled.push(CompiledTag { tag, expr, interval, last_computed: None });
+        }
+
+        let mut tags = self.tags.lock().unwrap();
+        for tag in &mut compiled {
+            tag.last_computed = tags.iter().find(|loaded| loaded.tag.id == tag.tag.id).and_then(|loaded| loaded.last_computed);
+        }
+        // Catch up with the values read so far rather than waiting for the next read
+        self.pending.lock().unwrap().extend(compiled.iter().filter(|tag| tag.interval.is_none()).map(|tag| tag.tag.id));
+        *tags = compiled;
+        self.inputs_read.notify_one();
+        Ok(())
+    }
+
+    /// Include the device's values in `group(...)` selectors of its ThingsBoard group
+    pub fn watch_device(&self, device_id: &str, group_id: Option<String>) {
+        self.groups.lock().unwrap().insert(device_id.to_string(), group_id);
+    }
+
+    /// Note a poll's values, so the tags reading them are computed next. Failed reads and
+    /// muted tags are skipped.
+    pub fn inputs_updated(&self, entries: &[LogEntry]) {
+        let tags = self.tags.lock().unwrap();
+        let groups = self.groups.lock().unwrap();
+        let mut pending = self.pending.lock().unwrap();
+        let mut added = false;
+        for tag in tags.iter().filter(|tag| tag.interval.is_none()) {
+            let selectors = tag.expr.selectors();
+            let reads_entry = entries
+                .iter()
+                .filter(|entry| entry.has_value())
+                .any(|entry| selectors.iter().any(|selector| selector.matches(&entry.device_id, &entry.tag_name, &groups)));
+            if reads_entry {
+                added |= pending.insert(tag.tag.id);
+            }
+        }
+        if added {
+            self.inputs_read.notify_one();
+        }
+    }
+
+    /// Wait until an input of a tag is read, or the tags are reloaded
+    pub async fn inputs_read(&self) {
+        self.inputs_read.notified().await
+    }
+
+    /// Compute the tags with inputs read since they were last computed, the scheduled tags
+    /// that are due, and the tags reading any of those, adding the values to `live_values`.
+    /// A tag is skipped when an input has no fresh value or the result isn't a number, e.g.
+    /// after a division by zero.
+    pub fn compute(&self, live_values: &LastValueCache, now: DateTime<Utc>) -> Vec<LogEntry> {
+        let mut tags = self.tags.lock().unwrap();
+        let groups = self.groups.lock().unwrap();
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+
+        let dependencies = dependencies(&tags.iter().map(|tag| (&tag.tag.tag, &tag.expr)).collect::<Vec<_>>(), &groups);
+        let values = |selector: &Selector| -> Vec<f64> {
+            match &selector.source {
+                Source::Device(device_id) => live_values.fresh_value(device_id, &selector.tag_name).into_iter().collect(),
+                Source::Group(_) => groups
+                    .keys()
+                    .filter(|device_id| selector.matches(device_id, &selector.tag_name, &groups))
+                    .filter_map(|device_id| live_values.fresh_value(device_id, &selector.tag_name))
+                    .collect(),
+            }
+        };
+
+        let mut computed = HashSet::new();
+        let mut entries = Vec::new();
+        for index in evaluation_order(&dependencies) {
+            let tag = &mut tags[index];
+            let due = match tag.interval {
+                None => pending.contains(&tag.tag.id) || dependencies[index].iter().any(|input| computed.contains(input)),
+                Some(interval) => tag.last_computed.is_none_or(|last| now - last >= interval),
+            };
+            if !due {
+                continue;
+            }
+            tag.last_computed = Some(now);
+            let Some(value) = tag.expr.evaluate(&values) else {
+                continue;
+            };
+
+            let entry = LogEntry {
+                id: None,
+                device_id: tag.tag.tag.owner().to_string(),
+                tag_name: tag.tag.tag.name.clone(),
+                value,
+                quality: QUALITY_COMPUTED.to_string(),
+                timestamp: now,
+                unit: tag.tag.tag.unit.clone(),
+            };
+            live_values.update(std::slice::from_ref(&entry));
+            computed.insert(index);
+            entries.push(entry);
+        }
+        entries
+    }
+}
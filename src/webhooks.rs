@@ -0,0 +1,259 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::Sha256;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::Semaphore;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::config::{WebhookConfig, WebhookEvent};
+use crate::database::{Database, WebhookDeliveryStatus};
+use crate::passwords::to_hex;
+
+/// Header carrying `sha256=<hex HMAC-SHA256 of the body keyed with the webhook's secret>`
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+pub const DELIVERY_HEADER: &str = "X-Webhook-Delivery";
+
+/// Events waiting to be sent; more are dropped and counted
+const DEFAULT_QUEUE_CAPACITY: usize = 256;
+/// Deliveries in flight at once, across all webhooks
+const MAX_CONCURRENT_DELIVERIES: usize = 8;
+/// Deliveries kept per webhook in the delivery log
+const DELIVERIES_KEPT: u32 = 500;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often and how patiently a delivery is retried after a non-2xx response or a failed request
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    /// Wait before the second attempt, doubled after every further one
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(2),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+/// The signature header value of a body
+pub fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(body);
+    format!("sha256={}", to_hex(&mac.finalize().into_bytes()))
+}
+
+/// The JSON body POSTed for an event
+fn payload(event: WebhookEvent, data: Value, at: DateTime<Utc>) -> String {
+    serde_json::json!({
+        "id": Uuid::new_v4().to_string(),
+        "event": event,
+        "timestamp": at,
+        "data": data,
+    })
+    .to_string()
+}
+
+struct QueuedEvent {
+    event: WebhookEvent,
+    data: Value,
+    at: DateTime<Utc>,
+}
+
+/// POSTs events to the webhooks subscribed to them.
+///
+/// Producers only queue the event, so a slow or unreachable endpoint never holds up the
+/// logging pipeline. When the queue is full the event is dropped and counted instead.
+/// Every delivery is recorded in `webhook_deliveries` with its attempts and last error.
+pub struct WebhookNotifier {
+    database: Arc<Database>,
+    hooks: StdMutex<Vec<WebhookConfig>>,
+    sender: mpsc::Sender<QueuedEvent>,
+    receiver: StdMutex<Option<mpsc::Receiver<QueuedEvent>>>,
+    dropped: AtomicU64,
+    retry: RetryPolicy,
+    client: reqwest::Client,
+}
+
+impl WebhookNotifier {
+    pub fn new(database: Arc<Database>, hooks: Vec<WebhookConfig>) -> Self {
+        let (sender, receiver) = mpsc::channel(DEFAULT_QUEUE_CAPACITY);
+        Self {
+            database,
+            hooks: StdMutex::new(hooks),
+            sender,
+            receiver: StdMutex::new(Some(receiver)),
+            dropped: AtomicU64::new(0),
+            retry: RetryPolicy::default(),
+            client: reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build().unwrap_or_default(),
+        }
+    }
+
+    pub fn with_queue_capacity(mut self, capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+        self.sender = sender;
+        self.receiver = StdMutex::new(Some(receiver));
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Start sending queued events; only the first call has an effect
+    pub fn start(self: &Arc<Self>) {
+        let Some(mut receiver) = self.receiver.lock().unwrap().take() else {
+            return;
+        };
+        let notifier = self.clone();
+        let in_flight = Arc::new(Semaphore::new(MAX_CONCURRENT_DELIVERIES));
+
+        tokio::spawn(async move {
+            while let Some(queued) = receiver.recv().await {
+                let body = payload(queued.event, queued.data, queued.at);
+
+                for hook in notifier.subscribers(queued.event) {
+                    // Waiting here lets the queue fill up, so a backlog is dropped rather than grown
+                    let Ok(permit) = in_flight.clone().acquire_owned().await else {
+                        return;
+                    };
+                    let delivery_id = match notifier.database.create_webhook_delivery(&hook.id, queued.event.as_str(), &body, DELIVERIES_KEPT).await {
+                        Ok(id) => id,
+                        Err(e) => {
+                            error!("Failed to record delivery of {} to webhook {}: {}", queued.event.as_str(), hook.id, e);
+                            continue;
+                        }
+                    };
+                    let notifier = notifier.clone();
+                    let body = body.clone();
+                    tokio::spawn(async move {
+                        notifier.deliver(delivery_id, &hook.id, queued.event, body).await;
+                        drop(permit);
+                    });
+                }
+            }
+        });
+    }
+
+    /// Replace the webhooks; deliveries being retried pick up the change on their next attempt
+    pub fn apply(&self, hooks: Vec<WebhookConfig>) {
+        *self.hooks.lock().unwrap() = hooks;
+    }
+
+    pub fn hooks(&self) -> Vec<WebhookConfig> {
+        self.hooks.lock().unwrap().clone()
+    }
+
+    /// Events dropped because the queue was full
+    pub fn dropped_events(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Queue an event for the webhooks subscribed to it, without waiting
+    pub fn send(&self, event: WebhookEvent, data: &impl Serialize) {
+        if self.subscribers(event).is_empty() {
+            return;
+        }
+        let data = match serde_json::to_value(data) {
+            Ok(data) => data,
+            Err(e) => {
+                error!("Failed to serialize {} webhook payload: {}", event.as_str(), e);
+                return;
+            }
+        };
+        match self.sender.try_send(QueuedEvent { event, data, at: Utc::now() }) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                // Warn on the first drop and then ever less often, so a dead endpoint can't flood the log
+                if dropped.is_power_of_two() {
+                    warn!("Webhook queue is full, dropped {} event (dropped {} so far)", event.as_str(), dropped);
+                }
+            }
+            Err(TrySendError::Closed(_)) => {}
+        }
+    }
+
+    /// Send an event to the webhooks subscribed to it and wait until each delivery succeeds or
+    /// runs out of retries, for events whose sender needs to know it arrived. Returns how many
+    /// webhooks it was sent to, or the webhooks it could not be delivered to.
+    pub async fn send_and_wait(&self, event: WebhookEvent, data: &impl Serialize) -> anyhow::Result<usize> {
+        let hooks = self.subscribers(event);
+        let body = payload(event, serde_json::to_value(data)?, Utc::now());
+        let mut failed = Vec::new();
+        for hook in &hooks {
+            let delivery_id = self.database.create_webhook_delivery(&hook.id, event.as_str(), &body, DELIVERIES_KEPT).await?;
+            if !self.deliver(delivery_id, &hook.id, event, body.clone()).await {
+                failed.push(hook.id.as_str());
+            }
+        }
+        if !failed.is_empty() {
+            anyhow::bail!("{} was not delivered to webhook {}", event.as_str(), failed.join(", "));
+        }
+        Ok(hooks.len())
+    }
+
+    fn subscribers(&self, event: WebhookEvent) -> Vec<WebhookConfig> {
+        self.hooks.lock().unwrap().iter().filter(|hook| hook.enabled && hook.events.contains(&event)).cloned().collect()
+    }
+
+    /// Send a recorded delivery until it gets a 2xx response or runs out of attempts; returns
+    /// whether it was delivered
+    async fn deliver(&self, delivery_id: i64, hook_id: &str, event: WebhookEvent, body: String) -> bool {
+        let mut backoff = self.retry.initial_backoff;
+        for attempt in 1..=self.retry.max_attempts.max(1) {
+            // Use the webhook as it is now; stop if it was deleted or disabled meanwhile
+            let Some(hook) = self.hooks().into_iter().find(|hook| hook.id == hook_id && hook.enabled) else {
+                self.record(delivery_id, WebhookDeliveryStatus::Failed, None, Some("webhook was deleted or disabled")).await;
+                return false;
+            };
+
+            let result = self.client
+                .post(hook.url.trim())
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(EVENT_HEADER, event.as_str())
+                .header(DELIVERY_HEADER, delivery_id.to_string())
+                .header(SIGNATURE_HEADER, signature(&hook.secret, body.as_bytes()))
+                .body(body.clone())
+                .send()
+                .await;
+            let (status_code, error) = match result {
+                Ok(response) if response.status().is_success() => {
+                    self.record(delivery_id, WebhookDeliveryStatus::Delivered, Some(response.status().as_u16()), None).await;
+                    return true;
+                }
+                Ok(response) => (Some(response.status().as_u16()), format!("HTTP {}", response.status())),
+                Err(e) => (None, e.to_string()),
+            };
+
+            if attempt == self.retry.max_attempts.max(1) {
+                warn!("Giving up on delivery {} of {} to webhook {} after {} attempts: {}", delivery_id, event.as_str(), hook_id, attempt, error);
+                self.record(delivery_id, WebhookDeliveryStatus::Failed, status_code, Some(&error)).await;
+                return false;
+            }
+            self.record(delivery_id, WebhookDeliveryStatus::Pending, status_code, Some(&error)).await;
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(self.retry.max_backoff);
+        }
+        false
+    }
+
+    async fn record(&self, delivery_id: i64, status: WebhookDeliveryStatus, status_code: Option<u16>, error: Option<&str>) {
+        if let Err(e) = self.database.record_webhook_attempt(delivery_id, status, status_code, error).await {
+            error!("Failed to record attempt of webhook delivery {}: {}", delivery_id, e);
+        }
+    }
+}
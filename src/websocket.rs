@@ -1,8 +1,134 @@
 use axum::{
+    extract::Query,
     response::Response,
 };
-use socketioxide::extract::SocketRef;
-use tracing::{info};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use socketioxide::extract::{AckSender, Data, SocketRef, TryData};
+use socketioxide::SocketIo;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::database::{AggregateFunction, Database, LocalUser};
+use crate::live_values::{DeviceValues, TagValue};
+
+/// How often a connection's session is checked again by default
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// What the Socket.IO handlers need besides the socket
+#[derive(Clone)]
+pub struct SocketContext {
+    pub database: Arc<Database>,
+    /// A connection whose session expired or was logged out is disconnected at the next check
+    pub session_check_interval: Duration,
+}
+
+impl SocketContext {
+    pub fn new(database: Arc<Database>) -> Self {
+        Self { database, session_check_interval: SESSION_CHECK_INTERVAL }
+    }
+}
+
+/// Auth payload of the Socket.IO handshake
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HandshakeAuth {
+    /// Session token from `/api/login`; may also be sent as the `token` query parameter
+    pub token: Option<String>,
+}
+
+/// Event sent just before the server disconnects a client over its session
+pub const AUTH_ERROR_EVENT: &str = "auth_error";
+
+/// Payload of `auth_error`, telling the UI to log in again
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthError {
+    /// `unauthorized` when the handshake had no valid session token, `session_expired` when
+    /// the session expired or was logged out while connected
+    pub reason: String,
+    pub message: String,
+}
+
+/// The account a connection logged in with. The session is verified once, by `on_connect` or
+/// by the first event if that comes in sooner; event handlers check the user's role here.
+#[derive(Clone)]
+struct SocketSession {
+    context: SocketContext,
+    token: Option<String>,
+    user: Arc<OnceCell<Option<LocalUser>>>,
+}
+
+impl SocketSession {
+    async fn user(&self) -> Option<&LocalUser> {
+        self.user
+            .get_or_init(|| async {
+                let token = self.token.as_deref()?;
+                match self.context.database.verify_session(token).await {
+                    Ok(user) => user,
+                    Err(e) => {
+                        warn!("Failed to verify the session of a Socket.IO client: {}", e);
+                        None
+                    }
+                }
+            })
+            .await
+            .as_ref()
+    }
+}
+
+/// One device id or several
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum DeviceIds {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl DeviceIds {
+    fn ids(&self) -> &[String] {
+        match self {
+            DeviceIds::One(id) => std::slice::from_ref(id),
+            DeviceIds::Many(ids) => ids,
+        }
+    }
+}
+
+/// Payload of `subscribe` and `unsubscribe`
+#[derive(Debug, Clone, Deserialize)]
+pub struct Subscription {
+    /// Left out of an `unsubscribe` to drop every subscription
+    pub device_id: Option<DeviceIds>,
+    /// Only these tags of the devices; every tag when empty
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// Acknowledgement of `subscribe` and `unsubscribe`, if the client asked for one
+#[derive(Debug, Clone, Serialize)]
+pub struct SubscriptionAck {
+    /// Device and tag rooms the client is in afterwards
+    pub rooms: Vec<String>,
+    pub error: Option<String>,
+}
+
+/// Room of clients receiving every tag of a device
+pub fn device_room(device_id: &str) -> String {
+    format!("device:{}", device_id)
+}
+
+/// Room of clients receiving one tag of a device
+pub fn tag_room(device_id: &str, tag_name: &str) -> String {
+    format!("device:{}:tag:{}", device_id, tag_name)
+}
+
+/// Room of one user's connections, joined once the handshake's session is verified, so
+/// notifications meant for that user reach no one else
+pub fn user_room(user_id: i64) -> String {
+    format!("user:{}", user_id)
+}
 
 pub async fn socket_handler() -> Response {
     Response::builder()
@@ -11,10 +137,389 @@ pub async fn socket_handler() -> Response {
         .unwrap()
 }
 
-pub async fn on_connect(socket: SocketRef) {
-    info!("Socket.IO client connected: {}", socket.id);
+/// Register the handlers of a new connection. Register it with a closure passing the context:
+/// `io.ns("/", move |socket, auth| websocket::on_connect(socket, auth, context.clone()))`
+///
+/// The handshake must carry a session token from `/api/login`; without a valid one the client
+/// gets an `auth_error` and is disconnected. Handlers are registered before the token is checked
+/// so that events sent right after connecting aren't lost, and they wait for the check.
+pub async fn on_connect(socket: SocketRef, TryData(auth): TryData<HandshakeAuth>, context: SocketContext) {
+    let token = auth.ok().and_then(|auth| auth.token).or_else(|| query_token(&socket));
+    let session = SocketSession { context, token, user: Arc::new(OnceCell::new()) };
 
-    socket.on_disconnect(|socket: SocketRef| async move {
+    let limiter = Arc::new(StdMutex::new(HistoryLimiter::new()));
+    let history_session = session.clone();
+    socket.on("history", move |socket: SocketRef, TryData::<HistoryRequest>(request), ack: AckSender| async move {
+        let reply = match request {
+            Ok(request) => history(&history_session, &limiter, request).await,
+            Err(e) => Err(format!("Invalid history request: {}", e)),
+        };
+        let reply = reply.unwrap_or_else(|error| HistoryAck { error: Some(error), ..Default::default() });
+        if let Err(e) = ack.send(reply) {
+            warn!("Failed to acknowledge history request of {}: {}", socket.id, e);
+        }
+    });
+
+    let subscribe_session = session.clone();
+    socket.on("subscribe", move |socket: SocketRef, Data::<Subscription>(subscription), ack: AckSender| async move {
+        // Every role may watch live values
+        let error = match &subscription.device_id {
+            _ if subscribe_session.user().await.is_none() => Some("Not logged in".to_string()),
+            Some(device_ids) => {
+                let rooms: Vec<String> = device_ids
+                    .ids()
+                    .iter()
+                    .flat_map(|device_id| match subscription.tags.is_empty() {
+                        true => vec![device_room(device_id)],
+                        false => subscription.tags.iter().map(|tag| tag_room(device_id, tag)).collect(),
+                    })
+                    .collect();
+                socket.join(rooms).ok();
+                None
+            }
+            None => Some("device_id is required".to_string()),
+        };
+        acknowledge(&socket, ack, error);
+    });
+
+    socket.on("unsubscribe", |socket: SocketRef, Data::<Subscription>(subscription), ack: AckSender| async move {
+        match &subscription.device_id {
+            Some(device_ids) => {
+                let joined = socket.rooms().unwrap_or_default();
+                for device_id in device_ids.ids() {
+                    // Without tags, the device's tag subscriptions go too
+                    let tag_prefix = tag_room(device_id, "");
+                    let rooms: Vec<String> = match subscription.tags.is_empty() {
+                        true => joined
+                            .iter()
+                            .filter(|room| **room == device_room(device_id) || room.starts_with(&tag_prefix))
+                            .map(|room| room.to_string())
+                            .collect(),
+                        false => subscription.tags.iter().map(|tag| tag_room(device_id, tag)).collect(),
+                    };
+                    socket.leave(rooms).ok();
+                }
+            }
+            None => {
+                // Every subscription, but not the user's room
+                let rooms: Vec<String> = socket
+                    .rooms()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|room| room.starts_with("device:"))
+                    .map(|room| room.to_string())
+                    .collect();
+                socket.leave(rooms).ok();
+            }
+        }
+        acknowledge(&socket, ack, None);
+    });
+
+    let disconnected = CancellationToken::new();
+    let on_disconnect = disconnected.clone();
+    socket.on_disconnect(move |socket: SocketRef| async move {
+        on_disconnect.cancel();
+        socket.leave_all().ok();
         info!("Socket.IO client disconnected: {}", socket.id);
     });
+
+    match session.user().await {
+        Some(user) => {
+            info!("Socket.IO client connected: {} as {}", socket.id, user.username);
+            if let Some(user_id) = user.id {
+                socket.join(user_room(user_id)).ok();
+            }
+        }
+        None => {
+            let message = match session.token {
+                Some(_) => "Invalid or expired session",
+                None => "Not logged in: connect with the session token as `token` in the handshake auth",
+            };
+            disconnect_for_session(socket, "unauthorized", message);
+            return;
+        }
+    }
+    tokio::spawn(watch_session(socket, session, disconnected));
+}
+
+/// Check the session every `session_check_interval` until the client disconnects, and
+/// disconnect the client once the session expired or was logged out
+async fn watch_session(socket: SocketRef, session: SocketSession, disconnected: CancellationToken) {
+    let Some(token) = session.token else {
+        return;
+    };
+    let mut checks = tokio::time::interval(session.context.session_check_interval);
+    checks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    checks.tick().await;
+    loop {
+        tokio::select! {
+            _ = disconnected.cancelled() => return,
+            _ = checks.tick() => {}
+        }
+        match session.context.database.verify_session(&token).await {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                disconnect_for_session(socket, "session_expired", "Session expired or was logged out; log in again");
+                return;
+            }
+            Err(e) => warn!("Failed to check the session of Socket.IO client {}: {}", socket.id, e),
+        }
+    }
+}
+
+/// Tell the client why with an `auth_error`, then disconnect it
+fn disconnect_for_session(socket: SocketRef, reason: &str, message: &str) {
+    info!("Disconnecting Socket.IO client {}: {}", socket.id, message);
+    let error = AuthError { reason: reason.to_string(), message: message.to_string() };
+    if let Err(e) = socket.emit(AUTH_ERROR_EVENT, error) {
+        warn!("Failed to send {} to {}: {}", AUTH_ERROR_EVENT, socket.id, e);
+    }
+    socket.disconnect().ok();
+}
+
+/// `token` query parameter of the handshake request
+fn query_token(socket: &SocketRef) -> Option<String> {
+    let Query(mut params) = Query::<HashMap<String, String>>::try_from_uri(&socket.req_parts().uri).ok()?;
+    params.remove("token")
+}
+
+/// Payload of `history`: one tag's logged values over the last `minutes`, or from `start` to `end`
+#[derive(Debug, Clone, Deserialize)]
+pub struct HistoryRequest {
+    pub device_id: String,
+    pub tag_name: String,
+    /// Defaults to 15 when neither `start` nor `end` is given
+    pub minutes: Option<u32>,
+    pub start: Option<DateTime<Utc>>,
+    /// Defaults to now
+    pub end: Option<DateTime<Utc>>,
+    /// Defaults to 1000, at most 5000
+    pub max_points: Option<u32>,
+}
+
+/// Acknowledgement of `history`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HistoryAck {
+    /// `[timestamp in milliseconds, value]` pairs of logged values, oldest first;
+    /// failed reads are left out
+    pub points: Vec<(i64, f64)>,
+    /// Set when more values were logged than `max_points`: they were averaged into buckets
+    /// this many seconds wide, each point being the start of a bucket
+    pub interval_seconds: Option<u64>,
+    pub error: Option<String>,
+}
+
+const DEFAULT_HISTORY_MINUTES: u32 = 15;
+const DEFAULT_HISTORY_POINTS: u32 = 1_000;
+/// Most points, and rows read, of a single `history` request
+pub const MAX_HISTORY_POINTS: u32 = 5_000;
+/// `history` requests a socket may burst before being limited to `HISTORY_REQUESTS_PER_SECOND`
+const HISTORY_BURST: f64 = 5.0;
+const HISTORY_REQUESTS_PER_SECOND: f64 = 1.0;
+
+/// Token bucket of one socket's `history` requests; a request over the limit is refused
+/// rather than queued, so a misbehaving client can't pile up database reads
+struct HistoryLimiter {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl HistoryLimiter {
+    fn new() -> Self {
+        Self { tokens: HISTORY_BURST, refilled_at: Instant::now() }
+    }
+
+    /// Take a request's token, or say how long until one is available
+    fn try_acquire(&mut self) -> Result<(), Duration> {
+        let now = Instant::now();
+        let refill = now.duration_since(self.refilled_at).as_secs_f64() * HISTORY_REQUESTS_PER_SECOND;
+        self.tokens = (self.tokens + refill).min(HISTORY_BURST);
+        self.refilled_at = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64((1.0 - self.tokens) / HISTORY_REQUESTS_PER_SECOND))
+    }
+}
+
+async fn history(session: &SocketSession, limiter: &StdMutex<HistoryLimiter>, request: HistoryRequest) -> Result<HistoryAck, String> {
+    if let Err(wait) = limiter.lock().unwrap().try_acquire() {
+        return Err(format!("Too many history requests; try again in {} ms", wait.as_millis().max(1)));
+    }
+    // Every role may read history
+    if session.user().await.is_none() {
+        return Err("Not logged in".to_string());
+    }
+    let context = &session.context;
+
+    let max_points = request.max_points.unwrap_or(DEFAULT_HISTORY_POINTS);
+    if !(2..=MAX_HISTORY_POINTS).contains(&max_points) {
+        return Err(format!("max_points must be between 2 and {}", MAX_HISTORY_POINTS));
+    }
+    let end = request.end.unwrap_or_else(Utc::now);
+    let start = match (request.start, request.minutes) {
+        (Some(_), Some(_)) => return Err("Give either minutes or start, not both".to_string()),
+        (Some(start), None) => start,
+        (None, minutes) => end - chrono::Duration::minutes(minutes.unwrap_or(DEFAULT_HISTORY_MINUTES).into()),
+    };
+    if start >= end {
+        return Err("start must be before end".to_string());
+    }
+
+    // One row more than fits tells whether the range has to be downsampled
+    let samples = context
+        .database
+        .get_tag_samples(&request.device_id, &request.tag_name, start, end, max_points + 1)
+        .await
+        .map_err(|e| format!("Failed to read history: {}", e))?;
+    if samples.len() <= max_points as usize {
+        let points = samples.into_iter().map(|(timestamp, value)| (timestamp.timestamp_millis(), value)).collect();
+        return Ok(HistoryAck { points, ..Default::default() });
+    }
+
+    // Epoch-aligned buckets can straddle both ends of the range, hence one fewer than allowed
+    let span_seconds = (end - start).num_seconds().max(1) as u64;
+    let interval_seconds = span_seconds.div_ceil(u64::from(max_points) - 1);
+    let buckets = context
+        .database
+        .get_aggregated_log_entries(&request.device_id, &request.tag_name, start, end, interval_seconds, AggregateFunction::Avg, false)
+        .await
+        .map_err(|e| format!("Failed to read history: {}", e))?;
+    Ok(HistoryAck {
+        points: buckets.into_iter().map(|bucket| (bucket.bucket_start.timestamp_millis(), bucket.value)).collect(),
+        interval_seconds: Some(interval_seconds),
+        error: None,
+    })
+}
+
+fn acknowledge(socket: &SocketRef, ack: AckSender, error: Option<String>) {
+    let mut rooms: Vec<String> = socket
+        .rooms()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|room| room.starts_with("device:"))
+        .map(|room| room.to_string())
+        .collect();
+    rooms.sort();
+    if let Err(e) = ack.send(SubscriptionAck { rooms, error }) {
+        warn!("Failed to acknowledge subscription of {}: {}", socket.id, e);
+    }
+}
+
+/// One tag's value in a `tag_update` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagUpdate {
+    pub device_id: String,
+    pub tag: String,
+    pub value: f64,
+    pub quality: String,
+    pub ts: DateTime<Utc>,
+    /// The device stopped or lost its connection after the value was read
+    pub stale: bool,
+}
+
+impl TagUpdate {
+    fn new(device_id: &str, value: &TagValue) -> Self {
+        Self {
+            device_id: device_id.to_string(),
+            tag: value.tag_name.clone(),
+            value: value.value,
+            quality: value.quality.clone(),
+            ts: value.timestamp,
+            stale: value.stale,
+        }
+    }
+
+    fn supersedes(&self, previous: &TagUpdate) -> bool {
+        self.device_id == previous.device_id
+            && self.tag == previous.tag
+            && self.quality == previous.quality
+            && self.stale == previous.stale
+    }
+}
+
+/// Updates waiting for the next flush of one room
+struct PendingRoom {
+    /// Room whose clients already get these updates through the device's room
+    except: Option<String>,
+    updates: Vec<TagUpdate>,
+}
+
+/// Coalesces `tag_update` events per room into at most one event every interval. A value
+/// replaces an earlier one of the same tag still waiting, unless its quality or staleness
+/// differs, so transitions are always delivered. A zero interval emits every update at once.
+pub struct TagUpdateBatcher {
+    io: SocketIo,
+    interval: Duration,
+    pending: Arc<StdMutex<HashMap<String, PendingRoom>>>,
+}
+
+impl TagUpdateBatcher {
+    /// Flushes run on a background task for as long as the batcher lives
+    pub fn new(io: SocketIo, interval: Duration) -> Self {
+        let pending = Arc::new(StdMutex::new(HashMap::new()));
+        if !interval.is_zero() {
+            let (io, pending) = (io.clone(), Arc::downgrade(&pending));
+            tokio::spawn(async move {
+                let mut ticks = tokio::time::interval(interval);
+                ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+                loop {
+                    ticks.tick().await;
+                    let Some(pending) = pending.upgrade() else {
+                        return;
+                    };
+                    let rooms = std::mem::take(&mut *pending.lock().unwrap());
+                    for (room, pending_room) in rooms {
+                        emit_tag_updates(&io, room, pending_room);
+                    }
+                }
+            });
+        }
+        Self { io, interval, pending }
+    }
+
+    /// Queue a device's values for the clients subscribed to the device or to some of the tags.
+    /// Tag subscribers get their tags in an event of their own, and only if not also subscribed
+    /// to the whole device.
+    pub fn push(&self, update: &DeviceValues) {
+        if update.values.is_empty() {
+            return;
+        }
+        let device = device_room(&update.device_id);
+        let mut rooms = vec![(device.clone(), None, update.values.iter().map(|value| TagUpdate::new(&update.device_id, value)).collect())];
+        for value in &update.values {
+            rooms.push((tag_room(&update.device_id, &value.tag_name), Some(device.clone()), vec![TagUpdate::new(&update.device_id, value)]));
+        }
+
+        if self.interval.is_zero() {
+            for (room, except, updates) in rooms {
+                emit_tag_updates(&self.io, room, PendingRoom { except, updates });
+            }
+            return;
+        }
+
+        let mut pending = self.pending.lock().unwrap();
+        for (room, except, updates) in rooms {
+            let pending_room = pending.entry(room).or_insert_with(|| PendingRoom { except, updates: Vec::new() });
+            for update in updates {
+                let latest = pending_room.updates.iter_mut().rev().find(|previous| previous.device_id == update.device_id && previous.tag == update.tag);
+                match latest {
+                    Some(previous) if update.supersedes(previous) => *previous = update,
+                    _ => pending_room.updates.push(update),
+                }
+            }
+        }
+    }
+}
+
+fn emit_tag_updates(io: &SocketIo, room: String, pending_room: PendingRoom) {
+    let operators = match pending_room.except {
+        Some(except) => io.to(room.clone()).except(except),
+        None => io.to(room.clone()),
+    };
+    // A top-level array would be spread into one event argument per update
+    if let Err(e) = operators.emit("tag_update", [&pending_room.updates]) {
+        warn!("Failed to emit tag updates to room {}: {}", room, e);
+    }
 }
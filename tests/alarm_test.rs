@@ -0,0 +1,242 @@
+mod support;
+
+use ava_device_logger::alarms::{evaluate, AlarmEngine, AlarmState, AlarmTransition};
+use ava_device_logger::database::{AlarmCondition, AlarmRule, Database, DeviceInstance, LogEntry, NewAlarmRule};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde_json::{json, Value};
+use std::error::Error;
+use support::Logger;
+
+fn rule(condition: AlarmCondition, threshold: f64, hysteresis: f64, clear_hold_seconds: u32) -> AlarmRule {
+    AlarmRule {
+        id: 1,
+        rule: NewAlarmRule {
+            device_id: Some("inv-1".to_string()),
+            model_id: None,
+            tag_name: "Temperature".to_string(),
+            condition,
+            threshold,
+            hysteresis,
+            clear_hold_seconds,
+            severity: "warning".to_string(),
+            enabled: true,
+        },
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    }
+}
+
+fn at(seconds: i64) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2026, 6, 1, 12, 0, 0).unwrap() + Duration::seconds(seconds)
+}
+
+/// Transitions of a rule over samples taken ten seconds apart
+fn run(rule: &AlarmRule, values: &[f64]) -> Vec<Option<AlarmTransition>> {
+    let mut state = AlarmState::default();
+    values.iter().enumerate().map(|(i, value)| evaluate(rule, &mut state, *value, at(i as i64 * 10))).collect()
+}
+
+#[test]
+fn test_hysteresis_keeps_alarms_from_flapping() {
+    let over_75 = rule(AlarmCondition::Gt, 75.0, 2.0, 0);
+    assert_eq!(
+        run(&over_75, &[74.0, 76.0, 74.5, 75.5, 78.0, 77.0, 73.0, 74.0, 76.0]),
+        [
+            None,
+            Some(AlarmTransition::Raised { value: 76.0 }),
+            // Back under the threshold but inside the hysteresis band
+            None,
+            None,
+            Some(AlarmTransition::Peak { value: 78.0 }),
+            None,
+            Some(AlarmTransition::Cleared),
+            None,
+            Some(AlarmTransition::Raised { value: 76.0 }),
+        ]
+    );
+
+    let string_down = rule(AlarmCondition::Lt, 0.5, 0.5, 0);
+    assert_eq!(
+        run(&string_down, &[8.0, 0.0, 0.7, 1.2]),
+        [None, Some(AlarmTransition::Raised { value: 0.0 }), None, Some(AlarmTransition::Cleared)]
+    );
+}
+
+#[test]
+fn test_alarms_clear_after_the_hold_time() {
+    // Clears once values have been back in bounds for 20 seconds; a relapse restarts the wait
+    let over_75 = rule(AlarmCondition::Gt, 75.0, 0.0, 20);
+    assert_eq!(
+        run(&over_75, &[80.0, 70.0, 70.0, 76.0, 70.0, 70.0, 70.0]),
+        [
+            Some(AlarmTransition::Raised { value: 80.0 }),
+            None,
+            None,
+            None,
+            None,
+            None,
+            Some(AlarmTransition::Cleared),
+        ]
+    );
+}
+
+#[test]
+fn test_eq_and_delta_conditions() {
+    let stopped = rule(AlarmCondition::Eq, 0.0, 0.1, 0);
+    assert_eq!(
+        run(&stopped, &[5.0, 0.0, 0.05, 0.2]),
+        [None, Some(AlarmTransition::Raised { value: 0.0 }), None, Some(AlarmTransition::Cleared)]
+    );
+
+    // Delta rules compare the change since the previous sample
+    let jump = rule(AlarmCondition::Delta, 10.0, 0.0, 0);
+    assert_eq!(
+        run(&jump, &[100.0, 105.0, 125.0, 90.0, 92.0]),
+        [
+            None,
+            None,
+            Some(AlarmTransition::Raised { value: 20.0 }),
+            Some(AlarmTransition::Peak { value: 35.0 }),
+            Some(AlarmTransition::Cleared),
+        ]
+    );
+}
+
+fn device(id: &str, model_id: Option<String>) -> DeviceInstance {
+    DeviceInstance {
+        id: id.to_string(),
+        name: id.to_string(),
+        serial_no: None,
+        model_id,
+        enabled: false,
+        polling_interval_ms: 1000,
+        timeout_ms: 1000,
+        retry_count: 1,
+        protocol_config: json!({"type": "modbus_tcp", "host": "10.0.0.5", "port": 502, "slave_id": 1}).to_string(),
+        tb_device_id: None,
+        tb_group_id: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    }
+}
+
+fn entry(device_id: &str, tag_name: &str, value: f64, quality: &str, seconds: i64) -> LogEntry {
+    LogEntry {
+        id: None,
+        device_id: device_id.to_string(),
+        tag_name: tag_name.to_string(),
+        value,
+        quality: quality.to_string(),
+        timestamp: at(seconds),
+        unit: None,
+    }
+}
+
+#[tokio::test]
+async fn test_engine_records_alarms_and_resumes_after_a_restart() -> Result<(), Box<dyn Error>> {
+    let db_path = std::env::temp_dir().join(format!("alarms-{}.db", uuid::Uuid::new_v4()));
+    let db = Database::new(&db_path.to_string_lossy()).await?;
+    let model = db.create_device_model("SG125CX", Some("Sungrow"), "modbus_tcp", None).await?;
+    db.create_device(&device("inv-1", Some(model.id.clone()))).await?;
+    db.create_device(&device("inv-2", Some(model.id.clone()))).await?;
+    db.create_device(&device("meter-1", None)).await?;
+
+    let over_75 = NewAlarmRule { model_id: Some(model.id.clone()), device_id: None, ..rule(AlarmCondition::Gt, 75.0, 2.0, 0).rule };
+    let over_75 = db.create_alarm_rule(&over_75).await?;
+
+    let engine = AlarmEngine::new();
+    engine.load(&db).await?;
+    for (device_id, model_id) in [("inv-1", Some(model.id.clone())), ("inv-2", Some(model.id.clone())), ("meter-1", None)] {
+        engine.watch_device(device_id, model_id);
+    }
+
+    // The model's rule covers both inverters but not the meter; failed reads are skipped
+    let raised = engine
+        .process(&db, &[
+            entry("inv-1", "Temperature", 80.0, "Good", 0),
+            entry("inv-2", "Temperature", 90.0, "Bad", 0),
+            entry("meter-1", "Temperature", 90.0, "Good", 0),
+            entry("inv-1", "Power", 90.0, "Good", 0),
+        ])
+        .await?;
+    assert_eq!(raised.len(), 1);
+    assert_eq!((raised[0].device_id.as_str(), raised[0].rule_id, raised[0].raised_value), ("inv-1", over_75.id, 80.0));
+    assert!(engine.process(&db, &[entry("inv-1", "Temperature", 85.0, "Good", 10)]).await?.is_empty());
+
+    let active = db.get_alarm_events(true, None, 100).await?;
+    assert_eq!(active.len(), 1);
+    assert_eq!((active[0].peak_value, active[0].cleared_at), (85.0, None));
+
+    // A restarted engine carries on with the open alarm instead of raising it again
+    let restarted = AlarmEngine::new();
+    restarted.load(&db).await?;
+    restarted.watch_device("inv-1", Some(model.id.clone()));
+    assert!(restarted.process(&db, &[entry("inv-1", "Temperature", 82.0, "Good", 20)]).await?.is_empty());
+    let cleared = restarted.process(&db, &[entry("inv-1", "Temperature", 70.0, "Good", 30)]).await?;
+    assert_eq!(cleared.len(), 1);
+    assert_eq!((cleared[0].id, cleared[0].peak_value, cleared[0].cleared_at), (active[0].id, 85.0, Some(at(30))));
+    assert!(db.get_alarm_events(true, None, 100).await?.is_empty());
+    assert_eq!(db.get_alarm_events(false, Some("inv-1"), 100).await?.len(), 1);
+
+    // Disabling a rule clears the alarms it has open
+    restarted.process(&db, &[entry("inv-1", "Temperature", 99.0, "Good", 40)]).await?;
+    let disabled = NewAlarmRule { enabled: false, ..over_75.rule.clone() };
+    db.update_alarm_rule(over_75.id, &disabled).await?;
+    assert!(db.get_alarm_events(true, None, 100).await?.is_empty());
+    restarted.load(&db).await?;
+    assert!(restarted.process(&db, &[entry("inv-1", "Temperature", 99.0, "Good", 50)]).await?.is_empty());
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_alarm_rules_are_validated_and_listed() -> Result<(), Box<dyn Error>> {
+    let work_dir = support::work_dir("alarm-api")?;
+    let db = Database::new(&work_dir.join("data.db").to_string_lossy()).await?;
+    db.create_device(&device("inv-1", None)).await?;
+
+    let logger = Logger::start_in(work_dir, "").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let body: Value = client
+        .post(format!("{}/api/alarm-rules", base_url))
+        .bearer_auth(token)
+        .json(&json!({"device_id": "no-such-device", "tag_name": " ", "condition": "gt", "threshold": 75.0, "hysteresis": -1.0, "severity": "critical"}))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["field_errors"], json!([
+        {"field": "device_id", "message": "no device 'no-such-device'"},
+        {"field": "tag_name", "message": "must not be empty"},
+        {"field": "hysteresis", "message": "must be zero or positive"},
+        {"field": "severity", "message": "must be one of info, warning, error"},
+    ]));
+
+    let body: Value = client
+        .post(format!("{}/api/alarm-rules", base_url))
+        .bearer_auth(token)
+        .json(&json!({"device_id": "inv-1", "tag_name": "Temperature", "condition": "gt", "threshold": 75.0, "hysteresis": 2.0, "clear_hold_seconds": 60, "severity": "warning"}))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!((&body["data"]["enabled"], &body["data"]["clear_hold_seconds"]), (&json!(true), &json!(60)));
+    let rule_id = body["data"]["id"].as_i64().unwrap();
+
+    let body: Value = client.get(format!("{}/api/alarm-rules", base_url)).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(body["data"].as_array().unwrap().len(), 1);
+    let body: Value = client.get(format!("{}/api/alarms?active=true", base_url)).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(body["data"], json!([]));
+
+    let response = client.delete(format!("{}/api/alarm-rules/{}", base_url, rule_id)).bearer_auth(token).send().await?;
+    assert_eq!(response.status(), 200);
+    let response = client.delete(format!("{}/api/alarm-rules/{}", base_url, rule_id)).bearer_auth(token).send().await?;
+    assert_eq!(response.status(), 404);
+
+    drop(db);
+    Ok(())
+}
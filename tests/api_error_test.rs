@@ -0,0 +1,98 @@
+mod support;
+
+use serde_json::{json, Value};
+use std::error::Error;
+use support::Logger;
+
+/// Status and body of a response, checking the body is the error envelope
+async fn error_envelope(request: reqwest::RequestBuilder) -> Result<(u16, Value), Box<dyn Error>> {
+    let response = request.send().await?;
+    let status = response.status().as_u16();
+    let content_type = response.headers().get("content-type").and_then(|value| value.to_str().ok()).unwrap_or_default().to_string();
+    let body: Value = response.json().await?;
+    assert!(content_type.starts_with("application/json"), "{} {}", status, content_type);
+    assert_eq!(body["success"], false, "{}", body);
+    assert!(body["error"].as_str().is_some_and(|error| !error.is_empty()), "{}", body);
+    assert!(body["code"].is_string(), "{}", body);
+    assert!(body.get("details").is_some(), "{}", body);
+    assert_eq!(body["data"], Value::Null, "{}", body);
+    Ok((status, body))
+}
+
+#[tokio::test]
+async fn test_failures_return_the_error_envelope_with_their_status() -> Result<(), Box<dyn Error>> {
+    let logger = Logger::start("").await?;
+    let (client, base_url, admin) = (&logger.client, &logger.base_url, &logger.token);
+    let installer = logger.login("installer", "installer123").await?;
+
+    // 401: no session, or a wrong password
+    let (status, body) = error_envelope(client.get(format!("{}/api/devices", base_url))).await?;
+    assert_eq!((status, &body["code"]), (401, &json!("unauthorized")));
+    let (status, body) = error_envelope(client.post(format!("{}/api/login", base_url)).json(&json!({"username": "admin", "password": "wrong"}))).await?;
+    assert_eq!((status, &body["code"]), (401, &json!("unauthorized")));
+    assert_eq!(body["error"], "Invalid username or password");
+
+    // 403: an installer on an admin endpoint
+    let (status, body) = error_envelope(client.get(format!("{}/api/users", base_url)).bearer_auth(&installer)).await?;
+    assert_eq!((status, &body["code"]), (403, &json!("forbidden")));
+
+    // 404: a missing entity and an unknown route
+    let (status, body) = error_envelope(client.get(format!("{}/api/devices-enhanced/no-such-device", base_url)).bearer_auth(admin)).await?;
+    assert_eq!((status, &body["code"]), (404, &json!("not_found")));
+    assert_eq!(body["error"], "Device no-such-device not found");
+    assert_eq!(body["details"], Value::Null);
+    let (status, body) = error_envelope(client.get(format!("{}/api/no-such-route", base_url)).bearer_auth(admin)).await?;
+    assert_eq!((status, &body["code"]), (404, &json!("not_found")));
+    let (status, body) = error_envelope(client.post(format!("{}/api/no-such-route", base_url)).bearer_auth(admin)).await?;
+    assert_eq!((status, &body["code"]), (404, &json!("not_found")));
+
+    // 405: a known route with the wrong method
+    let (status, body) = error_envelope(client.delete(format!("{}/api/users", base_url)).bearer_auth(admin)).await?;
+    assert_eq!((status, &body["code"]), (405, &json!("method_not_allowed")));
+
+    // 400: malformed JSON, JSON of the wrong shape, a bad query parameter and invalid fields
+    let (status, body) = error_envelope(
+        client.post(format!("{}/api/users", base_url)).bearer_auth(admin).header("content-type", "application/json").body("{\"username\": "),
+    ).await?;
+    assert_eq!((status, &body["code"]), (400, &json!("bad_request")));
+    let (status, body) = error_envelope(client.post(format!("{}/api/users", base_url)).bearer_auth(admin).json(&json!({"username": 42}))).await?;
+    assert_eq!((status, &body["code"]), (400, &json!("validation_failed")));
+    let (status, body) = error_envelope(client.get(format!("{}/api/reports/daily?date=yesterday", base_url)).bearer_auth(admin)).await?;
+    assert_eq!((status, &body["code"]), (400, &json!("bad_request")));
+    let (status, body) = error_envelope(
+        client.post(format!("{}/api/users", base_url)).bearer_auth(admin).json(&json!({"username": "tech", "password": "short", "role": "installer"})),
+    ).await?;
+    assert_eq!((status, &body["code"]), (400, &json!("validation_failed")));
+    let expected = json!([{"field": "password", "message": "must be at least 8 characters"}]);
+    assert_eq!((&body["field_errors"], &body["details"]), (&expected, &expected));
+
+    // 409: a device id that is already taken
+    let device = json!({
+        "id": "inv-1", "name": "Inverter 1", "enabled": false, "polling_interval_ms": 2000, "timeout_ms": 1000, "retry_count": 1,
+        "protocol_config": {"type": "modbus_tcp", "host": "10.0.0.10", "port": 502, "slave_id": 1}, "tags": [],
+    });
+    let body: Value = client.post(format!("{}/api/devices-enhanced", base_url)).bearer_auth(admin).json(&device).send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let (status, body) = error_envelope(client.post(format!("{}/api/devices-enhanced", base_url)).bearer_auth(admin).json(&device)).await?;
+    assert_eq!((status, &body["code"]), (409, &json!("conflict")));
+    assert_eq!(body["error"], "Device inv-1 already exists");
+
+    // 503: ThingsBoard isn't configured
+    let (status, body) = error_envelope(client.get(format!("{}/api/thingsboard/entity-groups", base_url)).bearer_auth(admin)).await?;
+    assert_eq!((status, &body["code"]), (503, &json!("unavailable")));
+
+    // 500: the database failing underneath the server
+    let database = rusqlite::Connection::open(logger.work_dir.join("data.db"))?;
+    database.execute_batch("DROP TABLE alarm_rules")?;
+    let (status, body) = error_envelope(client.get(format!("{}/api/alarm-rules", base_url)).bearer_auth(admin)).await?;
+    assert_eq!((status, &body["code"]), (500, &json!("internal")));
+    assert!(body["error"].as_str().unwrap().starts_with("Failed to get alarm rules"), "{}", body);
+
+    // Successes keep the envelope without the error fields
+    let body: Value = client.get(format!("{}/api/devices-enhanced/inv-1", base_url)).bearer_auth(admin).send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!(body["error"], Value::Null, "{}", body);
+    assert!(body.get("code").is_none() && body.get("details").is_none(), "{}", body);
+
+    Ok(())
+}
@@ -0,0 +1,77 @@
+mod support;
+
+use serde_json::{json, Value};
+use std::error::Error;
+use support::Logger;
+
+#[tokio::test]
+async fn test_device_history_outlives_the_device() -> Result<(), Box<dyn Error>> {
+
+    let logger = Logger::start("").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let tag = |name: &str, address: u16, multiplier: f64| json!({
+        "name": name, "address": address, "size": 1, "data_type": "uint16",
+        "scaling_multiplier": multiplier, "scaling_offset": 0.0, "read_only": true, "enabled": true
+    });
+    let device = |name: &str, tags: Value| json!({
+        "id": "inv-1", "name": name, "enabled": false, "polling_interval_ms": 2000, "timeout_ms": 1000, "retry_count": 1,
+        "protocol_config": {"type": "modbus_tcp", "host": "10.0.0.10", "port": 502, "slave_id": 1}, "tags": tags,
+    });
+
+    let body: Value = client.post(format!("{}/api/devices-enhanced", base_url)).bearer_auth(token)
+        .json(&device("Inverter 1", json!([tag("Power", 100, 1.0), tag("Energy", 101, 1.0)])))
+        .send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let body: Value = client.put(format!("{}/api/devices-enhanced/inv-1", base_url)).bearer_auth(token)
+        .json(&device("Inverter A", json!([tag("Power", 100, 0.1), tag("Voltage", 102, 1.0)])))
+        .send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let body: Value = client.delete(format!("{}/api/devices-enhanced/inv-1", base_url)).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+
+    let audit = |query: &str| {
+        let request = client.get(format!("{}/api/audit?{}", base_url, query)).bearer_auth(token);
+        async move { request.send().await?.json::<Value>().await }
+    };
+
+    // Newest first, and still there once the device is gone
+    let page = audit("entity_id=inv-1").await?;
+    let entries = page["data"]["entries"].as_array().expect("entries");
+    let actions: Vec<&str> = entries.iter().map(|entry| entry["action"].as_str().unwrap()).collect();
+    assert_eq!(actions, ["device.delete", "tag.delete", "tag.create", "tag.update", "device.update", "device.create"]);
+    assert_eq!(page["data"]["total"], 6);
+    assert!(entries.iter().all(|entry| entry["username"] == "admin"), "{}", page);
+
+    let (delete, tag_delete, tag_create, tag_update, update, create) =
+        (&entries[0], &entries[1], &entries[2], &entries[3], &entries[4], &entries[5]);
+    assert_eq!(create["after"]["name"], "Inverter 1");
+    assert_eq!(create["before"], Value::Null);
+    assert_eq!(update["before"], json!({"name": "Inverter 1"}));
+    assert_eq!(update["after"], json!({"name": "Inverter A"}));
+    assert_eq!(tag_update["before"], json!({"name": "Power", "scaling_multiplier": 1.0}));
+    assert_eq!(tag_update["after"], json!({"name": "Power", "scaling_multiplier": 0.1}));
+    assert_eq!(tag_create["after"]["name"], "Voltage");
+    assert_eq!(tag_delete["before"]["name"], "Energy");
+    assert_eq!(tag_delete["entity_type"], "tag");
+    assert_eq!(delete["before"]["name"], "Inverter A");
+    assert_eq!(delete["after"], Value::Null);
+
+    let page = audit("entity_id=inv-1&limit=2&offset=1").await?;
+    let actions: Vec<&str> = page["data"]["entries"].as_array().unwrap().iter().map(|entry| entry["action"].as_str().unwrap()).collect();
+    assert_eq!(actions, ["tag.delete", "tag.create"]);
+    assert_eq!(page["data"]["total"], 6);
+    assert_eq!(page["data"]["limit"], 2);
+    assert_eq!(page["data"]["offset"], 1);
+
+    let page = audit("entity_type=device&entity_id=inv-1").await?;
+    assert_eq!(page["data"]["total"], 3, "{}", page);
+
+    let page = audit("entity_type=session").await?;
+    let login = &page["data"]["entries"][0];
+    assert_eq!((&login["action"], &login["entity_id"], &login["username"]), (&json!("login"), &json!("admin"), &json!("admin")));
+
+    // Reading the audit log needs a session
+    assert_eq!(client.get(format!("{}/api/audit", base_url)).send().await?.status(), 401);
+    Ok(())
+}
@@ -0,0 +1,117 @@
+mod support;
+
+use ava_device_logger::database::{Database, DeviceInstance, DeviceTag, TagWritePolicy};
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::time::{Duration, Instant};
+use support::Logger;
+
+/// Seeds two enabled devices, a disabled one and an enabled one whose protocol config can't be
+/// parsed, then boots the server
+async fn boot(auto_start: bool) -> Result<Logger, Box<dyn Error>> {
+    let work_dir = support::work_dir("auto-start")?;
+    // Nothing listens here, so pollers keep retrying without ever connecting
+    let device_port = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+
+    let db = Database::new(&work_dir.join("data.db").to_string_lossy()).await?;
+    let protocol = json!({"type": "modbus_tcp", "host": "127.0.0.1", "port": device_port, "slave_id": 1}).to_string();
+    for (id, enabled, protocol_config) in [
+        ("inv-1", true, protocol.clone()),
+        ("inv-2", true, protocol.clone()),
+        ("inv-3", false, protocol.clone()),
+        ("inv-4", true, "{}".to_string()),
+    ] {
+        db.create_device(&DeviceInstance {
+            id: id.to_string(),
+            name: id.to_string(),
+            serial_no: None,
+            model_id: None,
+            enabled,
+            polling_interval_ms: 60_000,
+            timeout_ms: 200,
+            retry_count: 1,
+            protocol_config,
+            tb_device_id: None,
+            tb_group_id: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            strict_types: false,
+        }).await?;
+        db.create_device_tags(id, &[DeviceTag {
+            id: None,
+            device_id: id.to_string(),
+            name: "Active Power".to_string(),
+            address: 40001,
+            size: 1,
+            data_type: "uint16".to_string(),
+            description: None,
+            scaling_multiplier: 1.0,
+            scaling_offset: 0.0,
+            unit: None,
+            read_only: true,
+            enabled: true,
+            schedule_group_id: None,
+            agg_to_field: None,
+            write_policy: TagWritePolicy::AdminOnly,
+            byte_order: None,
+            deadband_absolute: None,
+            deadband_percent: None,
+            register_type: None,
+        }]).await?;
+    }
+    drop(db);
+
+    let server = format!("[server]\nauto_start = {auto_start}\nauto_start_stagger_ms = 300\n");
+    Logger::start_with_config(work_dir, |port| support::config(port, "").replace("[server]\n", &server)).await
+}
+
+async fn running_devices(base_url: &str, token: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let devices: Value = reqwest::Client::new()
+        .get(format!("{}/api/devices-enhanced", base_url))
+        .bearer_auth(token)
+        .send()
+        .await?
+        .json()
+        .await?;
+    Ok(devices["data"]
+        .as_array()
+        .expect("devices")
+        .iter()
+        .filter(|device| device["is_running"] == true)
+        .map(|device| device["device"]["id"].as_str().unwrap().to_string())
+        .collect())
+}
+
+#[tokio::test]
+async fn test_enabled_devices_start_on_boot_and_failures_are_recorded() -> Result<(), Box<dyn Error>> {
+    let logger = boot(true).await?;
+
+    let started = Instant::now();
+    while running_devices(&logger.base_url, &logger.token).await?.len() < 2 && started.elapsed() < Duration::from_secs(10) {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(running_devices(&logger.base_url, &logger.token).await?, vec!["inv-1", "inv-2"]);
+
+    // inv-4 is the last to be tried, after the stagger
+    let mut error = Value::Null;
+    while error.is_null() && started.elapsed() < Duration::from_secs(10) {
+        let status = logger.get("/api/status").await?;
+        if let Some(device) = status["data"]["devices"].as_array().unwrap().iter().find(|device| device["device_id"] == "inv-4") {
+            assert_eq!(device["status"], "Error");
+            error = device["error_message"].clone();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(error.as_str().expect("inv-4 error").contains("Failed to start"), "{}", error);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_auto_start_can_be_turned_off() -> Result<(), Box<dyn Error>> {
+    let logger = boot(false).await?;
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert!(running_devices(&logger.base_url, &logger.token).await?.is_empty());
+    Ok(())
+}
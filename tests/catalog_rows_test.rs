@@ -0,0 +1,240 @@
+use ava_device_logger::catalog::{
+    divider, includes_tag, parent_name, tag_matches_mppt, tag_matches_string, CatalogDevice, CatalogRow, CatalogRowBuilder, CatalogSource,
+    ScheduleLookup, UNKNOWN_PARENT,
+};
+use ava_device_logger::config::RegisterType;
+use ava_device_logger::database::{DeviceInstance, DeviceTag, TagWritePolicy};
+use ava_device_logger::tb_rust_client::DeviceData;
+use chrono::Utc;
+use serde_json::json;
+
+fn tb_device(name: &str, device_type: &str) -> DeviceData {
+    serde_json::from_value(json!({
+        "id": {"id": format!("tb-{}", name), "entityType": "DEVICE"},
+        "name": name,
+        "type": device_type,
+        "label": "label",
+    }))
+    .expect("device data")
+}
+
+fn instance(protocol_config: serde_json::Value) -> DeviceInstance {
+    DeviceInstance {
+        id: "inv-a".to_string(),
+        name: "Inverter A".to_string(),
+        serial_no: Some("SN-1".to_string()),
+        model_id: None,
+        enabled: true,
+        polling_interval_ms: 1000,
+        timeout_ms: 1000,
+        retry_count: 1,
+        protocol_config: protocol_config.to_string(),
+        tb_device_id: None,
+        tb_group_id: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    }
+}
+
+fn tag(name: &str, scaling_multiplier: f64) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: "inv-a".to_string(),
+        name: name.to_string(),
+        address: 100,
+        size: 2,
+        data_type: "float32".to_string(),
+        description: None,
+        scaling_multiplier,
+        scaling_offset: 0.0,
+        unit: Some("kW".to_string()),
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: Some("total".to_string()),
+        write_policy: TagWritePolicy::Disabled,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+fn catalog_device<'a>(device: &'a DeviceData, device_index: u32, mppt_index: u32, input_index: u32) -> CatalogDevice<'a> {
+    CatalogDevice {
+        device,
+        token: "token-1",
+        parent: parent_name(device, "ACCV-P002 Plant", "ACCV-P002", device_index, mppt_index),
+        device_index,
+        mppt_index,
+        input_index,
+    }
+}
+
+fn source(instance: &DeviceInstance) -> CatalogSource<'_> {
+    CatalogSource { instance, brand: "Sungrow".to_string(), model: "SG125CX".to_string() }
+}
+
+fn tcp() -> DeviceInstance {
+    instance(json!({"type": "modbus_tcp", "host": "10.0.0.5", "port": 502, "slave_id": 3}))
+}
+
+#[test]
+fn test_inverter_tag_row() {
+    let device = tb_device("ACCV-P002-I01", "Inverter");
+    let local = tcp();
+    let tag = DeviceTag { register_type: Some(RegisterType::Input), ..tag("P", 0.1) };
+    let row = CatalogRowBuilder::new("ACCV-P002 Plant").tag_row(&catalog_device(&device, 1, 0, 0), &source(&local), &tag, ScheduleLookup::Found(5000));
+
+    assert_eq!(
+        row,
+        CatalogRow {
+            serial_number: "SN-1".to_string(),
+            device_name: "ACCV-P002-I01".to_string(),
+            device_brand: "Sungrow".to_string(),
+            device_model: "SG125CX".to_string(),
+            customer: "ACCV".to_string(),
+            ava_type: "Inverter".to_string(),
+            token: "token-1".to_string(),
+            parent: "ACCV-P002 Plant".to_string(),
+            plant: "ACCV-P002 Plant".to_string(),
+            inv: Some(1),
+            label: "label".to_string(),
+            device_id: "tb-ACCV-P002-I01".to_string(),
+            host: "10.0.0.5".to_string(),
+            port: Some(502),
+            forwarding_modbus_id: Some(3),
+            protocol: "modbus_tcp".to_string(),
+            data_label: "P".to_string(),
+            address: Some(100),
+            size: Some(2),
+            data_type: "float32".to_string(),
+            divider: "10".to_string(),
+            unit: "kW".to_string(),
+            register_type: "input".to_string(),
+            frequency: "5000".to_string(),
+            agg_to_field: "total".to_string(),
+            ..CatalogRow::default()
+        }
+    );
+}
+
+#[test]
+fn test_header_follows_row_fields() {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.serialize(CatalogRow::default()).unwrap();
+    let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+    let header = csv.lines().next().unwrap();
+    assert!(header.starts_with("IOA,Index,Serial Number,Device Name,"));
+    assert!(header.ends_with(",Size,Data Type,Divider,Unit,Register Type,Frequency,Agg To Field"));
+    assert_eq!(header.split(',').count(), csv.lines().nth(1).unwrap().split(',').count());
+}
+
+#[test]
+fn test_mppt_and_string_rows_are_numbered_and_parented() {
+    let builder = CatalogRowBuilder::new("ACCV-P002 Plant");
+    let local = tcp();
+
+    let mppt = tb_device("ACCV-P002-I01-M02", "Mppt");
+    let row = builder.tag_row(&catalog_device(&mppt, 1, 2, 0), &source(&local), &tag("Udc", 1.0), ScheduleLookup::NoGroup);
+    assert_eq!((row.inv, row.mppt, row.input), (Some(1), Some(2), None));
+    assert_eq!(row.parent, "ACCV-P002-I01");
+    assert_eq!(row.serial_number, "SN-1");
+    assert_eq!(row.frequency, "None");
+    // Tags that don't set a Modbus table leave Register Type blank rather than showing the unit
+    assert_eq!((row.unit.as_str(), row.register_type.as_str()), ("kW", ""));
+
+    let string = tb_device("ACCV-P002-I01-M02-PV03", "String");
+    let row = builder.tag_row(&catalog_device(&string, 1, 2, 3), &source(&local), &tag("Idc", 1.0), ScheduleLookup::NoGroup);
+    assert_eq!((row.inv, row.mppt, row.input), (Some(1), Some(2), Some(3)));
+    assert_eq!(row.parent, "ACCV-P002-I01-M02");
+}
+
+#[test]
+fn test_meter_rows_are_not_numbered() {
+    let device = tb_device("ACCV-P002-MT01", "Meter");
+    let local = instance(json!({"type": "iec104", "host": "10.0.0.9", "port": 2404, "common_address": 1}));
+    let row = CatalogRowBuilder::new("ACCV-P002 Plant").device_row(&catalog_device(&device, 1, 0, 0), &source(&local));
+
+    assert_eq!((row.inv, row.mppt, row.input), (None, None, None));
+    assert_eq!(row.parent, "ACCV-P002 Plant");
+    assert_eq!((row.address, row.data_label.as_str()), (None, ""));
+    assert!(includes_tag("Meter", "Udc"));
+}
+
+#[test]
+fn test_placeholder_and_error_rows() {
+    let builder = CatalogRowBuilder::new("ACCV-P002 Plant");
+
+    let string = tb_device("ACCV-P002-I02-M01-PV01", "String");
+    let row = builder.placeholder_row(&catalog_device(&string, 2, 1, 1), "Parent inverter not found");
+    assert_eq!((row.inv, row.mppt, row.input), (Some(2), Some(1), Some(1)));
+    assert_eq!((row.device_brand.as_str(), row.device_model.as_str()), ("Unknown", "Unknown"));
+    assert_eq!((row.serial_number.as_str(), row.host.as_str(), row.port), ("", "", None));
+    assert_eq!(row.data_label, "Parent inverter not found");
+
+    let inverter = tb_device("ACCV-P002-I01", "Inverter");
+    let local = tcp();
+    let row = builder.error_row(&catalog_device(&inverter, 1, 0, 0), &source(&local));
+    assert_eq!(row.data_label, "ERROR: Failed to get tags");
+    assert_eq!((row.host.as_str(), row.address), ("10.0.0.5", None));
+}
+
+#[test]
+fn test_parent_names() {
+    let parent = |name: &str, device_type: &str, inv_index: u32, mppt_index: u32| {
+        parent_name(&tb_device(name, device_type), "GR-P001-Toyota Boshoku HN", "GR-P001", inv_index, mppt_index)
+    };
+
+    assert_eq!(parent("GR-P001-I01", "Inverter", 1, 0).as_deref(), Some("GR-P001-Toyota Boshoku HN"));
+    assert_eq!(parent("GR-P001-MT01", "Meter", 1, 0).as_deref(), Some("GR-P001-Toyota Boshoku HN"));
+    assert_eq!(parent("GR-P001-PM01", "PowerMeter", 1, 0).as_deref(), Some("GR-P001-Toyota Boshoku HN"));
+
+    assert_eq!(parent("GR-P001-I03-M02", "Mppt", 3, 2).as_deref(), Some("GR-P001-I03"));
+    assert_eq!(parent("GR-P001-I03-M02-PV04", "String", 3, 2).as_deref(), Some("GR-P001-I03-M02"));
+    // Extra dashes stay part of the parent
+    assert_eq!(parent("GR-P001-Roof-A-I03-M02", "Mppt", 3, 2).as_deref(), Some("GR-P001-Roof-A-I03"));
+
+    // Names without the expected separators fall back to the group prefix, never another site's
+    assert_eq!(parent("MPPT2", "Mppt", 3, 2).as_deref(), Some("GR-P001-I03"));
+    assert_eq!(parent("GR-P001-I03-MPPT2", "Mppt", 3, 2).as_deref(), Some("GR-P001-I03"));
+    assert_eq!(parent("String 4", "String", 3, 2).as_deref(), Some("GR-P001-I03-M02"));
+
+    // And are unknown when the indexes can't be read either
+    assert_eq!(parent("MPPT2", "Mppt", 0, 0), None);
+    assert_eq!(parent("String 4", "String", 3, 0), None);
+
+    let device = tb_device("MPPT2", "Mppt");
+    let unknown = CatalogDevice { parent: None, ..catalog_device(&device, 0, 0, 0) };
+    let row = CatalogRowBuilder::new("GR-P001-Toyota Boshoku HN").placeholder_row(&unknown, "Parent inverter not found");
+    assert_eq!(row.parent, UNKNOWN_PARENT);
+}
+
+#[test]
+fn test_tag_filters() {
+    assert!(includes_tag("Inverter", "P"));
+    assert!(!includes_tag("Inverter", "Udc"));
+    assert!(includes_tag("Mppt", "Idc"));
+    assert!(!includes_tag("String", "P"));
+
+    assert!(tag_matches_mppt(Some("MPPT - MPPT 1 (SG125CX-P2)"), 1));
+    assert!(!tag_matches_mppt(Some("MPPT - MPPT 2"), 1));
+    assert!(tag_matches_string(Some("String - MPPT 1 - Input 2"), 1, 2));
+    assert!(!tag_matches_string(None, 1, 2));
+}
+
+#[test]
+fn test_divider_and_frequency_conversions() {
+    assert_eq!(divider(0.1), "10");
+    assert_eq!(divider(0.001), "1000");
+    assert_eq!(divider(1.0), "1");
+    assert_eq!(divider(0.0), "1");
+    assert_eq!(divider(0.4), "2.5");
+
+    assert_eq!(ScheduleLookup::NoGroup.frequency(), "None");
+    assert_eq!(ScheduleLookup::Found(1000).frequency(), "1000");
+    assert_eq!(ScheduleLookup::Missing.frequency(), "Unknown");
+    assert_eq!(ScheduleLookup::Failed.frequency(), "Error");
+}
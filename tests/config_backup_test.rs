@@ -0,0 +1,239 @@
+mod support;
+
+use ava_device_logger::database::{
+    ConfigBundle, Database, DeviceInstance, DeviceTag, RestoreCounts, RestoreMode, TagTemplate, TagWritePolicy,
+};
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::error::Error;
+use support::Logger;
+
+fn device(id: &str, model_id: Option<&str>) -> DeviceInstance {
+    DeviceInstance {
+        id: id.to_string(),
+        name: format!("Device {}", id),
+        serial_no: None,
+        model_id: model_id.map(|id| id.to_string()),
+        enabled: true,
+        polling_interval_ms: 1000,
+        timeout_ms: 5000,
+        retry_count: 3,
+        protocol_config: r#"{"type":"modbus_tcp","host":"10.0.0.1","port":502,"slave_id":1}"#.to_string(),
+        tb_device_id: None,
+        tb_group_id: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    }
+}
+
+fn tag(device_id: &str, name: &str, address: u16) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: device_id.to_string(),
+        name: name.to_string(),
+        address,
+        size: 2,
+        data_type: "float32".to_string(),
+        description: None,
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: Some("kW".to_string()),
+        read_only: true,
+        enabled: true,
+        schedule_group_id: Some("low_freq".to_string()),
+        agg_to_field: None,
+        write_policy: TagWritePolicy::Disabled,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+async fn open_database() -> Result<(Database, std::path::PathBuf), Box<dyn Error>> {
+    let db_path = std::env::temp_dir().join(format!("config-backup-{}.db", uuid::Uuid::new_v4()));
+    let db = Database::new(&db_path.to_string_lossy()).await?;
+    Ok((db, db_path))
+}
+
+/// A gateway with a model, its template, and one device built from it
+async fn configured_database() -> Result<(Database, std::path::PathBuf), Box<dyn Error>> {
+    let (db, db_path) = open_database().await?;
+    let model = db.create_device_model("Inverter", Some("Acme"), "modbus_tcp", None).await?;
+    db.create_tag_template(&TagTemplate {
+        id: None,
+        model_id: model.id.clone(),
+        name: "Power".to_string(),
+        address: 100,
+        size: 2,
+        data_type: "float32".to_string(),
+        description: None,
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: Some("kW".to_string()),
+        read_only: true,
+    })
+    .await?;
+    db.create_device(&device("inv-1", Some(&model.id))).await?;
+    db.create_device_tags("inv-1", &[tag("inv-1", "Power", 100), tag("inv-1", "Energy", 102)]).await?;
+    db.update_plant_configuration("North Plant", None).await?;
+    Ok((db, db_path))
+}
+
+fn counts(created: usize, updated: usize, skipped: usize) -> RestoreCounts {
+    RestoreCounts { created, updated, skipped }
+}
+
+#[tokio::test]
+async fn test_backup_restores_onto_an_empty_gateway() -> Result<(), Box<dyn Error>> {
+    let (source, source_path) = configured_database().await?;
+    let bundle = source.export_config_bundle().await?;
+    assert_eq!(bundle.schema_version, 1);
+    let model = bundle.device_models.iter().find(|m| m.name == "Inverter").expect("model is exported");
+    assert_eq!(bundle.tag_templates.iter().filter(|t| t.model_id == model.id).count(), 1);
+    assert_eq!((bundle.devices.len(), bundle.device_tags.len()), (1, 2));
+
+    let (target, target_path) = open_database().await?;
+    let groups = target.get_schedule_groups().await?.len();
+    let report = target.restore_config_bundle(&bundle, RestoreMode::Replace).await?.expect("bundle is valid");
+    assert_eq!(report.device_models, counts(bundle.device_models.len(), 0, 0));
+    assert_eq!(report.devices, counts(1, 0, 0));
+    assert_eq!(report.device_tags, counts(2, 0, 0));
+    assert_eq!(report.schedule_groups, counts(groups, 0, 0));
+
+    let restored = target.export_config_bundle().await?;
+    assert_eq!(serde_json::to_value(&restored.devices)?, serde_json::to_value(&bundle.devices)?);
+    assert_eq!(restored.plant_configuration.map(|plant| plant.plant_name), Some("North Plant".to_string()));
+    let tags: Vec<(String, u16, Option<String>)> = restored.device_tags.into_iter().map(|t| (t.name, t.address, t.schedule_group_id)).collect();
+    assert_eq!(tags, [
+        ("Power".to_string(), 100, Some("low_freq".to_string())),
+        ("Energy".to_string(), 102, Some("low_freq".to_string())),
+    ]);
+
+    std::fs::remove_file(&source_path).ok();
+    std::fs::remove_file(&target_path).ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_merge_counts_created_updated_and_skipped() -> Result<(), Box<dyn Error>> {
+    let (db, db_path) = configured_database().await?;
+    let mut bundle = db.export_config_bundle().await?;
+
+    let report = db.restore_config_bundle(&bundle, RestoreMode::Merge).await?.expect("bundle is valid");
+    assert_eq!((report.devices.clone(), report.device_tags.clone()), (counts(0, 0, 1), counts(0, 0, 2)));
+
+    bundle.device_tags[0].scaling_multiplier = 0.1;
+    bundle.devices.push(device("inv-2", None));
+    bundle.device_tags.push(tag("inv-2", "Power", 100));
+    let report = db.restore_config_bundle(&bundle, RestoreMode::Merge).await?.expect("bundle is valid");
+    assert_eq!(report.devices, counts(1, 0, 1));
+    assert_eq!(report.device_tags, counts(1, 1, 1));
+    assert_eq!(report.tag_templates, counts(0, 0, bundle.tag_templates.len()));
+
+    let tags = db.get_device_tags("inv-1").await?;
+    assert_eq!(tags.iter().map(|t| t.scaling_multiplier).collect::<Vec<_>>(), [0.1, 1.0]);
+    assert_eq!(db.get_device_tags("inv-2").await?.len(), 1);
+
+    // Merging keeps what the bundle leaves out; replacing removes it
+    let report = db.restore_config_bundle(&ConfigBundle { schema_version: 1, ..Default::default() }, RestoreMode::Merge).await?.expect("empty bundle is valid");
+    assert_eq!(report.devices, counts(0, 0, 0));
+    assert_eq!(db.get_devices().await?.len(), 2);
+    bundle.devices.retain(|d| d.id == "inv-2");
+    bundle.device_tags.retain(|t| t.device_id == "inv-2");
+    db.restore_config_bundle(&bundle, RestoreMode::Replace).await?.expect("bundle is valid");
+    assert_eq!(db.get_devices().await?.iter().map(|d| d.id.as_str()).collect::<Vec<_>>(), ["inv-2"]);
+    assert!(db.get_device_tags("inv-1").await?.is_empty());
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_broken_references_are_reported_before_writing() -> Result<(), Box<dyn Error>> {
+    let (db, db_path) = configured_database().await?;
+    let mut bundle = db.export_config_bundle().await?;
+    let model_id = bundle.devices[0].model_id.clone().expect("device uses a model");
+    bundle.device_models.clear();
+    bundle.tag_templates.clear();
+    bundle.devices.push(device("inv-2", Some("gone")));
+    bundle.device_tags.push(tag("inv-3", "Power", 100));
+    bundle.device_tags.push(DeviceTag { schedule_group_id: Some("hourly".to_string()), ..tag("inv-2", "Power", 100) });
+    bundle.device_tags.push(tag("inv-2", "Power", 104));
+
+    // The model is still on the gateway, so merging only trips over the new references
+    let errors = db.restore_config_bundle(&bundle, RestoreMode::Merge).await?.expect_err("references are broken");
+    let errors: Vec<(String, String)> = errors.into_iter().map(|e| (e.field, e.message)).collect();
+    assert_eq!(errors, [
+        ("device_tags[4].name".to_string(), "device 'inv-2' has more than one tag 'Power'".to_string()),
+        ("devices[1].model_id".to_string(), "references missing device model 'gone'".to_string()),
+        ("device_tags[2].device_id".to_string(), "references missing device 'inv-3'".to_string()),
+        ("device_tags[3].schedule_group_id".to_string(), "references missing schedule group 'hourly'".to_string()),
+    ]);
+
+    bundle.devices.truncate(1);
+    bundle.device_tags.truncate(2);
+    let errors = db.restore_config_bundle(&bundle, RestoreMode::Replace).await?.expect_err("model is not in the bundle");
+    assert_eq!(errors[0].field, "devices[0].model_id");
+    assert_eq!(errors[0].message, format!("references missing device model '{}'", model_id));
+
+    assert_eq!(db.get_devices().await?.len(), 1);
+    assert_eq!(db.get_tag_templates(&model_id).await?.len(), 1);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[test]
+fn test_bundles_need_a_supported_schema_version() {
+    assert_eq!(ConfigBundle::from_json(json!({"devices": []})).unwrap_err(), "schema_version is missing");
+    assert_eq!(
+        ConfigBundle::from_json(json!({"schema_version": 2})).unwrap_err(),
+        "schema_version 2 is not supported; this gateway reads versions 1 to 1"
+    );
+    let bundle = serde_json::to_value(ConfigBundle { schema_version: 1, ..Default::default() }).unwrap();
+    assert!(ConfigBundle::from_json(bundle).is_ok());
+}
+
+#[tokio::test]
+async fn test_backup_endpoint_output_can_be_restored() -> Result<(), Box<dyn Error>> {
+
+    let logger = Logger::start("").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let device = json!({
+        "id": "meter-1", "name": "Meter 1", "enabled": false, "polling_interval_ms": 1000, "timeout_ms": 1000, "retry_count": 1,
+        "protocol_config": {"type": "modbus_tcp", "host": "127.0.0.1", "port": 502, "slave_id": 1},
+        "tags": [{"name": "Power", "address": 100, "size": 2, "data_type": "float32", "scaling_multiplier": 1.0,
+                  "scaling_offset": 0.0, "read_only": true, "enabled": true}],
+    });
+    let body: Value = client.post(format!("{}/api/devices-enhanced", base_url)).bearer_auth(token).json(&device).send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+
+    let response = client.get(format!("{}/api/backup", base_url)).bearer_auth(token).send().await?;
+    let disposition = response.headers()["content-disposition"].to_str()?.to_string();
+    assert!(disposition.starts_with("attachment; filename=\"gateway-backup-"), "{}", disposition);
+    let backup: Value = response.json().await?;
+    assert_eq!(backup["schema_version"], 1);
+    assert_eq!(backup["device_tags"][0]["name"], "Power");
+
+    let delete = client.delete(format!("{}/api/devices-enhanced/meter-1", base_url)).bearer_auth(token).send().await?;
+    assert!(delete.status().is_success());
+
+    let body: Value = client.post(format!("{}/api/restore?mode=merge", base_url)).bearer_auth(token).json(&backup).send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!(body["data"]["mode"], "merge");
+    assert_eq!(body["data"]["devices"], json!({"created": 1, "updated": 0, "skipped": 0}));
+    assert_eq!(body["data"]["device_tags"], json!({"created": 1, "updated": 0, "skipped": 0}));
+
+    let body: Value = client.get(format!("{}/api/devices-enhanced/meter-1", base_url)).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(body["data"]["tags"][0]["name"], "Power");
+
+    let mut broken = backup.clone();
+    broken["device_tags"][0]["device_id"] = json!("meter-9");
+    let body: Value = client.post(format!("{}/api/restore?mode=replace", base_url)).bearer_auth(token).json(&broken).send().await?.json().await?;
+    assert_eq!(body["success"], false);
+    assert_eq!(body["field_errors"], json!([{"field": "device_tags[0].device_id", "message": "references missing device 'meter-9'"}]));
+    Ok(())
+}
@@ -0,0 +1,117 @@
+mod support;
+
+use serde_json::Value;
+use std::error::Error;
+use support::{login, logger_command, wait_until_up, Server};
+
+fn fixture_config(port: u16) -> String {
+    format!(
+        r#"
+[server]
+port = {port}
+host = "127.0.0.1"
+
+[database]
+path = "data.db"
+max_log_entries = 1000
+cleanup_interval_hours = 24
+
+[[devices]]
+id = "meter-1"
+name = "Legacy Meter"
+enabled = false
+polling_interval_ms = 1000
+timeout_ms = 5000
+retry_count = 3
+
+[devices.protocol]
+type = "modbus_tcp"
+host = "192.168.1.100"
+port = 502
+slave_id = 1
+
+[[devices.tags]]
+name = "active_power"
+address = 30001
+size = 2
+data_type = "float32"
+description = "Active power"
+
+[devices.tags.scaling]
+multiplier = 0.1
+offset = 0.0
+unit = "kW"
+
+[[devices]]
+id = "rtu-1"
+name = "Legacy RTU"
+enabled = false
+polling_interval_ms = 2000
+timeout_ms = 5000
+retry_count = 1
+tags = []
+
+[devices.protocol]
+type = "iec104"
+host = "192.168.1.200"
+port = 2404
+common_address = 1
+
+[logging]
+level = "info"
+max_file_size_mb = 10
+max_files = 5
+"#
+    )
+}
+
+async fn get_data(client: &reqwest::Client, url: String, token: &str) -> Result<Value, Box<dyn Error>> {
+    let body: Value = client.get(url).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    Ok(body["data"].clone())
+}
+
+#[tokio::test]
+async fn test_config_devices_are_migrated_and_listed_identically() -> Result<(), Box<dyn Error>> {
+    // The migration runs as the binary starts, before the services do
+    let work_dir = support::work_dir("config-migration")?;
+    let port = support::free_port()?;
+    std::fs::write(work_dir.join("config.toml"), fixture_config(port))?;
+    let _server = Server(logger_command(&work_dir).spawn()?);
+    let base_url = &format!("http://127.0.0.1:{}", port);
+    wait_until_up(base_url).await?;
+    let token = &login(base_url, "admin", "admin123").await?;
+    let client = &reqwest::Client::new();
+
+    let legacy = get_data(client, format!("{}/api/devices", base_url), token).await?;
+    let enhanced = get_data(client, format!("{}/api/devices-enhanced", base_url), token).await?;
+    assert_eq!(legacy, enhanced);
+
+    let devices = legacy.as_array().expect("device list");
+    assert_eq!(devices.len(), 2);
+
+    let meter = devices.iter().find(|d| d["device"]["id"] == "meter-1").expect("meter-1 migrated");
+    assert_eq!(meter["device"]["name"], "Legacy Meter");
+    assert_eq!(meter["tags"][0]["name"], "active_power");
+    assert_eq!(meter["tags"][0]["data_type"], "float32");
+    assert_eq!(meter["tags"][0]["unit"], "kW");
+
+    let rtu = devices.iter().find(|d| d["device"]["id"] == "rtu-1").expect("rtu-1 migrated");
+    let protocol: Value = serde_json::from_str(rtu["device"]["protocol_config"].as_str().unwrap())?;
+    assert_eq!(protocol["type"], "iec104");
+
+    let single = get_data(client, format!("{}/api/devices/rtu-1", base_url), token).await?;
+    assert_eq!(&single, rtu);
+
+    // The devices section was emptied and the original kept as a backup
+    let config = std::fs::read_to_string(work_dir.join("config.toml"))?;
+    assert!(!config.contains("Legacy Meter"));
+    let backups: Vec<_> = std::fs::read_dir(&work_dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().ends_with(".bak"))
+        .collect();
+    assert_eq!(backups.len(), 1);
+    assert!(std::fs::read_to_string(backups[0].path())?.contains("Legacy Meter"));
+    std::fs::remove_dir_all(&work_dir).ok();
+    Ok(())
+}
@@ -0,0 +1,162 @@
+mod support;
+
+use ava_device_logger::config::{AppConfig, PvNaming, ThingsBoardConfig};
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::Stdio;
+use support::{logger_command, wait_until_up, Server};
+
+/// A config whose only problems are the ones `devices` brings
+fn fixture_config(port: u16, devices: &str) -> String {
+    format!(
+        r#"
+{devices}
+
+[server]
+port = {port}
+host = "127.0.0.1"
+
+[database]
+path = "data.db"
+max_log_entries = 1000
+cleanup_interval_hours = 24
+
+[logging]
+level = "info"
+max_file_size_mb = 10
+max_files = 5
+"#
+    )
+}
+
+/// Two devices sharing an ID, the second never polled and with a port out of range
+const BROKEN_DEVICES: &str = r#"
+[[devices]]
+id = "meter-1"
+name = "Meter 1"
+enabled = false
+polling_interval_ms = 1000
+timeout_ms = 1000
+retry_count = 3
+tags = []
+
+[devices.protocol]
+type = "modbus_tcp"
+host = "127.0.0.1"
+port = 502
+slave_id = 1
+
+[[devices]]
+id = "meter-1"
+name = "Meter 2"
+enabled = false
+polling_interval_ms = 0
+timeout_ms = 1000
+retry_count = 3
+tags = []
+
+[devices.protocol]
+type = "modbus_tcp"
+host = "127.0.0.1"
+port = 0
+slave_id = 1
+"#;
+
+fn work_dir(config: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let work_dir = support::work_dir("config-validation")?;
+    std::fs::write(work_dir.join("config.toml"), config)?;
+    Ok(work_dir)
+}
+
+fn parse(config: &str) -> Result<AppConfig, Box<dyn Error>> {
+    Ok(toml::from_str(config)?)
+}
+
+#[test]
+fn default_and_fixture_configs_are_valid() -> Result<(), Box<dyn Error>> {
+    assert_eq!(AppConfig::default().validate(), Vec::<String>::new());
+    assert_eq!(parse(&fixture_config(8080, "devices = []"))?.validate(), Vec::<String>::new());
+    Ok(())
+}
+
+#[test]
+fn every_problem_is_reported() -> Result<(), Box<dyn Error>> {
+    let mut config = parse(&fixture_config(0, BROKEN_DEVICES))?;
+    config.database.path = "/nonexistent-config-validation/data.db".to_string();
+    config.retention.batch_size = 0;
+    config.reports.webhook_url = Some("https://example.com/report".to_string());
+    config.thingsboard = Some(ThingsBoardConfig {
+        base_url: "thingsboard.local:8080".to_string(),
+        username: "tenant@example.com".to_string(),
+        password: "s3cret".to_string(),
+        tenant_label: None,
+        retry_max_attempts: 3,
+        retry_base_delay_ms: 500,
+        requests_per_second: 0.0,
+        sync_concurrency: 0,
+        pv_naming: PvNaming::Global,
+    });
+
+    let errors = config.validate();
+    let expected = [
+        "server.port must be greater than 0",
+        "retention.batch_size must be greater than 0",
+        "reports.webhook_url is no longer used",
+        "devices[1].id 'meter-1' is already used by another device",
+        "devices[1].polling_interval_ms must be from 1 to 4294967295",
+        "devices[1].protocol.port",
+        "database.path '/nonexistent-config-validation/data.db': directory /nonexistent-config-validation does not exist",
+        "thingsboard.base_url 'thingsboard.local:8080'",
+        "thingsboard.sync_concurrency must be greater than 0",
+    ];
+    for message in expected {
+        assert!(errors.iter().any(|error| error.starts_with(message)), "no '{}' in {:?}", message, errors);
+    }
+    assert_eq!(errors.len(), expected.len(), "{:?}", errors);
+    Ok(())
+}
+
+#[test]
+fn read_only_database_file_is_reported() -> Result<(), Box<dyn Error>> {
+    let work_dir = work_dir("")?;
+    let database = work_dir.join("data.db");
+    std::fs::write(&database, "")?;
+    let mut permissions = std::fs::metadata(&database)?.permissions();
+    permissions.set_readonly(true);
+    std::fs::set_permissions(&database, permissions)?;
+
+    let mut config = parse(&fixture_config(8080, "devices = []"))?;
+    config.database.path = database.to_string_lossy().to_string();
+    let errors = config.validate();
+    // root may write read-only files anyway, in which case there is nothing to report
+    let writable = std::fs::OpenOptions::new().write(true).open(&database).is_ok();
+    assert_eq!(errors.iter().any(|error| error.contains("file is not writable")), !writable, "{:?}", errors);
+    Ok(())
+}
+
+#[test]
+fn invalid_config_stops_startup_with_a_numbered_list() -> Result<(), Box<dyn Error>> {
+    let port = support::free_port()?;
+    let work_dir = work_dir(&fixture_config(port, BROKEN_DEVICES))?;
+
+    let output = logger_command(&work_dir).stderr(Stdio::piped()).output()?;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("config.toml has 3 errors:"), "{}", stderr);
+    assert!(stderr.contains("  1. devices[1].id 'meter-1' is already used by another device"), "{}", stderr);
+    assert!(stderr.contains("  2. devices[1].polling_interval_ms must be from 1 to 4294967295"), "{}", stderr);
+    assert!(stderr.contains("  3. devices[1].protocol.port"), "{}", stderr);
+    assert!(stderr.contains("--ignore-config-errors"), "{}", stderr);
+    assert!(!work_dir.join("data.db").exists(), "the database was opened despite the errors");
+    Ok(())
+}
+
+#[tokio::test]
+async fn ignore_config_errors_starts_anyway() -> Result<(), Box<dyn Error>> {
+    let port = support::free_port()?;
+    let work_dir = work_dir(&fixture_config(port, BROKEN_DEVICES))?;
+
+    let _server = Server(logger_command(&work_dir).arg("--ignore-config-errors").spawn()?);
+    wait_until_up(&format!("http://127.0.0.1:{}", port)).await?;
+    Ok(())
+}
@@ -0,0 +1,227 @@
+mod support;
+
+use serde_json::{json, Value};
+use std::error::Error;
+use support::Logger;
+
+const BOUNDARY: &str = "csv-import-test";
+const HEADER: &str = "Device Brand,Device Model,AVA Type,MPPT,INPUT,Data Label,Address,Size,Modbus Type,Divider,Register Type\n";
+
+/// multipart/form-data body for text fields and one CSV file
+fn multipart(fields: &[(&str, &str)], csv: &str) -> String {
+    let mut body = String::new();
+    for (name, value) in fields {
+        body += &format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n", BOUNDARY, name, value);
+    }
+    body += &format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"csv_file\"; filename=\"tags.csv\"\r\nContent-Type: text/csv\r\n\r\n{}\r\n--{}--\r\n",
+        BOUNDARY, csv, BOUNDARY
+    );
+    body
+}
+
+/// The report of an upload: `data` when it was accepted, `details` when it was rejected with
+/// an error status
+async fn upload_report(response: reqwest::Response) -> Result<Value, reqwest::Error> {
+    let status = response.status();
+    let body = response.json::<Value>().await?;
+    assert_eq!(status.is_success(), body["success"] == true, "{} {}", status, body);
+    Ok(if status.is_success() { body["data"].clone() } else { body["details"].clone() })
+}
+
+#[tokio::test]
+async fn test_register_map_rows_are_checked_one_by_one() -> Result<(), Box<dyn Error>> {
+    let logger = Logger::start("").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let upload = |flags: &[(&str, &str)], csv: &str| {
+        let mut fields = vec![("device_model_name", "STP"), ("manufacturer", "SMA")];
+        fields.extend_from_slice(flags);
+        let request = client
+            .post(format!("{}/api/modbus-tcp-tag-registers/upload-csv", base_url))
+            .bearer_auth(token)
+            .header("Content-Type", format!("multipart/form-data; boundary={}", BOUNDARY))
+            .body(multipart(&fields, csv));
+        async move { upload_report(request.send().await?).await }
+    };
+    let stored = || {
+        let request = client.get(format!("{}/api/modbus-tcp-tag-registers?device_brand=SMA&device_model=STP", base_url)).bearer_auth(token);
+        async move { Ok::<usize, Box<dyn Error>>(request.send().await?.json::<Value>().await?["data"].as_array().unwrap().len()) }
+    };
+
+    let csv = format!(
+        "{}SMA,STP,Inverter,,,Total Yield,30513,4,U64,1,holding\n\
+         SMA,STP,Inverter,,,Pac,30775,1,F128,1,holding\n\
+         SMA,STP,Inverter,,,Udc,abc,1,U16,10,holding\n\
+         SMA,STP,Inverter,,,Total Yield,30600,1,U16,1,holding\n\
+         SMA,STP,Inverter,,,Frequency,30803,1,U16,100,holding\n",
+        HEADER
+    );
+    let problems = json!([
+        "Row 3: Invalid Modbus Type 'F128'. Valid types: [\"U16\", \"I16\", \"U32\", \"I32\", \"U64\", \"I64\", \"FLOAT\", \"F32\", \"DOUBLE\", \"F64\"] (column 'Modbus Type')",
+        "Row 4: Failed to parse 'abc': invalid digit found in string (column 'Address')",
+        "Row 5: Tag name 'Total Yield' is used by an earlier tag",
+    ]);
+
+    // Every bad row is reported, and by default nothing is imported
+    for flags in [&[("dry_run", "true")][..], &[]] {
+        let body = upload(flags, &csv).await?;
+        assert_eq!(body["success"], false, "{}", body);
+        assert_eq!(body["validation_errors"], problems, "{}", body);
+        assert_eq!(body["records_processed"], 0);
+        assert!(body["message"].as_str().unwrap().starts_with("Found 3 problems in the CSV"), "{}", body);
+    }
+    assert_eq!(stored().await?, 0);
+
+    // A dry run with allow_partial says what would go in without saving it
+    let body = upload(&[("dry_run", "on"), ("allow_partial", "true")], &csv).await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!(body["validation_errors"], problems);
+    assert!(body["summary"].as_str().unwrap().starts_with("Would insert: 2 | Skipped: 2 | Duplicate conflicts: 1 |"), "{}", body);
+    assert_eq!(stored().await?, 0);
+
+    let body = upload(&[("allow_partial", "true")], &csv).await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!(body["records_processed"], 2);
+    assert_eq!(body["validation_errors"], problems);
+    assert!(body["summary"].as_str().unwrap().starts_with("Inserted: 2 | Skipped: 2 | Duplicate conflicts: 1 |"), "{}", body);
+    assert_eq!(stored().await?, 2);
+
+    // Long lists of problems are capped
+    let bad_rows: String = (0..150).map(|i| format!("SMA,STP,Inverter,,,Tag {},{},1,F128,1,holding\n", i, i)).collect();
+    let body = upload(&[], &format!("{}{}", HEADER, bad_rows)).await?;
+    assert_eq!(body["success"], false, "{}", body);
+    assert_eq!(body["validation_errors"].as_array().unwrap().len(), 100);
+    assert!(body["message"].as_str().unwrap().starts_with("Found 150 problems in the CSV, nothing was imported: first 100 listed"), "{}", body);
+
+    let body = upload(&[("dry_run", "maybe")], &csv).await?;
+    assert_eq!(body["validation_errors"], json!(["dry_run: must be true or false"]), "{}", body);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_reuploads_append_upsert_or_replace() -> Result<(), Box<dyn Error>> {
+    let logger = Logger::start("").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let upload = |mode: &str, rows: &str| {
+        let fields = [("device_model_name", "STP"), ("manufacturer", "SMA"), ("mode", mode)];
+        let request = client
+            .post(format!("{}/api/modbus-tcp-tag-registers/upload-csv", base_url))
+            .bearer_auth(token)
+            .header("Content-Type", format!("multipart/form-data; boundary={}", BOUNDARY))
+            .body(multipart(&fields, &format!("{}{}", HEADER, rows)));
+        async move { upload_report(request.send().await?).await }
+    };
+    let stored = || {
+        let request = client.get(format!("{}/api/modbus-tcp-tag-registers?device_brand=SMA&device_model=STP", base_url)).bearer_auth(token);
+        async move {
+            let body = request.send().await?.json::<Value>().await?;
+            let mut labels: Vec<(String, f64)> = body["data"].as_array().unwrap().iter()
+                .map(|row| (row["data_label"].as_str().unwrap().to_string(), row["divider"].as_f64().unwrap()))
+                .collect();
+            labels.sort_by(|a, b| a.0.cmp(&b.0));
+            Ok::<_, Box<dyn Error>>(labels)
+        }
+    };
+    let label = |name: &str, divider: f64| (name.to_string(), divider);
+
+    let body = upload("append", "SMA,STP,Inverter,,,Total Yield,30513,4,U64,1,holding\nSMA,STP,Inverter,,,Pac,30775,2,U32,1,holding\n").await?;
+    assert_eq!(body["import"], json!({"inserted": 2, "updated": 0, "deleted": 0}), "{}", body);
+
+    // Rows are matched on address, MPPT and INPUT, even for inverter rows without either
+    let body = upload("upsert", "SMA,STP,Inverter,,,Pac,30775,2,U32,10,holding\nSMA,STP,Inverter,,,Frequency,30803,1,U16,100,holding\n").await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!(body["import"], json!({"inserted": 1, "updated": 1, "deleted": 0}), "{}", body);
+    assert_eq!(body["records_processed"], 2);
+    assert!(body["summary"].as_str().unwrap().starts_with("Inserted: 1 | Skipped: 0 | Duplicate conflicts: 0 | Updated: 1 | Deleted: 0 |"), "{}", body);
+    assert_eq!(stored().await?, [label("Frequency", 100.0), label("Pac", 10.0), label("Total Yield", 1.0)]);
+
+    let body = upload("replace", "SMA,STP,Inverter,,,Udc,30771,1,U16,10,holding\n").await?;
+    assert_eq!(body["import"], json!({"inserted": 1, "updated": 0, "deleted": 3}), "{}", body);
+    assert_eq!(stored().await?, [label("Udc", 10.0)]);
+
+    // A failure part way through leaves the previous map as it was
+    let conn = rusqlite::Connection::open(logger.work_dir.join("data.db"))?;
+    conn.execute_batch(
+        "CREATE TRIGGER fail_import BEFORE INSERT ON modbus_tcp_tag_registers WHEN NEW.data_label = 'Broken'
+         BEGIN SELECT RAISE(ABORT, 'disk on fire'); END;",
+    )?;
+    let body = upload("replace", "SMA,STP,Inverter,,,Pac,30775,2,U32,1,holding\nSMA,STP,Inverter,,,Broken,30900,1,U16,1,holding\n").await?;
+    assert_eq!(body["success"], false, "{}", body);
+    assert!(body["message"].as_str().unwrap().contains("disk on fire"), "{}", body);
+    assert_eq!(body["import"], json!({"inserted": 0, "updated": 0, "deleted": 0}));
+    assert_eq!(stored().await?, [label("Udc", 10.0)]);
+
+    let body = upload("overwrite", "SMA,STP,Inverter,,,Pac,30775,2,U32,1,holding\n").await?;
+    assert_eq!(body["validation_errors"], json!(["mode: must be append, upsert or replace"]), "{}", body);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_register_maps_round_trip_through_csv_export() -> Result<(), Box<dyn Error>> {
+    let logger = Logger::start("").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let upload = |mode: &str, csv: String| {
+        let fields = [("device_model_name", "STP"), ("manufacturer", "SMA"), ("mode", mode)];
+        let request = client
+            .post(format!("{}/api/modbus-tcp-tag-registers/upload-csv", base_url))
+            .bearer_auth(token)
+            .header("Content-Type", format!("multipart/form-data; boundary={}", BOUNDARY))
+            .body(multipart(&fields, &csv));
+        async move { upload_report(request.send().await?).await }
+    };
+    let export = || {
+        let request = client.get(format!("{}/api/modbus-tcp-tag-registers/export-csv?device_brand=SMA&device_model=STP", base_url)).bearer_auth(token);
+        async move {
+            let response = request.send().await?;
+            assert_eq!(response.headers()["content-type"], "text/csv; charset=utf-8");
+            Ok::<String, Box<dyn Error>>(response.text().await?)
+        }
+    };
+
+    // Nothing stored yet still gives a file that can be filled in
+    assert_eq!(export().await?, "Device Brand,Device Model,AVA Type,MPPT,INPUT,Data Label,Address,Size,Modbus Type,Divider,Register Type,Deadband,Deadband %,Agg To Field\n");
+
+    let csv = "Device Brand,Device Model,AVA Type,MPPT,INPUT,Data Label,Address,Size,Modbus Type,Divider,Register Type,Deadband,Deadband %,Agg To Field\n\
+               SMA,STP,Inverter,,,Pac,30775,2,U32,1.0,holding,,,power\n\
+               SMA,STP,String,2,1,Udc,30771,1,U16,10.0,holding,0.5,2.0,\n\
+               SMA,STP,Inverter,,,Total Yield,30513,4,U64,1.0,holding,,, energy \n";
+    let body = upload("append", csv.to_string()).await?;
+    assert_eq!(body["import"], json!({"inserted": 3, "updated": 0, "deleted": 0}), "{}", body);
+
+    let body: Value = client.get(format!("{}/api/modbus-tcp-tag-registers?device_brand=SMA&device_model=STP", base_url)).bearer_auth(token).send().await?.json().await?;
+    let agg_fields: Vec<(&str, &Value)> = body["data"].as_array().unwrap().iter().map(|row| (row["data_label"].as_str().unwrap(), &row["agg_to_field"])).collect();
+    assert_eq!(agg_fields, [("Total Yield", &json!("energy")), ("Pac", &json!("power")), ("Udc", &Value::Null)]);
+
+    // Every column comes back out, and uploading the export again changes nothing
+    let exported = export().await?;
+    assert_eq!(
+        exported,
+        "Device Brand,Device Model,AVA Type,MPPT,INPUT,Data Label,Address,Size,Modbus Type,Divider,Register Type,Deadband,Deadband %,Agg To Field\n\
+         SMA,STP,Inverter,,,Total Yield,30513,4,U64,1.0,holding,,,energy\n\
+         SMA,STP,Inverter,,,Pac,30775,2,U32,1.0,holding,,,power\n\
+         SMA,STP,String,2,1,Udc,30771,1,U16,10.0,holding,0.5,2.0,\n"
+    );
+    let body = upload("replace", exported.clone()).await?;
+    assert_eq!(body["import"], json!({"inserted": 3, "updated": 0, "deleted": 3}), "{}", body);
+    assert_eq!(export().await?, exported);
+
+    // Tags generated from the map keep the field
+    let body = logger
+        .post("/api/devices-enhanced", &json!({
+            "id": "inverter-1", "name": "Inverter 1", "enabled": false,
+            "polling_interval_ms": 1000, "timeout_ms": 1000, "retry_count": 1,
+            "protocol_config": {"type": "modbus_tcp", "host": "127.0.0.1", "port": 502, "slave_id": 1},
+            "tags": [],
+        }))
+        .await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let body = logger.post("/api/devices-enhanced/inverter-1/tags/from-register-map", &json!({"device_brand": "SMA", "device_model": "STP"})).await?;
+    assert_eq!(body["data"]["created"], 3, "{}", body);
+    let body = logger.get("/api/devices/inverter-1/tags").await?;
+    let pac = body["data"].as_array().unwrap().iter().find(|tag| tag["name"] == "Pac").ok_or("no Pac tag")?;
+    assert_eq!(pac["agg_to_field"], "power");
+    Ok(())
+}
@@ -0,0 +1,195 @@
+mod support;
+
+use ava_device_logger::database::{AlarmCondition, DailyReport, Database, LogEntry, NewAlarmRule};
+use ava_device_logger::webhooks::{signature, SIGNATURE_HEADER};
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::post;
+use axum::Router;
+use chrono::{Duration, TimeZone, Utc};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use support::Logger;
+
+fn temp_db_path(name: &str) -> String {
+    std::env::temp_dir()
+        .join(format!("{}-{}.db", name, uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string()
+}
+
+#[tokio::test]
+async fn test_day_stats_and_energy_deltas() -> Result<(), Box<dyn Error>> {
+    let db_path = temp_db_path("daily-report");
+    let db = Database::new(&db_path).await?;
+
+    let start = Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap();
+    let samples = [(0, 100.0, "Good"), (60, 110.0, "Good"), (660, 0.0, "Bad"), (720, 125.0, "Good")];
+    for (offset, value, quality) in samples {
+        db.insert_log_entries(&[LogEntry {
+            id: None,
+            device_id: "inv-1".to_string(),
+            tag_name: "Daily Yield".to_string(),
+            value,
+            quality: quality.to_string(),
+            timestamp: start + Duration::seconds(offset),
+            unit: Some("kWh".to_string()),
+        }])
+        .await?;
+    }
+
+    let stats = db.get_device_day_stats(start, start + Duration::days(1)).await?;
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].total_samples, 4);
+    assert_eq!(stats[0].good_samples, 3);
+    assert!((stats[0].max_gap_seconds - 600.0).abs() < 1.0);
+
+    let deltas = db.get_energy_tag_deltas(start, start + Duration::days(1)).await?;
+    assert_eq!(deltas.len(), 1);
+    assert_eq!(deltas[0].first_value, 100.0);
+    assert_eq!(deltas[0].last_value, 125.0);
+
+    // Nothing logged the following day
+    assert!(db.get_device_day_stats(start + Duration::days(1), start + Duration::days(2)).await?.is_empty());
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_regenerating_report_is_idempotent() -> Result<(), Box<dyn Error>> {
+    let db_path = temp_db_path("daily-report-upsert");
+    let db = Database::new(&db_path).await?;
+
+    let mut report = DailyReport {
+        report_date: "2024-05-01".to_string(),
+        plant_name: "Test Plant".to_string(),
+        generated_at: Utc::now(),
+        content: "{\"version\":1}".to_string(),
+        summary_text: "first".to_string(),
+        delivered_at: None,
+    };
+    db.upsert_daily_report(&report).await?;
+    assert!(db.mark_daily_report_delivered("2024-05-01").await?);
+
+    report.content = "{\"version\":2}".to_string();
+    report.summary_text = "second".to_string();
+    db.upsert_daily_report(&report).await?;
+
+    let stored = db.get_daily_report("2024-05-01").await?.expect("report should exist");
+    assert_eq!(stored.summary_text, "second");
+    assert_eq!(stored.content, "{\"version\":2}");
+    // Regeneration must not forget that the report was already delivered
+    assert!(stored.delivered_at.is_some());
+    assert!(db.get_daily_report("2024-05-02").await?.is_none());
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+/// Requests received, as (signature header, body)
+type Received = Arc<Mutex<Vec<(String, String)>>>;
+
+async fn start_receiver() -> Result<(String, Received), Box<dyn Error>> {
+    async fn receive(State(received): State<Received>, headers: HeaderMap, body: String) -> StatusCode {
+        let signature = headers.get(SIGNATURE_HEADER).and_then(|value| value.to_str().ok()).unwrap_or_default().to_string();
+        received.lock().unwrap().push((signature, body));
+        StatusCode::OK
+    }
+
+    let received = Received::default();
+    let app = Router::new().route("/report", post(receive)).with_state(received.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}/report", listener.local_addr()?);
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
+    Ok((url, received))
+}
+
+/// Add inverter `inv-1` and raise an alarm on its `<script>` tag during 2024-05-01, so
+/// rendering has something to escape
+async fn raise_alarm(logger: &Logger) -> Result<(), Box<dyn Error>> {
+    let body = logger
+        .post("/api/devices-enhanced", &json!({
+            "id": "inv-1", "name": "Inverter 1", "enabled": false,
+            "polling_interval_ms": 1000, "timeout_ms": 1000, "retry_count": 1,
+            "protocol_config": {"type": "modbus_tcp", "host": "127.0.0.1", "port": 502, "slave_id": 1},
+            "tags": [],
+        }))
+        .await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let database = &logger.state().database;
+    let rule = database
+        .create_alarm_rule(&NewAlarmRule {
+            device_id: Some("inv-1".to_string()),
+            model_id: None,
+            tag_name: "<script>".to_string(),
+            condition: AlarmCondition::Gt,
+            threshold: 80.0,
+            hysteresis: 0.0,
+            clear_hold_seconds: 0,
+            severity: "error".to_string(),
+            enabled: true,
+        })
+        .await?;
+    database.raise_alarm(&rule, "inv-1", 85.0, Utc.with_ymd_and_hms(2024, 5, 1, 10, 0, 0).unwrap()).await?;
+    database.clear_alarm(rule.id, "inv-1", Utc.with_ymd_and_hms(2024, 5, 1, 10, 30, 0).unwrap()).await?;
+    // Raised the day after, so outside the report
+    database.raise_alarm(&rule, "inv-1", 90.0, Utc.with_ymd_and_hms(2024, 5, 2, 10, 0, 0).unwrap()).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_report_lists_alarms_and_is_delivered_through_the_webhooks() -> Result<(), Box<dyn Error>> {
+    let (url, received) = start_receiver().await?;
+    let logger = Logger::start(&format!(
+        "[[webhooks]]\nid = \"reports\"\nurl = \"{}\"\nevents = [\"daily_report\"]\nsecret = \"report-secret\"\n",
+        url
+    ))
+    .await?;
+    raise_alarm(&logger).await?;
+
+    let body = logger.post("/api/reports/daily/generate?date=2024-05-01&deliver=true", &json!({})).await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let alarms = &body["data"]["report"]["alarms"];
+    assert_eq!(alarms.as_array().map(Vec::len), Some(1), "{}", body);
+    assert_eq!((&alarms[0]["tag_name"], &alarms[0]["peak_value"]), (&json!("<script>"), &json!(85.0)));
+    assert!(body["data"]["summary_text"].as_str().unwrap().contains("Alarms raised:\n  Inverter 1 <script>: error gt 80 at 10:00:00, peak 85, cleared at 10:30:00"), "{}", body);
+    let html = body["data"]["summary_html"].as_str().unwrap();
+    assert!(html.contains("<h2>Alarms raised</h2>") && html.contains("Inverter 1 &lt;script&gt;: error gt 80"), "{}", html);
+    assert!(!html.contains("<script>"), "{}", html);
+    assert!(body["data"]["delivered_at"].is_string(), "{}", body);
+
+    // Sent once, signed, with the text and HTML renderings
+    let received = received.lock().unwrap().clone();
+    assert_eq!(received.len(), 1);
+    let (signature_header, payload) = &received[0];
+    assert_eq!(*signature_header, signature("report-secret", payload.as_bytes()));
+    let payload: Value = serde_json::from_str(payload)?;
+    assert_eq!(payload["event"], "daily_report");
+    assert_eq!(payload["data"]["report_date"], "2024-05-01");
+    assert_eq!(payload["data"]["html"].as_str(), Some(html));
+    assert_eq!(payload["data"]["text"], body["data"]["summary_text"]);
+    let deliveries = logger.state().database.get_webhook_deliveries("reports", 10).await?;
+    assert_eq!(deliveries.len(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_alarms_section_can_be_turned_off() -> Result<(), Box<dyn Error>> {
+    let logger = Logger::start("[reports.sections]\nalarms = false\n").await?;
+    raise_alarm(&logger).await?;
+
+    let body = logger.post("/api/reports/daily/generate?date=2024-05-01", &json!({})).await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert!(body["data"]["report"]["alarms"].is_null(), "{}", body);
+    assert!(!body["data"]["summary_text"].as_str().unwrap().contains("Alarms raised"), "{}", body);
+    assert!(!body["data"]["summary_html"].as_str().unwrap().contains("Alarms raised"), "{}", body);
+
+    // Nothing is subscribed to daily_report, so the report stays undelivered
+    let body = logger.post("/api/reports/daily/generate?date=2024-05-01&deliver=true", &json!({})).await?;
+    assert!(body["data"]["delivered_at"].is_null(), "{}", body);
+    Ok(())
+}
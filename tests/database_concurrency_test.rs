@@ -0,0 +1,103 @@
+use ava_device_logger::database::{Database, LogEntry};
+use chrono::Utc;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Database with `rows` log entries, bulk-inserted through a second connection
+async fn database_with_logs(rows: u32) -> Result<(Database, String), Box<dyn Error>> {
+    let db_path = std::env::temp_dir()
+        .join(format!("database-concurrency-{}.db", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string();
+    let db = Database::new(&db_path).await?;
+
+    let conn = rusqlite::Connection::open(&db_path)?;
+    conn.execute(
+        "INSERT INTO log_entries (device_id, tag_name, value, quality, timestamp, unit)
+         WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < ?1)
+         SELECT 'meter-1', 'active_power', i, 'Good', strftime('%Y-%m-%dT%H:%M:%S+00:00', 1700000000 + i, 'unixepoch'), 'kW' FROM n",
+        [rows],
+    )?;
+    Ok((db, db_path))
+}
+
+fn poll(device: usize) -> Vec<LogEntry> {
+    (0..10)
+        .map(|tag| LogEntry {
+            id: None,
+            device_id: format!("device-{}", device),
+            tag_name: format!("tag-{}", tag),
+            value: tag as f64,
+            quality: "Good".to_string(),
+            timestamp: Utc::now(),
+            unit: None,
+        })
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn test_history_queries_do_not_hold_up_logging() -> Result<(), Box<dyn Error>> {
+    let (db, db_path) = database_with_logs(200_000).await?;
+    let db = Arc::new(db);
+
+    // How long one full history read takes on this machine
+    let started = Instant::now();
+    assert_eq!(db.get_log_entries(None, None, None).await?.len(), 200_000);
+    let read_time = started.elapsed();
+
+    let running = Arc::new(AtomicBool::new(true));
+    let mut readers = Vec::new();
+    for _ in 0..2 {
+        let (db, running) = (db.clone(), running.clone());
+        readers.push(tokio::spawn(async move {
+            let mut reads = 0;
+            while running.load(Ordering::SeqCst) {
+                db.get_log_entries(None, None, None).await.unwrap();
+                db.count_log_entries(None, None, None).await.unwrap();
+                reads += 1;
+            }
+            reads
+        }));
+    }
+
+    // 20 devices polling while the history queries run
+    let mut writers = Vec::new();
+    for device in 0..20 {
+        let db = db.clone();
+        writers.push(tokio::spawn(async move {
+            let mut slowest = Duration::ZERO;
+            for _ in 0..20 {
+                let started = Instant::now();
+                db.insert_log_entries(&poll(device)).await.unwrap();
+                slowest = slowest.max(started.elapsed());
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            slowest
+        }));
+    }
+
+    let mut slowest_insert = Duration::ZERO;
+    for writer in writers {
+        slowest_insert = slowest_insert.max(writer.await?);
+    }
+    running.store(false, Ordering::SeqCst);
+    let mut reads = 0;
+    for reader in readers {
+        reads += reader.await?;
+    }
+
+    assert!(reads > 0);
+    assert!(
+        slowest_insert < read_time / 2,
+        "an insert took {:?} while a history read takes {:?}",
+        slowest_insert,
+        read_time
+    );
+    assert_eq!(db.count_log_entries(Some("device-7"), None, None).await?, 200);
+    assert_eq!(db.count_log_entries(None, None, None).await?, 200_000 + 20 * 20 * 10);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
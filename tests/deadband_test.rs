@@ -0,0 +1,202 @@
+mod support;
+
+use ava_device_logger::database::{Database, DeviceInstance, DeviceTag, LogEntry, TagWritePolicy};
+use ava_device_logger::deadband::DeadbandFilter;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde_json::json;
+use std::error::Error;
+use support::{eventually, log_entries, Logger, ModbusDevice, TestResult};
+
+const HEARTBEAT: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+const WAIT: std::time::Duration = std::time::Duration::from_secs(10);
+
+fn device(id: &str) -> DeviceInstance {
+    DeviceInstance {
+        id: id.to_string(),
+        name: "Meter".to_string(),
+        serial_no: None,
+        model_id: None,
+        enabled: false,
+        polling_interval_ms: 1000,
+        timeout_ms: 5000,
+        retry_count: 3,
+        protocol_config: "{}".to_string(),
+        tb_device_id: None,
+        tb_group_id: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    }
+}
+
+fn tag(name: &str, deadband_absolute: Option<f64>, deadband_percent: Option<f64>) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: "meter-1".to_string(),
+        name: name.to_string(),
+        address: 1,
+        size: 1,
+        data_type: "uint16".to_string(),
+        description: None,
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: None,
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy: TagWritePolicy::Disabled,
+        byte_order: None,
+        deadband_absolute,
+        deadband_percent,
+        register_type: None,
+    }
+}
+
+fn entry(tag_name: &str, value: f64, quality: &str, timestamp: DateTime<Utc>) -> LogEntry {
+    LogEntry {
+        id: None,
+        device_id: "meter-1".to_string(),
+        tag_name: tag_name.to_string(),
+        value,
+        quality: quality.to_string(),
+        timestamp,
+        unit: None,
+    }
+}
+
+/// Values stored from a sequence of single-sample polls of one tag, one minute apart
+fn logged(filter: &DeadbandFilter, tag: &DeviceTag, samples: &[(f64, &str)]) -> Vec<(f64, String)> {
+    let start = Utc.with_ymd_and_hms(2026, 3, 1, 0, 0, 0).unwrap();
+    let tags = std::slice::from_ref(tag);
+    samples
+        .iter()
+        .enumerate()
+        .flat_map(|(minute, (value, quality))| {
+            filter.filter(&[entry(&tag.name, *value, quality, start + Duration::minutes(minute as i64))], tags)
+        })
+        .map(|entry| (entry.value, entry.quality))
+        .collect()
+}
+
+fn good(values: &[f64]) -> Vec<(f64, String)> {
+    values.iter().map(|value| (*value, "Good".to_string())).collect()
+}
+
+#[test]
+fn test_values_inside_the_deadband_are_not_logged() {
+    let filter = DeadbandFilter::new(HEARTBEAT);
+
+    // Measured from the last logged value, so a slow drift is still logged eventually
+    let absolute = tag("Voltage", Some(0.5), None);
+    let logged_values = logged(&filter, &absolute, &[(230.0, "Good"), (230.2, "Good"), (230.5, "Good"), (230.6, "Good"), (229.0, "Good")]);
+    assert_eq!(logged_values, good(&[230.0, 230.6, 229.0]));
+
+    // 1% of the last logged value
+    let percent = tag("Power", None, Some(1.0));
+    let logged_values = logged(&filter, &percent, &[(1000.0, "Good"), (1009.0, "Good"), (990.0, "Good"), (10.0, "Good")]);
+    assert_eq!(logged_values, good(&[1000.0, 10.0]));
+
+    // With both, a value is logged once it leaves either
+    let both = tag("Current", Some(5.0), Some(1.0));
+    let logged_values = logged(&filter, &both, &[(100.0, "Good"), (103.0, "Good"), (103.5, "Good")]);
+    assert_eq!(logged_values, good(&[100.0, 103.0]));
+
+    // A zero deadband only skips repeated values
+    let exact = tag("Breaker", Some(0.0), None);
+    assert_eq!(logged(&filter, &exact, &[(1.0, "Good"), (1.0, "Good"), (0.0, "Good")]), good(&[1.0, 0.0]));
+
+    // Without a deadband every stored value is logged
+    let plain = tag("Frequency", None, None);
+    assert_eq!(logged(&filter, &plain, &[(50.0, "Good"), (50.0, "Good")]), good(&[50.0, 50.0]));
+}
+
+#[test]
+fn test_quality_changes_and_heartbeats_are_always_logged() {
+    let filter = DeadbandFilter::new(HEARTBEAT);
+    let voltage = tag("Voltage", Some(10.0), None);
+
+    // A tag that starts failing is stored once, and so is the first good value after it
+    let logged_values = logged(
+        &filter,
+        &voltage,
+        &[
+            (230.0, "Good"), (230.0, "Uncertain"), (230.0, "Uncertain"), (231.0, "Good"), (0.0, "CommFail"), (0.0, "CommFail"),
+            (231.0, "Good"), (231.0, "muted"), (231.0, "Good"),
+        ],
+    );
+    assert_eq!(
+        logged_values,
+        [(230.0, "Good"), (230.0, "Uncertain"), (231.0, "Good"), (0.0, "CommFail"), (231.0, "Good")].map(|(value, quality)| (value, quality.to_string()))
+    );
+
+    // An unchanged value is still logged every heartbeat interval
+    let filter = DeadbandFilter::new(HEARTBEAT);
+    let samples: Vec<(f64, &str)> = (0..40).map(|_| (230.0, "Good")).collect();
+    assert_eq!(logged(&filter, &voltage, &samples), good(&[230.0, 230.0, 230.0]));
+}
+
+#[tokio::test]
+async fn test_last_values_are_seeded_from_the_log() -> Result<(), Box<dyn Error>> {
+    let db_path = std::env::temp_dir()
+        .join(format!("deadband-{}.db", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string();
+    let db = Database::new(&db_path).await?;
+
+    // Deadbands are stored with the tag
+    db.create_device(&device("meter-1")).await?;
+    db.create_device_tags("meter-1", &[tag("Voltage", Some(0.5), None), tag("Power", None, Some(2.0))]).await?;
+    let tags = db.get_device_tags("meter-1").await?;
+    let deadbands: Vec<(Option<f64>, Option<f64>)> = tags.iter().map(|tag| (tag.deadband_absolute, tag.deadband_percent)).collect();
+    assert_eq!(deadbands, [(Some(0.5), None), (None, Some(2.0))]);
+
+    let now = Utc::now();
+    db.insert_log_entries(&[
+        entry("Voltage", 229.0, "Good", now - Duration::minutes(5)),
+        entry("Voltage", 230.0, "Good", now - Duration::minutes(1)),
+        // Older than the heartbeat interval, so the next value is due anyway
+        entry("Power", 1000.0, "Good", now - Duration::minutes(20)),
+    ])
+    .await?;
+
+    let filter = DeadbandFilter::new(HEARTBEAT);
+    filter.seed(&db, "meter-1").await?;
+    let poll = [entry("Voltage", 230.2, "Good", now), entry("Power", 1000.0, "Good", now)];
+    let stored: Vec<String> = filter.filter(&poll, &tags).into_iter().map(|entry| entry.tag_name).collect();
+    assert_eq!(stored, ["Power"]);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_values_inside_the_deadband_are_served_but_not_logged() -> TestResult {
+    let device = ModbusDevice::start([(100, 1000)]).await?;
+    let logger = Logger::start("").await?;
+    let db = logger.database().await?;
+    logger
+        .start_modbus_device("meter-1", &device, 100, vec![support::tag("Power", 100, "uint16", 1.0, json!({"deadband_absolute": 5.0}))])
+        .await?;
+
+    eventually(WAIT, || async { log_entries(&db, "meter-1", "Power").await.first().map(|_| ()) })
+        .await
+        .expect("the first value was not logged");
+
+    // A small change is served live, and the log keeps the first value only
+    device.set(100, 1003);
+    eventually(WAIT, || async { logger.values("meter-1").await.ok().filter(|values| values["Power"]["value"] == 1003.0) })
+        .await
+        .expect("the small change was not served");
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+    let values: Vec<f64> = log_entries(&db, "meter-1", "Power").await.iter().map(|entry| entry.value).collect();
+    assert_eq!(values, [1000.0]);
+
+    // A change past the deadband is logged
+    device.set(100, 1010);
+    let entries = eventually(WAIT, || async { Some(log_entries(&db, "meter-1", "Power").await).filter(|entries| entries.len() == 2) })
+        .await
+        .expect("the change past the deadband was not logged");
+    assert_eq!(entries[1].value, 1010.0);
+    Ok(())
+}
@@ -0,0 +1,117 @@
+mod support;
+
+use ava_device_logger::database::{Database, DeviceInstance, DeviceTag, TagWritePolicy};
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::error::Error;
+use support::Logger;
+
+fn outcomes(body: &Value) -> Vec<(String, String)> {
+    body["data"]["results"]
+        .as_array()
+        .expect("results")
+        .iter()
+        .map(|result| (result["device_id"].as_str().unwrap().to_string(), result["outcome"].as_str().unwrap().to_string()))
+        .collect()
+}
+
+fn pairs(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+    expected.iter().map(|(device_id, outcome)| (device_id.to_string(), outcome.to_string())).collect()
+}
+
+#[tokio::test]
+async fn test_bulk_actions_report_done_skipped_and_failed_per_device() -> Result<(), Box<dyn Error>> {
+    let work_dir = support::work_dir("device-bulk")?;
+    // Nothing listens here, so pollers keep retrying without ever connecting
+    let device_port = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+
+    let db = Database::new(&work_dir.join("data.db").to_string_lossy()).await?;
+    for (id, enabled) in [("inv-1", true), ("inv-2", false)] {
+        db.create_device(&DeviceInstance {
+            id: id.to_string(),
+            name: id.to_string(),
+            serial_no: None,
+            model_id: None,
+            enabled,
+            polling_interval_ms: 60_000,
+            timeout_ms: 200,
+            retry_count: 1,
+            protocol_config: json!({"type": "modbus_tcp", "host": "127.0.0.1", "port": device_port, "slave_id": 1}).to_string(),
+            tb_device_id: None,
+            tb_group_id: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            strict_types: false,
+        }).await?;
+        db.create_device_tags(id, &[DeviceTag {
+            id: None,
+            device_id: id.to_string(),
+            name: "Active Power".to_string(),
+            address: 40001,
+            size: 1,
+            data_type: "uint16".to_string(),
+            description: None,
+            scaling_multiplier: 1.0,
+            scaling_offset: 0.0,
+            unit: None,
+            read_only: true,
+            enabled: true,
+            schedule_group_id: None,
+            agg_to_field: None,
+            write_policy: TagWritePolicy::AdminOnly,
+            byte_order: None,
+            deadband_absolute: None,
+            deadband_percent: None,
+            register_type: None,
+        }]).await?;
+    }
+    drop(db);
+
+    let logger = Logger::start_in(work_dir, "").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let post = |path: &'static str, body: Value| {
+        let request = client
+            .post(format!("{}/api/devices-enhanced/{}", base_url, path))
+            .bearer_auth(token)
+            .json(&body);
+        async move { request.send().await?.json::<Value>().await }
+    };
+
+    // inv-1 was started with the server; inv-2 is disabled
+    let body = post("stop-all", json!({})).await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!(outcomes(&body), pairs(&[("inv-1", "done"), ("inv-2", "skipped")]));
+    assert_eq!((body["data"]["done"].as_u64(), body["data"]["skipped"].as_u64()), (Some(1), Some(1)));
+
+    let body = post("start-all", json!({})).await?;
+    assert_eq!(outcomes(&body), pairs(&[("inv-1", "done"), ("inv-2", "skipped")]));
+    assert_eq!(body["data"]["results"][1]["detail"], "Device is disabled");
+
+    // One unknown device doesn't stop the others
+    let body = post("bulk", json!({"action": "enable", "device_ids": ["missing", "inv-2"]})).await?;
+    assert_eq!(outcomes(&body), pairs(&[("missing", "failed"), ("inv-2", "done")]));
+    assert!(body["data"]["results"][0]["detail"].as_str().unwrap().contains("not found"), "{}", body);
+    assert_eq!(body["data"]["failed"], 1);
+
+    let body = post("bulk", json!({"action": "start", "all": true})).await?;
+    assert_eq!(outcomes(&body), pairs(&[("inv-1", "skipped"), ("inv-2", "skipped")]));
+
+    let body = post("bulk", json!({"action": "disable", "device_ids": ["inv-1"]})).await?;
+    assert_eq!(outcomes(&body), pairs(&[("inv-1", "done")]));
+    let body = post("stop-all", json!({})).await?;
+    assert_eq!(outcomes(&body), pairs(&[("inv-1", "skipped"), ("inv-2", "done")]));
+
+    let device: Value = client
+        .get(format!("{}/api/devices-enhanced/inv-1", base_url))
+        .bearer_auth(token)
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(device["data"]["device"]["enabled"], false, "{}", device);
+
+    assert_eq!(post("bulk", json!({"action": "stop"})).await?["success"], false);
+    assert_eq!(post("bulk", json!({"action": "stop", "all": true, "device_ids": ["inv-1"]})).await?["success"], false);
+    Ok(())
+}
@@ -0,0 +1,114 @@
+mod support;
+
+use ava_device_logger::catalog::CATALOG_FORMAT_VERSION;
+use ava_device_logger::config::RegisterType;
+use ava_device_logger::database::{Database, DeviceInstance, DeviceTag, TagWritePolicy};
+use ava_device_logger::tb_rust_client::{device_catalog_file_name, ThingsBoardClient};
+use chrono::Utc;
+use serde_json::json;
+use std::error::Error;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// ThingsBoard stand-in for group `group-1` holding the single inverter `tb-1`
+async fn spawn_tb_server() -> MockServer {
+    let server = support::thingsboard().await;
+    let groups = json!([{"id": {"id": "group-1", "entityType": "ENTITY_GROUP"}, "ownerId": {"id": "tenant", "entityType": "TENANT"}, "name": "ACCV-P002 Plant", "type": "DEVICE", "groupAll": false, "edgeGroupAll": false}]);
+    Mock::given(method("GET")).and(path("/api/entityGroups/DEVICE")).respond_with(ResponseTemplate::new(200).set_body_json(groups)).mount(&server).await;
+    let devices = json!({"data": [{"id": {"id": "tb-1", "entityType": "DEVICE"}, "name": "ACCV-P002-I01", "type": "Inverter", "label": "Inverter A"}], "totalPages": 1, "totalElements": 1, "hasNext": false});
+    Mock::given(method("GET")).and(path("/api/entityGroup/group-1/devices")).respond_with(ResponseTemplate::new(200).set_body_json(devices)).mount(&server).await;
+    let credentials = json!({"credentialsType": "ACCESS_TOKEN", "credentialsId": "token-1"});
+    Mock::given(method("GET")).and(path("/api/device/tb-1/credentials")).respond_with(ResponseTemplate::new(200).set_body_json(credentials)).mount(&server).await;
+    server
+}
+
+fn tag(name: &str, address: u16) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: "inv-a".to_string(),
+        name: name.to_string(),
+        address,
+        size: 2,
+        data_type: "float32".to_string(),
+        description: None,
+        scaling_multiplier: 0.1,
+        scaling_offset: 0.0,
+        unit: Some("kW".to_string()),
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy: TagWritePolicy::Disabled,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: Some(RegisterType::Input),
+    }
+}
+
+#[tokio::test]
+async fn test_catalog_rows_are_written_to_any_writer() -> Result<(), Box<dyn Error>> {
+    let db_path = std::env::temp_dir().join(format!("device-catalog-{}.db", uuid::Uuid::new_v4()));
+    let db = Database::new(&db_path.to_string_lossy()).await?;
+    db.create_device(&DeviceInstance {
+        id: "inv-a".to_string(),
+        name: "Inverter A".to_string(),
+        serial_no: Some("SN-1".to_string()),
+        model_id: None,
+        enabled: false,
+        polling_interval_ms: 1000,
+        timeout_ms: 1000,
+        retry_count: 1,
+        protocol_config: json!({"type": "modbus_tcp", "host": "10.0.0.5", "port": 502, "slave_id": 3}).to_string(),
+        tb_device_id: Some("tb-1".to_string()),
+        tb_group_id: Some("group-1".to_string()),
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    })
+    .await?;
+    // Inverter rows leave out the Udc and Idc tags of its strings
+    db.create_device_tags("inv-a", &[tag("P", 100), tag("Udc", 200)]).await?;
+
+    let tb = spawn_tb_server().await;
+    let mut client = ThingsBoardClient::new(&tb.uri());
+    client.login("user", "pass").await?;
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let summary = client.write_detailed_device_catalog("group-1", &db, &mut writer, &|_| {}).await?;
+    let csv = writer.into_inner()?;
+
+    assert_eq!(summary.file_name, "ACCV-P002-Plant-device-catalog.csv");
+    assert_eq!((summary.total_rows, summary.successful_tokens, summary.failed_tokens), (1, 1, 0));
+    assert_eq!(summary.format_version, CATALOG_FORMAT_VERSION);
+    assert_eq!(summary.devices, vec![("ACCV-P002-I01".to_string(), "Inverter".to_string())]);
+
+    let mut reader = csv::Reader::from_reader(csv.as_slice());
+    let headers = reader.headers()?.clone();
+    let rows: Vec<csv::StringRecord> = reader.records().collect::<Result<_, _>>()?;
+    assert_eq!(rows.len(), 1);
+    let column = |name: &str| rows[0].get(headers.iter().position(|header| header == name).expect(name)).unwrap_or_default().to_string();
+    assert_eq!(column("Device Name"), "ACCV-P002-I01");
+    assert_eq!(column("Serial Number"), "SN-1");
+    assert_eq!(column("Token"), "token-1");
+    assert_eq!(column("Plant"), "ACCV-P002 Plant");
+    assert_eq!(column("Data Label"), "P");
+    assert_eq!(column("Address"), "100");
+    assert_eq!(column("Divider"), "10");
+    assert_eq!(column("Data Type"), "float32");
+    assert_eq!(column("Unit"), "kW");
+    assert_eq!(column("Register Type"), "input");
+    assert!(!headers.iter().any(|header| header == "Modbus Type"));
+
+    // Rendering to memory produces the same bytes without touching the filesystem
+    let (_, rendered) = client.render_detailed_device_catalog_csv("group-1", &db, &|_| {}).await?;
+    assert_eq!(rendered, csv);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[test]
+fn test_catalog_file_names_replace_unsafe_characters() {
+    assert_eq!(device_catalog_file_name("ACCV/P002: Plant?"), "ACCV-P002--Plant--device-catalog.csv");
+}
@@ -0,0 +1,183 @@
+mod support;
+
+use ava_device_logger::config::{FieldError, ModbusTcpConfig, ProtocolConfig};
+use serde_json::{json, Value};
+use std::error::Error;
+use support::{Logger, ModbusDevice};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+fn fields(errors: &[FieldError]) -> Vec<&str> {
+    errors.iter().map(|error| error.field.as_str()).collect()
+}
+
+#[test]
+fn test_protocol_config_errors_are_reported_per_field() {
+    let config = ProtocolConfig::from_json(&json!({"type": "modbus_tcp", "host": "192.168.1.10", "port": 502, "slave_id": 1}));
+    assert!(matches!(config, Ok(ProtocolConfig::ModbusTcp(ModbusTcpConfig { port: 502, slave_id: 1, .. }))));
+
+    // Unknown fields are rejected rather than dropped, each reported at its path
+    let errors = ProtocolConfig::from_json(&json!({"type": "modbus_tcp", "host": "10.0.0.5", "port": 0, "slave_id": 248, "timeout": 5})).unwrap_err();
+    assert_eq!(fields(&errors), ["port", "slave_id", "timeout"]);
+    assert!(errors[2].message.starts_with("unknown field `timeout`"), "{:?}", errors);
+    let errors = ProtocolConfig::from_json(&json!({
+        "type": "modbus_tcp", "host": "10.0.0.5", "port": 502, "slave_id": 1, "timeout": 5, "retries": 2,
+        "serial_source": {"address": 4990, "length": 10, "swap": true},
+    }))
+    .unwrap_err();
+    assert_eq!(fields(&errors), ["retries", "serial_source.swap", "timeout"]);
+    assert!(errors[1].message.starts_with("unknown field `swap`"), "{:?}", errors);
+    let errors = ProtocolConfig::from_json(&json!({"type": "modbus_tcp", "host": "10.0.0.5", "port": 502, "slave_id": 1, "max_block_gap": "ten"})).unwrap_err();
+    assert_eq!(fields(&errors), ["max_block_gap"]);
+
+    let errors = ProtocolConfig::from_json(&json!({"type": "iec104", "host": 10, "port": "2404"})).unwrap_err();
+    assert_eq!(fields(&errors), ["host", "port"]);
+    assert_eq!(errors[0].message, "must be a string");
+
+    let errors = ProtocolConfig::from_json(&json!({"type": "modbus_tcp", "host": "192.168.1.300", "port": 70000, "slave_id": "1"})).unwrap_err();
+    assert_eq!(fields(&errors), ["host", "port", "slave_id"]);
+    assert_eq!(errors[0].message, "'192.168.1.300' is not an IP address");
+
+    let errors = ProtocolConfig::from_json(&json!({"type": "modbus_rtu", "baud_rate": 9600, "slave_id": 0, "parity": "mark"})).unwrap_err();
+    assert_eq!(fields(&errors), ["port", "slave_id", "parity"]);
+    assert_eq!(errors[0].message, "is required");
+    let errors = ProtocolConfig::from_json(&json!({"type": "modbus_rtu", "port": " ", "baud_rate": 9600, "slave_id": 1})).unwrap_err();
+    assert_eq!(fields(&errors), ["port"]);
+
+    let errors = ProtocolConfig::from_json(&json!({"type": "iec104", "host": "10.0.0.5", "port": 2404, "mode": "polling"})).unwrap_err();
+    assert_eq!(fields(&errors), ["mode"]);
+
+    assert_eq!(fields(&ProtocolConfig::from_json(&json!({"type": "bacnet"})).unwrap_err()), ["type"]);
+    assert_eq!(fields(&ProtocolConfig::from_json(&json!("modbus_tcp")).unwrap_err()), ["protocol_config"]);
+}
+
+/// Accepts connections and never answers
+async fn spawn_silent_listener() -> Result<u16, Box<dyn Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    tokio::spawn(async move {
+        let mut sockets = Vec::new();
+        while let Ok((socket, _)) = listener.accept().await {
+            sockets.push(socket);
+        }
+    });
+    Ok(port)
+}
+
+/// IEC 104 outstation confirming STARTDT
+async fn spawn_iec104_outstation() -> Result<u16, Box<dyn Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut frame = [0u8; 6];
+                while socket.read_exact(&mut frame).await.is_ok() {
+                    if frame[2] == 0x07 && socket.write_all(&[0x68, 0x04, 0x0B, 0, 0, 0]).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    Ok(port)
+}
+
+#[tokio::test]
+async fn test_connection_endpoint_reports_each_protocol() -> Result<(), Box<dyn Error>> {
+    let work_dir = support::work_dir("device-connection")?;
+    let closed_port = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+
+    let logger = Logger::start_in(work_dir, "").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let test = |protocol_config: Value, timeout_ms: u32| {
+        let request = client
+            .post(format!("{}/api/devices-enhanced/test-connection", base_url))
+            .bearer_auth(token)
+            .json(&json!({"protocol_config": protocol_config, "timeout_ms": timeout_ms}));
+        async move {
+            let response = request.send().await?;
+            Ok::<_, reqwest::Error>((response.status().as_u16(), response.json::<Value>().await?))
+        }
+    };
+
+    let slave = ModbusDevice::start([(0, 1234)]).await?;
+    let (status, body) = test(json!({"type": "modbus_tcp", "host": "127.0.0.1", "port": slave.port(), "slave_id": 1}), 2000).await?;
+    assert_eq!((status, &body["data"]["success"]), (200, &json!(true)), "{}", body);
+    assert!(body["data"]["message"].as_str().unwrap().contains("returned 1234"), "{}", body);
+    assert!(body["data"]["latency_ms"].as_u64().is_some());
+
+    let (_, body) = test(json!({"type": "modbus_tcp", "host": "127.0.0.1", "port": closed_port, "slave_id": 1}), 2000).await?;
+    assert_eq!(body["data"]["success"], false, "{}", body);
+    assert!(body["data"]["message"].as_str().unwrap().starts_with("Could not connect"), "{}", body);
+
+    // A device that never answers is given up on after the timeout
+    let silent_port = spawn_silent_listener().await?;
+    let (_, body) = test(json!({"type": "iec104", "host": "127.0.0.1", "port": silent_port}), 300).await?;
+    assert_eq!(body["data"]["success"], false, "{}", body);
+    assert!(body["data"]["message"].as_str().unwrap().starts_with("No answer within 300ms"), "{}", body);
+    assert!(body["data"]["latency_ms"].as_u64().unwrap() < 2000, "{}", body);
+
+    let outstation_port = spawn_iec104_outstation().await?;
+    let (_, body) = test(json!({"type": "iec104", "host": "127.0.0.1", "port": outstation_port}), 2000).await?;
+    assert_eq!(body["data"]["success"], true, "{}", body);
+    assert!(body["data"]["message"].as_str().unwrap().contains("STARTDT"), "{}", body);
+
+    // Nothing is tried with an invalid config
+    let (status, body) = test(json!({"type": "modbus_tcp", "host": "plc-1", "slave_id": 1}), 2000).await?;
+    assert_eq!(status, 400);
+    assert_eq!(body["success"], false);
+    assert_eq!(body["details"], json!([
+        {"field": "host", "message": "'plc-1' is not an IP address"},
+        {"field": "port", "message": "is required"},
+    ]));
+    assert_eq!(body["field_errors"], body["details"]);
+
+    // Devices are only saved with a valid config, stored as parsed
+    let device = |protocol_config: Value| json!({
+        "id": "inv-1", "name": "Inverter 1", "enabled": false, "polling_interval_ms": 1000, "timeout_ms": 1000,
+        "retry_count": 1, "protocol_config": protocol_config, "tags": [],
+    });
+    let body: Value = client
+        .post(format!("{}/api/devices-enhanced", base_url))
+        .bearer_auth(token)
+        .json(&device(json!({"type": "modbus_tcp", "host": "10.0.0.5", "port": "502"})))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["success"], false);
+    assert_eq!(body["error"], "Invalid protocol config: port: must be a whole number from 1 to 65535; slave_id: is required");
+    assert_eq!(body["field_errors"], json!([
+        {"field": "port", "message": "must be a whole number from 1 to 65535"},
+        {"field": "slave_id", "message": "is required"},
+    ]));
+
+    let body: Value = client
+        .post(format!("{}/api/devices-enhanced", base_url))
+        .bearer_auth(token)
+        .json(&device(json!({"type": "modbus_tcp", "host": "10.0.0.5", "port": 502, "slave_id": 3})))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["success"], true, "{}", body);
+
+    let body: Value = client
+        .put(format!("{}/api/devices-enhanced/inv-1", base_url))
+        .bearer_auth(token)
+        .json(&device(json!({"type": "modbus_tcp", "host": "10.0.0.5", "port": 502, "slave_id": 300})))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["field_errors"], json!([{"field": "slave_id", "message": "must be a whole number from 0 to 247"}]));
+
+    let body: Value = client.get(format!("{}/api/devices-enhanced/inv-1", base_url)).bearer_auth(token).send().await?.json().await?;
+    let stored: Value = serde_json::from_str(body["data"]["device"]["protocol_config"].as_str().unwrap())?;
+    assert_eq!(stored, json!({
+        "type": "modbus_tcp", "host": "10.0.0.5", "port": 502, "slave_id": 3, "max_block_gap": 0, "request_delay_ms": 0,
+    }));
+    Ok(())
+}
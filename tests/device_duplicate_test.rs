@@ -0,0 +1,83 @@
+mod support;
+
+use serde_json::{json, Value};
+use std::error::Error;
+use support::Logger;
+
+#[tokio::test]
+async fn test_device_is_copied_with_its_tags() -> Result<(), Box<dyn Error>> {
+
+    let logger = Logger::start("").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let tag = |name: &str, address: u16| json!({
+        "name": name, "address": address, "size": 2, "data_type": "float32", "unit": "kW",
+        "scaling_multiplier": 0.1, "scaling_offset": 0.0, "read_only": true, "enabled": true
+    });
+    let device = |id: &str, protocol_config: Value| json!({
+        "id": id, "name": id, "serial_no": "SN-1", "enabled": true, "polling_interval_ms": 2000, "timeout_ms": 1000,
+        "retry_count": 1, "protocol_config": protocol_config, "tags": [tag("Power", 100), tag("Energy", 102)],
+    });
+    for (id, protocol_config) in [
+        ("inv-1", json!({"type": "modbus_tcp", "host": "10.0.0.10", "port": 502, "slave_id": 1})),
+        ("rtu-1", json!({"type": "modbus_rtu", "port": "/dev/ttyUSB0", "baud_rate": 9600, "slave_id": 1})),
+    ] {
+        let body: Value = client.post(format!("{}/api/devices-enhanced", base_url)).bearer_auth(token).json(&device(id, protocol_config)).send().await?.json().await?;
+        assert_eq!(body["success"], true, "{}", body);
+    }
+
+    let duplicate = |id: &str, request: Value| {
+        let request = client.post(format!("{}/api/devices-enhanced/{}/duplicate", base_url, id)).bearer_auth(token).json(&request);
+        async move {
+            let response = request.send().await?;
+            Ok::<_, reqwest::Error>((response.status().as_u16(), response.json::<Value>().await.unwrap_or(Value::Null)))
+        }
+    };
+
+    let (_, body) = duplicate("inv-1", json!({"count": 3, "name_pattern": "Inverter {n}", "host_start": "10.0.0.11", "slave_id_start": 5})).await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!(body["data"], json!(["inv-1-1", "inv-1-2", "inv-1-3"]));
+
+    let body: Value = client.get(format!("{}/api/devices-enhanced/inv-1-3", base_url)).bearer_auth(token).send().await?.json().await?;
+    let copy = &body["data"]["device"];
+    assert_eq!(copy["name"], "Inverter 3");
+    assert_eq!(copy["enabled"], false);
+    assert_eq!(copy["serial_no"], Value::Null);
+    assert_eq!(copy["tb_device_id"], Value::Null);
+    assert_eq!(copy["polling_interval_ms"], 2000);
+    let protocol: Value = serde_json::from_str(copy["protocol_config"].as_str().unwrap())?;
+    assert_eq!((&protocol["host"], &protocol["slave_id"], &protocol["port"]), (&json!("10.0.0.13"), &json!(7), &json!(502)));
+    let tags: Vec<(&str, u64, f64)> = body["data"]["tags"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|tag| (tag["name"].as_str().unwrap(), tag["address"].as_u64().unwrap(), tag["scaling_multiplier"].as_f64().unwrap()))
+        .collect();
+    assert_eq!(tags, [("Power", 100, 0.1), ("Energy", 102, 0.1)]);
+
+    // Every problem with the request is listed, and nothing is created
+    let (_, body) = duplicate("inv-1", json!({
+        "count": 2, "name_pattern": "Inverter {n}", "id_pattern": "inv-1-{n}", "host_start": "10.0.0.255", "slave_id_start": 247,
+    })).await?;
+    assert_eq!(body["success"], false);
+    assert_eq!(body["field_errors"], json!([
+        {"field": "name_pattern", "message": "generates names already used by existing devices: Inverter 1, Inverter 2"},
+        {"field": "id_pattern", "message": "generates ids already used by existing devices: inv-1-1, inv-1-2"},
+        {"field": "host_start", "message": "leaves room for only 1 copies up to 10.0.0.255"},
+        {"field": "slave_id_start", "message": "reaches 248 after 2 copies; the last slave id is 247"},
+    ]));
+    let (_, body) = duplicate("inv-1", json!({"count": 2, "name_pattern": "Spare", "id_pattern": "spare-{n}", "host_list": ["10.0.1.1"]})).await?;
+    assert_eq!(body["field_errors"], json!([
+        {"field": "name_pattern", "message": "generates 'Spare' more than once; add {n}"},
+        {"field": "host_list", "message": "has 1 hosts for 2 copies"},
+    ]));
+
+    let (_, body) = duplicate("rtu-1", json!({"count": 2, "name_pattern": "Meter {n}", "host_list": ["a", "b"]})).await?;
+    assert_eq!(body["field_errors"], json!([{"field": "host_list", "message": "modbus_rtu devices have no host"}]));
+    let (_, body) = duplicate("rtu-1", json!({"count": 2, "name_pattern": "Meter {n}", "slave_id_start": 2})).await?;
+    assert_eq!(body["data"], json!(["rtu-1-1", "rtu-1-2"]), "{}", body);
+
+    let (status, _) = duplicate("no-such-device", json!({"count": 1, "name_pattern": "X"})).await?;
+    assert_eq!(status, 404);
+    Ok(())
+}
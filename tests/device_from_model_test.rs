@@ -0,0 +1,129 @@
+mod support;
+
+use ava_device_logger::database::Database;
+use serde_json::{json, Value};
+use std::error::Error;
+use support::Logger;
+
+#[tokio::test]
+async fn test_templates_stored_before_sizes_have_size_one() -> Result<(), Box<dyn Error>> {
+    let db_path = std::env::temp_dir().join(format!("template-size-{}.db", uuid::Uuid::new_v4()));
+    {
+        let conn = rusqlite::Connection::open(&db_path)?;
+        conn.execute_batch(
+            "CREATE TABLE tag_templates (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                model_id TEXT NOT NULL,
+                name TEXT NOT NULL,
+                address INTEGER NOT NULL,
+                data_type TEXT NOT NULL,
+                description TEXT,
+                scaling_multiplier REAL DEFAULT 1.0,
+                scaling_offset REAL DEFAULT 0.0,
+                unit TEXT,
+                read_only BOOLEAN DEFAULT FALSE
+            );
+            INSERT INTO tag_templates (model_id, name, address, data_type) VALUES ('meter', 'Energy', 100, 'float32');",
+        )?;
+    }
+
+    let db = Database::new(&db_path.to_string_lossy()).await?;
+    let templates = db.get_tag_templates("meter").await?;
+    assert_eq!(templates.len(), 1);
+    assert_eq!(templates[0].size, 1);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+/// multipart/form-data body for text fields and one CSV file
+fn multipart(boundary: &str, fields: &[(&str, &str)], csv: &str) -> String {
+    let mut body = String::new();
+    for (name, value) in fields {
+        body += &format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n", boundary, name, value);
+    }
+    body += &format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"csv_file\"; filename=\"tags.csv\"\r\nContent-Type: text/csv\r\n\r\n{}\r\n--{}--\r\n",
+        boundary, csv, boundary
+    );
+    body
+}
+
+#[tokio::test]
+async fn test_device_tags_are_instantiated_from_model_templates() -> Result<(), Box<dyn Error>> {
+
+    let logger = Logger::start("").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let boundary = "device-from-model-test";
+    let csv = "name,address,data_type,unit,description\nEnergy,100,float64,kWh,Total energy\nPower,104,float32,kW,\nStatus,110,uint16,,\n";
+    let body: Value = client
+        .post(format!("{}/api/device-models", base_url))
+        .bearer_auth(token)
+        .header("Content-Type", format!("multipart/form-data; boundary={}", boundary))
+        .body(multipart(boundary, &[("name", "Meter"), ("protocol_type", "modbus_tcp")], csv))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let model_id = body["data"]["id"].as_str().unwrap().to_string();
+
+    let device = |id: &str, protocol_config: Value, options: Value| {
+        let mut device = json!({
+            "id": id, "name": "Meter 1", "model_id": model_id, "enabled": false, "polling_interval_ms": 1000,
+            "timeout_ms": 1000, "retry_count": 1, "protocol_config": protocol_config,
+        });
+        device.as_object_mut().unwrap().extend(options.as_object().unwrap().clone());
+        device
+    };
+    let modbus = json!({"type": "modbus_tcp", "host": "127.0.0.1", "port": 502, "slave_id": 1});
+    let create = |request: Value| {
+        let request = client.post(format!("{}/api/devices-enhanced/from-model", base_url)).bearer_auth(token).json(&request);
+        async move {
+            let response = request.send().await?;
+            Ok::<_, reqwest::Error>((response.status().as_u16(), response.json::<Value>().await.unwrap_or(Value::Null)))
+        }
+    };
+
+    let (_, body) = create(device("meter-1", modbus.clone(), json!({"address_offset": 1000, "schedule_group_id": "low_freq"}))).await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!(body["data"], json!({"device_id": "meter-1", "tags_instantiated": 3}));
+
+    let body: Value = client.get(format!("{}/api/devices-enhanced/meter-1", base_url)).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(body["data"]["device"]["model_id"], model_id.as_str());
+    let mut tags: Vec<(String, u64, i64, String, String)> = body["data"]["tags"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|tag| {
+            (
+                tag["name"].as_str().unwrap().to_string(),
+                tag["address"].as_u64().unwrap(),
+                tag["size"].as_i64().unwrap(),
+                tag["unit"].as_str().unwrap_or_default().to_string(),
+                tag["schedule_group_id"].as_str().unwrap_or_default().to_string(),
+            )
+        })
+        .collect();
+    tags.sort();
+    assert_eq!(tags, [
+        ("Energy".to_string(), 1100, 4, "kWh".to_string(), "low_freq".to_string()),
+        ("Power".to_string(), 1104, 2, "kW".to_string(), "low_freq".to_string()),
+        ("Status".to_string(), 1110, 1, "".to_string(), "low_freq".to_string()),
+    ]);
+
+    // Every problem with the request is listed
+    let iec104 = json!({"type": "iec104", "host": "127.0.0.1", "port": 2404});
+    let (_, body) = create(device("meter-2", iec104, json!({"address_offset": 65430, "schedule_group_id": "hourly"}))).await?;
+    assert_eq!(body["success"], false);
+    assert_eq!(body["field_errors"], json!([
+        {"field": "protocol_config.type", "message": "must be modbus_tcp to match model 'Meter'"},
+        {"field": "schedule_group_id", "message": "no schedule group 'hourly'"},
+        {"field": "address_offset", "message": "moves tag 'Status' to address 65540, outside 0-65535"},
+    ]));
+
+    let (status, _) = create(device("meter-3", modbus, json!({"model_id": "no-such-model"}))).await?;
+    assert_eq!(status, 404);
+    Ok(())
+}
@@ -0,0 +1,185 @@
+mod support;
+
+use ava_device_logger::database::{Database, DeviceInstance, DeviceTag, ModelDependentDevice, TagTemplate, TagWritePolicy};
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::error::Error;
+use support::Logger;
+
+fn device(id: &str, name: &str, model_id: &str) -> DeviceInstance {
+    DeviceInstance {
+        id: id.to_string(),
+        name: name.to_string(),
+        serial_no: None,
+        model_id: Some(model_id.to_string()),
+        enabled: false,
+        polling_interval_ms: 1000,
+        timeout_ms: 5000,
+        retry_count: 3,
+        protocol_config: "{}".to_string(),
+        tb_device_id: None,
+        tb_group_id: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    }
+}
+
+fn tag(device_id: &str) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: device_id.to_string(),
+        name: "Power".to_string(),
+        address: 100,
+        size: 2,
+        data_type: "float32".to_string(),
+        description: None,
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: None,
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy: TagWritePolicy::Disabled,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+/// A database with one model, one template, and the given devices built from it
+async fn model_with_devices(devices: &[(&str, &str)]) -> Result<(Database, String, std::path::PathBuf), Box<dyn Error>> {
+    let db_path = std::env::temp_dir().join(format!("model-delete-{}.db", uuid::Uuid::new_v4()));
+    let db = Database::new(&db_path.to_string_lossy()).await?;
+    let model = db.create_device_model("Inverter", None, "modbus_tcp", None).await?;
+    db.create_tag_template(&TagTemplate {
+        id: None,
+        model_id: model.id.clone(),
+        name: "Power".to_string(),
+        address: 100,
+        size: 2,
+        data_type: "float32".to_string(),
+        description: None,
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: None,
+        read_only: true,
+    })
+    .await?;
+    for (id, name) in devices {
+        db.create_device(&device(id, name, &model.id)).await?;
+        db.create_device_tags(id, &[tag(id)]).await?;
+    }
+    Ok((db, model.id, db_path))
+}
+
+#[tokio::test]
+async fn test_model_in_use_is_not_deleted() -> Result<(), Box<dyn Error>> {
+    let (db, model_id, db_path) = model_with_devices(&[("inv-2", "Inverter B"), ("inv-1", "Inverter A")]).await?;
+
+    let deletion = db.delete_device_model(&model_id, false).await?;
+    assert!(!deletion.deleted);
+    assert_eq!(deletion.dependent_devices, [
+        ModelDependentDevice { id: "inv-1".to_string(), name: "Inverter A".to_string() },
+        ModelDependentDevice { id: "inv-2".to_string(), name: "Inverter B".to_string() },
+    ]);
+
+    assert!(db.get_device_model(&model_id).await?.is_some());
+    assert_eq!(db.get_tag_templates(&model_id).await?.len(), 1);
+    assert_eq!(db.get_device_tags("inv-1").await?.len(), 1);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_model_with_only_templates_is_deleted() -> Result<(), Box<dyn Error>> {
+    let (db, model_id, db_path) = model_with_devices(&[]).await?;
+
+    let deletion = db.delete_device_model(&model_id, false).await?;
+    assert!(deletion.deleted);
+    assert!(deletion.dependent_devices.is_empty());
+    assert!(db.get_device_model(&model_id).await?.is_none());
+    assert!(db.get_tag_templates(&model_id).await?.is_empty());
+    assert!(db.delete_device_model(&model_id, false).await.is_err(), "deleted model was still found");
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_forced_deletion_removes_devices_and_their_tags() -> Result<(), Box<dyn Error>> {
+    let (db, model_id, db_path) = model_with_devices(&[("inv-1", "Inverter A")]).await?;
+    let other_model = db.create_device_model("Meter", None, "modbus_tcp", None).await?;
+    db.create_device(&device("meter-1", "Meter", &other_model.id)).await?;
+    db.create_device_tags("meter-1", &[tag("meter-1")]).await?;
+
+    let deletion = db.delete_device_model(&model_id, true).await?;
+    assert!(deletion.deleted);
+    assert_eq!(deletion.dependent_devices.len(), 1);
+    assert!(db.get_device_model(&model_id).await?.is_none());
+    assert!(db.get_device_tags("inv-1").await?.is_empty());
+    assert_eq!(db.get_device_tags("meter-1").await?.len(), 1);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_delete_endpoint_lists_dependent_devices_until_forced() -> Result<(), Box<dyn Error>> {
+
+    let logger = Logger::start("").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let body: Value = client
+        .post(format!("{}/api/device-models", base_url))
+        .bearer_auth(token)
+        .header("Content-Type", "multipart/form-data; boundary=model-delete-test")
+        .body(
+            "--model-delete-test\r\nContent-Disposition: form-data; name=\"name\"\r\n\r\nInverter\r\n\
+             --model-delete-test\r\nContent-Disposition: form-data; name=\"protocol_type\"\r\n\r\nmodbus_tcp\r\n\
+             --model-delete-test--\r\n",
+        )
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let model_id = body["data"]["id"].as_str().unwrap().to_string();
+
+    let body: Value = client
+        .post(format!("{}/api/devices-enhanced/from-model", base_url))
+        .bearer_auth(token)
+        .json(&json!({
+            "id": "inv-1", "name": "Inverter A", "model_id": model_id, "enabled": false, "polling_interval_ms": 1000,
+            "timeout_ms": 1000, "retry_count": 1,
+            "protocol_config": {"type": "modbus_tcp", "host": "127.0.0.1", "port": 502, "slave_id": 1},
+        }))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["success"], true, "{}", body);
+
+    let delete = |force: bool| {
+        let request = client
+            .post(format!("{}/api/device-models/{}/delete", base_url, model_id))
+            .query(&[("force", force)])
+            .bearer_auth(token);
+        async move { request.send().await?.json::<Value>().await }
+    };
+    let body = delete(false).await?;
+    assert_eq!(body["success"], false);
+    assert_eq!(body["code"], "conflict");
+    assert_eq!(body["error"], "Device model is used by 1 device(s): Inverter A (inv-1). Pass force=true to delete them as well");
+    assert_eq!(body["details"], json!({"deleted": false, "dependent_devices": [{"id": "inv-1", "name": "Inverter A"}]}));
+
+    let body = delete(true).await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!(body["data"]["deleted"], true);
+    let status = client.get(format!("{}/api/devices-enhanced/inv-1", base_url)).bearer_auth(token).send().await?.status();
+    assert_eq!(status.as_u16(), 404);
+    Ok(())
+}
@@ -0,0 +1,124 @@
+mod support;
+
+use ava_device_logger::database::{Database, DeviceInstance, DeviceTag, TagWritePolicy};
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::time::{Duration, Instant};
+use support::{Logger, ModbusDevice};
+
+/// First addresses of the last `count` reads `device` answered
+fn last_reads(device: &ModbusDevice, count: usize) -> Vec<u16> {
+    let reads: Vec<u16> = device.requests().iter().map(|request| request.address).collect();
+    reads[reads.len().saturating_sub(count)..].to_vec()
+}
+
+const POLLING_INTERVAL_MS: u32 = 500;
+
+fn device(id: &str, modbus_port: u16) -> DeviceInstance {
+    DeviceInstance {
+        id: id.to_string(),
+        name: id.to_string(),
+        serial_no: None,
+        model_id: None,
+        enabled: true,
+        polling_interval_ms: POLLING_INTERVAL_MS,
+        timeout_ms: 500,
+        retry_count: 3,
+        protocol_config: json!({"type": "modbus_tcp", "host": "127.0.0.1", "port": modbus_port, "slave_id": 1}).to_string(),
+        tb_device_id: None,
+        tb_group_id: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    }
+}
+
+fn tag(device_id: &str, address: u16) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: device_id.to_string(),
+        name: "Voltage".to_string(),
+        address,
+        size: 1,
+        data_type: "uint16".to_string(),
+        description: None,
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: None,
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy: TagWritePolicy::Disabled,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+/// Update request for a device with its single tag at `address`
+fn update_request(device_id: &str, modbus_port: u16, address: u16) -> Value {
+    json!({
+        "id": device_id, "name": device_id, "serial_no": null, "model_id": null, "enabled": true,
+        "polling_interval_ms": POLLING_INTERVAL_MS, "timeout_ms": 500, "retry_count": 3,
+        "protocol_config": {"type": "modbus_tcp", "host": "127.0.0.1", "port": modbus_port, "slave_id": 1},
+        "tags": [{
+            "name": "Voltage", "address": address, "size": 1, "data_type": "uint16", "description": null,
+            "scaling_multiplier": 1.0, "scaling_offset": 0.0, "unit": null, "read_only": true, "enabled": true,
+            "schedule_group_id": null, "agg_to_field": null,
+        }],
+    })
+}
+
+#[tokio::test]
+async fn test_updating_a_running_device_reloads_only_that_device() -> Result<(), Box<dyn Error>> {
+    // Every register holds its own address
+    let edited = ModbusDevice::start([(100, 100), (200, 200)]).await?;
+    let untouched = ModbusDevice::start([(300, 300), (400, 400)]).await?;
+    let (edited_port, untouched_port) = (edited.port(), untouched.port());
+    let work_dir = support::work_dir("device-reload")?;
+
+    let db = Database::new(&work_dir.join("data.db").to_string_lossy()).await?;
+    db.create_device(&device("meter-1", edited_port)).await?;
+    db.create_device_tags("meter-1", &[tag("meter-1", 100)]).await?;
+    db.create_device(&device("meter-2", untouched_port)).await?;
+    db.create_device_tags("meter-2", &[tag("meter-2", 300)]).await?;
+
+    let logger = Logger::start_in(work_dir, "").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+    tokio::time::sleep(Duration::from_millis(1200)).await;
+    assert_eq!(last_reads(&edited, 2), [100, 100]);
+    let untouched_reads = untouched.requests().len();
+    assert!(untouched_reads >= 2, "{}", untouched_reads);
+
+    // The new address is read within one polling cycle of the update
+    let body: Value = client.put(format!("{}/api/devices-enhanced/meter-1", base_url)).bearer_auth(token)
+        .json(&update_request("meter-1", edited_port, 200)).send().await?.json().await?;
+    let tags = json!({"inserted": 0, "updated": 1, "deleted": 0, "unchanged": 0});
+    assert_eq!(body["data"], json!({"device_id": "meter-1", "reloaded": true, "tags": tags}), "{}", body);
+    let updated = Instant::now();
+    while last_reads(&edited, 1) != [200] {
+        assert!(updated.elapsed() < Duration::from_millis(POLLING_INTERVAL_MS as u64), "{:?}", edited.requests());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(1200)).await;
+    assert_eq!(last_reads(&edited, 2), [200, 200]);
+    let logged = db.get_log_entries(Some("meter-1"), Some(1), None).await?;
+    assert_eq!(logged.first().map(|entry| entry.value), Some(200.0));
+
+    // The other device kept its connection and kept polling throughout
+    assert_eq!(untouched.connections(), 1);
+    assert!(untouched.requests().len() >= untouched_reads + 2);
+    assert_eq!(last_reads(&untouched, 1), [300]);
+
+    // A stopped device picks the change up when it is next started
+    let body: Value = client.post(format!("{}/api/devices-enhanced/meter-2/stop", base_url)).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let body: Value = client.put(format!("{}/api/devices-enhanced/meter-2", base_url)).bearer_auth(token)
+        .json(&update_request("meter-2", untouched_port, 400)).send().await?.json().await?;
+    assert_eq!(body["data"], json!({"device_id": "meter-2", "reloaded": false, "tags": tags}), "{}", body);
+    assert_eq!(db.get_device_status("meter-2").await?.map(|status| status.status), Some("Stopped".to_string()));
+    Ok(())
+}
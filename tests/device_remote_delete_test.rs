@@ -0,0 +1,153 @@
+mod support;
+
+use ava_device_logger::database::{Database, DeviceInstance, TbChildDevice};
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::error::Error;
+use support::Logger;
+use wiremock::matchers::{method, path, path_regex};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// ThingsBoard stand-in where `tb-gone` was already deleted and deleting `tb-broken` fails
+async fn spawn_tb_server() -> MockServer {
+    let server = support::thingsboard().await;
+    Mock::given(method("DELETE")).and(path("/api/device/tb-gone")).respond_with(support::tb_not_found()).mount(&server).await;
+    let failure = ResponseTemplate::new(500).set_body_json(json!({"status": 500, "message": "Database is down"}));
+    Mock::given(method("DELETE")).and(path("/api/device/tb-broken")).respond_with(failure).mount(&server).await;
+    Mock::given(method("DELETE")).and(path_regex("^/api/device/[^/]+$")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+    server
+}
+
+/// Ids of the devices `tb` was asked to delete after the first `*seen`, moving `seen` past them
+async fn take_deleted(tb: &MockServer, seen: &mut usize) -> Vec<String> {
+    let deleted: Vec<String> = support::tb_requests(tb)
+        .await
+        .into_iter()
+        .filter_map(|(line, _)| line.strip_prefix("DELETE /api/device/").map(str::to_string))
+        .collect();
+    let new = deleted[*seen..].to_vec();
+    *seen = deleted.len();
+    new
+}
+
+fn device(id: &str, tb_device_id: Option<&str>) -> DeviceInstance {
+    DeviceInstance {
+        id: id.to_string(),
+        name: format!("Inverter {}", id),
+        serial_no: None,
+        model_id: None,
+        enabled: false,
+        polling_interval_ms: 1000,
+        timeout_ms: 1000,
+        retry_count: 1,
+        protocol_config: json!({"type": "modbus_tcp", "host": "127.0.0.1", "port": 502, "slave_id": 1}).to_string(),
+        tb_device_id: tb_device_id.map(str::to_string),
+        tb_group_id: tb_device_id.map(|_| "group-1".to_string()),
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    }
+}
+
+fn child(device_id: &str, tb_device_id: &str, device_type: &str, input_number: Option<u32>) -> TbChildDevice {
+    TbChildDevice {
+        device_id: device_id.to_string(),
+        tb_device_id: tb_device_id.to_string(),
+        name: format!("{}-{}", device_id, tb_device_id),
+        device_type: device_type.to_string(),
+        mppt_number: 1,
+        input_number,
+    }
+}
+
+/// ThingsBoard ids of one list of a remote deletion report
+fn ids(report: &Value, list: &str) -> Vec<String> {
+    report[list]
+        .as_array()
+        .unwrap_or_else(|| panic!("no {} in {}", list, report))
+        .iter()
+        .map(|entity| entity["tb_device_id"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_delete_device_from_thingsboard() -> Result<(), Box<dyn Error>> {
+    let tb = spawn_tb_server().await;
+    let tb_url = tb.uri();
+
+    let work_dir = support::work_dir("device-remote-delete")?;
+    let db_path = work_dir.join("data.db").to_string_lossy().to_string();
+    let db = Database::new(&db_path).await?;
+    db.create_device(&device("inv-1", Some("tb-inv-1"))).await?;
+    db.save_tb_child_devices("inv-1", &[
+        child("inv-1", "tb-m01", "Mppt", None),
+        child("inv-1", "tb-s0101", "String", Some(1)),
+        child("inv-1", "tb-gone", "String", Some(2)),
+    ]).await?;
+    db.create_device(&device("inv-2", Some("tb-inv-2"))).await?;
+    db.save_tb_child_devices("inv-2", &[child("inv-2", "tb-broken", "Mppt", None)]).await?;
+    db.create_device(&device("local-only", None)).await?;
+    db.create_device(&device("inv-3", Some("tb-inv-3"))).await?;
+
+    let extra_config = format!(
+        r#"
+[thingsboard]
+base_url = "{tb_url}"
+username = "tenant@example.com"
+password = "secret"
+retry_max_attempts = 1
+"#
+    );
+    let logger = Logger::start_in(work_dir, &extra_config).await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+    let delete = |device_id: &str, query: &str| {
+        client.delete(format!("{}/api/devices-enhanced/{}?{}", base_url, device_id, query)).bearer_auth(token).send()
+    };
+    let mut deleted_seen = 0;
+
+    // Strings and MPPTs go before their inverter; one already gone is skipped
+    let response = delete("inv-1", "delete_remote=true").await?;
+    assert_eq!(response.status(), 200);
+    let body: Value = response.json().await?;
+    let remote = &body["data"]["remote"];
+    assert_eq!(ids(remote, "removed"), ["tb-s0101", "tb-m01", "tb-inv-1"], "{}", body);
+    assert_eq!(ids(remote, "skipped"), ["tb-gone"], "{}", body);
+    assert_eq!(ids(remote, "failed"), Vec::<String>::new(), "{}", body);
+    assert_eq!(remote["removed"][2]["device_type"], Value::Null);
+    assert_eq!(take_deleted(&tb, &mut deleted_seen).await, ["tb-s0101", "tb-gone", "tb-m01", "tb-inv-1"]);
+    assert!(db.get_device("inv-1").await?.is_none());
+    assert!(db.get_tb_child_devices("inv-1").await?.is_empty());
+
+    // A failure keeps the device, and the inverter isn't tried while its MPPT remains
+    let response = delete("inv-2", "delete_remote=true").await?;
+    assert_eq!(response.status(), 502);
+    let body: Value = response.json().await?;
+    assert_eq!(ids(&body["details"], "failed"), ["tb-broken", "tb-inv-2"], "{}", body);
+    assert!(body["error"].as_str().unwrap().contains("force_local=true"), "{}", body);
+    assert_eq!(take_deleted(&tb, &mut deleted_seen).await, ["tb-broken"]);
+    assert!(db.get_device("inv-2").await?.is_some());
+
+    // Forced, the device is deleted locally and the ThingsBoard devices left behind are audited
+    let response = delete("inv-2", "delete_remote=true&force_local=true").await?;
+    assert_eq!(response.status(), 200);
+    let body: Value = response.json().await?;
+    assert_eq!(ids(&body["data"]["remote"], "failed"), ["tb-broken", "tb-inv-2"], "{}", body);
+    assert!(db.get_device("inv-2").await?.is_none());
+    for tb_device_id in ["tb-broken", "tb-inv-2"] {
+        let audit: Value = client.get(format!("{}/api/audit?entity_id={}", base_url, tb_device_id)).bearer_auth(token).send().await?.json().await?;
+        assert_eq!(audit["data"]["entries"][0]["action"], "thingsboard.orphan", "{}", audit);
+        assert_eq!(audit["data"]["entries"][0]["after"]["device_id"], "inv-2", "{}", audit);
+    }
+    take_deleted(&tb, &mut deleted_seen).await;
+
+    // A device never synced has nothing to delete remotely
+    let body: Value = delete("local-only", "delete_remote=true").await?.json().await?;
+    assert_eq!(body["data"]["remote"], json!({"removed": [], "skipped": [], "failed": []}), "{}", body);
+
+    // Without delete_remote ThingsBoard isn't asked at all
+    let body: Value = delete("inv-3", "").await?.json().await?;
+    assert_eq!(body["data"], json!({"device_id": "inv-3", "remote": null}), "{}", body);
+    assert!(take_deleted(&tb, &mut deleted_seen).await.is_empty());
+    assert!(db.get_device("inv-3").await?.is_none());
+    Ok(())
+}
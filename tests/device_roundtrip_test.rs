@@ -0,0 +1,85 @@
+mod support;
+
+use ava_device_logger::database::Database;
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::error::Error;
+use support::Logger;
+
+fn keys(value: &Value) -> BTreeSet<String> {
+    value.as_object().expect("object").keys().cloned().collect()
+}
+
+/// Every field of `sent` is stored unchanged, and `stored` has no fields besides those and `extra`
+fn assert_round_trip(sent: &Value, stored: &Value, extra: &[&str]) {
+    for (key, value) in sent.as_object().expect("object") {
+        assert_eq!(&stored[key], value, "field {} was not stored as sent", key);
+    }
+    let mut expected = keys(sent);
+    expected.extend(extra.iter().map(|key| key.to_string()));
+    assert_eq!(keys(stored), expected, "fields added to the model need to be sent by this test");
+}
+
+#[tokio::test]
+async fn test_device_and_tag_fields_survive_the_database() -> Result<(), Box<dyn Error>> {
+    let work_dir = support::work_dir("device-roundtrip")?;
+
+    // Opened before the server so every read below sees what the API stored
+    let db = Database::new(&work_dir.join("data.db").to_string_lossy()).await?;
+    let model = db.create_device_model("SG125CX", Some("Sungrow"), "modbus_tcp", None).await?;
+
+    let logger = Logger::start_in(work_dir, "").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    // No field is left at its default, so a field the queries drop can't pass unnoticed
+    let tag = json!({
+        "name": "Power", "address": 100, "size": 2, "data_type": "float32", "description": "Active power",
+        "scaling_multiplier": 0.5, "scaling_offset": 1.5, "unit": "kW", "read_only": false, "enabled": false,
+        "schedule_group_id": "high_freq", "agg_to_field": "total_power", "write_policy": "admin_only",
+        "byte_order": "CDAB", "deadband_absolute": 0.25, "deadband_percent": 2.5, "register_type": "input",
+    });
+    let device = json!({
+        "id": "inv-1", "name": "Inverter 1", "serial_no": "SN-1", "model_id": model.id, "enabled": false,
+        "polling_interval_ms": 2500, "timeout_ms": 1500, "retry_count": 4, "strict_types": true,
+    });
+    let protocol_config = json!({"type": "modbus_tcp", "host": "10.0.0.10", "port": 1502, "slave_id": 7});
+    let request = |device: &Value, tag: &Value| {
+        let mut request = device.clone();
+        request["protocol_config"] = protocol_config.clone();
+        request["tags"] = json!([tag]);
+        request
+    };
+
+    let body: Value = client.post(format!("{}/api/devices-enhanced", base_url)).bearer_auth(token).json(&request(&device, &tag)).send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+
+    let stored = db.get_device("inv-1").await?.expect("device stored");
+    assert_round_trip(&device, &serde_json::to_value(&stored)?, &["protocol_config", "tb_device_id", "tb_group_id", "created_at", "updated_at"]);
+    assert_eq!(serde_json::from_str::<Value>(&stored.protocol_config)?["slave_id"], 7);
+    let tags = db.get_device_tags("inv-1").await?;
+    assert_eq!(tags.len(), 1);
+    assert_round_trip(&tag, &serde_json::to_value(&tags[0])?, &["id", "device_id"]);
+
+    // Updates store every field too
+    let device = json!({
+        "id": "inv-1", "name": "Inverter 1b", "serial_no": "SN-2", "model_id": model.id, "enabled": false,
+        "polling_interval_ms": 3000, "timeout_ms": 2000, "retry_count": 2, "strict_types": false,
+    });
+    let tag = json!({
+        "name": "Energy", "address": 200, "size": 4, "data_type": "float64", "description": "Total energy",
+        "scaling_multiplier": 0.25, "scaling_offset": 0.0, "unit": "kWh", "read_only": true, "enabled": true,
+        "schedule_group_id": "low_freq", "agg_to_field": "total_energy", "write_policy": "any_authenticated",
+        "byte_order": "DCBA", "deadband_absolute": 1.0, "deadband_percent": 0.5, "register_type": "holding",
+    });
+    let body: Value = client.put(format!("{}/api/devices-enhanced/inv-1", base_url)).bearer_auth(token).json(&request(&device, &tag)).send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+
+    let stored = db.get_device("inv-1").await?.expect("device stored");
+    assert_round_trip(&device, &serde_json::to_value(&stored)?, &["protocol_config", "tb_device_id", "tb_group_id", "created_at", "updated_at"]);
+    let tags = db.get_device_tags("inv-1").await?;
+    assert_eq!(tags.len(), 1);
+    assert_round_trip(&tag, &serde_json::to_value(&tags[0])?, &["id", "device_id"]);
+
+    drop(db);
+    Ok(())
+}
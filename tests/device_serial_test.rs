@@ -0,0 +1,184 @@
+mod support;
+
+use ava_device_logger::config::SerialEncoding;
+use ava_device_logger::database::{Database, DeviceInstance};
+use ava_device_logger::modbus::decode_serial;
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::time::Duration;
+use support::{Logger, ModbusDevice};
+use wiremock::matchers::{method, path, path_regex};
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+/// Registers of a string, two characters per register, padded with NULs to `length`
+fn ascii_registers(text: &str, length: usize) -> Vec<u16> {
+    let mut bytes = text.as_bytes().to_vec();
+    bytes.resize(length * 2, 0);
+    bytes.chunks(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect()
+}
+
+#[test]
+fn test_serials_decode_from_ascii_and_packed_bcd() {
+    assert_eq!(decode_serial(SerialEncoding::Ascii, &ascii_registers(" A2203 ", 8)).unwrap(), "A2203");
+    assert_eq!(decode_serial(SerialEncoding::Bcd, &[0x0012, 0x3456]).unwrap(), "00123456");
+    assert!(decode_serial(SerialEncoding::Bcd, &[0x12AB]).unwrap_err().to_string().contains("0x12AB is not packed BCD"));
+    assert!(decode_serial(SerialEncoding::Ascii, &[0, 0]).is_err());
+    assert!(decode_serial(SerialEncoding::Ascii, &[0x4101]).is_err());
+}
+
+/// ThingsBoard stand-in for group `group-1` where every device exists
+async fn spawn_tb_server() -> MockServer {
+    let server = support::thingsboard().await;
+    let groups = json!([{"id": {"id": "group-1", "entityType": "ENTITY_GROUP"}, "ownerId": {"id": "tenant", "entityType": "TENANT"}, "name": "ACCV-P002-Plant", "type": "DEVICE", "groupAll": false, "edgeGroupAll": false}]);
+    Mock::given(method("GET")).and(path("/api/entityGroups/DEVICE")).respond_with(ResponseTemplate::new(200).set_body_json(groups)).mount(&server).await;
+    let devices = json!({"data": [], "totalPages": 1, "totalElements": 0, "hasNext": false});
+    Mock::given(method("GET")).and(path("/api/entityGroup/group-1/devices")).respond_with(ResponseTemplate::new(200).set_body_json(devices)).mount(&server).await;
+    let get_device = |request: &Request| {
+        let id = request.url.path().trim_start_matches("/api/device/");
+        ResponseTemplate::new(200).set_body_json(json!({"id": {"id": id, "entityType": "DEVICE"}, "name": "ACCV-P002-I01", "type": "Inverter", "label": ""}))
+    };
+    Mock::given(method("GET")).and(path_regex("^/api/device/[^/]+$")).respond_with(get_device).mount(&server).await;
+    Mock::given(method("POST")).and(path_regex("^/api/plugins/telemetry/")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+    server
+}
+
+/// `(tb_device_id, attributes)` of the attribute pushes `tb` answered after the first `*seen`,
+/// moving `seen` past them
+async fn take_attributes(tb: &MockServer, seen: &mut usize) -> Vec<(String, Value)> {
+    let pushes: Vec<(String, Value)> = support::tb_requests(tb)
+        .await
+        .into_iter()
+        .filter_map(|(line, body)| {
+            let tb_device_id = line.strip_prefix("POST /api/plugins/telemetry/")?.split('/').next()?.to_string();
+            Some((tb_device_id, body))
+        })
+        .collect();
+    let new = pushes[*seen..].to_vec();
+    *seen = pushes.len();
+    new
+}
+
+fn device(id: &str, serial_no: Option<&str>, protocol_config: Value, tb_device_id: Option<&str>) -> DeviceInstance {
+    DeviceInstance {
+        id: id.to_string(),
+        name: id.to_string(),
+        serial_no: serial_no.map(str::to_string),
+        model_id: None,
+        enabled: false,
+        polling_interval_ms: 1000,
+        timeout_ms: 1000,
+        retry_count: 1,
+        protocol_config: protocol_config.to_string(),
+        tb_device_id: tb_device_id.map(str::to_string),
+        tb_group_id: tb_device_id.map(|_| "group-1".to_string()),
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    }
+}
+
+#[tokio::test]
+async fn test_serial_numbers_are_read_from_devices_and_checked_by_syncs() -> Result<(), Box<dyn Error>> {
+    let slave = ModbusDevice::start((4990..).zip(ascii_registers("SG110CX-A2203", 10))).await?;
+    slave.set(6000, 0x0012);
+    slave.set(6001, 0x3456);
+    let modbus_port = slave.port();
+    let tb = spawn_tb_server().await;
+    let tb_url = tb.uri();
+
+    let modbus = |serial_source: Value| json!({"type": "modbus_tcp", "host": "127.0.0.1", "port": modbus_port, "slave_id": 1, "serial_source": serial_source});
+    let sungrow = json!({"address": 4990, "length": 10});
+    let work_dir = support::work_dir("device-serial")?;
+    let db = Database::new(&work_dir.join("data.db").to_string_lossy()).await?;
+    db.create_device(&device("inv-typo", Some("SG110CX-A2208"), modbus(sungrow.clone()), Some("tb-typo"))).await?;
+    db.create_device(&device("inv-ok", Some(" sg110cx-a2203"), modbus(sungrow.clone()), Some("tb-ok"))).await?;
+    db.create_device(&device("inv-plain", Some("ANY"), json!({"type": "modbus_tcp", "host": "127.0.0.1", "port": modbus_port, "slave_id": 1}), Some("tb-plain"))).await?;
+    db.create_device(&device("meter-bcd", None, modbus(json!({"address": 6000, "length": 2, "encoding": "bcd"})), None)).await?;
+
+    let extra_config = format!(
+        r#"
+[thingsboard]
+base_url = "{tb_url}"
+username = "tenant@example.com"
+password = "secret"
+retry_max_attempts = 1
+"#
+    );
+    let logger = Logger::start_in(work_dir, &extra_config).await?;
+    let mut attributes_seen = 0;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+    let read_serial = |device_id: &str, apply: bool| {
+        client
+            .post(format!("{}/api/devices-enhanced/{}/read-serial?apply={}", base_url, device_id, apply))
+            .bearer_auth(token)
+            .send()
+    };
+
+    // Serial sources are checked like the rest of the protocol config
+    let body: Value = client.post(format!("{}/api/devices-enhanced", base_url)).bearer_auth(token)
+        .json(&json!({
+            "id": "inv-new", "name": "inv-new", "serial_no": null, "model_id": null, "enabled": false,
+            "polling_interval_ms": 1000, "timeout_ms": 1000, "retry_count": 1,
+            "protocol_config": modbus(json!({"address": 4990, "length": 0, "encoding": "ascii"})), "tags": [],
+        }))
+        .send().await?.json().await?;
+    assert_eq!(body["field_errors"][0]["field"], "serial_source", "{}", body);
+
+    // Reading reports the mismatch without changing anything
+    let body: Value = read_serial("inv-typo", false).await?.json().await?;
+    assert_eq!(body["data"]["device_serial_no"], "SG110CX-A2203", "{}", body);
+    assert_eq!(body["data"]["configured_serial_no"], "SG110CX-A2208");
+    assert_eq!((body["data"]["matches"].clone(), body["data"]["applied"].clone()), (json!(false), json!(false)));
+    let body: Value = read_serial("inv-ok", true).await?.json().await?;
+    assert_eq!((body["data"]["matches"].clone(), body["data"]["applied"].clone()), (json!(true), json!(false)), "{}", body);
+    let body: Value = read_serial("inv-plain", false).await?.json().await?;
+    assert_eq!(body["success"], false);
+    assert!(body["error"].as_str().unwrap_or_default().contains("no serial_source"), "{}", body);
+    assert_eq!(read_serial("missing", false).await?.status(), 404);
+    assert!(take_attributes(&tb, &mut attributes_seen).await.is_empty());
+
+    // A sync verifying serials leaves out the device with the mistyped serial
+    let sync = |body: Value| {
+        let (client, base_url, token) = (client.clone(), base_url.clone(), token.clone());
+        async move {
+            let job: Value = client.post(format!("{}/api/sync-devices-to-thingsboard", base_url)).bearer_auth(&token).json(&body).send().await?.json().await?;
+            let job_id = job["data"]["id"].as_str().expect("job id").to_string();
+            loop {
+                let job: Value = client.get(format!("{}/api/jobs/{}", base_url, job_id)).bearer_auth(&token).send().await?.json().await?;
+                if job["data"]["state"] == "completed" {
+                    return Ok::<Value, reqwest::Error>(job["data"]["result"].clone());
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    };
+    let result = sync(json!({"entity_group_id": "group-1", "mode": "refresh_attributes", "verify_serials": true})).await?;
+    let outcomes: Vec<(&str, &str)> = result["outcomes"].as_array().expect("outcomes").iter()
+        .map(|outcome| (outcome["local_id"].as_str().unwrap(), outcome["outcome"].as_str().unwrap()))
+        .collect();
+    assert_eq!(outcomes, [("inv-typo", "serial_mismatch"), ("inv-ok", "attributes_refreshed"), ("inv-plain", "attributes_refreshed")], "{}", result);
+    assert_eq!(result["failed_count"], 1);
+    assert_eq!(result["failed_devices"][0]["error"], "configured serial number SG110CX-A2208 doesn't match SG110CX-A2203 read from the device");
+    let pushed: Vec<String> = take_attributes(&tb, &mut attributes_seen).await.into_iter().map(|(tb_device_id, _)| tb_device_id).collect();
+    assert_eq!(pushed, ["tb-ok", "tb-plain"]);
+
+    // Applying stores the device's serial and pushes it to ThingsBoard
+    let body: Value = read_serial("inv-typo", true).await?.json().await?;
+    assert_eq!((body["data"]["applied"].clone(), body["data"]["thingsboard_updated"].clone()), (json!(true), json!(true)), "{}", body);
+    assert_eq!(take_attributes(&tb, &mut attributes_seen).await, [("tb-typo".to_string(), json!({"SN": "SG110CX-A2203"}))]);
+    assert_eq!(db.get_device("inv-typo").await?.and_then(|device| device.serial_no).as_deref(), Some("SG110CX-A2203"));
+    let audit: Value = client.get(format!("{}/api/audit?entity_id=inv-typo", base_url)).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(audit["data"]["entries"][0]["action"], "device.serial_no", "{}", audit);
+    assert_eq!(audit["data"]["entries"][0]["after"], json!({"serial_no": "SG110CX-A2203"}), "{}", audit);
+
+    let result = sync(json!({"entity_group_id": "group-1", "mode": "refresh_attributes", "verify_serials": true})).await?;
+    assert_eq!(result["failed_count"], 0, "{}", result);
+
+    // BCD serials keep their leading zeros; an unsynced device has no ThingsBoard attribute to update
+    let body: Value = read_serial("meter-bcd", true).await?.json().await?;
+    assert_eq!(body["data"]["device_serial_no"], "00123456", "{}", body);
+    assert_eq!((body["data"]["applied"].clone(), body["data"]["thingsboard_updated"].clone()), (json!(true), json!(false)));
+    assert_eq!(db.get_device("meter-bcd").await?.and_then(|device| device.serial_no).as_deref(), Some("00123456"));
+    Ok(())
+}
@@ -0,0 +1,133 @@
+mod support;
+
+use serde_json::{json, Value};
+use std::error::Error;
+use support::Logger;
+
+fn tag(name: &str, address: u16) -> Value {
+    json!({
+        "name": name, "address": address, "size": 1, "data_type": "uint16", "description": null,
+        "scaling_multiplier": 1.0, "scaling_offset": 0.0, "unit": null, "read_only": true, "enabled": true,
+        "schedule_group_id": null, "agg_to_field": null,
+    })
+}
+
+fn device(tags: Value) -> Value {
+    json!({
+        "id": "inv-1", "name": "Inverter 1", "enabled": false, "polling_interval_ms": 2000, "timeout_ms": 1000, "retry_count": 1,
+        "protocol_config": {"type": "modbus_tcp", "host": "10.0.0.10", "port": 502, "slave_id": 1}, "tags": tags,
+    })
+}
+
+/// Tag ids of the device, by name
+async fn tag_ids(client: &reqwest::Client, base_url: &str, token: &str) -> Result<Vec<(String, i64)>, Box<dyn Error>> {
+    let body: Value = client.get(format!("{}/api/devices/inv-1/tags", base_url)).bearer_auth(token).send().await?.json().await?;
+    Ok(body["data"]
+        .as_array()
+        .expect("tags")
+        .iter()
+        .map(|tag| (tag["name"].as_str().unwrap().to_string(), tag["id"].as_i64().unwrap()))
+        .collect())
+}
+
+#[tokio::test]
+async fn test_saving_a_device_keeps_the_ids_of_its_tags() -> Result<(), Box<dyn Error>> {
+    let logger = Logger::start("").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let body: Value = client.post(format!("{}/api/devices-enhanced", base_url)).bearer_auth(token)
+        .json(&device(json!([tag("Power", 0), tag("Voltage", 1), tag("Current", 2)])))
+        .send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let before = tag_ids(client, base_url, token).await?;
+
+    // Power unchanged, Voltage moved, Current dropped and Frequency added
+    let body: Value = client.put(format!("{}/api/devices-enhanced/inv-1", base_url)).bearer_auth(token)
+        .json(&device(json!([tag("Power", 0), tag("Voltage", 5), tag("Frequency", 3)])))
+        .send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!(body["data"]["reloaded"], false);
+    assert_eq!(body["data"]["tags"], json!({"inserted": 1, "updated": 1, "deleted": 1, "unchanged": 1}));
+
+    let after = tag_ids(client, base_url, token).await?;
+    let id_of = |tags: &[(String, i64)], name: &str| tags.iter().find(|(tag, _)| tag == name).map(|(_, id)| *id);
+    assert_eq!(id_of(&after, "Power"), id_of(&before, "Power"));
+    assert_eq!(id_of(&after, "Voltage"), id_of(&before, "Voltage"));
+    assert_eq!(id_of(&after, "Current"), None);
+    assert!(id_of(&after, "Frequency").is_some());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_single_tags_are_added_changed_and_removed_in_place() -> Result<(), Box<dyn Error>> {
+    let logger = Logger::start("").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let body: Value = client.post(format!("{}/api/devices-enhanced", base_url)).bearer_auth(token)
+        .json(&device(json!([tag("Power", 0), tag("Voltage", 1)])))
+        .send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let body: Value = client.post(format!("{}/api/schedule-groups", base_url)).bearer_auth(token)
+        .json(&json!({"id": "slow", "name": "Slow", "polling_interval_ms": 60000, "description": null, "enabled": true}))
+        .send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let ids = tag_ids(client, base_url, token).await?;
+    let power = ids.iter().find(|(name, _)| name == "Power").unwrap().1;
+    let tag_url = |id: i64| format!("{}/api/devices/inv-1/tags/{}", base_url, id);
+
+    // Only the fields sent change
+    let body: Value = client.patch(tag_url(power)).bearer_auth(token)
+        .json(&json!({"enabled": false, "schedule_group_id": "slow", "scaling_multiplier": 0.1, "description": "AC power"}))
+        .send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!(body["data"]["reloaded"], false);
+    let patched = &body["data"]["tag"];
+    assert_eq!((patched["id"].as_i64(), &patched["enabled"], &patched["schedule_group_id"]), (Some(power), &json!(false), &json!("slow")));
+    assert_eq!((&patched["scaling_multiplier"], &patched["scaling_offset"], &patched["address"]), (&json!(0.1), &json!(0.0), &json!(0)));
+    assert_eq!(patched["description"], "AC power");
+
+    // Empty strings clear the schedule group and description
+    let body: Value = client.patch(tag_url(power)).bearer_auth(token)
+        .json(&json!({"schedule_group_id": "", "description": ""}))
+        .send().await?.json().await?;
+    assert_eq!((&body["data"]["tag"]["schedule_group_id"], &body["data"]["tag"]["description"]), (&Value::Null, &Value::Null));
+    assert_eq!(body["data"]["tag"]["enabled"], false);
+
+    // Bad values, a missing schedule group and unknown tags are refused
+    let response = client.patch(tag_url(power)).bearer_auth(token).json(&json!({"schedule_group_id": "nope"})).send().await?;
+    assert_eq!(response.status(), 400);
+    let body: Value = response.json().await?;
+    assert_eq!(body["field_errors"][0]["field"], "schedule_group_id");
+    let response = client.patch(tag_url(9999)).bearer_auth(token).json(&json!({"enabled": true})).send().await?;
+    assert_eq!(response.status(), 404);
+
+    // A new tag is checked against the device's tags
+    let response = client.post(format!("{}/api/devices/inv-1/tags", base_url)).bearer_auth(token).json(&tag("Voltage", 7)).send().await?;
+    assert_eq!(response.status(), 400);
+    let body: Value = response.json().await?;
+    assert_eq!(body["field_errors"][0]["field"], "name", "{}", body);
+    let response = client.post(format!("{}/api/devices/inv-1/tags", base_url)).bearer_auth(token).json(&tag("Energy", 1)).send().await?;
+    assert_eq!(response.status(), 400);
+    let response = client.post(format!("{}/api/devices/no-such-device/tags", base_url)).bearer_auth(token).json(&tag("Energy", 9)).send().await?;
+    assert_eq!(response.status(), 404);
+    let body: Value = client.post(format!("{}/api/devices/inv-1/tags", base_url)).bearer_auth(token)
+        .json(&tag("Energy", 9))
+        .send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let energy = body["data"]["tag"]["id"].as_i64().expect("new tag id");
+
+    let body: Value = client.delete(tag_url(power)).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!(body["data"]["tags"]["deleted"], 1);
+    assert_eq!(client.delete(tag_url(power)).bearer_auth(token).send().await?.status(), 404);
+
+    let remaining = tag_ids(client, base_url, token).await?;
+    let voltage = ids.iter().find(|(name, _)| name == "Voltage").unwrap().1;
+    assert_eq!(remaining, [("Voltage".to_string(), voltage), ("Energy".to_string(), energy)]);
+
+    // Every change is in the audit log
+    let body: Value = client.get(format!("{}/api/audit?entity_id=inv-1", base_url)).bearer_auth(token).send().await?.json().await?;
+    let actions: Vec<&str> = body["data"]["entries"].as_array().unwrap().iter().map(|entry| entry["action"].as_str().unwrap()).collect();
+    assert_eq!(actions, ["tag.delete", "tag.create", "tag.update", "tag.update", "device.create"]);
+    Ok(())
+}
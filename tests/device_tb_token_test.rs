@@ -0,0 +1,101 @@
+mod support;
+
+use ava_device_logger::database::{Database, DeviceInstance};
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::error::Error;
+use support::Logger;
+
+fn device(id: &str, tb_device_id: Option<&str>) -> DeviceInstance {
+    DeviceInstance {
+        id: id.to_string(),
+        name: id.to_string(),
+        serial_no: None,
+        model_id: None,
+        enabled: false,
+        polling_interval_ms: 2000,
+        timeout_ms: 1000,
+        retry_count: 1,
+        protocol_config: json!({"type": "modbus_tcp", "host": "10.0.0.10", "port": 502, "slave_id": 1}).to_string(),
+        tb_device_id: tb_device_id.map(str::to_string),
+        tb_group_id: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    }
+}
+
+#[tokio::test]
+async fn test_cached_token_is_dropped_when_the_device_is_relinked() -> Result<(), Box<dyn Error>> {
+    let work_dir = support::work_dir("device-tb-token")?;
+    let db = Database::new(&work_dir.join("data.db").to_string_lossy()).await?;
+    db.create_device(&device("inv-1", Some("tb-1"))).await?;
+
+    assert_eq!(db.get_device_tb_access_token("inv-1").await?, Some(None));
+    assert_eq!(db.get_device_tb_access_token("no-such-device").await?, None);
+    assert!(db.set_device_tb_access_token("inv-1", "tb-1", "token-1").await?);
+    assert_eq!(db.get_device_tb_access_token("inv-1").await?, Some(Some("token-1".to_string())));
+
+    // A token fetched for a device it is no longer linked to isn't stored
+    assert!(!db.set_device_tb_access_token("inv-1", "tb-other", "token-2").await?);
+
+    // Editing the device keeps the token, linking it to another ThingsBoard device drops it
+    db.update_device(&DeviceInstance { name: "Inverter 1".to_string(), ..device("inv-1", Some("tb-1")) }).await?;
+    assert_eq!(db.get_device_tb_access_token("inv-1").await?, Some(Some("token-1".to_string())));
+    db.update_device_thingsboard_id("inv-1", "tb-2").await?;
+    assert_eq!(db.get_device_tb_access_token("inv-1").await?, Some(None));
+    assert!(db.set_device_tb_access_token("inv-1", "tb-2", "token-2").await?);
+    db.update_device(&device("inv-1", None)).await?;
+    assert_eq!(db.get_device_tb_access_token("inv-1").await?, Some(None));
+
+    let _ = std::fs::remove_dir_all(&work_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_token_is_only_returned_by_its_own_endpoint() -> Result<(), Box<dyn Error>> {
+    let logger = Logger::start("").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+    let installer = logger.login("installer", "installer123").await?;
+    let db = logger.database().await?;
+    db.create_device(&device("synced", Some("tb-1"))).await?;
+    db.create_device(&device("local", None)).await?;
+    let tb_token_url = |id: &str| format!("{}/api/devices-enhanced/{}/tb-token", base_url, id);
+
+    assert_eq!(client.get(tb_token_url("synced")).send().await?.status(), 401);
+
+    let response = client.get(tb_token_url("no-such-device")).bearer_auth(token).send().await?;
+    assert_eq!(response.status(), 404);
+    let response = client.get(tb_token_url("local")).bearer_auth(token).send().await?;
+    assert_eq!(response.status(), 409);
+    let body: Value = response.json().await?;
+    assert_eq!(body["error"], "Device local is not synced to ThingsBoard; sync it first to get its access token");
+
+    // Without a cached token ThingsBoard is asked, which isn't configured here
+    assert_eq!(client.get(tb_token_url("synced")).bearer_auth(token).send().await?.status(), 503);
+
+    db.set_device_tb_access_token("synced", "tb-1", "secret-token").await?;
+    let body: Value = client.get(tb_token_url("synced")).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(body["data"], json!({"device_id": "synced", "tb_device_id": "tb-1", "access_token": "secret-token", "cached": true}));
+    // Installers set up uplinks in the field, so they get it too
+    let body: Value = client.get(tb_token_url("synced")).bearer_auth(&installer).send().await?.json().await?;
+    assert_eq!(body["data"]["access_token"], "secret-token", "{}", body);
+    let response = client.get(format!("{}?refresh=true", tb_token_url("synced"))).bearer_auth(token).send().await?;
+    assert_eq!(response.status(), 503);
+
+    // Device lists and the audit log never carry it
+    let admin = &token;
+    let body: Value = client.put(format!("{}/api/devices-enhanced/synced", base_url)).bearer_auth(admin)
+        .json(&json!({
+            "id": "synced", "name": "Synced", "enabled": false, "polling_interval_ms": 2000, "timeout_ms": 1000, "retry_count": 1,
+            "protocol_config": {"type": "modbus_tcp", "host": "10.0.0.10", "port": 502, "slave_id": 1}, "tags": [],
+        }))
+        .send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    for path in ["/api/devices-enhanced", "/api/devices-enhanced/synced", "/api/devices", "/api/audit?entity_id=synced"] {
+        let text = client.get(format!("{}{}", base_url, path)).bearer_auth(admin).send().await?.text().await?;
+        assert!(text.contains("synced") && !text.contains("secret-token"), "{}: {}", path, text);
+    }
+    assert_eq!(db.get_device_tb_access_token("synced").await?, Some(Some("secret-token".to_string())));
+    Ok(())
+}
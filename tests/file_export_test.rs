@@ -0,0 +1,167 @@
+mod support;
+
+use ava_device_logger::config::{ExportRotation, FileExportConfig};
+use ava_device_logger::database::LogEntry;
+use ava_device_logger::file_export::FileExporter;
+use chrono::{DateTime, TimeZone, Utc};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use support::Logger;
+
+fn entry(device_id: &str, tag_name: &str, value: f64, timestamp: DateTime<Utc>) -> LogEntry {
+    LogEntry {
+        id: None,
+        device_id: device_id.to_string(),
+        tag_name: tag_name.to_string(),
+        value,
+        quality: "Good".to_string(),
+        timestamp,
+        unit: Some("kW".to_string()),
+    }
+}
+
+fn exporter(directory: &Path, rotation: ExportRotation, max_files: usize, combined: bool) -> Arc<FileExporter> {
+    let exporter = Arc::new(FileExporter::new(FileExportConfig {
+        directory: directory.to_string_lossy().to_string(),
+        rotation,
+        max_files,
+        combined,
+    }));
+    exporter.start();
+    exporter
+}
+
+/// Contents of a file once the writer has written `lines` lines to it
+async fn wait_for_lines(path: &Path, lines: usize) -> Result<Vec<String>, Box<dyn Error>> {
+    for _ in 0..100 {
+        if let Ok(text) = std::fs::read_to_string(path) {
+            if text.lines().count() >= lines {
+                return Ok(text.lines().map(str::to_string).collect());
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    Err(format!("{} never had {} lines", path.display(), lines).into())
+}
+
+fn csv_files(directory: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut names: Vec<String> = std::fs::read_dir(directory)?
+        .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().to_string()))
+        .collect::<Result<_, _>>()?;
+    names.sort();
+    Ok(names)
+}
+
+#[tokio::test]
+async fn test_entries_are_appended_per_device_and_day() -> Result<(), Box<dyn Error>> {
+    let directory = std::env::temp_dir().join(format!("file-export-{}", uuid::Uuid::new_v4()));
+    let exporter = exporter(&directory, ExportRotation::Daily, 0, false);
+    let day_one = Utc.with_ymd_and_hms(2024, 6, 1, 23, 59, 0).unwrap();
+    let day_two = Utc.with_ymd_and_hms(2024, 6, 2, 0, 0, 30).unwrap();
+
+    exporter.export(&[entry("meter-1", "Power", 1.5, day_one), entry("site/meter 2", "Power", 2.0, day_one)]);
+    let lines = wait_for_lines(&directory.join("2024-06-01-meter-1.csv"), 2).await?;
+    assert_eq!(lines, ["timestamp,device_id,tag_name,value,unit,quality", "2024-06-01T23:59:00+00:00,meter-1,Power,1.5,kW,Good"]);
+    // Characters that can't be in a file name are replaced
+    wait_for_lines(&directory.join("2024-06-01-site_meter_2.csv"), 2).await?;
+    assert!(exporter.is_open("2024-06-01-meter-1.csv"));
+
+    // A new day closes the files of the one before
+    exporter.export(&[entry("meter-1", "Power", 1.75, day_two)]);
+    wait_for_lines(&directory.join("2024-06-02-meter-1.csv"), 2).await?;
+    for _ in 0..50 {
+        if !exporter.is_open("2024-06-01-meter-1.csv") {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(!exporter.is_open("2024-06-01-meter-1.csv"));
+    assert!(exporter.is_open("2024-06-02-meter-1.csv"));
+
+    // A late value reopens its day's file without a second header
+    exporter.export(&[entry("meter-1", "Energy", 10.0, day_one)]);
+    let lines = wait_for_lines(&directory.join("2024-06-01-meter-1.csv"), 3).await?;
+    assert_eq!(lines[2], "2024-06-01T23:59:00+00:00,meter-1,Energy,10,kW,Good");
+    assert_eq!(csv_files(&directory)?, ["2024-06-01-meter-1.csv", "2024-06-01-site_meter_2.csv", "2024-06-02-meter-1.csv"]);
+    let _ = std::fs::remove_dir_all(&directory);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_combined_hourly_files_are_pruned_beyond_max_files() -> Result<(), Box<dyn Error>> {
+    let directory = std::env::temp_dir().join(format!("file-export-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&directory)?;
+    // Left from before a restart, so the oldest of all
+    std::fs::write(directory.join("2024-05-31T23.csv"), "timestamp,device_id,tag_name,value,unit,quality\n")?;
+    std::fs::write(directory.join("notes.txt"), "not an export")?;
+    let exporter = exporter(&directory, ExportRotation::Hourly, 2, true);
+
+    for hour in 0..3 {
+        let timestamp = Utc.with_ymd_and_hms(2024, 6, 1, hour, 15, 0).unwrap();
+        exporter.export(&[entry("meter-1", "Power", 1.0, timestamp), entry("meter-2", "Power", 2.0, timestamp)]);
+        wait_for_lines(&directory.join(format!("2024-06-01T{:02}.csv", hour)), 3).await?;
+    }
+
+    for _ in 0..50 {
+        if csv_files(&directory)?.len() == 3 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(csv_files(&directory)?, ["2024-06-01T01.csv", "2024-06-01T02.csv", "notes.txt"]);
+    let lines = wait_for_lines(&directory.join("2024-06-01T02.csv"), 3).await?;
+    assert_eq!(&lines[1..], ["2024-06-01T02:15:00+00:00,meter-1,Power,1,kW,Good", "2024-06-01T02:15:00+00:00,meter-2,Power,2,kW,Good"]);
+    let _ = std::fs::remove_dir_all(&directory);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_file_endpoints_serve_the_export_directory() -> Result<(), Box<dyn Error>> {
+    let logger = Logger::start("[file_export]\ndirectory = \"exports\"\n").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+    std::fs::create_dir_all(logger.work_dir.join("exports"))?;
+    std::fs::write(logger.work_dir.join("exports/2024-06-01-meter-1.csv"), "timestamp,device_id,tag_name,value,unit,quality\n")?;
+    std::fs::write(logger.work_dir.join("config-copy.csv"), "outside")?;
+
+    let body: Value = client.get(format!("{}/api/files/exports", base_url)).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(body["data"].as_array().map(Vec::len), Some(1), "{}", body);
+    assert_eq!(body["data"][0]["name"], "2024-06-01-meter-1.csv");
+    assert_eq!(body["data"][0]["download_url"], "/api/files/exports/2024-06-01-meter-1.csv");
+
+    let response = client.get(format!("{}/api/files/exports/2024-06-01-meter-1.csv", base_url)).bearer_auth(token).send().await?;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-disposition"], "attachment; filename=\"2024-06-01-meter-1.csv\"");
+    assert_eq!(response.text().await?, "timestamp,device_id,tag_name,value,unit,quality\n");
+
+    // Nothing outside the directory, and only the two known directories
+    for path in ["exports/..%2Fconfig-copy.csv", "exports/%2E%2E%5Cconfig-copy.csv", "exports/data.db"] {
+        let response = client.get(format!("{}/api/files/{}", base_url, path)).bearer_auth(token).send().await?;
+        assert_eq!(response.status(), 400, "{}", path);
+    }
+    let response = client.get(format!("{}/api/files/logs", base_url)).bearer_auth(token).send().await?;
+    assert_eq!(response.status(), 400);
+
+    let response = client.delete(format!("{}/api/files/exports/2024-06-01-meter-1.csv", base_url)).bearer_auth(token).send().await?;
+    assert_eq!(response.status(), 200);
+    assert!(!logger.work_dir.join("exports/2024-06-01-meter-1.csv").exists());
+    let response = client.delete(format!("{}/api/files/exports/2024-06-01-meter-1.csv", base_url)).bearer_auth(token).send().await?;
+    assert_eq!(response.status(), 404);
+
+    // Catalogs are still served, and an empty list before any is generated
+    let body: Value = client.get(format!("{}/api/files/catalogs", base_url)).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(body["data"], json!([]));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_exports_are_not_found_without_file_export() -> Result<(), Box<dyn Error>> {
+    let logger = Logger::start("").await?;
+    let response = logger.client.get(logger.url("/api/files/exports")).bearer_auth(&logger.token).send().await?;
+    assert_eq!(response.status(), 404);
+    let body: Value = response.json().await?;
+    assert!(body["error"].as_str().unwrap_or_default().contains("[file_export]"), "{}", body);
+    Ok(())
+}
@@ -0,0 +1,163 @@
+#![cfg(unix)]
+
+mod support;
+
+use ava_device_logger::database::{Database, DeviceInstance, DeviceTag, TagWritePolicy};
+use chrono::Utc;
+use serde_json::json;
+use std::error::Error;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+use support::{logger_command, wait_until_up, ModbusDevice, Server};
+
+fn device(id: &str, modbus_port: u16, timeout_ms: u32) -> DeviceInstance {
+    DeviceInstance {
+        id: id.to_string(),
+        name: id.to_string(),
+        serial_no: None,
+        model_id: None,
+        enabled: true,
+        polling_interval_ms: 200,
+        timeout_ms,
+        retry_count: 3,
+        protocol_config: json!({"type": "modbus_tcp", "host": "127.0.0.1", "port": modbus_port, "slave_id": 1}).to_string(),
+        tb_device_id: None,
+        tb_group_id: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    }
+}
+
+fn tag(device_id: &str) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: device_id.to_string(),
+        name: "Voltage".to_string(),
+        address: 230,
+        size: 1,
+        data_type: "uint16".to_string(),
+        description: None,
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: None,
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy: TagWritePolicy::Disabled,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+/// Write a config with the given shutdown timeout and start the logger with one device, its
+/// stdout piped for `terminate`
+async fn start_server(prefix: &str, device: DeviceInstance, shutdown_timeout_secs: u64) -> Result<(Server, PathBuf), Box<dyn Error>> {
+    let work_dir = support::work_dir(prefix)?;
+    let port = support::free_port()?;
+    let server_config = format!("[server]\nshutdown_timeout_secs = {shutdown_timeout_secs}\n");
+    std::fs::write(work_dir.join("config.toml"), support::config(port, "").replace("[server]\n", &server_config))?;
+
+    let db = Database::new(&work_dir.join("data.db").to_string_lossy()).await?;
+    db.create_device(&device).await?;
+    db.create_device_tags(&device.id, &[tag(&device.id)]).await?;
+    drop(db);
+
+    let server = Server(logger_command(&work_dir).env("RUST_LOG", "info").stdout(Stdio::piped()).spawn()?);
+    wait_until_up(&format!("http://127.0.0.1:{}", port)).await?;
+    Ok((server, work_dir))
+}
+
+async fn wait_for_status(db: &Database, device_id: &str, status: &str) -> Result<(), Box<dyn Error>> {
+    for _ in 0..100 {
+        if db.get_device_status(device_id).await?.is_some_and(|current| current.status == status) {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    Err(format!("device {} never became {}", device_id, status).into())
+}
+
+/// Send SIGTERM and wait for the server to exit, returning its exit status and output
+fn terminate(server: &mut Server, within: Duration) -> Result<(ExitStatus, Duration, String), Box<dyn Error>> {
+    let mut stdout = server.0.stdout.take().expect("piped stdout");
+    let output = std::thread::spawn(move || {
+        let mut output = String::new();
+        let _ = stdout.read_to_string(&mut output);
+        output
+    });
+
+    let signalled = Instant::now();
+    assert_eq!(unsafe { libc::kill(server.0.id() as libc::pid_t, libc::SIGTERM) }, 0);
+    while signalled.elapsed() < within {
+        if let Some(status) = server.0.try_wait()? {
+            return Ok((status, signalled.elapsed(), output.join().unwrap_or_default()));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    Err(format!("server still running {:?} after SIGTERM", within).into())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_sigterm_closes_connections_and_marks_devices_stopped() -> Result<(), Box<dyn Error>> {
+    let slave = ModbusDevice::start([(230, 230)]).await?;
+    let (mut server, work_dir) = start_server("graceful-shutdown", device("meter-1", slave.port(), 500), 10).await?;
+
+    let db = Database::new(&work_dir.join("data.db").to_string_lossy()).await?;
+    wait_for_status(&db, "meter-1", "Reading").await?;
+
+    let (status, _, output) = terminate(&mut server, Duration::from_secs(10))?;
+    assert!(status.success(), "{:?}\n{}", status, output);
+    assert!(output.contains("Shutdown complete"), "{}", output);
+
+    let device_status = db.get_device_status("meter-1").await?.expect("device status");
+    assert_eq!((device_status.status.as_str(), device_status.error_message), ("Stopped", None));
+    for _ in 0..20 {
+        if slave.closed_connections() > 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(slave.closed_connections(), 1);
+    assert!(output.contains("Closed Modbus TCP connection"), "{}", output);
+    // Poll outcomes counted since the last periodic save were written before exiting
+    let stats = db.get_poll_stats(Some("meter-1"), Utc::now() - chrono::Duration::hours(1)).await?;
+    assert!(stats.iter().map(|bucket| bucket.successes).sum::<u64>() >= 1, "{:?}", stats);
+    let logged = db.get_log_entries(Some("meter-1"), Some(1), None).await?;
+    assert_eq!(logged.first().map(|entry| entry.value), Some(230.0));
+
+    std::fs::remove_dir_all(&work_dir).ok();
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_hung_poller_is_aborted_after_the_shutdown_timeout() -> Result<(), Box<dyn Error>> {
+    // The slave accepts the connection but never answers, and the device waits a minute for it
+    let slave = ModbusDevice::start([]).await?;
+    slave.set_silent(true);
+    let (mut server, work_dir) = start_server("graceful-shutdown-hung", device("meter-hung", slave.port(), 60_000), 1).await?;
+
+    let db = Database::new(&work_dir.join("data.db").to_string_lossy()).await?;
+    wait_for_status(&db, "meter-hung", "Connected").await?;
+
+    let (status, elapsed, output) = terminate(&mut server, Duration::from_secs(10))?;
+    assert_eq!(status.code(), Some(1), "{}", output);
+    assert!(elapsed < Duration::from_secs(5), "{:?}", elapsed);
+    assert!(
+        output.contains("Task for schedule group 'Device interval' of device meter-hung did not stop within 1s"),
+        "{}",
+        output
+    );
+    assert!(output.contains("Shutdown did not finish in time"), "{}", output);
+
+    let device_status = db.get_device_status("meter-hung").await?.expect("device status");
+    assert_eq!(device_status.status, "Stopped");
+
+    std::fs::remove_dir_all(&work_dir).ok();
+    Ok(())
+}
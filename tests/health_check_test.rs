@@ -0,0 +1,143 @@
+mod support;
+
+use ava_device_logger::database::{Database, DeviceInstance, DeviceTag, TagWritePolicy};
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::time::{Duration, Instant};
+use support::Logger;
+
+/// Start the server with `extra_config` appended to a minimal config and the given devices,
+/// each with one tag, pointing at Modbus TCP ports
+async fn start_server(extra_config: &str, devices: &[(&str, u16)]) -> Result<Logger, Box<dyn Error>> {
+    let work_dir = support::work_dir("health-check")?;
+    let db = Database::new(&work_dir.join("data.db").to_string_lossy()).await?;
+    for (id, device_port) in devices {
+        db.create_device(&DeviceInstance {
+            id: id.to_string(),
+            name: id.to_string(),
+            serial_no: None,
+            model_id: None,
+            enabled: true,
+            polling_interval_ms: 1000,
+            timeout_ms: 300,
+            retry_count: 1,
+            protocol_config: json!({"type": "modbus_tcp", "host": "127.0.0.1", "port": device_port, "slave_id": 1}).to_string(),
+            tb_device_id: None,
+            tb_group_id: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            strict_types: false,
+        }).await?;
+        db.create_device_tags(id, &[DeviceTag {
+            id: None,
+            device_id: id.to_string(),
+            name: "Active Power".to_string(),
+            address: 40001,
+            size: 1,
+            data_type: "uint16".to_string(),
+            description: None,
+            scaling_multiplier: 1.0,
+            scaling_offset: 0.0,
+            unit: None,
+            read_only: true,
+            enabled: true,
+            schedule_group_id: None,
+            agg_to_field: None,
+            write_policy: TagWritePolicy::AdminOnly,
+            byte_order: None,
+            deadband_absolute: None,
+            deadband_percent: None,
+            register_type: None,
+        }]).await?;
+    }
+    drop(db);
+
+    Logger::start_in(work_dir, extra_config).await
+}
+
+async fn health(base_url: &str) -> Result<(u16, Value), Box<dyn Error>> {
+    let response = reqwest::get(format!("{}/api/health", base_url)).await?;
+    let code = response.status().as_u16();
+    Ok((code, response.json().await?))
+}
+
+#[tokio::test]
+async fn test_idle_service_is_healthy() -> Result<(), Box<dyn Error>> {
+    let logger = start_server("", &[]).await?;
+
+    let (code, report) = health(&logger.base_url).await?;
+    assert_eq!(code, 200, "{}", report);
+    assert_eq!(report["status"], "healthy");
+    assert_eq!(report["service"], "AVA Device Logger");
+    assert_eq!(report["checks"]["database"]["status"], "healthy");
+    assert_eq!(report["checks"]["database"]["error"], Value::Null);
+    assert_eq!(report["checks"]["devices"]["running"], 0);
+    assert_eq!(report["checks"]["disk"]["min_free_mb"], 100);
+    assert!(report["checks"]["disk"]["free_mb"].as_u64().is_some(), "{}", report);
+    assert_eq!(report["checks"]["thingsboard"], Value::Null);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_failing_checks_report_degraded() -> Result<(), Box<dyn Error>> {
+    let tb_port = support::free_port()?;
+    let extra_config = format!(
+        r#"
+[health]
+min_free_disk_mb = 1000000000
+max_errored_fraction = 0.0
+
+[thingsboard]
+base_url = "http://127.0.0.1:{tb_port}"
+username = "tenant@example.com"
+password = "secret"
+
+[telemetry_forwarding]
+enabled = true
+"#
+    );
+    let logger = start_server(&extra_config, &[("inv-1", support::free_port()?)]).await?;
+
+    // The device can't connect, so it ends up in Error after its single retry
+    let started = Instant::now();
+    let report = loop {
+        let (code, report) = health(&logger.base_url).await?;
+        assert_eq!(code, 200, "{}", report);
+        if report["checks"]["devices"]["errored"] == 1 {
+            break report;
+        }
+        assert!(started.elapsed() < Duration::from_secs(20), "device never errored: {}", report);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+
+    assert_eq!(report["status"], "degraded");
+    assert_eq!(report["checks"]["database"]["status"], "healthy");
+    assert_eq!(report["checks"]["devices"]["status"], "degraded");
+    assert_eq!(report["checks"]["devices"]["running"], 1);
+    assert_eq!(report["checks"]["disk"]["status"], "degraded");
+    assert_eq!(report["checks"]["thingsboard"]["status"], "degraded");
+    assert!(!report["checks"]["thingsboard"]["error"].as_str().unwrap().contains("secret"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_locked_database_is_unavailable() -> Result<(), Box<dyn Error>> {
+    let logger = start_server("[health]\ndatabase_timeout_ms = 300\n", &[]).await?;
+
+    // Another process holding the write lock
+    let conn = rusqlite::Connection::open(logger.work_dir.join("data.db"))?;
+    conn.execute_batch("BEGIN EXCLUSIVE")?;
+
+    let (code, report) = health(&logger.base_url).await?;
+    assert_eq!(code, 503, "{}", report);
+    assert_eq!(report["status"], "unhealthy");
+    assert_eq!(report["checks"]["database"]["status"], "unhealthy");
+    assert!(report["checks"]["database"]["error"].as_str().unwrap().contains("locked"), "{}", report);
+
+    conn.execute_batch("ROLLBACK")?;
+    let (code, report) = health(&logger.base_url).await?;
+    assert_eq!(code, 200, "{}", report);
+    assert_eq!(report["status"], "healthy");
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use ava_device_logger::config::PvNaming;
+use ava_device_logger::database::{Database, DeviceInstance, DeviceTag, TagWritePolicy, TbChildDevice};
+use ava_device_logger::tb_rust_client::{InverterExport, ThingsBoardClient};
+use chrono::Utc;
+use std::error::Error;
+
+fn tag(name: &str, address: u16, description: &str) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: "inv-1".to_string(),
+        name: name.to_string(),
+        address,
+        size: 1,
+        data_type: "uint16".to_string(),
+        description: Some(description.to_string()),
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: None,
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy: TagWritePolicy::Disabled,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+#[tokio::test]
+async fn test_hierarchy_export_nests_strings_under_mppts() -> Result<(), Box<dyn Error>> {
+    let device = DeviceInstance {
+        id: "inv-1".to_string(),
+        name: "inv 1".to_string(),
+        serial_no: Some("A123".to_string()),
+        model_id: None,
+        enabled: true,
+        polling_interval_ms: 1000,
+        timeout_ms: 5000,
+        retry_count: 3,
+        protocol_config: "{}".to_string(),
+        tb_device_id: Some("tb-inv-1".to_string()),
+        tb_group_id: Some("group-1".to_string()),
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    };
+
+    let tags = vec![
+        tag("Total Active Power", 5008, "Inverter (SG150CX)"),
+        tag("Vmppt", 5010, "MPPT - MPPT 1 (SG150CX)"),
+        tag("Udc", 5012, "String - MPPT 1 - Input 2 (SG150CX)"),
+        tag("Idc", 5013, "String - MPPT 1 - Input 2 (SG150CX)"),
+        tag("Udc", 5014, "String - MPPT 2 - Input 4 (SG150CX)"),
+    ];
+
+    let client = ThingsBoardClient::new("http://localhost");
+    let hierarchy = client.analyze_device_hierarchy(tags, "ACCV-P002-Plant", 1, PvNaming::Global).await?;
+    let export = InverterExport::from_hierarchy(&device, hierarchy, &[]);
+
+    assert_eq!(export.name, "ACCV-P002-I01");
+    assert_eq!(export.model, "SG150CX");
+    assert_eq!(export.tags.len(), 1);
+    assert_eq!(export.mppts.len(), 2);
+
+    let mppt1 = &export.mppts[0];
+    assert_eq!(mppt1.mppt_number, 1);
+    assert_eq!(mppt1.tags.len(), 1);
+    assert_eq!(mppt1.strings.len(), 1);
+    assert_eq!(mppt1.strings[0].udc_tag.as_ref().map(|t| t.address), Some(5012));
+    assert_eq!(mppt1.strings[0].idc_tag.as_ref().map(|t| t.address), Some(5013));
+
+    // MPPT 2 has no MPPT-level tags but still parents its string
+    let mppt2 = &export.mppts[1];
+    assert!(mppt2.tags.is_empty());
+    assert_eq!(mppt2.strings[0].input_number, 4);
+
+    let json = serde_json::to_value(&export)?;
+    assert!(json.get("access_token").is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_hierarchy_export_carries_the_recorded_child_ids() -> Result<(), Box<dyn Error>> {
+    let db_path = std::env::temp_dir().join(format!("hierarchy-export-{}.db", uuid::Uuid::new_v4()));
+    let db = Database::new(&db_path.to_string_lossy()).await?;
+    let device = DeviceInstance {
+        id: "inv-1".to_string(),
+        name: "inv 1".to_string(),
+        serial_no: None,
+        model_id: None,
+        enabled: true,
+        polling_interval_ms: 1000,
+        timeout_ms: 5000,
+        retry_count: 3,
+        protocol_config: "{}".to_string(),
+        tb_device_id: Some("tb-inv-1".to_string()),
+        tb_group_id: Some("group-1".to_string()),
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    };
+    db.create_device(&device).await?;
+
+    // As recorded by a sync; MPPT 2 was never created, so it and its string have no id
+    let child = |tb_device_id: &str, name: &str, device_type: &str, mppt_number, input_number| TbChildDevice {
+        device_id: "inv-1".to_string(),
+        tb_device_id: tb_device_id.to_string(),
+        name: name.to_string(),
+        device_type: device_type.to_string(),
+        mppt_number,
+        input_number,
+    };
+    db.save_tb_child_devices("inv-1", &[
+        child("tb-m01", "ACCV-P002-I01-M01", "Mppt", 1, None),
+        child("tb-m01-pv01", "ACCV-P002-I01-M01-PV01", "String", 1, Some(2)),
+    ]).await?;
+
+    let tags = vec![
+        tag("Vmppt", 5010, "MPPT - MPPT 1 (SG150CX)"),
+        tag("Udc", 5012, "String - MPPT 1 - Input 2 (SG150CX)"),
+        tag("Udc", 5014, "String - MPPT 2 - Input 4 (SG150CX)"),
+    ];
+    let client = ThingsBoardClient::new("http://localhost");
+    let hierarchy = client.analyze_device_hierarchy(tags, "ACCV-P002-Plant", 1, PvNaming::Global).await?;
+    let export = InverterExport::from_hierarchy(&device, hierarchy, &db.get_tb_child_devices("inv-1").await?);
+
+    let json = serde_json::to_value(&export)?;
+    assert_eq!(json["tb_device_id"], "tb-inv-1");
+    assert_eq!(json["mppts"][0]["tb_device_id"], "tb-m01");
+    assert_eq!(json["mppts"][0]["strings"][0]["tb_device_id"], "tb-m01-pv01");
+    assert_eq!(json["mppts"][1]["tb_device_id"], serde_json::Value::Null);
+    assert_eq!(json["mppts"][1]["strings"][0]["tb_device_id"], serde_json::Value::Null);
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
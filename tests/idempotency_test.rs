@@ -0,0 +1,132 @@
+mod support;
+
+use ava_device_logger::database::{Database, IdempotencyOutcome};
+use chrono::{Duration, Utc};
+use serde_json::{json, Value};
+use std::error::Error;
+use support::{tag, Logger, TestResult};
+
+const ENDPOINT: &str = "POST /api/sync-devices-to-thingsboard";
+const ADMIN: i64 = 1;
+const INSTALLER: i64 = 2;
+
+async fn temp_database(name: &str) -> Result<(Database, String), Box<dyn Error>> {
+    let db_path = std::env::temp_dir()
+        .join(format!("{}-{}.db", name, uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string();
+    Ok((Database::new(&db_path).await?, db_path))
+}
+
+#[tokio::test]
+async fn test_replay_same_body_returns_stored_response() -> Result<(), Box<dyn Error>> {
+    let (db, db_path) = temp_database("idempotency-replay").await?;
+    let ttl = Duration::hours(24);
+
+    assert_eq!(db.begin_idempotent_request(ADMIN, "key-1", ENDPOINT, "hash-a", ttl).await?, IdempotencyOutcome::Proceed);
+
+    // A retry while the first request is still running must not execute again
+    assert_eq!(db.begin_idempotent_request(ADMIN, "key-1", ENDPOINT, "hash-a", ttl).await?, IdempotencyOutcome::InProgress);
+
+    db.complete_idempotent_request(ADMIN, "key-1", ENDPOINT, 200, r#"{"success":true}"#).await?;
+    assert_eq!(
+        db.begin_idempotent_request(ADMIN, "key-1", ENDPOINT, "hash-a", ttl).await?,
+        IdempotencyOutcome::Replay { status_code: 200, response_body: r#"{"success":true}"#.to_string() }
+    );
+
+    // Keys are scoped per endpoint
+    assert_eq!(
+        db.begin_idempotent_request(ADMIN, "key-1", "POST /api/devices-enhanced", "hash-a", ttl).await?,
+        IdempotencyOutcome::Proceed
+    );
+
+    // and per user: another user's request with the same key runs and is stored separately
+    assert_eq!(db.begin_idempotent_request(INSTALLER, "key-1", ENDPOINT, "hash-b", ttl).await?, IdempotencyOutcome::Proceed);
+    db.complete_idempotent_request(INSTALLER, "key-1", ENDPOINT, 200, r#"{"success":false}"#).await?;
+    assert_eq!(
+        db.begin_idempotent_request(ADMIN, "key-1", ENDPOINT, "hash-a", ttl).await?,
+        IdempotencyOutcome::Replay { status_code: 200, response_body: r#"{"success":true}"#.to_string() }
+    );
+    db.release_idempotent_request(INSTALLER, "key-1", ENDPOINT).await?;
+    assert_eq!(db.begin_idempotent_request(INSTALLER, "key-1", ENDPOINT, "hash-a", ttl).await?, IdempotencyOutcome::Proceed);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replay_different_body_is_rejected() -> Result<(), Box<dyn Error>> {
+    let (db, db_path) = temp_database("idempotency-mismatch").await?;
+    let ttl = Duration::hours(24);
+
+    assert_eq!(db.begin_idempotent_request(ADMIN, "key-2", ENDPOINT, "hash-a", ttl).await?, IdempotencyOutcome::Proceed);
+    db.complete_idempotent_request(ADMIN, "key-2", ENDPOINT, 200, r#"{"success":true}"#).await?;
+    assert_eq!(db.begin_idempotent_request(ADMIN, "key-2", ENDPOINT, "hash-b", ttl).await?, IdempotencyOutcome::Mismatch);
+
+    // A released key (failed request) can be retried
+    assert_eq!(db.begin_idempotent_request(ADMIN, "key-3", ENDPOINT, "hash-a", ttl).await?, IdempotencyOutcome::Proceed);
+    db.release_idempotent_request(ADMIN, "key-3", ENDPOINT).await?;
+    assert_eq!(db.begin_idempotent_request(ADMIN, "key-3", ENDPOINT, "hash-a", ttl).await?, IdempotencyOutcome::Proceed);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_expired_records_are_reusable_and_purged() -> Result<(), Box<dyn Error>> {
+    let (db, db_path) = temp_database("idempotency-expiry").await?;
+
+    assert_eq!(db.begin_idempotent_request(ADMIN, "key-4", ENDPOINT, "hash-a", Duration::zero()).await?, IdempotencyOutcome::Proceed);
+    db.complete_idempotent_request(ADMIN, "key-4", ENDPOINT, 200, r#"{"success":true}"#).await?;
+
+    // Expired: a different body is accepted as a new request
+    assert_eq!(db.begin_idempotent_request(ADMIN, "key-4", ENDPOINT, "hash-b", Duration::hours(24)).await?, IdempotencyOutcome::Proceed);
+
+    assert_eq!(db.begin_idempotent_request(ADMIN, "key-5", ENDPOINT, "hash-a", Duration::zero()).await?, IdempotencyOutcome::Proceed);
+    assert_eq!(db.purge_expired_idempotency_records(Utc::now()).await?, 1);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_bulk_tag_edit_is_replayed_per_user() -> TestResult {
+    let logger = Logger::start("").await?;
+    let body = logger
+        .post("/api/devices-enhanced", &json!({
+            "id": "inv-1", "name": "inv-1", "enabled": false, "polling_interval_ms": 1000, "timeout_ms": 1000, "retry_count": 1,
+            "protocol_config": {"type": "modbus_tcp", "host": "127.0.0.1", "port": 502, "slave_id": 1},
+            "tags": [tag("Power", 100, "uint16", 1.0, json!({"unit": "W"}))],
+        }))
+        .await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let login: Value = logger.client.post(logger.url("/api/login")).json(&json!({"username": "installer", "password": "installer123"})).send().await?.json().await?;
+    let installer = login["data"]["session_token"].as_str().ok_or("no session token")?.to_string();
+
+    let edit = json!({"filter": {"name_pattern": "Power"}, "changes": {"unit": "kW"}});
+    let bulk_edit = |token: &str| logger.client.post(logger.url("/api/tags/bulk-edit")).bearer_auth(token).header("Idempotency-Key", "edit-1").json(&edit).send();
+    let unit = || async {
+        let body = logger.get("/api/devices/inv-1/tags").await.unwrap();
+        body["data"][0]["unit"].as_str().unwrap().to_string()
+    };
+
+    let response = bulk_edit(&logger.token).await?;
+    assert!(response.headers().get("idempotent-replayed").is_none());
+    let first: Value = response.json().await?;
+    assert_eq!(first["data"]["updated_tags"], 1, "{}", first);
+    assert_eq!(unit().await, "kW");
+
+    // A retry by the same user gets the stored response and changes nothing
+    logger.post("/api/tags/bulk-edit", &json!({"filter": {"name_pattern": "Power"}, "changes": {"unit": "W"}})).await?;
+    let response = bulk_edit(&logger.token).await?;
+    assert_eq!(response.headers()["idempotent-replayed"], "true");
+    assert_eq!(response.json::<Value>().await?, first);
+    assert_eq!(unit().await, "W");
+
+    // Another user's request with the same key is their own
+    let response = bulk_edit(&installer).await?;
+    assert!(response.headers().get("idempotent-replayed").is_none());
+    assert_eq!(response.json::<Value>().await?["success"], true);
+    assert_eq!(unit().await, "kW");
+    Ok(())
+}
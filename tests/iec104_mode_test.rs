@@ -0,0 +1,214 @@
+mod support;
+
+use ava_device_logger::config::{DeviceConfig, Iec104Config, Iec104Mode, ProtocolConfig};
+use ava_device_logger::database::{Database, DeviceTag, TagWritePolicy};
+use ava_device_logger::iec104::{Iec104Client, Iec104ModeHandle, Iec104ModeSettings};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use support::Logger;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+/// I-format frame carrying one short float (M_ME_NC_1) with the given cause of transmission
+fn float_frame(ioa: u8, value: f32, cot: u8) -> Vec<u8> {
+    let mut frame = vec![0x68, 18, 0, 0, 0, 0, 13, 0x01, cot, 0, 1, 0, ioa, 0, 0];
+    frame.extend_from_slice(&value.to_le_bytes());
+    frame.push(0); // QDS
+    frame
+}
+
+struct MockRtu {
+    port: u16,
+    connections: Arc<AtomicUsize>,
+    interrogations: Arc<AtomicUsize>,
+    spontaneous: mpsc::UnboundedSender<f32>,
+}
+
+/// RTU stand-in that answers STARTDT and general interrogations, and pushes
+/// spontaneous values when the test asks for them
+async fn spawn_mock_rtu() -> Result<MockRtu, Box<dyn Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let interrogations = Arc::new(AtomicUsize::new(0));
+    let (spontaneous, mut pending) = mpsc::unbounded_channel::<f32>();
+
+    let connections_task = connections.clone();
+    let interrogations_task = interrogations.clone();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            connections_task.fetch_add(1, Ordering::SeqCst);
+            loop {
+                let mut header = [0u8; 2];
+                tokio::select! {
+                    read = socket.read_exact(&mut header) => {
+                        if read.is_err() {
+                            break;
+                        }
+                        let mut body = vec![0u8; header[1] as usize];
+                        if socket.read_exact(&mut body).await.is_err() {
+                            break;
+                        }
+                        if body[0] == 0x07 {
+                            let _ = socket.write_all(&[0x68, 4, 0x0B, 0, 0, 0]).await;
+                        } else if body.len() > 4 && body[4] == 100 {
+                            interrogations_task.fetch_add(1, Ordering::SeqCst);
+                            let _ = socket.write_all(&float_frame(100, 1.5, 20)).await;
+                        }
+                    }
+                    Some(value) = pending.recv() => {
+                        let _ = socket.write_all(&float_frame(101, value, 3)).await;
+                    }
+                }
+            }
+        }
+    });
+
+    Ok(MockRtu { port, connections, interrogations, spontaneous })
+}
+
+fn tag(name: &str, ioa: u16) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: "rtu-1".to_string(),
+        name: name.to_string(),
+        address: ioa,
+        size: 1,
+        data_type: "float32".to_string(),
+        description: None,
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: None,
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy: TagWritePolicy::Disabled,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+#[test]
+fn test_mode_defaults_to_interrogation_for_existing_configs() {
+    let protocol: ProtocolConfig =
+        serde_json::from_str(r#"{"type":"iec104","host":"10.0.0.5","port":2404,"common_address":1}"#).unwrap();
+    let settings = Iec104ModeSettings::from_protocol(&protocol).unwrap();
+    assert_eq!(settings.mode, Iec104Mode::Interrogation);
+    assert_eq!(settings.interrogation_interval_ms, 300_000);
+}
+
+#[tokio::test]
+async fn test_mode_switch_keeps_connection() -> Result<(), Box<dyn Error>> {
+    let rtu = spawn_mock_rtu().await?;
+    let db_path = std::env::temp_dir()
+        .join(format!("iec104-mode-{}.db", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string();
+    let db = Database::new(&db_path).await?;
+
+    let protocol = ProtocolConfig::Iec104(Iec104Config {
+        host: "127.0.0.1".to_string(),
+        port: rtu.port,
+        common_address: 1,
+        mode: Iec104Mode::Interrogation,
+        interrogation_interval_ms: 300_000,
+    });
+    let handle = Iec104ModeHandle::new(Iec104ModeSettings::from_protocol(&protocol).unwrap());
+    let mut client = Iec104Client::new(
+        DeviceConfig {
+            id: "rtu-1".to_string(),
+            name: "RTU 1".to_string(),
+            enabled: true,
+            protocol,
+            polling_interval_ms: 1000,
+            timeout_ms: 1000,
+            retry_count: 3,
+            tags: Vec::new(),
+            strict_types: false,
+        },
+        handle.clone(),
+    );
+    let tags = vec![tag("float_100", 100), tag("float_101", 101)];
+
+    client.connect().await?;
+    let entries = client.read_specific_tags(&db, &tags).await?;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(rtu.interrogations.load(Ordering::SeqCst), 1);
+
+    let diagnostics = handle.diagnostics();
+    assert_eq!(diagnostics.interrogated_values, 1);
+    assert!(diagnostics.last_general_interrogation.is_some());
+
+    // Switch to spontaneous-only on the live client
+    assert!(handle.set(Iec104ModeSettings { mode: Iec104Mode::Spontaneous, interrogation_interval_ms: 300_000 }));
+    assert!(!handle.set(Iec104ModeSettings { mode: Iec104Mode::Spontaneous, interrogation_interval_ms: 300_000 }));
+
+    rtu.spontaneous.send(42.0)?;
+    let entries = client.read_specific_tags(&db, &tags).await?;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].tag_name, "float_101");
+
+    let diagnostics = handle.diagnostics();
+    assert_eq!(diagnostics.mode, Iec104Mode::Spontaneous);
+    assert_eq!(diagnostics.spontaneous_values, 1);
+    assert!(diagnostics.mode_changed_at.is_some());
+
+    // No new interrogation and no reconnect
+    assert_eq!(rtu.interrogations.load(Ordering::SeqCst), 1);
+    assert_eq!(rtu.connections.load(Ordering::SeqCst), 1);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mode_change_on_a_running_device_keeps_its_connection() -> Result<(), Box<dyn Error>> {
+    let rtu = spawn_mock_rtu().await?;
+    let logger = Logger::start("").await?;
+    let mut request = json!({
+        "id": "rtu-1", "name": "RTU 1", "enabled": true,
+        "polling_interval_ms": 200, "timeout_ms": 1000, "retry_count": 1,
+        "protocol_config": {"type": "iec104", "host": "127.0.0.1", "port": rtu.port, "common_address": 1},
+        "tags": [support::tag("float_100", 100, "float32", 1.0, json!({})), support::tag("float_101", 101, "float32", 1.0, json!({}))],
+    });
+    logger.start_device(request.clone()).await?;
+    let interrogated = support::eventually(Duration::from_secs(5), || async {
+        (rtu.interrogations.load(Ordering::SeqCst) > 0).then_some(())
+    })
+    .await;
+    assert!(interrogated.is_some());
+
+    request["protocol_config"]["mode"] = json!("spontaneous");
+    let body: Value = logger.client.put(logger.url("/api/devices-enhanced/rtu-1")).bearer_auth(&logger.token)
+        .json(&request).send().await?.json().await?;
+    let tags = json!({"inserted": 0, "updated": 0, "deleted": 0, "unchanged": 2});
+    assert_eq!(body["data"], json!({"device_id": "rtu-1", "reloaded": false, "tags": tags}), "{}", body);
+
+    // The running driver switched mode and goes on receiving over the same connection
+    let body = logger.get("/api/devices/rtu-1/iec104-diagnostics").await?;
+    assert_eq!(body["data"]["mode"], "spontaneous", "{}", body);
+    rtu.spontaneous.send(42.0)?;
+    let received = support::eventually(Duration::from_secs(5), || async {
+        let values = logger.values("rtu-1").await.ok()?;
+        (values.get("float_101")?["value"] == 42.0).then_some(())
+    })
+    .await;
+    assert!(received.is_some(), "{:?}", logger.values("rtu-1").await?);
+    assert_eq!(rtu.connections.load(Ordering::SeqCst), 1);
+
+    // The switch is audited with the mode before and after
+    let body = logger.get("/api/audit?entity_id=rtu-1").await?;
+    let entry = body["data"]["entries"].as_array().unwrap().iter().find(|entry| entry["action"] == "device.iec104_mode").cloned();
+    let entry = entry.ok_or_else(|| format!("no mode change in {}", body))?;
+    assert_eq!(entry["before"], json!({"mode": "interrogation", "interrogation_interval_ms": 300_000}));
+    assert_eq!(entry["after"], json!({"mode": "spontaneous", "interrogation_interval_ms": 300_000}));
+    assert_eq!(entry["username"], "admin");
+    Ok(())
+}
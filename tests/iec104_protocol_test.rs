@@ -0,0 +1,244 @@
+use ava_device_logger::config::{DeviceConfig, Iec104Config, Iec104Mode, ProtocolConfig};
+use ava_device_logger::database::{Database, DeviceTag, TagWritePolicy};
+use ava_device_logger::iec104::{parse_data_unit, tag_entries, Iec104Client, Iec104ModeHandle, Iec104ModeSettings, InformationObject};
+use chrono::Utc;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// M_ME_NC_1, one short float 1.5 at IOA 100, spontaneous
+const FLOAT_SPONTANEOUS: [u8; 20] = [
+    0x68, 0x12, 0x00, 0x00, 0x00, 0x00, 0x0D, 0x01, 0x03, 0x00, 0x01, 0x00,
+    0x64, 0x00, 0x00, 0x00, 0x00, 0xC0, 0x3F, 0x00,
+];
+
+/// M_ME_NB_1 sequence of three scaled values from IOA 200, interrogated: 1000 good,
+/// -10 invalid and 100 not topical
+const SCALED_SEQUENCE: [u8; 24] = [
+    0x68, 0x16, 0x02, 0x00, 0x00, 0x00, 0x0B, 0x83, 0x14, 0x00, 0x01, 0x00,
+    0xC8, 0x00, 0x00, 0xE8, 0x03, 0x00, 0xF6, 0xFF, 0x80, 0x64, 0x00, 0x40,
+];
+
+/// M_SP_NA_1, two single points: IOA 10 on, IOA 11 off, blocked and invalid
+const SINGLE_POINTS: [u8; 20] = [
+    0x68, 0x12, 0x04, 0x00, 0x00, 0x00, 0x01, 0x02, 0x14, 0x00, 0x01, 0x00,
+    0x0A, 0x00, 0x00, 0x01, 0x0B, 0x00, 0x00, 0x90,
+];
+
+/// M_ME_TF_1, short float 2.5 at IOA 300 with a CP56Time2a time tag
+const FLOAT_TIME_TAGGED: [u8; 27] = [
+    0x68, 0x19, 0x06, 0x00, 0x00, 0x00, 0x24, 0x01, 0x03, 0x00, 0x01, 0x00,
+    0x2C, 0x01, 0x00, 0x00, 0x00, 0x20, 0x40, 0x00,
+    0x10, 0x27, 0x1E, 0x0A, 0x0F, 0x0A, 0x1A,
+];
+
+fn object(ioa: u32, value: f64, quality: u8) -> InformationObject {
+    InformationObject { ioa, value, quality }
+}
+
+fn tag(name: &str, ioa: u16, multiplier: f64) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: "rtu-1".to_string(),
+        name: name.to_string(),
+        address: ioa,
+        size: 1,
+        data_type: "float32".to_string(),
+        description: None,
+        scaling_multiplier: multiplier,
+        scaling_offset: 0.0,
+        unit: Some("kW".to_string()),
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy: TagWritePolicy::Disabled,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+#[test]
+fn test_measured_values_decode_with_their_quality() {
+    let unit = parse_data_unit(&FLOAT_SPONTANEOUS).unwrap();
+    assert_eq!((unit.type_id, unit.cause, unit.negative), (13, 3, false));
+    assert_eq!(unit.objects, vec![object(100, 1.5, 0)]);
+    assert_eq!(unit.objects[0].quality_label(), "Good");
+
+    // SQ=1: one IOA, then consecutive elements
+    let unit = parse_data_unit(&SCALED_SEQUENCE).unwrap();
+    assert_eq!(unit.cause, 20);
+    assert_eq!(unit.objects, vec![object(200, 1000.0, 0), object(201, -10.0, 0x80), object(202, 100.0, 0x40)]);
+    let labels: Vec<&str> = unit.objects.iter().map(|o| o.quality_label()).collect();
+    assert_eq!(labels, ["Good", "Bad", "Uncertain"]);
+
+    let unit = parse_data_unit(&FLOAT_TIME_TAGGED).unwrap();
+    assert_eq!(unit.objects, vec![object(300, 2.5, 0)]);
+}
+
+#[test]
+fn test_single_points_keep_the_value_bit_out_of_the_quality() {
+    let unit = parse_data_unit(&SINGLE_POINTS).unwrap();
+    assert_eq!(unit.objects, vec![object(10, 1.0, 0), object(11, 0.0, 0x90)]);
+    assert_eq!(unit.objects[0].quality_label(), "Good");
+    assert_eq!(unit.objects[1].quality_label(), "Bad");
+
+    // Overflow only exists for measured values; a blocked point is uncertain
+    assert_eq!(object(12, 1.0, 0x10).quality_label(), "Uncertain");
+    assert_eq!(object(12, 1.0, 0x01).quality_label(), "Uncertain");
+}
+
+#[test]
+fn test_other_frames_are_not_data_units() {
+    // STARTDT con (U-format) and an acknowledgement (S-format)
+    assert_eq!(parse_data_unit(&[0x68, 0x04, 0x0B, 0x00, 0x00, 0x00]), None);
+    assert_eq!(parse_data_unit(&[0x68, 0x04, 0x01, 0x00, 0x02, 0x00]), None);
+
+    // Declares three objects but carries two
+    let mut truncated = SCALED_SEQUENCE[..21].to_vec();
+    truncated[1] = 19;
+    assert_eq!(parse_data_unit(&truncated), None);
+    // Length byte disagrees with the frame
+    assert_eq!(parse_data_unit(&FLOAT_SPONTANEOUS[..19]), None);
+
+    // Double points aren't decoded, but the ASDU header still is
+    let double_point = [0x68, 0x0E, 0x00, 0x00, 0x00, 0x00, 0x03, 0x01, 0x03, 0x00, 0x01, 0x00, 0x05, 0x00, 0x00, 0x02];
+    let unit = parse_data_unit(&double_point).unwrap();
+    assert_eq!((unit.type_id, unit.objects.len()), (3, 0));
+
+    // Interrogation termination
+    let termination = [0x68, 0x0E, 0x00, 0x00, 0x00, 0x00, 0x64, 0x01, 0x0A, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x14];
+    let unit = parse_data_unit(&termination).unwrap();
+    assert_eq!((unit.type_id, unit.cause), (100, 10));
+}
+
+#[test]
+fn test_objects_map_to_tags_by_address() {
+    let mut disabled = tag("Spare", 202, 1.0);
+    disabled.enabled = false;
+    let tags = vec![
+        tag("P total", 200, 0.1),
+        tag("P total raw", 200, 1.0),
+        tag("Q total", 201, 1.0),
+        disabled,
+        tag("Not sent", 999, 1.0),
+    ];
+    let unit = parse_data_unit(&SCALED_SEQUENCE).unwrap();
+    let timestamp = Utc::now();
+
+    let entries = tag_entries("rtu-1", &unit, &tags, timestamp);
+    let summary: Vec<(&str, f64, &str)> = entries.iter().map(|e| (e.tag_name.as_str(), e.value, e.quality.as_str())).collect();
+    assert_eq!(summary, [("P total", 100.0, "Good"), ("P total raw", 1000.0, "Good"), ("Q total", -10.0, "Bad")]);
+    assert!(entries.iter().all(|e| e.device_id == "rtu-1" && e.timestamp == timestamp && e.unit.as_deref() == Some("kW")));
+}
+
+/// Frames the outstation received that weren't commands
+#[derive(Default)]
+struct Received {
+    acknowledged: Vec<u16>,
+    test_confirmations: usize,
+}
+
+/// Outstation answering a general interrogation with a test frame, the activation
+/// confirmation, two data ASDUs, a spontaneous value and the termination, in that order
+async fn spawn_outstation(received: Arc<Mutex<Received>>) -> Result<u16, Box<dyn Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut sent: u16 = 0;
+        loop {
+            let mut header = [0u8; 2];
+            if socket.read_exact(&mut header).await.is_err() {
+                break;
+            }
+            let mut body = vec![0u8; header[1] as usize];
+            if socket.read_exact(&mut body).await.is_err() {
+                break;
+            }
+            match body[0] {
+                0x07 => {
+                    let _ = socket.write_all(&[0x68, 4, 0x0B, 0, 0, 0]).await;
+                },
+                0x83 => received.lock().unwrap().test_confirmations += 1,
+                0x01 => received.lock().unwrap().acknowledged.push(u16::from_le_bytes([body[2], body[3]]) >> 1),
+                _ if body.len() > 4 && body[4] == 100 => {
+                    let mut confirmation = vec![0x68, header[1]];
+                    confirmation.extend(&body);
+                    confirmation[8] = 7;
+                    let mut termination = confirmation.clone();
+                    termination[8] = 10;
+
+                    let _ = socket.write_all(&[0x68, 4, 0x43, 0, 0, 0]).await;
+                    for frame in [confirmation, SCALED_SEQUENCE.to_vec(), SINGLE_POINTS.to_vec(), FLOAT_SPONTANEOUS.to_vec(), termination] {
+                        let mut frame = frame;
+                        frame[2..4].copy_from_slice(&(sent << 1).to_le_bytes());
+                        sent += 1;
+                        let _ = socket.write_all(&frame).await;
+                    }
+                },
+                _ => {},
+            }
+        }
+    });
+    Ok(port)
+}
+
+#[tokio::test]
+async fn test_interrogation_logs_mapped_tags_and_acknowledges() -> Result<(), Box<dyn Error>> {
+    let received = Arc::new(Mutex::new(Received::default()));
+    let port = spawn_outstation(received.clone()).await?;
+    let db_path = std::env::temp_dir()
+        .join(format!("iec104-protocol-{}.db", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string();
+    let db = Database::new(&db_path).await?;
+
+    let protocol = ProtocolConfig::Iec104(Iec104Config {
+        host: "127.0.0.1".to_string(),
+        port,
+        common_address: 1,
+        mode: Iec104Mode::Interrogation,
+        interrogation_interval_ms: 300_000,
+    });
+    let handle = Iec104ModeHandle::new(Iec104ModeSettings::from_protocol(&protocol).unwrap());
+    let mut client = Iec104Client::new(
+        DeviceConfig {
+            id: "rtu-1".to_string(),
+            name: "RTU 1".to_string(),
+            enabled: true,
+            protocol,
+            polling_interval_ms: 1000,
+            timeout_ms: 1000,
+            retry_count: 3,
+            tags: Vec::new(),
+            strict_types: false,
+        },
+        handle.clone(),
+    );
+    let tags = vec![tag("P total", 200, 0.1), tag("Breaker", 11, 1.0), tag("Frequency", 100, 1.0)];
+
+    client.connect().await?;
+    let started = Instant::now();
+    let entries = client.read_specific_tags(&db, &tags).await?;
+    // The termination ends the poll without waiting for the line to go quiet
+    assert!(started.elapsed() < Duration::from_millis(900), "{:?}", started.elapsed());
+
+    let summary: Vec<(&str, f64, &str)> = entries.iter().map(|e| (e.tag_name.as_str(), e.value, e.quality.as_str())).collect();
+    assert_eq!(summary, [("P total", 100.0, "Good"), ("Breaker", 0.0, "Bad"), ("Frequency", 1.5, "Good")]);
+    let diagnostics = handle.diagnostics();
+    assert_eq!((diagnostics.interrogated_values, diagnostics.spontaneous_values), (2, 1));
+
+    // Five I-frames received, all acknowledged once the poll ends, and the test frame answered
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let received = received.lock().unwrap();
+    assert_eq!(received.acknowledged, [5]);
+    assert_eq!(received.test_confirmations, 1);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
@@ -0,0 +1,309 @@
+use ava_device_logger::config::{Iec104ServerConfig, Iec104ServerPoint};
+use ava_device_logger::database::LogEntry;
+use ava_device_logger::iec104::{parse_data_unit, DataUnit, Iec104Server, InformationObject};
+use chrono::Utc;
+use std::error::Error;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const STARTDT_ACT: u8 = 0x07;
+const STARTDT_CON: u8 = 0x0B;
+const STOPDT_ACT: u8 = 0x13;
+const STOPDT_CON: u8 = 0x23;
+const TESTFR_ACT: u8 = 0x43;
+const TESTFR_CON: u8 = 0x83;
+
+/// A SCADA master speaking raw APDUs
+struct Master {
+    stream: TcpStream,
+    sent: u16,
+}
+
+impl Master {
+    async fn connect(port: u16) -> Result<Self, Box<dyn Error>> {
+        Ok(Self { stream: TcpStream::connect(("127.0.0.1", port)).await?, sent: 0 })
+    }
+
+    async fn send_u(&mut self, control: u8) -> Result<(), Box<dyn Error>> {
+        self.stream.write_all(&[0x68, 4, control, 0, 0, 0]).await?;
+        Ok(())
+    }
+
+    async fn send_asdu(&mut self, asdu: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut frame = vec![0x68, asdu.len() as u8 + 4];
+        frame.extend((self.sent << 1).to_le_bytes());
+        frame.extend([0, 0]);
+        frame.extend(asdu);
+        self.sent += 1;
+        self.stream.write_all(&frame).await?;
+        Ok(())
+    }
+
+    async fn interrogate(&mut self, common_address: u16, qualifier: u8) -> Result<(), Box<dyn Error>> {
+        let [low, high] = common_address.to_le_bytes();
+        self.send_asdu(&[100, 1, 6, 0, low, high, 0, 0, 0, qualifier]).await
+    }
+
+    /// Next I- or U-format frame; acknowledgements are skipped
+    async fn next(&mut self, wait: Duration) -> Option<Vec<u8>> {
+        loop {
+            let mut header = [0u8; 2];
+            tokio::time::timeout(wait, self.stream.read_exact(&mut header)).await.ok()?.ok()?;
+            let mut frame = header.to_vec();
+            frame.resize(header[1] as usize + 2, 0);
+            self.stream.read_exact(&mut frame[2..]).await.ok()?;
+            if frame[2] & 0x03 != 0x01 {
+                return Some(frame);
+            }
+        }
+    }
+
+    async fn frame(&mut self) -> Vec<u8> {
+        self.next(Duration::from_secs(2)).await.expect("frame from the server")
+    }
+
+    async fn unit(&mut self) -> DataUnit {
+        let frame = self.frame().await;
+        parse_data_unit(&frame).unwrap_or_else(|| panic!("not a data unit: {:02X?}", frame))
+    }
+
+    /// Objects sent with the given cause until the interrogation ends
+    async fn interrogation_objects(&mut self, cause: u8) -> Vec<(u8, InformationObject)> {
+        let confirmation = self.unit().await;
+        assert_eq!((confirmation.type_id, confirmation.cause, confirmation.negative), (100, 7, false));
+        let mut objects = Vec::new();
+        loop {
+            let unit = self.unit().await;
+            if unit.type_id == 100 {
+                assert_eq!(unit.cause, 10);
+                return objects;
+            }
+            assert_eq!(unit.cause, cause);
+            objects.extend(unit.objects.into_iter().map(|object| (unit.type_id, object)));
+        }
+    }
+
+    async fn assert_quiet(&mut self) {
+        if let Some(frame) = self.next(Duration::from_millis(200)).await {
+            panic!("unexpected frame {:02X?}", frame);
+        }
+    }
+}
+
+fn point(tag_name: &str, ioa: u32, type_id: u8, group: Option<u8>) -> Iec104ServerPoint {
+    Iec104ServerPoint {
+        device_id: "meter-1".to_string(),
+        tag_name: tag_name.to_string(),
+        ioa,
+        type_id,
+        group,
+        deadband: None,
+    }
+}
+
+fn config(points: Vec<Iec104ServerPoint>, deadband: f64) -> Iec104ServerConfig {
+    Iec104ServerConfig {
+        enabled: true,
+        port: 0,
+        common_address: 7,
+        deadband,
+        points,
+    }
+}
+
+fn entry(tag_name: &str, value: f64, quality: &str) -> LogEntry {
+    LogEntry {
+        id: None,
+        device_id: "meter-1".to_string(),
+        tag_name: tag_name.to_string(),
+        value,
+        quality: quality.to_string(),
+        timestamp: Utc::now(),
+        unit: None,
+    }
+}
+
+fn object(ioa: u32, value: f64, quality: u8) -> InformationObject {
+    InformationObject { ioa, value, quality }
+}
+
+async fn started_server(config: Iec104ServerConfig) -> Result<(Iec104Server, u16), Box<dyn Error>> {
+    let server = Iec104Server::new();
+    server.apply(config).await?;
+    let port = server.status().port.expect("listening");
+    Ok((server, port))
+}
+
+async fn started_master(port: u16) -> Result<Master, Box<dyn Error>> {
+    let mut master = Master::connect(port).await?;
+    master.send_u(STARTDT_ACT).await?;
+    assert_eq!(master.frame().await, [0x68, 4, STARTDT_CON, 0, 0, 0]);
+    Ok(master)
+}
+
+async fn wait_for(condition: impl Fn() -> bool) {
+    for _ in 0..100 {
+        if condition() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+async fn test_interrogation_serves_latest_values_by_group() -> Result<(), Box<dyn Error>> {
+    let (server, port) = started_server(config(
+        vec![
+            point("P total", 100, 13, Some(1)),
+            point("Breaker", 10, 1, Some(2)),
+            point("Energy", 200, 11, None),
+            point("Not logged yet", 300, 13, Some(1)),
+        ],
+        0.0,
+    ))
+    .await?;
+    server.publish(&[
+        entry("P total", 12.5, "Good"),
+        entry("Breaker", 1.0, "Good"),
+        entry("Energy", 40_000.0, "Uncertain"),
+        entry("Unmapped", 3.0, "Good"),
+    ]);
+    let mut master = started_master(port).await?;
+
+    // Station interrogation: every point, grouped into one ASDU per type
+    master.interrogate(7, 20).await?;
+    let objects = master.interrogation_objects(20).await;
+    assert_eq!(
+        objects,
+        [
+            (1, object(10, 1.0, 0)),
+            // Out of 16-bit range: clamped and flagged as overflow, besides not topical
+            (11, object(200, 32767.0, 0x41)),
+            (13, object(100, 12.5, 0)),
+            // No value yet: invalid and not topical
+            (13, object(300, 0.0, 0xC0)),
+        ]
+    );
+
+    // Group 1 only, through the global common address
+    master.interrogate(0xFFFF, 21).await?;
+    let objects = master.interrogation_objects(21).await;
+    assert_eq!(objects, [(13, object(100, 12.5, 0)), (13, object(300, 0.0, 0xC0))]);
+
+    // Another station's common address is refused
+    master.interrogate(8, 20).await?;
+    let refusal = master.unit().await;
+    assert_eq!((refusal.type_id, refusal.cause, refusal.negative), (100, 46, true));
+
+    // Read command for one point, and for an address that isn't served
+    master.send_asdu(&[102, 1, 5, 0, 7, 0, 100, 0, 0]).await?;
+    let read = master.unit().await;
+    assert_eq!((read.type_id, read.cause, read.objects), (13, 5, vec![object(100, 12.5, 0)]));
+    master.send_asdu(&[102, 1, 5, 0, 7, 0, 0xE7, 0x03, 0]).await?;
+    let unknown = master.unit().await;
+    assert_eq!((unknown.type_id, unknown.cause, unknown.negative), (102, 47, true));
+
+    master.send_u(TESTFR_ACT).await?;
+    assert_eq!(master.frame().await, [0x68, 4, TESTFR_CON, 0, 0, 0]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_spontaneous_updates_follow_deadband_and_data_transfer() -> Result<(), Box<dyn Error>> {
+    let mut exact = point("Breaker", 10, 1, None);
+    exact.deadband = Some(0.0);
+    let (server, port) = started_server(config(vec![point("P total", 100, 13, None), exact], 1.0)).await?;
+
+    let mut first = started_master(port).await?;
+    // Connected, but data transfer not started
+    let mut second = Master::connect(port).await?;
+    wait_for(|| server.status().masters == 2).await;
+    let status = server.status();
+    assert_eq!((status.masters, status.active_masters), (2, 1));
+
+    server.publish(&[entry("P total", 10.0, "Good")]);
+    let unit = first.unit().await;
+    assert_eq!((unit.type_id, unit.cause, unit.objects), (13, 3, vec![object(100, 10.0, 0)]));
+
+    // Within the deadband of the value last sent, then beyond it
+    server.publish(&[entry("P total", 10.5, "Good"), entry("P total", 10.9, "Good")]);
+    first.assert_quiet().await;
+    server.publish(&[entry("P total", 11.5, "Good")]);
+    assert_eq!(first.unit().await.objects, [object(100, 11.5, 0)]);
+
+    // A quality change is sent regardless of the deadband, and muted samples never are
+    server.publish(&[entry("P total", 11.5, "Bad"), entry("Breaker", 1.0, "muted")]);
+    assert_eq!(first.unit().await.objects, [object(100, 11.5, 0x80)]);
+    first.assert_quiet().await;
+
+    // A point's own deadband overrides the server's
+    server.publish(&[entry("Breaker", 1.0, "Good")]);
+    let unit = first.unit().await;
+    assert_eq!((unit.type_id, unit.objects), (1, vec![object(10, 1.0, 0)]));
+    second.assert_quiet().await;
+
+    second.send_u(STARTDT_ACT).await?;
+    assert_eq!(second.frame().await, [0x68, 4, STARTDT_CON, 0, 0, 0]);
+    server.publish(&[entry("Breaker", 0.0, "Good")]);
+    assert_eq!(first.unit().await.objects, [object(10, 0.0, 0)]);
+    assert_eq!(second.unit().await.objects, [object(10, 0.0, 0)]);
+
+    // After STOPDT the first master gets nothing until it starts again
+    first.send_u(STOPDT_ACT).await?;
+    assert_eq!(first.frame().await, [0x68, 4, STOPDT_CON, 0, 0, 0]);
+    server.publish(&[entry("Breaker", 1.0, "Good")]);
+    assert_eq!(second.unit().await.objects, [object(10, 1.0, 0)]);
+    first.assert_quiet().await;
+    assert_eq!(server.status().active_masters, 1);
+
+    drop(second);
+    wait_for(|| server.status().masters == 1).await;
+    let status = server.status();
+    assert_eq!((status.masters, status.active_masters), (1, 0));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_reconfiguring_drops_masters_and_keeps_mapped_values() -> Result<(), Box<dyn Error>> {
+    let (server, port) = started_server(config(vec![point("P total", 100, 13, None), point("Q total", 101, 13, None)], 0.0)).await?;
+    server.publish(&[entry("P total", 5.0, "Good"), entry("Q total", 2.0, "Good")]);
+    let mut master = started_master(port).await?;
+
+    // Invalid maps are refused and leave the server as it was
+    let duplicate = config(vec![point("P total", 100, 13, None), point("Q total", 100, 13, None)], 0.0);
+    let error = server.apply(duplicate).await.unwrap_err();
+    assert_eq!(error.to_string(), "IOA 100 is mapped more than once");
+    let mut double_point = config(vec![point("P total", 100, 3, None)], 0.0);
+    assert!(server.apply(double_point.clone()).await.unwrap_err().to_string().contains("type 3 can't be served"));
+    double_point.points[0].type_id = 13;
+    double_point.points[0].group = Some(17);
+    assert!(server.apply(double_point).await.is_err());
+    assert_eq!(server.status().port, Some(port));
+    master.send_u(TESTFR_ACT).await?;
+    assert_eq!(master.frame().await, [0x68, 4, TESTFR_CON, 0, 0, 0]);
+
+    // Applying restarts the listener; the connected master is dropped
+    server.apply(config(vec![point("P total", 500, 11, None)], 0.0)).await?;
+    assert_eq!(master.next(Duration::from_secs(2)).await, None);
+    let status = server.status();
+    assert_eq!((status.points, status.points_with_value), (1, 1));
+
+    let mut master = started_master(status.port.unwrap()).await?;
+    master.interrogate(7, 20).await?;
+    assert_eq!(master.interrogation_objects(20).await, [(11, object(500, 5.0, 0))]);
+
+    // A port already taken is reported instead of served
+    let taken = std::net::TcpListener::bind("0.0.0.0:0")?;
+    let mut conflicting = config(Vec::new(), 0.0);
+    conflicting.port = taken.local_addr()?.port();
+    assert!(server.apply(conflicting).await.is_err());
+    let status = server.status();
+    assert!(!status.listening);
+    assert!(status.error.unwrap().contains("Failed to listen on port"));
+
+    server.apply(Iec104ServerConfig::default()).await?;
+    let status = server.status();
+    assert!(!status.listening && status.error.is_none());
+    Ok(())
+}
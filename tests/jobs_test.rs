@@ -0,0 +1,200 @@
+mod support;
+
+use ava_device_logger::database::{Database, DeviceInstance, FailedRequest, Job, JobItemResult, JobState, SyncReport};
+use chrono::{Duration, Utc};
+use serde_json::{json, Value};
+use std::error::Error;
+use support::Logger;
+use wiremock::matchers::{method, path, path_regex};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn job(id: &str, state: JobState, created_at: chrono::DateTime<Utc>) -> Job {
+    Job {
+        id: id.to_string(),
+        kind: "sync".to_string(),
+        entity_group_id: Some("group-1".to_string()),
+        description: "Sync devices to entity group group-1".to_string(),
+        requested_by: Some("admin".to_string()),
+        state,
+        progress_current: 0,
+        progress_total: 0,
+        items: Vec::new(),
+        result: None,
+        error: None,
+        report: None,
+        created_at,
+        started_at: None,
+        finished_at: None,
+    }
+}
+
+#[tokio::test]
+async fn test_unfinished_jobs_fail_and_keep_their_progress() -> Result<(), Box<dyn Error>> {
+    let db_path = std::env::temp_dir()
+        .join(format!("jobs-{}.db", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string();
+    let db = Database::new(&db_path).await?;
+    let now = Utc::now();
+
+    let mut completed = job("job-done", JobState::Completed, now - Duration::minutes(10));
+    completed.result = Some(json!({"total_devices": 0}));
+    db.save_job(&completed).await?;
+
+    let mut running = job("job-running", JobState::Running, now);
+    running.progress_current = 1;
+    running.progress_total = 3;
+    running.items.push(JobItemResult { name: "Inverter A".to_string(), outcome: "created".to_string(), error: None });
+    running.report = Some(SyncReport {
+        requests: 4,
+        throttle_pauses: 0,
+        failed_requests: vec![FailedRequest {
+            request: "POST /api/device".to_string(),
+            status: 400,
+            body: r#"{"message":"Device with such name already exists!"}"#.to_string(),
+            at: now,
+        }],
+    });
+    db.save_job(&running).await?;
+
+    assert_eq!(db.get_active_job("sync", "group-1").await?.map(|job| job.id), Some("job-running".to_string()));
+    assert!(db.get_active_job("catalog", "group-1").await?.is_none());
+    assert!(db.get_active_job("sync", "group-2").await?.is_none());
+
+    assert_eq!(db.fail_interrupted_jobs().await?, 1);
+
+    let interrupted = db.get_job("job-running").await?.expect("job");
+    assert_eq!(interrupted.state, JobState::Failed);
+    assert_eq!(interrupted.error.as_deref(), Some("Interrupted by a restart"));
+    assert!(interrupted.finished_at.is_some());
+    assert_eq!((interrupted.progress_current, interrupted.progress_total), (1, 3));
+    assert_eq!(interrupted.items, running.items);
+    assert_eq!(interrupted.report, running.report);
+
+    let done = db.get_job("job-done").await?.expect("job");
+    assert_eq!(done.state, JobState::Completed);
+    assert_eq!(done.result, Some(json!({"total_devices": 0})));
+
+    assert!(db.get_active_job("sync", "group-1").await?.is_none());
+    let ids: Vec<String> = db.get_jobs(10).await?.into_iter().map(|job| job.id).collect();
+    assert_eq!(ids, vec!["job-running", "job-done"]);
+    assert!(db.get_job("missing").await?.is_none());
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+/// ThingsBoard stand-in for an empty group `group-1` that creates the first device it is
+/// asked for and never answers the requests to create any more, so a sync stays running
+async fn spawn_tb_server() -> MockServer {
+    let server = support::thingsboard().await;
+    let groups = json!([{"id": {"id": "group-1", "entityType": "ENTITY_GROUP"}, "ownerId": {"id": "tenant", "entityType": "TENANT"}, "name": "ACCV-P002-Plant", "type": "DEVICE", "groupAll": false, "edgeGroupAll": false}]);
+    Mock::given(method("GET")).and(path("/api/entityGroups/DEVICE")).respond_with(ResponseTemplate::new(200).set_body_json(groups)).mount(&server).await;
+    let devices = json!({"data": [], "totalPages": 1, "totalElements": 0, "hasNext": false});
+    Mock::given(method("GET")).and(path("/api/entityGroup/group-1/devices")).respond_with(ResponseTemplate::new(200).set_body_json(devices)).mount(&server).await;
+    let created = ResponseTemplate::new(200).set_body_json(json!({"id": {"id": "tb-1", "entityType": "DEVICE"}, "name": "ACCV-P002-I01", "type": "Inverter"}));
+    Mock::given(method("POST")).and(path("/api/device")).respond_with(created.clone()).up_to_n_times(1).mount(&server).await;
+    Mock::given(method("POST")).and(path("/api/device")).respond_with(created.set_delay(std::time::Duration::from_secs(600))).mount(&server).await;
+    Mock::given(method("POST")).and(path_regex("^/api/plugins/telemetry/")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+    server
+}
+
+fn device(id: &str, name: &str) -> DeviceInstance {
+    DeviceInstance {
+        id: id.to_string(),
+        name: name.to_string(),
+        serial_no: None,
+        model_id: None,
+        enabled: false,
+        polling_interval_ms: 1000,
+        timeout_ms: 1000,
+        retry_count: 1,
+        protocol_config: json!({"type": "modbus_tcp", "host": "127.0.0.1", "port": 502, "slave_id": 1}).to_string(),
+        tb_device_id: None,
+        tb_group_id: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    }
+}
+
+#[tokio::test]
+async fn test_sync_job_reports_progress_and_is_failed_by_a_restart() -> Result<(), Box<dyn Error>> {
+    let tb = spawn_tb_server().await;
+    let tb_url = tb.uri();
+
+    let work_dir = support::work_dir("jobs")?;
+    {
+        let db = Database::new(&work_dir.join("data.db").to_string_lossy()).await?;
+        db.create_device(&device("inv-a", "Inverter A")).await?;
+        db.create_device(&device("inv-b", "Inverter B")).await?;
+        db.create_device(&device("inv-c", "Inverter C")).await?;
+    }
+
+    let extra_config = format!(
+        r#"
+[thingsboard]
+base_url = "{tb_url}"
+username = "tenant@example.com"
+password = "secret"
+retry_max_attempts = 1
+"#
+    );
+    let mut logger = Logger::start_in(work_dir, &extra_config).await?;
+    let (client, base_url, token) = (logger.client.clone(), logger.base_url.clone(), logger.token.clone());
+    let sync = |entity_group_id: &str| {
+        client
+            .post(format!("{}/api/sync-devices-to-thingsboard", base_url))
+            .bearer_auth(&token)
+            .json(&json!({"entity_group_id": entity_group_id}))
+            .send()
+    };
+
+    let response: Value = sync("group-1").await?.json().await?;
+    assert_eq!(response["success"], true, "{}", response);
+    let job_id = response["data"]["id"].as_str().expect("job id").to_string();
+    assert_eq!(response["data"]["kind"], "sync");
+
+    // Asking again for the same group returns the job already running
+    let again: Value = sync("group-1").await?.json().await?;
+    assert_eq!(again["data"]["id"], job_id.as_str(), "{}", again);
+
+    // Another group's sync still conflicts with it
+    assert_eq!(sync("group-2").await?.status(), reqwest::StatusCode::CONFLICT);
+
+    // The first device is created, the second never answers
+    let job_url = format!("{}/api/jobs/{}", base_url, job_id);
+    let mut job = Value::Null;
+    for _ in 0..100 {
+        job = client.get(&job_url).bearer_auth(&token).send().await?.json().await?;
+        if job["data"]["progress_current"] == 1 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert_eq!(job["data"]["state"], "running", "{}", job);
+    assert_eq!(job["data"]["progress_total"], 3);
+    assert_eq!(job["data"]["items"][0]["name"], "Inverter A");
+    assert_eq!(job["data"]["items"][0]["outcome"], "created");
+    // Login, entity groups, group devices and the device created so far
+    assert!(job["data"]["report"]["requests"].as_u64() >= Some(4), "{}", job);
+    assert_eq!(job["data"]["report"]["failed_requests"], json!([]));
+
+    // The job can't survive a restart, but what it did stays readable
+    logger.restart().await?;
+    let token = &logger.token;
+
+    let job: Value = client.get(&job_url).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(job["data"]["state"], "failed", "{}", job);
+    assert_eq!(job["data"]["error"], "Interrupted by a restart");
+    assert_eq!(job["data"]["progress_current"], 1);
+    assert_eq!(job["data"]["items"].as_array().map(Vec::len), Some(1));
+    assert!(job["data"]["report"]["requests"].as_u64() >= Some(4), "{}", job);
+
+    let jobs: Value = client.get(format!("{}/api/jobs", base_url)).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(jobs["data"][0]["id"], job_id.as_str(), "{}", jobs);
+
+    let missing = client.get(format!("{}/api/jobs/unknown", base_url)).bearer_auth(token).send().await?;
+    assert_eq!(missing.status(), reqwest::StatusCode::NOT_FOUND);
+    Ok(())
+}
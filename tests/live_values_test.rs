@@ -0,0 +1,78 @@
+use ava_device_logger::database::LogEntry;
+use ava_device_logger::live_values::LastValueCache;
+use chrono::{Duration, Utc};
+
+fn entry(device_id: &str, tag_name: &str, value: f64, quality: &str, seconds_ago: i64) -> LogEntry {
+    LogEntry {
+        id: None,
+        device_id: device_id.to_string(),
+        tag_name: tag_name.to_string(),
+        value,
+        quality: quality.to_string(),
+        timestamp: Utc::now() - Duration::seconds(seconds_ago),
+        unit: Some("V".to_string()),
+    }
+}
+
+fn summary(cache: &LastValueCache, device_id: &str) -> Vec<(String, f64, String, bool)> {
+    cache
+        .device_values(device_id)
+        .values
+        .into_iter()
+        .map(|value| (value.tag_name, value.value, value.quality, value.stale))
+        .collect()
+}
+
+#[test]
+fn test_cache_keeps_the_last_value_of_each_tag() {
+    let cache = LastValueCache::new();
+    assert!(cache.device_values("meter-1").values.is_empty());
+
+    cache.update(&[entry("meter-1", "Voltage", 229.0, "Good", 10), entry("meter-1", "Current", 4.0, "Good", 10)]);
+    cache.update(&[entry("meter-1", "Voltage", 230.0, "Good", 2), entry("meter-2", "Voltage", 400.0, "Good", 2)]);
+
+    let values = cache.device_values("meter-1").values;
+    assert_eq!(summary(&cache, "meter-1"), [
+        ("Current".to_string(), 4.0, "Good".to_string(), false),
+        ("Voltage".to_string(), 230.0, "Good".to_string(), false),
+    ]);
+    assert_eq!(values[1].unit.as_deref(), Some("V"));
+    assert!((2000..3000).contains(&values[1].age_ms));
+
+    // A failed read keeps the last value with its quality; a muted tag is left alone
+    cache.update(&[entry("meter-1", "Voltage", 0.0, "Bad", 0), entry("meter-1", "Current", 0.0, "muted", 0)]);
+    assert_eq!(summary(&cache, "meter-1"), [
+        ("Current".to_string(), 4.0, "Good".to_string(), false),
+        ("Voltage".to_string(), 230.0, "Bad".to_string(), false),
+    ]);
+
+    let devices: Vec<String> = cache.all_values().into_iter().map(|device| device.device_id).collect();
+    assert_eq!(devices, ["meter-1", "meter-2"]);
+
+    cache.remove_device("meter-2");
+    assert!(cache.device_values("meter-2").values.is_empty());
+}
+
+#[test]
+fn test_values_of_stopped_or_disconnected_devices_are_stale() {
+    let cache = LastValueCache::new();
+    cache.update(&[entry("meter-1", "Voltage", 230.0, "Good", 1), entry("meter-1", "Energy", 12.5, "Good", 1)]);
+
+    // A lost connection only affects the schedule group's own tags
+    cache.mark_stale("meter-1", Some(&["Energy".to_string()]), "Connection reset");
+    let values = cache.device_values("meter-1").values;
+    assert_eq!(values[0].tag_name, "Energy");
+    assert!(values[0].stale);
+    assert_eq!(values[0].stale_reason.as_deref(), Some("Connection reset"));
+    assert!(!values[1].stale);
+
+    cache.mark_stale("meter-1", None, "Device stopped");
+    assert!(cache.device_values("meter-1").values.iter().all(|value| value.stale_reason.as_deref() == Some("Device stopped")));
+
+    // Fresh values clear it again
+    cache.update(&[entry("meter-1", "Voltage", 231.0, "Good", 0)]);
+    assert_eq!(summary(&cache, "meter-1"), [
+        ("Energy".to_string(), 12.5, "Stale".to_string(), true),
+        ("Voltage".to_string(), 231.0, "Good".to_string(), false),
+    ]);
+}
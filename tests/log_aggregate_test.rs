@@ -0,0 +1,114 @@
+mod support;
+
+use ava_device_logger::database::{AggregateBucket, AggregateFunction, Database, LogEntry};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde_json::{json, Value};
+use std::error::Error;
+use support::Logger;
+
+fn entry(tag_name: &str, value: f64, quality: &str, timestamp: DateTime<Utc>) -> LogEntry {
+    LogEntry {
+        id: None,
+        device_id: "inv-1".to_string(),
+        tag_name: tag_name.to_string(),
+        value,
+        quality: quality.to_string(),
+        timestamp,
+        unit: Some("kW".to_string()),
+    }
+}
+
+#[tokio::test]
+async fn test_samples_are_bucketed_per_function() -> Result<(), Box<dyn Error>> {
+    let db_path = std::env::temp_dir()
+        .join(format!("log-aggregate-{}.db", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string();
+    let db = Database::new(&db_path).await?;
+
+    // Two samples in the first 5 minutes, the rest in the third; the second bucket is empty
+    let start = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+    for (offset_seconds, value) in [(10, 4.0), (200, 8.0), (610, 1.0), (700, 3.0), (890, 2.0)] {
+        db.insert_log_entries(&[entry("Pac", value, "Good", start + Duration::seconds(offset_seconds))]).await?;
+    }
+    // Sub-second timestamps, bad samples and other tags stay out of the buckets
+    db.insert_log_entries(&[entry("Pac", 5.0, "Good", start + Duration::milliseconds(899_250))]).await?;
+    db.insert_log_entries(&[entry("Pac", 0.0, "Bad", start + Duration::seconds(20))]).await?;
+    db.insert_log_entries(&[entry("Vdc", 600.0, "Good", start + Duration::seconds(30))]).await?;
+
+    let end = start + Duration::minutes(15);
+    let aggregate = |function| db.get_aggregated_log_entries("inv-1", "Pac", start, end, 300, function, false);
+
+    let avg = aggregate(AggregateFunction::Avg).await?;
+    assert_eq!(avg.len(), 2);
+    assert_eq!(avg[0].bucket_start, start);
+    assert_eq!(avg[1].bucket_start, start + Duration::minutes(10));
+    assert_eq!((avg[0].value, avg[0].samples), (6.0, 2));
+    assert_eq!((avg[1].value, avg[1].samples), (11.0 / 4.0, 4));
+
+    let values = |buckets: Vec<AggregateBucket>| buckets.iter().map(|b| b.value).collect::<Vec<_>>();
+    assert_eq!(values(aggregate(AggregateFunction::Min).await?), vec![4.0, 1.0]);
+    assert_eq!(values(aggregate(AggregateFunction::Max).await?), vec![8.0, 5.0]);
+    assert_eq!(values(aggregate(AggregateFunction::Last).await?), vec![8.0, 5.0]);
+    assert_eq!(values(aggregate(AggregateFunction::Count).await?), vec![2.0, 4.0]);
+
+    // The range end is exclusive
+    let first_bucket_only = db
+        .get_aggregated_log_entries("inv-1", "Pac", start, start + Duration::minutes(5), 300, AggregateFunction::Count, false)
+        .await?;
+    assert_eq!(values(first_bucket_only), vec![2.0]);
+
+    // Failed reads count when asked for; other qualities always do
+    db.insert_log_entries(&[entry("Pac", 0.0, "CommFail", start + Duration::seconds(40))]).await?;
+    db.insert_log_entries(&[entry("Pac", 6.0, "Uncertain", start + Duration::seconds(50))]).await?;
+    let min = aggregate(AggregateFunction::Min).await?;
+    assert_eq!((min[0].value, min[0].samples), (4.0, 3));
+    let with_bad = db
+        .get_aggregated_log_entries("inv-1", "Pac", start, end, 300, AggregateFunction::Min, true)
+        .await?;
+    assert_eq!((with_bad[0].value, with_bad[0].samples), (0.0, 5));
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_endpoint_validates_interval_and_bucket_count() -> Result<(), Box<dyn Error>> {
+
+    let logger = Logger::start("").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let aggregate = |query: &'static str| {
+        let request = client
+            .get(format!("{}/api/logs/inv-1/aggregate?{}", base_url, query))
+            .bearer_auth(token);
+        async move { request.send().await?.json::<Value>().await }
+    };
+
+    let body = aggregate("tag_name=Pac&interval=5m&fn=max&start=2026-03-01T00:00:00Z&end=2026-03-02T00:00:00Z").await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!(body["data"]["function"], "max");
+    assert_eq!(body["data"]["interval_seconds"], 300);
+    assert_eq!(body["data"]["buckets"], json!([]));
+
+    // A day at 1 second per bucket is far over the cap
+    let body = aggregate("tag_name=Pac&interval=1s&start=2026-03-01T00:00:00Z&end=2026-03-02T00:00:00Z").await?;
+    assert_eq!(body["success"], false);
+    assert!(body["error"].as_str().unwrap().contains("buckets"), "{}", body);
+
+    for interval in ["5", "m", "0m", "-5m", "5w", "1.5h"] {
+        let body = client
+            .get(format!("{}/api/logs/inv-1/aggregate", base_url))
+            .query(&[("tag_name", "Pac"), ("interval", interval)])
+            .bearer_auth(token)
+            .send()
+            .await?
+            .json::<Value>()
+            .await?;
+        assert_eq!(body["success"], false, "{} was accepted", interval);
+    }
+
+    let body = aggregate("tag_name=Pac&interval=1h&start=2026-03-02T00:00:00Z&end=2026-03-01T00:00:00Z").await?;
+    assert_eq!(body["success"], false);
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use ava_device_logger::database::{Database, LogEntry};
+use chrono::{Duration, TimeZone, Utc};
+use std::error::Error;
+use std::time::Instant;
+
+fn temp_db_path() -> String {
+    std::env::temp_dir()
+        .join(format!("log-batch-insert-{}.db", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string()
+}
+
+/// One poll of a device with `tags` tags
+fn poll(cycle: i64, tags: usize) -> Vec<LogEntry> {
+    let timestamp = Utc.with_ymd_and_hms(2026, 3, 1, 0, 0, 0).unwrap() + Duration::seconds(cycle);
+    (0..tags)
+        .map(|tag| LogEntry {
+            id: None,
+            device_id: "inv-1".to_string(),
+            tag_name: format!("tag_{}", tag),
+            value: tag as f64,
+            quality: "Good".to_string(),
+            timestamp,
+            unit: Some("kW".to_string()),
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_invalid_entries_are_skipped_without_losing_the_batch() -> Result<(), Box<dyn Error>> {
+    let db_path = temp_db_path();
+    let db = Database::new(&db_path).await?;
+
+    // NaN is stored as NULL and fails the NOT NULL constraint on value
+    let mut entries = poll(0, 5);
+    entries[2].value = f64::NAN;
+    assert_eq!(db.insert_log_entries(&entries).await?, 4);
+    assert_eq!(db.insert_log_entries(&[]).await?, 0);
+
+    let logged = db.get_log_entries(Some("inv-1"), None, None).await?;
+    let mut tags: Vec<&str> = logged.iter().map(|entry| entry.tag_name.as_str()).collect();
+    tags.sort();
+    assert_eq!(tags, vec!["tag_0", "tag_1", "tag_3", "tag_4"]);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_batched_inserts_sustain_ten_thousand_entries_per_second() -> Result<(), Box<dyn Error>> {
+    let db_path = temp_db_path();
+    let db = Database::new(&db_path).await?;
+
+    // 200 polls of a 100-tag device
+    let polls: Vec<Vec<LogEntry>> = (0..200).map(|cycle| poll(cycle, 100)).collect();
+    let started = Instant::now();
+    let mut written = 0;
+    for entries in &polls {
+        written += db.insert_log_entries(entries).await?;
+    }
+    let elapsed = started.elapsed();
+
+    assert_eq!(written, 20_000);
+    assert_eq!(db.count_log_entries(Some("inv-1"), None, None).await?, 20_000);
+    let rate = written as f64 / elapsed.as_secs_f64();
+    assert!(rate >= 10_000.0, "{:.0} entries/s ({} in {:?})", rate, written, elapsed);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[test]
+fn test_failed_reads_are_logged_without_a_value_and_muted_tags_are_not_logged() {
+    let mut entry = poll(0, 1).remove(0);
+    assert!(entry.is_logged() && entry.has_value());
+    for quality in ["type_mismatch", "Uncertain", "Stale", "computed"] {
+        entry.quality = quality.to_string();
+        assert!(entry.is_logged() && entry.has_value(), "{}", quality);
+    }
+    for quality in ["Bad", "CommFail"] {
+        entry.quality = quality.to_string();
+        assert!(entry.is_logged() && !entry.has_value(), "{}", quality);
+    }
+    entry.quality = "muted".to_string();
+    assert!(!entry.is_logged() && !entry.has_value());
+}
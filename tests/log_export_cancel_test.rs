@@ -0,0 +1,64 @@
+mod support;
+
+use ava_device_logger::database::Database;
+use serde_json::Value;
+use std::error::Error;
+use std::time::Duration;
+use support::Logger;
+
+async fn operation_stats(client: &reqwest::Client, base_url: &str, token: &str) -> Result<Value, Box<dyn Error>> {
+    let status: Value = client
+        .get(format!("{}/api/status", base_url))
+        .bearer_auth(token)
+        .send()
+        .await?
+        .json()
+        .await?;
+    Ok(status["data"]["database_operations"].clone())
+}
+
+#[tokio::test]
+async fn test_export_stops_when_client_disconnects() -> Result<(), Box<dyn Error>> {
+    let work_dir = support::work_dir("log-export")?;
+
+    // Enough rows that the export is still streaming when the client leaves
+    let db_path = work_dir.join("data.db").to_string_lossy().to_string();
+    drop(Database::new(&db_path).await?);
+    rusqlite::Connection::open(&db_path)?.execute(
+        "INSERT INTO log_entries (device_id, tag_name, value, quality, timestamp, unit)
+         WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 300000)
+         SELECT 'meter-1', 'active_power', i, 'good', strftime('%Y-%m-%dT%H:%M:%S+00:00', 1700000000 + i, 'unixepoch'), 'kW' FROM n",
+        [],
+    )?;
+
+    // Kept well above the seeded rows so the cleanup doesn't trim them
+    let config = |port| support::config(port, "").replace("max_log_entries = 1000\n", "max_log_entries = 10000000\n");
+    let logger = Logger::start_with_config(work_dir, config).await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let mut export = client
+        .get(format!("{}/api/logs/export?device_id=meter-1", base_url))
+        .bearer_auth(token)
+        .send()
+        .await?;
+    assert_eq!(export.status(), 200);
+    let first_chunk = export.chunk().await?.expect("first chunk");
+    assert!(String::from_utf8_lossy(&first_chunk).starts_with("timestamp,device_id,tag_name"));
+    assert_eq!(operation_stats(client, base_url, token).await?["active"], 1);
+
+    // Close the tab mid-download
+    drop(export);
+
+    let mut stats = Value::Null;
+    for _ in 0..50 {
+        stats = operation_stats(client, base_url, token).await?;
+        if stats["active"] == 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(stats["active"], 0, "export still running: {}", stats);
+    assert_eq!(stats["cancelled"], 1);
+    assert_eq!(stats["completed"], 0);
+    Ok(())
+}
@@ -0,0 +1,71 @@
+mod support;
+
+use ava_device_logger::database::{Database, LogEntry};
+use chrono::{Duration, TimeZone, Utc};
+use serde_json::{json, Value};
+use std::error::Error;
+use support::Logger;
+
+#[tokio::test]
+async fn test_export_formats_and_range_validation() -> Result<(), Box<dyn Error>> {
+    let work_dir = support::work_dir("log-export-format")?;
+
+    let start = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+    let db = Database::new(&work_dir.join("data.db").to_string_lossy()).await?;
+    let entries: Vec<LogEntry> = (0..3)
+        .map(|i| LogEntry {
+            id: None,
+            device_id: if i < 2 { "meter-1" } else { "meter-2" }.to_string(),
+            tag_name: "active_power".to_string(),
+            value: 10.0 + i as f64,
+            quality: "Good".to_string(),
+            timestamp: start + Duration::minutes(i),
+            unit: if i == 0 { Some("kW".to_string()) } else { None },
+        })
+        .collect();
+    db.insert_log_entries(&entries).await?;
+    drop(db);
+
+    let logger = Logger::start_in(work_dir, "").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let export = |query: &'static str| {
+        let request = client
+            .get(format!("{}/api/logs/export?{}", base_url, query))
+            .bearer_auth(token);
+        async move { request.send().await }
+    };
+
+    let response = export("device_id=meter-1").await?;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-disposition"], "attachment; filename=\"logs-meter-1.csv\"");
+    let csv = response.text().await?;
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "timestamp,device_id,tag_name,value,unit,quality");
+    assert_eq!(lines[1], "2026-03-01T12:00:00+00:00,meter-1,active_power,10,kW,Good");
+    assert_eq!(lines.len(), 3);
+
+    let response = export("format=json&start=2026-03-01T12:01:00Z").await?;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "application/x-ndjson");
+    assert_eq!(response.headers()["content-disposition"], "attachment; filename=\"logs.ndjson\"");
+    let rows: Vec<Value> = response.text().await?.lines().map(serde_json::from_str).collect::<Result<_, _>>()?;
+    assert_eq!(rows.len(), 2);
+    assert_eq!(
+        rows[0],
+        json!({"timestamp": "2026-03-01T12:01:00+00:00", "device_id": "meter-1", "tag_name": "active_power", "value": 11.0, "unit": null, "quality": "Good"})
+    );
+    assert_eq!(rows[1]["device_id"], "meter-2");
+
+    // Nothing matches: still a file, CSV with just its header
+    let csv = export("device_id=missing").await?.text().await?;
+    assert_eq!(csv, "timestamp,device_id,tag_name,value,unit,quality\n");
+    assert_eq!(export("device_id=missing&format=json").await?.text().await?, "");
+
+    let response = export("start=2026-03-02T00:00:00Z&end=2026-03-01T00:00:00Z").await?;
+    assert_eq!(response.status(), 400);
+    assert_eq!(response.json::<Value>().await?["success"], false);
+    assert_eq!(export("start=yesterday").await?.status(), 400);
+    assert_eq!(export("format=xml").await?.status(), 400);
+    Ok(())
+}
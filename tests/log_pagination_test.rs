@@ -0,0 +1,55 @@
+use ava_device_logger::database::{Database, LogEntry};
+use chrono::{Duration, TimeZone, Utc};
+use std::error::Error;
+
+#[tokio::test]
+async fn test_offset_pages_and_counts_follow_the_device_filter() -> Result<(), Box<dyn Error>> {
+    let db_path = std::env::temp_dir()
+        .join(format!("log-pagination-{}.db", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string();
+    let db = Database::new(&db_path).await?;
+
+    let start = Utc.with_ymd_and_hms(2026, 3, 1, 0, 0, 0).unwrap();
+    for i in 0..25 {
+        for device_id in ["meter-1", "meter-2"] {
+            if device_id == "meter-2" && i >= 5 {
+                continue;
+            }
+            db.insert_log_entries(&[LogEntry {
+                id: None,
+                device_id: device_id.to_string(),
+                tag_name: "active_power".to_string(),
+                value: i as f64,
+                quality: "Good".to_string(),
+                timestamp: start + Duration::minutes(i),
+                unit: Some("kW".to_string()),
+            }]).await?;
+        }
+    }
+
+    assert_eq!(db.count_log_entries(None, None, None).await?, 30);
+    assert_eq!(db.count_log_entries(Some("meter-1"), None, None).await?, 25);
+    assert_eq!(db.count_log_entries(Some("meter-2"), None, None).await?, 5);
+    assert_eq!(db.count_log_entries(Some("missing"), None, None).await?, 0);
+    assert_eq!(
+        db.count_log_entries(Some("meter-1"), Some(start + Duration::minutes(10)), Some(start + Duration::minutes(20))).await?,
+        10
+    );
+
+    // Pages are newest first and don't overlap
+    let first = db.get_log_entries(Some("meter-1"), Some(10), Some(0)).await?;
+    let second = db.get_log_entries(Some("meter-1"), Some(10), Some(10)).await?;
+    let last = db.get_log_entries(Some("meter-1"), Some(10), Some(20)).await?;
+    assert_eq!(first.iter().map(|e| e.value).collect::<Vec<_>>(), (15..25).rev().map(f64::from).collect::<Vec<_>>());
+    assert_eq!(second.iter().map(|e| e.value).collect::<Vec<_>>(), (5..15).rev().map(f64::from).collect::<Vec<_>>());
+    assert_eq!(last.len(), 5);
+    assert!(last.iter().all(|e| e.device_id == "meter-1"));
+
+    // Offset without a limit skips rows; offset past the end is an empty page
+    assert_eq!(db.get_log_entries(None, None, Some(28)).await?.len(), 2);
+    assert!(db.get_log_entries(Some("meter-1"), Some(10), Some(100)).await?.is_empty());
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
@@ -0,0 +1,166 @@
+mod support;
+
+use ava_device_logger::database::{Database, DeviceInstance, DeviceTag, TagWritePolicy};
+use ava_device_logger::metrics::Metrics;
+use chrono::Utc;
+use serde_json::json;
+use std::error::Error;
+use std::time::{Duration, Instant};
+use support::{Logger, ModbusDevice};
+
+fn device(id: &str, port: u16) -> DeviceInstance {
+    DeviceInstance {
+        id: id.to_string(),
+        name: id.to_string(),
+        serial_no: None,
+        model_id: None,
+        enabled: true,
+        polling_interval_ms: 500,
+        timeout_ms: 300,
+        retry_count: 1,
+        protocol_config: json!({"type": "modbus_tcp", "host": "127.0.0.1", "port": port, "slave_id": 1}).to_string(),
+        tb_device_id: None,
+        tb_group_id: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    }
+}
+
+fn tag(device_id: &str) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: device_id.to_string(),
+        name: "Active Power".to_string(),
+        address: 40001,
+        size: 1,
+        data_type: "uint16".to_string(),
+        description: None,
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: None,
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy: TagWritePolicy::AdminOnly,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+/// Value of one series in a scrape, by its name and labels exactly as rendered
+fn sample(metrics: &str, series: &str) -> Option<f64> {
+    metrics.lines().find_map(|line| line.strip_prefix(series)?.strip_prefix(' ')?.parse().ok())
+}
+
+#[test]
+fn test_render_exposes_device_series() {
+    let metrics = Metrics::new();
+    metrics.record_poll_success("inv-1", Duration::from_millis(20));
+    metrics.record_poll_success("inv-1", Duration::from_millis(300));
+    metrics.record_poll_failure("inv-1");
+    metrics.record_reconnect("inv-1");
+    metrics.record_log_entries_written("inv-1", 12);
+    metrics.record_telemetry_push("inv-1", true);
+    metrics.record_telemetry_push("inv-1", false);
+    metrics.set_telemetry_backlog("inv-1", 40);
+    metrics.reduce_telemetry_backlog("inv-1", 15);
+    metrics.record_poll_failure("meter \"A\"");
+
+    let text = metrics.render(Some(4096));
+    assert_eq!(sample(&text, r#"ava_device_polls_total{device="inv-1",result="success"}"#), Some(2.0));
+    assert_eq!(sample(&text, r#"ava_device_polls_total{device="inv-1",result="failure"}"#), Some(1.0));
+    assert_eq!(sample(&text, r#"ava_device_polls_total{device="meter \"A\"",result="failure"}"#), Some(1.0));
+    assert_eq!(sample(&text, r#"ava_device_reconnects_total{device="inv-1"}"#), Some(1.0));
+    assert_eq!(sample(&text, r#"ava_log_entries_written_total{device="inv-1"}"#), Some(12.0));
+    assert_eq!(sample(&text, r#"ava_telemetry_pushes_total{device="inv-1",result="success"}"#), Some(1.0));
+    assert_eq!(sample(&text, r#"ava_telemetry_pushes_total{device="inv-1",result="failure"}"#), Some(1.0));
+    assert_eq!(sample(&text, r#"ava_telemetry_backlog{device="inv-1"}"#), Some(25.0));
+    assert_eq!(sample(&text, "ava_database_size_bytes"), Some(4096.0));
+    assert!(sample(&text, "ava_process_uptime_seconds").is_some());
+
+    // Buckets are cumulative
+    assert_eq!(sample(&text, r#"ava_device_read_duration_seconds_bucket{device="inv-1",le="0.01"}"#), Some(0.0));
+    assert_eq!(sample(&text, r#"ava_device_read_duration_seconds_bucket{device="inv-1",le="0.025"}"#), Some(1.0));
+    assert_eq!(sample(&text, r#"ava_device_read_duration_seconds_bucket{device="inv-1",le="0.5"}"#), Some(2.0));
+    assert_eq!(sample(&text, r#"ava_device_read_duration_seconds_bucket{device="inv-1",le="+Inf"}"#), Some(2.0));
+    assert_eq!(sample(&text, r#"ava_device_read_duration_seconds_count{device="inv-1"}"#), Some(2.0));
+    assert!((sample(&text, r#"ava_device_read_duration_seconds_sum{device="inv-1"}"#).unwrap() - 0.32).abs() < 1e-9);
+
+    // Devices that never queued telemetry have no backlog series, and an unreadable database no size
+    assert_eq!(sample(&text, r#"ava_telemetry_backlog{device="meter \"A\""}"#), None);
+    assert_eq!(sample(&metrics.render(None), "ava_database_size_bytes"), None);
+    assert!(text.contains("# TYPE ava_device_read_duration_seconds histogram"));
+}
+
+/// Devices polling `good`, and `hung` which never answers so every poll times out
+async fn start_server(require_auth: bool, good: &ModbusDevice, hung: &ModbusDevice) -> Result<Logger, Box<dyn Error>> {
+    hung.set_silent(true);
+
+    let work_dir = support::work_dir("metrics")?;
+    let db = Database::new(&work_dir.join("data.db").to_string_lossy()).await?;
+    for (id, device_port) in [("good-1", good.port()), ("hung-1", hung.port())] {
+        db.create_device(&device(id, device_port)).await?;
+        db.create_device_tags(id, &[tag(id)]).await?;
+    }
+    drop(db);
+
+    Logger::start_in(work_dir, &format!("[metrics]\nrequire_auth = {require_auth}\n")).await
+}
+
+#[tokio::test]
+async fn test_poll_failures_are_counted_per_device() -> Result<(), Box<dyn Error>> {
+    let (good, hung) = (ModbusDevice::start([(40001, 42)]).await?, ModbusDevice::start([]).await?);
+    let logger = start_server(false, &good, &hung).await?;
+    let (client, base_url) = (&logger.client, &logger.base_url);
+
+    // Scraped without a session
+    let started = Instant::now();
+    let text = loop {
+        let response = client.get(format!("{}/metrics", base_url)).send().await?;
+        assert_eq!(response.status(), 200);
+        assert!(response.headers()["content-type"].to_str()?.starts_with("text/plain"));
+        let text = response.text().await?;
+        let hung_failures = sample(&text, r#"ava_device_polls_total{device="hung-1",result="failure"}"#).unwrap_or(0.0);
+        let good_successes = sample(&text, r#"ava_device_polls_total{device="good-1",result="success"}"#).unwrap_or(0.0);
+        if hung_failures >= 1.0 && good_successes >= 2.0 {
+            break text;
+        }
+        assert!(started.elapsed() < Duration::from_secs(20), "polls never counted:\n{}", text);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+
+    // The failing device's poll failed and it reconnected; the healthy one only succeeded
+    assert_eq!(sample(&text, r#"ava_device_polls_total{device="hung-1",result="success"}"#), Some(0.0));
+    assert_eq!(sample(&text, r#"ava_device_read_duration_seconds_count{device="hung-1"}"#), Some(0.0));
+    assert_eq!(sample(&text, r#"ava_device_polls_total{device="good-1",result="failure"}"#), Some(0.0));
+    assert_eq!(sample(&text, r#"ava_device_reconnects_total{device="good-1"}"#), Some(0.0));
+    assert!(sample(&text, r#"ava_log_entries_written_total{device="good-1"}"#).unwrap() >= 1.0);
+    assert_eq!(sample(&text, r#"ava_log_entries_written_total{device="hung-1"}"#), Some(0.0));
+    assert!(sample(&text, r#"ava_device_read_duration_seconds_count{device="good-1"}"#).unwrap() >= 2.0);
+    assert!(sample(&text, "ava_database_size_bytes").unwrap() > 0.0);
+
+    let started = Instant::now();
+    while sample(&client.get(format!("{}/metrics", base_url)).send().await?.text().await?, r#"ava_device_reconnects_total{device="hung-1"}"#).unwrap_or(0.0) < 1.0 {
+        assert!(started.elapsed() < Duration::from_secs(20), "hung device never reconnected");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_metrics_can_require_a_session() -> Result<(), Box<dyn Error>> {
+    let (good, hung) = (ModbusDevice::start([(40001, 42)]).await?, ModbusDevice::start([]).await?);
+    let logger = start_server(true, &good, &hung).await?;
+    let (client, base_url) = (&logger.client, &logger.base_url);
+
+    assert_eq!(client.get(format!("{}/metrics", base_url)).send().await?.status(), 401);
+
+    let response = client.get(format!("{}/metrics", base_url)).bearer_auth(&logger.token).send().await?;
+    assert_eq!(response.status(), 200);
+    assert!(response.text().await?.contains("ava_process_uptime_seconds"));
+    Ok(())
+}
@@ -0,0 +1,101 @@
+use ava_device_logger::config::RegisterType;
+use ava_device_logger::database::{DeviceTag, TagWritePolicy};
+use ava_device_logger::modbus::{plan_blocks, ReadBlock};
+
+fn tag(name: &str, address: u16, data_type: &str) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: "meter-1".to_string(),
+        name: name.to_string(),
+        address,
+        size: if matches!(data_type, "float32" | "uint32") { 2 } else { 1 },
+        data_type: data_type.to_string(),
+        description: None,
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: None,
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy: TagWritePolicy::Disabled,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+fn block(register_type: RegisterType, start: u16, count: u16, tags: &[usize]) -> ReadBlock {
+    ReadBlock { register_type, start, count, tags: tags.to_vec() }
+}
+
+#[test]
+fn test_adjacent_and_overlapping_tags_share_a_block() {
+    let tags = vec![
+        tag("Pac", 10, "float32"),
+        tag("Status", 12, "uint16"),
+        // Reads the low word of Pac on its own
+        tag("Pac low", 11, "uint16"),
+        tag("Energy", 13, "uint32"),
+    ];
+
+    assert_eq!(plan_blocks(&tags, 0), vec![block(RegisterType::Holding, 10, 5, &[0, 2, 1, 3])]);
+}
+
+#[test]
+fn test_gaps_over_the_threshold_split_blocks() {
+    let tags = vec![tag("Vac", 100, "uint16"), tag("Iac", 104, "uint16"), tag("Freq", 110, "uint16")];
+
+    // Without a gap allowance only touching ranges are joined
+    assert_eq!(
+        plan_blocks(&tags, 0),
+        vec![
+            block(RegisterType::Holding, 100, 1, &[0]),
+            block(RegisterType::Holding, 104, 1, &[1]),
+            block(RegisterType::Holding, 110, 1, &[2]),
+        ]
+    );
+    // 3 unused registers sit between Vac and Iac, 5 between Iac and Freq
+    assert_eq!(
+        plan_blocks(&tags, 3),
+        vec![block(RegisterType::Holding, 100, 5, &[0, 1]), block(RegisterType::Holding, 110, 1, &[2])]
+    );
+    assert_eq!(plan_blocks(&tags, 5), vec![block(RegisterType::Holding, 100, 11, &[0, 1, 2])]);
+}
+
+#[test]
+fn test_32_bit_tags_are_never_split_at_the_register_limit() {
+    // A float32 at 124 would need registers 124 and 125, one past the 125-register limit of a block from 0
+    let mut tags: Vec<DeviceTag> = (0..124).map(|address| tag(&format!("R{}", address), address, "uint16")).collect();
+    tags.push(tag("Pac", 124, "float32"));
+
+    let blocks = plan_blocks(&tags, 0);
+    assert_eq!(blocks.len(), 2);
+    assert_eq!((blocks[0].start, blocks[0].count, blocks[0].tags.len()), (0, 124, 124));
+    assert_eq!(blocks[1], block(RegisterType::Holding, 124, 2, &[124]));
+    assert!(blocks.iter().all(|b| b.count <= 125));
+}
+
+#[test]
+fn test_register_types_and_disabled_tags_are_kept_apart() {
+    let mut disabled = tag("Spare", 5, "uint16");
+    disabled.enabled = false;
+    let tags = vec![
+        tag("Breaker", 4, "coil"),
+        tag("Setpoint", 4, "holding_register"),
+        disabled,
+        tag("Measured", 6, "input_register"),
+        tag("Trip", 5, "coil"),
+        tag("Limit", 6, "uint16"),
+    ];
+
+    let blocks = plan_blocks(&tags, 0);
+    assert!(blocks.iter().all(|b| !b.tags.contains(&2)));
+    assert!(blocks.contains(&block(RegisterType::Coil, 4, 2, &[0, 4])));
+    assert!(blocks.contains(&block(RegisterType::Input, 6, 1, &[3])));
+    // The disabled tag at 5 no longer bridges the two holding registers
+    assert!(blocks.contains(&block(RegisterType::Holding, 4, 1, &[1])));
+    assert!(blocks.contains(&block(RegisterType::Holding, 6, 1, &[5])));
+    assert_eq!(blocks.len(), 4);
+}
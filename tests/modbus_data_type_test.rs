@@ -0,0 +1,350 @@
+mod support;
+
+use ava_device_logger::config::{ByteOrder, DataType, DeviceConfig, ModbusTcpConfig, ProtocolConfig};
+use ava_device_logger::database::{Database, DeviceTag, TagWritePolicy};
+use ava_device_logger::modbus::{check_type_range, decode_registers, encode_registers, ModbusClient};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::error::Error;
+use support::{Logger, ModbusDevice};
+
+const ORDERS: [ByteOrder; 4] = [ByteOrder::Abcd, ByteOrder::Cdab, ByteOrder::Badc, ByteOrder::Dcba];
+
+/// A value and the registers it is sent as in ABCD, CDAB, BADC and DCBA order
+fn patterns() -> Vec<(DataType, f64, [Vec<u16>; 4])> {
+    vec![
+        (DataType::Int32, -123456789.0, [
+            vec![0xF8A4, 0x32EB],
+            vec![0x32EB, 0xF8A4],
+            vec![0xA4F8, 0xEB32],
+            vec![0xEB32, 0xA4F8],
+        ]),
+        (DataType::UInt32, 3_000_000_000.0, [
+            vec![0xB2D0, 0x5E00],
+            vec![0x5E00, 0xB2D0],
+            vec![0xD0B2, 0x005E],
+            vec![0x005E, 0xD0B2],
+        ]),
+        (DataType::Float32, -1234.5, [
+            vec![0xC49A, 0x5000],
+            vec![0x5000, 0xC49A],
+            vec![0x9AC4, 0x0050],
+            vec![0x0050, 0x9AC4],
+        ]),
+        (DataType::Float64, std::f64::consts::PI, [
+            vec![0x4009, 0x21FB, 0x5444, 0x2D18],
+            vec![0x2D18, 0x5444, 0x21FB, 0x4009],
+            vec![0x0940, 0xFB21, 0x4454, 0x182D],
+            vec![0x182D, 0x4454, 0xFB21, 0x0940],
+        ]),
+        (DataType::Int64, -1_234_567_890_123.0, [
+            vec![0xFFFF, 0xFEE0, 0x8E04, 0xFB35],
+            vec![0xFB35, 0x8E04, 0xFEE0, 0xFFFF],
+            vec![0xFFFF, 0xE0FE, 0x048E, 0x35FB],
+            vec![0x35FB, 0x048E, 0xE0FE, 0xFFFF],
+        ]),
+        (DataType::UInt64, 9_876_543_210_123.0, [
+            vec![0x0000, 0x08FB, 0x8FD9, 0x828B],
+            vec![0x828B, 0x8FD9, 0x08FB, 0x0000],
+            vec![0x0000, 0xFB08, 0xD98F, 0x8B82],
+            vec![0x8B82, 0xD98F, 0xFB08, 0x0000],
+        ]),
+    ]
+}
+
+#[test]
+fn test_known_register_patterns_decode_and_encode_in_every_order() -> Result<(), Box<dyn Error>> {
+    for (data_type, value, layouts) in patterns() {
+        for (order, registers) in ORDERS.iter().zip(layouts) {
+            let decoded = decode_registers(&data_type, Some(*order), &registers)?;
+            assert_eq!(decoded, value, "{:?} {:?} {:04X?}", data_type, order, registers);
+            assert_eq!(encode_registers(&data_type, Some(*order), value)?, registers, "{:?} {:?}", data_type, order);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_unset_order_keeps_the_existing_layouts() -> Result<(), Box<dyn Error>> {
+    // Integers are low word first, floats high word first
+    assert_eq!(decode_registers(&DataType::UInt32, None, &[0x5E00, 0xB2D0])?, 3_000_000_000.0);
+    assert_eq!(decode_registers(&DataType::Int64, None, &[0xFB35, 0x8E04, 0xFEE0, 0xFFFF])?, -1_234_567_890_123.0);
+    assert_eq!(decode_registers(&DataType::Float32, None, &[0xC49A, 0x5000])?, -1234.5);
+    assert_eq!(encode_registers(&DataType::Float64, None, std::f64::consts::PI)?, vec![0x4009, 0x21FB, 0x5444, 0x2D18]);
+
+    // 16-bit values ignore the order and extra registers
+    assert_eq!(decode_registers(&DataType::Int16, Some(ByteOrder::Dcba), &[0xFF85, 0x1234])?, -123.0);
+    assert!(decode_registers(&DataType::Float64, None, &[0x4009, 0x21FB]).is_err());
+    assert!(encode_registers(&DataType::UInt64, None, -1.0).is_err());
+    assert!(encode_registers(&DataType::Float64, None, f64::NAN).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_raw_registers_are_checked_against_the_declared_type() {
+    // int16 with the high bit set is only a mismatch when the unit can't be negative
+    let reason = check_type_range(&DataType::Int16, None, &[0x9C40], Some("kWh")).expect("40000 read as int16 kWh");
+    assert!(reason.contains("non-negative unit kWh") && reason.contains("0x9C40"), "{}", reason);
+    assert_eq!(check_type_range(&DataType::Int16, None, &[0x9C40], Some("kW")), None);
+    assert_eq!(check_type_range(&DataType::Int16, None, &[0x9C40], None), None);
+    assert_eq!(check_type_range(&DataType::Int16, None, &[0x1C40], Some("kWh")), None);
+
+    // A uint32 high word on a tag declared uint16 but sized for two registers
+    let reason = check_type_range(&DataType::UInt16, None, &[0x86A0, 0x0001], None).expect("100000 read as uint16");
+    assert!(reason.contains("declared 16-bit type"), "{}", reason);
+    assert_eq!(check_type_range(&DataType::UInt16, None, &[0x86A0, 0x0000], None), None);
+
+    // The sign is read from the most significant word in the tag's byte order
+    for unit in ["kWh", "MWh", "Wh", "V", "kV", "Hz"] {
+        assert!(check_type_range(&DataType::Int32, Some(ByteOrder::Abcd), &[0xFFFF, 0xFF85], Some(unit)).is_some(), "{}", unit);
+        assert_eq!(check_type_range(&DataType::Int32, Some(ByteOrder::Cdab), &[0xFFFF, 0x0085], Some(unit)), None, "{}", unit);
+    }
+
+    // Unsigned and floating point values have no sign bit to misread
+    assert_eq!(check_type_range(&DataType::UInt16, None, &[0x9C40], Some("kWh")), None);
+    assert_eq!(check_type_range(&DataType::Float32, None, &[0xC49A, 0x5000], Some("kWh")), None);
+}
+
+#[test]
+fn test_tag_data_type_names() {
+    for (name, expected) in [("float64", DataType::Float64), ("DOUBLE", DataType::Float64), ("F64", DataType::Float64), ("U64", DataType::UInt64), ("int64", DataType::Int64), ("I32", DataType::Int32)] {
+        let parsed = DataType::from_tag_type(name).unwrap_or_else(|| panic!("{} was rejected", name));
+        assert_eq!(format!("{:?}", parsed), format!("{:?}", expected));
+    }
+    assert!(DataType::from_tag_type("float128").is_none());
+    assert!(DataType::from_tag_type("").is_none());
+    assert_eq!(ByteOrder::parse("cdab"), Some(ByteOrder::Cdab));
+    assert_eq!(ByteOrder::parse("ACBD"), None);
+}
+
+fn tag(name: &str, address: u16, data_type: &str, byte_order: Option<ByteOrder>, scaling_multiplier: f64) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: "inv-1".to_string(),
+        name: name.to_string(),
+        address,
+        size: DataType::from_tag_type(data_type).map_or(1, |t| t.register_width() as i32),
+        data_type: data_type.to_string(),
+        description: None,
+        scaling_multiplier,
+        scaling_offset: 0.0,
+        unit: Some("kWh".to_string()),
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy: TagWritePolicy::Disabled,
+        byte_order,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+#[tokio::test]
+async fn test_polled_values_are_decoded_per_tag_and_scaled() -> Result<(), Box<dyn Error>> {
+    // A float64 energy counter in DCBA order, an int64 in BADC, and a uint64 left at its default
+    let mut registers = HashMap::new();
+    for (start, words) in [
+        (100u16, vec![0x182D, 0x4454, 0xFB21, 0x0940]),
+        (104, vec![0xFFFF, 0xE0FE, 0x048E, 0x35FB]),
+        (110, vec![0x828B, 0x8FD9, 0x08FB, 0x0000]),
+    ] {
+        for (i, word) in words.into_iter().enumerate() {
+            registers.insert(start + i as u16, word);
+        }
+    }
+    let device = ModbusDevice::start(registers).await?;
+    let port = device.port();
+
+    let db_path = std::env::temp_dir()
+        .join(format!("modbus-data-type-{}.db", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string();
+    let db = Database::new(&db_path).await?;
+
+    let tags = vec![
+        tag("Energy", 100, "float64", Some(ByteOrder::Dcba), 1000.0),
+        tag("Balance", 104, "int64", Some(ByteOrder::Badc), 0.001),
+        tag("Lifetime", 110, "U64", None, 1.0),
+    ];
+    let mut client = ModbusClient::new(DeviceConfig {
+        id: "inv-1".to_string(),
+        name: "Inverter 1".to_string(),
+        enabled: true,
+        protocol: ProtocolConfig::ModbusTcp(ModbusTcpConfig { host: "127.0.0.1".to_string(), port, slave_id: 1, max_block_gap: 0, request_delay_ms: 0, serial_source: None }),
+        polling_interval_ms: 1000,
+        timeout_ms: 1000,
+        retry_count: 3,
+        tags: Vec::new(),
+        strict_types: false,
+    });
+    client.connect().await?;
+
+    let entries = client.read_specific_tags(&db, &tags).await?;
+    let values: HashMap<&str, (f64, &str)> = entries.iter().map(|e| (e.tag_name.as_str(), (e.value, e.quality.as_str()))).collect();
+    assert_eq!(values["Energy"], (std::f64::consts::PI * 1000.0, "Good"));
+    // Negative energy is still flagged on signed tags, with the sign read from the right word
+    assert_eq!(values["Balance"].0, -1_234_567_890.123);
+    assert_eq!(values["Balance"].1, "type_mismatch");
+    assert_eq!(values["Lifetime"], (9_876_543_210_123.0, "Good"));
+    let mismatches = db.get_type_mismatches("inv-1").await?;
+    assert_eq!(mismatches.len(), 1);
+    assert_eq!((mismatches[0].tag_name.as_str(), mismatches[0].mismatch_count), ("Balance", 1));
+
+    // A strict device drops the sample instead of logging the suspect value, but still counts it
+    let mut strict = ModbusClient::new(DeviceConfig {
+        id: "inv-1".to_string(),
+        name: "Inverter 1".to_string(),
+        enabled: true,
+        protocol: ProtocolConfig::ModbusTcp(ModbusTcpConfig { host: "127.0.0.1".to_string(), port, slave_id: 1, max_block_gap: 0, request_delay_ms: 0, serial_source: None }),
+        polling_interval_ms: 1000,
+        timeout_ms: 1000,
+        retry_count: 3,
+        tags: Vec::new(),
+        strict_types: true,
+    });
+    strict.connect().await?;
+    let entries = strict.read_specific_tags(&db, &tags).await?;
+    let names: Vec<&str> = entries.iter().map(|e| e.tag_name.as_str()).collect();
+    assert_eq!(names, ["Energy", "Lifetime"]);
+    assert_eq!(db.get_type_mismatches("inv-1").await?[0].mismatch_count, 2);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+/// multipart/form-data body for text fields and one CSV file
+fn multipart(boundary: &str, fields: &[(&str, &str)], file_field: &str, csv: &str) -> String {
+    let mut body = String::new();
+    for (name, value) in fields {
+        body += &format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n", boundary, name, value);
+    }
+    body += &format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"tags.csv\"\r\nContent-Type: text/csv\r\n\r\n{}\r\n--{}--\r\n",
+        boundary, file_field, csv, boundary
+    );
+    body
+}
+
+#[tokio::test]
+async fn test_unknown_data_types_are_rejected() -> Result<(), Box<dyn Error>> {
+
+    let logger = Logger::start("").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let device = |data_type: &str, byte_order: Value| json!({
+        "id": "meter-1",
+        "name": "Meter 1",
+        "enabled": false,
+        "polling_interval_ms": 1000,
+        "timeout_ms": 1000,
+        "retry_count": 3,
+        "protocol_config": {"type": "modbus_tcp", "host": "127.0.0.1", "port": 502, "slave_id": 1},
+        "tags": [{
+            "name": "Energy", "address": 100, "size": 4, "data_type": data_type, "byte_order": byte_order,
+            "scaling_multiplier": 1.0, "scaling_offset": 0.0, "read_only": true, "enabled": true
+        }]
+    });
+
+    let body = client
+        .post(format!("{}/api/devices-enhanced", base_url))
+        .bearer_auth(token)
+        .json(&device("float128", Value::Null))
+        .send()
+        .await?
+        .json::<Value>()
+        .await?;
+    assert_eq!(body["success"], false);
+    assert!(body["error"].as_str().unwrap().contains("Unknown data type 'float128'"), "{}", body);
+
+    let body = client
+        .post(format!("{}/api/devices-enhanced", base_url))
+        .bearer_auth(token)
+        .json(&device("float64", json!("CDAB")))
+        .send()
+        .await?
+        .json::<Value>()
+        .await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let device = client
+        .get(format!("{}/api/devices-enhanced/meter-1", base_url))
+        .bearer_auth(token)
+        .send()
+        .await?
+        .json::<Value>()
+        .await?;
+    assert_eq!(device["data"]["tags"][0]["byte_order"], "CDAB", "{}", device);
+
+    // Register map uploads name the bad row
+    let csv = "Device Brand,Device Model,AVA Type,MPPT,INPUT,Data Label,Address,Size,Modbus Type,Divider,Register Type\n\
+               SMA,STP,Inverter,,,Total Yield,30513,4,U64,1,holding\n\
+               SMA,STP,Inverter,,,Pac,30775,2,F128,1,holding\n";
+    let boundary = "data-type-test";
+    let body = client
+        .post(format!("{}/api/modbus-tcp-tag-registers/upload-csv", base_url))
+        .bearer_auth(token)
+        .header("Content-Type", format!("multipart/form-data; boundary={}", boundary))
+        .body(multipart(boundary, &[("device_model_name", "STP"), ("manufacturer", "SMA")], "csv_file", csv))
+        .send()
+        .await?
+        .json::<Value>()
+        .await?;
+    assert_eq!(body["success"], false, "{}", body);
+    assert!(body["details"]["message"].as_str().unwrap().contains("Row 3: Invalid Modbus Type 'F128'"), "{}", body);
+
+    // So do device model tag lists, before any template is created
+    let csv = "name,address,data_type\nEnergy,100,float64\nPac,104,real\n";
+    let body = client
+        .post(format!("{}/api/device-models", base_url))
+        .bearer_auth(token)
+        .header("Content-Type", format!("multipart/form-data; boundary={}", boundary))
+        .body(multipart(boundary, &[("name", "Meter"), ("protocol_type", "modbus_tcp")], "csv_file", csv))
+        .send()
+        .await?
+        .json::<Value>()
+        .await?;
+    assert_eq!(body["success"], false, "{}", body);
+    assert!(body["error"].as_str().unwrap().contains("Row 3: Unknown data type 'real'"), "{}", body);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_register_map_flags_tags_with_recurring_type_mismatches() -> Result<(), Box<dyn Error>> {
+    let logger = Logger::start("").await?;
+    let body = logger
+        .post("/api/devices-enhanced", &json!({
+            "id": "meter-1", "name": "Meter 1", "enabled": false,
+            "polling_interval_ms": 1000, "timeout_ms": 1000, "retry_count": 3,
+            "protocol_config": {"type": "modbus_tcp", "host": "127.0.0.1", "port": 502, "slave_id": 1},
+            "tags": [
+                {"name": "Energy", "address": 100, "size": 1, "data_type": "int16", "unit": "kWh", "scaling_multiplier": 1.0, "scaling_offset": 0.0, "read_only": true, "enabled": true},
+                {"name": "Voltage", "address": 101, "size": 1, "data_type": "int16", "unit": "V", "scaling_multiplier": 1.0, "scaling_offset": 0.0, "read_only": true, "enabled": true},
+                {"name": "Power", "address": 102, "size": 1, "data_type": "int16", "unit": "kW", "scaling_multiplier": 1.0, "scaling_offset": 0.0, "read_only": true, "enabled": true},
+            ]
+        }))
+        .await?;
+    assert_eq!(body["success"], true, "{}", body);
+
+    // Energy keeps wrapping, Voltage did once
+    let database = &logger.state().database;
+    for _ in 0..10 {
+        database.record_type_mismatch("meter-1", "Energy", "sign bit set on signed tag with non-negative unit kWh (raw 0x9C40)").await?;
+    }
+    database.record_type_mismatch("meter-1", "Voltage", "sign bit set on signed tag with non-negative unit V (raw 0x8001)").await?;
+
+    let body = logger.get("/api/devices/meter-1/tags").await?;
+    let tags: HashMap<&str, &Value> = body["data"].as_array().unwrap().iter().map(|tag| (tag["name"].as_str().unwrap(), tag)).collect();
+    assert_eq!((&tags["Energy"]["type_mismatch_count"], &tags["Energy"]["probable_config_error"]), (&json!(10), &json!(true)));
+    assert_eq!(tags["Energy"]["last_type_mismatch"], "sign bit set on signed tag with non-negative unit kWh (raw 0x9C40)");
+    assert_eq!(tags["Energy"]["address"], 100);
+    assert_eq!((&tags["Voltage"]["type_mismatch_count"], &tags["Voltage"]["probable_config_error"]), (&json!(1), &json!(false)));
+    assert_eq!((&tags["Power"]["type_mismatch_count"], &tags["Power"]["last_type_mismatch"]), (&json!(0), &Value::Null));
+
+    // Resetting after fixing the data types clears the flag
+    let response = logger.client.delete(logger.url("/api/devices/meter-1/type-mismatches")).bearer_auth(&logger.token).send().await?;
+    assert_eq!(response.status(), 200);
+    let body = logger.get("/api/devices/meter-1/tags").await?;
+    assert!(body["data"].as_array().unwrap().iter().all(|tag| tag["probable_config_error"] == false), "{}", body);
+    Ok(())
+}
@@ -0,0 +1,238 @@
+use ava_device_logger::config::{DeviceConfig, ModbusTcpConfig, ProtocolConfig};
+use ava_device_logger::database::{Database, DeviceTag, TagWritePolicy};
+use ava_device_logger::modbus::ModbusClient;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::time::Instant;
+
+/// What a serial-to-TCP gateway saw from the logger
+#[derive(Default)]
+struct Gateway {
+    accepted: AtomicUsize,
+    closed: AtomicUsize,
+    /// Requests that arrived while the previous one was still unanswered
+    overlaps: AtomicUsize,
+    /// Stop answering any slave, like a gateway that lost power without closing its sockets
+    muted: AtomicBool,
+    /// Unit id of each request, with its arrival time
+    requests: Mutex<Vec<(u8, Instant)>>,
+}
+
+/// Gateway stand-in: unit `n` holds `n * 1000 + address` in every holding register, and unit 9
+/// never answers
+async fn spawn_gateway() -> Result<(u16, Arc<Gateway>), Box<dyn Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    let gateway = Arc::new(Gateway::default());
+    let state = gateway.clone();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            state.accepted.fetch_add(1, Ordering::SeqCst);
+            let state = state.clone();
+            tokio::spawn(async move {
+                loop {
+                    let mut header = [0u8; 7];
+                    if socket.read_exact(&mut header).await.is_err() {
+                        break;
+                    }
+                    let mut pdu = vec![0u8; u16::from_be_bytes([header[4], header[5]]) as usize - 1];
+                    if socket.read_exact(&mut pdu).await.is_err() {
+                        break;
+                    }
+                    let unit = header[6];
+                    state.requests.lock().unwrap().push((unit, Instant::now()));
+
+                    // Slow serial side: anything sent meanwhile would be a second request in flight
+                    let mut extra = [0u8; 1];
+                    if let Ok(Ok(n)) = tokio::time::timeout(Duration::from_millis(10), socket.peek(&mut extra)).await {
+                        if n > 0 && unit != 9 && !state.muted.load(Ordering::SeqCst) {
+                            state.overlaps.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                    if unit == 9 || state.muted.load(Ordering::SeqCst) {
+                        continue;
+                    }
+
+                    let address = u16::from_be_bytes([pdu[1], pdu[2]]);
+                    let count = u16::from_be_bytes([pdu[3], pdu[4]]);
+                    let mut response = vec![pdu[0], (count * 2) as u8];
+                    (0..count).for_each(|i| response.extend((unit as u16 * 1000 + address + i).to_be_bytes()));
+
+                    let mut frame = header[..4].to_vec();
+                    frame.extend(((response.len() + 1) as u16).to_be_bytes());
+                    frame.push(unit);
+                    frame.extend(response);
+                    if socket.write_all(&frame).await.is_err() {
+                        break;
+                    }
+                }
+                state.closed.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+    });
+    Ok((port, gateway))
+}
+
+fn meter(port: u16, slave_id: u8, request_delay_ms: u64) -> ModbusClient {
+    ModbusClient::new(DeviceConfig {
+        id: format!("meter-{}", slave_id),
+        name: format!("Meter {}", slave_id),
+        enabled: true,
+        protocol: ProtocolConfig::ModbusTcp(ModbusTcpConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            slave_id,
+            max_block_gap: 0,
+            request_delay_ms,
+            serial_source: None,
+        }),
+        polling_interval_ms: 1000,
+        timeout_ms: 200,
+        retry_count: 3,
+        tags: Vec::new(),
+        strict_types: false,
+    })
+}
+
+fn tag(slave_id: u8, name: &str, address: u16) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: format!("meter-{}", slave_id),
+        name: name.to_string(),
+        address,
+        size: 1,
+        data_type: "uint16".to_string(),
+        description: None,
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: None,
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy: TagWritePolicy::Disabled,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+async fn temp_database() -> Result<(Arc<Database>, String), Box<dyn Error>> {
+    let db_path = std::env::temp_dir()
+        .join(format!("modbus-gateway-{}.db", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string();
+    Ok((Arc::new(Database::new(&db_path).await?), db_path))
+}
+
+async fn wait_for(condition: impl Fn() -> bool) {
+    for _ in 0..100 {
+        if condition() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_slaves_behind_a_gateway_share_one_paced_connection() -> Result<(), Box<dyn Error>> {
+    let (port, gateway) = spawn_gateway().await?;
+    let (db, db_path) = temp_database().await?;
+
+    let mut handles = Vec::new();
+    for slave_id in [1u8, 2, 3] {
+        let db = db.clone();
+        handles.push(tokio::spawn(async move {
+            let mut client = meter(port, slave_id, 20);
+            client.connect().await.unwrap();
+            let tags = [tag(slave_id, "Va", 10), tag(slave_id, "Vb", 20)];
+            let mut values = Vec::new();
+            for _ in 0..2 {
+                values.extend(client.read_specific_tags(&db, &tags).await.unwrap().into_iter().map(|e| e.value));
+            }
+            values
+        }));
+    }
+    for (slave_id, handle) in [1.0, 2.0, 3.0].into_iter().zip(handles) {
+        let expected = [10.0, 20.0, 10.0, 20.0].map(|address| slave_id * 1000.0 + address);
+        assert_eq!(handle.await?, expected);
+    }
+
+    assert_eq!(gateway.accepted.load(Ordering::SeqCst), 1);
+    assert_eq!(gateway.overlaps.load(Ordering::SeqCst), 0);
+    let requests = gateway.requests.lock().unwrap();
+    assert_eq!(requests.len(), 12);
+    // Each request waits for the previous answer plus the request delay
+    assert!(requests.windows(2).all(|pair| pair[1].1 - pair[0].1 >= Duration::from_millis(28)), "requests too close together");
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_connection_closes_when_the_last_device_stops() -> Result<(), Box<dyn Error>> {
+    let (port, gateway) = spawn_gateway().await?;
+    let (db, db_path) = temp_database().await?;
+
+    let mut first = meter(port, 1, 0);
+    let mut second = meter(port, 2, 0);
+    first.connect().await?;
+    second.connect().await?;
+
+    first.disconnect().await;
+    assert_eq!(second.read_specific_tags(&db, &[tag(2, "Va", 10)]).await?[0].value, 2010.0);
+    assert_eq!(gateway.closed.load(Ordering::SeqCst), 0);
+
+    // Dropping a stopped device's client releases the connection the same way
+    drop(second);
+    wait_for(|| gateway.closed.load(Ordering::SeqCst) == 1).await;
+    assert_eq!(gateway.closed.load(Ordering::SeqCst), 1);
+
+    first.connect().await?;
+    assert_eq!(first.read_specific_tags(&db, &[tag(1, "Va", 10)]).await?[0].value, 1010.0);
+    assert_eq!(gateway.accepted.load(Ordering::SeqCst), 2);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_silent_slave_keeps_the_connection_for_its_siblings() -> Result<(), Box<dyn Error>> {
+    let (port, gateway) = spawn_gateway().await?;
+    let (db, db_path) = temp_database().await?;
+
+    let mut live = meter(port, 1, 0);
+    let mut silent = meter(port, 9, 0);
+    live.connect().await?;
+    silent.connect().await?;
+    assert_eq!(live.read_specific_tags(&db, &[tag(1, "Va", 10)]).await?[0].value, 1010.0);
+
+    let error = silent.read_specific_tags(&db, &[tag(9, "Va", 10)]).await.unwrap_err();
+    assert!(error.to_string().contains("no answer"), "{}", error);
+    assert!(!silent.is_connected());
+    assert!(live.is_connected());
+    assert_eq!(live.read_specific_tags(&db, &[tag(1, "Va", 20)]).await?[0].value, 1020.0);
+    assert_eq!(gateway.accepted.load(Ordering::SeqCst), 1);
+    assert_eq!(gateway.closed.load(Ordering::SeqCst), 0);
+
+    // When no slave answers at all the gateway itself is gone, so the connection is reopened
+    silent.connect().await?;
+    gateway.muted.store(true, Ordering::SeqCst);
+    assert!(live.read_specific_tags(&db, &[tag(1, "Va", 10)]).await.is_err());
+    assert!(silent.read_specific_tags(&db, &[tag(9, "Va", 10)]).await.is_err());
+    wait_for(|| gateway.closed.load(Ordering::SeqCst) == 1).await;
+    assert_eq!(gateway.closed.load(Ordering::SeqCst), 1);
+
+    gateway.muted.store(false, Ordering::SeqCst);
+    live.connect().await?;
+    assert_eq!(live.read_specific_tags(&db, &[tag(1, "Va", 10)]).await?[0].value, 1010.0);
+    assert_eq!(gateway.accepted.load(Ordering::SeqCst), 2);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
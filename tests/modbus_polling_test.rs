@@ -0,0 +1,108 @@
+mod support;
+
+use serde_json::json;
+use std::time::Duration;
+use support::{eventually, log_entries, tag, Logger, ModbusDevice, ModbusRequest, TestResult};
+
+const WAIT: Duration = Duration::from_secs(10);
+
+fn assert_close(actual: f64, expected: f64, what: &str) {
+    assert!((actual - expected).abs() < 1e-6, "{}: {} is not {}", what, actual, expected);
+}
+
+#[tokio::test]
+async fn test_polled_values_are_scaled_logged_and_served() -> TestResult {
+    let device = ModbusDevice::start([(100, 2345), (101, (-10i16) as u16), (200, 500)]).await?;
+    device.set_f32(102, 49.98);
+    device.set_u32(104, 123_456);
+    let logger = Logger::start("").await?;
+    let db = logger.database().await?;
+
+    logger
+        .start_modbus_device("inv-1", &device, 200, vec![
+            tag("Voltage", 100, "uint16", 0.1, json!({"unit": "V"})),
+            tag("Reactive Power", 101, "int16", 0.5, json!({})),
+            tag("Frequency", 102, "float32", 1.0, json!({"unit": "Hz"})),
+            tag("Energy", 104, "uint32", 0.001, json!({"unit": "kWh", "byte_order": "ABCD"})),
+            tag("Temperature", 200, "uint16", 1.0, json!({"register_type": "input", "scaling_offset": -40.0})),
+        ])
+        .await?;
+
+    // Two polls of every tag reach the log, scaled and with their units
+    let expected = [("Voltage", 234.5), ("Reactive Power", -5.0), ("Frequency", 49.98f32 as f64), ("Energy", 123.456), ("Temperature", 460.0)];
+    for (name, value) in expected {
+        let entries = eventually(WAIT, || async { Some(log_entries(&db, "inv-1", name).await).filter(|entries| entries.len() >= 2) })
+            .await
+            .unwrap_or_else(|| panic!("{} was not logged twice", name));
+        for entry in &entries {
+            assert_close(entry.value, value, name);
+            assert_eq!(entry.quality, "Good", "{:?}", entry);
+        }
+    }
+    assert_eq!(log_entries(&db, "inv-1", "Voltage").await[0].unit.as_deref(), Some("V"));
+
+    // Adjacent holding registers are read as one block, the input register on its own
+    let requests = device.take_requests();
+    assert!(!requests.is_empty());
+    for request in requests {
+        assert!(
+            [ModbusRequest { slave: 1, function: 0x03, address: 100, count: 6 }, ModbusRequest { slave: 1, function: 0x04, address: 200, count: 1 }].contains(&request),
+            "unexpected {:?}",
+            request
+        );
+    }
+
+    // The live values match the log
+    let values = logger.values("inv-1").await?;
+    for (name, value) in expected {
+        assert_close(values[name]["value"].as_f64().unwrap(), value, name);
+        assert_eq!(values[name]["quality"], "Good");
+        assert_eq!(values[name]["stale"], false);
+    }
+    assert_eq!(values["Frequency"]["unit"], "Hz");
+
+    // A change on the device shows up in both
+    device.set(100, 2400);
+    eventually(WAIT, || async {
+        let values = logger.values("inv-1").await.ok()?;
+        ((values["Voltage"]["value"].as_f64().unwrap_or_default() - 240.0).abs() < 1e-6).then_some(())
+    })
+    .await
+    .expect("the new voltage was not served");
+    let entries = log_entries(&db, "inv-1", "Voltage").await;
+    assert_close(entries.last().unwrap().value, 240.0, "Voltage");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_gaps_up_to_max_block_gap_are_read_in_one_request() -> TestResult {
+    let device = ModbusDevice::start([(100, 1), (110, 2)]).await?;
+    let logger = Logger::start("").await?;
+    let tags = vec![tag("First", 100, "uint16", 1.0, json!({})), tag("Second", 110, "uint16", 1.0, json!({}))];
+
+    for (device_id, max_block_gap) in [("bridged", 10), ("split", 0)] {
+        logger
+            .start_device(json!({
+                "id": device_id, "name": device_id, "enabled": true,
+                "polling_interval_ms": 200, "timeout_ms": 1000, "retry_count": 1,
+                "protocol_config": {"type": "modbus_tcp", "host": "127.0.0.1", "port": device.port(), "slave_id": 1, "max_block_gap": max_block_gap},
+                "tags": tags,
+            }))
+            .await?;
+        let values = eventually(WAIT, || async { logger.values(device_id).await.ok().filter(|values| values.len() == 2) })
+            .await
+            .unwrap_or_else(|| panic!("{} was not polled", device_id));
+        assert_eq!(values["First"]["value"], 1.0);
+        assert_eq!(values["Second"]["value"], 2.0);
+        logger.post(&format!("/api/devices-enhanced/{}/stop", device_id), &json!({})).await?;
+
+        let mut reads: Vec<(u16, u16)> = device.take_requests().iter().map(|request| (request.address, request.count)).collect();
+        reads.sort();
+        reads.dedup();
+        match max_block_gap {
+            0 => assert_eq!(reads, [(100, 1), (110, 1)]),
+            _ => assert_eq!(reads, [(100, 11)]),
+        }
+    }
+    Ok(())
+}
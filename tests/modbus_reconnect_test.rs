@@ -0,0 +1,160 @@
+mod support;
+
+use ava_device_logger::config::{DeviceConfig, ModbusTcpConfig, ProtocolConfig};
+use ava_device_logger::database::{Database, DeviceTag, TagWritePolicy};
+use ava_device_logger::modbus::ModbusClient;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::time::{Duration, Instant};
+use support::{eventually, log_entries, Logger, ModbusDevice, TestResult};
+
+const WAIT: Duration = Duration::from_secs(10);
+
+fn tag(device_id: &str) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: device_id.to_string(),
+        name: "Active Power".to_string(),
+        address: 40001,
+        size: 1,
+        data_type: "uint16".to_string(),
+        description: None,
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: None,
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy: TagWritePolicy::AdminOnly,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+#[tokio::test]
+async fn test_lost_connection_fails_the_poll_and_drops_the_session() -> TestResult {
+    let mut device = ModbusDevice::start([(40001, 42)]).await?;
+    let port = device.port();
+    let db_path = std::env::temp_dir()
+        .join(format!("modbus-reconnect-{}.db", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string();
+    let db = Database::new(&db_path).await?;
+
+    let mut client = ModbusClient::new(DeviceConfig {
+        id: "inv-1".to_string(),
+        name: "Inverter 1".to_string(),
+        enabled: true,
+        protocol: ProtocolConfig::ModbusTcp(ModbusTcpConfig { host: "127.0.0.1".to_string(), port, slave_id: 1, max_block_gap: 0, request_delay_ms: 0, serial_source: None }),
+        polling_interval_ms: 1000,
+        timeout_ms: 500,
+        retry_count: 3,
+        tags: Vec::new(),
+        strict_types: false,
+    });
+    client.connect().await?;
+    let entries = client.read_specific_tags(&db, &[tag("inv-1")]).await?;
+    assert_eq!((entries[0].value, entries[0].quality.as_str()), (42.0, "Good"));
+
+    device.stop().await;
+    let lost = client.read_specific_tags(&db, &[tag("inv-1")]).await;
+    assert!(lost.unwrap_err().to_string().contains("Connection to device inv-1 lost"));
+    assert!(!client.is_connected());
+
+    // Nothing listens any more
+    assert!(client.connect().await.is_err());
+    device.restart().await?;
+    client.connect().await?;
+    assert_eq!(client.read_specific_tags(&db, &[tag("inv-1")]).await?[0].value, 42.0);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+async fn device_status(logger: &Logger) -> TestResult<Value> {
+    let status = logger.get("/api/status").await?;
+    Ok(status["data"]["devices"]
+        .as_array()
+        .and_then(|devices| devices.iter().find(|device| device["device_id"] == "inv-1"))
+        .cloned()
+        .unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn test_poller_reconnects_with_backoff_after_a_device_reboot() -> TestResult {
+    let mut device = ModbusDevice::start([(40001, 42)]).await?;
+    let logger = Logger::start("").await?;
+    logger
+        .start_device(json!({
+            "id": "inv-1", "name": "inv-1", "enabled": true,
+            "polling_interval_ms": 1000, "timeout_ms": 500, "retry_count": 2,
+            "protocol_config": {"type": "modbus_tcp", "host": "127.0.0.1", "port": device.port(), "slave_id": 1},
+            "tags": [support::tag("Active Power", 40001, "uint16", 1.0, json!({}))],
+        }))
+        .await?;
+
+    let wait_for = |wanted: &'static str| {
+        let logger = &logger;
+        async move {
+            let started = Instant::now();
+            loop {
+                let status = device_status(logger).await?;
+                if status["status"] == wanted {
+                    return Ok::<Value, Box<dyn Error>>(status);
+                }
+                assert!(started.elapsed() < Duration::from_secs(20), "never became {}: {}", wanted, status);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        }
+    };
+
+    assert_eq!(wait_for("Reading").await?["connection_count"], 1);
+
+    // The device reboots: the link drops, then connects are refused until it's back
+    device.stop().await;
+    let reconnecting = wait_for("Reconnecting").await?;
+    assert_eq!(reconnecting["connection_count"], 1);
+    assert!(reconnecting["error_message"].as_str().unwrap().contains("lost"), "{}", reconnecting);
+    let failed = wait_for("Error").await?;
+    assert!(failed["error_message"].as_str().unwrap().contains("Failed to connect"), "{}", failed);
+    let running = logger.get("/api/devices-enhanced").await?;
+    assert_eq!(running["data"][0]["is_running"], true);
+
+    device.restart().await?;
+    assert_eq!(wait_for("Reading").await?["connection_count"], 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_polling_resumes_after_the_device_restarts() -> TestResult {
+    let mut device = ModbusDevice::start([(100, 42)]).await?;
+    let logger = Logger::start("").await?;
+    let db = logger.database().await?;
+    logger.start_modbus_device("inv-1", &device, 200, vec![support::tag("Power", 100, "uint16", 1.0, json!({}))]).await?;
+
+    eventually(WAIT, || async { logger.values("inv-1").await.ok().filter(|values| values.contains_key("Power")) })
+        .await
+        .expect("the device was not polled");
+    assert_eq!(device.connections(), 1);
+
+    // Values read before the device went away are kept but marked stale
+    device.stop().await;
+    let values = eventually(WAIT, || async { logger.values("inv-1").await.ok().filter(|values| values["Power"]["stale"] == true) })
+        .await
+        .expect("the values were not marked stale");
+    assert_eq!(values["Power"]["value"], 42.0);
+
+    device.set(100, 43);
+    device.restart().await?;
+    let values = eventually(WAIT, || async { logger.values("inv-1").await.ok().filter(|values| values["Power"]["value"] == 43.0) })
+        .await
+        .expect("polling did not resume");
+    assert_eq!(values["Power"]["stale"], false);
+    assert_eq!(values["Power"]["quality"], "Good");
+    assert!(device.connections() >= 2);
+    assert!(log_entries(&db, "inv-1", "Power").await.iter().any(|entry| entry.value == 43.0 && entry.quality == "Good"));
+    Ok(())
+}
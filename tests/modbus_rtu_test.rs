@@ -0,0 +1,244 @@
+use ava_device_logger::config::{DeviceConfig, ModbusRtuConfig, ProtocolConfig};
+use ava_device_logger::database::{Database, DeviceTag, TagWritePolicy};
+use ava_device_logger::modbus::{ModbusClient, SerialSettings};
+use serde_json::json;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::Instant;
+use tokio_serial::{SerialPort, SerialStream};
+
+fn crc16(frame: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for byte in frame {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xA001 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+/// What the stand-in slaves saw on the line
+#[derive(Default)]
+struct LineLog {
+    /// Slave id of each request, in arrival order
+    requests: Vec<u8>,
+    /// Requests that arrived while another transaction was still waiting for its response
+    overlaps: usize,
+    /// Shortest silence between a response and the next request
+    shortest_gap: Option<Duration>,
+}
+
+/// Answer holding register reads on the master end of a pseudo terminal. Slave `n` holds
+/// `n * 1000 + address` at every address; slave 3 never answers.
+fn spawn_rtu_slaves(mut master: SerialStream, log: Arc<Mutex<LineLog>>) {
+    tokio::spawn(async move {
+        let mut last_response: Option<Instant> = None;
+        loop {
+            let mut request = [0u8; 8];
+            if master.read_exact(&mut request).await.is_err() {
+                break;
+            }
+            let arrived = Instant::now();
+            assert_eq!(crc16(&request[..6]).to_le_bytes(), [request[6], request[7]]);
+            let slave = request[0];
+            let address = u16::from_be_bytes([request[2], request[3]]);
+            let count = u16::from_be_bytes([request[4], request[5]]);
+            {
+                let mut log = log.lock().unwrap();
+                log.requests.push(slave);
+                if let Some(last) = last_response {
+                    let gap = arrived - last;
+                    log.shortest_gap = Some(log.shortest_gap.map_or(gap, |shortest| shortest.min(gap)));
+                }
+            }
+
+            // Take a while to answer; anything sent meanwhile would collide on a real bus
+            let mut extra = [0u8; 1];
+            if let Ok(Ok(n)) = tokio::time::timeout(Duration::from_millis(15), master.read(&mut extra)).await {
+                if n > 0 {
+                    log.lock().unwrap().overlaps += 1;
+                }
+            }
+            if slave == 3 {
+                continue;
+            }
+
+            let mut response = vec![slave, 0x03, (count * 2) as u8];
+            for i in 0..count {
+                response.extend((slave as u16 * 1000 + address + i).to_be_bytes());
+            }
+            response.extend(crc16(&response).to_le_bytes());
+            if master.write_all(&response).await.is_err() {
+                break;
+            }
+            last_response = Some(Instant::now());
+        }
+    });
+}
+
+/// A pseudo terminal standing in for a serial line. The slave end is closed again so the logger can
+/// take its exclusive lock; the master end only reads cleanly once the logger has the port open.
+fn pty_port() -> Result<(SerialStream, String), Box<dyn Error>> {
+    let (master, slave_end) = SerialStream::pair()?;
+    let port = slave_end.name().expect("pseudo terminal path");
+    Ok((master, port))
+}
+
+fn rtu_device(id: &str, port: &str, slave_id: u8) -> DeviceConfig {
+    DeviceConfig {
+        id: id.to_string(),
+        name: id.to_string(),
+        enabled: true,
+        protocol: ProtocolConfig::ModbusRtu(ModbusRtuConfig {
+            port: port.to_string(),
+            baud_rate: 19_200,
+            data_bits: 8,
+            stop_bits: 1,
+            parity: "none".to_string(),
+            slave_id,
+            max_block_gap: 0,
+            inter_frame_delay_ms: 5,
+            serial_source: None,
+        }),
+        polling_interval_ms: 1000,
+        timeout_ms: 200,
+        retry_count: 3,
+        tags: Vec::new(),
+        strict_types: false,
+    }
+}
+
+fn tag(device_id: &str, name: &str, address: u16) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: device_id.to_string(),
+        name: name.to_string(),
+        address,
+        size: 1,
+        data_type: "uint16".to_string(),
+        description: None,
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: None,
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy: TagWritePolicy::Disabled,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_devices_sharing_a_port_take_turns_on_the_bus() -> Result<(), Box<dyn Error>> {
+    let (master, port) = pty_port()?;
+    let log = Arc::new(Mutex::new(LineLog::default()));
+
+    let db_path = std::env::temp_dir()
+        .join(format!("modbus-rtu-{}.db", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string();
+    let db = Arc::new(Database::new(&db_path).await?);
+
+    let mut clients = Vec::new();
+    for slave_id in [1u8, 2] {
+        let mut client = ModbusClient::new(rtu_device(&format!("meter-{}", slave_id), &port, slave_id));
+        client.connect().await?;
+        clients.push((slave_id, client));
+    }
+    spawn_rtu_slaves(master, log.clone());
+
+    // Tags far enough apart that every poll is three separate transactions
+    let mut handles = Vec::new();
+    for (slave_id, mut client) in clients {
+        let db = db.clone();
+        handles.push(tokio::spawn(async move {
+            let device_id = format!("meter-{}", slave_id);
+            let tags = vec![tag(&device_id, "Va", 10), tag(&device_id, "Vb", 20), tag(&device_id, "Vc", 30)];
+            let mut polls = Vec::new();
+            for _ in 0..3 {
+                polls.push(client.read_specific_tags(&db, &tags).await.unwrap());
+            }
+            polls
+        }));
+    }
+
+    for (slave_id, handle) in [1u16, 2].into_iter().zip(handles) {
+        for poll in handle.await? {
+            let values: Vec<(String, f64)> = poll.iter().map(|e| (e.tag_name.clone(), e.value)).collect();
+            let expected: Vec<(String, f64)> = [("Va", 10), ("Vb", 20), ("Vc", 30)]
+                .iter()
+                .map(|(name, address)| (name.to_string(), (slave_id * 1000 + address) as f64))
+                .collect();
+            assert_eq!(values, expected);
+            assert!(poll.iter().all(|e| e.quality == "Good"));
+        }
+    }
+
+    let log = log.lock().unwrap();
+    assert_eq!(log.requests.len(), 18);
+    assert_eq!(log.requests.iter().filter(|slave| **slave == 1).count(), 9);
+    assert_eq!(log.overlaps, 0, "requests were interleaved: {:?}", log.requests);
+    // Both devices were in flight together, not one after the other
+    assert!(log.requests.windows(2).any(|pair| pair[0] != pair[1]), "{:?}", log.requests);
+    assert!(log.shortest_gap.unwrap() >= Duration::from_millis(4), "{:?}", log.shortest_gap);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_silent_slave_drops_only_its_own_session() -> Result<(), Box<dyn Error>> {
+    let (master, port) = pty_port()?;
+
+    let db_path = std::env::temp_dir()
+        .join(format!("modbus-rtu-{}.db", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string();
+    let db = Database::new(&db_path).await?;
+
+    let mut silent = ModbusClient::new(rtu_device("meter-3", &port, 3));
+    silent.connect().await?;
+    spawn_rtu_slaves(master, Arc::new(Mutex::new(LineLog::default())));
+    let error = silent.read_specific_tags(&db, &[tag("meter-3", "Va", 10)]).await.unwrap_err();
+    assert!(error.to_string().contains("no answer"), "{}", error);
+    assert!(!silent.is_connected());
+
+    // The port stays open for the other devices on the bus
+    let mut answering = ModbusClient::new(rtu_device("meter-1", &port, 1));
+    answering.connect().await?;
+    let entries = answering.read_specific_tags(&db, &[tag("meter-1", "Va", 10)]).await?;
+    assert_eq!(entries[0].value, 1010.0);
+
+    // A port that doesn't exist fails to connect, like an unreachable TCP device
+    let mut missing = ModbusClient::new(rtu_device("meter-4", "/dev/ttyMISSING0", 1));
+    let error = missing.connect().await.unwrap_err();
+    assert!(error.to_string().contains("Failed to open serial port /dev/ttyMISSING0"), "{}", error);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[test]
+fn test_line_settings_default_from_protocol_config() -> Result<(), Box<dyn Error>> {
+    // The device form only sends port, baud rate and slave id
+    let protocol: ProtocolConfig = serde_json::from_value(json!({
+        "type": "modbus_rtu", "port": "/dev/ttyUSB0", "baud_rate": 9600, "slave_id": 4
+    }))?;
+    let settings = SerialSettings::from_protocol(&protocol).expect("RTU settings");
+    assert_eq!((settings.data_bits, settings.stop_bits, settings.parity.as_str()), (8, 1, "none"));
+    // 3.5 characters of 10 bits at 9600 baud
+    assert_eq!(settings.inter_frame_delay, Duration::from_micros(3645));
+
+    let protocol: ProtocolConfig = serde_json::from_value(json!({
+        "type": "modbus_rtu", "port": "/dev/ttyUSB0", "baud_rate": 115200, "parity": "even", "slave_id": 4
+    }))?;
+    assert_eq!(SerialSettings::from_protocol(&protocol).unwrap().inter_frame_delay, Duration::from_micros(1750));
+    Ok(())
+}
@@ -0,0 +1,248 @@
+use ava_device_logger::config::MqttConfig;
+use ava_device_logger::database::{DeviceStatus, LogEntry};
+use ava_device_logger::metrics::Metrics;
+use ava_device_logger::mqtt::MqttPublisher;
+use chrono::{TimeZone, Utc};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+fn config(port: u16, queue_size: usize) -> MqttConfig {
+    MqttConfig {
+        host: "127.0.0.1".to_string(),
+        port,
+        tls: false,
+        username: "scada".to_string(),
+        password: "hunter22".to_string(),
+        client_id: "logger-test".to_string(),
+        base_topic: "site1".to_string(),
+        qos: 1,
+        queue_size,
+        keep_alive_secs: 30,
+    }
+}
+
+fn entry(tag_name: &str, value: f64, quality: &str) -> LogEntry {
+    LogEntry {
+        id: None,
+        device_id: "inv-1".to_string(),
+        tag_name: tag_name.to_string(),
+        value,
+        quality: quality.to_string(),
+        timestamp: Utc.with_ymd_and_hms(2026, 6, 1, 12, 0, 0).unwrap(),
+        unit: Some("kW".to_string()),
+    }
+}
+
+/// A packet's fixed header byte and body
+async fn read_packet(stream: &mut TcpStream) -> std::io::Result<(u8, Vec<u8>)> {
+    let header = stream.read_u8().await?;
+    let (mut length, mut shift) = (0usize, 0);
+    loop {
+        let byte = stream.read_u8().await?;
+        length |= ((byte & 0x7F) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).await?;
+    Ok((header, body))
+}
+
+fn string_at(body: &[u8], at: usize) -> (String, usize) {
+    let length = u16::from_be_bytes([body[at], body[at + 1]]) as usize;
+    (String::from_utf8_lossy(&body[at + 2..at + 2 + length]).to_string(), at + 2 + length)
+}
+
+/// What the broker saw of a PUBLISH: topic, payload and retain flag
+type Published = (String, Value, bool);
+
+/// Accepts a session, reports the credentials it connected with and every PUBLISH, and
+/// acknowledges them. The first session is dropped right after the CONNACK.
+async fn start_broker() -> Result<(u16, mpsc::UnboundedReceiver<Vec<String>>, mpsc::UnboundedReceiver<Published>), Box<dyn Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    let (connects, connect_rx) = mpsc::unbounded_channel();
+    let (published, published_rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        let mut sessions = 0;
+        while let Ok((mut stream, _)) = listener.accept().await {
+            sessions += 1;
+            let Ok((header, body)) = read_packet(&mut stream).await else { continue };
+            assert_eq!(header, 0x10);
+            let (protocol, at) = string_at(&body, 0);
+            assert_eq!((protocol.as_str(), body[at], body[at + 1] & 0xC2), ("MQTT", 4, 0xC2));
+            let (client_id, at) = string_at(&body, at + 4);
+            let (username, at) = string_at(&body, at);
+            let (password, _) = string_at(&body, at);
+            connects.send(vec![client_id, username, password]).ok();
+            stream.write_all(&[0x20, 0x02, 0x00, 0x00]).await.ok();
+            if sessions == 1 {
+                continue;
+            }
+
+            while let Ok((header, body)) = read_packet(&mut stream).await {
+                if header >> 4 != 3 {
+                    continue;
+                }
+                let (topic, at) = string_at(&body, 0);
+                let packet_id = [body[at], body[at + 1]];
+                let payload = serde_json::from_slice(&body[at + 2..]).unwrap_or(Value::Null);
+                published.send((topic, payload, header & 0x01 == 1)).ok();
+                stream.write_all(&[0x40, 0x02, packet_id[0], packet_id[1]]).await.ok();
+            }
+        }
+    });
+    Ok((port, connect_rx, published_rx))
+}
+
+#[tokio::test]
+async fn test_values_and_statuses_are_published_after_a_reconnect() -> Result<(), Box<dyn Error>> {
+    let (port, mut connects, mut published) = start_broker().await?;
+    let metrics = Arc::new(Metrics::new());
+    let publisher = Arc::new(MqttPublisher::new(config(port, 100)).with_metrics(metrics.clone()));
+
+    // Queued before the first session, which is lost without any acknowledgement
+    publisher.publish_values(&[entry("Active Power", 42.5, "Good"), entry("Grid V/A", 1.0, "Good"), entry("Temperature", 0.0, "Bad")]);
+    publisher.publish_status(&DeviceStatus {
+        device_id: "inv-1".to_string(),
+        status: "Reading".to_string(),
+        last_update: Utc.with_ymd_and_hms(2026, 6, 1, 12, 0, 0).unwrap(),
+        error_message: None,
+        connection_count: 1,
+    });
+    publisher.start();
+
+    for _ in 0..2 {
+        let credentials = tokio::time::timeout(Duration::from_secs(10), connects.recv()).await?.expect("connect");
+        assert_eq!(credentials, ["logger-test", "scada", "hunter22"]);
+    }
+    let mut received = Vec::new();
+    while received.len() < 3 {
+        received.push(tokio::time::timeout(Duration::from_secs(10), published.recv()).await?.expect("publish"));
+    }
+
+    // Failed reads are skipped, and only the status is retained
+    assert_eq!(received, [
+        ("site1/inv-1/Active Power".to_string(), json!({"value": 42.5, "unit": "kW", "quality": "Good", "ts": "2026-06-01T12:00:00Z"}), false),
+        ("site1/inv-1/Grid V_A".to_string(), json!({"value": 1.0, "unit": "kW", "quality": "Good", "ts": "2026-06-01T12:00:00Z"}), false),
+        ("site1/inv-1/status".to_string(), json!({"status": "Reading", "error_message": null, "connection_count": 1, "ts": "2026-06-01T12:00:00Z"}), true),
+    ]);
+
+    // Counted once acknowledged
+    for _ in 0..50 {
+        if publisher.status().published == 3 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let status = publisher.status();
+    assert_eq!((status.connected, status.connects, status.published, status.dropped, status.queued), (true, 2, 3, 0, 0));
+    let text = metrics.render(None);
+    for line in ["ava_mqtt_connected 1", "ava_mqtt_connects_total 2", "ava_mqtt_messages_published_total 3", "ava_mqtt_queue_length 0"] {
+        assert!(text.lines().any(|sample| sample == line), "{} missing from\n{}", line, text);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_the_oldest_messages_are_dropped_while_disconnected() {
+    let metrics = Arc::new(Metrics::new());
+    let publisher = MqttPublisher::new(config(1, 3)).with_metrics(metrics.clone());
+    let entries: Vec<LogEntry> = (0..5).map(|i| entry(&format!("Tag{}", i), i as f64, "Good")).collect();
+    publisher.publish_values(&entries);
+
+    let status = publisher.status();
+    assert_eq!((status.connected, status.queued, status.dropped, status.published), (false, 3, 2, 0));
+    assert!(metrics.render(None).lines().any(|sample| sample == "ava_mqtt_messages_dropped_total 2"));
+}
+
+#[test]
+fn test_metrics_leave_out_mqtt_when_it_is_not_configured() {
+    assert!(!Metrics::new().render(None).contains("ava_mqtt"));
+}
+
+/// Answers every CONNECT with `return_code` and reports the fixed header and topic of every
+/// PUBLISH without acknowledging it
+async fn start_silent_broker(return_code: u8) -> Result<(u16, mpsc::UnboundedReceiver<(u8, String)>), Box<dyn Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    let (published, published_rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let published = published.clone();
+            tokio::spawn(async move {
+                if read_packet(&mut stream).await.is_err() {
+                    return;
+                }
+                stream.write_all(&[0x20, 0x02, 0x00, return_code]).await.ok();
+                while let Ok((header, body)) = read_packet(&mut stream).await {
+                    match header >> 4 {
+                        3 => {
+                            published.send((header, string_at(&body, 0).0)).ok();
+                        }
+                        // PINGREQ
+                        12 => {
+                            stream.write_all(&[0xD0, 0x00]).await.ok();
+                        }
+                        _ => {}
+                    }
+                }
+            });
+        }
+    });
+    Ok((port, published_rx))
+}
+
+#[tokio::test]
+async fn test_qos_0_messages_count_once_written() -> Result<(), Box<dyn Error>> {
+    let (port, mut published) = start_silent_broker(0).await?;
+    let publisher = Arc::new(MqttPublisher::new(MqttConfig { qos: 0, ..config(port, 100) }));
+    publisher.start();
+    publisher.publish_values(&[entry("Active Power", 42.5, "Good"), entry("Reactive Power", 1.0, "Good")]);
+
+    for topic in ["site1/inv-1/Active Power", "site1/inv-1/Reactive Power"] {
+        let (header, received) = tokio::time::timeout(Duration::from_secs(10), published.recv()).await?.expect("publish");
+        // No QoS bits, so no packet id and nothing to acknowledge
+        assert_eq!((header & 0x06, received.as_str()), (0, topic));
+    }
+    for _ in 0..50 {
+        if publisher.status().published == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let status = publisher.status();
+    assert_eq!((status.connected, status.connects, status.published, status.queued), (true, 1, 2, 0));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_refused_connections_keep_messages_queued() -> Result<(), Box<dyn Error>> {
+    // Bad user name or password
+    let (port, mut published) = start_silent_broker(4).await?;
+    let publisher = Arc::new(MqttPublisher::new(config(port, 100)));
+    publisher.publish_values(&[entry("Active Power", 42.5, "Good")]);
+    publisher.start();
+
+    let mut status = publisher.status();
+    for _ in 0..100 {
+        if status.last_error.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        status = publisher.status();
+    }
+    assert!(status.last_error.is_some(), "{:?}", status);
+    assert_eq!((status.connected, status.connects, status.published, status.queued), (false, 0, 0, 1));
+    assert!(published.try_recv().is_err());
+    Ok(())
+}
@@ -0,0 +1,86 @@
+mod support;
+
+use serde_json::{json, Value};
+use std::error::Error;
+use support::Logger;
+
+const BOUNDARY: &str = "multipart-upload-test";
+
+/// multipart/form-data body for text fields and one CSV file, cut off after `truncate_to` bytes
+fn multipart(fields: &[(&str, &str)], csv: &[u8], truncate_to: Option<usize>) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (name, value) in fields {
+        body.extend(format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n", BOUNDARY, name, value).bytes());
+    }
+    body.extend(format!("--{}\r\nContent-Disposition: form-data; name=\"csv_file\"; filename=\"tags.csv\"\r\nContent-Type: text/csv\r\n\r\n", BOUNDARY).bytes());
+    body.extend(csv);
+    body.extend(format!("\r\n--{}--\r\n", BOUNDARY).bytes());
+    body.truncate(truncate_to.unwrap_or(body.len()));
+    body
+}
+
+#[tokio::test]
+async fn test_bad_uploads_are_rejected_by_field() -> Result<(), Box<dyn Error>> {
+    let config = |port| support::config(port, "").replace("[server]\n", "[server]\nmax_upload_mb = 1\n");
+    let logger = Logger::start_with_config(support::work_dir("multipart-upload")?, config).await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let upload = |path: &str, body: Vec<u8>| {
+        let request = client
+            .post(format!("{}{}", base_url, path))
+            .bearer_auth(token)
+            .header("Content-Type", format!("multipart/form-data; boundary={}", BOUNDARY))
+            .body(body);
+        async move { request.send().await?.json::<Value>().await }
+    };
+    let model_fields = [("name", "Meter"), ("protocol_type", "modbus_tcp")];
+    let register_fields = [("device_model_name", "STP"), ("manufacturer", "SMA")];
+    let model_csv = "name,address,data_type\nEnergy,100,float64\n";
+    let register_csv = "Device Brand,Device Model,AVA Type,MPPT,INPUT,Data Label,Address,Size,Modbus Type,Divider,Register Type\n\
+                        SMA,STP,Inverter,,,Total Yield,5003,2,U32,1,input\n";
+
+    // A body cut off part way through the file names the file
+    let body = multipart(&model_fields, model_csv.as_bytes(), None);
+    let cut = body.len() - 20;
+    let response = upload("/api/device-models", multipart(&model_fields, model_csv.as_bytes(), Some(cut))).await?;
+    assert_eq!(response["success"], false, "{}", response);
+    assert_eq!(response["field_errors"][0]["field"], "csv_file", "{}", response);
+    assert!(response["error"].as_str().unwrap().starts_with("Upload failed: csv_file: could not be read"), "{}", response);
+
+    let body = multipart(&register_fields, register_csv.as_bytes(), None);
+    let cut = body.len() - 20;
+    let response = upload("/api/modbus-tcp-tag-registers/upload-csv", multipart(&register_fields, register_csv.as_bytes(), Some(cut))).await?;
+    assert_eq!(response["success"], false, "{}", response);
+    assert!(response["details"]["validation_errors"][0].as_str().unwrap().starts_with("csv_file: could not be read"), "{}", response);
+
+    // More than max_upload_mb
+    let oversized = format!("name,address,data_type\n{}", "Energy,100,float64\n".repeat(60_000));
+    assert!(oversized.len() > 1024 * 1024);
+    let response = upload("/api/device-models", multipart(&model_fields, oversized.as_bytes(), None)).await?;
+    assert_eq!(response["success"], false, "{}", response);
+    assert_eq!(response["field_errors"], json!([{"field": "csv_file", "message": "file too large; uploads are limited to 1 MB"}]));
+    let response = upload("/api/modbus-tcp-tag-registers/upload-csv", multipart(&register_fields, oversized.as_bytes(), None)).await?;
+    assert_eq!(response["details"]["validation_errors"], json!(["csv_file: file too large; uploads are limited to 1 MB"]), "{}", response);
+
+    // Binary data isn't parsed as CSV
+    let response = upload("/api/device-models", multipart(&model_fields, b"PK\x03\x04\x00\x00binary", None)).await?;
+    assert_eq!(response["field_errors"][0]["field"], "csv_file", "{}", response);
+    assert!(response["field_errors"][0]["message"].as_str().unwrap().contains("binary"), "{}", response);
+
+    // Latin-1 from a spreadsheet export is transcoded, and a UTF-8 byte order mark dropped
+    let latin1 = b"name,address,data_type\nTemp\xe9rature,100,float32\n";
+    let response = upload("/api/device-models", multipart(&model_fields, latin1, None)).await?;
+    assert_eq!(response["success"], true, "{}", response);
+    let model_id = response["data"]["id"].as_str().unwrap().to_string();
+    let tags: Value = client.get(format!("{}/api/device-models/{}/tags", base_url, model_id)).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(tags["data"][0]["name"], "Température", "{}", tags);
+
+    let with_bom = [b"\xEF\xBB\xBF".as_slice(), register_csv.as_bytes()].concat();
+    let response = upload("/api/modbus-tcp-tag-registers/upload-csv", multipart(&register_fields, &with_bom, None)).await?;
+    assert_eq!(response["success"], true, "{}", response);
+    assert_eq!(response["data"]["records_processed"], 1);
+
+    // And the service is still up after all of it
+    assert_eq!(client.get(format!("{}/api/health", base_url)).send().await?.status(), 200);
+    Ok(())
+}
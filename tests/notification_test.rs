@@ -0,0 +1,53 @@
+use ava_device_logger::database::{Database, NewNotification};
+use chrono::{Duration, Utc};
+use std::error::Error;
+
+fn notification(title: &str, user_id: Option<i64>) -> NewNotification {
+    NewNotification {
+        notification_type: "sync_completed".to_string(),
+        severity: "info".to_string(),
+        title: title.to_string(),
+        body: "body".to_string(),
+        entity_type: None,
+        entity_id: None,
+        user_id,
+    }
+}
+
+#[tokio::test]
+async fn test_broadcast_read_state_is_per_user() -> Result<(), Box<dyn Error>> {
+    let db_path = std::env::temp_dir()
+        .join(format!("notifications-{}.db", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string();
+    let db = Database::new(&db_path).await?;
+
+    let admin = db.verify_user("admin", "admin123").await?.unwrap().id.unwrap();
+    let installer = db.verify_user("installer", "installer123").await?.unwrap().id.unwrap();
+
+    let broadcast = db.create_notification(&notification("broadcast", None)).await?;
+    let private = db.create_notification(&notification("admin only", Some(admin))).await?;
+
+    assert_eq!(db.get_notifications(admin, true, 100).await?.len(), 2);
+    assert_eq!(db.get_notifications(installer, true, 100).await?.len(), 1);
+
+    // Installers can't see or mark the admin's private notification
+    assert!(!db.mark_notification_read(private, installer).await?);
+
+    assert!(db.mark_notification_read(broadcast, admin).await?);
+    assert_eq!(db.get_notifications(admin, true, 100).await?.len(), 1);
+    assert_eq!(db.get_notifications(installer, true, 100).await?.len(), 1);
+
+    // Purge keeps the broadcast until every user has read it
+    db.mark_all_notifications_read(admin).await?;
+    let future = Utc::now() + Duration::minutes(1);
+    assert_eq!(db.purge_read_notifications(future).await?, 1);
+    assert_eq!(db.get_notifications(installer, false, 100).await?.len(), 1);
+
+    db.mark_all_notifications_read(installer).await?;
+    assert_eq!(db.purge_read_notifications(future).await?, 1);
+    assert!(db.get_notifications(admin, false, 100).await?.is_empty());
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
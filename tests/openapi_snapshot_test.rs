@@ -0,0 +1,36 @@
+use std::path::Path;
+use std::process::Command;
+
+const SNAPSHOT: &str = "tests/snapshots/openapi.json";
+
+/// Fails when the generated OpenAPI document drifts from the committed snapshot.
+///
+/// After an intentional API change, regenerate it with
+/// `UPDATE_OPENAPI_SNAPSHOT=1 cargo test --test openapi_snapshot_test`.
+#[test]
+fn test_openapi_document_matches_snapshot() {
+    let output = Command::new(env!("CARGO_BIN_EXE_ava-device-logger"))
+        .arg("--openapi")
+        .output()
+        .expect("failed to run ava-device-logger --openapi");
+    assert!(output.status.success(), "--openapi exited with {}", output.status);
+
+    let generated = String::from_utf8(output.stdout).expect("OpenAPI document is not UTF-8");
+    let document: serde_json::Value = serde_json::from_str(&generated).expect("OpenAPI document is not valid JSON");
+    assert!(document["paths"]["/api/login"]["post"].is_object());
+    assert!(document["components"]["securitySchemes"]["session_token"].is_object());
+
+    let snapshot_path = Path::new(env!("CARGO_MANIFEST_DIR")).join(SNAPSHOT);
+    if std::env::var_os("UPDATE_OPENAPI_SNAPSHOT").is_some() {
+        std::fs::write(&snapshot_path, &generated).expect("failed to write OpenAPI snapshot");
+        return;
+    }
+
+    let snapshot = std::fs::read_to_string(&snapshot_path).expect("missing OpenAPI snapshot");
+    assert!(
+        snapshot == generated,
+        "OpenAPI document changed; review the API change and run \
+         `UPDATE_OPENAPI_SNAPSHOT=1 cargo test --test openapi_snapshot_test` to update {}",
+        SNAPSHOT
+    );
+}
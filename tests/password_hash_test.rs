@@ -0,0 +1,114 @@
+mod support;
+
+use ava_device_logger::database::Database;
+use argon2::password_hash::{PasswordHasher, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use ava_device_logger::passwords::{hash_password, verify_password, PasswordCheck};
+use serde_json::{json, Value};
+use std::error::Error;
+use support::Logger;
+
+#[test]
+fn test_hashes_are_salted_argon2_phc_strings() {
+    let first = hash_password("hunter22");
+    let second = hash_password("hunter22");
+    assert_ne!(first, second, "each hash gets its own salt");
+    assert!(first.starts_with("$argon2id$v=19$"), "{}", first);
+
+    assert_eq!(verify_password("hunter22", &first), PasswordCheck::Match);
+    assert_eq!(verify_password("hunter23", &first), PasswordCheck::Mismatch);
+    // Plaintext legacy values ask to be replaced
+    assert_eq!(verify_password("hunter22", "hunter22"), PasswordCheck::MatchNeedsRehash);
+    assert_eq!(verify_password("hunter2", "hunter22"), PasswordCheck::Mismatch);
+}
+
+#[test]
+fn test_older_hashes_ask_to_be_replaced() {
+    // Argon2 with cheaper parameters than new hashes get
+    let params = Params::new(1024, 1, 1, None).unwrap();
+    let salt = SaltString::encode_b64(b"sixteen byte salt").unwrap();
+    let weak = Argon2::new(Algorithm::Argon2id, Version::V0x13, params).hash_password(b"hunter22", &salt).unwrap().to_string();
+    assert_eq!(verify_password("hunter22", &weak), PasswordCheck::MatchNeedsRehash);
+    assert_eq!(verify_password("hunter23", &weak), PasswordCheck::Mismatch);
+}
+
+#[tokio::test]
+async fn test_legacy_hashes_are_replaced_on_login() -> Result<(), Box<dyn Error>> {
+    let path = std::env::temp_dir().join(format!("password-rehash-{}.db", uuid::Uuid::new_v4()));
+    let db = Database::new(&path.to_string_lossy()).await?;
+
+    // A database seeded before passwords were hashed, plus a plaintext password
+    let conn = rusqlite::Connection::open(&path)?;
+    conn.execute("UPDATE local_users SET password_hash = '$2b$12$LQv3c1yqBWVHxkd0LHAkCOYz6TtxMaHKi9M2F6bOVOA8qVCNzqb1q', must_change_password = 0 WHERE username = 'admin'", [])?;
+    conn.execute("UPDATE local_users SET password_hash = 'letmein!', must_change_password = 0 WHERE username = 'installer'", [])?;
+    drop(conn);
+    drop(db);
+    let db = Database::new(&path.to_string_lossy()).await?;
+
+    assert!(db.verify_user("admin", "wrong").await?.is_none());
+    let admin = db.verify_user("admin", "admin123").await?.expect("legacy default password accepted");
+    assert!(admin.must_change_password, "seeded accounts must change their password");
+    assert!(admin.password_hash.starts_with("$argon2id$"));
+    assert_eq!(verify_password("admin123", &admin.password_hash), PasswordCheck::Match);
+
+    let installer = db.verify_user("installer", "letmein!").await?.expect("plaintext password accepted");
+    assert!(!installer.must_change_password);
+    assert!(installer.password_hash.starts_with("$argon2id$"));
+    // The plaintext value itself no longer works as a hash
+    assert!(db.verify_user("installer", &installer.password_hash).await?.is_none());
+
+    let conn = rusqlite::Connection::open(&path)?;
+    let stored: String = conn.query_row("SELECT password_hash FROM local_users WHERE username = 'installer'", [], |row| row.get(0))?;
+    assert_eq!(stored, installer.password_hash);
+
+    drop(db);
+    let _ = std::fs::remove_file(&path);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_password_change_revokes_other_sessions() -> Result<(), Box<dyn Error>> {
+    let logger = Logger::start("[auth]\nmin_password_length = 12\n").await?;
+    let (client, base_url) = (&logger.client, &logger.base_url);
+    let login = |password: &str| {
+        let request = client.post(format!("{}/api/login", base_url)).json(&json!({"username": "installer", "password": password}));
+        async move { request.send().await?.json::<Value>().await }
+    };
+
+    let body = login("installer123").await?;
+    assert_eq!(body["data"]["user"]["must_change_password"], true, "{}", body);
+    let token = body["data"]["session_token"].as_str().unwrap().to_string();
+    let other = login("installer123").await?["data"]["session_token"].as_str().unwrap().to_string();
+
+    let change = |current: &str, new: &str| {
+        let request = client.post(format!("{}/api/users/me/password", base_url)).bearer_auth(&token)
+            .json(&json!({"current_password": current, "new_password": new}));
+        async move { request.send().await?.json::<Value>().await }
+    };
+
+    let body = change("wrong-password", "a much longer password").await?;
+    assert_eq!(body["success"], false);
+    assert_eq!(body["field_errors"], json!([{"field": "current_password", "message": "is incorrect"}]));
+
+    let body = change("installer123", "short").await?;
+    assert_eq!(body["field_errors"], json!([{"field": "new_password", "message": "must be at least 12 characters"}]));
+
+    let body = change("installer123", "a much longer password").await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!(body["data"]["revoked_sessions"], 1);
+
+    // The other session is gone, this one and other users' sessions are kept
+    let verify = |token: String| {
+        let request = client.get(format!("{}/api/sessions", base_url)).bearer_auth(token);
+        async move { request.send().await.map(|response| response.status().as_u16()) }
+    };
+    assert_eq!(verify(other).await?, 401);
+    assert_eq!(verify(token.clone()).await?, 200);
+    assert_eq!(verify(logger.token.clone()).await?, 200);
+
+    assert_eq!(login("installer123").await?["success"], false);
+    let body = login("a much longer password").await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!(body["data"]["user"]["must_change_password"], false);
+    Ok(())
+}
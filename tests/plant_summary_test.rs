@@ -0,0 +1,149 @@
+mod support;
+
+use ava_device_logger::database::{AlarmCondition, DeviceInstance, DeviceTag, LogEntry, NewAlarmRule, TagWritePolicy};
+use chrono::{Duration as ChronoDuration, Utc};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::time::Duration;
+use support::Logger;
+
+fn device(id: &str) -> DeviceInstance {
+    DeviceInstance {
+        id: id.to_string(),
+        name: id.to_string(),
+        serial_no: None,
+        model_id: None,
+        enabled: false,
+        polling_interval_ms: 2000,
+        timeout_ms: 1000,
+        retry_count: 1,
+        protocol_config: json!({"type": "modbus_tcp", "host": "10.0.0.10", "port": 502, "slave_id": 1}).to_string(),
+        tb_device_id: None,
+        tb_group_id: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    }
+}
+
+fn tag(device_id: &str, name: &str, address: u16, unit: &str, agg_to_field: Option<&str>) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: device_id.to_string(),
+        name: name.to_string(),
+        address,
+        size: 1,
+        data_type: "uint16".to_string(),
+        description: None,
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: Some(unit.to_string()),
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: agg_to_field.map(str::to_string),
+        write_policy: TagWritePolicy::Disabled,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+fn entry(device_id: &str, tag_name: &str, value: f64, unit: &str, age_seconds: i64) -> LogEntry {
+    LogEntry {
+        id: None,
+        device_id: device_id.to_string(),
+        tag_name: tag_name.to_string(),
+        value,
+        quality: "Good".to_string(),
+        timestamp: Utc::now() - ChronoDuration::seconds(age_seconds),
+        unit: Some(unit.to_string()),
+    }
+}
+
+#[tokio::test]
+async fn test_plant_summary_combines_states_power_energy_and_alarms() -> Result<(), Box<dyn Error>> {
+    let logger = Logger::start("").await?;
+    let (client, base_url) = (&logger.client, &logger.base_url);
+    let token = logger.login("installer", "installer123").await?;
+    let db = logger.database().await?;
+
+    // A running simulated inverter whose power comes from the live value cache
+    let simulated = json!({
+        "id": "sim-1", "name": "Simulated", "enabled": true, "polling_interval_ms": 200, "timeout_ms": 1000, "retry_count": 1,
+        "protocol_config": {"type": "simulated", "patterns": {"AC Power": {"pattern": "constant", "value": 20}}},
+        "tags": [{
+            "name": "AC Power", "address": 0, "size": 1, "data_type": "uint16", "description": null, "scaling_multiplier": 1.0,
+            "scaling_offset": 0.0, "unit": "kW", "read_only": true, "enabled": true, "schedule_group_id": null, "agg_to_field": "power",
+        }],
+    });
+    let body: Value = client.post(format!("{}/api/devices-enhanced", base_url)).bearer_auth(&token).json(&simulated).send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let body: Value = client.post(format!("{}/api/devices-enhanced/sim-1/start", base_url)).bearer_auth(&token).send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+
+    // Stopped devices whose power comes from the log: producing, idle, too old, and two without power tags
+    for (id, tags) in [
+        ("inv-1", vec![tag("inv-1", "AC Power", 0, "W", Some("power")), tag("inv-1", "Total Energy", 2, "kWh", None)]),
+        ("inv-2", vec![tag("inv-2", "AC Power", 0, "kW", Some("power"))]),
+        ("inv-3", vec![tag("inv-3", "AC Power", 0, "kW", Some("power"))]),
+        ("inv-4", vec![tag("inv-4", "Pac", 0, "kW", None)]),
+        ("meter-1", vec![tag("meter-1", "Voltage", 0, "V", None)]),
+    ] {
+        db.create_device(&device(id)).await?;
+        db.create_device_tags(id, &tags).await?;
+    }
+    db.insert_log_entries(&[
+        entry("inv-1", "AC Power", 5000.0, "W", 10),
+        entry("inv-1", "Total Energy", 100.0, "kWh", 1),
+        entry("inv-1", "Total Energy", 112.5, "kWh", 0),
+        entry("inv-2", "AC Power", 0.0, "kW", 10),
+        entry("inv-3", "AC Power", 7.0, "kW", 600),
+        entry("inv-4", "Pac", 3.0, "kW", 10),
+    ]).await?;
+
+    let rule = db.create_alarm_rule(&NewAlarmRule {
+        device_id: Some("inv-1".to_string()),
+        model_id: None,
+        tag_name: "AC Power".to_string(),
+        condition: AlarmCondition::Gt,
+        threshold: 4000.0,
+        hysteresis: 0.0,
+        clear_hold_seconds: 0,
+        severity: "warning".to_string(),
+        enabled: true,
+    }).await?;
+    db.raise_alarm(&rule, "inv-1", 5000.0, Utc::now()).await?;
+
+    let summary_url = format!("{}/api/plant/summary", base_url);
+    let mut summary = Value::Null;
+    for _ in 0..50 {
+        let body: Value = client.get(&summary_url).bearer_auth(&token).send().await?.json().await?;
+        summary = body["data"].clone();
+        if summary["power"]["reporting_devices"] == 3 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(summary["devices"], json!({"total": 6, "running": 1, "stopped": 5, "error": 0, "offline": 0}), "{}", summary);
+    assert_eq!(
+        summary["power"],
+        json!({
+            "total_kw": 25.0, "producing_devices": 2, "reporting_devices": 3, "missing_value_devices": 1,
+            "excluded_devices": 2, "values_from_log": 2,
+        }),
+        "{}",
+        summary
+    );
+    assert_eq!(summary["energy_today_kwh"], 12.5);
+    assert_eq!(summary["active_alarms"], 1);
+    assert_eq!(summary["plant"]["plant_name"], "Default Plant");
+
+    // Power tags picked by name instead of the marker
+    let body: Value = client.get(format!("{}?power_tag=pac", summary_url)).bearer_auth(&token).send().await?.json().await?;
+    assert_eq!((&body["data"]["power"]["total_kw"], &body["data"]["power"]["excluded_devices"]), (&json!(3.0), &json!(5)), "{}", body);
+    let body: Value = client.get(format!("{}?power_tag=*AC*", summary_url)).bearer_auth(&token).send().await?.json().await?;
+    assert_eq!((&body["data"]["power"]["total_kw"], &body["data"]["power"]["reporting_devices"]), (&json!(28.0), &json!(4)), "{}", body);
+    Ok(())
+}
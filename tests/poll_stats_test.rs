@@ -0,0 +1,175 @@
+mod support;
+
+use ava_device_logger::database::{Database, DeviceInstance, DeviceTag, Quality, TagWritePolicy};
+use ava_device_logger::poll_stats::{failure_quality, failure_reason, PollStats, StatsWindow};
+use chrono::{TimeZone, Utc};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::io::ErrorKind;
+use std::time::Duration;
+use support::{Logger, ModbusDevice};
+
+#[test]
+fn test_failures_are_classified_by_reason() {
+    let reason = |error: std::io::Error| failure_reason(&anyhow::Error::new(error));
+    assert_eq!(reason(std::io::Error::new(ErrorKind::TimedOut, "slave 1 gave no answer")), "timeout");
+    assert_eq!(reason(std::io::Error::other("Modbus function 3: Illegal data address")), "exception");
+    assert_eq!(reason(std::io::Error::from(ErrorKind::BrokenPipe)), "connection_lost");
+    assert_eq!(failure_reason(&anyhow::anyhow!("No client connected")), "other");
+
+    // Only a device that didn't answer is a communication failure; everything else is Bad
+    let quality = |error: std::io::Error| failure_quality(&anyhow::Error::new(error));
+    assert_eq!(quality(std::io::Error::new(ErrorKind::TimedOut, "slave 1 gave no answer")), Quality::CommFail);
+    assert_eq!(quality(std::io::Error::other("Modbus function 3: Illegal data address")), Quality::Bad);
+    assert_eq!(failure_quality(&anyhow::anyhow!("No client connected")), Quality::Bad);
+}
+
+#[tokio::test]
+async fn test_stats_are_reported_per_group_and_survive_a_restart() -> Result<(), Box<dyn Error>> {
+    let db_path = std::env::temp_dir().join(format!("poll-stats-{}.db", uuid::Uuid::new_v4()));
+    let db = Database::new(&db_path.to_string_lossy()).await?;
+    let now = Utc.with_ymd_and_hms(2026, 6, 1, 12, 0, 0).unwrap();
+    let minutes_ago = |minutes: i64| now - chrono::Duration::minutes(minutes);
+
+    let stats = PollStats::new();
+    // 18 fast polls and 2 slow ones in the last hour, plus failures of the energy group
+    for i in 0..18 {
+        stats.record_success("inv-1", "device", Duration::from_millis(20), minutes_ago(i % 30));
+    }
+    stats.record_success("inv-1", "device", Duration::from_millis(800), minutes_ago(5));
+    stats.record_success("inv-1", "device", Duration::from_millis(900), minutes_ago(5));
+    stats.record_failure("inv-1", "energy", "timeout", "slave 1 gave no answer within 1s", minutes_ago(10));
+    stats.record_failure("inv-1", "energy", "timeout", "slave 1 gave no answer within 1s", minutes_ago(9));
+    stats.record_failure("inv-1", "energy", "connect_failed", "Connection refused", minutes_ago(3));
+    stats.record_success("inv-1", "energy", Duration::from_millis(30), minutes_ago(2));
+    // Only in the 24 hour window, and another device
+    stats.record_failure("inv-1", "device", "exception", "Illegal data address", minutes_ago(300));
+    stats.record_success("inv-2", "device", Duration::from_millis(10), minutes_ago(1));
+
+    // Half saved, half still in memory
+    stats.save(&db, now).await?;
+    stats.record_success("inv-1", "device", Duration::from_millis(20), minutes_ago(0));
+
+    let report = stats.report(&db, "inv-1", StatsWindow::Hour, now).await?;
+    assert_eq!((report.summary.polls, report.summary.successes, report.summary.failures, report.summary.timeouts), (25, 22, 3, 2));
+    assert_eq!(report.summary.success_rate, Some(22.0 / 25.0));
+    assert_eq!(report.summary.failure_reasons.get("connect_failed"), Some(&1));
+    assert_eq!(report.summary.last_error.as_deref(), Some("Connection refused"));
+    let p50 = report.summary.latency_p50_ms.unwrap();
+    let p95 = report.summary.latency_p95_ms.unwrap();
+    assert!((10.0..=25.0).contains(&p50), "p50 {}", p50);
+    assert!((500.0..=1000.0).contains(&p95), "p95 {}", p95);
+
+    let groups: Vec<(&str, u64, u64)> = report.schedule_groups.iter()
+        .map(|group| (group.schedule_group_id.as_str(), group.summary.successes, group.summary.failures))
+        .collect();
+    assert_eq!(groups, [("device", 21, 0), ("energy", 1, 3)]);
+
+    // A restart starts from what was saved; the unsaved poll is lost
+    stats.save(&db, now).await?;
+    let restarted = PollStats::new();
+    let report = restarted.report(&db, "inv-1", StatsWindow::Day, now).await?;
+    assert_eq!((report.summary.polls, report.summary.failures), (26, 4));
+    assert_eq!(report.summary.failure_reasons.get("exception"), Some(&1));
+    assert_eq!(report.window, StatsWindow::Day);
+
+    let rates = restarted.success_rates(&db, StatsWindow::Day, now).await?;
+    assert_eq!((rates.get("inv-1"), rates.get("inv-2")), (Some(&(22.0 / 26.0)), Some(&1.0)));
+
+    // Buckets older than two days are deleted on the next save
+    restarted.save(&db, now + chrono::Duration::hours(49)).await?;
+    assert!(db.get_poll_stats(None, now - chrono::Duration::days(7)).await?.is_empty());
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+fn device(id: &str, modbus_port: u16) -> DeviceInstance {
+    DeviceInstance {
+        id: id.to_string(),
+        name: id.to_string(),
+        serial_no: None,
+        model_id: None,
+        enabled: true,
+        polling_interval_ms: 200,
+        timeout_ms: 500,
+        retry_count: 3,
+        protocol_config: json!({"type": "modbus_tcp", "host": "127.0.0.1", "port": modbus_port, "slave_id": 1}).to_string(),
+        tb_device_id: None,
+        tb_group_id: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    }
+}
+
+fn tag(device_id: &str) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: device_id.to_string(),
+        name: "Voltage".to_string(),
+        address: 40001,
+        size: 1,
+        data_type: "uint16".to_string(),
+        description: None,
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: None,
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy: TagWritePolicy::Disabled,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+#[tokio::test]
+async fn test_stats_endpoint_and_status_report_success_rates() -> Result<(), Box<dyn Error>> {
+    let slave = ModbusDevice::start([(40001, 40001)]).await?;
+    let modbus_port = slave.port();
+    let closed_port = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+    let work_dir = support::work_dir("poll-stats-api")?;
+
+    let db = Database::new(&work_dir.join("data.db").to_string_lossy()).await?;
+    db.create_device(&device("meter-ok", modbus_port)).await?;
+    db.create_device_tags("meter-ok", &[tag("meter-ok")]).await?;
+    db.create_device(&device("meter-down", closed_port)).await?;
+    db.create_device_tags("meter-down", &[tag("meter-down")]).await?;
+    drop(db);
+
+    let logger = Logger::start_in(work_dir, "").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+
+    let body: Value = client.get(format!("{}/api/devices-enhanced/meter-ok/stats", base_url)).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let stats = &body["data"];
+    assert_eq!((&stats["window"], &stats["failures"], &stats["success_rate"]), (&json!("1h"), &json!(0), &json!(1.0)), "{}", stats);
+    assert!(stats["successes"].as_u64() >= Some(3), "{}", stats);
+    assert!(stats["latency_p50_ms"].is_number() && stats["latency_p95_ms"].is_number(), "{}", stats);
+    assert_eq!(stats["schedule_groups"][0]["schedule_group_id"], "device");
+
+    let body: Value = client.get(format!("{}/api/devices-enhanced/meter-down/stats?window=24h", base_url)).bearer_auth(token).send().await?.json().await?;
+    let stats = &body["data"];
+    assert_eq!((&stats["window"], &stats["successes"], &stats["success_rate"]), (&json!("24h"), &json!(0), &json!(0.0)), "{}", stats);
+    assert!(stats["failure_reasons"]["connect_failed"].as_u64() >= Some(1), "{}", stats);
+    assert!(stats["last_error"].is_string(), "{}", stats);
+
+    let response = client.get(format!("{}/api/devices-enhanced/meter-ok/stats?window=7d", base_url)).bearer_auth(token).send().await?;
+    assert_eq!(response.status(), 400);
+    let response = client.get(format!("{}/api/devices-enhanced/no-such-device/stats", base_url)).bearer_auth(token).send().await?;
+    assert_eq!(response.status(), 404);
+
+    let body: Value = client.get(format!("{}/api/status", base_url)).bearer_auth(token).send().await?.json().await?;
+    let rate = |device_id: &str| {
+        body["data"]["devices"].as_array().unwrap().iter()
+            .find(|device| device["device_id"] == device_id)
+            .map(|device| device["success_rate_24h"].clone())
+    };
+    assert_eq!((rate("meter-ok"), rate("meter-down")), (Some(json!(1.0)), Some(json!(0.0))), "{}", body);
+    Ok(())
+}
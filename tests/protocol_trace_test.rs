@@ -0,0 +1,248 @@
+mod support;
+
+use ava_device_logger::config::{DataType, DeviceConfig, Iec104Config, Iec104Mode, ModbusTcpConfig, ProtocolConfig, RegisterRead, RegisterType};
+use ava_device_logger::database::{Database, DeviceInstance};
+use ava_device_logger::iec104::{Iec104Client, Iec104ModeHandle, Iec104ModeSettings};
+use ava_device_logger::modbus::ModbusClient;
+use ava_device_logger::protocol_trace::{hex_dump, ProtocolTraces, TraceDirection, MAX_TRACE_ENTRIES};
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::time::Duration;
+use support::{Logger, ModbusDevice};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+fn device_config(protocol: ProtocolConfig) -> DeviceConfig {
+    DeviceConfig {
+        id: "inv-1".to_string(),
+        name: "Inverter 1".to_string(),
+        enabled: true,
+        protocol,
+        polling_interval_ms: 1000,
+        timeout_ms: 1000,
+        retry_count: 1,
+        tags: Vec::new(),
+        strict_types: false,
+    }
+}
+
+fn holding(address: u16, size: u16) -> RegisterRead {
+    RegisterRead { register_type: RegisterType::Holding, address, size, data_type: DataType::UInt16, byte_order: None }
+}
+
+#[tokio::test]
+async fn test_modbus_pdus_are_captured_while_enabled() -> Result<(), Box<dyn Error>> {
+    // Holding registers hold their own address and input register 30001 doesn't exist
+    let device = ModbusDevice::start((100..110).map(|address| (address, address))).await?;
+    device.refuse(30001);
+    let port = device.port();
+    let traces = ProtocolTraces::new();
+    let mut client = ModbusClient::new(device_config(ProtocolConfig::ModbusTcp(ModbusTcpConfig {
+        host: "127.0.0.1".to_string(),
+        port,
+        slave_id: 1,
+        max_block_gap: 0,
+        request_delay_ms: 0,
+        serial_source: None,
+    })))
+    .with_trace(traces.device("inv-1"));
+    client.connect().await?;
+
+    // Nothing is kept before the trace is enabled
+    client.read_registers(&holding(100, 1)).await?;
+    assert!(traces.snapshot("inv-1").entries.is_empty());
+
+    let snapshot = traces.set("inv-1", true, Duration::from_secs(60));
+    assert!(snapshot.enabled);
+    assert!(snapshot.until.is_some());
+    let (registers, _) = client.read_registers(&holding(100, 10)).await?;
+    assert_eq!(registers, (100..110).collect::<Vec<u16>>());
+    let _ = client.read_registers(&RegisterRead { register_type: RegisterType::Input, ..holding(30001, 1) }).await;
+
+    let entries = traces.snapshot("inv-1").entries;
+    assert_eq!(entries.len(), 4, "{:?}", entries);
+    assert_eq!(entries[0].direction, TraceDirection::Tx);
+    assert_eq!(entries[0].hex, "03 00 64 00 0a");
+    assert_eq!(entries[0].summary, "slave 1 read holding registers 100-109");
+    assert_eq!(entries[1].direction, TraceDirection::Rx);
+    assert!(entries[1].hex.starts_with("03 14 00 64 00 65"), "{}", entries[1].hex);
+    assert_eq!(entries[2].summary, "slave 1 read input registers 30001");
+    // An exception arrives as an error, with no PDU to show
+    assert_eq!(entries[3].direction, TraceDirection::Rx);
+    assert_eq!(entries[3].hex, "");
+    assert!(entries[3].summary.starts_with("slave 1 "), "{}", entries[3].summary);
+
+    // Stopping keeps what was captured but adds nothing more
+    assert!(!traces.set("inv-1", false, Duration::ZERO).enabled);
+    client.read_registers(&holding(100, 1)).await?;
+    assert_eq!(traces.snapshot("inv-1").entries.len(), 4);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_iec104_apdus_are_captured() -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        loop {
+            let mut header = [0u8; 2];
+            if socket.read_exact(&mut header).await.is_err() {
+                break;
+            }
+            let mut body = vec![0u8; header[1] as usize];
+            if socket.read_exact(&mut body).await.is_err() {
+                break;
+            }
+            if body[0] == 0x07 {
+                let _ = socket.write_all(&[0x68, 4, 0x0B, 0, 0, 0]).await;
+            }
+        }
+    });
+
+    let protocol = ProtocolConfig::Iec104(Iec104Config {
+        host: "127.0.0.1".to_string(),
+        port,
+        common_address: 1,
+        mode: Iec104Mode::Interrogation,
+        interrogation_interval_ms: 300_000,
+    });
+    let traces = ProtocolTraces::new();
+    traces.set("rtu-1", true, Duration::from_secs(60));
+    let handle = Iec104ModeHandle::new(Iec104ModeSettings::from_protocol(&protocol).unwrap());
+    let mut client = Iec104Client::new(device_config(protocol), handle).with_trace(traces.device("rtu-1"));
+    client.connect().await?;
+
+    let entries = traces.snapshot("rtu-1").entries;
+    assert!(entries.len() >= 2, "{:?}", entries);
+    assert_eq!(entries[0].direction, TraceDirection::Tx);
+    assert_eq!(entries[0].hex, "68 04 07 00 00 00");
+    assert_eq!(entries[0].summary, "U STARTDT act");
+    assert_eq!(entries[1].direction, TraceDirection::Rx);
+    assert_eq!(entries[1].hex, "68 04 0b 00 00 00");
+    assert_eq!(entries[1].summary, "U STARTDT con");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_trace_is_bounded_and_expires() -> Result<(), Box<dyn Error>> {
+    let traces = ProtocolTraces::new();
+    let trace = traces.device("inv-1");
+    traces.set("inv-1", true, Duration::from_secs(60));
+    for i in 0..MAX_TRACE_ENTRIES + 5 {
+        trace.record(TraceDirection::Tx, &[0x03, 0x00, 0x64, 0x00, 0x01], || format!("request {}", i));
+    }
+    let snapshot = traces.snapshot("inv-1");
+    assert_eq!(snapshot.entries.len(), MAX_TRACE_ENTRIES);
+    assert_eq!(snapshot.dropped, 5);
+    assert_eq!(snapshot.entries[0].summary, "request 5");
+
+    // Starting again begins a new capture
+    traces.set("inv-1", true, Duration::from_millis(100));
+    let snapshot = traces.snapshot("inv-1");
+    assert!(snapshot.entries.is_empty());
+    assert_eq!(snapshot.dropped, 0);
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    trace.record(TraceDirection::Tx, &[0x03], || "too late".to_string());
+    let snapshot = traces.snapshot("inv-1");
+    assert!(!snapshot.enabled);
+    assert!(snapshot.until.is_none());
+    assert!(snapshot.entries.is_empty());
+    assert!(!trace.is_enabled());
+    Ok(())
+}
+
+#[test]
+fn test_hex_dump_lists_each_pdu_with_offsets() {
+    let traces = ProtocolTraces::new();
+    let trace = traces.device("inv-1");
+    traces.set("inv-1", true, Duration::from_secs(60));
+    trace.record(TraceDirection::Tx, &[0x03, 0x00, 0x64, 0x00, 0x01], || "slave 1 read holding registers 100".to_string());
+    trace.record(TraceDirection::Rx, &(0x30..0x44).collect::<Vec<u8>>(), || "serial number".to_string());
+
+    let dump = hex_dump(&traces.snapshot("inv-1"));
+    let lines: Vec<&str> = dump.lines().collect();
+    assert_eq!(lines[0], "# Protocol trace of inv-1, 2 PDUs");
+    assert!(lines[2].ends_with(" TX slave 1 read holding registers 100"), "{}", dump);
+    assert_eq!(lines[3], format!("  0000  {:<47}  |..d..|", "03 00 64 00 01"));
+    assert!(lines[5].ends_with(" RX serial number"), "{}", dump);
+    assert_eq!(lines[6], "  0000  30 31 32 33 34 35 36 37 38 39 3a 3b 3c 3d 3e 3f  |0123456789:;<=>?|");
+    assert_eq!(lines[7], format!("  0010  {:<47}  |@ABC|", "40 41 42 43"));
+}
+
+#[tokio::test]
+async fn test_trace_endpoints() -> Result<(), Box<dyn Error>> {
+    let device = ModbusDevice::start([(100, 100)]).await?;
+    let modbus_port = device.port();
+    let work_dir = support::work_dir("protocol-trace")?;
+    let db = Database::new(&work_dir.join("data.db").to_string_lossy()).await?;
+    db.create_device(&DeviceInstance {
+        id: "inv-1".to_string(),
+        name: "Inverter 1".to_string(),
+        serial_no: None,
+        model_id: None,
+        enabled: false,
+        polling_interval_ms: 1000,
+        timeout_ms: 1000,
+        retry_count: 1,
+        protocol_config: json!({"type": "modbus_tcp", "host": "127.0.0.1", "port": modbus_port, "slave_id": 3}).to_string(),
+        tb_device_id: None,
+        tb_group_id: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    })
+    .await?;
+
+    let logger = Logger::start_in(work_dir, "").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+    let trace_url = |device_id: &str, query: &str| format!("{}/api/devices-enhanced/{}/trace?{}", base_url, device_id, query);
+
+    let response = client.post(trace_url("missing", "enabled=true")).bearer_auth(token).send().await?;
+    assert_eq!(response.status(), 404);
+    let response = client.post(trace_url("inv-1", "enabled=true&duration_s=0")).bearer_auth(token).send().await?;
+    assert_eq!(response.status(), 400);
+    let body: Value = response.json().await?;
+    assert_eq!(body["field_errors"][0]["field"], "duration_s", "{}", body);
+
+    let body: Value = client.post(trace_url("inv-1", "enabled=true&duration_s=60")).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(body["data"]["enabled"], true, "{}", body);
+    assert_eq!(body["data"]["max_entries"], MAX_TRACE_ENTRIES);
+
+    // A one-off read of the stopped device is captured too
+    let body: Value = client
+        .post(format!("{}/api/devices-enhanced/inv-1/read", base_url))
+        .bearer_auth(token)
+        .json(&json!({"address": 100, "data_type": "uint16"}))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["data"]["raw_value"], 100.0, "{}", body);
+
+    let body: Value = client.get(trace_url("inv-1", "")).bearer_auth(token).send().await?.json().await?;
+    let entries = &body["data"]["entries"];
+    assert_eq!(entries[0]["direction"], "tx", "{}", body);
+    assert_eq!(entries[0]["hex"], "03 00 64 00 01", "{}", body);
+    assert_eq!(entries[0]["summary"], "slave 3 read holding registers 100", "{}", body);
+    assert_eq!(entries[1]["direction"], "rx", "{}", body);
+    assert_eq!(entries[1]["hex"], "03 02 00 64", "{}", body);
+
+    let response = client.get(trace_url("inv-1", "format=hexdump")).bearer_auth(token).send().await?;
+    assert_eq!(response.status(), 200);
+    assert!(response.headers()["content-type"].to_str()?.starts_with("text/plain"));
+    assert!(response.headers()["content-disposition"].to_str()?.contains("trace-inv-1-"));
+    let dump = response.text().await?;
+    assert!(dump.starts_with("# Protocol trace of inv-1, 2 PDUs"), "{}", dump);
+
+    let body: Value = client.post(trace_url("inv-1", "enabled=false")).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(body["data"]["enabled"], false, "{}", body);
+    assert_eq!(body["data"]["entries"].as_array().map(Vec::len), Some(2), "{}", body);
+
+    let audit: Value = client.get(format!("{}/api/audit?entity_id=inv-1", base_url)).bearer_auth(token).send().await?.json().await?;
+    let actions: Vec<&str> = audit["data"]["entries"].as_array().unwrap().iter().filter_map(|entry| entry["action"].as_str()).collect();
+    assert!(actions.contains(&"device.trace_start") && actions.contains(&"device.trace_stop"), "{}", audit);
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use ava_device_logger::config::PvNaming;
+use ava_device_logger::database::{DeviceTag, TagWritePolicy};
+use ava_device_logger::tb_rust_client::{parent_mppt_name, pv_indexes, ThingsBoardClient};
+use std::collections::HashSet;
+use std::error::Error;
+
+fn string_tag(mppt: u32, input: u32) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: "inv-1".to_string(),
+        name: "Udc".to_string(),
+        address: 5000 + input as u16,
+        size: 1,
+        data_type: "uint16".to_string(),
+        description: Some(format!("String - MPPT {} - Input {} (SG250HX)", mppt, input)),
+        scaling_multiplier: 0.1,
+        scaling_offset: 0.0,
+        unit: None,
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy: TagWritePolicy::Disabled,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+/// String tags of MPPTs with the given input counts, inputs numbered across the inverter
+fn layout(inputs_per_mppt: &[u32]) -> Vec<DeviceTag> {
+    let mut tags = Vec::new();
+    let mut input = 0;
+    for (mppt, &inputs) in inputs_per_mppt.iter().enumerate() {
+        for _ in 0..inputs {
+            input += 1;
+            tags.push(string_tag(mppt as u32 + 1, input));
+        }
+    }
+    tags
+}
+
+async fn string_names(tags: Vec<DeviceTag>, pv_naming: PvNaming) -> Result<Vec<String>, Box<dyn Error>> {
+    let client = ThingsBoardClient::new("http://localhost");
+    let hierarchy = client.analyze_device_hierarchy(tags, "ACCV-P002-Plant", 1, pv_naming).await?;
+    for string in &hierarchy.strings {
+        assert_eq!(parent_mppt_name(&string.name), Some(string.parent_mppt.as_str()));
+        assert!(string.name.ends_with(&format!("-PV{:02}", string.pv_index)), "{}", string.name);
+    }
+    Ok(hierarchy.strings.into_iter().map(|string| string.name).collect())
+}
+
+#[tokio::test]
+async fn test_uniform_layouts_have_unique_names() -> Result<(), Box<dyn Error>> {
+    for inputs in [2, 3, 4] {
+        for pv_naming in [PvNaming::Global, PvNaming::PerMppt] {
+            let names = string_names(layout(&[inputs; 3]), pv_naming).await?;
+            assert_eq!(names.len(), 3 * inputs as usize);
+            assert_eq!(names.iter().collect::<HashSet<_>>().len(), names.len(), "{:?}: {:?}", pv_naming, names);
+        }
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_per_mppt_naming_counts_actual_inputs() -> Result<(), Box<dyn Error>> {
+    // Inputs 1 and 4 both mapped to PV01 when every MPPT was assumed to have 3 inputs
+    let names = string_names(layout(&[2, 3, 4]), PvNaming::PerMppt).await?;
+    assert_eq!(names, [
+        "ACCV-P002-I01-M01-PV01", "ACCV-P002-I01-M01-PV02",
+        "ACCV-P002-I01-M02-PV01", "ACCV-P002-I01-M02-PV02", "ACCV-P002-I01-M02-PV03",
+        "ACCV-P002-I01-M03-PV01", "ACCV-P002-I01-M03-PV02", "ACCV-P002-I01-M03-PV03", "ACCV-P002-I01-M03-PV04",
+    ]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_global_naming_runs_across_mppts() -> Result<(), Box<dyn Error>> {
+    let names = string_names(layout(&[2, 3, 4]), PvNaming::Global).await?;
+    assert_eq!(names, [
+        "ACCV-P002-I01-M01-PV01", "ACCV-P002-I01-M01-PV02",
+        "ACCV-P002-I01-M02-PV03", "ACCV-P002-I01-M02-PV04", "ACCV-P002-I01-M02-PV05",
+        "ACCV-P002-I01-M03-PV06", "ACCV-P002-I01-M03-PV07", "ACCV-P002-I01-M03-PV08", "ACCV-P002-I01-M03-PV09",
+    ]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_names_do_not_depend_on_tag_order() -> Result<(), Box<dyn Error>> {
+    for pv_naming in [PvNaming::Global, PvNaming::PerMppt] {
+        let tags = layout(&[4, 2, 3]);
+        let mut reversed = tags.clone();
+        reversed.reverse();
+        assert_eq!(string_names(tags, pv_naming).await?, string_names(reversed, pv_naming).await?);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_pv_indexes() {
+    let strings = [(1, 1), (1, 2), (2, 3), (2, 4), (2, 5)];
+    assert_eq!(pv_indexes(PvNaming::Global, &strings), [1, 2, 3, 4, 5]);
+    assert_eq!(pv_indexes(PvNaming::PerMppt, &strings), [1, 2, 1, 2, 3]);
+    assert_eq!(parent_mppt_name("ACCV-P002-I01-M02-PV03"), Some("ACCV-P002-I01-M02"));
+    assert_eq!(parent_mppt_name("ACCV-P002-I01-M02"), None);
+}
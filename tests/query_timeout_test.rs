@@ -0,0 +1,67 @@
+use ava_device_logger::database::{Database, OperationError};
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+/// Database with `rows` log entries, bulk-inserted through a second connection
+async fn database_with_logs(name: &str, rows: u32) -> Result<(Database, String), Box<dyn Error>> {
+    let db_path = std::env::temp_dir()
+        .join(format!("{}-{}.db", name, uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string();
+    let db = Database::new(&db_path).await?;
+
+    let conn = rusqlite::Connection::open(&db_path)?;
+    conn.execute(
+        "INSERT INTO log_entries (device_id, tag_name, value, quality, timestamp, unit)
+         WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < ?1)
+         SELECT 'meter-1', 'active_power', i, 'good', strftime('%Y-%m-%dT%H:%M:%S+00:00', 1700000000 + i, 'unixepoch'), 'kW' FROM n",
+        [rows],
+    )?;
+    Ok((db, db_path))
+}
+
+#[tokio::test]
+async fn test_deadline_interrupts_running_statement() -> Result<(), Box<dyn Error>> {
+    let (db, db_path) = database_with_logs("query-timeout", 300_000).await?;
+
+    // Without the progress handler the statement would run to completion and return rows
+    let started = Instant::now();
+    let mut operation = db.begin_operation(Duration::from_millis(1));
+    let result = operation.run(db.get_log_entries(None, None, None)).await;
+    assert!(matches!(result, Err(OperationError::TimedOut(_))), "{:?}", result.map(|rows| rows.len()));
+    assert!(started.elapsed() < Duration::from_secs(5));
+    drop(operation);
+
+    // The connection is released and usable afterwards
+    let mut operation = db.begin_operation(Duration::from_secs(30));
+    let entries = operation.run(db.get_log_entries(Some("meter-1"), Some(10), None)).await?;
+    assert_eq!(entries.len(), 10);
+    operation.finish();
+
+    let stats = db.operation_stats();
+    assert_eq!((stats.active, stats.completed, stats.cancelled, stats.timed_out), (0, 1, 0, 1));
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_dropped_operation_counts_as_cancelled() -> Result<(), Box<dyn Error>> {
+    let (db, db_path) = database_with_logs("query-cancel", 10).await?;
+
+    let mut operation = db.begin_operation(Duration::from_secs(30));
+    let first = operation.run(db.get_log_entries_after(None, None, None, 0, 4)).await?;
+    assert_eq!(first.len(), 4);
+    let token = operation.token().clone();
+    assert_eq!(db.operation_stats().active, 1);
+
+    // The client went away between chunks
+    drop(operation);
+    assert!(token.is_cancelled());
+
+    let stats = db.operation_stats();
+    assert_eq!((stats.active, stats.cancelled), (0, 1));
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
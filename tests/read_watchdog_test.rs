@@ -0,0 +1,88 @@
+use std::time::Duration;
+use ava_device_logger::read_watchdog::ReadWatchdog;
+use chrono::Utc;
+use tokio::sync::watch;
+
+fn seconds(seconds: i64) -> chrono::Duration {
+    chrono::Duration::seconds(seconds)
+}
+
+#[test]
+fn test_device_goes_offline_after_three_missed_intervals() {
+    let watchdog = ReadWatchdog::new();
+    let started = Utc::now();
+    let (_fast, fast_interval) = watch::channel(Duration::from_secs(5));
+    let (_slow, slow_interval) = watch::channel(Duration::from_secs(60));
+    watchdog.watch("meter-1", vec![slow_interval, fast_interval], started);
+
+    // The fastest group sets the threshold, counted from the start until the first read
+    assert!(watchdog.overdue(started + seconds(14)).is_empty());
+    watchdog.record_read("meter-1", started + seconds(10));
+    assert!(watchdog.overdue(started + seconds(24)).is_empty());
+
+    let overdue = watchdog.overdue(started + seconds(25));
+    assert_eq!(overdue.len(), 1);
+    assert_eq!(overdue[0].device_id, "meter-1");
+    assert_eq!(overdue[0].silence, Duration::from_secs(15));
+    assert_eq!(overdue[0].interval, Duration::from_secs(5));
+    assert_eq!(overdue[0].last_successful_read, Some(started + seconds(10)));
+
+    // Reported once per outage
+    assert!(watchdog.overdue(started + seconds(40)).is_empty());
+    let health = watchdog.health("meter-1");
+    assert_eq!(health.offline_events, 1);
+    assert_eq!(health.last_successful_read, Some(started + seconds(10)));
+}
+
+#[test]
+fn test_next_good_read_recovers_the_device() {
+    let watchdog = ReadWatchdog::new();
+    let started = Utc::now();
+    let (_sender, interval) = watch::channel(Duration::from_secs(1));
+    watchdog.watch("meter-1", vec![interval], started);
+
+    assert!(!watchdog.record_read("meter-1", started + seconds(1)));
+    assert_eq!(watchdog.overdue(started + seconds(4)).len(), 1);
+
+    assert!(watchdog.record_read("meter-1", started + seconds(6)));
+    assert!(!watchdog.record_read("meter-1", started + seconds(7)));
+    assert!(watchdog.overdue(started + seconds(9)).is_empty());
+
+    // A second outage is counted again
+    assert_eq!(watchdog.overdue(started + seconds(10)).len(), 1);
+    assert_eq!(watchdog.health("meter-1").offline_events, 2);
+}
+
+#[test]
+fn test_threshold_follows_interval_changes() {
+    let watchdog = ReadWatchdog::new();
+    let started = Utc::now();
+    let (sender, interval) = watch::channel(Duration::from_secs(10));
+    watchdog.watch("meter-1", vec![interval], started);
+
+    assert!(watchdog.overdue(started + seconds(20)).is_empty());
+    sender.send(Duration::from_secs(5)).unwrap();
+    assert_eq!(watchdog.overdue(started + seconds(20)).len(), 1);
+}
+
+#[test]
+fn test_stopped_devices_are_not_watched() {
+    let watchdog = ReadWatchdog::new();
+    let started = Utc::now();
+    let (_sender, interval) = watch::channel(Duration::from_secs(1));
+    watchdog.watch("meter-1", vec![interval.clone()], started);
+    watchdog.record_read("meter-1", started);
+    watchdog.unwatch("meter-1");
+
+    assert!(watchdog.overdue(started + seconds(60)).is_empty());
+    assert_eq!(watchdog.health("meter-1").last_successful_read, Some(started));
+
+    // A restart gets a full threshold before its first read
+    watchdog.watch("meter-1", vec![interval], started + seconds(60));
+    assert!(watchdog.overdue(started + seconds(62)).is_empty());
+    assert_eq!(watchdog.overdue(started + seconds(63)).len(), 1);
+
+    let unknown = watchdog.health("meter-2");
+    assert_eq!(unknown.last_successful_read, None);
+    assert_eq!(unknown.offline_events, 0);
+}
@@ -0,0 +1,139 @@
+mod support;
+
+use ava_device_logger::database::Database;
+use serde_json::{json, Value};
+use std::error::Error;
+use support::Logger;
+
+#[tokio::test]
+async fn test_existing_maps_are_linked_to_their_model() -> Result<(), Box<dyn Error>> {
+    let db_path = std::env::temp_dir().join(format!("register-map-model-{}.db", uuid::Uuid::new_v4()));
+    {
+        // Register maps as stored before they had a model_id
+        let conn = rusqlite::Connection::open(&db_path)?;
+        conn.execute_batch(
+            "CREATE TABLE device_models (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                description TEXT,
+                manufacturer TEXT,
+                protocol_type TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            );
+            CREATE TABLE modbus_tcp_tag_registers (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                device_brand TEXT NOT NULL,
+                device_model TEXT NOT NULL,
+                ava_type TEXT NOT NULL,
+                mppt INTEGER,
+                input INTEGER,
+                data_label TEXT NOT NULL,
+                address INTEGER NOT NULL,
+                size INTEGER NOT NULL,
+                modbus_type TEXT NOT NULL,
+                divider REAL NOT NULL DEFAULT 1.0,
+                register_type TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL,
+                UNIQUE(device_brand, device_model, address, mppt, input)
+            );
+            INSERT INTO device_models VALUES ('stp', 'STP', NULL, 'SMA', 'modbus_tcp', '2024-01-01T00:00:00+00:00', '2024-01-01T00:00:00+00:00');
+            INSERT INTO device_models VALUES ('sun-a', 'SUN', NULL, 'Huawei', 'modbus_tcp', '2024-01-01T00:00:00+00:00', '2024-01-01T00:00:00+00:00');
+            INSERT INTO device_models VALUES ('sun-b', 'SUN', NULL, 'Huawei', 'modbus_tcp', '2024-01-01T00:00:00+00:00', '2024-01-01T00:00:00+00:00');
+            INSERT INTO modbus_tcp_tag_registers (device_brand, device_model, ava_type, data_label, address, size, modbus_type, register_type, created_at, updated_at)
+                VALUES ('SMA', 'STP', 'Inverter', 'Pac', 30775, 2, 'U32', 'input', '2024-01-01T00:00:00+00:00', '2024-01-01T00:00:00+00:00'),
+                       ('SMA', 'Other', 'Inverter', 'Pac', 30775, 2, 'U32', 'input', '2024-01-01T00:00:00+00:00', '2024-01-01T00:00:00+00:00'),
+                       ('Huawei', 'SUN', 'Inverter', 'Pac', 32080, 2, 'I32', 'holding', '2024-01-01T00:00:00+00:00', '2024-01-01T00:00:00+00:00');",
+        )?;
+    }
+
+    let db = Database::new(&db_path.to_string_lossy()).await?;
+    let registers = db.get_all_modbus_tcp_tag_registers().await?;
+    let linked = |model: &str| registers.iter().find(|register| register.device_model == model).unwrap().model_id.clone();
+    assert_eq!(linked("STP").as_deref(), Some("stp"));
+    // No model matches, or two do
+    assert_eq!(linked("Other"), None);
+    assert_eq!(linked("SUN"), None);
+
+    // Renaming the model no longer loses its map
+    rusqlite::Connection::open(&db_path)?.execute("UPDATE device_models SET name = 'Sunny Tripower' WHERE id = 'stp'", [])?;
+    let registers = db.get_modbus_tcp_tag_registers_by_model_id("stp").await?;
+    assert_eq!(registers.len(), 1);
+    assert_eq!(registers[0].data_label, "Pac");
+
+    // Unlinked rows are still found by brand and name
+    assert_eq!(db.get_modbus_tcp_tag_registers_by_model_id("sun-a").await?.len(), 1);
+
+    assert_eq!(db.delete_modbus_tcp_tag_registers_by_model_id("stp").await?, 1);
+    assert!(db.get_modbus_tcp_tag_registers_by_model_id("stp").await?.is_empty());
+    assert_eq!(db.get_all_modbus_tcp_tag_registers().await?.len(), 2);
+
+    drop(db);
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+/// multipart/form-data body for text fields and an optional CSV file
+fn multipart(boundary: &str, fields: &[(&str, &str)], csv: Option<&str>) -> String {
+    let mut body = String::new();
+    for (name, value) in fields {
+        body += &format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n", boundary, name, value);
+    }
+    if let Some(csv) = csv {
+        body += &format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"csv_file\"; filename=\"tags.csv\"\r\nContent-Type: text/csv\r\n\r\n{}\r\n",
+            boundary, csv
+        );
+    }
+    body + &format!("--{}--\r\n", boundary)
+}
+
+#[tokio::test]
+async fn test_maps_are_uploaded_and_removed_by_model_id() -> Result<(), Box<dyn Error>> {
+
+    let logger = Logger::start("").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let boundary = "register-map-model-test";
+    let post = |path: &str, body: String| {
+        let request = client
+            .post(format!("{}{}", base_url, path))
+            .bearer_auth(token)
+            .header("Content-Type", format!("multipart/form-data; boundary={}", boundary))
+            .body(body);
+        async move { request.send().await?.json::<Value>().await }
+    };
+    let model = post("/api/device-models", multipart(boundary, &[("name", "Sunny"), ("manufacturer", "SMA"), ("protocol_type", "modbus_tcp")], None)).await?;
+    assert_eq!(model["success"], true, "{}", model);
+    let model_id = model["data"]["id"].as_str().unwrap().to_string();
+
+    // The model's names are used when the form leaves them out
+    let csv = "Device Brand,Device Model,AVA Type,MPPT,INPUT,Data Label,Address,Size,Modbus Type,Divider,Register Type\n\
+               x,x,Inverter,,,Pac,30775,2,U32,1,input\n";
+    let body = post("/api/modbus-tcp-tag-registers/upload-csv", multipart(boundary, &[("model_id", &model_id)], Some(csv))).await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!((&body["data"]["device_brand"], &body["data"]["device_model"]), (&json!("SMA"), &json!("Sunny")));
+
+    let body = post("/api/modbus-tcp-tag-registers/upload-csv", multipart(boundary, &[("model_id", "retired")], Some(csv))).await?;
+    assert_eq!(body["details"]["validation_errors"], json!(["model_id: no device model 'retired'"]), "{}", body);
+
+    let registers = |query: String| {
+        let request = client.get(format!("{}/api/modbus-tcp-tag-registers?{}", base_url, query)).bearer_auth(token);
+        async move { request.send().await?.json::<Value>().await }
+    };
+    let body = registers(format!("model_id={}", model_id)).await?;
+    assert_eq!(body["data"].as_array().unwrap().len(), 1, "{}", body);
+    assert_eq!(body["data"][0]["model_id"], model_id.as_str());
+
+    let delete = |query: &str| {
+        let request = client.delete(format!("{}/api/modbus-tcp-tag-registers{}", base_url, query)).bearer_auth(token);
+        async move { request.send().await?.json::<Value>().await }
+    };
+    let body = delete("").await?;
+    assert_eq!(body["field_errors"], json!([{"field": "model_id", "message": "is required"}]), "{}", body);
+    let body = delete(&format!("?model_id={}", model_id)).await?;
+    assert_eq!(body["data"], 1, "{}", body);
+    assert!(registers(format!("model_id={}", model_id)).await?["data"].as_array().unwrap().is_empty());
+    Ok(())
+}
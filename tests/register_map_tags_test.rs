@@ -0,0 +1,173 @@
+mod support;
+
+use ava_device_logger::config::{DataType, RegisterType};
+use ava_device_logger::database::{CreateModbusTcpTagRegister, Database, DeviceInstance, DeviceTag, RegisterImportMode, TagWritePolicy};
+use ava_device_logger::modbus::{find_tag_conflicts, TagFootprint};
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::error::Error;
+use support::Logger;
+
+fn register(ava_type: &str, mppt: Option<i32>, data_label: &str, address: i32, modbus_type: &str, divider: f64, register_type: &str) -> CreateModbusTcpTagRegister {
+    CreateModbusTcpTagRegister {
+        device_brand: "SMA".to_string(),
+        device_model: "STP".to_string(),
+        ava_type: ava_type.to_string(),
+        mppt,
+        input: None,
+        data_label: data_label.to_string(),
+        address,
+        size: if modbus_type == "U32" { 2 } else { 1 },
+        modbus_type: modbus_type.to_string(),
+        divider,
+        register_type: register_type.to_string(),
+        deadband_absolute: None,
+        deadband_percent: None,
+        agg_to_field: None,
+    }
+}
+
+fn tag(name: &str, address: u16) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: "inverter-1".to_string(),
+        name: name.to_string(),
+        address,
+        size: 1,
+        data_type: "uint16".to_string(),
+        description: None,
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: None,
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy: TagWritePolicy::Disabled,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+#[test]
+fn test_input_register_tags_only_conflict_with_input_registers() {
+    let tags = [
+        TagFootprint::of("Power", &DataType::UInt16, 5003, 1),
+        TagFootprint::of("Total Yield", &DataType::UInt32, 5003, 2).with_register_type(Some(RegisterType::Input)),
+        TagFootprint::of("Running", &DataType::Coil, 5003, 1).with_register_type(Some(RegisterType::Input)),
+    ];
+    assert_eq!(tags[1].register_type, RegisterType::Input);
+    assert_eq!(tags[2].register_type, RegisterType::Coil);
+    assert!(find_tag_conflicts(&tags).is_empty());
+}
+
+#[tokio::test]
+async fn test_tags_are_generated_from_the_register_map() -> Result<(), Box<dyn Error>> {
+    let work_dir = support::work_dir("register-map-tags")?;
+    {
+        let db = Database::new(&work_dir.join("data.db").to_string_lossy()).await?;
+        db.create_device(&DeviceInstance {
+            id: "inverter-1".to_string(),
+            name: "Inverter 1".to_string(),
+            serial_no: None,
+            model_id: None,
+            enabled: false,
+            polling_interval_ms: 1000,
+            timeout_ms: 1000,
+            retry_count: 1,
+            protocol_config: json!({"type": "modbus_tcp", "host": "127.0.0.1", "port": 502, "slave_id": 1}).to_string(),
+            tb_device_id: None,
+            tb_group_id: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            strict_types: false,
+        }).await?;
+        db.create_device_tags("inverter-1", &[tag("Power", 40000), tag("Holding 5003", 5003)]).await?;
+        db.bulk_insert_modbus_tcp_tag_registers(vec![
+            register("Inverter", None, "Daily Yield", 5002, "U16", 10.0, "input"),
+            register("Inverter", None, "Total Yield", 5003, "U32", 1.0, "input"),
+            register("Inverter", None, "Yield High Word", 5004, "U16", 1.0, "input"),
+            register("Inverter", None, "Broken", 5006, "U16", 0.0, "input"),
+            register("Inverter", None, "Status", 40000, "U16", 1.0, "holding"),
+            register("MPPT", Some(1), "Udc 1", 5010, "U16", 10.0, "input"),
+            register("MPPT", Some(2), "Udc 2", 5012, "U16", 10.0, "input"),
+        ], None, RegisterImportMode::Append).await?;
+    }
+
+    let logger = Logger::start_in(work_dir, "").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let generate = |body: Value| {
+        let request = client
+            .post(format!("{}/api/devices-enhanced/inverter-1/tags/from-register-map", base_url))
+            .bearer_auth(token)
+            .json(&body);
+        async move { request.send().await?.json::<Value>().await }
+    };
+    let tags = || {
+        let request = client.get(format!("{}/api/devices-enhanced/inverter-1", base_url)).bearer_auth(token);
+        async move { Ok::<Value, reqwest::Error>(request.send().await?.json::<Value>().await?["data"]["tags"].clone()) }
+    };
+
+    // The register map must be named one way or the other
+    let response = generate(json!({})).await?;
+    assert_eq!(response["field_errors"][0]["field"], "model_id", "{}", response);
+    let response = generate(json!({"model_id": "missing"})).await?;
+    assert_eq!(response["field_errors"], json!([{"field": "model_id", "message": "no device model 'missing'"}]));
+
+    // A row on the registers of an existing tag fails the call; input registers don't clash with holding ones
+    let inverter = json!({"device_brand": "SMA", "device_model": "STP", "ava_type": "inverter"});
+    let response = generate(inverter.clone()).await?;
+    assert_eq!(response["success"], false, "{}", response);
+    assert_eq!(response["field_errors"], json!([
+        {"field": "replace_existing", "message": "'Status' (holding 40000) overlaps 'Power' (holding 40000)"},
+    ]));
+    assert_eq!(tags().await?.as_array().unwrap().len(), 2);
+
+    let mut replace = inverter.clone();
+    replace["replace_existing"] = json!(true);
+    let response = generate(replace).await?;
+    assert_eq!(response["success"], true, "{}", response);
+    assert_eq!(response["data"]["created"], 3);
+    assert_eq!(response["data"]["replaced"], json!(["Power"]));
+    let skipped: Vec<(&str, &str)> = response["data"]["skipped"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|row| (row["data_label"].as_str().unwrap(), row["reason"].as_str().unwrap()))
+        .collect();
+    assert_eq!(skipped, [
+        ("Broken", "divider 0 can't be turned into a scaling multiplier"),
+        ("Yield High Word", "'Yield High Word' (input 5004) overlaps 'Total Yield' (input 5003-5004)"),
+    ]);
+
+    let tags = tags().await?;
+    let by_name = |name: &str| tags.as_array().unwrap().iter().find(|tag| tag["name"] == name).cloned().unwrap_or(Value::Null);
+    assert_eq!(tags.as_array().unwrap().len(), 4, "{}", tags);
+    assert_eq!(by_name("Power"), Value::Null);
+    assert_eq!(by_name("Holding 5003")["register_type"], Value::Null);
+    let total_yield = by_name("Total Yield");
+    assert_eq!((total_yield["address"].clone(), total_yield["size"].clone()), (json!(5003), json!(2)));
+    assert_eq!(total_yield["data_type"], "uint32");
+    assert_eq!(total_yield["register_type"], "input");
+    assert_eq!(by_name("Daily Yield")["scaling_multiplier"], 0.1);
+    assert_eq!(by_name("Status")["register_type"], "holding");
+
+    // MPPT ranges leave out rows outside them, and rows without an MPPT
+    let response = generate(json!({"device_brand": "SMA", "device_model": "STP", "mppt_min": 2})).await?;
+    assert_eq!(response["data"]["created"], 1, "{}", response);
+    let response = client.get(format!("{}/api/devices-enhanced/inverter-1", base_url)).bearer_auth(token).send().await?.json::<Value>().await?;
+    let udc = response["data"]["tags"].as_array().unwrap().iter().find(|tag| tag["name"] == "Udc 2").cloned().unwrap();
+    assert_eq!(udc["description"], "MPPT 2");
+
+    let response = client
+        .post(format!("{}/api/devices-enhanced/missing/tags/from-register-map", base_url))
+        .bearer_auth(token)
+        .json(&inverter)
+        .send()
+        .await?;
+    assert_eq!(response.status(), 404);
+    Ok(())
+}
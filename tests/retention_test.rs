@@ -0,0 +1,106 @@
+use ava_device_logger::config::{DatabaseConfig, RetentionConfig};
+use ava_device_logger::database::{Database, LogEntry, RetentionPolicy};
+use chrono::{Duration, Utc};
+use std::error::Error;
+
+async fn database_with_entries(ages_in_days: &[i64]) -> Result<(Database, String), Box<dyn Error>> {
+    let db_path = std::env::temp_dir()
+        .join(format!("retention-{}.db", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string();
+    let db = Database::new(&db_path).await?;
+
+    let now = Utc::now();
+    for (i, age) in ages_in_days.iter().enumerate() {
+        db.insert_log_entries(&[LogEntry {
+            id: None,
+            device_id: "meter-1".to_string(),
+            tag_name: "active_power".to_string(),
+            value: i as f64,
+            quality: "Good".to_string(),
+            timestamp: now - Duration::days(*age) - Duration::seconds(i as i64),
+            unit: Some("kW".to_string()),
+        }]).await?;
+    }
+
+    Ok((db, db_path))
+}
+
+/// Space the database takes on disk, including pages still in the write-ahead log
+fn size_on_disk(db_path: &str) -> u64 {
+    [db_path.to_string(), format!("{}-wal", db_path)]
+        .iter()
+        .filter_map(|path| std::fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+#[tokio::test]
+async fn test_age_and_count_limits_are_applied_in_batches() -> Result<(), Box<dyn Error>> {
+    // Seven entries older than 30 days, then six recent ones, oldest first
+    let ages: Vec<i64> = [45; 7].into_iter().chain([2; 6]).collect();
+    let (db, db_path) = database_with_entries(&ages).await?;
+
+    let run = db.cleanup_entries(&RetentionPolicy {
+        max_age: Some(Duration::days(30)),
+        max_entries: Some(4),
+        batch_size: 3,
+    }).await?;
+    assert_eq!((run.deleted_by_age, run.deleted_by_count, run.deleted_rows()), (7, 2, 9));
+    assert!(run.finished_at >= run.started_at);
+
+    // The newest entries are the ones kept
+    let remaining = db.get_log_entries(None, None, None).await?;
+    let mut values: Vec<f64> = remaining.iter().map(|entry| entry.value).collect();
+    values.sort_by(f64::total_cmp);
+    assert_eq!(values, vec![9.0, 10.0, 11.0, 12.0]);
+
+    // Nothing more to do on the next run
+    let run = db.cleanup_entries(&RetentionPolicy { max_age: Some(Duration::days(30)), max_entries: Some(4), batch_size: 3 }).await?;
+    assert_eq!(run.deleted_rows(), 0);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_deleted_pages_are_returned_to_the_filesystem() -> Result<(), Box<dyn Error>> {
+    let (db, db_path) = database_with_entries(&[90; 3000]).await?;
+    let size_before = size_on_disk(&db_path);
+
+    let run = db.cleanup_entries(&RetentionPolicy { max_age: Some(Duration::days(30)), max_entries: None, batch_size: 1000 }).await?;
+    assert_eq!(run.deleted_by_age, 3000);
+    assert!(run.reclaimed_bytes > 0, "{:?}", run);
+    assert!(size_on_disk(&db_path) < size_before);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[test]
+fn test_unset_limits_fall_back_to_database_section() {
+    let database = DatabaseConfig {
+        path: "data.db".to_string(),
+        max_log_entries: 250_000,
+        cleanup_interval_hours: 6,
+        deadband_heartbeat_minutes: 15,
+    };
+
+    let defaults = RetentionConfig::default();
+    let policy = defaults.policy(&database);
+    assert_eq!(policy.max_entries, Some(250_000));
+    assert!(policy.max_age.is_none());
+    assert_eq!(defaults.interval(&database), std::time::Duration::from_secs(6 * 3600));
+
+    let configured = RetentionConfig {
+        max_age_days: Some(30),
+        max_entries: Some(1000),
+        cleanup_interval_minutes: Some(15),
+        batch_size: 0,
+    };
+    let policy = configured.policy(&database);
+    assert_eq!(policy.max_age, Some(Duration::days(30)));
+    assert_eq!(policy.max_entries, Some(1000));
+    assert_eq!(policy.batch_size, 1);
+    assert_eq!(configured.interval(&database), std::time::Duration::from_secs(15 * 60));
+}
@@ -0,0 +1,137 @@
+mod support;
+
+use ava_device_logger::database::Database;
+use serde_json::Value;
+use std::error::Error;
+use std::time::Duration;
+use support::{config, logger_command, Server};
+
+async fn wait_for_health(client: &reqwest::Client, base_url: &str, status: &str) -> Result<Value, Box<dyn Error>> {
+    for _ in 0..100 {
+        if let Ok(response) = client.get(format!("{}/api/health", base_url)).send().await {
+            let health: Value = response.json().await?;
+            if health["status"] == status {
+                return Ok(health);
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    Err(format!("server never reported status {}", status).into())
+}
+
+#[tokio::test]
+async fn test_corrupt_database_starts_safe_mode_and_recovers() -> Result<(), Box<dyn Error>> {
+    let work_dir = support::work_dir("safe-mode")?;
+    let port = support::free_port()?;
+    std::fs::write(work_dir.join("config.toml"), config(port, ""))?;
+    std::fs::write(work_dir.join("data.db"), b"this is not a sqlite database, just garbage bytes".repeat(100))?;
+
+    let server = Server(logger_command(&work_dir).env_remove("AVA_RECOVERY_KEY").spawn()?);
+
+    let client = reqwest::Client::new();
+    let base_url = format!("http://127.0.0.1:{}", port);
+
+    // The server binds and explains why it is in safe mode
+    let health = wait_for_health(&client, &base_url, "safe_mode").await?;
+    assert_eq!(health["safe_mode"]["component"], "database");
+    assert!(!health["safe_mode"]["reason"].as_str().unwrap_or_default().is_empty());
+
+    // Everything else is refused with an explanation
+    let devices = client.get(format!("{}/api/devices", base_url)).send().await?;
+    assert_eq!(devices.status(), 503);
+    let body: Value = devices.json().await?;
+    assert!(body["error"].as_str().unwrap_or_default().contains("safe mode"));
+
+    // Recovery endpoints are served
+    let config = client.get(format!("{}/api/safe-mode/config", base_url)).send().await?;
+    assert_eq!(config.status(), 200);
+    assert!(config.text().await?.contains(&format!("port = {}", port)));
+
+    let backup = client.get(format!("{}/api/safe-mode/database", base_url)).send().await?;
+    assert_eq!(backup.status(), 200);
+
+    let exit = client.post(format!("{}/api/safe-mode/exit", base_url)).send().await?;
+    assert_eq!(exit.status(), 409);
+
+    // A corrupt upload is rejected, a healthy backup is restored
+    let rejected = client
+        .put(format!("{}/api/safe-mode/database", base_url))
+        .body(b"still not a database".to_vec())
+        .send()
+        .await?;
+    assert_eq!(rejected.status(), 400);
+
+    let healthy_path = std::env::temp_dir().join(format!("safe-mode-backup-{}.db", uuid::Uuid::new_v4()));
+    drop(Database::new(&healthy_path.to_string_lossy()).await?);
+    let restored = client
+        .put(format!("{}/api/safe-mode/database", base_url))
+        .body(std::fs::read(&healthy_path)?)
+        .send()
+        .await?;
+    assert_eq!(restored.status(), 200);
+
+    let exit = client.post(format!("{}/api/safe-mode/exit", base_url)).send().await?;
+    assert_eq!(exit.status(), 200);
+
+    // Normal startup resumes on the same port
+    wait_for_health(&client, &base_url, "healthy").await?;
+    support::login(&base_url, "admin", "admin123").await?;
+
+    let events = std::fs::read_to_string(work_dir.join("safe-mode-events.log"))?;
+    assert!(events.contains("safe_mode_entered"));
+    assert!(events.contains("database_restored"));
+    assert!(events.contains("safe_mode_exited"));
+
+    drop(server);
+    std::fs::remove_file(&healthy_path).ok();
+    let _ = std::fs::remove_dir_all(&work_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unparseable_config_can_be_repaired_from_safe_mode() -> Result<(), Box<dyn Error>> {
+    let work_dir = support::work_dir("safe-mode-config")?;
+    let port = support::free_port()?;
+    // Port is still readable, but the [database] table is missing
+    std::fs::write(work_dir.join("config.toml"), format!("devices = []\n\n[server]\nport = {}\nhost = \"127.0.0.1\"\n", port))?;
+
+    let server = Server(logger_command(&work_dir).env("AVA_RECOVERY_KEY", "field-key").spawn()?);
+
+    let client = reqwest::Client::new();
+    let base_url = format!("http://127.0.0.1:{}", port);
+
+    let health = wait_for_health(&client, &base_url, "safe_mode").await?;
+    assert_eq!(health["safe_mode"]["component"], "config");
+
+    // Recovery endpoints need the key when one is configured
+    let unauthorized = client.put(format!("{}/api/safe-mode/config", base_url)).body(config(port, "")).send().await?;
+    assert_eq!(unauthorized.status(), 401);
+
+    let invalid = client
+        .put(format!("{}/api/safe-mode/config", base_url))
+        .header("X-Recovery-Key", "field-key")
+        .body("[server]\nport = \"not a number\"")
+        .send()
+        .await?;
+    assert_eq!(invalid.status(), 400);
+
+    let repaired = client
+        .put(format!("{}/api/safe-mode/config", base_url))
+        .header("X-Recovery-Key", "field-key")
+        .body(config(port, ""))
+        .send()
+        .await?;
+    assert_eq!(repaired.status(), 200);
+
+    let exit = client
+        .post(format!("{}/api/safe-mode/exit", base_url))
+        .header("X-Recovery-Key", "field-key")
+        .send()
+        .await?;
+    assert_eq!(exit.status(), 200);
+    wait_for_health(&client, &base_url, "healthy").await?;
+
+    drop(server);
+    let _ = std::fs::remove_dir_all(&work_dir);
+    Ok(())
+}
@@ -0,0 +1,170 @@
+mod support;
+
+use ava_device_logger::config::{DeviceConfig, ModbusTcpConfig, ProtocolConfig};
+use ava_device_logger::database::{Database, DeviceInstance, DeviceTag, ScheduleGroup, TagWritePolicy};
+use ava_device_logger::modbus::ModbusClient;
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::time::Duration;
+use support::{Logger, ModbusDevice, ModbusRequest, TestResult};
+
+/// Modbus TCP stand-in where every register the tests read holds its own address and coil 1
+/// is on; register 40050 doesn't exist, so any read covering it is refused
+async fn start_device() -> TestResult<ModbusDevice> {
+    let registers = (40001..=40010).chain([30001, 40049, 40100, 40101]).map(|address| (address, address));
+    let device = ModbusDevice::start(registers.chain([(1, 1)])).await?;
+    device.refuse(40050);
+    Ok(device)
+}
+
+/// Function code, first address and count of each request
+fn sent(requests: &[ModbusRequest]) -> Vec<(u8, u16, u16)> {
+    requests.iter().map(|request| (request.function, request.address, request.count)).collect()
+}
+
+fn tag(name: &str, address: u16, data_type: &str, schedule_group_id: Option<&str>) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: "inv-1".to_string(),
+        name: name.to_string(),
+        address,
+        size: if data_type == "float32" { 2 } else { 1 },
+        data_type: data_type.to_string(),
+        description: None,
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: None,
+        read_only: true,
+        enabled: true,
+        schedule_group_id: schedule_group_id.map(str::to_string),
+        agg_to_field: None,
+        write_policy: TagWritePolicy::AdminOnly,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+#[tokio::test]
+async fn test_contiguous_tags_are_read_in_one_request() -> Result<(), Box<dyn Error>> {
+    let device = start_device().await?;
+    let port = device.port();
+    let db_path = std::env::temp_dir()
+        .join(format!("schedule-polling-{}.db", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string();
+    let db = Database::new(&db_path).await?;
+
+    let mut client = ModbusClient::new(DeviceConfig {
+        id: "inv-1".to_string(),
+        name: "Inverter 1".to_string(),
+        enabled: true,
+        protocol: ProtocolConfig::ModbusTcp(ModbusTcpConfig { host: "127.0.0.1".to_string(), port, slave_id: 1, max_block_gap: 0, request_delay_ms: 0, serial_source: None }),
+        polling_interval_ms: 1000,
+        timeout_ms: 1000,
+        retry_count: 3,
+        tags: Vec::new(),
+        strict_types: false,
+    });
+    client.connect().await?;
+
+    let tags = [
+        tag("Energy", 40003, "uint32", None),
+        tag("Voltage", 40001, "uint16", None),
+        tag("Current", 40002, "uint16", None),
+        tag("Frequency", 40010, "uint16", None),
+        tag("Active Power", 30001, "input_register", None),
+        tag("Run", 1, "coil", None),
+    ];
+    let entries = client.read_specific_tags(&db, &tags).await?;
+
+    // Entries keep the tags' order and decode from their own slice of the block
+    let values: Vec<(&str, f64)> = entries.iter().map(|entry| (entry.tag_name.as_str(), entry.value)).collect();
+    assert_eq!(values, vec![
+        ("Energy", ((40004u32 << 16) | 40003) as f64),
+        ("Voltage", 40001.0),
+        ("Current", 40002.0),
+        ("Frequency", 40010.0),
+        ("Active Power", 30001.0),
+        ("Run", 1.0),
+    ]);
+    let mut requests = sent(&device.take_requests());
+    requests.sort();
+    assert_eq!(requests, vec![(0x01, 1, 1), (0x03, 40001, 4), (0x03, 40010, 1), (0x04, 30001, 1)]);
+
+    // A refused block falls back to reading its tags one by one
+    let entries = client.read_specific_tags(&db, &[tag("Ok", 40049, "uint16", None), tag("Missing", 40050, "uint16", None)]).await?;
+    assert_eq!((entries[0].value, entries[0].quality.as_str()), (40049.0, "Good"));
+    assert_eq!(entries[1].quality, "Bad");
+    assert_eq!(sent(&device.take_requests()), vec![(0x03, 40049, 2), (0x03, 40049, 1), (0x03, 40050, 1)]);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+fn reads_of(device: &ModbusDevice, address: u16) -> usize {
+    device.requests().iter().filter(|request| request.address == address).count()
+}
+
+#[tokio::test]
+async fn test_groups_poll_at_their_own_interval_and_follow_changes() -> Result<(), Box<dyn Error>> {
+    let device = start_device().await?;
+    let modbus_port = device.port();
+
+    let work_dir = support::work_dir("schedule-polling")?;
+
+    let db = Database::new(&work_dir.join("data.db").to_string_lossy()).await?;
+    db.create_schedule_group(&ScheduleGroup {
+        id: "energy".to_string(),
+        name: "Energy Monitoring".to_string(),
+        polling_interval_ms: 30_000,
+        description: None,
+        enabled: true,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    }).await?;
+    db.create_device(&DeviceInstance {
+        id: "inv-1".to_string(),
+        name: "inv-1".to_string(),
+        serial_no: None,
+        model_id: None,
+        enabled: true,
+        polling_interval_ms: 200,
+        timeout_ms: 1000,
+        retry_count: 3,
+        protocol_config: json!({"type": "modbus_tcp", "host": "127.0.0.1", "port": modbus_port, "slave_id": 1}).to_string(),
+        tb_device_id: None,
+        tb_group_id: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    }).await?;
+    db.create_device_tags("inv-1", &[tag("Active Power", 40001, "uint16", None), tag("Energy", 40100, "uint32", Some("energy"))]).await?;
+    drop(db);
+
+    let logger = Logger::start_in(work_dir, "").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    // The ungrouped tag follows the device's 200ms; the energy group waits 30s after its first poll
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert!(reads_of(&device, 40001) >= 4, "{:?}", device.requests());
+    assert_eq!(reads_of(&device, 40100), 1);
+
+    let body: Value = client
+        .put(format!("{}/api/schedule-groups/energy", base_url))
+        .bearer_auth(token)
+        .json(&json!({"id": "energy", "name": "Energy Monitoring", "polling_interval_ms": 200, "description": null, "enabled": true}))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!(body["data"]["reloaded_devices"], json!(["inv-1"]), "{}", body);
+
+    // Picked up by the running poller, without restarting the device
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert!(reads_of(&device, 40100) >= 4, "{:?}", device.requests());
+    Ok(())
+}
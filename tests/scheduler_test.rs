@@ -0,0 +1,100 @@
+use ava_device_logger::scheduler::{OperationKind, OperationScheduler, OperationState};
+use std::error::Error;
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_same_kind_is_rejected_with_blocking_job() -> Result<(), Box<dyn Error>> {
+    let scheduler = OperationScheduler::new();
+
+    let sync = scheduler.start(OperationKind::Sync, "first sync", None).await.unwrap();
+    let conflict = match scheduler.start(OperationKind::Sync, "second sync", None).await {
+        Ok(_) => return Err("second sync should have been rejected".into()),
+        Err(conflict) => conflict,
+    };
+
+    assert_eq!(conflict.blocking_job_id, sync.job_id());
+    assert!(conflict.to_string().contains(sync.job_id()));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_conflicting_operation_is_queued_until_release() -> Result<(), Box<dyn Error>> {
+    let scheduler = OperationScheduler::new();
+
+    let sync = scheduler.start(OperationKind::Sync, "sync", None).await.unwrap();
+
+    // Catalog also needs the thingsboard lock, so it must wait for the sync
+    let waiter = {
+        let scheduler = scheduler.clone();
+        tokio::spawn(async move {
+            let guard = scheduler.start(OperationKind::Catalog, "catalog", None).await.unwrap();
+            guard.job_id().to_string()
+        })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let snapshot = scheduler.snapshot();
+    let queued = snapshot.iter().find(|op| op.kind == OperationKind::Catalog).unwrap();
+    assert_eq!(queued.state, OperationState::Queued);
+    assert_eq!(queued.queue_position, Some(1));
+    assert_eq!(queued.blocked_by, vec![sync.job_id().to_string()]);
+
+    // Export only takes a shared database lock and runs alongside the sync
+    let export = tokio::time::timeout(
+        Duration::from_millis(200),
+        scheduler.start(OperationKind::Export, "export", None),
+    )
+    .await?
+    .unwrap();
+    drop(export);
+
+    drop(sync);
+    tokio::time::timeout(Duration::from_secs(1), waiter).await??;
+    assert!(scheduler.snapshot().is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_restore_and_vacuum_wait_for_every_database_user() -> Result<(), Box<dyn Error>> {
+    let scheduler = OperationScheduler::new();
+
+    // Backups and exports only read, so they share the database
+    let backup = scheduler.start(OperationKind::Backup, "backup", None).await.unwrap();
+    let export = tokio::time::timeout(Duration::from_millis(200), scheduler.start(OperationKind::Export, "export", None))
+        .await?
+        .unwrap();
+
+    let restore = {
+        let scheduler = scheduler.clone();
+        tokio::spawn(async move { scheduler.start(OperationKind::Restore, "restore", None).await.unwrap() })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let snapshot = scheduler.snapshot();
+    let queued = snapshot.iter().find(|op| op.kind == OperationKind::Restore).unwrap();
+    assert_eq!(queued.state, OperationState::Queued);
+    assert_eq!(queued.blocked_by, vec![backup.job_id().to_string(), export.job_id().to_string()]);
+
+    drop(backup);
+    drop(export);
+    let restore = tokio::time::timeout(Duration::from_secs(1), restore).await??;
+
+    // A vacuum waits for the restore, and an import behind it waits for both
+    let vacuum = {
+        let scheduler = scheduler.clone();
+        tokio::spawn(async move { scheduler.start(OperationKind::Vacuum, "vacuum", None).await.unwrap() })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let snapshot = scheduler.snapshot();
+    let queued = snapshot.iter().find(|op| op.kind == OperationKind::Vacuum).unwrap();
+    assert_eq!(queued.blocked_by, vec![restore.job_id().to_string()]);
+    let import = scheduler.enqueue(OperationKind::Import, "import", None).unwrap();
+    assert_eq!(scheduler.queue_position(import.job_id()), Some(2));
+
+    drop(restore);
+    let vacuum = tokio::time::timeout(Duration::from_secs(1), vacuum).await??;
+    assert_eq!(scheduler.queue_position(import.job_id()), Some(1));
+    drop(vacuum);
+    tokio::time::timeout(Duration::from_secs(1), import.wait()).await?;
+    assert!(scheduler.snapshot().is_empty());
+    Ok(())
+}
@@ -0,0 +1,170 @@
+mod support;
+
+use ava_device_logger::database::{Database, LATEST_SCHEMA_VERSION};
+use rusqlite::{params, Connection};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::process::Stdio;
+use support::{config, login, logger_command, wait_until_up, Server};
+
+fn temp_db_path(name: &str) -> String {
+    std::env::temp_dir().join(format!("{}-{}.db", name, uuid::Uuid::new_v4())).to_string_lossy().to_string()
+}
+
+/// The device tables as an early release created them, before any column was added
+fn create_legacy_database(db_path: &str) -> Result<(), Box<dyn Error>> {
+    let conn = Connection::open(db_path)?;
+    conn.execute_batch(
+        "CREATE TABLE devices (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            model_id TEXT,
+            enabled BOOLEAN DEFAULT FALSE,
+            polling_interval_ms INTEGER DEFAULT 1000,
+            timeout_ms INTEGER DEFAULT 5000,
+            retry_count INTEGER DEFAULT 3,
+            protocol_config TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        );
+        CREATE TABLE device_tags (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            device_id TEXT NOT NULL,
+            name TEXT NOT NULL,
+            address INTEGER NOT NULL,
+            data_type TEXT NOT NULL,
+            description TEXT,
+            scaling_multiplier REAL DEFAULT 1.0,
+            scaling_offset REAL DEFAULT 0.0,
+            unit TEXT,
+            read_only BOOLEAN DEFAULT FALSE,
+            enabled BOOLEAN DEFAULT TRUE,
+            schedule_group_id TEXT
+        );",
+    )?;
+    let now = chrono::Utc::now().to_rfc3339();
+    conn.execute(
+        "INSERT INTO devices (id, name, enabled, protocol_config, created_at, updated_at) VALUES ('meter-1', 'Meter 1', 0, ?1, ?2, ?2)",
+        params![json!({"type": "modbus_tcp", "host": "127.0.0.1", "port": 502, "slave_id": 1}).to_string(), now],
+    )?;
+    conn.execute(
+        "INSERT INTO device_tags (device_id, name, address, data_type) VALUES ('meter-1', 'Voltage', 100, 'uint16')",
+        [],
+    )?;
+    Ok(())
+}
+
+fn columns(db_path: &str, table: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let conn = Connection::open(db_path)?;
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns = stmt.query_map([], |row| row.get(1))?.collect::<rusqlite::Result<Vec<String>>>()?;
+    Ok(columns)
+}
+
+#[tokio::test]
+async fn test_legacy_database_is_migrated_exactly_once() -> Result<(), Box<dyn Error>> {
+    let db_path = temp_db_path("schema-legacy");
+    create_legacy_database(&db_path)?;
+
+    // The dry run reports every migration as pending and changes nothing
+    let status = Database::check_migrations(&db_path)?;
+    assert_eq!((status.current_version, status.latest_version), (0, LATEST_SCHEMA_VERSION));
+    assert_eq!(status.pending.len() as u32, LATEST_SCHEMA_VERSION);
+    assert!(!columns(&db_path, "devices")?.contains(&"serial_no".to_string()));
+
+    let db = Database::new(&db_path).await?;
+    for column in ["tb_device_id", "tb_group_id", "serial_no", "strict_types", "forward_telemetry"] {
+        assert!(columns(&db_path, "devices")?.contains(&column.to_string()), "devices.{}", column);
+    }
+    for column in ["size", "agg_to_field", "write_policy", "byte_order", "deadband_absolute", "register_type"] {
+        assert!(columns(&db_path, "device_tags")?.contains(&column.to_string()), "device_tags.{}", column);
+    }
+    let tags = db.get_device_tags("meter-1").await?;
+    assert_eq!(tags.iter().map(|tag| (tag.name.as_str(), tag.address, tag.size)).collect::<Vec<_>>(), [("Voltage", 100, 1)]);
+    assert_eq!(db.get_devices().await?.len(), 1);
+
+    let status = db.schema_status().await?;
+    assert_eq!((status.current_version, status.newer_than_build), (LATEST_SCHEMA_VERSION, false));
+    assert!(status.pending.is_empty());
+    let versions: Vec<u32> = status.applied.iter().map(|migration| migration.version).collect();
+    assert_eq!(versions, (1..=LATEST_SCHEMA_VERSION).collect::<Vec<_>>());
+    drop(db);
+
+    // A second start finds nothing to do
+    let db = Database::new(&db_path).await?;
+    let status = db.schema_status().await?;
+    assert_eq!(status.applied.len() as u32, LATEST_SCHEMA_VERSION);
+
+    // A new database is created at the latest version too
+    let fresh_path = temp_db_path("schema-fresh");
+    let fresh = Database::new(&fresh_path).await?;
+    assert_eq!(fresh.schema_status().await?.current_version, LATEST_SCHEMA_VERSION);
+
+    std::fs::remove_file(&db_path).ok();
+    std::fs::remove_file(&fresh_path).ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_database_newer_than_the_build_is_refused() -> Result<(), Box<dyn Error>> {
+    let work_dir = support::work_dir("schema-newer")?;
+    let db_path = work_dir.join("data.db").to_string_lossy().to_string();
+    drop(Database::new(&db_path).await?);
+    Connection::open(&db_path)?.execute(
+        "INSERT INTO schema_migrations (version, description, applied_at) VALUES (?1, 'From a newer build', ?2)",
+        params![LATEST_SCHEMA_VERSION + 1, chrono::Utc::now().to_rfc3339()],
+    )?;
+
+    let status = Database::check_migrations(&db_path)?;
+    assert_eq!((status.current_version, status.newer_than_build), (LATEST_SCHEMA_VERSION + 1, true));
+    let error = Database::new(&db_path).await.err().expect("newer database opened").to_string();
+    assert!(error.contains("newer than this build supports"), "{}", error);
+
+    // The service stays in safe mode, where a matching backup can be restored
+    let port = support::free_port()?;
+    std::fs::write(work_dir.join("config.toml"), config(port, ""))?;
+    let _server = Server(logger_command(&work_dir).spawn()?);
+    let base_url = format!("http://127.0.0.1:{}", port);
+    wait_until_up(&base_url).await?;
+    let health: Value = reqwest::get(format!("{}/api/health", base_url)).await?.json().await?;
+    assert_eq!(health["status"], "safe_mode", "{}", health);
+    assert!(health["safe_mode"]["reason"].as_str().unwrap_or_default().contains("newer than this build supports"), "{}", health);
+
+    std::fs::remove_dir_all(&work_dir).ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_admins_see_the_schema_version() -> Result<(), Box<dyn Error>> {
+    let work_dir = support::work_dir("schema-endpoint")?;
+    let port = support::free_port()?;
+    std::fs::write(work_dir.join("config.toml"), config(port, ""))?;
+    let db = Database::new(&work_dir.join("data.db").to_string_lossy()).await?;
+    db.create_user("installer1", "installer-pass-1", "installer").await?;
+
+    // The dry run of the command line reads the configured database without changing it
+    let output = logger_command(&work_dir).arg("--check-migrations").stdout(Stdio::piped()).stderr(Stdio::piped()).output()?;
+    assert!(output.status.success(), "{:?}", output);
+    let status: Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(status["current_version"], LATEST_SCHEMA_VERSION);
+    assert_eq!(status["pending"], json!([]));
+
+    let _server = Server(logger_command(&work_dir).spawn()?);
+    let client = reqwest::Client::new();
+    let base_url = format!("http://127.0.0.1:{}", port);
+    wait_until_up(&base_url).await?;
+    let admin = login(&base_url, "admin", "admin123").await?;
+    let installer = login(&base_url, "installer1", "installer-pass-1").await?;
+
+    let body: Value = client.get(format!("{}/api/schema-migrations", base_url)).bearer_auth(&admin).send().await?.json().await?;
+    assert_eq!(body["data"]["current_version"], LATEST_SCHEMA_VERSION, "{}", body);
+    assert_eq!(body["data"]["latest_version"], LATEST_SCHEMA_VERSION, "{}", body);
+    assert_eq!(body["data"]["newer_than_build"], false, "{}", body);
+    assert_eq!(body["data"]["applied"].as_array().map(Vec::len), Some(LATEST_SCHEMA_VERSION as usize), "{}", body);
+
+    let response = client.get(format!("{}/api/schema-migrations", base_url)).bearer_auth(&installer).send().await?;
+    assert_eq!(response.status(), 403);
+
+    std::fs::remove_dir_all(&work_dir).ok();
+    Ok(())
+}
@@ -0,0 +1,113 @@
+mod support;
+
+use serde_json::Value;
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::Duration;
+use support::{eventually, logger_command, Server};
+
+/// Start a server with `extra` appended to its config, returning its base URL once it answers
+async fn start_server(scheme: &str, extra: &str) -> Result<(Server, String, PathBuf), Box<dyn Error>> {
+    let work_dir = support::work_dir("server-config")?;
+    let port = support::free_port()?;
+    let config = support::config(port, extra).replace("[server]\n", "[server]\nbind_address = \"127.0.0.1\"\n");
+    std::fs::write(work_dir.join("config.toml"), config)?;
+    let server = Server(logger_command(&work_dir).spawn()?);
+
+    let client = test_client()?;
+    let base_url = format!("{}://127.0.0.1:{}", scheme, port);
+    eventually(Duration::from_secs(10), || async { client.get(format!("{}/api/health", base_url)).send().await.ok() })
+        .await
+        .ok_or("server did not start")?;
+    Ok((server, base_url, work_dir))
+}
+
+/// Trusts the self-signed certificate of the TLS tests
+fn test_client() -> Result<reqwest::Client, Box<dyn Error>> {
+    Ok(reqwest::Client::builder().danger_accept_invalid_certs(true).build()?)
+}
+
+/// The `Access-Control-Allow-*` headers of a preflight request from `origin`
+async fn preflight(base_url: &str, origin: &str) -> Result<(Option<String>, Option<String>), Box<dyn Error>> {
+    let response = test_client()?
+        .request(reqwest::Method::OPTIONS, format!("{}/api/devices", base_url))
+        .header("Origin", origin)
+        .header("Access-Control-Request-Method", "DELETE")
+        .send()
+        .await?;
+    let header = |name: &str| response.headers().get(name).and_then(|value| value.to_str().ok()).map(str::to_string);
+    Ok((header("access-control-allow-origin"), header("access-control-allow-credentials")))
+}
+
+#[tokio::test]
+async fn test_cross_origin_requests_need_a_configured_origin() -> Result<(), Box<dyn Error>> {
+    // Same-origin only without a [server.cors] section
+    let (server, base_url, work_dir) = start_server("http", "").await?;
+    assert_eq!(preflight(&base_url, "http://scada.example.com").await?, (None, None));
+    let response = test_client()?.get(format!("{}/api/health", base_url)).header("Origin", "http://scada.example.com").send().await?;
+    assert!(response.headers().get("access-control-allow-origin").is_none());
+    drop(server);
+    let _ = std::fs::remove_dir_all(&work_dir);
+
+    let cors = r#"
+[server.cors]
+allowed_origins = ["http://scada.example.com"]
+allowed_methods = ["GET", "DELETE"]
+allow_credentials = true
+"#;
+    let (_server, base_url, work_dir) = start_server("http", cors).await?;
+    assert_eq!(
+        preflight(&base_url, "http://scada.example.com").await?,
+        (Some("http://scada.example.com".to_string()), Some("true".to_string()))
+    );
+    assert_eq!(preflight(&base_url, "http://other.example.com").await?.0, None);
+    let _ = std::fs::remove_dir_all(&work_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_invalid_server_settings_hold_startup_in_safe_mode() -> Result<(), Box<dyn Error>> {
+    let cors = r#"
+[server.cors]
+allowed_origins = ["*", "scada.example.com/"]
+allow_credentials = true
+"#;
+    let (_server, base_url, work_dir) = start_server("http", cors).await?;
+    let health: Value = test_client()?.get(format!("{}/api/health", base_url)).send().await?.json().await?;
+    assert_eq!(health["status"], "safe_mode", "{}", health);
+    let reason = health["safe_mode"]["reason"].as_str().unwrap_or_default();
+    assert!(reason.starts_with("Invalid [server] settings in config.toml"), "{}", reason);
+    assert!(reason.contains("can't be combined with other origins"), "{}", reason);
+    assert!(reason.contains("allow_credentials can't be used"), "{}", reason);
+    assert!(reason.contains("'scada.example.com/' is not a URL"), "{}", reason);
+    let _ = std::fs::remove_dir_all(&work_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_tls_serves_the_api_over_https() -> Result<(), Box<dyn Error>> {
+    let fixtures = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let tls = format!(
+        "\n[server.tls]\ncert_path = {:?}\nkey_path = {:?}\n",
+        fixtures.join("localhost.crt").to_string_lossy(),
+        fixtures.join("localhost.key").to_string_lossy()
+    );
+    let (_server, base_url, work_dir) = start_server("https", &tls).await?;
+
+    let health: Value = test_client()?.get(format!("{}/api/health", base_url)).send().await?.json().await?;
+    assert_eq!(health["status"], "healthy", "{}", health);
+    let body: Value = test_client()?
+        .post(format!("{}/api/login", base_url))
+        .json(&serde_json::json!({"username": "admin", "password": "admin123"}))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert!(body["data"]["session_token"].is_string(), "{}", body);
+
+    // Plain HTTP isn't answered on the HTTPS port
+    let plain = base_url.replace("https://", "http://");
+    assert!(reqwest::Client::new().get(format!("{}/api/health", plain)).timeout(Duration::from_secs(2)).send().await.is_err());
+    let _ = std::fs::remove_dir_all(&work_dir);
+    Ok(())
+}
@@ -0,0 +1,92 @@
+mod support;
+
+use ava_device_logger::database::Database;
+use serde_json::Value;
+use std::error::Error;
+use std::time::{Duration, Instant};
+use support::Logger;
+
+/// Start the server on a database holding one session of admin's that expired yesterday
+async fn start_server() -> Result<Logger, Box<dyn Error>> {
+    let work_dir = support::work_dir("session")?;
+    drop(Database::new(&work_dir.join("data.db").to_string_lossy()).await?);
+    let conn = rusqlite::Connection::open(work_dir.join("data.db"))?;
+    conn.execute(
+        "INSERT INTO user_sessions (user_id, session_token, expires_at)
+         SELECT id, 'expired-token', ?1 FROM local_users WHERE username = 'admin'",
+        [(chrono::Utc::now() - chrono::Duration::days(1)).to_rfc3339()],
+    )?;
+    drop(conn);
+
+    Logger::start_in(work_dir, "").await
+}
+
+fn session_count(work_dir: &std::path::Path, token: &str) -> Result<i64, Box<dyn Error>> {
+    let conn = rusqlite::Connection::open(work_dir.join("data.db"))?;
+    Ok(conn.query_row("SELECT COUNT(*) FROM user_sessions WHERE session_token = ?1", [token], |row| row.get(0))?)
+}
+
+#[tokio::test]
+async fn test_sessions_are_listed_refreshed_and_revoked() -> Result<(), Box<dyn Error>> {
+    let logger = start_server().await?;
+    let (client, base_url, admin) = (&logger.client, &logger.base_url, logger.token.as_str());
+
+    // Expired sessions are swept when the service starts
+    let started = Instant::now();
+    while session_count(&logger.work_dir, "expired-token")? > 0 {
+        assert!(started.elapsed() < Duration::from_secs(10), "expired session never purged");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let other_admin = logger.login("admin", "admin123").await?;
+    let installer = logger.login("installer", "installer123").await?;
+
+    let sessions = |token: &str| {
+        let request = client.get(format!("{}/api/sessions", base_url)).bearer_auth(token);
+        async move { request.send().await?.json::<Value>().await }
+    };
+
+    // An admin sees everyone's sessions, marked with the one asking
+    let body = sessions(admin).await?;
+    let listed = body["data"].as_array().expect("sessions");
+    assert_eq!(listed.len(), 3, "{}", body);
+    assert_eq!(listed.iter().filter(|session| session["current"] == true).count(), 1);
+    let current = listed.iter().find(|session| session["current"] == true).unwrap();
+    assert_eq!(current["username"], "admin");
+    assert_eq!(current["source_ip"], "127.0.0.1");
+    assert!(current["created_at"].is_string() && current["last_used"].is_string(), "{}", current);
+    assert!(!body.to_string().contains(admin), "tokens are never listed");
+
+    // An installer only their own, and can't revoke an admin's
+    let body = sessions(&installer).await?;
+    let listed = body["data"].as_array().unwrap();
+    assert_eq!(listed.len(), 1, "{}", body);
+    let installer_session = listed[0]["id"].as_i64().unwrap();
+    let admin_session = current["id"].as_i64().unwrap();
+    let response = client.delete(format!("{}/api/sessions/{}", base_url, admin_session)).bearer_auth(&installer).send().await?;
+    assert_eq!(response.status(), 404);
+
+    let body: Value = client.delete(format!("{}/api/sessions/{}", base_url, installer_session)).bearer_auth(admin).send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!(client.get(format!("{}/api/sessions", base_url)).bearer_auth(&installer).send().await?.status(), 401);
+
+    // last_used is brought forward once it is out of date
+    let conn = rusqlite::Connection::open(logger.work_dir.join("data.db"))?;
+    conn.execute("UPDATE user_sessions SET last_used = '2020-01-01T00:00:00+00:00' WHERE session_token = ?1", [&other_admin])?;
+    let response = client.get(format!("{}/api/verify-session", base_url)).bearer_auth(&other_admin).send().await?;
+    assert_eq!(response.status(), 200);
+    let last_used: String = conn.query_row("SELECT last_used FROM user_sessions WHERE session_token = ?1", [&other_admin], |row| row.get(0))?;
+    assert!(last_used.as_str() > "2020-01-01T00:00:00+00:00", "{}", last_used);
+
+    // A refresh hands out a new token and the old one stops working
+    let body: Value = client.post(format!("{}/api/session/refresh", base_url)).bearer_auth(admin).send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let refreshed = body["data"]["session_token"].as_str().unwrap().to_string();
+    assert_ne!(refreshed, admin);
+    assert_eq!(body["data"]["user"]["username"], "admin");
+    assert_eq!(client.get(format!("{}/api/sessions", base_url)).bearer_auth(admin).send().await?.status(), 401);
+    assert_eq!(client.post(format!("{}/api/session/refresh", base_url)).bearer_auth(admin).send().await?.status(), 401);
+    let body = sessions(&refreshed).await?;
+    assert_eq!(body["data"].as_array().unwrap().len(), 2, "{}", body);
+    Ok(())
+}
@@ -0,0 +1,134 @@
+mod support;
+
+use ava_device_logger::database::Database;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::time::Duration;
+use support::Logger;
+
+fn tag(name: &str, address: u16, data_type: &str, scaling_multiplier: f64) -> Value {
+    json!({
+        "name": name, "address": address, "size": 1, "data_type": data_type, "description": null,
+        "scaling_multiplier": scaling_multiplier, "scaling_offset": 0.0, "unit": null, "read_only": true, "enabled": true,
+        "schedule_group_id": null, "agg_to_field": null,
+    })
+}
+
+fn create_request(id: &str, protocol_config: Value, tags: Vec<Value>) -> Value {
+    json!({
+        "id": id, "name": id, "serial_no": null, "model_id": null, "enabled": true,
+        "polling_interval_ms": 200, "timeout_ms": 1000, "retry_count": 1,
+        "protocol_config": protocol_config,
+        "tags": tags,
+    })
+}
+
+#[tokio::test]
+async fn test_simulated_devices_flow_through_logging_and_live_values() -> Result<(), Box<dyn Error>> {
+    let work_dir = support::work_dir("simulated-device")?;
+    // The logger runs in the test's process, so the replay file is given by its full path
+    let replay_file = work_dir.join("power.csv");
+    std::fs::write(&replay_file, "time,kW\n0,1.5\n1,2.5\n2,not measured\n3,3.5\n")?;
+
+    let logger = Logger::start_in(work_dir, "").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    // Invalid patterns are reported field by field
+    let invalid = json!({
+        "type": "simulated",
+        "patterns": {"Voltage": {"pattern": "sine", "amplitude": 1, "period_seconds": 0}, "Current": {"pattern": "square"}},
+        "dropout": {"seconds": 120, "every_minutes": 1},
+        "bad_quality_probability": 2,
+    });
+    let body: Value = client.post(format!("{}/api/devices-enhanced", base_url)).bearer_auth(token)
+        .json(&create_request("sim-invalid", invalid, vec![tag("Voltage", 1, "uint16", 1.0)])).send().await?.json().await?;
+    assert_eq!(body["success"], false, "{}", body);
+    let mut fields: Vec<&str> = body["field_errors"].as_array().expect("field errors").iter().map(|error| error["field"].as_str().unwrap()).collect();
+    fields.sort();
+    assert_eq!(fields, ["bad_quality_probability", "dropout", "patterns.Current", "patterns.Voltage"], "{}", body);
+
+    let protocol_config = json!({
+        "type": "simulated",
+        "patterns": {
+            "Voltage": {"pattern": "constant", "value": 115},
+            "Wave": {"pattern": "sine", "amplitude": 10, "period_seconds": 4, "offset": 50},
+            "Power": {"pattern": "replay", "file": replay_file, "column": "kW"},
+        },
+    });
+    let tags = vec![
+        tag("Voltage", 1, "uint16", 2.0),
+        tag("Wave", 2, "float32", 1.0),
+        tag("Power", 4, "float32", 1.0),
+        tag("Level", 42, "uint16", 0.5),
+    ];
+    let body: Value = client.post(format!("{}/api/devices-enhanced", base_url)).bearer_auth(token)
+        .json(&create_request("sim-1", protocol_config.clone(), tags)).send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+
+    let body: Value = client.post(format!("{}/api/devices-enhanced/test-connection", base_url)).bearer_auth(token)
+        .json(&json!({"protocol_config": protocol_config, "timeout_ms": 1000})).send().await?.json().await?;
+    assert_eq!(body["data"]["success"], true, "{}", body);
+
+    let body: Value = client.post(format!("{}/api/devices-enhanced/sim-1/start", base_url)).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+
+    // Patterns give the raw value and the tag's scaling still applies
+    let db = Database::new(&logger.work_dir.join("data.db").to_string_lossy()).await?;
+    let logged = db.get_log_entries(Some("sim-1"), Some(1000), None).await?;
+    let values = |tag_name: &str| -> Vec<f64> {
+        let mut values: Vec<(chrono::DateTime<chrono::Utc>, f64)> = logged.iter()
+            .filter(|entry| entry.tag_name == tag_name && entry.quality == "Good")
+            .map(|entry| (entry.timestamp, entry.value))
+            .collect();
+        values.sort_by_key(|value| value.0);
+        values.into_iter().map(|(_, value)| value).collect()
+    };
+    let voltage = values("Voltage");
+    assert!(voltage.len() >= 3, "{:?}", logged);
+    assert!(voltage.iter().all(|value| *value == 230.0), "{:?}", voltage);
+    assert!(values("Level").iter().all(|value| *value == 21.0), "{:?}", values("Level"));
+    let wave = values("Wave");
+    assert!(wave.iter().all(|value| (40.0..=60.0).contains(value)), "{:?}", wave);
+    assert!(wave.windows(2).any(|pair| pair[0] != pair[1]), "{:?}", wave);
+    // The replay skips the cell that isn't a number and starts over at the end of the file
+    let power = values("Power");
+    assert!(power.len() >= 4, "{:?}", power);
+    assert_eq!(power[..4], [1.5, 2.5, 3.5, 1.5]);
+
+    let body: Value = client.get(format!("{}/api/devices-enhanced/sim-1/values", base_url)).bearer_auth(token).send().await?.json().await?;
+    let voltage = body["data"]["values"].as_array().expect("values").iter().find(|value| value["tag_name"] == "Voltage").cloned();
+    assert_eq!(voltage.map(|value| (value["value"].clone(), value["stale"].clone())), Some((json!(230.0), json!(false))), "{}", body);
+
+    // Every read comes back bad when the probability is 1
+    let bad_config = json!({"type": "simulated", "bad_quality_probability": 1});
+    let body: Value = client.post(format!("{}/api/devices-enhanced", base_url)).bearer_auth(token)
+        .json(&create_request("sim-bad", bad_config, vec![tag("Voltage", 1, "uint16", 1.0)])).send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    client.post(format!("{}/api/devices-enhanced/sim-bad/start", base_url)).bearer_auth(token).send().await?;
+
+    // A device that is down for 1 of every 3 seconds loses its connection and gets it back
+    let dropout_config = json!({"type": "simulated", "dropout": {"seconds": 1, "every_minutes": 0.05}});
+    let body: Value = client.post(format!("{}/api/devices-enhanced", base_url)).bearer_auth(token)
+        .json(&create_request("sim-dropout", dropout_config, vec![tag("Voltage", 1, "uint16", 1.0)])).send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    client.post(format!("{}/api/devices-enhanced/sim-dropout/start", base_url)).bearer_auth(token).send().await?;
+
+    let mut statuses = Vec::new();
+    for _ in 0..70 {
+        if let Some(status) = db.get_device_status("sim-dropout").await? {
+            statuses.push(status.status);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(statuses.iter().any(|status| status == "Reconnecting" || status == "Error"), "{:?}", statuses);
+    assert!(statuses.iter().any(|status| status == "Connected" || status == "Reading"), "{:?}", statuses);
+
+    // Bad reads are logged with their quality, and show in the live values
+    let entries = db.get_log_entries(Some("sim-bad"), Some(100), None).await?;
+    assert!(!entries.is_empty());
+    assert!(entries.iter().all(|entry| entry.quality == "Bad" && !entry.has_value()), "{:?}", entries);
+    let body: Value = client.get(format!("{}/api/devices-enhanced/sim-bad/values", base_url)).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(body["data"]["values"][0]["quality"], "Bad", "{}", body);
+    Ok(())
+}
@@ -0,0 +1,280 @@
+mod support;
+
+use ava_device_logger::config::{ByteOrder, ModbusTcpConfig, RegisterType};
+use ava_device_logger::modbus::discover_sunspec;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use support::Logger;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Holding registers of a slave, written from a start address on
+#[derive(Default)]
+struct RegisterMap(HashMap<u16, u16>);
+
+impl RegisterMap {
+    fn write(&mut self, address: u16, words: &[u16]) -> u16 {
+        for (i, word) in words.iter().enumerate() {
+            self.0.insert(address + i as u16, *word);
+        }
+        address + words.len() as u16
+    }
+
+    /// A model header and body, padded with zeros to `length`; returns the next model's address
+    fn model(&mut self, address: u16, id: u16, length: u16, body: &[(u16, &[u16])]) -> u16 {
+        self.write(address, &[id, length]);
+        self.write(address + 2, &vec![0; length as usize]);
+        for (offset, words) in body {
+            self.write(address + 2 + offset, words);
+        }
+        address + 2 + length
+    }
+}
+
+fn text(value: &str, registers: usize) -> Vec<u16> {
+    let mut bytes = value.as_bytes().to_vec();
+    bytes.resize(registers * 2, 0);
+    bytes.chunks(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect()
+}
+
+fn float(value: f32) -> [u16; 2] {
+    let bits = value.to_bits();
+    [(bits >> 16) as u16, bits as u16]
+}
+
+fn sf(exponent: i16) -> u16 {
+    exponent as u16
+}
+
+const NAN: [u16; 2] = [0x7FC0, 0x0000];
+
+/// Three-phase inverter with integer models and two MPPTs, at the usual base of 40000
+fn integer_inverter() -> RegisterMap {
+    let mut map = RegisterMap::default();
+    let next = map.write(40000, &[0x5375, 0x6E53]);
+    let next = map.model(next, 1, 66, &[(0, &text("Fronius", 16)), (16, &text("Symo 10.0-3-M", 16)), (40, &text("1.2.3", 8)), (48, &text("SN123456", 16))]);
+    let next = map.model(next, 103, 50, &[
+        (0, &[200, 70, 70, 70, sf(-1)]),
+        (5, &[4000, 4000, 4000, 2300, 2300, 2300, sf(-1)]),
+        // Apparent power isn't implemented, and reactive power has no scale factor
+        (12, &[10000, 0, 5000, sf(-2), 0x8000, 0, 100, 0x8000, 95, 0]),
+        (22, &[0x0001, 0x86A0, 0]),
+        // Neither is DC current, nor any temperature but the cabinet's
+        (25, &[0xFFFF, 0, 6000, sf(-1), 10500, 0]),
+        (31, &[450, 0x8000, 0x8000, 0x8000, sf(-1), 4, 0xFFFF]),
+    ]);
+    // Not a model discovery knows, so it is only listed
+    let next = map.model(next, 64, 3, &[]);
+    let next = map.model(next, 160, 48, &[
+        (0, &[sf(-2), sf(-1), 0, 0]),
+        (6, &[2]),
+        (8, &[1]),
+        (17, &[850, 6000, 5100, 0, 1000, 0, 0, 0x8000]),
+        (28, &[2]),
+        (37, &[900, 6100, 5490, 0, 2000, 0, 0, 0x8000]),
+    ]);
+    map.write(next, &[0xFFFF, 0]);
+    map
+}
+
+/// Single-phase inverter with the float model, at base 0
+fn float_inverter() -> RegisterMap {
+    let mut map = RegisterMap::default();
+    let next = map.write(0, &[0x5375, 0x6E53]);
+    let next = map.model(next, 1, 65, &[(0, &text("Acme", 16))]);
+    let mut body: Vec<u16> = Vec::new();
+    for value in [float(12.5), float(12.5), NAN, NAN, NAN, NAN, NAN, float(230.0), NAN, NAN, float(2875.0), float(50.0), NAN, NAN, NAN, float(1.0e6)] {
+        body.extend(value);
+    }
+    for _ in 0..7 {
+        body.extend(NAN);
+    }
+    body.extend([4, 0xFFFF]);
+    let next = map.model(next, 111, 60, &[(0, &body)]);
+    map.write(next, &[0xFFFF, 0]);
+    map
+}
+
+/// Modbus TCP slave answering reads of the registers in `map`, and every other read with an
+/// illegal data address exception. Without a map, every register reads 0.
+async fn spawn_slave(map: Option<RegisterMap>) -> Result<u16, Box<dyn Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    let map = Arc::new(map);
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let map = map.clone();
+            tokio::spawn(async move {
+                let mut request = [0u8; 12];
+                while socket.read_exact(&mut request).await.is_ok() {
+                    let address = u16::from_be_bytes([request[8], request[9]]);
+                    let count = u16::from_be_bytes([request[10], request[11]]);
+                    let words: Option<Vec<u16>> = (0..count)
+                        .map(|i| match map.as_ref() {
+                            Some(map) => map.0.get(&address.wrapping_add(i)).copied(),
+                            None => Some(0),
+                        })
+                        .collect();
+                    let pdu = match words {
+                        Some(words) => {
+                            let mut pdu = vec![0x03, (count * 2) as u8];
+                            words.iter().for_each(|word| pdu.extend(word.to_be_bytes()));
+                            pdu
+                        }
+                        None => vec![0x83, 0x02],
+                    };
+
+                    let mut frame = request[..4].to_vec();
+                    frame.extend(((pdu.len() + 1) as u16).to_be_bytes());
+                    frame.push(request[6]);
+                    frame.extend(pdu);
+                    if socket.write_all(&frame).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    Ok(port)
+}
+
+fn slave(port: u16) -> ModbusTcpConfig {
+    ModbusTcpConfig { host: "127.0.0.1".to_string(), port, slave_id: 1, max_block_gap: 0, request_delay_ms: 0, serial_source: None }
+}
+
+#[tokio::test]
+async fn test_integer_models_are_discovered_with_scale_factors() -> Result<(), Box<dyn Error>> {
+    let port = spawn_slave(Some(integer_inverter())).await?;
+    let discovery = discover_sunspec(slave(port), Duration::from_secs(5)).await?;
+
+    assert_eq!(discovery.base_address, 40000);
+    assert_eq!(discovery.models, [1, 103, 64, 160]);
+    assert_eq!(
+        (discovery.manufacturer.as_deref(), discovery.model.as_deref(), discovery.version.as_deref(), discovery.serial_no.as_deref()),
+        (Some("Fronius"), Some("Symo 10.0-3-M"), Some("1.2.3"), Some("SN123456"))
+    );
+
+    // Points the inverter doesn't implement are left out
+    let names: Vec<&str> = discovery.tags.iter().map(|tag| tag.name.as_str()).collect();
+    assert_eq!(names, [
+        "ac_current", "ac_current_a", "ac_current_b", "ac_current_c", "ac_voltage_ab", "ac_voltage_bc", "ac_voltage_ca",
+        "ac_voltage_an", "ac_voltage_bn", "ac_voltage_cn", "active_power", "frequency", "power_factor", "total_energy",
+        "dc_voltage", "dc_power", "cabinet_temperature", "operating_state",
+        "mppt1_dc_current", "mppt1_dc_voltage", "mppt1_dc_power", "mppt1_dc_energy",
+        "mppt2_dc_current", "mppt2_dc_voltage", "mppt2_dc_power", "mppt2_dc_energy",
+    ]);
+
+    let tag = |name: &str| discovery.tags.iter().find(|tag| tag.name == name).unwrap();
+    let ac_current = tag("ac_current");
+    assert_eq!((ac_current.address, ac_current.size, ac_current.data_type.as_str()), (40072, 1, "uint16"));
+    assert_eq!((ac_current.scaling_multiplier, ac_current.unit.as_deref()), (0.1, Some("A")));
+    assert_eq!(ac_current.description.as_deref(), Some("Inverter (Symo 10.0-3-M)"));
+    assert_eq!(ac_current.register_type, Some(RegisterType::Holding));
+    assert_eq!((tag("active_power").data_type.as_str(), tag("active_power").scaling_multiplier), ("int16", 1.0));
+    assert_eq!(tag("frequency").scaling_multiplier, 0.01);
+
+    let energy = tag("total_energy");
+    assert_eq!((energy.address, energy.size, energy.data_type.as_str(), energy.byte_order), (40094, 2, "uint32", Some(ByteOrder::Abcd)));
+
+    let mppt = tag("mppt2_dc_current");
+    assert_eq!((mppt.address, mppt.scaling_multiplier, mppt.unit.as_deref()), (40166, 0.01, Some("A")));
+    assert_eq!(mppt.description.as_deref(), Some("MPPT - MPPT 2 (Symo 10.0-3-M)"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_float_models_are_discovered_at_base_zero() -> Result<(), Box<dyn Error>> {
+    let port = spawn_slave(Some(float_inverter())).await?;
+    let discovery = discover_sunspec(slave(port), Duration::from_secs(5)).await?;
+
+    assert_eq!((discovery.base_address, discovery.models.clone()), (0, vec![1, 111]));
+    assert_eq!((discovery.manufacturer.as_deref(), discovery.model.as_deref()), (Some("Acme"), None));
+    let tags: Vec<(&str, u16, &str, f64)> = discovery.tags.iter()
+        .map(|tag| (tag.name.as_str(), tag.address, tag.data_type.as_str(), tag.scaling_multiplier))
+        .collect();
+    assert_eq!(tags, [
+        ("ac_current", 71, "float32", 1.0),
+        ("ac_current_a", 73, "float32", 1.0),
+        ("ac_voltage_an", 85, "float32", 1.0),
+        ("active_power", 91, "float32", 1.0),
+        ("frequency", 93, "float32", 1.0),
+        ("total_energy", 101, "float32", 1.0),
+        ("operating_state", 117, "uint16", 1.0),
+    ]);
+    assert_eq!(discovery.tags[0].description.as_deref(), Some("Inverter"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_devices_without_sunspec_fail_fast() -> Result<(), Box<dyn Error>> {
+    let port = spawn_slave(None).await?;
+    let error = discover_sunspec(slave(port), Duration::from_secs(5)).await.unwrap_err();
+    assert!(error.to_string().contains("the device does not support SunSpec"), "{}", error);
+
+    // A device that never answers is given up on once the walk's time is up
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let silent_port = listener.local_addr()?.port();
+    tokio::spawn(async move {
+        let mut sockets = Vec::new();
+        while let Ok((socket, _)) = listener.accept().await {
+            sockets.push(socket);
+        }
+    });
+    let started = Instant::now();
+    let error = discover_sunspec(slave(silent_port), Duration::from_millis(500)).await.unwrap_err();
+    assert!(started.elapsed() < Duration::from_secs(3), "took {:?}", started.elapsed());
+    assert!(error.to_string().contains("within 500ms"), "{}", error);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_a_discovered_tag_list_creates_a_device() -> Result<(), Box<dyn Error>> {
+    let slave_port = spawn_slave(Some(integer_inverter())).await?;
+
+    let logger = Logger::start("").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let response = client
+        .post(format!("{}/api/devices-enhanced/discover-sunspec", base_url))
+        .bearer_auth(token)
+        .json(&json!({"host": "127.0.0.1", "port": slave_port, "slave_id": 248}))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 400);
+    let body: Value = response.json().await?;
+    assert_eq!(body["field_errors"][0]["field"], "slave_id", "{}", body);
+
+    let body: Value = client
+        .post(format!("{}/api/devices-enhanced/discover-sunspec", base_url))
+        .bearer_auth(token)
+        .json(&json!({"host": "127.0.0.1", "port": slave_port}))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let (serial_no, tags) = (body["data"]["serial_no"].clone(), body["data"]["tags"].clone());
+    assert_eq!(serial_no, "SN123456");
+    assert_eq!(tags.as_array().map(Vec::len), Some(26));
+
+    // Nothing was stored, and the proposal is accepted as it is
+    let body: Value = client.get(format!("{}/api/devices-enhanced", base_url)).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(body["data"], json!([]));
+    let device = json!({
+        "id": "inverter-1", "name": "Inverter 1", "serial_no": serial_no, "enabled": false,
+        "polling_interval_ms": 1000, "timeout_ms": 1000, "retry_count": 1,
+        "protocol_config": {"type": "modbus_tcp", "host": "127.0.0.1", "port": slave_port, "slave_id": 1},
+        "tags": tags,
+    });
+    let body: Value = client.post(format!("{}/api/devices-enhanced", base_url)).bearer_auth(token).json(&device).send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let body: Value = client.get(format!("{}/api/devices-enhanced", base_url)).bearer_auth(token).send().await?.json().await?;
+    let saved = &body["data"][0]["tags"];
+    assert_eq!(saved.as_array().map(Vec::len), Some(26));
+    let energy = saved.as_array().unwrap().iter().find(|tag| tag["name"] == "total_energy").unwrap();
+    assert_eq!((&energy["address"], &energy["byte_order"]), (&json!(40094), &json!("ABCD")));
+    Ok(())
+}
@@ -0,0 +1,547 @@
+//! Fixtures shared by the end-to-end tests: a Modbus TCP device served in-process, a
+//! ThingsBoard stand-in and the logger's router and services run in-process against a
+//! temporary work directory. Tests of the binary's own startup and shutdown run it as a child
+//! process instead.
+//!
+//! Test files pull this in with `mod support;`; each uses only part of it.
+#![allow(dead_code)]
+
+use ava_device_logger::app::App;
+use ava_device_logger::config::AppConfig;
+use ava_device_logger::database::{Database, LogEntry};
+use ava_device_logger::AppState;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio_util::sync::CancellationToken;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+pub type TestResult<T = ()> = Result<T, Box<dyn Error>>;
+
+/// One request a [`ModbusDevice`] answered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModbusRequest {
+    pub slave: u8,
+    pub function: u8,
+    pub address: u16,
+    pub count: u16,
+}
+
+#[derive(Default)]
+struct DeviceState {
+    registers: Mutex<HashMap<u16, u16>>,
+    refused: Mutex<HashSet<u16>>,
+    requests: Mutex<Vec<ModbusRequest>>,
+    connections: AtomicUsize,
+    closed: AtomicUsize,
+    silent: AtomicBool,
+}
+
+/// Modbus TCP device backed by one register map, answering every slave id. Holding and input
+/// registers read the same map and unset registers read 0; coils and discrete inputs are on
+/// when their register is non-zero. Reads of coils, discrete inputs, holding and input
+/// registers and writes of single coils and single and multiple registers are answered,
+/// anything else gets an illegal function exception; reads covering a [refused](Self::refuse)
+/// address get an illegal data address exception.
+pub struct ModbusDevice {
+    port: u16,
+    state: Arc<DeviceState>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl ModbusDevice {
+    pub async fn start(registers: impl IntoIterator<Item = (u16, u16)>) -> TestResult<Self> {
+        let state = Arc::new(DeviceState::default());
+        state.registers.lock().unwrap().extend(registers);
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        Ok(Self { port, task: Some(tokio::spawn(serve(listener, state.clone()))), state })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn set(&self, address: u16, value: u16) {
+        self.state.registers.lock().unwrap().insert(address, value);
+    }
+
+    /// Store a 32-bit value big-endian, high word first; integer tags read it with byte order
+    /// ABCD, as their default is low word first
+    pub fn set_u32(&self, address: u16, value: u32) {
+        let mut registers = self.state.registers.lock().unwrap();
+        registers.insert(address, (value >> 16) as u16);
+        registers.insert(address + 1, value as u16);
+    }
+
+    pub fn set_f32(&self, address: u16, value: f32) {
+        self.set_u32(address, value.to_bits());
+    }
+
+    pub fn get(&self, address: u16) -> u16 {
+        self.state.registers.lock().unwrap().get(&address).copied().unwrap_or(0)
+    }
+
+    /// Refuse reads covering `address` from now on, as if the register didn't exist
+    pub fn refuse(&self, address: u16) {
+        self.state.refused.lock().unwrap().insert(address);
+    }
+
+    /// While silent, requests are read but never answered, like a hung device
+    pub fn set_silent(&self, silent: bool) {
+        self.state.silent.store(silent, Ordering::SeqCst);
+    }
+
+    /// Requests received since the last [`take_requests`](Self::take_requests)
+    pub fn requests(&self) -> Vec<ModbusRequest> {
+        self.state.requests.lock().unwrap().clone()
+    }
+
+    /// Requests received since the last call
+    pub fn take_requests(&self) -> Vec<ModbusRequest> {
+        std::mem::take(&mut *self.state.requests.lock().unwrap())
+    }
+
+    /// Connections accepted since the device first started
+    pub fn connections(&self) -> usize {
+        self.state.connections.load(Ordering::SeqCst)
+    }
+
+    /// Connections the other side closed
+    pub fn closed_connections(&self) -> usize {
+        self.state.closed.load(Ordering::SeqCst)
+    }
+
+    /// Close the listener and every open connection, like a device powering off
+    pub async fn stop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+            let _ = task.await;
+        }
+    }
+
+    /// Listen again on the same port with the registers as they were
+    pub async fn restart(&mut self) -> TestResult {
+        self.stop().await;
+        let listener = TcpListener::bind(("127.0.0.1", self.port)).await?;
+        self.task = Some(tokio::spawn(serve(listener, self.state.clone())));
+        Ok(())
+    }
+}
+
+impl Drop for ModbusDevice {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+async fn serve(listener: TcpListener, state: Arc<DeviceState>) {
+    // Held here so aborting the task closes the connections too
+    let mut connections = JoinSet::new();
+    while let Ok((mut socket, _)) = listener.accept().await {
+        state.connections.fetch_add(1, Ordering::SeqCst);
+        let state = state.clone();
+        connections.spawn(async move {
+            loop {
+                let mut header = [0u8; 7];
+                if socket.read_exact(&mut header).await.is_err() {
+                    break;
+                }
+                let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+                let mut pdu = vec![0u8; length.saturating_sub(1)];
+                if socket.read_exact(&mut pdu).await.is_err() {
+                    break;
+                }
+
+                let response = answer(&state, header[6], &pdu);
+                if state.silent.load(Ordering::SeqCst) {
+                    continue;
+                }
+                let mut frame = header[..4].to_vec();
+                frame.extend(((response.len() + 1) as u16).to_be_bytes());
+                frame.push(header[6]);
+                frame.extend(response);
+                if socket.write_all(&frame).await.is_err() {
+                    break;
+                }
+            }
+            state.closed.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+}
+
+fn answer(state: &DeviceState, slave: u8, pdu: &[u8]) -> Vec<u8> {
+    if pdu.len() < 5 {
+        return vec![pdu.first().copied().unwrap_or(0) | 0x80, 0x03];
+    }
+    let word = |i: usize| u16::from_be_bytes([pdu[i], pdu[i + 1]]);
+    let (function, address) = (pdu[0], word(1));
+    let count = match function {
+        0x05 | 0x06 => 1,
+        _ => word(3),
+    };
+    state.requests.lock().unwrap().push(ModbusRequest { slave, function, address, count });
+
+    let refused = state.refused.lock().unwrap();
+    if matches!(function, 0x01..=0x04) && (0..count).any(|i| refused.contains(&address.wrapping_add(i))) {
+        return vec![function | 0x80, 0x02];
+    }
+    let mut registers = state.registers.lock().unwrap();
+    let read = |offset: u16| registers.get(&address.wrapping_add(offset)).copied().unwrap_or(0);
+    match function {
+        0x01 | 0x02 => {
+            let mut bits = vec![0u8; count.div_ceil(8) as usize];
+            for i in 0..count {
+                if read(i) != 0 {
+                    bits[i as usize / 8] |= 1 << (i % 8);
+                }
+            }
+            let mut response = vec![function, bits.len() as u8];
+            response.extend(bits);
+            response
+        }
+        0x03 | 0x04 => {
+            let mut response = vec![function, (count * 2) as u8];
+            (0..count).for_each(|i| response.extend(read(i).to_be_bytes()));
+            response
+        }
+        0x05 => {
+            registers.insert(address, (word(3) == 0xFF00) as u16);
+            pdu.to_vec()
+        }
+        0x06 => {
+            registers.insert(address, word(3));
+            pdu.to_vec()
+        }
+        0x10 => {
+            for i in 0..count {
+                registers.insert(address.wrapping_add(i), word(6 + i as usize * 2));
+            }
+            pdu[..5].to_vec()
+        }
+        _ => vec![function | 0x80, 0x01],
+    }
+}
+
+/// Kills the logger when the test finishes, pass or fail
+pub struct Server(pub Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// The logger binary, run in `work_dir` with its output discarded
+pub fn logger_command(work_dir: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_ava-device-logger"));
+    command.current_dir(work_dir).stdout(Stdio::null()).stderr(Stdio::null());
+    command
+}
+
+/// A new empty directory in the system temp dir, named after `prefix`
+pub fn work_dir(prefix: &str) -> TestResult<PathBuf> {
+    let work_dir = std::env::temp_dir().join(format!("{}-{}", prefix, uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&work_dir)?;
+    Ok(work_dir)
+}
+
+pub fn free_port() -> TestResult<u16> {
+    Ok(std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port())
+}
+
+/// config.toml with no devices, serving on `port` with the database in data.db;
+/// `extra_config` is appended, e.g. a `[thingsboard]` section
+pub fn config(port: u16, extra_config: &str) -> String {
+    format!(
+        r#"
+devices = []
+
+[server]
+port = {port}
+host = "127.0.0.1"
+
+[database]
+path = "data.db"
+max_log_entries = 1000
+cleanup_interval_hours = 24
+
+[logging]
+level = "info"
+max_file_size_mb = 10
+max_files = 5
+
+{extra_config}
+"#
+    )
+}
+
+/// Session token of a login, or the error the server gave
+pub async fn login(base_url: &str, username: &str, password: &str) -> TestResult<String> {
+    let body: Value = reqwest::Client::new()
+        .post(format!("{}/api/login", base_url))
+        .json(&json!({"username": username, "password": password}))
+        .send()
+        .await?
+        .json()
+        .await?;
+    Ok(body["data"]["session_token"].as_str().ok_or_else(|| format!("login as {} failed: {}", username, body))?.to_string())
+}
+
+/// Wait for the server at `base_url` to answer its health check
+pub async fn wait_until_up(base_url: &str) -> TestResult {
+    let client = reqwest::Client::new();
+    eventually(Duration::from_secs(10), || async { client.get(format!("{}/api/health", base_url)).send().await.ok() })
+        .await
+        .ok_or("the logger did not start")?;
+    Ok(())
+}
+
+/// ThingsBoard's answer for an entity it doesn't have
+pub fn tb_not_found() -> ResponseTemplate {
+    ResponseTemplate::new(404).set_body_json(json!({"status": 404, "message": "Requested item wasn't found!", "errorCode": 32}))
+}
+
+/// A ThingsBoard stand-in that logs in any user and answers every request the test doesn't
+/// mount a mock for with [`tb_not_found`]
+pub async fn thingsboard() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/api/auth/login"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"token": "test-token", "refreshToken": "refresh"})))
+        .mount(&server)
+        .await;
+    Mock::given(any()).respond_with(tb_not_found()).with_priority(u8::MAX).mount(&server).await;
+    server
+}
+
+/// Request line (`POST /api/device?accessToken=...`) and JSON body, or `null`, of every
+/// request `server` answered apart from logins, oldest first
+pub async fn tb_requests(server: &MockServer) -> Vec<(String, Value)> {
+    server
+        .received_requests()
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|request| request.url.path() != "/api/auth/login")
+        .map(|request| {
+            let query = request.url.query().map(|query| format!("?{}", query)).unwrap_or_default();
+            let line = format!("{} {}{}", request.method, request.url.path(), query);
+            (line, serde_json::from_slice(&request.body).unwrap_or_default())
+        })
+        .collect()
+}
+
+/// The logger's router and services running in-process on a free port in a temporary work
+/// directory, logged in as the default admin. Stopped and its work directory removed when
+/// dropped.
+pub struct Logger {
+    state: AppState,
+    shutdown: CancellationToken,
+    server: JoinHandle<()>,
+    port: u16,
+    pub work_dir: PathBuf,
+    pub base_url: String,
+    pub token: String,
+    pub client: reqwest::Client,
+}
+
+impl Logger {
+    /// Start with no devices; `extra_config` is appended to config.toml, e.g. a
+    /// `[thingsboard]` section
+    pub async fn start(extra_config: &str) -> TestResult<Self> {
+        Self::start_in(work_dir("logger")?, extra_config).await
+    }
+
+    /// Start in a work directory the test prepared, e.g. with a seeded database
+    pub async fn start_in(work_dir: PathBuf, extra_config: &str) -> TestResult<Self> {
+        Self::start_with_config(work_dir, |port| config(port, extra_config)).await
+    }
+
+    /// Start with the whole config.toml written by `config` for the port to serve on
+    pub async fn start_with_config(work_dir: PathBuf, config: impl FnOnce(u16) -> String) -> TestResult<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        std::fs::write(work_dir.join("config.toml"), config(port))?;
+        let (state, shutdown, server) = serve_logger(&work_dir, listener).await?;
+        let base_url = format!("http://127.0.0.1:{}", port);
+        let token = login(&base_url, "admin", "admin123").await?;
+        Ok(Self { state, shutdown, server, port, work_dir, base_url, token, client: reqwest::Client::new() })
+    }
+
+    /// Stop the logger and start it again on the same port and work directory, with a new
+    /// admin session
+    pub async fn restart(&mut self) -> TestResult {
+        // Pooled connections would keep the stopped server from finishing
+        self.client = reqwest::Client::new();
+        self.stop().await;
+        let listener = TcpListener::bind(("127.0.0.1", self.port)).await?;
+        (self.state, self.shutdown, self.server) = serve_logger(&self.work_dir, listener).await?;
+        self.token = self.login("admin", "admin123").await?;
+        Ok(())
+    }
+
+    /// Stop serving and polling, as a shutdown would
+    pub async fn stop(&mut self) {
+        self.shutdown.cancel();
+        self.state.logging_service.shutdown(Duration::from_secs(5)).await;
+        if tokio::time::timeout(Duration::from_secs(5), &mut self.server).await.is_err() {
+            self.server.abort();
+        }
+    }
+
+    /// The state the handlers share, e.g. to reach the logging service directly
+    pub fn state(&self) -> &AppState {
+        &self.state
+    }
+
+    /// Session token of another login
+    pub async fn login(&self, username: &str, password: &str) -> TestResult<String> {
+        login(&self.base_url, username, password).await
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// The logger's database, opened alongside it
+    pub async fn database(&self) -> TestResult<Database> {
+        Ok(Database::new(&self.work_dir.join("data.db").to_string_lossy()).await?)
+    }
+
+    pub async fn get(&self, path: &str) -> TestResult<Value> {
+        Ok(self.client.get(self.url(path)).bearer_auth(&self.token).send().await?.json().await?)
+    }
+
+    pub async fn post(&self, path: &str, body: &Value) -> TestResult<Value> {
+        Ok(self.client.post(self.url(path)).bearer_auth(&self.token).json(body).send().await?.json().await?)
+    }
+
+    /// Create a Modbus TCP device with the given tags through the API and start polling it
+    pub async fn start_modbus_device(&self, device_id: &str, device: &ModbusDevice, polling_interval_ms: u32, tags: Vec<Value>) -> TestResult {
+        self.start_device(
+            json!({
+                "id": device_id, "name": device_id, "enabled": true,
+                "polling_interval_ms": polling_interval_ms, "timeout_ms": 1000, "retry_count": 1,
+                "protocol_config": {"type": "modbus_tcp", "host": "127.0.0.1", "port": device.port(), "slave_id": 1},
+                "tags": tags,
+            }),
+        )
+        .await
+    }
+
+    /// Create a device from a full `POST /api/devices-enhanced` body and start polling it
+    pub async fn start_device(&self, request: Value) -> TestResult {
+        let device_id = request["id"].as_str().ok_or("no device id")?.to_string();
+        let body = self.post("/api/devices-enhanced", &request).await?;
+        if body["success"] != true {
+            return Err(format!("creating {} failed: {}", device_id, body).into());
+        }
+        let body = self.post(&format!("/api/devices-enhanced/{}/start", device_id), &json!({})).await?;
+        if body["success"] != true {
+            return Err(format!("starting {} failed: {}", device_id, body).into());
+        }
+        Ok(())
+    }
+
+    /// Current values of a device's tags by tag name
+    pub async fn values(&self, device_id: &str) -> TestResult<HashMap<String, Value>> {
+        let body = self.get(&format!("/api/devices-enhanced/{}/values", device_id)).await?;
+        Ok(body["data"]["values"]
+            .as_array()
+            .ok_or_else(|| format!("no values in {}", body))?
+            .iter()
+            .filter_map(|value| Some((value["tag_name"].as_str()?.to_string(), value.clone())))
+            .collect())
+    }
+}
+
+impl Drop for Logger {
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+        self.server.abort();
+        std::fs::remove_dir_all(&self.work_dir).ok();
+    }
+}
+
+/// Start the logger for the config.toml in `work_dir` and serve it on `listener` until the
+/// returned token is cancelled. Relative paths in the config are taken from `work_dir`, as the
+/// binary takes them from its working directory.
+async fn serve_logger(work_dir: &Path, listener: TcpListener) -> TestResult<(AppState, CancellationToken, JoinHandle<()>)> {
+    let mut config: AppConfig = toml::from_str(&std::fs::read_to_string(work_dir.join("config.toml"))?)?;
+    let in_work_dir = |path: &str| work_dir.join(path).to_string_lossy().to_string();
+    config.database.path = in_work_dir(&config.database.path);
+    if let Some(file_export) = config.file_export.as_mut() {
+        file_export.directory = in_work_dir(&file_export.directory);
+    }
+    let database = Arc::new(Database::new(&config.database.path).await?);
+    let app = App::start(Arc::new(config), database, work_dir.to_path_buf()).await?;
+
+    let shutdown = CancellationToken::new();
+    let serving = axum::serve(listener, app.router.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown.clone().cancelled_owned());
+    let server = tokio::spawn(async move {
+        serving.await.ok();
+    });
+    Ok((app.state, shutdown, server))
+}
+
+/// A tag for `POST /api/devices-enhanced`; `extra` overrides or adds fields, e.g.
+/// `json!({"deadband_absolute": 5.0})`
+pub fn tag(name: &str, address: u16, data_type: &str, scaling_multiplier: f64, extra: Value) -> Value {
+    let mut tag = json!({
+        "name": name, "address": address, "data_type": data_type,
+        "size": if matches!(data_type, "float32" | "uint32" | "int32") { 2 } else { 1 },
+        "scaling_multiplier": scaling_multiplier, "scaling_offset": 0.0,
+        "read_only": true, "enabled": true,
+    });
+    if let (Some(tag), Some(extra)) = (tag.as_object_mut(), extra.as_object()) {
+        tag.extend(extra.clone());
+    }
+    tag
+}
+
+/// A tag's log entries, oldest first
+pub async fn log_entries(db: &Database, device_id: &str, tag_name: &str) -> Vec<LogEntry> {
+    let mut entries: Vec<LogEntry> = db
+        .get_log_entries(Some(device_id), None, None)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|entry| entry.tag_name == tag_name)
+        .collect();
+    entries.sort_by_key(|entry| entry.timestamp);
+    entries
+}
+
+/// Call `check` until it returns something or `timeout` passes
+pub async fn eventually<T, F, Fut>(timeout: Duration, mut check: F) -> Option<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Option<T>>,
+{
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(value) = check().await {
+            return Some(value);
+        }
+        if Instant::now() >= deadline {
+            return None;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
@@ -0,0 +1,151 @@
+mod support;
+
+use ava_device_logger::config::{DataType, RegisterType};
+use ava_device_logger::modbus::{find_tag_conflicts, TagConflictKind, TagFootprint};
+use serde_json::{json, Value};
+use std::error::Error;
+use support::Logger;
+
+fn summary(tags: &[TagFootprint]) -> Vec<(TagConflictKind, usize, Option<usize>)> {
+    find_tag_conflicts(tags).into_iter().map(|conflict| (conflict.kind, conflict.tag, conflict.other)).collect()
+}
+
+#[test]
+fn test_every_conflict_is_reported() {
+    let tags = [
+        TagFootprint::of("Active Power", &DataType::Float32, 100, 2),
+        TagFootprint::of("Reactive Power", &DataType::Float32, 101, 2),
+        TagFootprint::of("Energy", &DataType::UInt64, 200, 0),
+        TagFootprint::of("Active Power", &DataType::UInt16, 300, 1),
+        TagFootprint::of("Frequency", &DataType::UInt16, 203, 1),
+        TagFootprint::of("Counter", &DataType::UInt32, 65535, 2),
+        TagFootprint::of("Status", &DataType::UInt16, 102, 1),
+    ];
+    assert_eq!(summary(&tags), [
+        (TagConflictKind::Overlap, 1, Some(0)),
+        (TagConflictKind::DuplicateName, 3, Some(0)),
+        (TagConflictKind::Overlap, 4, Some(2)),
+        (TagConflictKind::OutOfRange, 5, None),
+        (TagConflictKind::Overlap, 6, Some(1)),
+    ]);
+
+    let messages: Vec<String> = find_tag_conflicts(&tags).into_iter().map(|conflict| conflict.message).collect();
+    assert_eq!(messages[0], "'Reactive Power' (holding 101-102) overlaps 'Active Power' (holding 100-101)");
+    assert_eq!(messages[1], "Tag name 'Active Power' is used by an earlier tag");
+    assert_eq!(messages[3], "'Counter' (holding 65535-65536) runs past the last Modbus address 65535");
+}
+
+#[test]
+fn test_adjacent_ranges_and_other_tables_do_not_conflict() {
+    let tags = [
+        TagFootprint::of("Voltage", &DataType::Float32, 100, 2),
+        TagFootprint::of("Current", &DataType::Float32, 102, 2),
+        TagFootprint::of("Running", &DataType::Coil, 100, 1),
+        TagFootprint::of("Temperature", &DataType::InputRegister, 101, 1),
+        TagFootprint { name: "Last".to_string(), register_type: RegisterType::Input, address: 65535, count: 1 },
+    ];
+    assert!(find_tag_conflicts(&tags).is_empty());
+}
+
+/// multipart/form-data body for text fields and one CSV file
+fn multipart(boundary: &str, fields: &[(&str, &str)], csv: &str) -> String {
+    let mut body = String::new();
+    for (name, value) in fields {
+        body += &format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n", boundary, name, value);
+    }
+    body += &format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"csv_file\"; filename=\"tags.csv\"\r\nContent-Type: text/csv\r\n\r\n{}\r\n--{}--\r\n",
+        boundary, csv, boundary
+    );
+    body
+}
+
+#[tokio::test]
+async fn test_conflicting_tags_are_rejected_at_every_entry_point() -> Result<(), Box<dyn Error>> {
+
+    let logger = Logger::start("").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let tag = |name: &str, address: u16, size: i32, data_type: &str| json!({
+        "name": name, "address": address, "size": size, "data_type": data_type,
+        "scaling_multiplier": 1.0, "scaling_offset": 0.0, "read_only": true, "enabled": true
+    });
+    let device = |protocol_config: Value, tags: Vec<Value>| json!({
+        "id": "meter-1", "name": "Meter 1", "enabled": false, "polling_interval_ms": 1000, "timeout_ms": 1000,
+        "retry_count": 1, "protocol_config": protocol_config, "tags": tags,
+    });
+    let modbus = json!({"type": "modbus_tcp", "host": "127.0.0.1", "port": 502, "slave_id": 1});
+
+    let body: Value = client
+        .post(format!("{}/api/devices-enhanced", base_url))
+        .bearer_auth(token)
+        .json(&device(modbus.clone(), vec![
+            tag("Voltage", 100, 2, "float32"),
+            tag("Current", 101, 2, "float32"),
+            tag("Voltage", 200, 1, "uint16"),
+        ]))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["success"], false);
+    assert_eq!(body["field_errors"], json!([
+        {"field": "tags[1].address", "message": "'Current' (holding 101-102) overlaps 'Voltage' (holding 100-101)"},
+        {"field": "tags[2].name", "message": "Tag name 'Voltage' is used by an earlier tag"},
+    ]));
+
+    // IEC 104 addresses are IOAs, so only names are checked
+    let iec104 = json!({"type": "iec104", "host": "127.0.0.1", "port": 2404});
+    let tags = vec![tag("Voltage", 100, 2, "float32"), tag("Current", 101, 2, "float32")];
+    let body: Value = client.post(format!("{}/api/devices-enhanced", base_url)).bearer_auth(token).json(&device(iec104, tags)).send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+
+    let body: Value = client
+        .put(format!("{}/api/devices-enhanced/meter-1", base_url))
+        .bearer_auth(token)
+        .json(&device(modbus, vec![tag("Counter", 65535, 2, "uint32")]))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["field_errors"][0]["field"], "tags[0].address", "{}", body);
+
+    // Register maps are checked per inverter, MPPT and string
+    let csv = "Device Brand,Device Model,AVA Type,MPPT,INPUT,Data Label,Address,Size,Modbus Type,Divider,Register Type\n\
+               SMA,STP,Inverter,,,Total Yield,5003,2,U32,1,input\n\
+               SMA,STP,Inverter,,,Total Yield,5143,2,U32,10,input\n\
+               SMA,STP,MPPT,1,,Udc,5010,1,U16,10,input\n\
+               SMA,STP,String,1,1,Udc,5010,1,U16,10,input\n\
+               SMA,STP,Inverter,,,Daily Yield,5004,1,U16,10,input\n";
+    let boundary = "tag-conflict-test";
+    let body: Value = client
+        .post(format!("{}/api/modbus-tcp-tag-registers/upload-csv", base_url))
+        .bearer_auth(token)
+        .header("Content-Type", format!("multipart/form-data; boundary={}", boundary))
+        .body(multipart(boundary, &[("device_model_name", "STP"), ("manufacturer", "SMA")], csv))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["success"], false, "{}", body);
+    assert_eq!(body["details"]["validation_errors"], json!([
+        "Row 3: Tag name 'Total Yield' is used by an earlier tag",
+        "Row 6: 'Daily Yield' (input 5004) overlaps 'Total Yield' (input 5003-5004)",
+    ]));
+
+    let csv = "name,address,data_type\nEnergy,100,float64\nPower,102,float32\nPower,104,float32\n";
+    let body: Value = client
+        .post(format!("{}/api/device-models", base_url))
+        .bearer_auth(token)
+        .header("Content-Type", format!("multipart/form-data; boundary={}", boundary))
+        .body(multipart(boundary, &[("name", "Meter"), ("protocol_type", "modbus_tcp")], csv))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["success"], false, "{}", body);
+    assert!(body["error"].as_str().unwrap().ends_with(
+        "Row 3: 'Power' (holding 102-103) overlaps 'Energy' (holding 100-103); Row 4: Tag name 'Power' is used by an earlier tag"
+    ), "{}", body);
+    Ok(())
+}
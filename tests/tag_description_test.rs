@@ -0,0 +1,47 @@
+use ava_device_logger::tag_description::{model, parse, DeviceType};
+
+#[test]
+fn test_descriptions_parse_despite_format_drift() {
+    let cases = [
+        ("Inverter (SG150CX)", DeviceType::Inverter),
+        ("Inverter", DeviceType::Inverter),
+        ("inverter - Active Power", DeviceType::Inverter),
+        ("MPPT - MPPT 1 (SG150CX)", DeviceType::Mppt(1)),
+        ("MPPT - MPPT 1 (SG125CX-P2)", DeviceType::Mppt(1)),
+        ("MPPT - MPPT 12", DeviceType::Mppt(12)),
+        ("mppt-mppt 3", DeviceType::Mppt(3)),
+        ("MPPT  -  MPPT   04 (SG150CX)", DeviceType::Mppt(4)),
+        ("MPPT - MPPT2", DeviceType::Mppt(2)),
+        ("String - MPPT 1 - Input 1 (SG150CX)", DeviceType::String(1, 1)),
+        ("String - MPPT 1 - Input 12 (SG150CX)", DeviceType::String(1, 12)),
+        ("String - MPPT 10 - Input 2", DeviceType::String(10, 2)),
+        ("STRING - mppt 2 - input 3 (SG110CX-P2)", DeviceType::String(2, 3)),
+        (" String-MPPT 3-Input 24 ", DeviceType::String(3, 24)),
+        // Malformed: unknown rather than grouped under the wrong device
+        ("", DeviceType::Unknown),
+        ("Active power", DeviceType::Unknown),
+        ("MPPT - MPPT", DeviceType::Unknown),
+        ("MPPT - MPPT one", DeviceType::Unknown),
+        ("MPPT - MPPT 0", DeviceType::Unknown),
+        ("MPPT - MPPT 1 - Input 2", DeviceType::Unknown),
+        ("MPPT - MPPT 1.5", DeviceType::Unknown),
+        ("String - MPPT 1", DeviceType::Unknown),
+        ("String - MPPT 1 - Input", DeviceType::Unknown),
+        ("String - Input 1 - MPPT 1", DeviceType::Unknown),
+        ("String - MPPT 1 - Input 2 - Extra", DeviceType::Unknown),
+        ("String - MPPT -1 - Input 2", DeviceType::Unknown),
+        ("String - MPPT 99999999999 - Input 2", DeviceType::Unknown),
+    ];
+
+    for (description, expected) in cases {
+        assert_eq!(parse(description), expected, "{:?}", description);
+    }
+}
+
+#[test]
+fn test_model_comes_from_parentheses() {
+    assert_eq!(model("String - MPPT 1 - Input 1 (SG150CX)"), Some("SG150CX"));
+    assert_eq!(model("MPPT - MPPT 1 ( SG125CX-P2 )"), Some("SG125CX-P2"));
+    assert_eq!(model("MPPT - MPPT 1"), None);
+    assert_eq!(model("MPPT - MPPT 1 )("), None);
+}
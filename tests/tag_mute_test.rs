@@ -0,0 +1,108 @@
+use ava_device_logger::database::{Database, DeviceInstance, DeviceTag, TagWritePolicy};
+use chrono::{Duration, TimeZone, Utc};
+use std::error::Error;
+
+fn device(id: &str) -> DeviceInstance {
+    DeviceInstance {
+        id: id.to_string(),
+        name: "Weather Station".to_string(),
+        serial_no: None,
+        model_id: None,
+        enabled: false,
+        polling_interval_ms: 1000,
+        timeout_ms: 5000,
+        retry_count: 3,
+        protocol_config: "{}".to_string(),
+        tb_device_id: None,
+        tb_group_id: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    }
+}
+
+fn tag(name: &str, address: u16) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: "ws-1".to_string(),
+        name: name.to_string(),
+        address,
+        size: 1,
+        data_type: "uint16".to_string(),
+        description: None,
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: Some("W/m2".to_string()),
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy: TagWritePolicy::Disabled,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+#[tokio::test]
+async fn test_mutes_expire_can_be_lifted_and_are_kept_as_history() -> Result<(), Box<dyn Error>> {
+    let db_path = std::env::temp_dir()
+        .join(format!("tag-mute-{}.db", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string();
+    let db = Database::new(&db_path).await?;
+
+    db.create_device(&device("ws-1")).await?;
+    db.create_device_tags("ws-1", &[tag("Irradiance", 100), tag("Module Temp", 101)]).await?;
+    let tags = db.get_device_tags("ws-1").await?;
+
+    let mute = db.mute_tag(&tags[0], Utc::now() + Duration::days(7), Some("Sensor replacement".to_string()), Some("admin".to_string())).await?;
+    assert!(mute.is_active(Utc::now()));
+    db.mute_tag(&tags[1], Utc::now() - Duration::seconds(1), None, None).await?;
+
+    let muted = db.get_muted_tag_names("ws-1").await?;
+    assert_eq!(muted.len(), 1);
+    assert!(muted.contains("Irradiance"));
+
+    // Re-muting replaces the active mute instead of stacking a second one
+    db.mute_tag(&tags[0], Utc::now() + Duration::days(1), None, Some("admin".to_string())).await?;
+    let mutes = db.get_tag_mutes("ws-1").await?;
+    assert_eq!(mutes.len(), 3);
+    assert_eq!(mutes.iter().filter(|m| m.is_active(Utc::now())).count(), 1);
+
+    assert!(db.lift_tag_mute(tags[0].id.unwrap(), Some("operator".to_string())).await?);
+    assert!(!db.lift_tag_mute(tags[0].id.unwrap(), None).await?);
+    assert!(db.get_muted_tag_names("ws-1").await?.is_empty());
+
+    let lifted = db.get_tag_mutes("ws-1").await?.into_iter().find(|m| m.lifted_by.as_deref() == Some("operator")).unwrap();
+    assert_eq!(lifted.tag_name, "Irradiance");
+    assert!(lifted.lifted_at.is_some());
+
+    let _ = std::fs::remove_file(&db_path);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_muted_samples_are_counted_per_day() -> Result<(), Box<dyn Error>> {
+    let db_path = std::env::temp_dir()
+        .join(format!("tag-mute-counts-{}.db", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string();
+    let db = Database::new(&db_path).await?;
+
+    let day = Utc.with_ymd_and_hms(2025, 3, 10, 0, 0, 0).unwrap();
+    for minutes in [5, 10, 15] {
+        db.record_muted_sample("ws-1", "Irradiance", day + Duration::minutes(minutes)).await?;
+    }
+    db.record_muted_sample("ws-1", "Irradiance", day + Duration::days(1)).await?;
+
+    let counts = db.get_muted_sample_counts(day, day + Duration::days(1)).await?;
+    assert_eq!(counts.get("ws-1"), Some(&3));
+
+    // Muted samples never reach the log, so they don't affect the day's sample stats
+    assert!(db.get_device_day_stats(day, day + Duration::days(1)).await?.is_empty());
+
+    let _ = std::fs::remove_file(&db_path);
+    Ok(())
+}
@@ -0,0 +1,184 @@
+mod support;
+
+use ava_device_logger::config::{DataType, DeviceConfig, Iec104Config, Iec104Mode, ProtocolConfig, RegisterType};
+use ava_device_logger::database::{Database, DeviceInstance, DeviceTag, TagWritePolicy};
+use ava_device_logger::iec104::{Iec104Client, Iec104ModeHandle, Iec104ModeSettings};
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::error::Error;
+use support::{Logger, ModbusDevice};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+fn tag(device_id: &str, name: &str, address: u16, data_type: &str, scaling_multiplier: f64) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: device_id.to_string(),
+        name: name.to_string(),
+        address,
+        size: if data_type == "float32" { 2 } else { 1 },
+        data_type: data_type.to_string(),
+        description: None,
+        scaling_multiplier,
+        scaling_offset: 0.0,
+        unit: None,
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy: TagWritePolicy::AdminOnly,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+#[test]
+fn test_tag_data_types_map_to_register_reads() {
+    let read = tag("inv-1", "Power Factor", 40020, "float32", 1.0).register_read();
+    assert_eq!((read.register_type, read.address, read.size), (RegisterType::Holding, 40020, 2));
+    assert!(matches!(read.data_type, DataType::Float32));
+
+    assert_eq!(tag("inv-1", "Pac", 30001, "input_register", 1.0).register_read().register_type, RegisterType::Input);
+    assert_eq!(tag("inv-1", "Run", 1, "coil", 1.0).register_read().register_type, RegisterType::Coil);
+}
+
+#[tokio::test]
+async fn test_iec104_read_command_returns_the_point_value() -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        loop {
+            let mut header = [0u8; 2];
+            if socket.read_exact(&mut header).await.is_err() {
+                break;
+            }
+            let mut body = vec![0u8; header[1] as usize];
+            if socket.read_exact(&mut body).await.is_err() {
+                break;
+            }
+            if body[0] == 0x07 {
+                let _ = socket.write_all(&[0x68, 4, 0x0B, 0, 0, 0]).await;
+            } else if body[4] == 102 {
+                let ioa = &body[10..13];
+                let answer = if ioa[0] == 44 {
+                    // Short float with COT 5 (requested)
+                    let mut answer = vec![0x68, 18, 0, 0, 0, 0, 13, 1, 5, 0, 1, 0];
+                    answer.extend(ioa);
+                    answer.extend(12.5f32.to_le_bytes());
+                    answer.push(0);
+                    answer
+                } else {
+                    let mut answer = vec![0x68, header[1]];
+                    answer.extend(&body);
+                    answer[8] = 5 | 0x40;
+                    answer
+                };
+                let _ = socket.write_all(&answer).await;
+            }
+        }
+    });
+
+    let protocol = ProtocolConfig::Iec104(Iec104Config {
+        host: "127.0.0.1".to_string(),
+        port,
+        common_address: 1,
+        mode: Iec104Mode::Interrogation,
+        interrogation_interval_ms: 300_000,
+    });
+    let handle = Iec104ModeHandle::new(Iec104ModeSettings::from_protocol(&protocol).unwrap());
+    let mut client = Iec104Client::new(
+        DeviceConfig {
+            id: "rtu-1".to_string(),
+            name: "RTU 1".to_string(),
+            enabled: true,
+            protocol,
+            polling_interval_ms: 1000,
+            timeout_ms: 1000,
+            retry_count: 3,
+            tags: Vec::new(),
+            strict_types: false,
+        },
+        handle,
+    );
+    client.connect().await?;
+
+    assert_eq!(client.read_point(44).await?, 12.5);
+    let missing = client.read_point(45).await;
+    assert!(missing.unwrap_err().to_string().contains("no information object"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_read_endpoint_uses_poller_or_short_lived_connection() -> Result<(), Box<dyn Error>> {
+    let bits = 0.95f32.to_bits();
+    let device = ModbusDevice::start([(40001, 750), (40020, (bits >> 16) as u16), (40021, bits as u16), (30001, 0xFFFE)]).await?;
+    let modbus_port = device.port();
+
+    let work_dir = support::work_dir("tag-read")?;
+
+    let db = Database::new(&work_dir.join("data.db").to_string_lossy()).await?;
+    for (id, enabled) in [("inv-1", true), ("inv-2", false)] {
+        db.create_device(&DeviceInstance {
+            id: id.to_string(),
+            name: id.to_string(),
+            serial_no: None,
+            model_id: None,
+            enabled,
+            polling_interval_ms: 60_000,
+            timeout_ms: 1000,
+            retry_count: 3,
+            protocol_config: json!({"type": "modbus_tcp", "host": "127.0.0.1", "port": modbus_port, "slave_id": 1}).to_string(),
+            tb_device_id: None,
+            tb_group_id: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            strict_types: false,
+        }).await?;
+        db.create_device_tags(id, &[tag(id, "Output Limit", 40001, "uint16", 0.1)]).await?;
+    }
+    drop(db);
+
+    let logger = Logger::start_in(work_dir, "").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let read = |device_id: &'static str, request: Value| {
+        let request = client
+            .post(format!("{}/api/devices-enhanced/{}/read", base_url, device_id))
+            .bearer_auth(token)
+            .json(&request);
+        async move { request.send().await }
+    };
+
+    // The running device answers through its poller, without opening another connection
+    let body: Value = read("inv-1", json!({"tag_name": "Output Limit"})).await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!(body["data"]["tag_name"], "Output Limit");
+    assert_eq!(body["data"]["value"], 75.0);
+    assert_eq!(body["data"]["raw_value"], 750.0);
+    assert_eq!(body["data"]["registers"], json!([750]));
+    assert_eq!(body["data"]["through_poller"], true);
+    let poller_connections = device.connections();
+
+    // The stopped device gets a session of its own for each read, on the connection already
+    // open to the same host and port
+    let body: Value = read("inv-2", json!({"address": 40020, "data_type": "float32"})).await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!(body["data"]["through_poller"], false);
+    assert!(body["data"]["tag_name"].is_null());
+    assert!((body["data"]["value"].as_f64().unwrap() - 0.95).abs() < 1e-6);
+    assert_eq!(body["data"]["registers"], json!([bits >> 16, bits & 0xFFFF]));
+    assert!(body["data"]["elapsed_ms"].is_u64());
+
+    let body: Value = read("inv-2", json!({"address": 30001, "data_type": "int16", "register_type": "input"})).await?.json().await?;
+    assert_eq!(body["data"]["value"], -2.0, "{}", body);
+    assert_eq!(device.connections(), poller_connections);
+
+    let body: Value = read("inv-2", json!({"address": 40001})).await?.json().await?;
+    assert_eq!(body["success"], false);
+    assert_eq!(read("inv-1", json!({"tag_name": "Missing"})).await?.status(), 404);
+    assert_eq!(read("inv-9", json!({"address": 1, "data_type": "uint16"})).await?.status(), 404);
+    Ok(())
+}
@@ -0,0 +1,101 @@
+use ava_device_logger::database::{Database, DeviceInstance, DeviceTag, SavedTagSearch, TagBulkChanges, TagSearchFilter, TagWritePolicy};
+use chrono::Utc;
+use std::error::Error;
+
+fn device(id: &str, name: &str) -> DeviceInstance {
+    DeviceInstance {
+        id: id.to_string(),
+        name: name.to_string(),
+        serial_no: None,
+        model_id: None,
+        enabled: false,
+        polling_interval_ms: 1000,
+        timeout_ms: 5000,
+        retry_count: 3,
+        protocol_config: "{}".to_string(),
+        tb_device_id: None,
+        tb_group_id: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    }
+}
+
+fn tag(device_id: &str, name: &str, address: u16, data_type: &str, schedule_group_id: &str) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: device_id.to_string(),
+        name: name.to_string(),
+        address,
+        size: 2,
+        data_type: data_type.to_string(),
+        description: None,
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: Some("kWh".to_string()),
+        read_only: true,
+        enabled: true,
+        schedule_group_id: Some(schedule_group_id.to_string()),
+        agg_to_field: None,
+        write_policy: TagWritePolicy::Disabled,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+#[tokio::test]
+async fn test_search_and_bulk_edit_by_saved_search() -> Result<(), Box<dyn Error>> {
+    let db_path = std::env::temp_dir()
+        .join(format!("tag-search-{}.db", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string();
+    let db = Database::new(&db_path).await?;
+
+    for (id, name) in [("inv-1", "Inverter 1"), ("inv-2", "Inverter 2")] {
+        db.create_device(&device(id, name)).await?;
+        db.create_device_tags(id, &[
+            tag(id, "Daily Energy", 5003, "uint32", "high_freq"),
+            tag(id, "Total Energy", 5038, "uint32", "medium_freq"),
+        ]).await?;
+    }
+
+    let by_address = TagSearchFilter { address: Some(5038), data_type: Some("UINT32".to_string()), ..Default::default() };
+    assert_eq!(db.count_tag_search(&by_address).await?, 2);
+
+    let results = db.search_tags(&by_address, 1, 1).await?;
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].device_name, "Inverter 2");
+
+    let by_group = TagSearchFilter { schedule_group_id: Some("high_freq".to_string()), ..Default::default() };
+    assert_eq!(db.count_tag_search(&by_group).await?, 2);
+
+    let by_name = TagSearchFilter { name_pattern: Some("total*".to_string()), address_max: Some(6000), ..Default::default() };
+    assert_eq!(db.count_tag_search(&by_name).await?, 2);
+
+    // Save the search, then fix the register everywhere by reference
+    db.save_tag_search(&SavedTagSearch {
+        id: "search-1".to_string(),
+        name: Some("Total energy".to_string()),
+        filter: by_address,
+        created_by: Some("admin".to_string()),
+        created_at: Utc::now(),
+    }).await?;
+    let saved = db.get_saved_tag_search("search-1").await?.expect("saved search");
+
+    let changes = TagBulkChanges { address: Some(5040), data_type: Some("uint64".to_string()), ..Default::default() };
+    let (updated, device_ids) = db.bulk_update_tags(&saved.filter, &changes).await?;
+    assert_eq!(updated, 2);
+    assert_eq!(device_ids, vec!["inv-1".to_string(), "inv-2".to_string()]);
+
+    let tags = db.get_device_tags("inv-1").await?;
+    let total = tags.iter().find(|t| t.name == "Total Energy").unwrap();
+    assert_eq!(total.address, 5040);
+    assert_eq!(total.data_type, "uint64");
+    assert_eq!(total.unit.as_deref(), Some("kWh"));
+    assert_eq!(tags.iter().find(|t| t.name == "Daily Energy").unwrap().address, 5003);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
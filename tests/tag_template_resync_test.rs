@@ -0,0 +1,247 @@
+mod support;
+
+use ava_device_logger::database::{Database, DeviceInstance, DeviceTag, TagTemplate, TagWritePolicy};
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::error::Error;
+use support::Logger;
+
+#[tokio::test]
+async fn test_devices_without_links_are_matched_by_name() -> Result<(), Box<dyn Error>> {
+    let db_path = std::env::temp_dir().join(format!("template-resync-{}.db", uuid::Uuid::new_v4()));
+    let db = Database::new(&db_path.to_string_lossy()).await?;
+    let model = db.create_device_model("Meter", None, "modbus_tcp", None).await?;
+    for (name, address, unit) in [("Voltage", 10, "V"), ("Current", 20, "A")] {
+        db.create_tag_template(&TagTemplate {
+            id: None,
+            model_id: model.id.clone(),
+            name: name.to_string(),
+            address,
+            size: 2,
+            data_type: "float32".to_string(),
+            description: None,
+            scaling_multiplier: 1.0,
+            scaling_offset: 0.0,
+            unit: Some(unit.to_string()),
+            read_only: true,
+        })
+        .await?;
+    }
+
+    db.create_device(&DeviceInstance {
+        id: "meter-1".to_string(),
+        name: "Meter 1".to_string(),
+        serial_no: None,
+        model_id: Some(model.id.clone()),
+        enabled: false,
+        polling_interval_ms: 1000,
+        timeout_ms: 5000,
+        retry_count: 3,
+        protocol_config: "{}".to_string(),
+        tb_device_id: None,
+        tb_group_id: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    })
+    .await?;
+    let tag = |name: &str, address: u16, unit: &str| DeviceTag {
+        id: None,
+        device_id: "meter-1".to_string(),
+        name: name.to_string(),
+        address,
+        size: 2,
+        data_type: "float32".to_string(),
+        description: None,
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: Some(unit.to_string()),
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy: TagWritePolicy::Disabled,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    };
+    // Voltage only differs in unit; Current sits at another address with no record of why
+    db.create_device_tags("meter-1", &[tag("Voltage", 10, "kV"), tag("Current", 22, "A"), tag("Extra", 30, "")]).await?;
+
+    let resync = db.resync_model_devices(&model.id, false).await?;
+    let updated: Vec<(&str, Vec<&str>)> = resync.updated.iter().map(|t| (t.tag_name.as_str(), t.changes.iter().map(|c| c.field.as_str()).collect())).collect();
+    assert_eq!(updated, [("Voltage", vec!["unit"])]);
+    let conflicts: Vec<(&str, &[String])> = resync.conflicts.iter().map(|t| (t.tag_name.as_str(), t.customized.as_slice())).collect();
+    assert_eq!(conflicts, [("Current", &["address".to_string()][..])]);
+    assert_eq!(resync.unchanged, 0);
+
+    let tags = db.get_device_tags("meter-1").await?;
+    assert_eq!(tags.iter().map(|t| (t.name.as_str(), t.address, t.unit.as_deref())).collect::<Vec<_>>(), [
+        ("Voltage", 10, Some("V")),
+        ("Current", 22, Some("A")),
+        ("Extra", 30, Some("")),
+    ]);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+/// multipart/form-data body for text fields and one CSV file
+fn multipart(boundary: &str, fields: &[(&str, &str)], csv: &str) -> String {
+    let mut body = String::new();
+    for (name, value) in fields {
+        body += &format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n", boundary, name, value);
+    }
+    body += &format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"csv_file\"; filename=\"tags.csv\"\r\nContent-Type: text/csv\r\n\r\n{}\r\n--{}--\r\n",
+        boundary, csv, boundary
+    );
+    body
+}
+
+#[tokio::test]
+async fn test_template_edits_are_resynced_to_devices_except_customized_tags() -> Result<(), Box<dyn Error>> {
+
+    let logger = Logger::start("").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let boundary = "template-resync-test";
+    let csv = "name,address,data_type,unit,description\nEnergy,100,float64,kWh,Total energy\nPower,104,float32,kW,\nStatus,110,uint16,,\n";
+    let body: Value = client
+        .post(format!("{}/api/device-models", base_url))
+        .bearer_auth(token)
+        .header("Content-Type", format!("multipart/form-data; boundary={}", boundary))
+        .body(multipart(boundary, &[("name", "Meter"), ("protocol_type", "modbus_tcp")], csv))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let model_id = body["data"]["id"].as_str().unwrap().to_string();
+
+    let body: Value = client
+        .post(format!("{}/api/devices-enhanced/from-model", base_url))
+        .bearer_auth(token)
+        .json(&json!({
+            "id": "meter-1", "name": "Meter 1", "model_id": model_id, "enabled": false, "polling_interval_ms": 1000,
+            "timeout_ms": 1000, "retry_count": 1, "address_offset": 1000,
+            "protocol_config": {"type": "modbus_tcp", "host": "127.0.0.1", "port": 502, "slave_id": 1},
+        }))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["success"], true, "{}", body);
+
+    // Rescale Power on the device itself
+    let device: Value = client.get(format!("{}/api/devices-enhanced/meter-1", base_url)).bearer_auth(token).send().await?.json().await?;
+    let mut request = device["data"]["device"].clone();
+    request["protocol_config"] = serde_json::from_str(device["data"]["device"]["protocol_config"].as_str().unwrap())?;
+    request["tags"] = device["data"]["tags"].clone();
+    for tag in request["tags"].as_array_mut().unwrap() {
+        if tag["name"] == "Power" {
+            tag["scaling_multiplier"] = json!(0.1);
+        }
+    }
+    let body: Value = client.put(format!("{}/api/devices-enhanced/meter-1", base_url)).bearer_auth(token).json(&request).send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+
+    let templates: Value = client.get(format!("{}/api/device-models/{}/tags", base_url, model_id)).bearer_auth(token).send().await?.json().await?;
+    let template = |name: &str| templates["data"].as_array().unwrap().iter().find(|t| t["name"] == name).unwrap().clone();
+    let template_id = |name: &str| template(name)["id"].as_i64().unwrap();
+    let edit = |name: &str, address: u16, data_type: &str, scaling_multiplier: f64, unit: &str| {
+        let request = client
+            .put(format!("{}/api/device-models/{}/tags/{}", base_url, model_id, template_id(name)))
+            .bearer_auth(token)
+            .json(&json!({
+                "name": name, "address": address, "data_type": data_type, "scaling_multiplier": scaling_multiplier,
+                "scaling_offset": 0.0, "unit": unit, "read_only": false, "description": template(name)["description"],
+            }));
+        async move {
+            let response = request.send().await?;
+            Ok::<_, reqwest::Error>((response.status().as_u16(), response.json::<Value>().await.unwrap_or(Value::Null)))
+        }
+    };
+
+    let (_, body) = edit("Energy", 100, "float64", 0.001, "MWh").await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!(body["data"]["size"], 4);
+    let (_, body) = edit("Power", 104, "float32", 2.0, "kW").await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let (_, body) = edit("Status", 105, "uint16", 1.0, "").await?;
+    assert_eq!(body["field_errors"], json!([
+        {"field": "address", "message": "'Status' (holding 105) overlaps 'Power' (holding 104-105)"},
+    ]));
+    let (_, body) = edit("Status", 110, "bcd", 1.0, "").await?;
+    assert_eq!(body["field_errors"][0]["field"], "data_type", "{}", body);
+
+    let resync = |dry_run: bool| {
+        let request = client
+            .post(format!("{}/api/device-models/{}/resync-devices", base_url, model_id))
+            .bearer_auth(token)
+            .json(&json!({"dry_run": dry_run}));
+        async move { request.send().await?.json::<Value>().await }
+    };
+    let energy_change = json!({
+        "device_id": "meter-1", "tag_name": "Energy", "template_id": template_id("Energy"),
+        "changes": [
+            {"field": "scaling_multiplier", "device_value": 1.0, "template_value": 0.001},
+            {"field": "unit", "device_value": "kWh", "template_value": "MWh"},
+        ],
+    });
+    let power_conflict = json!({
+        "device_id": "meter-1", "tag_name": "Power", "template_id": template_id("Power"),
+        "changes": [{"field": "scaling_multiplier", "device_value": 0.1, "template_value": 2.0}],
+        "customized": ["scaling_multiplier"],
+    });
+
+    let body = resync(true).await?;
+    assert_eq!(body["data"]["updated"], json!([energy_change]), "{}", body);
+    assert_eq!(body["data"]["conflicts"], json!([power_conflict]));
+    assert_eq!(body["data"]["unchanged"], 1);
+
+    let tags = |body: &Value| -> Vec<(String, u64, f64, String)> {
+        body["data"]["tags"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|tag| {
+                (
+                    tag["name"].as_str().unwrap().to_string(),
+                    tag["address"].as_u64().unwrap(),
+                    tag["scaling_multiplier"].as_f64().unwrap(),
+                    tag["unit"].as_str().unwrap_or_default().to_string(),
+                )
+            })
+            .collect()
+    };
+    let device: Value = client.get(format!("{}/api/devices-enhanced/meter-1", base_url)).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(tags(&device)[0], ("Energy".to_string(), 1100, 1.0, "kWh".to_string()), "dry run wrote changes");
+
+    let body = resync(false).await?;
+    assert_eq!(body["data"]["updated"], json!([energy_change]), "{}", body);
+    let device: Value = client.get(format!("{}/api/devices-enhanced/meter-1", base_url)).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(tags(&device), [
+        ("Energy".to_string(), 1100, 0.001, "MWh".to_string()),
+        ("Power".to_string(), 1104, 0.1, "kW".to_string()),
+        ("Status".to_string(), 1110, 1.0, "".to_string()),
+    ]);
+
+    // Once synced only the conflict remains
+    let body = resync(false).await?;
+    assert_eq!(body["data"]["updated"], json!([]));
+    assert_eq!(body["data"]["conflicts"], json!([power_conflict]));
+    assert_eq!(body["data"]["unchanged"], 2);
+
+    // Deleting a template keeps the tags already copied from it
+    let status_id = template_id("Status");
+    let delete = || client.delete(format!("{}/api/device-models/{}/tags/{}", base_url, model_id, status_id)).bearer_auth(token).send();
+    assert_eq!(delete().await?.status().as_u16(), 200);
+    assert_eq!(delete().await?.status().as_u16(), 404);
+    let device: Value = client.get(format!("{}/api/devices-enhanced/meter-1", base_url)).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(tags(&device).len(), 3);
+    let (status, _) = edit("Status", 110, "uint16", 1.0, "").await?;
+    assert_eq!(status, 404);
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use ava_device_logger::database::{Database, DeviceInstance, DeviceTag, TagWriteAudit, TagWritePolicy};
+use chrono::Utc;
+use std::error::Error;
+
+fn device(id: &str) -> DeviceInstance {
+    DeviceInstance {
+        id: id.to_string(),
+        name: "Inverter 1".to_string(),
+        serial_no: None,
+        model_id: None,
+        enabled: false,
+        polling_interval_ms: 1000,
+        timeout_ms: 5000,
+        retry_count: 3,
+        protocol_config: "{}".to_string(),
+        tb_device_id: None,
+        tb_group_id: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    }
+}
+
+fn tag(name: &str, address: u16, read_only: bool, write_policy: TagWritePolicy) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: "inv-1".to_string(),
+        name: name.to_string(),
+        address,
+        size: 1,
+        data_type: "uint16".to_string(),
+        description: None,
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: None,
+        read_only,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+#[test]
+fn test_read_only_overrides_write_policy() {
+    let locked = tag("Output Limit", 100, true, TagWritePolicy::AnyAuthenticated);
+    assert!(locked.check_write("admin").is_err());
+
+    let disabled = tag("Output Limit", 100, false, TagWritePolicy::Disabled);
+    assert!(disabled.check_write("admin").is_err());
+
+    let admin_only = tag("Output Limit", 100, false, TagWritePolicy::AdminOnly);
+    assert!(admin_only.check_write("admin").is_ok());
+    assert!(admin_only.check_write("viewer").is_err());
+
+    let open = tag("Output Limit", 100, false, TagWritePolicy::AnyAuthenticated);
+    assert!(open.check_write("viewer").is_ok());
+}
+
+#[test]
+fn test_write_policy_defaults_to_disabled_when_omitted() -> Result<(), Box<dyn Error>> {
+    let mut value = serde_json::to_value(tag("Output Limit", 100, false, TagWritePolicy::AdminOnly))?;
+    assert_eq!(value["write_policy"], "admin_only");
+
+    value.as_object_mut().unwrap().remove("write_policy");
+    let parsed: DeviceTag = serde_json::from_value(value)?;
+    assert_eq!(parsed.write_policy, TagWritePolicy::Disabled);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_write_policy_persists_and_writes_are_audited() -> Result<(), Box<dyn Error>> {
+    let db_path = std::env::temp_dir()
+        .join(format!("tag-write-policy-{}.db", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string();
+    let db = Database::new(&db_path).await?;
+
+    db.create_device(&device("inv-1")).await?;
+    db.create_device_tags("inv-1", &[
+        tag("Active Power", 30001, true, TagWritePolicy::Disabled),
+        tag("Output Limit", 40001, false, TagWritePolicy::AdminOnly),
+    ]).await?;
+
+    let tags = db.get_device_tags("inv-1").await?;
+    assert_eq!(tags[0].write_policy, TagWritePolicy::Disabled);
+    assert!(tags[0].read_only);
+    assert_eq!(tags[1].write_policy, TagWritePolicy::AdminOnly);
+
+    let limit = &tags[1];
+    for (username, role, requested) in [("operator", "viewer", 80.0), ("admin", "admin", 90.0)] {
+        let (outcome, detail) = match limit.check_write(role) {
+            Ok(()) => ("accepted", None),
+            Err(reason) => ("rejected", Some(reason)),
+        };
+        db.record_tag_write_audit(&TagWriteAudit {
+            id: None,
+            device_id: "inv-1".to_string(),
+            tag_id: limit.id,
+            tag_name: limit.name.clone(),
+            username: username.to_string(),
+            previous_value: Some(100.0),
+            requested_value: requested,
+            outcome: outcome.to_string(),
+            detail,
+            created_at: Utc::now(),
+        }).await?;
+    }
+
+    let audit = db.get_tag_write_audit("inv-1", 10).await?;
+    assert_eq!(audit.len(), 2);
+    assert_eq!((audit[0].username.as_str(), audit[0].outcome.as_str()), ("admin", "accepted"));
+    assert_eq!((audit[1].username.as_str(), audit[1].outcome.as_str()), ("operator", "rejected"));
+    assert_eq!(audit[1].previous_value, Some(100.0));
+    assert_eq!(audit[1].requested_value, 80.0);
+    assert!(audit[1].detail.as_deref().unwrap_or_default().contains("admins"));
+
+    let _ = std::fs::remove_file(&db_path);
+    Ok(())
+}
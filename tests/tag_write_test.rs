@@ -0,0 +1,214 @@
+mod support;
+
+use ava_device_logger::config::{DeviceConfig, Iec104Config, Iec104Mode, ModbusTcpConfig, ProtocolConfig};
+use ava_device_logger::database::{Database, DeviceInstance, DeviceTag, TagWritePolicy};
+use ava_device_logger::iec104::{Iec104Client, Iec104ModeHandle, Iec104ModeSettings};
+use ava_device_logger::modbus::ModbusClient;
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::error::Error;
+use support::{Logger, ModbusDevice};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+fn tag(device_id: &str, name: &str, address: u16, data_type: &str, scaling_multiplier: f64) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: device_id.to_string(),
+        name: name.to_string(),
+        address,
+        size: if data_type == "float32" { 2 } else { 1 },
+        data_type: data_type.to_string(),
+        description: None,
+        scaling_multiplier,
+        scaling_offset: 0.0,
+        unit: None,
+        read_only: false,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy: TagWritePolicy::AdminOnly,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+fn device_config(protocol: ProtocolConfig) -> DeviceConfig {
+    DeviceConfig {
+        id: "inv-1".to_string(),
+        name: "Inverter 1".to_string(),
+        enabled: true,
+        protocol,
+        polling_interval_ms: 1000,
+        timeout_ms: 1000,
+        retry_count: 3,
+        tags: Vec::new(),
+        strict_types: false,
+    }
+}
+
+#[tokio::test]
+async fn test_modbus_writes_unscale_encode_and_read_back() -> Result<(), Box<dyn Error>> {
+    let device = ModbusDevice::start([(40001, 800)]).await?;
+    let port = device.port();
+    let mut client = ModbusClient::new(device_config(ProtocolConfig::ModbusTcp(ModbusTcpConfig {
+        host: "127.0.0.1".to_string(),
+        port,
+        slave_id: 1,
+        max_block_gap: 0,
+        request_delay_ms: 0,
+        serial_source: None,
+    })));
+    client.connect().await?;
+
+    // 0.1 kW per count: 75 kW is 750 counts, written with a single-register write
+    let limit = tag("inv-1", "Output Limit", 40001, "uint16", 0.1);
+    let result = client.write_tag(&limit, 75.0).await?;
+    assert_eq!(result.registers, vec![750]);
+    assert_eq!(result.raw_value, 750.0);
+    assert_eq!(result.previous_value, Some(80.0));
+    assert_eq!(result.read_back, Some(75.0));
+    assert_eq!(device.get(40001), 750);
+
+    // Signed and 32-bit types use the same layouts as reads
+    let offset = tag("inv-1", "Reactive Offset", 40010, "int16", 1.0);
+    assert_eq!(client.write_tag(&offset, -2.0).await?.registers, vec![0xFFFE]);
+    let setpoint = tag("inv-1", "Power Factor", 40020, "float32", 1.0);
+    let result = client.write_tag(&setpoint, 0.95).await?;
+    let bits = 0.95f32.to_bits();
+    assert_eq!(result.registers, vec![(bits >> 16) as u16, bits as u16]);
+    assert!((result.read_back.unwrap() - 0.95).abs() < 1e-6);
+
+    // Values the register can't hold never reach the device
+    assert!(client.write_tag(&limit, -1.0).await.is_err());
+    assert!(client.write_tag(&limit, 10_000.0).await.is_err());
+    assert!(client.write_tag(&tag("inv-1", "Active Power", 30001, "input_register", 1.0), 1.0).await.is_err());
+    assert_eq!(device.get(40001), 750);
+
+    let run = tag("inv-1", "Run", 1, "coil", 1.0);
+    assert_eq!(client.write_tag(&run, 1.0).await?.read_back, Some(1.0));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_iec104_setpoint_waits_for_confirmation() -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        loop {
+            let mut header = [0u8; 2];
+            if socket.read_exact(&mut header).await.is_err() {
+                break;
+            }
+            let mut body = vec![0u8; header[1] as usize];
+            if socket.read_exact(&mut body).await.is_err() {
+                break;
+            }
+            if body[0] == 0x07 {
+                let _ = socket.write_all(&[0x68, 4, 0x0B, 0, 0, 0]).await;
+            } else if body[4] == 50 {
+                // Confirm IOA 200 and refuse anything else
+                let mut confirmation = vec![0x68, header[1]];
+                confirmation.extend(&body);
+                confirmation[8] = if body[10] == 200 { 7 } else { 7 | 0x40 };
+                let _ = socket.write_all(&confirmation).await;
+            }
+        }
+    });
+
+    let protocol = ProtocolConfig::Iec104(Iec104Config {
+        host: "127.0.0.1".to_string(),
+        port,
+        common_address: 1,
+        mode: Iec104Mode::Interrogation,
+        interrogation_interval_ms: 300_000,
+    });
+    let handle = Iec104ModeHandle::new(Iec104ModeSettings::from_protocol(&protocol).unwrap());
+    let mut client = Iec104Client::new(device_config(protocol), handle);
+    client.connect().await?;
+
+    let result = client.write_setpoint(&tag("rtu-1", "float_200", 200, "float32", 0.5), 21.0).await?;
+    assert_eq!(result.raw_value, 42.0);
+    assert_eq!(result.read_back, Some(21.0));
+    assert!(result.registers.is_empty());
+
+    let refused = client.write_setpoint(&tag("rtu-1", "float_201", 201, "float32", 1.0), 1.0).await;
+    assert!(refused.unwrap_err().to_string().contains("rejected"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_write_endpoint_checks_policy_and_running_state_and_audits() -> Result<(), Box<dyn Error>> {
+    let device = ModbusDevice::start([(40001, 800)]).await?;
+    let modbus_port = device.port();
+
+    let work_dir = support::work_dir("tag-write")?;
+
+    let db = Database::new(&work_dir.join("data.db").to_string_lossy()).await?;
+    for (id, enabled) in [("inv-1", true), ("inv-2", false)] {
+        db.create_device(&DeviceInstance {
+            id: id.to_string(),
+            name: id.to_string(),
+            serial_no: None,
+            model_id: None,
+            enabled,
+            polling_interval_ms: 1000,
+            timeout_ms: 1000,
+            retry_count: 3,
+            protocol_config: json!({"type": "modbus_tcp", "host": "127.0.0.1", "port": modbus_port, "slave_id": 1}).to_string(),
+            tb_device_id: None,
+            tb_group_id: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            strict_types: false,
+        }).await?;
+        let mut locked = tag(id, "Active Power", 40002, "uint16", 1.0);
+        locked.read_only = true;
+        db.create_device_tags(id, &[tag(id, "Output Limit", 40001, "uint16", 0.1), locked]).await?;
+    }
+    drop(db);
+
+    let logger = Logger::start_in(work_dir, "").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let write = |device_id: &'static str, tag_name: &'static str, value: f64| {
+        let request = client
+            .post(format!("{}/api/devices-enhanced/{}/write", base_url, device_id))
+            .bearer_auth(token)
+            .json(&json!({"tag_name": tag_name, "value": value}));
+        async move { request.send().await }
+    };
+
+    let body: Value = write("inv-1", "Output Limit", 60.0).await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!(body["data"]["registers"], json!([600]));
+    assert_eq!(body["data"]["previous_value"], 80.0);
+    assert_eq!(body["data"]["read_back"], 60.0);
+    assert_eq!(device.get(40001), 600);
+
+    let body: Value = write("inv-1", "Active Power", 1.0).await?.json().await?;
+    assert!(body["error"].as_str().unwrap().contains("read-only"), "{}", body);
+    let body: Value = write("inv-2", "Output Limit", 50.0).await?.json().await?;
+    assert!(body["error"].as_str().unwrap().contains("not running"), "{}", body);
+    assert_eq!(write("inv-1", "Missing", 1.0).await?.status(), 404);
+    assert_eq!(device.get(40001), 600);
+
+    let audit: Value = client
+        .get(format!("{}/api/devices-enhanced/inv-1/writes", base_url))
+        .bearer_auth(token)
+        .send()
+        .await?
+        .json()
+        .await?;
+    let audit = audit["data"].as_array().expect("audit entries").clone();
+    assert_eq!(audit.len(), 2);
+    assert_eq!((audit[0]["tag_name"].as_str(), audit[0]["outcome"].as_str()), (Some("Active Power"), Some("rejected")));
+    assert_eq!((audit[1]["tag_name"].as_str(), audit[1]["outcome"].as_str()), (Some("Output Limit"), Some("accepted")));
+    assert_eq!(audit[1]["username"], "admin");
+    assert_eq!(audit[1]["requested_value"], 60.0);
+    assert_eq!(audit[1]["previous_value"], 80.0);
+    Ok(())
+}
@@ -11,7 +11,7 @@ fn export_devices_to_csv(devices: &[DeviceData], filename: &str) -> Result<(), B
     let mut writer = Writer::from_writer(file);
 
     // Write CSV header
-    writer.write_record(&[
+    writer.write_record([
         "Device ID",
         "Name", 
         "Type",
@@ -28,7 +28,7 @@ fn export_devices_to_csv(devices: &[DeviceData], filename: &str) -> Result<(), B
     for device in devices {
         let created_time_str = if let Some(created_time) = device.created_time {
             DateTime::from_timestamp_millis(created_time)
-                .unwrap_or_else(|| Utc::now())
+                .unwrap_or_else(Utc::now)
                 .format("%Y-%m-%d %H:%M:%S UTC")
                 .to_string()
         } else {
@@ -52,7 +52,7 @@ fn export_devices_to_csv(devices: &[DeviceData], filename: &str) -> Result<(), B
             "None".to_string()
         };
 
-        writer.write_record(&[
+        writer.write_record([
             &device.id.id,
             &device.name,
             &device.device_type,
@@ -240,7 +240,7 @@ async fn test_get_all_entity_groups() -> Result<(), Box<dyn Error>> {
             
             if let Some(created_time) = group.created_time {
                 let datetime = chrono::DateTime::from_timestamp_millis(created_time)
-                    .unwrap_or_else(|| chrono::Utc::now());
+                    .unwrap_or_else(chrono::Utc::now);
                 println!("├─ Created: {}", datetime.format("%Y-%m-%d %H:%M:%S UTC"));
             }
             
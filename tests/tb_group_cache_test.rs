@@ -0,0 +1,98 @@
+mod support;
+
+use ava_device_logger::tb_rust_client::{CreateDeviceRequest, GroupDeviceCache, ThingsBoardClient};
+use serde_json::json;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const GROUP_ID: &str = "group-1";
+
+/// ThingsBoard stand-in serving login, a one-page group listing and device creation
+async fn spawn_tb_server() -> MockServer {
+    let server = support::thingsboard().await;
+    let listing = json!({"data": [{"id": {"id": "tb-1", "entityType": "DEVICE"}, "name": "ACCV-P002-I01", "type": "Inverter", "label": ""}], "totalPages": 1, "totalElements": 1, "has_next": false});
+    Mock::given(method("GET"))
+        .and(path(format!("/api/entityGroup/{}/devices", GROUP_ID)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(listing))
+        .mount(&server)
+        .await;
+    let created = json!({"id": {"id": "tb-2", "entityType": "DEVICE"}, "name": "ACCV-P002-I02", "type": "Inverter"});
+    Mock::given(any()).respond_with(ResponseTemplate::new(200).set_body_json(created)).mount(&server).await;
+    server
+}
+
+/// How many group listing pages `tb` served
+async fn listings(tb: &MockServer) -> usize {
+    support::tb_requests(tb).await.iter().filter(|(line, _)| line.contains(&format!("/api/entityGroup/{}/devices", GROUP_ID))).count()
+}
+
+fn create_request(name: &str) -> CreateDeviceRequest {
+    CreateDeviceRequest {
+        id: None,
+        tenant_id: None,
+        customer_id: None,
+        owner_id: None,
+        name: name.to_string(),
+        device_type: "Inverter".to_string(),
+        label: None,
+        device_profile_id: None,
+        device_data: None,
+        firmware_id: None,
+        software_id: None,
+        additional_info: None,
+    }
+}
+
+#[tokio::test]
+async fn test_group_listing_is_shared_between_clients_and_invalidated_on_create() -> Result<(), Box<dyn Error>> {
+    let tb = spawn_tb_server().await;
+    let base_url = tb.uri();
+    let cache = Arc::new(GroupDeviceCache::new(Duration::from_secs(60), 100));
+
+    // Sync seeds its counters, then catalog export lists the same group with its own client
+    let mut sync_client = ThingsBoardClient::new(&base_url).with_group_cache(cache.clone());
+    sync_client.login("user", "pass").await?;
+    assert_eq!(sync_client.get_all_group_devices_cached(GROUP_ID).await?.len(), 1);
+
+    let mut catalog_client = ThingsBoardClient::new(&base_url).with_group_cache(cache.clone());
+    catalog_client.login("user", "pass").await?;
+    let devices = catalog_client.get_all_group_devices_cached(GROUP_ID).await?;
+    assert_eq!(devices[0].name, "ACCV-P002-I01");
+    assert_eq!(listings(&tb).await, 1);
+
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses, stats.cached_groups), (1, 1, 1));
+
+    // Creating a device in the group drops the stale listing
+    sync_client.create_device(&create_request("ACCV-P002-I02"), GROUP_ID, None).await?;
+    assert_eq!(cache.stats().cached_groups, 0);
+    catalog_client.get_all_group_devices_cached(GROUP_ID).await?;
+    assert_eq!(listings(&tb).await, 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_oversized_groups_and_expired_listings_are_crawled() -> Result<(), Box<dyn Error>> {
+    let tb = spawn_tb_server().await;
+    let base_url = tb.uri();
+
+    let oversized = Arc::new(GroupDeviceCache::new(Duration::from_secs(60), 0));
+    let mut client = ThingsBoardClient::new(&base_url).with_group_cache(oversized.clone());
+    client.login("user", "pass").await?;
+    client.get_all_group_devices_cached(GROUP_ID).await?;
+    client.get_all_group_devices_cached(GROUP_ID).await?;
+    assert_eq!(listings(&tb).await, 2);
+    assert_eq!(oversized.stats().cached_groups, 0);
+
+    let expiring = Arc::new(GroupDeviceCache::new(Duration::ZERO, 100));
+    let mut client = ThingsBoardClient::new(&base_url).with_group_cache(expiring.clone());
+    client.login("user", "pass").await?;
+    client.get_all_group_devices_cached(GROUP_ID).await?;
+    client.get_all_group_devices_cached(GROUP_ID).await?;
+    assert_eq!(listings(&tb).await, 4);
+    assert_eq!(expiring.stats().hits, 0);
+    Ok(())
+}
@@ -0,0 +1,167 @@
+mod support;
+
+use ava_device_logger::config::PvNaming;
+use ava_device_logger::database::{DeviceTag, TagWritePolicy, TbChildDevice};
+use ava_device_logger::tb_rust_client::{Device, DeviceHierarchy, TbError, ThingsBoardClient};
+use serde_json::{json, Value};
+use std::error::Error;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+fn device_json(id: &str, name: &str) -> Value {
+    json!({"id": {"id": id, "entityType": "DEVICE"}, "name": name, "type": "Mppt", "label": name})
+}
+
+/// ThingsBoard stand-in where only `new_device` can be created; every other name already
+/// exists and is found by name as `tb-<name>`, except names containing `missing`.
+/// Relations to `tb-new` are rejected and relations to `tb-ACCV-P002-I01-M01` already exist.
+async fn spawn_tb_server(new_device: &'static str, missing: &'static str) -> MockServer {
+    let server = support::thingsboard().await;
+    let relate = |request: &Request| {
+        let relation: Value = serde_json::from_slice(&request.body).unwrap();
+        match relation["to"]["id"].as_str().unwrap() {
+            "tb-new" => ResponseTemplate::new(400).set_body_json(json!({"status": 400, "message": "Invalid relation", "errorCode": 31})),
+            "tb-ACCV-P002-I01-M01" => ResponseTemplate::new(409).set_body_json(json!({"status": 409, "message": "Relation already exists", "errorCode": 31})),
+            _ => ResponseTemplate::new(200),
+        }
+    };
+    Mock::given(method("POST")).and(path("/api/relation")).respond_with(relate).mount(&server).await;
+
+    let create = move |request: &Request| {
+        let device: Value = serde_json::from_slice(&request.body).unwrap();
+        let name = device["name"].as_str().unwrap();
+        if name == new_device {
+            ResponseTemplate::new(200).set_body_json(device_json("tb-new", name))
+        } else {
+            ResponseTemplate::new(400).set_body_json(json!({"status": 400, "message": "Device with such name already exists!", "errorCode": 31}))
+        }
+    };
+    Mock::given(method("POST")).and(path("/api/device")).respond_with(create).mount(&server).await;
+
+    let find = move |request: &Request| {
+        let name = request.url.query_pairs().find(|(key, _)| key == "deviceName").map(|(_, name)| name.to_string()).unwrap_or_default();
+        if name.contains(missing) {
+            support::tb_not_found()
+        } else {
+            ResponseTemplate::new(200).set_body_json(device_json(&format!("tb-{}", name), &name))
+        }
+    };
+    Mock::given(method("GET")).and(path("/api/tenant/devices")).respond_with(find).mount(&server).await;
+    server
+}
+
+fn tag(name: &str, description: &str) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: "inv-1".to_string(),
+        name: name.to_string(),
+        address: 5000,
+        size: 1,
+        data_type: "uint16".to_string(),
+        description: Some(description.to_string()),
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: None,
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy: TagWritePolicy::Disabled,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+async fn hierarchy(client: &ThingsBoardClient, inverter_index: u32) -> Result<DeviceHierarchy, TbError> {
+    let tags = vec![
+        tag("Pac", "Inverter (SG250HX)"),
+        tag("Vmppt", "MPPT - MPPT 1 (SG250HX)"),
+        tag("Vmppt", "MPPT - MPPT 2 (SG250HX)"),
+        tag("Udc", "String - MPPT 1 - Input 1 (SG250HX)"),
+        tag("Udc", "String - MPPT 2 - Input 2 (SG250HX)"),
+    ];
+    client.analyze_device_hierarchy(tags, "ACCV-P002-Plant", inverter_index, PvNaming::Global).await
+}
+
+/// `(from, to)` ids of every relation posted so far
+async fn relations(tb: &MockServer) -> Vec<(String, String)> {
+    support::tb_requests(tb)
+        .await
+        .into_iter()
+        .filter(|(line, _)| line.starts_with("POST /api/relation"))
+        .map(|(_, relation)| {
+            assert_eq!((relation["type"].as_str(), relation["from"]["entityType"].as_str()), (Some("Contains"), Some("DEVICE")));
+            (relation["from"]["id"].as_str().unwrap().to_string(), relation["to"]["id"].as_str().unwrap().to_string())
+        })
+        .collect()
+}
+
+fn ids(devices: &[Device]) -> Vec<(String, String)> {
+    devices.iter().map(|device| (device.name.clone(), device.id.as_ref().map(|id| id.id.clone()).unwrap_or_default())).collect()
+}
+
+#[tokio::test]
+async fn test_existing_hierarchy_devices_get_their_real_ids() -> Result<(), Box<dyn Error>> {
+    let tb = spawn_tb_server("ACCV-P002-I01-M02", "PV02").await;
+    let mut client = ThingsBoardClient::new(&tb.uri());
+    client.login("user", "pass").await?;
+
+    let hierarchy = hierarchy(&client, 1).await?;
+    let created = client.create_mppt_and_string_devices(&hierarchy, "group-1", "tb-inverter").await?;
+    assert_eq!(ids(&created.devices), [
+        ("ACCV-P002-I01-M01".to_string(), "tb-ACCV-P002-I01-M01".to_string()),
+        ("ACCV-P002-I01-M02".to_string(), "tb-new".to_string()),
+        ("ACCV-P002-I01-M01-PV01".to_string(), "tb-ACCV-P002-I01-M01-PV01".to_string()),
+    ]);
+
+    // A device that exists but can't be found is reported, not given a made-up id
+    assert_eq!(created.failed.len(), 1);
+    let (name, error) = &created.failed[0];
+    assert_eq!(name, "ACCV-P002-I01-M02-PV02");
+    assert!(error.to_string().contains("already exists but could not be looked up"), "{}", error);
+
+    let lookups: Vec<String> = support::tb_requests(&tb).await.into_iter().map(|(line, _)| line).filter(|line| line.contains("/api/tenant/devices")).collect();
+    assert_eq!(lookups.len(), 3, "{:?}", lookups);
+
+    // MPPTs hang off the inverter and strings off their MPPT; an existing relation is fine
+    assert_eq!(relations(&tb).await, [
+        ("tb-inverter".to_string(), "tb-ACCV-P002-I01-M01".to_string()),
+        ("tb-inverter".to_string(), "tb-new".to_string()),
+        ("tb-ACCV-P002-I01-M01".to_string(), "tb-ACCV-P002-I01-M01-PV01".to_string()),
+    ]);
+    assert_eq!(created.relation_failures.len(), 1, "{:?}", created.relation_failures);
+    let (relation, error) = &created.relation_failures[0];
+    assert_eq!(relation, "ACCV-P002-I01 -> ACCV-P002-I01-M02");
+    assert!(error.to_string().contains("Invalid relation"), "{}", error);
+
+    // Found and created children are recorded locally with their numbering; the failed string isn't
+    let child = |tb_device_id: &str, name: &str, device_type: &str, mppt_number, input_number| TbChildDevice {
+        device_id: "inv-1".to_string(),
+        tb_device_id: tb_device_id.to_string(),
+        name: name.to_string(),
+        device_type: device_type.to_string(),
+        mppt_number,
+        input_number,
+    };
+    assert_eq!(created.child_devices(&hierarchy, "inv-1"), [
+        child("tb-ACCV-P002-I01-M01", "ACCV-P002-I01-M01", "Mppt", 1, None),
+        child("tb-new", "ACCV-P002-I01-M02", "Mppt", 2, None),
+        child("tb-ACCV-P002-I01-M01-PV01", "ACCV-P002-I01-M01-PV01", "String", 1, Some(1)),
+    ]);
+
+    // The inverter itself is looked up too, and the whole call fails without it
+    let created = client.create_hierarchical_devices(&hierarchy, "group-1").await?;
+    assert_eq!(ids(&created.devices)[0], ("ACCV-P002-I01".to_string(), "tb-ACCV-P002-I01".to_string()));
+    assert!(created.devices.iter().all(|device| !device.id.as_ref().unwrap().id.starts_with("existing-")));
+    assert!(relations(&tb).await.contains(&("tb-ACCV-P002-I01".to_string(), "tb-ACCV-P002-I01-M01".to_string())));
+
+    let tb = spawn_tb_server("none", "I09").await;
+    let mut client = ThingsBoardClient::new(&tb.uri());
+    client.login("user", "pass").await?;
+    let hierarchy = self::hierarchy(&client, 9).await?;
+    let error = client.create_hierarchical_devices(&hierarchy, "group-1").await.unwrap_err();
+    assert!(error.to_string().contains("ACCV-P002-I09 already exists but could not be looked up"), "{}", error);
+    Ok(())
+}
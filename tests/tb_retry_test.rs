@@ -0,0 +1,194 @@
+mod support;
+
+use ava_device_logger::tb_rust_client::{CreateDeviceRequest, RateLimiter, RetryPolicy, TbError, ThingsBoardClient};
+use serde_json::json;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::field::{Field, Visit};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::Layer;
+use wiremock::matchers::any;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// ThingsBoard's answer to an API call with `status`
+fn tb_response(status: u16) -> ResponseTemplate {
+    match status {
+        200 => ResponseTemplate::new(200).set_body_json(json!({"id": {"id": "tb-1", "entityType": "DEVICE"}, "name": "ACCV-P002-I01", "type": "Inverter"})),
+        400 => ResponseTemplate::new(400).set_body_json(json!({"message": "Device with such name already exists!"})),
+        429 => ResponseTemplate::new(429).set_body_json(json!({"message": "Bad gateway"})).insert_header("Retry-After", "1"),
+        status => ResponseTemplate::new(status).set_body_json(json!({"message": "Bad gateway"})),
+    }
+}
+
+/// Number of API calls, logins left out, `tb` answered
+async fn api_requests(tb: &MockServer) -> usize {
+    support::tb_requests(tb).await.len()
+}
+
+/// Tracing layer keeping the level and fields of every event, to check what was logged
+#[derive(Clone, Default)]
+struct CapturedEvents(Arc<Mutex<Vec<String>>>);
+
+impl<S: tracing::Subscriber> Layer<S> for CapturedEvents {
+    fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+        struct Fields(String);
+        impl Visit for Fields {
+            fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                self.0.push_str(&format!(" {}={:?}", field.name(), value));
+            }
+        }
+
+        let mut fields = Fields(event.metadata().level().to_string());
+        event.record(&mut fields);
+        self.0.lock().unwrap().push(fields.0);
+    }
+}
+
+fn fast_retries() -> RetryPolicy {
+    RetryPolicy { max_attempts: 3, base_delay: Duration::from_millis(5) }
+}
+
+fn create_request() -> CreateDeviceRequest {
+    CreateDeviceRequest {
+        id: None,
+        tenant_id: None,
+        customer_id: None,
+        owner_id: None,
+        name: "ACCV-P002-I01".to_string(),
+        device_type: "Inverter".to_string(),
+        label: None,
+        device_profile_id: None,
+        device_data: None,
+        firmware_id: None,
+        software_id: None,
+        additional_info: None,
+    }
+}
+
+/// A client logged in to a ThingsBoard stand-in answering API calls with a scripted sequence
+/// of statuses, 200 once they are used up
+async fn client_for(statuses: &[u16]) -> Result<(MockServer, ThingsBoardClient), Box<dyn Error>> {
+    let tb = support::thingsboard().await;
+    for &status in statuses {
+        Mock::given(any()).respond_with(tb_response(status)).up_to_n_times(1).mount(&tb).await;
+    }
+    Mock::given(any()).respond_with(tb_response(200)).mount(&tb).await;
+    let mut client = ThingsBoardClient::new(&tb.uri()).with_retry_policy(fast_retries());
+    client.login("user", "pass").await?;
+    Ok((tb, client))
+}
+
+#[tokio::test]
+async fn test_transient_failures_are_retried() -> Result<(), Box<dyn Error>> {
+    let (tb, client) = client_for(&[502, 429]).await?;
+    let device = client.create_device(&create_request(), "group-1", None).await?;
+    assert_eq!(device.name, "ACCV-P002-I01");
+    assert_eq!(api_requests(&tb).await, 3);
+
+    let (tb, client) = client_for(&[503]).await?;
+    assert_eq!(client.get_device_by_id("tb-1").await?.name, "ACCV-P002-I01");
+    assert_eq!(api_requests(&tb).await, 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_failed_requests_are_reported_and_logged() -> Result<(), Box<dyn Error>> {
+    let events = CapturedEvents::default();
+    let _subscriber = tracing::subscriber::set_default(tracing_subscriber::registry().with(events.clone()));
+
+    let (_tb, client) = client_for(&[502, 400]).await?;
+    client.create_device(&create_request(), "group-1", None).await.unwrap_err();
+
+    let report = client.report();
+    assert_eq!(report.requests, 3);
+    let failed: Vec<(&str, u16)> = report.failed_requests.iter().map(|failed| (failed.request.as_str(), failed.status)).collect();
+    assert_eq!(failed, vec![("POST /api/device", 502), ("POST /api/device", 400)]);
+    assert_eq!(report.failed_requests[1].body, r#"{"message":"Device with such name already exists!"}"#);
+
+    let events = events.0.lock().unwrap();
+    assert!(
+        events.iter().any(|event| event.starts_with("WARN")
+            && event.contains("request=POST /api/device")
+            && event.contains("status=400")
+            && event.contains("already exists")),
+        "{:#?}",
+        events
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_business_errors_are_not_retried() -> Result<(), Box<dyn Error>> {
+    let (tb, client) = client_for(&[400]).await?;
+
+    let error = client.create_device(&create_request(), "group-1", None).await.unwrap_err();
+    assert!(error.to_string().contains("already exists"), "{}", error);
+    assert_eq!(api_requests(&tb).await, 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_exhausted_retries_report_last_error_and_attempts() -> Result<(), Box<dyn Error>> {
+    let (tb, client) = client_for(&[502, 502, 503]).await?;
+
+    let error = client.update_device_attributes("tb-1", serde_json::json!({"SN": "123"})).await.unwrap_err();
+    assert!(matches!(error, TbError::RetriesExhausted { attempts: 3, .. }));
+    let message = error.to_string();
+    assert!(message.contains("503") && message.contains("3 attempts"), "{}", message);
+    assert_eq!(api_requests(&tb).await, 3);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_connection_errors_are_retried() -> Result<(), Box<dyn Error>> {
+    let (tb, client) = client_for(&[]).await?;
+    // Hang up on API requests without answering, like a connection reset
+    let reset = |_: &wiremock::Request| std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+    Mock::given(any()).respond_with_err(reset).with_priority(1).mount(&tb).await;
+
+    let error = client.get_group_devices("group-1", 50, 0).await.unwrap_err();
+    assert!(matches!(error, TbError::RetriesExhausted { attempts: 3, .. }), "{}", error);
+    assert_eq!(api_requests(&tb).await, 3);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_rate_limiter_paces_requests_beyond_a_second_of_burst() {
+    let limiter = RateLimiter::new(20.0);
+    let started = Instant::now();
+    for _ in 0..20 {
+        assert!(!limiter.acquire().await);
+    }
+    assert!(limiter.acquire().await);
+    assert!(started.elapsed() >= Duration::from_millis(40));
+
+    let unlimited = RateLimiter::unlimited();
+    for _ in 0..100 {
+        assert!(!unlimited.acquire().await);
+    }
+}
+
+#[tokio::test]
+async fn test_retry_after_pauses_every_client_sharing_the_limit() -> Result<(), Box<dyn Error>> {
+    let limiter = Arc::new(RateLimiter::unlimited());
+    let (tb, client) = client_for(&[429]).await?;
+    let client = client.with_rate_limiter(limiter.clone());
+
+    // Retry-After replaces the backoff and is counted as a throttle pause
+    let started = Instant::now();
+    client.create_device(&create_request(), "group-1", None).await?;
+    assert!(started.elapsed() >= Duration::from_secs(1));
+    assert_eq!(api_requests(&tb).await, 2);
+    assert_eq!(client.throttle_pauses(), 1);
+
+    let (_other_tb, other) = client_for(&[]).await?;
+    let other = other.with_rate_limiter(limiter.clone());
+    limiter.pause_for(Duration::from_millis(300));
+    let started = Instant::now();
+    other.get_device_by_id("tb-1").await?;
+    assert!(started.elapsed() >= Duration::from_millis(300));
+    assert_eq!(other.throttle_pauses(), 1);
+    assert_eq!(client.throttle_pauses(), 1);
+    Ok(())
+}
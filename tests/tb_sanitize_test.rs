@@ -0,0 +1,51 @@
+use ava_device_logger::tb_rust_client::{sanitize_message, ThingsBoardClient, MAX_SAFE_ERROR_LEN};
+use std::error::Error;
+use wiremock::matchers::any;
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+const USERNAME: &str = "installer@example.com";
+const PASSWORD: &str = "s3cr3t-P@ssw0rd";
+
+/// Minimal ThingsBoard stand-in that rejects the login and echoes the request body back
+async fn spawn_rejecting_server() -> MockServer {
+    let server = MockServer::start().await;
+    let reject = |request: &Request| {
+        let echoed = String::from_utf8_lossy(&request.body);
+        let body = format!("{{\"status\":401,\"message\":\"Authentication failed for request {} {}\"}}", echoed, "x".repeat(500));
+        ResponseTemplate::new(401).set_body_raw(body, "application/json")
+    };
+    Mock::given(any()).respond_with(reject).mount(&server).await;
+    server
+}
+
+#[tokio::test]
+async fn test_login_failure_never_leaks_credentials() -> Result<(), Box<dyn Error>> {
+    let tb = spawn_rejecting_server().await;
+    let mut client = ThingsBoardClient::new(&tb.uri());
+
+    let error = client.login(USERNAME, PASSWORD).await.expect_err("login should be rejected");
+
+    // The raw error carries the echoed body, the sanitized one must not
+    assert!(error.to_string().contains(PASSWORD));
+
+    let sanitized = client.sanitize_error(&error);
+    assert!(!sanitized.contains(PASSWORD));
+    assert!(!sanitized.contains(USERNAME));
+    assert!(sanitized.chars().count() <= MAX_SAFE_ERROR_LEN + 3);
+
+    // So must the failed request kept in the client's report
+    let report = client.report();
+    assert_eq!(report.failed_requests.len(), 1);
+    assert!(!report.failed_requests[0].body.contains(PASSWORD));
+    assert!(!report.failed_requests[0].body.contains(USERNAME));
+    Ok(())
+}
+
+#[test]
+fn test_sanitize_message_truncates_on_char_boundary() {
+    let message = format!("token abc123 {}", "é".repeat(300));
+    let sanitized = sanitize_message(&message, &["abc123", ""]);
+    assert!(sanitized.starts_with("token *** "));
+    assert!(sanitized.ends_with("..."));
+    assert_eq!(sanitized.chars().count(), MAX_SAFE_ERROR_LEN + 3);
+}
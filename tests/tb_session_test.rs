@@ -0,0 +1,129 @@
+use ava_device_logger::config::{AppConfig, PvNaming, ThingsBoardConfig};
+use ava_device_logger::tb_rust_client::{TbSession, ThingsBoardClient};
+use serde_json::json;
+use std::collections::HashSet;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use wiremock::matchers::any;
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+/// ThingsBoard stand-in that counts logins and refreshes and rejects revoked tokens
+#[derive(Default)]
+struct MockTb {
+    logins: AtomicUsize,
+    refreshes: AtomicUsize,
+    refresh_fails: AtomicBool,
+    revoked: Mutex<HashSet<String>>,
+}
+
+impl MockTb {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        match request.url.path() {
+            "/api/auth/login" => {
+                let login = self.logins.fetch_add(1, Ordering::SeqCst) + 1;
+                return ResponseTemplate::new(200).set_body_json(json!({"token": format!("jwt-login-{}", login), "refreshToken": format!("refresh-{}", login)}));
+            }
+            "/api/auth/token" => {
+                if self.refresh_fails.load(Ordering::SeqCst) {
+                    return ResponseTemplate::new(401).set_body_json(json!({"message": "Refresh token expired"}));
+                }
+                let refresh = self.refreshes.fetch_add(1, Ordering::SeqCst) + 1;
+                return ResponseTemplate::new(200).set_body_json(json!({"token": format!("jwt-refresh-{}", refresh), "refreshToken": format!("refresh-r{}", refresh)}));
+            }
+            _ => {}
+        }
+
+        let token = request
+            .headers
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default()
+            .trim();
+        if token.is_empty() || self.revoked.lock().unwrap().contains(token) {
+            return ResponseTemplate::new(401).set_body_json(json!({"message": "Token has expired"}));
+        }
+        ResponseTemplate::new(200).set_body_json(json!([]))
+    }
+}
+
+async fn spawn_tb_server(mock: Arc<MockTb>) -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(any()).respond_with(move |request: &Request| mock.respond(request)).mount(&server).await;
+    server
+}
+
+fn config(base_url: &str) -> AppConfig {
+    AppConfig {
+        thingsboard: Some(ThingsBoardConfig {
+            base_url: base_url.to_string(),
+            username: "tenant@example.com".to_string(),
+            password: "secret".to_string(),
+            tenant_label: None,
+            retry_max_attempts: 3,
+            retry_base_delay_ms: 500,
+            requests_per_second: 0.0,
+            sync_concurrency: 3,
+            pv_naming: PvNaming::Global,
+        }),
+        ..Default::default()
+    }
+}
+
+/// What a handler does: build a client from config on the shared session and list groups
+async fn list_groups(config: &AppConfig, session: &Arc<TbSession>) -> Result<String, Box<dyn Error>> {
+    let mut client = ThingsBoardClient::from_config(config)?.with_session(session.clone());
+    client.login_configured().await?;
+    client.get_all_entity_groups("DEVICE").await?;
+    Ok(client.get_token().expect("token"))
+}
+
+#[tokio::test]
+async fn test_consecutive_operations_reuse_the_session_token() -> Result<(), Box<dyn Error>> {
+    let mock = Arc::new(MockTb::default());
+    let tb = spawn_tb_server(mock.clone()).await;
+    let config = config(&tb.uri());
+    let session = Arc::new(TbSession::new());
+
+    let first = list_groups(&config, &session).await?;
+    let second = list_groups(&config, &session).await?;
+    assert_eq!(first, second);
+    assert_eq!(mock.logins.load(Ordering::SeqCst), 1);
+    assert_eq!(session.stats().logins, 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_expired_token_is_refreshed_once() -> Result<(), Box<dyn Error>> {
+    let mock = Arc::new(MockTb::default());
+    let tb = spawn_tb_server(mock.clone()).await;
+    let config = config(&tb.uri());
+    let session = Arc::new(TbSession::new());
+
+    let token = list_groups(&config, &session).await?;
+    mock.revoked.lock().unwrap().insert(token);
+
+    // Several requests hit the expired token at once; only one of them refreshes it
+    let mut client = ThingsBoardClient::from_config(&config)?.with_session(session.clone());
+    client.login_configured().await?;
+    let (a, b, c) = tokio::join!(
+        client.get_all_entity_groups("DEVICE"),
+        client.get_all_entity_groups("DEVICE"),
+        client.get_all_entity_groups("DEVICE"),
+    );
+    a?;
+    b?;
+    c?;
+    assert_eq!(mock.refreshes.load(Ordering::SeqCst), 1);
+    assert_eq!(mock.logins.load(Ordering::SeqCst), 1);
+    assert_eq!(client.get_token().as_deref(), Some("jwt-refresh-1"));
+
+    // Once the refresh token has expired as well, a full login takes over
+    mock.refresh_fails.store(true, Ordering::SeqCst);
+    mock.revoked.lock().unwrap().insert("jwt-refresh-1".to_string());
+    client.get_all_entity_groups("DEVICE").await?;
+    assert_eq!(mock.logins.load(Ordering::SeqCst), 2);
+    assert_eq!(session.stats().refreshes, 1);
+    Ok(())
+}
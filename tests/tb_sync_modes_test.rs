@@ -0,0 +1,207 @@
+mod support;
+
+use ava_device_logger::database::{Database, DeviceInstance, TbChildDevice};
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::error::Error;
+use support::Logger;
+use wiremock::matchers::{method, path, path_regex, query_param};
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+fn device_json(id: &str, name: &str) -> Value {
+    json!({"id": {"id": id, "entityType": "DEVICE"}, "name": name, "type": "Inverter", "label": name})
+}
+
+/// ThingsBoard stand-in for group `group-1` (ACCV-P002-Plant) holding ACCV-P002-I01 (`tb-ok`) and
+/// ACCV-P002-I02 (`tb-relinked`). Devices `tb-gone` and `tb-lost` were deleted; new devices get
+/// `tb-recreated`.
+async fn spawn_tb_server() -> MockServer {
+    let server = support::thingsboard().await;
+    let groups = json!([{"id": {"id": "group-1", "entityType": "ENTITY_GROUP"}, "ownerId": {"id": "tenant", "entityType": "TENANT"}, "name": "ACCV-P002-Plant", "type": "DEVICE", "groupAll": false, "edgeGroupAll": false}]);
+    Mock::given(method("GET")).and(path("/api/entityGroups/DEVICE")).respond_with(ResponseTemplate::new(200).set_body_json(groups)).mount(&server).await;
+    let devices = json!({"data": [device_json("tb-ok", "ACCV-P002-I01"), device_json("tb-relinked", "ACCV-P002-I02")], "totalPages": 1, "totalElements": 2, "hasNext": false});
+    Mock::given(method("GET")).and(path("/api/entityGroup/group-1/devices")).respond_with(ResponseTemplate::new(200).set_body_json(devices)).mount(&server).await;
+    Mock::given(method("GET"))
+        .and(path("/api/tenant/devices"))
+        .and(query_param("deviceName", "ACCV-P002-I02"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(device_json("tb-relinked", "ACCV-P002-I02")))
+        .mount(&server)
+        .await;
+
+    let get_device = |request: &Request| match request.url.path().trim_start_matches("/api/device/") {
+        "tb-gone" | "tb-lost" => support::tb_not_found(),
+        "tb-ok" => ResponseTemplate::new(200).set_body_json(device_json("tb-ok", "ACCV-P002-I01")),
+        "tb-relinked" => ResponseTemplate::new(200).set_body_json(device_json("tb-relinked", "ACCV-P002-I02")),
+        id => ResponseTemplate::new(200).set_body_json(device_json(id, "ACCV-P002-I03")),
+    };
+    Mock::given(method("GET")).and(path_regex("^/api/device/[^/]+$")).respond_with(get_device).mount(&server).await;
+    let create = |request: &Request| {
+        let device: Value = serde_json::from_slice(&request.body).unwrap();
+        ResponseTemplate::new(200).set_body_json(device_json("tb-recreated", device["name"].as_str().unwrap()))
+    };
+    Mock::given(method("POST")).and(path("/api/device")).respond_with(create).mount(&server).await;
+    Mock::given(method("POST")).and(path_regex("^/api/plugins/telemetry/")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+    server
+}
+
+/// `(tb_device_id, ava_name)` of the attribute pushes `tb` answered after the first `*seen`,
+/// moving `seen` past them
+async fn attribute_pushes(tb: &MockServer, seen: &mut usize) -> Vec<(String, Value)> {
+    let pushes: Vec<(String, Value)> = support::tb_requests(tb)
+        .await
+        .into_iter()
+        .filter(|(line, _)| line.contains("/SERVER_SCOPE"))
+        .map(|(line, body)| (line.split('/').nth(4).unwrap_or_default().to_string(), body["ava_name"].clone()))
+        .collect();
+    let new = pushes[*seen..].to_vec();
+    *seen = pushes.len();
+    new
+}
+
+fn device(id: &str, name: &str, tb_device_id: &str) -> DeviceInstance {
+    DeviceInstance {
+        id: id.to_string(),
+        name: name.to_string(),
+        serial_no: None,
+        model_id: None,
+        enabled: false,
+        polling_interval_ms: 1000,
+        timeout_ms: 1000,
+        retry_count: 1,
+        protocol_config: json!({"type": "modbus_tcp", "host": "127.0.0.1", "port": 502, "slave_id": 1}).to_string(),
+        tb_device_id: Some(tb_device_id.to_string()),
+        tb_group_id: Some("group-1".to_string()),
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    }
+}
+
+/// `(local_id, outcome)` of each device in a sync response
+fn outcomes(response: &Value) -> Vec<(String, String)> {
+    response["data"]["outcomes"]
+        .as_array()
+        .unwrap_or_else(|| panic!("{}", response))
+        .iter()
+        .map(|outcome| (outcome["local_id"].as_str().unwrap().to_string(), outcome["outcome"].as_str().unwrap().to_string()))
+        .collect()
+}
+
+fn pairs(items: &[(&str, &str)]) -> Vec<(String, String)> {
+    items.iter().map(|(a, b)| (a.to_string(), b.to_string())).collect()
+}
+
+#[tokio::test]
+async fn test_repair_and_refresh_synced_devices() -> Result<(), Box<dyn Error>> {
+    let tb = spawn_tb_server().await;
+    let tb_url = tb.uri();
+
+    let work_dir = support::work_dir("tb-sync-modes")?;
+    let db_path = work_dir.join("data.db").to_string_lossy().to_string();
+    {
+        let db = Database::new(&db_path).await?;
+        db.create_device(&device("inv-ok", "Inverter A", "tb-ok")).await?;
+        db.create_device(&device("inv-gone", "Inverter B", "tb-gone")).await?;
+        db.create_device(&device("inv-lost", "Inverter C", "tb-lost")).await?;
+        // The deleted inverter's MPPT tells which name it had
+        db.save_tb_child_devices("inv-gone", &[TbChildDevice {
+            device_id: "inv-gone".to_string(),
+            tb_device_id: "tb-m01".to_string(),
+            name: "ACCV-P002-I02-M01".to_string(),
+            device_type: "Mppt".to_string(),
+            mppt_number: 1,
+            input_number: None,
+        }]).await?;
+    }
+
+    let extra_config = format!(
+        r#"
+[thingsboard]
+base_url = "{tb_url}"
+username = "tenant@example.com"
+password = "secret"
+retry_max_attempts = 1
+"#
+    );
+    let logger = Logger::start_in(work_dir, &extra_config).await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    // Syncs run as jobs; wait for each and hand back its result shaped like the old inline response
+    let sync = |body: Value| {
+        let client = client.clone();
+        let base_url = base_url.clone();
+        let token = token.clone();
+        async move {
+            let job = client
+                .post(format!("{}/api/sync-devices-to-thingsboard", base_url))
+                .bearer_auth(&token)
+                .json(&body)
+                .send()
+                .await?
+                .json::<Value>()
+                .await?;
+            let job_id = job["data"]["id"].as_str().expect("job id").to_string();
+            loop {
+                let job = client
+                    .get(format!("{}/api/jobs/{}", base_url, job_id))
+                    .bearer_auth(&token)
+                    .send()
+                    .await?
+                    .json::<Value>()
+                    .await?;
+                match job["data"]["state"].as_str() {
+                    Some("completed") => return Ok::<Value, reqwest::Error>(json!({"success": true, "data": job["data"]["result"]})),
+                    Some("failed") => return Ok(json!({"success": false, "error": job["data"]["error"]})),
+                    _ => tokio::time::sleep(std::time::Duration::from_millis(100)).await,
+                }
+            }
+        }
+    };
+    let mut pushes_seen = 0;
+
+    // Everything is synced already, so the default mode has nothing to do
+    let response = sync(json!({"entity_group_id": "group-1"})).await?;
+    assert_eq!(response["data"]["total_devices"], 0, "{}", response);
+    assert_eq!(response["data"]["mode"], "new_only");
+
+    // Devices deleted in ThingsBoard can't have their attributes refreshed
+    let response = sync(json!({"entity_group_id": "group-1", "mode": "refresh_attributes"})).await?;
+    assert_eq!(outcomes(&response), pairs(&[("inv-ok", "attributes_refreshed"), ("inv-gone", "failed"), ("inv-lost", "failed")]));
+    assert_eq!(response["data"]["failed_count"], 2);
+    assert!(response["data"]["outcomes"][1]["error"].as_str().unwrap().contains("run a repair sync"), "{}", response);
+    attribute_pushes(&tb, &mut pushes_seen).await;
+
+    // Repair relinks the device by its previous name and recreates the one whose name is unknown
+    let response = sync(json!({"entity_group_id": "group-1", "mode": "repair"})).await?;
+    assert_eq!(response["success"], true, "{}", response);
+    assert_eq!(outcomes(&response), pairs(&[("inv-ok", "verified"), ("inv-gone", "relinked"), ("inv-lost", "recreated")]));
+    assert_eq!(response["data"]["created_count"], 1);
+    assert_eq!(response["data"]["outcomes"][1]["thingsboard_id"], "tb-relinked");
+    assert_eq!(response["data"]["updated_device_ids"].as_array().unwrap().len(), 2, "{}", response);
+    let created: Vec<Value> = support::tb_requests(&tb)
+        .await
+        .into_iter()
+        .filter(|(line, _)| line.starts_with("POST /api/device?"))
+        .map(|(_, body)| body["name"].clone())
+        .collect();
+    assert_eq!(created, [json!("ACCV-P002-I03")]);
+    attribute_pushes(&tb, &mut pushes_seen).await;
+
+    {
+        let db = Database::new(&db_path).await?;
+        let tb_id = |device: Option<DeviceInstance>| device.and_then(|device| device.tb_device_id);
+        assert_eq!(tb_id(db.get_device("inv-gone").await?).as_deref(), Some("tb-relinked"));
+        assert_eq!(tb_id(db.get_device("inv-lost").await?).as_deref(), Some("tb-recreated"));
+    }
+
+    // With every device back, refreshing pushes each one's attributes under its ThingsBoard name
+    let response = sync(json!({"entity_group_id": "group-1", "mode": "refresh_attributes"})).await?;
+    assert_eq!(outcomes(&response), pairs(&[("inv-ok", "attributes_refreshed"), ("inv-gone", "attributes_refreshed"), ("inv-lost", "attributes_refreshed")]));
+    assert_eq!(response["data"]["failed_count"], 0);
+    assert_eq!(attribute_pushes(&tb, &mut pushes_seen).await, [
+        ("tb-ok".to_string(), json!("ACCV-P002-I01")),
+        ("tb-relinked".to_string(), json!("ACCV-P002-I02")),
+        ("tb-recreated".to_string(), json!("ACCV-P002-I03")),
+    ]);
+    Ok(())
+}
@@ -0,0 +1,305 @@
+mod support;
+
+use ava_device_logger::config::{AppConfig, NonGoodTelemetry, PvNaming, TelemetryForwardingConfig, ThingsBoardConfig};
+use ava_device_logger::database::{Database, DeviceInstance, DeviceTag, LogEntry, TagWritePolicy, TbChildDevice};
+use ava_device_logger::tb_rust_client::TbSession;
+use ava_device_logger::telemetry_forwarder::TelemetryForwarder;
+use chrono::{TimeZone, Utc};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+use wiremock::matchers::any;
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+/// ThingsBoard stand-in recording telemetry bodies and answering them with `status`
+struct MockTb {
+    status: AtomicU16,
+    telemetry: Mutex<Vec<(String, Value)>>,
+}
+
+async fn spawn_tb_server(mock: Arc<MockTb>) -> MockServer {
+    let server = support::thingsboard().await;
+    let respond = move |request: &Request| {
+        let status = mock.status.load(Ordering::SeqCst);
+        if status != 200 {
+            return ResponseTemplate::new(status).set_body_json(json!({"message": "Service unavailable"}));
+        }
+        let body = serde_json::from_slice(&request.body).unwrap_or(Value::Null);
+        mock.telemetry.lock().unwrap().push((request.url.path().to_string(), body));
+        ResponseTemplate::new(200)
+    };
+    Mock::given(any()).respond_with(respond).mount(&server).await;
+    server
+}
+
+fn config(base_url: &str) -> Arc<AppConfig> {
+    Arc::new(AppConfig {
+        thingsboard: Some(ThingsBoardConfig {
+            base_url: base_url.to_string(),
+            username: "tenant@example.com".to_string(),
+            password: "secret".to_string(),
+            tenant_label: None,
+            retry_max_attempts: 1,
+            retry_base_delay_ms: 0,
+            requests_per_second: 0.0,
+            sync_concurrency: 3,
+            pv_naming: PvNaming::Global,
+        }),
+        telemetry_forwarding: TelemetryForwardingConfig {
+            enabled: true,
+            batch_interval_seconds: 3600,
+            max_batch_size: 100,
+            non_good_values: NonGoodTelemetry::Skip,
+        },
+        ..Default::default()
+    })
+}
+
+fn entry(tag_name: &str, value: f64, quality: &str, second: u32) -> LogEntry {
+    LogEntry {
+        id: None,
+        device_id: "inv-1".to_string(),
+        tag_name: tag_name.to_string(),
+        value,
+        quality: quality.to_string(),
+        timestamp: Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, second).unwrap(),
+        unit: None,
+    }
+}
+
+fn poll() -> Vec<LogEntry> {
+    vec![
+        entry("active_power", 12.5, "Good", 0),
+        entry("voltage", 230.0, "Good", 0),
+        entry("active_power", 13.0, "Good", 5),
+        entry("irradiance", 800.0, "muted", 5),
+        entry("frequency", 0.0, "Bad", 5),
+    ]
+}
+
+fn inverter() -> DeviceInstance {
+    DeviceInstance {
+        id: "inv-1".to_string(),
+        name: "Inverter 1".to_string(),
+        serial_no: None,
+        model_id: None,
+        enabled: false,
+        polling_interval_ms: 1000,
+        timeout_ms: 5000,
+        retry_count: 3,
+        protocol_config: "{}".to_string(),
+        tb_device_id: Some("tb-inv-1".to_string()),
+        tb_group_id: None,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    }
+}
+
+fn tag(name: &str, address: u16, description: &str) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: "inv-1".to_string(),
+        name: name.to_string(),
+        address,
+        size: 1,
+        data_type: "uint16".to_string(),
+        description: Some(description.to_string()),
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: None,
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy: TagWritePolicy::Disabled,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+fn child(tb_device_id: &str, name: &str, mppt_number: u32, input_number: Option<u32>) -> TbChildDevice {
+    TbChildDevice {
+        device_id: "inv-1".to_string(),
+        tb_device_id: tb_device_id.to_string(),
+        name: name.to_string(),
+        device_type: if input_number.is_some() { "String" } else { "Mppt" }.to_string(),
+        mppt_number,
+        input_number,
+    }
+}
+
+fn temp_db_path() -> String {
+    std::env::temp_dir()
+        .join(format!("telemetry-forwarding-{}.db", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string()
+}
+
+#[tokio::test]
+async fn test_good_values_are_pushed_as_timestamped_batches() -> Result<(), Box<dyn Error>> {
+    let mock = Arc::new(MockTb { status: AtomicU16::new(200), telemetry: Mutex::new(Vec::new()) });
+    let tb = spawn_tb_server(mock.clone()).await;
+    let config = config(&tb.uri());
+    let db = Arc::new(Database::new(&temp_db_path()).await?);
+    let forwarder = Arc::new(TelemetryForwarder::new(db.clone(), config, Arc::new(TbSession::new())));
+
+    forwarder.enqueue("tb-inv-1", &poll()).await?;
+    assert_eq!(db.get_telemetry_backlog("inv-1").await?.pending, 3);
+
+    assert_eq!(forwarder.flush_device("inv-1").await?, 3);
+    assert_eq!(db.get_telemetry_backlog("inv-1").await?.pending, 0);
+
+    let telemetry = mock.telemetry.lock().unwrap().clone();
+    assert_eq!(telemetry.len(), 1);
+    let (path, body) = &telemetry[0];
+    assert_eq!(path, "/api/plugins/telemetry/DEVICE/tb-inv-1/timeseries/ANY");
+    let points = body.as_array().expect("timestamped points");
+    assert_eq!(points.len(), 2);
+    let first_ts = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap().timestamp_millis();
+    assert_eq!(points[0]["ts"], first_ts);
+    assert_eq!(points[0]["values"]["active_power"], 12.5);
+    assert_eq!(points[0]["values"]["voltage"], 230.0);
+    assert_eq!(points[1]["ts"], first_ts + 5000);
+    assert_eq!(points[1]["values"].as_object().unwrap().len(), 1);
+
+    // Nothing left to send
+    assert_eq!(forwarder.flush_device("inv-1").await?, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_values_that_are_not_good_can_be_annotated() -> Result<(), Box<dyn Error>> {
+    let mock = Arc::new(MockTb { status: AtomicU16::new(200), telemetry: Mutex::new(Vec::new()) });
+    let tb = spawn_tb_server(mock.clone()).await;
+    let config = config(&tb.uri());
+    let config = Arc::new(AppConfig {
+        telemetry_forwarding: TelemetryForwardingConfig { non_good_values: NonGoodTelemetry::Annotate, ..config.telemetry_forwarding.clone() },
+        ..(*config).clone()
+    });
+    let db = Arc::new(Database::new(&temp_db_path()).await?);
+    let forwarder = Arc::new(TelemetryForwarder::new(db.clone(), config, Arc::new(TbSession::new())));
+
+    let mut entries = poll();
+    entries.push(entry("voltage", 231.0, "Uncertain", 5));
+    forwarder.enqueue("tb-inv-1", &entries).await?;
+    assert_eq!(forwarder.flush_device("inv-1").await?, 5);
+
+    // Muted tags are still left out, and a failed read only sends its quality
+    let telemetry = mock.telemetry.lock().unwrap().clone();
+    let points = telemetry[0].1.as_array().expect("timestamped points");
+    assert_eq!(points[0]["values"], serde_json::json!({"active_power": 12.5, "voltage": 230.0}));
+    assert_eq!(
+        points[1]["values"],
+        serde_json::json!({"active_power": 13.0, "voltage": 231.0, "voltage_quality": "Uncertain", "frequency_quality": "Bad"})
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_failed_pushes_stay_queued_across_restarts() -> Result<(), Box<dyn Error>> {
+    let mock = Arc::new(MockTb { status: AtomicU16::new(503), telemetry: Mutex::new(Vec::new()) });
+    let tb = spawn_tb_server(mock.clone()).await;
+    let config = config(&tb.uri());
+    let db_path = temp_db_path();
+
+    {
+        let db = Arc::new(Database::new(&db_path).await?);
+        let forwarder = Arc::new(TelemetryForwarder::new(db.clone(), config.clone(), Arc::new(TbSession::new())));
+        forwarder.enqueue("tb-inv-1", &poll()).await?;
+        assert!(forwarder.flush_device("inv-1").await.is_err());
+
+        let backlog = db.get_telemetry_backlog("inv-1").await?;
+        assert_eq!(backlog.pending, 3);
+        assert_eq!(backlog.oldest_timestamp, Some(Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap()));
+        assert!(backlog.last_error.expect("failure recorded").contains("Service unavailable"));
+        assert_eq!(db.get_telemetry_batch("inv-1", 10).await?[0].attempts, 1);
+    }
+
+    // A fresh process picks the queue up once ThingsBoard is back
+    mock.status.store(200, Ordering::SeqCst);
+    let db = Arc::new(Database::new(&db_path).await?);
+    assert_eq!(db.get_telemetry_backlog_counts().await?.get("inv-1"), Some(&3));
+    let forwarder = Arc::new(TelemetryForwarder::new(db.clone(), config, Arc::new(TbSession::new())));
+    assert_eq!(forwarder.flush_device("inv-1").await?, 3);
+    assert!(db.get_telemetry_backlog_counts().await?.is_empty());
+    assert_eq!(mock.telemetry.lock().unwrap().len(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_forwarding_switch_defaults_on_per_device() -> Result<(), Box<dyn Error>> {
+    let db = Database::new(&temp_db_path()).await?;
+    db.create_device(&inverter()).await?;
+
+    assert_eq!(db.get_device_telemetry_forwarding("inv-1").await?, Some(true));
+    assert!(db.set_device_telemetry_forwarding("inv-1", false).await?);
+    assert_eq!(db.get_device_telemetry_forwarding("inv-1").await?, Some(false));
+
+    assert_eq!(db.get_device_telemetry_forwarding("missing").await?, None);
+    assert!(!db.set_device_telemetry_forwarding("missing", true).await?);
+
+    // Forwarding needs the config switch as well as a ThingsBoard server
+    let disabled = Arc::new(AppConfig {
+        telemetry_forwarding: TelemetryForwardingConfig { enabled: true, ..Default::default() },
+        ..Default::default()
+    });
+    assert!(!TelemetryForwarder::new(Arc::new(db), disabled, Arc::new(TbSession::new())).is_enabled());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mppt_and_string_values_go_to_recorded_child_devices() -> Result<(), Box<dyn Error>> {
+    let mock = Arc::new(MockTb { status: AtomicU16::new(200), telemetry: Mutex::new(Vec::new()) });
+    let tb = spawn_tb_server(mock.clone()).await;
+    let config = config(&tb.uri());
+    let db = Arc::new(Database::new(&temp_db_path()).await?);
+    db.create_device(&inverter()).await?;
+    db.create_device_tags("inv-1", &[
+        tag("active_power", 5000, "Inverter (SG250HX)"),
+        tag("Vmppt 1", 5010, "MPPT - MPPT 1 (SG250HX)"),
+        tag("Udc 1", 5020, "String - MPPT 1 - Input 1 (SG250HX)"),
+        tag("Udc 2", 5021, "String - MPPT 2 - Input 2 (SG250HX)"),
+    ]).await?;
+    let forwarder = Arc::new(TelemetryForwarder::new(db.clone(), config, Arc::new(TbSession::new())));
+    let poll = || vec![entry("active_power", 12.5, "Good", 0), entry("Vmppt 1", 610.0, "Good", 0), entry("Udc 1", 605.0, "Good", 0), entry("Udc 2", 600.0, "Good", 0)];
+
+    // Without recorded children everything goes to the inverter
+    forwarder.enqueue("tb-inv-1", &poll()).await?;
+    assert_eq!(forwarder.flush_device("inv-1").await?, 4);
+    let paths: Vec<String> = mock.telemetry.lock().unwrap().drain(..).map(|(path, _)| path).collect();
+    assert_eq!(paths, ["/api/plugins/telemetry/DEVICE/tb-inv-1/timeseries/ANY"]);
+
+    // Recording replaces what an earlier sync left behind
+    db.save_tb_child_devices("inv-1", &[child("tb-old", "ACCV-P002-I01-M09", 9, None)]).await?;
+    db.save_tb_child_devices("inv-1", &[
+        child("tb-pv01", "ACCV-P002-I01-M01-PV01", 1, Some(1)),
+        child("tb-m01", "ACCV-P002-I01-M01", 1, None),
+    ]).await?;
+    let children = db.get_tb_child_devices("inv-1").await?;
+    assert_eq!(children, [child("tb-m01", "ACCV-P002-I01-M01", 1, None), child("tb-pv01", "ACCV-P002-I01-M01-PV01", 1, Some(1))]);
+
+    // Tags of a recorded MPPT or string go to its device; a string without a recorded device stays with the inverter
+    forwarder.enqueue("tb-inv-1", &poll()).await?;
+    assert_eq!(forwarder.flush_device("inv-1").await?, 4);
+    let mut telemetry = mock.telemetry.lock().unwrap().clone();
+    telemetry.sort_by(|a, b| a.0.cmp(&b.0));
+    let keys: Vec<(String, Vec<String>)> = telemetry
+        .iter()
+        .map(|(path, body)| (path.clone(), body[0]["values"].as_object().unwrap().keys().cloned().collect()))
+        .collect();
+    assert_eq!(keys, [
+        ("/api/plugins/telemetry/DEVICE/tb-inv-1/timeseries/ANY".to_string(), vec!["Udc 2".to_string(), "active_power".to_string()]),
+        ("/api/plugins/telemetry/DEVICE/tb-m01/timeseries/ANY".to_string(), vec!["Vmppt 1".to_string()]),
+        ("/api/plugins/telemetry/DEVICE/tb-pv01/timeseries/ANY".to_string(), vec!["Udc 1".to_string()]),
+    ]);
+
+    // Deleting the inverter forgets its children
+    db.delete_device("inv-1").await?;
+    assert!(db.get_tb_child_devices("inv-1").await?.is_empty());
+    assert_eq!(db.delete_tb_child_devices("inv-1").await?, 0);
+    Ok(())
+}
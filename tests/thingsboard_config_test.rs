@@ -0,0 +1,104 @@
+mod support;
+
+use ava_device_logger::config::{AppConfig, PvNaming, ThingsBoardConfig};
+use ava_device_logger::tb_rust_client::{TbError, ThingsBoardClient};
+use serde_json::{json, Value};
+use std::error::Error;
+use support::Logger;
+
+#[test]
+fn test_client_requires_thingsboard_section() {
+    let mut config = AppConfig::default();
+    assert!(matches!(ThingsBoardClient::from_config(&config), Err(TbError::NotConfigured)));
+
+    config.thingsboard = Some(ThingsBoardConfig {
+        base_url: "https://tb.example.com/".to_string(),
+        username: "tenant@example.com".to_string(),
+        password: "s3cret".to_string(),
+        tenant_label: None,
+        retry_max_attempts: 3,
+        retry_base_delay_ms: 500,
+        requests_per_second: 0.0,
+        sync_concurrency: 3,
+        pv_naming: PvNaming::Global,
+    });
+    let client = ThingsBoardClient::from_config(&config).expect("configured client");
+    let error = TbError::Auth("bad credentials for tenant@example.com / s3cret".to_string());
+    assert!(!client.sanitize_error(&error).contains("s3cret"));
+}
+
+#[tokio::test]
+async fn test_password_is_write_only_through_config_api() -> Result<(), Box<dyn Error>> {
+    let section = r#"
+[thingsboard]
+base_url = "http://127.0.0.1:9"
+username = "tenant@example.com"
+password = "s3cret"
+tenant_label = "Plant A"
+"#;
+    let logger = Logger::start(section).await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let body: Value = client.get(format!("{}/api/config", base_url)).bearer_auth(token).send().await?.json().await?;
+    let mut config = body["data"].clone();
+    assert_eq!(config["thingsboard"]["username"], "tenant@example.com");
+    assert_eq!(config["thingsboard"]["password"], "");
+    assert!(!body.to_string().contains("s3cret"));
+
+    // Saving the config back unchanged keeps the stored password
+    config["thingsboard"]["tenant_label"] = json!("Plant B");
+    let body: Value = client.post(format!("{}/api/config", base_url)).bearer_auth(token).json(&config).send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let saved = std::fs::read_to_string(logger.work_dir.join("config.toml"))?;
+    assert!(saved.contains("s3cret"));
+    assert!(saved.contains("Plant B"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_thingsboard_handlers_report_missing_section() -> Result<(), Box<dyn Error>> {
+    let logger = Logger::start("").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let body: Value = client
+        .get(format!("{}/api/thingsboard/entity-groups", base_url))
+        .bearer_auth(token)
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["success"], false);
+    assert!(body["error"].as_str().unwrap_or_default().contains("not configured"), "{}", body);
+
+    // The catalog runs as a job, which fails with the same error
+    let body: Value = client
+        .post(format!("{}/api/generate-device-catalog", base_url))
+        .bearer_auth(token)
+        .json(&json!({"entity_group_id": "group-1", "output_dir": "catalogs"}))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let job_url = format!("{}/api/jobs/{}", base_url, body["data"]["id"].as_str().unwrap_or_default());
+    let mut job = Value::Null;
+    for _ in 0..50 {
+        job = client.get(&job_url).bearer_auth(token).send().await?.json().await?;
+        if job["data"]["state"] == "failed" {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert_eq!(job["data"]["state"], "failed", "{}", job);
+    assert!(job["data"]["error"].as_str().unwrap_or_default().contains("not configured"), "{}", job);
+
+    let response = client
+        .get(format!("{}/api/device-catalog/group-1/download", base_url))
+        .bearer_auth(token)
+        .send()
+        .await?;
+    assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+    let body: Value = response.json().await?;
+    assert!(body["error"].as_str().unwrap_or_default().contains("not configured"), "{}", body);
+    Ok(())
+}
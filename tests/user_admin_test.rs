@@ -0,0 +1,86 @@
+mod support;
+
+use serde_json::{json, Value};
+use std::error::Error;
+use support::Logger;
+
+#[tokio::test]
+async fn test_admins_manage_users() -> Result<(), Box<dyn Error>> {
+    let logger = Logger::start("").await?;
+    let (client, base_url, admin) = (&logger.client, &logger.base_url, &logger.token);
+    let installer = logger.login("installer", "installer123").await?;
+
+    // Listed without password hashes, and only to admins
+    let body: Value = client.get(format!("{}/api/users", base_url)).bearer_auth(admin).send().await?.json().await?;
+    let usernames: Vec<&str> = body["data"].as_array().expect("users").iter().map(|user| user["username"].as_str().unwrap()).collect();
+    assert_eq!(usernames, ["admin", "installer"]);
+    assert!(!body.to_string().contains("password_hash") && !body.to_string().contains("$argon2"), "{}", body);
+    assert_eq!(client.get(format!("{}/api/users", base_url)).bearer_auth(&installer).send().await?.status(), 403);
+    let response = client.post(format!("{}/api/users", base_url)).bearer_auth(&installer).json(&json!({"username": "x", "password": "long enough", "role": "admin"})).send().await?;
+    assert_eq!(response.status(), 403);
+
+    let create = |request: Value| {
+        let request = client.post(format!("{}/api/users", base_url)).bearer_auth(admin).json(&request);
+        async move { request.send().await?.json::<Value>().await }
+    };
+    let body = create(json!({"username": "field-tech", "password": "s3cret-pass", "role": "installer"})).await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!((&body["data"]["role"], &body["data"]["disabled"]), (&json!("installer"), &json!(false)));
+    let tech_id = body["data"]["id"].as_i64().unwrap();
+
+    let body = create(json!({"username": "field-tech", "password": "another-pass", "role": "installer"})).await?;
+    assert_eq!(body["field_errors"], json!([{"field": "username", "message": "'field-tech' is already taken"}]));
+    let body = create(json!({"username": " ", "password": "short", "role": "owner"})).await?;
+    assert_eq!(body["field_errors"], json!([
+        {"field": "username", "message": "must not be empty"},
+        {"field": "password", "message": "must be at least 8 characters"},
+        {"field": "role", "message": "must be one of admin, installer"},
+    ]));
+
+    // Disabling signs the user out and keeps them out
+    let tech = logger.login("field-tech", "s3cret-pass").await?;
+    assert_eq!(client.get(format!("{}/api/sessions", base_url)).bearer_auth(&tech).send().await?.status(), 200);
+    let update = |user_id: i64, changes: Value| {
+        let request = client.put(format!("{}/api/users/{}", base_url, user_id)).bearer_auth(admin).json(&changes);
+        async move { request.send().await?.json::<Value>().await }
+    };
+    let body = update(tech_id, json!({"disabled": true})).await?;
+    assert_eq!(body["data"]["disabled"], true, "{}", body);
+    assert_eq!(client.get(format!("{}/api/sessions", base_url)).bearer_auth(&tech).send().await?.status(), 401);
+    assert!(logger.login("field-tech", "s3cret-pass").await.is_err());
+    let body = update(tech_id, json!({"disabled": false})).await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let tech = logger.login("field-tech", "s3cret-pass").await?;
+
+    // Deleting signs the user out too
+    let body: Value = client.delete(format!("{}/api/users/{}", base_url, tech_id)).bearer_auth(admin).send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!(client.get(format!("{}/api/sessions", base_url)).bearer_auth(&tech).send().await?.status(), 401);
+    assert_eq!(client.delete(format!("{}/api/users/{}", base_url, tech_id)).bearer_auth(admin).send().await?.status(), 404);
+
+    // The only admin can't be demoted, disabled or deleted
+    let conn = rusqlite::Connection::open(logger.work_dir.join("data.db"))?;
+    let admin_id: i64 = conn.query_row("SELECT id FROM local_users WHERE username = 'admin'", [], |row| row.get(0))?;
+    for changes in [json!({"role": "installer"}), json!({"disabled": true})] {
+        let body = update(admin_id, changes).await?;
+        assert_eq!(body["success"], false);
+        assert!(body["error"].as_str().unwrap().contains("last enabled admin"), "{}", body);
+    }
+    let body: Value = client.delete(format!("{}/api/users/{}", base_url, admin_id)).bearer_auth(admin).send().await?.json().await?;
+    assert!(body["error"].as_str().unwrap().contains("last enabled admin"), "{}", body);
+
+    // A disabled admin doesn't count as the other admin
+    let body = create(json!({"username": "backup-admin", "password": "backup-pass", "role": "admin"})).await?;
+    let backup_id = body["data"]["id"].as_i64().unwrap();
+    update(backup_id, json!({"disabled": true})).await?;
+    let body = update(admin_id, json!({"role": "installer"})).await?;
+    assert_eq!(body["success"], false, "{}", body);
+
+    // Once there is another enabled admin, the first can step down
+    update(backup_id, json!({"disabled": false})).await?;
+    let body = update(admin_id, json!({"role": "installer"})).await?;
+    assert_eq!(body["data"]["role"], "installer", "{}", body);
+    let role: String = conn.query_row("SELECT role FROM local_users WHERE id = ?1", [admin_id], |row| row.get(0))?;
+    assert_eq!(role, "installer");
+    Ok(())
+}
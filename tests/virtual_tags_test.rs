@@ -0,0 +1,289 @@
+mod support;
+
+use ava_device_logger::database::{Database, DeviceInstance, DeviceTag, LogEntry, NewVirtualTag, ScheduleGroup, TagWritePolicy};
+use ava_device_logger::live_values::LastValueCache;
+use ava_device_logger::virtual_tags::{find_cycle, DeviceGroups, Expr, Selector, Source, VirtualTagEngine};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde_json::{json, Value};
+use std::error::Error;
+use support::{Logger, ModbusDevice};
+
+fn virtual_tag(owner: &str, name: &str, expression: &str) -> NewVirtualTag {
+    let is_device = owner.starts_with("inv");
+    NewVirtualTag {
+        name: name.to_string(),
+        expression: expression.to_string(),
+        device_id: is_device.then(|| owner.to_string()),
+        group_id: (!is_device).then(|| owner.to_string()),
+        schedule_group_id: None,
+        unit: None,
+        enabled: true,
+    }
+}
+
+fn groups(devices: &[(&str, &str)]) -> DeviceGroups {
+    devices.iter().map(|(device_id, group)| (device_id.to_string(), Some(group.to_string()))).collect()
+}
+
+#[test]
+fn test_expressions_are_parsed_and_evaluated() {
+    let values = |selector: &Selector| match (&selector.source, selector.tag_name.as_str()) {
+        (Source::Device(device_id), "Pac") if device_id == "inv1" => vec![1500.0],
+        (Source::Group(group), "Pac") if group == "*" => vec![1500.0, 2500.0, 1000.0],
+        (Source::Group(group), "Pac") if group == "north" => vec![1500.0, 2500.0],
+        _ => Vec::new(),
+    };
+    let evaluate = |text: &str| Expr::parse(text).unwrap().evaluate(&values);
+
+    assert_eq!(evaluate("sum(group('*').tag('Pac')) / 1000"), Some(5.0));
+    assert_eq!(evaluate("max(group(\"north\").tag(\"Pac\"), 2000) - min(group('*').tag('Pac'))"), Some(1500.0));
+    assert_eq!(evaluate("-(2 + 3) * 4 - 1.5e1 / 3"), Some(-25.0));
+    assert_eq!(evaluate("device('inv1').tag('Pac') / sum(group('*').tag('Pac')) * 100"), Some(30.0));
+    // Inputs without a value and divisions by zero leave the tag uncomputed
+    assert_eq!(evaluate("device('inv2').tag('Pac') + 1"), None);
+    assert_eq!(evaluate("sum(group('south').tag('Pac'))"), None);
+    assert_eq!(evaluate("device('inv1').tag('Pac') / (1 - 1)"), None);
+
+    let parsed = Expr::parse("sum(device('inv1').tag('Pac'), group('*').tag('Pac'))").unwrap();
+    assert_eq!(parsed.selectors().len(), 2);
+}
+
+#[test]
+fn test_parse_errors_say_what_is_wrong_and_where() {
+    let error = |text: &str| Expr::parse(text).unwrap_err().to_string();
+
+    assert_eq!(error("1 + "), "expected a value but the expression ended at character 5");
+    assert_eq!(error("sum(group('*').tag('Pac')"), "expected ')' but the expression ended at character 26");
+    assert_eq!(error("avg(1, 2)"), "unknown name 'avg', expected sum, min, max, device or group at character 1");
+    assert_eq!(error("device('inv1').value('Pac')"), "expected .tag('name') at character 16");
+    assert_eq!(error("device(inv1).tag('Pac')"), "expected a quoted name at character 8");
+    assert_eq!(error("device('inv1"), "missing closing quote at character 8");
+    assert_eq!(error("2 * 3)"), "unexpected ')' at character 6");
+    assert_eq!(error("sum()"), "sum needs at least one value at character 5");
+    assert_eq!(
+        error("group('*').tag('Pac') * 2"),
+        "group('*').tag('Pac') has a value per device, so it can only be an argument of sum, min or max"
+    );
+}
+
+#[test]
+fn test_circular_references_are_found() {
+    let tags = [
+        virtual_tag("plant", "total", "sum(group('north').tag('share'))"),
+        virtual_tag("inv1", "share", "device('inv1').tag('Pac') / device('plant').tag('total_kw')"),
+        virtual_tag("plant", "total_kw", "device('plant').tag('total') / 1000"),
+    ];
+    assert_eq!(
+        find_cycle(&tags, &groups(&[("inv1", "north")])),
+        Some(vec!["plant/total".to_string(), "inv1/share".to_string(), "plant/total_kw".to_string(), "plant/total".to_string()])
+    );
+    // inv1 isn't in the group the total adds up, so there is no loop
+    assert_eq!(find_cycle(&tags, &groups(&[("inv1", "south")])), None);
+
+    let reads_itself = [virtual_tag("inv1", "Pac_total", "sum(group('*').tag('Pac_total'))")];
+    assert_eq!(find_cycle(&reads_itself, &groups(&[("inv1", "north")])), Some(vec!["inv1/Pac_total".to_string(), "inv1/Pac_total".to_string()]));
+}
+
+fn device(id: &str, tb_group_id: Option<&str>, modbus_port: u16) -> DeviceInstance {
+    DeviceInstance {
+        id: id.to_string(),
+        name: id.to_string(),
+        serial_no: None,
+        model_id: None,
+        enabled: true,
+        polling_interval_ms: 200,
+        timeout_ms: 500,
+        retry_count: 3,
+        protocol_config: json!({"type": "modbus_tcp", "host": "127.0.0.1", "port": modbus_port, "slave_id": 1}).to_string(),
+        tb_device_id: None,
+        tb_group_id: tb_group_id.map(str::to_string),
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        strict_types: false,
+    }
+}
+
+fn entry(device_id: &str, tag_name: &str, value: f64, quality: &str, at: DateTime<Utc>) -> LogEntry {
+    LogEntry {
+        id: None,
+        device_id: device_id.to_string(),
+        tag_name: tag_name.to_string(),
+        value,
+        quality: quality.to_string(),
+        timestamp: at,
+        unit: Some("W".to_string()),
+    }
+}
+
+fn computed(entries: &[LogEntry]) -> Vec<(String, String, f64)> {
+    assert!(entries.iter().all(|entry| entry.quality == "computed"));
+    entries.iter().map(|entry| (entry.device_id.clone(), entry.tag_name.clone(), entry.value)).collect()
+}
+
+#[tokio::test]
+async fn test_tags_are_computed_on_reads_and_schedules() -> Result<(), Box<dyn Error>> {
+    let db_path = std::env::temp_dir().join(format!("virtual-tags-{}.db", uuid::Uuid::new_v4()));
+    let db = Database::new(&db_path.to_string_lossy()).await?;
+    for (id, group) in [("inv1", "north"), ("inv2", "north"), ("inv3", "south")] {
+        db.create_device(&device(id, Some(group), 502)).await?;
+    }
+    db.create_schedule_group(&ScheduleGroup {
+        id: "hourly".to_string(),
+        name: "Hourly".to_string(),
+        polling_interval_ms: 3_600_000,
+        description: None,
+        enabled: true,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    }).await?;
+
+    // Created before the total it reads, so they are computed in dependency order, not id order
+    db.create_virtual_tag(&virtual_tag("inv1", "share", "device('inv1').tag('Pac') / device('plant').tag('total') * 100")).await?;
+    db.create_virtual_tag(&NewVirtualTag { unit: Some("W".to_string()), ..virtual_tag("plant", "total", "sum(group('*').tag('Pac'))") }).await?;
+    db.create_virtual_tag(&virtual_tag("plant", "north_max", "max(group('north').tag('Pac'))")).await?;
+    db.create_virtual_tag(&virtual_tag("plant", "broken", "device('inv1').tag('Pac') / device('inv3').tag('Zero')")).await?;
+    db.create_virtual_tag(&NewVirtualTag { schedule_group_id: Some("hourly".to_string()), ..virtual_tag("plant", "doubled", "device('plant').tag('total') * 2") }).await?;
+    db.create_virtual_tag(&NewVirtualTag { enabled: false, ..virtual_tag("plant", "off", "1") }).await?;
+
+    let engine = VirtualTagEngine::new();
+    engine.load(&db).await?;
+    for (id, group) in [("inv1", "north"), ("inv2", "north"), ("inv3", "south")] {
+        engine.watch_device(id, Some(group.to_string()));
+    }
+    let live_values = LastValueCache::new();
+    let now = Utc.with_ymd_and_hms(2026, 6, 1, 12, 0, 0).unwrap();
+
+    // Nothing read yet
+    assert!(engine.compute(&live_values, now).is_empty());
+
+    let poll = [
+        entry("inv1", "Pac", 1500.0, "Good", now),
+        entry("inv2", "Pac", 2500.0, "Good", now),
+        entry("inv3", "Pac", 1000.0, "Good", now),
+        entry("inv3", "Zero", 0.0, "Good", now),
+    ];
+    live_values.update(&poll);
+    engine.inputs_updated(&poll);
+    let at = now + Duration::seconds(1);
+    let entries = engine.compute(&live_values, at);
+    assert_eq!(computed(&entries), [
+        ("plant".to_string(), "total".to_string(), 5000.0),
+        ("inv1".to_string(), "share".to_string(), 30.0),
+        ("plant".to_string(), "north_max".to_string(), 2500.0),
+    ]);
+    assert_eq!((entries[0].unit.as_deref(), entries[0].timestamp), (Some("W"), at));
+    assert_eq!(live_values.fresh_value("plant", "total"), Some(5000.0));
+
+    // Only the scheduled tag is due an hour after it was last computed
+    assert!(engine.compute(&live_values, now + Duration::minutes(30)).is_empty());
+    assert_eq!(computed(&engine.compute(&live_values, now + Duration::minutes(61))), [("plant".to_string(), "doubled".to_string(), 10000.0)]);
+
+    // Failed reads and stale values are left out of group selectors. A failed read alone
+    // doesn't trigger a tag, so the north maximum isn't computed again.
+    let poll = [entry("inv2", "Pac", 0.0, "Bad", now), entry("inv3", "Pac", 1200.0, "Good", now)];
+    live_values.update(&poll);
+    live_values.mark_stale("inv3", None, "Device stopped");
+    engine.inputs_updated(&poll);
+    assert_eq!(computed(&engine.compute(&live_values, now + Duration::minutes(62))), [
+        ("plant".to_string(), "total".to_string(), 1500.0),
+        ("inv1".to_string(), "share".to_string(), 100.0),
+    ]);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+fn tag(device_id: &str, address: u16) -> DeviceTag {
+    DeviceTag {
+        id: None,
+        device_id: device_id.to_string(),
+        name: "Pac".to_string(),
+        address,
+        size: 1,
+        data_type: "uint16".to_string(),
+        description: None,
+        scaling_multiplier: 1.0,
+        scaling_offset: 0.0,
+        unit: Some("W".to_string()),
+        read_only: true,
+        enabled: true,
+        schedule_group_id: None,
+        agg_to_field: None,
+        write_policy: TagWritePolicy::Disabled,
+        byte_order: None,
+        deadband_absolute: None,
+        deadband_percent: None,
+        register_type: None,
+    }
+}
+
+#[tokio::test]
+async fn test_virtual_tags_are_managed_and_logged_through_the_api() -> Result<(), Box<dyn Error>> {
+    let slave = ModbusDevice::start([(100, 100), (200, 200)]).await?;
+    let modbus_port = slave.port();
+    let work_dir = support::work_dir("virtual-tags-api")?;
+
+    let db = Database::new(&work_dir.join("data.db").to_string_lossy()).await?;
+    db.create_device(&device("inv1", None, modbus_port)).await?;
+    db.create_device_tags("inv1", &[tag("inv1", 100)]).await?;
+    db.create_device(&device("inv2", None, modbus_port)).await?;
+    db.create_device_tags("inv2", &[tag("inv2", 200)]).await?;
+    drop(db);
+
+    let logger = Logger::start_in(work_dir, "").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let body: Value = client.post(format!("{}/api/virtual-tags", base_url)).bearer_auth(token)
+        .json(&json!({"name": "total", "expression": "sum(group('*').tag('Pac'))", "group_id": "plant", "unit": "W"}))
+        .send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let total_id = body["data"]["id"].as_i64().unwrap();
+    let body: Value = client.post(format!("{}/api/virtual-tags", base_url)).bearer_auth(token)
+        .json(&json!({"name": "total_kw", "expression": "device('plant').tag('total') / 1000", "group_id": "plant", "unit": "kW"}))
+        .send().await?.json().await?;
+    assert_eq!(body["success"], true, "{}", body);
+
+    // Invalid tags are rejected naming the field, a loop with the tags around it
+    let body: Value = client.post(format!("{}/api/virtual-tags", base_url)).bearer_auth(token)
+        .json(&json!({"name": "Pac", "expression": "max(", "device_id": "inv1"}))
+        .send().await?.json().await?;
+    assert_eq!(body["success"], false);
+    assert_eq!(body["field_errors"], json!([
+        {"field": "name", "message": "device 'inv1' already has a tag 'Pac'"},
+        {"field": "expression", "message": "expected a value but the expression ended at character 5"},
+    ]));
+    let body: Value = client.put(format!("{}/api/virtual-tags/{}", base_url, total_id)).bearer_auth(token)
+        .json(&json!({"name": "total", "expression": "device('plant').tag('total_kw') * 1000", "group_id": "plant"}))
+        .send().await?.json().await?;
+    assert_eq!(body["field_errors"], json!([
+        {"field": "expression", "message": "circular reference: plant/total_kw -> plant/total -> plant/total_kw"},
+    ]));
+    let response = client.put(format!("{}/api/virtual-tags/9999", base_url)).bearer_auth(token)
+        .json(&json!({"name": "x", "expression": "1", "group_id": "plant"}))
+        .send().await?;
+    assert_eq!(response.status(), 404);
+
+    // Registers hold their address, so the inverters read 100 and 200
+    let mut plant = Value::Null;
+    for _ in 0..50 {
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        let body: Value = client.get(format!("{}/api/values", base_url)).bearer_auth(token).send().await?.json().await?;
+        plant = body["data"].as_array().unwrap().iter().find(|device| device["device_id"] == "plant").cloned().unwrap_or(Value::Null);
+        if plant["values"].as_array().is_some_and(|values| values.len() == 2) {
+            break;
+        }
+    }
+    let values: Vec<(&str, f64, &str, &str)> = plant["values"].as_array().expect("plant values").iter()
+        .map(|value| (value["tag_name"].as_str().unwrap(), value["value"].as_f64().unwrap(), value["unit"].as_str().unwrap(), value["quality"].as_str().unwrap()))
+        .collect();
+    assert_eq!(values, [("total", 300.0, "W", "computed"), ("total_kw", 0.3, "kW", "computed")]);
+
+    let body: Value = client.get(format!("{}/api/logs/plant", base_url)).bearer_auth(token).send().await?.json().await?;
+    assert!(body["data"]["entries"].as_array().unwrap().iter().any(|entry| entry["tag_name"] == "total" && entry["quality"] == "computed"), "{}", body);
+
+    let response = client.delete(format!("{}/api/virtual-tags/{}", base_url, total_id)).bearer_auth(token).send().await?;
+    assert_eq!(response.status(), 200);
+    let body: Value = client.get(format!("{}/api/virtual-tags", base_url)).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(body["data"].as_array().unwrap().len(), 1);
+    Ok(())
+}
@@ -0,0 +1,199 @@
+mod support;
+
+use ava_device_logger::config::{WebhookConfig, WebhookEvent};
+use ava_device_logger::database::{Database, WebhookDeliveryStatus};
+use ava_device_logger::webhooks::{signature, RetryPolicy, WebhookNotifier, SIGNATURE_HEADER};
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::post;
+use axum::Router;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use support::Logger;
+
+#[test]
+fn test_signature_is_hmac_sha256_of_the_body() {
+    // RFC 4231 test case 2
+    assert_eq!(
+        signature("Jefe", b"what do ya want for nothing?"),
+        "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+}
+
+fn hook(id: &str, url: String, events: Vec<WebhookEvent>, enabled: bool) -> WebhookConfig {
+    WebhookConfig { id: id.to_string(), url, events, secret: format!("{}-secret", id), enabled }
+}
+
+/// Requests received, as (path, signature header, body)
+type Received = Arc<Mutex<Vec<(String, String, String)>>>;
+
+/// `/flaky` fails twice before accepting, `/down` always fails
+async fn start_receiver() -> Result<(String, Received), Box<dyn Error>> {
+    async fn receive(State(received): State<Received>, Path(path): Path<String>, headers: HeaderMap, body: String) -> StatusCode {
+        let signature = headers.get(SIGNATURE_HEADER).and_then(|value| value.to_str().ok()).unwrap_or_default().to_string();
+        let mut received = received.lock().unwrap();
+        received.push((path.clone(), signature, body));
+        let attempts = received.iter().filter(|(received_path, _, _)| *received_path == path).count();
+        match path.as_str() {
+            "flaky" if attempts > 2 => StatusCode::OK,
+            _ => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    let received = Received::default();
+    let app = Router::new().route("/:path", post(receive)).with_state(received.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let base_url = format!("http://{}", listener.local_addr()?);
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
+    Ok((base_url, received))
+}
+
+#[tokio::test]
+async fn test_deliveries_are_signed_retried_and_logged() -> Result<(), Box<dyn Error>> {
+    let db_path = std::env::temp_dir().join(format!("webhooks-{}.db", uuid::Uuid::new_v4()));
+    let db = Arc::new(Database::new(&db_path.to_string_lossy()).await?);
+    let (base_url, received) = start_receiver().await?;
+
+    let notifier = Arc::new(
+        WebhookNotifier::new(db.clone(), vec![
+            hook("flaky", format!("{}/flaky", base_url), vec![WebhookEvent::AlarmRaised], true),
+            hook("down", format!("{}/down", base_url), vec![WebhookEvent::AlarmRaised, WebhookEvent::DeviceOffline], true),
+            hook("disabled", format!("{}/disabled", base_url), vec![WebhookEvent::AlarmRaised], false),
+            hook("other", format!("{}/other", base_url), vec![WebhookEvent::JobFinished], true),
+        ])
+        .with_retry_policy(RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(20),
+        }),
+    );
+    notifier.start();
+    notifier.send(WebhookEvent::AlarmRaised, &json!({"device_id": "inv-1", "tag_name": "Temperature"}));
+
+    let mut finished = false;
+    for _ in 0..200 {
+        let flaky = db.get_webhook_deliveries("flaky", 10).await?;
+        let down = db.get_webhook_deliveries("down", 10).await?;
+        if flaky.first().is_some_and(|delivery| delivery.status != WebhookDeliveryStatus::Pending)
+            && down.first().is_some_and(|delivery| delivery.status != WebhookDeliveryStatus::Pending)
+        {
+            finished = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(25)).await;
+    }
+    assert!(finished, "deliveries did not finish");
+
+    // Only enabled subscribers are sent the event, each attempt signed with its own secret
+    let received = received.lock().unwrap().clone();
+    let paths: Vec<&str> = received.iter().map(|(path, _, _)| path.as_str()).collect();
+    assert_eq!(paths.iter().filter(|path| **path == "flaky").count(), 3);
+    assert_eq!(paths.iter().filter(|path| **path == "down").count(), 3);
+    assert_eq!(paths.len(), 6, "{:?}", paths);
+    for (path, signature_header, body) in &received {
+        assert_eq!(*signature_header, signature(&format!("{}-secret", path), body.as_bytes()));
+        let body: Value = serde_json::from_str(body)?;
+        assert_eq!((&body["event"], &body["data"]["device_id"]), (&json!("alarm_raised"), &json!("inv-1")));
+    }
+
+    let flaky = db.get_webhook_deliveries("flaky", 10).await?;
+    assert_eq!(flaky.len(), 1);
+    assert_eq!((flaky[0].status, flaky[0].attempts, flaky[0].last_status_code), (WebhookDeliveryStatus::Delivered, 3, Some(200)));
+    assert_eq!(flaky[0].payload["event"], "alarm_raised");
+    let down = db.get_webhook_deliveries("down", 10).await?;
+    assert_eq!((down[0].status, down[0].attempts, down[0].last_status_code), (WebhookDeliveryStatus::Failed, 3, Some(503)));
+    assert_eq!(down[0].last_error.as_deref(), Some("HTTP 503 Service Unavailable"));
+    assert!(db.get_webhook_deliveries("disabled", 10).await?.is_empty());
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_events_are_dropped_and_counted_when_the_queue_is_full() -> Result<(), Box<dyn Error>> {
+    let db_path = std::env::temp_dir().join(format!("webhooks-{}.db", uuid::Uuid::new_v4()));
+    let db = Arc::new(Database::new(&db_path.to_string_lossy()).await?);
+
+    // Never started, so nothing drains the queue; sending must still return at once
+    let notifier = WebhookNotifier::new(db, vec![hook("slow", "http://127.0.0.1:9/".to_string(), vec![WebhookEvent::DeviceOffline], true)])
+        .with_queue_capacity(2);
+    for _ in 0..10 {
+        notifier.send(WebhookEvent::DeviceOffline, &json!({"device_id": "inv-1"}));
+    }
+    // Events nobody subscribed to aren't queued, so they can't be dropped either
+    notifier.send(WebhookEvent::DeviceOnline, &json!({"device_id": "inv-1"}));
+    assert_eq!(notifier.dropped_events(), 8);
+
+    std::fs::remove_file(&db_path).ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_webhooks_are_managed_through_the_api() -> Result<(), Box<dyn Error>> {
+
+    let logger = Logger::start("").await?;
+    let (client, base_url, token) = (&logger.client, &logger.base_url, &logger.token);
+
+    let body: Value = client
+        .post(format!("{}/api/webhooks", base_url))
+        .bearer_auth(token)
+        .json(&json!({"url": "ftp://example.com/hook", "events": [], "secret": ""}))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["field_errors"], json!([
+        {"field": "url", "message": "must be an http or https URL"},
+        {"field": "events", "message": "must list at least one event"},
+        {"field": "secret", "message": "must not be empty"},
+    ]));
+
+    let body: Value = client
+        .post(format!("{}/api/webhooks", base_url))
+        .bearer_auth(token)
+        .json(&json!({"url": "https://example.com/hook", "events": ["device_offline", "alarm_raised"], "secret": "s3cret"}))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["success"], true, "{}", body);
+    assert_eq!((&body["data"]["enabled"], &body["data"]["secret"]), (&json!(true), &json!("")));
+    let webhook_id = body["data"]["id"].as_str().unwrap().to_string();
+
+    // Saved to config.toml, but the secret never comes back out
+    let body: Value = client.get(format!("{}/api/webhooks", base_url)).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(body["data"]["dropped_events"], 0);
+    assert_eq!(body["data"]["webhooks"][0]["url"], "https://example.com/hook");
+    assert!(!body.to_string().contains("s3cret"), "{}", body);
+    let body: Value = client.get(format!("{}/api/config", base_url)).bearer_auth(token).send().await?.json().await?;
+    assert!(!body.to_string().contains("s3cret"), "{}", body);
+
+    // A blank secret keeps the current one
+    let body: Value = client
+        .put(format!("{}/api/webhooks/{}", base_url, webhook_id))
+        .bearer_auth(token)
+        .json(&json!({"url": "https://example.com/other", "events": ["job_finished"], "secret": "", "enabled": false}))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(body["success"], true, "{}", body);
+    let saved = std::fs::read_to_string(logger.work_dir.join("config.toml"))?;
+    assert!(saved.contains("s3cret") && saved.contains("https://example.com/other"), "{}", saved);
+
+    let body: Value = client.get(format!("{}/api/webhooks/{}/deliveries", base_url, webhook_id)).bearer_auth(token).send().await?.json().await?;
+    assert_eq!(body["data"], json!([]));
+    let response = client.get(format!("{}/api/webhooks/no-such-webhook/deliveries", base_url)).bearer_auth(token).send().await?;
+    assert_eq!(response.status(), 404);
+
+    let response = client.delete(format!("{}/api/webhooks/{}", base_url, webhook_id)).bearer_auth(token).send().await?;
+    assert_eq!(response.status(), 200);
+    let response = client.delete(format!("{}/api/webhooks/{}", base_url, webhook_id)).bearer_auth(token).send().await?;
+    assert_eq!(response.status(), 404);
+    assert!(!std::fs::read_to_string(logger.work_dir.join("config.toml"))?.contains("s3cret"));
+    Ok(())
+}
@@ -0,0 +1,488 @@
+use ava_device_logger::database::{Database, LogEntry, NewNotification, UserChange};
+use ava_device_logger::live_values::{DeviceValues, TagValue};
+use ava_device_logger::notifications::NotificationService;
+use ava_device_logger::webhooks::WebhookNotifier;
+use ava_device_logger::websocket::{self, AuthError, HistoryAck, SocketContext, TagUpdate, TagUpdateBatcher};
+use axum::Router;
+use chrono::{DateTime, Utc};
+use futures_util::FutureExt;
+use rust_socketio::asynchronous::{Client as SocketClient, ClientBuilder};
+use rust_socketio::{Event, Payload, TransportType};
+use serde_json::{json, Value};
+use socketioxide::SocketIo;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::time::{timeout, Duration};
+
+type TestResult = Result<(), Box<dyn Error>>;
+
+async fn start_server() -> Result<(SocketAddr, SocketIo, Arc<Database>), Box<dyn Error>> {
+    let db_path = std::env::temp_dir().join(format!("websocket-{}.db", uuid::Uuid::new_v4()));
+    let database = Arc::new(Database::new(&db_path.to_string_lossy()).await?);
+    let context = SocketContext { session_check_interval: Duration::from_millis(200), ..SocketContext::new(database.clone()) };
+    let (layer, io) = SocketIo::new_layer();
+    io.ns("/", move |socket, auth| websocket::on_connect(socket, auth, context.clone()));
+    let app = Router::new().layer(layer);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
+    Ok((addr, io, database))
+}
+
+/// Socket.IO client on the default namespace, collecting the events it receives
+struct Client {
+    socket: SocketClient,
+    events: mpsc::UnboundedReceiver<(String, Value)>,
+}
+
+impl Client {
+    /// Connect logged in with a session `token`
+    async fn connect(addr: SocketAddr, token: &str) -> Result<Self, Box<dyn Error>> {
+        Self::connect_with(addr, "", json!({"token": token})).await
+    }
+
+    /// Connect with `query` as the handshake URL's query, sending `auth` in the namespace connect
+    async fn connect_with(addr: SocketAddr, query: &str, auth: Value) -> Result<Self, Box<dyn Error>> {
+        let (sender, events) = mpsc::unbounded_channel();
+        let (connected, close) = (sender.clone(), sender.clone());
+        let socket = ClientBuilder::new(format!("http://{}/{}", addr, query))
+            .transport_type(TransportType::Websocket)
+            .reconnect(false)
+            .auth(auth)
+            .on_any(move |event, payload, _| {
+                let _ = sender.send((event.as_str().to_string(), first_value(payload)));
+                async {}.boxed()
+            })
+            .on(Event::Connect, move |_, _| {
+                let _ = connected.send((Event::Connect.as_str().to_string(), Value::Null));
+                async {}.boxed()
+            })
+            .on(Event::Close, move |_, _| {
+                let _ = close.send((Event::Close.as_str().to_string(), Value::Null));
+                async {}.boxed()
+            })
+            .connect()
+            .await?;
+        let mut client = Self { socket, events };
+
+        assert_eq!(client.event().await?.0, Event::Connect.as_str(), "expected the namespace connect");
+        Ok(client)
+    }
+
+    /// Emit an event and wait for its acknowledgement
+    async fn request(&mut self, event: &str, data: Value) -> Result<Value, Box<dyn Error>> {
+        let (sender, mut ack) = mpsc::unbounded_channel();
+        // Acknowledgements carry their arguments as one array
+        let callback = move |payload: Payload, _| {
+            let ack = match first_value(payload) {
+                Value::Array(args) => args.into_iter().next().unwrap_or(Value::Null),
+                _ => Value::Null,
+            };
+            let _ = sender.send(ack);
+            async {}.boxed()
+        };
+        self.socket.emit_with_ack(event, data, Duration::from_secs(5), callback).await?;
+        Ok(timeout(Duration::from_secs(5), ack.recv()).await?.ok_or("no acknowledgement")?)
+    }
+
+    /// Next event, as its name and data
+    async fn event(&mut self) -> Result<(String, Value), Box<dyn Error>> {
+        Ok(timeout(Duration::from_secs(5), self.events.recv()).await?.ok_or("connection closed")?)
+    }
+
+    /// Updates of the next `tag_update`
+    async fn tag_updates(&mut self) -> Result<Vec<TagUpdate>, Box<dyn Error>> {
+        let (name, data) = self.event().await?;
+        assert_eq!(name, "tag_update");
+        Ok(serde_json::from_value(data)?)
+    }
+
+    /// Device id and tag names of the next `tag_update` of a single device
+    async fn tag_update(&mut self) -> Result<(String, Vec<String>), Box<dyn Error>> {
+        let updates = self.tag_updates().await?;
+        assert!(updates.windows(2).all(|pair| pair[0].device_id == pair[1].device_id));
+        Ok((updates[0].device_id.clone(), updates.into_iter().map(|update| update.tag).collect()))
+    }
+
+    /// Leave the namespace and close the connection
+    async fn disconnect(self) -> Result<(), Box<dyn Error>> {
+        Ok(self.socket.disconnect().await?)
+    }
+}
+
+/// First argument of an event or acknowledgement
+fn first_value(payload: Payload) -> Value {
+    match payload {
+        Payload::Text(values) => values.into_iter().next().unwrap_or(Value::Null),
+        _ => Value::Null,
+    }
+}
+
+fn update(device_id: &str, tag_names: &[&str]) -> DeviceValues {
+    let values: Vec<(&str, f64, &str)> = tag_names.iter().map(|tag_name| (*tag_name, 230.0, "Good")).collect();
+    update_with(device_id, &values)
+}
+
+fn update_with(device_id: &str, values: &[(&str, f64, &str)]) -> DeviceValues {
+    DeviceValues {
+        device_id: device_id.to_string(),
+        values: values
+            .iter()
+            .map(|(tag_name, value, quality)| TagValue {
+                tag_name: tag_name.to_string(),
+                value: *value,
+                unit: Some("V".to_string()),
+                quality: quality.to_string(),
+                timestamp: Utc::now(),
+                age_ms: 0,
+                stale: false,
+                stale_reason: None,
+            })
+            .collect(),
+    }
+}
+
+fn update_of(device_id: &str, tag_names: &[&str]) -> (String, Vec<String>) {
+    (device_id.to_string(), tag_names.iter().map(|tag_name| tag_name.to_string()).collect())
+}
+
+#[tokio::test]
+async fn test_clients_only_receive_updates_of_subscribed_devices() -> TestResult {
+    let (addr, io, db) = start_server().await?;
+    let token = session(&db).await?;
+    let batcher = TagUpdateBatcher::new(io.clone(), Duration::ZERO);
+    let mut client_a = Client::connect(addr, &token).await?;
+    let mut client_b = Client::connect(addr, &token).await?;
+    let mut client_both = Client::connect(addr, &token).await?;
+
+    let ack = client_a.request("subscribe", json!({"device_id": "inverter-a"})).await?;
+    assert_eq!(ack, json!({"rooms": ["device:inverter-a"], "error": null}));
+    client_b.request("subscribe", json!({"device_id": "inverter-b"})).await?;
+    client_both.request("subscribe", json!({"device_id": ["inverter-a", "inverter-b"]})).await?;
+
+    // Events reach a client in order, so B's update would arrive before A's
+    batcher.push(&update("inverter-b", &["Power"]));
+    batcher.push(&update("inverter-a", &["Voltage", "Current"]));
+
+    assert_eq!(client_a.tag_update().await?, update_of("inverter-a", &["Voltage", "Current"]));
+    assert_eq!(client_b.tag_update().await?, update_of("inverter-b", &["Power"]));
+    assert_eq!(client_both.tag_update().await?, update_of("inverter-b", &["Power"]));
+    assert_eq!(client_both.tag_update().await?, update_of("inverter-a", &["Voltage", "Current"]));
+
+    let ack = client_a.request("subscribe", json!({"tags": ["Voltage"]})).await?;
+    assert_eq!(ack["error"], json!("device_id is required"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_tag_subscriptions_only_receive_their_tags() -> TestResult {
+    let (addr, io, db) = start_server().await?;
+    let token = session(&db).await?;
+    let batcher = TagUpdateBatcher::new(io.clone(), Duration::ZERO);
+    let mut tags_only = Client::connect(addr, &token).await?;
+    let mut device_and_tag = Client::connect(addr, &token).await?;
+
+    tags_only.request("subscribe", json!({"device_id": "inverter-a", "tags": ["Voltage", "Energy"]})).await?;
+    device_and_tag.request("subscribe", json!({"device_id": "inverter-a"})).await?;
+    device_and_tag.request("subscribe", json!({"device_id": "inverter-a", "tags": ["Voltage"]})).await?;
+
+    batcher.push(&update("inverter-a", &["Voltage", "Current", "Energy"]));
+    batcher.push(&update("inverter-a", &["Current"]));
+    batcher.push(&update("inverter-a", &["Energy"]));
+
+    assert_eq!(tags_only.tag_update().await?, update_of("inverter-a", &["Voltage"]));
+    assert_eq!(tags_only.tag_update().await?, update_of("inverter-a", &["Energy"]));
+    assert_eq!(tags_only.tag_update().await?, update_of("inverter-a", &["Energy"]));
+
+    // A device subscriber isn't sent its tag subscriptions a second time
+    assert_eq!(device_and_tag.tag_update().await?, update_of("inverter-a", &["Voltage", "Current", "Energy"]));
+    assert_eq!(device_and_tag.tag_update().await?, update_of("inverter-a", &["Current"]));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unsubscribe_and_disconnect_leave_rooms() -> TestResult {
+    let (addr, io, db) = start_server().await?;
+    let token = session(&db).await?;
+    let batcher = TagUpdateBatcher::new(io.clone(), Duration::ZERO);
+    let mut client = Client::connect(addr, &token).await?;
+
+    client.request("subscribe", json!({"device_id": ["inverter-a", "inverter-b"]})).await?;
+    client.request("subscribe", json!({"device_id": "inverter-a", "tags": ["Voltage"]})).await?;
+    let ack = client.request("unsubscribe", json!({"device_id": "inverter-a"})).await?;
+    assert_eq!(ack["rooms"], json!(["device:inverter-b"]));
+
+    batcher.push(&update("inverter-a", &["Voltage"]));
+    batcher.push(&update("inverter-b", &["Voltage"]));
+    assert_eq!(client.tag_update().await?, update_of("inverter-b", &["Voltage"]));
+
+    let ack = client.request("unsubscribe", json!({})).await?;
+    assert_eq!(ack["rooms"], json!([]));
+
+    client.request("subscribe", json!({"device_id": "inverter-c"})).await?;
+    assert_eq!(io.within("device:inverter-c").sockets()?.len(), 1);
+    client.disconnect().await?;
+    timeout(Duration::from_secs(5), async {
+        while !io.within("device:inverter-c").sockets().unwrap().is_empty() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_updates_are_batched_keeping_quality_transitions() -> TestResult {
+    let (addr, io, db) = start_server().await?;
+    let token = session(&db).await?;
+    let batcher = TagUpdateBatcher::new(io.clone(), Duration::from_millis(300));
+    let mut client = Client::connect(addr, &token).await?;
+    client.request("subscribe", json!({"device_id": "inverter-a"})).await?;
+
+    batcher.push(&update_with("inverter-a", &[("Voltage", 1.0, "Good"), ("Current", 10.0, "Good")]));
+    batcher.push(&update_with("inverter-a", &[("Voltage", 2.0, "Good")]));
+    batcher.push(&update_with("inverter-a", &[("Voltage", 3.0, "Bad")]));
+    batcher.push(&update_with("inverter-a", &[("Voltage", 4.0, "Bad"), ("Current", 11.0, "Good")]));
+
+    // Only the latest value of each quality run is left, in one event
+    let updates: Vec<(String, f64, String)> =
+        client.tag_updates().await?.into_iter().map(|update| (update.tag, update.value, update.quality)).collect();
+    assert_eq!(updates, [
+        ("Voltage".to_string(), 2.0, "Good".to_string()),
+        ("Current".to_string(), 11.0, "Good".to_string()),
+        ("Voltage".to_string(), 4.0, "Bad".to_string()),
+    ]);
+
+    batcher.push(&update_with("inverter-a", &[("Voltage", 5.0, "Good")]));
+    assert_eq!(client.tag_update().await?, update_of("inverter-a", &["Voltage"]));
+    Ok(())
+}
+
+/// A session token of a new installer account
+async fn session(db: &Database) -> Result<String, Box<dyn Error>> {
+    Ok(user_session(db).await?.1)
+}
+
+/// The id and a session token of a new installer account
+async fn user_session(db: &Database) -> Result<(i64, String), Box<dyn Error>> {
+    let username = format!("viewer-{}", uuid::Uuid::new_v4().simple());
+    let UserChange::Done(user) = db.create_user(&username, "password123", "installer").await? else {
+        return Err("failed to create the user".into());
+    };
+    let token = uuid::Uuid::new_v4().to_string();
+    db.create_session(user.id, &token, Utc::now() + chrono::Duration::hours(1), None).await?;
+    Ok((user.id, token))
+}
+
+fn log_entry(tag_name: &str, value: f64, quality: &str, timestamp: DateTime<Utc>) -> LogEntry {
+    LogEntry {
+        id: None,
+        device_id: "inverter-a".to_string(),
+        tag_name: tag_name.to_string(),
+        value,
+        quality: quality.to_string(),
+        timestamp,
+        unit: Some("V".to_string()),
+    }
+}
+
+#[tokio::test]
+async fn test_history_returns_logged_values_and_downsamples_long_ranges() -> TestResult {
+    let (addr, _io, db) = start_server().await?;
+    let token = session(&db).await?;
+    let now = Utc::now();
+    let mut entries: Vec<LogEntry> = (0..10).map(|minute| log_entry("Voltage", minute as f64, "Good", now - chrono::Duration::minutes(10 - minute))).collect();
+    entries.push(log_entry("Voltage", 99.0, "CommFail", now - chrono::Duration::seconds(30)));
+    entries.push(log_entry("Current", 5.0, "Good", now - chrono::Duration::seconds(30)));
+    entries.push(log_entry("Voltage", -1.0, "Good", now - chrono::Duration::minutes(20)));
+    db.insert_log_entries(&entries).await?;
+
+    let mut client = Client::connect_with(addr, "", json!({"token": token})).await?;
+    let ack: HistoryAck = serde_json::from_value(client.request("history", json!({"device_id": "inverter-a", "tag_name": "Voltage"})).await?)?;
+    assert_eq!(ack.error, None);
+    assert_eq!(ack.interval_seconds, None);
+    let values: Vec<f64> = ack.points.iter().map(|(_, value)| *value).collect();
+    assert_eq!(values, (0..10).map(f64::from).collect::<Vec<_>>());
+    assert_eq!(ack.points[0].0, entries[0].timestamp.timestamp_millis());
+
+    // An explicit range
+    let ack: HistoryAck = serde_json::from_value(
+        client
+            .request("history", json!({"device_id": "inverter-a", "tag_name": "Voltage", "start": now - chrono::Duration::minutes(30), "end": now - chrono::Duration::minutes(5)}))
+            .await?,
+    )?;
+    let values: Vec<f64> = ack.points.iter().map(|(_, value)| *value).collect();
+    assert_eq!(values, [-1.0, 0.0, 1.0, 2.0, 3.0, 4.0]);
+
+    // More values than max_points are averaged into buckets
+    let ack: HistoryAck = serde_json::from_value(
+        client.request("history", json!({"device_id": "inverter-a", "tag_name": "Voltage", "minutes": 15, "max_points": 3})).await?,
+    )?;
+    assert_eq!(ack.error, None);
+    assert_eq!(ack.interval_seconds, Some(450));
+    assert!(!ack.points.is_empty() && ack.points.len() <= 3, "{:?}", ack.points);
+    assert!(ack.points.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    let total: f64 = ack.points.iter().map(|(_, value)| *value).sum();
+    assert!(total > 0.0 && total < 45.0, "{:?}", ack.points);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_history_is_validated_and_rate_limited() -> TestResult {
+    let (addr, _io, db) = start_server().await?;
+    let request = json!({"device_id": "inverter-a", "tag_name": "Voltage", "minutes": 5});
+
+    // The token may come as a query parameter too
+    let token = session(&db).await?;
+    let mut client = Client::connect_with(addr, &format!("?token={}", token), json!({})).await?;
+    let ack = client.request("history", request.clone()).await?;
+    assert_eq!(ack, json!({"points": [], "interval_seconds": null, "error": null}));
+    let ack = client.request("history", json!({"device_id": "inverter-a", "tag_name": "Voltage", "max_points": 10_000})).await?;
+    assert_eq!(ack["error"], "max_points must be between 2 and 5000");
+    let ack = client.request("history", json!({"device_id": "inverter-a"})).await?;
+    assert!(ack["error"].as_str().unwrap_or_default().starts_with("Invalid history request"), "{}", ack);
+
+    // Three of the burst of five are left, then requests are refused until a token refills
+    for _ in 0..3 {
+        assert_eq!(client.request("history", request.clone()).await?["error"], Value::Null);
+    }
+    let ack = client.request("history", request.clone()).await?;
+    assert!(ack["error"].as_str().unwrap_or_default().starts_with("Too many history requests"), "{}", ack);
+
+    // Limits are per socket
+    let mut other = Client::connect_with(addr, "", json!({"token": token})).await?;
+    assert_eq!(other.request("history", request.clone()).await?["error"], Value::Null);
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(client.request("history", request).await?["error"], Value::Null);
+    Ok(())
+}
+
+/// The `auth_error` sent before the server disconnects the client
+async fn auth_error(client: &mut Client) -> Result<AuthError, Box<dyn Error>> {
+    let (name, data) = client.event().await?;
+    assert_eq!(name, websocket::AUTH_ERROR_EVENT);
+    assert_eq!(client.event().await?.0, Event::Close.as_str(), "expected the namespace disconnect");
+    Ok(serde_json::from_value(data)?)
+}
+
+#[tokio::test]
+async fn test_connections_need_a_valid_session() -> TestResult {
+    let (addr, io, db) = start_server().await?;
+
+    let mut client = Client::connect_with(addr, "", json!({})).await?;
+    let error = auth_error(&mut client).await?;
+    assert_eq!(error.reason, "unauthorized");
+    assert!(error.message.starts_with("Not logged in"), "{}", error.message);
+
+    let mut client = Client::connect_with(addr, "", json!({"token": "not-a-session"})).await?;
+    assert_eq!(auth_error(&mut client).await?.reason, "unauthorized");
+
+    let UserChange::Done(user) = db.create_user("expired-user", "password123", "installer").await? else {
+        return Err("failed to create the user".into());
+    };
+    db.create_session(user.id, "expired-token", Utc::now() - chrono::Duration::minutes(1), None).await?;
+    let mut client = Client::connect_with(addr, "?token=expired-token", json!({})).await?;
+    assert_eq!(auth_error(&mut client).await?.reason, "unauthorized");
+
+    // A valid session may subscribe straight after connecting
+    let token = session(&db).await?;
+    let mut client = Client::connect(addr, &token).await?;
+    let ack = client.request("subscribe", json!({"device_id": "inverter-a"})).await?;
+    assert_eq!(ack["error"], Value::Null);
+    assert_eq!(io.within("device:inverter-a").sockets()?.len(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sessions_ending_while_connected_disconnect_the_client() -> TestResult {
+    let (addr, io, db) = start_server().await?;
+    let UserChange::Done(user) = db.create_user("short-session", "password123", "installer").await? else {
+        return Err("failed to create the user".into());
+    };
+    let token = session(&db).await?;
+    let mut logged_out = Client::connect(addr, &token).await?;
+    logged_out.request("subscribe", json!({"device_id": "inverter-a"})).await?;
+    let other = session(&db).await?;
+    let mut staying = Client::connect(addr, &other).await?;
+    staying.request("subscribe", json!({"device_id": "inverter-a"})).await?;
+
+    db.create_session(user.id, "short-token", Utc::now() + chrono::Duration::seconds(1), None).await?;
+    let mut expiring = Client::connect(addr, "short-token").await?;
+    expiring.request("subscribe", json!({"device_id": "inverter-a"})).await?;
+    assert_eq!(io.within("device:inverter-a").sockets()?.len(), 3);
+
+    db.revoke_session(&token).await?;
+    let error = auth_error(&mut logged_out).await?;
+    assert_eq!(error.reason, "session_expired");
+    let error = auth_error(&mut expiring).await?;
+    assert_eq!(error.reason, "session_expired");
+
+    timeout(Duration::from_secs(5), async {
+        while io.within("device:inverter-a").sockets().unwrap().len() > 1 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await?;
+    assert_eq!(staying.request("history", json!({"device_id": "inverter-a", "tag_name": "Voltage"})).await?["error"], Value::Null);
+    Ok(())
+}
+
+
+fn notification(title: &str, user_id: Option<i64>) -> NewNotification {
+    NewNotification {
+        notification_type: "test".to_string(),
+        severity: "info".to_string(),
+        title: title.to_string(),
+        body: String::new(),
+        entity_type: None,
+        entity_id: None,
+        user_id,
+    }
+}
+
+#[tokio::test]
+async fn test_user_notifications_only_reach_that_users_connections() -> TestResult {
+    let (addr, io, db) = start_server().await?;
+    let notifications = NotificationService::new(
+        db.clone(),
+        io.clone(),
+        TagUpdateBatcher::new(io.clone(), Duration::ZERO),
+        Arc::new(WebhookNotifier::new(db.clone(), Vec::new())),
+    );
+    let (alice, alice_token) = user_session(&db).await?;
+    let (bob, bob_token) = user_session(&db).await?;
+    let mut alice_tab = Client::connect(addr, &alice_token).await?;
+    let mut alice_other_tab = Client::connect(addr, &alice_token).await?;
+    let mut bob_tab = Client::connect(addr, &bob_token).await?;
+    timeout(Duration::from_secs(5), async {
+        while io.within(websocket::user_room(alice)).sockets().unwrap().len() < 2 || io.within(websocket::user_room(bob)).sockets().unwrap().is_empty() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await?;
+
+    // Leaving every subscription keeps the user's room
+    let ack = bob_tab.request("unsubscribe", json!({})).await?;
+    assert_eq!(ack["rooms"], json!([]));
+    assert_eq!(io.within(websocket::user_room(bob)).sockets()?.len(), 1);
+
+    let alice_only = notifications.notify(notification("For Alice", Some(alice))).await.ok_or("not stored")?;
+    let everyone = notifications.notify(notification("For everyone", None)).await.ok_or("not stored")?;
+
+    for tab in [&mut alice_tab, &mut alice_other_tab] {
+        let (name, data) = tab.event().await?;
+        assert_eq!((name.as_str(), &data["id"], &data["user_id"]), ("notification", &json!(alice_only), &json!(alice)));
+        let (name, data) = tab.event().await?;
+        assert_eq!((name.as_str(), &data["id"], &data["user_id"]), ("notification", &json!(everyone), &Value::Null));
+    }
+    // Bob only hears about the broadcast
+    let (name, data) = bob_tab.event().await?;
+    assert_eq!((name.as_str(), &data["id"]), ("notification", &json!(everyone)));
+    Ok(())
+}
@@ -69,7 +69,7 @@ const Dashboard = () => {
       }
 
       if (logsRes.data.success) {
-        setRecentLogs(logsRes.data.data);
+        setRecentLogs(logsRes.data.data.entries);
       }
     } catch (error) {
       console.error('Error fetching data:', error);
@@ -122,6 +122,17 @@ const Dashboard = () => {
     }
   };
 
+  const handleBulkAction = async (action) => {
+    try {
+      const response = await axios.post(`/api/devices-enhanced/${action}-all`);
+      const failed = (response.data.data?.results || []).filter(r => r.outcome === 'failed');
+      failed.forEach(r => console.error(`Error ${action} device ${r.device_id}:`, r.detail));
+      fetchData(); // Refresh data
+    } catch (error) {
+      console.error(`Error ${action} all devices:`, error);
+    }
+  };
+
   const getDeviceName = (deviceId) => {
     const device = devices.find(d => d.id === deviceId);
     return device ? device.name : deviceId;
@@ -191,7 +202,7 @@ const Dashboard = () => {
           icon = getStatusIcon('Disconnected');
         } else if (record.is_running) {
           status = record.status || 'Running';
-          color = record.status === 'Error' ? 'error' : 'success';
+          color = getStatusColor(record.status || 'Connected');
           icon = getStatusIcon(record.status || 'Connected');
         } else {
           status = 'Stopped';
@@ -374,7 +385,20 @@ const Dashboard = () => {
 
       <Row gutter={[16, 16]} style={{ marginTop: 16 }}>
         <Col span={24}>
-          <Card title="Device Status" className="status-card">
+          <Card
+            title="Device Status"
+            className="status-card"
+            extra={
+              <Space>
+                <Button icon={<PlayCircleOutlined />} onClick={() => handleBulkAction('start')} size="small">
+                  Start All
+                </Button>
+                <Button icon={<PauseCircleOutlined />} onClick={() => handleBulkAction('stop')} size="small">
+                  Stop All
+                </Button>
+              </Space>
+            }
+          >
             <Table
               dataSource={devices || []}
               columns={deviceColumns}
@@ -67,9 +67,8 @@ const DataLogs = () => {
 
       const response = await axios.get(url);
       if (response.data.success) {
-        setLogs(response.data.data);
-        // Note: In a real implementation, you'd get the total count from the API
-        setPagination(prev => ({ ...prev, total: response.data.data.length }));
+        setLogs(response.data.data.entries);
+        setPagination(prev => ({ ...prev, total: response.data.data.total }));
       }
     } catch (error) {
       console.error('Error fetching logs:', error);
@@ -102,7 +101,7 @@ const DataLogs = () => {
       });
 
       if (response.data.success) {
-        const deviceLogs = response.data.data;
+        const deviceLogs = response.data.data.entries;
         const now = moment();
         const twentyFourHoursAgo = now.clone().subtract(24, 'hours');
         
@@ -193,24 +192,22 @@ const DataLogs = () => {
       const now = moment();
       const twentyFourHoursAgo = now.clone().subtract(24, 'hours');
       
-      // Fetch more logs for the specific device
-      const response = await axios.get(`/api/logs/${selectedDevice}`, {
+      // Let the server average the last 24 hours into 5 minute buckets
+      const response = await axios.get(`/api/logs/${selectedDevice}/aggregate`, {
         params: {
-          limit: 1000 // Get more data points for better chart
+          tag_name: tagName,
+          interval: '5m',
+          fn: 'avg',
+          start: twentyFourHoursAgo.toISOString(),
+          end: now.toISOString(),
         }
       });
 
       if (response.data.success) {
-        // Filter for the specific tag and time range
-        const tagLogs = response.data.data.filter(log => 
-          log.tag_name === tagName &&
-          moment(log.timestamp).isAfter(twentyFourHoursAgo)
-        ).sort((a, b) => moment(a.timestamp).valueOf() - moment(b.timestamp).valueOf());
-
-        const chartDataForTag = tagLogs.map(log => ({
-          time: moment(log.timestamp).format('MM-DD HH:mm'),
-          value: log.value,
-          timestamp: log.timestamp
+        const chartDataForTag = response.data.data.buckets.map(bucket => ({
+          time: moment(bucket.bucket_start).format('MM-DD HH:mm'),
+          value: bucket.value,
+          timestamp: bucket.bucket_start
         }));
 
         setChartData(chartDataForTag);
@@ -252,9 +249,26 @@ const DataLogs = () => {
     setPagination(paginationInfo);
   };
 
-  const handleExport = () => {
-    // In a real implementation, this would trigger a CSV/Excel export
-    console.log('Exporting data...');
+  const handleExport = async () => {
+    try {
+      const params = new URLSearchParams({ format: 'csv' });
+      if (selectedDevice) {
+        params.append('device_id', selectedDevice);
+      }
+
+      // Goes through axios so the session header is sent, then saved as a file
+      const response = await axios.get(`/api/logs/export?${params}`, { responseType: 'blob' });
+      const disposition = response.headers['content-disposition'] || '';
+      const match = disposition.match(/filename="([^"]+)"/);
+      const link = document.createElement('a');
+      link.href = URL.createObjectURL(response.data);
+      link.download = match ? match[1] : 'logs.csv';
+      link.click();
+      URL.revokeObjectURL(link.href);
+    } catch (error) {
+      console.error('Error exporting logs:', error);
+      message.error('Failed to export logs');
+    }
   };
 
   const columns = [
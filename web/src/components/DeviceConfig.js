@@ -40,7 +40,12 @@ const DeviceConfig = () => {
     try {
       const response = await axios.get('/api/devices');
       if (response.data.success) {
-        setDevices(response.data.data);
+        // /api/devices returns the database listing; flatten it to the config shape this page edits
+        setDevices(response.data.data.map(({ device, tags }) => ({
+          ...device,
+          protocol: JSON.parse(device.protocol_config || '{}'),
+          tags,
+        })));
       }
     } catch (error) {
       message.error('Failed to fetch devices');
@@ -236,10 +236,10 @@ const DeviceModelBrowser = ({ onSelectModel, visible, onClose, onTagTemplatesLoa
         });
 
         if (csvResponse.data.success) {
-          message.success(`Successfully uploaded CSV: ${csvResponse.data.message}`);
-          message.info(`${csvResponse.data.summary}`);
+          message.success(`Successfully uploaded CSV: ${csvResponse.data.data.message}`);
+          message.info(`${csvResponse.data.data.summary}`);
         } else {
-          throw new Error(csvResponse.data.message || 'Failed to upload CSV');
+          throw new Error(csvResponse.data.details?.message || csvResponse.data.error || 'Failed to upload CSV');
         }
       }
       
@@ -303,12 +303,15 @@ const DeviceModelBrowser = ({ onSelectModel, visible, onClose, onTagTemplatesLoa
     onClose();
   };
 
-  const handleDeleteModel = async (modelId) => {
+  const handleDeleteModel = async (modelId, force = false) => {
     try {
       setLoading(true);
-      const response = await axios.post(`/api/device-models/${modelId}/delete`);
+      const response = await axios.post(`/api/device-models/${modelId}/delete`, null, { params: force ? { force: true } : {} });
+      const dependentDevices = response.data.details?.dependent_devices || [];
       
-      if (response.data.success) {
+      if (!response.data.success && dependentDevices.length > 0) {
+        showForceDeleteConfirm(modelId, dependentDevices);
+      } else if (response.data.success) {
         Modal.success({
           title: 'Success',
           content: 'Device model deleted successfully',
@@ -349,6 +352,28 @@ const DeviceModelBrowser = ({ onSelectModel, visible, onClose, onTagTemplatesLoa
     });
   };
 
+  const showForceDeleteConfirm = (modelId, devices) => {
+    Modal.confirm({
+      title: 'Device Model In Use',
+      content: (
+        <div>
+          <p>The following devices use this model and will be deleted together with their tags:</p>
+          <ul>
+            {devices.map(device => (
+              <li key={device.id}>{device.name} ({device.id})</li>
+            ))}
+          </ul>
+        </div>
+      ),
+      okText: `Delete Model and ${devices.length} Device${devices.length === 1 ? '' : 's'}`,
+      okType: 'danger',
+      cancelText: 'Cancel',
+      onOk() {
+        handleDeleteModel(modelId, true);
+      },
+    });
+  };
+
   const getProtocolColor = (protocol) => {
     const colors = {
       modbus_tcp: 'blue',
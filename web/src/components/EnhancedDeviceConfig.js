@@ -59,6 +59,8 @@ const EnhancedDeviceConfig = () => {
   const [selectedModel, setSelectedModel] = useState(null);
   const [tagTemplates, setTagTemplates] = useState([]);
   const [loading, setLoading] = useState(false);
+  const [testingConnection, setTestingConnection] = useState(false);
+  const [readingSerial, setReadingSerial] = useState(false);
   const [modalVisible, setModalVisible] = useState(false);
   const [editingDevice, setEditingDevice] = useState(null);
   const [form] = Form.useForm();
@@ -324,6 +326,8 @@ const EnhancedDeviceConfig = () => {
           scaling_offset: 0,
           unit: item.register_type,
           read_only: item.register_type === 'input',
+          deadband_absolute: item.deadband_absolute,
+          deadband_percent: item.deadband_percent,
           // Keep original fields for compatibility
           data_label: item.data_label,
           modbus_type: item.modbus_type,
@@ -390,6 +394,8 @@ const EnhancedDeviceConfig = () => {
         enabled: true,
         schedule_group_id: defaultScheduleGroup?.id || null,
         agg_to_field: template.agg_to_field || null,
+        deadband_absolute: template.deadband_absolute ?? null,
+        deadband_percent: template.deadband_percent ?? null,
       }));
       setDeviceTags(newTags);
     }
@@ -842,8 +848,26 @@ const EnhancedDeviceConfig = () => {
       host: protocolConfig.host || '',
       port: protocolConfig.port || (protocolConfig.type === 'iec104' ? 2404 : 502),
       slave_id: protocolConfig.slave_id || 1,
+      max_block_gap: protocolConfig.max_block_gap || 0,
+      request_delay_ms: protocolConfig.request_delay_ms || 0,
       baud_rate: protocolConfig.baud_rate || 9600,
+      data_bits: protocolConfig.data_bits || 8,
+      stop_bits: protocolConfig.stop_bits || 1,
+      parity: protocolConfig.parity || 'none',
+      inter_frame_delay_ms: protocolConfig.inter_frame_delay_ms || 0,
       common_address: protocolConfig.common_address || 1,
+      serial_register_type: protocolConfig.serial_source?.register_type || 'holding',
+      serial_address: protocolConfig.serial_source?.address ?? null,
+      serial_length: protocolConfig.serial_source?.length ?? null,
+      serial_encoding: protocolConfig.serial_source?.encoding || 'ascii',
+      simulation: protocolConfig.type === 'simulated'
+        ? JSON.stringify({
+          patterns: protocolConfig.patterns || {},
+          dropout: protocolConfig.dropout || null,
+          bad_quality_probability: protocolConfig.bad_quality_probability || 0,
+          seed: protocolConfig.seed ?? null,
+        }, null, 2)
+        : undefined,
     });
 
     // Don't automatically load tag templates when editing
@@ -854,28 +878,129 @@ const EnhancedDeviceConfig = () => {
     setModalVisible(true);
   };
 
-  const handleSubmit = async (values) => {
-    try {
-      setLoading(true);
+  // Build protocol config based on protocol type
+  const buildProtocolConfig = (values) => {
+    const protocolConfig = {
+      type: values.protocol_type,
+    };
 
-      // Build protocol config based on protocol type
-      const protocolConfig = {
-        type: values.protocol_type,
+    if (values.protocol_type === 'modbus_tcp') {
+      protocolConfig.host = values.host;
+      protocolConfig.port = values.port;
+      protocolConfig.slave_id = values.slave_id;
+      protocolConfig.max_block_gap = values.max_block_gap || 0;
+      protocolConfig.request_delay_ms = values.request_delay_ms || 0;
+    } else if (values.protocol_type === 'modbus_rtu') {
+      protocolConfig.port = values.port; // Serial port path
+      protocolConfig.baud_rate = values.baud_rate;
+      protocolConfig.data_bits = values.data_bits || 8;
+      protocolConfig.stop_bits = values.stop_bits || 1;
+      protocolConfig.parity = values.parity || 'none';
+      protocolConfig.inter_frame_delay_ms = values.inter_frame_delay_ms || 0;
+      protocolConfig.slave_id = values.slave_id;
+      protocolConfig.max_block_gap = values.max_block_gap || 0;
+    } else if (values.protocol_type === 'iec104') {
+      protocolConfig.host = values.host;
+      protocolConfig.port = values.port;
+      protocolConfig.common_address = values.common_address || 1;
+    } else if (values.protocol_type === 'simulated') {
+      // Invalid JSON is sent as is, so the server reports which field is wrong
+      try {
+        Object.assign(protocolConfig, JSON.parse(values.simulation || '{}'));
+      } catch (e) {
+        protocolConfig.patterns = values.simulation;
+      }
+    }
+
+    if ((values.protocol_type === 'modbus_tcp' || values.protocol_type === 'modbus_rtu')
+      && values.serial_address != null && values.serial_length) {
+      protocolConfig.serial_source = {
+        register_type: values.serial_register_type || 'holding',
+        address: values.serial_address,
+        length: values.serial_length,
+        encoding: values.serial_encoding || 'ascii',
       };
+    }
+
+    return protocolConfig;
+  };
 
-      if (values.protocol_type === 'modbus_tcp') {
-        protocolConfig.host = values.host;
-        protocolConfig.port = values.port;
-        protocolConfig.slave_id = values.slave_id;
-      } else if (values.protocol_type === 'modbus_rtu') {
-        protocolConfig.port = values.port; // Serial port path
-        protocolConfig.baud_rate = values.baud_rate;
-        protocolConfig.slave_id = values.slave_id;
-      } else if (values.protocol_type === 'iec104') {
-        protocolConfig.host = values.host;
-        protocolConfig.port = values.port;
-        // No common_address needed - IEC 104 will read all available IOAs
+  // Read the serial number from the saved device, offering to replace the stored one if it differs
+  const handleReadSerial = async () => {
+    const deviceId = editingDevice.device.id;
+    try {
+      setReadingSerial(true);
+      const response = await axios.post(`/api/devices-enhanced/${deviceId}/read-serial`);
+      if (!response.data.success) {
+        message.error(response.data.error || 'Failed to read the serial number');
+        return;
+      }
+      const result = response.data.data;
+      if (result.matches) {
+        message.success(`Device reports ${result.device_serial_no}, matching the stored serial number`);
+        return;
       }
+      Modal.confirm({
+        title: 'Serial number differs',
+        content: `The device reports ${result.device_serial_no}, but ${result.configured_serial_no || 'no serial number'} is stored. Store the device's serial number and update ThingsBoard?`,
+        okText: 'Use Device Serial',
+        onOk: async () => {
+          const applied = await axios.post(`/api/devices-enhanced/${deviceId}/read-serial?apply=true`);
+          if (!applied.data.success) {
+            message.error(applied.data.error || 'Failed to store the serial number');
+            return;
+          }
+          form.setFieldsValue({ serial_no: applied.data.data.device_serial_no });
+          if (applied.data.data.thingsboard_error) {
+            message.warning(`Serial number stored, but ThingsBoard was not updated: ${applied.data.data.thingsboard_error}`);
+          } else {
+            message.success('Serial number stored');
+          }
+          fetchDevices();
+        },
+      });
+    } catch (error) {
+      message.error(error.response?.data?.error || 'Failed to read the serial number');
+    } finally {
+      setReadingSerial(false);
+    }
+  };
+
+  const showFieldErrors = (fieldErrors) => {
+    if (Array.isArray(fieldErrors)) {
+      form.setFields(fieldErrors
+        .filter(fieldError => form.getFieldInstance(fieldError.field))
+        .map(fieldError => ({ name: fieldError.field, errors: [fieldError.message] })));
+    }
+  };
+
+  const handleTestConnection = async () => {
+    const values = form.getFieldsValue();
+    try {
+      setTestingConnection(true);
+      const response = await axios.post('/api/devices-enhanced/test-connection', {
+        protocol_config: buildProtocolConfig(values),
+        timeout_ms: values.timeout_ms || 5000,
+      });
+      const result = response.data.data;
+      if (result.success) {
+        message.success(`${result.message} (${result.latency_ms} ms)`);
+      } else {
+        message.error(`${result.message} (${result.latency_ms} ms)`);
+      }
+    } catch (error) {
+      showFieldErrors(error.response?.data?.field_errors);
+      message.error(error.response?.data?.error || 'Connection test failed');
+    } finally {
+      setTestingConnection(false);
+    }
+  };
+
+  const handleSubmit = async (values) => {
+    try {
+      setLoading(true);
+
+      const protocolConfig = buildProtocolConfig(values);
 
       // Generate device ID based on protocol configuration
       let deviceId;
@@ -920,13 +1045,20 @@ const EnhancedDeviceConfig = () => {
       }
 
       if (response.data.success) {
-        message.success(editingDevice ? 'Device updated successfully' : 'Device created successfully');
+        if (!editingDevice) {
+          message.success('Device created successfully');
+        } else if (response.data.data?.reloaded) {
+          message.success('Device updated and restarted with the new configuration');
+        } else {
+          message.success('Device updated successfully');
+        }
         setModalVisible(false);
         fetchDevices();
         fetchUnsyncedDevices();
         fetchSyncedDevicesForGroup(selectedDeviceGroup);
       } else {
-        message.error(editingDevice ? 'Failed to update device' : 'Failed to create device');
+        showFieldErrors(response.data.field_errors);
+        message.error(response.data.error || (editingDevice ? 'Failed to update device' : 'Failed to create device'));
       }
     } catch (error) {
       message.error(editingDevice ? 'Failed to update device' : 'Failed to create device');
@@ -951,6 +1083,7 @@ const EnhancedDeviceConfig = () => {
       enabled: true,
       schedule_group_id: defaultScheduleGroup?.id || null,
       agg_to_field: null,
+      write_policy: 'disabled',
     };
     setDeviceTags([...deviceTags, newTag]);
   };
@@ -996,6 +1129,7 @@ const EnhancedDeviceConfig = () => {
       modbus_tcp: 'blue',
       modbus_rtu: 'green',
       iec104: 'orange',
+      simulated: 'purple',
     };
     return colors[type] || 'default';
   };
@@ -1308,7 +1442,33 @@ const EnhancedDeviceConfig = () => {
           <Option value="int16">Int16</Option>
           <Option value="uint32">UInt32</Option>
           <Option value="int32">Int32</Option>
+          <Option value="uint64">UInt64</Option>
+          <Option value="int64">Int64</Option>
           <Option value="float32">Float32</Option>
+          <Option value="float64">Float64</Option>
+        </Select>
+      ),
+    },
+    {
+      title: (
+        <Tooltip title="Layout of multi-register values. Leave on Default to keep integers low word first and floats high word first">
+          Byte Order
+        </Tooltip>
+      ),
+      dataIndex: 'byte_order',
+      key: 'byte_order',
+      width: 110,
+      render: (value, record, index) => (
+        <Select
+          value={value || 'default'}
+          onChange={(val) => updateTag(index, 'byte_order', val === 'default' ? null : val)}
+          style={{ width: '100%' }}
+        >
+          <Option value="default">Default</Option>
+          <Option value="ABCD">ABCD</Option>
+          <Option value="CDAB">CDAB</Option>
+          <Option value="BADC">BADC</Option>
+          <Option value="DCBA">DCBA</Option>
         </Select>
       ),
     },
@@ -1369,6 +1529,47 @@ const EnhancedDeviceConfig = () => {
         />
       ),
     },
+    {
+      title: (
+        <Tooltip title="Only log a value once it moves by more than this from the last logged value. Leave empty to log every poll">
+          Deadband
+        </Tooltip>
+      ),
+      dataIndex: 'deadband_absolute',
+      key: 'deadband_absolute',
+      width: 110,
+      render: (value, record, index) => (
+        <InputNumber
+          value={value}
+          onChange={(val) => updateTag(index, 'deadband_absolute', val ?? null)}
+          min={0}
+          step={0.1}
+          placeholder="None"
+          style={{ width: '100%' }}
+        />
+      ),
+    },
+    {
+      title: (
+        <Tooltip title="Same as Deadband, as a percentage of the last logged value">
+          Deadband %
+        </Tooltip>
+      ),
+      dataIndex: 'deadband_percent',
+      key: 'deadband_percent',
+      width: 110,
+      render: (value, record, index) => (
+        <InputNumber
+          value={value}
+          onChange={(val) => updateTag(index, 'deadband_percent', val ?? null)}
+          min={0}
+          max={100}
+          step={0.5}
+          placeholder="None"
+          style={{ width: '100%' }}
+        />
+      ),
+    },
     {
       title: 'Schedule Group',
       dataIndex: 'schedule_group_id',
@@ -1423,6 +1624,25 @@ const EnhancedDeviceConfig = () => {
         </Select>
       ),
     },
+    {
+      title: 'Writes',
+      dataIndex: 'write_policy',
+      key: 'write_policy',
+      width: 150,
+      render: (value, record, index) => (
+        <Select
+          value={record.read_only ? 'disabled' : (value || 'disabled')}
+          onChange={(val) => updateTag(index, 'write_policy', val)}
+          style={{ width: '100%' }}
+          disabled={record.read_only}
+          title={record.read_only ? 'Read-only register' : undefined}
+        >
+          <Option value="disabled">Disabled</Option>
+          <Option value="admin_only">Admin only</Option>
+          <Option value="any_authenticated">Any user</Option>
+        </Select>
+      ),
+    },
     {
       title: 'Enabled',
       dataIndex: 'enabled',
@@ -1823,7 +2043,13 @@ const EnhancedDeviceConfig = () => {
             host: '192.168.1.100',
             port: 502,
             slave_id: 1,
+            max_block_gap: 0,
+            request_delay_ms: 0,
             baud_rate: 9600,
+            data_bits: 8,
+            stop_bits: 1,
+            parity: 'none',
+            inter_frame_delay_ms: 0,
             common_address: 1,
           }}
         >
@@ -1842,7 +2068,16 @@ const EnhancedDeviceConfig = () => {
                 label="Serial Number"
                 name="serial_no"
               >
-                <Input placeholder="Optional serial number" />
+                <Input
+                  placeholder="Optional serial number"
+                  addonAfter={editingDevice && (
+                    <Tooltip title="Read the serial number from the device's serial number registers">
+                      <Button type="link" size="small" loading={readingSerial} onClick={handleReadSerial} style={{ padding: 0, height: 'auto' }}>
+                        Read from Device
+                      </Button>
+                    </Tooltip>
+                  )}
+                />
               </Form.Item>
             </Col>
           </Row>
@@ -1906,6 +2141,7 @@ const EnhancedDeviceConfig = () => {
               <Option value="modbus_tcp">Modbus TCP</Option>
               <Option value="modbus_rtu">Modbus RTU</Option>
               <Option value="iec104">IEC 104</Option>
+              <Option value="simulated">Simulated</Option>
             </Select>
           </Form.Item>
 
@@ -1943,6 +2179,24 @@ const EnhancedDeviceConfig = () => {
                         <InputNumber min={1} max={255} placeholder="1" style={{ width: '100%' }} />
                       </Form.Item>
                     </Col>
+                    <Col span={8}>
+                      <Form.Item
+                        name="max_block_gap"
+                        label="Max Block Gap"
+                        tooltip="Unused registers a single read may span to join nearby tags"
+                      >
+                        <InputNumber min={0} max={124} placeholder="0" style={{ width: '100%' }} />
+                      </Form.Item>
+                    </Col>
+                    <Col span={8}>
+                      <Form.Item
+                        name="request_delay_ms"
+                        label="Request Delay (ms)"
+                        tooltip="Pause between requests on the connection, shared with other devices at the same host and port"
+                      >
+                        <InputNumber min={0} max={10000} placeholder="0" style={{ width: '100%' }} />
+                      </Form.Item>
+                    </Col>
                   </Row>
                 );
               } else if (protocolType === 'modbus_rtu') {
@@ -1981,6 +2235,49 @@ const EnhancedDeviceConfig = () => {
                         <InputNumber min={1} max={255} placeholder="1" style={{ width: '100%' }} />
                       </Form.Item>
                     </Col>
+                    <Col span={8}>
+                      <Form.Item
+                        name="max_block_gap"
+                        label="Max Block Gap"
+                        tooltip="Unused registers a single read may span to join nearby tags"
+                      >
+                        <InputNumber min={0} max={124} placeholder="0" style={{ width: '100%' }} />
+                      </Form.Item>
+                    </Col>
+                    <Col span={8}>
+                      <Form.Item name="data_bits" label="Data Bits">
+                        <Select style={{ width: '100%' }}>
+                          <Option value={7}>7</Option>
+                          <Option value={8}>8</Option>
+                        </Select>
+                      </Form.Item>
+                    </Col>
+                    <Col span={8}>
+                      <Form.Item name="parity" label="Parity">
+                        <Select style={{ width: '100%' }}>
+                          <Option value="none">None</Option>
+                          <Option value="even">Even</Option>
+                          <Option value="odd">Odd</Option>
+                        </Select>
+                      </Form.Item>
+                    </Col>
+                    <Col span={8}>
+                      <Form.Item name="stop_bits" label="Stop Bits">
+                        <Select style={{ width: '100%' }}>
+                          <Option value={1}>1</Option>
+                          <Option value={2}>2</Option>
+                        </Select>
+                      </Form.Item>
+                    </Col>
+                    <Col span={8}>
+                      <Form.Item
+                        name="inter_frame_delay_ms"
+                        label="Inter-frame Delay (ms)"
+                        tooltip="Silence kept on the bus between transactions; 0 uses 3.5 character times"
+                      >
+                        <InputNumber min={0} max={1000} placeholder="0" style={{ width: '100%' }} />
+                      </Form.Item>
+                    </Col>
                   </Row>
                 );
               } else if (protocolType === 'iec104') {
@@ -2004,13 +2301,79 @@ const EnhancedDeviceConfig = () => {
                         <InputNumber min={1} max={65535} placeholder="2404" style={{ width: '100%' }} />
                       </Form.Item>
                     </Col>
+                    <Col span={12}>
+                      <Form.Item
+                        name="common_address"
+                        label="Common Address"
+                        tooltip="ASDU address of the outstation; tag addresses are information object addresses (IOAs)"
+                      >
+                        <InputNumber min={1} max={65534} placeholder="1" style={{ width: '100%' }} />
+                      </Form.Item>
+                    </Col>
                   </Row>
                 );
+              } else if (protocolType === 'simulated') {
+                return (
+                  <Form.Item
+                    name="simulation"
+                    label="Simulation"
+                    tooltip="Patterns by tag name (sine, ramp, random_walk, constant, replay), dropout, bad_quality_probability and seed; tags without a pattern read their own address"
+                  >
+                    <Input.TextArea
+                      rows={6}
+                      placeholder={'{"patterns": {"Voltage": {"pattern": "sine", "amplitude": 10, "period_seconds": 60, "offset": 230}}}'}
+                      style={{ fontFamily: 'monospace' }}
+                    />
+                  </Form.Item>
+                );
               }
               return null;
             }}
           </Form.Item>
 
+          <Form.Item dependencies={['protocol_type']} noStyle>
+            {({ getFieldValue }) => {
+              const protocolType = getFieldValue('protocol_type');
+              if (protocolType !== 'modbus_tcp' && protocolType !== 'modbus_rtu') {
+                return null;
+              }
+              return (
+                <Row gutter={16}>
+                  <Col span={6}>
+                    <Form.Item
+                      name="serial_address"
+                      label="Serial Number Register"
+                      tooltip="First register of the serial number, e.g. 4990 on Sungrow inverters; leave empty if the device has none"
+                    >
+                      <InputNumber min={0} max={65535} placeholder="None" style={{ width: '100%' }} />
+                    </Form.Item>
+                  </Col>
+                  <Col span={6}>
+                    <Form.Item name="serial_length" label="Registers" tooltip="Registers the serial number spans">
+                      <InputNumber min={1} max={64} placeholder="10" style={{ width: '100%' }} />
+                    </Form.Item>
+                  </Col>
+                  <Col span={6}>
+                    <Form.Item name="serial_register_type" label="Register Type">
+                      <Select style={{ width: '100%' }}>
+                        <Option value="holding">Holding</Option>
+                        <Option value="input">Input</Option>
+                      </Select>
+                    </Form.Item>
+                  </Col>
+                  <Col span={6}>
+                    <Form.Item name="serial_encoding" label="Encoding">
+                      <Select style={{ width: '100%' }}>
+                        <Option value="ascii">ASCII</Option>
+                        <Option value="bcd">Packed BCD</Option>
+                      </Select>
+                    </Form.Item>
+                  </Col>
+                </Row>
+              );
+            }}
+          </Form.Item>
+
           <Row gutter={16}>
             <Col span={8}>
               <Form.Item label="Polling Interval (ms)" name="polling_interval_ms">
@@ -2055,6 +2418,9 @@ const EnhancedDeviceConfig = () => {
           <div style={{ marginTop: 16, textAlign: 'right' }}>
             <Space>
               <Button onClick={() => setModalVisible(false)}>Cancel</Button>
+              <Button onClick={handleTestConnection} loading={testingConnection}>
+                Test Connection
+              </Button>
               <Button type="primary" htmlType="submit" loading={loading}>
                 {editingDevice ? 'Update Device' : 'Create Device'}
               </Button>
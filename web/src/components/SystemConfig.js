@@ -41,6 +41,9 @@ const SystemConfig = () => {
 
   const handleSubmit = async (values) => {
     setSaving(true);
+    if (!values.thingsboard?.base_url) {
+      delete values.thingsboard;
+    }
     try {
       const response = await axios.post('/api/config', values);
       if (response.data.success) {
@@ -134,6 +137,38 @@ const SystemConfig = () => {
             </Col>
           </Row>
 
+          <Divider>Log Retention</Divider>
+
+          <Row gutter={16}>
+            <Col span={8}>
+              <Form.Item
+                name={['retention', 'max_age_days']}
+                label="Keep Entries For (days)"
+                tooltip="Leave empty to keep entries regardless of age"
+              >
+                <InputNumber min={1} placeholder="30" />
+              </Form.Item>
+            </Col>
+            <Col span={8}>
+              <Form.Item
+                name={['retention', 'max_entries']}
+                label="Max Entries"
+                tooltip="Leave empty to use Max Log Entries above"
+              >
+                <InputNumber min={1000} />
+              </Form.Item>
+            </Col>
+            <Col span={8}>
+              <Form.Item
+                name={['retention', 'cleanup_interval_minutes']}
+                label="Run Every (minutes)"
+                tooltip="Leave empty to use the cleanup interval above"
+              >
+                <InputNumber min={1} />
+              </Form.Item>
+            </Col>
+          </Row>
+
           <Divider>Logging Configuration</Divider>
           
           <Row gutter={16}>
@@ -182,6 +217,46 @@ const SystemConfig = () => {
               </Form.Item>
             </Col>
           </Row>
+
+          <Divider>ThingsBoard</Divider>
+
+          <Row gutter={16}>
+            <Col span={12}>
+              <Form.Item
+                name={['thingsboard', 'base_url']}
+                label="Server URL"
+              >
+                <Input placeholder="https://thingsboard.example.com" />
+              </Form.Item>
+            </Col>
+            <Col span={12}>
+              <Form.Item
+                name={['thingsboard', 'tenant_label']}
+                label="Tenant Label"
+              >
+                <Input placeholder="Main tenant" />
+              </Form.Item>
+            </Col>
+          </Row>
+
+          <Row gutter={16}>
+            <Col span={12}>
+              <Form.Item
+                name={['thingsboard', 'username']}
+                label="Username"
+              >
+                <Input placeholder="tenant@example.com" />
+              </Form.Item>
+            </Col>
+            <Col span={12}>
+              <Form.Item
+                name={['thingsboard', 'password']}
+                label="Password"
+              >
+                <Input.Password placeholder="Leave blank to keep the current password" autoComplete="new-password" />
+              </Form.Item>
+            </Col>
+          </Row>
         </Form>
       </Card>
     </div>
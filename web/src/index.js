@@ -1,10 +1,21 @@
 import React from 'react';
 import ReactDOM from 'react-dom/client';
 import { BrowserRouter } from 'react-router-dom';
+import axios from 'axios';
 import App from './App';
 import 'antd/dist/reset.css';
 import './index.css';
 
+// Failed API requests come back with an error status and the usual envelope
+// ({ success: false, error, code, details }); hand those to callers as responses
+// so they can check `success` whatever the status was.
+axios.interceptors.response.use(undefined, (error) => {
+  if (error.response?.data?.success === false) {
+    return error.response;
+  }
+  return Promise.reject(error);
+});
+
 const root = ReactDOM.createRoot(document.getElementById('root'));
 root.render(
   <React.StrictMode>